@@ -20,7 +20,7 @@ cfg_if::cfg_if! {
 }
 
 use cortex_m_rt::entry;
-use drv_stm32h7_startup::{system_init, ClockConfig};
+use drv_stm32h7_startup::{enable_rtc_lse, system_init, ClockConfig};
 
 #[entry]
 fn main() -> ! {
@@ -74,7 +74,10 @@ fn main() -> ! {
         }
     }
 
-    system_init(CLOCKS);
+    let p = system_init(CLOCKS);
+
+    // The board has a 32.768 kHz crystal for the RTC.
+    enable_rtc_lse(&p);
 
     // Turn on profiling. We're sneaking around behind the GPIO driver's back
     // for this, but, it's a debug feature.
@@ -0,0 +1,296 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Build-time configuration for the STM32H7 FDCAN server.
+//!
+//! Each controller is described in the app's global config under
+//! `[config.fdcan.fdcanN]`, which names its pins, its kernel clock, and the
+//! bit rates wanted. We work out the bit timing here, so that a rate the
+//! clock can't produce exactly is a build error rather than a bus that
+//! almost works.
+
+use anyhow::{anyhow, bail, Result};
+use proc_macro2::TokenStream;
+use quote::{ToTokens, TokenStreamExt};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// This represents our _subset_ of global config and _must not_ be marked with
+/// `deny_unknown_fields`!
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FdcanGlobalConfig {
+    pub fdcan: BTreeMap<String, FdcanConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FdcanConfig {
+    pub controller: usize,
+    /// Frequency of the FDCAN kernel clock, which is HSE out of reset.
+    pub kernel_clock_hz: u32,
+    /// Nominal (arbitration phase) bit rate.
+    pub bitrate: u32,
+    /// Data phase bit rate for FD frames with bit rate switching. If absent,
+    /// the controller is configured for classic CAN only.
+    pub data_bitrate: Option<u32>,
+    /// Sample point, in tenths of a percent of the bit time.
+    #[serde(default = "default_sample_point")]
+    pub sample_point: u32,
+    pub tx: AfPinConfig,
+    pub rx: AfPinConfig,
+    /// Start bus-off recovery as soon as the controller goes bus-off, rather
+    /// than waiting to be asked.
+    #[serde(default)]
+    pub auto_recover: bool,
+}
+
+fn default_sample_point() -> u32 {
+    // CiA 601-3 recommends 87.5% for most nominal rates.
+    875
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum ConfigPort {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AfPinConfig {
+    pub port: ConfigPort,
+    pub pin: usize,
+    pub af: usize,
+}
+
+/// Limits on the fields of one bit timing register, in their natural
+/// (not minus-one) form.
+#[derive(Copy, Clone, Debug)]
+pub struct TimingLimits {
+    pub max_prescaler: u32,
+    pub max_seg1: u32,
+    pub max_seg2: u32,
+    pub max_sjw: u32,
+}
+
+/// FDCAN_NBTP.
+pub const NOMINAL_LIMITS: TimingLimits = TimingLimits {
+    max_prescaler: 512,
+    max_seg1: 256,
+    max_seg2: 128,
+    max_sjw: 128,
+};
+
+/// FDCAN_DBTP.
+pub const DATA_LIMITS: TimingLimits = TimingLimits {
+    max_prescaler: 32,
+    max_seg1: 32,
+    max_seg2: 16,
+    max_sjw: 16,
+};
+
+/// Bit timing in time quanta; a bit is `1 + seg1 + seg2` quanta long, and
+/// each quantum is `prescaler` kernel clock cycles.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BitTiming {
+    pub prescaler: u32,
+    pub seg1: u32,
+    pub seg2: u32,
+    pub sjw: u32,
+}
+
+/// Finds timing that gives exactly `bitrate` from `clock_hz`, with the
+/// sample point as close to `sample_point` (in tenths of a percent) as the
+/// limits allow. Prefers the smallest prescaler, which gives the most
+/// quanta per bit and so the finest control of the sample point.
+pub fn bit_timing(
+    clock_hz: u32,
+    bitrate: u32,
+    sample_point: u32,
+    limits: &TimingLimits,
+) -> Result<BitTiming> {
+    if bitrate == 0 || sample_point == 0 || sample_point >= 1000 {
+        bail!("bad bit rate {bitrate} or sample point {sample_point}");
+    }
+    for prescaler in 1..=limits.max_prescaler {
+        let per_bit = prescaler * bitrate;
+        if clock_hz % per_bit != 0 {
+            continue;
+        }
+        let quanta = clock_hz / per_bit;
+        if quanta < 4 || quanta > 1 + limits.max_seg1 + limits.max_seg2 {
+            continue;
+        }
+
+        let mut seg2 = (quanta * (1000 - sample_point) + 500) / 1000;
+        seg2 = seg2.clamp(1, limits.max_seg2);
+        let mut seg1 = quanta - 1 - seg2;
+        if seg1 > limits.max_seg1 {
+            seg1 = limits.max_seg1;
+            seg2 = quanta - 1 - seg1;
+        }
+        if seg1 < 1 || seg2 > limits.max_seg2 {
+            continue;
+        }
+
+        return Ok(BitTiming {
+            prescaler,
+            seg1,
+            seg2,
+            sjw: seg2.min(limits.max_sjw),
+        });
+    }
+    Err(anyhow!(
+        "can't make {bitrate} bit/s from a {clock_hz} Hz kernel clock"
+    ))
+}
+
+/// Checks `config` and generates the `CONFIG` constant the server expects.
+pub fn generate(config: &FdcanConfig) -> Result<TokenStream> {
+    if !(1..=2).contains(&config.controller) {
+        bail!(
+            "bad controller {}, valid values are 1 and 2",
+            config.controller
+        );
+    }
+    for pin in [&config.tx, &config.rx] {
+        if pin.pin > 15 {
+            bail!(
+                "pin {:?}{} is invalid, pins are numbered 0-15",
+                pin.port,
+                pin.pin
+            );
+        }
+        if pin.af > 15 {
+            bail!("af {} is invalid, functions are numbered 0-15", pin.af);
+        }
+    }
+
+    let nominal = bit_timing(
+        config.kernel_clock_hz,
+        config.bitrate,
+        config.sample_point,
+        &NOMINAL_LIMITS,
+    )?;
+    let data = config
+        .data_bitrate
+        .map(|rate| {
+            if rate < config.bitrate {
+                bail!("data bit rate must be at least the nominal bit rate");
+            }
+            // The data phase is short enough that a late sample point buys
+            // little, and fewer quanta leave less room for one; 75% is the
+            // usual choice.
+            bit_timing(config.kernel_clock_hz, rate, 750, &DATA_LIMITS)
+        })
+        .transpose()?;
+
+    let controller = config.controller;
+    let data = match data {
+        Some(t) => quote::quote! { Some(#t) },
+        None => quote::quote! { None },
+    };
+    let tx = &config.tx;
+    let rx = &config.rx;
+    let auto_recover = config.auto_recover;
+
+    Ok(quote::quote! {
+        const CONFIG: ServerConfig = ServerConfig {
+            controller: #controller,
+            nominal: #nominal,
+            data: #data,
+            pins: &[#tx, #rx],
+            auto_recover: #auto_recover,
+        };
+    })
+}
+
+impl ToTokens for BitTiming {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let prescaler = self.prescaler as u16;
+        let seg1 = self.seg1 as u16;
+        let seg2 = self.seg2 as u8;
+        let sjw = self.sjw as u8;
+        tokens.append_all(quote::quote! {
+            BitTiming {
+                prescaler: #prescaler,
+                seg1: #seg1,
+                seg2: #seg2,
+                sjw: #sjw,
+            }
+        });
+    }
+}
+
+impl ToTokens for AfPinConfig {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let port: syn::Ident =
+            syn::parse_str(&format!("{:?}", self.port)).unwrap();
+        let pin = self.pin;
+        let af: syn::Ident = syn::parse_str(&format!("AF{}", self.af)).unwrap();
+        tokens.append_all(quote::quote! {
+            (
+                sys_api::PinSet {
+                    port: sys_api::Port::#port,
+                    pin_mask: 1 << #pin,
+                },
+                sys_api::Alternate::#af,
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nominal_timing() {
+        let t = bit_timing(8_000_000, 500_000, 875, &NOMINAL_LIMITS).unwrap();
+        assert_eq!(
+            t,
+            BitTiming {
+                prescaler: 1,
+                seg1: 13,
+                seg2: 2,
+                sjw: 2
+            }
+        );
+
+        let t =
+            bit_timing(80_000_000, 1_000_000, 800, &NOMINAL_LIMITS).unwrap();
+        assert_eq!((t.prescaler, t.seg1, t.seg2), (1, 63, 16));
+    }
+
+    #[test]
+    fn prescaler_used_when_quanta_overflow() {
+        // 200 MHz / 125 kbit/s is 1600 quanta, far more than fit.
+        let t = bit_timing(200_000_000, 125_000, 875, &NOMINAL_LIMITS).unwrap();
+        assert_eq!(t.prescaler, 5);
+        assert_eq!(1 + t.seg1 + t.seg2, 320);
+    }
+
+    #[test]
+    fn data_timing() {
+        let t = bit_timing(8_000_000, 2_000_000, 750, &DATA_LIMITS).unwrap();
+        assert_eq!((t.prescaler, t.seg1, t.seg2, t.sjw), (1, 2, 1, 1));
+    }
+
+    #[test]
+    fn inexact_rates_rejected() {
+        assert!(bit_timing(8_000_000, 3_000_000, 750, &DATA_LIMITS).is_err());
+        assert!(bit_timing(8_000_000, 0, 875, &NOMINAL_LIMITS).is_err());
+        assert!(bit_timing(8_000_000, 500_000, 1000, &NOMINAL_LIMITS).is_err());
+    }
+}
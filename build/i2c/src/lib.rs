@@ -26,6 +26,16 @@ struct Config {
 struct I2cConfig {
     controllers: Vec<I2cController>,
     devices: Option<Vec<I2cDevice>>,
+
+    /// strap pins giving the board revision, if any devices depend on it
+    board_rev: Option<I2cBoardRev>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct I2cBoardRev {
+    /// strap pins, most significant first; they are read with pull-ups
+    pins: Vec<I2cGpio>,
 }
 
 //
@@ -51,6 +61,16 @@ struct I2cController {
     target: bool,
 }
 
+impl I2cController {
+    //
+    // Our initiator implementation supports 10-bit addressing, but our target
+    // implementation only responds to 7-bit addresses.
+    //
+    fn ten_bit_capable(&self) -> bool {
+        !self.target
+    }
+}
+
 //
 // Unfortunately, the toml-rs parsing of enums isn't quite right (see
 // https://github.com/alexcrichton/toml-rs/issues/390 for details).  As a
@@ -81,7 +101,11 @@ struct I2cDevice {
     port: Option<String>,
 
     /// I2C address
-    address: u8,
+    address: u16,
+
+    /// address is a 10-bit address
+    #[serde(default)]
+    ten_bit: bool,
 
     /// I2C mux, if any
     mux: Option<u8>,
@@ -104,6 +128,15 @@ struct I2cDevice {
     /// device is removable
     #[serde(default)]
     removable: bool,
+
+    /// task to notify when this device asserts SMBALERT#, if any
+    smbalert: Option<I2cTaskNote>,
+
+    /// stable name by which drivers find this device, if any
+    alias: Option<String>,
+
+    /// board revisions on which this device is fitted, if not all of them
+    revs: Option<Vec<u32>>,
 }
 
 impl I2cDevice {
@@ -138,6 +171,7 @@ struct I2cPort {
     af: u8,
     #[serde(default)]
     muxes: Vec<I2cMux>,
+    smbalert: Option<I2cSmbAlertLine>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -154,6 +188,23 @@ struct I2cGpio {
     pin: u8,
 }
 
+/// An SMBALERT# line, and (if it's routed to us through EXTI) the
+/// notification that it has been asserted
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cSmbAlertLine {
+    port: String,
+    pin: u8,
+    notification: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cTaskNote {
+    name: String,
+    notification: String,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct I2cMux {
@@ -266,6 +317,11 @@ struct I2cSensorsDescription {
     by_bus: MultiMap<DeviceBusKey, usize>,
     by_bus_name: MultiMap<DeviceBusNameKey, usize>,
 
+    // The first sensor of each kind on each removable device, in the order
+    // the devices appear in the config; the index into each list is the
+    // device's slot.
+    by_slot: MultiMap<DeviceKey, usize>,
+
     // list of all devices and a list of their sensors, with an optional sensor
     // name (if present)
     device_sensors: Vec<Vec<DeviceSensor>>,
@@ -280,6 +336,7 @@ impl I2cSensorsDescription {
             by_name: MultiMap::new(),
             by_bus: MultiMap::new(),
             by_bus_name: MultiMap::new(),
+            by_slot: MultiMap::new(),
             device_sensors: vec![Vec::new(); devices.len()],
             total_sensors: 0,
         };
@@ -402,6 +459,17 @@ impl I2cSensorsDescription {
             },
             id,
         );
+
+        if d.removable && idx == 0 {
+            self.by_slot.insert(
+                DeviceKey {
+                    device: d.device.clone(),
+                    kind,
+                },
+                id,
+            );
+        }
+
         self.device_sensors[dev_index].push(DeviceSensor { name, kind, id });
     }
 }
@@ -484,6 +552,9 @@ struct ConfigGenerator {
 
     /// hash of controllers to single port indices
     singletons: HashMap<u8, usize>,
+
+    /// board revision strap, if any
+    board_rev: Option<I2cBoardRev>,
 }
 
 impl ConfigGenerator {
@@ -499,8 +570,13 @@ impl ConfigGenerator {
         let mut buses = HashMap::new();
         let mut ports = IndexMap::new();
         let mut singletons = HashMap::new();
+        let mut seven_bit_only = HashSet::new();
 
         for c in i2c.controllers {
+            if !c.ten_bit_capable() {
+                seven_bit_only.insert(c.controller);
+            }
+
             //
             // We always insert our buses (even for controllers that don't
             // match our dispostion) to assure that devices can always find
@@ -530,8 +606,56 @@ impl ConfigGenerator {
             controllers.push(c);
         }
 
+        let mut aliases = HashSet::new();
+
         if let Some(devices) = &i2c.devices {
             for d in devices {
+                if let Some(alias) = &d.alias {
+                    let valid = alias
+                        .starts_with(|c: char| c.is_ascii_lowercase())
+                        && alias.chars().all(|c| {
+                            c.is_ascii_lowercase()
+                                || c.is_ascii_digit()
+                                || c == '_'
+                        });
+                    if !valid {
+                        panic!(
+                            "device {} at address {:#x} has alias \"{}\", \
+                            which is not a snake_case identifier",
+                            d.device, d.address, alias
+                        );
+                    }
+
+                    //
+                    // Aliases share a namespace with the functions generated
+                    // for each kind of device, so they can't collide with
+                    // them either.
+                    //
+                    if !aliases.insert(alias.clone())
+                        || devices.iter().any(|other| other.device == *alias)
+                    {
+                        panic!("i2c device alias {} is not unique", alias);
+                    }
+                }
+
+                match &d.revs {
+                    Some(revs) if revs.is_empty() => {
+                        panic!(
+                            "device {} at address {:#x} is fitted on no \
+                            board revisions",
+                            d.device, d.address
+                        );
+                    }
+                    Some(_) if i2c.board_rev.is_none() => {
+                        panic!(
+                            "device {} at address {:#x} depends on the board \
+                            revision, but there is no board-rev strap",
+                            d.device, d.address
+                        );
+                    }
+                    _ => {}
+                }
+
                 match (d.controller, d.bus.as_ref()) {
                     (None, None) => {
                         panic!(
@@ -556,6 +680,47 @@ impl ConfigGenerator {
                     }
                     (_, _) => {}
                 }
+
+                if !d.ten_bit {
+                    if d.address > 0x7f {
+                        panic!(
+                            "device {} at address {:#x} has an address \
+                            that exceeds 7 bits, but is not marked ten-bit",
+                            d.device, d.address
+                        );
+                    }
+
+                    continue;
+                }
+
+                if d.address > 0x3ff {
+                    panic!(
+                        "device {} at address {:#x} has an address \
+                        that exceeds 10 bits",
+                        d.device, d.address
+                    );
+                }
+
+                if d.smbalert.is_some() {
+                    panic!(
+                        "device {} at address {:#x} has a 10-bit address, \
+                        but SMBus alerts require a 7-bit address",
+                        d.device, d.address
+                    );
+                }
+
+                let controller = match &d.bus {
+                    Some(bus) => buses.get(bus).unwrap().0,
+                    None => d.controller.unwrap(),
+                };
+
+                if seven_bit_only.contains(&controller) {
+                    panic!(
+                        "device {} at address {:#x} has a 10-bit address, \
+                        but I2C{} only supports 7-bit addresses",
+                        d.device, d.address, controller
+                    );
+                }
             }
         }
 
@@ -567,6 +732,7 @@ impl ConfigGenerator {
             buses,
             ports,
             singletons,
+            board_rev: i2c.board_rev,
         }
     }
 
@@ -762,10 +928,12 @@ impl ConfigGenerator {
     #[allow(dead_code)]
     pub const NMUXEDBUSES: usize = {nmuxedbuses};
 
+    #[allow(dead_code)]
+    pub const NMUXES: usize = {len};
+
     use drv_stm32xx_i2c::I2cMux;
 
-    pub fn muxes() -> [I2cMux<'static>; {}] {{"##,
-            len
+    pub fn muxes() -> [I2cMux<'static>; NMUXES] {{"##
         )?;
 
         if len > 0 {
@@ -840,6 +1008,156 @@ impl ConfigGenerator {
         Ok(())
     }
 
+    pub fn generate_smbalerts(&mut self) -> Result<()> {
+        if self.disposition != Disposition::Initiator {
+            panic!("can only generate SMBALERT# lines as an initiator");
+        }
+
+        let mut s = &mut self.output;
+        let mut len = 0;
+
+        for c in &self.controllers {
+            len += c.ports.values().filter(|p| p.smbalert.is_some()).count();
+        }
+
+        write!(
+            &mut s,
+            r##"
+    #[allow(unused_imports)]
+    use drv_stm32xx_i2c::{{I2cSmbAlert, I2cSmbAlertOwner}};
+
+    pub fn smbalerts() -> [I2cSmbAlert; {}] {{"##,
+            len
+        )?;
+
+        if len > 0 {
+            writeln!(
+                &mut s,
+                r##"
+        use drv_i2c_api::{{Controller, PortIndex}};
+        use drv_stm32xx_sys_api as gpio_api;"##
+            )?;
+        }
+
+        write!(
+            &mut s,
+            r##"
+        ["##
+        )?;
+
+        for c in &self.controllers {
+            for (index, port) in c.ports.values().enumerate() {
+                if let Some(alert) = &port.smbalert {
+                    let notification = match &alert.notification {
+                        Some(note) => format!(
+                            "crate::notifications::{}_MASK",
+                            note.to_uppercase().replace('-', "_")
+                        ),
+                        None => "0".to_owned(),
+                    };
+
+                    write!(
+                        &mut s,
+                        r##"
+            I2cSmbAlert {{
+                controller: Controller::I2C{controller},
+                port: PortIndex({index}),
+                pin: I2cGpio {{
+                    gpio_pins: gpio_api::Port::{gpio_port}.pin({gpio_pin}),
+                }},
+                notification: {notification},
+            }},"##,
+                        controller = c.controller,
+                        index = index,
+                        gpio_port = alert.port,
+                        gpio_pin = alert.pin,
+                    )?;
+                }
+            }
+        }
+
+        writeln!(
+            &mut s,
+            r##"
+        ]
+    }}"##
+        )?;
+
+        let mut s = String::new();
+        let mut nowners = 0;
+
+        for d in &self.devices {
+            let owner = match &d.smbalert {
+                Some(owner) => owner,
+                None => continue,
+            };
+
+            let (controller, port) = self.lookup_controller_port(d);
+
+            if !self.controllers.iter().any(|c| c.controller == controller) {
+                continue;
+            }
+
+            let segment = match (d.mux, d.segment) {
+                (Some(mux), Some(segment)) => {
+                    format!("Some((Mux::M{}, Segment::S{}))", mux, segment)
+                }
+                _ => "None".to_owned(),
+            };
+
+            nowners += 1;
+
+            write!(
+                &mut s,
+                r##"
+            // {description}
+            I2cSmbAlertOwner {{
+                controller: Controller::I2C{controller},
+                port: PortIndex({port}),
+                segment: {segment},
+                address: {address:#x},
+                task: TaskId::for_index_and_gen(
+                    hubris_num_tasks::Task::{task} as usize,
+                    Generation::ZERO,
+                ),
+                notification: crate::notifications::{task}::{note}_MASK,
+            }},"##,
+                description = d.description,
+                address = d.address,
+                task = owner.name,
+                note = owner.notification.to_uppercase().replace('-', "_"),
+            )?;
+        }
+
+        write!(
+            &mut self.output,
+            r##"
+
+    pub fn smbalert_owners() -> [I2cSmbAlertOwner; {}] {{"##,
+            nowners,
+        )?;
+
+        if !s.is_empty() {
+            writeln!(
+                &mut self.output,
+                r##"
+        use drv_i2c_api::{{Controller, PortIndex, Mux, Segment}};
+        use userlib::{{Generation, TaskId}};"##
+            )?;
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+        [{}
+        ]
+    }}"##,
+            s
+        )?;
+
+        Ok(())
+    }
+
     fn lookup_controller_port(&self, d: &I2cDevice) -> (u8, usize) {
         let controller = match &d.bus {
             Some(bus) => self.buses.get(bus).unwrap().0,
@@ -906,13 +1224,14 @@ impl ConfigGenerator {
         format!(
             r##"
 {indent}// {description}
-{indent}I2cDevice::new(task,
+{indent}I2cDevice::{new}(task,
 {indent}    Controller::I2C{controller},
 {indent}    PortIndex({port}),
 {indent}    {segment},
 {indent}    {address:#x}
 {indent})"##,
             description = d.description,
+            new = if d.ten_bit { "new_ten_bit" } else { "new" },
             controller = controller,
             port = port,
             segment = segment,
@@ -1090,6 +1409,51 @@ impl ConfigGenerator {
             )?;
         }
 
+        //
+        // A device that is only fitted on some board revisions can only be
+        // found by its alias given the board revision.
+        //
+        for d in &self.devices {
+            let Some(alias) = &d.alias else {
+                continue;
+            };
+
+            match &d.revs {
+                None => {
+                    write!(
+                        &mut self.output,
+                        r##"
+        #[allow(dead_code)]
+        pub fn {}(task: TaskId) -> I2cDevice {{"##,
+                        alias
+                    )?;
+                    let out = self.generate_device(d, 12);
+                    write!(&mut self.output, "{}", out)?;
+                }
+                Some(revs) => {
+                    write!(
+                        &mut self.output,
+                        r##"
+        #[allow(dead_code)]
+        pub fn {}(task: TaskId, rev: u32) -> Option<I2cDevice> {{
+            match rev {{
+                {} => Some({}),
+                _ => None,
+            }}"##,
+                        alias,
+                        rev_pattern(revs),
+                        self.generate_device(d, 20),
+                    )?;
+                }
+            }
+
+            writeln!(
+                &mut self.output,
+                r##"
+        }}"##
+            )?;
+        }
+
         writeln!(&mut self.output, "    }}")?;
 
         self.generate_power(PowerDevices::PMBus)?;
@@ -1203,12 +1567,97 @@ impl ConfigGenerator {
                 _ => Err(drv_i2c_api::ResponseCode::BadArg)
             }}
         }}
-    }}"##
+"##
+        )?;
+
+        self.generate_presence()?;
+
+        writeln!(&mut self.output, "    }}")?;
+
+        Ok(())
+    }
+
+    ///
+    /// Generates what the validate task needs to tell a device that's
+    /// missing from one that isn't fitted on this board: whether each device
+    /// is fitted on a given board revision, and (if there's a strap) how to
+    /// read the board revision.
+    ///
+    fn generate_presence(&mut self) -> Result<()> {
+        write!(
+            &mut self.output,
+            r##"
+        /// Returns whether the device with the given index is fitted on
+        /// board revision `rev`.
+        #[allow(dead_code, unused_variables)]
+        pub fn fitted(index: usize, rev: u32) -> bool {{
+            match index {{"##
         )?;
 
+        for (index, device) in self.devices.iter().enumerate() {
+            if let Some(revs) = &device.revs {
+                write!(
+                    &mut self.output,
+                    r##"
+                {} => matches!(rev, {}),"##,
+                    index,
+                    rev_pattern(revs)
+                )?;
+            }
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+                _ => true,
+            }}
+        }}"##
+        )?;
+
+        if let Some(board_rev) = &self.board_rev {
+            write!(
+                &mut self.output,
+                r##"
+        /// Reads the board revision from its strap.
+        pub fn board_rev(sys: &drv_stm32xx_sys_api::Sys) -> u32 {{
+            use drv_stm32xx_sys_api::{{Port, Pull}};
+
+            let pins = ["##
+            )?;
+            for pin in &board_rev.pins {
+                write!(
+                    &mut self.output,
+                    r##"
+                Port::{}.pin({}),"##,
+                    pin.port, pin.pin
+                )?;
+            }
+            writeln!(
+                &mut self.output,
+                r##"
+            ];
+
+            for &pin in &pins {{
+                sys.gpio_configure_input(pin, Pull::Up);
+            }}
+
+            // Give the pull-ups time to charge any floating traces.
+            userlib::hl::sleep_for(1);
+
+            pins.iter()
+                .fold(0, |rev, &pin| rev << 1 | (sys.gpio_read(pin) != 0) as u32)
+        }}"##
+            )?;
+        }
+
         Ok(())
     }
 
+    /// Returns whether the generated code uses a board revision strap.
+    pub fn has_board_rev(&self) -> bool {
+        self.board_rev.is_some()
+    }
+
     fn generate_power(&mut self, which: PowerDevices) -> Result<()> {
         let mut byrail = HashMap::new();
 
@@ -1402,6 +1851,30 @@ impl ConfigGenerator {
             self.emit_sensor(&k.device, &label, ids)?;
         }
 
+        //
+        // Removable devices also get their sensors by slot, so that (say)
+        // the temperature of the drive in the third bay is always at index 2
+        // -- even if the device has other sensors, or the slots have no
+        // names.
+        //
+        for (k, ids) in s.by_slot.iter_all() {
+            writeln!(
+                &mut self.output,
+                r##"
+        #[allow(dead_code)]
+        pub const {}_SLOT_{}_SENSORS: [SensorId; {}] = [ "##,
+                k.device.to_uppercase(),
+                k.kind,
+                ids.len(),
+            )?;
+
+            for id in ids {
+                writeln!(&mut self.output, "            SensorId({}), ", id)?;
+            }
+
+            writeln!(&mut self.output, "        ];")?;
+        }
+
         writeln!(&mut self.output, "\n    }}")?;
         Ok(())
     }
@@ -1466,6 +1939,7 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
             g.generate_pins()?;
             g.generate_ports()?;
             g.generate_muxes()?;
+            g.generate_smbalerts()?;
         }
 
         Disposition::Devices => {
@@ -1494,6 +1968,9 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
 pub struct I2cDeviceDescription {
     pub device: String,
     pub description: String,
+    pub alias: Option<String>,
+    /// board revisions on which the device is fitted, if not all of them
+    pub revs: Option<Vec<u32>>,
     pub sensors: Vec<DeviceSensor>,
 }
 
@@ -1515,7 +1992,25 @@ pub fn device_descriptions() -> impl Iterator<Item = I2cDeviceDescription> {
         |(device, sensors)| I2cDeviceDescription {
             device: device.device,
             description: device.description,
+            alias: device.alias,
+            revs: device.revs,
             sensors,
         },
     )
 }
+
+///
+/// Returns whether the I2C configuration has a board revision strap, in which
+/// case the generated validation code reads it with `drv-stm32xx-sys-api`.
+///
+pub fn has_board_rev() -> bool {
+    ConfigGenerator::new(Disposition::Validation).has_board_rev()
+}
+
+/// Returns a pattern matching any of `revs`.
+fn rev_pattern(revs: &[u32]) -> String {
+    revs.iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
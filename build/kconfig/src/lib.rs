@@ -18,6 +18,22 @@ pub struct KernelConfig {
 
     /// Interrupts hooked by the application, keyed by IRQ number.
     pub irqs: BTreeMap<u32, InterruptConfig>,
+
+    /// What to do with interrupts that nobody hooked.
+    pub spurious_irqs: SpuriousIrqPolicy,
+}
+
+/// Handling for interrupts that fire with no task to handle them.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum SpuriousIrqPolicy {
+    /// Panic, naming the interrupt.
+    Panic,
+    /// Count the interrupt where the supervisor can read it, disabling it
+    /// if it storms.
+    Count,
+    /// As with `Count`, and also post these notification bits to the
+    /// supervisor.
+    Notify(u32),
 }
 
 /// Configuration for a single hooked interrupt.
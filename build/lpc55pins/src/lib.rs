@@ -47,6 +47,54 @@ pub struct PinConfig {
     opendrain: Option<String>,
     direction: Option<String>,
     name: Option<String>,
+    /// Routes the pin's interrupt to this task (see `drv-lpc55-gpio`)
+    pint: Option<PintConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct PintConfig {
+    /// Notification to post when the interrupt fires
+    notification: String,
+}
+
+/// This represents our _subset_ of each task's config, and _must not_ be
+/// marked with `deny_unknown_fields`!
+#[derive(Deserialize)]
+struct PinsConfig {
+    #[serde(default)]
+    pins: Vec<PinConfig>,
+}
+
+/// A pin whose interrupt is routed through PINT to a task
+#[derive(Clone, Debug)]
+pub struct PintRoute {
+    /// Name of the task that owns the pin
+    pub task: String,
+    /// The pin, numbered as in `drv_lpc55_gpio_api::Pin`
+    pub pin: usize,
+    /// Notification to post to `task`
+    pub notification: String,
+}
+
+/// Collects the pins, across every task's `pins`, whose interrupts are to be
+/// routed through PINT, in task order.
+pub fn pint_routes() -> Result<Vec<PintRoute>> {
+    let mut routes = vec![];
+    for (task, config) in build_util::all_task_full_configs::<PinsConfig>()? {
+        let Some(config) = config.config else { continue };
+        for p in config.pins {
+            if let Some(pint) = p.pint {
+                let (port, pin) = p.pin.get_port_pin();
+                routes.push(PintRoute {
+                    task: task.clone(),
+                    pin: port * 32 + pin,
+                    notification: pint.notification,
+                });
+            }
+        }
+    }
+    Ok(routes)
 }
 
 impl PinConfig {
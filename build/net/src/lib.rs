@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::Deserialize;
 use std::collections::BTreeMap;
 
@@ -28,33 +28,154 @@ pub struct NetConfig {
     /// during the `net` build, so it must be present iff the `vlan` feature
     /// is turned on.
     pub vlan: Option<VLanConfig>,
+
+    /// DHCPv4 configuration, or None. This must be present iff the `net`
+    /// task's `dhcpv4` feature is turned on, which is checked during its
+    /// build.
+    pub dhcp: Option<DhcpConfig>,
+
+    /// mDNS responder configuration, or None. Like `dhcp`, this must be
+    /// present iff the `net` task's `mdns` feature is turned on.
+    pub mdns: Option<MdnsConfig>,
+
+    /// Packet capture configuration, or None. Like `dhcp`, this must be
+    /// present iff the `net` task's `capture` feature is turned on.
+    pub capture: Option<CaptureConfig>,
+
+    /// Ingress filter policy, or None. Like `dhcp`, this must be present iff
+    /// the `net` task's `firewall` feature is turned on.
+    pub firewall: Option<FirewallConfig>,
+
+    /// IEEE 1588 clock configuration, or None. Like `dhcp`, this must be
+    /// present iff the `net` task's `ptp` feature is turned on.
+    pub ptp: Option<PtpConfig>,
 }
 
 /// TODO: this type really wants to be an enum, but the toml crate's enum
-/// handling is really, really fragile, and currently it would be an enum with a
-/// single variant anyway.
+/// handling is really, really fragile.
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SocketConfig {
+    /// `udp` or `tcp`
     pub kind: String,
     pub owner: TaskNote,
     pub port: u16,
     pub tx: BufSize,
     pub rx: BufSize,
+    /// Whether the socket takes IPv4 as well as IPv6, which needs DHCP to be
+    /// configured (for an IPv4 address to take it on) and the owner to be
+    /// built with `task-net-api/ipv4` (to make sense of what it's given).
+    #[serde(default)]
+    pub ipv4: bool,
+    /// VLANs the socket is bound on, by VID; if this is missing, it's bound
+    /// on all of them. Only buffers for these VLANs are allocated.
+    pub vlans: Option<Vec<u16>>,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct VLanConfig {
     /// Address of the 0-index VLAN
     pub start: usize,
     /// Number of VLANs
     pub count: usize,
+    /// Management switch ports (1 or 2) carrying each VLAN, in VID order. If
+    /// this is missing, the `i`th VLAN is carried by port `i + 1` alone, which
+    /// suits the usual two-port switch with one VLAN per port. A port that
+    /// carries more than one VLAN passes them along tagged.
+    pub ports: Option<Vec<Vec<u8>>>,
+}
+
+/// Most VLANs that fit in the management switch's table, which also needs
+/// room for the VLAN that unwanted frames are dropped into
+pub const MAX_VLANS: usize = 15;
+
+/// VID of the VLAN that unwanted frames are dropped into, which must not be
+/// configured for anything else
+pub const DROP_VID: usize = 0x3FF;
+
+/// Upstream ports on the management switch, numbered from 1
+pub const SWITCH_PORTS: u8 = 2;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DhcpConfig {
+    /// How long to go without a lease before using the fallback address
+    pub timeout_ms: u64,
+    /// Address to fall back to, as `a.b.c.d/len`; if this is missing, we fall
+    /// back to an IPv4 link-local address (RFC 3927) instead. With VLANs,
+    /// only the first VLAN uses this, and the others fall back to link-local.
+    pub fallback: Option<String>,
+    /// Default gateway to go with `fallback`
+    pub gateway: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MdnsConfig {
+    /// DNS-SD service type to advertise, e.g. `_oxide-sp._udp`
+    pub service: String,
+    /// Socket whose port the service is on
+    pub socket: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Where captured frames go, as `[ipv6-address]:port`; this is reached
+    /// through the first VLAN
+    pub collector: String,
+    /// Bytes of each frame to keep, unless the filter says otherwise
+    #[serde(default = "default_snaplen")]
+    pub snaplen: u16,
+    /// Most frames to capture in a second, unless the filter says otherwise
+    #[serde(default = "default_rate")]
+    pub rate: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FirewallConfig {
+    /// Rules, checked in order; the first one matching a packet's
+    /// destination port and source decides whether it gets in.
+    pub rules: Vec<FirewallRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FirewallRule {
+    /// Destination ports (TCP or UDP) this rule covers. Any port named by a
+    /// rule is closed to sources that no rule for it allows.
+    pub ports: Vec<u16>,
+    /// Source prefixes allowed in, as `address/len` (IPv6, or IPv4 with
+    /// DHCP); if this is empty, any source is.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Most packets to let in through this rule in a second, if limited
+    pub rate: Option<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PtpConfig {
+    /// Frequency of the AHB clock, which drives the MAC's IEEE 1588 clock;
+    /// this must match the clock tree set up by the app's `main`
+    pub ahb_clock_hz: u32,
+}
+
+fn default_snaplen() -> u16 {
+    128
+}
+
+fn default_rate() -> u16 {
+    100
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct BufSize {
+    /// Packets the buffer can hold, which only matters for UDP
+    #[serde(default)]
     pub packets: usize,
     pub bytes: usize,
 }
@@ -79,26 +200,357 @@ pub fn load_net_config() -> Result<NetConfig> {
         _ => (),
     }
 
+    check_vlans(&cfg)?;
+
+    if cfg.dhcp.is_none() {
+        if let Some(name) = cfg.sockets.iter().find(|(_, s)| s.ipv4) {
+            panic!(
+                "socket {} takes IPv4, but dhcp is missing from config",
+                name.0
+            );
+        }
+    }
+
     Ok(cfg)
 }
 
+/// Checks the VLAN layout, and the VLANs that each socket is bound on.
+fn check_vlans(cfg: &NetConfig) -> Result<()> {
+    let Some(vlan) = &cfg.vlan else {
+        if let Some((name, _)) =
+            cfg.sockets.iter().find(|(_, s)| s.vlans.is_some())
+        {
+            bail!("socket {name} lists vlans, but vlan is missing from config");
+        }
+        return Ok(());
+    };
+
+    let vids = vlan.start..vlan.start + vlan.count;
+    if vlan.count == 0 || vids.end > 0xFFF {
+        bail!("invalid VLAN range {vids:#x?} (must be nonempty and < 4096)");
+    }
+    if vlan.count > MAX_VLANS {
+        bail!("too many VLANs ({}; at most {MAX_VLANS})", vlan.count);
+    }
+    if vids.contains(&DROP_VID) {
+        bail!(
+            "VLAN range {vids:#x?} includes {DROP_VID:#x}, which is reserved"
+        );
+    }
+    if let Some(ports) = &vlan.ports {
+        if ports.len() != vlan.count {
+            bail!(
+                "vlan ports has {} entries, but there are {} VLANs",
+                ports.len(),
+                vlan.count
+            );
+        }
+        for (vid, ports) in vids.clone().zip(ports) {
+            if let Some(p) = ports.iter().find(|&&p| p == 0 || p > SWITCH_PORTS)
+            {
+                bail!("VLAN {vid:#x} has invalid port {p}");
+            }
+        }
+    }
+
+    for (name, socket) in &cfg.sockets {
+        let Some(vlans) = &socket.vlans else {
+            continue;
+        };
+        if vlans.is_empty() {
+            bail!("socket {name} isn't bound on any VLANs");
+        }
+        for (i, vid) in vlans.iter().enumerate() {
+            if !vids.contains(&usize::from(*vid)) {
+                bail!(
+                    "socket {name} lists VLAN {vid:#x}, which isn't in the \
+                     range {vids:#x?}"
+                );
+            }
+            if vlans[..i].contains(vid) {
+                bail!("socket {name} lists VLAN {vid:#x} twice");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the indices (from 0, in VID order) of the VLANs that `socket` is
+/// bound on. Without VLANs, there's just the one interface, index 0.
+pub fn socket_vlans(config: &NetConfig, socket: &SocketConfig) -> Vec<usize> {
+    match (&config.vlan, &socket.vlans) {
+        (None, _) => vec![0],
+        (Some(vlan), None) => (0..vlan.count).collect(),
+        (Some(vlan), Some(vids)) => (0..vlan.count)
+            .filter(|i| vids.contains(&((vlan.start + i) as u16)))
+            .collect(),
+    }
+}
+
+pub fn generate_dhcp_consts(
+    config: &NetConfig,
+    mut out: impl std::io::Write,
+) -> Result<()> {
+    use std::net::Ipv4Addr;
+
+    let dhcp = config.dhcp.as_ref().unwrap();
+    let fallback = match &dhcp.fallback {
+        Some(f) => {
+            let (addr, len) = f.split_once('/').ok_or_else(|| {
+                anyhow::anyhow!("bad DHCP fallback {f:?}: expected a.b.c.d/len")
+            })?;
+            let addr: Ipv4Addr = addr.parse()?;
+            let len: u8 = len.parse()?;
+            if len > 32 {
+                anyhow::bail!("bad DHCP fallback {f:?}: prefix too long");
+            }
+            format!("Some(({:?}, {len}))", addr.octets())
+        }
+        None => "None".to_string(),
+    };
+    let gateway = match &dhcp.gateway {
+        Some(g) => {
+            if dhcp.fallback.is_none() {
+                anyhow::bail!("DHCP gateway given without a fallback address");
+            }
+            format!("Some({:?})", g.parse::<Ipv4Addr>()?.octets())
+        }
+        None => "None".to_string(),
+    };
+    writeln!(
+        out,
+        "
+pub const DHCP_TIMEOUT_MS: u64 = {};
+pub const DHCP_FALLBACK: Option<([u8; 4], u8)> = {fallback};
+pub const DHCP_FALLBACK_GATEWAY: Option<[u8; 4]> = {gateway};
+",
+        dhcp.timeout_ms
+    )?;
+    Ok(())
+}
+
+pub fn generate_mdns_consts(
+    config: &NetConfig,
+    mut out: impl std::io::Write,
+) -> Result<()> {
+    let mdns = config.mdns.as_ref().unwrap();
+    let socket = config.sockets.get(&mdns.socket).ok_or_else(|| {
+        anyhow::anyhow!("mDNS socket {:?} is not in the config", mdns.socket)
+    })?;
+
+    // RFC 6763 section 7: an underscore-prefixed name, then the protocol.
+    let labels: Vec<&str> = mdns.service.split('.').collect();
+    let good = match labels[..] {
+        [name, proto] => {
+            name.len() <= 16
+                && name.starts_with('_')
+                && name[1..]
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && (proto == "_udp" || proto == "_tcp")
+        }
+        _ => false,
+    };
+    if !good {
+        anyhow::bail!(
+            "bad mDNS service {:?}: expected _name._udp or _name._tcp",
+            mdns.service
+        );
+    }
+    let kind = if socket.kind == "tcp" { "_tcp" } else { "_udp" };
+    if labels[1] != kind {
+        anyhow::bail!(
+            "mDNS service {:?} doesn't match socket {:?}, which is {}",
+            mdns.service,
+            mdns.socket,
+            socket.kind,
+        );
+    }
+
+    let board =
+        build_util::target_board().unwrap_or_else(|| "hubris".to_string());
+    writeln!(
+        out,
+        "
+pub const MDNS_SERVICE: &[&[u8]] = &[b{:?}, b{:?}];
+pub const MDNS_PORT: u16 = {};
+pub const MDNS_BOARD: &str = {board:?};
+",
+        labels[0], labels[1], socket.port
+    )?;
+    Ok(())
+}
+
+pub fn generate_capture_consts(
+    config: &NetConfig,
+    mut out: impl std::io::Write,
+) -> Result<()> {
+    let capture = config.capture.as_ref().unwrap();
+    let collector: std::net::SocketAddrV6 =
+        capture.collector.parse().map_err(|e| {
+            anyhow::anyhow!(
+                "bad capture collector {:?}: {e}; expected [address]:port",
+                capture.collector
+            )
+        })?;
+    if capture.snaplen == 0 || capture.rate == 0 {
+        anyhow::bail!("capture snaplen and rate must be nonzero");
+    }
+    writeln!(
+        out,
+        "
+pub const CAPTURE_COLLECTOR: [u8; 16] = {:?};
+pub const CAPTURE_PORT: u16 = {};
+pub const CAPTURE_SNAPLEN: u16 = {};
+pub const CAPTURE_RATE: u16 = {};
+",
+        collector.ip().octets(),
+        collector.port(),
+        capture.snaplen,
+        capture.rate,
+    )?;
+    Ok(())
+}
+
+/// Nanoseconds the MAC's IEEE 1588 clock moves on by at each tick; this must
+/// match `PTP_TICK_NS` in the Ethernet driver.
+const PTP_TICK_NS: u64 = 20;
+
+pub fn generate_ptp_consts(
+    config: &NetConfig,
+    mut out: impl std::io::Write,
+) -> Result<()> {
+    let ptp = config.ptp.as_ref().unwrap();
+    // The clock ticks each time adding the addend to a 32-bit accumulator at
+    // the AHB clock rate carries, so the AHB clock must be faster than the
+    // ticks for the addend to fit.
+    let tick_hz = 1_000_000_000 / PTP_TICK_NS;
+    let ahb_hz = u64::from(ptp.ahb_clock_hz);
+    if ahb_hz <= tick_hz {
+        anyhow::bail!(
+            "ptp ahb-clock-hz must be more than {tick_hz}, for {PTP_TICK_NS} \
+             ns ticks"
+        );
+    }
+    let addend = (tick_hz << 32) / ahb_hz;
+    writeln!(out, "pub const PTP_ADDEND: u32 = {addend:#x};")?;
+    Ok(())
+}
+
+pub fn generate_firewall_consts(
+    config: &NetConfig,
+    mut out: impl std::io::Write,
+) -> Result<()> {
+    let firewall = config.firewall.as_ref().unwrap();
+    writeln!(
+        out,
+        "pub const FIREWALL_RULES: [crate::firewall::Rule; {}] = [",
+        firewall.rules.len()
+    )?;
+    for (i, rule) in firewall.rules.iter().enumerate() {
+        if rule.ports.is_empty() {
+            anyhow::bail!("firewall rule {i} has no ports");
+        }
+        if rule.rate == Some(0) {
+            anyhow::bail!("firewall rule {i} has a rate of 0");
+        }
+        writeln!(out, "    crate::firewall::Rule {{")?;
+        writeln!(out, "        ports: &{:?},", rule.ports)?;
+        writeln!(out, "        sources: &[")?;
+        for source in &rule.sources {
+            let (addr, len) = parse_prefix(source).map_err(|e| {
+                anyhow::anyhow!(
+                    "bad firewall source {source:?}: {e}; expected address/len"
+                )
+            })?;
+            if addr.to_ipv4_mapped().is_some() && config.dhcp.is_none() {
+                anyhow::bail!(
+                    "firewall source {source:?} is IPv4, \
+                     but dhcp is missing from config"
+                );
+            }
+            writeln!(
+                out,
+                "            crate::firewall::Prefix {{ \
+                 addr: {:?}, len: {len} }},",
+                addr.octets()
+            )?;
+        }
+        writeln!(out, "        ],")?;
+        writeln!(out, "        rate: {:?},", rule.rate)?;
+        writeln!(out, "    }},")?;
+    }
+    writeln!(out, "];")?;
+    Ok(())
+}
+
+/// Parses `address/len`, with IPv4 prefixes turned into IPv4-mapped IPv6 ones
+/// so that the firewall only has to deal with the one kind.
+fn parse_prefix(s: &str) -> Result<(std::net::Ipv6Addr, u8)> {
+    let (addr, len) = s
+        .split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("missing prefix length"))?;
+    let len: u8 = len.parse()?;
+    match addr.parse::<std::net::IpAddr>()? {
+        std::net::IpAddr::V6(_) if len > 128 => {
+            anyhow::bail!("prefix length is more than 128")
+        }
+        std::net::IpAddr::V4(_) if len > 32 => {
+            anyhow::bail!("prefix length is more than 32")
+        }
+        std::net::IpAddr::V6(a) => Ok((a, len)),
+        std::net::IpAddr::V4(a) => Ok((a.to_ipv6_mapped(), len + 96)),
+    }
+}
+
 pub fn generate_vlan_consts(
     config: &NetConfig,
     mut out: impl std::io::Write,
-) -> Result<(), std::io::Error> {
-    let vlan = config.vlan.unwrap();
+) -> Result<()> {
+    let vlan = config.vlan.as_ref().unwrap();
     let end = vlan.start + vlan.count;
-    if end > 0xFFF {
-        panic!("Invalid VLAN range (must be < 4096)");
-    }
+
+    // Switch ports carrying each VLAN, as a mask with bit 0 for port 1
+    let ports: Vec<u8> = match &vlan.ports {
+        Some(ports) => ports
+            .iter()
+            .map(|ports| ports.iter().fold(0, |m, p| m | (1 << (p - 1))))
+            .collect(),
+        None => (0..vlan.count)
+            .map(|i| if i < SWITCH_PORTS.into() { 1 << i } else { 0 })
+            .collect(),
+    };
+
+    // VLANs each socket is bound on, as a mask with bit `i` for the VLAN at
+    // index `i`
+    let sockets: Vec<u16> = config
+        .sockets
+        .values()
+        .map(|s| socket_vlans(config, s).iter().fold(0, |m, i| m | (1 << i)))
+        .collect();
+
     writeln!(
         out,
         "
 pub const VLAN_RANGE: core::ops::Range<u16> = {:#x}..{:#x};
 pub const VLAN_COUNT: usize = {};
+#[allow(unused)]
+pub const VLAN_SWITCH_PORTS: [u8; {}] = [{}];
+#[allow(unused)]
+pub const SOCKET_VLANS: [u16; {}] = {sockets:?};
 ",
-        vlan.start, end, vlan.count
-    )
+        vlan.start,
+        end,
+        vlan.count,
+        vlan.count,
+        ports
+            .iter()
+            .map(|p| format!("{p:#04b}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+        sockets.len(),
+    )?;
+    Ok(())
 }
 
 pub fn generate_socket_enum(
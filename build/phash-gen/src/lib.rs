@@ -2,13 +2,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{collections::HashSet, hash::Hash};
+use std::{cmp::Reverse, collections::HashSet, hash::Hash};
 
 use anyhow::{bail, Result};
 use rand::prelude::*;
 use rand_chacha::ChaCha20Rng;
 
-use phash::PerfectHash;
+use phash::{mix, PerfectHash};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -64,6 +64,11 @@ where
 
         bail!("Could not generate perfect hash");
     }
+
+    /// Returns the fraction of slots in the table which hold a value
+    pub fn density(&self) -> f64 {
+        density(self.values.iter().flatten().count(), self.values.len())
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -152,6 +157,141 @@ where
 
         bail!("Could not generate perfect hash");
     }
+
+    /// Returns the fraction of slots in the sub-tables which hold a value
+    pub fn density(&self) -> f64 {
+        density(
+            self.values.iter().flatten().flatten().count(),
+            self.values.iter().map(Vec::len).sum(),
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// An owned two-level perfect hash, for use in codegen; see
+/// `phash::DisplacedPerfectHashMap` for the runtime side.
+pub struct OwnedDisplacedPerfectHashMap<K, V> {
+    pub m: u32,
+    pub g: Vec<u32>,
+    pub values: Vec<Option<(K, V)>>,
+}
+
+impl<K, V> OwnedDisplacedPerfectHashMap<K, V>
+where
+    K: PerfectHash + Hash + Eq,
+{
+    /// Average number of keys in each first-level bucket
+    const BUCKET_SIZE: usize = 4;
+
+    /// Tries to find a seed for each bucket, when keys are sorted into
+    /// buckets by `m`, so that every key lands in its own slot.
+    ///
+    /// Buckets are placed largest first, while the table is still mostly
+    /// empty. Returns the seeds, or `None` if some bucket wouldn't fit.
+    fn place(
+        values: &[(K, V)],
+        m: u32,
+        buckets: usize,
+        slots: usize,
+        rng: &mut ChaCha20Rng,
+    ) -> Option<Vec<u32>> {
+        const SEED_TRIES: usize = 10_000;
+
+        let mut members = vec![vec![]; buckets];
+        for (i, (k, _v)) in values.iter().enumerate() {
+            members[mix(k.phash(m)) % buckets].push(i);
+        }
+        let mut order = (0..buckets).collect::<Vec<_>>();
+        order.sort_by_key(|&b| Reverse(members[b].len()));
+
+        let mut taken = vec![false; slots];
+        let mut g = vec![0u32; buckets];
+        let mut pos = vec![];
+        for b in order {
+            if members[b].is_empty() {
+                break;
+            }
+            let seed = (0..SEED_TRIES).find_map(|_| {
+                let seed: u32 = rng.gen();
+                pos.clear();
+                for &i in &members[b] {
+                    let j = mix(values[i].0.phash(seed)) % slots;
+                    if taken[j] || pos.contains(&j) {
+                        return None;
+                    }
+                    pos.push(j);
+                }
+                Some(seed)
+            })?;
+            for &j in &pos {
+                taken[j] = true;
+            }
+            g[b] = seed;
+        }
+        Some(g)
+    }
+
+    /// Attempt to generate a perfect hash for the given input data
+    ///
+    /// This tries to fill the table completely, and only adds spare slots if
+    /// it can't.
+    pub fn build(values: Vec<(K, V)>) -> Result<Self> {
+        if values.iter().map(|v| &v.0).collect::<HashSet<_>>().len()
+            != values.len()
+        {
+            bail!("Cannot build a perfect hash with duplicate keys");
+        }
+        if values.is_empty() {
+            return Ok(Self {
+                m: 0,
+                g: vec![],
+                values: vec![],
+            });
+        }
+
+        const TRY_COUNT: usize = 100;
+        let n = values.len();
+        let buckets = (n + Self::BUCKET_SIZE - 1) / Self::BUCKET_SIZE;
+        let mut sizes = vec![n, n + n / 16, n + n / 8, n + n / 4, n + n / 2];
+        sizes.push(2 * n);
+        sizes.dedup();
+
+        let mut rng = ChaCha20Rng::seed_from_u64(0x1de);
+        for slots in sizes {
+            for _ in 0..TRY_COUNT {
+                let m = rng.gen();
+                let Some(g) =
+                    Self::place(&values, m, buckets, slots, &mut rng)
+                else {
+                    continue;
+                };
+                let mut out = (0..slots).map(|_| None).collect::<Vec<_>>();
+                for (k, v) in values.into_iter() {
+                    let i = mix(k.phash(m)) % buckets;
+                    let j = mix(k.phash(g[i])) % slots;
+                    assert!(out[j].is_none());
+                    out[j] = Some((k, v));
+                }
+                return Ok(Self { m, g, values: out });
+            }
+        }
+
+        bail!("Could not generate perfect hash");
+    }
+
+    /// Returns the fraction of slots in the table which hold a value
+    pub fn density(&self) -> f64 {
+        density(self.values.iter().flatten().count(), self.values.len())
+    }
+}
+
+fn density(used: usize, slots: usize) -> f64 {
+    if slots == 0 {
+        1.0
+    } else {
+        used as f64 / slots as f64
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -286,6 +426,67 @@ mod tests {
         assert!(OwnedNestedPerfectHashMap::build(values).is_ok());
     }
 
+    /// Builds a displaced hash of `keys`, and checks that every key (and
+    /// nothing else) can be found with the runtime lookup.
+    fn check_displaced<K>(keys: Vec<K>, missing: K, empty: K) -> f64
+    where
+        K: PerfectHash + Hash + Eq + Copy,
+    {
+        let values = keys.iter().map(|&k| (k, ())).collect();
+        let owned = OwnedDisplacedPerfectHashMap::build(values).unwrap();
+        let table = owned
+            .values
+            .iter()
+            .map(|v| v.unwrap_or((empty, ())))
+            .collect::<Vec<_>>();
+        let map = phash::DisplacedPerfectHashMap {
+            m: owned.m,
+            g: &owned.g,
+            values: &table,
+        };
+        for k in keys {
+            assert!(map.get(k).is_some());
+        }
+        assert!(map.get(missing).is_none());
+        owned.density()
+    }
+
+    #[test]
+    fn displaced_hash() {
+        let keys = (0..1024).step_by(3).map(U).collect();
+        let density = check_displaced(keys, U(1), U(u32::MAX));
+        assert!(density > 0.9, "density {density}");
+    }
+
+    #[test]
+    fn displaced_hash_high_bits() {
+        // These only differ above bit 20, which a bare multiplication doesn't
+        // carry down into the low bits
+        let keys = (0..200).map(|i| U(i << 20)).collect();
+        check_displaced(keys, U(1), U(u32::MAX));
+    }
+
+    #[test]
+    fn displaced_tuple_hash() {
+        let keys = (0..40)
+            .flat_map(|t| (0..8).map(move |n| U2(t, 1 << n)))
+            .collect();
+        check_displaced(keys, U2(41, 1), U2(u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn displaced_empty() {
+        let owned = OwnedDisplacedPerfectHashMap::<U, ()>::build(vec![]);
+        assert!(owned.unwrap().values.is_empty());
+    }
+
+    #[test]
+    fn density() {
+        let values = (0..8).map(|i| (U(i), ())).collect();
+        let owned = OwnedPerfectHashMap::build(values).unwrap();
+        assert_eq!(owned.density(), 8.0 / owned.values.len() as f64);
+    }
+
     #[test]
     fn relative_primes() {
         let values = vec![U(5), U(7)];
@@ -83,6 +83,16 @@ pub struct DeviceDescriptorConfig {
     #[serde(default)]
     pub clock_divider: ClockDivider,
     pub cs: Vec<GpioPinConfig>,
+    /// How long a task may hold the controller locked to this device without
+    /// using it before the server takes the lock away, in milliseconds. Zero
+    /// means never, for devices (like FPGA configuration ports) that are
+    /// legitimately held across long gaps.
+    #[serde(default = "default_lock_timeout_ms")]
+    pub lock_timeout_ms: u64,
+}
+
+fn default_lock_timeout_ms() -> u64 {
+    1000
 }
 
 #[derive(Copy, Clone, Debug, Deserialize)]
@@ -131,6 +141,10 @@ impl ToTokens for SpiConfig {
             let cs = &dev.cs;
             let div: syn::Ident =
                 syn::parse_str(&format!("{:?}", dev.clock_divider)).unwrap();
+            let lock_timeout = match dev.lock_timeout_ms {
+                0 => quote::quote! { None },
+                ms => quote::quote! { Some(#ms) },
+            };
             quote::quote! {
                 DeviceDescriptor {
                     mux_index: #mux_index,
@@ -138,6 +152,7 @@ impl ToTokens for SpiConfig {
                     // `spi1` here is _not_ a typo/oversight, the PAC calls all
                     // SPI types spi1.
                     clock_divider: device::spi1::cfg1::MBR_A::#div,
+                    lock_timeout: #lock_timeout,
                 }
             }
         });
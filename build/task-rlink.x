@@ -63,12 +63,28 @@ SECTIONS
     KEEP(*(.task_slot_table));
   }
 
+  /* ## .ringbuf_registry */
+  /* Descriptions of the task's ringbufs. Used to build jefe's registry of
+     ringbufs during packaging. */
+  .ringbuf_registry (INFO) : {
+    . = .;
+    KEEP(*(.ringbuf_registry));
+  }
+
   /* ## .idolatry */
   .idolatry (INFO) : {
     . = .;
     KEEP(*(.idolatry));
   }
 
+  /* ## .idol_hashes */
+  /* Hashes of the Idol interfaces the task has clients and servers for. Used
+     to check that clients match their servers during packaging. */
+  .idol_hashes (INFO) : {
+    . = .;
+    KEEP(*(.idol_hashes));
+  }
+
   /* ## Discarded sections */
   /DISCARD/ :
   {
@@ -72,6 +72,14 @@ SECTIONS
     KEEP(*(.task_slot_table));
   }
 
+  /* ## .ringbuf_registry */
+  /* Descriptions of the task's ringbufs. Used to build jefe's registry of
+     ringbufs during packaging. */
+  .ringbuf_registry (INFO) : {
+    . = .;
+    KEEP(*(.ringbuf_registry));
+  }
+
   /* ## .idolatry */
   .idolatry (INFO) : {
     . = .;
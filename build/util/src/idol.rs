@@ -0,0 +1,301 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Wrappers around Idol's code generators, which also give clients and
+//! servers a hash of the interface they were generated from.
+//!
+//! A client and server that disagree about an interface -- say, because one
+//! of them comes from an older revision of an API crate -- would otherwise
+//! misinterpret each other's messages. The hash covers the interface as
+//! written (the ops, their arguments, leases, replies and error types), but
+//! not comments or `doc` strings. Types are hashed by name alone: changing
+//! the variants of a `CLike` error or the fields of an argument type in its
+//! API crate doesn't change the hash, so such changes still need care (or a
+//! rename).
+//!
+//! For an interface named `Vpd`, the generated stub gains:
+//!
+//! - `VPD_INTERFACE_HASH`, a `u64`, for code that wants to check at runtime
+//!   (as with `SpRot`, whose server answers an `interface_hash` op that its
+//!   clients compare against their own);
+//! - a record in the `.idol_hashes` section, which `xtask` uses to check that
+//!   each task's clients match the servers at the other end of its task-slots.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::Write;
+
+/// Which end of an interface a stub is for
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Generates a client stub with `idol`, then appends the interface hash.
+pub fn build_client_stub(source: &str, stub_name: &str) -> Result<()> {
+    ::idol::client::build_client_stub(source, stub_name)
+        .map_err(|e| anyhow!("{e}"))?;
+    append_interface_hash(source, stub_name, Role::Client)
+}
+
+/// Generates server support with `idol`, then appends the interface hash.
+pub fn build_server_support(
+    source: &str,
+    stub_name: &str,
+    style: ::idol::server::ServerStyle,
+) -> Result<()> {
+    ::idol::server::build_server_support(source, stub_name, style)
+        .map_err(|e| anyhow!("{e}"))?;
+    append_interface_hash(source, stub_name, Role::Server)
+}
+
+/// Appends the hash of the interface at `source` to the stub `stub_name` in
+/// `OUT_DIR`, which must already have been generated.
+pub fn append_interface_hash(
+    source: &str,
+    stub_name: &str,
+    role: Role,
+) -> Result<()> {
+    let text = std::fs::read_to_string(source)
+        .with_context(|| format!("could not read {source}"))?;
+    let (name, hash) = interface_hash(&text)
+        .with_context(|| format!("could not hash {source}"))?;
+
+    let ident = screaming_snake_case(&name);
+    let record = format!(
+        "{} {name} {hash:#018x}\n",
+        match role {
+            Role::Client => "client",
+            Role::Server => "server",
+        }
+    );
+
+    let path = crate::out_dir().join(stub_name);
+    let mut out = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("could not open {}", path.display()))?;
+    writeln!(out)?;
+    writeln!(out, "/// Hash of the `{name}` Idol interface")?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "pub const {ident}_INTERFACE_HASH: u64 = {hash:#018x};")?;
+    writeln!(out)?;
+    writeln!(out, "#[cfg(target_os = \"none\")]")?;
+    writeln!(out, "#[used]")?;
+    writeln!(out, "#[link_section = \".idol_hashes\"]")?;
+    writeln!(
+        out,
+        "static {ident}_INTERFACE_HASH_RECORD: [u8; {}] = *b{record:?};",
+        record.len()
+    )?;
+    Ok(())
+}
+
+/// Returns the name of the interface in `text`, and its hash.
+pub fn interface_hash(text: &str) -> Result<(String, u64)> {
+    let tokens = strip_docs(tokenize(text)?);
+
+    use Token::{Punct, Str, Word};
+    let name = match tokens.as_slice() {
+        [Word(i), Punct('('), Word(n), Punct(':'), Str(name), ..]
+            if i == "Interface" && n == "name" =>
+        {
+            name.clone()
+        }
+        _ => bail!("interface doesn't start with its name"),
+    };
+
+    // FNV-1a, which (unlike `DefaultHasher`) is the same everywhere
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for &b in bytes {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    for t in &tokens {
+        // Tag each token, so that `ab` and `a b` (or `a` and `"a"`) don't
+        // collide
+        match t {
+            Word(w) => {
+                feed(b"w");
+                feed(w.as_bytes());
+            }
+            Str(s) => {
+                feed(b"s");
+                feed(s.as_bytes());
+            }
+            Punct(p) => feed(&[b'p', *p as u8]),
+        }
+    }
+    Ok((name, hash))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    /// An identifier or number (including any sign or decimal point)
+    Word(String),
+    /// A string literal, with its escapes left as written
+    Str(String),
+    Punct(char),
+}
+
+/// Splits RON into tokens, dropping comments and whitespace.
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut out = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if prev == '*' => break,
+                        Some(c) => prev = c,
+                        None => bail!("unterminated comment"),
+                    }
+                }
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            s.push('\\');
+                            s.push(
+                                chars
+                                    .next()
+                                    .ok_or_else(|| anyhow!("bad escape"))?,
+                            );
+                        }
+                        Some(c) => s.push(c),
+                        None => bail!("unterminated string"),
+                    }
+                }
+                out.push(Token::Str(s));
+            }
+            '(' | ')' | '[' | ']' | '{' | '}' | ':' | ',' => {
+                out.push(Token::Punct(c))
+            }
+            c if c.is_whitespace() => (),
+            c if is_word(c) => {
+                let mut w = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if !is_word(c) {
+                        break;
+                    }
+                    w.push(c);
+                    chars.next();
+                }
+                out.push(Token::Word(w));
+            }
+            c => bail!("unexpected {c:?}"),
+        }
+    }
+    Ok(out)
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// Drops `doc` fields, and the trailing commas that RON allows.
+fn strip_docs(tokens: Vec<Token>) -> Vec<Token> {
+    let mut out: Vec<Token> = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        if let [Token::Word(w), Token::Punct(':'), Token::Str(_), ..] =
+            &tokens[i..]
+        {
+            if w == "doc" {
+                i += 3;
+                if tokens.get(i) == Some(&Token::Punct(',')) {
+                    i += 1;
+                }
+                continue;
+            }
+        }
+        let t = &tokens[i];
+        if matches!(t, Token::Punct(')' | ']' | '}'))
+            && out.last() == Some(&Token::Punct(','))
+        {
+            out.pop();
+        }
+        out.push(t.clone());
+        i += 1;
+    }
+    out
+}
+
+/// Turns an interface name like `UserLeds` into `USER_LEDS`.
+fn screaming_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_uppercase());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VPD: &str = r#"
+        // VPD server IPC interface
+        Interface(
+            name: "Vpd",
+            ops: {
+                "read": (
+                    doc: "Reads a \"block\"",
+                    args: {
+                        "index": "u8",
+                    },
+                    reply: Result(
+                        ok: "[u8; 16]",
+                        err: CLike("VpdError"),
+                    ),
+                ),
+            },
+        )
+    "#;
+
+    #[test]
+    fn ignores_formatting() {
+        let compact = r#"Interface(name:"Vpd",ops:{"read":(args:{"index":"u8"},
+            reply:Result(ok:"[u8; 16]",err:CLike("VpdError")))})"#;
+        let (name, hash) = interface_hash(VPD).unwrap();
+        assert_eq!(name, "Vpd");
+        assert_eq!(hash, interface_hash(compact).unwrap().1);
+    }
+
+    #[test]
+    fn sees_changes() {
+        let (_, hash) = interface_hash(VPD).unwrap();
+        for changed in [
+            VPD.replace("\"u8\"", "\"u16\""),
+            VPD.replace("CLike", "Complex"),
+            VPD.replace("\"read\"", "\"read_block\""),
+        ] {
+            assert_ne!(hash, interface_hash(&changed).unwrap().1, "{changed}");
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(screaming_snake_case("Vpd"), "VPD");
+        assert_eq!(screaming_snake_case("UserLeds"), "USER_LEDS");
+    }
+}
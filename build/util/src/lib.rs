@@ -8,6 +8,8 @@ use serde::de::DeserializeOwned;
 use std::collections::BTreeMap;
 use std::io::Write;
 
+pub mod idol;
+
 /// Reads the given environment variable and marks that it's used
 ///
 /// This ensures a rebuild if the variable changes
@@ -51,12 +53,23 @@ pub fn has_feature(s: &str) -> bool {
     .is_ok()
 }
 
+/// Checks whether we're building for the host simulation (i.e. with
+/// `--cfg hubris_sim`), rather than for a microcontroller
+pub fn is_sim() -> bool {
+    std::env::var("CARGO_CFG_HUBRIS_SIM").is_ok()
+}
+
 /// Exposes the CPU's M-profile architecture version. This isn't available in
 /// rustc's standard environment.
 ///
 /// This will set one of `cfg(armv6m)`, `cfg(armv7m)`, or `cfg(armv8m)`
 /// depending on the value of the `TARGET` environment variable.
 pub fn expose_m_profile() {
+    // The host simulation has no M-profile, and goes without.
+    if is_sim() {
+        return;
+    }
+
     let target = crate::target();
 
     if target.starts_with("thumbv6m") {
@@ -146,14 +159,18 @@ pub fn task_extern_regions<T: DeserializeOwned>() -> Result<IndexMap<String, T>>
     Ok(t)
 }
 
+/// Pulls the full task configuration blocks of every task, by name
+pub fn all_task_full_configs<T: DeserializeOwned>(
+) -> Result<IndexMap<String, toml_task::Task<T>>> {
+    toml_from_env("HUBRIS_ALL_TASK_CONFIGS")?
+        .ok_or_else(|| anyhow!("HUBRIS_ALL_TASK_CONFIGS is not defined"))
+}
+
 /// Pulls the full task configuration block of a different task
 pub fn other_task_full_config<T: DeserializeOwned>(
     name: &str,
 ) -> Result<toml_task::Task<T>> {
-    let mut t = toml_from_env::<IndexMap<String, toml_task::Task<_>>>(
-        "HUBRIS_ALL_TASK_CONFIGS",
-    )?
-    .ok_or_else(|| anyhow!("HUBRIS_ALL_TASK_CONFIGS is not defined"))?;
+    let mut t = all_task_full_configs()?;
     let out = t
         .remove(name)
         .ok_or_else(|| anyhow!("Could not find {name} in tasks"))?;
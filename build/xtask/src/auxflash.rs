@@ -3,24 +3,58 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
 use std::collections::BTreeMap;
 
+/// Tag of the manifest, which is the first chunk in `AUXI`
+const MANIFEST_TAG: &str = "MNFT";
+
+/// Most blobs a manifest may list; this must match `MAX_MANIFEST_BLOBS` in
+/// `drv-auxflash-api`
+const MAX_BLOBS: usize = 8;
+
 /// List of binary blobs to include in the auxiliary flash binary shipped with
 /// this image.  The auxiliary flash is used to offload storage of large
 /// configuration files (e.g. FPGA bitstreams)
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AuxFlash {
+    /// Version of the slot image as a whole, recorded in its manifest
+    #[serde(default)]
+    pub version: u32,
     pub blobs: Vec<AuxFlashBlob>,
 }
 
 /// A single binary blob, encoded into the auxiliary flash file.
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AuxFlashBlob {
     pub file: String,
     pub compress: bool,
     pub tag: String,
+    /// Version of the blob, recorded in the manifest
+    #[serde(default)]
+    pub version: u32,
+}
+
+/// A human-readable copy of the manifest, written alongside the slot image
+#[derive(Clone, Debug, Serialize)]
+pub struct AuxFlashManifest {
+    pub version: u32,
+    pub blobs: Vec<AuxFlashManifestBlob>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuxFlashManifestBlob {
+    pub tag: String,
+    pub file: String,
+    pub version: u32,
+    pub compressed: bool,
+    /// Length of the blob as stored
+    pub len: usize,
+    /// SHA3-256 of the blob as stored, in hex
+    pub checksum: String,
 }
 
 pub type AuxFlashChecksum = [u8; 32];
@@ -33,15 +67,20 @@ pub struct AuxFlashData {
     pub checksums: BTreeMap<String, AuxFlashChecksum>,
     /// Full serialized data
     pub data: Vec<u8>,
+    /// What's in it
+    pub manifest: AuxFlashManifest,
 }
 
 /// Packs a single blob into a TLV-C structure
 fn pack_blob(
     blob: &AuxFlashBlob,
-) -> Result<(tlvc_text::Piece, AuxFlashChecksum)> {
+) -> Result<(tlvc_text::Piece, AuxFlashChecksum, usize)> {
     if blob.tag.len() != 4 {
         bail!("Tag must be a 4-byte value, not '{}'", blob.tag);
     }
+    if blob.tag == MANIFEST_TAG {
+        bail!("Tag {MANIFEST_TAG} is reserved for the manifest");
+    }
     let data = std::fs::read(&blob.file)
         .with_context(|| format!("Could not read blob {}", blob.file))?;
     let data = if blob.compress {
@@ -51,25 +90,73 @@ fn pack_blob(
     };
     let blob_checksum = Sha3_256::digest(&data);
 
+    let len = data.len();
     let tag: [u8; 4] = blob.tag.as_bytes().try_into().unwrap();
     let piece = tlvc_text::Piece::Chunk(
         tlvc_text::Tag::new(tag),
         vec![tlvc_text::Piece::Bytes(data)],
     );
-    Ok((piece, blob_checksum.into()))
+    Ok((piece, blob_checksum.into(), len))
 }
 
-/// Constructs an auxiliary flash image, based on RFD 311
+/// Packs the manifest, as read by `TlvcReadAuxFlash::verify_manifest`: a
+/// header of the slot image's version and the blob count, then for each blob
+/// its tag, version, length, and checksum (all little-endian).
+fn pack_manifest(
+    aux: &AuxFlash,
+    packed: &[(AuxFlashChecksum, usize)],
+) -> tlvc_text::Piece {
+    let mut data = vec![];
+    data.extend(aux.version.to_le_bytes());
+    data.extend((aux.blobs.len() as u32).to_le_bytes());
+    for (blob, (checksum, len)) in aux.blobs.iter().zip(packed) {
+        data.extend(blob.tag.as_bytes());
+        data.extend(blob.version.to_le_bytes());
+        data.extend((*len as u32).to_le_bytes());
+        data.extend(checksum);
+    }
+    let tag: [u8; 4] = MANIFEST_TAG.as_bytes().try_into().unwrap();
+    tlvc_text::Piece::Chunk(
+        tlvc_text::Tag::new(tag),
+        vec![tlvc_text::Piece::Bytes(data)],
+    )
+}
+
+/// Constructs an auxiliary flash image, based on RFD 311, checking that it
+/// fits in a slot of `slot_size` bytes (if known)
 ///
 /// Returns the checksum and the raw data to be saved
-pub fn build_auxflash(aux: &AuxFlash) -> Result<AuxFlashData> {
-    let mut auxi = vec![];
+pub fn build_auxflash(
+    aux: &AuxFlash,
+    slot_size: Option<usize>,
+) -> Result<AuxFlashData> {
+    if aux.blobs.len() > MAX_BLOBS {
+        bail!("auxflash can hold at most {MAX_BLOBS} blobs");
+    }
+    let mut pieces = vec![];
+    let mut packed = vec![];
+    let mut entries = vec![];
     let mut blob_checksums = BTreeMap::new();
     for f in &aux.blobs {
-        let (piece, checksum) = pack_blob(f)?;
-        auxi.push(piece);
+        if blob_checksums.contains_key(&f.tag) {
+            bail!("auxflash has more than one blob tagged {}", f.tag);
+        }
+        let (piece, checksum, len) = pack_blob(f)?;
+        pieces.push(piece);
+        packed.push((checksum, len));
+        entries.push(AuxFlashManifestBlob {
+            tag: f.tag.clone(),
+            file: f.file.clone(),
+            version: f.version,
+            compressed: f.compress,
+            len,
+            checksum: checksum.iter().map(|b| format!("{b:02x}")).collect(),
+        });
         blob_checksums.insert(f.tag.clone(), checksum);
     }
+
+    let mut auxi = vec![pack_manifest(aux, &packed)];
+    auxi.extend(pieces);
     let sha = Sha3_256::digest(tlvc_text::pack(&auxi));
 
     let out = [
@@ -80,9 +167,24 @@ pub fn build_auxflash(aux: &AuxFlash) -> Result<AuxFlashData> {
         tlvc_text::Piece::Chunk(tlvc_text::Tag::new(*b"AUXI"), auxi),
     ];
 
+    let data = tlvc_text::pack(&out);
+    if let Some(slot_size) = slot_size {
+        if data.len() > slot_size {
+            bail!(
+                "auxflash data is {:#x} bytes, but slots are only {:#x}",
+                data.len(),
+                slot_size
+            );
+        }
+    }
+
     Ok(AuxFlashData {
         chck: sha.into(),
         checksums: blob_checksums,
-        data: tlvc_text::pack(&out),
+        data,
+        manifest: AuxFlashManifest {
+            version: aux.version,
+            blobs: entries,
+        },
     })
 }
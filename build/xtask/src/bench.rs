@@ -0,0 +1,293 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Collects the results of the on-target microbenchmarks in
+//! `test/bench-suite`, and reports them as JSON so that runs can be compared
+//! across commits.
+//!
+//! The suite prints each run on ITM (or on whatever `--input` points at, such
+//! as a log or a serial port), bracketed by `bench start N` and `done`. We
+//! wait for the first complete run, skipping any partial one we joined in
+//! the middle of.
+
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::{dist, humility, HumilityArgs};
+
+#[derive(Debug, Serialize)]
+struct Report {
+    app: String,
+    board: String,
+    image: String,
+    git_rev: Option<String>,
+    /// Which of the suite's runs these results come from
+    run: u32,
+    results: Vec<BenchResult>,
+    /// Names of benchmarks that failed on the target
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BenchResult {
+    name: String,
+    iterations: u64,
+    elapsed_ms: u64,
+    /// Bytes moved by each iteration, or 0 for latency benchmarks
+    bytes_per_iteration: u64,
+    ns_per_iteration: f64,
+    bytes_per_second: Option<f64>,
+}
+
+/// A run in progress, accumulated line by line
+#[derive(Default)]
+struct Run {
+    number: u32,
+    results: Vec<BenchResult>,
+    errors: Vec<String>,
+}
+
+/// Feeds one line of output to the parser; returns a run once its `done`
+/// line arrives. Lines we don't recognize (other output sharing the port,
+/// Humility's own chatter) are ignored.
+fn parse_line(current: &mut Option<Run>, line: &str) -> Result<Option<Run>> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["bench", "start", n] => {
+            let number = n
+                .parse()
+                .with_context(|| format!("bad run number in {:?}", line))?;
+            *current = Some(Run {
+                number,
+                ..Default::default()
+            });
+        }
+        ["result", name, iterations, elapsed_ms, bytes] => {
+            if let Some(run) = current {
+                let parse = |s: &str| {
+                    s.parse::<u64>()
+                        .with_context(|| format!("bad result {:?}", line))
+                };
+                let iterations = parse(*iterations)?;
+                let elapsed_ms = parse(*elapsed_ms)?;
+                let bytes = parse(*bytes)?;
+                let ns = elapsed_ms as f64 * 1_000_000.0;
+                let secs = elapsed_ms as f64 / 1000.0;
+                run.results.push(BenchResult {
+                    name: name.to_string(),
+                    iterations,
+                    elapsed_ms,
+                    bytes_per_iteration: bytes,
+                    ns_per_iteration: ns / iterations.max(1) as f64,
+                    bytes_per_second: if bytes != 0 && elapsed_ms != 0 {
+                        Some((bytes * iterations) as f64 / secs)
+                    } else {
+                        None
+                    },
+                });
+            }
+        }
+        ["error", name] => {
+            if let Some(run) = current {
+                run.errors.push(name.to_string());
+            }
+        }
+        ["done"] => return Ok(current.take()),
+        _ => (),
+    }
+    Ok(None)
+}
+
+fn collect(
+    lines: mpsc::Receiver<std::io::Result<String>>,
+    timeout: Duration,
+    verbose: bool,
+) -> Result<Run> {
+    let deadline = Instant::now() + timeout;
+    let mut current = None;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let line = match lines.recv_timeout(left) {
+            Ok(line) => line.context("failed to read benchmark output")?,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                bail!("no complete run within {}s", timeout.as_secs())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                bail!("benchmark output ended before a complete run")
+            }
+        };
+        if verbose {
+            eprintln!("{}", line);
+        }
+        if let Some(run) = parse_line(&mut current, &line)? {
+            return Ok(run);
+        }
+    }
+}
+
+pub fn run(
+    args: &HumilityArgs,
+    image_name: &String,
+    input: Option<&Path>,
+    output: Option<&Path>,
+    timeout: Duration,
+) -> Result<()> {
+    let toml = Config::from_file(&args.cfg)?;
+
+    let run = if let Some(input) = input {
+        let file = File::open(input)
+            .with_context(|| format!("failed to open {}", input.display()))?;
+        collect(humility::lines(BufReader::new(file)), timeout, args.verbose)?
+    } else {
+        let mut child = humility::itm(args, image_name)?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("no stdout from humility"))?;
+        let run = collect(
+            humility::lines(BufReader::new(stdout)),
+            timeout,
+            args.verbose,
+        );
+        // ITM runs until it's stopped, whether or not we got what we wanted
+        let _ = child.kill();
+        let _ = child.wait();
+        run?
+    };
+
+    let git_rev = dist::get_git_status().ok().map(|(rev, dirty)| {
+        if dirty {
+            rev + "-dirty"
+        } else {
+            rev
+        }
+    });
+
+    let report = Report {
+        app: toml.name.clone(),
+        board: toml.board.clone(),
+        image: image_name.clone(),
+        git_rev,
+        run: run.number,
+        results: run.results,
+        errors: run.errors,
+    };
+
+    for r in &report.results {
+        eprint!("{:<24} {:>12.1} ns/iter", r.name, r.ns_per_iteration);
+        if let Some(bps) = r.bytes_per_second {
+            eprint!(" {:>12.1} KiB/s", bps / 1024.0);
+        }
+        eprintln!();
+    }
+
+    let json = serde_json::to_string_pretty(&report)?;
+    match output {
+        Some(path) => {
+            let mut f = File::create(path).with_context(|| {
+                format!("failed to create {}", path.display())
+            })?;
+            writeln!(f, "{}", json)?;
+        }
+        None => println!("{}", json),
+    }
+
+    if !report.errors.is_empty() {
+        bail!("benchmarks failed: {}", report.errors.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(lines: &[&str]) -> Option<Run> {
+        let mut current = None;
+        let mut out = None;
+        for line in lines {
+            assert!(out.is_none(), "run finished before {line:?}");
+            out = parse_line(&mut current, line).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn results() {
+        let run = feed(&[
+            "bench start 3",
+            "result ipc-send 20000 500 0",
+            "result lease-read-1k 1000 250 1024",
+            "done",
+        ])
+        .unwrap();
+        assert_eq!(run.number, 3);
+        assert!(run.errors.is_empty());
+
+        let [ipc, lease] = run.results.as_slice() else {
+            panic!("wrong number of results");
+        };
+        assert_eq!(ipc.name, "ipc-send");
+        assert_eq!(ipc.iterations, 20000);
+        assert_eq!(ipc.ns_per_iteration, 25_000.0);
+        assert_eq!(ipc.bytes_per_second, None);
+
+        assert_eq!(lease.bytes_per_iteration, 1024);
+        assert_eq!(lease.ns_per_iteration, 250_000.0);
+        assert_eq!(lease.bytes_per_second, Some(1024.0 * 4000.0));
+    }
+
+    #[test]
+    fn errors() {
+        let run = feed(&["bench start 0", "error i2c-read", "done"]).unwrap();
+        assert!(run.results.is_empty());
+        assert_eq!(run.errors, ["i2c-read"]);
+    }
+
+    #[test]
+    fn skips_partial_run_and_other_output() {
+        // We joined the previous run halfway through.
+        let run = feed(&[
+            "result ipc-send 20000 500 0",
+            "done",
+            "humility: attached via ST-Link",
+            "bench start 1",
+            "jefe: restarting task",
+            "result ipc-send 10000 500 0",
+            "done",
+        ]);
+        let run = run.unwrap();
+        assert_eq!(run.number, 1);
+        assert_eq!(run.results.len(), 1);
+        assert_eq!(run.results[0].iterations, 10000);
+    }
+
+    #[test]
+    fn incomplete() {
+        assert!(feed(&["bench start 0", "result ipc-send 1 1 0"]).is_none());
+    }
+
+    #[test]
+    fn zero_time() {
+        let run = feed(&["bench start 0", "result spi 0 0 64", "done"]);
+        let result = &run.unwrap().results[0];
+        assert_eq!(result.ns_per_iteration, 0.0);
+        assert_eq!(result.bytes_per_second, None);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut current = None;
+        assert!(parse_line(&mut current, "bench start x").is_err());
+        parse_line(&mut current, "bench start 0").unwrap();
+        assert!(parse_line(&mut current, "result ipc-send 1 -1 0").is_err());
+    }
+}
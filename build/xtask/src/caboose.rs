@@ -0,0 +1,272 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Contents of the default caboose.
+//!
+//! With `default = true`, the caboose is filled in at build time with the
+//! keys that identify the image (see `drv_caboose::tags`), then any `entries`
+//! from the app.toml, then any `--caboose KEY=VALUE` given to `dist`:
+//!
+//! ```toml
+//! [caboose]
+//! region = "flash"
+//! size = 256
+//! default = true
+//! entries = { CHAN = "dev", FPGA = "1.2.3" }
+//! ```
+//!
+//! Keys are four ASCII characters, and each may only be set once: an entry
+//! can't replace a default key, and the command line can't replace the
+//! app.toml. The entries must fit in the caboose, less the two words the
+//! system reserves; if they don't, we say how much each one takes.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::config::{CabooseConfig, Config};
+
+/// Version in a default caboose. The Git hash is included under its own key,
+/// so we don't include it here.
+const DEFAULT_VERSION: &str = "0.0.0-git";
+
+/// One key for the caboose, and where it came from (for error messages)
+struct Entry<'a> {
+    key: &'a str,
+    value: &'a str,
+    source: &'static str,
+}
+
+/// Parses `KEY=VALUE` arguments from the command line.
+pub fn parse_args(args: &[String]) -> Result<Vec<(String, String)>> {
+    args.iter()
+        .map(|arg| {
+            let (key, value) = arg.split_once('=').ok_or_else(|| {
+                anyhow!("caboose entry {arg:?} should be KEY=VALUE")
+            })?;
+            Ok((key.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+fn tag(key: &str) -> Result<[u8; 4]> {
+    let tag: [u8; 4] = key.as_bytes().try_into().map_err(|_| {
+        anyhow!("caboose key {key:?} must be exactly four characters")
+    })?;
+    if !tag.iter().all(|b| b.is_ascii_graphic()) {
+        bail!("caboose key {key:?} must be printable ASCII");
+    }
+    Ok(tag)
+}
+
+/// Checks that extra entries are only given where they can be used.
+pub fn check(toml: &Config, cli: &[(String, String)]) -> Result<()> {
+    match &toml.caboose {
+        None if !cli.is_empty() => {
+            bail!("caboose entries given, but the app has no [caboose]")
+        }
+        Some(c) if !c.default && (!c.entries.is_empty() || !cli.is_empty()) => {
+            bail!(
+                "caboose entries need `default = true`, since otherwise the \
+                 caboose is left for other tools to fill in"
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Builds the TLV-C contents of a default caboose, which go between its
+/// leading magic word and trailing size.
+pub fn contents(
+    toml: &Config,
+    caboose: &CabooseConfig,
+    git_rev: &str,
+    cli: &[(String, String)],
+) -> Result<Vec<u8>> {
+    pack(&toml.name, &toml.board, caboose, git_rev, cli)
+}
+
+fn pack(
+    name: &str,
+    board: &str,
+    caboose: &CabooseConfig,
+    git_rev: &str,
+    cli: &[(String, String)],
+) -> Result<Vec<u8>> {
+    let mut entries = vec![
+        Entry {
+            key: "NAME",
+            value: name,
+            source: "default",
+        },
+        Entry {
+            key: "BORD",
+            value: board,
+            source: "default",
+        },
+        Entry {
+            key: "GITC",
+            value: git_rev,
+            source: "default",
+        },
+        Entry {
+            key: "VERS",
+            value: DEFAULT_VERSION,
+            source: "default",
+        },
+    ];
+    entries.extend(caboose.entries.iter().map(|(k, v)| Entry {
+        key: k,
+        value: v,
+        source: "app.toml",
+    }));
+    entries.extend(cli.iter().map(|(k, v)| Entry {
+        key: k,
+        value: v,
+        source: "command line",
+    }));
+
+    let mut seen = BTreeMap::new();
+    let mut pieces = vec![];
+    for e in &entries {
+        let tag = tag(e.key)?;
+        if let Some(prev) = seen.insert(tag, e.source) {
+            bail!(
+                "caboose key {} is set more than once (by {} and {})",
+                e.key,
+                prev,
+                e.source
+            );
+        }
+        pieces.push(tlvc_text::Piece::Chunk(
+            tlvc_text::Tag::new(tag),
+            vec![tlvc_text::Piece::Bytes(e.value.as_bytes().to_vec())],
+        ));
+    }
+
+    let data = tlvc_text::pack(&pieces);
+    let budget = caboose.size as usize - 2 * std::mem::size_of::<u32>();
+    if data.len() > budget {
+        let mut msg = format!(
+            "caboose entries need {} bytes, but a {}-byte caboose only has \
+             room for {}:",
+            data.len(),
+            caboose.size,
+            budget
+        );
+        for (e, p) in entries.iter().zip(pieces) {
+            let len = tlvc_text::pack(&[p]).len();
+            msg += &format!("\n  {} ({}): {} bytes", e.key, e.source, len);
+        }
+        bail!(msg);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caboose(size: u32, entries: &[(&str, &str)]) -> CabooseConfig {
+        CabooseConfig {
+            tasks: vec![],
+            region: "flash".to_owned(),
+            size,
+            default: true,
+            entries: entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    fn cli(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Reads the keys back out, skipping the checksums: each chunk is a tag,
+    /// a length and a header checksum, then the body padded to a word, then
+    /// the body checksum.
+    fn keys(mut data: &[u8]) -> Vec<(String, String)> {
+        let mut out = vec![];
+        while !data.is_empty() {
+            let tag = String::from_utf8(data[..4].to_vec()).unwrap();
+            let len = u32::from_le_bytes(data[4..8].try_into().unwrap());
+            let len = len as usize;
+            let value = String::from_utf8(data[12..][..len].to_vec()).unwrap();
+            out.push((tag, value));
+            data = &data[12 + ((len + 3) & !3) + 4..];
+        }
+        out
+    }
+
+    #[test]
+    fn defaults_then_app_then_cli() {
+        let c = caboose(256, &[("CHAN", "dev")]);
+        let data =
+            pack("app", "board", &c, "abc123", &cli(&[("FPGA", "1.2.3")]))
+                .unwrap();
+        assert_eq!(
+            keys(&data),
+            cli(&[
+                ("NAME", "app"),
+                ("BORD", "board"),
+                ("GITC", "abc123"),
+                ("VERS", DEFAULT_VERSION),
+                ("CHAN", "dev"),
+                ("FPGA", "1.2.3"),
+            ])
+        );
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let err = pack("a", "b", &caboose(256, &[("NAME", "x")]), "c", &[])
+            .unwrap_err();
+        assert!(err.to_string().contains("by default and app.toml"), "{err}");
+
+        let c = caboose(256, &[("CHAN", "dev")]);
+        let err =
+            pack("a", "b", &c, "c", &cli(&[("CHAN", "prod")])).unwrap_err();
+        assert!(
+            err.to_string().contains("by app.toml and command line"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn bad_keys() {
+        for key in ["CHA", "CHANN", "CH N", "CH\tN"] {
+            let c = caboose(256, &[(key, "x")]);
+            assert!(pack("a", "b", &c, "c", &[]).is_err(), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn size_budget() {
+        let fits = pack("a", "b", &caboose(256, &[]), "c", &[]).unwrap();
+        let size = fits.len() as u32 + 8;
+        pack("a", "b", &caboose(size, &[]), "c", &[]).unwrap();
+
+        let err =
+            pack("a", "b", &caboose(size - 1, &[]), "c", &[]).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("only has room for"), "{msg}");
+        for key in ["NAME", "BORD", "GITC", "VERS"] {
+            assert!(msg.contains(key), "{msg}");
+        }
+    }
+
+    #[test]
+    fn args() {
+        assert_eq!(
+            parse_args(&["CHAN=dev".to_owned(), "X=a=b".to_owned()]).unwrap(),
+            cli(&[("CHAN", "dev"), ("X", "a=b")])
+        );
+        assert!(parse_args(&["CHAN".to_owned()]).is_err());
+    }
+}
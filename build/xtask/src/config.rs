@@ -14,23 +14,43 @@ use serde::{Deserialize, Serialize};
 use crate::auxflash::{build_auxflash, AuxFlash, AuxFlashData};
 use lpc55_areas::{DebugSettings, DefaultIsp, ROTKeyStatus};
 
-/// A `PatchedConfig` allows a minimal form of inheritance between TOML files
-/// Specifically, it allows you to **add features** to specific tasks; nothing
-/// else.
+/// An `app.toml` may build on another by naming it in `inherit` (relative to
+/// the inheriting file), and is then an overlay on the file it inherits:
+///
+/// - Tables are merged key by key, recursively, so an overlay need only
+///   mention what it changes.
+/// - Any other value -- including an array, or an array of tables such as
+///   `[[config.i2c.devices]]` -- replaces the inherited value outright.
+/// - `remove` lists keys, as dotted paths, to delete from the inherited config
+///   before the overlay is merged, e.g. `remove = ["tasks.ping"]`.
+/// - `[patches]` is applied last, once the whole chain is merged: it renames
+///   the image and adds features to tasks, which must not already have them.
+///
+/// Overlays may themselves be inherited. Paths within the config (such as
+/// `chip`) are relative to the file at the bottom of the chain, which inherits
+/// nothing. `cargo xtask resolve` prints the result of merging.
 ///
 /// Here's an example:
 /// ```toml
-/// name = "sidecar-c-lab"
+/// inherit = "rev-c.toml"
 ///
 /// [patches]
-/// inherit = "rev-c.toml"
+/// name = "sidecar-c-lab"
 /// features.sequencer = ["stay-in-a2"]
 /// ```
-#[derive(Clone, Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct PatchedConfig {
-    inherit: String,
-    patches: ConfigPatches,
+struct Layered {
+    /// The file at the bottom of the chain
+    root: PathBuf,
+    /// The contents of `root`
+    root_contents: String,
+    /// Whether there's anything above `root`
+    inherited: bool,
+    /// The merged config, without patches
+    value: ordered_toml::Value,
+    /// Whether any overlay changes more than its `patches`
+    overlaid: bool,
+    /// Patches from the whole chain, combined
+    patches: Option<ConfigPatches>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -41,6 +61,163 @@ pub struct ConfigPatches {
     features: IndexMap<String, Vec<String>>,
 }
 
+impl Layered {
+    fn load(cfg: &Path, hasher: &mut DefaultHasher) -> Result<Self> {
+        let contents = std::fs::read_to_string(cfg)
+            .with_context(|| format!("could not read {}", cfg.display()))?;
+
+        // Accumulate the contents into the buildhash here, so that we hash
+        // every file in the chain.
+        hasher.write(contents.as_bytes());
+
+        let mut value: ordered_toml::Value = ordered_toml::from_str(&contents)
+            .with_context(|| format!("could not parse {}", cfg.display()))?;
+        let table = value.as_table_mut().unwrap();
+        let Some(inherit) = remove_key(table, "inherit") else {
+            return Ok(Self {
+                root: cfg.to_owned(),
+                root_contents: contents,
+                inherited: false,
+                value,
+                overlaid: false,
+                patches: None,
+            });
+        };
+        let inherit = inherit
+            .as_str()
+            .ok_or_else(|| anyhow!("`inherit` must be a path"))?;
+        let file = cfg.parent().unwrap().join(inherit);
+        let mut out = Self::load(&file, hasher)
+            .with_context(|| format!("could not load template {file:?}"))?;
+        out.inherited = true;
+
+        let remove: Vec<String> = match remove_key(table, "remove") {
+            Some(r) => r.try_into().context("`remove` must be a list")?,
+            None => vec![],
+        };
+        for path in &remove {
+            remove_path(&mut out.value, path)?;
+        }
+
+        if let Some(p) = remove_key(table, "patches") {
+            let p: ConfigPatches = p.try_into().context("bad [patches]")?;
+            match &mut out.patches {
+                Some(prev) => {
+                    prev.name = p.name;
+                    for (task, features) in p.features {
+                        prev.features.entry(task).or_default().extend(features);
+                    }
+                }
+                None => out.patches = Some(p),
+            }
+        }
+
+        out.overlaid |= !remove.is_empty() || !table.is_empty();
+        merge(&mut out.value, value);
+        Ok(out)
+    }
+
+    /// Returns the config as TOML, with patches applied.
+    fn resolved(&self) -> Result<String> {
+        if !self.inherited {
+            return Ok(self.root_contents.clone());
+        }
+        let mut value = self.value.clone();
+        if let Some(patches) = &self.patches {
+            apply_patches(&mut value, patches)?;
+        }
+        Ok(ordered_toml::to_string(&value)?)
+    }
+}
+
+/// Removes `key` from `table`, keeping the order of the rest (which matters
+/// for tasks).
+fn remove_key(
+    table: &mut ordered_toml::value::Table,
+    key: &str,
+) -> Option<ordered_toml::Value> {
+    let mut removed = None;
+    *table = std::mem::take(table)
+        .into_iter()
+        .filter_map(|(k, v)| {
+            if k == key {
+                removed = Some(v);
+                None
+            } else {
+                Some((k, v))
+            }
+        })
+        .collect();
+    removed
+}
+
+/// Removes the key at the dotted `path` from `value`.
+fn remove_path(value: &mut ordered_toml::Value, path: &str) -> Result<()> {
+    let mut parts: Vec<&str> = path.split('.').collect();
+    let key = parts.pop().unwrap();
+    let mut table = value.as_table_mut().unwrap();
+    for part in parts {
+        table = table
+            .get_mut(part)
+            .and_then(ordered_toml::Value::as_table_mut)
+            .ok_or_else(|| anyhow!("cannot remove {path}: no table {part}"))?;
+    }
+    remove_key(table, key)
+        .ok_or_else(|| anyhow!("cannot remove {path}: not present"))?;
+    Ok(())
+}
+
+/// Merges `overlay` into `base`.
+fn merge(base: &mut ordered_toml::Value, overlay: ordered_toml::Value) {
+    match (base, overlay) {
+        (
+            ordered_toml::Value::Table(base),
+            ordered_toml::Value::Table(overlay),
+        ) => {
+            for (k, v) in overlay {
+                match base.get_mut(&k) {
+                    Some(b) => merge(b, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn apply_patches(
+    value: &mut ordered_toml::Value,
+    patches: &ConfigPatches,
+) -> Result<()> {
+    let table = value.as_table_mut().unwrap();
+    table.insert(
+        "name".to_string(),
+        ordered_toml::Value::String(patches.name.clone()),
+    );
+    for (task, features) in &patches.features {
+        let t = table
+            .get_mut("tasks")
+            .and_then(|t| t.get_mut(task))
+            .and_then(ordered_toml::Value::as_table_mut)
+            .ok_or_else(|| anyhow!("No such task {task}"))?;
+        let list = t
+            .entry("features")
+            .or_insert(ordered_toml::Value::Array(vec![]))
+            .as_array_mut()
+            .ok_or_else(|| anyhow!("Task {task} has malformed features"))?;
+        for f in features {
+            let value = ordered_toml::Value::String(f.to_owned());
+            if list.contains(&value) {
+                bail!("Task {task} already contains feature {f}");
+            }
+            list.push(value);
+        }
+    }
+    Ok(())
+}
+
 /// A `RawConfig` represents an `app.toml` file that has been deserialized,
 /// but may not be ready for use.  In particular, we use the `chip` field
 /// to load a second file containing peripheral register addresses.
@@ -60,6 +237,9 @@ struct RawConfig {
     image_names: Vec<String>,
     #[serde(default)]
     external_images: Vec<String>,
+    /// Whether to pass the memory map to builds, for the `memory-map` crate
+    #[serde(default)]
+    memory_map: bool,
     #[serde(default)]
     signing: Option<RoTMfgSettings>,
     stacksize: Option<u32>,
@@ -92,9 +272,12 @@ pub struct Config {
     pub config: Option<ordered_toml::Value>,
     pub buildhash: u64,
     pub app_toml_path: PathBuf,
+    /// The merged config, without patches, if `app_toml_path` is overlaid by
+    /// more than patches
+    pub overlay: Option<String>,
     pub patches: Option<ConfigPatches>,
     pub auxflash: Option<AuxFlashData>,
-    pub dice_mfg: Option<Output>,
+    pub memory_map: bool,
     pub caboose: Option<CabooseConfig>,
 }
 
@@ -126,9 +309,14 @@ pub struct CabooseConfig {
     /// user-accessible space is 8 bytes less than this value.
     pub size: u32,
 
-    /// If `true`, populates the caboose with default values using `hubtools`
+    /// If `true`, populates the caboose with default values at build time
     #[serde(default)]
     pub default: bool,
+
+    /// Extra keys for a default caboose (such as a build channel, or an FPGA
+    /// bitstream version), as four-character tags mapped to values
+    #[serde(default)]
+    pub entries: IndexMap<String, String>,
 }
 
 impl Config {
@@ -136,43 +324,19 @@ impl Config {
         Self::from_file_with_hasher(cfg, DefaultHasher::new())
     }
 
+    /// Returns the `app.toml` at `cfg` as TOML, with overlays merged and
+    /// patches applied.
+    pub fn resolve(cfg: &Path) -> Result<String> {
+        Layered::load(cfg, &mut DefaultHasher::new())?.resolved()
+    }
+
     fn from_file_with_hasher(
         cfg: &Path,
         mut hasher: DefaultHasher,
     ) -> Result<Self> {
-        let cfg_contents = std::fs::read(&cfg)
-            .with_context(|| format!("could not read {}", cfg.display()))?;
-
-        // Accumulate the contents into the buildhash here, so that we hash both
-        // the inheritance file and the target if this is an `PatchedConfig`
-        hasher.write(&cfg_contents);
-
-        // Minimal TOML file inheritance, to enable features on a per-task basis
-        if let Ok(inherited) =
-            toml::from_str::<PatchedConfig>(std::str::from_utf8(&cfg_contents)?)
-        {
-            let file = cfg.parent().unwrap().join(&inherited.inherit);
-            let mut original = Config::from_file_with_hasher(&file, hasher)
-                .context(format!("Could not load template from {file:?}"))?;
-            original.name = inherited.patches.name.to_owned();
-            for (task, features) in &inherited.patches.features {
-                let t = original
-                    .tasks
-                    .get_mut(task)
-                    .ok_or_else(|| anyhow!("No such task {task}"))?;
-                for f in features {
-                    if t.features.contains(f) {
-                        bail!("Task {task} already contains feature {f}");
-                    }
-                    t.features.push(f.to_owned());
-                }
-            }
-            original.patches = Some(inherited.patches);
-            return Ok(original);
-        }
-
-        let toml: RawConfig =
-            toml::from_str(std::str::from_utf8(&cfg_contents)?)?;
+        let layered = Layered::load(cfg, &mut hasher)?;
+        let cfg = layered.root.as_path();
+        let toml: RawConfig = toml::from_str(&layered.resolved()?)?;
         if toml.tasks.contains_key("kernel") {
             bail!("'kernel' is reserved and cannot be used as a task name");
         }
@@ -205,6 +369,14 @@ impl Config {
 
         let buildhash = hasher.finish();
 
+        // If overlays change more than patches, the archive needs the merged
+        // config, since the root file alone doesn't describe the image.
+        let overlay = if layered.overlaid {
+            Some(ordered_toml::to_string(&layered.value)?)
+        } else {
+            None
+        };
+
         let img_names = if toml.image_names.is_empty() {
             vec!["default".to_string()]
         } else {
@@ -214,12 +386,9 @@ impl Config {
         // Build the auxiliary flash data so that we can inject it as an
         // environmental variable in the build system.
         let auxflash = match &toml.auxflash {
-            Some(a) => Some(build_auxflash(a)?),
-            None => None,
-        };
-
-        let dice_mfg = match outputs.get("flash") {
-            Some(f) => f.iter().find(|&o| o.name == "dice-mfg").cloned(),
+            Some(a) => {
+                Some(build_auxflash(a, auxflash_slot_size(&toml.config))?)
+            }
             None => None,
         };
 
@@ -243,8 +412,9 @@ impl Config {
             auxflash,
             buildhash,
             app_toml_path: cfg.to_owned(),
-            patches: None,
-            dice_mfg,
+            overlay,
+            patches: layered.patches,
+            memory_map: toml.memory_map,
             caboose: toml.caboose,
         })
     }
@@ -338,10 +508,10 @@ impl Config {
             env.insert("HUBRIS_APP_CONFIG".to_string(), app_config);
         }
 
-        if let Some(output) = &self.dice_mfg {
+        if self.memory_map {
             env.insert(
-                "HUBRIS_DICE_MFG".to_string(),
-                toml::to_string(&output).unwrap(),
+                "HUBRIS_MEMORY_MAP".to_string(),
+                toml::to_string(&self.outputs).unwrap(),
             );
         }
 
@@ -608,6 +778,18 @@ impl MpuAlignment {
     }
 }
 
+/// Returns the size of an auxflash slot, from `[config.auxflash]` (following
+/// `drv-auxflash-api`), if it's there.
+fn auxflash_slot_size(config: &Option<ordered_toml::Value>) -> Option<usize> {
+    let aux = config.as_ref()?.get("auxflash")?;
+    let get = |key: &str| aux.get(key).and_then(|v| v.as_integer());
+    let memory_size = get("memory-size")?;
+    let slot_count = get("slot-count")?;
+    let spare_sectors = get("spare-sectors").unwrap_or(0);
+    let slot_memory = memory_size.checked_sub(spare_sectors * (64 << 10))?;
+    usize::try_from(slot_memory.checked_div(slot_count)?).ok()
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct RoTMfgSettings {
@@ -631,6 +813,25 @@ pub struct RoTMfgSettings {
     #[serde(default = "DefaultIsp::auto")]
     pub default_isp: DefaultIsp,
     pub boot_error_gpio: RoTBootErrorPin,
+    #[serde(default)]
+    pub signer: Signer,
+    /// A signed stage0 binary, relative to the app.toml. If given, it's
+    /// bundled with the signed images into `bundle.bin`, ready to flash.
+    pub stage0: Option<PathBuf>,
+}
+
+/// How images are signed
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Signer {
+    /// With `certs.private-key`, as part of the build
+    #[default]
+    Local,
+    /// By running a command (such as `support/remote-sign.sh`) from the
+    /// app.toml's directory, with the path to the unsigned archive appended.
+    /// The command must replace the archive with a signed one; the private
+    /// key in `certs` isn't read.
+    Command(Vec<String>),
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -648,6 +849,24 @@ pub struct Kernel {
     pub stacksize: Option<u32>,
     #[serde(default)]
     pub features: Vec<String>,
+    /// What the kernel does with an interrupt that no task has hooked
+    #[serde(default)]
+    pub spurious_irqs: SpuriousIrqs,
+}
+
+/// Handling for spurious interrupts, as written in an app.toml
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpuriousIrqs {
+    /// Panic, naming the interrupt
+    #[default]
+    Panic,
+    /// Count the interrupt, for the supervisor to read with
+    /// `kipc::read_spurious_irqs`, disabling it if it storms
+    Count,
+    /// As with `Count`, and also post the supervisor's `spurious-irq`
+    /// notification
+    Notify,
 }
 
 fn default_name() -> String {
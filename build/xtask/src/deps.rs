@@ -0,0 +1,292 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checks the dependencies between tasks, so that mistakes in the app.toml
+//! fail the build rather than faulting a task at runtime.
+//!
+//! Before building, we check the graph of task-slots: each slot must name a
+//! task in the image, and the slots mustn't form a cycle (some call around
+//! which would have to go downhill).
+//!
+//! Once the tasks are linked, each one's `.task_slot_table` tells us which
+//! slots its code uses. (Using a slot that the app.toml doesn't declare is
+//! caught when the table is patched.) A declared slot that isn't used gets a
+//! warning, since it still constrains priorities. And a used slot must point
+//! at a task serving an Idol interface the caller has a client for, if it
+//! serves any at all -- otherwise the slot names the wrong server, and every
+//! call through it would fail.
+//!
+//! Finally, each task's `.idol_hashes` records the interfaces its clients and
+//! servers were generated from (see `build_util::idol`). A client must have
+//! been generated from the same interface as the server its slot points at,
+//! or the two would misread each other's messages.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use regex::Regex;
+
+use crate::{config::Config, dist::PackageConfig, lsp::PackageGraph};
+
+/// Checks that every task-slot names a task, and that there are no cycles.
+pub fn check_graph(toml: &Config) -> Result<()> {
+    let mut dangling = vec![];
+    for (name, task) in &toml.tasks {
+        for (slot, target) in &task.task_slots {
+            if !toml.tasks.contains_key(target) {
+                dangling.push(format!("{name}.{slot} = {target:?}"));
+            }
+        }
+    }
+    if !dangling.is_empty() {
+        bail!(
+            "task-slots name tasks that aren't in the image: {}",
+            dangling.join(", ")
+        );
+    }
+
+    let mut done = BTreeSet::new();
+    for name in toml.tasks.keys() {
+        find_cycle(toml, name, &mut vec![], &mut done)?;
+    }
+    Ok(())
+}
+
+/// Searches the task-slots depth-first from `name`, with `path` holding the
+/// tasks on the way there, and `done` those already searched.
+fn find_cycle<'a>(
+    toml: &'a Config,
+    name: &'a str,
+    path: &mut Vec<&'a str>,
+    done: &mut BTreeSet<&'a str>,
+) -> Result<()> {
+    if let Some(i) = path.iter().position(|p| *p == name) {
+        let mut cycle = path[i..].to_vec();
+        cycle.push(name);
+        bail!("task-slots form a cycle: {}", cycle.join(" -> "));
+    }
+    if done.contains(name) {
+        return Ok(());
+    }
+
+    path.push(name);
+    for target in toml.tasks[name].task_slots.values() {
+        // A task may hold a slot for itself.
+        if target != name {
+            find_cycle(toml, target, path, done)?;
+        }
+    }
+    path.pop();
+    done.insert(name);
+    Ok(())
+}
+
+/// Checks the task-slots used by each of `tasks`, as linked for `image_name`.
+pub fn check_slots(
+    cfg: &PackageConfig,
+    tasks: &BTreeSet<&str>,
+    image_name: &str,
+) -> Result<()> {
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .no_deps()
+        .features(cargo_metadata::CargoOpt::AllFeatures)
+        .exec()
+        .context("failed to run cargo metadata")?;
+    let packages = PackageGraph::new(metadata);
+    let mut interfaces = Interfaces::new(&packages);
+
+    let mut hashes = BTreeMap::new();
+    for name in cfg.toml.tasks.keys() {
+        if tasks.contains(name.as_str()) {
+            let bin = std::fs::read(cfg.img_file(name, image_name))?;
+            let elf = goblin::elf::Elf::parse(&bin)?;
+            let h = idol_hashes(&bin, &elf)
+                .with_context(|| format!("reading {name}'s Idol hashes"))?;
+            hashes.insert(name.as_str(), h);
+        }
+    }
+
+    let mut errors = vec![];
+    for (name, task) in &cfg.toml.tasks {
+        if !tasks.contains(name.as_str()) {
+            continue;
+        }
+        let bin = std::fs::read(cfg.img_file(name, image_name))?;
+        let elf = goblin::elf::Elf::parse(&bin)?;
+        let used: BTreeSet<&str> =
+            crate::task_slot::get_task_slot_table_entries(&bin, &elf)?
+                .into_iter()
+                .map(|e| e.slot_name)
+                .collect();
+
+        for slot in task.task_slots.keys() {
+            if !used.contains(slot.as_str()) {
+                println!(
+                    "{}: task {name} declares task-slot {slot}, but doesn't \
+                     use it",
+                    "warning".bold().yellow(),
+                );
+            }
+        }
+
+        let (clients, _) = interfaces.of(&task.name, &task.features)?;
+        for slot in used {
+            let target_name = &task.task_slots[slot];
+            let target = &cfg.toml.tasks[target_name];
+            let (_, servers) = interfaces.of(&target.name, &target.features)?;
+            if !servers.is_empty() && servers.is_disjoint(&clients) {
+                errors.push(format!(
+                    "task {name} calls {target_name} through task-slot \
+                     {slot}, but has no client for what it serves ({})",
+                    servers.iter().cloned().collect::<Vec<_>>().join(", ")
+                ));
+            }
+
+            let (Some((clients, _)), Some((_, servers))) =
+                (hashes.get(name.as_str()), hashes.get(target_name.as_str()))
+            else {
+                continue;
+            };
+            for (iface, server) in servers {
+                match clients.get(iface) {
+                    Some(client) if client != server => {
+                        errors.push(format!(
+                            "task {name}'s client for {iface} ({client:#x}) \
+                             doesn't match the interface {target_name} \
+                             serves ({server:#x}); are they built from \
+                             different revisions?"
+                        ));
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "task-slots point at the wrong tasks, or servers their clients \
+             don't match:\n{}",
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Finds the Idol interfaces a task has clients and servers for, from the
+/// calls to `idol` in the build scripts of the packages it's built from.
+struct Interfaces<'a> {
+    packages: &'a PackageGraph,
+    stubs: Regex,
+    /// Clients and servers by package
+    cache: BTreeMap<String, (BTreeSet<String>, BTreeSet<String>)>,
+}
+
+impl<'a> Interfaces<'a> {
+    fn new(packages: &'a PackageGraph) -> Self {
+        let stubs = Regex::new(concat!(
+            r#"(build_client_stub|build_server_support"#,
+            r#"|build_restricted_server_support)\(\s*"([^"]+)""#,
+        ))
+        .unwrap();
+        Self {
+            packages,
+            stubs,
+            cache: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the interfaces that the crate `name`, built with `features`,
+    /// has clients and servers for, by their `.idol` file names.
+    fn of(
+        &mut self,
+        name: &str,
+        features: &[String],
+    ) -> Result<(BTreeSet<String>, BTreeSet<String>)> {
+        let mut clients = BTreeSet::new();
+        let mut servers = BTreeSet::new();
+        let packages = self.packages;
+        for pkg in packages.resolve(name, features).keys() {
+            let (c, s) = self.of_package(pkg)?;
+            clients.extend(c.iter().cloned());
+            servers.extend(s.iter().cloned());
+        }
+        Ok((clients, servers))
+    }
+
+    fn of_package(
+        &mut self,
+        pkg: &str,
+    ) -> Result<&(BTreeSet<String>, BTreeSet<String>)> {
+        if !self.cache.contains_key(pkg) {
+            let mut clients = BTreeSet::new();
+            let mut servers = BTreeSet::new();
+            let dir = self.packages.manifest_dir(pkg).unwrap();
+            let build_rs = dir.join("build.rs");
+            if build_rs.exists() {
+                let src = std::fs::read_to_string(&build_rs)
+                    .with_context(|| format!("could not read {build_rs:?}"))?;
+                for c in self.stubs.captures_iter(&src) {
+                    let idol = Path::new(&c[2]).file_name().unwrap();
+                    let idol = idol.to_string_lossy().into_owned();
+                    if &c[1] == "build_client_stub" {
+                        clients.insert(idol);
+                    } else {
+                        servers.insert(idol);
+                    }
+                }
+            }
+            self.cache.insert(pkg.to_owned(), (clients, servers));
+        }
+        Ok(&self.cache[pkg])
+    }
+}
+
+/// Name of the section holding a task's Idol interface hashes
+const IDOL_HASHES_SECTION: &str = ".idol_hashes";
+
+/// Interface hashes by interface name
+type IdolHashes = BTreeMap<String, u64>;
+
+/// Reads the hashes of the interfaces a task has clients and servers for.
+/// Each is a line of the form `client Vpd 0x0123456789abcdef`.
+fn idol_hashes(
+    bin: &[u8],
+    elf: &goblin::elf::Elf,
+) -> Result<(IdolHashes, IdolHashes)> {
+    let mut clients = BTreeMap::new();
+    let mut servers = BTreeMap::new();
+    let section = crate::elf::get_section_by_name(elf, IDOL_HASHES_SECTION);
+    let Some(section) = section else {
+        return Ok((clients, servers));
+    };
+    let start = section.sh_offset as usize;
+    let text = std::str::from_utf8(&bin[start..][..section.sh_size as usize])?;
+    for line in text.lines().filter(|l| !l.is_empty()) {
+        let (role, iface, hash) = match line.split(' ').collect::<Vec<_>>()[..]
+        {
+            [role, iface, hash] => (role, iface, hash),
+            _ => bail!("malformed record {line:?}"),
+        };
+        let hash = hash
+            .strip_prefix("0x")
+            .and_then(|h| u64::from_str_radix(h, 16).ok())
+            .with_context(|| format!("malformed hash in {line:?}"))?;
+        let map = match role {
+            "client" => &mut clients,
+            "server" => &mut servers,
+            _ => bail!("unknown role in {line:?}"),
+        };
+        // The same client may be linked in from more than one place, which is
+        // fine as long as they agree.
+        if let Some(prev) = map.insert(iface.to_owned(), hash) {
+            if prev != hash {
+                bail!("conflicting {role}s for {iface}: {prev:#x}, {hash:#x}");
+            }
+        }
+    }
+    Ok((clients, servers))
+}
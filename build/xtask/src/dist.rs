@@ -16,11 +16,13 @@ use atty::Stream;
 use indexmap::IndexMap;
 use multimap::MultiMap;
 use path_slash::{PathBufExt, PathExt};
+use serde::Serialize;
 use zerocopy::AsBytes;
 
 use crate::{
-    config::{BuildConfig, CabooseConfig, Config, ConfigPatches},
-    elf,
+    caboose,
+    config::{BuildConfig, CabooseConfig, Config, ConfigPatches, SpuriousIrqs},
+    deps, elf, repro, ringbuf, sign,
     sizes::load_task_size,
     task_slot,
 };
@@ -50,14 +52,15 @@ pub struct PackageConfig {
     /// Path to the `app.toml` file being built
     ///
     /// If this app is built using inheritance, `app_toml_file` refers to the
-    /// **root** TOML file (and patches are in `self.patches`)
+    /// **root** TOML file (and patches are in `self.patches`); if overlays
+    /// change more than patches, the archive gets `toml.overlay` instead.
     app_toml_file: PathBuf,
 
     /// Patches from TOML inheritance mechanism
     patches: Option<ConfigPatches>,
 
     /// Directory containing the `app.toml` file being built
-    app_src_dir: PathBuf,
+    pub app_src_dir: PathBuf,
 
     /// Loaded configuration
     pub toml: Config,
@@ -86,6 +89,12 @@ pub struct PackageConfig {
     /// allows us to force a rebuild when the linker scripts change, which
     /// is not normally tracked by `cargo build`.
     link_script_hash: u64,
+
+    /// Settings for a reproducible build, if this is one
+    reproducible: Option<repro::Settings>,
+
+    /// Extra caboose entries from the command line, as `(key, value)`
+    caboose_entries: Vec<(String, String)>,
 }
 
 impl PackageConfig {
@@ -140,6 +149,8 @@ impl PackageConfig {
             host_triple,
             remap_paths: Self::remap_paths()?,
             link_script_hash: extra_hash.finish(),
+            reproducible: None,
+            caboose_entries: vec![],
         })
     }
 
@@ -221,8 +232,20 @@ pub fn package(
     app_toml: &Path,
     tasks_to_build: Option<Vec<String>>,
     dirty_ok: bool,
+    reproducible: bool,
+    caboose_entries: &[(String, String)],
 ) -> Result<BTreeMap<String, AllocationMap>> {
-    let cfg = PackageConfig::new(app_toml, verbose, edges)?;
+    let mut cfg = PackageConfig::new(app_toml, verbose, edges)?;
+    caboose::check(&cfg.toml, caboose_entries)?;
+    cfg.caboose_entries = caboose_entries.to_vec();
+    if reproducible {
+        if dirty_ok {
+            bail!("a reproducible build can't skip cleaning");
+        }
+        let settings = repro::Settings::new()?;
+        cfg.remap_paths.extend(settings.remap_paths.clone());
+        cfg.reproducible = Some(settings);
+    }
 
     // Verify that our dump configuration is correct (or absent)
     check_dump_config(&cfg.toml)?;
@@ -235,6 +258,7 @@ pub fn package(
             (true, task_names.iter().map(|p| p.as_str()).collect())
         } else {
             assert!(!cfg.toml.tasks.contains_key("kernel"));
+            deps::check_graph(&cfg.toml)?;
             check_task_priorities(&cfg.toml)?;
             (
                 false,
@@ -296,6 +320,10 @@ pub fn package(
         let (allocs, memories) = allocated
             .get(image_name)
             .ok_or_else(|| anyhow!("failed to get image name"))?;
+        write_allocations(
+            &cfg.img_file("allocations.toml", image_name),
+            allocs,
+        )?;
 
         // Check external regions, which cannot be used for normal allocations
         let alloc_regions = allocs.regions();
@@ -346,6 +374,21 @@ pub fn package(
             })
             .collect::<Result<_, _>>()?;
 
+        // Every image links the same tasks, so checking the first is enough.
+        if !partial_build && *image_name == cfg.toml.image_names[0] {
+            deps::check_slots(&cfg, &tasks_to_build, image_name)?;
+        }
+
+        // Now that every task has been linked, and its ringbufs have
+        // addresses, we can tell the supervisor where they are.
+        if !partial_build {
+            resolve_ringbuf_registry(
+                &cfg,
+                image_name,
+                &mut all_output_sections,
+            )?;
+        }
+
         // Add an empty output section for the caboose
         //
         // This has to be done before building the kernel, because the caboose
@@ -371,12 +414,28 @@ pub fn package(
             // can decode the caboose start by looking at it while only knowing
             // total image size.  The first word is CABOOSE_MAGIC, so we can
             // check that a valid caboose exists.  Everything else is left to
-            // the user.
+            // the user, unless they've asked for a default caboose.
             let mut caboose_data = vec![0xFF; caboose.size as usize];
             caboose_data[caboose.size as usize - 4..]
                 .copy_from_slice(&caboose.size.to_le_bytes());
             caboose_data[0..4]
                 .copy_from_slice(&abi::CABOOSE_MAGIC.to_le_bytes());
+            if caboose.default {
+                let (git_rev, git_dirty) = get_git_status()?;
+                let git_rev = format!(
+                    "{}{}",
+                    git_rev,
+                    if git_dirty { "-dirty" } else { "" }
+                );
+                let data = caboose::contents(
+                    &cfg.toml,
+                    caboose,
+                    &git_rev,
+                    &cfg.caboose_entries,
+                )
+                .context("building default caboose")?;
+                caboose_data[4..][..data.len()].copy_from_slice(&data);
+            }
 
             all_output_sections.insert(
                 caboose_range.start,
@@ -456,88 +515,29 @@ pub fn package(
         write_gdb_script(&cfg, image_name)?;
         let archive_name = build_archive(&cfg, image_name, raw_image)?;
 
-        // Post-build modifications: populate a default caboose if requested
-        if let Some(caboose) = &cfg.toml.caboose {
-            if caboose.default {
-                let mut archive =
-                    hubtools::RawHubrisArchive::load(&archive_name)
-                        .context("loading archive with hubtools")?;
-                // The Git hash is included in the default caboose under the key
-                // `GITC`, so we don't include it in the pseudo-version.
-                archive
-                    .write_default_caboose(Some(&"0.0.0-git".to_owned()))
-                    .context("writing caboose into archive")?;
-                archive.overwrite().context("overwriting archive")?;
-            }
-        }
-
         // Post-build modifications: sign the image if requested
         if let Some(signing) = &cfg.toml.signing {
-            let mut archive = hubtools::RawHubrisArchive::load(&archive_name)
-                .context("loading archive with hubtools")?;
-            let private_key = lpc55_sign::cert::read_rsa_private_key(
-                &cfg.app_src_dir.join(&signing.certs.private_key),
-            )
-            .with_context(|| {
-                format!(
-                    "could not read private key {:?}",
-                    signing.certs.private_key
-                )
-            })?;
-
-            // Certificate paths are relative to the app.toml.  Resolve them
-            // before attempting to read them.
-            let root_cert_abspaths: Vec<PathBuf> = signing
-                .certs
-                .root_certs
-                .iter()
-                .map(|c| cfg.app_src_dir.join(c))
-                .collect();
-            let root_certs = lpc55_sign::cert::read_certs(&root_cert_abspaths)?;
-
-            let signing_cert_abspaths: Vec<PathBuf> = signing
-                .certs
-                .signing_certs
-                .iter()
-                .map(|c| cfg.app_src_dir.join(c))
-                .collect();
-            let signing_certs =
-                lpc55_sign::cert::read_certs(&signing_cert_abspaths)?;
-
-            archive.sign(
-                signing_certs,
-                root_certs.clone(),
-                &private_key,
-                0, // execution address (TODO)
-            )?;
+            sign::sign(&cfg, signing, &archive_name)?;
+        }
 
-            let boot_pin = lpc55_areas::BootErrorPin::new(
-                signing.boot_error_gpio.port,
-                signing.boot_error_gpio.pin,
-            )
-            .ok_or_else(|| {
-                anyhow!("invalid boot error pin {:?}", signing.boot_error_gpio)
-            })?;
-            archive.set_cmpa(
-                signing.dice.clone(),
-                signing.enable_secure_boot,
-                signing.cmpa_settings,
-                signing.default_isp,
-                lpc55_areas::BootSpeed::Fro96mhz,
-                boot_pin,
-                root_certs,
-            )?;
-            archive.set_cfpa(
-                signing.cfpa_settings,
-                [signing.rotk0, signing.rotk1, signing.rotk2, signing.rotk3],
-                0,
-            )?;
-            archive.overwrite()?;
+        if cfg.reproducible.is_some() {
+            repro::normalize_archive(&archive_name)
+                .context("normalizing archive")?;
         }
 
         // Unzip the signed + caboose'd images into our build directory
         let archive = hubtools::RawHubrisArchive::load(&archive_name)
             .context("loading archive with hubtools")?;
+        if cfg.toml.signing.is_some() {
+            let bin = archive
+                .extract_file("img/final.bin")
+                .context("extracting signed file from archive")?;
+            if let Err(e) = sign::validate(&cfg.toml, image_name, &bin) {
+                // Don't leave a bad archive lying around to be flashed.
+                std::fs::remove_file(&archive_name)?;
+                return Err(e.context(format!("signed image {image_name}")));
+            }
+        }
         for ext in ["elf", "bin"] {
             let name = format!("final.{}", ext);
             let file_data = archive
@@ -546,6 +546,12 @@ pub fn package(
             std::fs::write(cfg.img_file(&name, image_name), file_data)?;
         }
     }
+
+    if let Some(stage0) =
+        cfg.toml.signing.as_ref().and_then(|s| s.stage0.as_ref())
+    {
+        sign::bundle(&cfg, stage0)?;
+    }
     Ok(allocated)
 }
 
@@ -567,6 +573,11 @@ fn write_gdb_script(cfg: &PackageConfig, image_name: &str) -> Result<()> {
             cfg.dist_file(name).to_slash().unwrap()
         )?;
     }
+    // These name paths on this machine, which would make the archive depend
+    // on where it was built.
+    if cfg.reproducible.is_some() {
+        return Ok(());
+    }
     for (path, remap) in &cfg.remap_paths {
         let mut path_str = path
             .to_str()
@@ -600,6 +611,7 @@ fn build_archive(
         - app.toml is the config file used to build the firmware.\n\
         - git-rev is the commit it was built from, with optional dirty flag.\n\
         - info/ contains human-readable data like logs.\n\
+        - info/allocations.toml is where each task was placed in memory.\n\
         - elf/ contains ELF images for all firmware components.\n\
         - elf/tasks/ contains each task by name.\n\
         - elf/kernel is the kernel.\n\
@@ -619,7 +631,10 @@ fn build_archive(
         .text("image-name", image_name)
         .context("failed writing `image-name`")?;
 
-    archive.copy(&cfg.app_toml_file, "app.toml")?;
+    match &cfg.toml.overlay {
+        Some(overlay) => archive.text("app.toml", overlay)?,
+        None => archive.copy(&cfg.app_toml_file, "app.toml")?,
+    }
     if let Some(patches) = cfg.patches.as_ref() {
         archive
             .text(
@@ -635,6 +650,11 @@ fn build_archive(
     let chip_filename = chip_file.file_name().unwrap();
     archive.copy(&chip_file, &chip_filename)?;
 
+    archive.copy(
+        cfg.img_file("allocations.toml", image_name),
+        PathBuf::from("info").join("allocations.toml"),
+    )?;
+
     archive
         .text(
             "memory.toml",
@@ -684,6 +704,11 @@ fn build_archive(
         std::fs::write(&file, &auxflash.data)
             .context(format!("Failed to write auxi to {:?}", file))?;
         archive.copy(cfg.dist_file("auxi.tlvc"), img_dir.join("auxi.tlvc"))?;
+
+        let file = cfg.dist_file("auxi-manifest.toml");
+        std::fs::write(&file, toml::to_string(&auxflash.manifest)?)
+            .context(format!("Failed to write manifest to {:?}", file))?;
+        archive.copy(&file, img_dir.join("auxi-manifest.toml"))?;
     }
 
     // Copy `openocd.cfg` into the archive if it exists; it's not used for
@@ -821,20 +846,7 @@ fn check_rebuild(toml: &Config) -> Result<()> {
     // if we need to rebuild, we should clean everything before we start building
     if rebuild {
         println!("app.toml has changed; rebuilding all tasks");
-        let mut names = vec![toml.kernel.name.as_str()];
-        for name in toml.tasks.keys() {
-            // This may feel redundant: don't we already have the name?
-            // Well, consider our supervisor:
-            //
-            // [tasks.jefe]
-            // name = "task-jefe"
-            //
-            // The "name" in the key is `jefe`, but the package (crate)
-            // name is in `tasks.jefe.name`, and that's what we need to
-            // give to `cargo`.
-            names.push(toml.tasks[name].name.as_str());
-        }
-        cargo_clean(&names, &toml.target)?;
+        clean(toml)?;
     }
 
     // now that we're clean, update our buildstamp file; any failure to build
@@ -844,6 +856,25 @@ fn check_rebuild(toml: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Runs `cargo clean` on the kernel and every task
+pub fn clean(toml: &Config) -> Result<()> {
+    let mut names = vec![toml.kernel.name.as_str()];
+    for name in toml.tasks.keys() {
+        // This may feel redundant: don't we already have the name?
+        // Well, consider our supervisor:
+        //
+        // [tasks.jefe]
+        // name = "task-jefe"
+        //
+        // The "name" in the key is `jefe`, but the package (crate)
+        // name is in `tasks.jefe.name`, and that's what we need to
+        // give to `cargo`.
+        names.push(toml.tasks[name].name.as_str());
+    }
+    cargo_clean(&names, &toml.target)?;
+    Ok(())
+}
+
 #[derive(Debug, Hash)]
 struct LoadSegment {
     source_file: PathBuf,
@@ -966,13 +997,18 @@ fn task_entry_point(
         &mut symbol_table,
     )?;
 
-    if let Some(required) = task_toml.max_sizes.get("flash") {
-        if flash > *required as usize {
+    if let Some(&required) = task_toml.max_sizes.get("flash") {
+        if flash > required as usize {
+            let needed =
+                cfg.toml.suggest_memory_region_size(name, flash as u64);
+            let mut sizes = task_toml.max_sizes.clone();
+            sizes.insert("flash".to_string(), needed as u32);
             bail!(
-                "{} has insufficient flash: specified {} bytes, needs {}",
+                "{} has insufficient flash: specified {} bytes, needs {}\n{}",
                 task_toml.name,
                 required,
-                flash
+                flash,
+                max_sizes_diff(&cfg.toml, &[(name, sizes)].into())
             );
         }
     }
@@ -1449,12 +1485,16 @@ fn build(
              -C link-arg=-z -C link-arg=max-page-size=0x20 \
              -C llvm-args=--enable-machine-outliner=never \
              -C overflow-checks=y \
+             -Z emit-stack-sizes \
              -C metadata={} \
              {}
              ",
             cfg.link_script_hash, remap_path_prefix,
         ),
     );
+    if let Some(r) = &cfg.reproducible {
+        cmd.env("SOURCE_DATE_EPOCH", r.source_date_epoch.to_string());
+    }
     cmd.arg("--");
 
     // We use attributes to conditionally import based on feature flags;
@@ -1756,14 +1796,21 @@ pub fn allocate_all(
         let mut task_requests: BTreeMap<&str, BTreeMap<u32, VecDeque<&str>>> =
             BTreeMap::new();
 
+        // Tasks that have outgrown their max-sizes, with the max-sizes they'd
+        // need. We gather them all up so that they can be fixed in one go.
+        let mut outgrown: BTreeMap<&str, IndexMap<String, u32>> =
+            BTreeMap::new();
+
         for name in tasks.keys() {
             for (mem, amt) in task_sizes[name.as_str()].iter() {
                 let bytes = toml.suggest_memory_region_size(name, *amt);
                 if let Some(r) = tasks[name].max_sizes.get(&mem.to_string()) {
                     if bytes > *r as u64 {
-                        bail!(
-                        "task {}: needs {} bytes of {} but max-sizes limits it to {}",
-                        name, bytes, mem, r);
+                        outgrown
+                            .entry(name.as_str())
+                            .or_insert_with(|| tasks[name].max_sizes.clone())
+                            .insert(mem.to_string(), bytes.try_into().unwrap());
+                        continue;
                     }
                 }
                 task_requests
@@ -1775,11 +1822,32 @@ pub fn allocate_all(
             }
         }
 
+        if !outgrown.is_empty() {
+            bail!(
+                "{} task(s) need more than max-sizes allows\n{}",
+                outgrown.len(),
+                max_sizes_diff(toml, &outgrown)
+            );
+        }
+
         // Okay! Do memory types one by one, fitting kernel first.
         for (region, avail) in &mut free {
             let mut k_req = kernel_requests.get(region.as_str());
             let mut t_reqs = task_requests.get_mut(region.as_str());
 
+            // Everything that wants this region, for explaining ourselves if
+            // it won't all fit.
+            let wanted: Vec<(&str, u32)> = k_req
+                .map(|&sz| ("kernel", sz))
+                .into_iter()
+                .chain(t_reqs.iter().flat_map(|map| {
+                    map.iter()
+                        .flat_map(|(&sz, q)| q.iter().map(move |&t| (t, sz)))
+                }))
+                .collect();
+            let capacity = avail.end - avail.start;
+            let exhausted = || region_report(region, capacity, &wanted);
+
             fn reqs_map_not_empty(
                 om: &Option<&mut BTreeMap<u32, VecDeque<&str>>>,
             ) -> bool {
@@ -1808,7 +1876,8 @@ pub fn allocate_all(
                     // The kernel wants in on this.
                     allocs.kernel.insert(
                         region.to_string(),
-                        allocate_k(region, sz, avail)?,
+                        allocate_k(region, sz, avail)
+                            .with_context(exhausted)?,
                     );
                     continue 'fitloop;
                 }
@@ -1824,7 +1893,8 @@ pub fn allocate_all(
                                 .or_default()
                                 .insert(
                                     region.to_string(),
-                                    allocate_one(region, sz, align, avail)?,
+                                    allocate_one(region, sz, align, avail)
+                                        .with_context(exhausted)?,
                                 );
                             continue 'fitloop;
                         }
@@ -1840,7 +1910,8 @@ pub fn allocate_all(
                                 .or_default()
                                 .insert(
                                     region.to_string(),
-                                    allocate_one(region, sz, align, avail)?,
+                                    allocate_one(region, sz, align, avail)
+                                        .with_context(exhausted)?,
                                 );
                             continue 'fitloop;
                         }
@@ -1876,6 +1947,115 @@ pub fn allocate_all(
     Ok(result)
 }
 
+/// Explains why `region`, of `capacity` bytes, can't hold everything in
+/// `wanted`, listing the biggest requests as the likeliest to trim.
+fn region_report(
+    region: &str,
+    capacity: u32,
+    wanted: &[(&str, u32)],
+) -> String {
+    let total: u64 = wanted.iter().map(|&(_, sz)| u64::from(sz)).sum();
+    let mut out = format!(
+        "{region} is over-committed: {} requests total {total} bytes, of \
+         {capacity} available",
+        wanted.len()
+    );
+    if total <= u64::from(capacity) {
+        out += "; they'd fit end to end, but not once each is aligned";
+    }
+
+    let mut biggest = wanted.to_vec();
+    biggest.sort_by_key(|&(name, sz)| (std::cmp::Reverse(sz), name));
+    out += "\nlargest requests (shrink one of these, or grow the region):";
+    for (name, sz) in biggest.iter().take(5) {
+        write!(out, "\n  {name:<24} {sz:>8}").unwrap();
+    }
+    out
+}
+
+/// Formats the changes to app.toml that would give each task in `needed` the
+/// max-sizes it's paired with, as a diff.
+fn max_sizes_diff(
+    toml: &Config,
+    needed: &BTreeMap<&str, IndexMap<String, u32>>,
+) -> String {
+    fn sizes(sizes: &IndexMap<String, u32>) -> String {
+        let fields: Vec<_> = sizes
+            .iter()
+            .map(|(mem, sz)| format!("{mem} = {sz}"))
+            .collect();
+        format!("{{{}}}", fields.join(", "))
+    }
+
+    let mut out = "suggested changes to app.toml:".to_string();
+    for (name, new) in needed {
+        let old = &toml.tasks[*name].max_sizes;
+        write!(
+            out,
+            "\n\n  [tasks.{name}]\n- max-sizes = {}\n+ max-sizes = {}",
+            sizes(old),
+            sizes(new)
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// A region as it appears in `allocations.toml`
+#[derive(Serialize)]
+struct ResolvedRegion {
+    base: String,
+    size: u32,
+}
+
+impl From<&Range<u32>> for ResolvedRegion {
+    fn from(r: &Range<u32>) -> Self {
+        Self {
+            base: format!("{:#010x}", r.start),
+            size: r.end - r.start,
+        }
+    }
+}
+
+/// The resolved memory map of an image, as written to `allocations.toml`
+#[derive(Serialize)]
+struct ResolvedMap<'a> {
+    kernel: BTreeMap<&'a str, ResolvedRegion>,
+    tasks: BTreeMap<&'a str, BTreeMap<&'a str, ResolvedRegion>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caboose: Option<BTreeMap<&'a str, ResolvedRegion>>,
+}
+
+/// Writes out where everything in an image was placed, so that the resolved
+/// map can be reviewed (and diffed between builds) without reading linker
+/// scripts.
+fn write_allocations(path: &Path, allocs: &Allocations) -> Result<()> {
+    fn regions(
+        map: &BTreeMap<String, Range<u32>>,
+    ) -> BTreeMap<&str, ResolvedRegion> {
+        map.iter()
+            .map(|(mem, r)| (mem.as_str(), r.into()))
+            .collect()
+    }
+
+    let map = ResolvedMap {
+        kernel: regions(&allocs.kernel),
+        tasks: allocs
+            .tasks
+            .iter()
+            .map(|(name, map)| (name.as_str(), regions(map)))
+            .collect(),
+        caboose: allocs
+            .caboose
+            .as_ref()
+            .map(|(mem, r)| [(mem.as_str(), r.into())].into()),
+    };
+    let text = toml::to_string(&map)
+        .context("could not serialize allocations.toml")?;
+    std::fs::write(path, text)
+        .with_context(|| format!("could not write {}", path.display()))
+}
+
 fn allocate_k(
     region: &str,
     size: u32,
@@ -2161,10 +2341,28 @@ pub fn make_kconfig(
     // Pare down the list of shared regions.
     flat_shared.retain(|name, _v| used_shared_regions.contains(name.as_str()));
 
+    let spurious_irqs = match toml.kernel.spurious_irqs {
+        SpuriousIrqs::Panic => build_kconfig::SpuriousIrqPolicy::Panic,
+        SpuriousIrqs::Count => build_kconfig::SpuriousIrqPolicy::Count,
+        SpuriousIrqs::Notify => {
+            let (name, supervisor) = toml.tasks.first().unwrap();
+            let mask = supervisor
+                .notification_mask("spurious-irq")
+                .with_context(|| {
+                    format!(
+                        "`spurious-irqs = \"notify\"` needs the supervisor \
+                         ({name}) to have a `spurious-irq` notification"
+                    )
+                })?;
+            build_kconfig::SpuriousIrqPolicy::Notify(mask)
+        }
+    };
+
     Ok(build_kconfig::KernelConfig {
         irqs,
         tasks,
         shared_regions: flat_shared,
+        spurious_irqs,
     })
 }
 
@@ -2348,7 +2546,7 @@ impl Archive {
 ///
 /// - A `String` containing the git commit hash.
 /// - A `bool` indicating whether the repository has uncommitted changes.
-fn get_git_status() -> Result<(String, bool)> {
+pub fn get_git_status() -> Result<(String, bool)> {
     let mut cmd = Command::new("git");
     cmd.arg("rev-parse").arg("HEAD");
     let out = cmd.output()?;
@@ -2386,6 +2584,58 @@ fn cargo_clean(names: &[&str], target: &str) -> Result<()> {
     Ok(())
 }
 
+/// Fills in the supervisor's ringbuf registry, if it has one, with every
+/// task's ringbufs, and reloads the supervisor's image.
+fn resolve_ringbuf_registry(
+    cfg: &PackageConfig,
+    image_name: &str,
+    all_output_sections: &mut BTreeMap<u32, LoadSegment>,
+) -> Result<()> {
+    let supervisor = cfg.toml.tasks.keys().next().unwrap();
+    let supervisor_bin = cfg.img_file(supervisor, image_name);
+    let in_supervisor_bin = std::fs::read(&supervisor_bin)?;
+    let elf = goblin::elf::Elf::parse(&in_supervisor_bin)?;
+    let Some((offset, capacity)) = ringbuf::find_registry(&elf)? else {
+        return Ok(());
+    };
+
+    let mut out_supervisor_bin = in_supervisor_bin.clone();
+    let mut count = 0;
+    for (index, name) in cfg.toml.tasks.keys().enumerate().skip(1) {
+        let task_bin = std::fs::read(cfg.img_file(name, image_name))?;
+        let task_elf = goblin::elf::Elf::parse(&task_bin)?;
+        for entry in
+            ringbuf::get_ringbuf_registry_entries(&task_bin, &task_elf)?
+        {
+            if count == capacity {
+                bail!(
+                    "too many ringbufs for {supervisor}'s registry, which \
+                     has room for {capacity}"
+                );
+            }
+            ringbuf::write_registry_entry(
+                &mut out_supervisor_bin,
+                offset + count * ringbuf::REGISTRY_ENTRY_SIZE,
+                elf::get_endianness(&elf),
+                index as u32,
+                &entry,
+            )?;
+            if cfg.verbose {
+                println!(
+                    "Ringbuf {} in task '{name}' at {:#x}",
+                    entry.name, entry.address
+                );
+            }
+            count += 1;
+        }
+    }
+
+    std::fs::write(&supervisor_bin, out_supervisor_bin)?;
+    let mut symbol_table = BTreeMap::default();
+    load_elf(&supervisor_bin, all_output_sections, &mut symbol_table)?;
+    Ok(())
+}
+
 fn resolve_task_slots(
     cfg: &PackageConfig,
     task_name: &str,
@@ -0,0 +1,236 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checks tasks with each combination of the features in their
+//! `feature-matrix`, so that a combination no app happens to use today doesn't
+//! quietly stop compiling:
+//!
+//! ```toml
+//! [tasks.vpd]
+//! name = "task-vpd"
+//! features = ["g031", "tmp117-eeprom"]
+//! feature-matrix = ["tmp117-eeprom"]
+//! ```
+//!
+//! checks `task-vpd` with `g031`, and with `g031` and `tmp117-eeprom`, in the
+//! context of that app. Every check is a `cargo check`; they run in parallel,
+//! each worker with its own target directory so that they don't wait on each
+//! other's locks.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::{Config, Task};
+
+/// Most features a task can list in its `feature-matrix`, which keeps the
+/// number of combinations in check
+const MAX_MATRIX_FEATURES: usize = 6;
+
+/// A single `cargo check` of a task
+struct Job<'a> {
+    app_toml: &'a Path,
+    config: &'a Config,
+    task: &'a str,
+    features: Vec<String>,
+}
+
+impl Job<'_> {
+    fn describe(&self) -> String {
+        format!(
+            "{} {} [{}]",
+            self.app_toml.display(),
+            self.task,
+            self.features.join(", ")
+        )
+    }
+}
+
+pub fn run(
+    verbose: bool,
+    cfgs: &[PathBuf],
+    jobs: Option<usize>,
+    matrix_only: bool,
+) -> Result<()> {
+    let cfgs = if cfgs.is_empty() {
+        all_apps()?
+    } else {
+        cfgs.to_vec()
+    };
+
+    let configs = cfgs
+        .iter()
+        .map(|app_toml| {
+            Config::from_file(app_toml)
+                .with_context(|| format!("loading {}", app_toml.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut queue = vec![];
+    for (app_toml, config) in cfgs.iter().zip(&configs) {
+        for (name, task) in &config.tasks {
+            if matrix_only && task.feature_matrix.is_empty() {
+                continue;
+            }
+            for features in combinations(name, task)? {
+                queue.push(Job {
+                    app_toml,
+                    config,
+                    task: name,
+                    features,
+                });
+            }
+        }
+    }
+
+    let total = queue.len();
+    let workers = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(Into::into))
+        .unwrap_or(1)
+        .clamp(1, total.max(1));
+    println!(
+        "checking {total} task configurations from {} app(s), {workers} at a \
+         time",
+        cfgs.len()
+    );
+
+    let log_dir = Path::new("target").join("check-features");
+    std::fs::create_dir_all(log_dir.join("logs"))?;
+
+    let queue = Mutex::new(queue.into_iter().enumerate());
+    let failures = Mutex::new(vec![]);
+    std::thread::scope(|s| {
+        for w in 0..workers {
+            let (queue, failures, log_dir) = (&queue, &failures, &log_dir);
+            s.spawn(move || loop {
+                let Some((i, job)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let log = log_dir.join("logs").join(format!("{i}.log"));
+                let target_dir = log_dir.join(format!("worker-{w}"));
+                let result = check(verbose, &job, &target_dir, &log);
+                let ok = matches!(result, Ok(true));
+                println!(
+                    "[{}/{total}] {} {}",
+                    i + 1,
+                    if ok { "ok    " } else { "FAILED" },
+                    job.describe()
+                );
+                if !ok {
+                    let why = match result {
+                        Ok(_) => format!("see {}", log.display()),
+                        Err(e) => format!("{e:#}"),
+                    };
+                    failures.lock().unwrap().push((i, job.describe(), why));
+                }
+            });
+        }
+    });
+
+    let mut failures = failures.into_inner().unwrap();
+    if failures.is_empty() {
+        println!("all {total} task configurations check cleanly");
+        return Ok(());
+    }
+    failures.sort();
+    println!(
+        "\n{} of {total} task configurations failed:",
+        failures.len()
+    );
+    for (_, what, why) in &failures {
+        println!("  {what}\n      {why}");
+    }
+    bail!("feature check failed");
+}
+
+/// Lists every app configuration under `app/`.
+fn all_apps() -> Result<Vec<PathBuf>> {
+    let mut out = vec![];
+    for dir in std::fs::read_dir("app")? {
+        let dir = dir?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        for f in std::fs::read_dir(&dir)? {
+            let f = f?.path();
+            if f.extension().map(|e| e == "toml").unwrap_or(false)
+                && f.file_name().map(|n| n != "Cargo.toml").unwrap_or(false)
+            {
+                out.push(f);
+            }
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Returns each set of features to check `task` with: its own features, with
+/// those in its `feature-matrix` toggled in every combination.
+fn combinations(name: &str, task: &Task) -> Result<Vec<Vec<String>>> {
+    let matrix = &task.feature_matrix;
+    if matrix.len() > MAX_MATRIX_FEATURES {
+        bail!(
+            "task {name} has {} features in its feature-matrix; at most \
+             {MAX_MATRIX_FEATURES} are allowed",
+            matrix.len()
+        );
+    }
+    for (i, f) in matrix.iter().enumerate() {
+        if matrix[..i].contains(f) {
+            bail!("task {name} lists {f} twice in its feature-matrix");
+        }
+    }
+
+    let base: Vec<String> = task
+        .features
+        .iter()
+        .filter(|f| !matrix.contains(f))
+        .cloned()
+        .collect();
+    Ok((0..1usize << matrix.len())
+        .map(|mask| {
+            let mut out = base.clone();
+            out.extend(
+                matrix
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << i) != 0)
+                    .map(|(_, f)| f.clone()),
+            );
+            out
+        })
+        .collect())
+}
+
+/// Runs `cargo check` for `job`, logging its output to `log`. Returns whether
+/// the check passed.
+fn check(
+    verbose: bool,
+    job: &Job,
+    target_dir: &Path,
+    log: &Path,
+) -> Result<bool> {
+    let mut config = job.config.clone();
+    config.tasks[job.task].features = job.features.clone();
+    let build_config = config
+        .task_build_config(job.task, verbose, None)
+        .map_err(anyhow::Error::msg)?;
+
+    let mut cmd = build_config.cmd("check");
+    cmd.env("CARGO_TARGET_DIR", target_dir);
+    let out = cmd
+        .output()
+        .with_context(|| format!("failed to run {cmd:?}"))?;
+    std::fs::write(
+        log,
+        [
+            format!("{}\n\n", job.describe()).as_bytes(),
+            &out.stdout,
+            &out.stderr,
+        ]
+        .concat(),
+    )?;
+    Ok(out.status.success())
+}
@@ -3,23 +3,21 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::env;
+use std::io::BufRead;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
 
 use anyhow::Context;
 
 use crate::{Config, HumilityArgs};
 
-pub fn run(
+/// Builds a Humility command that's pointed at the archive for the given
+/// image, without any subcommand or options.
+pub fn command(
     args: &HumilityArgs,
-    precmd: &[&str],
-    cmd: Option<&str>,
-    interactive: bool,
     image_name: &String,
-) -> anyhow::Result<()> {
-    if interactive {
-        ctrlc::set_handler(|| {}).expect("Error setting Ctrl-C handler");
-    }
+) -> anyhow::Result<Command> {
     let toml = Config::from_file(&args.cfg)?;
 
     let archive = Path::new("target")
@@ -35,6 +33,20 @@ pub fn run(
 
     let mut humility = Command::new(humility_path);
     humility.arg("-a").arg(archive);
+    Ok(humility)
+}
+
+pub fn run(
+    args: &HumilityArgs,
+    precmd: &[&str],
+    cmd: Option<&str>,
+    interactive: bool,
+    image_name: &String,
+) -> anyhow::Result<()> {
+    if interactive {
+        ctrlc::set_handler(|| {}).expect("Error setting Ctrl-C handler");
+    }
+    let mut humility = command(args, image_name)?;
     for c in precmd {
         humility.arg(c);
     }
@@ -57,3 +69,33 @@ pub fn run(
 
     Ok(())
 }
+
+/// Starts `humility itm` on the given image, with its stdout piped back to
+/// us. ITM runs until it's killed.
+pub fn itm(args: &HumilityArgs, image_name: &String) -> anyhow::Result<Child> {
+    let mut humility = command(args, image_name)?;
+    humility.arg("itm").arg("-ea");
+    for opt in &args.extra_options {
+        humility.arg(opt);
+    }
+    humility
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run humility ({:?})", humility))
+}
+
+/// Reads lines on a separate thread, so that callers can give up on a source
+/// (like ITM) that has gone quiet.
+pub fn lines<R: BufRead + Send + 'static>(
+    reader: R,
+) -> mpsc::Receiver<std::io::Result<String>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
@@ -10,7 +10,7 @@ use std::{
     collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
     hash::{Hash, Hasher},
     io::Read,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 #[derive(Debug, Deserialize, Clone)]
@@ -52,10 +52,10 @@ struct LspConfig {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-struct PackageGraph(BTreeMap<String, cargo_metadata::Package>);
+pub(crate) struct PackageGraph(BTreeMap<String, cargo_metadata::Package>);
 
 impl PackageGraph {
-    fn new(metadata: cargo_metadata::Metadata) -> Self {
+    pub(crate) fn new(metadata: cargo_metadata::Metadata) -> Self {
         let packages = metadata
             .packages
             .into_iter()
@@ -64,7 +64,13 @@ impl PackageGraph {
         Self(packages)
     }
 
-    fn resolve(
+    /// Returns the directory holding the named package's `Cargo.toml`
+    pub(crate) fn manifest_dir(&self, name: &str) -> Option<&Path> {
+        let pkg = self.0.get(name)?;
+        pkg.manifest_path.parent().map(|d| d.as_std_path())
+    }
+
+    pub(crate) fn resolve(
         &self,
         root: &str,
         features: &[String],
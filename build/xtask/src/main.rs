@@ -10,16 +10,27 @@ use clap::Parser;
 use crate::config::Config;
 
 mod auxflash;
+mod bench;
+mod caboose;
 mod clippy;
 mod config;
+mod deps;
 mod dist;
 mod elf;
+mod features;
 mod flash;
 mod graph;
 mod humility;
 mod lsp;
 mod print;
+mod repro;
+mod ringbuf;
+mod sign;
+mod sim;
+mod sizediff;
 mod sizes;
+mod stack;
+mod tap;
 mod task_slot;
 
 #[derive(Debug, Parser)]
@@ -42,6 +53,14 @@ enum Xtask {
         /// rebuilding even if it looks like we need to.
         #[clap(long)]
         dirty: bool,
+        /// Build so that the same commit always gives the same images and
+        /// archives, byte for byte. This needs a clean checkout.
+        #[clap(long, conflicts_with = "dirty")]
+        reproducible: bool,
+        /// Add a key to the default caboose, in addition to those in the
+        /// app.toml (may be repeated)
+        #[clap(long = "caboose", value_name = "KEY=VALUE")]
+        caboose_entries: Vec<String>,
     },
 
     /// Builds one or more cross-compiled binary as it would appear in the
@@ -110,18 +129,115 @@ enum Xtask {
         dirty: bool,
     },
 
+    /// Estimates each task's worst-case stack depth from its last build, and
+    /// compares it to the task's stack size
+    Stack {
+        /// Print the deepest call path, and the functions behind any
+        /// uncertainty in the estimate
+        #[clap(short)]
+        verbose: bool,
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+        /// Name of task(s) to check; all of them if none are given.
+        tasks: Vec<String>,
+        /// Percentage of the stack that should be left to spare
+        #[clap(long, default_value_t = 10)]
+        margin: u32,
+        /// Fail if any task has less than `margin` to spare, not just if a
+        /// task may overflow
+        #[clap(long)]
+        strict: bool,
+    },
+
+    /// Checks each task in one or more apps with every combination of the
+    /// features in its `feature-matrix`
+    CheckFeatures {
+        /// Request verbosity from tools we shell out to.
+        #[clap(short)]
+        verbose: bool,
+        /// Number of checks to run at once; defaults to the number of CPUs
+        #[clap(short, long)]
+        jobs: Option<usize>,
+        /// Only check tasks with a `feature-matrix`, rather than every task
+        #[clap(long)]
+        matrix_only: bool,
+        /// Paths to the image configuration files, in TOML; every app under
+        /// `app/` if none are given
+        cfgs: Vec<PathBuf>,
+    },
+
+    /// Builds the app twice from clean, with `dist --reproducible`, and checks
+    /// that the archives are identical
+    VerifyRepro {
+        /// Request verbosity from tools we shell out to.
+        #[clap(short)]
+        verbose: bool,
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+    },
+
+    /// Compares task and image sizes between two build archives
+    Sizediff {
+        /// Archive from the old build
+        old: PathBuf,
+        /// Archive from the new build
+        new: PathBuf,
+        /// Print the comparison as JSON
+        #[clap(long)]
+        json: bool,
+        /// Number of symbols to list for each task
+        #[clap(long, default_value_t = 10)]
+        top: usize,
+    },
+
     /// Runs `humility`, passing any arguments
     Humility {
         #[clap(flatten)]
         args: HumilityArgs,
     },
 
-    /// Runs `xtask dist`, `xtask flash` and then `humility test`
+    /// Runs `xtask dist`, `xtask flash` and then `humility test` (or, if the
+    /// image's test runner reports in TAP, collects that report itself)
     Test {
         /// Do not flash a new image; just run `humility test`
         #[clap(long, short)]
         noflash: bool,
 
+        /// For a test runner with the `tap` feature, read its report from
+        /// this file (a saved log, or a serial port) rather than from ITM
+        #[clap(long)]
+        input: Option<PathBuf>,
+
+        /// For a test runner with the `tap` feature, seconds to wait for all
+        /// tests to finish
+        #[clap(long, default_value_t = 600)]
+        timeout: u64,
+
+        #[clap(flatten)]
+        args: HumilityArgs,
+    },
+
+    /// Runs `xtask dist` and `xtask flash` on a benchmark image (such as
+    /// `test/bench-stm32h7/app-h753.toml`), then collects one run of its
+    /// results as JSON
+    Bench {
+        /// Do not flash a new image; just collect results
+        #[clap(long, short)]
+        noflash: bool,
+
+        /// Read results from this file (a saved log, or a serial port)
+        /// rather than from ITM through Humility
+        #[clap(long)]
+        input: Option<PathBuf>,
+
+        /// Write results to this file, rather than to stdout
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+
+        /// Seconds to wait for a complete run
+        #[clap(long, default_value_t = 60)]
+        timeout: u64,
+
         #[clap(flatten)]
         args: HumilityArgs,
     },
@@ -143,6 +259,24 @@ enum Xtask {
         extra_options: Vec<String>,
     },
 
+    /// Runs a task's tests on the host, against a simulated kernel
+    Sim {
+        /// Request verbosity from tools we shell out to.
+        #[clap(short)]
+        verbose: bool,
+
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+
+        /// Name of task(s) to test.
+        #[clap(required = true)]
+        tasks: Vec<String>,
+
+        /// Extra options to pass to the test harness
+        #[clap(last = true)]
+        extra_options: Vec<String>,
+    },
+
     /// Show a task's .task_slot_table contents
     TaskSlots {
         /// Path to task executable
@@ -186,6 +320,12 @@ enum Xtask {
         expanded_config: bool,
     },
 
+    /// Print an app.toml with its inheritance resolved, as a single TOML file
+    Resolve {
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+    },
+
     /// Print a JSON blob with configuration info for `rust-analyzer`
     Lsp {
         /// Existing LSP clients.
@@ -244,8 +384,19 @@ fn run(xtask: Xtask) -> Result<()> {
             edges,
             cfg,
             dirty,
+            reproducible,
+            caboose_entries,
         } => {
-            let allocs = dist::package(verbose, edges, &cfg, None, dirty)?;
+            let caboose_entries = caboose::parse_args(&caboose_entries)?;
+            let allocs = dist::package(
+                verbose,
+                edges,
+                &cfg,
+                None,
+                dirty,
+                reproducible,
+                &caboose_entries,
+            )?;
             for (_, (a, _)) in allocs {
                 sizes::run(&cfg, &a, true, false, false)?;
             }
@@ -261,11 +412,27 @@ fn run(xtask: Xtask) -> Result<()> {
             if list {
                 dist::list_tasks(&cfg)?;
             } else {
-                dist::package(verbose, edges, &cfg, Some(tasks), dirty)?;
+                dist::package(
+                    verbose,
+                    edges,
+                    &cfg,
+                    Some(tasks),
+                    dirty,
+                    false,
+                    &[],
+                )?;
             }
         }
         Xtask::Flash { dirty, mut args } => {
-            dist::package(args.verbose, false, &args.cfg, None, dirty)?;
+            dist::package(
+                args.verbose,
+                false,
+                &args.cfg,
+                None,
+                dirty,
+                false,
+                &[],
+            )?;
             let toml = Config::from_file(&args.cfg)?;
             let chip = ["-c", crate::flash::chip_name(&toml.board)?];
             args.extra_options.push("--force".to_string());
@@ -288,11 +455,40 @@ fn run(xtask: Xtask) -> Result<()> {
             save,
             dirty,
         } => {
-            let allocs = dist::package(verbose, false, &cfg, None, dirty)?;
+            let allocs =
+                dist::package(verbose, false, &cfg, None, dirty, false, &[])?;
             for (_, (a, _)) in allocs {
                 sizes::run(&cfg, &a, false, compare, save)?;
             }
         }
+        Xtask::Stack {
+            verbose,
+            cfg,
+            tasks,
+            margin,
+            strict,
+        } => {
+            stack::run(&cfg, &tasks, margin, strict, verbose)?;
+        }
+        Xtask::CheckFeatures {
+            verbose,
+            jobs,
+            matrix_only,
+            cfgs,
+        } => {
+            features::run(verbose, &cfgs, jobs, matrix_only)?;
+        }
+        Xtask::VerifyRepro { verbose, cfg } => {
+            repro::verify(verbose, &cfg)?;
+        }
+        Xtask::Sizediff {
+            old,
+            new,
+            json,
+            top,
+        } => {
+            sizediff::run(&old, &new, top, json)?;
+        }
         Xtask::Humility { args } => {
             let toml = Config::from_file(&args.cfg)?;
             let image_name = if let Some(ref name) = args.image_name {
@@ -316,14 +512,27 @@ fn run(xtask: Xtask) -> Result<()> {
                 &toml.image_names[0]
             };
             if !noflash {
-                dist::package(args.verbose, false, &args.cfg, None, false)?;
+                dist::package(
+                    args.verbose,
+                    false,
+                    &args.cfg,
+                    None,
+                    false,
+                    false,
+                    &[],
+                )?;
                 // Delegate flashing to `humility gdb`, which also modifies
                 // the GDB startup script slightly (adding `stepi`)
                 args.extra_options.push("--load".to_string());
             }
             humility::run(&args, &[], Some("gdb"), true, image_name)?;
         }
-        Xtask::Test { args, noflash } => {
+        Xtask::Test {
+            args,
+            noflash,
+            input,
+            timeout,
+        } => {
             let toml = Config::from_file(&args.cfg)?;
             let image_name = if let Some(ref name) = args.image_name {
                 if !toml.check_image_name(name) {
@@ -339,7 +548,46 @@ fn run(xtask: Xtask) -> Result<()> {
                     dirty: false,
                 })?;
             }
-            humility::run(&args, &[], Some("test"), false, image_name)?;
+            if tap::enabled(&toml) {
+                tap::run(
+                    &args,
+                    image_name,
+                    input.as_deref(),
+                    std::time::Duration::from_secs(timeout),
+                )?;
+            } else {
+                humility::run(&args, &[], Some("test"), false, image_name)?;
+            }
+        }
+        Xtask::Bench {
+            args,
+            noflash,
+            input,
+            output,
+            timeout,
+        } => {
+            let toml = Config::from_file(&args.cfg)?;
+            let image_name = if let Some(ref name) = args.image_name {
+                if !toml.check_image_name(name) {
+                    bail!("Image name {} not declared in TOML", name);
+                }
+                name
+            } else {
+                &toml.image_names[0]
+            };
+            if !noflash && input.is_none() {
+                run(Xtask::Flash {
+                    args: args.clone(),
+                    dirty: false,
+                })?;
+            }
+            bench::run(
+                &args,
+                image_name,
+                input.as_deref(),
+                output.as_deref(),
+                std::time::Duration::from_secs(timeout),
+            )?;
         }
         Xtask::Clippy {
             verbose,
@@ -349,6 +597,14 @@ fn run(xtask: Xtask) -> Result<()> {
         } => {
             clippy::run(verbose, cfg, &tasks, &extra_options)?;
         }
+        Xtask::Sim {
+            verbose,
+            cfg,
+            tasks,
+            extra_options,
+        } => {
+            sim::run(verbose, cfg, &tasks, &extra_options)?;
+        }
         Xtask::TaskSlots { task_bin } => {
             task_slot::dump_task_slot_table(&task_bin)?;
         }
@@ -364,6 +620,11 @@ fn run(xtask: Xtask) -> Result<()> {
             print::run(&cfg, archive, image_name, expanded_config)
                 .context("could not print information about the build")?;
         }
+        Xtask::Resolve { cfg } => {
+            let resolved = Config::resolve(&cfg)
+                .with_context(|| format!("could not resolve {cfg:?}"))?;
+            print!("{resolved}");
+        }
         Xtask::Lsp { clients, file } => {
             lsp::run(&file, &clients)?;
         }
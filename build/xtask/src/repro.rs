@@ -0,0 +1,223 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reproducible builds.
+//!
+//! With `--reproducible`, `dist` pins the things that would otherwise leak
+//! into the output from the machine or the moment it was built on:
+//!
+//! - The checkout must be clean, since `git-rev` can't describe anything else.
+//! - `SOURCE_DATE_EPOCH` is set to the commit time, for any build script that
+//!   wants a timestamp.
+//! - Every path a binary could mention (this checkout, and each crate source
+//!   under `CARGO_HOME`) is remapped to a fixed name. For the same reason, the
+//!   GDB script doesn't map them back.
+//! - Each archive is rewritten with its files in name order, with fixed
+//!   timestamps and permissions, after all of the post-build steps (caboose,
+//!   signing) have had their way with it.
+//!
+//! `verify-repro` builds twice, cleaning in between, and compares the
+//! archives byte for byte.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::config::Config;
+
+/// The environment a reproducible build runs in
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Commit time of `HEAD`, in seconds since the epoch
+    pub source_date_epoch: u64,
+    /// Additional paths to remap, beyond the ones used for every build
+    pub remap_paths: BTreeMap<PathBuf, &'static str>,
+}
+
+impl Settings {
+    /// Checks that the checkout is clean, and works out its settings.
+    pub fn new() -> Result<Self> {
+        let status = Command::new("git")
+            .args(["status", "--porcelain", "--untracked-files=normal"])
+            .output()
+            .context("failed to get git status")?;
+        if !status.status.success() {
+            bail!("git status failed");
+        }
+        if !status.stdout.is_empty() {
+            bail!(
+                "reproducible builds need a clean checkout, but this one \
+                 has changes:\n{}",
+                String::from_utf8_lossy(&status.stdout)
+            );
+        }
+
+        let out = Command::new("git")
+            .args(["log", "-1", "--format=%ct", "HEAD"])
+            .output()
+            .context("failed to get commit time")?;
+        if !out.status.success() {
+            bail!("git log failed");
+        }
+        let source_date_epoch = std::str::from_utf8(&out.stdout)?
+            .trim()
+            .parse()
+            .context("could not parse commit time")?;
+
+        Ok(Self {
+            source_date_epoch,
+            remap_paths: remap_paths()?,
+        })
+    }
+}
+
+/// Finds paths which would otherwise only be remapped if they happen to match
+/// the defaults in `PackageConfig::remap_paths`.
+fn remap_paths() -> Result<BTreeMap<PathBuf, &'static str>> {
+    let mut remap_paths = BTreeMap::new();
+
+    // The checkout, even if we weren't run by `cargo`
+    let hubris_dir = dunce::canonicalize(std::env::current_dir()?)?;
+    remap_paths.insert(hubris_dir, "/hubris");
+
+    let cargo_home = match std::env::var_os("CARGO_HOME") {
+        Some(home) => PathBuf::from(home),
+        None => std::env::var_os("HOME")
+            .map(|h| PathBuf::from(h).join(".cargo"))
+            .ok_or_else(|| anyhow!("neither CARGO_HOME nor HOME is set"))?,
+    };
+    let cargo_home = dunce::canonicalize(cargo_home)?;
+    remap_paths.insert(cargo_home.join("git").join("checkouts"), "/git");
+
+    // Each registry gets a directory named for a hash of its URL and the
+    // host, so we remap all of them instead of guessing.
+    if let Ok(dirs) = std::fs::read_dir(cargo_home.join("registry").join("src"))
+    {
+        for d in dirs {
+            remap_paths.insert(d?.path(), "/crates.io");
+        }
+    }
+    Ok(remap_paths)
+}
+
+/// Rewrites the archive at `path` with its files sorted by name, and with
+/// fixed timestamps and permissions.
+pub fn normalize_archive(path: &Path) -> Result<()> {
+    let mut files = BTreeMap::new();
+    let comment = {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        for i in 0..archive.len() {
+            let mut f = archive.by_index(i)?;
+            if f.is_dir() {
+                continue;
+            }
+            let mut data = vec![];
+            f.read_to_end(&mut data)?;
+            files.insert(f.name().to_owned(), data);
+        }
+        archive.comment().to_vec()
+    };
+
+    let opts = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Bzip2)
+        .last_modified_time(zip::DateTime::default())
+        .unix_permissions(0o644);
+
+    let mut tmp_path = path.to_owned();
+    tmp_path.set_extension("zip.partial");
+    let mut out = zip::ZipWriter::new(File::create(&tmp_path)?);
+    out.set_comment(String::from_utf8(comment)?);
+    for (name, data) in &files {
+        out.start_file(name, opts)?;
+        out.write_all(data)?;
+    }
+    out.finish()?;
+    drop(out);
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Builds the app twice, from clean, and checks that the archives match.
+pub fn verify(verbose: bool, app_toml: &Path) -> Result<()> {
+    let toml = Config::from_file(app_toml)?;
+    let archives: Vec<PathBuf> = toml
+        .image_names
+        .iter()
+        .map(|image_name| {
+            Path::new("target")
+                .join(&toml.name)
+                .join("dist")
+                .join(image_name)
+                .join(toml.archive_name(image_name))
+        })
+        .collect();
+
+    println!("verify-repro: first build");
+    crate::dist::clean(&toml)?;
+    crate::dist::package(verbose, false, app_toml, None, false, true, &[])?;
+    let first = archives
+        .iter()
+        .map(|a| {
+            std::fs::read(a)
+                .with_context(|| format!("could not read {}", a.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    println!("verify-repro: second build");
+    crate::dist::clean(&toml)?;
+    crate::dist::package(verbose, false, app_toml, None, false, true, &[])?;
+
+    let mut mismatched = 0;
+    for (path, first) in archives.iter().zip(&first) {
+        let second = std::fs::read(path)?;
+        if *first == second {
+            println!("{}: identical", path.display());
+            continue;
+        }
+        mismatched += 1;
+        println!("{}: differs", path.display());
+        for name in differing_files(first, &second)? {
+            println!("  {name}");
+        }
+    }
+    if mismatched > 0 {
+        bail!("{mismatched} archive(s) differ between builds");
+    }
+    Ok(())
+}
+
+/// Lists the files that differ between two archives, including any that are
+/// only in one of them.
+fn differing_files(a: &[u8], b: &[u8]) -> Result<Vec<String>> {
+    let read = |data: &[u8]| -> Result<BTreeMap<String, Vec<u8>>> {
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+        let mut out = BTreeMap::new();
+        for i in 0..archive.len() {
+            let mut f = archive.by_index(i)?;
+            let mut data = vec![];
+            f.read_to_end(&mut data)?;
+            out.insert(f.name().to_owned(), data);
+        }
+        Ok(out)
+    };
+    let (a, b) = (read(a)?, read(b)?);
+
+    let mut out: Vec<String> = a
+        .iter()
+        .filter(|(name, data)| b.get(*name) != Some(data))
+        .map(|(name, _)| name.clone())
+        .collect();
+    out.extend(b.keys().filter(|name| !a.contains_key(*name)).cloned());
+    out.sort();
+    if out.is_empty() {
+        // The contents match, so it must be the metadata.
+        out.push("(archive metadata)".to_owned());
+    }
+    Ok(out)
+}
@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The ringbuf registry: descriptions of every task's ringbufs, gathered from
+//! their `.ringbuf_registry` sections and written into the supervisor, so it
+//! can find them at runtime.
+
+use crate::elf;
+use anyhow::{bail, Result};
+use scroll::{Pread, Pwrite};
+
+pub const RINGBUF_REGISTRY_SECTION: &str = ".ringbuf_registry";
+
+/// Symbol for the supervisor's registry, which it leaves zeroed for us to
+/// fill in. If the supervisor doesn't have one, we don't build a registry.
+pub const RINGBUF_REGISTRY_SYMBOL: &str = "RINGBUF_REGISTRY";
+
+/// Names are truncated (from the front, since the end is more specific) to
+/// this many bytes in the supervisor's registry. This must match
+/// `task_jefe_api::RINGBUF_NAME_LEN`.
+pub const RINGBUF_NAME_LEN: usize = 48;
+
+/// Size of an entry in the supervisor's registry: eleven `u32`s, then the
+/// name.
+pub const REGISTRY_ENTRY_SIZE: usize = 11 * 4 + RINGBUF_NAME_LEN;
+
+/// A ringbuf, as described by `ringbuf::RegistryEntry`.
+#[derive(Debug)]
+pub struct RingbufRegistryEntry<'a> {
+    pub address: u32,
+    pub size: u32,
+    pub buffer_offset: u32,
+    pub entries: u32,
+    pub entry_size: u32,
+    pub payload_offset: u32,
+    pub payload_size: u32,
+    pub dropped_offset: u32,
+    pub flags: u32,
+    pub name: &'a str,
+}
+
+impl<'a> scroll::ctx::TryFromCtx<'a, &goblin::elf::Elf<'a>>
+    for RingbufRegistryEntry<'a>
+{
+    type Error = anyhow::Error;
+
+    fn try_from_ctx(
+        src: &'a [u8],
+        elf: &goblin::elf::Elf<'a>,
+    ) -> Result<(Self, usize), Self::Error> {
+        let endianness = elf::get_endianness(elf);
+        let src_offset = &mut 0;
+
+        let address = if elf.is_64 {
+            src.gread_with::<u64>(src_offset, endianness)? as u32
+        } else {
+            src.gread_with::<u32>(src_offset, endianness)?
+        };
+        let mut word = || src.gread_with::<u32>(src_offset, endianness);
+        let size = word()?;
+        let buffer_offset = word()?;
+        let entries = word()?;
+        let entry_size = word()?;
+        let payload_offset = word()?;
+        let payload_size = word()?;
+        let dropped_offset = word()?;
+        let flags = word()?;
+        let name_len = word()? as usize;
+        let name: &str =
+            src.gread_with(src_offset, scroll::ctx::StrCtx::Length(name_len))?;
+
+        Ok((
+            Self {
+                address,
+                size,
+                buffer_offset,
+                entries,
+                entry_size,
+                payload_offset,
+                payload_size,
+                dropped_offset,
+                flags,
+                name,
+            },
+            *src_offset,
+        ))
+    }
+}
+
+/// Returns the ringbufs described in a task's `.ringbuf_registry` section; a
+/// task with no ringbufs may not have the section at all.
+pub fn get_ringbuf_registry_entries<'a>(
+    src: &'a [u8],
+    elf: &goblin::elf::Elf<'a>,
+) -> Result<Vec<RingbufRegistryEntry<'a>>> {
+    let Some(section) = elf::get_section_by_name(elf, RINGBUF_REGISTRY_SECTION)
+    else {
+        return Ok(vec![]);
+    };
+
+    let table = &src[section.sh_offset as usize
+        ..(section.sh_offset + section.sh_size) as usize];
+
+    let mut entries = vec![];
+    let cur_offset = &mut 0;
+    while *cur_offset < table.len() {
+        entries
+            .push(table.gread_with::<RingbufRegistryEntry>(cur_offset, elf)?);
+    }
+    Ok(entries)
+}
+
+/// Finds the supervisor's registry, returning its file offset and its
+/// capacity in entries.
+pub fn find_registry(elf: &goblin::elf::Elf) -> Result<Option<(usize, usize)>> {
+    let Some(sym) = elf.syms.iter().find(|s| {
+        elf.strtab.get_at(s.st_name) == Some(RINGBUF_REGISTRY_SYMBOL)
+    }) else {
+        return Ok(None);
+    };
+    let Some(section) = elf::get_section_by_vma(elf, sym.st_value) else {
+        bail!("{RINGBUF_REGISTRY_SYMBOL} isn't in any section");
+    };
+    let offset = sym.st_value - section.sh_addr + section.sh_offset;
+    Ok(Some((
+        offset as usize,
+        sym.st_size as usize / REGISTRY_ENTRY_SIZE,
+    )))
+}
+
+/// Writes one entry of the supervisor's registry at `offset`.
+pub fn write_registry_entry(
+    out: &mut [u8],
+    offset: usize,
+    endianness: scroll::Endian,
+    task: u32,
+    entry: &RingbufRegistryEntry,
+) -> Result<()> {
+    let mut name = entry.name.as_bytes();
+    if name.len() > RINGBUF_NAME_LEN {
+        name = &name[name.len() - RINGBUF_NAME_LEN..];
+    }
+
+    let mut cur = offset;
+    for word in [
+        task,
+        entry.address,
+        entry.size,
+        entry.buffer_offset,
+        entry.entries,
+        entry.entry_size,
+        entry.payload_offset,
+        entry.payload_size,
+        entry.dropped_offset,
+        entry.flags,
+        name.len() as u32,
+    ] {
+        out.gwrite_with::<u32>(word, &mut cur, endianness)?;
+    }
+    out[cur..cur + name.len()].copy_from_slice(name);
+    Ok(())
+}
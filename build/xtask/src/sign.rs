@@ -0,0 +1,242 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Signing for LPC55 images.
+//!
+//! If the app.toml has a `[signing]` section, each image's archive is signed
+//! after it's built -- which generates the certificate block and patches the
+//! image header -- and its CMPA and CFPA are filled in. The signed image is
+//! then checked before it's extracted into the build directory, so that an
+//! unsigned or malformed image (say, from a misbehaving external signer)
+//! fails the build instead of turning up at the ROM.
+//!
+//! If `[signing]` also names a stage0 binary, it's bundled with the signed
+//! images into `bundle.bin`, laid out as in flash.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, bail, Context, Result};
+use zerocopy::FromBytes;
+
+use crate::config::{Config, RoTMfgSettings, Signer};
+use crate::dist::PackageConfig;
+
+/// Offset of the image length in the vector table
+const IMAGE_LENGTH: usize = 0x20;
+/// Offset of the image type in the vector table
+const IMAGE_TYPE: usize = 0x24;
+/// Offset of the certificate block's offset in the vector table
+const CERT_BLOCK: usize = 0x28;
+
+/// The image type of a signed image executing in place
+const SIGNED_XIP: u32 = 0x4;
+/// Length of the certificate block header
+const CERT_HEADER_LEN: usize = 0x20;
+/// Possible lengths of the signature, for 2048- and 4096-bit RSA keys
+const SIGNATURE_LENS: [usize; 2] = [256, 512];
+
+/// How far into the image to look for the Hubris header, which follows the
+/// vector table
+const HEADER_SEARCH_LEN: usize = 0x400;
+
+/// Signs the archive at `archive_name`, and fills in its CMPA and CFPA.
+pub fn sign(
+    cfg: &PackageConfig,
+    signing: &RoTMfgSettings,
+    archive_name: &Path,
+) -> Result<()> {
+    // Certificate paths are relative to the app.toml.  Resolve them before
+    // attempting to read them.
+    let app_src_dir = &cfg.app_src_dir;
+    let read_certs = |paths: &[PathBuf]| {
+        let abspaths: Vec<_> =
+            paths.iter().map(|c| app_src_dir.join(c)).collect();
+        lpc55_sign::cert::read_certs(&abspaths)
+    };
+    let root_certs = read_certs(&signing.certs.root_certs)?;
+
+    match &signing.signer {
+        Signer::Local => {
+            let mut archive = hubtools::RawHubrisArchive::load(archive_name)
+                .context("loading archive with hubtools")?;
+            let private_key = lpc55_sign::cert::read_rsa_private_key(
+                &app_src_dir.join(&signing.certs.private_key),
+            )
+            .with_context(|| {
+                format!(
+                    "could not read private key {:?}",
+                    signing.certs.private_key
+                )
+            })?;
+            let signing_certs = read_certs(&signing.certs.signing_certs)?;
+
+            archive.sign(
+                signing_certs,
+                root_certs.clone(),
+                &private_key,
+                0, // execution address (TODO)
+            )?;
+            archive.overwrite()?;
+        }
+        Signer::Command(command) => {
+            let Some((program, args)) = command.split_first() else {
+                bail!("signer command is empty");
+            };
+            let archive_path = archive_name.canonicalize()?;
+            let status = Command::new(program)
+                .args(args)
+                .arg(&archive_path)
+                .current_dir(app_src_dir)
+                .status()
+                .with_context(|| format!("could not run signer {program}"))?;
+            if !status.success() {
+                bail!("signer {program} failed: {status}");
+            }
+        }
+    }
+
+    let mut archive = hubtools::RawHubrisArchive::load(archive_name)
+        .context("loading signed archive with hubtools")?;
+    let boot_pin = lpc55_areas::BootErrorPin::new(
+        signing.boot_error_gpio.port,
+        signing.boot_error_gpio.pin,
+    )
+    .ok_or_else(|| {
+        anyhow!("invalid boot error pin {:?}", signing.boot_error_gpio)
+    })?;
+    archive.set_cmpa(
+        signing.dice.clone(),
+        signing.enable_secure_boot,
+        signing.cmpa_settings,
+        signing.default_isp,
+        lpc55_areas::BootSpeed::Fro96mhz,
+        boot_pin,
+        root_certs,
+    )?;
+    archive.set_cfpa(
+        signing.cfpa_settings,
+        [signing.rotk0, signing.rotk1, signing.rotk2, signing.rotk3],
+        0,
+    )?;
+    archive.overwrite()?;
+    Ok(())
+}
+
+/// Checks that `bin` is a signed image that will fit in the flash of
+/// `image_name`, with a Hubris header matching the app.toml.
+pub fn validate(toml: &Config, image_name: &str, bin: &[u8]) -> Result<()> {
+    let flash = &toml.memories(image_name)?["flash"];
+    if bin.len() > (flash.end - flash.start) as usize {
+        bail!(
+            "image is {:#x} bytes, but its flash is only {:#x}",
+            bin.len(),
+            flash.end - flash.start
+        );
+    }
+
+    check_signed(bin)?;
+
+    let header = (0..HEADER_SEARCH_LEN.min(bin.len()))
+        .step_by(4)
+        .filter_map(|i| abi::ImageHeader::read_from_prefix(&bin[i..]))
+        .find(|h| h.magic == abi::HEADER_MAGIC)
+        .ok_or_else(|| anyhow!("image has no Hubris header"))?;
+    if header.total_image_len as usize > bin.len() {
+        bail!(
+            "Hubris header gives a length of {:#x}, but the image is {:#x}",
+            header.total_image_len,
+            bin.len()
+        );
+    }
+    if (header.version, header.epoch) != (toml.version, toml.epoch) {
+        bail!(
+            "Hubris header has version {} epoch {}, expected {} and {}",
+            header.version,
+            header.epoch,
+            toml.version,
+            toml.epoch
+        );
+    }
+    Ok(())
+}
+
+/// Checks that `bin` has the layout the ROM expects of a signed image: a
+/// certificate block within the signed length, followed by the signature.
+fn check_signed(bin: &[u8]) -> Result<()> {
+    let word = |offset: usize| {
+        bin.get(offset..offset + 4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .ok_or_else(|| anyhow!("image is truncated at {offset:#x}"))
+    };
+
+    let image_type = word(IMAGE_TYPE)?;
+    if image_type & 0xff != SIGNED_XIP {
+        bail!("image isn't signed (image type {image_type:#x})");
+    }
+
+    let image_len = word(IMAGE_LENGTH)? as usize;
+    let signature_len = bin.len().checked_sub(image_len).ok_or_else(|| {
+        anyhow!(
+            "image length is {image_len:#x}, but the image is {:#x}",
+            bin.len()
+        )
+    })?;
+    if !SIGNATURE_LENS.contains(&signature_len) {
+        bail!("signature is {signature_len} bytes, which no key would make");
+    }
+
+    let cert_block = word(CERT_BLOCK)? as usize;
+    if cert_block + CERT_HEADER_LEN > image_len {
+        bail!("certificate block at {cert_block:#x} is outside the image");
+    }
+    if &bin[cert_block..cert_block + 4] != b"cert" {
+        bail!("certificate block at {cert_block:#x} has a bad signature");
+    }
+    // The header's certificate count
+    if word(cert_block + 0x18)? == 0 {
+        bail!("certificate block has no certificates");
+    }
+    Ok(())
+}
+
+/// Assembles `stage0` and the signed images into `bundle.bin`, which covers
+/// flash from the lowest address of any of them to the end of the last.
+pub fn bundle(cfg: &PackageConfig, stage0: &Path) -> Result<()> {
+    let stage0_path = cfg.app_src_dir.join(stage0);
+    let stage0_bin = std::fs::read(&stage0_path)
+        .with_context(|| format!("could not read stage0 {stage0_path:?}"))?;
+    check_signed(&stage0_bin)
+        .with_context(|| format!("stage0 {stage0_path:?} is invalid"))?;
+
+    let mut parts = vec![("stage0", stage0_bin)];
+    for image_name in &cfg.toml.image_names {
+        let bin = std::fs::read(cfg.img_file("final.bin", image_name))?;
+        parts.push((image_name, bin));
+    }
+
+    let regions = cfg.toml.all_regions("flash".to_string())?;
+    let mut placed = vec![];
+    for (name, bin) in parts {
+        let flash = regions
+            .get(name)
+            .ok_or_else(|| anyhow!("no flash region for {name}"))?;
+        if bin.len() > (flash.end - flash.start) as usize {
+            bail!("{name} doesn't fit in its flash {flash:#x?}");
+        }
+        placed.push((flash.start as usize, bin));
+    }
+
+    let start = placed.iter().map(|(a, _)| *a).min().unwrap();
+    let end = placed.iter().map(|(a, b)| a + b.len()).max().unwrap();
+    let mut out = vec![0xFF; end - start];
+    for (addr, bin) in &placed {
+        out[addr - start..][..bin.len()].copy_from_slice(bin);
+    }
+
+    let bundle = cfg.dist_file("bundle.bin");
+    std::fs::write(&bundle, out)?;
+    println!("bundled stage0 and images at {start:#010x} into {bundle:?}");
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Runs tasks' tests on the host, against the simulated kernel in
+//! `userlib::sim`.
+//!
+//! Each task is built as it would be for the image -- with its features and
+//! the app's configuration in the environment for its build script -- but for
+//! the host, with `--cfg hubris_sim`. A task's tests live in its `main.rs`,
+//! which must step aside for the test harness:
+//!
+//! ```ignore
+//! #![cfg_attr(not(test), no_main)]
+//!
+//! #[cfg_attr(not(test), export_name = "main")]
+//! fn main() -> ! {
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use crate::config::Config;
+
+pub fn run(
+    verbose: bool,
+    cfg: PathBuf,
+    tasks: &[String],
+    options: &[String],
+) -> Result<()> {
+    let toml = Config::from_file(&cfg)?;
+
+    for name in tasks {
+        if !toml.tasks.contains_key(name) {
+            bail!("{}", toml.task_name_suggestion(name));
+        }
+    }
+
+    for (i, name) in tasks.iter().enumerate() {
+        let crate_name = toml.tasks[name].name.as_str();
+        if tasks.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!(
+                "================== {} [{}] ==================",
+                name, crate_name
+            );
+        }
+
+        let mut build_config =
+            toml.task_build_config(name, verbose, None).unwrap();
+
+        // Build for the host rather than the image's target.
+        let args = &mut build_config.args;
+        if let Some(i) = args.iter().position(|a| a == "--target") {
+            args.drain(i..i + 2);
+        }
+        build_config.args.push("--bin".to_string());
+        build_config.args.push(crate_name.to_string());
+
+        build_config
+            .env
+            .insert("RUSTFLAGS".to_string(), "--cfg hubris_sim".to_string());
+        // Keep host builds from clobbering the image's.
+        build_config
+            .env
+            .insert("CARGO_TARGET_DIR".to_string(), "target/sim".to_string());
+
+        let mut cmd = build_config.cmd("test");
+        if !options.is_empty() {
+            cmd.arg("--");
+            for opt in options {
+                cmd.arg(opt);
+            }
+        }
+
+        let status = cmd.status()?;
+        if !status.success() {
+            bail!("`cargo test` failed, see output for details");
+        }
+    }
+    Ok(())
+}
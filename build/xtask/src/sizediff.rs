@@ -0,0 +1,308 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Compares the sizes of everything in two build archives.
+//!
+//! For each task (and the kernel) we add up what its ELF puts in flash and
+//! RAM -- not what it was allocated, which only moves in powers of two on
+//! some parts -- and list the symbols that grew or shrank the most. We also
+//! compare how much of the image's flash region is left.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use goblin::elf::{
+    section_header::{SHF_ALLOC, SHF_WRITE, SHT_NOBITS},
+    sym::{STT_FUNC, STT_OBJECT},
+    Elf,
+};
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::{config::Output, stack::demangle};
+
+/// What one build of a task (or the kernel) uses
+#[derive(Default)]
+struct Usage {
+    flash: u64,
+    ram: u64,
+    /// Sizes of its functions and objects, by name
+    symbols: BTreeMap<String, u64>,
+}
+
+/// What we pull out of a build archive
+struct Build {
+    /// Usage by task, with the kernel as "kernel"
+    usage: BTreeMap<String, Usage>,
+    /// Bytes of the image's flash region not taken by the image
+    headroom: Option<u64>,
+}
+
+/// A size in the old and new builds, either of which may be missing
+#[derive(Copy, Clone, Default, Serialize)]
+struct Change {
+    old: Option<u64>,
+    new: Option<u64>,
+}
+
+impl Change {
+    fn delta(&self) -> i64 {
+        self.new.unwrap_or(0) as i64 - self.old.unwrap_or(0) as i64
+    }
+}
+
+#[derive(Serialize)]
+struct SymbolChange {
+    name: String,
+    #[serde(flatten)]
+    size: Change,
+    delta: i64,
+}
+
+#[derive(Serialize)]
+struct TaskChange {
+    name: String,
+    flash: Change,
+    ram: Change,
+    /// The symbols that moved the most
+    symbols: Vec<SymbolChange>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    tasks: Vec<TaskChange>,
+    headroom: Change,
+}
+
+/// Compares the archives at `old` and `new`, printing a report (as JSON, if
+/// `json`) including the `top` symbols that moved most in each task.
+pub fn run(old: &Path, new: &Path, top: usize, json: bool) -> Result<()> {
+    let old = load(old)?;
+    let new = load(new)?;
+
+    let names: BTreeSet<&String> =
+        old.usage.keys().chain(new.usage.keys()).collect();
+    let mut tasks = vec![];
+    for name in names {
+        let (o, n) = (old.usage.get(name), new.usage.get(name));
+        let change = |f: fn(&Usage) -> u64| Change {
+            old: o.map(f),
+            new: n.map(f),
+        };
+
+        let empty = BTreeMap::new();
+        let o_syms = o.map_or(&empty, |u| &u.symbols);
+        let n_syms = n.map_or(&empty, |u| &u.symbols);
+        let mut symbols: Vec<SymbolChange> = o_syms
+            .keys()
+            .chain(n_syms.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|sym| {
+                let size = Change {
+                    old: o_syms.get(sym).copied(),
+                    new: n_syms.get(sym).copied(),
+                };
+                SymbolChange {
+                    name: sym.clone(),
+                    size,
+                    delta: size.delta(),
+                }
+            })
+            .filter(|s| s.delta != 0)
+            .collect();
+        symbols.sort_by_key(|s| std::cmp::Reverse(s.delta.abs()));
+        symbols.truncate(top);
+
+        tasks.push(TaskChange {
+            name: name.clone(),
+            flash: change(|u| u.flash),
+            ram: change(|u| u.ram),
+            symbols,
+        });
+    }
+
+    let report = Report {
+        tasks,
+        headroom: Change {
+            old: old.headroom,
+            new: new.headroom,
+        },
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+fn print_report(report: &Report) {
+    fn size(s: Option<u64>) -> String {
+        s.map_or_else(|| "-".to_string(), |s| s.to_string())
+    }
+    fn delta(d: i64) -> ColoredString {
+        let s = format!("{d:+}");
+        match d {
+            0 => s.dimmed(),
+            d if d > 0 => s.red(),
+            _ => s.green(),
+        }
+    }
+
+    println!(
+        "{:<24} {:>8} {:>8} {:>8}   {:>8} {:>8} {:>8}",
+        "TASK", "FLASH", "", "", "RAM", "", ""
+    );
+    for t in &report.tasks {
+        println!(
+            "{:<24} {:>8} {:>8} {:>8}   {:>8} {:>8} {:>8}",
+            t.name,
+            size(t.flash.old),
+            size(t.flash.new),
+            delta(t.flash.delta()),
+            size(t.ram.old),
+            size(t.ram.new),
+            delta(t.ram.delta()),
+        );
+    }
+
+    let total = |f: fn(&TaskChange) -> Change| {
+        report.tasks.iter().map(|t| f(t).delta()).sum::<i64>()
+    };
+    println!(
+        "{:<24} {:>8} {:>8} {:>8}   {:>8} {:>8} {:>8}",
+        "total",
+        "",
+        "",
+        delta(total(|t| t.flash)),
+        "",
+        "",
+        delta(total(|t| t.ram)),
+    );
+
+    let h = &report.headroom;
+    println!(
+        "\nflash headroom: {} -> {} ({})",
+        size(h.old),
+        size(h.new),
+        // Losing headroom is what we're worried about.
+        delta(-h.delta()),
+    );
+
+    for t in report.tasks.iter().filter(|t| !t.symbols.is_empty()) {
+        println!("\n{}:", t.name.bold());
+        for s in &t.symbols {
+            println!(
+                "  {:>8} {:>8} {:>8}  {}",
+                size(s.size.old),
+                size(s.size.new),
+                delta(s.delta),
+                s.name
+            );
+        }
+    }
+}
+
+/// Reads the file at `path` in `archive`, if it's there.
+fn read_file(
+    archive: &mut zip::ZipArchive<File>,
+    path: &str,
+) -> Result<Option<Vec<u8>>> {
+    let mut file = match archive.by_name(path) {
+        Ok(f) => f,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut out = vec![];
+    file.read_to_end(&mut out)?;
+    Ok(Some(out))
+}
+
+fn load(path: &Path) -> Result<Build> {
+    let file = File::open(path)
+        .with_context(|| format!("could not open {}", path.display()))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .with_context(|| format!("could not read {}", path.display()))?;
+
+    let mut usage = BTreeMap::new();
+    let elves: Vec<String> = archive
+        .file_names()
+        .filter(|f| *f == "elf/kernel" || f.starts_with("elf/task/"))
+        .map(str::to_owned)
+        .collect();
+    for f in elves {
+        let name = f.rsplit('/').next().unwrap().to_string();
+        let data = read_file(&mut archive, &f)?.unwrap();
+        let elf = Elf::parse(&data)
+            .with_context(|| format!("could not parse {f}"))?;
+        usage.insert(name, elf_usage(&elf));
+    }
+
+    Ok(Build {
+        usage,
+        headroom: headroom(&mut archive)?,
+    })
+}
+
+fn elf_usage(elf: &Elf) -> Usage {
+    let mut out = Usage::default();
+    for sec in &elf.section_headers {
+        // `.fill` pads a task's flash out to its allocation.
+        if sec.sh_flags & u64::from(SHF_ALLOC) == 0
+            || elf.shdr_strtab.get_at(sec.sh_name) == Some(".fill")
+        {
+            continue;
+        }
+        if sec.sh_flags & u64::from(SHF_WRITE) == 0 {
+            out.flash += sec.sh_size;
+        } else {
+            out.ram += sec.sh_size;
+            // Initialized data is loaded from flash.
+            if sec.sh_type != SHT_NOBITS {
+                out.flash += sec.sh_size;
+            }
+        }
+    }
+
+    for sym in elf.syms.iter() {
+        let kind = sym.st_type();
+        if sym.st_size == 0 || (kind != STT_FUNC && kind != STT_OBJECT) {
+            continue;
+        }
+        let name = elf.strtab.get_at(sym.st_name).unwrap_or("?");
+        *out.symbols.entry(demangle(name)).or_default() += sym.st_size;
+    }
+    out
+}
+
+/// Works out how much of the flash region the image leaves free.
+fn headroom(archive: &mut zip::ZipArchive<File>) -> Result<Option<u64>> {
+    let (Some(image), Some(memory), Some(name)) = (
+        read_file(archive, "img/final.bin")?,
+        read_file(archive, "memory.toml")?,
+        read_file(archive, "image-name")?,
+    ) else {
+        return Ok(None);
+    };
+    let memory: IndexMap<String, Vec<Output>> =
+        toml::from_str(std::str::from_utf8(&memory)?)
+            .context("could not parse memory.toml")?;
+    let name = String::from_utf8(name)?;
+    let outputs = memory
+        .get("flash")
+        .ok_or_else(|| anyhow!("no flash region in memory.toml"))?;
+    // Images that share flash each get an output named after them.
+    let flash = outputs
+        .iter()
+        .find(|o| o.name == name)
+        .or_else(|| outputs.first())
+        .ok_or_else(|| anyhow!("no flash region for image {name}"))?;
+    let used = image.len() as u64;
+    Ok(Some(u64::from(flash.size).saturating_sub(used)))
+}
@@ -0,0 +1,459 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Static estimates of each task's worst-case stack depth.
+//!
+//! Tasks are built with `-Z emit-stack-sizes`, which has LLVM record the frame
+//! size of every function it compiles in a `.stack_sizes` section. We recover
+//! the call graph by decoding the direct calls (`bl`) and tail calls (`b.w` to
+//! the start of another function) in each function's text, and then find the
+//! deepest path from the task's entry point.
+//!
+//! Indirect calls (`blx rN`) can't be followed from the binary alone. A task
+//! can say where they go with a `stack-calls` table, mapping (part of) the
+//! path of a function making indirect calls to the paths of everything those
+//! calls may reach:
+//!
+//! ```toml
+//! [tasks.foo.stack-calls]
+//! "idol_runtime::dispatch" = ["<task_foo::ServerImpl as"]
+//! ```
+//!
+//! Unannotated indirect calls, recursion, and functions built without frame
+//! sizes (the precompiled `compiler_builtins`, say) all make the estimate a
+//! lower bound, and are reported as such. Tail calls are counted as calls,
+//! and literal pools can decode as calls, so otherwise we err high.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use colored::*;
+use goblin::elf::{sym::STT_FUNC, Elf};
+use indexmap::IndexMap;
+
+use crate::{dist::PackageConfig, elf};
+
+/// Largest exception frame the hardware can push onto a task's stack: eight
+/// words of integer state, plus eighteen of floating-point state on parts
+/// with an FPU. An interrupt can arrive at any depth.
+const EXCEPTION_FRAME: u64 = 26 * 4;
+
+/// A function in a task's image
+struct Function {
+    name: String,
+    size: u32,
+    /// Frame size, if the compiler recorded one
+    frame: Option<u64>,
+    /// Addresses of the functions it calls
+    callees: BTreeSet<u32>,
+    /// Whether it makes indirect calls that haven't been annotated
+    indirect: bool,
+}
+
+/// What we found for one task
+#[derive(Default)]
+struct Estimate {
+    /// Deepest stack use found, including an exception frame
+    depth: u64,
+    /// Functions along the deepest path, from the entry point
+    path: Vec<String>,
+    /// Reachable functions making unannotated indirect calls
+    indirect: BTreeSet<String>,
+    /// Reachable functions without a recorded frame size
+    unknown: BTreeSet<String>,
+    /// Reachable functions that are part of a cycle
+    recursive: BTreeSet<String>,
+}
+
+impl Estimate {
+    fn is_lower_bound(&self) -> bool {
+        !(self.indirect.is_empty()
+            && self.unknown.is_empty()
+            && self.recursive.is_empty())
+    }
+}
+
+/// Estimates the stack depth of each of `tasks` (or every task, if it's
+/// empty) in the most recent build of `cfg`, and compares it to the task's
+/// stack size.
+///
+/// Fails if any task may overflow its stack, or, if `strict`, if any has
+/// less than `margin` percent of its stack to spare.
+pub fn run(
+    cfg: &Path,
+    tasks: &[String],
+    margin: u32,
+    strict: bool,
+    verbose: bool,
+) -> Result<()> {
+    let cfg = PackageConfig::new(cfg, false, false)?;
+    let toml = &cfg.toml;
+    let image_name = &toml.image_names[0];
+
+    for name in tasks {
+        if !toml.tasks.contains_key(name) {
+            bail!("no such task {name}");
+        }
+    }
+
+    let mut overflows = vec![];
+    let mut thin = vec![];
+    for (name, task) in &toml.tasks {
+        if !tasks.is_empty() && !tasks.contains(name) {
+            continue;
+        }
+        let stacksize = task.stacksize.or(toml.stacksize).ok_or_else(|| {
+            anyhow!("{name}: no stack size specified and there is no default")
+        })?;
+        let est = estimate(&cfg.img_file(name, image_name), &task.stack_calls)
+            .with_context(|| format!("could not analyze {name}"))?;
+
+        let stacksize = u64::from(stacksize);
+        let percent = est.depth * 100 / stacksize.max(1);
+        let comfortable = stacksize * u64::from(100 - margin.min(100)) / 100;
+        let bound = if est.is_lower_bound() { ">=" } else { "" };
+        let summary =
+            format!("{name}: {bound}{} of {stacksize} bytes", est.depth);
+        if est.depth > stacksize {
+            println!("{} ({percent}%)", summary.bold().red());
+            overflows.push(name.as_str());
+        } else if est.depth > comfortable {
+            println!("{} ({percent}%)", summary.yellow());
+            thin.push(name.as_str());
+        } else {
+            println!("{summary} ({percent}%)");
+        }
+
+        if verbose {
+            println!("  deepest path:");
+            for f in &est.path {
+                println!("    {f}");
+            }
+        }
+        for (what, set) in [
+            ("unannotated indirect calls", &est.indirect),
+            ("no recorded frame size", &est.unknown),
+            ("recursion", &est.recursive),
+        ] {
+            if set.is_empty() {
+                continue;
+            }
+            println!("  {what} in {} function(s)", set.len());
+            if verbose {
+                for f in set {
+                    println!("    {f}");
+                }
+            }
+        }
+    }
+
+    if !overflows.is_empty() {
+        bail!("stack may overflow in {}", overflows.join(", "));
+    }
+    if strict && !thin.is_empty() {
+        bail!(
+            "less than {margin}% of stack to spare in {}",
+            thin.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn estimate(
+    path: &Path,
+    annotations: &IndexMap<String, Vec<String>>,
+) -> Result<Estimate> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    let elf = Elf::parse(&data)?;
+    let frames = frame_sizes(&elf, &data)?;
+
+    let mut functions: BTreeMap<u32, Function> = BTreeMap::new();
+    for sym in elf.syms.iter() {
+        if sym.st_type() != STT_FUNC || sym.st_value == 0 {
+            continue;
+        }
+        // Thumb function addresses have their bottom bit set.
+        let addr = sym.st_value as u32 & !1;
+        let name = elf.strtab.get_at(sym.st_name).unwrap_or("?");
+        functions.entry(addr).or_insert_with(|| Function {
+            name: demangle(name),
+            size: sym.st_size as u32,
+            frame: frames.get(&addr).copied(),
+            callees: BTreeSet::new(),
+            indirect: false,
+        });
+    }
+
+    let starts: BTreeSet<u32> = functions.keys().copied().collect();
+    for (&addr, f) in &mut functions {
+        if let Some(code) = code(&elf, &data, addr, f.size) {
+            decode(addr, code, &starts, f);
+        }
+    }
+
+    for (caller, callees) in annotations {
+        let mut targets = BTreeSet::new();
+        for callee in callees {
+            let found: Vec<u32> = functions
+                .iter()
+                .filter(|(_, f)| f.name.contains(callee.as_str()))
+                .map(|(&addr, _)| addr)
+                .collect();
+            if found.is_empty() {
+                bail!("stack-calls: no function matches {callee:?}");
+            }
+            targets.extend(found);
+        }
+
+        let mut matched = false;
+        for f in functions
+            .values_mut()
+            .filter(|f| f.name.contains(caller.as_str()))
+        {
+            f.callees.extend(&targets);
+            f.indirect = false;
+            matched = true;
+        }
+        if !matched {
+            bail!("stack-calls: no function matches {caller:?}");
+        }
+    }
+
+    let mut walk = Walk {
+        functions: &functions,
+        memo: BTreeMap::new(),
+        active: BTreeSet::new(),
+        est: Estimate::default(),
+    };
+    let entry = elf.header.e_entry as u32 & !1;
+    let depth = walk.depth(entry);
+
+    let mut est = walk.est;
+    est.depth = depth + EXCEPTION_FRAME;
+    let mut next = Some(entry);
+    while let Some(addr) = next {
+        // Bail out of any cycle that the memo leads us around.
+        if est.path.len() > functions.len() {
+            break;
+        }
+        let Some(f) = functions.get(&addr) else {
+            break;
+        };
+        est.path.push(f.name.clone());
+        next = walk.memo.get(&addr).and_then(|&(_, n)| n);
+    }
+    Ok(est)
+}
+
+/// Depth-first search of the call graph, remembering the depth of each
+/// function we've finished with, and the callee that it comes from.
+struct Walk<'a> {
+    functions: &'a BTreeMap<u32, Function>,
+    memo: BTreeMap<u32, (u64, Option<u32>)>,
+    /// Functions on the path we're exploring
+    active: BTreeSet<u32>,
+    est: Estimate,
+}
+
+impl Walk<'_> {
+    fn depth(&mut self, addr: u32) -> u64 {
+        if let Some(&(depth, _)) = self.memo.get(&addr) {
+            return depth;
+        }
+        let Some(f) = self.functions.get(&addr) else {
+            return 0;
+        };
+        if !self.active.insert(addr) {
+            self.est.recursive.insert(f.name.clone());
+            return 0;
+        }
+        if f.frame.is_none() {
+            self.est.unknown.insert(f.name.clone());
+        }
+        if f.indirect {
+            self.est.indirect.insert(f.name.clone());
+        }
+
+        let mut deepest = (0, None);
+        for &callee in &f.callees {
+            let depth = self.depth(callee);
+            if deepest.1.is_none() || depth > deepest.0 {
+                deepest = (depth, Some(callee));
+            }
+        }
+        self.active.remove(&addr);
+
+        let depth = f.frame.unwrap_or(0) + deepest.0;
+        self.memo.insert(addr, (depth, deepest.1));
+        depth
+    }
+}
+
+/// Reads every `.stack_sizes` section: each entry is a function address,
+/// followed by its frame size as a ULEB128.
+fn frame_sizes(elf: &Elf, data: &[u8]) -> Result<BTreeMap<u32, u64>> {
+    let mut out = BTreeMap::new();
+    let mut found = false;
+    for sec in &elf.section_headers {
+        if elf.shdr_strtab.get_at(sec.sh_name) != Some(".stack_sizes") {
+            continue;
+        }
+        found = true;
+
+        let start = sec.sh_offset as usize;
+        let mut bytes = data
+            .get(start..start + sec.sh_size as usize)
+            .ok_or_else(|| anyhow!(".stack_sizes is out of bounds"))?;
+        while bytes.len() >= 4 {
+            let addr = u32::from_le_bytes(bytes[..4].try_into().unwrap()) & !1;
+            bytes = &bytes[4..];
+
+            let mut size = 0u64;
+            let mut shift = 0;
+            loop {
+                let (&b, rest) = bytes
+                    .split_first()
+                    .ok_or_else(|| anyhow!("truncated .stack_sizes"))?;
+                bytes = rest;
+                size |= u64::from(b & 0x7F) << shift;
+                shift += 7;
+                if b & 0x80 == 0 {
+                    break;
+                }
+            }
+            out.insert(addr, size);
+        }
+    }
+    if !found {
+        bail!("no .stack_sizes section (was it built with emit-stack-sizes?)");
+    }
+    Ok(out)
+}
+
+/// Returns the `size` bytes of code at `addr`, if they're in the file.
+fn code<'a>(
+    elf: &Elf,
+    data: &'a [u8],
+    addr: u32,
+    size: u32,
+) -> Option<&'a [u8]> {
+    let sec = elf::get_section_by_vma(elf, addr.into())?;
+    let start = (u64::from(addr) - sec.sh_addr + sec.sh_offset) as usize;
+    data.get(start..start + size as usize)
+}
+
+/// Records the calls made by `f`, whose code is `code` at `addr`; `starts`
+/// holds the address of every function.
+fn decode(addr: u32, code: &[u8], starts: &BTreeSet<u32>, f: &mut Function) {
+    let end = addr + code.len() as u32;
+    let halfword =
+        |i: usize| code.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+
+    let mut i = 0;
+    while let Some(hw1) = halfword(i) {
+        let pc = addr + i as u32;
+
+        // Anything but 0b11101, 0b11110, and 0b11111 in the top five bits is
+        // a 16-bit instruction.
+        if hw1 >> 11 < 0b11101 {
+            // blx rN
+            if hw1 & 0xFF87 == 0x4780 {
+                f.indirect = true;
+            }
+            i += 2;
+            continue;
+        }
+        let Some(hw2) = halfword(i + 2) else {
+            break;
+        };
+        i += 4;
+
+        let bl = hw1 & 0xF800 == 0xF000 && hw2 & 0xD000 == 0xD000;
+        let b_w = hw1 & 0xF800 == 0xF000 && hw2 & 0xD000 == 0x9000;
+        if !(bl || b_w) {
+            continue;
+        }
+        let target = branch_target(pc, hw1, hw2);
+        // Branches within the function are just control flow.
+        if !starts.contains(&target) || (b_w && (addr..end).contains(&target)) {
+            continue;
+        }
+        f.callees.insert(target);
+    }
+}
+
+/// Decodes the target of a `bl` or `b.w` (encoding T4) at `pc`.
+fn branch_target(pc: u32, hw1: u16, hw2: u16) -> u32 {
+    let s = u32::from(hw1 >> 10) & 1;
+    let j1 = u32::from(hw2 >> 13) & 1;
+    let j2 = u32::from(hw2 >> 11) & 1;
+    let i1 = !(j1 ^ s) & 1;
+    let i2 = !(j2 ^ s) & 1;
+    let imm = s << 24
+        | i1 << 23
+        | i2 << 22
+        | u32::from(hw1 & 0x3FF) << 12
+        | u32::from(hw2 & 0x7FF) << 1;
+    // Sign-extend from 25 bits.
+    let offset = ((imm << 7) as i32) >> 7;
+    pc.wrapping_add(4).wrapping_add(offset as u32)
+}
+
+/// Turns a legacy-mangled Rust symbol into a path, for reports and for
+/// matching `stack-calls`; anything else is left as it is.
+pub(crate) fn demangle(name: &str) -> String {
+    let Some(mut rest) = name.strip_prefix("_ZN") else {
+        return name.to_string();
+    };
+    let mut parts = vec![];
+    while let Some(digits) = rest.find(|c: char| !c.is_ascii_digit()) {
+        let Ok(len) = rest[..digits].parse::<usize>() else {
+            break;
+        };
+        let Some(part) = rest.get(digits..digits + len) else {
+            return name.to_string();
+        };
+        // Parts starting with an escape get an underscore in front.
+        parts.push(
+            part.strip_prefix('_')
+                .filter(|p| p.starts_with('$'))
+                .unwrap_or(part),
+        );
+        rest = &rest[digits + len..];
+    }
+    if rest != "E" || parts.is_empty() {
+        return name.to_string();
+    }
+    // The last part is a hash.
+    if parts.len() > 1
+        && parts
+            .last()
+            .map_or(false, |p| p.len() == 17 && p.starts_with('h'))
+    {
+        parts.pop();
+    }
+
+    let mut out = parts.join("::");
+    for (from, to) in [
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$RF$", "&"),
+        ("$BP$", "*"),
+        ("$C$", ","),
+        ("$SP$", "@"),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+        ("..", "::"),
+    ] {
+        out = out.replace(from, to);
+    }
+    out
+}
@@ -0,0 +1,196 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Collects test results from images whose `test-runner` has the `tap`
+//! feature, which reports in TAP rather than in the format `humility test`
+//! understands.
+//!
+//! We pass the report through to stdout as it arrives, starting from its
+//! `TAP version` line (so a run we joined in the middle of is skipped), and
+//! stop once every case in the plan has a result.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::config::Config;
+use crate::{humility, HumilityArgs};
+
+/// Returns true if the image's test runner reports in TAP.
+pub fn enabled(toml: &Config) -> bool {
+    toml.tasks.values().any(|task| {
+        task.name == "test-runner" && task.features.iter().any(|f| f == "tap")
+    })
+}
+
+/// Tallies one TAP report
+#[derive(Default)]
+struct Report {
+    started: bool,
+    plan: Option<usize>,
+    results: usize,
+    failures: Vec<String>,
+}
+
+impl Report {
+    /// Feeds one line to the report, printing it if it's part of the report;
+    /// returns true once every case in the plan has a result.
+    fn line(&mut self, line: &str) -> Result<bool> {
+        let line = line.trim_end();
+        if line.starts_with("TAP version") {
+            *self = Report {
+                started: true,
+                ..Default::default()
+            };
+        } else if !self.started {
+            return Ok(false);
+        } else if let Some(n) = line.strip_prefix("1..") {
+            let n = n
+                .trim()
+                .parse()
+                .with_context(|| format!("bad TAP plan {:?}", line))?;
+            self.plan = Some(n);
+        } else if line.starts_with("ok ") {
+            self.results += 1;
+        } else if let Some(rest) = line.strip_prefix("not ok ") {
+            self.results += 1;
+            self.failures.push(rest.to_string());
+        } else if !line.starts_with('#') {
+            // Not ours; ITM output can be shared with other things.
+            return Ok(false);
+        }
+        println!("{}", line);
+        Ok(self.plan == Some(self.results))
+    }
+}
+
+fn collect(
+    lines: mpsc::Receiver<std::io::Result<String>>,
+    timeout: Duration,
+) -> Result<Report> {
+    let deadline = Instant::now() + timeout;
+    let mut report = Report::default();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let line = match lines.recv_timeout(left) {
+            Ok(line) => line.context("failed to read test output")?,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                bail!("tests did not finish within {}s", timeout.as_secs())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                bail!("test output ended before all tests finished")
+            }
+        };
+        if report.line(&line)? {
+            return Ok(report);
+        }
+    }
+}
+
+pub fn run(
+    args: &HumilityArgs,
+    image_name: &String,
+    input: Option<&Path>,
+    timeout: Duration,
+) -> Result<()> {
+    let report = if let Some(input) = input {
+        let file = File::open(input)
+            .with_context(|| format!("failed to open {}", input.display()))?;
+        collect(humility::lines(BufReader::new(file)), timeout)?
+    } else {
+        let mut child = humility::itm(args, image_name)?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("no stdout from humility"))?;
+        let report = collect(humility::lines(BufReader::new(stdout)), timeout);
+        let _ = child.kill();
+        let _ = child.wait();
+        report?
+    };
+
+    if !report.failures.is_empty() {
+        for f in &report.failures {
+            eprintln!("failed: {}", f);
+        }
+        bail!(
+            "{} of {} tests failed",
+            report.failures.len(),
+            report.results
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(lines: &[&str]) -> (Report, bool) {
+        let mut report = Report::default();
+        let mut done = false;
+        for line in lines {
+            assert!(!done, "report finished before {line:?}");
+            done = report.line(line).unwrap();
+        }
+        (report, done)
+    }
+
+    #[test]
+    fn plan_and_results() {
+        let (report, done) = feed(&[
+            "TAP version 13",
+            "1..3",
+            "# start test_send",
+            "ok 1 - test_send",
+            "# start test_flaky",
+            "# test_flaky: attempt 1 of 3: Fail",
+            "ok 2 - test_flaky # passed on attempt 2 of 3",
+            "# start test_hang",
+            "not ok 3 - test_hang # timed out after 10000 ms",
+        ]);
+        assert!(done);
+        assert_eq!(report.plan, Some(3));
+        assert_eq!(report.results, 3);
+        assert_eq!(
+            report.failures,
+            ["3 - test_hang # timed out after 10000 ms"]
+        );
+    }
+
+    #[test]
+    fn incomplete() {
+        let (report, done) =
+            feed(&["TAP version 13", "1..2", "ok 1 - test_send"]);
+        assert!(!done);
+        assert_eq!(report.results, 1);
+    }
+
+    #[test]
+    fn skips_partial_run_and_other_output() {
+        let (report, done) = feed(&[
+            "not ok 7 - test_from_an_earlier_run",
+            "1..9",
+            "humility: attached via ST-Link",
+            "TAP version 13",
+            "1..1",
+            "jefe: restarting task",
+            "ok 1 - test_send",
+        ]);
+        assert!(done);
+        assert_eq!(report.plan, Some(1));
+        assert!(report.failures.is_empty());
+    }
+
+    #[test]
+    fn bad_plan() {
+        let mut report = Report::default();
+        report.line("TAP version 13").unwrap();
+        assert!(report.line("1..lots").is_err());
+    }
+}
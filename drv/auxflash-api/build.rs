@@ -9,7 +9,7 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let global_config = build_util::config::<GlobalConfig>()?;
     generate_auxflash_config(&global_config.auxflash)?;
 
-    idol::client::build_client_stub(
+    build_util::idol::build_client_stub(
         "../../idl/auxflash.idol",
         "client_stub.rs",
     )?;
@@ -29,6 +29,12 @@ struct GlobalConfig {
 struct AuxFlashConfig {
     memory_size: u32,
     slot_count: u32,
+
+    /// Number of 64 KiB sectors at the top of memory set aside for wear
+    /// leveling (two for the wear table, the rest as spares); if zero, wear
+    /// leveling is disabled and slots cover all of memory.
+    #[serde(default)]
+    spare_sectors: u32,
 }
 
 fn generate_auxflash_config(
@@ -42,22 +48,37 @@ fn generate_auxflash_config(
     // Check that the config is reasonable:
     // a. We have at least 6 slots (see RFD 311)
     assert!(config.slot_count >= 6, "auxflash requires at least 6 slots");
-    // b. Memory size is evenly divisible by the slot count
+    // b. If wear leveling is enabled, there's room for the two wear table
+    //    sectors and at least one spare
+    assert!(
+        config.spare_sectors == 0 || config.spare_sectors >= 3,
+        "auxflash wear leveling requires at least 3 spare sectors"
+    );
+    let slot_memory = config
+        .memory_size
+        .checked_sub(config.spare_sectors * (64 << 10))
+        .expect("auxflash spare sectors exceed memory size");
+    // c. Memory (less spares) is evenly divisible by the slot count
     assert_eq!(
-        config.memory_size % config.slot_count,
+        slot_memory % config.slot_count,
         0,
         "auxflash memory must be evenly divisble by slot count"
     );
-    // c. Slot offsets are page-aligned (assuming 64 KiB pages; we can update
+    // d. Slot offsets are page-aligned (assuming 64 KiB pages; we can update
     //    this as needed)
     assert_eq!(
-        config.memory_size / config.slot_count % (64 << 10),
+        slot_memory / config.slot_count % (64 << 10),
         0,
         "auxflash slots must be page aligned"
     );
 
     writeln!(out, "pub const MEMORY_SIZE: u32 = {};", config.memory_size)?;
     writeln!(out, "pub const SLOT_COUNT: u32 = {};", config.slot_count)?;
+    writeln!(
+        out,
+        "pub const SPARE_SECTORS: u32 = {};",
+        config.spare_sectors
+    )?;
 
     Ok(())
 }
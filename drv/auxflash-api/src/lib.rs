@@ -45,6 +45,17 @@ pub enum AuxFlashError {
     NoSuchBlob,
     /// Writes to the currently-active slot are not allowed
     SlotActive,
+    /// Data read back after an erase or program did not match
+    VerifyFailed,
+    /// A sector failed verification, and there are no spares left to remap
+    /// it to
+    NoSpareSectors,
+    /// There is no manifest at the start of the `AUXI` block in this slot
+    MissingManifest,
+    /// The manifest is malformed, or lists more than `MAX_MANIFEST_BLOBS`
+    BadManifest,
+    /// A blob doesn't match its manifest entry, or isn't listed
+    ManifestMismatch,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -70,6 +81,51 @@ pub struct AuxFlashBlob {
     pub end: u32,
 }
 
+/// Most blobs a slot's manifest may list
+pub const MAX_MANIFEST_BLOBS: usize = 8;
+
+/// Header of the manifest, the `MNFT` chunk at the start of `AUXI`, which is
+/// followed by an [`AuxFlashManifestEntry`] for each blob (as packed by
+/// `xtask`)
+#[derive(Copy, Clone, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct AuxFlashManifestHeader {
+    /// Version of the slot's contents as a whole
+    pub version: u32,
+    pub blob_count: u32,
+}
+
+#[derive(Copy, Clone, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct AuxFlashManifestEntry {
+    pub tag: [u8; 4],
+    pub version: u32,
+    /// Length of the blob, as stored
+    pub len: u32,
+    /// SHA3-256 of the blob, as stored
+    pub checksum: [u8; 32],
+}
+
+/// Wear leveling statistics, as returned by `AuxFlash::wear_stats`
+#[derive(Copy, Clone, Debug, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct AuxFlashWearStats {
+    /// Number of spare sectors (zero if wear leveling is disabled)
+    pub spare_sectors: u32,
+    /// Spare sectors neither in use nor retired
+    pub free_spares: u32,
+    /// Sectors retired after failing verification
+    pub bad_sectors: u32,
+    /// Number of times a sector was remapped after failing verification
+    pub remaps: u32,
+    /// Number of times a sector was swapped with a less-worn spare on erase
+    pub swaps: u32,
+    /// Fewest erases of any sector in service
+    pub min_erases: u32,
+    /// Most erases of any sector in service
+    pub max_erases: u32,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Extension trait to do auxflash operations on anything that
@@ -81,6 +137,30 @@ pub trait TlvcReadAuxFlash {
         slot: u32,
         tag: [u8; 4],
     ) -> Result<AuxFlashBlob, AuxFlashError>;
+    /// Checks that the blobs in `AUXI` are those listed in its manifest, in
+    /// order, each with the listed length and checksum, returning the
+    /// version from the manifest.
+    fn verify_manifest(self) -> Result<u32, AuxFlashError>;
+}
+
+/// Computes the SHA3-256 of a chunk's contents, using a scratch buffer
+fn chunk_checksum<R: TlvcRead>(
+    chunk: &tlvc::ChunkHandle<R>,
+) -> Result<[u8; 32], AuxFlashError> {
+    let mut sha = Sha3_256::new();
+    let mut scratch = [0u8; 256];
+    let mut i: u64 = 0;
+    while i < chunk.len() {
+        let amount = (chunk.len() - i).min(scratch.len() as u64);
+        chunk
+            .read_exact(i, &mut scratch[0..(amount as usize)])
+            .map_err(|_| AuxFlashError::ChunkReadFail)?;
+        i += amount;
+        sha.update(&scratch[0..(amount as usize)]);
+    }
+    let mut out = [0; 32];
+    out.copy_from_slice(sha.finalize().as_slice());
+    Ok(out)
 }
 
 impl<R> TlvcReadAuxFlash for R
@@ -110,24 +190,7 @@ where
                     return Err(AuxFlashError::MultipleAuxi);
                 }
 
-                // Read data and calculate the checksum using a scratch buffer
-                let mut sha = Sha3_256::new();
-                let mut scratch = [0u8; 256];
-                let mut i: u64 = 0;
-                while i < chunk.len() {
-                    let amount = (chunk.len() - i).min(scratch.len() as u64);
-                    chunk
-                        .read_exact(i, &mut scratch[0..(amount as usize)])
-                        .map_err(|_| AuxFlashError::ChunkReadFail)?;
-                    i += amount;
-                    sha.update(&scratch[0..(amount as usize)]);
-                }
-                let sha_out = sha.finalize();
-
-                // Save the checksum in `chck_actual`
-                let mut out = [0; 32];
-                out.copy_from_slice(sha_out.as_slice());
-                chck_actual = Some(out);
+                chck_actual = Some(chunk_checksum(&chunk)?);
             }
         }
         match (chck_expected, chck_actual) {
@@ -175,6 +238,60 @@ where
         }
         Err(AuxFlashError::MissingAuxi)
     }
+
+    fn verify_manifest(self) -> Result<u32, AuxFlashError> {
+        let mut outer_reader = TlvcReader::begin(self)
+            .map_err(|_| AuxFlashError::TlvcReaderBeginFailed)?;
+        while let Ok(Some(outer_chunk)) = outer_reader.next() {
+            if &outer_chunk.header().tag != b"AUXI" {
+                continue;
+            }
+            let mut inner_reader = outer_chunk.read_as_chunks();
+
+            let manifest = match inner_reader.next() {
+                Ok(Some(c)) if &c.header().tag == b"MNFT" => c,
+                _ => return Err(AuxFlashError::MissingManifest),
+            };
+            let mut header = AuxFlashManifestHeader::default();
+            manifest
+                .read_exact(0, header.as_bytes_mut())
+                .map_err(|_| AuxFlashError::ChunkReadFail)?;
+            let count = header.blob_count as usize;
+            let entry_size = core::mem::size_of::<AuxFlashManifestEntry>();
+            if count > MAX_MANIFEST_BLOBS
+                || manifest.len() as usize
+                    != core::mem::size_of_val(&header) + count * entry_size
+            {
+                return Err(AuxFlashError::BadManifest);
+            }
+            let mut entries =
+                [AuxFlashManifestEntry::default(); MAX_MANIFEST_BLOBS];
+            manifest
+                .read_exact(
+                    core::mem::size_of_val(&header) as u64,
+                    entries[..count].as_bytes_mut(),
+                )
+                .map_err(|_| AuxFlashError::ChunkReadFail)?;
+
+            for entry in &entries[..count] {
+                let blob = match inner_reader.next() {
+                    Ok(Some(c)) => c,
+                    _ => return Err(AuxFlashError::ManifestMismatch),
+                };
+                if blob.header().tag != entry.tag
+                    || blob.len() != u64::from(entry.len)
+                    || chunk_checksum(&blob)? != entry.checksum
+                {
+                    return Err(AuxFlashError::ManifestMismatch);
+                }
+            }
+            if !matches!(inner_reader.next(), Ok(None)) {
+                return Err(AuxFlashError::ManifestMismatch);
+            }
+            return Ok(header.version);
+        }
+        Err(AuxFlashError::MissingAuxi)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -185,5 +302,7 @@ mod config {
     include!(concat!(env!("OUT_DIR"), "/auxflash_config.rs"));
 }
 
-pub use self::config::SLOT_COUNT;
-pub const SLOT_SIZE: usize = (self::config::MEMORY_SIZE / SLOT_COUNT) as usize;
+pub use self::config::{MEMORY_SIZE, SLOT_COUNT, SPARE_SECTORS};
+pub const SLOT_SIZE: usize = ((MEMORY_SIZE
+    - SPARE_SECTORS * SECTOR_SIZE_BYTES as u32)
+    / SLOT_COUNT) as usize;
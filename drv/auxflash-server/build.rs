@@ -5,7 +5,7 @@ use std::io::Write;
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::build_notifications()?;
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/auxflash.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
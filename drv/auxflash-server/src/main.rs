@@ -7,8 +7,8 @@
 
 use drv_auxflash_api::{
     AuxFlashBlob, AuxFlashChecksum, AuxFlashError, AuxFlashId,
-    TlvcReadAuxFlash, PAGE_SIZE_BYTES, SECTOR_SIZE_BYTES, SLOT_COUNT,
-    SLOT_SIZE,
+    AuxFlashWearStats, TlvcReadAuxFlash, MEMORY_SIZE, PAGE_SIZE_BYTES,
+    SECTOR_SIZE_BYTES, SLOT_COUNT, SLOT_SIZE,
 };
 use idol_runtime::{ClientError, Leased, RequestError, R, W};
 use tlvc::{TlvcRead, TlvcReadError, TlvcReader};
@@ -20,14 +20,17 @@ use stm32h7::stm32h753 as device;
 use drv_stm32h7_qspi::Qspi;
 use drv_stm32xx_sys_api as sys_api;
 
+mod wear;
+use wear::Flash;
+
 task_slot!(SYS, sys);
 
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Simple handle which holds a `&Qspi` and allows us to implement `TlvcRead`
+/// Simple handle which holds a `&Flash` and allows us to implement `TlvcRead`
 #[derive(Copy, Clone)]
 struct SlotReader<'a> {
-    qspi: &'a Qspi,
+    flash: &'a Flash,
     base: u32,
 }
 
@@ -42,7 +45,7 @@ impl<'a> TlvcRead for SlotReader<'a> {
         dest: &mut [u8],
     ) -> Result<(), TlvcReadError> {
         let addr: u32 = self.base + u32::try_from(offset).unwrap_lite();
-        self.qspi.read_memory(addr, dest);
+        self.flash.read(addr, dest);
         Ok(())
     }
 }
@@ -60,7 +63,6 @@ fn main() -> ! {
     let qspi = Qspi::new(reg, notifications::QSPI_IRQ_MASK);
 
     let clock = 5; // 200MHz kernel / 5 = 40MHz clock
-    const MEMORY_SIZE: usize = SLOT_COUNT as usize * SLOT_SIZE;
     assert!(MEMORY_SIZE.is_power_of_two());
     let memory_size_log2 = MEMORY_SIZE.trailing_zeros().try_into().unwrap();
     qspi.configure(clock, memory_size_log2);
@@ -123,8 +125,9 @@ fn main() -> ! {
     // Gimlet is  MT25QU256ABA8E12
     // Sidecar is S25FL128SAGMFIR01
     let mut buffer = [0; idl::INCOMING_SIZE];
-    let active_slot = scan_for_active_slot(&qspi);
-    let mut server = ServerImpl { qspi, active_slot };
+    let flash = Flash::new(qspi);
+    let active_slot = scan_for_active_slot(&flash);
+    let mut server = ServerImpl { flash, active_slot };
 
     let _ = server.ensure_redundancy();
 
@@ -136,44 +139,16 @@ fn main() -> ! {
 ////////////////////////////////////////////////////////////////////////////////
 
 struct ServerImpl {
-    qspi: Qspi,
+    flash: Flash,
     active_slot: Option<u32>,
 }
 
 impl ServerImpl {
-    /// Polls for the "Write Complete" flag.
-    ///
-    /// Sleep times are in ticks (typically milliseconds) and are somewhat
-    /// experimentally determined, see hubris#753 for details.
-    fn poll_for_write_complete(&self, sleep: Option<u64>) {
-        loop {
-            let status = self.qspi.read_status();
-            if status & 1 == 0 {
-                // ooh we're done
-                break;
-            }
-            if let Some(sleep) = sleep {
-                hl::sleep_for(sleep);
-            }
-        }
-    }
-
-    fn set_and_check_write_enable(&self) -> Result<(), AuxFlashError> {
-        self.qspi.write_enable();
-        let status = self.qspi.read_status();
-
-        if status & 0b10 == 0 {
-            // oh oh
-            return Err(AuxFlashError::WriteEnableFailed);
-        }
-        Ok(())
-    }
-
     fn read_slot_checksum(
         &self,
         slot: u32,
     ) -> Result<AuxFlashChecksum, AuxFlashError> {
-        read_slot_checksum(&self.qspi, slot)
+        read_slot_checksum(&self.flash, slot)
     }
 
     /// Checks that the matched slot in this even/odd pair also has valid data.
@@ -191,7 +166,7 @@ impl ServerImpl {
 
         // Find the length of data by finding the final TLV-C slot
         let handle = SlotReader {
-            qspi: &self.qspi,
+            flash: &self.flash,
             base: active_slot * SLOT_SIZE as u32,
         };
         let mut reader = TlvcReader::begin(handle)
@@ -209,24 +184,21 @@ impl ServerImpl {
             let amount = (read_end - read_addr).min(buf.len());
 
             // Read from the active slot
-            self.qspi.read_memory(read_addr as u32, &mut buf[..amount]);
+            self.flash.read(read_addr as u32, &mut buf[..amount]);
 
             // If we're at the start of a sector, erase it before we start
             // writing the copy.
             if write_addr % SECTOR_SIZE_BYTES == 0 {
-                self.set_and_check_write_enable()?;
-                self.qspi.sector_erase(write_addr as u32);
-                self.poll_for_write_complete(Some(1));
+                self.flash.erase_sector(write_addr as u32)?;
             }
 
             // Write back to the redundant slot
-            self.set_and_check_write_enable()?;
-            self.qspi.page_program(write_addr as u32, &buf[..amount]);
-            self.poll_for_write_complete(None);
+            self.flash.program(write_addr as u32, &buf[..amount])?;
 
             read_addr += amount;
             write_addr += amount;
         }
+        self.flash.commit()?;
 
         // Confirm that the spare write worked
         let spare_checksum = self.read_slot_checksum(spare_slot)?;
@@ -244,7 +216,7 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
         _: &RecvMessage,
     ) -> Result<AuxFlashId, RequestError<AuxFlashError>> {
         let mut idbuf = [0; 20];
-        self.qspi.read_id(&mut idbuf);
+        self.flash.qspi().read_id(&mut idbuf);
         Ok(AuxFlashId(idbuf))
     }
 
@@ -252,7 +224,7 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
         &mut self,
         _: &RecvMessage,
     ) -> Result<u8, RequestError<AuxFlashError>> {
-        Ok(self.qspi.read_status())
+        Ok(self.flash.qspi().read_status())
     }
 
     fn slot_count(
@@ -293,11 +265,10 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
 
         let mut addr = mem_start;
         while addr < mem_end {
-            self.set_and_check_write_enable()?;
-            self.qspi.sector_erase(addr as u32);
+            self.flash.erase_sector(addr as u32)?;
             addr += SECTOR_SIZE_BYTES;
-            self.poll_for_write_complete(Some(1));
         }
+        self.flash.commit()?;
         Ok(())
     }
 
@@ -318,9 +289,8 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
             return Err(AuxFlashError::AddressOverflow.into());
         }
 
-        self.set_and_check_write_enable()?;
-        self.qspi.sector_erase(addr as u32);
-        self.poll_for_write_complete(Some(1));
+        self.flash.erase_sector(addr as u32)?;
+        self.flash.commit()?;
         Ok(())
     }
 
@@ -354,9 +324,7 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
             data.read_range(read..(read + amount), &mut buf[..amount])
                 .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
 
-            self.set_and_check_write_enable()?;
-            self.qspi.page_program(addr as u32, &buf[..amount]);
-            self.poll_for_write_complete(None);
+            self.flash.program(addr as u32, &buf[..amount])?;
             addr += amount;
             read += amount;
         }
@@ -370,6 +338,9 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
         offset: u32,
         dest: Leased<W, [u8]>,
     ) -> Result<(), RequestError<AuxFlashError>> {
+        if slot >= SLOT_COUNT {
+            return Err(AuxFlashError::InvalidSlot.into());
+        }
         if offset as usize + dest.len() > SLOT_SIZE {
             return Err(AuxFlashError::AddressOverflow.into());
         }
@@ -381,7 +352,7 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
         let mut buf = [0u8; 256];
         while addr < end {
             let amount = (end - addr).min(buf.len());
-            self.qspi.read_memory(addr as u32, &mut buf[..amount]);
+            self.flash.read(addr as u32, &mut buf[..amount]);
             dest.write_range(write..(write + amount), &buf[..amount])
                 .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
             write += amount;
@@ -415,6 +386,28 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
         ServerImpl::ensure_redundancy(self).map_err(Into::into)
     }
 
+    fn wear_stats(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<AuxFlashWearStats, RequestError<AuxFlashError>> {
+        Ok(self.flash.stats())
+    }
+
+    fn sector_erase_count(
+        &mut self,
+        _: &RecvMessage,
+        slot: u32,
+        offset: u32,
+    ) -> Result<u32, RequestError<AuxFlashError>> {
+        if slot >= SLOT_COUNT {
+            return Err(AuxFlashError::InvalidSlot.into());
+        }
+        if offset >= SLOT_SIZE as u32 {
+            return Err(AuxFlashError::AddressOverflow.into());
+        }
+        Ok(self.flash.erase_count(slot * SLOT_SIZE as u32 + offset))
+    }
+
     fn get_blob_by_tag(
         &mut self,
         _: &RecvMessage,
@@ -424,7 +417,7 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
             .active_slot
             .ok_or_else(|| RequestError::from(AuxFlashError::NoActiveSlot))?;
         let handle = SlotReader {
-            qspi: &self.qspi,
+            flash: &self.flash,
             base: active_slot * SLOT_SIZE as u32,
         };
         handle
@@ -433,10 +426,16 @@ impl idl::InOrderAuxFlashImpl for ServerImpl {
     }
 }
 
-fn scan_for_active_slot(qspi: &Qspi) -> Option<u32> {
+/// Finds the first slot whose checksum matches the one in our image, and
+/// whose blobs match its manifest.
+fn scan_for_active_slot(flash: &Flash) -> Option<u32> {
     for i in 0..SLOT_COUNT {
-        if let Ok(chck) = read_slot_checksum(qspi, i) {
-            if chck.0 == AUXI_CHECKSUM {
+        if let Ok(chck) = read_slot_checksum(flash, i) {
+            let handle = SlotReader {
+                flash,
+                base: i * SLOT_SIZE as u32,
+            };
+            if chck.0 == AUXI_CHECKSUM && handle.verify_manifest().is_ok() {
                 return Some(i);
             }
         }
@@ -445,14 +444,14 @@ fn scan_for_active_slot(qspi: &Qspi) -> Option<u32> {
 }
 
 fn read_slot_checksum(
-    qspi: &Qspi,
+    flash: &Flash,
     slot: u32,
 ) -> Result<AuxFlashChecksum, AuxFlashError> {
     if slot >= SLOT_COUNT {
         return Err(AuxFlashError::InvalidSlot);
     }
     let handle = SlotReader {
-        qspi,
+        flash,
         base: slot * SLOT_SIZE as u32,
     };
     handle.read_checksum()
@@ -462,7 +461,9 @@ fn read_slot_checksum(
 
 mod idl {
     use super::AuxFlashError;
-    use drv_auxflash_api::{AuxFlashBlob, AuxFlashChecksum, AuxFlashId};
+    use drv_auxflash_api::{
+        AuxFlashBlob, AuxFlashChecksum, AuxFlashId, AuxFlashWearStats,
+    };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -0,0 +1,477 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Wear leveling and bad sector remapping.
+//!
+//! If the app sets aside `spare-sectors` at the top of auxflash, the (logical)
+//! sectors that make up the slots are mapped onto physical sectors through a
+//! table, which lets us:
+//!
+//! - count erases of every physical sector;
+//! - when erasing a logical sector whose physical sector has seen many more
+//!   erases than the least-worn spare, swap the two.  This costs nothing,
+//!   since the contents are being thrown away anyway, and spreads the wear
+//!   from frequently rewritten slots across the spare pool; and
+//! - verify every erase and program, retiring physical sectors that fail and
+//!   moving the logical sector (along with its contents) to a spare.
+//!
+//! The table lives in the top two sectors and is written log-style: each
+//! update is appended to the active sector, and when that fills up we erase
+//! the other one and carry on there.  At startup, the valid record with the
+//! highest sequence number wins.  Erase counts are only written out at the end
+//! of each operation (see [`Flash::commit`]), but changes to the mapping are
+//! written out before any data lands in a newly mapped sector.
+//!
+//! With no spare sectors, this is a thin pass-through to the QSPI driver.
+
+use drv_auxflash_api::{
+    AuxFlashError, AuxFlashWearStats, MEMORY_SIZE, PAGE_SIZE_BYTES,
+    SECTOR_SIZE_BYTES, SLOT_COUNT, SLOT_SIZE, SPARE_SECTORS,
+};
+use drv_stm32h7_qspi::Qspi;
+use mutable_statics::mutable_statics;
+use userlib::hl;
+use zerocopy::{AsBytes, FromBytes};
+
+const SECTOR: u32 = SECTOR_SIZE_BYTES as u32;
+const PAGE: u32 = PAGE_SIZE_BYTES as u32;
+
+const ENABLED: bool = SPARE_SECTORS != 0;
+
+const PHYSICAL_SECTORS: usize = (MEMORY_SIZE / SECTOR) as usize;
+const LOGICAL_SECTORS: usize =
+    SLOT_COUNT as usize * SLOT_SIZE / SECTOR_SIZE_BYTES;
+
+/// First of the two physical sectors holding the wear table; everything from
+/// the end of the slots up to here is a spare.
+const TABLE_SECTOR: usize = PHYSICAL_SECTORS - 2;
+
+/// Length of the map in the table, rounded up to keep the table free of
+/// padding (and zero if wear leveling is disabled).
+const MAP_LEN: usize = if ENABLED {
+    (LOGICAL_SECTORS + 1) & !1
+} else {
+    0
+};
+const COUNT_LEN: usize = if ENABLED { PHYSICAL_SECTORS } else { 0 };
+
+const TABLE_MAGIC: u32 = 0x1dea_3a4f;
+
+/// Space taken by each table record within a table sector
+const RECORD_STRIDE: u32 =
+    (core::mem::size_of::<Table>() as u32 + PAGE - 1) & !(PAGE - 1);
+
+/// Erase count marking a physical sector as retired
+const BAD: u32 = u32::MAX;
+
+/// How many more erases a sector must have seen than the least-worn spare
+/// before we swap them.  This is large enough that a slot being rewritten
+/// over and over doesn't churn the table, and small enough that the spread
+/// stays a tiny fraction of the parts' 100k cycle endurance.
+const SWAP_THRESHOLD: u32 = 128;
+
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+struct Table {
+    /// Must always be `TABLE_MAGIC`
+    magic: u32,
+    sequence: u32,
+    remaps: u32,
+    swaps: u32,
+    /// Physical sector backing each logical sector
+    map: [u16; MAP_LEN],
+    /// Erases of each physical sector, or `BAD` if it has been retired
+    erases: [u32; COUNT_LEN],
+    /// CRC-32 over the rest of the table
+    checksum: u32,
+}
+
+impl Table {
+    fn reset(&mut self) {
+        *self = Self::new_zeroed();
+        for (logical, physical) in self.map.iter_mut().enumerate() {
+            *physical = logical as u16;
+        }
+    }
+
+    fn expected_checksum(&self) -> u32 {
+        let c = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+        let bytes = self.as_bytes();
+        c.checksum(&bytes[..bytes.len() - core::mem::size_of::<u32>()])
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == TABLE_MAGIC && self.checksum == self.expected_checksum()
+    }
+}
+
+pub struct Flash {
+    qspi: Qspi,
+    table: &'static mut Table,
+
+    /// Table sector (0 or 1) being appended to, and the offset within it at
+    /// which the next record goes
+    table_sector: u32,
+    table_offset: u32,
+
+    /// Whether the table has changed since it was last written out
+    dirty: bool,
+}
+
+impl Flash {
+    /// Wraps `qspi`, loading the wear table if wear leveling is enabled. Can
+    /// only be called once.
+    pub fn new(qspi: Qspi) -> Self {
+        let tables = mutable_statics! {
+            static mut TABLE: [Table; 1] = [Table::new_zeroed; _];
+        };
+        let mut out = Self {
+            qspi,
+            table: &mut tables[0],
+            table_sector: 0,
+            table_offset: 0,
+            dirty: false,
+        };
+        if ENABLED {
+            out.load();
+        }
+        out
+    }
+
+    pub fn qspi(&self) -> &Qspi {
+        &self.qspi
+    }
+
+    /// Reads from logical address `addr` into `out`.
+    pub fn read(&self, addr: u32, out: &mut [u8]) {
+        let mut addr = addr;
+        let mut out = out;
+        while !out.is_empty() {
+            // Physical sectors needn't be contiguous, so split at boundaries.
+            let amount = ((SECTOR - addr % SECTOR) as usize).min(out.len());
+            let (chunk, rest) = out.split_at_mut(amount);
+            self.qspi.read_memory(self.physical(addr), chunk);
+            addr += amount as u32;
+            out = rest;
+        }
+    }
+
+    /// Erases the logical sector containing `addr`.
+    pub fn erase_sector(&mut self, addr: u32) -> Result<(), AuxFlashError> {
+        if !ENABLED {
+            return erase(&self.qspi, addr);
+        }
+
+        let logical = (addr / SECTOR) as usize;
+        let mut remapped = false;
+
+        if let Some(spare) = self.least_worn_spare() {
+            let current = usize::from(self.table.map[logical]);
+            let spare_erases = self.table.erases[spare];
+            if self.table.erases[current]
+                >= spare_erases.saturating_add(SWAP_THRESHOLD)
+            {
+                self.table.map[logical] = spare as u16;
+                self.table.swaps = self.table.swaps.wrapping_add(1);
+                remapped = true;
+            }
+        }
+
+        loop {
+            let physical = u32::from(self.table.map[logical]);
+            if self.erase_and_verify(physical)? {
+                break;
+            }
+            self.retire(logical)?;
+            remapped = true;
+        }
+
+        if remapped {
+            self.write_table()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Programs `data`, which must not cross a page boundary, at logical
+    /// address `addr`.
+    pub fn program(
+        &mut self,
+        addr: u32,
+        data: &[u8],
+    ) -> Result<(), AuxFlashError> {
+        assert!(addr % PAGE + data.len() as u32 <= PAGE);
+
+        if !ENABLED {
+            return program(&self.qspi, addr, data);
+        }
+
+        loop {
+            let physical = self.physical(addr);
+            program(&self.qspi, physical, data)?;
+            if self.verify(physical, data) {
+                return Ok(());
+            }
+            self.relocate(addr)?;
+        }
+    }
+
+    /// Writes out the wear table if erase counts have changed.  Call this at
+    /// the end of each operation that erases.
+    pub fn commit(&mut self) -> Result<(), AuxFlashError> {
+        if self.dirty {
+            self.write_table()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn stats(&self) -> AuxFlashWearStats {
+        if !ENABLED {
+            return AuxFlashWearStats::default();
+        }
+
+        let in_use = self.in_use();
+        let mut out = AuxFlashWearStats {
+            spare_sectors: (TABLE_SECTOR - LOGICAL_SECTORS) as u32,
+            remaps: self.table.remaps,
+            swaps: self.table.swaps,
+            min_erases: u32::MAX,
+            ..Default::default()
+        };
+        for (physical, &erases) in self.table.erases.iter().enumerate() {
+            if erases == BAD {
+                out.bad_sectors += 1;
+            } else if in_use[physical] {
+                out.min_erases = out.min_erases.min(erases);
+                out.max_erases = out.max_erases.max(erases);
+            } else if physical < TABLE_SECTOR {
+                out.free_spares += 1;
+            }
+        }
+        out
+    }
+
+    /// Returns the erase count of the physical sector backing logical address
+    /// `addr`, or zero if wear leveling is disabled.
+    pub fn erase_count(&self, addr: u32) -> u32 {
+        if !ENABLED {
+            return 0;
+        }
+        let physical = self.table.map[(addr / SECTOR) as usize];
+        self.table.erases[usize::from(physical)]
+    }
+
+    fn physical(&self, addr: u32) -> u32 {
+        if !ENABLED {
+            return addr;
+        }
+        let physical = u32::from(self.table.map[(addr / SECTOR) as usize]);
+        physical * SECTOR + addr % SECTOR
+    }
+
+    /// Returns which physical sectors are backing logical ones.
+    fn in_use(&self) -> [bool; PHYSICAL_SECTORS] {
+        let mut out = [false; PHYSICAL_SECTORS];
+        for &physical in &self.table.map[..LOGICAL_SECTORS] {
+            out[usize::from(physical)] = true;
+        }
+        out
+    }
+
+    fn least_worn_spare(&self) -> Option<usize> {
+        let in_use = self.in_use();
+        (0..TABLE_SECTOR)
+            .filter(|&p| !in_use[p] && self.table.erases[p] != BAD)
+            .min_by_key(|&p| self.table.erases[p])
+    }
+
+    /// Marks the physical sector backing `logical` as bad, and maps `logical`
+    /// to the least-worn spare instead, returning that spare.
+    fn retire(&mut self, logical: usize) -> Result<u32, AuxFlashError> {
+        let physical = usize::from(self.table.map[logical]);
+        self.table.erases[physical] = BAD;
+        self.table.remaps = self.table.remaps.wrapping_add(1);
+        self.dirty = true;
+
+        let spare = self
+            .least_worn_spare()
+            .ok_or(AuxFlashError::NoSpareSectors)?;
+        self.table.map[logical] = spare as u16;
+        Ok(spare as u32)
+    }
+
+    /// Moves the logical sector containing `addr` to a spare after a program
+    /// of `addr` failed to verify, copying over every page except the one
+    /// containing `addr` (which the caller will program again).
+    fn relocate(&mut self, addr: u32) -> Result<(), AuxFlashError> {
+        let logical = (addr / SECTOR) as usize;
+        let old = u32::from(self.table.map[logical]);
+        let skip = addr % SECTOR / PAGE;
+        let mut buf = [0u8; PAGE_SIZE_BYTES];
+
+        'spare: loop {
+            let new = self.retire(logical)?;
+            if !self.erase_and_verify(new)? {
+                continue;
+            }
+            for page in (0..SECTOR / PAGE).filter(|&p| p != skip) {
+                let offset = page * PAGE;
+                self.qspi.read_memory(old * SECTOR + offset, &mut buf);
+                if buf.iter().all(|b| *b == 0xFF) {
+                    continue;
+                }
+                program(&self.qspi, new * SECTOR + offset, &buf)?;
+                if !self.verify(new * SECTOR + offset, &buf) {
+                    continue 'spare;
+                }
+            }
+            return self.write_table();
+        }
+    }
+
+    /// Erases a physical sector, returning whether it reads back as erased.
+    fn erase_and_verify(
+        &mut self,
+        physical: u32,
+    ) -> Result<bool, AuxFlashError> {
+        erase(&self.qspi, physical * SECTOR)?;
+
+        let erases = &mut self.table.erases[physical as usize];
+        *erases = erases.saturating_add(1).min(BAD - 1);
+        self.dirty = true;
+
+        let mut buf = [0u8; PAGE_SIZE_BYTES];
+        Ok((0..SECTOR).step_by(PAGE_SIZE_BYTES).all(|offset| {
+            self.qspi.read_memory(physical * SECTOR + offset, &mut buf);
+            buf.iter().all(|b| *b == 0xFF)
+        }))
+    }
+
+    /// Checks that `data` reads back from physical address `addr`.
+    fn verify(&self, addr: u32, data: &[u8]) -> bool {
+        let mut buf = [0u8; PAGE_SIZE_BYTES];
+        let buf = &mut buf[..data.len()];
+        self.qspi.read_memory(addr, buf);
+        buf == data
+    }
+
+    /// Finds the newest valid table record, and where the next one goes.
+    fn load(&mut self) {
+        let mut best: Option<(u32, u32, u32)> = None;
+        let mut next = [SECTOR; 2];
+
+        for sector in 0..2 {
+            let base = (TABLE_SECTOR as u32 + sector) * SECTOR;
+            for offset in
+                (0..=SECTOR - RECORD_STRIDE).step_by(RECORD_STRIDE as usize)
+            {
+                self.qspi
+                    .read_memory(base + offset, self.table.as_bytes_mut());
+                if self.table.magic == u32::MAX {
+                    next[sector as usize] = offset;
+                    break;
+                }
+                if self.table.is_valid()
+                    && best.map(|b| self.table.sequence > b.2).unwrap_or(true)
+                {
+                    best = Some((sector, offset, self.table.sequence));
+                }
+            }
+        }
+
+        match best {
+            Some((sector, offset, _)) => {
+                let base = (TABLE_SECTOR as u32 + sector) * SECTOR;
+                self.qspi
+                    .read_memory(base + offset, self.table.as_bytes_mut());
+                self.table_sector = sector;
+                self.table_offset = next[sector as usize];
+            }
+            None => {
+                // Start afresh; the first write will erase table sector 0.
+                self.table.reset();
+                self.table_sector = 1;
+                self.table_offset = SECTOR;
+            }
+        }
+    }
+
+    fn write_table(&mut self) -> Result<(), AuxFlashError> {
+        if self.table_offset + RECORD_STRIDE > SECTOR {
+            // This sector is full; move on to the other one.
+            self.table_sector ^= 1;
+            self.table_offset = 0;
+            let physical = TABLE_SECTOR + self.table_sector as usize;
+            erase(&self.qspi, physical as u32 * SECTOR)?;
+            let erases = &mut self.table.erases[physical];
+            *erases = erases.saturating_add(1).min(BAD - 1);
+        }
+
+        self.table.magic = TABLE_MAGIC;
+        self.table.sequence = self.table.sequence.wrapping_add(1);
+        self.table.checksum = self.table.expected_checksum();
+
+        let base = (TABLE_SECTOR as u32 + self.table_sector) * SECTOR
+            + self.table_offset;
+        // Whatever happens next, don't write over this record.
+        self.table_offset += RECORD_STRIDE;
+
+        for (i, chunk) in
+            self.table.as_bytes().chunks(PAGE_SIZE_BYTES).enumerate()
+        {
+            let addr = base + i as u32 * PAGE;
+            program(&self.qspi, addr, chunk)?;
+            if !self.verify(addr, chunk) {
+                return Err(AuxFlashError::VerifyFailed);
+            }
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Polls for the "Write Complete" flag.
+///
+/// Sleep times are in ticks (typically milliseconds) and are somewhat
+/// experimentally determined, see hubris#753 for details.
+fn poll_for_write_complete(qspi: &Qspi, sleep: Option<u64>) {
+    loop {
+        let status = qspi.read_status();
+        if status & 1 == 0 {
+            // ooh we're done
+            break;
+        }
+        if let Some(sleep) = sleep {
+            hl::sleep_for(sleep);
+        }
+    }
+}
+
+fn set_and_check_write_enable(qspi: &Qspi) -> Result<(), AuxFlashError> {
+    qspi.write_enable();
+    let status = qspi.read_status();
+
+    if status & 0b10 == 0 {
+        // oh oh
+        return Err(AuxFlashError::WriteEnableFailed);
+    }
+    Ok(())
+}
+
+fn erase(qspi: &Qspi, addr: u32) -> Result<(), AuxFlashError> {
+    set_and_check_write_enable(qspi)?;
+    qspi.sector_erase(addr);
+    poll_for_write_complete(qspi, Some(1));
+    Ok(())
+}
+
+fn program(qspi: &Qspi, addr: u32, data: &[u8]) -> Result<(), AuxFlashError> {
+    set_and_check_write_enable(qspi)?;
+    qspi.page_program(addr, data);
+    poll_for_write_complete(qspi, None);
+    Ok(())
+}
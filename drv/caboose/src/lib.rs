@@ -4,11 +4,12 @@
 
 //! API crate for the caboose reader task
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use derive_idol_err::IdolError;
 use tlvc::{TlvcRead, TlvcReadError, TlvcReader};
-use userlib::FromPrimitive;
+use userlib::{sys_send, FromPrimitive};
+use zerocopy::AsBytes;
 
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
 pub enum CabooseError {
@@ -20,6 +21,29 @@ pub enum CabooseError {
     NoImageHeader,
 }
 
+/// Which image's caboose to read.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum CabooseSlot {
+    /// The image we're running
+    Active = 0,
+    /// The image that isn't running, which is the one updates write to
+    Inactive = 1,
+}
+
+/// Tags of the keys the build puts in a default caboose (see `[caboose]` in
+/// an app.toml).
+pub mod tags {
+    /// Name of the app
+    pub const NAME: [u8; 4] = *b"NAME";
+    /// Name of the board the image is for
+    pub const BOARD: [u8; 4] = *b"BORD";
+    /// Git commit the image was built from
+    pub const GIT_COMMIT: [u8; 4] = *b"GITC";
+    /// Version of the image
+    pub const VERSION: [u8; 4] = *b"VERS";
+}
+
 /// Simple handle which points to the beginning of the TLV-C region of the
 /// caboose and allows us to implement `TlvcRead`
 #[derive(Copy, Clone)]
@@ -60,6 +84,53 @@ impl<'a> CabooseReader<'a> {
 
         Err(CabooseError::NoSuchTag)
     }
+
+    /// Iterates over the caboose's keys and their values, in the order they
+    /// were written. A key whose value fails its checksum gives an error, but
+    /// doesn't stop the iteration.
+    pub fn iter(&self) -> CabooseIter<'a> {
+        CabooseIter {
+            caboose: *self,
+            reader: TlvcReader::begin(*self).ok(),
+            pos: 0,
+        }
+    }
+}
+
+/// Iterator over the keys in a caboose; see `CabooseReader::iter`
+pub struct CabooseIter<'a> {
+    caboose: CabooseReader<'a>,
+    reader: Option<TlvcReader<CabooseReader<'a>>>,
+    /// Offset of the next chunk
+    pos: usize,
+}
+
+impl<'a> Iterator for CabooseIter<'a> {
+    type Item = Result<([u8; 4], &'a [u8]), CabooseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = match self.reader.as_mut()?.next() {
+            Ok(Some(chunk)) => chunk,
+            // The end of the entries, or something that isn't an entry (such
+            // as the erased space after them)
+            _ => {
+                self.reader = None;
+                return None;
+            }
+        };
+        let header = chunk.header();
+        let data_start = self.pos + core::mem::size_of::<tlvc::ChunkHeader>();
+        self.pos += header.total_len_in_bytes();
+
+        let mut tmp = [0u8; 32];
+        if chunk.check_body_checksum(&mut tmp).is_err() {
+            return Some(Err(CabooseError::BadChecksum));
+        }
+        // The TLV-C reader guarantees that this chunk does not extend past the
+        // end of the medium, so making this slice should never panic.
+        let data = &self.caboose.0[data_start..][..header.len.get() as usize];
+        Some(Ok((header.tag, data)))
+    }
 }
 
 impl TlvcRead for CabooseReader<'_> {
@@ -76,3 +147,67 @@ impl TlvcRead for CabooseReader<'_> {
         Ok(())
     }
 }
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
+
+// Like the rest of this crate, these need `userlib`, which only builds for the
+// host with `--cfg hubris_sim`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tlvc_text::{Piece, Tag};
+
+    fn chunk(tag: [u8; 4], value: &[u8]) -> Piece {
+        Piece::Chunk(Tag::new(tag), vec![Piece::Bytes(value.to_vec())])
+    }
+
+    /// Packs the chunks, followed by erased flash as in a real caboose
+    fn caboose(chunks: &[Piece]) -> Vec<u8> {
+        let mut data = tlvc_text::pack(chunks);
+        data.resize(data.len() + 32, 0xff);
+        data
+    }
+
+    #[test]
+    fn iterates_in_order() {
+        let data = caboose(&[
+            chunk(tags::NAME, b"app"),
+            chunk(tags::BOARD, b"board-rev-a"),
+            chunk(*b"CHAN", b""),
+        ]);
+        let reader = CabooseReader::new(&data);
+        let keys = reader.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            keys,
+            [
+                (tags::NAME, &b"app"[..]),
+                (tags::BOARD, &b"board-rev-a"[..]),
+                (*b"CHAN", &b""[..]),
+            ]
+        );
+        for (tag, value) in keys {
+            assert_eq!(reader.get(tag), Ok(value));
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(CabooseReader::new(&caboose(&[])).iter().count(), 0);
+        assert_eq!(CabooseReader::new(&[]).iter().count(), 0);
+    }
+
+    #[test]
+    fn continues_past_bad_checksum() {
+        let mut data =
+            caboose(&[chunk(tags::NAME, b"app"), chunk(tags::BOARD, b"b")]);
+        // Corrupt the first value, which starts after its 12-byte header.
+        data[12] ^= 1;
+        let reader = CabooseReader::new(&data);
+        let keys = reader.iter().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [Err(CabooseError::BadChecksum), Ok((tags::BOARD, &b"b"[..]))]
+        );
+        assert_eq!(reader.get(tags::NAME), Err(CabooseError::BadChecksum));
+    }
+}
@@ -0,0 +1,143 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! API crate for CAN servers.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum CanError {
+    /// The transmit queue is full; wait for a notification and try again.
+    TxFull = 1,
+    /// No frame has been received.
+    RxEmpty,
+    /// The controller is bus-off and can't send; see `Can::recover`.
+    BusOff,
+    /// The data is too long for the kind of frame requested (8 bytes for
+    /// classic CAN, 64 for FD), or FD was requested of a controller
+    /// configured without it.
+    BadFrame,
+    /// The filter slot doesn't exist, or the ID or mask has bits set above
+    /// the width of the ID.
+    BadFilter,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Largest identifier in a standard (11-bit) frame.
+pub const MAX_STANDARD_ID: u32 = 0x7ff;
+/// Largest identifier in an extended (29-bit) frame.
+pub const MAX_EXTENDED_ID: u32 = 0x1fff_ffff;
+
+/// Describes a frame returned by `recv`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct FrameInfo {
+    pub id: u32,
+    /// Length of the frame's data, which may be more than was copied out.
+    pub len: u8,
+    /// Some combination of the flag constants below.
+    pub flags: u8,
+    /// Reception time, in bit times, from a free-running 16-bit counter.
+    pub timestamp: u16,
+}
+
+impl FrameInfo {
+    /// The frame has a 29-bit identifier.
+    pub const EXTENDED: u8 = 1 << 0;
+    /// The frame is in CAN FD format.
+    pub const FD: u8 = 1 << 1;
+    /// The data phase of an FD frame is sent at the faster data bit rate.
+    pub const BIT_RATE_SWITCH: u8 = 1 << 2;
+    /// The frame is a remote transmission request (classic CAN only).
+    pub const REMOTE: u8 = 1 << 3;
+
+    pub fn is_extended(&self) -> bool {
+        self.flags & Self::EXTENDED != 0
+    }
+
+    pub fn is_fd(&self) -> bool {
+        self.flags & Self::FD != 0
+    }
+}
+
+/// An acceptance filter: a frame matches if its identifier, with the bits
+/// clear in `mask` ignored, equals `id`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct CanFilter {
+    pub id: u32,
+    pub mask: u32,
+    /// Nonzero to match extended frames, zero for standard ones.
+    pub extended: u8,
+    _reserved: [u8; 3],
+}
+
+impl CanFilter {
+    pub fn standard(id: u32, mask: u32) -> Self {
+        Self {
+            id,
+            mask,
+            extended: 0,
+            _reserved: [0; 3],
+        }
+    }
+
+    pub fn extended(id: u32, mask: u32) -> Self {
+        Self {
+            id,
+            mask,
+            extended: 1,
+            _reserved: [0; 3],
+        }
+    }
+}
+
+/// Fault confinement state of the controller, from ISO 11898-1.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum BusState {
+    ErrorActive = 0,
+    /// An error counter has reached 96.
+    ErrorWarning = 1,
+    /// An error counter has passed 127; the controller may still talk, but
+    /// can only signal errors passively.
+    ErrorPassive = 2,
+    /// The transmit error counter passed 255, and the controller has taken
+    /// itself off the bus.
+    BusOff = 3,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct CanCounters {
+    /// Transmit error counter.
+    pub tec: u8,
+    /// Receive error counter.
+    pub rec: u8,
+    /// A `BusState`.
+    pub state: u8,
+    /// Last error code from the protocol state machine, as defined by the
+    /// controller (0 for none).
+    pub last_error: u8,
+    /// Times the controller has gone bus-off.
+    pub bus_off_events: u32,
+    /// Frames dropped because the receive queue was full.
+    pub rx_overruns: u32,
+    /// Protocol errors seen in either phase of a frame.
+    pub protocol_errors: u32,
+}
+
+impl CanCounters {
+    pub fn bus_state(&self) -> Option<BusState> {
+        BusState::from_u8(self.state)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
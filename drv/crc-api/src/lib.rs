@@ -0,0 +1,116 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! API crate for the CRC server, and a checksum interface shared with the
+//! software implementation in `crc-soft`.
+//!
+//! The server keeps no state between calls: every request carries the
+//! algorithm and the running CRC state, so any number of clients can use the
+//! unit without claiming it, and a computation can move between the unit and
+//! software partway through. Code that wants a CRC should be written against
+//! `CrcEngine` and handed a `Crc` client, `Software`, or (most usefully)
+//! `Fallback`.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+pub use crc_soft::{
+    CrcParams, CRC_16_XMODEM, CRC_32_ISCSI, CRC_32_ISO_HDLC, CRC_32_MPEG_2,
+    CRC_8_SMBUS,
+};
+
+/// Largest chunk the server accepts in a single `update`.
+pub const MAX_UPDATE_SZ: usize = 1024;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum CrcError {
+    /// The hardware can't compute a CRC with these parameters.
+    Unsupported = 1,
+    /// The parameters don't describe a CRC.
+    BadParams,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Something that computes CRCs.
+pub trait CrcEngine {
+    /// Runs `data` through the CRC from the raw `state` (`params.init` for a
+    /// new message), returning the new raw state; see `CrcParams::update`.
+    fn crc_update(
+        &self,
+        params: &CrcParams,
+        state: u32,
+        data: &[u8],
+    ) -> Result<u32, CrcError>;
+
+    /// Computes the CRC of `data` in one go.
+    fn checksum(
+        &self,
+        params: &CrcParams,
+        data: &[u8],
+    ) -> Result<u32, CrcError> {
+        let state = self.crc_update(params, params.init, data)?;
+        Ok(params.finalize(state))
+    }
+}
+
+impl CrcEngine for Crc {
+    fn crc_update(
+        &self,
+        params: &CrcParams,
+        mut state: u32,
+        data: &[u8],
+    ) -> Result<u32, CrcError> {
+        for chunk in data.chunks(MAX_UPDATE_SZ) {
+            state = self.update(*params, state, chunk)?;
+        }
+        Ok(state)
+    }
+}
+
+/// CRCs computed on the calling task's CPU time.
+pub struct Software;
+
+impl CrcEngine for Software {
+    fn crc_update(
+        &self,
+        params: &CrcParams,
+        state: u32,
+        data: &[u8],
+    ) -> Result<u32, CrcError> {
+        if !params.is_valid() {
+            return Err(CrcError::BadParams);
+        }
+        Ok(params.update(state, data))
+    }
+}
+
+/// Uses the CRC server where it can, and software where the server doesn't
+/// support the parameters or has restarted mid-computation.
+pub struct Fallback(pub Crc);
+
+impl CrcEngine for Fallback {
+    fn crc_update(
+        &self,
+        params: &CrcParams,
+        mut state: u32,
+        data: &[u8],
+    ) -> Result<u32, CrcError> {
+        for chunk in data.chunks(MAX_UPDATE_SZ) {
+            state = match self.0.update(*params, state, chunk) {
+                Ok(state) => state,
+                Err(CrcError::Unsupported | CrcError::ServerRestarted) => {
+                    Software.crc_update(params, state, chunk)?
+                }
+                Err(e) => return Err(e),
+            };
+        }
+        Ok(state)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -5,7 +5,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_i2c::codegen(build_i2c::Disposition::Devices)?;
 
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/eeprom.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
@@ -36,6 +36,7 @@ pub enum EepromError {
     InvalidPageSize,
     InvalidSecurityRegisterReadByte,
     InvalidSecurityRegisterWriteByte,
+    WriteTimeout,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -56,6 +57,7 @@ impl From<Error> for EepromError {
             Error::InvalidSecurityRegisterWriteByte(_) => {
                 Self::InvalidSecurityRegisterWriteByte
             }
+            Error::WriteTimeout(_) => Self::WriteTimeout,
         }
     }
 }
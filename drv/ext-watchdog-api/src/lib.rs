@@ -0,0 +1,40 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the external watchdog driver.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum WatchdogError {
+    /// The period is zero, or too close to the watchdog's timeout.
+    BadPeriod = 1,
+    /// Jefe says the system isn't healthy, so the watchdog wasn't petted.
+    Unhealthy,
+    /// Longer than the configured limit on holdoffs.
+    HoldoffTooLong,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct WatchdogStatus {
+    /// Jefe's `health` bits at the last check; zero is healthy.
+    pub health: u32,
+    /// Times the watchdog has been petted.
+    pub pets: u32,
+    /// Times a pet was skipped because the system was unhealthy.
+    pub refused: u32,
+    /// Time left in the current holdoff.
+    pub holdoff_ms: u32,
+    pub period_ms: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,87 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the flash option byte server.
+//!
+//! Option bytes are changed the way the OTP API programs a page: the new
+//! values are staged, a field at a time; then verified, by handing the server
+//! every field's value as it should end up; and only then committed, after
+//! which the server reads them all back. Staging again, or aborting, means
+//! verifying again.
+//!
+//! A server built for simulation goes through every check but changes a copy
+//! in RAM instead, which `status` owns up to.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+pub use drv_stm32h7_flash_options::{OptionField, NUM_FIELDS};
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum FlashOptionsError {
+    /// An option byte change is already under way.
+    Busy = 1,
+    /// Too large for the field.
+    BadValue,
+    /// The flash controller refused the change.
+    ChangeFailed,
+    /// The change went through, but didn't take.
+    VerifyFailed,
+    /// The bank that would be booted doesn't hold an image.
+    NoImage,
+    /// Nothing has been staged.
+    NotStaged,
+    /// The staged values haven't been verified since they last changed.
+    NotVerified,
+    /// The staged values aren't what the caller expected, or the caller
+    /// didn't give a value for every field.
+    Mismatch,
+    /// The option bytes read back differently from what was committed.
+    ReadbackMismatch,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+impl From<drv_stm32h7_flash_options::Error> for FlashOptionsError {
+    fn from(e: drv_stm32h7_flash_options::Error) -> Self {
+        use drv_stm32h7_flash_options::Error;
+        match e {
+            Error::Busy => Self::Busy,
+            Error::BadValue => Self::BadValue,
+            Error::ChangeFailed => Self::ChangeFailed,
+            Error::VerifyFailed => Self::VerifyFailed,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct BankState {
+    /// Nonzero if we booted with the banks swapped.
+    pub running_swapped: u8,
+    /// Nonzero if the banks will be swapped after the next reset; if that
+    /// differs from `running_swapped`, the next reset boots the other bank.
+    pub swap_on_reset: u8,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct OptionsStatus {
+    /// Nonzero if values are staged.
+    pub staged: u8,
+    /// Nonzero if the staged values have been verified.
+    pub verified: u8,
+    /// Number of staged fields that differ from those in force.
+    pub changes: u8,
+    /// Nonzero if this server only pretends to change anything.
+    pub simulated: u8,
+    /// Commits since the server started.
+    pub commits: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -29,6 +29,8 @@ pub enum FpgaError {
     AuxReadError,
     AuxMissingBlob,
     CommsError,
+    BitstreamChecksumMismatch,
+    NoSubscriptionSlots,
 }
 
 // TODO is this right? We cause clients to panic if we die; should we have a
@@ -52,6 +54,8 @@ impl From<FpgaError> for u16 {
             FpgaError::AuxReadError => 0x0504,
             FpgaError::AuxMissingBlob => 0x0505,
             FpgaError::CommsError => 0x0506,
+            FpgaError::BitstreamChecksumMismatch => 0x0507,
+            FpgaError::NoSubscriptionSlots => 0x0509,
         }
     }
 }
@@ -86,6 +90,8 @@ impl core::convert::TryFrom<u16> for FpgaError {
                 0x0504 => Ok(FpgaError::AuxReadError),
                 0x0505 => Ok(FpgaError::AuxMissingBlob),
                 0x0506 => Ok(FpgaError::CommsError),
+                0x0507 => Ok(FpgaError::BitstreamChecksumMismatch),
+                0x0509 => Ok(FpgaError::NoSubscriptionSlots),
                 _ => Err(()),
             },
         }
@@ -228,6 +234,18 @@ impl Bitstream {
         (*self.0).finish_bitstream_load()
     }
 
+    /// Finishes loading the bitstream only if the CRC-32 of all data passed
+    /// to `continue_load` matches `expected_crc32`; otherwise, the load is
+    /// aborted and the device is left awaiting a bitstream.  Nothing streams
+    /// bitstreams in from the network yet; this is for loads driven from a
+    /// debugger (e.g. via `hiffy`), and for that path when it comes.
+    pub fn finish_load_checked(
+        &mut self,
+        expected_crc32: u32,
+    ) -> Result<(), FpgaError> {
+        (*self.0).finish_bitstream_load_checked(expected_crc32)
+    }
+
     pub fn cancel_load(&mut self) -> Result<(), FpgaError> {
         (*self.0).reset_device(self.0.device_index)
     }
@@ -311,6 +329,27 @@ impl FpgaUserDesign {
         self.server
             .user_design_write(self.device_index, op, addr.into(), data)
     }
+
+    /// Ask the server to post `notification` to the calling task whenever any
+    /// of the bits in `mask` change in the register at `addr`. Subscribing
+    /// again to the same register replaces the previous subscription.
+    pub fn subscribe(
+        &self,
+        addr: impl Into<u16>,
+        mask: u8,
+        notification: u32,
+    ) -> Result<(), FpgaError> {
+        self.server.subscribe(
+            self.device_index,
+            addr.into(),
+            mask,
+            notification,
+        )
+    }
+
+    pub fn unsubscribe(&self, addr: impl Into<u16>) -> Result<(), FpgaError> {
+        self.server.unsubscribe(self.device_index, addr.into())
+    }
 }
 
 /// Poll the device state of the FPGA to determine if it is either ready to receive
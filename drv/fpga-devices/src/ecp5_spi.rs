@@ -43,6 +43,8 @@ impl From<Ecp5UsingSpiError> for u8 {
                 SpiError::TaskRestarted => 4,
                 SpiError::NothingToRelease => 5,
                 SpiError::BadDevice => 6,
+                SpiError::Busy => 7,
+                SpiError::LockExpired => 8,
             },
         }
     }
@@ -41,6 +41,9 @@ impl From<Error> for u8 {
                 SpiError::TaskRestarted => 4,
                 SpiError::NothingToRelease => 5,
                 SpiError::BadDevice => 6,
+                // 8 and up are taken by I2C errors, so lock contention and
+                // expiry share a code.
+                SpiError::Busy | SpiError::LockExpired => 7,
             },
             Error::I2cError(e) => 8 + (e as u8),
         }
@@ -15,7 +15,7 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     }
 
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/fpga.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
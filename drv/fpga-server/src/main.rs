@@ -7,6 +7,7 @@
 #![no_std]
 #![no_main]
 
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
 use ringbuf::*;
 use userlib::*;
 use zerocopy::{byteorder, AsBytes, Unaligned, U16};
@@ -15,7 +16,7 @@ use drv_fpga_api::{BitstreamType, DeviceState, FpgaError, WriteOp};
 use drv_fpga_devices::{ecp5, Fpga, FpgaBitstream, FpgaUserDesign};
 use drv_spi_api::SpiServer;
 use drv_stm32xx_sys_api::{self as sys_api, Sys};
-use idol_runtime::{ClientError, Leased, LenLimit, R, W};
+use idol_runtime::{ClientError, Leased, LenLimit, NotificationHandler, R, W};
 
 task_slot!(SYS, sys);
 
@@ -53,11 +54,28 @@ enum Trace {
     StartBitstreamLoad(u8, BitstreamType),
     ContinueBitstreamLoad(usize),
     FinishBitstreamLoad(usize),
+    BitstreamChecksumMismatch { expected: u32, actual: u32 },
     Locked(TaskId),
     Released(TaskId),
+    Subscribed { task: TaskId, addr: u16, mask: u8 },
+    Unsubscribed { task: TaskId, addr: u16 },
+    SubscriberRestarted(TaskId),
+    RegisterChanged { addr: u16, old: u8, new: u8 },
+    ScanFailed(u8, FpgaError),
 }
 ringbuf!(Trace, 64, Trace::None);
 
+/// Maximum number of register subscriptions across all client tasks
+const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// Interval at which subscribed registers are checked for changes, in
+/// milliseconds
+const SCAN_INTERVAL: u64 = 20;
+
+/// CRC used to check a bitstream (as sent by the client) before finishing a
+/// load
+static BITSTREAM_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
 #[export_name = "main"]
 fn main() -> ! {
     let sys = Sys::from(SYS.get_task_id());
@@ -87,6 +105,7 @@ fn main() -> ! {
             driver.configure_gpio();
 
             let devices = [ecp5::Ecp5::new(driver)];
+            let irq = None;
         } else if #[cfg(all(any(target_board = "sidecar-b",
                                 target_board = "sidecar-c"),
                             feature = "front_io"))] {
@@ -131,6 +150,7 @@ fn main() -> ! {
                     Err(_) => userlib::hl::sleep_for(10),
                 }
             };
+            let irq = None;
         } else if #[cfg(target_board = "gimletlet-2")] {
             // Hard-coding because the TOML file doesn't specify great names
             let configuration_port = spi.device(0);
@@ -148,17 +168,27 @@ fn main() -> ! {
             driver.configure_gpio();
 
             let devices = [ecp5::Ecp5::new(driver)];
+            let irq = None;
         } else {
             compile_error!("Board is not supported by drv/fpga-server");
         }
     }
 
+    let sys = Sys::from(SYS.get_task_id());
+    if let Some(irq) = irq {
+        sys.gpio_configure_input(irq, sys_api::Pull::Up);
+    }
+
     let mut incoming = [0u8; idl::INCOMING_SIZE];
     let mut server = ServerImpl {
         lock_holder: None,
         devices: &devices,
         buffer: [0u8; 128],
         bitstream_loader: None,
+        bitstream_crc: None,
+        sys,
+        irq,
+        subscriptions: [None; MAX_SUBSCRIPTIONS],
     };
 
     for (i, device) in server.devices.iter().enumerate() {
@@ -171,7 +201,7 @@ fn main() -> ! {
     }
 
     loop {
-        idol_runtime::dispatch(&mut incoming, &mut server);
+        idol_runtime::dispatch_n(&mut incoming, &mut server);
     }
 }
 
@@ -185,11 +215,32 @@ struct LockState {
     device_index: usize,
 }
 
+/// A client's interest in changes to bits of a user design register
+#[derive(Copy, Clone)]
+struct Subscription {
+    task: userlib::TaskId,
+    device_index: usize,
+    addr: u16,
+    mask: u8,
+    notification: u32,
+
+    /// Register value as of the last scan
+    last: u8,
+}
+
 struct ServerImpl<'a, Device: Fpga<'a> + FpgaUserDesign> {
     lock_holder: Option<LockState>,
     devices: &'a [Device],
     buffer: [u8; 128],
     bitstream_loader: Option<BitstreamLoader<'a, Device>>,
+    bitstream_crc: Option<Digest<'static, u32>>,
+    sys: Sys,
+
+    /// If the board routes the FPGA interrupt line to us, the (active low) pin
+    /// on which it arrives. Subscribed registers are only scanned while it is
+    /// asserted; without it, they are scanned on every tick.
+    irq: Option<sys_api::PinSet>,
+    subscriptions: [Option<Subscription>; MAX_SUBSCRIPTIONS],
 }
 
 /// This UserDesignLock is used to ensure atomic read/write operations to the
@@ -240,6 +291,132 @@ impl<'a, Device: Fpga<'a> + FpgaUserDesign> ServerImpl<'a, Device> {
         device.user_design_lock().map_err(FpgaError::from)?;
         Ok(UserDesignLock(device))
     }
+
+    fn read_user_design_reg(
+        &mut self,
+        device: &'a Device,
+        addr: u16,
+    ) -> Result<u8, FpgaError> {
+        let header = UserDesignRequestHeader {
+            cmd: 0x1,
+            addr: U16::new(addr),
+        };
+
+        device.user_design_lock().map_err(FpgaError::from)?;
+        let lock = UserDesignLock(device);
+
+        lock.0
+            .user_design_write(header.as_bytes())
+            .map_err(FpgaError::from)?;
+        lock.0
+            .user_design_read(&mut self.buffer[..1])
+            .map_err(FpgaError::from)?;
+
+        Ok(self.buffer[0])
+    }
+
+    fn irq_asserted(&self) -> bool {
+        match self.irq {
+            Some(irq) => self.sys.gpio_read(irq) == 0,
+            None => true,
+        }
+    }
+
+    /// Check subscribed registers for changes, posting notifications to the
+    /// subscribers of any that have changed.
+    fn scan_subscriptions(&mut self) {
+        // A lock holder may be loading a bitstream or otherwise expects the
+        // device to be left alone; subscribers will hear about any changes
+        // once the lock is released.
+        if self.lock_holder.is_some() || !self.irq_asserted() {
+            return;
+        }
+
+        for device_index in 0..self.devices.len() {
+            if !self
+                .subscriptions
+                .iter()
+                .flatten()
+                .any(|s| s.device_index == device_index)
+            {
+                continue;
+            }
+
+            let device = &self.devices[device_index];
+            match device.device_state() {
+                Ok(DeviceState::RunningUserDesign) => (),
+                Ok(_) => continue,
+                Err(e) => {
+                    ringbuf_entry!(Trace::ScanFailed(device_index as u8, e));
+                    continue;
+                }
+            }
+
+            for i in 0..self.subscriptions.len() {
+                let Some(sub) = self.subscriptions[i] else {
+                    continue;
+                };
+
+                if sub.device_index != device_index {
+                    continue;
+                }
+
+                // A subscriber that has restarted since subscribing is no
+                // longer expecting our notifications.
+                let task = sys_refresh_task_id(sub.task);
+                if task != sub.task {
+                    ringbuf_entry!(Trace::SubscriberRestarted(sub.task));
+                    self.subscriptions[i] = None;
+                    continue;
+                }
+
+                let new = match self.read_user_design_reg(device, sub.addr) {
+                    Ok(new) => new,
+                    Err(e) => {
+                        ringbuf_entry!(Trace::ScanFailed(
+                            device_index as u8,
+                            e
+                        ));
+                        continue;
+                    }
+                };
+
+                if (new ^ sub.last) & sub.mask != 0 {
+                    ringbuf_entry!(Trace::RegisterChanged {
+                        addr: sub.addr,
+                        old: sub.last,
+                        new,
+                    });
+                    sys_post(task, sub.notification);
+                }
+
+                if let Some(sub) = &mut self.subscriptions[i] {
+                    sub.last = new;
+                }
+            }
+        }
+    }
+}
+
+fn arm_scan_timer() {
+    let deadline = sys_get_timer().now + SCAN_INTERVAL;
+    sys_set_timer(Some(deadline), notifications::TIMER_MASK);
+}
+
+impl<'a, Device: Fpga<'a> + FpgaUserDesign> NotificationHandler
+    for ServerImpl<'a, Device>
+{
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.scan_subscriptions();
+
+        if self.subscriptions.iter().any(Option::is_some) {
+            arm_scan_timer();
+        }
+    }
 }
 
 type RequestError = idol_runtime::RequestError<FpgaError>;
@@ -257,6 +434,7 @@ impl<'a, Device: Fpga<'a> + FpgaUserDesign> idl::InOrderFpgaImpl
         // lock and any resources acquired from the device driver.
         self.lock_holder = None;
         self.bitstream_loader = None;
+        self.bitstream_crc = None;
     }
 
     fn lock(
@@ -402,6 +580,8 @@ impl<'a, Device: Fpga<'a> + FpgaUserDesign> idl::InOrderFpgaImpl
             ),
         });
 
+        self.bitstream_crc = Some(BITSTREAM_CRC.digest());
+
         ringbuf_entry!(Trace::StartBitstreamLoad(device_index, bitstream_type));
         Ok(())
     }
@@ -416,6 +596,10 @@ impl<'a, Device: Fpga<'a> + FpgaUserDesign> idl::InOrderFpgaImpl
 
         let mut chunk = &self.buffer[..data.len()];
 
+        if let Some(crc) = &mut self.bitstream_crc {
+            crc.update(chunk);
+        }
+
         match &mut self.bitstream_loader {
             None => return Err(RequestError::Runtime(FpgaError::InvalidState)),
             Some(BitstreamLoader::Uncompressed(bitstream, len)) => {
@@ -465,9 +649,41 @@ impl<'a, Device: Fpga<'a> + FpgaUserDesign> idl::InOrderFpgaImpl
         }
 
         self.bitstream_loader = None;
+        self.bitstream_crc = None;
         Ok(())
     }
 
+    fn finish_bitstream_load_checked(
+        &mut self,
+        msg: &RecvMessage,
+        expected_crc32: u32,
+    ) -> Result<(), RequestError> {
+        let crc = match self.bitstream_crc.take() {
+            Some(crc) if self.bitstream_loader.is_some() => crc.finalize(),
+            _ => return Err(RequestError::Runtime(FpgaError::InvalidState)),
+        };
+
+        if crc != expected_crc32 {
+            ringbuf_entry!(Trace::BitstreamChecksumMismatch {
+                expected: expected_crc32,
+                actual: crc,
+            });
+
+            // Abandon the load without letting the device wake up, leaving it
+            // ready for another attempt.
+            self.bitstream_loader = None;
+            if let Some(lock_state) = &self.lock_holder {
+                self.devices[lock_state.device_index].reset_device()?;
+            }
+
+            return Err(RequestError::Runtime(
+                FpgaError::BitstreamChecksumMismatch,
+            ));
+        }
+
+        self.finish_bitstream_load(msg)
+    }
+
     fn user_design_read(
         &mut self,
         msg: &RecvMessage,
@@ -548,22 +764,10 @@ impl<'a, Device: Fpga<'a> + FpgaUserDesign> idl::InOrderFpgaImpl
         device_index: u8,
         addr: u16,
     ) -> Result<u8, RequestError> {
-        let header = UserDesignRequestHeader {
-            cmd: 0x1,
-            addr: U16::new(addr),
-        };
-
-        // Released on function exit.
-        let lock = self.lock_user_design(msg.sender, device_index)?;
-
-        lock.0
-            .user_design_write(header.as_bytes())
-            .map_err(FpgaError::from)?;
-        lock.0
-            .user_design_read(&mut self.buffer[..1])
-            .map_err(FpgaError::from)?;
+        let device =
+            self.check_lock_and_get_device(msg.sender, device_index)?;
 
-        Ok(self.buffer[0])
+        self.read_user_design_reg(device, addr).map_err(Into::into)
     }
 
     fn user_design_write_reg(
@@ -591,6 +795,81 @@ impl<'a, Device: Fpga<'a> + FpgaUserDesign> idl::InOrderFpgaImpl
 
         Ok(())
     }
+
+    fn subscribe(
+        &mut self,
+        msg: &RecvMessage,
+        device_index: u8,
+        addr: u16,
+        mask: u8,
+        notification: u32,
+    ) -> Result<(), RequestError> {
+        if mask == 0 || notification == 0 {
+            return Err(RequestError::Runtime(FpgaError::InvalidValue));
+        }
+
+        let device =
+            self.check_lock_and_get_device(msg.sender, device_index)?;
+        let device_index = usize::from(device_index);
+
+        let existing = self.subscriptions.iter().position(|s| {
+            matches!(s, Some(s) if s.task == msg.sender
+                && s.device_index == device_index
+                && s.addr == addr)
+        });
+        let slot = existing
+            .or_else(|| self.subscriptions.iter().position(Option::is_none))
+            .ok_or(FpgaError::NoSubscriptionSlots)?;
+
+        // Changes are reported relative to the value at the time of
+        // subscription.
+        let last = self.read_user_design_reg(device, addr)?;
+
+        let armed = self.subscriptions.iter().any(Option::is_some);
+        self.subscriptions[slot] = Some(Subscription {
+            task: msg.sender,
+            device_index,
+            addr,
+            mask,
+            notification,
+            last,
+        });
+
+        if !armed {
+            arm_scan_timer();
+        }
+
+        ringbuf_entry!(Trace::Subscribed {
+            task: msg.sender,
+            addr,
+            mask,
+        });
+        Ok(())
+    }
+
+    fn unsubscribe(
+        &mut self,
+        msg: &RecvMessage,
+        device_index: u8,
+        addr: u16,
+    ) -> Result<(), RequestError> {
+        let device_index = usize::from(device_index);
+
+        for slot in self.subscriptions.iter_mut() {
+            if matches!(slot, Some(s) if s.task == msg.sender
+                && s.device_index == device_index
+                && s.addr == addr)
+            {
+                *slot = None;
+                ringbuf_entry!(Trace::Unsubscribed {
+                    task: msg.sender,
+                    addr,
+                });
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(AsBytes, Unaligned)]
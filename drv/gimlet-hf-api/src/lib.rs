@@ -30,6 +30,11 @@ pub enum HfError {
     Sector0IsReserved,
     NoPersistentData,
     MonotonicCounterOverflow,
+    WriteProtected,
+    WriteProtectFailed,
+    BadProtectRange,
+    BadProtectWindow,
+    RotNotAttested,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -98,4 +103,25 @@ pub struct HfPersistentData {
     pub dev_select: HfDevSelect,
 }
 
+/// Longest we'll lift write protection for, in milliseconds
+pub const MAX_WRITE_PROTECT_WINDOW_MS: u32 = 30 * 60 * 1000;
+
+/// Host flash write protection, as set by the control plane and as it stands
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct HfWriteProtect {
+    /// First protected address
+    pub start: u32,
+    /// Length of the protected range, which is 0 if nothing is protected
+    pub len: u32,
+    /// Whether lifting protection requires the RoT to have been attested
+    /// since we started
+    pub require_attested_rot: bool,
+    /// Whether the RoT has been attested since we started
+    pub rot_attested: bool,
+    /// Milliseconds until protection comes back, if it's lifted
+    pub lifted_ms: Option<u32>,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -6,6 +6,23 @@
 //!
 //! This server is responsible for managing access to the host flash; it embeds
 //! the QSPI flash driver.
+//!
+//! It also write protects a range of the host flash, as the control plane
+//! asks, using the parts' block protect bits: these protect a power-of-two
+//! number of sectors at the top or bottom of a part, or all of it, from
+//! whoever is muxed to it. (There's no /WP line to lock those bits with, IO2
+//! being data in quad mode, so this guards against mistakes rather than a
+//! host bent on writing.) Programs and erases of ours are refused in the
+//! range too. Protection can be lifted for a window of time, for an update,
+//! and comes back by itself when the window ends; if the control plane says
+//! so, lifting it also requires that the control plane have attested the RoT
+//! since we started. Changes reach the parts straight away if we're muxed to
+//! them, and otherwise when we next are. Protection is never lifted while the
+//! host has the flash: handing it over ends any window, and no window can be
+//! opened until we have it back.
+//!
+//! Only the tasks the app names in `allowed-callers` can set protection,
+//! record the RoT's attestation or lift protection; by default, none can.
 
 #![no_std]
 #![no_main]
@@ -28,10 +45,13 @@ mod bsp;
 
 use userlib::*;
 
+use core::ops::Range;
 use drv_gimlet_hf_api::SECTOR_SIZE_BYTES;
-use drv_stm32h7_qspi::Qspi;
+use drv_stm32h7_qspi::{AddressWidth, Protocol, Qspi, ReadMode};
 use drv_stm32xx_sys_api as sys_api;
-use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R, W};
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
 use zerocopy::{AsBytes, FromBytes};
 
 #[cfg(feature = "h743")]
@@ -49,7 +69,7 @@ use drv_hash_api::SHA256_SZ;
 
 use drv_gimlet_hf_api::{
     HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
-    PAGE_SIZE_BYTES,
+    HfWriteProtect, MAX_WRITE_PROTECT_WINDOW_MS, PAGE_SIZE_BYTES,
 };
 
 task_slot!(SYS, sys);
@@ -90,7 +110,7 @@ fn main() -> ! {
     sys.leave_reset(sys_api::Peripheral::QuadSpi);
 
     let reg = unsafe { &*device::QUADSPI::ptr() };
-    let qspi = Qspi::new(reg, notifications::QSPI_IRQ_MASK);
+    let mut qspi = Qspi::new(reg, notifications::QSPI_IRQ_MASK);
 
     // Build a pin struct using a board-specific init function
     let cfg = bsp::init(&qspi, &sys);
@@ -109,9 +129,9 @@ fn main() -> ! {
 
     // Check the ID.
     // TODO: If different flash parts are used on the same board name,
-    // then hard-coding commands, capacity, and clocks will get us into
-    // trouble. Someday we will need more flexability here.
-    let log2_capacity = {
+    // then hard-coding capacity and clocks will get us into trouble. Someday
+    // we will need more flexability here.
+    let part = {
         let mut idbuf = [0; 20];
         qspi.read_id(&mut idbuf);
 
@@ -122,7 +142,7 @@ fn main() -> ! {
                 if idbuf[1] != 0x40 {
                     None
                 } else {
-                    Some(idbuf[2])
+                    Some(Part::Winbond(idbuf[2]))
                 }
             }
             0x20 => {
@@ -131,20 +151,27 @@ fn main() -> ! {
                     None
                 } else {
                     // TODO: Stash, or read on demand, Micron Unique ID for measurement?
-                    Some(idbuf[2])
+                    Some(Part::Micron(idbuf[2]))
                 }
             }
             _ => None, // Unknown
         }
     };
 
-    let Some(log2_capacity) = log2_capacity else {
+    let Some(part) = part else {
         loop {
             // We are dead now.
             hl::sleep_for(1000);
         }
     };
+    let log2_capacity = part.log2_capacity();
     qspi.configure(cfg.clock, log2_capacity);
+    qspi.set_protocol(part.protocol(&qspi));
+
+    // Until the control plane tells us otherwise, we keep whatever protection
+    // the selected part has -- its block protect bits are non-volatile -- and
+    // only lift it for an attested RoT.
+    let protect = part.read_block_protect(qspi.read_status());
 
     let mut buffer = [0; idl::INCOMING_SIZE];
     let mut server = ServerImpl {
@@ -155,6 +182,15 @@ fn main() -> ! {
         dev_state: HfDevSelect::Flash0,
         mux_select_pin: cfg.sp_host_mux_select,
         dev_select_pin: cfg.flash_dev_select,
+        in_flight: None,
+        part,
+        wp: WriteProtect {
+            protect,
+            require_attested_rot: true,
+            rot_attested: false,
+            lifted_until: None,
+            stale: true,
+        },
     };
 
     server.ensure_persistent_data_is_redundant().unwrap(); // TODO: log this?
@@ -177,11 +213,186 @@ fn main() -> ! {
         }
     };
 
+    // Make any other chip match the one we took our protection from. If this
+    // fails, the parts stay marked stale, and we try again whenever we're next
+    // asked to touch protection or the mux; meanwhile our own writes are
+    // still checked against the protected range.
+    let _ = server.sync_block_protect();
+
     loop {
-        idol_runtime::dispatch(&mut buffer, &mut server);
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Flash parts we know how to talk to, with the log2 of their capacity
+#[derive(Copy, Clone)]
+enum Part {
+    Winbond(u8),
+    Micron(u8),
+}
+
+impl Part {
+    fn log2_capacity(self) -> u8 {
+        match self {
+            Part::Winbond(c) | Part::Micron(c) => c,
+        }
+    }
+
+    /// Picks the fastest way of talking to the part that it supports.
+    fn protocol(self, qspi: &Qspi) -> Protocol {
+        // Parts over 16 MiB need 4-byte addresses; smaller ones may not
+        // implement the 4-byte commands at all.
+        let address = if self.log2_capacity() > 24 {
+            AddressWidth::FourByte
+        } else {
+            AddressWidth::ThreeByte
+        };
+        match self {
+            Part::Winbond(..) => {
+                // Quad commands only work if the QE bit in status register 2
+                // is set; otherwise IO2 and IO3 are /WP and /HOLD. That bit is
+                // non-volatile (and fixed on some parts), so we don't set it
+                // ourselves -- we just use it if it's there.
+                let quad = qspi.read_status_reg2() & 0b10 != 0;
+                Protocol {
+                    address,
+                    read: if quad {
+                        ReadMode::QuadIo
+                    } else {
+                        ReadMode::Single
+                    },
+                    // Two mode cycles plus four dummy cycles for EBh/ECh.
+                    read_dummy_cycles: 6,
+                    quad_program: quad,
+                    suspend: true,
+                }
+            }
+            Part::Micron(..) => Protocol {
+                address,
+                // Quad commands are always available on Micron parts.
+                read: ReadMode::QuadIo,
+                // Default from the nonvolatile configuration register.
+                read_dummy_cycles: 10,
+                quad_program: true,
+                suspend: true,
+            },
+        }
+    }
+}
+
+/// A setting of a part's block protect bits
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct BlockProtect {
+    /// The BP field, which protects `SECTOR_SIZE_BYTES << (bp - 1)` bytes, or
+    /// all of the part if that's more than there is; 0 protects nothing.
+    bp: u8,
+    /// Whether the protected sectors are at the bottom of the part (the TB
+    /// bit), rather than the top
+    bottom: bool,
+}
+
+impl BlockProtect {
+    const NONE: Self = Self {
+        bp: 0,
+        bottom: false,
+    };
+}
+
+impl Part {
+    /// Returns the positions of the BP3 and TB bits in the status register,
+    /// BP0 to BP2 being bits 2 to 4, for parts whose layout we know.
+    fn block_protect_layout(self) -> Option<(u8, u8)> {
+        match self {
+            // Smaller Winbond parts have only three BP bits.
+            Part::Winbond(c) if c >= 25 => Some((5, 6)),
+            Part::Winbond(..) => None,
+            Part::Micron(..) => Some((6, 5)),
+        }
+    }
+
+    /// Returns the status register bits that `block_protect_status` sets.
+    fn block_protect_mask(self) -> Option<u8> {
+        let (bp3, tb) = self.block_protect_layout()?;
+        Some(0b111 << 2 | 1 << bp3 | 1 << tb)
+    }
+
+    fn block_protect_status(self, p: BlockProtect) -> u8 {
+        match self.block_protect_layout() {
+            Some((bp3, tb)) => {
+                (p.bp & 0b111) << 2
+                    | (p.bp >> 3) << bp3
+                    | u8::from(p.bottom) << tb
+            }
+            None => 0,
+        }
+    }
+
+    fn read_block_protect(self, status: u8) -> BlockProtect {
+        match self.block_protect_layout() {
+            Some((bp3, tb)) => BlockProtect {
+                bp: (status >> 2) & 0b111 | ((status >> bp3) & 1) << 3,
+                bottom: (status >> tb) & 1 != 0,
+            },
+            None => BlockProtect::NONE,
+        }
+    }
+
+    /// Returns the addresses that `p` protects.
+    fn protected_range(self, p: BlockProtect) -> Range<u32> {
+        let capacity = 1u32 << self.log2_capacity();
+        if p.bp == 0 {
+            return 0..0;
+        }
+        let len = (SECTOR_SIZE_BYTES as u32) << (p.bp - 1);
+        if len >= capacity {
+            0..capacity
+        } else if p.bottom {
+            0..len
+        } else {
+            capacity - len..capacity
+        }
+    }
+
+    /// Finds the block protect setting that protects exactly `len` bytes from
+    /// `start`, if there is one.
+    fn block_protect_for(self, start: u32, len: u32) -> Option<BlockProtect> {
+        if len == 0 {
+            return Some(BlockProtect::NONE);
+        }
+        self.block_protect_layout()?;
+        let range = start..start.checked_add(len)?;
+        (1..16)
+            .flat_map(|bp| {
+                [false, true].map(|bottom| BlockProtect { bp, bottom })
+            })
+            .find(|&p| self.protected_range(p) == range)
     }
 }
 
+/// Write protection, as the control plane has asked for it
+struct WriteProtect {
+    /// What the parts' block protect bits should be, unless it's lifted
+    protect: BlockProtect,
+    require_attested_rot: bool,
+    rot_attested: bool,
+    /// When protection comes back, if it's lifted
+    lifted_until: Option<u64>,
+    /// Whether the parts' bits may not be what they should, because they've
+    /// been out of our reach (or we failed to set them)
+    stale: bool,
+}
+
+/// A program or sector erase which has been started, but which may still be
+/// in progress
+struct InFlight {
+    /// Addresses being modified
+    range: Range<u32>,
+    /// Time to sleep between status polls while waiting for it to finish
+    poll_interval: Option<u64>,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Represents persistent data that is both stored on the host flash and used to
@@ -277,6 +488,17 @@ struct ServerImpl {
     /// changed by `set_dev` without necessarily being persisted to flash.
     dev_state: HfDevSelect,
     dev_select_pin: Option<sys_api::PinSet>,
+
+    /// Program or erase started by a client and not yet known to be done.
+    ///
+    /// Rather than block all other clients for the milliseconds that a sector
+    /// erase takes, we return as soon as it's started; anything touching the
+    /// flash afterwards first waits for it to finish, except reads outside the
+    /// affected range, which suspend it instead.
+    in_flight: Option<InFlight>,
+
+    part: Part,
+    wp: WriteProtect,
 }
 
 impl ServerImpl {
@@ -294,6 +516,121 @@ impl ServerImpl {
         }
     }
 
+    /// Returns what the parts' block protect bits should be right now.
+    fn block_protect(&self) -> BlockProtect {
+        match self.wp.lifted_until {
+            Some(_) => BlockProtect::NONE,
+            None => self.wp.protect,
+        }
+    }
+
+    /// Checks that `range` isn't write protected, before we program or erase
+    /// it for a client.
+    fn check_write_protect(&self, range: Range<u32>) -> Result<(), HfError> {
+        let p = self.part.protected_range(self.block_protect());
+        if range.start < p.end && p.start < range.end {
+            Err(HfError::WriteProtected)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sets the block protect bits of each flash chip to what they should be,
+    /// or, if we aren't muxed to them, leaves that until we are.
+    fn sync_block_protect(&mut self) -> Result<(), HfError> {
+        self.wp.stale = true;
+        if self.mux_state != HfMuxState::SP {
+            return Ok(());
+        }
+        self.finish_in_flight();
+
+        let p = self.block_protect();
+        if self.dev_select_pin.is_some() {
+            // As for persistent data, we can unwrap `set_dev` here.
+            let prev_slot = self.dev_state;
+            self.set_dev(!prev_slot).unwrap();
+            let out = self.write_block_protect(p);
+            self.set_dev(prev_slot).unwrap();
+            out?;
+        }
+        self.write_block_protect(p)?;
+
+        self.wp.stale = false;
+        Ok(())
+    }
+
+    /// Sets the block protect bits of the selected chip to `p`, unless they
+    /// already are; they're non-volatile, so we write them no more than we
+    /// have to.
+    fn write_block_protect(&self, p: BlockProtect) -> Result<(), HfError> {
+        let Some(mask) = self.part.block_protect_mask() else {
+            // We only ever protect nothing on parts like this.
+            return Ok(());
+        };
+        let bits = self.part.block_protect_status(p);
+        if self.qspi.read_status() & mask == bits {
+            return Ok(());
+        }
+
+        self.set_and_check_write_enable()?;
+        // This also clears the bit that would lock the block protect bits
+        // with /WP, which we don't have.
+        self.qspi.write_status(bits);
+        self.poll_for_write_complete(Some(1));
+
+        if self.qspi.read_status() & mask != bits {
+            return Err(HfError::WriteProtectFailed);
+        }
+        Ok(())
+    }
+
+    /// Brings protection back, if it's lifted.
+    fn engage_write_protect(&mut self) -> Result<(), HfError> {
+        if self.wp.lifted_until.take().is_some() {
+            sys_set_timer(None, notifications::TIMER_MASK);
+        }
+        self.sync_block_protect()
+    }
+
+    /// Waits for any in-flight program or erase to finish.
+    fn finish_in_flight(&mut self) {
+        if let Some(op) = self.in_flight.take() {
+            self.poll_for_write_complete(op.poll_interval);
+        }
+    }
+
+    /// Reads `len` bytes at `addr` into `self.block`.
+    ///
+    /// If a program or erase is in flight and the part can suspend it, and the
+    /// read doesn't overlap the addresses being modified, the operation is
+    /// suspended for the duration of the read rather than waited out.
+    fn read_block(&mut self, addr: u32, len: usize) {
+        let end = addr.saturating_add(len as u32);
+        let suspend = match &self.in_flight {
+            Some(op) => {
+                self.qspi.protocol().suspend
+                    && (end <= op.range.start || addr >= op.range.end)
+            }
+            None => false,
+        };
+
+        if !suspend {
+            self.finish_in_flight();
+            self.qspi.read_memory(addr, &mut self.block[..len]);
+        } else if self.qspi.read_status() & 1 == 0 {
+            // It finished on its own.
+            self.in_flight = None;
+            self.qspi.read_memory(addr, &mut self.block[..len]);
+        } else {
+            self.qspi.suspend();
+            // Suspending takes some tens of microseconds; WIP clears once the
+            // part is ready for reads.
+            self.poll_for_write_complete(None);
+            self.qspi.read_memory(addr, &mut self.block[..len]);
+            self.qspi.resume();
+        }
+    }
+
     fn page_program_raw(&self, addr: u32, data: &[u8]) -> Result<(), HfError> {
         self.set_and_check_write_enable()?;
         self.qspi.page_program(addr, data);
@@ -307,6 +644,9 @@ impl ServerImpl {
 
         self.check_muxed_to_sp()?;
 
+        // Anything in flight is on the currently selected chip.
+        self.finish_in_flight();
+
         let sys = sys_api::Sys::from(SYS.get_task_id());
         match state {
             HfDevSelect::Flash0 => sys.gpio_reset(dev_select_pin),
@@ -395,16 +735,36 @@ impl ServerImpl {
         &mut self,
         addr: u32,
         protect: HfProtectMode,
+    ) -> Result<(), HfError> {
+        self.start_sector_erase(addr, protect)?;
+        self.finish_in_flight();
+        Ok(())
+    }
+
+    /// Starts erasing the sector containing the given address, recording it
+    /// as in flight rather than waiting for it to finish.
+    ///
+    /// The same sector 0 protections apply as for `sector_erase`.
+    fn start_sector_erase(
+        &mut self,
+        addr: u32,
+        protect: HfProtectMode,
     ) -> Result<(), HfError> {
         if addr as usize / SECTOR_SIZE_BYTES == 0
             && !matches!(protect, HfProtectMode::AllowModificationsToSector0)
         {
-            return Err(HfError::Sector0IsReserved.into());
+            return Err(HfError::Sector0IsReserved);
         }
         self.check_muxed_to_sp()?;
+        self.finish_in_flight();
         self.set_and_check_write_enable()?;
         self.qspi.sector_erase(addr);
-        self.poll_for_write_complete(Some(1));
+
+        let start = addr & !(SECTOR_SIZE_BYTES as u32 - 1);
+        self.in_flight = Some(InFlight {
+            range: start..start.saturating_add(SECTOR_SIZE_BYTES as u32),
+            poll_interval: Some(1),
+        });
         Ok(())
     }
 
@@ -418,17 +778,25 @@ impl ServerImpl {
         addr: Option<u32>,
         raw_data: &RawPersistentData,
     ) -> Result<(), HfError> {
-        let addr = match addr {
-            Some(a) => a,
-            None => {
-                self.sector_erase(
-                    0,
-                    HfProtectMode::AllowModificationsToSector0,
-                )?;
-                0
-            }
+        // Persistent data is ours to write, protected or not.
+        let p = self.block_protect();
+        let protected = self.part.protected_range(p).contains(&0);
+        if protected {
+            self.finish_in_flight();
+            self.write_block_protect(BlockProtect::NONE)?;
+        }
+
+        let out = match addr {
+            Some(a) => self.page_program_raw(a, raw_data.as_bytes()),
+            None => self
+                .sector_erase(0, HfProtectMode::AllowModificationsToSector0)
+                .and_then(|()| self.page_program_raw(0, raw_data.as_bytes())),
         };
-        self.page_program_raw(addr, raw_data.as_bytes())
+
+        if protected {
+            self.write_block_protect(p)?;
+        }
+        out
     }
 
     /// Checks that the persistent data is consistent between the two flash ICs.
@@ -511,6 +879,7 @@ impl ServerImpl {
     }
 
     fn get_persistent_data(&mut self) -> Result<HfPersistentData, HfError> {
+        self.finish_in_flight();
         let out = self.get_raw_persistent_data()?;
         Ok(HfPersistentData {
             dev_select: HfDevSelect::from_u8(out.dev_select as u8).unwrap(),
@@ -524,6 +893,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         _: &RecvMessage,
     ) -> Result<[u8; 20], RequestError<HfError>> {
         self.check_muxed_to_sp()?;
+        self.finish_in_flight();
 
         let mut idbuf = [0; 20];
         self.qspi.read_id(&mut idbuf);
@@ -554,6 +924,8 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
             return Err(HfError::Sector0IsReserved.into());
         }
         self.check_muxed_to_sp()?;
+        self.check_write_protect(0..self.capacity as u32)?;
+        self.finish_in_flight();
         self.set_and_check_write_enable()?;
         self.qspi.bulk_erase();
         self.poll_for_write_complete(Some(100));
@@ -573,12 +945,19 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
             return Err(HfError::Sector0IsReserved.into());
         }
         self.check_muxed_to_sp()?;
+        self.check_write_protect(addr..addr.saturating_add(data.len() as u32))?;
         // Read the entire data block into our address space.
         data.read_range(0..data.len(), &mut self.block[..data.len()])
             .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
 
-        // Now we can't fail. (TODO is this comment outdated?)
-        self.page_program_raw(addr, &self.block[..data.len()])?;
+        // Start the program, but don't wait for it; see `in_flight`.
+        self.finish_in_flight();
+        self.set_and_check_write_enable()?;
+        self.qspi.page_program(addr, &self.block[..data.len()]);
+        self.in_flight = Some(InFlight {
+            range: addr..addr.saturating_add(data.len() as u32),
+            poll_interval: None,
+        });
         Ok(())
     }
 
@@ -589,7 +968,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         dest: LenLimit<Leased<W, [u8]>, PAGE_SIZE_BYTES>,
     ) -> Result<(), RequestError<HfError>> {
         self.check_muxed_to_sp()?;
-        self.qspi.read_memory(addr, &mut self.block[..dest.len()]);
+        self.read_block(addr, dest.len());
 
         dest.write_range(0..dest.len(), &self.block[..dest.len()])
             .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
@@ -603,7 +982,12 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         addr: u32,
         protect: HfProtectMode,
     ) -> Result<(), RequestError<HfError>> {
-        self.sector_erase(addr, protect).map_err(RequestError::from)
+        let start = addr & !(SECTOR_SIZE_BYTES as u32 - 1);
+        self.check_write_protect(
+            start..start.saturating_add(SECTOR_SIZE_BYTES as u32),
+        )?;
+        self.start_sector_erase(addr, protect)
+            .map_err(RequestError::from)
     }
 
     fn get_mux(
@@ -618,6 +1002,19 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         _: &RecvMessage,
         state: HfMuxState,
     ) -> Result<(), RequestError<HfError>> {
+        // The host must not find the part busy with something of ours.
+        self.finish_in_flight();
+
+        // Nor may it find protection lifted: windows are for our writes, not
+        // the host's. If we can't put the bits back (or set them at all), we
+        // keep the flash.
+        if state == HfMuxState::HostCPU
+            && self.mux_state == HfMuxState::SP
+            && (self.wp.lifted_until.is_some() || self.wp.stale)
+        {
+            self.engage_write_protect()?;
+        }
+
         let sys = sys_api::Sys::from(SYS.get_task_id());
 
         match state {
@@ -626,6 +1023,11 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         }
 
         self.mux_state = state;
+
+        // Protection may have changed while the host had the flash.
+        if self.wp.stale {
+            self.sync_block_protect()?;
+        }
         Ok(())
     }
 
@@ -652,6 +1054,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         len: u32,
     ) -> Result<[u8; SHA256_SZ], RequestError<HfError>> {
         self.check_muxed_to_sp()?;
+        self.finish_in_flight();
         let hash_driver = hash_api::Hash::from(HASH.get_task_id());
         if hash_driver.init_sha256().is_err() {
             return Err(HfError::HashError.into());
@@ -720,6 +1123,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
     ) -> Result<(), RequestError<HfError>> {
         let data = HfPersistentData { dev_select };
         self.check_muxed_to_sp()?;
+        self.finish_in_flight();
         if self.dev_select_pin.is_some() {
             let prev_slot = self.dev_state;
 
@@ -789,11 +1193,113 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         }
         Ok(())
     }
+
+    fn set_write_protect(
+        &mut self,
+        _: &RecvMessage,
+        start: u32,
+        len: u32,
+        require_attested_rot: bool,
+    ) -> Result<(), RequestError<HfError>> {
+        let protect = self
+            .part
+            .block_protect_for(start, len)
+            .ok_or(HfError::BadProtectRange)?;
+        self.wp.protect = protect;
+        self.wp.require_attested_rot = require_attested_rot;
+        self.engage_write_protect().map_err(RequestError::from)
+    }
+
+    fn write_protect(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<HfWriteProtect, RequestError<HfError>> {
+        let range = self.part.protected_range(self.wp.protect);
+        let now = sys_get_timer().now;
+        Ok(HfWriteProtect {
+            start: range.start,
+            len: range.end - range.start,
+            require_attested_rot: self.wp.require_attested_rot,
+            rot_attested: self.wp.rot_attested,
+            lifted_ms: self
+                .wp
+                .lifted_until
+                .map(|until| until.saturating_sub(now) as u32),
+        })
+    }
+
+    fn set_rot_attested(
+        &mut self,
+        _: &RecvMessage,
+        attested: bool,
+    ) -> Result<(), RequestError<HfError>> {
+        self.wp.rot_attested = attested;
+        if !attested && self.wp.require_attested_rot {
+            self.engage_write_protect()?;
+        }
+        Ok(())
+    }
+
+    fn lift_write_protect(
+        &mut self,
+        _: &RecvMessage,
+        window_ms: u32,
+    ) -> Result<(), RequestError<HfError>> {
+        if window_ms == 0 || window_ms > MAX_WRITE_PROTECT_WINDOW_MS {
+            return Err(HfError::BadProtectWindow.into());
+        }
+        // With nothing protected, there's nothing to lift.
+        if self.wp.protect == BlockProtect::NONE {
+            return Ok(());
+        }
+        // The host would have the window to itself.
+        self.check_muxed_to_sp()?;
+        if self.wp.require_attested_rot && !self.wp.rot_attested {
+            return Err(HfError::RotNotAttested.into());
+        }
+
+        let until = sys_get_timer().now + u64::from(window_ms);
+        self.wp.lifted_until = Some(until);
+        sys_set_timer(Some(until), notifications::TIMER_MASK);
+
+        if let Err(e) = self.sync_block_protect() {
+            // Don't leave protection half lifted.
+            let _ = self.engage_write_protect();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn engage_write_protect(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<HfError>> {
+        self.engage_write_protect().map_err(RequestError::from)
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        match self.wp.lifted_until {
+            Some(until) if sys_get_timer().now >= until => {
+                // If this fails, the parts are left marked stale, and we try
+                // again whenever we're next asked to touch protection or the
+                // mux; meanwhile our own writes are refused.
+                let _ = self.engage_write_protect();
+            }
+            _ => (),
+        }
+    }
 }
 
 mod idl {
     use super::{
         HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
+        HfWriteProtect,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
@@ -0,0 +1,13 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    build_util::build_notifications()?;
+    build_util::idol::build_server_support(
+        "../../idl/host-mailbox.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,381 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Server for Gimlet's mailbox between the SP and the host OS.
+//!
+//! The mailbox is a block of registers and two buffers in the sequencer FPGA,
+//! which the host reaches over eSPI and we reach over the same SPI interface
+//! the sequencer uses. Each direction has a buffer, a length, and a bit in
+//! the status register saying the buffer is full. Whoever fills a buffer sets
+//! its bit, which for our buffer also raises the host's doorbell interrupt;
+//! whoever empties it clears the bit, which is how the other side knows it
+//! may send again.
+//!
+//! The host's doorbell to us is its setting of its bit, which we poll for:
+//! there's no spare pin from the FPGA to give us an interrupt, and the
+//! messages this is for, like inventory requests and telemetry, don't need
+//! answering within a few milliseconds. We poll more slowly while the host
+//! isn't ready, and rarely if the FPGA image has no mailbox at all.
+//!
+//! The register block isn't part of the sequencer's generated register map,
+//! so its layout is spelled out here and must be kept in step with the FPGA
+//! by hand.
+
+#![no_std]
+#![no_main]
+
+use drv_host_mailbox_api::{HostMailboxError, MailboxStatus, MAX_MESSAGE};
+use drv_spi_api::{SpiDevice, SpiError, SpiServer};
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use userlib::*;
+use zerocopy::{AsBytes, Unaligned, U16};
+
+task_slot!(SPI, spi_driver);
+
+/// Where the mailbox sits in the FPGA's address space.
+const MAILBOX_BASE: u16 = 0x0400;
+
+mod reg {
+    /// Reads as `MAILBOX_ID` if the mailbox is there.
+    pub const ID: u16 = 0x00;
+    pub const STATUS: u16 = 0x02;
+    /// Big-endian length of the message from the host.
+    pub const H2S_LEN: u16 = 0x04;
+    /// Big-endian length of our message to the host.
+    pub const S2H_LEN: u16 = 0x06;
+    pub const H2S_BUF: u16 = 0x100;
+    pub const S2H_BUF: u16 = 0x500;
+}
+
+const MAILBOX_ID: u16 = 0x4d42;
+
+/// Set by the host while it's listening.
+const STATUS_HOST_READY: u8 = 1 << 0;
+/// Set by the host when it sends; cleared by us when we've received.
+const STATUS_H2S_FULL: u8 = 1 << 1;
+/// Set by us when we send; cleared by the host when it's received.
+const STATUS_S2H_FULL: u8 = 1 << 2;
+
+/// How often to look for the host, while it's ready, while it isn't, and
+/// when there's no mailbox to look in.
+const POLL_READY_MS: u64 = 10;
+const POLL_NOT_READY_MS: u64 = 100;
+const POLL_NOT_PRESENT_MS: u64 = 5000;
+
+/// Bytes moved per SPI transaction.
+const CHUNK: usize = 64;
+
+/// Commands understood by the sequencer FPGA's SPI interface.
+#[derive(Copy, Clone, AsBytes, Unaligned)]
+#[repr(u8)]
+enum Cmd {
+    Write = 0,
+    Read = 1,
+    BitSet = 2,
+    BitClear = 3,
+}
+
+#[derive(AsBytes, Unaligned)]
+#[repr(C)]
+struct CmdHeader {
+    cmd: Cmd,
+    addr: U16<byteorder::BigEndian>,
+}
+
+const HEADER_LEN: usize = core::mem::size_of::<CmdHeader>();
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Present(bool),
+    HostReady(bool),
+    Sent(u16),
+    Received(u16),
+    BadLength(u16),
+    Spi(SpiError),
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+struct Mailbox<S: SpiServer> {
+    spi: SpiDevice<S>,
+}
+
+impl<S: SpiServer> Mailbox<S> {
+    fn transfer(
+        &self,
+        cmd: Cmd,
+        offset: u16,
+        out: &[u8],
+        into: &mut [u8],
+    ) -> Result<(), HostMailboxError> {
+        let len = HEADER_LEN + out.len().max(into.len());
+        let mut tx = [0u8; HEADER_LEN + CHUNK];
+        let mut rx = [0u8; HEADER_LEN + CHUNK];
+        let header = CmdHeader {
+            cmd,
+            addr: U16::new(MAILBOX_BASE + offset),
+        };
+        tx[..HEADER_LEN].copy_from_slice(header.as_bytes());
+        tx[HEADER_LEN..][..out.len()].copy_from_slice(out);
+
+        self.spi.exchange(&tx[..len], &mut rx[..len]).map_err(|e| {
+            ringbuf_entry!(Trace::Spi(e));
+            HostMailboxError::SpiFailed
+        })?;
+        into.copy_from_slice(&rx[HEADER_LEN..][..into.len()]);
+        Ok(())
+    }
+
+    fn read(
+        &self,
+        offset: u16,
+        into: &mut [u8],
+    ) -> Result<(), HostMailboxError> {
+        self.transfer(Cmd::Read, offset, &[], into)
+    }
+
+    fn write(&self, offset: u16, out: &[u8]) -> Result<(), HostMailboxError> {
+        self.transfer(Cmd::Write, offset, out, &mut [])
+    }
+
+    fn read_u16(&self, offset: u16) -> Result<u16, HostMailboxError> {
+        let mut v = [0; 2];
+        self.read(offset, &mut v)?;
+        Ok(u16::from_be_bytes(v))
+    }
+
+    fn status(&self) -> Result<u8, HostMailboxError> {
+        let mut v = [0];
+        self.read(reg::STATUS, &mut v)?;
+        Ok(v[0])
+    }
+
+    fn set_status(&self, bits: u8) -> Result<(), HostMailboxError> {
+        self.transfer(Cmd::BitSet, reg::STATUS, &[bits], &mut [])
+    }
+
+    fn clear_status(&self, bits: u8) -> Result<(), HostMailboxError> {
+        self.transfer(Cmd::BitClear, reg::STATUS, &[bits], &mut [])
+    }
+
+    fn is_present(&self) -> bool {
+        self.read_u16(reg::ID) == Ok(MAILBOX_ID)
+    }
+}
+
+struct ServerImpl<S: SpiServer> {
+    mailbox: Mailbox<S>,
+    present: bool,
+    /// The status register when we last looked, so that we can tell the
+    /// listener when it changes.
+    last_status: u8,
+    listener: Option<(TaskId, u32)>,
+    received: u32,
+    sent: u32,
+}
+
+impl<S: SpiServer> ServerImpl<S> {
+    /// Checks that there's a mailbox and reads its status.
+    fn status(&mut self) -> Result<u8, HostMailboxError> {
+        if !self.present {
+            self.present = self.mailbox.is_present();
+            if !self.present {
+                return Err(HostMailboxError::NotPresent);
+            }
+            ringbuf_entry!(Trace::Present(true));
+        }
+        self.mailbox.status()
+    }
+
+    /// Looks for activity from the host, tells the listener about it, and
+    /// sets the timer to look again.
+    fn poll(&mut self) {
+        let interval = match self.status() {
+            Ok(status) => {
+                // Anything the host did shows up as a change in status, as
+                // `send` and `recv` keep `last_status` up to date with what
+                // we did.
+                if (status ^ self.last_status) & STATUS_HOST_READY != 0 {
+                    let ready = status & STATUS_HOST_READY != 0;
+                    ringbuf_entry!(Trace::HostReady(ready));
+                }
+                if status != self.last_status {
+                    self.last_status = status;
+                    self.notify();
+                }
+                if status & STATUS_HOST_READY != 0 {
+                    POLL_READY_MS
+                } else {
+                    POLL_NOT_READY_MS
+                }
+            }
+            Err(HostMailboxError::NotPresent) => POLL_NOT_PRESENT_MS,
+            Err(_) => {
+                // Maybe the FPGA is being reloaded; find out when it's back.
+                if self.present {
+                    ringbuf_entry!(Trace::Present(false));
+                }
+                self.present = false;
+                POLL_NOT_READY_MS
+            }
+        };
+        let deadline = sys_get_timer().now + interval;
+        sys_set_timer(Some(deadline), notifications::TIMER_MASK);
+    }
+
+    fn notify(&self) {
+        if let Some((task, notification)) = self.listener {
+            let task = sys_refresh_task_id(task);
+            sys_post(task, notification);
+        }
+    }
+}
+
+impl<S: SpiServer> idl::InOrderHostMailboxImpl for ServerImpl<S> {
+    fn send(
+        &mut self,
+        _: &RecvMessage,
+        data: LenLimit<Leased<R, [u8]>, MAX_MESSAGE>,
+    ) -> Result<(), RequestError<HostMailboxError>> {
+        let status = self.status()?;
+        if status & STATUS_HOST_READY == 0 {
+            return Err(HostMailboxError::HostNotReady.into());
+        }
+        if status & STATUS_S2H_FULL != 0 {
+            return Err(HostMailboxError::Busy.into());
+        }
+
+        let mut chunk = [0u8; CHUNK];
+        for start in (0..data.len()).step_by(CHUNK) {
+            let end = (start + CHUNK).min(data.len());
+            let buf = &mut chunk[..end - start];
+            data.read_range(start..end, buf)
+                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+            self.mailbox.write(reg::S2H_BUF + start as u16, buf)?;
+        }
+        let len = data.len() as u16;
+        self.mailbox.write(reg::S2H_LEN, &len.to_be_bytes())?;
+        // This rings the host's doorbell, so it must come last.
+        self.mailbox.set_status(STATUS_S2H_FULL)?;
+        self.last_status |= STATUS_S2H_FULL;
+
+        ringbuf_entry!(Trace::Sent(len));
+        self.sent = self.sent.wrapping_add(1);
+        Ok(())
+    }
+
+    fn recv(
+        &mut self,
+        msg: &RecvMessage,
+        sink: LenLimit<Leased<W, [u8]>, MAX_MESSAGE>,
+    ) -> Result<u32, RequestError<HostMailboxError>> {
+        match self.listener {
+            Some((task, _)) if task.index() == msg.sender.index() => (),
+            _ => return Err(HostMailboxError::NotListening.into()),
+        }
+        let status = self.status()?;
+        if status & STATUS_H2S_FULL == 0 {
+            return Err(HostMailboxError::Empty.into());
+        }
+
+        let len = self.mailbox.read_u16(reg::H2S_LEN)?;
+        if usize::from(len) > MAX_MESSAGE {
+            // There's nothing to be done with this but let the host try
+            // again.
+            ringbuf_entry!(Trace::BadLength(len));
+            self.mailbox.clear_status(STATUS_H2S_FULL)?;
+            return Err(HostMailboxError::BadLength.into());
+        }
+        let len = usize::from(len);
+        if len > sink.len() {
+            return Err(HostMailboxError::BufferTooSmall.into());
+        }
+
+        let mut chunk = [0u8; CHUNK];
+        for start in (0..len).step_by(CHUNK) {
+            let end = (start + CHUNK).min(len);
+            let buf = &mut chunk[..end - start];
+            self.mailbox.read(reg::H2S_BUF + start as u16, buf)?;
+            sink.write_range(start..end, buf)
+                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        }
+        // Tells the host it may send again.
+        self.mailbox.clear_status(STATUS_H2S_FULL)?;
+        self.last_status &= !STATUS_H2S_FULL;
+
+        ringbuf_entry!(Trace::Received(len as u16));
+        self.received = self.received.wrapping_add(1);
+        Ok(len as u32)
+    }
+
+    fn listen(
+        &mut self,
+        msg: &RecvMessage,
+        notification: u32,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.listener = Some((msg.sender, notification));
+        // Anything that's already waiting is news to the new listener.
+        if self.last_status & STATUS_H2S_FULL != 0 {
+            self.notify();
+        }
+        Ok(())
+    }
+
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<MailboxStatus, RequestError<HostMailboxError>> {
+        let status = self.status()?;
+        Ok(MailboxStatus::new(
+            status & STATUS_HOST_READY != 0,
+            status & STATUS_H2S_FULL != 0,
+            status & STATUS_S2H_FULL != 0,
+            self.received,
+            self.sent,
+        ))
+    }
+}
+
+impl<S: SpiServer> NotificationHandler for ServerImpl<S> {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.poll();
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let spi = drv_spi_api::Spi::from(SPI.get_task_id());
+    let mut server = ServerImpl {
+        mailbox: Mailbox {
+            spi: spi.device(drv_spi_api::devices::SEQUENCER),
+        },
+        present: false,
+        last_status: 0,
+        listener: None,
+        received: 0,
+        sent: 0,
+    };
+    server.poll();
+
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_host_mailbox_api::{HostMailboxError, MailboxStatus};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
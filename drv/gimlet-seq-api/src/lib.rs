@@ -7,12 +7,26 @@
 #![no_std]
 
 use derive_idol_err::IdolError;
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
 use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
 
-// Re-export PowerState for client convenience.
-pub use drv_gimlet_state::PowerState;
+// Re-export shared state types for client convenience.
+pub use drv_gimlet_state::{FanEvent, PowerState, Rail, RailStatus, NUM_RAILS};
 
-#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    IdolError,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
 pub enum SeqError {
     IllegalTransition = 1,
     MuxToHostCPUFailed,
@@ -23,11 +37,139 @@ pub enum SeqError {
     A1Timeout,
     A0TimeoutGroupC,
     A0Timeout,
+    NoFanEvent,
+    RailTimeout,
+    RailLost,
+    NoRailFault,
+    NoPowerEvent,
+    NotInA0,
 
     #[idol(server_death)]
     ServerRestarted,
 }
 
+/// What the sequencer FPGA says it's running.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct FpgaIdent {
+    /// Design ident, from the IDENT0:1 registers
+    pub ident: u32,
+    /// Bitstream checksum, as recorded in the CS registers after loading
+    pub checksum: u32,
+}
+
+/// Why a task asked the sequencer to change power state, as recorded in its
+/// power event log.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    AsBytes,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+#[repr(u8)]
+pub enum PowerReason {
+    /// The caller didn't say (it used `set_state`)
+    Unspecified = 0,
+    /// The sequencer's own move to A0 once it has come up
+    Boot = 1,
+    /// The host asked to be powered off or rebooted
+    HostRequest = 2,
+    /// The control plane asked, via MGS
+    ControlPlane = 3,
+    /// The thermal loop is shutting the host down to protect it
+    Overheat = 4,
+    /// Re-running the sequence after a rail failed to come up
+    /// (`retry_sequence`)
+    SequenceRetry = 5,
+}
+
+/// Why the host lost power without anyone asking.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum DropCause {
+    /// A rail failed while sequencing up to A0
+    SequencingFailed(SeqError),
+    /// The CPU's PWROK fell in A0: the host reset, or lost power
+    HostReset,
+    /// The CPU signalled a thermal trip in A0
+    Thermtrip,
+}
+
+/// One entry in the sequencer's power event log.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct PowerEvent {
+    /// Counts events since the sequencer started (wrapping); a gap between
+    /// successive events means the ones in between were dropped to make room
+    pub number: u32,
+    /// When it happened, in milliseconds since boot
+    pub timestamp: u64,
+    pub kind: PowerEventKind,
+}
+
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum PowerEventKind {
+    /// A task asked for a power state.
+    Request {
+        /// Index of the task that asked, or `None` if the sequencer decided
+        /// on its own
+        task: Option<u16>,
+        reason: PowerReason,
+        from: PowerState,
+        to: PowerState,
+        /// Why we didn't get there, if we didn't
+        error: Option<SeqError>,
+    },
+    /// Power dropped out from under the host.
+    Drop {
+        cause: DropCause,
+        from: PowerState,
+        /// The rail to blame: for a sequencing failure, the one that timed
+        /// out or lost power good; otherwise, the first group B or C rail,
+        /// in sequencing order, without power good in `snapshot`
+        rail: Option<Rail>,
+        /// The FPGA's fault snapshot, as of when we noticed the drop:
+        /// `FLT_A0_SMSTATUS`, `FLT_GROUPB_PG` and `FLT_GROUPC_PG`, in that
+        /// order
+        snapshot: [u8; 3],
+    },
+}
+
+/// The state of the CPU's PROCHOT_L, which we can assert to have the CPU
+/// throttle itself.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+pub struct ProchotStatus {
+    /// Whether we're asserting it
+    pub asserted: bool,
+    /// Whether it's asserted at all -- by us, or by anything else that can
+    /// pull it low -- and so whether the CPU is being throttled
+    pub throttling: bool,
+    /// Number of times we've asserted it
+    pub assertions: u32,
+    /// Number of times we've seen it asserted while we weren't asserting it
+    pub external: u32,
+}
+
 // On Gimlet, we have two banks of up to 8 DIMMs apiece. Export the "two banks"
 // bit of knowledge here so it can be used by gimlet-seq-server, spd, and
 // packrat, all of which want to know at compile-time how many banks there are.
@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The power event log.
+//!
+//! The ringbuf tells us what the sequencer did, but not for long, and not
+//! why; when the host has spontaneously reset, we want to be able to find
+//! out after the fact who asked for each power state change, and what
+//! dropped out first when nobody asked.  We keep the most recent events
+//! here -- each request for a power state (whether or not we got there),
+//! and each drop -- dropping the oldest to make room.
+
+use crate::Trace;
+use drv_gimlet_seq_api::{PowerEvent, PowerEventKind};
+use heapless::Deque;
+use ringbuf::ringbuf_entry_root as ringbuf_entry;
+use userlib::sys_get_timer;
+
+/// Number of events we keep
+const MAX_EVENTS: usize = 16;
+
+pub struct EventLog {
+    events: Deque<PowerEvent, MAX_EVENTS>,
+
+    /// Number to give the next event
+    next: u32,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self {
+            events: Deque::new(),
+            next: 0,
+        }
+    }
+
+    pub fn record(&mut self, kind: PowerEventKind) {
+        let event = PowerEvent {
+            number: self.next,
+            timestamp: sys_get_timer().now,
+            kind,
+        };
+        self.next = self.next.wrapping_add(1);
+
+        ringbuf_entry!(Trace::PowerEvent(event.number));
+
+        if let Err(event) = self.events.push_back(event) {
+            //
+            // The most recent events are the most relevant, so make room by
+            // dropping the oldest.
+            //
+            let dropped = self.events.pop_front().unwrap();
+            ringbuf_entry!(Trace::PowerEventDropped(dropped.number));
+            self.events.push_back(event).unwrap();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns event `index`, counting from the oldest we have.
+    pub fn get(&self, index: usize) -> Option<PowerEvent> {
+        self.events.iter().nth(index).copied()
+    }
+}
@@ -0,0 +1,200 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fan hot-swap handling.
+//!
+//! The fans on Gimlet are on a separate board that sits behind a hot-swap
+//! controller, which the FPGA enables via `EARLY_POWER_CTRL.FANPWREN`.  There
+//! is no presence signal for the fan board (`FAN_TO_SEQ_FAN_FAIL`, like
+//! `FANHP_TO_SEQ_FAULT`, is a fault output of the hot-swap controller), so we
+//! go by the controller's power good:  fans are only considered powered once
+//! power good has been continuously asserted for `SETTLE_MS`, and we consider
+//! them lost (pulled, or tripped) when power good drops for `DEBOUNCE_POLLS`
+//! polls.  Losing them cuts fan power; if so configured, we then retry
+//! enabling it every `RETRY_MS`, which brings the fans back once a board is
+//! reinserted.  Events are queued for interested tasks.
+
+use crate::seq_spi::{Addr, Reg, SequencerFpga};
+use crate::Trace;
+use drv_gimlet_seq_api::FanEvent;
+use drv_spi_api::SpiServer;
+use heapless::Deque;
+use ringbuf::ringbuf_entry_root as ringbuf_entry;
+
+/// Interval at which we poll fan power good, in milliseconds
+pub const POLL_INTERVAL: u64 = 100;
+
+/// Number of consecutive polls for which power good must be deasserted
+/// before we consider powered fans lost
+const DEBOUNCE_POLLS: u8 = 5;
+
+/// How long power good must be continuously asserted after enabling fan
+/// power before we consider the fans enabled, in milliseconds
+const SETTLE_MS: u64 = 500;
+
+/// How long we wait for power good after enabling fan power before declaring
+/// a fault, in milliseconds
+const ENABLE_TIMEOUT_MS: u64 = 2000;
+
+/// How long we wait after losing fan power (or failing to enable it) before
+/// automatically trying again, in milliseconds
+const RETRY_MS: u64 = 5000;
+
+/// Number of events we'll queue before dropping the oldest
+const MAX_EVENTS: usize = 8;
+
+/// State of an in-progress enable of fan power
+struct Enabling {
+    /// Time by which power good must have settled
+    deadline: u64,
+
+    /// Time at which power good was most recently asserted, if it is
+    pg_since: Option<u64>,
+}
+
+pub struct FanHotswap {
+    /// Fan power is enabled and power good has settled
+    powered: bool,
+
+    /// Number of consecutive polls without power good while `powered`
+    lost: u8,
+
+    /// If we've enabled fan power, our progress towards power good
+    enabling: Option<Enabling>,
+
+    /// If we're going to retry enabling fan power, when
+    retry_at: Option<u64>,
+
+    /// Automatically retry enabling fan power when it's lost
+    auto_enable: bool,
+
+    /// Events awaiting consumption
+    events: Deque<FanEvent, MAX_EVENTS>,
+}
+
+impl FanHotswap {
+    //
+    // We take our initial state from the hardware without generating an
+    // event:  if fan power is good at boot, the fans are already powered.
+    //
+    pub fn new<S: SpiServer>(seq: &SequencerFpga<S>) -> Self {
+        let rbks = seq.read_byte(Addr::EARLY_RBKS).unwrap();
+
+        Self {
+            powered: rbks & Reg::EARLY_RBKS::FANHP_TO_SEQ_PWRGD != 0,
+            lost: 0,
+            enabling: None,
+            retry_at: None,
+            auto_enable: true,
+            events: Deque::new(),
+        }
+    }
+
+    pub fn set_auto_enable(&mut self, enabled: bool) {
+        self.auto_enable = enabled;
+
+        if !enabled {
+            self.retry_at = None;
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<FanEvent> {
+        self.events.pop_front()
+    }
+
+    fn push_event(&mut self, event: FanEvent) {
+        ringbuf_entry!(Trace::FanEvent(event));
+
+        if let Err(event) = self.events.push_back(event) {
+            //
+            // Nobody is consuming our events; the most recent events are
+            // the most relevant, so make room by dropping the oldest.
+            //
+            let dropped = self.events.pop_front().unwrap();
+            ringbuf_entry!(Trace::FanEventDropped(dropped));
+            self.events.push_back(event).unwrap();
+        }
+    }
+
+    pub fn enable<S: SpiServer>(&mut self, seq: &SequencerFpga<S>, now: u64) {
+        let on = Reg::EARLY_POWER_CTRL::FANPWREN;
+        seq.set_bytes(Addr::EARLY_POWER_CTRL, &[on]).unwrap();
+
+        self.enabling = Some(Enabling {
+            deadline: now + ENABLE_TIMEOUT_MS,
+            pg_since: None,
+        });
+        self.retry_at = None;
+    }
+
+    pub fn disable<S: SpiServer>(&mut self, seq: &SequencerFpga<S>) {
+        self.power_off(seq);
+        self.retry_at = None;
+    }
+
+    fn power_off<S: SpiServer>(&mut self, seq: &SequencerFpga<S>) {
+        let off = Reg::EARLY_POWER_CTRL::FANPWREN;
+        seq.clear_bytes(Addr::EARLY_POWER_CTRL, &[off]).unwrap();
+
+        self.powered = false;
+        self.lost = 0;
+        self.enabling = None;
+    }
+
+    /// Cuts fan power after a failure and, if so configured, arranges to
+    /// try again later.
+    fn fail<S: SpiServer>(
+        &mut self,
+        seq: &SequencerFpga<S>,
+        now: u64,
+        event: FanEvent,
+    ) {
+        self.push_event(event);
+        self.power_off(seq);
+
+        if self.auto_enable {
+            self.retry_at = Some(now + RETRY_MS);
+        }
+    }
+
+    pub fn poll<S: SpiServer>(&mut self, seq: &SequencerFpga<S>, now: u64) {
+        let rbks = seq.read_byte(Addr::EARLY_RBKS).unwrap();
+        let pg = rbks & Reg::EARLY_RBKS::FANHP_TO_SEQ_PWRGD != 0;
+        let fault = rbks & Reg::EARLY_RBKS::FANHP_TO_SEQ_FAULT != 0;
+
+        if let Some(enabling) = &mut self.enabling {
+            if fault {
+                self.fail(seq, now, FanEvent::Fault);
+            } else if pg {
+                let since = *enabling.pg_since.get_or_insert(now);
+
+                if now >= since + SETTLE_MS {
+                    self.enabling = None;
+                    self.powered = true;
+                    self.push_event(FanEvent::Enabled);
+                }
+            } else {
+                enabling.pg_since = None;
+
+                if now >= enabling.deadline {
+                    self.fail(seq, now, FanEvent::Fault);
+                }
+            }
+        } else if self.powered {
+            if pg && !fault {
+                self.lost = 0;
+            } else {
+                self.lost += 1;
+
+                if self.lost >= DEBOUNCE_POLLS {
+                    self.fail(seq, now, FanEvent::PowerLost);
+                }
+            }
+        } else if let Some(retry_at) = self.retry_at {
+            if now >= retry_at {
+                self.enable(seq, now);
+            }
+        }
+    }
+}
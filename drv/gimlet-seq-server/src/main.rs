@@ -7,13 +7,20 @@
 #![no_std]
 #![no_main]
 
+mod events;
+mod fans;
+mod prochot;
+mod rails;
 mod seq_spi;
 
 use ringbuf::*;
 use userlib::*;
 
 use drv_gimlet_hf_api as hf_api;
-use drv_gimlet_seq_api::{PowerState, SeqError};
+use drv_gimlet_seq_api::{
+    DropCause, FanEvent, FpgaIdent, PowerEvent, PowerEventKind, PowerReason,
+    PowerState, ProchotStatus, Rail, RailStatus, SeqError,
+};
 use drv_ice40_spi_program as ice40;
 use drv_packrat_vpd_loader::{read_vpd_and_load_packrat, Packrat};
 use drv_spi_api::{SpiDevice, SpiServer};
@@ -99,6 +106,18 @@ enum Trace {
     SpdAbsent(u8, u8, u8),
     SpdDimmsFound(usize),
 
+    FanEvent(FanEvent),
+    FanEventDropped(FanEvent),
+
+    RailFault(Rail, RailStatus),
+    SequenceRetry(Rail),
+
+    PowerEvent(u32),
+    PowerEventDropped(u32),
+
+    Prochot(bool),
+    ProchotExternal,
+
     None,
 }
 
@@ -370,6 +389,8 @@ fn main() -> ! {
     // Turn on the chassis LED once we reach A2
     sys.gpio_set(CHASSIS_LED);
 
+    let fans = fans::FanHotswap::new(&seq);
+
     let mut buffer = [0; idl::INCOMING_SIZE];
     let mut server = ServerImpl {
         state: PowerState::A2,
@@ -377,12 +398,21 @@ fn main() -> ! {
         seq,
         jefe,
         hf,
-        deadline: 0,
+        fans,
+        rails: rails::Rails::new(),
+        events: events::EventLog::new(),
+        prochot: prochot::Prochot::new(&sys),
+        deadline: sys_get_timer().now,
     };
 
+    //
+    // We poll fan power good in every power state, so start our timer now.
+    //
+    sys_set_timer(Some(server.deadline), notifications::TIMER_MASK);
+
     // Power on, unless suppressed by the `stay-in-a2` feature
     if !cfg!(feature = "stay-in-a2") {
-        _ = server.set_state_internal(PowerState::A0);
+        _ = server.request_state(None, PowerState::A0, PowerReason::Boot);
     }
 
     //
@@ -522,6 +552,10 @@ struct ServerImpl<S: SpiServer> {
     seq: seq_spi::SequencerFpga<S>,
     jefe: Jefe,
     hf: hf_api::HostFlash,
+    fans: fans::FanHotswap,
+    rails: rails::Rails,
+    events: events::EventLog,
+    prochot: prochot::Prochot,
     deadline: u64,
 }
 
@@ -599,12 +633,28 @@ impl<S: SpiServer> NotificationHandler for ServerImpl<S> {
                     unreachable!();
                 }
             }
-        }
 
-        if let Some(interval) = self.poll_interval() {
-            self.deadline += interval;
-            sys_set_timer(Some(self.deadline), notifications::TIMER_MASK);
+            //
+            // Finally, look for anyone else asserting PROCHOT_L -- unless
+            // we've just left A0, in which case it may be on its way down
+            // with the rails.
+            //
+            if self.is_a0() {
+                self.prochot.poll(&self.sys);
+            }
+        } else {
+            //
+            // There's no CPU to throttle -- and PROCHOT_L is pulled up to an
+            // A0 rail, so there's nothing to read back.  Make sure we don't
+            // come up throttled the next time we get to A0.
+            //
+            self.prochot.power_off(&self.sys);
         }
+
+        self.fans.poll(&self.seq, sys_get_timer().now);
+
+        self.deadline += self.poll_interval();
+        sys_set_timer(Some(self.deadline), notifications::TIMER_MASK);
     }
 }
 
@@ -615,6 +665,51 @@ impl<S: SpiServer> ServerImpl<S> {
         self.jefe.set_state(state as u32);
     }
 
+    /// Moves to `state` on behalf of `task` (or ourselves, if `None`),
+    /// recording the request in the event log.
+    fn request_state(
+        &mut self,
+        task: Option<u16>,
+        state: PowerState,
+        reason: PowerReason,
+    ) -> Result<(), SeqError> {
+        let from = self.state;
+        let result = self.set_state_internal(state);
+
+        self.events.record(PowerEventKind::Request {
+            task,
+            reason,
+            from,
+            to: state,
+            error: result.err(),
+        });
+
+        result
+    }
+
+    /// Records that power dropped out from under the host, for `cause`.
+    fn record_drop(&mut self, cause: DropCause) {
+        let snapshot = [
+            self.seq.read_byte(Addr::FLT_A0_SMSTATUS).unwrap(),
+            self.seq.read_byte(Addr::FLT_GROUPB_PG).unwrap(),
+            self.seq.read_byte(Addr::FLT_GROUPC_PG).unwrap(),
+        ];
+
+        let rail = match cause {
+            DropCause::SequencingFailed(_) => self.rails.fault(),
+            DropCause::HostReset | DropCause::Thermtrip => {
+                rails::Rails::first_without_pg(snapshot[1], snapshot[2])
+            }
+        };
+
+        self.events.record(PowerEventKind::Drop {
+            cause,
+            from: self.state,
+            rail,
+            snapshot,
+        });
+    }
+
     fn set_state_internal(
         &mut self,
         state: PowerState,
@@ -656,6 +751,12 @@ impl<S: SpiServer> ServerImpl<S> {
                 let start = sys_get_timer().now;
                 let deadline = start + A0_TIMEOUT_MILLIS;
 
+                //
+                // Forget the outcome of any previous attempt; from here on,
+                // we track each rail as its stage is enabled.
+                //
+                self.rails.reset();
+
                 //
                 // We are going to pass through A1 on the way to A0.  A1 is
                 // more or less an implementation detail of our journey to A0,
@@ -666,6 +767,7 @@ impl<S: SpiServer> ServerImpl<S> {
                 //
                 let a1 = Reg::PWR_CTRL::A1PWREN;
                 self.seq.write_bytes(Addr::PWR_CTRL, &[a1]).unwrap();
+                let stage_start = self.begin_stage(rails::Stage::A1);
 
                 loop {
                     let mut status = [0u8];
@@ -673,11 +775,14 @@ impl<S: SpiServer> ServerImpl<S> {
                     self.seq.read_bytes(Addr::A1SMSTATUS, &mut status).unwrap();
                     ringbuf_entry!(Trace::A1Status(status[0]));
 
+                    self.check_rails(rails::Stage::A1, stage_start)?;
+
                     if status[0] == Reg::A1SMSTATUS::Encoded::DONE as u8 {
                         break;
                     }
 
                     if sys_get_timer().now > deadline {
+                        self.rails.stage_timed_out(rails::Stage::A1);
                         return Err(self.a0_failure(SeqError::A1Timeout));
                     }
 
@@ -720,6 +825,7 @@ impl<S: SpiServer> ServerImpl<S> {
                 //
                 let a0 = Reg::PWR_CTRL::A0A_EN;
                 self.seq.write_bytes(Addr::PWR_CTRL, &[a0]).unwrap();
+                let stage_start = self.begin_stage(rails::Stage::GroupB);
 
                 loop {
                     let mut status = [0u8];
@@ -727,11 +833,14 @@ impl<S: SpiServer> ServerImpl<S> {
                     self.seq.read_bytes(Addr::A0SMSTATUS, &mut status).unwrap();
                     ringbuf_entry!(Trace::A0Status(status[0]));
 
+                    self.check_rails(rails::Stage::GroupB, stage_start)?;
+
                     if status[0] == Reg::A0SMSTATUS::Encoded::GROUPC_PG as u8 {
                         break;
                     }
 
                     if sys_get_timer().now > deadline {
+                        self.rails.stage_timed_out(rails::Stage::GroupB);
                         return Err(self.a0_failure(SeqError::A0TimeoutGroupC));
                     }
 
@@ -743,6 +852,7 @@ impl<S: SpiServer> ServerImpl<S> {
                 //
                 vcore_soc_on();
                 ringbuf_entry!(Trace::RailsOn);
+                let stage_start = self.begin_stage(rails::Stage::GroupC);
 
                 //
                 // Now wait for the end of Group C.
@@ -753,11 +863,14 @@ impl<S: SpiServer> ServerImpl<S> {
                     self.seq.read_bytes(Addr::A0SMSTATUS, &mut status).unwrap();
                     ringbuf_entry!(Trace::A0Power(status[0]));
 
+                    self.check_rails(rails::Stage::GroupC, stage_start)?;
+
                     if status[0] == Reg::A0SMSTATUS::Encoded::DONE as u8 {
                         break;
                     }
 
                     if sys_get_timer().now > deadline {
+                        self.rails.stage_timed_out(rails::Stage::GroupC);
                         return Err(self.a0_failure(SeqError::A0Timeout));
                     }
 
@@ -821,6 +934,7 @@ impl<S: SpiServer> ServerImpl<S> {
                     return Err(SeqError::MuxToSPFailed);
                 }
 
+                self.rails.reset();
                 self.update_state_internal(PowerState::A2);
                 ringbuf_entry_v3p3_sys_a0_vout();
                 ringbuf_entry!(Trace::A2);
@@ -841,6 +955,32 @@ impl<S: SpiServer> ServerImpl<S> {
         }
     }
 
+    //
+    // Mark the rails of `stage` as enabled, returning the time at which the
+    // stage started.
+    //
+    fn begin_stage(&mut self, stage: rails::Stage) -> u64 {
+        self.rails.enable(stage);
+        sys_get_timer().now
+    }
+
+    //
+    // Check the rails enabled thus far, backing out to A2 if any rail has
+    // failed.
+    //
+    fn check_rails(
+        &mut self,
+        stage: rails::Stage,
+        stage_start: u64,
+    ) -> Result<(), SeqError> {
+        let elapsed = sys_get_timer().now - stage_start;
+
+        match self.rails.check(&self.seq, stage, elapsed) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.a0_failure(err)),
+        }
+    }
+
     fn a0_failure(&mut self, err: SeqError) -> SeqError {
         let record_reg = |addr| {
             ringbuf_entry!(Trace::A0FailureDetails(
@@ -861,6 +1001,7 @@ impl<S: SpiServer> ServerImpl<S> {
         record_reg(Addr::FLT_A0_SMSTATUS);
         record_reg(Addr::FLT_GROUPB_PG);
         record_reg(Addr::FLT_GROUPC_PG);
+        self.record_drop(DropCause::SequencingFailed(err));
 
         //
         // Now put ourselves back in A2.
@@ -886,6 +1027,7 @@ impl<S: SpiServer> ServerImpl<S> {
 
         if ifr & thermtrip != 0 {
             self.seq.clear_bytes(Addr::IFR, &[thermtrip]).unwrap();
+            self.record_drop(DropCause::Thermtrip);
             self.update_state_internal(PowerState::A0Thermtrip);
         }
     }
@@ -918,21 +1060,26 @@ impl<S: SpiServer> ServerImpl<S> {
             let mask = pwrok_fedge | Reg::IFR::AMD_RSTN_FEDGE;
             self.seq.clear_bytes(Addr::IFR, &[mask]).unwrap();
 
+            self.record_drop(DropCause::HostReset);
             self.update_state_internal(PowerState::A0Reset);
         }
     }
 
+    fn is_a0(&self) -> bool {
+        matches!(self.state, PowerState::A0 | PowerState::A0PlusHP)
+    }
+
     //
     // Return the current timer interval, in milliseconds.  If we are in A0,
     // we are polling for NIC_PWREN_L; if we are in A0PlusHP, we are polling
-    // for a thermtrip or for someone disabling NIC_PWREN_L.  If we are in
-    // any other state, we don't need to poll.
+    // for a thermtrip or for someone disabling NIC_PWREN_L.  In any other
+    // state, we are only polling fan power good.
     //
-    fn poll_interval(&self) -> Option<u64> {
+    fn poll_interval(&self) -> u64 {
         match self.state {
-            PowerState::A0 => Some(10),
-            PowerState::A0PlusHP => Some(100),
-            _ => None,
+            PowerState::A0 => 10,
+            PowerState::A0PlusHP => 100,
+            _ => fans::POLL_INTERVAL,
         }
     }
 }
@@ -947,18 +1094,28 @@ impl<S: SpiServer> idl::InOrderSequencerImpl for ServerImpl<S> {
 
     fn set_state(
         &mut self,
-        _: &RecvMessage,
+        msg: &RecvMessage,
         state: PowerState,
     ) -> Result<(), RequestError<SeqError>> {
-        self.set_state_internal(state).map_err(RequestError::from)
+        self.set_state_with_reason(msg, state, PowerReason::Unspecified)
+    }
+
+    fn set_state_with_reason(
+        &mut self,
+        msg: &RecvMessage,
+        state: PowerState,
+        reason: PowerReason,
+    ) -> Result<(), RequestError<SeqError>> {
+        let task = msg.sender.index() as u16;
+        self.request_state(Some(task), state, reason)
+            .map_err(RequestError::from)
     }
 
     fn fans_on(
         &mut self,
         _: &RecvMessage,
     ) -> Result<(), RequestError<SeqError>> {
-        let on = Reg::EARLY_POWER_CTRL::FANPWREN;
-        self.seq.set_bytes(Addr::EARLY_POWER_CTRL, &[on]).unwrap();
+        self.fans.enable(&self.seq, sys_get_timer().now);
         Ok(())
     }
 
@@ -966,10 +1123,25 @@ impl<S: SpiServer> idl::InOrderSequencerImpl for ServerImpl<S> {
         &mut self,
         _: &RecvMessage,
     ) -> Result<(), RequestError<SeqError>> {
-        let off = Reg::EARLY_POWER_CTRL::FANPWREN;
-        self.seq
-            .clear_bytes(Addr::EARLY_POWER_CTRL, &[off])
-            .unwrap();
+        self.fans.disable(&self.seq);
+        Ok(())
+    }
+
+    fn next_fan_event(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FanEvent, RequestError<SeqError>> {
+        self.fans
+            .next_event()
+            .ok_or(RequestError::Runtime(SeqError::NoFanEvent))
+    }
+
+    fn set_fan_auto_enable(
+        &mut self,
+        _: &RecvMessage,
+        enabled: bool,
+    ) -> Result<(), RequestError<SeqError>> {
+        self.fans.set_auto_enable(enabled);
         Ok(())
     }
 
@@ -986,6 +1158,28 @@ impl<S: SpiServer> idl::InOrderSequencerImpl for ServerImpl<S> {
         Ok(())
     }
 
+    fn set_prochot(
+        &mut self,
+        _: &RecvMessage,
+        asserted: bool,
+    ) -> Result<(), RequestError<SeqError>> {
+        if asserted && !self.is_a0() {
+            return Err(RequestError::Runtime(SeqError::NotInA0));
+        }
+        self.prochot.set(&self.sys, asserted);
+        Ok(())
+    }
+
+    fn prochot_status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<ProchotStatus, RequestError<SeqError>> {
+        if self.is_a0() {
+            self.prochot.poll(&self.sys);
+        }
+        Ok(self.prochot.status())
+    }
+
     fn read_fpga_regs(
         &mut self,
         _: &RecvMessage,
@@ -1001,6 +1195,79 @@ impl<S: SpiServer> idl::InOrderSequencerImpl for ServerImpl<S> {
 
         Ok(buf)
     }
+
+    fn fpga_ident(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FpgaIdent, RequestError<SeqError>> {
+        let ident = self
+            .seq
+            .read_ident()
+            .map_err(|_| SeqError::ReadRegsFailed)?;
+        let checksum = self
+            .seq
+            .read_checksum()
+            .map_err(|_| SeqError::ReadRegsFailed)?;
+        Ok(FpgaIdent {
+            ident: ident.into(),
+            checksum,
+        })
+    }
+
+    fn rail_status(
+        &mut self,
+        _: &RecvMessage,
+        rail: Rail,
+    ) -> Result<RailStatus, RequestError<SeqError>> {
+        Ok(self.rails.status(rail))
+    }
+
+    fn rail_fault(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<Rail, RequestError<SeqError>> {
+        self.rails
+            .fault()
+            .ok_or(RequestError::Runtime(SeqError::NoRailFault))
+    }
+
+    fn retry_sequence(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<SeqError>> {
+        let rail = self.rails.fault().ok_or(SeqError::NoRailFault)?;
+
+        //
+        // The FPGA enables rails a group at a time, so we can't bring up the
+        // failed rail in isolation:  retrying it means re-running the
+        // sequence up through its stage (and, if it comes up, on to A0).
+        //
+        ringbuf_entry!(Trace::SequenceRetry(rail));
+        let task = msg.sender.index() as u16;
+        self.request_state(
+            Some(task),
+            PowerState::A0,
+            PowerReason::SequenceRetry,
+        )
+        .map_err(RequestError::from)
+    }
+
+    fn power_event_count(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<core::convert::Infallible>> {
+        Ok(self.events.len() as u32)
+    }
+
+    fn power_event(
+        &mut self,
+        _: &RecvMessage,
+        index: u32,
+    ) -> Result<PowerEvent, RequestError<SeqError>> {
+        self.events
+            .get(index as usize)
+            .ok_or(RequestError::Runtime(SeqError::NoPowerEvent))
+    }
 }
 
 fn reprogram_fpga<S: SpiServer>(
@@ -1051,6 +1318,9 @@ cfg_if::cfg_if! {
         const SP_TO_SP3_NMI_SYNC_FLOOD_L: sys_api::PinSet =
             sys_api::Port::J.pin(2);
 
+        // SP_TO_SP3_PROCHOT_L, externally pulled up to V3P3_SYS_A0
+        const PROCHOT_L: sys_api::PinSet = sys_api::Port::J.pin(3);
+
         //
         // SP_TO_SP3_UARTA_OE_L must be driven low to allow for transmission
         // into the SP3's UART
@@ -1190,7 +1460,7 @@ cfg_if::cfg_if! {
 }
 
 mod idl {
-    use super::SeqError;
+    use super::{PowerEvent, PowerReason, ProchotStatus, SeqError};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -0,0 +1,79 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PROCHOT_L, which has the CPU throttle itself.
+//!
+//! The thermal task asserts this when the fans alone can't keep up, to buy
+//! time before it has to power the host down.  We drive it open drain, as
+//! we do the NMI pin, so that we can both leave it to its pullup and read it
+//! back: other parts (the CPU's voltage regulators, when they get hot) can
+//! pull it low too, and we want to know when something else is throttling
+//! the CPU.
+
+use crate::{Trace, PROCHOT_L};
+use drv_gimlet_seq_api::ProchotStatus;
+use drv_stm32xx_sys_api as sys_api;
+use ringbuf::ringbuf_entry_root as ringbuf_entry;
+
+pub struct Prochot {
+    status: ProchotStatus,
+}
+
+impl Prochot {
+    /// Configures the pin, deasserted.
+    pub fn new(sys: &sys_api::Sys) -> Self {
+        sys.gpio_set(PROCHOT_L);
+        sys.gpio_configure_output(
+            PROCHOT_L,
+            sys_api::OutputType::OpenDrain,
+            sys_api::Speed::Low,
+            sys_api::Pull::None,
+        );
+
+        Self {
+            status: ProchotStatus::default(),
+        }
+    }
+
+    pub fn set(&mut self, sys: &sys_api::Sys, asserted: bool) {
+        if asserted == self.status.asserted {
+            return;
+        }
+
+        ringbuf_entry!(Trace::Prochot(asserted));
+
+        if asserted {
+            sys.gpio_reset(PROCHOT_L);
+            self.status.assertions = self.status.assertions.wrapping_add(1);
+        } else {
+            sys.gpio_set(PROCHOT_L);
+        }
+
+        self.status.asserted = asserted;
+    }
+
+    /// Samples the pin, counting assertions that aren't ours; only to be
+    /// called in A0.  We only see the ones that last until we next poll, but
+    /// those are the ones that matter.
+    pub fn poll(&mut self, sys: &sys_api::Sys) {
+        let throttling = sys.gpio_read(PROCHOT_L) == 0;
+
+        if throttling && !self.status.throttling && !self.status.asserted {
+            ringbuf_entry!(Trace::ProchotExternal);
+            self.status.external = self.status.external.wrapping_add(1);
+        }
+
+        self.status.throttling = throttling;
+    }
+
+    /// Deasserts the pin outside of A0, where nothing is being throttled.
+    pub fn power_off(&mut self, sys: &sys_api::Sys) {
+        self.set(sys, false);
+        self.status.throttling = false;
+    }
+
+    pub fn status(&self) -> ProchotStatus {
+        self.status
+    }
+}
@@ -0,0 +1,205 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Per-rail tracking of the A2 to A0 power sequence.
+//!
+//! The FPGA enables rails a group at a time (the A1 rails on `A1PWREN`, the
+//! group B rails on `A0A_EN`), while we enable the group C rails ourselves
+//! over PMBus.  The state machines only tell us how far along a group is; to
+//! know *which* rail is holding things up, we watch the individual power good
+//! readbacks.  Each rail is described in [`RAILS`], in enable order, along
+//! with the stage that enables it and how long it may take to come up once
+//! that stage has started.  Rails in a stage depend on all rails of earlier
+//! stages, so a rail from an earlier stage losing power good while a later
+//! stage is coming up is treated as a fault of that earlier rail.
+
+use crate::seq_spi::{Addr, Reg, SequencerFpga};
+use crate::Trace;
+use drv_gimlet_seq_api::{Rail, RailStatus, SeqError, NUM_RAILS};
+use drv_spi_api::SpiServer;
+use ringbuf::ringbuf_entry_root as ringbuf_entry;
+use Reg::{A1_READBACKS as A1, GROUPB_PG as B, GROUPC_PG as C};
+
+/// A group of rails enabled together
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    A1 = 0,
+    GroupB = 1,
+    GroupC = 2,
+}
+
+const STAGES: [Stage; 3] = [Stage::A1, Stage::GroupB, Stage::GroupC];
+
+impl Stage {
+    /// Register containing the power good readbacks for this stage's rails
+    fn pg_register(self) -> Addr {
+        match self {
+            Stage::A1 => Addr::A1_READBACKS,
+            Stage::GroupB => Addr::GROUPB_PG,
+            Stage::GroupC => Addr::GROUPC_PG,
+        }
+    }
+}
+
+struct RailDesc {
+    rail: Rail,
+    stage: Stage,
+
+    /// Bit within the stage's power good register
+    pg: u8,
+
+    /// Time from the start of the stage by which power good must be
+    /// asserted, in milliseconds
+    timeout: u64,
+}
+
+const fn rail(rail: Rail, stage: Stage, pg: u8, timeout: u64) -> RailDesc {
+    RailDesc {
+        rail,
+        stage,
+        pg,
+        timeout,
+    }
+}
+
+/// Rails in enable order; this must be in the same order as [`Rail`].
+const RAILS: [RailDesc; NUM_RAILS] = [
+    rail(Rail::V1P5Rtc, Stage::A1, A1::V1P5_RTC_PG, 500),
+    rail(Rail::V3P3S5, Stage::A1, A1::V3P3_S5_PG, 500),
+    rail(Rail::V1P8S5, Stage::A1, A1::V1P8_S5_PG, 500),
+    rail(Rail::V0P9VddSocS5, Stage::A1, A1::V0P9_VDD_SOC_S5_PG, 500),
+    rail(Rail::VppAbcd, Stage::GroupB, B::VPP_ABCD_PG, 1000),
+    rail(Rail::VppEfgh, Stage::GroupB, B::VPP_EFGH_PG, 1000),
+    rail(Rail::VddMemAbcd, Stage::GroupB, B::VDD_MEM_ABCD_PG, 1000),
+    rail(Rail::VddMemEfgh, Stage::GroupB, B::VDD_MEM_EFGH_PG, 1000),
+    rail(Rail::VttAbcd, Stage::GroupB, B::VTT_ABCD_PG, 1000),
+    rail(Rail::VttEfgh, Stage::GroupB, B::VTT_EFGH_PG, 1000),
+    rail(Rail::V1P8Sp3, Stage::GroupB, B::V1P8_SP3_PG, 1000),
+    rail(Rail::V3P3Sys, Stage::GroupB, B::V3P3_SYS_PG, 1000),
+    rail(Rail::VddcrSoc, Stage::GroupC, C::VDDCR_SOC_PG, 500),
+    rail(Rail::VddVcore, Stage::GroupC, C::VDD_VCORE, 500),
+];
+
+pub struct Rails {
+    /// Status of each rail as of the most recent sequencing attempt
+    status: [RailStatus; NUM_RAILS],
+
+    /// The rail that caused the most recent sequencing attempt to fail
+    fault: Option<Rail>,
+}
+
+impl Rails {
+    pub fn new() -> Self {
+        Self {
+            status: [RailStatus::Off; NUM_RAILS],
+            fault: None,
+        }
+    }
+
+    pub fn status(&self, rail: Rail) -> RailStatus {
+        self.status[rail as usize]
+    }
+
+    pub fn fault(&self) -> Option<Rail> {
+        self.fault
+    }
+
+    /// Returns the first group B or C rail, in enable order, that isn't
+    /// power good according to `groupb_pg` and `groupc_pg` (as read from
+    /// `GROUPB_PG` and `GROUPC_PG`, or their fault snapshots).
+    pub fn first_without_pg(groupb_pg: u8, groupc_pg: u8) -> Option<Rail> {
+        RAILS
+            .iter()
+            .find(|desc| match desc.stage {
+                Stage::A1 => false,
+                Stage::GroupB => groupb_pg & desc.pg == 0,
+                Stage::GroupC => groupc_pg & desc.pg == 0,
+            })
+            .map(|desc| desc.rail)
+    }
+
+    /// Marks all rails as off and forgets any recorded fault.
+    pub fn reset(&mut self) {
+        self.status = [RailStatus::Off; NUM_RAILS];
+        self.fault = None;
+    }
+
+    /// Marks the rails of `stage` as enabled and awaiting power good.
+    pub fn enable(&mut self, stage: Stage) {
+        for (desc, status) in RAILS.iter().zip(self.status.iter_mut()) {
+            if desc.stage == stage {
+                *status = RailStatus::Pending;
+            }
+        }
+    }
+
+    /// Checks power good for every rail enabled up to and including `stage`,
+    /// where `elapsed` is the time since `stage` started, in milliseconds.
+    /// Returns an error (having recorded the fault) if a rail in `stage` has
+    /// exceeded its timeout or a rail that was good has lost power good.
+    pub fn check<S: SpiServer>(
+        &mut self,
+        seq: &SequencerFpga<S>,
+        stage: Stage,
+        elapsed: u64,
+    ) -> Result<(), SeqError> {
+        let mut pg = [0u8; STAGES.len()];
+
+        for s in STAGES.iter().filter(|s| **s <= stage) {
+            pg[*s as usize] = seq.read_byte(s.pg_register()).unwrap();
+        }
+
+        for (ndx, desc) in RAILS.iter().enumerate() {
+            if desc.stage > stage {
+                break;
+            }
+
+            let good = pg[desc.stage as usize] & desc.pg != 0;
+
+            match (self.status[ndx], good) {
+                (RailStatus::Pending, true) => {
+                    self.status[ndx] = RailStatus::Good;
+                }
+                (RailStatus::Good, false) => {
+                    return Err(self.record(ndx, RailStatus::Lost));
+                }
+                (RailStatus::Pending, false)
+                    if desc.stage == stage && elapsed > desc.timeout =>
+                {
+                    return Err(self.record(ndx, RailStatus::TimedOut));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Attributes a timeout of `stage` as a whole to the first of its rails
+    /// that has not indicated power good, if any.  (If all of the stage's
+    /// rails are good, the state machine is stuck for some other reason and
+    /// no rail is blamed.)
+    pub fn stage_timed_out(&mut self, stage: Stage) {
+        let pending = RAILS.iter().enumerate().find(|(ndx, desc)| {
+            desc.stage == stage && self.status[*ndx] == RailStatus::Pending
+        });
+
+        if let Some((ndx, _)) = pending {
+            self.record(ndx, RailStatus::TimedOut);
+        }
+    }
+
+    fn record(&mut self, ndx: usize, status: RailStatus) -> SeqError {
+        let rail = RAILS[ndx].rail;
+
+        ringbuf_entry!(Trace::RailFault(rail, status));
+        self.status[ndx] = status;
+        self.fault = Some(rail);
+
+        match status {
+            RailStatus::Lost => SeqError::RailLost,
+            _ => SeqError::RailTimeout,
+        }
+    }
+}
@@ -7,10 +7,23 @@
 
 #![no_std]
 
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
 use userlib::FromPrimitive;
 use zerocopy::AsBytes;
 
-#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, Eq, AsBytes)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    PartialEq,
+    Eq,
+    AsBytes,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
 #[repr(u8)]
 pub enum PowerState {
     /// Initial A2 state where the SP and most associated circuitry is powered.
@@ -35,3 +48,73 @@ pub enum PowerState {
     /// an explicit transition back to A2.
     A0Reset = 8,
 }
+
+/// Events reported by the sequencer as fan power comes and goes.
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, Eq, AsBytes)]
+#[repr(u8)]
+pub enum FanEvent {
+    /// Fan power good was lost while the fans were powered: the fan board
+    /// has been removed, or its hot-swap controller has tripped.
+    PowerLost = 1,
+    /// Fan power has been enabled and power good has settled.
+    Enabled = 3,
+    /// The fan hot-swap controller faulted, or failed to indicate power good
+    /// after being enabled.
+    Fault = 4,
+}
+
+/// Power rails brought up by the sequencer FPGA on the way to A0, in the
+/// order in which they are sequenced.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    PartialEq,
+    Eq,
+    AsBytes,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+#[repr(u8)]
+pub enum Rail {
+    // A1 rails, enabled by the FPGA on A1PWREN.
+    V1P5Rtc = 0,
+    V3P3S5 = 1,
+    V1P8S5 = 2,
+    V0P9VddSocS5 = 3,
+
+    // Group B rails, enabled by the FPGA on A0A_EN.
+    VppAbcd = 4,
+    VppEfgh = 5,
+    VddMemAbcd = 6,
+    VddMemEfgh = 7,
+    VttAbcd = 8,
+    VttEfgh = 9,
+    V1P8Sp3 = 10,
+    V3P3Sys = 11,
+
+    // Group C rails, enabled by the SP over PMBus.
+    VddcrSoc = 12,
+    VddVcore = 13,
+}
+
+/// Number of variants in [`Rail`].
+pub const NUM_RAILS: usize = 14;
+
+/// State of an individual rail, as observed by the sequencer.
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, Eq, AsBytes)]
+#[repr(u8)]
+pub enum RailStatus {
+    /// The rail has not been enabled.
+    Off = 0,
+    /// The rail has been enabled, but has not yet indicated power good.
+    Pending = 1,
+    /// The rail has indicated power good.
+    Good = 2,
+    /// The rail failed to indicate power good within its timeout.
+    TimedOut = 3,
+    /// The rail indicated power good, but lost it during sequencing.
+    Lost = 4,
+}
@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the mailbox between the SP and the host OS.
+//!
+//! The mailbox holds one message in each direction. A message sent to the
+//! host sits there until the host takes it, and until then `send` says
+//! `Busy`; a message from the host sits there until the listener takes it
+//! with `recv`, and the host can't send another until it does.
+//!
+//! One task is the listener, by calling `listen`. It's notified when the
+//! host sends a message, takes one of ours, or comes or goes, and should
+//! check `status` to see which.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Largest message in either direction.
+pub const MAX_MESSAGE: usize = 1024;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum HostMailboxError {
+    /// The FPGA has no mailbox, probably because its image predates it.
+    NotPresent = 1,
+    /// The host isn't listening.
+    HostNotReady,
+    /// The host hasn't taken our last message.
+    Busy,
+    /// The host hasn't sent anything.
+    Empty,
+    /// The host's message is larger than the buffer given for it, and is
+    /// still there.
+    BufferTooSmall,
+    /// Only the listener may receive.
+    NotListening,
+    /// The host gave its message an impossible length; it's been thrown
+    /// away.
+    BadLength,
+    /// Talking to the FPGA failed.
+    SpiFailed,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct MailboxStatus {
+    /// Nonzero if the host says it's listening.
+    pub host_ready: u8,
+    /// Nonzero if the host has sent a message that hasn't been received.
+    pub inbound: u8,
+    /// Nonzero if we've sent a message the host hasn't taken.
+    pub outbound: u8,
+    _pad: u8,
+    /// Messages received from the host since the server started.
+    pub received: u32,
+    /// Messages sent to the host since the server started.
+    pub sent: u32,
+}
+
+impl MailboxStatus {
+    pub fn new(
+        host_ready: bool,
+        inbound: bool,
+        outbound: bool,
+        received: u32,
+        sent: u32,
+    ) -> Self {
+        Self {
+            host_ready: host_ready as u8,
+            inbound: inbound as u8,
+            outbound: outbound as u8,
+            _pad: 0,
+            received,
+            sent,
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -20,7 +20,7 @@
 //! - The address of the device itself
 //!
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
@@ -49,6 +49,133 @@ pub enum Op {
     /// very strange device indeed.
     WriteReadBlock = 2,
     SelectedMuxSegment = 3,
+
+    /// In a `Transaction` operation, the message carries a list of
+    /// [`TransactionStep`]s, each of which corresponds to the lease at the
+    /// same index.  All steps are performed under a single bus claim, with a
+    /// repeated START between each step and a single STOP at the end.
+    Transaction = 4,
+
+    /// `WriteReadPec` and `WriteReadBlockPec` are identical to `WriteRead` and
+    /// `WriteReadBlock`, respectively, but with SMBus Packet Error Checking:
+    /// a PEC byte is appended to any write that is not followed by a read,
+    /// and a PEC byte is read (and verified) at the end of any read.
+    WriteReadPec = 5,
+    WriteReadBlockPec = 6,
+
+    /// Polls the SMBus Alert Response Address on the specified bus (and
+    /// segment), notifying the owning task of each device that responds.
+    SmbAlertPoll = 7,
+
+    /// Submits a transfer to be performed asynchronously, returning a ticket.
+    /// The write data is leased; the message carries the notification to be
+    /// posted upon completion and the number of bytes to read.
+    SubmitTransfer = 8,
+
+    /// Collects the result of a transfer previously submitted with
+    /// `SubmitTransfer`, given its ticket.
+    CollectTransfer = 9,
+
+    /// Returns the [`SegmentStatus`] of the specified mux segment.
+    SegmentStatus = 10,
+
+    /// Scans the specified bus (and segment) for devices, returning a
+    /// [`Presence`] bitmap.  This is only supported if the server has been
+    /// built with diagnostics enabled.
+    Scan = 11,
+
+    /// Returns the server's [`ErrorCounts`].  The message carries nothing.
+    ErrorCounts = 12,
+}
+
+///
+/// A bitmap of the 7-bit addresses found to acknowledge on a bus, as
+/// returned by [`I2cDevice::scan`]:  address `a` is present if bit `a % 8`
+/// of byte `a / 8` is set.
+///
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Presence(pub [u8; 16]);
+
+impl Presence {
+    pub fn set(&mut self, addr: u8) {
+        self.0[usize::from(addr >> 3) & 0xf] |= 1 << (addr & 0x7);
+    }
+
+    pub fn is_present(&self, addr: u8) -> bool {
+        self.0[usize::from(addr >> 3) & 0xf] & (1 << (addr & 0x7)) != 0
+    }
+
+    /// Returns an iterator over the addresses that are present.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..128).filter(|&addr| self.is_present(addr))
+    }
+}
+
+/// The maximum number of bytes that can be written or read by a transfer
+/// submitted via [`I2cDevice::submit`].
+pub const ASYNC_TRANSFER_MAX: usize = 32;
+
+/// The size of the message sent for a [`Op::SubmitTransfer`]: the marshalled
+/// device, the completion notification, and the read length.
+pub const SUBMIT_MESSAGE_SIZE: usize = 4 + 4 + 1;
+
+///
+/// A ticket denoting an asynchronous transfer, as returned by
+/// [`I2cDevice::submit`] and redeemed via [`I2cDevice::collect`].
+///
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Ticket(pub u32);
+
+/// The SMBus Alert Response Address, read by the host to determine which
+/// device(s) have asserted SMBALERT#.
+pub const SMBUS_ALERT_RESPONSE_ADDRESS: u8 = 0x0c;
+
+/// The maximum payload of an SMBus block write, as constructed by
+/// [`I2cDevice::write_block`].  (This is the SMBus 2.0 limit; while SMBus 3.0
+/// raises it to 255 bytes, the block is constructed on the caller's stack.)
+pub const SMBUS_BLOCK_MAX: usize = 32;
+
+///
+/// Computes the SMBus Packet Error Code (a CRC-8 with the polynomial
+/// x^8 + x^2 + x + 1) over `data`, starting from the specified `crc` (which
+/// should be 0 at the start of a message).
+///
+pub fn smbus_pec(crc: u8, data: &[u8]) -> u8 {
+    data.iter().fold(crc, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// The maximum number of steps that can be performed in a single
+/// [`I2cDevice::transaction`].
+pub const MAX_TRANSACTION_STEPS: usize = 8;
+
+/// The size of the message sent for a [`Op::Transaction`]: the marshalled
+/// device followed by the encoded steps (with unused steps set to zero).
+pub const TRANSACTION_MESSAGE_SIZE: usize = 4 + MAX_TRANSACTION_STEPS;
+
+///
+/// A single step within a transaction.  Each step consumes exactly one
+/// lease:  a `Write` requires a readable lease, while a `Read` or
+/// `ReadBlock` requires a writable one.
+///
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq)]
+#[repr(u8)]
+pub enum TransactionStep {
+    /// Write the entire contents of the lease to the device
+    Write = 1,
+    /// Read from the device until the lease is full
+    Read = 2,
+    /// Perform an SMBus block read into the lease; as with
+    /// [`I2cDevice::read_block`], the byte count is not written to the lease
+    ReadBlock = 3,
 }
 
 /// The response code returned from the I2C server.  These response codes pretty
@@ -108,6 +235,17 @@ pub enum ResponseCode {
     OperationNotSupported = 25,
     /// Illegal number of leases
     IllegalLeaseCount = 26,
+    /// SMBus Packet Error Code did not match the data received
+    PecMismatch = 27,
+    /// Asynchronous transfer queue for the controller is full, or the caller
+    /// already holds its share of it
+    QueueFull = 28,
+    /// Asynchronous transfer has not yet been performed
+    TransferPending = 29,
+    /// Ticket does not denote an outstanding transfer for this caller
+    BadTicket = 30,
+    /// Segment has repeatedly failed to be selected and is quarantined
+    SegmentQuarantined = 31,
 }
 
 ///
@@ -156,6 +294,57 @@ pub enum ReservedAddress {
     TenBit11 = 0b1111_111,
 }
 
+///
+/// The address of an I2C target.  The overwhelming majority of devices have
+/// 7-bit addresses, but some have 10-bit addresses, which are sent as a
+/// two-byte sequence beginning with one of the [`ReservedAddress::TenBit00`]
+/// (etc.) patterns.
+///
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Address {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl From<u8> for Address {
+    fn from(addr: u8) -> Self {
+        Address::SevenBit(addr)
+    }
+}
+
+impl Address {
+    /// Returns the 7-bit address, if this is one.
+    pub fn seven_bit(&self) -> Option<u8> {
+        match self {
+            Address::SevenBit(addr) => Some(*addr),
+            Address::TenBit(_) => None,
+        }
+    }
+
+    ///
+    /// Returns true if this address is reserved.  All 10-bit addresses are
+    /// valid:  it is the 7-bit addresses that collide with the 10-bit
+    /// address prefix (among others) that are reserved.
+    ///
+    pub fn is_reserved(&self) -> bool {
+        match self {
+            Address::SevenBit(addr) => {
+                ReservedAddress::from_u8(*addr).is_some()
+            }
+            Address::TenBit(_) => false,
+        }
+    }
+}
+
+impl core::fmt::Display for Address {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Address::SevenBit(addr) => write!(f, "{addr:#x}"),
+            Address::TenBit(addr) => write!(f, "{addr:#05x} (10-bit)"),
+        }
+    }
+}
+
 ///
 /// The port index for a given I2C device.  Some controllers can have multiple
 /// ports (which themselves are connected to different I2C buses), but only
@@ -224,6 +413,82 @@ pub enum Segment {
     S8 = 8,
 }
 
+///
+/// The health of a segment on a multiplexer, as determined by the server's
+/// record of attempts to select it.
+///
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub enum SegmentHealth {
+    /// The most recent attempt to select the segment succeeded
+    Healthy,
+    /// The most recent attempt(s) to select the segment failed
+    Degraded,
+    /// The segment has failed repeatedly and operations to it are being
+    /// failed without accessing the bus
+    Quarantined,
+}
+
+///
+/// The status of a segment on a multiplexer, as returned by
+/// [`I2cDevice::segment_status`].
+///
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub struct SegmentStatus {
+    pub health: SegmentHealth,
+    /// Total number of failures to select this segment
+    pub failures: u32,
+    /// Total number of times the segment's mux has been reset due to
+    /// repeated failures
+    pub mux_resets: u32,
+}
+
+///
+/// Counts of failed operations across all of a server's buses since it
+/// started, as returned by [`error_counts`].  All of them wrap.
+///
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    SerializedSize,
+    Serialize,
+    Deserialize,
+)]
+pub struct ErrorCounts {
+    /// Transfers (including mux segment selections) that failed
+    pub errors: u32,
+    /// Of those, the ones that failed because the device didn't respond
+    pub no_device: u32,
+    /// Times a controller (and the mux on it, if any) was reset
+    pub resets: u32,
+}
+
+///
+/// Returns counts of the errors the I2C server `task` has seen, on any of
+/// its buses.
+///
+pub fn error_counts(task: TaskId) -> Result<ErrorCounts, ResponseCode> {
+    let mut response = [0u8; ErrorCounts::MAX_SIZE];
+
+    let (code, _) =
+        sys_send(task, Op::ErrorCounts as u16, &[], &mut response, &[]);
+
+    if code != 0 {
+        Err(ResponseCode::from_u32(code).ok_or(ResponseCode::BadResponse)?)
+    } else {
+        let (counts, _) = hubpack::deserialize::<ErrorCounts>(&response)
+            .map_err(|_| ResponseCode::BadResponse)?;
+        Ok(counts)
+    }
+}
+
 ///
 /// The 5-tuple that uniquely identifies an I2C device.  The multiplexer and
 /// the segment are optional, but if one is present, the other must be.
@@ -234,10 +499,20 @@ pub struct I2cDevice {
     pub controller: Controller,
     pub port: PortIndex,
     pub segment: Option<(Mux, Segment)>,
-    pub address: u8,
+    pub address: Address,
 }
 
-type I2cMessage = (u8, Controller, PortIndex, Option<(Mux, Segment)>);
+type I2cMessage = (Address, Controller, PortIndex, Option<(Mux, Segment)>);
+
+//
+// A 10-bit address is marshalled by putting its low eight bits in the address
+// byte, and setting TEN_BIT_FLAG (along with the top two bits of the
+// address) in the controller byte, which has bits to spare.  (The mock
+// controller has no such bits to spare -- but also has no need for 10-bit
+// addresses.)
+//
+const TEN_BIT_FLAG: u8 = 0b0100_0000;
+const TEN_BIT_SHIFT: u32 = 4;
 
 pub trait Marshal<T> {
     fn marshal(&self) -> T;
@@ -248,9 +523,19 @@ pub trait Marshal<T> {
 
 impl Marshal<[u8; 4]> for I2cMessage {
     fn marshal(&self) -> [u8; 4] {
+        let (addr, controller) = match self.0 {
+            Address::SevenBit(addr) => (addr, self.1 as u8),
+            Address::TenBit(addr) => (
+                addr as u8,
+                TEN_BIT_FLAG
+                    | (((addr >> 8) as u8 & 0b11) << TEN_BIT_SHIFT)
+                    | self.1 as u8,
+            ),
+        };
+
         [
-            self.0,
-            self.1 as u8,
+            addr,
+            controller,
             self.2 .0,
             match self.3 {
                 Some((mux, seg)) => {
@@ -261,9 +546,21 @@ impl Marshal<[u8; 4]> for I2cMessage {
         ]
     }
     fn unmarshal(val: &[u8; 4]) -> Result<Self, ResponseCode> {
+        let (addr, controller) =
+            if val[1] != Controller::Mock as u8 && val[1] & TEN_BIT_FLAG != 0 {
+                let hi = u16::from((val[1] >> TEN_BIT_SHIFT) & 0b11);
+                (
+                    Address::TenBit(hi << 8 | u16::from(val[0])),
+                    val[1] & 0b1111,
+                )
+            } else {
+                (Address::SevenBit(val[0]), val[1])
+            };
+
         Ok((
-            val[0],
-            Controller::from_u8(val[1]).ok_or(ResponseCode::BadController)?,
+            addr,
+            Controller::from_u8(controller)
+                .ok_or(ResponseCode::BadController)?,
             PortIndex(val[2]),
             if val[3] == 0 {
                 None
@@ -281,16 +578,16 @@ impl Marshal<[u8; 4]> for I2cMessage {
 
 impl core::fmt::Display for I2cDevice {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        let addr = self.address;
+        let addr = self.target();
 
         match self.segment {
             None => {
-                write!(f, "{:?}:{:?} {:#x}", self.controller, self.port, addr)
+                write!(f, "{:?}:{:?} {}", self.controller, self.port, addr)
             }
             Some((mux, segment)) => {
                 write!(
                     f,
-                    "{:?}:{:?}, {:?}:{:?} {:#x}",
+                    "{:?}:{:?}, {:?}:{:?} {}",
                     self.controller, self.port, mux, segment, addr
                 )
             }
@@ -298,6 +595,25 @@ impl core::fmt::Display for I2cDevice {
     }
 }
 
+///
+/// Constructs an SMBus block write payload in `buf`, returning its length.
+///
+fn block_payload(
+    buf: &mut [u8; SMBUS_BLOCK_MAX + 2],
+    cmd: u8,
+    data: &[u8],
+) -> Result<usize, ResponseCode> {
+    if data.len() > SMBUS_BLOCK_MAX {
+        return Err(ResponseCode::BadArg);
+    }
+
+    buf[0] = cmd;
+    buf[1] = data.len() as u8;
+    buf[2..2 + data.len()].copy_from_slice(data);
+
+    Ok(data.len() + 2)
+}
+
 impl I2cDevice {
     ///
     /// Return a new [`I2cDevice`], given a 5-tuple identifying a device plus
@@ -316,9 +632,31 @@ impl I2cDevice {
             controller,
             port,
             segment,
-            address,
+            address: Address::SevenBit(address),
+        }
+    }
+
+    /// Return a new [`I2cDevice`] for a device with a 10-bit address
+    pub fn new_ten_bit(
+        task: TaskId,
+        controller: Controller,
+        port: PortIndex,
+        segment: Option<(Mux, Segment)>,
+        address: u16,
+    ) -> Self {
+        Self {
+            task,
+            controller,
+            port,
+            segment,
+            address: Address::TenBit(address & 0x3ff),
         }
     }
+
+    /// Returns the device's address, be it 7-bit or 10-bit.
+    pub fn target(&self) -> Address {
+        self.address
+    }
 }
 
 impl I2cDevice {
@@ -350,7 +688,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -383,7 +721,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -418,7 +756,7 @@ impl I2cDevice {
             self.task,
             Op::WriteReadBlock as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -450,7 +788,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -479,7 +817,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -507,7 +845,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -544,7 +882,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -591,7 +929,7 @@ impl I2cDevice {
             self.task,
             Op::WriteReadBlock as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -631,7 +969,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -674,7 +1012,7 @@ impl I2cDevice {
             self.task,
             Op::WriteRead as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 self.segment,
@@ -698,6 +1036,423 @@ impl I2cDevice {
         }
     }
 
+    ///
+    /// Like [`read_reg`], but with SMBus Packet Error Checking:  the device
+    /// is expected to follow the register value with a PEC byte, which is
+    /// verified by the server.  If the PEC does not match,
+    /// [`ResponseCode::PecMismatch`] is returned.
+    ///
+    pub fn read_reg_pec<R: AsBytes, V: AsBytes + FromBytes>(
+        &self,
+        reg: R,
+    ) -> Result<V, ResponseCode> {
+        let mut val = V::new_zeroed();
+        let mut response = 0_usize;
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::WriteReadPec as u16,
+            &Marshal::marshal(&(
+                self.target(),
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            response.as_bytes_mut(),
+            &[Lease::from(reg.as_bytes()), Lease::from(val.as_bytes_mut())],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(val)
+        }
+    }
+
+    ///
+    /// Like [`read_block`], but with SMBus Packet Error Checking:  the PEC
+    /// that follows the block is verified by the server (and is not present
+    /// in the specified buffer).
+    ///
+    pub fn read_block_pec<R: AsBytes>(
+        &self,
+        reg: R,
+        buf: &mut [u8],
+    ) -> Result<usize, ResponseCode> {
+        let mut response = 0_usize;
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::WriteReadBlockPec as u16,
+            &Marshal::marshal(&(
+                self.target(),
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            response.as_bytes_mut(),
+            &[Lease::from(reg.as_bytes()), Lease::from(buf)],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(response)
+        }
+    }
+
+    ///
+    /// Like [`write`], but with SMBus Packet Error Checking:  the server
+    /// appends a PEC byte to the buffer.
+    ///
+    pub fn write_pec(&self, buffer: &[u8]) -> Result<(), ResponseCode> {
+        let mut response = 0_usize;
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::WriteReadPec as u16,
+            &Marshal::marshal(&(
+                self.target(),
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            response.as_bytes_mut(),
+            &[Lease::from(buffer), Lease::read_only(&[])],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(())
+        }
+    }
+
+    ///
+    /// Performs an SMBus block write of `data` to the specified command code
+    /// (that is, writes `[cmd, data.len(), data[0], data[1], ...]`).  The
+    /// data may be no longer than [`SMBUS_BLOCK_MAX`] bytes.
+    ///
+    pub fn write_block(
+        &self,
+        cmd: u8,
+        data: &[u8],
+    ) -> Result<(), ResponseCode> {
+        let mut buf = [0u8; SMBUS_BLOCK_MAX + 2];
+        let len = block_payload(&mut buf, cmd, data)?;
+        self.write(&buf[..len])
+    }
+
+    ///
+    /// Like [`write_block`], but with SMBus Packet Error Checking.
+    ///
+    pub fn write_block_pec(
+        &self,
+        cmd: u8,
+        data: &[u8],
+    ) -> Result<(), ResponseCode> {
+        let mut buf = [0u8; SMBUS_BLOCK_MAX + 2];
+        let len = block_payload(&mut buf, cmd, data)?;
+        self.write_pec(&buf[..len])
+    }
+
+    ///
+    /// Services SMBALERT# on this device's bus (and segment, if any) by
+    /// polling the Alert Response Address until no device responds.  Each
+    /// responding device that has been configured with an owning task will
+    /// have that task notified.  Returns the number of devices that
+    /// responded; if the bus has an SMBALERT# line configured and it is not
+    /// asserted, the Alert Response Address is not polled at all.
+    ///
+    /// A line that is given a `notification` in the app's I2C config (and
+    /// routed to the I2C server as a GPIO interrupt) is serviced by the
+    /// server as soon as it is asserted, with no need to poll.
+    ///
+    pub fn poll_smbalert(&self) -> Result<usize, ResponseCode> {
+        let mut response = 0_usize;
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::SmbAlertPoll as u16,
+            &Marshal::marshal(&(
+                Address::SevenBit(SMBUS_ALERT_RESPONSE_ADDRESS),
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            response.as_bytes_mut(),
+            &[],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(response)
+        }
+    }
+
+    ///
+    /// Performs an arbitrary sequence of writes and reads as a single I2C
+    /// transaction:  each step is separated from the next by a repeated
+    /// START, and the bus is not released (that is, no STOP is sent) until
+    /// all steps have been performed.  This accommodates devices that
+    /// require multiple writes and reads without interruption, and
+    /// guarantees that no other bus user can intervene.
+    ///
+    /// Each step in `steps` operates on the lease at the same index in
+    /// `leases`; at most [`MAX_TRANSACTION_STEPS`] steps may be specified.
+    /// On success, returns the total number of bytes read across all
+    /// steps.
+    ///
+    pub fn transaction(
+        &self,
+        steps: &[TransactionStep],
+        leases: &[Lease<'_>],
+    ) -> Result<usize, ResponseCode> {
+        if steps.is_empty() || steps.len() > MAX_TRANSACTION_STEPS {
+            return Err(ResponseCode::BadArg);
+        }
+
+        if steps.len() != leases.len() {
+            return Err(ResponseCode::IllegalLeaseCount);
+        }
+
+        let mut msg = [0u8; TRANSACTION_MESSAGE_SIZE];
+
+        msg[..4].copy_from_slice(&Marshal::marshal(&(
+            self.target(),
+            self.controller,
+            self.port,
+            self.segment,
+        )));
+
+        for (dest, step) in msg[4..].iter_mut().zip(steps) {
+            *dest = *step as u8;
+        }
+
+        let mut response = 0_usize;
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::Transaction as u16,
+            &msg,
+            response.as_bytes_mut(),
+            leases,
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(response)
+        }
+    }
+
+    ///
+    /// Reads several registers, storing the value of each register in `regs`
+    /// at the same index in `values`.  This is equivalent to calling
+    /// [`read_reg`] for each register, but batches the reads into
+    /// [`transaction`]s of up to `MAX_TRANSACTION_STEPS / 2` registers
+    /// apiece, saving both IPCs and bus turnarounds.  Because each batch is
+    /// a single transaction, its values are also read without any
+    /// intervening bus traffic.
+    ///
+    pub fn read_regs<R: AsBytes, V: AsBytes + FromBytes>(
+        &self,
+        regs: &[R],
+        values: &mut [V],
+    ) -> Result<(), ResponseCode> {
+        use TransactionStep::{Read, Write};
+        const BATCH: usize = MAX_TRANSACTION_STEPS / 2;
+        const STEPS: [TransactionStep; BATCH * 2] =
+            [Write, Read, Write, Read, Write, Read, Write, Read];
+
+        if regs.len() != values.len() {
+            return Err(ResponseCode::BadArg);
+        }
+
+        for (regs, values) in regs.chunks(BATCH).zip(values.chunks_mut(BATCH)) {
+            let nsteps = regs.len() * 2;
+            let mut regs = regs.iter();
+            let mut values = values.iter_mut();
+
+            //
+            // Our leases alternate between the register to write and the
+            // value to read; any beyond the end of this batch are empty (and
+            // aren't sent).
+            //
+            let leases: [Lease<'_>; BATCH * 2] = core::array::from_fn(|i| {
+                let lease = if i % 2 == 0 {
+                    regs.next().map(|r| Lease::from(r.as_bytes()))
+                } else {
+                    values.next().map(|v| Lease::from(v.as_bytes_mut()))
+                };
+
+                lease.unwrap_or_else(|| Lease::from(&[0u8; 0][..]))
+            });
+
+            self.transaction(&STEPS[..nsteps], &leases[..nsteps])?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Submits a write of `write` followed by a read of `rlen` bytes to be
+    /// performed asynchronously, returning a [`Ticket`] without waiting for
+    /// the transfer to occur.  (Either `write` or `rlen` may be empty, but
+    /// not both, and neither may exceed [`ASYNC_TRANSFER_MAX`] bytes.)  Once
+    /// the transfer has been performed, the specified notification bits
+    /// will be posted to the calling task, at which point the result can be
+    /// retrieved with [`collect`].  Transfers on a given controller are
+    /// performed in the order in which they are submitted, driven by the
+    /// controller's interrupts.  A caller can hold only a couple of
+    /// transfers on a controller at once, and a completed transfer left
+    /// uncollected for a second may be reclaimed.
+    ///
+    pub fn submit(
+        &self,
+        write: &[u8],
+        rlen: usize,
+        notification: u32,
+    ) -> Result<Ticket, ResponseCode> {
+        if write.len() > ASYNC_TRANSFER_MAX || rlen > ASYNC_TRANSFER_MAX {
+            return Err(ResponseCode::BadArg);
+        }
+
+        let mut msg = [0u8; SUBMIT_MESSAGE_SIZE];
+
+        msg[..4].copy_from_slice(&Marshal::marshal(&(
+            self.target(),
+            self.controller,
+            self.port,
+            self.segment,
+        )));
+        msg[4..8].copy_from_slice(&notification.to_le_bytes());
+        msg[8] = rlen as u8;
+
+        let mut response = 0_u32;
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::SubmitTransfer as u16,
+            &msg,
+            response.as_bytes_mut(),
+            &[Lease::from(write)],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(Ticket(response))
+        }
+    }
+
+    ///
+    /// Collects the result of a transfer submitted via [`submit`], placing
+    /// any bytes read into `buf` and returning the number of bytes read.  If
+    /// the transfer has not yet been performed,
+    /// [`ResponseCode::TransferPending`] is returned, and the ticket remains
+    /// valid; otherwise, the ticket is consumed (including if the transfer
+    /// itself failed, in which case its error is returned).  `buf` must be
+    /// large enough to hold the read requested at submission.
+    ///
+    pub fn collect(
+        &self,
+        ticket: Ticket,
+        buf: &mut [u8],
+    ) -> Result<usize, ResponseCode> {
+        let mut response = 0_usize;
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::CollectTransfer as u16,
+            ticket.0.as_bytes(),
+            response.as_bytes_mut(),
+            &[Lease::from(buf)],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(response)
+        }
+    }
+
+    ///
+    /// Returns the status of the mux segment on which this device resides.
+    /// If the device is not behind a mux, [`ResponseCode::BadArg`] is
+    /// returned.
+    ///
+    pub fn segment_status(&self) -> Result<SegmentStatus, ResponseCode> {
+        if self.segment.is_none() {
+            return Err(ResponseCode::BadArg);
+        }
+
+        let mut response = [0u8; SegmentStatus::MAX_SIZE];
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::SegmentStatus as u16,
+            &Marshal::marshal(&(
+                self.target(),
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            &mut response,
+            &[],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            let (status, _) = hubpack::deserialize::<SegmentStatus>(&response)
+                .map_err(|_| ResponseCode::BadResponse)?;
+            Ok(status)
+        }
+    }
+
+    ///
+    /// Scans the bus (and segment, if any) on which this device resides for
+    /// devices that acknowledge a read, returning a bitmap of the addresses
+    /// that responded; this device's own address is ignored.  Reserved
+    /// addresses are not scanned.  This is intended for bring-up and
+    /// diagnostics, and is only supported by servers built with
+    /// diagnostics enabled; others will return
+    /// [`ResponseCode::OperationNotSupported`].
+    ///
+    pub fn scan(&self) -> Result<Presence, ResponseCode> {
+        let mut presence = Presence::default();
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::Scan as u16,
+            &Marshal::marshal(&(
+                self.target(),
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            presence.as_bytes_mut(),
+            &[],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(presence)
+        }
+    }
+
     pub fn selected_mux_segment(
         &self,
     ) -> Result<Option<(Mux, Segment)>, ResponseCode> {
@@ -707,7 +1462,7 @@ impl I2cDevice {
             self.task,
             Op::SelectedMuxSegment as u16,
             &Marshal::marshal(&(
-                self.address,
+                self.target(),
                 self.controller,
                 self.port,
                 None,
@@ -724,7 +1479,7 @@ impl I2cDevice {
                 Marshal::unmarshal(&response)?;
 
             if controller != self.controller
-                || address != self.address
+                || address != self.target()
                 || port != self.port
             {
                 Err(ResponseCode::BadSelectedMux)
@@ -734,3 +1489,39 @@ impl I2cDevice {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smbus_pec_vectors() {
+        // The check value for CRC-8 (poly 0x07, init 0, no reflection or
+        // final XOR), as given in the CRC catalogue.
+        assert_eq!(smbus_pec(0, b"123456789"), 0xf4);
+
+        assert_eq!(smbus_pec(0, &[]), 0);
+        assert_eq!(smbus_pec(0x5a, &[]), 0x5a);
+        assert_eq!(smbus_pec(0, &[0x01]), 0x07);
+        assert_eq!(smbus_pec(0, &[0x80]), 0x89);
+    }
+
+    #[test]
+    fn smbus_pec_is_incremental() {
+        // A PMBus READ_WORD of command 0x8b from address 0x5b, as the server
+        // computes it: address and command, then the repeated start, then
+        // the data.
+        let msg = [0xb6, 0x8b, 0xb7, 0x00, 0x30];
+        let pec = smbus_pec(0, &msg);
+        assert_eq!(pec, 0x5d);
+
+        let split = smbus_pec(smbus_pec(0, &msg[..2]), &msg[2..]);
+        assert_eq!(split, pec);
+
+        let bytewise = msg.iter().fold(0, |crc, &b| smbus_pec(crc, &[b]));
+        assert_eq!(bytewise, pec);
+
+        // A message followed by its PEC checks to zero.
+        assert_eq!(smbus_pec(pec, &[pec]), 0);
+    }
+}
@@ -60,6 +60,16 @@ struct Coefficients {
     power: pmbus::Coefficients,
 }
 
+///
+/// A full set of telemetry, as returned by [`Adm1272::read_telemetry`]
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Telemetry {
+    pub vout: Volts,
+    pub iout: Amperes,
+    pub temperature: Celsius,
+}
+
 pub struct Adm1272 {
     /// Underlying I2C device
     device: I2cDevice,
@@ -265,6 +275,52 @@ impl Adm1272 {
         Ok(Amperes(iout.get(&self.load_coefficients()?.current)?.0))
     }
 
+    ///
+    /// Reads output voltage, output current and temperature as a single
+    /// batch, rather than paying for a transaction for each.
+    ///
+    pub fn read_telemetry(&self) -> Result<Telemetry, Error> {
+        use adm1272::{READ_IOUT, READ_TEMPERATURE_1, READ_VOUT};
+
+        self.enable_vout_sampling()?;
+        self.enable_temp1_sampling()?;
+        let coefficients = self.load_coefficients()?;
+
+        let cmds = [
+            READ_VOUT::CommandData::code(),
+            READ_IOUT::CommandData::code(),
+            READ_TEMPERATURE_1::CommandData::code(),
+        ];
+        let mut raw = [[0u8; 2]; 3];
+
+        if self.device.read_regs(&cmds, &mut raw).is_err() {
+            //
+            // A failed batch doesn't tell us which of its reads failed, so
+            // read each register on its own to find (and report) the one
+            // that did.
+            //
+            for (&cmd, raw) in cmds.iter().zip(raw.iter_mut()) {
+                *raw = self
+                    .device
+                    .read_reg(cmd)
+                    .map_err(|code| Error::BadRead { cmd, code })?;
+            }
+        }
+
+        let vout = READ_VOUT::CommandData::from_slice(&raw[0])
+            .ok_or(Error::BadData { cmd: cmds[0] })?;
+        let iout = READ_IOUT::CommandData::from_slice(&raw[1])
+            .ok_or(Error::BadData { cmd: cmds[1] })?;
+        let temp = READ_TEMPERATURE_1::CommandData::from_slice(&raw[2])
+            .ok_or(Error::BadData { cmd: cmds[2] })?;
+
+        Ok(Telemetry {
+            vout: Volts(vout.get(&coefficients.voltage)?.0),
+            iout: Amperes(iout.get(&coefficients.current)?.0),
+            temperature: Celsius(temp.get()?.0),
+        })
+    }
+
     pub fn i2c_device(&self) -> &I2cDevice {
         &self.device
     }
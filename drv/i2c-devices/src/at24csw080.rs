@@ -7,24 +7,28 @@
 use crate::Validate;
 use core::convert::TryInto;
 use drv_i2c_api::*;
-use userlib::{hl::sleep_for, FromPrimitive, ToPrimitive};
+use userlib::{hl::sleep_for, sys_get_timer, FromPrimitive, ToPrimitive};
 use zerocopy::{AsBytes, FromBytes};
 
 /// Number of bytes stored in the EEPROM
 pub const EEPROM_SIZE: u16 = 1024;
 
-/// Wait time after performing a write
+/// Number of bytes in a page, the unit of a page write
+pub const PAGE_SIZE: u16 = 16;
+
+/// Maximum write cycle time
 const WRITE_TIME_MS: u64 = 5;
 
 /// The AT24CSW080/4 is an I2C EEPROM used as the FRU ID. It includes 8-Kbit of
 /// memory (arranged as 1024 x 8), software write protection, a 256-bit
 /// Security Register, and various other useful features.
 ///
-/// Write functions are conservative with respect to timing, waiting the
-/// entire 5 ms (maximum write cycle time) before returning. If this proves
-/// limiting, it may be possible to use Acknowledge Polling (section 7.3 of the
-/// datasheet). This would use NAK to indicate that the device is not present,
-/// which has more room for confusion.
+/// Rather than waiting the entire 5 ms (maximum write cycle time) after each
+/// write, EEPROM writes use Acknowledge Polling (section 7.3 of the
+/// datasheet) to determine when the write cycle has completed:  the device
+/// NAKs its address until then.  Because a NAK is indistinguishable from the
+/// device not being present, polling is bounded by the write cycle time
+/// (plus a tick of slop), after which we fail with [`Error::WriteTimeout`].
 pub struct At24Csw080 {
     /// We store a `DeviceHandle` instead of an `I2cDevice` to force users
     /// of this API to call either `eeprom()` or `registers()`, since the I2C
@@ -49,9 +53,13 @@ pub enum Error {
     /// In a page write, the start address is misaligned
     MisalignedPage(u16),
 
-    /// In a page write, the data is more than a single page (16 bytes)
+    /// In a page write, the data is more than a single page (16 bytes) or
+    /// would cross a page boundary
     InvalidPageSize(usize),
 
+    /// The device failed to acknowledge after the maximum write cycle time
+    WriteTimeout(u16),
+
     /// Requested an invalid security register byte when reading (>= 32)
     InvalidSecurityRegisterReadByte(u8),
 
@@ -150,10 +158,34 @@ impl At24Csw080 {
             .map_err(Into::into)
     }
 
+    /// Waits for the write cycle of a write to the given address to
+    /// complete by polling for an acknowledge.  We poll with a read of a
+    /// byte (which resets the device's address pointer, but is otherwise
+    /// side-effect free), as our I2C API doesn't allow for an empty write,
+    /// and sleep for a millisecond between polls rather than tying up the
+    /// bus.
+    fn wait_for_write(&self, addr: u16) -> Result<(), Error> {
+        let device = self.device.eeprom(addr);
+        let deadline = sys_get_timer().now + WRITE_TIME_MS + 1;
+
+        loop {
+            match device.read_reg::<u8, u8>(addr as u8) {
+                Ok(_) => return Ok(()),
+                Err(ResponseCode::NoDevice) => {
+                    if sys_get_timer().now > deadline {
+                        return Err(Error::WriteTimeout(addr));
+                    }
+                    sleep_for(1);
+                }
+                Err(code) => return Err(code.into()),
+            }
+        }
+    }
+
     /// Writes a single byte to the EEPROM at the given address
     ///
-    /// On success, sleeps for 5 ms (the EEPROM's write cycle time) before
-    /// returning `Ok(())`
+    /// On success, waits for the write cycle to complete before returning
+    /// `Ok(())`
     pub fn write_byte(&self, addr: u16, val: u8) -> Result<(), Error> {
         if addr >= EEPROM_SIZE {
             return Err(Error::InvalidAddress(addr));
@@ -162,48 +194,51 @@ impl At24Csw080 {
         // Write the low byte of the address followed by the actual value
         let buffer = [addr as u8, val];
         self.device.eeprom(addr).write(&buffer)?;
-        sleep_for(WRITE_TIME_MS);
-        Ok(())
+        self.wait_for_write(addr)
     }
 
-    /// Writes up to 16 bytes to a page.
-    ///
-    /// `addr` must be 16-byte aligned (i.e. the four lowest bits must be 0)
-    /// and less than `EEPROM_SIZE`.
+    /// Writes up to a page of data.
     ///
-    /// `buf` must be 16 bytes or less.
+    /// `addr` must be less than `EEPROM_SIZE`, and `buf` must not extend
+    /// beyond the end of the page containing `addr`:  the device wraps
+    /// around within a page, so a write that crossed a page boundary would
+    /// overwrite the beginning of the page.
     ///
     /// This function will return an error if either of those conditions is
     /// violated
     ///
-    /// On success, sleeps for 5 ms (the EEPROM's write cycle time) before
-    /// returning `Ok(())`
+    /// On success, waits for the write cycle to complete before returning
+    /// `Ok(())`
     fn write_page(&self, addr: u16, buf: &[u8]) -> Result<(), Error> {
+        const LEN: usize = PAGE_SIZE as usize;
+        let offset = (addr % PAGE_SIZE) as usize;
+
         if addr >= EEPROM_SIZE {
             return Err(Error::InvalidAddress(addr));
-        } else if addr & 0b1111 != 0 {
-            return Err(Error::MisalignedPage(addr));
-        } else if buf.len() > 16 {
+        } else if offset + buf.len() > LEN {
             return Err(Error::InvalidPageSize(buf.len()));
         }
 
-        let mut out: [u8; 17] = [0; 17];
+        let mut out = [0u8; LEN + 1];
 
-        // Write the low byte of the address followed by up to 16 bytes of
+        // Write the low byte of the address followed by up to a page of
         // buffer data.
         out[0] = addr as u8;
         out[1..=buf.len()].copy_from_slice(buf);
         self.device.eeprom(addr).write(&out[0..=buf.len()])?;
-        sleep_for(WRITE_TIME_MS);
-        Ok(())
+        self.wait_for_write(addr)
     }
 
-    /// Writes a buffer to the EEPROM at the specified address, taking
-    /// advantage of page writes when possible.
+    /// Writes a buffer to the EEPROM at the specified address, using page
+    /// writes (and waiting only as long as necessary for each to complete).
     ///
-    /// `addr` and `addr + buf.len()` must be < `EEPROM_SIZE`; otherwise, this
-    /// function returns an error
-    fn write_buffer(&self, mut addr: u16, mut buf: &[u8]) -> Result<(), Error> {
+    /// `addr` and `addr + buf.len()` must be <= `EEPROM_SIZE`; otherwise,
+    /// this function returns an error
+    pub fn write_buffer(
+        &self,
+        mut addr: u16,
+        mut buf: &[u8],
+    ) -> Result<(), Error> {
         // Address validation
         if addr >= EEPROM_SIZE {
             return Err(Error::InvalidAddress(addr));
@@ -219,20 +254,19 @@ impl At24Csw080 {
             return Err(Error::InvalidEndAddress(end_addr));
         }
 
-        // Write single bytes until we reach an aligned address or run out
-        // of buffer data to write. Note that the datasheet says we need
-        // address bits A9-A3 to be the same for the write, but that doesn't
-        // make sense: if we can write 16 bytes, then A3 is by definition
-        // going to change. Instead, we look for an address that is aligned
-        // to a 16-byte boundary.
-        while (addr & 0b1111) != 0 && !buf.is_empty() {
-            self.write_byte(addr, buf[0])?;
-            buf = &buf[1..];
-            addr += 1;
-        }
-        for chunk in buf.chunks(16) {
+        // Write a page at a time, with the first write extending only to
+        // the end of the (potentially partial) first page.  Note that the
+        // datasheet says we need address bits A9-A3 to be the same for the
+        // write, but that doesn't make sense: if we can write 16 bytes, then
+        // A3 is by definition going to change. Instead, we assure that each
+        // write stays within a 16-byte page.
+        while !buf.is_empty() {
+            let room = (PAGE_SIZE - addr % PAGE_SIZE) as usize;
+            let (chunk, rest) = buf.split_at(room.min(buf.len()));
+
             self.write_page(addr, chunk)?;
             addr += chunk.len() as u16;
+            buf = rest;
         }
         Ok(())
     }
@@ -428,7 +462,7 @@ mod handle {
             assert!(addr < EEPROM_SIZE);
             let a_9_8 = ((addr >> 8) & 0b11) as u8;
             I2cDevice {
-                address: self.0.address | a_9_8,
+                address: self.address_with(a_9_8),
                 ..self.0
             }
         }
@@ -437,10 +471,20 @@ mod handle {
         /// and write protection registers.
         pub(super) fn registers(&self) -> I2cDevice {
             I2cDevice {
-                address: self.0.address | (1 << 3),
+                address: self.address_with(1 << 3),
                 ..self.0
             }
         }
+
+        /// Returns our address with the given address bits set.
+        fn address_with(&self, bits: u8) -> Address {
+            match self.0.target() {
+                Address::SevenBit(addr) => Address::SevenBit(addr | bits),
+                Address::TenBit(addr) => {
+                    Address::TenBit(addr | u16::from(bits))
+                }
+            }
+        }
     }
     impl core::fmt::Display for DeviceHandle {
         fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -25,7 +25,9 @@
 //! - [`pca9538`]: PCA9538 GPIO expander
 //! - [`pca9956b`]: PCA9956B LED driver
 //! - [`pct2075`]: PCT2075 temperature sensor
+//! - [`pmbus_util`]: PMBus status decoding and paged reads
 //! - [`raa229618`]: RAA229618 power controller
+//! - [`raa229620`]: RAA229620 power controller
 //! - [`sbrmi`]: AMD SB-RMI driver
 //! - [`sbtsi`]: AMD SB-TSI temperature sensor
 //! - [`tmp116`]: TMP116 temperature sensor
@@ -235,7 +237,9 @@ pub mod nvme_bmc;
 pub mod pca9538;
 pub mod pca9956b;
 pub mod pct2075;
+pub mod pmbus_util;
 pub mod raa229618;
+pub mod raa229620;
 pub mod sbrmi;
 pub mod sbtsi;
 pub mod tmp117;
@@ -15,7 +15,7 @@ use pmbus::commands::CommandCode;
 use pmbus::units::{Celsius, Rpm};
 use pmbus::*;
 use task_power_api::PmbusValue;
-use userlib::units::{Amperes, Volts};
+use userlib::units::{Amperes, Volts, Watts};
 
 pub struct Mwocp68 {
     device: I2cDevice,
@@ -350,6 +350,62 @@ impl Mwocp68 {
         Ok(val)
     }
 
+    /// Reads STATUS_WORD, which summarizes everything the PSU has to
+    /// complain about.
+    pub fn status_word(&self) -> Result<u16, Error> {
+        self.set_rail()?;
+        let (val, _) = pmbus_read!(self.device, STATUS_WORD)?.raw();
+        Ok(val as u16)
+    }
+
+    /// Reads STATUS_INPUT, which has the details of input faults.
+    pub fn status_input(&self) -> Result<u8, Error> {
+        self.set_rail()?;
+        let (val, _) = pmbus_read!(self.device, STATUS_INPUT)?.raw();
+        Ok(val as u8)
+    }
+
+    /// Clears the PSU's latched faults.
+    pub fn clear_faults(&self) -> Result<(), Error> {
+        let cmd = CommandCode::CLEAR_FAULTS as u8;
+        self.device
+            .write(&[cmd])
+            .map_err(|code| Error::BadWrite { cmd, code })
+    }
+
+    /// Turns the PSU's output on or off.
+    pub fn set_on(&self, on: bool) -> Result<(), Error> {
+        self.set_rail()?;
+        let mut op = pmbus_read!(self.device, OPERATION)?;
+        op.set_on_off_state(if on {
+            OPERATION::OnOffState::On
+        } else {
+            OPERATION::OnOffState::Off
+        });
+        pmbus_write!(self.device, OPERATION, op)
+    }
+
+    pub fn read_pin(&self) -> Result<Watts, Error> {
+        Ok(Watts(pmbus_read!(self.device, READ_PIN)?.get()?.0))
+    }
+
+    pub fn read_pout(&self) -> Result<Watts, Error> {
+        self.set_rail()?;
+        Ok(Watts(pmbus_read!(self.device, READ_POUT)?.get()?.0))
+    }
+
+    /// Reads the firmware version, which is the ASCII string in
+    /// MFR_REVISION, into `buf`, returning its length.
+    pub fn read_firmware_version(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let cmd = CommandCode::MFR_REVISION as u8;
+        self.device
+            .read_block(cmd, buf)
+            .map_err(|code| Error::BadRead { cmd, code })
+    }
+
     pub fn i2c_device(&self) -> &I2cDevice {
         &self.device
     }
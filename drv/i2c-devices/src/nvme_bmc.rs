@@ -3,7 +3,8 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::Validate;
-use drv_i2c_api::{I2cDevice, ResponseCode};
+use bitfield::bitfield;
+use drv_i2c_api::{Address, I2cDevice, ResponseCode};
 use userlib::units::Celsius;
 use zerocopy::{AsBytes, FromBytes};
 
@@ -16,6 +17,10 @@ pub enum Error {
     Reserved,
     InvalidLength,
     BadChecksum,
+
+    /// The drive is present, but indicates that it is not (yet) ready to
+    /// process management commands; its temperature is not valid.
+    DriveNotReady,
 }
 
 impl From<Error> for ResponseCode {
@@ -26,7 +31,8 @@ impl From<Error> for ResponseCode {
             | Error::SensorFailure
             | Error::Reserved
             | Error::InvalidLength
-            | Error::BadChecksum => ResponseCode::BadDeviceState,
+            | Error::BadChecksum
+            | Error::DriveNotReady => ResponseCode::BadDeviceState,
         }
     }
 }
@@ -49,11 +55,40 @@ pub struct DriveStatus {
     pec: u8,
 }
 
+bitfield! {
+    /// Status Flags (SFLGS) from the subsystem management data structure.
+    /// Note that the sense of the "not ready" bit is inverted relative to
+    /// the others, and that the reserved bits read as 1.
+    #[derive(Copy, Clone, Eq, PartialEq)]
+    pub struct StatusFlags(u8);
+    impl Debug;
+    pub smbus_arbitration, _: 7;
+    pub drive_not_ready, _: 6;
+    pub drive_functional, _: 5;
+    pub reset_not_required, _: 4;
+    pub pcie_link_active, _: 3;
+}
+
+impl DriveStatus {
+    pub fn flags(&self) -> StatusFlags {
+        StatusFlags(self.flags)
+    }
+
+    /// Returns the percentage of drive life used, where 255 indicates that
+    /// the drive has exceeded its rated life
+    pub fn drive_life_used(&self) -> u8 {
+        self.drive_life_used
+    }
+}
+
 impl NvmeBmc {
     pub fn new(device: &I2cDevice) -> Self {
         Self { device: *device }
     }
-    pub fn read_temperature(&self) -> Result<Celsius, Error> {
+
+    /// Reads and checks the subsystem management data structure, returning
+    /// it without interpretation of the temperature.
+    pub fn read_drive_status(&self) -> Result<DriveStatus, Error> {
         let v = self
             .device
             .read_reg::<u8, DriveStatus>(0)
@@ -64,15 +99,31 @@ impl NvmeBmc {
         }
 
         // Calculate the PEC, which is based on the entire SMBus transaction
+        // (and so on the 7-bit address that SMBus requires)
+        let Address::SevenBit(addr) = self.device.target() else {
+            return Err(Error::I2cError(ResponseCode::BadArg));
+        };
         let mut raw_buf: [u8; 10] = [0u8; 10];
-        raw_buf[0] = self.device.address << 1;
-        raw_buf[2] = (self.device.address << 1) | 1;
+        raw_buf[0] = addr << 1;
+        raw_buf[2] = (addr << 1) | 1;
         raw_buf[3..].copy_from_slice(&v.as_bytes()[..7]);
         let checksum = smbus_pec::pec(&raw_buf);
         if checksum != v.pec {
             return Err(Error::BadChecksum);
         }
 
+        Ok(v)
+    }
+
+    pub fn read_temperature(&self) -> Result<Celsius, Error> {
+        let v = self.read_drive_status()?;
+
+        // A drive that is still initializing (e.g. having just been inserted
+        // or powered) reports a temperature that we cannot use.
+        if v.flags().drive_not_ready() {
+            return Err(Error::DriveNotReady);
+        }
+
         // Again, see Figure 112 in "NVM Express Management Interface",
         // revision 1.0a, April 8, 2017
         match v.temperature {
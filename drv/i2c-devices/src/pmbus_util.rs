@@ -0,0 +1,133 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PMBus utility routines
+//!
+//! The `pmbus` crate describes commands and converts their data; this module
+//! holds what our PMBus drivers share beyond that: reading (optionally paged)
+//! words, and decoding of `STATUS_WORD`.
+
+use bitfield::bitfield;
+use drv_i2c_api::*;
+
+/// `PAGE`
+pub const PAGE: u8 = 0x00;
+
+/// `STATUS_WORD`
+pub const STATUS_WORD: u8 = 0x79;
+
+bitfield! {
+    ///
+    /// `STATUS_WORD`, as defined by PMBus Part II.  The low byte is
+    /// `STATUS_BYTE`.
+    ///
+    #[derive(Copy, Clone, Eq, PartialEq)]
+    pub struct StatusWord(u16);
+    impl Debug;
+    pub vout, _: 15;
+    pub iout_pout, _: 14;
+    pub input, _: 13;
+    pub mfr_specific, _: 12;
+    pub power_good_n, _: 11;
+    pub fans, _: 10;
+    pub other, _: 9;
+    pub unknown, _: 8;
+    pub busy, _: 7;
+    pub off, _: 6;
+    pub vout_ov_fault, _: 5;
+    pub iout_oc_fault, _: 4;
+    pub vin_uv_fault, _: 3;
+    pub temperature, _: 2;
+    pub cml, _: 1;
+    pub none_of_the_above, _: 0;
+}
+
+///
+/// A fault (or warning) class, as indicated by `STATUS_WORD`; for details,
+/// the corresponding `STATUS_*` command must be read.
+///
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Fault {
+    Vout,
+    IoutPout,
+    Input,
+    MfrSpecific,
+    PowerNotGood,
+    Fans,
+    Other,
+    Unknown,
+    Busy,
+    VoutOvFault,
+    IoutOcFault,
+    VinUvFault,
+    Temperature,
+    Cml,
+    NoneOfTheAbove,
+}
+
+impl StatusWord {
+    ///
+    /// Returns true if any fault or warning is indicated.  Note that `OFF`
+    /// is not itself a fault, and is therefore excluded.
+    ///
+    pub fn faulted(&self) -> bool {
+        self.faults().next().is_some()
+    }
+
+    ///
+    /// Returns an iterator over the faults indicated.
+    ///
+    pub fn faults(&self) -> impl Iterator<Item = Fault> {
+        let status = *self;
+
+        [
+            (status.vout(), Fault::Vout),
+            (status.iout_pout(), Fault::IoutPout),
+            (status.input(), Fault::Input),
+            (status.mfr_specific(), Fault::MfrSpecific),
+            (status.power_good_n(), Fault::PowerNotGood),
+            (status.fans(), Fault::Fans),
+            (status.other(), Fault::Other),
+            (status.unknown(), Fault::Unknown),
+            (status.busy(), Fault::Busy),
+            (status.vout_ov_fault(), Fault::VoutOvFault),
+            (status.iout_oc_fault(), Fault::IoutOcFault),
+            (status.vin_uv_fault(), Fault::VinUvFault),
+            (status.temperature(), Fault::Temperature),
+            (status.cml(), Fault::Cml),
+            (status.none_of_the_above(), Fault::NoneOfTheAbove),
+        ]
+        .into_iter()
+        .filter_map(|(set, fault)| if set { Some(fault) } else { None })
+    }
+}
+
+///
+/// Reads a (little-endian) word from the specified command, first selecting
+/// the specified page (if any).
+///
+pub fn read_word(
+    device: &I2cDevice,
+    page: Option<u8>,
+    cmd: u8,
+) -> Result<u16, ResponseCode> {
+    let raw = match page {
+        Some(page) => {
+            device.write_read_reg::<u8, [u8; 2]>(cmd, &[PAGE, page])?
+        }
+        None => device.read_reg::<u8, [u8; 2]>(cmd)?,
+    };
+
+    Ok(u16::from_le_bytes(raw))
+}
+
+///
+/// Reads and decodes `STATUS_WORD` for the specified page (if any).
+///
+pub fn read_status(
+    device: &I2cDevice,
+    page: Option<u8>,
+) -> Result<StatusWord, ResponseCode> {
+    Ok(StatusWord(read_word(device, page, STATUS_WORD)?))
+}
@@ -4,6 +4,7 @@
 
 use core::cell::Cell;
 
+use crate::pmbus_util::{self, StatusWord};
 use crate::{
     pmbus_validate, BadValidation, CurrentSensor, TempSensor, Validate,
     VoltageSensor,
@@ -125,6 +126,18 @@ impl Raa229618 {
         Ok(Amperes(iout.get()?.0))
     }
 
+    ///
+    /// Reads and decodes `STATUS_WORD` for our rail.
+    ///
+    pub fn read_status(&self) -> Result<StatusWord, Error> {
+        pmbus_util::read_status(&self.device, Some(self.rail)).map_err(|code| {
+            Error::BadRead {
+                cmd: pmbus_util::STATUS_WORD,
+                code,
+            }
+        })
+    }
+
     pub fn i2c_device(&self) -> &I2cDevice {
         &self.device
     }
@@ -63,11 +63,27 @@ impl Tmp117 {
     }
 
     pub fn read_eeprom(&self) -> Result<[u8; 6], Error> {
-        let ee1 = self.read_reg(Register::EEPROM1)?;
-        let ee2 = self.read_reg(Register::EEPROM2)?;
-        let ee3 = self.read_reg(Register::EEPROM3)?;
+        let regs = [
+            Register::EEPROM1 as u8,
+            Register::EEPROM2 as u8,
+            Register::EEPROM3 as u8,
+        ];
+        let mut ee = [[0u8; 2]; 3];
 
-        Ok([ee1.0, ee1.1, ee2.0, ee2.1, ee3.0, ee3.1])
+        //
+        // The EEPROM registers aren't contiguous (and the TMP117 doesn't
+        // auto-increment its pointer in any case), so we read them as a
+        // batch of register reads.
+        //
+        match self.device.read_regs(&regs, &mut ee) {
+            Ok(()) => {
+                Ok([ee[0][0], ee[0][1], ee[1][0], ee[1][1], ee[2][0], ee[2][1]])
+            }
+            Err(code) => Err(Error::BadRegisterRead {
+                reg: Register::EEPROM1,
+                code,
+            }),
+        }
     }
 }
 
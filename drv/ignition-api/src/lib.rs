@@ -51,6 +51,9 @@ pub enum IgnitionError {
     /// Indicates the given request conflicts with the Target system power
     /// state. Poll the Target state and retry if desired.
     RequestDiscarded,
+    /// Indicates a bulk request is already being rolled out. Poll its status,
+    /// or cancel it, before starting another.
+    BulkRequestInProgress,
 
     #[idol(server_death)]
     ServerDied,
@@ -123,6 +126,43 @@ impl Ignition {
         self.controller.send_request(port, request)
     }
 
+    /// Start sending the given system power `Request` to each port in the
+    /// `ports` bit vector, in ascending port order, waiting `interval_ms`
+    /// between ports. The rollout stops early once `max_failures` ports have
+    /// failed to accept the request (or never, if `max_failures` is zero).
+    /// This returns once the first port has been sent the request; follow the
+    /// rest of the rollout with `bulk_request_status`.
+    #[inline]
+    pub fn start_bulk_request(
+        &self,
+        ports: u64,
+        request: Request,
+        interval_ms: u32,
+        max_failures: u8,
+    ) -> Result<(), IgnitionError> {
+        self.controller.start_bulk_request(
+            ports,
+            request,
+            interval_ms,
+            max_failures,
+        )
+    }
+
+    /// Return the progress of the current (or most recent) bulk request.
+    #[inline]
+    pub fn bulk_request_status(
+        &self,
+    ) -> Result<BulkRequestStatus, IgnitionError> {
+        self.controller.bulk_request_status()
+    }
+
+    /// Stop rolling out the current bulk request. Ports which have already
+    /// been sent the request are unaffected.
+    #[inline]
+    pub fn cancel_bulk_request(&self) -> Result<(), IgnitionError> {
+        self.controller.cancel_bulk_request()
+    }
+
     /// Return the `Counters` for the given port. This function has the
     /// side-effect of clearing the counters.
     #[inline]
@@ -159,6 +199,19 @@ impl Ignition {
         self.controller.link_events(port).map(LinkEvents::from)
     }
 
+    /// Return the `PortHistory` for the given port, as collected by the server
+    /// since it started or since the history was last cleared.
+    #[inline]
+    pub fn port_history(&self, port: u8) -> Result<PortHistory, IgnitionError> {
+        self.controller.port_history(port)
+    }
+
+    /// Clear the `PortHistory` for the given port.
+    #[inline]
+    pub fn clear_port_history(&self, port: u8) -> Result<(), IgnitionError> {
+        self.controller.clear_port_history(port)
+    }
+
     /// Fetch the state of all ports in a single operation and return an
     /// iterator over the individual ports. Be aware that this reply is fairly
     /// large and may require enlarging the stack of the caller.
@@ -419,6 +472,17 @@ impl From<u8> for SystemFaults {
     }
 }
 
+impl From<SystemFaults> for u8 {
+    fn from(faults: SystemFaults) -> u8 {
+        use Reg::TARGET_SYSTEM_FAULTS::*;
+
+        0u8 | if faults.power_a3 { POWER_FAULT_A3 } else { 0 }
+            | if faults.power_a2 { POWER_FAULT_A2 } else { 0 }
+            | if faults.rot { ROT_FAULT } else { 0 }
+            | if faults.sp { SP_FAULT } else { 0 }
+    }
+}
+
 /// A numeric id identifying a major type of system. This allows differentiating
 /// between different types of compute, network and power elements but not
 /// different minor revisions of the same systems.
@@ -632,6 +696,244 @@ impl From<[u8; 3]> for LinkEvents {
     }
 }
 
+/// The kind of a `PortEvent`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u8)]
+pub enum PortEventKind {
+    /// A Target became present on the port.
+    TargetArrived = 1,
+    /// The Target on the port went away.
+    TargetDeparted = 2,
+    /// The Target reported new system faults. `PortEvent::data` holds the
+    /// complete set of faults, see `SystemFaults`.
+    TargetFaults = 3,
+    /// New events were observed by a transceiver. `PortEvent::txr` selects the
+    /// transceiver and `PortEvent::data` holds the newly observed events, see
+    /// `TransceiverEvents`.
+    TransceiverEvents = 4,
+}
+
+/// An entry in the event history of a port.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, AsBytes, FromBytes, Serialize,
+)]
+#[repr(C)]
+pub struct PortEvent {
+    /// Time at which the event was observed, in seconds since the Ignition
+    /// server started.
+    pub timestamp: u32,
+    /// The raw `PortEventKind`, or zero if this entry is unused.
+    pub kind: u8,
+    /// The raw `TransceiverSelect` for `TransceiverEvents` events, zero
+    /// otherwise.
+    pub txr: u8,
+    /// Event specific data, see `PortEventKind`.
+    pub data: u8,
+    _pad: u8,
+}
+
+impl PortEvent {
+    pub fn new(timestamp: u32, kind: PortEventKind, txr: u8, data: u8) -> Self {
+        Self {
+            timestamp,
+            kind: kind as u8,
+            txr,
+            data,
+            _pad: 0,
+        }
+    }
+
+    /// Return the kind of the event, or `None` for an unused entry.
+    pub fn kind(&self) -> Option<PortEventKind> {
+        PortEventKind::from_u8(self.kind)
+    }
+}
+
+/// Number of events retained in a `PortHistory`.
+pub const PORT_HISTORY_DEPTH: usize = 4;
+
+/// `PortHistory` holds the events and counters collected for a port by the
+/// Ignition server while polling the Controller. Unlike `Counters` and
+/// `TransceiverEvents` it is not affected by reading or clearing either of
+/// those, allowing flaky links to be diagnosed after the fact.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, AsBytes, FromBytes, Serialize,
+)]
+#[repr(C)]
+pub struct PortHistory {
+    /// The number of times a Target became present.
+    pub target_arrivals: u16,
+    /// The number of times a Target went away.
+    pub target_departures: u16,
+    /// The number of times the Target reported new system faults.
+    pub fault_events: u16,
+    /// The number of times new transceiver events were observed.
+    pub transceiver_events: u16,
+    /// The total number of Status messages received from the Target.
+    pub status_received: u32,
+    /// The total number of Hello messages sent by the Controller. A high count
+    /// relative to `status_received` indicates a link which repeatedly needs
+    /// to be re-established.
+    pub hello_sent: u32,
+    /// The total number of requests sent by the Controller.
+    pub request_sent: u32,
+    /// The total number of messages dropped by the Controller.
+    pub message_dropped: u32,
+    /// The most recent events, most recent first.
+    pub events: [PortEvent; PORT_HISTORY_DEPTH],
+}
+
+impl PortHistory {
+    /// Record the given event, discarding the oldest event if needed.
+    pub fn record(&mut self, event: PortEvent) {
+        self.events.copy_within(..PORT_HISTORY_DEPTH - 1, 1);
+        self.events[0] = event;
+
+        match event.kind() {
+            Some(PortEventKind::TargetArrived) => {
+                self.target_arrivals = self.target_arrivals.saturating_add(1)
+            }
+            Some(PortEventKind::TargetDeparted) => {
+                self.target_departures =
+                    self.target_departures.saturating_add(1)
+            }
+            Some(PortEventKind::TargetFaults) => {
+                self.fault_events = self.fault_events.saturating_add(1)
+            }
+            Some(PortEventKind::TransceiverEvents) => {
+                self.transceiver_events =
+                    self.transceiver_events.saturating_add(1)
+            }
+            None => (),
+        }
+    }
+
+    /// Accumulate the given `Counters`, as read from the Controller, into the
+    /// totals.
+    pub fn accumulate(&mut self, counters: &Counters) {
+        self.status_received = self
+            .status_received
+            .saturating_add(counters.status_received.into());
+        self.hello_sent =
+            self.hello_sent.saturating_add(counters.hello_sent.into());
+        self.request_sent = self
+            .request_sent
+            .saturating_add(counters.request_sent.into());
+        self.message_dropped = self
+            .message_dropped
+            .saturating_add(counters.message_dropped.into());
+    }
+
+    /// Return an iterator over the recorded events, most recent first.
+    pub fn events(&self) -> impl Iterator<Item = &PortEvent> {
+        self.events.iter().filter(|e| e.kind().is_some())
+    }
+}
+
+/// The state of a bulk request rollout, see `Ignition::start_bulk_request`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u8)]
+pub enum BulkRequestState {
+    /// No bulk request has been started.
+    Idle = 0,
+    /// The request is still being sent to ports.
+    InProgress = 1,
+    /// The request has been sent to every port.
+    Complete = 2,
+    /// The rollout was stopped after `max_failures` ports failed.
+    Stopped = 3,
+    /// The rollout was cancelled.
+    Cancelled = 4,
+}
+
+/// The progress of a bulk request. Each port in the request is in exactly one
+/// of `pending`, `succeeded` or `failed`, unless the rollout ended early, in
+/// which case the ports it never got to remain in `pending`.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, AsBytes, FromBytes, Serialize,
+)]
+#[repr(C)]
+pub struct BulkRequestStatus {
+    /// Ports yet to be sent the request.
+    pub pending: u64,
+    /// Ports which accepted the request.
+    pub succeeded: u64,
+    /// Ports which failed to accept the request, or had no Target present.
+    pub failed: u64,
+    /// Time between ports, in milliseconds.
+    pub interval_ms: u32,
+    /// The raw `Request`, or zero if no bulk request has been started.
+    pub request: u8,
+    /// The raw `BulkRequestState`.
+    pub state: u8,
+    /// The number of failed ports at which the rollout stops, or zero if it
+    /// never does.
+    pub max_failures: u8,
+    _pad: u8,
+}
+
+impl BulkRequestStatus {
+    pub fn new(
+        ports: u64,
+        request: Request,
+        interval_ms: u32,
+        max_failures: u8,
+    ) -> Self {
+        Self {
+            pending: ports,
+            succeeded: 0,
+            failed: 0,
+            interval_ms,
+            request: request.into(),
+            state: BulkRequestState::InProgress as u8,
+            max_failures,
+            _pad: 0,
+        }
+    }
+
+    /// Return the request, or `None` if no bulk request has been started.
+    pub fn request(&self) -> Option<Request> {
+        Request::from_u8(self.request)
+    }
+
+    pub fn state(&self) -> BulkRequestState {
+        BulkRequestState::from_u8(self.state).unwrap_or(BulkRequestState::Idle)
+    }
+
+    pub fn set_state(&mut self, state: BulkRequestState) {
+        self.state = state as u8;
+    }
+
+    /// Return the next port to be sent the request, if any.
+    pub fn next_port(&self) -> Option<u8> {
+        if self.state() == BulkRequestState::InProgress && self.pending != 0 {
+            Some(self.pending.trailing_zeros() as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Record the outcome for the given port, updating the state of the
+    /// rollout.
+    pub fn record(&mut self, port: u8, success: bool) {
+        let mask = 1 << port;
+        self.pending &= !mask;
+        if success {
+            self.succeeded |= mask;
+        } else {
+            self.failed |= mask;
+        }
+
+        if self.max_failures != 0
+            && self.failed.count_ones() >= u32::from(self.max_failures)
+        {
+            self.set_state(BulkRequestState::Stopped);
+        } else if self.pending == 0 {
+            self.set_state(BulkRequestState::Complete);
+        }
+    }
+}
+
 /// A flattened struct representing the state of a port which can be
 /// reconstructed by Humility from a ssmarshal encoded buffer using DWARF
 /// information.
@@ -714,7 +1016,8 @@ const_assert!(
 
 mod idl {
     use super::{
-        Counters, IgnitionError, PortState, Request, TransceiverSelect,
+        Counters, IgnitionError, PortHistory, PortState, Request,
+        TransceiverSelect,
     };
     use userlib::sys_send;
 
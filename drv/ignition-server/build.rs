@@ -5,7 +5,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/ignition.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
@@ -27,8 +27,13 @@ enum Trace {
     TargetError(u8, IgnitionError),
     TargetArrive(u8),
     TargetDepart(u8),
+    TargetFaults(u8, u8),
+    TransceiverEvents(u8, TransceiverSelect, u8),
     SystemPowerRequest(u8, Request),
     SystemPowerRequestError(u8, IgnitionError),
+    BulkRequestStart(u64, Request),
+    BulkRequestPort(u8, Request),
+    BulkRequestEnd(BulkRequestState),
 }
 ringbuf!(Trace, 16, Trace::None);
 
@@ -41,6 +46,13 @@ fn main() -> ! {
         controller: IgnitionController::new(FPGA.get_task_id()),
         port_count: 0,
         last_presence_summary: 0,
+        history: [Default::default(); PORT_MAX as usize],
+        unreported_counters: [Default::default(); PORT_MAX as usize],
+        last_faults: [0; PORT_MAX as usize],
+        last_transceiver_events: [[0; 3]; PORT_MAX as usize],
+        poll_deadline: 0,
+        bulk: Default::default(),
+        bulk_deadline: 0,
     };
 
     // This task is expected to run in an environment where a sequencer is
@@ -79,6 +91,36 @@ struct ServerImpl {
     controller: IgnitionController,
     port_count: u8,
     last_presence_summary: u64,
+
+    /// Per port event history and accumulated counters.
+    history: [PortHistory; PORT_MAX as usize],
+    /// Counters read from the Controller (which clears them) while polling,
+    /// but not yet returned to a client through the `counters` op.
+    unreported_counters: [Counters; PORT_MAX as usize],
+    /// Target faults as of the last poll, used to detect new faults.
+    last_faults: [u8; PORT_MAX as usize],
+    /// Transceiver events as of the last poll, used to detect new events.
+    last_transceiver_events: [[u8; 3]; PORT_MAX as usize],
+    /// When the Controller is next due to be polled.
+    poll_deadline: u64,
+    /// The current (or most recent) bulk request.
+    bulk: BulkRequestStatus,
+    /// When the next port is due to be sent the bulk request.
+    bulk_deadline: u64,
+}
+
+/// Return the time in seconds since boot, as used in `PortEvent`s.
+fn timestamp() -> u32 {
+    (sys_get_timer().now / 1000) as u32
+}
+
+fn saturating_add_counters(a: Counters, b: Counters) -> Counters {
+    Counters {
+        status_received: a.status_received.saturating_add(b.status_received),
+        hello_sent: a.hello_sent.saturating_add(b.hello_sent),
+        request_sent: a.request_sent.saturating_add(b.request_sent),
+        message_dropped: a.message_dropped.saturating_add(b.message_dropped),
+    }
 }
 
 impl ServerImpl {
@@ -114,12 +156,114 @@ impl ServerImpl {
             self.last_presence_summary = arrived_targets
                 | (self.last_presence_summary & !departed_targets);
 
+            self.record_presence_changes(arrived_targets, departed_targets);
+
             ringbuf_entry!(Trace::PresenceUpdate(self.last_presence_summary));
         }
 
         Ok(())
     }
 
+    /// Record the arrival and departure of Targets in the port history.
+    fn record_presence_changes(&mut self, arrived: u64, departed: u64) {
+        let now = timestamp();
+
+        for port in 0..self.port_count.min(PORT_MAX) {
+            let mask = 1 << port;
+            let i = usize::from(port);
+
+            if arrived & mask != 0 {
+                // The counters were cleared on arrival and any events or
+                // faults are those of the newly arrived Target.
+                self.unreported_counters[i] = Default::default();
+                self.last_faults[i] = 0;
+                self.last_transceiver_events[i] = [0; 3];
+                self.history[i].record(PortEvent::new(
+                    now,
+                    PortEventKind::TargetArrived,
+                    0,
+                    0,
+                ));
+            }
+            if departed & mask != 0 {
+                self.history[i].record(PortEvent::new(
+                    now,
+                    PortEventKind::TargetDeparted,
+                    0,
+                    0,
+                ));
+            }
+        }
+    }
+
+    /// Poll the faults, counters and transceiver events of each port with a
+    /// Target present, recording any changes in the port history.
+    fn poll_history(&mut self) {
+        for port in 0..self.port_count.min(PORT_MAX) {
+            if self.last_presence_summary & (1 << port) != 0 {
+                if let Err(e) = self.poll_port_history(port) {
+                    ringbuf_entry!(Trace::TargetError(port, e));
+                }
+            }
+        }
+    }
+
+    fn poll_port_history(&mut self, port: u8) -> Result<(), IgnitionError> {
+        let now = timestamp();
+        let i = usize::from(port);
+
+        if let Ok(target) = self.target(port) {
+            let faults = u8::from(target.faults);
+
+            if faults & !self.last_faults[i] != 0 {
+                ringbuf_entry!(Trace::TargetFaults(port, faults));
+                self.history[i].record(PortEvent::new(
+                    now,
+                    PortEventKind::TargetFaults,
+                    0,
+                    faults,
+                ));
+            }
+            self.last_faults[i] = faults;
+        }
+
+        let counters = self.accumulate_counters(port)?;
+        self.unreported_counters[i] =
+            saturating_add_counters(self.unreported_counters[i], counters);
+
+        for (j, txr) in TransceiverSelect::ALL.into_iter().enumerate() {
+            let events = self
+                .controller
+                .transceiver_events(port, txr)
+                .map_err(IgnitionError::from)?;
+            let new_events = events & !self.last_transceiver_events[i][j];
+
+            if new_events != 0 {
+                ringbuf_entry!(Trace::TransceiverEvents(port, txr, new_events));
+                self.history[i].record(PortEvent::new(
+                    now,
+                    PortEventKind::TransceiverEvents,
+                    txr as u8,
+                    new_events,
+                ));
+            }
+            self.last_transceiver_events[i][j] = events;
+        }
+
+        Ok(())
+    }
+
+    /// Read (and thereby clear) the counters of the given port, accumulating
+    /// them in the port history.
+    fn accumulate_counters(
+        &mut self,
+        port: u8,
+    ) -> Result<Counters, IgnitionError> {
+        let counters = self.controller.counters(port)?;
+        self.history[usize::from(port)].accumulate(&counters);
+        Ok(counters)
+    }
+
     /// Apply the given function to each port for which a bit in the `ports`
     /// vector is set. Returns a bit vector with bits set for ports for which
     /// the operation was succesful. Under normal circumstances this output
@@ -218,6 +362,46 @@ impl ServerImpl {
             _ => Err(IgnitionError::RequestDiscarded),
         }
     }
+
+    /// Send the bulk request to each port which is due for it.
+    fn step_bulk_request(&mut self) {
+        if self.bulk.state() != BulkRequestState::InProgress {
+            return;
+        }
+
+        while let Some(port) = self.bulk.next_port() {
+            let now = sys_get_timer().now;
+            if now < self.bulk_deadline {
+                break;
+            }
+
+            // A bulk request in progress always has a valid request.
+            let request = self.bulk.request().unwrap_lite();
+            ringbuf_entry!(Trace::BulkRequestPort(port, request));
+
+            let result = self.target_request(port, request);
+            if let Err(e) = result {
+                ringbuf_entry!(Trace::SystemPowerRequestError(port, e));
+            }
+
+            self.bulk.record(port, result.is_ok());
+            self.bulk_deadline = now + u64::from(self.bulk.interval_ms);
+        }
+
+        if self.bulk.state() != BulkRequestState::InProgress {
+            ringbuf_entry!(Trace::BulkRequestEnd(self.bulk.state()));
+        }
+    }
+
+    /// Set the timer for whichever is due first of polling the Controller and
+    /// sending the bulk request to the next port.
+    fn set_timer(&self) {
+        let deadline = match self.bulk.next_port() {
+            Some(_) => self.poll_deadline.min(self.bulk_deadline),
+            None => self.poll_deadline,
+        };
+        sys_set_timer(Some(deadline), notifications::TIMER_MASK);
+    }
 }
 
 type RequestError = idol_runtime::RequestError<IgnitionError>;
@@ -261,14 +445,20 @@ impl idl::InOrderIgnitionImpl for ServerImpl {
         _: &userlib::RecvMessage,
         port: u8,
     ) -> Result<Counters, RequestError> {
-        if port >= self.port_count {
+        if port >= self.port_count.min(PORT_MAX) {
             return Err(RequestError::from(IgnitionError::InvalidPort));
         }
 
-        self.controller
-            .counters(port)
-            .map_err(IgnitionError::from)
-            .map_err(RequestError::from)
+        // The server reads the counters while polling, clearing them in the
+        // Controller. Include anything read since the last call to preserve
+        // the read-to-clear semantics for clients.
+        let i = usize::from(port);
+        let counters = self.accumulate_counters(port)?;
+        let counters =
+            saturating_add_counters(self.unreported_counters[i], counters);
+        self.unreported_counters[i] = Default::default();
+
+        Ok(counters)
     }
 
     fn transceiver_events(
@@ -323,6 +513,31 @@ impl idl::InOrderIgnitionImpl for ServerImpl {
         Ok(events)
     }
 
+    fn port_history(
+        &mut self,
+        _: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<PortHistory, RequestError> {
+        if port >= self.port_count.min(PORT_MAX) {
+            return Err(RequestError::from(IgnitionError::InvalidPort));
+        }
+
+        Ok(self.history[usize::from(port)])
+    }
+
+    fn clear_port_history(
+        &mut self,
+        _: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<(), RequestError> {
+        if port >= self.port_count.min(PORT_MAX) {
+            return Err(RequestError::from(IgnitionError::InvalidPort));
+        }
+
+        self.history[usize::from(port)] = Default::default();
+        Ok(())
+    }
+
     fn send_request(
         &mut self,
         _: &userlib::RecvMessage,
@@ -341,6 +556,57 @@ impl idl::InOrderIgnitionImpl for ServerImpl {
         })
     }
 
+    fn start_bulk_request(
+        &mut self,
+        _: &userlib::RecvMessage,
+        ports: u64,
+        request: Request,
+        interval_ms: u32,
+        max_failures: u8,
+    ) -> Result<(), RequestError> {
+        if self.bulk.state() == BulkRequestState::InProgress {
+            return Err(RequestError::from(
+                IgnitionError::BulkRequestInProgress,
+            ));
+        }
+
+        let valid_ports = (1u64 << self.port_count.min(PORT_MAX)) - 1;
+        if ports == 0 {
+            return Err(RequestError::from(IgnitionError::InvalidValue));
+        }
+        if ports & !valid_ports != 0 {
+            return Err(RequestError::from(IgnitionError::InvalidPort));
+        }
+
+        ringbuf_entry!(Trace::BulkRequestStart(ports, request));
+
+        self.bulk =
+            BulkRequestStatus::new(ports, request, interval_ms, max_failures);
+        self.bulk_deadline = sys_get_timer().now;
+        self.step_bulk_request();
+        self.set_timer();
+
+        Ok(())
+    }
+
+    fn bulk_request_status(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<BulkRequestStatus, RequestError> {
+        Ok(self.bulk)
+    }
+
+    fn cancel_bulk_request(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<(), RequestError> {
+        if self.bulk.state() == BulkRequestState::InProgress {
+            self.bulk.set_state(BulkRequestState::Cancelled);
+            ringbuf_entry!(Trace::BulkRequestEnd(self.bulk.state()));
+        }
+        Ok(())
+    }
+
     fn all_port_state(
         &mut self,
         _: &userlib::RecvMessage,
@@ -379,28 +645,37 @@ impl idol_runtime::NotificationHandler for ServerImpl {
     fn handle_notification(&mut self, _bits: u32) {
         let start = sys_get_timer().now;
 
-        // Only poll the presence summary if the port count seems reasonable. A
-        // count of 0xff may occur if the FPGA is running an incorrect
-        // bitstream.
-        if self.port_count > 0 && self.port_count != 0xff {
-            if let Err(e) = self.poll_presence() {
-                ringbuf_entry!(Trace::PresencePollError(e));
+        // The timer may have fired for the bulk request rather than for
+        // polling.
+        if start >= self.poll_deadline {
+            // Only poll the presence summary if the port count seems
+            // reasonable. A count of 0xff may occur if the FPGA is running an
+            // incorrect bitstream.
+            if self.port_count > 0 && self.port_count != 0xff {
+                if let Err(e) = self.poll_presence() {
+                    ringbuf_entry!(Trace::PresencePollError(e));
+                }
+
+                self.poll_history();
             }
-        }
 
-        let finish = sys_get_timer().now;
+            let finish = sys_get_timer().now;
 
-        // We now know when we were notified and when any work was completed.
-        // Note that the assumption here is that `start` < `finish` and that
-        // this won't hold if the system time rolls over. But, the system timer
-        // is a u64, with each bit representing a ms, so in practice this should
-        // be fine. Anyway, armed with this information, find the next deadline
-        // some multiple of `TIMER_INTERVAL` in the future.
+            // We now know when we were notified and when any work was
+            // completed. Note that the assumption here is that `start` <
+            // `finish` and that this won't hold if the system time rolls over.
+            // But, the system timer is a u64, with each bit representing a ms,
+            // so in practice this should be fine. Anyway, armed with this
+            // information, find the next deadline some multiple of
+            // `TIMER_INTERVAL` in the future.
 
-        let delta = finish - start;
-        let next_deadline = finish + TIMER_INTERVAL - (delta % TIMER_INTERVAL);
+            let delta = finish - start;
+            self.poll_deadline =
+                finish + TIMER_INTERVAL - (delta % TIMER_INTERVAL);
+        }
 
-        sys_set_timer(Some(next_deadline), notifications::TIMER_MASK);
+        self.step_bulk_request();
+        self.set_timer();
     }
 }
 
@@ -16,6 +16,12 @@ pub use registers::{MIBCounter, Register};
 pub enum Error {
     SpiError(SpiError),
     WrongChipId(u16),
+    /// Every slot in the VLAN table is already in use
+    VLanTableFull,
+    /// The port is in 100BASE-FX mode, so there's no cable to test
+    NotCopper,
+    /// LinkMD didn't finish in time
+    LinkMdTimeout,
 }
 
 impl From<SpiError> for Error {
@@ -25,22 +31,50 @@ impl From<SpiError> for Error {
 }
 
 pub enum VLanMode {
-    /// Configure VLAN tags 0x301 and 0x302 for (upstream) ports 1 and 2
-    /// respectively.  Allow untagged frames on any port, but drop tagged
-    /// frames with an _incorrect_ tag.  Do not use any VLAN tags on port 3
-    /// (the downstream port to the SP).
+    /// Configure the given VLANs (by default, 0x301 and 0x302 for upstream
+    /// ports 1 and 2 respectively).  Allow untagged frames on any port, but
+    /// drop tagged frames with an _incorrect_ tag.  Do not use any VLAN tags
+    /// on port 3 (the downstream port to the SP).
     Optional,
 
-    /// Require VLAN tags on port 3.  Frames tagged with 0x301/0x302 are sent
-    /// to ports 1 and 2 respectively; the tag is stripped before egress.
+    /// Require VLAN tags on port 3.  Frames tagged with one of the given
+    /// VLANs are sent to the upstream ports carrying it; the tag is stripped
+    /// before egress, unless the port carries more than one VLAN.
     ///
-    /// Reject tagged frames on ingress into ports 1 and 2.
+    /// Reject tagged frames on ingress into ports 1 and 2, unless they carry
+    /// more than one VLAN.
     Mandatory,
 
     /// Don't do any configuration of the VLANs.
     Off,
 }
 
+/// A VLAN to set up in [`Ksz8463::configure`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VLan {
+    pub vid: u16,
+    /// Upstream ports carrying the VLAN, with port 1 in bit 0.  Port 3 (the
+    /// downstream port to the SP) carries every VLAN.
+    pub ports: u8,
+}
+
+/// The VLANs we use unless told otherwise: 0x301 on port 1, and 0x302 on
+/// port 2
+pub const DEFAULT_VLANS: [VLan; 2] = [
+    VLan {
+        vid: 0x301,
+        ports: 0b01,
+    },
+    VLan {
+        vid: 0x302,
+        ports: 0b10,
+    },
+];
+
+/// Tag given to frames that we don't want, so that they're dropped (or, in
+/// `VLanMode::Optional`, passed through untagged)
+const DROP_VID: u16 = 0x3FF;
+
 pub enum Mode {
     /// 10/100BASE-TX mode
     Copper,
@@ -59,6 +93,21 @@ enum Trace {
 }
 ringbuf!(Trace, 16, Trace::None);
 
+/// Changes to VLAN membership made after `configure`, kept apart from the
+/// register trace so that they aren't lost among everyday reads.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum VLanChange {
+    None,
+    Membership {
+        vid: u16,
+        table_entry: u8,
+        /// Port mask before the change, or `None` for a new entry
+        old: Option<u8>,
+        new: u8,
+    },
+}
+ringbuf!(VLAN_RINGBUF, VLanChange, 16, VLanChange::None);
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Data from a management information base (MIB) counter on the chip,
@@ -101,6 +150,33 @@ pub struct KszRawMacTableEntry {
     pub addr: [u8; 6],
 }
 
+/// Result of a LinkMD cable test
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CableStatus {
+    Normal,
+    Open,
+    Short,
+    /// The test itself failed, e.g. because the link partner was transmitting
+    Failed,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CableDiagnostics {
+    pub status: CableStatus,
+    /// Estimated distance to an open or short, in decimetres
+    pub distance_dm: u16,
+}
+
+/// A valid entry in the VLAN table
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VLanEntry {
+    pub vid: u16,
+    /// Member ports, with port 1 in bit 0
+    pub port_mask: u8,
+    /// Filter ID
+    pub fid: u8,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct Ksz8463<S: SpiServer> {
@@ -322,11 +398,198 @@ impl<S: SpiServer> Ksz8463<S> {
         self.write(Register::IACR, 0x400 | u16::from(table_entry))
     }
 
-    /// Configures the KSZ8463 switch in 100BASE-FX mode.
+    /// Reads an entry from the VLAN table, returning `None` if it's not
+    /// valid.  `table_entry` must be <= 15, otherwise this will panic.
+    pub fn read_vlan_table(
+        &self,
+        table_entry: u8,
+    ) -> Result<Option<VLanEntry>, Error> {
+        assert!(table_entry <= 15);
+        self.write(Register::IACR, 0x1400 | u16::from(table_entry))?;
+        let d_31_16 = self.read(Register::IADR5)?;
+        let d_15_0 = self.read(Register::IADR4)?;
+
+        let valid = (d_31_16 & (1 << 3)) != 0;
+        if !valid {
+            return Ok(None);
+        }
+        Ok(Some(VLanEntry {
+            vid: d_15_0 & 0xFFF,
+            port_mask: (d_31_16 & 0b111) as u8,
+            fid: (d_15_0 >> 12) as u8,
+        }))
+    }
+
+    /// Adds `port` (1-3) to the VLAN with tag `vid`, or removes it, updating
+    /// that VLAN's table entry.  A VLAN that isn't in the table yet gets the
+    /// first free slot (and the FID to go with it); one that loses all its
+    /// ports keeps its entry, so that frames tagged for it are still dropped
+    /// rather than treated as unknown.
+    ///
+    /// This panics if `port` or `vid` are out of range.
+    pub fn set_vlan_membership(
+        &self,
+        port: u8,
+        vid: u16,
+        member: bool,
+    ) -> Result<(), Error> {
+        assert!((1..=3).contains(&port));
+        assert!(vid <= 0xFFF);
+        let bit = 1 << (port - 1);
+
+        let mut free = None;
+        let mut found = None;
+        for i in 0..16 {
+            match self.read_vlan_table(i)? {
+                Some(e) if e.vid == vid => {
+                    found = Some((i, e.port_mask));
+                    break;
+                }
+                Some(_) => (),
+                None => {
+                    free.get_or_insert(i);
+                }
+            }
+        }
+
+        let (table_entry, old) = match (found, member) {
+            (Some((i, mask)), _) => (i, Some(mask)),
+            // Removing a port from a VLAN that isn't there is a no-op
+            (None, false) => return Ok(()),
+            (None, true) => (free.ok_or(Error::VLanTableFull)?, None),
+        };
+        let new = match (old, member) {
+            (Some(mask), true) => mask | bit,
+            (Some(mask), false) => mask & !bit,
+            (None, _) => bit,
+        };
+        if old != Some(new) {
+            self.write_vlan_table(table_entry, new, vid)?;
+            ringbuf_entry!(
+                VLAN_RINGBUF,
+                VLanChange::Membership {
+                    vid,
+                    table_entry,
+                    old,
+                    new,
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks whether `port` (1 or 2, the ports with PHYs) has link.
+    pub fn port_link_up(&self, port: u8) -> Result<bool, Error> {
+        assert!((1..=2).contains(&port));
+        Ok(self.read(Register::PxMBSR(port))? & (1 << 2) != 0)
+    }
+
+    /// Reads the default VLAN tag given to untagged frames arriving on
+    /// `port` (1-3).
+    pub fn port_default_vid(&self, port: u8) -> Result<u16, Error> {
+        let reg = match port {
+            1 => Register::P1VIDCR,
+            2 => Register::P2VIDCR,
+            3 => Register::P3VIDCR,
+            _ => panic!("Invalid port"),
+        };
+        Ok(self.read(reg)? & 0xFFF)
+    }
+
+    /// Runs a LinkMD (time-domain reflectometry) test on the cable attached
+    /// to `port` (1 or 2), which must be in copper mode.  This takes the link
+    /// down while it runs, and brings it back afterwards.
+    pub fn cable_diagnostics(
+        &self,
+        port: u8,
+    ) -> Result<CableDiagnostics, Error> {
+        assert!((1..=2).contains(&port));
+
+        // `configure` clears these bits (one per port) for 100BASE-FX
+        if self.read(Register::CFGR)? & (1 << (5 + port)) == 0 {
+            return Err(Error::NotCopper);
+        }
+        let (lmd, cr4) = match port {
+            1 => (Register::P1SCSLMD, Register::P1CR4),
+            _ => (Register::P2SCSLMD, Register::P2CR4),
+        };
+
+        // The pulse must go out on a known pair, so turn off auto
+        // MDI/MDI-X while the test runs, then start it.
+        let old_cr4 = self.read(cr4)?;
+        self.write(cr4, old_cr4 | (1 << 10))?;
+        self.modify(lmd, |r| *r |= 1 << 12)?;
+
+        let mut result = Err(Error::LinkMdTimeout);
+        for _ in 0..100 {
+            let v = self.read(lmd)?;
+            if v & (1 << 12) == 0 {
+                result = Ok(v);
+                break;
+            }
+            sleep_for(1);
+        }
+        self.write(cr4, old_cr4)?;
+        let v = result?;
+
+        let status = match (v >> 13) & 0b11 {
+            0b00 => CableStatus::Normal,
+            0b01 => CableStatus::Open,
+            0b10 => CableStatus::Short,
+            _ => CableStatus::Failed,
+        };
+        // The datasheet gives the distance as 0.4 m × (count - 26)
+        let count = v & 0x1FF;
+        Ok(CableDiagnostics {
+            status,
+            distance_dm: 4 * count.saturating_sub(26),
+        })
+    }
+
+    /// Writes `vlans` to the VLAN table, each including port 3, followed by
+    /// the VLAN for unwanted frames (tag 0x3FF), with ports `drop_ports`.
+    /// All other slots in the table are disabled (in particular, VLAN with
+    /// VID 1, which by default includes all ports).
+    ///
+    /// Each upstream port's default tag is that of the first VLAN it
+    /// carries, or 0x3FF if it carries none; port 3's is 0x3FF.
+    ///
+    /// This uses slots `0..=vlans.len()` in the table, and the FID to match
+    /// each slot.  It panics if `vlans` doesn't leave a slot spare.
+    fn configure_vlan_table(
+        &self,
+        vlans: &[VLan],
+        drop_ports: u8,
+    ) -> Result<(), Error> {
+        assert!(vlans.len() < 16);
+        for (i, v) in vlans.iter().enumerate() {
+            self.write_vlan_table(i as u8, v.ports | 0b100, v.vid)?;
+        }
+        let n = vlans.len() as u8;
+        self.write_vlan_table(n, drop_ports, DROP_VID)?;
+        for i in n + 1..16 {
+            self.disable_vlan(i)?;
+        }
+
+        // Assign default VLAN tags to each port
+        for (port, reg) in [(1, Register::P1VIDCR), (2, Register::P2VIDCR)] {
+            let vid = vlans
+                .iter()
+                .find(|v| v.ports & (1 << (port - 1)) != 0)
+                .map(|v| v.vid)
+                .unwrap_or(DROP_VID);
+            self.write(reg, vid)?;
+        }
+        self.write(Register::P3VIDCR, DROP_VID)
+    }
+
+    /// Configures the KSZ8463 switch in 100BASE-FX mode, with `vlans` (for
+    /// example, [`DEFAULT_VLANS`]) unless `vlan_mode` is `VLanMode::Off`.
     pub fn configure(
         &self,
         mode: Mode,
         vlan_mode: VLanMode,
+        vlans: &[VLan],
     ) -> Result<(), Error> {
         let id = self.read(Register::CIDER)? & !1;
         ringbuf_entry!(Trace::Id(id));
@@ -354,24 +617,9 @@ impl<S: SpiServer> Ksz8463<S> {
             // but strip them before frames are delivered downstream.  This
             // lets us test the VLAN before the SP netstack supports tags.
             VLanMode::Optional => {
-                // Configure VLAN table for the device:
-                // - VLAN 0 has tag 0x301, and contains ports 1 and 3
-                // - VLAN 1 has tag 0x302, and contains ports 2 and 3
-                // - VLAN 2 has tag 0x3FF, and contains all ports
-                //
-                // This uses slots 0-2 in the table, and FID 0-2 (same as slot),
-                // then disables the remaining slots in the VLAN table.
-                self.write_vlan_table(0, 0b101, 0x301)?;
-                self.write_vlan_table(1, 0b110, 0x302)?;
-                self.write_vlan_table(2, 0b111, 0x3FF)?;
-                for i in 3..16 {
-                    self.disable_vlan(i)?;
-                }
-
-                // Assign default VLAN tags to each port
-                self.write(Register::P1VIDCR, 0x301)?;
-                self.write(Register::P2VIDCR, 0x302)?;
-                self.write(Register::P3VIDCR, 0x3FF)?;
+                // Configure VLAN table for the device, with the VLAN for
+                // untagged frames from the SP containing all ports.
+                self.configure_vlan_table(vlans, 0b111)?;
 
                 // Enable ingress VLAN filtering on upstream ports
                 for i in [1, 2] {
@@ -385,28 +633,21 @@ impl<S: SpiServer> Ksz8463<S> {
             // and tagged frames on Port 3. Untagged frames arriving on Port 3
             // are assigned to VLAN 0x3FF, which drops them.
             VLanMode::Mandatory => {
-                // Configure VLAN table for the device:
-                // - VLAN 0 has tag 0x301, and contains ports 1 and 3
-                // - VLAN 1 has tag 0x302, and contains ports 2 and 3
-                // - VLAN 2 has tag 0x3FF, and contains no ports
-                //
-                // This uses slots 0-2 in the table, and FID 0-2 (same as
-                // slot); all other VLANs are disabled (in particular, VLAN
-                // with VID 1, which by default includes all ports).
-                self.write_vlan_table(0, 0b101, 0x301)?;
-                self.write_vlan_table(1, 0b110, 0x302)?;
-                self.write_vlan_table(2, 0b000, 0x3FF)?;
-                for i in 3..16 {
-                    self.disable_vlan(i)?;
-                }
-
-                // Assign default VLAN tags to each port
-                self.write(Register::P1VIDCR, 0x301)?;
-                self.write(Register::P2VIDCR, 0x302)?;
-                self.write(Register::P3VIDCR, 0x3FF)?;
+                // Configure VLAN table for the device, with the VLAN for
+                // untagged frames from the SP containing no ports.
+                self.configure_vlan_table(vlans, 0b000)?;
 
-                // Enable tag removal on both ports
+                // Enable tag removal on ports carrying a single VLAN
                 for i in [1, 2] {
+                    let carried = vlans
+                        .iter()
+                        .filter(|v| v.ports & (1 << (i - 1)) != 0)
+                        .count();
+                    if carried > 1 {
+                        // This port is a trunk, so frames keep their tags
+                        // in both directions.
+                        continue;
+                    }
                     // For upstream ports, drop tagged ingress packets and
                     // remove tags on packet egress.  This is because there
                     // should be no VLAN tags between the VSC7448 on the
@@ -426,9 +667,9 @@ impl<S: SpiServer> Ksz8463<S> {
                 self.write(Register::SGCR9, (1 << 3) | (1 << 1))?;
 
                 // Enable ingress VLAN filtering on Port 3.  This will cause it
-                // to drop packets that have a tag other than 0x301/0x302 (and
-                // untagged frames will be assigned 0x3FF then unceremoniously
-                // dropped).
+                // to drop packets that have a tag other than those configured
+                // (and untagged frames will be assigned 0x3FF then
+                // unceremoniously dropped).
                 self.modify(Register::P3CR2, |r| *r |= 1 << 14)?;
             }
 
@@ -174,4 +174,49 @@ pub fn read_config_into(
     }
 }
 
+/// Checks that the local VPD is intact: that it has a `FRU0` chunk, and that
+/// it and every chunk nested in it pass their checksums.
+pub fn check(i2c_task: TaskId) -> Result<(), LocalVpdError> {
+    let eeprom = drv_i2c_devices::at24csw080::At24Csw080::new(
+        i2c_config::devices::at24csw080_local_vpd(i2c_task),
+    );
+    let eeprom_reader = EepromReader { eeprom: &eeprom };
+
+    let err = |e| {
+        ringbuf_entry!(Trace::Error(e));
+        e
+    };
+
+    let mut reader = TlvcReader::begin(eeprom_reader)
+        .map_err(|_| err(LocalVpdError::DeviceError))?;
+    let mut scratch = [0u8; 32];
+
+    loop {
+        match reader.next() {
+            Ok(Some(chunk)) if chunk.header().tag == *b"FRU0" => {
+                chunk
+                    .check_body_checksum(&mut scratch)
+                    .map_err(|_| err(LocalVpdError::InvalidChecksum))?;
+                let mut inner = chunk.read_as_chunks();
+                loop {
+                    match inner.next() {
+                        Ok(Some(chunk)) => {
+                            chunk.check_body_checksum(&mut scratch).map_err(
+                                |_| err(LocalVpdError::InvalidChecksum),
+                            )?;
+                        }
+                        Ok(None) => return Ok(()),
+                        Err(_) => return Err(err(LocalVpdError::BadRootChunk)),
+                    }
+                }
+            }
+            Ok(Some(chunk)) => {
+                ringbuf_entry!(Trace::UnrelatedChunk(chunk.header().tag));
+            }
+            Ok(None) => return Err(err(LocalVpdError::NoRootChunk)),
+            Err(_) => return Err(err(LocalVpdError::BadRootChunk)),
+        }
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
@@ -0,0 +1,111 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! API crate for the LPC55 crypto accelerator server.
+//!
+//! The raw IPC interface is asynchronous; `Sha256` and `Crypto::rsa_public`
+//! wrap it for callers that just want to block on their own notification
+//! while the hardware does the work.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+pub const SHA256_SZ: usize = 32;
+
+/// Largest modulus accepted by `rsa_public_start`, in bytes (RSA-2048).
+pub const MAX_MODULUS_SZ: usize = 256;
+
+/// Largest chunk accepted by a single `sha256_update`.
+pub const MAX_UPDATE_SZ: usize = 512;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum CryptoError {
+    /// Another task owns the engine.
+    Busy = 1,
+    /// The session's input queue is full; wait for a notification and retry.
+    QueueFull,
+    /// The result isn't ready yet; wait for a notification and retry.
+    NotReady,
+    /// The caller has no session or job in the required state.
+    NoSession,
+    /// Modulus, input or output lengths are unsupported or inconsistent, the
+    /// modulus is even, or the input isn't smaller than the modulus.
+    BadArg,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
+
+fn wait(notify: u32) {
+    let _ = sys_recv_closed(&mut [], notify, TaskId::KERNEL);
+}
+
+/// A blocking SHA-256 computation on the HASHCRYPT engine.
+///
+/// `notify` must be a notification bit reserved for this purpose in the
+/// calling task; the caller sleeps on it whenever the server is behind.
+pub struct Sha256<'a> {
+    crypto: &'a Crypto,
+    notify: u32,
+}
+
+impl<'a> Sha256<'a> {
+    pub fn begin(crypto: &'a Crypto, notify: u32) -> Result<Self, CryptoError> {
+        crypto.sha256_begin(notify)?;
+        Ok(Self { crypto, notify })
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> Result<(), CryptoError> {
+        for chunk in data.chunks(MAX_UPDATE_SZ) {
+            loop {
+                match self.crypto.sha256_update(chunk) {
+                    Err(CryptoError::QueueFull) => wait(self.notify),
+                    r => break r?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<[u8; SHA256_SZ], CryptoError> {
+        loop {
+            match self.crypto.sha256_finish() {
+                Err(CryptoError::QueueFull) => wait(self.notify),
+                r => break r?,
+            }
+        }
+        loop {
+            match self.crypto.sha256_digest() {
+                Err(CryptoError::NotReady) => wait(self.notify),
+                r => break r,
+            }
+        }
+    }
+}
+
+impl Crypto {
+    /// Computes `input ^ exponent mod modulus` on CASPER, sleeping on
+    /// `notify` until it's done. `modulus`, `input` and `output` are
+    /// big-endian and must all be the same length.
+    pub fn rsa_public(
+        &self,
+        notify: u32,
+        exponent: u32,
+        modulus: &[u8],
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), CryptoError> {
+        self.rsa_public_start(notify, exponent, modulus, input)?;
+        loop {
+            match self.rsa_public_result(output) {
+                Err(CryptoError::NotReady) => wait(notify),
+                r => break r,
+            }
+        }
+    }
+}
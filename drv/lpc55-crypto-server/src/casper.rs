@@ -0,0 +1,411 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Interrupt-driven RSA public-key operations on the CASPER engine.
+//!
+//! CASPER is a multiply-accumulate coprocessor rather than a complete RSA
+//! engine: given a multi-precision A, a 64-bit B and a multi-precision C in
+//! its RAM, one `MUL6464SUM` operation computes `A * B + C` in place. That's
+//! exactly one row of a Montgomery multiplication, so we compute
+//! `input ^ exponent mod modulus` with textbook CIOS Montgomery
+//! multiplication, handing each row to CASPER and doing the cheap parts
+//! (choosing the reduction factor, shifting, the final subtraction) in
+//! software. Each row is started from the completion interrupt of the one
+//! before it, so the server keeps answering IPCs while the engine works.
+//!
+//! Operands are 64-bit digits stored least significant first. Offsets given
+//! to the engine are in 32-bit words from the start of CASPER RAM.
+
+use drv_lpc55_crypto_api::{CryptoError, MAX_MODULUS_SZ};
+use drv_lpc55_syscon_api::{Peripheral, Syscon};
+use userlib::*;
+
+const MAX_DIGITS: usize = MAX_MODULUS_SZ / 8;
+
+/// Base of CASPER RAM, as mapped by the `casper_ram` region.
+const RAM_BASE: *mut u64 = 0x0400_0000 as *mut u64;
+
+// Layout of CASPER RAM, in digits. The multiplier for a row sits in the
+// digit immediately before the multi-precision operand it scales, which is
+// how the engine expects an A/B pair to be laid out.
+const N_MUL: usize = 0;
+const N: usize = N_MUL + 1;
+const A_MUL: usize = N + MAX_DIGITS;
+const A: usize = A_MUL + 1;
+/// The accumulator has two extra digits for carries.
+const T: usize = A + MAX_DIGITS;
+
+/// CASPER_CTRL1 mode for `RES = A * B + C`.
+const MODE_MUL6464SUM: u8 = 0x02;
+
+/// Which Montgomery multiplication we're in the middle of.
+#[derive(Copy, Clone, PartialEq)]
+enum Phase {
+    /// `x * R^2`, bringing the input into Montgomery form.
+    ToMont,
+    /// Squaring for exponent bit `bit`.
+    Square { bit: u32 },
+    /// Multiplying in the input for exponent bit `bit`.
+    Multiply { bit: u32 },
+    /// `acc * 1`, bringing the result back out of Montgomery form.
+    FromMont,
+}
+
+/// Which half of a CIOS iteration the engine is working on.
+#[derive(Copy, Clone, PartialEq)]
+enum Row {
+    Multiply,
+    Reduce,
+}
+
+struct Job {
+    owner: TaskId,
+    notify: u32,
+    exponent: u32,
+    digits: usize,
+    /// `-N^-1 mod 2^64`
+    n0_inv: u64,
+    /// The input in Montgomery form.
+    x_mont: [u64; MAX_DIGITS],
+    /// The multiplier for the current Montgomery multiplication, which we
+    /// walk through one digit per CIOS iteration.
+    b: [u64; MAX_DIGITS],
+    phase: Phase,
+    iteration: usize,
+    row: Row,
+    /// Top two digits of the accumulator, saved before each row because the
+    /// engine writes its carry-out over them.
+    saved_top: [u64; 2],
+    /// The result, big-endian, once we're done.
+    result: Option<[u8; MAX_MODULUS_SZ]>,
+}
+
+pub struct CasperEngine {
+    regs: &'static lpc55_pac::casper::RegisterBlock,
+    syscon: Syscon,
+    irq_mask: u32,
+    job: Option<Job>,
+}
+
+fn ram_read(digit: usize) -> u64 {
+    unsafe { core::ptr::read_volatile(RAM_BASE.add(digit)) }
+}
+
+fn ram_write(digit: usize, value: u64) {
+    unsafe { core::ptr::write_volatile(RAM_BASE.add(digit), value) }
+}
+
+/// Converts big-endian bytes into little-endian digits.
+fn to_digits(bytes: &[u8], out: &mut [u64]) {
+    for (digit, chunk) in out.iter_mut().zip(bytes.rchunks_exact(8)) {
+        *digit = u64::from_be_bytes(chunk.try_into().unwrap_lite());
+    }
+}
+
+/// Returns true if `a >= b`, comparing the low `digits` digits.
+fn geq(a: &[u64], b: &[u64]) -> bool {
+    for (x, y) in a.iter().zip(b).rev() {
+        if x != y {
+            return x > y;
+        }
+    }
+    true
+}
+
+/// `a -= b`, returning the borrow out.
+fn sub_in_place(a: &mut [u64], b: &[u64]) -> bool {
+    let mut borrow = false;
+    for (x, &y) in a.iter_mut().zip(b) {
+        let (d, b1) = x.overflowing_sub(y);
+        let (d, b2) = d.overflowing_sub(borrow as u64);
+        *x = d;
+        borrow = b1 || b2;
+    }
+    borrow
+}
+
+/// Computes `R^2 mod N` where `R = 2^(64 * digits)`, by doubling.
+fn r_squared(n: &[u64], out: &mut [u64]) {
+    out.fill(0);
+    out[0] = 1;
+    for _ in 0..2 * 64 * n.len() {
+        let mut carry = 0;
+        for d in out.iter_mut() {
+            let next = *d >> 63;
+            *d = (*d << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 || geq(out, n) {
+            sub_in_place(out, n);
+        }
+    }
+}
+
+impl CasperEngine {
+    /// Creates the engine, leaving CASPER in reset until it's needed.
+    pub fn new(syscon: Syscon, irq_mask: u32) -> Self {
+        syscon.enter_reset(Peripheral::Casper);
+        Self {
+            regs: unsafe { &*lpc55_pac::CASPER::ptr() },
+            syscon,
+            irq_mask,
+            job: None,
+        }
+    }
+
+    pub fn start(
+        &mut self,
+        caller: TaskId,
+        notify: u32,
+        exponent: u32,
+        modulus: &[u8],
+        input: &[u8],
+    ) -> Result<(), CryptoError> {
+        if let Some(j) = &self.job {
+            if j.owner != caller && sys_refresh_task_id(j.owner) == j.owner {
+                return Err(CryptoError::Busy);
+            }
+        }
+        self.abandon();
+
+        let len = modulus.len();
+        if len == 0
+            || len > MAX_MODULUS_SZ
+            || len % 8 != 0
+            || input.len() != len
+            || exponent == 0
+            || modulus[len - 1] & 1 == 0
+        {
+            return Err(CryptoError::BadArg);
+        }
+        let digits = len / 8;
+
+        let mut n = [0; MAX_DIGITS];
+        let mut x = [0; MAX_DIGITS];
+        to_digits(modulus, &mut n[..digits]);
+        to_digits(input, &mut x[..digits]);
+        if geq(&x[..digits], &n[..digits]) {
+            return Err(CryptoError::BadArg);
+        }
+
+        // Newton's iteration for N^-1 mod 2^64; each step doubles the number
+        // of correct low bits, and any odd number is its own inverse mod 8.
+        let mut inv = n[0];
+        for _ in 0..5 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(n[0].wrapping_mul(inv)));
+        }
+
+        let mut r2 = [0; MAX_DIGITS];
+        r_squared(&n[..digits], &mut r2[..digits]);
+
+        self.syscon.leave_reset(Peripheral::Casper);
+        for (i, &d) in n[..digits].iter().enumerate() {
+            ram_write(N + i, d);
+        }
+        for (i, &d) in x[..digits].iter().enumerate() {
+            ram_write(A + i, d);
+        }
+
+        let mut job = Job {
+            owner: caller,
+            notify,
+            exponent,
+            digits,
+            n0_inv: inv.wrapping_neg(),
+            x_mont: [0; MAX_DIGITS],
+            b: r2,
+            phase: Phase::ToMont,
+            iteration: 0,
+            row: Row::Multiply,
+            saved_top: [0; 2],
+            result: None,
+        };
+        self.begin_mont_mul(&mut job);
+        self.job = Some(job);
+        Ok(())
+    }
+
+    pub fn result(
+        &mut self,
+        caller: TaskId,
+    ) -> Result<([u8; MAX_MODULUS_SZ], usize), CryptoError> {
+        match &self.job {
+            Some(j) if j.owner == caller => match j.result {
+                Some(r) => {
+                    let len = j.digits * 8;
+                    self.abandon();
+                    Ok((r, len))
+                }
+                None => Err(CryptoError::NotReady),
+            },
+            _ => Err(CryptoError::NoSession),
+        }
+    }
+
+    /// Drops the caller's job, if it has one.
+    pub fn cancel(&mut self, caller: TaskId) {
+        if matches!(&self.job, Some(j) if j.owner == caller) {
+            self.abandon();
+        }
+    }
+
+    pub fn handle_irq(&mut self) {
+        if self.regs.status.read().done().bit_is_clear() {
+            sys_irq_control(self.irq_mask, true);
+            return;
+        }
+        self.regs.intenclr.write(|w| w.done().set_bit());
+
+        if let Some(mut job) = self.job.take() {
+            if job.result.is_none() {
+                self.row_done(&mut job);
+            }
+            self.job = Some(job);
+        }
+    }
+
+    fn abandon(&mut self) {
+        if self.job.take().is_some() {
+            self.regs.intenclr.write(|w| w.done().set_bit());
+            // Don't leave key material or intermediate values around.
+            self.syscon.enter_reset(Peripheral::Casper);
+        }
+    }
+
+    /// Clears the accumulator and starts the first row of a Montgomery
+    /// multiplication of the operand in the A slot by `job.b`.
+    fn begin_mont_mul(&self, job: &mut Job) {
+        for i in 0..job.digits + 2 {
+            ram_write(T + i, 0);
+        }
+        job.iteration = 0;
+        job.row = Row::Multiply;
+        self.start_row(job);
+    }
+
+    /// Starts `T += A * b[i]` or `T += N * m` on the engine.
+    fn start_row(&self, job: &mut Job) {
+        let digits = job.digits;
+        let (mul, b) = match job.row {
+            Row::Multiply => (A_MUL, job.b[job.iteration]),
+            Row::Reduce => (N_MUL, ram_read(T).wrapping_mul(job.n0_inv)),
+        };
+        ram_write(mul, b);
+        job.saved_top = [ram_read(T + digits), ram_read(T + digits + 1)];
+
+        self.regs.ctrl0.write(|w| unsafe {
+            w.abbpair()
+                .set_bit()
+                .aboff()
+                .bits((mul * 2) as u16)
+                .cdoff()
+                .bits((T * 2) as u16)
+        });
+        self.regs.intenset.write(|w| w.done().set_bit());
+        sys_irq_control(self.irq_mask, true);
+        // ITER is one less than the number of digits in A.
+        self.regs.ctrl1.write(|w| unsafe {
+            w.iter()
+                .bits((digits - 1) as u8)
+                .mode()
+                .bits(MODE_MUL6464SUM)
+                .resoff()
+                .bits((T * 2) as u16)
+        });
+    }
+
+    /// Finishes the row the engine just completed and starts the next one,
+    /// moving on to the next phase when a multiplication is complete.
+    fn row_done(&self, job: &mut Job) {
+        let digits = job.digits;
+
+        // The engine left its carry-out in T[digits]; fold the digits it
+        // overwrote back in.
+        let carry = ram_read(T + digits);
+        let (lo, c) = job.saved_top[0].overflowing_add(carry);
+        ram_write(T + digits, lo);
+        ram_write(T + digits + 1, job.saved_top[1].wrapping_add(c as u64));
+
+        match job.row {
+            Row::Multiply => {
+                job.row = Row::Reduce;
+                self.start_row(job);
+                return;
+            }
+            Row::Reduce => {
+                // T[0] is now zero by construction; shift it out.
+                for i in 0..digits + 1 {
+                    ram_write(T + i, ram_read(T + i + 1));
+                }
+                ram_write(T + digits + 1, 0);
+                job.iteration += 1;
+                if job.iteration < digits {
+                    job.row = Row::Multiply;
+                    self.start_row(job);
+                    return;
+                }
+            }
+        }
+
+        // One Montgomery multiplication done: reduce T below N once.
+        let mut t = [0; MAX_DIGITS + 1];
+        let mut n = [0; MAX_DIGITS + 1];
+        for i in 0..=digits {
+            t[i] = ram_read(T + i);
+            n[i] = if i < digits { ram_read(N + i) } else { 0 };
+        }
+        if geq(&t[..=digits], &n[..=digits]) {
+            sub_in_place(&mut t[..=digits], &n[..=digits]);
+        }
+        let t = &t[..digits];
+
+        let top = 31 - job.exponent.leading_zeros();
+        let next = match job.phase {
+            Phase::ToMont => {
+                job.x_mont[..digits].copy_from_slice(t);
+                if top == 0 {
+                    Phase::FromMont
+                } else {
+                    Phase::Square { bit: top - 1 }
+                }
+            }
+            Phase::Square { bit } if job.exponent & (1 << bit) != 0 => {
+                Phase::Multiply { bit }
+            }
+            Phase::Square { bit } | Phase::Multiply { bit } => {
+                if bit == 0 {
+                    Phase::FromMont
+                } else {
+                    Phase::Square { bit: bit - 1 }
+                }
+            }
+            Phase::FromMont => {
+                let mut out = [0; MAX_MODULUS_SZ];
+                for (chunk, d) in
+                    out[..digits * 8].rchunks_exact_mut(8).zip(t.iter())
+                {
+                    chunk.copy_from_slice(&d.to_be_bytes());
+                }
+                job.result = Some(out);
+                sys_post(job.owner, job.notify);
+                return;
+            }
+        };
+
+        // The accumulator becomes the A operand of the next multiplication;
+        // pick the matching multiplier.
+        for (i, &d) in t.iter().enumerate() {
+            ram_write(A + i, d);
+        }
+        job.b = [0; MAX_DIGITS];
+        match next {
+            Phase::Square { .. } => job.b[..digits].copy_from_slice(t),
+            Phase::Multiply { .. } => job.b = job.x_mont,
+            Phase::FromMont => job.b[0] = 1,
+            Phase::ToMont => unreachable!(),
+        }
+        job.phase = next;
+        self.begin_mont_mul(job);
+    }
+}
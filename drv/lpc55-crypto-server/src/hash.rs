@@ -0,0 +1,325 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Interrupt-driven SHA-256 on the HASHCRYPT engine.
+//!
+//! Unlike `drv_lpc55_sha256`, which blocks the calling task until the engine
+//! is ready for each block, this keeps a queue of caller data and feeds it to
+//! the engine one block at a time from the WAITING interrupt. Callers can
+//! queue their next chunk (or do something else entirely) while the engine
+//! works on the previous one.
+//!
+//! The engine holds a single hash context, so there is at most one session
+//! at a time, owned by the task that started it.
+//!
+//! The same engine also does AES with a key delivered over the PUF's hidden
+//! bus, for the PUF server's key derivation. That's a handful of blocks, so
+//! it's done synchronously, but it claims the engine like a hash session.
+
+use drv_lpc55_crypto_api::{CryptoError, SHA256_SZ};
+use drv_lpc55_syscon_api::{Peripheral, Syscon};
+use heapless::Deque;
+use userlib::*;
+
+const BLOCK_BYTES: usize = 64;
+
+/// Caller data waiting to be fed to the engine. Large enough to hold a
+/// maximum-size update on top of a partly-drained one.
+const QUEUE_BYTES: usize = 1024;
+
+enum State {
+    /// Accepting data from the owner.
+    Accepting,
+    /// Padding has been queued; waiting for the engine to drain the queue and
+    /// produce a digest.
+    Finishing,
+    Done([u8; SHA256_SZ]),
+}
+
+struct Session {
+    owner: TaskId,
+    notify: u32,
+    queue: Deque<u8, QUEUE_BYTES>,
+    /// Bytes of message accepted so far, for the length in the padding.
+    length: u64,
+    state: State,
+    /// Set when we've told the owner the queue is full, so we know to post
+    /// when space frees up.
+    stalled: bool,
+}
+
+pub struct HashEngine {
+    regs: &'static lpc55_pac::hashcrypt::RegisterBlock,
+    syscon: Syscon,
+    irq_mask: u32,
+    session: Option<Session>,
+    /// Task that has the engine set up for AES with the PUF key, if any.
+    aes_owner: Option<TaskId>,
+}
+
+impl HashEngine {
+    /// Creates the engine, leaving HASHCRYPT in reset until it's needed.
+    pub fn new(syscon: Syscon, irq_mask: u32) -> Self {
+        syscon.enter_reset(Peripheral::HashAes);
+        Self {
+            regs: unsafe { &*lpc55_pac::HASHCRYPT::ptr() },
+            syscon,
+            irq_mask,
+            session: None,
+            aes_owner: None,
+        }
+    }
+
+    /// Checks that nobody but `caller` has a live claim on the engine, then
+    /// drops whatever claim is left.
+    fn claim(&mut self, caller: TaskId) -> Result<(), CryptoError> {
+        let owner = self.session.as_ref().map(|s| s.owner).or(self.aes_owner);
+        if let Some(owner) = owner {
+            if owner != caller && sys_refresh_task_id(owner) == owner {
+                return Err(CryptoError::Busy);
+            }
+        }
+        self.abandon();
+        Ok(())
+    }
+
+    pub fn begin(
+        &mut self,
+        caller: TaskId,
+        notify: u32,
+    ) -> Result<(), CryptoError> {
+        self.claim(caller)?;
+
+        // The boot ROM leaves state behind in the engine, and so might a
+        // previous session, so start every session from reset.
+        self.syscon.leave_reset(Peripheral::HashAes);
+        self.regs
+            .ctrl
+            .write(|w| w.mode().sha2_256().new_hash().start());
+
+        self.session = Some(Session {
+            owner: caller,
+            notify,
+            queue: Deque::new(),
+            length: 0,
+            state: State::Accepting,
+            stalled: false,
+        });
+        Ok(())
+    }
+
+    /// Copies a chunk of message into the queue with `read`, which is given
+    /// the offset and destination of each byte range to fill.
+    pub fn update(
+        &mut self,
+        caller: TaskId,
+        len: usize,
+        mut read: impl FnMut(usize, &mut [u8]) -> Result<(), ()>,
+    ) -> Result<(), Result<CryptoError, ()>> {
+        let s = self.session_for(caller).map_err(Ok)?;
+        if !matches!(s.state, State::Accepting) {
+            return Err(Ok(CryptoError::NoSession));
+        }
+        if s.queue.capacity() - s.queue.len() < len {
+            s.stalled = true;
+            return Err(Ok(CryptoError::QueueFull));
+        }
+
+        let mut buf = [0u8; 64];
+        let mut offset = 0;
+        while offset < len {
+            let n = usize::min(buf.len(), len - offset);
+            read(offset, &mut buf[..n]).map_err(Err)?;
+            for &b in &buf[..n] {
+                // Can't fail, we checked for space above.
+                let _ = s.queue.push_back(b);
+            }
+            offset += n;
+        }
+        s.length = s.length.wrapping_add(len as u64);
+
+        self.pump();
+        Ok(())
+    }
+
+    pub fn finish(&mut self, caller: TaskId) -> Result<(), CryptoError> {
+        let s = self.session_for(caller)?;
+        if !matches!(s.state, State::Accepting) {
+            return Err(CryptoError::NoSession);
+        }
+
+        // MD padding: a single 1 bit, zeros up to 56 bytes into the block,
+        // then the message length in bits as a 64-bit big-endian integer.
+        let used = (s.length % BLOCK_BYTES as u64) as usize;
+        let zeros = (BLOCK_BYTES * 2 - 8 - 1 - used) % BLOCK_BYTES;
+        if s.queue.capacity() - s.queue.len() < 1 + zeros + 8 {
+            s.stalled = true;
+            return Err(CryptoError::QueueFull);
+        }
+        let _ = s.queue.push_back(0x80);
+        for _ in 0..zeros {
+            let _ = s.queue.push_back(0);
+        }
+        for b in s.length.wrapping_mul(8).to_be_bytes() {
+            let _ = s.queue.push_back(b);
+        }
+        s.state = State::Finishing;
+
+        self.pump();
+        Ok(())
+    }
+
+    pub fn digest(
+        &mut self,
+        caller: TaskId,
+    ) -> Result<[u8; SHA256_SZ], CryptoError> {
+        let s = self.session_for(caller)?;
+        match s.state {
+            State::Done(digest) => {
+                self.abandon();
+                Ok(digest)
+            }
+            State::Finishing => Err(CryptoError::NotReady),
+            State::Accepting => Err(CryptoError::NoSession),
+        }
+    }
+
+    /// Sets the engine up for AES-256-ECB encryption keyed from the PUF. The
+    /// caller must then have the PUF reconstruct an index 0 key before
+    /// calling `aes_puf_ecb`.
+    pub fn aes_puf_begin(&mut self, caller: TaskId) -> Result<(), CryptoError> {
+        self.claim(caller)?;
+
+        self.syscon.leave_reset(Peripheral::HashAes);
+        self.regs.cryptcfg.write(|w| {
+            w.aesmode()
+                .ecb()
+                .aesdecrypt()
+                .encrypt()
+                .aessecret()
+                .hidden_way()
+                .aeskeysz()
+                .bits256()
+        });
+        self.regs.ctrl.write(|w| w.mode().aes().new_hash().start());
+        self.aes_owner = Some(caller);
+        Ok(())
+    }
+
+    /// Encrypts whole 16-byte blocks from `input` into `output`, then
+    /// releases the engine.
+    pub fn aes_puf_ecb(
+        &mut self,
+        caller: TaskId,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), CryptoError> {
+        if self.aes_owner != Some(caller) {
+            return Err(CryptoError::NoSession);
+        }
+        if input.len() % 16 != 0 || input.len() != output.len() {
+            self.abandon();
+            return Err(CryptoError::BadArg);
+        }
+
+        for (block, out) in
+            input.chunks_exact(16).zip(output.chunks_exact_mut(16))
+        {
+            while self.regs.status.read().waiting().is_not_waiting() {}
+            for word in block.chunks_exact(4) {
+                let word = u32::from_le_bytes(word.try_into().unwrap_lite());
+                self.regs.indata.write(|w| unsafe { w.data().bits(word) });
+            }
+            while self.regs.status.read().digest().is_not_ready() {}
+            for (dest, reg) in out.chunks_exact_mut(4).zip(&self.regs.digest0) {
+                dest.copy_from_slice(&reg.read().bits().to_le_bytes());
+            }
+        }
+
+        self.abandon();
+        Ok(())
+    }
+
+    /// Drops the caller's session, if it has one.
+    pub fn cancel(&mut self, caller: TaskId) {
+        if self.session_for(caller).is_ok() || self.aes_owner == Some(caller) {
+            self.abandon();
+        }
+    }
+
+    pub fn handle_irq(&mut self) {
+        self.regs
+            .intenclr
+            .write(|w| w.waiting().set_bit().digest().set_bit());
+        self.pump();
+    }
+
+    fn session_for(
+        &mut self,
+        caller: TaskId,
+    ) -> Result<&mut Session, CryptoError> {
+        match &mut self.session {
+            Some(s) if s.owner == caller => Ok(s),
+            _ => Err(CryptoError::NoSession),
+        }
+    }
+
+    fn abandon(&mut self) {
+        let held = self.session.take().is_some();
+        if held | self.aes_owner.take().is_some() {
+            self.regs
+                .intenclr
+                .write(|w| w.waiting().set_bit().digest().set_bit());
+            // Don't leave anything about the last message lying around.
+            self.syscon.enter_reset(Peripheral::HashAes);
+        }
+    }
+
+    /// Feeds the engine as far as it'll go, arranging for an interrupt when
+    /// it's ready for more.
+    fn pump(&mut self) {
+        let Some(s) = &mut self.session else {
+            return;
+        };
+
+        let mut drained = false;
+        while s.queue.len() >= BLOCK_BYTES {
+            if self.regs.status.read().waiting().is_not_waiting() {
+                self.regs.intenset.write(|w| w.waiting().set_bit());
+                sys_irq_control(self.irq_mask, true);
+                break;
+            }
+            for _ in 0..BLOCK_BYTES / 4 {
+                let mut word = [0; 4];
+                for b in &mut word {
+                    // Can't fail, we checked the length above.
+                    *b = s.queue.pop_front().unwrap_or(0);
+                }
+                let word = u32::from_le_bytes(word);
+                self.regs.indata.write(|w| unsafe { w.data().bits(word) });
+            }
+            drained = true;
+        }
+
+        if matches!(s.state, State::Finishing) && s.queue.is_empty() {
+            if self.regs.status.read().digest().is_not_ready() {
+                self.regs.intenset.write(|w| w.digest().set_bit());
+                sys_irq_control(self.irq_mask, true);
+                return;
+            }
+            let mut digest = [0; SHA256_SZ];
+            for (dest, reg) in
+                digest.chunks_exact_mut(4).zip(&self.regs.digest0)
+            {
+                dest.copy_from_slice(&reg.read().bits().to_be_bytes());
+            }
+            s.state = State::Done(digest);
+            self.syscon.enter_reset(Peripheral::HashAes);
+            sys_post(s.owner, s.notify);
+        } else if drained && s.stalled {
+            s.stalled = false;
+            sys_post(s.owner, s.notify);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! LPC55 crypto accelerator server.
+//!
+//! This owns the HASHCRYPT (SHA-256) and CASPER (RSA public-key) engines and
+//! runs work for clients from the engines' interrupts, so a client can queue
+//! work and go on with something else -- the sprot server answering the SP,
+//! say -- rather than spinning in a software implementation. Clients are
+//! told about progress by posting the notification bits they supply; see
+//! `drv_lpc55_crypto_api` for blocking wrappers.
+
+#![no_std]
+#![no_main]
+
+mod casper;
+mod hash;
+
+use casper::CasperEngine;
+use drv_lpc55_crypto_api::{CryptoError, SHA256_SZ};
+use drv_lpc55_syscon_api::Syscon;
+use hash::HashEngine;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use userlib::*;
+
+task_slot!(SYSCON, syscon_driver);
+
+struct ServerImpl {
+    hash: HashEngine,
+    casper: CasperEngine,
+}
+
+impl idl::InOrderCryptoImpl for ServerImpl {
+    fn sha256_begin(
+        &mut self,
+        msg: &RecvMessage,
+        notify: u32,
+    ) -> Result<(), RequestError<CryptoError>> {
+        self.hash.begin(msg.sender, notify)?;
+        Ok(())
+    }
+
+    fn sha256_update(
+        &mut self,
+        msg: &RecvMessage,
+        data: LenLimit<Leased<R, [u8]>, 512>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        self.hash
+            .update(msg.sender, data.len(), |offset, buf| {
+                data.read_range(offset..offset + buf.len(), buf)
+            })
+            .map_err(|e| match e {
+                Ok(e) => RequestError::from(e),
+                Err(()) => RequestError::Fail(ClientError::WentAway),
+            })
+    }
+
+    fn sha256_finish(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<CryptoError>> {
+        self.hash.finish(msg.sender)?;
+        Ok(())
+    }
+
+    fn sha256_digest(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<[u8; SHA256_SZ], RequestError<CryptoError>> {
+        Ok(self.hash.digest(msg.sender)?)
+    }
+
+    fn rsa_public_start(
+        &mut self,
+        msg: &RecvMessage,
+        notify: u32,
+        exponent: u32,
+        modulus: LenLimit<Leased<R, [u8]>, 256>,
+        input: LenLimit<Leased<R, [u8]>, 256>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        let mut m = [0; drv_lpc55_crypto_api::MAX_MODULUS_SZ];
+        let mut x = [0; drv_lpc55_crypto_api::MAX_MODULUS_SZ];
+        let m = &mut m[..modulus.len()];
+        let x = &mut x[..input.len()];
+        modulus
+            .read_range(0..m.len(), m)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        input
+            .read_range(0..x.len(), x)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        self.casper.start(msg.sender, notify, exponent, m, x)?;
+        Ok(())
+    }
+
+    fn rsa_public_result(
+        &mut self,
+        msg: &RecvMessage,
+        output: LenLimit<Leased<W, [u8]>, 256>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        let (result, len) = self.casper.result(msg.sender)?;
+        if output.len() != len {
+            return Err(CryptoError::BadArg.into());
+        }
+        output
+            .write_range(0..len, &result[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(())
+    }
+
+    fn aes_puf_begin(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<CryptoError>> {
+        self.hash.aes_puf_begin(msg.sender)?;
+        Ok(())
+    }
+
+    fn aes_puf_ecb(
+        &mut self,
+        msg: &RecvMessage,
+        input: LenLimit<Leased<R, [u8]>, 64>,
+        output: LenLimit<Leased<W, [u8]>, 64>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        let mut inbuf = [0; 64];
+        let mut outbuf = [0; 64];
+        let inbuf = &mut inbuf[..input.len()];
+        input
+            .read_range(0..inbuf.len(), inbuf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        let outbuf = &mut outbuf[..output.len()];
+
+        self.hash.aes_puf_ecb(msg.sender, inbuf, outbuf)?;
+        output
+            .write_range(0..outbuf.len(), outbuf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(())
+    }
+
+    fn cancel(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.hash.cancel(msg.sender);
+        self.casper.cancel(msg.sender);
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::HASHCRYPT_IRQ_MASK | notifications::CASPER_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::HASHCRYPT_IRQ_MASK != 0 {
+            self.hash.handle_irq();
+        }
+        if bits & notifications::CASPER_IRQ_MASK != 0 {
+            self.casper.handle_irq();
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let syscon = Syscon::from(SYSCON.get_task_id());
+    syscon.enable_clock(drv_lpc55_syscon_api::Peripheral::HashAes);
+    syscon.enable_clock(drv_lpc55_syscon_api::Peripheral::Casper);
+
+    let mut server = ServerImpl {
+        hash: HashEngine::new(syscon, notifications::HASHCRYPT_IRQ_MASK),
+        casper: CasperEngine::new(
+            Syscon::from(SYSCON.get_task_id()),
+            notifications::CASPER_IRQ_MASK,
+        ),
+    };
+    let mut incoming = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch_n(&mut incoming, &mut server);
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
+
+mod idl {
+    use drv_lpc55_crypto_api::CryptoError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
@@ -0,0 +1,89 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! API crate for the LPC55 flash server.
+//!
+//! The server owns the flash controller, and erases, programs and reads whole
+//! pages on behalf of other tasks, but only within the regions below, and
+//! only for the tasks the app lets use each one. Pages are numbered from the
+//! start of their region; for `Storage`, from the start of the caller's own
+//! partition of it.
+//!
+//! `program` only writes pages that are erased, and checks what it wrote,
+//! so a page that has been programmed can only be lost by erasing it. A
+//! client that needs to replace data without ever being caught with none
+//! should write the new copy to an erased page, and only then erase the old.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Size of a flash page, the unit of erasing and programming.
+pub const PAGE_SIZE: usize = 512;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum FlashRegion {
+    /// The image slot we aren't running from
+    InactiveImage = 1,
+    /// The caller's partition of the pages set aside for persistent storage
+    Storage = 2,
+    /// The stage0 bootloader
+    Stage0 = 3,
+    /// The CFPA scratch page, which the ROM checks at reset and, if it likes
+    /// it, copies over the older of the ping and pong pages
+    CfpaScratch = 4,
+    /// The CFPA ping page, which is read-only
+    CfpaPing = 5,
+    /// The CFPA pong page, which is read-only
+    CfpaPong = 6,
+    /// Key codes managed by the PUF server
+    PufKeystore = 7,
+    /// The CMPA, which can be read, but only written with `write_cmpa`
+    Cmpa = 8,
+}
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum FlashError {
+    /// The page isn't within the region.
+    OutOfBounds = 1,
+    /// The data isn't exactly a page long.
+    BadLength,
+    /// The page has to be erased before it can be programmed.
+    NotErased,
+    /// The page is erased, so there's nothing to read.
+    Erased,
+    /// The page doesn't hold the expected data.
+    VerifyFailed,
+    /// The page can't be read, most likely because programming or erasing
+    /// it was interrupted; it needs erasing.
+    Corrupt,
+    /// The flash controller reported an error.
+    FlashError,
+    /// The caller isn't allowed to use the region.
+    NotAllowed,
+    /// The region can be read, but not erased or programmed.
+    ReadOnly,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct RegionInfo {
+    /// Number of pages in the region (for `Storage`, in the caller's
+    /// partition)
+    pub pages: u32,
+    /// Pages erased since boot
+    pub erases: u32,
+    /// Pages programmed since boot
+    pub programs: u32,
+    /// Operations the flash controller failed since boot
+    pub failures: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Tasks allowed to use each region other than storage, by region.
+    #[serde(default)]
+    regions: BTreeMap<Region, Vec<String>>,
+    /// Each task's partition of storage, by task.
+    #[serde(default)]
+    storage: BTreeMap<String, Partition>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+enum Region {
+    InactiveImage,
+    Stage0,
+    CfpaScratch,
+    CfpaPing,
+    CfpaPong,
+    PufKeystore,
+    Cmpa,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Partition {
+    first: u32,
+    pages: u32,
+}
+
+fn main() -> Result<()> {
+    build_util::build_notifications()?;
+    build_util::idol::build_server_support(
+        "../../idl/lpc55-flash.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+
+    let task = build_util::task_full_config::<Config>()?;
+    let config = task.config.unwrap_or_default();
+    // We write the CMPA through the ROM, which we'll fault trying to call if
+    // we can't see it.
+    if config.regions.contains_key(&Region::Cmpa)
+        && !task.uses.iter().any(|u| u == "bootrom")
+    {
+        bail!("the flash server needs `bootrom` to write the CMPA");
+    }
+    let task_ids = build_util::task_ids();
+
+    let out = build_util::out_dir();
+    let mut file = std::fs::File::create(out.join("access.rs"))?;
+
+    writeln!(
+        file,
+        "const REGION_CALLERS: &[(FlashRegion, &[usize])] = &["
+    )?;
+    for (region, tasks) in &config.regions {
+        let ids = task_ids.names_to_ids(tasks)?;
+        writeln!(file, "    (FlashRegion::{region:?}, &{ids:?}),")?;
+    }
+    writeln!(file, "];")?;
+
+    let mut partitions: Vec<(&String, &Partition)> =
+        config.storage.iter().collect();
+    partitions.sort_by_key(|(_, p)| p.first);
+    for pair in partitions.windows(2) {
+        let ((a, pa), (b, pb)) = (pair[0], pair[1]);
+        if pa.first + pa.pages > pb.first {
+            bail!("the storage partitions of {a} and {b} overlap");
+        }
+    }
+
+    writeln!(file, "const PARTITIONS: &[Partition] = &[")?;
+    for (task, p) in &partitions {
+        if p.pages == 0 {
+            bail!("the storage partition of {task} is empty");
+        }
+        let Some(id) = task_ids.get(task) else {
+            bail!("storage partition for no such task {task:?}");
+        };
+        writeln!(
+            file,
+            "    Partition {{ task: {id}, first: {}, pages: {} }},",
+            p.first, p.pages
+        )?;
+    }
+    writeln!(file, "];")?;
+
+    Ok(())
+}
@@ -0,0 +1,631 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! LPC55 flash server.
+//!
+//! This is the only task with the flash controller, and it erases, programs,
+//! verifies and reads flash pages on behalf of the others, one request at a
+//! time, so no page is ever touched by two clients at once. It only reaches
+//! the regions in `FlashRegion`, whose bounds come from the memory map (or,
+//! for the CFPA pages, the ROM's fixed layout): the running image and the
+//! rest of the protected flash region can't be reached through us. The app
+//! says which tasks may use each region, and gives each task that needs
+//! persistent storage its own partition of the `storage` pages:
+//!
+//! ```toml
+//! [tasks.flash.config.regions]
+//! inactive-image = ["update_server"]
+//! puf-keystore = ["puf"]
+//!
+//! [tasks.flash.config.storage]
+//! kv_store = { first = 0, pages = 2 }
+//! ```
+//!
+//! `program` refuses pages that aren't erased, and checks what it wrote (see
+//! `drv_lpc55_flash_api` for what clients can build on that). We read through
+//! the controller rather than the bus: a page whose programming or erasing
+//! was cut short can fail ECC, and while the controller reports that, the bus
+//! faults us. The one exception is the CMPA, which we program through the
+//! ROM's API, since that keeps the digest it checks sealed CMPAs against;
+//! letting any task do that needs `bootrom` in our `uses`.
+//!
+//! # Wear
+//!
+//! Storage pages are the ones rewritten in the field, so we count how often
+//! each is erased over the life of the part, for clients to spread their
+//! wear. The counts live in a wear log in the last `WEAR_LOG_PAGES` pages of
+//! storage (which no partition may overlap): each page holds a complete,
+//! numbered copy of the counts, and we write a new copy over the older page
+//! after every `WEAR_FLUSH_INTERVAL` erases. A reset loses the erases since
+//! the last copy, which undercounts by less than the interval each time; a
+//! reset in the middle of writing a copy costs us that copy, and we carry on
+//! from the other.
+
+#![no_std]
+#![no_main]
+
+use core::ops::Range;
+use drv_lpc55_flash::{ProgramState, ReadError, BYTES_PER_FLASH_WORD};
+use drv_lpc55_flash_api::{FlashError, FlashRegion, RegionInfo, PAGE_SIZE};
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R, W};
+use lpc55_romapi::FlashStatus;
+use memory_map::{flash, puf_keystore, storage};
+use ringbuf::*;
+use userlib::*;
+use zerocopy::AsBytes;
+
+const PAGE_WORDS: usize = PAGE_SIZE / 4;
+const WORDS_PER_PAGE: u32 = (PAGE_SIZE / BYTES_PER_FLASH_WORD) as u32;
+
+// We shouldn't actually dereference this. The type is not correct.
+// It's just here to allow a mechanism for getting the address.
+extern "C" {
+    static __this_image: [u32; 0];
+}
+
+// This part aliases flash in two positions that differ in bit 28; we compare
+// addresses with it clear.
+const ADDRMASK: u32 = !(1 << 28);
+
+// The CFPA and CMPA pages, which the ROM keeps at fixed addresses in the
+// protected flash region (see Figure 13, "Protected Flash Region," in UM11126
+// rev 2.4).
+const CFPA_SCRATCH: Range<u32> = 0x9_de00..0x9_e000;
+const CFPA_PING: Range<u32> = 0x9_e000..0x9_e200;
+const CFPA_PONG: Range<u32> = 0x9_e200..0x9_e400;
+const CMPA: Range<u32> = 0x9_e400..0x9_e600;
+
+// Every image shares the one storage region and keystore.
+const STORAGE: Range<u32> = storage::A;
+const STORAGE_PAGES: usize = (STORAGE.end - STORAGE.start) as usize / PAGE_SIZE;
+
+const WEAR_LOG_PAGES: u32 = 2;
+const WEAR_LOG_FIRST: u32 = STORAGE_PAGES as u32 - WEAR_LOG_PAGES;
+const WEAR_FLUSH_INTERVAL: u32 = 16;
+const WEAR_MAGIC: u32 = 0x5241_4557; // "WEAR"
+/// A wear log copy is this magic word, its sequence number, and a count for
+/// every storage page.
+const WEAR_RECORD_WORDS: usize = 2 + STORAGE_PAGES;
+
+const _: () = assert!(STORAGE.start as usize % PAGE_SIZE == 0);
+const _: () = assert!(STORAGE.end as usize % PAGE_SIZE == 0);
+const _: () = assert!(WEAR_RECORD_WORDS <= PAGE_WORDS);
+
+struct Partition {
+    task: usize,
+    first: u32,
+    pages: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/access.rs"));
+
+const _: () = {
+    let mut i = 0;
+    while i < PARTITIONS.len() {
+        let p = &PARTITIONS[i];
+        assert!(
+            p.first + p.pages <= WEAR_LOG_FIRST,
+            "storage partitions must fit below the wear log"
+        );
+        i += 1;
+    }
+};
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Erase(FlashRegion, u32),
+    Program(FlashRegion, u32),
+    VerifyFailed(FlashRegion, u32),
+    Corrupt(FlashRegion, u32),
+    Failed(FlashRegion, u32),
+    NotAllowed(FlashRegion, u16),
+    WearLogLoaded(u32),
+    WearLogWritten(u32),
+    WearLogFailed,
+    CmpaWritten { seal: bool },
+    Rom(FlashStatus),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct ServerImpl {
+    flash: drv_lpc55_flash::Flash<'static>,
+    /// The image slot we aren't running from
+    image: Range<u32>,
+    /// Counters for each region, indexed by `FlashRegion` value
+    info: [RegionInfo; 9],
+    /// Lifetime erases of each storage page
+    wear: [u32; STORAGE_PAGES],
+    /// Sequence number of the newest wear log copy
+    wear_seq: u32,
+    /// Wear log page holding the newest copy
+    wear_page: u32,
+    /// Erases since we last wrote the wear log
+    wear_unlogged: u32,
+    page: [u32; PAGE_WORDS],
+}
+
+impl ServerImpl {
+    /// Returns the range of flash that `region` covers for `caller`, checking
+    /// that they may use it.
+    fn region(
+        &self,
+        caller: TaskId,
+        region: FlashRegion,
+    ) -> Result<Range<u32>, FlashError> {
+        let caller = caller.index();
+        let allowed = if region == FlashRegion::Storage {
+            PARTITIONS.iter().find(|p| p.task == caller).map(|p| {
+                let start = STORAGE.start + p.first * PAGE_SIZE as u32;
+                start..start + p.pages * PAGE_SIZE as u32
+            })
+        } else {
+            REGION_CALLERS
+                .iter()
+                .any(|(r, tasks)| *r == region && tasks.contains(&caller))
+                .then(|| match region {
+                    FlashRegion::InactiveImage => self.image.clone(),
+                    FlashRegion::Stage0 => flash::STAGE0,
+                    FlashRegion::CfpaScratch => CFPA_SCRATCH,
+                    FlashRegion::CfpaPing => CFPA_PING,
+                    FlashRegion::CfpaPong => CFPA_PONG,
+                    FlashRegion::PufKeystore => puf_keystore::A,
+                    FlashRegion::Cmpa => CMPA,
+                    FlashRegion::Storage => unreachable!(),
+                })
+        };
+        allowed.ok_or_else(|| {
+            ringbuf_entry!(Trace::NotAllowed(region, caller as u16));
+            FlashError::NotAllowed
+        })
+    }
+
+    /// Returns the number of the first flash word of `page` within `region`
+    /// for `caller`.
+    fn page_word(
+        &self,
+        caller: TaskId,
+        region: FlashRegion,
+        page: u32,
+    ) -> Result<u32, FlashError> {
+        let range = self.region(caller, region)?;
+        let pages = (range.end - range.start) / PAGE_SIZE as u32;
+        if page >= pages {
+            return Err(FlashError::OutOfBounds);
+        }
+        Ok((range.start + page * PAGE_SIZE as u32)
+            / BYTES_PER_FLASH_WORD as u32)
+    }
+
+    /// As `page_word`, for a region the caller means to change.
+    fn writable_page_word(
+        &self,
+        caller: TaskId,
+        region: FlashRegion,
+        page: u32,
+    ) -> Result<u32, FlashError> {
+        let word = self.page_word(caller, region, page)?;
+        if matches!(
+            region,
+            FlashRegion::CfpaPing | FlashRegion::CfpaPong | FlashRegion::Cmpa
+        ) {
+            return Err(FlashError::ReadOnly);
+        }
+        Ok(word)
+    }
+
+    fn info(&mut self, region: FlashRegion) -> &mut RegionInfo {
+        &mut self.info[region as usize]
+    }
+
+    fn failed(&mut self, region: FlashRegion, word: u32) -> FlashError {
+        ringbuf_entry!(Trace::Failed(region, word));
+        let info = self.info(region);
+        info.failures = info.failures.wrapping_add(1);
+        FlashError::FlashError
+    }
+
+    /// Sleeps until the flash controller interrupts us.
+    fn wait_for_irq(&mut self) {
+        self.flash.enable_interrupt_sources();
+        sys_irq_control(notifications::FLASH_IRQ_MASK, true);
+        // RECV from the kernel cannot produce an error, so ignore it.
+        let _ = sys_recv_closed(
+            &mut [],
+            notifications::FLASH_IRQ_MASK,
+            TaskId::KERNEL,
+        );
+        self.flash.disable_interrupt_sources();
+    }
+
+    fn wait_for_erase_or_program(
+        &mut self,
+        region: FlashRegion,
+        word: u32,
+    ) -> Result<(), FlashError> {
+        loop {
+            if let Some(result) = self.flash.poll_erase_or_program_result() {
+                return result.map_err(|_| self.failed(region, word));
+            }
+            self.wait_for_irq();
+        }
+    }
+
+    fn page_is_erased(&mut self, word: u32) -> bool {
+        self.flash
+            .start_blank_check(word..=word + WORDS_PER_PAGE - 1);
+        loop {
+            if let Some(state) = self.flash.poll_blank_check_result() {
+                return state == ProgramState::Blank;
+            }
+            self.wait_for_irq();
+        }
+    }
+
+    fn erase_page(
+        &mut self,
+        region: FlashRegion,
+        word: u32,
+    ) -> Result<(), FlashError> {
+        ringbuf_entry!(Trace::Erase(region, word));
+        self.flash
+            .start_erase_range(word..=word + WORDS_PER_PAGE - 1);
+        self.wait_for_erase_or_program(region, word)?;
+
+        let info = self.info(region);
+        info.erases = info.erases.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Programs our page buffer into the erased page starting at `word`.
+    fn program_page(
+        &mut self,
+        region: FlashRegion,
+        word: u32,
+    ) -> Result<(), FlashError> {
+        ringbuf_entry!(Trace::Program(region, word));
+
+        // Load the page into the controller a flash word at a time, then
+        // program it by naming any word in it.
+        let bytes = self.page.as_bytes();
+        for (i, row) in bytes.chunks_exact(BYTES_PER_FLASH_WORD).enumerate() {
+            let row: &[u8; BYTES_PER_FLASH_WORD] = row.try_into().unwrap_lite();
+            self.flash.start_write_row(i as u32, row);
+            while !self.flash.poll_write_result() {
+                // This is supposed to be very quick in hardware.
+            }
+        }
+        self.flash.start_program(word);
+        self.wait_for_erase_or_program(region, word)?;
+
+        let info = self.info(region);
+        info.programs = info.programs.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Reads the flash words starting at `word` into `out`.
+    fn read_words(
+        &mut self,
+        region: FlashRegion,
+        word: u32,
+        out: &mut [u32],
+    ) -> Result<(), FlashError> {
+        for (wn, dest) in (word..).zip(out.chunks_mut(4)) {
+            self.flash.start_read(wn);
+            let result = loop {
+                // Reads are quick; this will most likely not sleep.
+                if let Some(result) = self.flash.poll_read_result() {
+                    break result;
+                }
+                self.wait_for_irq();
+            };
+            match result {
+                Ok(data) => dest.copy_from_slice(&data[..dest.len()]),
+                Err(ReadError::Ecc) => {
+                    ringbuf_entry!(Trace::Corrupt(region, wn));
+                    return Err(FlashError::Corrupt);
+                }
+                Err(ReadError::IllegalOperation | ReadError::Fail) => {
+                    return Err(self.failed(region, wn));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the page starting at `word` holds our page buffer.
+    fn verify_page(
+        &mut self,
+        region: FlashRegion,
+        word: u32,
+    ) -> Result<(), FlashError> {
+        for i in 0..WORDS_PER_PAGE {
+            let wn = word + i;
+            let mut data = [0; 4];
+            match self.read_words(region, wn, &mut data) {
+                Ok(()) if data[..] == self.page[i as usize * 4..][..4] => (),
+                Ok(()) | Err(FlashError::Corrupt) => {
+                    ringbuf_entry!(Trace::VerifyFailed(region, wn));
+                    return Err(FlashError::VerifyFailed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills our page buffer from a lease, which must be exactly a page.
+    fn read_lease(
+        &mut self,
+        data: LenLimit<Leased<R, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<FlashError>> {
+        if data.len() != PAGE_SIZE {
+            return Err(FlashError::BadLength.into());
+        }
+        data.read_range(0..PAGE_SIZE, self.page.as_bytes_mut())
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))
+    }
+
+    /// Counts an erase of the storage page starting at `word`, writing the
+    /// wear log if it's due.
+    fn count_wear(&mut self, word: u32) {
+        let page = (word * BYTES_PER_FLASH_WORD as u32 - STORAGE.start)
+            / PAGE_SIZE as u32;
+        let count = &mut self.wear[page as usize];
+        *count = count.saturating_add(1);
+
+        self.wear_unlogged += 1;
+        if self.wear_unlogged >= WEAR_FLUSH_INTERVAL {
+            self.write_wear_log();
+        }
+    }
+
+    fn wear_log_word(page: u32) -> u32 {
+        (STORAGE.start + (WEAR_LOG_FIRST + page) * PAGE_SIZE as u32)
+            / BYTES_PER_FLASH_WORD as u32
+    }
+
+    /// Picks up the newest copy of the wear log that we can read.
+    fn load_wear_log(&mut self) {
+        let mut newest: Option<(u32, u32)> = None;
+        for page in 0..WEAR_LOG_PAGES {
+            let word = Self::wear_log_word(page);
+            if self.page_is_erased(word) {
+                continue;
+            }
+            let mut header = [0u32; 2];
+            if self
+                .read_words(FlashRegion::Storage, word, &mut header)
+                .is_err()
+                || header[0] != WEAR_MAGIC
+            {
+                continue;
+            }
+            let seq = header[1];
+            let mut record = [0u32; WEAR_RECORD_WORDS];
+            if newest.map_or(true, |(_, s)| newer(seq, s))
+                && self
+                    .read_words(FlashRegion::Storage, word, &mut record)
+                    .is_ok()
+            {
+                newest = Some((page, seq));
+                self.wear.copy_from_slice(&record[2..]);
+            }
+        }
+        if let Some((page, seq)) = newest {
+            ringbuf_entry!(Trace::WearLogLoaded(seq));
+            self.wear_page = page;
+            self.wear_seq = seq;
+        }
+    }
+
+    /// Writes the counts over the older copy of the wear log.
+    fn write_wear_log(&mut self) {
+        let page = (self.wear_page + 1) % WEAR_LOG_PAGES;
+        let seq = self.wear_seq.wrapping_add(1);
+        let word = Self::wear_log_word(page);
+
+        // Count this erase in the copy we're about to write.
+        let count = &mut self.wear[(WEAR_LOG_FIRST + page) as usize];
+        *count = count.saturating_add(1);
+
+        self.page.fill(0);
+        self.page[0] = WEAR_MAGIC;
+        self.page[1] = seq;
+        self.page[2..WEAR_RECORD_WORDS].copy_from_slice(&self.wear);
+
+        let region = FlashRegion::Storage;
+        let r = self.erase_page(region, word).and_then(|()| {
+            self.program_page(region, word)?;
+            self.verify_page(region, word)
+        });
+        match r {
+            Ok(()) => {
+                ringbuf_entry!(Trace::WearLogWritten(seq));
+                self.wear_page = page;
+                self.wear_seq = seq;
+                self.wear_unlogged = 0;
+            }
+            // We'll try again after the next erase.
+            Err(_) => ringbuf_entry!(Trace::WearLogFailed),
+        }
+    }
+}
+
+/// Compares wear log sequence numbers, allowing for wrapping.
+fn newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+impl idl::InOrderFlashImpl for ServerImpl {
+    fn region_info(
+        &mut self,
+        msg: &RecvMessage,
+        region: FlashRegion,
+    ) -> Result<RegionInfo, RequestError<FlashError>> {
+        let range = self.region(msg.sender, region)?;
+        Ok(RegionInfo {
+            pages: (range.end - range.start) / PAGE_SIZE as u32,
+            ..*self.info(region)
+        })
+    }
+
+    fn erase_count(
+        &mut self,
+        msg: &RecvMessage,
+        page: u32,
+    ) -> Result<u32, RequestError<FlashError>> {
+        let word = self.page_word(msg.sender, FlashRegion::Storage, page)?;
+        let page = (word * BYTES_PER_FLASH_WORD as u32 - STORAGE.start)
+            / PAGE_SIZE as u32;
+        Ok(self.wear[page as usize])
+    }
+
+    fn is_erased(
+        &mut self,
+        msg: &RecvMessage,
+        region: FlashRegion,
+        page: u32,
+    ) -> Result<bool, RequestError<FlashError>> {
+        let word = self.page_word(msg.sender, region, page)?;
+        Ok(self.page_is_erased(word))
+    }
+
+    fn erase(
+        &mut self,
+        msg: &RecvMessage,
+        region: FlashRegion,
+        page: u32,
+    ) -> Result<(), RequestError<FlashError>> {
+        let word = self.writable_page_word(msg.sender, region, page)?;
+        self.erase_page(region, word)?;
+        if region == FlashRegion::Storage {
+            self.count_wear(word);
+        }
+        Ok(())
+    }
+
+    fn program(
+        &mut self,
+        msg: &RecvMessage,
+        region: FlashRegion,
+        page: u32,
+        data: LenLimit<Leased<R, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<FlashError>> {
+        let word = self.writable_page_word(msg.sender, region, page)?;
+        self.read_lease(data)?;
+
+        // Programming over programmed flash corrupts it, and whatever was
+        // there was presumably wanted.
+        if !self.page_is_erased(word) {
+            return Err(FlashError::NotErased.into());
+        }
+
+        self.program_page(region, word)?;
+        self.verify_page(region, word)?;
+        Ok(())
+    }
+
+    fn verify(
+        &mut self,
+        msg: &RecvMessage,
+        region: FlashRegion,
+        page: u32,
+        data: LenLimit<Leased<R, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<FlashError>> {
+        let word = self.page_word(msg.sender, region, page)?;
+        self.read_lease(data)?;
+        self.verify_page(region, word)?;
+        Ok(())
+    }
+
+    fn read(
+        &mut self,
+        msg: &RecvMessage,
+        region: FlashRegion,
+        page: u32,
+        data: LenLimit<Leased<W, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<FlashError>> {
+        let word = self.page_word(msg.sender, region, page)?;
+
+        if self.page_is_erased(word) {
+            return Err(FlashError::Erased.into());
+        }
+
+        // Only read as many flash words as the lease needs.
+        let len = data.len();
+        let words = (len + 3) / 4;
+        let mut page = [0u32; PAGE_WORDS];
+        self.read_words(region, word, &mut page[..words])?;
+
+        data.write_range(0..len, &page.as_bytes()[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(())
+    }
+
+    fn write_cmpa(
+        &mut self,
+        msg: &RecvMessage,
+        seal: bool,
+        data: LenLimit<Leased<R, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<FlashError>> {
+        let region = FlashRegion::Cmpa;
+        self.region(msg.sender, region)?;
+        self.read_lease(data)?;
+
+        let page: &[u8; PAGE_SIZE] =
+            self.page.as_bytes().try_into().unwrap_lite();
+        lpc55_romapi::write_cmpa(page, seal).map_err(|e| {
+            ringbuf_entry!(Trace::Rom(e));
+            let info = self.info(region);
+            info.failures = info.failures.wrapping_add(1);
+            FlashError::FlashError
+        })?;
+        ringbuf_entry!(Trace::CmpaWritten { seal });
+
+        let info = self.info(region);
+        info.erases = info.erases.wrapping_add(1);
+        info.programs = info.programs.wrapping_add(1);
+        Ok(())
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let this = unsafe { __this_image.as_ptr() } as u32 & ADDRMASK;
+    let image = if flash::A.contains(&this) {
+        flash::B
+    } else {
+        flash::A
+    };
+
+    let mut server = ServerImpl {
+        flash: drv_lpc55_flash::Flash::new(unsafe {
+            &*lpc55_pac::FLASH::ptr()
+        }),
+        image,
+        info: [RegionInfo::default(); 9],
+        wear: [0; STORAGE_PAGES],
+        wear_seq: 0,
+        // Start so that the first copy we write goes in the first page.
+        wear_page: WEAR_LOG_PAGES - 1,
+        wear_unlogged: 0,
+        page: [0; PAGE_WORDS],
+    };
+    server.load_wear_log();
+
+    let mut incoming = [0u8; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut incoming, &mut server);
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
+
+mod idl {
+    use drv_lpc55_flash_api::{FlashError, FlashRegion, RegionInfo};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
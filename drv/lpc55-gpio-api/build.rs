@@ -4,7 +4,7 @@
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
-    idol::client::build_client_stub(
+    build_util::idol::build_client_stub(
         "../../idl/lpc55-pins.idol",
         "client_stub.rs",
     )?;
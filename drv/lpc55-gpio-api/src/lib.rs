@@ -4,6 +4,7 @@
 
 #![no_std]
 
+use derive_idol_err::IdolError;
 use userlib::{sys_send, FromPrimitive};
 use zerocopy::AsBytes;
 
@@ -200,6 +201,26 @@ pub enum Value {
     One = 1,
 }
 
+#[derive(Copy, Clone, Debug, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum PintError {
+    /// Some bit in the notification mask doesn't correspond to a pin
+    /// interrupt owned by the caller.
+    NotOwner = 1,
+}
+
+/// What on a pin generates its interrupt: edges, or (for as long as it
+/// lasts) a level.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum Sense {
+    Rising = 1,
+    Falling = 2,
+    Both = 3,
+    High = 4,
+    Low = 5,
+}
+
 impl Pins {
     // Calling into the GPIO task each time can be slow, this function
     // allows tasks to get the appropriate values to write manually.
@@ -2,12 +2,92 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::server::build_server_support(
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+
+/// Number of PINT channels, each of which serves one pin.
+const PINT_CHANNELS: usize = 8;
+
+fn main() -> Result<()> {
+    build_util::idol::build_server_support(
         "../../idl/lpc55-pins.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+    )
+    .unwrap();
+
+    let routes = build_lpc55pins::pint_routes()?;
+
+    if build_util::has_feature("pint") {
+        build_util::build_notifications()?;
+        generate_pint_config(&routes)?;
+    } else if let Some(r) = routes.first() {
+        bail!(
+            "task {} routes a pin interrupt, but the `pint` feature is not \
+             enabled",
+            r.task
+        );
+    }
+
+    Ok(())
+}
+
+fn generate_pint_config(routes: &[build_lpc55pins::PintRoute]) -> Result<()> {
+    if routes.len() > PINT_CHANNELS {
+        bail!(
+            "{} pin interrupts are configured, but there are only {} PINT \
+             channels",
+            routes.len(),
+            PINT_CHANNELS
+        );
+    }
+
+    let task_ids = build_util::task_ids();
+    for (i, r) in routes.iter().enumerate() {
+        if task_ids.get(&r.task).is_none() {
+            bail!("pin interrupt for no such task {:?}", r.task);
+        }
+        if let Some(other) = routes[..i].iter().find(|o| o.pin == r.pin) {
+            bail!(
+                "tasks {} and {} both route interrupts from pin {}",
+                other.task,
+                r.task,
+                r.pin
+            );
+        }
+    }
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("pint_config.rs");
+    let mut out =
+        std::fs::File::create(dest_path).context("creating pint_config.rs")?;
+
+    writeln!(
+        out,
+        "pub(crate) const PINT_DISPATCH_TABLE: \
+         [Option<PintDispatch>; {PINT_CHANNELS}] = ["
     )?;
+    for channel in 0..PINT_CHANNELS {
+        match routes.get(channel) {
+            None => writeln!(out, "    None,")?,
+            Some(r) => {
+                let owner = &r.task;
+                writeln!(out, "    Some(PintDispatch {{")?;
+                writeln!(out, "        pin: {},", r.pin)?;
+                writeln!(
+                    out,
+                    "        task: hubris_num_tasks::Task::{owner},"
+                )?;
+                writeln!(
+                    out,
+                    "        mask: crate::notifications::{owner}::{}_MASK,",
+                    r.notification.to_ascii_uppercase().replace('-', "_")
+                )?;
+                writeln!(out, "    }}),")?;
+            }
+        }
+    }
+    writeln!(out, "];")?;
 
     Ok(())
 }
@@ -36,6 +36,31 @@
 //! Request message format: single `u8` giving GPIO number
 //! Returns: Digital value
 //!
+//! # Pin interrupts
+//!
+//! With the `pint` feature, this task also owns the PINT block (in its pin
+//! interrupt mode; we don't use pattern match), and routes interrupts on
+//! pins to the tasks that care about them. A task claims a pin's interrupt
+//! in its own `pins` config:
+//!
+//! ```toml
+//! [[tasks.sprot.config.pins]]
+//! name = "SP_RESET"
+//! pin = {port = 0, pin = 9}
+//! alt = 0
+//! direction = "input"
+//! pint = {notification = "sp-reset"}
+//! ```
+//!
+//! Each such pin takes one of the eight PINT channels, in task order. The
+//! owner then picks what triggers it with `pint_configure` and unmasks it
+//! with `pint_control`, both of which name the pin by the owner's own
+//! notification bit. When the interrupt fires, we post that notification to
+//! the owner. Edge interrupts stay unmasked; a level interrupt is masked
+//! when it fires, and the owner unmasks it again once it's dealt with the
+//! level, so that we're not interrupted for as long as the level lasts.
+//! Only the owner may configure or mask a pin.
+//!
 
 #![no_std]
 #![no_main]
@@ -51,6 +76,8 @@ task_slot!(SYSCON, syscon_driver);
 
 struct ServerImpl<'a> {
     gpio: &'a device::gpio::RegisterBlock,
+    #[cfg(feature = "pint")]
+    pint: pint::Pint,
 }
 
 impl idl::InOrderPinsImpl for ServerImpl<'_> {
@@ -139,6 +166,51 @@ impl idl::InOrderPinsImpl for ServerImpl<'_> {
 
         Ok(())
     }
+
+    fn pint_configure(
+        &mut self,
+        rm: &RecvMessage,
+        mask: u32,
+        sense: Sense,
+    ) -> Result<(), RequestError<PintError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "pint")] {
+                let channels = pint::owned_channels(rm.sender, mask)?;
+                self.pint.configure(channels, sense);
+                Ok(())
+            } else {
+                let _ = (rm, sense);
+                if mask == 0 {
+                    Ok(())
+                } else {
+                    Err(PintError::NotOwner.into())
+                }
+            }
+        }
+    }
+
+    fn pint_control(
+        &mut self,
+        rm: &RecvMessage,
+        disable_mask: u32,
+        enable_mask: u32,
+    ) -> Result<(), RequestError<PintError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "pint")] {
+                let disable = pint::owned_channels(rm.sender, disable_mask)?;
+                let enable = pint::owned_channels(rm.sender, enable_mask)?;
+                self.pint.control(disable, enable);
+                Ok(())
+            } else {
+                let _ = rm;
+                if disable_mask | enable_mask == 0 {
+                    Ok(())
+                } else {
+                    Err(PintError::NotOwner.into())
+                }
+            }
+        }
+    }
 }
 
 #[export_name = "main"]
@@ -147,10 +219,17 @@ fn main() -> ! {
 
     let gpio = unsafe { &*device::GPIO::ptr() };
 
-    let mut server = ServerImpl { gpio };
+    let mut server = ServerImpl {
+        gpio,
+        #[cfg(feature = "pint")]
+        pint: pint::Pint::new(),
+    };
 
     let mut incoming = [0; idl::INCOMING_SIZE];
     loop {
+        #[cfg(feature = "pint")]
+        idol_runtime::dispatch_n(&mut incoming, &mut server);
+        #[cfg(not(feature = "pint"))]
         idol_runtime::dispatch(&mut incoming, &mut server);
     }
 }
@@ -179,8 +258,218 @@ fn turn_on_gpio_clocks() {
     syscon.leave_reset(Peripheral::Gpio1);
 }
 
+#[cfg(feature = "pint")]
+impl idol_runtime::NotificationHandler for ServerImpl<'_> {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::PINT_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::PINT_IRQ_MASK != 0 {
+            self.pint.dispatch();
+        }
+    }
+}
+
+#[cfg(feature = "pint")]
+mod pint {
+    use super::device;
+    use drv_lpc55_gpio_api::{PintError, Sense};
+    use drv_lpc55_syscon_api::{Peripheral, Syscon};
+    use userlib::*;
+
+    pub(crate) struct PintDispatch {
+        /// The pin, numbered as in `Pin`
+        pub(crate) pin: u8,
+        pub(crate) task: hubris_num_tasks::Task,
+        /// Notification bit(s) to post to `task`
+        pub(crate) mask: u32,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/pint_config.rs"));
+
+    pub(crate) struct Pint {
+        regs: &'static device::pint::RegisterBlock,
+        /// What triggers each channel, once enabled
+        sense: [Sense; 8],
+        /// Channels that are unmasked
+        enabled: u8,
+    }
+
+    impl Pint {
+        /// Connects each configured channel to its pin, leaving them all
+        /// masked until their owners ask otherwise.
+        pub(crate) fn new() -> Self {
+            let syscon = Syscon::from(super::SYSCON.get_task_id());
+            syscon.enable_clock(Peripheral::Mux);
+            syscon.leave_reset(Peripheral::Mux);
+            syscon.enable_clock(Peripheral::Pint);
+            syscon.leave_reset(Peripheral::Pint);
+
+            // Safety: as with the GPIO block, these are essentially statics,
+            // and we only touch them through shared references.
+            let inputmux = unsafe { &*device::INPUTMUX::ptr() };
+            let regs = unsafe { &*device::PINT::ptr() };
+
+            // Pin interrupts, not pattern match.
+            regs.pmctrl.write(|w| unsafe { w.bits(0) });
+            regs.cienr.write(|w| unsafe { w.bits(0xFF) });
+            regs.cienf.write(|w| unsafe { w.bits(0xFF) });
+            regs.isel.write(|w| unsafe { w.bits(0) });
+            regs.rise.write(|w| unsafe { w.bits(0xFF) });
+            regs.fall.write(|w| unsafe { w.bits(0xFF) });
+
+            for (channel, entry) in PINT_DISPATCH_TABLE.iter().enumerate() {
+                if let Some(entry) = entry {
+                    inputmux.pintsel[channel]
+                        .write(|w| unsafe { w.bits(u32::from(entry.pin)) });
+                }
+            }
+
+            sys_irq_control(crate::notifications::PINT_IRQ_MASK, true);
+
+            Self {
+                regs,
+                sense: [Sense::Both; 8],
+                enabled: 0,
+            }
+        }
+
+        pub(crate) fn configure(&mut self, channels: u8, sense: Sense) {
+            for channel in 0..8 {
+                if channels & (1 << channel) != 0 {
+                    self.sense[channel] = sense;
+                }
+            }
+            self.apply(channels);
+        }
+
+        pub(crate) fn control(&mut self, disable: u8, enable: u8) {
+            self.enabled = self.enabled & !disable | enable;
+            self.apply(disable | enable);
+        }
+
+        /// Brings the hardware in line with `sense` and `enabled` for
+        /// `channels`.
+        fn apply(&self, channels: u8) {
+            for channel in 0..8 {
+                let bit = 1 << channel;
+                if channels & bit == 0 {
+                    continue;
+                }
+                let enabled = self.enabled & bit != 0;
+                let level =
+                    matches!(self.sense[channel], Sense::High | Sense::Low);
+
+                // For an edge, IENR and IENF enable the rising and falling
+                // edges; for a level, IENR enables it, and IENF selects
+                // whether it's active high.
+                let (rise, fall) = match self.sense[channel] {
+                    Sense::Rising => (enabled, false),
+                    Sense::Falling => (false, enabled),
+                    Sense::Both => (enabled, enabled),
+                    Sense::High => (enabled, true),
+                    Sense::Low => (enabled, false),
+                };
+
+                // Mask while we change things, so as not to be interrupted
+                // by some intermediate state.
+                self.regs.cienr.write(|w| unsafe { w.bits(bit.into()) });
+                self.regs.isel.modify(|r, w| unsafe {
+                    w.bits(set(r.bits(), bit, level))
+                });
+                if fall {
+                    self.regs.sienf.write(|w| unsafe { w.bits(bit.into()) });
+                } else {
+                    self.regs.cienf.write(|w| unsafe { w.bits(bit.into()) });
+                }
+                if !level {
+                    // Forget any edges from before.
+                    self.regs.rise.write(|w| unsafe { w.bits(bit.into()) });
+                    self.regs.fall.write(|w| unsafe { w.bits(bit.into()) });
+                }
+                if rise {
+                    self.regs.sienr.write(|w| unsafe { w.bits(bit.into()) });
+                }
+            }
+        }
+
+        /// Forwards pending interrupts to their owners, masking the level
+        /// ones, and re-enables our interrupts.
+        pub(crate) fn dispatch(&mut self) {
+            let pending = self.regs.ist.read().bits() as u8;
+
+            let mut edges = 0;
+            let mut levels = 0;
+            for channel in 0..8 {
+                let bit = 1 << channel;
+                if pending & bit != 0 {
+                    match self.sense[channel] {
+                        Sense::High | Sense::Low => levels |= bit,
+                        _ => edges |= bit,
+                    }
+                }
+            }
+
+            // Writing IST clears a detected edge, but would flip the active
+            // level of a level interrupt; those we mask instead.
+            self.regs.ist.write(|w| unsafe { w.bits(edges.into()) });
+            self.control(levels, 0);
+
+            for (channel, entry) in PINT_DISPATCH_TABLE.iter().enumerate() {
+                let Some(entry) = entry else { continue };
+                if pending & (1 << channel) != 0 {
+                    let task = TaskId::for_index_and_gen(
+                        entry.task as usize,
+                        Generation::ZERO,
+                    );
+                    let task = sys_refresh_task_id(task);
+                    sys_post(task, entry.mask);
+                }
+            }
+
+            sys_irq_control(crate::notifications::PINT_IRQ_MASK, true);
+        }
+    }
+
+    fn set(bits: u32, bit: u8, value: bool) -> u32 {
+        if value {
+            bits | u32::from(bit)
+        } else {
+            bits & !u32::from(bit)
+        }
+    }
+
+    /// Returns the PINT channels corresponding to the notification bits in
+    /// `mask`, or an error unless `caller` owns all of them.
+    pub(crate) fn owned_channels(
+        caller: TaskId,
+        mask: u32,
+    ) -> Result<u8, PintError> {
+        let mut channels = 0;
+        let mut owned = 0;
+        for (channel, entry) in PINT_DISPATCH_TABLE.iter().enumerate() {
+            if let Some(entry) = entry {
+                if entry.task as usize == caller.index()
+                    && entry.mask & mask != 0
+                {
+                    channels |= 1 << channel;
+                    owned |= entry.mask;
+                }
+            }
+        }
+        if mask & !owned != 0 {
+            return Err(PintError::NotOwner);
+        }
+        Ok(channels)
+    }
+}
+
 mod idl {
-    use drv_lpc55_gpio_api::{Direction, Pin, Value};
+    use drv_lpc55_gpio_api::{Direction, Pin, PintError, Sense, Value};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
+
+#[cfg(feature = "pint")]
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
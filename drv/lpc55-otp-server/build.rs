@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Map of operation names to tasks allowed to call them.
+    #[serde(default)]
+    allowed_callers: BTreeMap<String, Vec<String>>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut cfg =
+        build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    // Anyone may stage and verify, but only the tasks the app names may
+    // commit, since that's what burns the part.
+    cfg.allowed_callers.entry("commit".to_string()).or_default();
+
+    let allowed_callers = build_util::task_ids()
+        .remap_allowed_caller_names_to_ids(&cfg.allowed_callers)?;
+
+    idol::server::build_restricted_server_support(
+        "../../idl/otp.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+        &allowed_callers,
+    )?;
+    build_util::idol::append_interface_hash(
+        "../../idl/otp.idol",
+        "server_stub.rs",
+        build_util::idol::Role::Server,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,317 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Programs the LPC55's CMPA, the customer manufacturing page of the
+//! protected flash region, for manufacturing.
+//!
+//! The CMPA isn't quite OTP: it can be rewritten until it's sealed, at which
+//! point the ROM records a digest of it in its last 32 bytes and refuses any
+//! further writes. But it holds the boot and debug settings, and the hash of
+//! the keys images must be signed with, so a bad write is as good as
+//! permanent once the part has reset. Hence the staging and verification in
+//! the API, and a read-back after every commit.
+//!
+//! Built with the `simulate` feature, we start from a copy of the real CMPA
+//! and program that instead, so a manufacturing image can be rehearsed on
+//! parts that matter. Sealing is refused unless we're built with
+//! `allow-seal`, simulated or not.
+//!
+//! Only the tasks named in the app's `allowed-callers` for `commit` may
+//! commit; with none named, nothing can be.
+//!
+//! The flash server reads and writes the real CMPA for us, the latter through
+//! the ROM's API, since the flash controller is its alone.
+//!
+//! STM32 option bytes don't go through here: the flash options server stages,
+//! verifies and commits them in the same way.
+
+#![no_std]
+#![no_main]
+
+use core::ops::Range;
+use drv_lpc55_flash_api::{Flash, FlashError, FlashRegion};
+use drv_otp_api::{OtpError, OtpStatus, PAGE_SIZE};
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R, W};
+use ringbuf::*;
+use userlib::*;
+
+task_slot!(FLASH, flash);
+
+/// Where the ROM keeps the digest of a sealed CMPA, which is the rest of the
+/// page, and isn't ours to stage.
+const DIGEST_OFFSET: usize = 0x1e0;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Staged { offset: u32, len: u32 },
+    Mismatch,
+    Committed { seal: bool },
+    ReadbackMismatch,
+    Flash(FlashError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// A CMPA's worth of bytes.
+#[derive(Copy, Clone)]
+struct Page([u8; PAGE_SIZE]);
+
+impl Page {
+    const fn new() -> Self {
+        Self([0; PAGE_SIZE])
+    }
+
+    fn is_sealed(&self) -> bool {
+        self.0[DIGEST_OFFSET..].iter().any(|&b| b != 0)
+    }
+}
+
+/// Somewhere to program a CMPA: the real one, or a stand-in.
+trait Cmpa {
+    fn read(&mut self, page: &mut Page) -> Result<(), OtpError>;
+    fn write(&mut self, page: &Page, seal: bool) -> Result<(), OtpError>;
+}
+
+/// The real CMPA, by way of the flash server.
+struct Real(Flash);
+
+impl Cmpa for Real {
+    fn read(&mut self, page: &mut Page) -> Result<(), OtpError> {
+        self.0.read(FlashRegion::Cmpa, 0, &mut page.0).map_err(|e| {
+            ringbuf_entry!(Trace::Flash(e));
+            OtpError::ReadFailed
+        })
+    }
+
+    fn write(&mut self, page: &Page, seal: bool) -> Result<(), OtpError> {
+        self.0.write_cmpa(seal, &page.0).map_err(|e| {
+            ringbuf_entry!(Trace::Flash(e));
+            OtpError::WriteFailed
+        })
+    }
+}
+
+#[cfg(feature = "simulate")]
+struct Simulated {
+    page: Page,
+}
+
+#[cfg(feature = "simulate")]
+impl Cmpa for Simulated {
+    fn read(&mut self, page: &mut Page) -> Result<(), OtpError> {
+        *page = self.page;
+        Ok(())
+    }
+
+    fn write(&mut self, page: &Page, seal: bool) -> Result<(), OtpError> {
+        self.page.0[..DIGEST_OFFSET].copy_from_slice(&page.0[..DIGEST_OFFSET]);
+        if seal {
+            // Anything nonzero will do for the digest the ROM would write.
+            self.page.0[DIGEST_OFFSET..].fill(0xa5);
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `len` bytes at `offset` fall below `limit`.
+fn range(
+    offset: u32,
+    len: usize,
+    limit: usize,
+) -> Result<Range<usize>, OtpError> {
+    let start = offset as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= limit => Ok(start..end),
+        _ => Err(OtpError::BadRange),
+    }
+}
+
+struct ServerImpl<C> {
+    cmpa: C,
+    staged: Option<Page>,
+    verified: bool,
+    commits: u32,
+}
+
+impl<C: Cmpa> idl::InOrderOtpImpl for ServerImpl<C> {
+    fn stage(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        data: LenLimit<Leased<R, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<OtpError>> {
+        let r = range(offset, data.len(), DIGEST_OFFSET)?;
+        self.verified = false;
+        let page = match self.staged.take() {
+            Some(page) => page,
+            None => {
+                let mut page = Page::new();
+                self.cmpa.read(&mut page)?;
+                if page.is_sealed() {
+                    return Err(OtpError::Sealed.into());
+                }
+                page
+            }
+        };
+        let staged = self.staged.insert(page);
+        data.read_range(0..data.len(), &mut staged.0[r])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        ringbuf_entry!(Trace::Staged {
+            offset,
+            len: data.len() as u32,
+        });
+        Ok(())
+    }
+
+    fn read_staged(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        sink: LenLimit<Leased<W, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<OtpError>> {
+        let r = range(offset, sink.len(), PAGE_SIZE)?;
+        let staged = self.staged.as_ref().ok_or(OtpError::NotStaged)?;
+        sink.write_range(0..sink.len(), &staged.0[r])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(())
+    }
+
+    fn verify(
+        &mut self,
+        _: &RecvMessage,
+        expected: LenLimit<Leased<R, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<OtpError>> {
+        if expected.len() != PAGE_SIZE {
+            return Err(OtpError::BadRange.into());
+        }
+        let staged = self.staged.as_ref().ok_or(OtpError::NotStaged)?;
+        let mut page = Page::new();
+        expected
+            .read_range(0..PAGE_SIZE, &mut page.0)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        self.verified = page.0 == staged.0;
+        if self.verified {
+            Ok(())
+        } else {
+            ringbuf_entry!(Trace::Mismatch);
+            Err(OtpError::Mismatch.into())
+        }
+    }
+
+    fn commit(
+        &mut self,
+        _: &RecvMessage,
+        seal: bool,
+    ) -> Result<(), RequestError<OtpError>> {
+        let staged = self.staged.ok_or(OtpError::NotStaged)?;
+        if !self.verified {
+            return Err(OtpError::NotVerified.into());
+        }
+        if seal && !cfg!(feature = "allow-seal") {
+            return Err(OtpError::SealNotAllowed.into());
+        }
+
+        // It may have been sealed some other way, such as over ISP, since
+        // we staged.
+        let mut page = Page::new();
+        self.cmpa.read(&mut page)?;
+        if page.is_sealed() {
+            return Err(OtpError::Sealed.into());
+        }
+
+        self.cmpa.write(&staged, seal)?;
+
+        self.cmpa.read(&mut page)?;
+        if page.0[..DIGEST_OFFSET] != staged.0[..DIGEST_OFFSET]
+            || page.is_sealed() != seal
+        {
+            ringbuf_entry!(Trace::ReadbackMismatch);
+            return Err(OtpError::ReadbackMismatch.into());
+        }
+
+        ringbuf_entry!(Trace::Committed { seal });
+        self.staged = None;
+        self.verified = false;
+        self.commits = self.commits.wrapping_add(1);
+        Ok(())
+    }
+
+    fn abort(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.staged = None;
+        self.verified = false;
+        Ok(())
+    }
+
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        sink: LenLimit<Leased<W, [u8]>, PAGE_SIZE>,
+    ) -> Result<(), RequestError<OtpError>> {
+        let r = range(offset, sink.len(), PAGE_SIZE)?;
+        let mut page = Page::new();
+        self.cmpa.read(&mut page)?;
+        sink.write_range(0..sink.len(), &page.0[r])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(())
+    }
+
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<OtpStatus, RequestError<core::convert::Infallible>> {
+        let mut page = Page::new();
+        let sealed = self.cmpa.read(&mut page).is_ok() && page.is_sealed();
+        Ok(OtpStatus {
+            staged: self.staged.is_some() as u8,
+            verified: self.verified as u8,
+            sealed: sealed as u8,
+            simulated: cfg!(feature = "simulate") as u8,
+            commits: self.commits,
+        })
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let real = Real(Flash::from(FLASH.get_task_id()));
+
+    #[cfg(not(feature = "simulate"))]
+    let cmpa = real;
+
+    // Start from the real thing, so that what we'd program is checked
+    // against what's really there.
+    #[cfg(feature = "simulate")]
+    let cmpa = {
+        let mut real = real;
+        let mut page = Page::new();
+        if real.read(&mut page).is_err() {
+            page = Page::new();
+        }
+        Simulated { page }
+    };
+
+    let mut server = ServerImpl {
+        cmpa,
+        staged: None,
+        verified: false,
+        commits: 0,
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_otp_api::{OtpError, OtpStatus};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
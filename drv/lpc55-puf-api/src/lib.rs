@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! API crate for the LPC55 PUF server.
+//!
+//! PUF keys never leave the hardware: each slot holds the key code for a
+//! 256-bit key that the PUF reconstructs straight into the AES engine, and
+//! callers get back `AES-256-ECB(key, context)`. Different contexts give
+//! unrelated keys, so tasks should use a context unique to each purpose.
+//! Only the tasks the app gives a slot to can use it.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Size of both the context and the key produced by `derive_key`.
+pub const DERIVED_KEY_SZ: usize = 32;
+
+/// Number of key code slots in the keystore.
+pub const SLOT_COUNT: u8 = 5;
+
+/// Slot holding the key that attestation keys are derived from.
+pub const ATTESTATION_SLOT: u8 = 0;
+
+/// Slot holding the key that sprot session keys are derived from.
+pub const SPROT_SESSION_SLOT: u8 = 1;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum PufError {
+    /// The PUF hasn't been started, so it can't generate or rebuild keys.
+    NotStarted = 1,
+    /// The PUF is already running, enrolled either by the ROM or by us.
+    AlreadyEnrolled,
+    /// The hardware refused the command.
+    NotAllowed,
+    /// The PUF reported an error while running the command.
+    HardwareError,
+    BadSlot,
+    /// The slot already has a key code; slots are write-once.
+    SlotOccupied,
+    SlotEmpty,
+    /// Writing the keystore flash failed.
+    FlashError,
+    /// The AES engine is in use by another task; try again.
+    Busy,
+    /// The caller doesn't own the slot.
+    SlotNotAllowed,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct PufStatus {
+    /// Nonzero if the PUF is running.
+    pub started: u8,
+    /// Nonzero if the PUF was enrolled by this server rather than the ROM.
+    pub self_enrolled: u8,
+    /// Bit `n` is set if slot `n` holds a key code.
+    pub slots: u16,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
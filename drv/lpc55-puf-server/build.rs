@@ -0,0 +1,63 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Map of operation names to tasks allowed to call them.
+    #[serde(default)]
+    allowed_callers: BTreeMap<String, Vec<String>>,
+    /// Key slots each task may generate and derive keys from, by task.
+    #[serde(default)]
+    slots: BTreeMap<String, Vec<u8>>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut cfg =
+        build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    // Enrolling is up to the app; generating and deriving keys, to the tasks
+    // that own a slot.
+    cfg.allowed_callers.entry("enroll".to_string()).or_default();
+    for op in ["generate_key", "derive_key"] {
+        if cfg.allowed_callers.contains_key(op) {
+            return Err(format!("name the callers of {op} in `slots`").into());
+        }
+        let tasks = cfg.slots.keys().cloned().collect();
+        cfg.allowed_callers.insert(op.to_string(), tasks);
+    }
+
+    let task_ids = build_util::task_ids();
+    let allowed_callers =
+        task_ids.remap_allowed_caller_names_to_ids(&cfg.allowed_callers)?;
+
+    idol::server::build_restricted_server_support(
+        "../../idl/lpc55-puf.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+        &allowed_callers,
+    )?;
+    build_util::idol::append_interface_hash(
+        "../../idl/lpc55-puf.idol",
+        "server_stub.rs",
+        build_util::idol::Role::Server,
+    )?;
+
+    let out = build_util::out_dir();
+    let mut file = std::fs::File::create(out.join("slots.rs"))?;
+    writeln!(file, "const TASK_SLOTS: &[(usize, &[u8])] = &[")?;
+    for (task, slots) in &cfg.slots {
+        let Some(id) = task_ids.get(task) else {
+            return Err(format!("slots for no such task {task:?}").into());
+        };
+        writeln!(file, "    ({id}, &{slots:?}),")?;
+    }
+    writeln!(file, "];")?;
+
+    Ok(())
+}
@@ -0,0 +1,351 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! LPC55 PUF key storage server.
+//!
+//! This manages PUF keys on behalf of other tasks without ever letting them
+//! see one. Each key slot in the keystore holds the key code for a 256-bit
+//! PUF key generated with index 0, which the PUF can only deliver over its
+//! hidden bus to the AES engine. To use a slot, a caller asks for a key
+//! derived from it: we have the crypto server set HASHCRYPT up to take its
+//! key from the PUF, have the PUF reconstruct the slot's key into it, and
+//! return the caller's context encrypted under that key.
+//!
+//! The keystore is the `puf_keystore` flash region. Its first pages hold the
+//! PUF activation code, if we had to enroll the PUF ourselves (normally the
+//! ROM enrolls it and keeps the activation code in the PFR for DICE); each
+//! following page holds one slot. We read and write it through the flash
+//! server, which owns the flash controller.
+//!
+//! Derived keys are as secret as the PUF key they come from, so each slot
+//! belongs to the tasks the app names for it, and only they can generate or
+//! derive keys from it:
+//!
+//! ```toml
+//! [tasks.puf.config.slots]
+//! sprot = [0]
+//! ```
+//!
+//! Enrolling is refused unless the app names a caller for it in
+//! `allowed-callers`.
+
+#![no_std]
+#![no_main]
+
+use drv_lpc55_crypto_api::{Crypto, CryptoError};
+use drv_lpc55_flash_api::{Flash, FlashError, FlashRegion, PAGE_SIZE};
+use drv_lpc55_puf_api::{PufError, PufStatus, DERIVED_KEY_SZ, SLOT_COUNT};
+use drv_lpc55_syscon_api::{Peripheral, Syscon};
+use idol_runtime::RequestError;
+use lpc55_puf::Puf;
+use ringbuf::*;
+use userlib::*;
+use zerocopy::AsBytes;
+
+task_slot!(CRYPTO, crypto);
+task_slot!(FLASH, flash);
+task_slot!(SYSCON, syscon_driver);
+
+const PAGE_WORDS: usize = PAGE_SIZE / 4;
+
+/// Every PUF key we generate is 256 bits.
+const KEY_LEN: usize = 32;
+const KEYCODE_WORDS: usize = Puf::key_to_keycode_len(KEY_LEN) / 4;
+
+const AC_WORDS: usize = Puf::ACTIVATION_CODE_WORDS;
+/// Pages holding the activation code record: a magic word followed by the
+/// activation code.
+const AC_PAGES: u32 = (((AC_WORDS + 1) * 4 + PAGE_SIZE - 1) / PAGE_SIZE) as u32;
+const AC_MAGIC: u32 = 0x4341_4650; // "PFAC"
+const KC_MAGIC: u32 = 0x434b_4650; // "PFKC"
+
+const _: () = assert!(
+    (AC_PAGES + SLOT_COUNT as u32) * PAGE_SIZE as u32
+        <= memory_map::puf_keystore::A.end - memory_map::puf_keystore::A.start,
+    "puf_keystore region is too small"
+);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    StartedByRom,
+    Started,
+    StartFailed,
+    Enrolled,
+    KeyGenerated(u8),
+    SlotNotAllowed(u8, u16),
+    Flash(FlashError),
+    Crypto(CryptoError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Reads the start of a keystore page into `out`, or returns `false` if the
+/// page is erased.
+fn read_page(page: u32, out: &mut [u32]) -> Result<bool, PufError> {
+    let flash = Flash::from(FLASH.get_task_id());
+    match flash.read(FlashRegion::PufKeystore, page, out.as_bytes_mut()) {
+        Ok(()) => Ok(true),
+        Err(FlashError::Erased) => Ok(false),
+        Err(e) => {
+            ringbuf_entry!(Trace::Flash(e));
+            Err(PufError::FlashError)
+        }
+    }
+}
+
+fn write_page(page: u32, data: &[u32; PAGE_WORDS]) -> Result<(), PufError> {
+    let flash = Flash::from(FLASH.get_task_id());
+    let region = FlashRegion::PufKeystore;
+    flash
+        .erase(region, page)
+        .and_then(|()| flash.program(region, page, data.as_bytes()))
+        .map_err(|e| {
+            ringbuf_entry!(Trace::Flash(e));
+            PufError::FlashError
+        })
+}
+
+/// Checks that `caller` owns `slot`.
+fn check_slot(caller: TaskId, slot: u8) -> Result<(), PufError> {
+    let owned = TASK_SLOTS
+        .iter()
+        .any(|(task, slots)| *task == caller.index() && slots.contains(&slot));
+    if owned {
+        Ok(())
+    } else {
+        ringbuf_entry!(Trace::SlotNotAllowed(slot, caller.index() as u16));
+        Err(PufError::SlotNotAllowed)
+    }
+}
+
+fn slot_page(slot: u8) -> Result<u32, PufError> {
+    if slot < SLOT_COUNT {
+        Ok(AC_PAGES + slot as u32)
+    } else {
+        Err(PufError::BadSlot)
+    }
+}
+
+/// Reads the key code stored in `slot`, if any.
+fn read_keycode(slot: u8) -> Result<Option<[u32; KEYCODE_WORDS]>, PufError> {
+    let mut record = [0u32; KEYCODE_WORDS + 1];
+    if !read_page(slot_page(slot)?, &mut record)? || record[0] != KC_MAGIC {
+        return Ok(None);
+    }
+    let mut keycode = [0; KEYCODE_WORDS];
+    keycode.copy_from_slice(&record[1..]);
+    Ok(Some(keycode))
+}
+
+/// Reads the activation code we stored when enrolling, if any.
+fn read_activation_code(ac: &mut [u32; AC_WORDS]) -> Result<bool, PufError> {
+    let mut page = [0u32; PAGE_WORDS];
+    let mut filled = 0;
+    for p in 0..AC_PAGES {
+        if !read_page(p, &mut page)? {
+            return Ok(false);
+        }
+        let words = if p == 0 {
+            if page[0] != AC_MAGIC {
+                return Ok(false);
+            }
+            &page[1..]
+        } else {
+            &page[..]
+        };
+        let n = usize::min(words.len(), AC_WORDS - filled);
+        ac[filled..filled + n].copy_from_slice(&words[..n]);
+        filled += n;
+    }
+    Ok(true)
+}
+
+fn write_activation_code(ac: &[u32; AC_WORDS]) -> Result<(), PufError> {
+    let mut page = [0u32; PAGE_WORDS];
+    let mut written = 0;
+    for p in 0..AC_PAGES {
+        page.fill(0);
+        let words = if p == 0 {
+            page[0] = AC_MAGIC;
+            &mut page[1..]
+        } else {
+            &mut page[..]
+        };
+        let n = usize::min(words.len(), AC_WORDS - written);
+        words[..n].copy_from_slice(&ac[written..written + n]);
+        written += n;
+        write_page(p, &page)?;
+    }
+    Ok(())
+}
+
+struct ServerImpl<'a> {
+    puf: Puf<'a>,
+    crypto: Crypto,
+    self_enrolled: bool,
+}
+
+impl ServerImpl<'_> {
+    fn crypto_err(e: CryptoError) -> PufError {
+        ringbuf_entry!(Trace::Crypto(e));
+        match e {
+            CryptoError::Busy => PufError::Busy,
+            _ => PufError::HardwareError,
+        }
+    }
+}
+
+impl idl::InOrderPufImpl for ServerImpl<'_> {
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<PufStatus, RequestError<core::convert::Infallible>> {
+        let mut slots = 0;
+        for slot in 0..SLOT_COUNT {
+            if matches!(read_keycode(slot), Ok(Some(_))) {
+                slots |= 1 << slot;
+            }
+        }
+        Ok(PufStatus {
+            started: self.puf.is_started() as u8,
+            self_enrolled: self.self_enrolled as u8,
+            slots,
+        })
+    }
+
+    fn enroll(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<PufError>> {
+        if self.puf.is_started() {
+            return Err(PufError::AlreadyEnrolled.into());
+        }
+
+        let mut ac = [0; AC_WORDS];
+        if read_activation_code(&mut ac)? {
+            // We enrolled before but couldn't start at boot; enrolling again
+            // would invalidate every key code we've handed out.
+            return Err(PufError::AlreadyEnrolled.into());
+        }
+        if !self.puf.is_enroll_allowed() {
+            return Err(PufError::NotAllowed.into());
+        }
+        if !self.puf.enroll(&mut ac) {
+            return Err(PufError::HardwareError.into());
+        }
+        write_activation_code(&ac)?;
+        ringbuf_entry!(Trace::Enrolled);
+
+        if !self.puf.start(&ac) {
+            ringbuf_entry!(Trace::StartFailed);
+            return Err(PufError::HardwareError.into());
+        }
+        self.self_enrolled = true;
+        Ok(())
+    }
+
+    fn generate_key(
+        &mut self,
+        msg: &RecvMessage,
+        slot: u8,
+    ) -> Result<(), RequestError<PufError>> {
+        check_slot(msg.sender, slot)?;
+        if read_keycode(slot)?.is_some() {
+            return Err(PufError::SlotOccupied.into());
+        }
+        if !self.puf.is_started() {
+            return Err(PufError::NotStarted.into());
+        }
+        if !self.puf.is_generatekey_allowed() {
+            return Err(PufError::NotAllowed.into());
+        }
+
+        let mut page = [0u32; PAGE_WORDS];
+        page[0] = KC_MAGIC;
+        // Index 0 keys go only to the AES engine, never to software.
+        if !self.puf.generate_keycode(
+            0,
+            KEY_LEN,
+            &mut page[1..1 + KEYCODE_WORDS],
+        ) {
+            return Err(PufError::HardwareError.into());
+        }
+        write_page(slot_page(slot)?, &page)?;
+        ringbuf_entry!(Trace::KeyGenerated(slot));
+        Ok(())
+    }
+
+    fn derive_key(
+        &mut self,
+        msg: &RecvMessage,
+        slot: u8,
+        context: [u8; DERIVED_KEY_SZ],
+    ) -> Result<[u8; DERIVED_KEY_SZ], RequestError<PufError>> {
+        check_slot(msg.sender, slot)?;
+        let keycode = read_keycode(slot)?.ok_or(PufError::SlotEmpty)?;
+        if !self.puf.is_started() {
+            return Err(PufError::NotStarted.into());
+        }
+
+        self.crypto.aes_puf_begin().map_err(Self::crypto_err)?;
+        if !self.puf.get_key_to_bus(&keycode) {
+            // Release the engine; we don't care whether this succeeds.
+            let _ = self.crypto.cancel();
+            return Err(PufError::HardwareError.into());
+        }
+
+        let mut key = [0; DERIVED_KEY_SZ];
+        self.crypto
+            .aes_puf_ecb(&context, &mut key)
+            .map_err(Self::crypto_err)?;
+        Ok(key)
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    // SAFETY: we're the only task that has the PUF mapped.
+    let peripherals = unsafe { lpc55_pac::Peripherals::steal() };
+    let puf = Puf::new(&peripherals.PUF);
+
+    let mut self_enrolled = false;
+    if puf.is_started() {
+        ringbuf_entry!(Trace::StartedByRom);
+    } else {
+        let mut ac = [0; AC_WORDS];
+        if read_activation_code(&mut ac) == Ok(true) {
+            let syscon = Syscon::from(SYSCON.get_task_id());
+            syscon.enable_clock(Peripheral::Puf);
+            syscon.leave_reset(Peripheral::Puf);
+            puf.power_on();
+
+            if puf.start(&ac) {
+                ringbuf_entry!(Trace::Started);
+                self_enrolled = true;
+            } else {
+                ringbuf_entry!(Trace::StartFailed);
+            }
+        }
+    }
+
+    let mut server = ServerImpl {
+        puf,
+        crypto: Crypto::from(CRYPTO.get_task_id()),
+        self_enrolled,
+    };
+    let mut incoming = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch(&mut incoming, &mut server);
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/slots.rs"));
+
+mod idl {
+    use super::{PufError, PufStatus};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
@@ -5,154 +5,72 @@
 //! Driver for the LPC55 random number generator.
 //!
 //! Use the rng-api crate to interact with this driver.
+//!
+//! Output from the hardware is health tested and used to seed a ChaCha20
+//! DRBG, which is what clients actually read from; see `drv_rng_api::health`.
 
 #![no_std]
 #![no_main]
 
 use core::mem::size_of;
 use drv_lpc55_syscon_api::{Peripheral, Syscon};
-use drv_rng_api::RngError;
+use drv_rng_api::health::{EntropySource, HealthChecked, ReseedingRng};
+use drv_rng_api::{RngError, RngHealthStats};
 use idol_runtime::{ClientError, RequestError};
 use rand_chacha::ChaCha20Rng;
-use rand_core::block::{BlockRng, BlockRngCore};
-use rand_core::{impls, Error, RngCore, SeedableRng};
+use rand_core::{Error, RngCore};
 use userlib::*;
 
 use lpc55_pac as device;
 
 task_slot!(SYSCON, syscon_driver);
 
-struct Lpc55Core {
+struct Lpc55Rng {
     pmc: &'static lpc55_pac::pmc::RegisterBlock,
     rng: &'static lpc55_pac::rng::RegisterBlock,
     syscon: Syscon,
 }
 
-impl Lpc55Core {
+impl Lpc55Rng {
     fn new() -> Self {
         let syscon = SYSCON.get_task_id();
-        Lpc55Core {
+        Lpc55Rng {
             pmc: unsafe { &*device::PMC::ptr() },
             rng: unsafe { &*device::RNG::ptr() },
             syscon: Syscon::from(syscon),
         }
     }
-}
-
-impl BlockRngCore for Lpc55Core {
-    type Item = u32;
-    type Results = [u32; 1];
-
-    fn generate(&mut self, results: &mut Self::Results) {
-        results[0] = self.rng.random_number.read().bits();
-    }
-}
-
-struct Lpc55Rng(BlockRng<Lpc55Core>);
-
-impl Lpc55Rng {
-    fn new() -> Self {
-        Lpc55Rng(BlockRng::new(Lpc55Core::new()))
-    }
 
     fn init(&self) {
-        self.0
-            .core
-            .pmc
-            .pdruncfg0
-            .modify(|_, w| w.pden_rng().poweredon());
+        self.pmc.pdruncfg0.modify(|_, w| w.pden_rng().poweredon());
 
-        self.0.core.syscon.enable_clock(Peripheral::Rng);
+        self.syscon.enable_clock(Peripheral::Rng);
 
-        self.0.core.syscon.enter_reset(Peripheral::Rng);
-        self.0.core.syscon.leave_reset(Peripheral::Rng);
+        self.syscon.enter_reset(Peripheral::Rng);
+        self.syscon.leave_reset(Peripheral::Rng);
     }
 }
 
-impl RngCore for Lpc55Rng {
-    fn next_u32(&mut self) -> u32 {
-        self.0.next_u32()
-    }
-    fn next_u64(&mut self) -> u64 {
-        self.0.next_u64()
-    }
-    fn fill_bytes(&mut self, bytes: &mut [u8]) {
-        self.0.fill_bytes(bytes)
-    }
-    fn try_fill_bytes(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
-        if self.0.core.pmc.pdruncfg0.read().pden_rng().bits() {
-            return Err(RngError::PoweredOff.into());
+impl EntropySource for Lpc55Rng {
+    fn next_word(&mut self) -> Result<u32, RngError> {
+        if self.pmc.pdruncfg0.read().pden_rng().bits() {
+            return Err(RngError::PoweredOff);
         }
 
-        self.0.try_fill_bytes(bytes)
+        Ok(self.rng.random_number.read().bits())
     }
-}
-
-// low-budget rand::rngs::adapter::ReseedingRng w/o fork stuff
-struct ReseedingRng<T: SeedableRng> {
-    inner: T,
-    reseeder: Lpc55Rng,
-    threshold: usize,
-    bytes_until_reseed: usize,
-}
 
-impl<T> ReseedingRng<T>
-where
-    T: SeedableRng,
-{
-    fn new(mut reseeder: Lpc55Rng, threshold: usize) -> Result<Self, Error> {
-        use ::core::usize::MAX;
-
-        let threshold = if threshold == 0 { MAX } else { threshold };
-
-        // try_trait_v2 is still experimental
-        let inner = match T::from_rng(&mut reseeder) {
-            Ok(rng) => rng,
-            Err(err) => return Err(err),
-        };
-        Ok(ReseedingRng {
-            inner,
-            reseeder,
-            threshold,
-            bytes_until_reseed: threshold,
-        })
-    }
-}
-
-impl<T> RngCore for ReseedingRng<T>
-where
-    T: SeedableRng + RngCore,
-{
-    fn next_u32(&mut self) -> u32 {
-        impls::next_u32_via_fill(self)
-    }
-    fn next_u64(&mut self) -> u64 {
-        impls::next_u64_via_fill(self)
-    }
-    fn fill_bytes(&mut self, dest: &mut [u8]) {
-        self.try_fill_bytes(dest)
-            .expect("Failed to get entropy from RNG.")
-    }
-    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
-        let num_bytes = dest.len();
-        if num_bytes >= self.bytes_until_reseed || num_bytes >= self.threshold {
-            // try_trait_v2 is still experimental
-            self.inner = match T::from_rng(&mut self.reseeder) {
-                Ok(rng) => rng,
-                Err(e) => return Err(e),
-            };
-            self.bytes_until_reseed = self.threshold;
-        } else {
-            self.bytes_until_reseed -= num_bytes;
-        }
-        self.inner.try_fill_bytes(dest)
+    fn restart(&mut self) -> Result<(), RngError> {
+        self.init();
+        Ok(())
     }
 }
 
-struct Lpc55RngServer(ReseedingRng<ChaCha20Rng>);
+struct Lpc55RngServer(ReseedingRng<ChaCha20Rng, Lpc55Rng>);
 
 impl Lpc55RngServer {
-    fn new(reseeder: Lpc55Rng, threshold: usize) -> Result<Self, Error> {
+    fn new(rng: Lpc55Rng, threshold: usize) -> Result<Self, Error> {
+        let reseeder = HealthChecked::new(rng);
         Ok(Lpc55RngServer(ReseedingRng::new(reseeder, threshold)?))
     }
 }
@@ -184,6 +102,13 @@ impl idl::InOrderRngImpl for Lpc55RngServer {
         }
         Ok(cnt)
     }
+
+    fn health_stats(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<RngHealthStats, RequestError<core::convert::Infallible>> {
+        Ok(self.0.stats())
+    }
 }
 
 #[export_name = "main"]
@@ -202,7 +127,7 @@ fn main() -> ! {
 }
 
 mod idl {
-    use drv_rng_api::RngError;
+    use drv_rng_api::{RngError, RngHealthStats};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
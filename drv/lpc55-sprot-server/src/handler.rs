@@ -5,9 +5,10 @@
 use crate::Trace;
 use crc::{Crc, CRC_32_CKSUM};
 use drv_sprot_api::{
-    DumpReq, DumpRsp, ReqBody, Request, Response, RotIoStats, RotState,
-    RotStatus, RspBody, SprocketsError, SprotError, SprotProtocolError,
-    UpdateReq, UpdateRsp, CURRENT_VERSION, MIN_VERSION, REQUEST_BUF_SIZE,
+    CertReq, CertRsp, DumpReq, DumpRsp, MeasurementReq, MeasurementRsp,
+    ReqBody, Request, Response, RotIoStats, RotState, RotStatus, RspBody,
+    SprocketsError, SprotError, SprotProtocolError, UpdateReq, UpdateRsp,
+    CURRENT_VERSION, MAX_BLOB_SIZE, MIN_VERSION, REQUEST_BUF_SIZE,
     RESPONSE_BUF_SIZE,
 };
 use drv_update_api::{Update, UpdateStatus};
@@ -17,8 +18,13 @@ use ringbuf::ringbuf_entry_root as ringbuf_entry;
 use sprockets_rot::RotSprocket;
 use userlib::{task_slot, UnwrapLite};
 
+mod certs;
+mod measurements;
 mod sprockets;
 
+use certs::CertStore;
+use measurements::Measurements;
+
 task_slot!(UPDATE_SERVER, update_server);
 task_slot!(DUMPER, dumper);
 
@@ -41,7 +47,12 @@ pub struct StartupState {
 pub struct Handler {
     sprocket: RotSprocket,
     update: Update,
+    certs: CertStore,
+    measurements: Measurements,
     startup_state: StartupState,
+
+    /// Any blob to send along with the response we're building
+    blob: [u8; MAX_BLOB_SIZE],
 }
 
 impl Handler {
@@ -49,14 +60,23 @@ impl Handler {
         Handler {
             sprocket: crate::handler::sprockets::init(),
             update: Update::from(UPDATE_SERVER.get_task_id()),
+            certs: CertStore::new(),
+            measurements: Measurements::new(),
             startup_state: StartupState {
                 bootrom_crc32: CRC32.checksum(&bootrom().data[..]),
                 max_request_size: REQUEST_BUF_SIZE.try_into().unwrap_lite(),
                 max_response_size: RESPONSE_BUF_SIZE.try_into().unwrap_lite(),
             },
+            blob: [0; MAX_BLOB_SIZE],
         }
     }
 
+    /// Resets anything that describes the SP's current boot.
+    pub fn sp_reset(&mut self) {
+        ringbuf_entry!(Trace::SpReset);
+        self.measurements.sp_reset();
+    }
+
     /// Serialize and return a `SprotError::FlowError`
     pub fn flow_error(&self, tx_buf: &mut [u8; RESPONSE_BUF_SIZE]) -> usize {
         let body = Err(SprotProtocolError::FlowError.into());
@@ -70,8 +90,9 @@ impl Handler {
         stats: &mut RotIoStats,
     ) -> usize {
         stats.rx_received = stats.rx_received.wrapping_add(1);
+        let mut blob_len = 0;
         let rsp_body = match Request::unpack(rx_buf) {
-            Ok(request) => self.handle_request(request, stats),
+            Ok(request) => self.handle_request(request, stats, &mut blob_len),
             Err(e) => {
                 ringbuf_entry!(Trace::Err(e));
                 stats.rx_invalid = stats.rx_invalid.wrapping_add(1);
@@ -79,13 +100,16 @@ impl Handler {
             }
         };
 
-        Response::pack(&rsp_body, tx_buf)
+        Response::pack_with_slice(&rsp_body, tx_buf, &self.blob[..blob_len])
     }
 
+    /// Handles a request, leaving any blob for the response at the start of
+    /// `self.blob`, `blob_len` bytes long.
     pub fn handle_request(
         &mut self,
         req: Request,
         stats: &mut RotIoStats,
+        blob_len: &mut usize,
     ) -> Result<RspBody, SprotError> {
         match req.body {
             ReqBody::Status => {
@@ -158,6 +182,60 @@ impl Handler {
                 self.update.reset()?;
                 Ok(RspBody::Ok)
             }
+            ReqBody::Certs(cert_req) => Ok(RspBody::Certs(
+                self.handle_cert_request(cert_req, req.blob, blob_len),
+            )),
+            ReqBody::Measurements(m_req) => Ok(RspBody::Measurements(
+                self.handle_measurement_request(m_req, req.blob, blob_len),
+            )),
         }
     }
+
+    fn handle_cert_request(
+        &mut self,
+        req: CertReq,
+        blob: &[u8],
+        blob_len: &mut usize,
+    ) -> CertRsp {
+        let rsp = match req {
+            CertReq::Count => Ok(CertRsp::Count(self.certs.count())),
+            CertReq::Len { index } => self.certs.len(index).map(CertRsp::Len),
+            CertReq::Read { index, offset } => {
+                self.certs.read(index, offset, &mut self.blob).map(|n| {
+                    *blob_len = n;
+                    CertRsp::Read
+                })
+            }
+            CertReq::Write { index, offset, len } => self
+                .certs
+                .write(index, offset, len, blob)
+                .map(|()| CertRsp::Written),
+            CertReq::Lock => self.certs.lock().map(|()| CertRsp::Locked),
+        };
+        rsp.unwrap_or_else(CertRsp::Err)
+    }
+
+    fn handle_measurement_request(
+        &mut self,
+        req: MeasurementReq,
+        blob: &[u8],
+        blob_len: &mut usize,
+    ) -> MeasurementRsp {
+        let rsp = match req {
+            MeasurementReq::Extend { index } => self
+                .measurements
+                .extend(index, blob)
+                .map(|()| MeasurementRsp::Extended),
+            MeasurementReq::Read { index } => {
+                self.measurements.read(index).map(MeasurementRsp::Value)
+            }
+            MeasurementReq::Quote { nonce } => {
+                self.measurements.quote(nonce, &mut self.blob).map(|n| {
+                    *blob_len = n;
+                    MeasurementRsp::Quote
+                })
+            }
+        };
+        rsp.unwrap_or_else(MeasurementRsp::Err)
+    }
 }
@@ -0,0 +1,683 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Flash-backed storage for the device identity certificate chain.
+//!
+//! Each certificate gets a slot of two copies, each two storage pages long:
+//! a header (with a generation number and a CRC) followed by the
+//! certificate. A new certificate is written to the copy that isn't current,
+//! header page last, so until it's complete and checked the old one is still
+//! what we find at boot. We have as many slots as our partition of storage
+//! has room for, up to `MAX_CERTS`.
+//!
+//! Certificates arrive in chunks, which we gather in RAM until the last one.
+//! A chunk we've already taken is accepted again, so that the SP can retry
+//! one whose response it lost.
+//!
+//! Once the chain is provisioned, the SP locks it, and we refuse writes from
+//! then on. The lock lives in the key-value store, so that it can't be lifted
+//! by erasing our partition.
+
+use crate::Trace;
+use crc::{Crc, CRC_32_ISCSI};
+use drv_lpc55_flash_api::{Flash, FlashError, FlashRegion, PAGE_SIZE};
+use drv_sprot_api::CertError;
+use ringbuf::ringbuf_entry_root as ringbuf_entry;
+use task_kv_store_api::{Key, KvError, KvStore};
+use userlib::{task_slot, UnwrapLite};
+use zerocopy::{AsBytes, FromBytes};
+
+task_slot!(FLASH, flash);
+task_slot!(KV_STORE, kv_store);
+
+/// Most certificates we keep, however big our partition
+const MAX_CERTS: usize = 4;
+
+const COPY_PAGES: usize = 2;
+const COPY_SIZE: usize = COPY_PAGES * PAGE_SIZE;
+const SLOT_PAGES: usize = 2 * COPY_PAGES;
+
+/// Largest certificate a slot can hold
+const MAX_CERT_SIZE: usize = COPY_SIZE - HEADER_SIZE;
+
+const MAGIC: u32 = 0x5452_4543; // "CERT"
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+struct Header {
+    magic: u32,
+    /// Goes up by one each time the slot is written
+    generation: u32,
+    len: u32,
+    /// CRC of the fields before it and the certificate
+    checksum: u32,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+impl Header {
+    fn expected_checksum(&self, cert: &[u8]) -> u32 {
+        let mut digest = CRC32.digest();
+        digest.update(&self.as_bytes()[..HEADER_SIZE - 4]);
+        digest.update(cert);
+        digest.finalize()
+    }
+}
+
+/// Where the current copy of a certificate is
+#[derive(Copy, Clone)]
+struct Slot {
+    copy: u32,
+    generation: u32,
+    len: u32,
+}
+
+/// A certificate we're receiving
+struct Pending {
+    index: usize,
+    len: usize,
+    /// Bytes received so far, from the start
+    received: usize,
+    /// Whether all of it has been received and written to flash
+    stored: bool,
+}
+
+/// Returns the page of our storage partition holding page `n` of `copy` of
+/// certificate `index`.
+fn page(index: usize, copy: u32, n: usize) -> u32 {
+    ((index as u32 * 2 + copy) * COPY_PAGES as u32) + n as u32
+}
+
+/// Returns true if generation `a` is later than `b`, allowing for them
+/// wrapping.
+fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Our partition of storage, as the flash server provides it
+pub trait Pages {
+    /// Returns the number of pages in the partition.
+    fn pages(&self) -> Result<u32, FlashError>;
+    fn read(
+        &self,
+        page: u32,
+        buf: &mut [u8; PAGE_SIZE],
+    ) -> Result<(), FlashError>;
+    /// Erases a page and programs it with `data`, which is a page long.
+    fn write(&self, page: u32, data: &[u8]) -> Result<(), FlashError>;
+}
+
+impl Pages for Flash {
+    fn pages(&self) -> Result<u32, FlashError> {
+        Ok(self.region_info(FlashRegion::Storage)?.pages)
+    }
+
+    fn read(
+        &self,
+        page: u32,
+        buf: &mut [u8; PAGE_SIZE],
+    ) -> Result<(), FlashError> {
+        Flash::read(self, FlashRegion::Storage, page, buf)
+    }
+
+    fn write(&self, page: u32, data: &[u8]) -> Result<(), FlashError> {
+        self.erase(FlashRegion::Storage, page)?;
+        self.program(FlashRegion::Storage, page, data)
+    }
+}
+
+/// Where we record that the chain is locked
+pub trait Lock {
+    fn is_locked(&self) -> bool;
+    fn lock(&self) -> Result<(), KvError>;
+}
+
+impl Lock for KvStore {
+    fn is_locked(&self) -> bool {
+        loop {
+            match self.get(Key::CertLock) {
+                Ok(v) => return v != 0,
+                Err(KvError::NotSet) => return false,
+                Err(KvError::ServerRestarted) => continue,
+                // Better to refuse writes we should have taken than to take
+                // ones we shouldn't.
+                Err(_) => return true,
+            }
+        }
+    }
+
+    fn lock(&self) -> Result<(), KvError> {
+        self.advance(Key::CertLock, 1)
+    }
+}
+
+pub struct CertStore<P: Pages = Flash, L: Lock = KvStore> {
+    pages: P,
+    lock: L,
+    /// Whether the chain is locked against writes
+    locked: bool,
+    /// Number of slots our partition has room for
+    capacity: usize,
+    slots: [Option<Slot>; MAX_CERTS],
+    pending: Option<Pending>,
+    /// A copy being written, header and all
+    staging: [u8; COPY_SIZE],
+    page: [u8; PAGE_SIZE],
+}
+
+impl CertStore {
+    pub fn new() -> Self {
+        Self::with(
+            Flash::from(FLASH.get_task_id()),
+            KvStore::from(KV_STORE.get_task_id()),
+        )
+    }
+}
+
+impl<P: Pages, L: Lock> CertStore<P, L> {
+    fn with(pages: P, lock: L) -> Self {
+        let partition = loop {
+            match pages.pages() {
+                Err(FlashError::ServerRestarted) => continue,
+                r => break r.unwrap_or(0),
+            }
+        };
+        let capacity = MAX_CERTS.min(partition as usize / SLOT_PAGES);
+        ringbuf_entry!(Trace::CertCapacity(capacity));
+
+        let mut store = CertStore {
+            locked: lock.is_locked(),
+            pages,
+            lock,
+            capacity,
+            slots: [None; MAX_CERTS],
+            pending: None,
+            staging: [0; COPY_SIZE],
+            page: [0; PAGE_SIZE],
+        };
+        for index in 0..capacity {
+            for copy in 0..2 {
+                let Some(slot) = store.load(index, copy) else {
+                    continue;
+                };
+                match store.slots[index] {
+                    Some(s) if !is_newer(slot.generation, s.generation) => (),
+                    _ => store.slots[index] = Some(slot),
+                }
+            }
+        }
+        store
+    }
+
+    /// Reads a page, retrying if the flash server restarts.
+    fn read_page(&mut self, page: u32) -> Result<(), FlashError> {
+        loop {
+            match self.pages.read(page, &mut self.page) {
+                Err(FlashError::ServerRestarted) => continue,
+                r => return r,
+            }
+        }
+    }
+
+    /// Reads a copy into `staging` and checks it.
+    fn load(&mut self, index: usize, copy: u32) -> Option<Slot> {
+        for n in 0..COPY_PAGES {
+            // An erased or corrupt page means this copy was never finished.
+            self.read_page(page(index, copy, n)).ok()?;
+            self.staging[n * PAGE_SIZE..][..PAGE_SIZE]
+                .copy_from_slice(&self.page);
+        }
+
+        let header = Header::read_from_prefix(&self.staging[..]).unwrap_lite();
+        let len = header.len as usize;
+        if header.magic != MAGIC || len == 0 || len > MAX_CERT_SIZE {
+            return None;
+        }
+        let cert = &self.staging[HEADER_SIZE..][..len];
+        if header.checksum != header.expected_checksum(cert) {
+            ringbuf_entry!(Trace::CertInvalid(index));
+            return None;
+        }
+        Some(Slot {
+            copy,
+            generation: header.generation,
+            len: header.len,
+        })
+    }
+
+    /// Returns the number of certificates in the chain, which ends at the
+    /// first empty slot.
+    pub fn count(&self) -> u32 {
+        self.slots.iter().take_while(|s| s.is_some()).count() as u32
+    }
+
+    fn slot(&self, index: u32) -> Result<Slot, CertError> {
+        let slot = self.slots[..self.capacity]
+            .get(index as usize)
+            .ok_or(CertError::BadIndex)?;
+        slot.ok_or(CertError::NoCert)
+    }
+
+    pub fn len(&self, index: u32) -> Result<u32, CertError> {
+        Ok(self.slot(index)?.len)
+    }
+
+    /// Reads as much of certificate `index`, from `offset`, as fits in
+    /// `out`, returning the number of bytes read.
+    pub fn read(
+        &mut self,
+        index: u32,
+        offset: u32,
+        out: &mut [u8],
+    ) -> Result<usize, CertError> {
+        let slot = self.slot(index)?;
+        if offset >= slot.len {
+            return Err(CertError::BadOffset);
+        }
+        let len = out.len().min((slot.len - offset) as usize);
+
+        let mut pos = HEADER_SIZE + offset as usize;
+        let end = pos + len;
+        let mut out = &mut out[..len];
+        while pos < end {
+            let n = pos / PAGE_SIZE;
+            self.read_page(page(index as usize, slot.copy, n))
+                .map_err(|_| CertError::FlashError)?;
+            let part = &self.page[pos % PAGE_SIZE..];
+            let part = &part[..part.len().min(end - pos)];
+            let (head, rest) =
+                core::mem::take(&mut out).split_at_mut(part.len());
+            head.copy_from_slice(part);
+            out = rest;
+            pos += part.len();
+        }
+        Ok(len)
+    }
+
+    /// Takes a chunk of a certificate `len` bytes long, and stores the
+    /// certificate once we have all of it.
+    pub fn write(
+        &mut self,
+        index: u32,
+        offset: u32,
+        len: u32,
+        chunk: &[u8],
+    ) -> Result<(), CertError> {
+        if self.locked {
+            return Err(CertError::Locked);
+        }
+        let index = index as usize;
+        let (offset, len) = (offset as usize, len as usize);
+        if index >= self.capacity {
+            return Err(CertError::BadIndex);
+        }
+        if len == 0
+            || len > MAX_CERT_SIZE
+            || offset > len
+            || chunk.len() > len - offset
+        {
+            return Err(CertError::BadLength);
+        }
+        let end = offset + chunk.len();
+
+        if offset == 0 {
+            self.pending = Some(Pending {
+                index,
+                len,
+                received: 0,
+                stored: false,
+            });
+        }
+        let p = match &mut self.pending {
+            Some(p) if p.index == index && p.len == len => p,
+            _ => return Err(CertError::OutOfOrder),
+        };
+        if offset > p.received {
+            return Err(CertError::OutOfOrder);
+        }
+
+        let dest = &mut self.staging[HEADER_SIZE + offset..][..chunk.len()];
+        if p.stored {
+            // This can only be a retry of a chunk we've already stored.
+            return if dest == chunk {
+                Ok(())
+            } else {
+                Err(CertError::OutOfOrder)
+            };
+        }
+        dest.copy_from_slice(chunk);
+        p.received = p.received.max(end);
+
+        if p.received == len {
+            self.store(index, len)?;
+            if let Some(p) = &mut self.pending {
+                p.stored = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Locks the chain against writes, for good. There has to be a chain to
+    /// lock; locking it again does nothing.
+    pub fn lock(&mut self) -> Result<(), CertError> {
+        if self.locked {
+            return Ok(());
+        }
+        if self.count() == 0 {
+            return Err(CertError::NoCert);
+        }
+        if let Err(e) = self.lock.lock() {
+            ringbuf_entry!(Trace::CertLockFailed(e));
+            return Err(CertError::FlashError);
+        }
+        ringbuf_entry!(Trace::CertsLocked);
+        self.locked = true;
+        self.pending = None;
+        Ok(())
+    }
+
+    /// Writes the certificate in `staging` to the slot's other copy, and
+    /// makes it current.
+    fn store(&mut self, index: usize, len: usize) -> Result<(), CertError> {
+        let (copy, generation) = match self.slots[index] {
+            Some(s) => (1 - s.copy, s.generation.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        self.staging[HEADER_SIZE + len..].fill(0);
+        let mut header = Header {
+            magic: MAGIC,
+            generation,
+            len: len as u32,
+            checksum: 0,
+        };
+        header.checksum =
+            header.expected_checksum(&self.staging[HEADER_SIZE..][..len]);
+        header.write_to_prefix(&mut self.staging[..]).unwrap_lite();
+
+        // The header page goes last, so that the copy isn't valid until
+        // everything else is in place.
+        let r = (0..COPY_PAGES).rev().try_for_each(|n| {
+            let data = &self.staging[n * PAGE_SIZE..][..PAGE_SIZE];
+            self.pages.write(page(index, copy, n), data)
+        });
+        if let Err(e) = r {
+            ringbuf_entry!(Trace::CertStoreFailed(e));
+            return Err(CertError::FlashError);
+        }
+
+        ringbuf_entry!(Trace::CertStored { index, generation });
+        self.slots[index] = Some(Slot {
+            copy,
+            generation,
+            len: len as u32,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+
+    const PARTITION_PAGES: usize = MAX_CERTS * SLOT_PAGES;
+
+    /// A partition in RAM, which can be told to fail writes
+    struct FakePages {
+        pages: RefCell<[Option<[u8; PAGE_SIZE]>; PARTITION_PAGES]>,
+        size: u32,
+        fail_writes: Cell<bool>,
+    }
+
+    impl FakePages {
+        fn new(size: usize) -> Self {
+            Self {
+                pages: RefCell::new([None; PARTITION_PAGES]),
+                size: size as u32,
+                fail_writes: Cell::new(false),
+            }
+        }
+    }
+
+    impl Pages for &FakePages {
+        fn pages(&self) -> Result<u32, FlashError> {
+            Ok(self.size)
+        }
+
+        fn read(
+            &self,
+            page: u32,
+            buf: &mut [u8; PAGE_SIZE],
+        ) -> Result<(), FlashError> {
+            let p = self.pages.borrow()[page as usize];
+            *buf = p.ok_or(FlashError::Erased)?;
+            Ok(())
+        }
+
+        fn write(&self, page: u32, data: &[u8]) -> Result<(), FlashError> {
+            assert!(page < self.size);
+            // A failed write leaves the page erased, at best.
+            let mut pages = self.pages.borrow_mut();
+            pages[page as usize] = None;
+            if self.fail_writes.get() {
+                return Err(FlashError::FlashError);
+            }
+            pages[page as usize] = Some(data.try_into().unwrap());
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeLock {
+        locked: Cell<bool>,
+    }
+
+    impl Lock for &FakeLock {
+        fn is_locked(&self) -> bool {
+            self.locked.get()
+        }
+
+        fn lock(&self) -> Result<(), KvError> {
+            self.locked.set(true);
+            Ok(())
+        }
+    }
+
+    /// Returns a certificate `len` bytes long, made from `seed`.
+    fn cert(seed: u8, len: usize) -> [u8; MAX_CERT_SIZE] {
+        let mut c = [0; MAX_CERT_SIZE];
+        for (i, b) in c[..len].iter_mut().enumerate() {
+            *b = seed.wrapping_add(i as u8);
+        }
+        c
+    }
+
+    /// Writes `cert` to `index` in chunks of `chunk` bytes.
+    fn write_all(
+        store: &mut CertStore<&FakePages, &FakeLock>,
+        index: u32,
+        cert: &[u8],
+        chunk: usize,
+    ) -> Result<(), CertError> {
+        for (i, c) in cert.chunks(chunk).enumerate() {
+            let offset = (i * chunk) as u32;
+            store.write(index, offset, cert.len() as u32, c)?;
+        }
+        Ok(())
+    }
+
+    fn read_all(
+        store: &mut CertStore<&FakePages, &FakeLock>,
+        index: u32,
+    ) -> [u8; MAX_CERT_SIZE] {
+        let len = store.len(index).unwrap();
+        let mut out = [0; MAX_CERT_SIZE];
+        let mut offset = 0;
+        while offset < len {
+            let end = (offset as usize + 100).min(MAX_CERT_SIZE);
+            let buf = &mut out[offset as usize..end];
+            offset += store.read(index, offset, buf).unwrap() as u32;
+        }
+        out
+    }
+
+    #[test]
+    fn starts_empty() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.count(), 0);
+        assert_eq!(store.len(0), Err(CertError::NoCert));
+        assert_eq!(store.read(0, 0, &mut [0; 8]), Err(CertError::NoCert));
+        assert_eq!(store.len(4), Err(CertError::BadIndex));
+    }
+
+    #[test]
+    fn stores_and_reloads() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        let leaf = cert(1, 700);
+        let root = cert(2, 300);
+        write_all(&mut store, 0, &leaf[..700], 128).unwrap();
+        write_all(&mut store, 1, &root[..300], 512).unwrap();
+        assert_eq!(store.count(), 2);
+        assert_eq!(store.len(0), Ok(700));
+        assert_eq!(read_all(&mut store, 0), leaf);
+
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.count(), 2);
+        assert_eq!(store.len(1), Ok(300));
+        assert_eq!(read_all(&mut store, 0), leaf);
+        assert_eq!(read_all(&mut store, 1), root);
+    }
+
+    #[test]
+    fn read_past_end() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        write_all(&mut store, 0, &cert(1, 10)[..10], 10).unwrap();
+        assert_eq!(store.read(0, 10, &mut [0; 8]), Err(CertError::BadOffset));
+        assert_eq!(store.read(0, 6, &mut [0; 8]), Ok(4));
+    }
+
+    #[test]
+    fn chunks_in_order() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        let c = cert(3, 300);
+
+        // Nothing but the first chunk can start a certificate.
+        assert_eq!(
+            store.write(0, 100, 300, &c[100..200]),
+            Err(CertError::OutOfOrder)
+        );
+        store.write(0, 0, 300, &c[..100]).unwrap();
+        assert_eq!(
+            store.write(0, 200, 300, &c[200..300]),
+            Err(CertError::OutOfOrder)
+        );
+        // Nor can the length change partway.
+        assert_eq!(
+            store.write(0, 100, 301, &c[100..200]),
+            Err(CertError::OutOfOrder)
+        );
+        store.write(0, 100, 300, &c[100..200]).unwrap();
+        store.write(0, 200, 300, &c[200..300]).unwrap();
+        assert_eq!(store.len(0), Ok(300));
+
+        // A stored chunk can be sent again, but not changed.
+        store.write(0, 200, 300, &c[200..300]).unwrap();
+        assert_eq!(
+            store.write(0, 200, 300, &[0; 100]),
+            Err(CertError::OutOfOrder)
+        );
+    }
+
+    #[test]
+    fn bad_lengths() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.write(0, 0, 0, &[]), Err(CertError::BadLength));
+        let too_big = MAX_CERT_SIZE as u32 + 1;
+        assert_eq!(
+            store.write(0, 0, too_big, &[0; 8]),
+            Err(CertError::BadLength)
+        );
+        assert_eq!(store.write(0, 0, 4, &[0; 8]), Err(CertError::BadLength));
+    }
+
+    #[test]
+    fn rewrite_keeps_old_copy_until_done() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        let old = cert(1, 600);
+        write_all(&mut store, 0, &old[..600], 600).unwrap();
+
+        pages.fail_writes.set(true);
+        assert_eq!(
+            write_all(&mut store, 0, &cert(2, 600)[..600], 600),
+            Err(CertError::FlashError)
+        );
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(read_all(&mut store, 0), old);
+
+        pages.fail_writes.set(false);
+        let new = cert(3, 500);
+        write_all(&mut store, 0, &new[..500], 500).unwrap();
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.len(0), Ok(500));
+        assert_eq!(read_all(&mut store, 0), new);
+    }
+
+    #[test]
+    fn newest_copy_wins_across_wrap() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        write_all(&mut store, 0, &cert(1, 50)[..50], 50).unwrap();
+        store.slots[0].as_mut().unwrap().generation = u32::MAX - 1;
+        write_all(&mut store, 0, &cert(2, 50)[..50], 50).unwrap();
+        let new = cert(3, 60);
+        write_all(&mut store, 0, &new[..60], 60).unwrap();
+
+        // The copies now hold generations 0 and u32::MAX.
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.slots[0].unwrap().generation, 0);
+        assert_eq!(store.slots[0].unwrap().copy, 0);
+        assert_eq!(read_all(&mut store, 0), new);
+    }
+
+    #[test]
+    fn sized_from_partition() {
+        let (pages, lock) = (FakePages::new(9), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.capacity, 2);
+        write_all(&mut store, 1, &cert(1, 10)[..10], 10).unwrap();
+        assert_eq!(store.write(2, 0, 10, &[0; 10]), Err(CertError::BadIndex));
+        assert_eq!(store.len(2), Err(CertError::BadIndex));
+
+        let (pages, lock) = (FakePages::new(3), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.write(0, 0, 10, &[0; 10]), Err(CertError::BadIndex));
+    }
+
+    #[test]
+    fn locking() {
+        let (pages, lock) = (FakePages::new(16), FakeLock::default());
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.lock(), Err(CertError::NoCert));
+        write_all(&mut store, 0, &cert(1, 10)[..10], 10).unwrap();
+        store.lock().unwrap();
+        assert!(lock.locked.get());
+        assert_eq!(store.write(0, 0, 10, &[0; 10]), Err(CertError::Locked));
+        // Locking again is harmless.
+        store.lock().unwrap();
+
+        // The lock outlives us.
+        let mut store = CertStore::with(&pages, &lock);
+        assert_eq!(store.write(1, 0, 10, &[0; 10]), Err(CertError::Locked));
+        assert_eq!(store.len(0), Ok(10));
+    }
+}
@@ -0,0 +1,119 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Measurement registers for the SP's boot.
+//!
+//! Beyond the image hashes that DICE covers, the SP extends these with
+//! digests of whatever else it wants attested (the host's boot artifacts,
+//! say), and we sign quotes of them with the DICE alias key, whose
+//! certificate stage0 handed us along with the key itself. Without DICE,
+//! stage0 hands us no alias key, and we sign with a key derived from the
+//! PUF's attestation slot instead; that one is the part's for good, so its
+//! public half can be certified when the part is provisioned.
+//!
+//! The registers are cleared whenever we see the SP reset, so what they hold
+//! only ever describes the SP's current boot. A quote also carries how many
+//! resets we've seen, so that a verifier can tell two boots apart even if
+//! they measured the same things.
+//!
+//! The registers themselves, and the layout of a quote and of what's signed
+//! (which starts with a domain tag), are in the `measurements` crate.
+
+use crate::Trace;
+use dice::{AliasData, SeedBuf};
+use drv_lpc55_puf_api::{Puf, PufError, ATTESTATION_SLOT, DERIVED_KEY_SZ};
+use drv_sprot_api::{MeasurementError, MEASUREMENT_SIZE};
+use measurements::Registers;
+use ringbuf::ringbuf_entry_root as ringbuf_entry;
+use salty::Keypair;
+use stage0_handoff::HandoffData;
+use userlib::task_slot;
+
+task_slot!(PUF, puf);
+
+/// What we derive our signing key from the PUF's attestation slot for
+const QUOTE_KEY_CONTEXT: [u8; DERIVED_KEY_SZ] =
+    *b"sprot measurement quote key v1\0\0";
+
+pub struct Measurements {
+    registers: Registers,
+    /// The alias key, if stage0 handed us one
+    key: Option<Keypair>,
+}
+
+impl Measurements {
+    pub fn new() -> Self {
+        let key = match AliasData::load() {
+            Ok(data) => Some(Keypair::from(data.alias_seed.as_bytes())),
+            Err(_) => {
+                ringbuf_entry!(Trace::NoAliasKey);
+                puf_key()
+            }
+        };
+        Self {
+            registers: Registers::new(),
+            key,
+        }
+    }
+
+    /// Clears every register, since the SP has reset.
+    pub fn sp_reset(&mut self) {
+        self.registers.reset();
+    }
+
+    pub fn extend(
+        &mut self,
+        index: u32,
+        digest: &[u8],
+    ) -> Result<(), MeasurementError> {
+        let index = self.registers.extend(index, digest)?;
+        ringbuf_entry!(Trace::Extended(index));
+        Ok(())
+    }
+
+    pub fn read(
+        &self,
+        index: u32,
+    ) -> Result<[u8; MEASUREMENT_SIZE], MeasurementError> {
+        self.registers.read(index)
+    }
+
+    /// Writes a quote of every register, followed by our signature of it,
+    /// to `out`, returning the length of the two.
+    pub fn quote(
+        &self,
+        nonce: [u8; 32],
+        out: &mut [u8],
+    ) -> Result<usize, MeasurementError> {
+        let key = self.key.as_ref().ok_or(MeasurementError::NoKey)?;
+        Ok(self
+            .registers
+            .quote(nonce, out, |message| key.sign(message).to_bytes()))
+    }
+}
+
+/// Derives our signing key from the PUF's attestation slot, generating the
+/// slot's key first if the part doesn't have one yet.
+fn puf_key() -> Option<Keypair> {
+    let puf = Puf::from(PUF.get_task_id());
+    let mut generated = false;
+    loop {
+        match puf.derive_key(ATTESTATION_SLOT, QUOTE_KEY_CONTEXT) {
+            Ok(seed) => return Some(Keypair::from(&seed)),
+            Err(PufError::SlotEmpty) if !generated => {
+                if let Err(e) = puf.generate_key(ATTESTATION_SLOT) {
+                    ringbuf_entry!(Trace::NoPufKey(e));
+                    return None;
+                }
+                generated = true;
+            }
+            Err(PufError::Busy) => userlib::hl::sleep_for(1),
+            Err(PufError::ServerRestarted) => continue,
+            Err(e) => {
+                ringbuf_entry!(Trace::NoPufKey(e));
+                return None;
+            }
+        }
+    }
+}
@@ -36,9 +36,11 @@
 //! Upper layers may mitigate that, but check on it.
 
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 
-use drv_lpc55_gpio_api::{Direction, Value};
+use drv_lpc55_flash_api::FlashError;
+use drv_lpc55_gpio_api::{Direction, Sense, Value};
+use drv_lpc55_puf_api::PufError;
 use drv_lpc55_spi as spi_core;
 use drv_lpc55_syscon_api::{Peripheral, Syscon};
 use drv_sprot_api::{
@@ -47,6 +49,7 @@ use drv_sprot_api::{
 };
 use lpc55_pac as device;
 use ringbuf::{ringbuf, ringbuf_entry};
+use task_kv_store_api::KvError;
 use userlib::{
     sys_irq_control, sys_recv_closed, task_slot, TaskId, UnwrapLite,
 };
@@ -67,6 +70,16 @@ pub(crate) enum Trace {
     Underrun,
     Err(SprotProtocolError),
     Stats(RotIoStats),
+    CertInvalid(usize),
+    CertStored { index: usize, generation: u32 },
+    CertStoreFailed(FlashError),
+    CertCapacity(usize),
+    CertsLocked,
+    CertLockFailed(KvError),
+    NoAliasKey,
+    NoPufKey(PufError),
+    SpReset,
+    Extended(usize),
 }
 ringbuf!(Trace, 32, Trace::None);
 
@@ -89,6 +102,13 @@ fn configure_spi() -> Io {
     gpio.set_dir(ROT_IRQ, Direction::Output);
     gpio.set_val(ROT_IRQ, Value::One);
 
+    // Have the GPIO server tell us when the SP resets, which is when
+    // SP_RESET (following the SP's active-low reset) falls.
+    gpio.pint_configure(notifications::SP_RESET_MASK, Sense::Falling)
+        .unwrap_lite();
+    gpio.pint_control(0, notifications::SP_RESET_MASK)
+        .unwrap_lite();
+
     // We have two blocks to worry about: the FLEXCOMM for switching
     // between modes and the actual SPI block. These are technically
     // part of the same block for the purposes of a register block
@@ -135,6 +155,7 @@ fn configure_spi() -> Io {
         gpio,
         stats: RotIoStats::default(),
         rot_irq_asserted: false,
+        sp_reset: false,
     }
 }
 
@@ -148,6 +169,9 @@ struct Io {
     /// have to.
     /// ROT_IRQ is deasserted on startup in main.
     rot_irq_asserted: bool,
+
+    /// Set when we see the SP reset, until the handler has been told.
+    sp_reset: bool,
 }
 
 enum IoError {
@@ -155,7 +179,7 @@ enum IoError {
     Flow,
 }
 
-#[export_name = "main"]
+#[cfg_attr(not(test), export_name = "main")]
 fn main() -> ! {
     let mut io = configure_spi();
 
@@ -173,7 +197,14 @@ fn main() -> ! {
     }
 
     loop {
-        let rsp_len = match io.wait_for_request(rx_buf) {
+        let request = io.wait_for_request(rx_buf);
+
+        // Whatever the SP sends next comes from its new boot.
+        if core::mem::take(&mut io.sp_reset) {
+            handler.sp_reset();
+        }
+
+        let rsp_len = match request {
             Ok(rx_len) => {
                 handler.handle(&rx_buf[..rx_len], tx_buf, &mut io.stats)
             }
@@ -207,12 +238,15 @@ impl Io {
                 self.assert_rot_irq();
             }
 
-            sys_recv_closed(
+            let msg = sys_recv_closed(
                 &mut [],
-                notifications::SPI_IRQ_MASK,
+                notifications::SPI_IRQ_MASK | notifications::SP_RESET_MASK,
                 TaskId::KERNEL,
             )
             .unwrap_lite();
+            if msg.operation & notifications::SP_RESET_MASK != 0 {
+                self.sp_reset = true;
+            }
 
             // Is CSn asserted by the SP?
             let intstat = self.spi.intstat();
@@ -89,7 +89,7 @@ fn generate_swd_functions(config: &TaskConfig) -> Result<()> {
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/sp-ctrl.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
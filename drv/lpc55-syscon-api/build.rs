@@ -4,6 +4,9 @@
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
-    idol::client::build_client_stub("../../idl/syscon.idol", "client_stub.rs")?;
+    build_util::idol::build_client_stub(
+        "../../idl/syscon.idol",
+        "client_stub.rs",
+    )?;
     Ok(())
 }
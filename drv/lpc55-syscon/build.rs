@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/syscon.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
@@ -5,32 +5,30 @@
 // Functions for writing to flash for updates
 //
 // This driver is intended to carry as little state as possible. Most of the
-// heavy work and decision making should be handled in other tasks.
+// heavy work and decision making should be handled in other tasks, including
+// the flash itself, which we erase, program and read through the flash
+// server.
 #![no_std]
 #![no_main]
 
 use core::convert::Infallible;
 use core::mem::MaybeUninit;
+use core::ops::Range;
 use drv_caboose::CabooseError;
-use drv_lpc55_flash::{BYTES_PER_FLASH_PAGE, BYTES_PER_FLASH_WORD};
+use drv_lpc55_flash_api::{Flash, FlashError, FlashRegion, PAGE_SIZE};
 use drv_update_api::{
-    SlotId, SwitchDuration, UpdateError, UpdateStatus, UpdateTarget,
+    SlotId, SpImageState, SwitchDuration, UpdateError, UpdateStatus,
+    UpdateTarget,
 };
 use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R};
+use memory_map::flash;
 use stage0_handoff::{HandoffData, ImageVersion, RotBootState};
 use userlib::*;
 use zerocopy::AsBytes;
 
-// We shouldn't actually dereference these. The types are not correct.
-// They are just here to allow a mechanism for getting the addresses.
+// We shouldn't actually dereference this. The type is not correct.
+// It's just here to allow a mechanism for getting the address.
 extern "C" {
-    static __IMAGE_A_BASE: [u32; 0];
-    static __IMAGE_B_BASE: [u32; 0];
-    static __IMAGE_STAGE0_BASE: [u32; 0];
-    static __IMAGE_A_END: [u32; 0];
-    static __IMAGE_B_END: [u32; 0];
-    static __IMAGE_STAGE0_END: [u32; 0];
-
     static __this_image: [u32; 0];
 }
 
@@ -47,14 +45,12 @@ enum UpdateState {
 
 // Note that we could cache the full stage0 image before flashing it.
 // That would reduce our time window of having a partially written stage0.
-struct ServerImpl<'a> {
+struct ServerImpl {
     header_block: Option<[u8; BLOCK_SIZE_BYTES]>,
     state: UpdateState,
     image: Option<UpdateTarget>,
 
-    flash: drv_lpc55_flash::Flash<'a>,
-    hashcrypt: &'a lpc55_pac::hashcrypt::RegisterBlock,
-    syscon: drv_lpc55_syscon_api::Syscon,
+    flash: Flash,
 }
 
 // TODO: This is the size of the vector table on the LPC55. We should
@@ -62,12 +58,12 @@ struct ServerImpl<'a> {
 const MAGIC_OFFSET: usize = 0x130;
 const RESET_VECTOR_OFFSET: usize = 4;
 
-const BLOCK_SIZE_BYTES: usize = BYTES_PER_FLASH_PAGE;
+const BLOCK_SIZE_BYTES: usize = PAGE_SIZE;
 
 const MAX_LEASE: usize = 1024;
 const HEADER_BLOCK: usize = 0;
 
-impl idl::InOrderUpdateImpl for ServerImpl<'_> {
+impl idl::InOrderUpdateImpl for ServerImpl {
     fn prep_image_update(
         &mut self,
         _: &RecvMessage,
@@ -159,7 +155,7 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             flash_page[len..].fill(0);
         }
 
-        do_block_write(&mut self.flash, target, block_num, &flash_page)?;
+        do_block_write(&self.flash, target, block_num, &flash_page)?;
 
         Ok(())
     }
@@ -183,7 +179,7 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         }
 
         do_block_write(
-            &mut self.flash,
+            &self.flash,
             self.image.unwrap_lite(),
             HEADER_BLOCK,
             self.header_block.as_ref().unwrap_lite(),
@@ -262,47 +258,39 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
                 // resetting without burning many monotonic versions, if you
                 // want to do that for some reason.
                 //
-                // The addresses of these pages are as follows (see Figure 13,
-                // "Protected Flash Region," in UM11126 rev 2.4, or the NXP
-                // flash layout spreadsheet):
-                //
-                // Page     Addr        16-byte word number
-                // Scratch  0x9_DE00    0x9DE0
-                // Ping     0x9_E000    0x9E00
-                // Pong     0x9_E200    0x9E20
+                // The flash server knows where these pages are (see Figure
+                // 13, "Protected Flash Region," in UM11126 rev 2.4, or the NXP
+                // flash layout spreadsheet), and lets us read ping and pong,
+                // but only write the scratch page.
 
-                let cfpa_word_number = {
+                let cfpa_region = {
                     // Read the two versions. We do this with smaller buffers so
                     // we don't need 2x 512B buffers to read the entire CFPAs.
                     let mut ping_header = [0u32; 4];
                     let mut pong_header = [0u32; 4];
 
-                    indirect_flash_read(
-                        &mut self.flash,
-                        0x9E00,
-                        core::slice::from_mut(&mut ping_header),
+                    flash_read(
+                        &self.flash,
+                        FlashRegion::CfpaPing,
+                        ping_header.as_bytes_mut(),
                     )?;
-                    indirect_flash_read(
-                        &mut self.flash,
-                        0x9E20,
-                        core::slice::from_mut(&mut pong_header),
+                    flash_read(
+                        &self.flash,
+                        FlashRegion::CfpaPong,
+                        pong_header.as_bytes_mut(),
                     )?;
 
                     // Work out where to read the authoritative contents from.
                     if ping_header[1] >= pong_header[1] {
-                        0x9E00
+                        FlashRegion::CfpaPing
                     } else {
-                        0x9E20
+                        FlashRegion::CfpaPong
                     }
                 };
 
                 // Read current CFPA contents.
                 let mut cfpa = [[0u32; 4]; 512 / 16];
-                indirect_flash_read(
-                    &mut self.flash,
-                    cfpa_word_number,
-                    &mut cfpa,
-                )?;
+                flash_read(&self.flash, cfpa_region, cfpa.as_bytes_mut())?;
 
                 // Increment the monotonic version. The manual doesn't specify
                 // how the version numbers are compared or what happens if they
@@ -323,29 +311,18 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
                 // The last two flash words are a SHA256 hash of the preceding
                 // data. This means we need to compute a SHA256 hash of the
                 // preceding data -- meaning flash words 0 thru 29 inclusive.
-                let cfpa_hash = {
-                    // We leave the hashcrypt unit in reset when unused,
-                    // starting in the `main` function, so we only need to bring
-                    // it _out of_ reset here.
-                    self.syscon
-                        .leave_reset(drv_lpc55_syscon_api::Peripheral::HashAes);
-                    let mut h = drv_lpc55_sha256::Hasher::begin(
-                        self.hashcrypt,
-                        notifications::HASHCRYPT_IRQ_MASK,
-                    );
-                    for chunk in &cfpa[..30] {
-                        h.update(chunk, 0);
-                    }
-                    let hash = h.finish();
-
-                    // Put it back.
-                    self.syscon
-                        .enter_reset(drv_lpc55_syscon_api::Peripheral::HashAes);
-
-                    hash
-                };
-                cfpa[30] = cfpa_hash[..4].try_into().unwrap_lite();
-                cfpa[31] = cfpa_hash[4..].try_into().unwrap_lite();
+                let crypto =
+                    drv_lpc55_crypto_api::Crypto::from(CRYPTO.get_task_id());
+                let cfpa_hash = (|| {
+                    let mut h = drv_lpc55_crypto_api::Sha256::begin(
+                        &crypto,
+                        notifications::CRYPTO_DONE_MASK,
+                    )?;
+                    h.update(cfpa[..30].as_bytes())?;
+                    h.finish()
+                })()
+                .map_err(|_| UpdateError::SecureErr)?;
+                cfpa[30..].as_bytes_mut().copy_from_slice(&cfpa_hash);
 
                 // Recast that as a page-sized byte array because that's what
                 // the update side of the machinery wants. The try_into on the
@@ -361,17 +338,33 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
                 // ROM will check its contents before making it authoritative,
                 // we can fail during this operation without corrupting anything
                 // permanent. Yay!
-                //
-                // Note that the page write machinery uses page numbers. This
-                // should probably change. But, for now, we must divide our word
-                // number by 32.
-                do_raw_page_write(&mut self.flash, 0x9DE0 / 32, &cfpa_bytes)?;
+                do_page_write(
+                    &self.flash,
+                    FlashRegion::CfpaScratch,
+                    0,
+                    cfpa_bytes,
+                )?;
             }
         }
 
         Ok(())
     }
 
+    fn sp_image_state(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<SpImageState, RequestError<UpdateError>> {
+        // We're not an SP.
+        Err(UpdateError::NotImplemented.into())
+    }
+
+    fn commit_image(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<UpdateError>> {
+        Err(UpdateError::NotImplemented.into())
+    }
+
     /// Reset.
     fn reset(
         &mut self,
@@ -385,69 +378,30 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
     }
 }
 
-/// Reads an arbitrary contiguous set of flash words from flash, indirectly,
-/// using the flash controller interface. This allows access to sections of
-/// flash that are not direct-mapped into our task's memory, saving MPU regions.
-///
-/// `flash_word_number` is (as its name suggests) a _word number,_ a 0-based
-/// index of a 16-byte word within flash where reading should begin.
-///
-/// `output` implies the length to read.
-///
-/// The main way this can fail is by ECC error; currently, if this occurs, no
-/// feedback is provided as to _where_ in the region the error occurred. We may
-/// wish to fix this.
-///
-/// This API produces flash words in the form of `[u32; 4]`, because that's how
-/// the hardware produces them. Elements of the array are in ascending address
-/// order when the flash is viewed as bytes. The easiest way to view the
-/// corresponding block of 16 bytes is using `zerocopy::AsBytes` to reinterpret
-/// the array in place.
-fn indirect_flash_read(
-    flash: &mut drv_lpc55_flash::Flash<'_>,
-    flash_word_number: u32,
-    output: &mut [[u32; 4]],
+/// Reads the start of the first page of a flash region, as much as `output`
+/// holds, through the flash server.
+fn flash_read(
+    flash: &Flash,
+    region: FlashRegion,
+    output: &mut [u8],
 ) -> Result<(), UpdateError> {
-    use drv_lpc55_flash::ReadError;
-
-    for (wn, dest) in (flash_word_number..).zip(output) {
-        flash.start_read(wn);
-        loop {
-            // Reading is relatively fast; this loop will most likely not sleep,
-            // most of the time.
-            if let Some(result) = flash.poll_read_result() {
-                match result {
-                    Ok(data) => {
-                        *dest = data;
-                        break;
-                    }
-                    Err(ReadError::IllegalOperation) => {
-                        return Err(UpdateError::FlashIllegalRead);
-                    }
-                    Err(ReadError::Ecc) => {
-                        return Err(UpdateError::EccDoubleErr);
-                    }
-                    Err(ReadError::Fail) => {
-                        return Err(UpdateError::FlashReadFail);
-                    }
-                }
-            }
+    flash.read(region, 0, output).map_err(flash_err)
+}
 
-            // But just in case it needs to:
-
-            flash.enable_interrupt_sources();
-            sys_irq_control(notifications::FLASH_IRQ_MASK, true);
-            // RECV from the kernel cannot produce an error, so ignore it.
-            let _ = sys_recv_closed(
-                &mut [],
-                notifications::FLASH_IRQ_MASK,
-                TaskId::KERNEL,
-            );
-            flash.disable_interrupt_sources();
+fn flash_err(e: FlashError) -> UpdateError {
+    match e {
+        FlashError::OutOfBounds => UpdateError::OutOfBounds,
+        FlashError::Corrupt => UpdateError::EccDoubleErr,
+        FlashError::Erased => UpdateError::FlashReadFail,
+        FlashError::NotAllowed | FlashError::ReadOnly => {
+            UpdateError::FlashIllegalRead
         }
+        FlashError::BadLength
+        | FlashError::NotErased
+        | FlashError::VerifyFailed
+        | FlashError::FlashError
+        | FlashError::ServerRestarted => UpdateError::FlashError,
     }
-
-    Ok(())
 }
 
 // Perform some sanity checking on the header block.
@@ -469,19 +423,14 @@ fn validate_header_block(
     let reset_vector = u32::from_le_bytes(
         block[RESET_VECTOR_OFFSET..][..4].try_into().unwrap_lite(),
     ) & ADDRMASK;
-    let a_base = unsafe { __IMAGE_A_BASE.as_ptr() } as u32 & ADDRMASK;
-    let b_base = unsafe { __IMAGE_B_BASE.as_ptr() } as u32 & ADDRMASK;
-    let stage0_base = unsafe { __IMAGE_STAGE0_BASE.as_ptr() } as u32 & ADDRMASK;
-    let a_end = unsafe { __IMAGE_A_END.as_ptr() } as u32 & ADDRMASK;
-    let b_end = unsafe { __IMAGE_B_END.as_ptr() } as u32 & ADDRMASK;
-    let stage0_end = unsafe { __IMAGE_STAGE0_END.as_ptr() } as u32 & ADDRMASK;
+    let masked = |r: Range<u32>| (r.start & ADDRMASK)..(r.end & ADDRMASK);
 
     // Ensure the image is destined for the right target
     let valid = match target {
-        UpdateTarget::ImageA => (a_base..a_end).contains(&reset_vector),
-        UpdateTarget::ImageB => (b_base..b_end).contains(&reset_vector),
+        UpdateTarget::ImageA => masked(flash::A).contains(&reset_vector),
+        UpdateTarget::ImageB => masked(flash::B).contains(&reset_vector),
         UpdateTarget::Bootloader => {
-            (stage0_base..stage0_end).contains(&reset_vector)
+            masked(flash::STAGE0).contains(&reset_vector)
         }
         _ => false,
     };
@@ -502,172 +451,82 @@ fn validate_header_block(
 /// Performs an erase-write sequence to a single page within a given target
 /// image.
 fn do_block_write(
-    flash: &mut drv_lpc55_flash::Flash<'_>,
+    flash: &Flash,
     img: UpdateTarget,
     block_num: usize,
     flash_page: &[u8; BLOCK_SIZE_BYTES],
 ) -> Result<(), UpdateError> {
-    // The update.idol definition uses usize; our hardware uses u32; convert
-    // here so we don't have to cast everywhere.
-    let page_num = block_num as u32;
+    // The flash server only lets us at the image we aren't running from, and
+    // checks the page is within it.
+    let region = match img {
+        UpdateTarget::ImageA | UpdateTarget::ImageB => {
+            FlashRegion::InactiveImage
+        }
+        UpdateTarget::Bootloader => FlashRegion::Stage0,
+        _ => return Err(UpdateError::BadImageType),
+    };
 
     // Can only update opposite image
     if same_image(img) {
         return Err(UpdateError::RunningImage);
     }
 
-    let write_addr = match target_addr(img, page_num) {
-        Some(addr) => addr,
-        None => return Err(UpdateError::OutOfBounds),
-    };
-
-    // write_addr is a byte address; convert it back to a page number, but this
-    // time, an absolute page number in the flash device.
-    let page_num = write_addr / BYTES_PER_FLASH_PAGE as u32;
+    // The update.idol definition uses usize; the flash server uses u32.
+    let page_num =
+        u32::try_from(block_num).map_err(|_| UpdateError::OutOfBounds)?;
 
-    do_raw_page_write(flash, page_num, flash_page)
+    do_page_write(flash, region, page_num, flash_page)
 }
 
-/// Performs an erase-write sequence to a single page within the raw flash
-/// device. This function is capable of writing outside of any image slot, which
-/// is important for doing CFPA updates. If you're writing to an image slot, use
-/// `do_block_write`.
-fn do_raw_page_write(
-    flash: &mut drv_lpc55_flash::Flash<'_>,
+/// Performs an erase-write sequence to a single page of a flash region. This
+/// can write outside of any image slot, which is important for doing CFPA
+/// updates. If you're writing to an image slot, use `do_block_write`.
+fn do_page_write(
+    flash: &Flash,
+    region: FlashRegion,
     page_num: u32,
-    flash_page: &[u8; BYTES_PER_FLASH_PAGE],
-) -> Result<(), UpdateError> {
-    // We regularly need the number of flash words per flash page below, and
-    // specifically as a u32, so:
-    static_assertions::const_assert_eq!(
-        BYTES_PER_FLASH_PAGE % BYTES_PER_FLASH_WORD,
-        0
-    );
-    const WORDS_PER_PAGE: u32 =
-        (BYTES_PER_FLASH_PAGE / BYTES_PER_FLASH_WORD) as u32;
-
-    // The hardware operates in terms of word numbers, never page numbers.
-    // Convert the page number to the number of the first word in that page.
-    // (This is equivalent to multiplying by 32 but named constants are nice.)
-    let word_num = page_num * WORDS_PER_PAGE;
-
-    // Step one: erase the page. Note that this range is INCLUSIVE. The hardware
-    // will happily erase multiple pages if you let it. We don't want that here.
-    flash.start_erase_range(word_num..=word_num + (WORDS_PER_PAGE - 1));
-    wait_for_erase_or_program(flash)?;
-
-    // Step two: Transfer each 16-byte flash word (page row) into the write
-    // registers in the flash controller.
-    for (i, row) in flash_page.chunks_exact(BYTES_PER_FLASH_WORD).enumerate() {
-        // TODO: this will be unnecessary if array_chunks stabilizes
-        let row: &[u8; BYTES_PER_FLASH_WORD] = row.try_into().unwrap_lite();
-
-        flash.start_write_row(i as u32, row);
-        while !flash.poll_write_result() {
-            // spin - supposed to be very quick in hardware.
-        }
-    }
-
-    // Step three: program the whole page into non-volatile storage by naming
-    // the first word in the target page. (Any word in the page will do,
-    // actually, but we've conveniently got the first word available.)
-    flash.start_program(word_num);
-    wait_for_erase_or_program(flash)?;
-
-    Ok(())
-}
-
-/// Utility function that does an interrupt-driven poll and sleep while the
-/// flash controller finishes a write or erase.
-fn wait_for_erase_or_program(
-    flash: &mut drv_lpc55_flash::Flash<'_>,
+    flash_page: &[u8; BLOCK_SIZE_BYTES],
 ) -> Result<(), UpdateError> {
-    loop {
-        if let Some(result) = flash.poll_erase_or_program_result() {
-            return result.map_err(|_| UpdateError::FlashError);
-        }
-
-        flash.enable_interrupt_sources();
-        sys_irq_control(notifications::FLASH_IRQ_MASK, true);
-        // RECV from the kernel cannot produce an error, so ignore it.
-        let _ = sys_recv_closed(
-            &mut [],
-            notifications::FLASH_IRQ_MASK,
-            TaskId::KERNEL,
-        );
-        flash.disable_interrupt_sources();
-    }
+    flash.erase(region, page_num).map_err(flash_err)?;
+    // This checks that the page took, too.
+    flash
+        .program(region, page_num, flash_page)
+        .map_err(flash_err)
 }
 
 fn same_image(which: UpdateTarget) -> bool {
     get_base(which) == unsafe { __this_image.as_ptr() } as u32
 }
 
-/// Returns the byte address of the first byte of the given flash target slot,
-/// or panics if you're holding it wrong.
-fn get_base(which: UpdateTarget) -> u32 {
-    (match which {
-        UpdateTarget::ImageA => unsafe { __IMAGE_A_BASE.as_ptr() },
-        UpdateTarget::ImageB => unsafe { __IMAGE_B_BASE.as_ptr() },
-        UpdateTarget::Bootloader => unsafe { __IMAGE_STAGE0_BASE.as_ptr() },
-        _ => unreachable!(),
-    }) as u32
-}
-
-fn get_end(which: UpdateTarget) -> u32 {
-    (match which {
-        UpdateTarget::ImageA => unsafe { __IMAGE_A_END.as_ptr() },
-        UpdateTarget::ImageB => unsafe { __IMAGE_B_END.as_ptr() },
-        UpdateTarget::Bootloader => unsafe { __IMAGE_STAGE0_END.as_ptr() },
+/// Returns the flash range of the given target slot, or panics if you're
+/// holding it wrong.
+fn get_range(which: UpdateTarget) -> Range<u32> {
+    match which {
+        UpdateTarget::ImageA => flash::A,
+        UpdateTarget::ImageB => flash::B,
+        UpdateTarget::Bootloader => flash::STAGE0,
         _ => unreachable!(),
-    }) as u32
-}
-
-/// Computes the byte address of the first byte in a particular (slot, page)
-/// combination.
-///
-/// `image_target` designates the flash slot and must be `ImageA`, `ImageB`, or
-/// `Bootloader`, despite containing many other variants. All other choices will
-/// panic. (TODO: fix this when time permits.)
-///
-/// `page_num` designates a flash page (called a block elsewhere in this file, a
-/// 512B unit) within the flash slot. If the page is out range for the target
-/// slot, returns `None`.
-fn target_addr(image_target: UpdateTarget, page_num: u32) -> Option<u32> {
-    let base = get_base(image_target);
-
-    // This is safely calculating addr = base + page_num * PAGE_SIZE
-    let addr = page_num
-        .checked_mul(BLOCK_SIZE_BYTES as u32)?
-        .checked_add(base)?;
-
-    // check addr + PAGE_SIZE <= end
-    if addr.checked_add(BLOCK_SIZE_BYTES as u32)? > get_end(image_target) {
-        return None;
     }
+}
 
-    Some(addr)
+/// Returns the byte address of the first byte of the given flash target slot,
+/// or panics if you're holding it wrong.
+fn get_base(which: UpdateTarget) -> u32 {
+    get_range(which).start
 }
 
-task_slot!(SYSCON, syscon);
+task_slot!(CRYPTO, crypto);
+task_slot!(FLASH, flash);
 task_slot!(JEFE, jefe);
 
 #[export_name = "main"]
 fn main() -> ! {
-    let syscon = drv_lpc55_syscon_api::Syscon::from(SYSCON.get_task_id());
-
-    // Go ahead and put the HASHCRYPT unit into reset.
-    syscon.enter_reset(drv_lpc55_syscon_api::Peripheral::HashAes);
     let mut server = ServerImpl {
         header_block: None,
         state: UpdateState::NoUpdate,
         image: None,
 
-        flash: drv_lpc55_flash::Flash::new(unsafe {
-            &*lpc55_pac::FLASH::ptr()
-        }),
-        hashcrypt: unsafe { &*lpc55_pac::HASHCRYPT::ptr() },
-        syscon,
+        flash: Flash::from(FLASH.get_task_id()),
     };
     let mut incoming = [0u8; idl::INCOMING_SIZE];
 
@@ -680,7 +539,7 @@ include!(concat!(env!("OUT_DIR"), "/consts.rs"));
 include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
 mod idl {
     use super::{CabooseError, ImageVersion, UpdateTarget};
-    use drv_update_api::{SlotId, SwitchDuration};
+    use drv_update_api::{SlotId, SpImageState, SwitchDuration};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -0,0 +1,60 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::io::Write;
+
+/// What the host sees in the device descriptor.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    vendor_id: u16,
+    product_id: u16,
+    manufacturer: String,
+    product: String,
+    serial: String,
+    #[serde(default)]
+    self_powered: bool,
+}
+
+fn main() -> Result<()> {
+    build_util::build_notifications()?;
+    build_util::idol::build_server_support(
+        "../../idl/uart.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+
+    let config = build_util::task_config::<TaskConfig>()?;
+    for s in [&config.manufacturer, &config.product, &config.serial] {
+        if !s.is_ascii() || s.len() > 126 {
+            anyhow::bail!("USB strings must be ASCII, and at most 126 long");
+        }
+    }
+
+    let dest_path = build_util::out_dir().join("usb_config.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+    writeln!(
+        out,
+        "static DEVICE_INFO: usbd::DeviceInfo = usbd::DeviceInfo {{
+    vendor_id: {:#06x},
+    product_id: {:#06x},
+    release: 0x0100,
+    manufacturer: {:?},
+    product: {:?},
+    serial: {:?},
+    max_power_ma: 100,
+    self_powered: {},
+}};",
+        config.vendor_id,
+        config.product_id,
+        config.manufacturer,
+        config.product,
+        config.serial,
+        config.self_powered,
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,299 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A USB serial port (CDC ACM) on the LPC55's high-speed USB controller,
+//! serving the same `Uart` interface as a hardware UART server, so that a
+//! console can move from one to the other without its clients noticing.
+//!
+//! Data from the host goes into a receive queue, and we only arm the OUT
+//! endpoint while there's room in the queue for a full packet, so the host
+//! is flow-controlled rather than losing data. Data for the host goes out a
+//! packet at a time from a transmit queue. A transmit queue that fills up
+//! while nobody on the host is reading fails `write` with `TxFull`, as a
+//! UART's would.
+//!
+//! Breaks the host sends are counted in `UartEvents::breaks`; `send_break`
+//! sends one to the host, as a SERIAL_STATE notification.
+//!
+//! The vendor and product IDs and strings come from the task config.
+
+#![no_std]
+#![no_main]
+
+use drv_lpc55_syscon_api::{Peripheral, Syscon};
+use drv_lpc55_usb::Usb1;
+use drv_uart_api::{UartError, UartEvents};
+use heapless::Deque;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use usbd::cdc_acm::{serial_state, CdcAcm};
+use usbd::{Bus, Class, Device};
+use userlib::*;
+
+task_slot!(SYSCON, syscon_driver);
+
+include!(concat!(env!("OUT_DIR"), "/usb_config.rs"));
+
+/// Endpoint for CDC notifications.
+const COMM_EP: u8 = 1;
+/// Endpoint pair for data.
+const DATA_EP: u8 = 2;
+
+const RX_QUEUE: usize = 1024;
+const TX_QUEUE: usize = 1024;
+/// Largest data packet, at high speed.
+const MAX_PACKET: usize = 512;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Reset { high_speed: bool },
+    Configured,
+    HostBreak,
+    BreakDropped,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct ServerImpl {
+    usb: Usb1,
+    device: Device,
+    cdc: CdcAcm,
+    rx: Deque<u8, RX_QUEUE>,
+    tx: Deque<u8, TX_QUEUE>,
+    /// The data OUT endpoint is waiting for a packet.
+    rx_armed: bool,
+    /// The data IN endpoint holds a packet the host hasn't taken.
+    tx_busy: bool,
+    /// The last packet sent was full, so the host is waiting for a short
+    /// one to end the transfer.
+    tx_needs_zlp: bool,
+    subscriber: Option<(TaskId, u32)>,
+    events: UartEvents,
+}
+
+impl ServerImpl {
+    fn notify(&self) {
+        if let Some((task, bits)) = self.subscriber {
+            sys_post(sys_refresh_task_id(task), bits);
+        }
+    }
+
+    /// Moves data between the queues and the data endpoints as far as
+    /// possible.
+    fn pump(&mut self) {
+        if !self.device.is_configured() {
+            return;
+        }
+        let max = CdcAcm::max_packet(self.usb.is_high_speed());
+
+        if !self.rx_armed && self.rx.capacity() - self.rx.len() >= max {
+            self.usb.arm(DATA_EP);
+            self.rx_armed = true;
+        }
+
+        if !self.tx_busy && (!self.tx.is_empty() || self.tx_needs_zlp) {
+            let mut buf = [0; MAX_PACKET];
+            let n = usize::min(max, self.tx.len());
+            for b in &mut buf[..n] {
+                // Can't fail; we checked the length.
+                *b = self.tx.pop_front().unwrap_or(0);
+            }
+            // Can't block; we track the endpoint's state.
+            let _ = self.usb.write(DATA_EP, &buf[..n]);
+            self.tx_busy = true;
+            self.tx_needs_zlp = n == max;
+        }
+    }
+
+    fn handle_usb(&mut self) -> bool {
+        let ev = self.usb.poll();
+        let classes: &mut [&mut dyn Class] = &mut [&mut self.cdc];
+        let was_configured = self.device.is_configured();
+
+        if ev.reset {
+            ringbuf_entry!(Trace::Reset {
+                high_speed: self.usb.is_high_speed()
+            });
+            self.device.reset(&mut self.usb, classes);
+        }
+        if let Some(setup) = ev.setup {
+            self.device.setup(&mut self.usb, classes, &setup);
+        }
+        if ev.ep0_out {
+            self.device.ep0_out(&mut self.usb, classes);
+        }
+        if ev.ep0_in {
+            self.device.ep0_in_complete(&mut self.usb);
+        }
+
+        if self.cdc.take_endpoints_reset() {
+            self.rx_armed = false;
+            self.tx_busy = false;
+            self.tx_needs_zlp = false;
+        }
+        if self.device.is_configured() && !was_configured {
+            ringbuf_entry!(Trace::Configured);
+        }
+
+        let mut progress = false;
+        if self.device.is_configured() {
+            if ev.out_complete(DATA_EP) {
+                let mut buf = [0; MAX_PACKET];
+                if let Ok(n) = self.usb.read(DATA_EP, &mut buf) {
+                    for &b in &buf[..n] {
+                        // Can't fail; we only arm with room for a packet.
+                        let _ = self.rx.push_back(b);
+                    }
+                    progress |= n != 0;
+                }
+                self.rx_armed = false;
+            }
+            if ev.in_complete(DATA_EP) {
+                self.tx_busy = false;
+                progress = true;
+            }
+        }
+
+        let breaks = self.cdc.breaks();
+        if breaks != self.events.breaks {
+            ringbuf_entry!(Trace::HostBreak);
+            self.events.breaks = breaks;
+            progress = true;
+        }
+
+        self.pump();
+        progress
+    }
+}
+
+impl idl::InOrderUartImpl for ServerImpl {
+    fn write(
+        &mut self,
+        _: &RecvMessage,
+        data: LenLimit<Leased<R, [u8]>, 256>,
+    ) -> Result<u32, RequestError<UartError>> {
+        let n = usize::min(data.len(), self.tx.capacity() - self.tx.len());
+        if n == 0 {
+            return Err(UartError::TxFull.into());
+        }
+        let mut buf = [0; 256];
+        data.read_range(0..n, &mut buf[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        for &b in &buf[..n] {
+            // Can't fail, we checked for space above.
+            let _ = self.tx.push_back(b);
+        }
+        self.pump();
+        Ok(n as u32)
+    }
+
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        data: LenLimit<Leased<W, [u8]>, 256>,
+    ) -> Result<u32, RequestError<UartError>> {
+        let mut buf = [0; 256];
+        let n = usize::min(data.len(), self.rx.len());
+        for b in &mut buf[..n] {
+            *b = self.rx.pop_front().unwrap_or(0);
+        }
+        data.write_range(0..n, &buf[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        // We may have made room to take another packet.
+        self.pump();
+        Ok(n as u32)
+    }
+
+    fn subscribe(
+        &mut self,
+        msg: &RecvMessage,
+        notify: u32,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.subscriber = Some((msg.sender, notify));
+        Ok(())
+    }
+
+    fn events(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<UartEvents, RequestError<core::convert::Infallible>> {
+        Ok(self.events)
+    }
+
+    fn send_break(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        if !self.device.is_configured()
+            || self
+                .cdc
+                .notify_serial_state(&mut self.usb, serial_state::BREAK)
+                .is_err()
+        {
+            // Nobody to tell, or the last notification hasn't been
+            // collected; either way there's nothing better to do.
+            ringbuf_entry!(Trace::BreakDropped);
+        }
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::USB_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        if self.handle_usb() {
+            self.notify();
+        }
+        sys_irq_control(notifications::USB_IRQ_MASK, true);
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let syscon = Syscon::from(SYSCON.get_task_id());
+    for p in [
+        Peripheral::Usb1Phy,
+        Peripheral::Usb1Dev,
+        Peripheral::Usb1Ram,
+    ] {
+        syscon.enable_clock(p);
+        syscon.leave_reset(p);
+    }
+
+    let mut usb = Usb1::turn_on();
+    sys_irq_control(notifications::USB_IRQ_MASK, true);
+    usb.set_connected(true);
+
+    let mut server = ServerImpl {
+        usb,
+        device: Device::new(&DEVICE_INFO),
+        cdc: CdcAcm::new(COMM_EP, DATA_EP),
+        rx: Deque::new(),
+        tx: Deque::new(),
+        rx_armed: false,
+        tx_busy: false,
+        tx_needs_zlp: false,
+        subscriber: None,
+        events: UartEvents::default(),
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_uart_api::{UartError, UartEvents};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
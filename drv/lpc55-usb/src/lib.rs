@@ -0,0 +1,446 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Driver for the LPC55's high-speed USB device controller (USB1), as a
+//! `usbd::Bus`.
+//!
+//! The controller finds its endpoints through a command/status list in
+//! memory, one word per endpoint buffer, giving the buffer's offset and
+//! length and whether it's ready for the hardware. We keep the list and
+//! every buffer in the top half of the USB1 SRAM (the bottom half carries
+//! the DICE handoff from the ROM, see the `dice_*` regions), which the task
+//! must have mapped as `usb1_ram`.
+//!
+//! Each endpoint is single-buffered: 64 bytes for endpoint 0 and 512 in each
+//! direction for the others, which is enough for a bulk packet at high
+//! speed.
+
+#![no_std]
+
+use core::ptr::{read_volatile, write_volatile};
+use lpc55_pac as device;
+use usbd::{Bus, EndpointType, WouldBlock, EP_IN};
+
+/// Endpoints the controller has, including endpoint 0.
+pub const ENDPOINTS: usize = 6;
+
+const REG_BASE: usize = 0x4014_4000;
+const PHY_BASE: usize = 0x4003_8000;
+const RAM_BASE: usize = 0x4010_2000;
+const RAM_SIZE: usize = 0x2000;
+
+// Device controller registers.
+const DEVCMDSTAT: usize = 0x000;
+const EPLISTSTART: usize = 0x008;
+const DATABUFSTART: usize = 0x00c;
+const EPSKIP: usize = 0x014;
+const INTSTAT: usize = 0x020;
+const INTEN: usize = 0x024;
+
+const DEVCMDSTAT_DEV_EN: u32 = 1 << 7;
+const DEVCMDSTAT_SETUP: u32 = 1 << 8;
+const DEVCMDSTAT_DCON: u32 = 1 << 16;
+const DEVCMDSTAT_SPEED_SHIFT: u32 = 22;
+const DEVCMDSTAT_SPEED_HIGH: u32 = 0b10;
+const DEVCMDSTAT_DCON_C: u32 = 1 << 24;
+const DEVCMDSTAT_DSUS_C: u32 = 1 << 25;
+const DEVCMDSTAT_DRES_C: u32 = 1 << 26;
+/// Bits cleared by writing 1, which a read-modify-write must leave as 0.
+const DEVCMDSTAT_W1C: u32 = DEVCMDSTAT_SETUP
+    | DEVCMDSTAT_DCON_C
+    | DEVCMDSTAT_DSUS_C
+    | DEVCMDSTAT_DRES_C;
+
+/// INTSTAT and INTEN: the endpoint bits are 2n for OUT and 2n + 1 for IN.
+const INT_DEV: u32 = 1 << 31;
+const INT_EP0: u32 = 0b11;
+
+// PHY registers; each has SET and CLR aliases at +4 and +8.
+const PHY_PWD: usize = 0x00;
+const PHY_CTRL_CLR: usize = 0x38;
+const PHY_PLL_SIC: usize = 0xa0;
+const PHY_PLL_SIC_SET: usize = 0xa4;
+const PHY_PLL_SIC_CLR: usize = 0xa8;
+
+const CTRL_SFTRST: u32 = 1 << 31;
+const CTRL_CLKGATE: u32 = 1 << 30;
+
+const PLL_EN_USB_CLKS: u32 = 1 << 6;
+const PLL_POWER: u32 = 1 << 12;
+const PLL_ENABLE: u32 = 1 << 13;
+const PLL_BYPASS: u32 = 1 << 16;
+const PLL_REG_ENABLE: u32 = 1 << 21;
+const PLL_DIV_SEL_MASK: u32 = 0b111 << 22;
+/// Divider for the 16 MHz crystal.
+const PLL_DIV_SEL_16MHZ: u32 = 0b110 << 22;
+const PLL_LOCK: u32 = 1 << 31;
+
+// Command/status list entries.
+const EP_ACTIVE: u32 = 1 << 31;
+const EP_DISABLED: u32 = 1 << 30;
+const EP_STALL: u32 = 1 << 29;
+const EP_TOGGLE_RESET: u32 = 1 << 28;
+const EP_ISO: u32 = 1 << 26;
+const EP_NBYTES_SHIFT: u32 = 11;
+const EP_NBYTES_MASK: u32 = 0x7fff;
+
+// Layout of our part of the USB SRAM, in bytes from its start. Buffers are
+// addressed in 64-byte units, so each must be aligned to that.
+const LIST_OFFSET: usize = 0x000;
+const SETUP_OFFSET: usize = 0x100;
+const EP0_OUT_OFFSET: usize = 0x140;
+const EP0_IN_OFFSET: usize = 0x180;
+const EP_BUF_OFFSET: usize = 0x200;
+const EP_BUF_SIZE: usize = 512;
+const EP0_BUF_SIZE: usize = 64;
+
+const _: () = assert!(
+    EP_BUF_OFFSET + 2 * EP_BUF_SIZE * (ENDPOINTS - 1) <= RAM_SIZE,
+    "endpoint buffers don't fit in usb1_ram"
+);
+
+/// What `poll` found.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Events {
+    /// The host reset the bus; `usbd::Device::reset` is due.
+    pub reset: bool,
+    /// A SETUP packet arrived.
+    pub setup: Option<[u8; 8]>,
+    /// Endpoint 0 received an OUT packet.
+    pub ep0_out: bool,
+    /// Endpoint 0 finished sending an IN packet.
+    pub ep0_in: bool,
+    /// Other endpoints that completed a packet, as bits `2n` for OUT
+    /// endpoint `n` and `2n + 1` for IN endpoint `n`.
+    pub endpoints: u32,
+}
+
+impl Events {
+    pub fn out_complete(&self, ep: u8) -> bool {
+        self.endpoints & (1 << (2 * ep)) != 0
+    }
+
+    pub fn in_complete(&self, ep: u8) -> bool {
+        self.endpoints & (1 << (2 * ep + 1)) != 0
+    }
+}
+
+pub struct Usb1 {
+    high_speed: bool,
+    /// Lengths armed on each OUT endpoint, to work out what arrived.
+    armed: [u16; ENDPOINTS],
+    /// Largest packet on each OUT endpoint.
+    out_max: [u16; ENDPOINTS],
+}
+
+impl Usb1 {
+    /// Powers up the PHY and brings up the controller, without connecting
+    /// to the bus. The caller must have turned on the `Usb1Dev`, `Usb1Ram`
+    /// and `Usb1Phy` clocks and taken them out of reset, and must have
+    /// `usb1`, `usbphy`, `usb1_ram`, `pmc` and `anactrl` mapped.
+    pub fn turn_on() -> Self {
+        let usb = Self {
+            high_speed: false,
+            armed: [0; ENDPOINTS],
+            out_max: [EP_BUF_SIZE as u16; ENDPOINTS],
+        };
+
+        // The PHY and its PLL run from the 16 MHz crystal.
+        let pmc = unsafe { &*device::PMC::ptr() };
+        let anactrl = unsafe { &*device::ANACTRL::ptr() };
+        pmc.pdruncfg0.modify(|_, w| {
+            w.pden_xtal32m()
+                .poweredon()
+                .pden_ldoxo32m()
+                .poweredon()
+                .pden_usbhsphy()
+                .poweredon()
+                .pden_ldousbhs()
+                .poweredon()
+        });
+        anactrl
+            .xo32m_ctrl
+            .modify(|_, w| w.enable_pll_usb_out().set_bit());
+
+        phy_write(PHY_CTRL_CLR, CTRL_SFTRST | CTRL_CLKGATE);
+        phy_write(PHY_PLL_SIC_SET, PLL_POWER | PLL_REG_ENABLE);
+        let sic = phy_read(PHY_PLL_SIC);
+        phy_write(PHY_PLL_SIC, (sic & !PLL_DIV_SEL_MASK) | PLL_DIV_SEL_16MHZ);
+        phy_write(PHY_PLL_SIC_CLR, PLL_BYPASS);
+        phy_write(PHY_PLL_SIC_SET, PLL_ENABLE | PLL_EN_USB_CLKS);
+        while phy_read(PHY_PLL_SIC) & PLL_LOCK == 0 {
+            // This takes tens of microseconds.
+        }
+        phy_write(PHY_PWD, 0);
+
+        for i in 0..4 * ENDPOINTS {
+            usb.set_entry(i, EP_DISABLED);
+        }
+        usb.init_ep0();
+        write_reg(EPLISTSTART, (RAM_BASE + LIST_OFFSET) as u32);
+        write_reg(DATABUFSTART, RAM_BASE as u32);
+        write_reg(INTEN, INT_DEV | INT_EP0);
+        write_reg(DEVCMDSTAT, DEVCMDSTAT_DEV_EN);
+
+        usb
+    }
+
+    /// Connects to or disconnects from the bus, with the D+ pull-up.
+    pub fn set_connected(&mut self, connected: bool) {
+        let cmd = read_reg(DEVCMDSTAT) & !DEVCMDSTAT_W1C;
+        if connected {
+            write_reg(DEVCMDSTAT, cmd | DEVCMDSTAT_DCON);
+        } else {
+            write_reg(DEVCMDSTAT, cmd & !DEVCMDSTAT_DCON);
+        }
+    }
+
+    /// Collects and acknowledges whatever the controller has to report.
+    pub fn poll(&mut self) -> Events {
+        let int = read_reg(INTSTAT);
+        write_reg(INTSTAT, int);
+        let cmd = read_reg(DEVCMDSTAT);
+
+        let mut events = Events::default();
+        if int & INT_DEV != 0 {
+            let changes = cmd
+                & (DEVCMDSTAT_DCON_C | DEVCMDSTAT_DSUS_C | DEVCMDSTAT_DRES_C);
+            write_reg(DEVCMDSTAT, (cmd & !DEVCMDSTAT_W1C) | changes);
+            if changes & DEVCMDSTAT_DRES_C != 0 {
+                events.reset = true;
+                self.high_speed = (cmd >> DEVCMDSTAT_SPEED_SHIFT) & 0b11
+                    == DEVCMDSTAT_SPEED_HIGH;
+                self.init_ep0();
+            }
+        }
+
+        if cmd & DEVCMDSTAT_SETUP != 0 {
+            let mut setup = [0; 8];
+            ram_read(SETUP_OFFSET, &mut setup);
+            write_reg(
+                DEVCMDSTAT,
+                (read_reg(DEVCMDSTAT) & !DEVCMDSTAT_W1C) | DEVCMDSTAT_SETUP,
+            );
+            events.setup = Some(setup);
+        }
+
+        events.ep0_out = int & 0b01 != 0;
+        events.ep0_in = int & 0b10 != 0;
+        events.endpoints =
+            int & !(INT_DEV | INT_EP0) & ((1 << (2 * ENDPOINTS)) - 1);
+        events
+    }
+
+    fn init_ep0(&self) {
+        self.set_entry(0, 0);
+        self.set_entry(1, buf_field(SETUP_OFFSET));
+        self.set_entry(2, 0);
+        self.set_entry(3, EP_DISABLED);
+    }
+
+    fn entry(&self, index: usize) -> u32 {
+        // Safety: the list is in our slice of USB SRAM, and `index` is
+        // below 4 * ENDPOINTS.
+        unsafe {
+            read_volatile((RAM_BASE + LIST_OFFSET + 4 * index) as *const u32)
+        }
+    }
+
+    fn set_entry(&self, index: usize, value: u32) {
+        // Safety: as for `entry`.
+        unsafe {
+            write_volatile(
+                (RAM_BASE + LIST_OFFSET + 4 * index) as *mut u32,
+                value,
+            )
+        }
+    }
+}
+
+/// Index of the first list entry for an endpoint and direction.
+fn entry_index(ep: u8, is_in: bool) -> usize {
+    4 * usize::from(ep) + if is_in { 2 } else { 0 }
+}
+
+fn buf_offset(ep: u8, is_in: bool) -> usize {
+    match (ep, is_in) {
+        (0, false) => EP0_OUT_OFFSET,
+        (0, true) => EP0_IN_OFFSET,
+        _ => {
+            EP_BUF_OFFSET
+                + 2 * EP_BUF_SIZE * (usize::from(ep) - 1)
+                + if is_in { EP_BUF_SIZE } else { 0 }
+        }
+    }
+}
+
+/// The offset field of a list entry for a buffer at `offset`.
+fn buf_field(offset: usize) -> u32 {
+    (offset / 64) as u32
+}
+
+fn max_len(ep: u8) -> usize {
+    if ep == 0 {
+        EP0_BUF_SIZE
+    } else {
+        EP_BUF_SIZE
+    }
+}
+
+impl Bus for Usb1 {
+    fn is_high_speed(&self) -> bool {
+        self.high_speed
+    }
+
+    fn set_address(&mut self, address: u8) {
+        let cmd = read_reg(DEVCMDSTAT) & !DEVCMDSTAT_W1C;
+        write_reg(DEVCMDSTAT, (cmd & !0x7f) | u32::from(address & 0x7f));
+    }
+
+    fn configure_endpoint(
+        &mut self,
+        address: u8,
+        kind: EndpointType,
+        max_packet: u16,
+    ) {
+        let ep = address & 0xf;
+        let is_in = address & EP_IN != 0;
+        if ep == 0 || usize::from(ep) >= ENDPOINTS {
+            return;
+        }
+        if !is_in {
+            self.out_max[usize::from(ep)] = max_packet.min(EP_BUF_SIZE as u16);
+        }
+        let iso = if kind == EndpointType::Isochronous {
+            EP_ISO
+        } else {
+            0
+        };
+        let i = entry_index(ep, is_in);
+        self.set_entry(
+            i,
+            EP_TOGGLE_RESET | iso | buf_field(buf_offset(ep, is_in)),
+        );
+        let bit = 1 << (2 * u32::from(ep) + u32::from(is_in));
+        write_reg(INTEN, read_reg(INTEN) | bit);
+    }
+
+    fn reset_endpoints(&mut self) {
+        let mut skip = 0;
+        for ep in 1..ENDPOINTS as u8 {
+            for is_in in [false, true] {
+                let i = entry_index(ep, is_in);
+                if self.entry(i) & EP_ACTIVE != 0 {
+                    skip |= 1 << (2 * u32::from(ep) + u32::from(is_in));
+                }
+                self.set_entry(i, EP_DISABLED);
+                self.set_entry(i + 1, EP_DISABLED);
+            }
+        }
+        // Buffers the hardware still owns have to be taken back explicitly.
+        write_reg(EPSKIP, skip);
+        while read_reg(EPSKIP) != 0 {}
+        write_reg(INTEN, INT_DEV | INT_EP0);
+        self.armed[1..].fill(0);
+    }
+
+    fn write(&mut self, ep: u8, data: &[u8]) -> Result<(), WouldBlock> {
+        let i = entry_index(ep, true);
+        if self.entry(i) & EP_ACTIVE != 0 {
+            return Err(WouldBlock);
+        }
+        let data = &data[..data.len().min(max_len(ep))];
+        let offset = buf_offset(ep, true);
+        ram_write(offset, data);
+        self.set_entry(
+            i,
+            EP_ACTIVE
+                | (data.len() as u32) << EP_NBYTES_SHIFT
+                | buf_field(offset),
+        );
+        Ok(())
+    }
+
+    fn read(&mut self, ep: u8, buf: &mut [u8]) -> Result<usize, WouldBlock> {
+        let i = entry_index(ep, false);
+        let entry = self.entry(i);
+        let armed = usize::from(self.armed[usize::from(ep)]);
+        if armed == 0 || entry & EP_ACTIVE != 0 {
+            return Err(WouldBlock);
+        }
+        // The hardware counts NBytes down as data arrives.
+        let left = ((entry >> EP_NBYTES_SHIFT) & EP_NBYTES_MASK) as usize;
+        let n = armed.saturating_sub(left).min(buf.len());
+        ram_read(buf_offset(ep, false), &mut buf[..n]);
+        self.armed[usize::from(ep)] = 0;
+        Ok(n)
+    }
+
+    fn arm(&mut self, ep: u8) {
+        let i = entry_index(ep, false);
+        let len = if ep == 0 {
+            EP0_BUF_SIZE as u16
+        } else {
+            self.out_max[usize::from(ep)]
+        };
+        self.armed[usize::from(ep)] = len;
+        self.set_entry(
+            i,
+            EP_ACTIVE
+                | u32::from(len) << EP_NBYTES_SHIFT
+                | buf_field(buf_offset(ep, false)),
+        );
+    }
+
+    fn set_stalled(&mut self, address: u8, stalled: bool) {
+        let ep = address & 0xf;
+        let i = entry_index(ep, address & EP_IN != 0);
+        let entry = self.entry(i);
+        if stalled {
+            self.set_entry(i, (entry & !EP_ACTIVE) | EP_STALL);
+        } else {
+            self.set_entry(
+                i,
+                (entry & !(EP_STALL | EP_ACTIVE)) | EP_TOGGLE_RESET,
+            );
+            if address & EP_IN == 0 {
+                self.armed[usize::from(ep)] = 0;
+            }
+        }
+    }
+}
+
+fn read_reg(offset: usize) -> u32 {
+    // Safety: `offset` is one of the register offsets above.
+    unsafe { read_volatile((REG_BASE + offset) as *const u32) }
+}
+
+fn write_reg(offset: usize, value: u32) {
+    // Safety: as for `read_reg`.
+    unsafe { write_volatile((REG_BASE + offset) as *mut u32, value) }
+}
+
+fn phy_read(offset: usize) -> u32 {
+    // Safety: `offset` is one of the PHY register offsets above.
+    unsafe { read_volatile((PHY_BASE + offset) as *const u32) }
+}
+
+fn phy_write(offset: usize, value: u32) {
+    // Safety: as for `phy_read`.
+    unsafe { write_volatile((PHY_BASE + offset) as *mut u32, value) }
+}
+
+fn ram_read(offset: usize, out: &mut [u8]) {
+    for (i, b) in out.iter_mut().enumerate() {
+        // Safety: the buffers laid out above are within our slice of SRAM.
+        *b = unsafe { read_volatile((RAM_BASE + offset + i) as *const u8) };
+    }
+}
+
+fn ram_write(offset: usize, data: &[u8]) {
+    for (i, &b) in data.iter().enumerate() {
+        // Safety: as for `ram_read`.
+        unsafe { write_volatile((RAM_BASE + offset + i) as *mut u8, b) }
+    }
+}
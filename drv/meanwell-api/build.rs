@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::client::build_client_stub(
+    build_util::idol::build_client_stub(
         "../../idl/meanwell.idol",
         "client_stub.rs",
     )?;
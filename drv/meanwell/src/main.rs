@@ -12,7 +12,7 @@
 //! particularly Gimletlet is serving to manage Meanwell supplies.
 //!
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 
 use drv_meanwell_api::MeanwellError;
 use idol_runtime::NotificationHandler;
@@ -46,6 +46,26 @@ cfg_if::cfg_if! {
     }
 }
 
+fn configure_pins() {
+    use drv_stm32xx_sys_api::*;
+
+    let sys = SYS.get_task_id();
+    let sys = Sys::from(sys);
+
+    //
+    // We can safely do this even if the pins are already configured without
+    // changing their state.
+    //
+    for pin in MEANWELL_PINS {
+        sys.gpio_configure_output(
+            *pin,
+            OutputType::PushPull,
+            Speed::Low,
+            Pull::None,
+        )
+    }
+}
+
 fn set(index: usize, val: bool) -> Result<(), RequestError<MeanwellError>> {
     use drv_stm32xx_sys_api::*;
 
@@ -129,7 +149,7 @@ impl NotificationHandler for ServerImpl {
     }
 }
 
-#[export_name = "main"]
+#[cfg_attr(not(test), export_name = "main")]
 fn main() -> ! {
     let deadline = sys_get_timer().now;
 
@@ -143,23 +163,7 @@ fn main() -> ! {
         deadline,
     };
 
-    use drv_stm32xx_sys_api::*;
-
-    let sys = SYS.get_task_id();
-    let sys = Sys::from(sys);
-
-    //
-    // We can safely do this even if the pins are already configured without
-    // changing their state.
-    //
-    for pin in MEANWELL_PINS {
-        sys.gpio_configure_output(
-            *pin,
-            OutputType::PushPull,
-            Speed::Low,
-            Pull::None,
-        )
-    }
+    configure_pins();
 
     let mut incoming = [0u8; idl::INCOMING_SIZE];
     loop {
@@ -173,3 +177,68 @@ mod idl {
 }
 
 include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use drv_mock_gpio::Pins;
+    use drv_stm32xx_sys_api::Port;
+    use userlib::sim::Sim;
+
+    /// Runs `test` against the mock `sys` server, with the Meanwell pins
+    /// configured as they would be by `main`.
+    fn with_pins(test: impl FnOnce(&Pins)) {
+        let pins = Pins::default();
+        let server = pins.clone();
+        Sim::new()
+            .task("sys", 1, move || drv_mock_gpio::serve(server.clone()))
+            .run("test", 2, || {
+                configure_pins();
+                test(&pins);
+            });
+    }
+
+    #[test]
+    fn pins_are_outputs() {
+        with_pins(|pins| {
+            assert_eq!(pins.outputs(Port::B), 0b1100_0000_0000_0000);
+            assert_eq!(pins.outputs(Port::D), 0b0001_1111_0000_0000);
+            assert_eq!(pins.read(Port::B) | pins.read(Port::D), 0);
+        });
+    }
+
+    /// Each supply is switched by its own pin, without disturbing the others.
+    #[test]
+    fn power_on_and_off() {
+        with_pins(|pins| {
+            for (index, pin) in MEANWELL_PINS.iter().enumerate() {
+                assert!(!get(index).unwrap());
+                set(index, true).unwrap();
+                assert!(get(index).unwrap());
+                assert_eq!(pins.read(pin.port), pin.pin_mask);
+
+                set(index, false).unwrap();
+                assert!(!get(index).unwrap());
+                assert_eq!(pins.read(pin.port), 0);
+            }
+        });
+    }
+
+    #[test]
+    fn bad_index() {
+        with_pins(|pins| {
+            let index = MEANWELL_PINS.len();
+            assert!(matches!(
+                set(index, true),
+                Err(RequestError::Runtime(MeanwellError::NotPresent))
+            ));
+            assert!(matches!(
+                get(index),
+                Err(RequestError::Runtime(MeanwellError::NotPresent))
+            ));
+            assert_eq!(pins.read(Port::B) | pins.read(Port::D), 0);
+        });
+    }
+}
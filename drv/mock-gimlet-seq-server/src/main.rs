@@ -7,7 +7,10 @@
 #![no_std]
 #![no_main]
 
-use drv_gimlet_seq_api::{PowerState, SeqError};
+use drv_gimlet_seq_api::{
+    FanEvent, FpgaIdent, PowerEvent, PowerReason, PowerState, ProchotStatus,
+    Rail, RailStatus, SeqError,
+};
 use idol_runtime::RequestError;
 use task_jefe_api::Jefe;
 use userlib::{FromPrimitive, RecvMessage, UnwrapLite};
@@ -72,6 +75,15 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         }
     }
 
+    fn set_state_with_reason(
+        &mut self,
+        msg: &RecvMessage,
+        state: PowerState,
+        _reason: PowerReason,
+    ) -> Result<(), RequestError<SeqError>> {
+        self.set_state(msg, state)
+    }
+
     fn fans_on(
         &mut self,
         _: &RecvMessage,
@@ -86,6 +98,21 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         Ok(())
     }
 
+    fn next_fan_event(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FanEvent, RequestError<SeqError>> {
+        Err(RequestError::Runtime(SeqError::NoFanEvent))
+    }
+
+    fn set_fan_auto_enable(
+        &mut self,
+        _: &RecvMessage,
+        _enabled: bool,
+    ) -> Result<(), RequestError<SeqError>> {
+        Ok(())
+    }
+
     fn send_hardware_nmi(
         &mut self,
         _: &RecvMessage,
@@ -99,10 +126,75 @@ impl idl::InOrderSequencerImpl for ServerImpl {
     ) -> Result<[u8; 64], RequestError<SeqError>> {
         Ok([0; 64])
     }
+
+    fn fpga_ident(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FpgaIdent, RequestError<SeqError>> {
+        Ok(FpgaIdent {
+            ident: 0,
+            checksum: 0,
+        })
+    }
+
+    fn rail_status(
+        &mut self,
+        _: &RecvMessage,
+        _rail: Rail,
+    ) -> Result<RailStatus, RequestError<SeqError>> {
+        match self.get_state_impl() {
+            PowerState::A0 | PowerState::A0PlusHP => Ok(RailStatus::Good),
+            _ => Ok(RailStatus::Off),
+        }
+    }
+
+    fn rail_fault(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<Rail, RequestError<SeqError>> {
+        Err(RequestError::Runtime(SeqError::NoRailFault))
+    }
+
+    fn retry_sequence(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<SeqError>> {
+        Err(RequestError::Runtime(SeqError::NoRailFault))
+    }
+
+    fn power_event_count(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<core::convert::Infallible>> {
+        Ok(0)
+    }
+
+    fn power_event(
+        &mut self,
+        _: &RecvMessage,
+        _index: u32,
+    ) -> Result<PowerEvent, RequestError<SeqError>> {
+        Err(RequestError::Runtime(SeqError::NoPowerEvent))
+    }
+
+    fn set_prochot(
+        &mut self,
+        _: &RecvMessage,
+        _asserted: bool,
+    ) -> Result<(), RequestError<SeqError>> {
+        Ok(())
+    }
+
+    fn prochot_status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<ProchotStatus, RequestError<SeqError>> {
+        Ok(ProchotStatus::default())
+    }
 }
 
 mod idl {
-    use super::SeqError;
+    use super::{PowerEvent, PowerReason, ProchotStatus, SeqError};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -0,0 +1,307 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A mock `sys` server, for tasks running in the host simulation (see
+//! `userlib::sim`).
+//!
+//! The server implements the `Sys` interface over a model of the GPIO pins,
+//! [`Pins`], which the test shares: it can drive the level seen on input pins
+//! and check what the task under test has driven on its outputs. Clock and
+//! reset control always succeed and do nothing.
+//!
+//! GPIO interrupts are routed with [`Pins::route`], standing in for the
+//! `gpio-irqs` config of the real server. Driving a routed pin with
+//! [`Pins::drive`] then posts its owner's notification on each enabled edge.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use drv_stm32xx_sys_api::{Edge, GpioIrqError, Mode, Port, RccError};
+use idol_runtime::{NotificationHandler, RequestError};
+use userlib::*;
+
+#[derive(Copy, Clone, Default)]
+struct PortState {
+    /// Pins configured as outputs
+    outputs: u16,
+    /// Output data register
+    odr: u16,
+    /// Levels driven onto the pins from outside
+    inputs: u16,
+    /// Packed attributes from the last `gpio_configure_raw`, by pin
+    config: [u16; 16],
+}
+
+impl PortState {
+    fn read(&self) -> u16 {
+        self.odr & self.outputs | self.inputs & !self.outputs
+    }
+}
+
+struct Route {
+    port: Port,
+    pin: u8,
+    owner: &'static str,
+    mask: u32,
+    /// `Edge` bits, or 0 if not configured
+    edges: u8,
+    enabled: bool,
+}
+
+#[derive(Default)]
+struct State {
+    ports: BTreeMap<u8, PortState>,
+    routes: Vec<Route>,
+}
+
+/// The GPIO pins behind the mock server.
+#[derive(Clone, Default)]
+pub struct Pins {
+    state: Arc<Mutex<State>>,
+}
+
+impl Pins {
+    /// Routes interrupts on `pin` of `port` to the notification bits `mask`
+    /// of the task named `owner`.
+    pub fn route(&self, port: Port, pin: u8, owner: &'static str, mask: u32) {
+        self.state.lock().unwrap().routes.push(Route {
+            port,
+            pin,
+            owner,
+            mask,
+            edges: 0,
+            enabled: false,
+        });
+    }
+
+    /// Drives the `pins` of `port` high or low from outside, posting
+    /// notifications for any routed interrupts that fire. This must be called
+    /// from a simulated task (such as the test itself).
+    pub fn drive(&self, port: Port, pins: u16, high: bool) {
+        let mut state = self.state.lock().unwrap();
+        let p = state.ports.entry(port as u8).or_default();
+        let before = p.read();
+        if high {
+            p.inputs |= pins;
+        } else {
+            p.inputs &= !pins;
+        }
+        let after = p.read();
+
+        let mut posts = vec![];
+        for r in state.routes.iter().filter(|r| r.port == port && r.enabled) {
+            let bit = 1 << r.pin;
+            let rising = before & bit == 0 && after & bit != 0;
+            let falling = before & bit != 0 && after & bit == 0;
+            if rising && r.edges & Edge::Rising as u8 != 0
+                || falling && r.edges & Edge::Falling as u8 != 0
+            {
+                posts.push((r.owner, r.mask));
+            }
+        }
+        drop(state);
+
+        for (owner, mask) in posts {
+            sys_post(sim::task_id(owner), mask);
+        }
+    }
+
+    /// Returns the level of each pin of `port`, as the task would read it.
+    pub fn read(&self, port: Port) -> u16 {
+        let state = self.state.lock().unwrap();
+        state
+            .ports
+            .get(&(port as u8))
+            .copied()
+            .unwrap_or_default()
+            .read()
+    }
+
+    /// Returns the pins of `port` configured as outputs.
+    pub fn outputs(&self, port: Port) -> u16 {
+        let state = self.state.lock().unwrap();
+        state.ports.get(&(port as u8)).map_or(0, |p| p.outputs)
+    }
+
+    /// Returns the packed attributes (as passed to `gpio_configure_raw`) last
+    /// given to `pin` of `port`.
+    pub fn config(&self, port: Port, pin: u8) -> u16 {
+        let state = self.state.lock().unwrap();
+        state
+            .ports
+            .get(&(port as u8))
+            .map_or(0, |p| p.config[usize::from(pin)])
+    }
+}
+
+/// Serves `Sys` requests over `pins`; this is the mock server's `main`.
+pub fn serve(pins: Pins) -> ! {
+    let mut server = ServerImpl { pins };
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+struct ServerImpl {
+    pins: Pins,
+}
+
+impl ServerImpl {
+    fn modify<R>(&self, port: Port, f: impl FnOnce(&mut PortState) -> R) -> R {
+        let mut state = self.pins.state.lock().unwrap();
+        f(state.ports.entry(port as u8).or_default())
+    }
+
+    /// Applies `f` to the routes owned by `sender` whose notifications are in
+    /// `mask`, failing if `mask` has bits that aren't the sender's.
+    fn owned_routes(
+        &self,
+        sender: TaskId,
+        mask: u32,
+        mut f: impl FnMut(&mut Route),
+    ) -> Result<(), RequestError<GpioIrqError>> {
+        let mut state = self.pins.state.lock().unwrap();
+        let mut owned = 0;
+        for r in state.routes.iter_mut() {
+            if r.mask & mask != 0
+                && sim::task_id(r.owner).index() == sender.index()
+            {
+                owned |= r.mask;
+                f(r);
+            }
+        }
+        if mask & !owned != 0 {
+            return Err(GpioIrqError::NotOwner.into());
+        }
+        Ok(())
+    }
+}
+
+impl idl::InOrderSysImpl for ServerImpl {
+    fn enable_clock_raw(
+        &mut self,
+        _: &RecvMessage,
+        _peripheral: u32,
+    ) -> Result<(), RequestError<RccError>> {
+        Ok(())
+    }
+
+    fn disable_clock_raw(
+        &mut self,
+        _: &RecvMessage,
+        _peripheral: u32,
+    ) -> Result<(), RequestError<RccError>> {
+        Ok(())
+    }
+
+    fn enter_reset_raw(
+        &mut self,
+        _: &RecvMessage,
+        _peripheral: u32,
+    ) -> Result<(), RequestError<RccError>> {
+        Ok(())
+    }
+
+    fn leave_reset_raw(
+        &mut self,
+        _: &RecvMessage,
+        _peripheral: u32,
+    ) -> Result<(), RequestError<RccError>> {
+        Ok(())
+    }
+
+    fn gpio_configure_raw(
+        &mut self,
+        _: &RecvMessage,
+        port: Port,
+        pins: u16,
+        packed_attributes: u16,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        let output = packed_attributes & 0b11 == Mode::Output as u16;
+        self.modify(port, |p| {
+            for pin in (0..16).filter(|i| pins & 1 << i != 0) {
+                p.config[pin] = packed_attributes;
+            }
+            if output {
+                p.outputs |= pins;
+            } else {
+                p.outputs &= !pins;
+            }
+        });
+        Ok(())
+    }
+
+    fn gpio_set_reset(
+        &mut self,
+        _: &RecvMessage,
+        port: Port,
+        set_pins: u16,
+        reset_pins: u16,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        // As with BSRR, set wins.
+        self.modify(port, |p| p.odr = p.odr & !reset_pins | set_pins);
+        Ok(())
+    }
+
+    fn gpio_toggle(
+        &mut self,
+        _: &RecvMessage,
+        port: Port,
+        pins: u16,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.modify(port, |p| p.odr ^= pins);
+        Ok(())
+    }
+
+    fn gpio_read_input(
+        &mut self,
+        _: &RecvMessage,
+        port: Port,
+    ) -> Result<u16, RequestError<core::convert::Infallible>> {
+        Ok(self.modify(port, |p| p.read()))
+    }
+
+    fn gpio_irq_configure(
+        &mut self,
+        rm: &RecvMessage,
+        mask: u32,
+        sensitivity: Edge,
+    ) -> Result<(), RequestError<GpioIrqError>> {
+        self.owned_routes(rm.sender, mask, |r| r.edges = sensitivity as u8)
+    }
+
+    fn gpio_irq_control(
+        &mut self,
+        rm: &RecvMessage,
+        disable_mask: u32,
+        enable_mask: u32,
+    ) -> Result<(), RequestError<GpioIrqError>> {
+        self.owned_routes(rm.sender, disable_mask, |r| r.enabled = false)?;
+        self.owned_routes(rm.sender, enable_mask, |r| r.enabled = true)
+    }
+
+    fn read_uid(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<[u32; 3], RequestError<core::convert::Infallible>> {
+        Ok([0x1de_0000, 0, 0])
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        0
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        unreachable!()
+    }
+}
+
+mod idl {
+    use super::{Edge, GpioIrqError, Port, RccError};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
@@ -0,0 +1,253 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A mock I2C server, for tasks running in the host simulation (see
+//! `userlib::sim`).
+//!
+//! The server speaks the same protocol as the real I2C servers, but the
+//! devices on its buses are models: anything implementing [`Device`]. Most
+//! devices can be modelled with [`Registers`], a file of byte-wide registers.
+//! [`Bus::attach`] hands back the device it attaches, so that a test can change
+//! what the device will report, or check what was written to it.
+//!
+//! Only write/read operations (including block reads) are supported. Packet
+//! Error Checking isn't modelled: PEC operations behave like their plain
+//! counterparts.
+
+use std::sync::{Arc, Mutex};
+
+use drv_i2c_api::*;
+use userlib::*;
+
+/// A model of an I2C device.
+pub trait Device: Send {
+    /// Writes `write`, then reads into `read` (either of which may be empty),
+    /// returning the number of bytes read.
+    ///
+    /// For an SMBus block read, `read` has room for the count byte and the
+    /// largest possible block, and the device should supply the count byte
+    /// first, as it would on the wire.
+    fn write_read(
+        &mut self,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<usize, ResponseCode>;
+}
+
+/// A device with 256 byte-wide registers: a write sets the register pointer
+/// from its first byte and writes the rest from there, and a read reads from
+/// the register pointer, which increments as it goes.
+pub struct Registers {
+    pub regs: [u8; 256],
+    pointer: u8,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self {
+            regs: [0; 256],
+            pointer: 0,
+        }
+    }
+}
+
+impl Registers {
+    /// Returns a device whose registers initially hold `init`, starting from
+    /// register 0.
+    pub fn new(init: &[u8]) -> Self {
+        let mut out = Self::default();
+        out.regs[..init.len()].copy_from_slice(init);
+        out
+    }
+}
+
+impl Device for Registers {
+    fn write_read(
+        &mut self,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<usize, ResponseCode> {
+        if let Some((&pointer, data)) = write.split_first() {
+            self.pointer = pointer;
+            for &byte in data {
+                self.regs[usize::from(self.pointer)] = byte;
+                self.pointer = self.pointer.wrapping_add(1);
+            }
+        }
+        for byte in read.iter_mut() {
+            *byte = self.regs[usize::from(self.pointer)];
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+        Ok(read.len())
+    }
+}
+
+type Location = (Controller, PortIndex, Option<(Mux, Segment)>, u8);
+
+/// The devices on the mock server's buses.
+#[derive(Clone, Default)]
+pub struct Bus {
+    devices: Arc<Mutex<Vec<(Location, Arc<Mutex<dyn Device>>)>>>,
+}
+
+impl Bus {
+    /// Attaches `device` at the given (7-bit) `address`, returning it for the
+    /// test to keep an eye on.
+    pub fn attach<D: Device + 'static>(
+        &self,
+        controller: Controller,
+        port: PortIndex,
+        segment: Option<(Mux, Segment)>,
+        address: u8,
+        device: D,
+    ) -> Arc<Mutex<D>> {
+        let device = Arc::new(Mutex::new(device));
+        let model: Arc<Mutex<dyn Device>> = device.clone();
+        self.devices
+            .lock()
+            .unwrap()
+            .push(((controller, port, segment, address), model));
+        device
+    }
+
+    fn find(&self, at: Location) -> Option<Arc<Mutex<dyn Device>>> {
+        let devices = self.devices.lock().unwrap();
+        devices
+            .iter()
+            .find(|(l, _)| *l == at)
+            .map(|(_, d)| d.clone())
+    }
+}
+
+/// Serves I2C requests for the devices on `bus`; this is the mock server's
+/// `main`.
+pub fn serve(bus: Bus) -> ! {
+    let mut buffer = [0; 4];
+
+    loop {
+        hl::recv_without_notification(&mut buffer, |op, msg| match op {
+            Op::WriteRead
+            | Op::WriteReadBlock
+            | Op::WriteReadPec
+            | Op::WriteReadBlockPec => {
+                let lease_count = msg.lease_count();
+
+                let (payload, caller) = msg
+                    .fixed::<[u8; 4], usize>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                if lease_count < 2 || lease_count % 2 != 0 {
+                    return Err(ResponseCode::IllegalLeaseCount);
+                }
+
+                let (addr, controller, port, segment) =
+                    Marshal::unmarshal(payload)?;
+
+                if addr.is_reserved() {
+                    return Err(ResponseCode::ReservedAddress);
+                }
+
+                // We don't model any devices with 10-bit addresses.
+                let addr = addr.seven_bit().ok_or(ResponseCode::NoDevice)?;
+                let device = bus
+                    .find((controller, port, segment, addr))
+                    .ok_or(ResponseCode::NoDevice)?;
+                let mut device = device.lock().unwrap();
+
+                let mut total = 0;
+
+                for i in (0..lease_count).step_by(2) {
+                    let wbuf = caller.borrow(i);
+                    let winfo = wbuf.info().ok_or(ResponseCode::BadArg)?;
+
+                    if !winfo.attributes.contains(LeaseAttributes::READ) {
+                        return Err(ResponseCode::BadArg);
+                    }
+
+                    let rbuf = caller.borrow(i + 1);
+                    let rinfo = rbuf.info().ok_or(ResponseCode::BadArg)?;
+
+                    if winfo.len == 0 && rinfo.len == 0 {
+                        return Err(ResponseCode::BadArg);
+                    }
+
+                    if winfo.len > 255 || rinfo.len > 255 {
+                        return Err(ResponseCode::BadArg);
+                    }
+
+                    let mut write = [0; 255];
+                    let write = &mut write[..winfo.len];
+                    wbuf.read_fully_at(0, write).ok_or(ResponseCode::BadArg)?;
+
+                    let block = (op == Op::WriteReadBlock
+                        || op == Op::WriteReadBlockPec)
+                        && i == lease_count - 2;
+
+                    let mut read = [0; 256];
+                    let read = if block {
+                        // Leave the count byte out, as the real server does.
+                        device.write_read(write, &mut read)?;
+                        let count = usize::from(read[0]);
+                        if count > rinfo.len {
+                            return Err(ResponseCode::BadDeviceState);
+                        }
+                        &read[1..=count]
+                    } else {
+                        let n =
+                            device.write_read(write, &mut read[..rinfo.len])?;
+                        &read[..n]
+                    };
+
+                    rbuf.write_fully_at(0, read).ok_or(ResponseCode::BadArg)?;
+                    total += read.len();
+                }
+
+                caller.reply(total);
+                Ok(())
+            }
+            Op::SelectedMuxSegment
+            | Op::Transaction
+            | Op::SmbAlertPoll
+            | Op::SubmitTransfer
+            | Op::CollectTransfer
+            | Op::SegmentStatus
+            | Op::Scan
+            | Op::ErrorCounts => Err(ResponseCode::OperationNotSupported),
+        });
+    }
+}
+
+#[cfg(all(test, hubris_sim))]
+mod tests {
+    use super::*;
+    use userlib::sim::{task_id, Sim};
+
+    #[test]
+    fn read_and_write_registers() {
+        let bus = Bus::default();
+        let port = PortIndex(0);
+        let regs = Registers::new(&[0x12, 0x34, 0x56]);
+        let dev = bus.attach(Controller::I2C2, port, None, 0x48, regs);
+
+        let server = bus.clone();
+        Sim::new()
+            .task("i2c_driver", 1, move || serve(server.clone()))
+            .run("test", 2, || {
+                let task = task_id("i2c_driver");
+                let i2c =
+                    I2cDevice::new(task, Controller::I2C2, port, None, 0x48);
+
+                assert_eq!(i2c.read_reg::<u8, [u8; 2]>(1), Ok([0x34, 0x56]));
+                i2c.write(&[2, 0xab]).unwrap();
+                assert_eq!(dev.lock().unwrap().regs[2], 0xab);
+
+                let absent =
+                    I2cDevice::new(task, Controller::I2C2, port, None, 0x49);
+                assert_eq!(
+                    absent.read_reg::<u8, u8>(0),
+                    Err(ResponseCode::NoDevice)
+                );
+            });
+    }
+}
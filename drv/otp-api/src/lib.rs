@@ -0,0 +1,66 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for programming one-time-programmable configuration.
+//!
+//! Nothing is programmed in one step. A page is staged, a field at a time if
+//! need be; then verified, by handing the server the whole page as it should
+//! end up, which catches a staging mistake before it's permanent; and only
+//! then committed, after which the server reads it back. Staging again, or
+//! aborting, means verifying again.
+//!
+//! A server built for simulation goes through every check but programs a
+//! copy in RAM instead, which `status` owns up to.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Size of the page that's staged and committed.
+pub const PAGE_SIZE: usize = 512;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum OtpError {
+    /// Past the end of the page, or into a part of it that's reserved; or,
+    /// for `verify`, not a whole page.
+    BadRange = 1,
+    /// Nothing has been staged.
+    NotStaged,
+    /// The staged page hasn't been verified since it last changed.
+    NotVerified,
+    /// The staged page isn't what the caller expected.
+    Mismatch,
+    /// The page has been sealed, and can't be programmed again.
+    Sealed,
+    /// This server isn't built to seal the page.
+    SealNotAllowed,
+    /// Programming failed; the page may be in any state, and should be read.
+    WriteFailed,
+    /// Reading the programmed page failed.
+    ReadFailed,
+    /// The page reads back differently from what was committed.
+    ReadbackMismatch,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct OtpStatus {
+    /// Nonzero if a page is staged.
+    pub staged: u8,
+    /// Nonzero if the staged page has been verified.
+    pub verified: u8,
+    /// Nonzero if the page is sealed.
+    pub sealed: u8,
+    /// Nonzero if this server only pretends to program anything.
+    pub simulated: u8,
+    /// Commits since the server started.
+    pub commits: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
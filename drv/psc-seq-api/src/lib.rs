@@ -3,10 +3,16 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! API crate for the PSC Sequencer server.
+//!
+//! On a power shelf, sequencing amounts to looking after the PSUs, so that's
+//! what this API is about. PSUs are numbered from 0, as they're marked on the
+//! shelf (RFD 200).
 
 #![no_std]
 
-use userlib::FromPrimitive;
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive)]
 #[repr(u8)]
@@ -14,3 +20,134 @@ pub enum PowerState {
     Init = 0,
     A2 = 1,
 }
+
+/// Number of PSUs in a shelf.
+pub const NUM_PSUS: usize = 6;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum PsuError {
+    /// There's no PSU with that index.
+    BadIndex = 1,
+    /// The PSU isn't there, or isn't answering.
+    NotPresent,
+    /// Turning the PSU off would leave the shelf unable to carry its load.
+    OverBudget,
+    /// The PSU didn't take a PMBus command.
+    PmbusFailed,
+    /// The PSU hasn't told us its firmware version.
+    NoFirmwareVersion,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum PsuState {
+    /// Not in the shelf, or not answering.
+    Absent = 0,
+    /// In the shelf, but without good AC input.
+    NoAc,
+    /// Turned off by request.
+    Off,
+    /// Should be on, with AC, but its output isn't good.
+    Faulted,
+    /// On and well.
+    On,
+}
+
+/// Faults and warnings reported by a PSU, as latched in
+/// [`PsuStatus::faults`] until cleared.
+pub mod faults {
+    pub const VOUT: u16 = 1 << 0;
+    pub const IOUT: u16 = 1 << 1;
+    pub const INPUT: u16 = 1 << 2;
+    pub const TEMPERATURE: u16 = 1 << 3;
+    pub const FANS: u16 = 1 << 4;
+    pub const CML: u16 = 1 << 5;
+    pub const MFR_SPECIFIC: u16 = 1 << 6;
+    pub const POWER_NOT_GOOD: u16 = 1 << 7;
+    pub const OFF: u16 = 1 << 8;
+    pub const OTHER: u16 = 1 << 9;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct PsuStatus {
+    /// A `PsuState`.
+    pub state: u8,
+    /// Nonzero if we've asked for the PSU to be on.
+    pub commanded_on: u8,
+    /// Bits from [`faults`], latched since they were last cleared.
+    pub faults: u16,
+    /// STATUS_WORD as last read.
+    pub status_word: u16,
+    _pad: u16,
+    /// Input power, in watts.
+    pub input_watts: u32,
+    /// Output power, in watts.
+    pub output_watts: u32,
+}
+
+impl PsuStatus {
+    pub fn new(
+        state: PsuState,
+        commanded_on: bool,
+        faults: u16,
+        status_word: u16,
+        input_watts: u32,
+        output_watts: u32,
+    ) -> Self {
+        Self {
+            state: state as u8,
+            commanded_on: commanded_on as u8,
+            faults,
+            status_word,
+            _pad: 0,
+            input_watts,
+            output_watts,
+        }
+    }
+
+    pub fn state(&self) -> Option<PsuState> {
+        PsuState::from_u8(self.state)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct ShelfStatus {
+    /// PSUs in the shelf and answering.
+    pub present: u8,
+    /// PSUs that are on and well.
+    pub on: u8,
+    /// PSUs we keep spare when working out what the shelf can carry.
+    pub redundancy: u8,
+    _pad: u8,
+    /// Output power of the whole shelf, in watts.
+    pub load_watts: u32,
+    /// What the PSUs that are on can carry with `redundancy` of them lost,
+    /// in watts.
+    pub capacity_watts: u32,
+}
+
+impl ShelfStatus {
+    pub fn new(
+        present: u8,
+        on: u8,
+        redundancy: u8,
+        load_watts: u32,
+        capacity_watts: u32,
+    ) -> Self {
+        Self {
+            present,
+            on,
+            redundancy,
+            _pad: 0,
+            load_watts,
+            capacity_watts,
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
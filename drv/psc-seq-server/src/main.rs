@@ -3,13 +3,30 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! Server for managing the PSC sequencing process.
+//!
+//! A power shelf has nothing to sequence beyond getting to A2, after which
+//! this looks after the PSUs: polling each over PMBus, latching the faults
+//! it reports until they're cleared, and turning its output on and off on
+//! request. The shelf's load is tracked against what the PSUs that are on
+//! can carry with one of them lost, and turning a PSU off is refused if that
+//! would leave too little.
 
 #![no_std]
 #![no_main]
 
+mod psu;
+
+use drv_i2c_devices::mwocp68::Mwocp68;
 use drv_packrat_vpd_loader::{read_vpd_and_load_packrat, Packrat};
-use drv_psc_seq_api::PowerState;
+use drv_psc_seq_api::{
+    PowerState, PsuError, PsuState, PsuStatus, ShelfStatus, NUM_PSUS,
+};
 use drv_stm32xx_sys_api as sys_api;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, W,
+};
+use psu::{Event, Psu, FIRMWARE_VERSION_LEN};
+use ringbuf::*;
 use task_jefe_api::Jefe;
 use userlib::*;
 
@@ -18,8 +35,163 @@ task_slot!(I2C, i2c_driver);
 task_slot!(JEFE, jefe);
 task_slot!(PACKRAT, packrat);
 
+include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
+
 const STATUS_LED: sys_api::PinSet = sys_api::Port::A.pin(3);
 
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// Rated output of an MWOCP68-3600, in watts.
+const PSU_RATED_WATTS: u32 = 3600;
+
+/// PSUs whose loss the shelf should be able to ride out.
+const REDUNDANCY: u8 = 1;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Psu(u8, Event),
+    SetOn(u8, bool),
+    OverBudget { load: u32, capacity: u32 },
+    ClearFaults(u8),
+    PmbusError(u8, drv_i2c_devices::mwocp68::Error),
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+struct ServerImpl {
+    psus: [Psu; NUM_PSUS],
+}
+
+impl ServerImpl {
+    fn poll(&mut self) {
+        for (i, psu) in self.psus.iter_mut().enumerate() {
+            psu.poll(|e| ringbuf_entry!(Trace::Psu(i as u8, e)));
+        }
+        let deadline = sys_get_timer().now + POLL_INTERVAL_MS;
+        sys_set_timer(Some(deadline), notifications::TIMER_MASK);
+    }
+
+    fn psu(&mut self, index: u8) -> Result<&mut Psu, PsuError> {
+        let psu = self
+            .psus
+            .get_mut(usize::from(index))
+            .ok_or(PsuError::BadIndex)?;
+        if psu.is_present() {
+            Ok(psu)
+        } else {
+            Err(PsuError::NotPresent)
+        }
+    }
+
+    /// What the shelf can carry with `on` PSUs on.
+    fn capacity(on: u8) -> u32 {
+        u32::from(on.saturating_sub(REDUNDANCY)) * PSU_RATED_WATTS
+    }
+
+    fn shelf(&self) -> ShelfStatus {
+        let present = self.psus.iter().filter(|p| p.is_present()).count();
+        let on = self
+            .psus
+            .iter()
+            .filter(|p| p.state() == PsuState::On)
+            .count() as u8;
+        let load = self.psus.iter().map(Psu::output_watts).sum();
+        ShelfStatus::new(
+            present as u8,
+            on,
+            REDUNDANCY,
+            load,
+            Self::capacity(on),
+        )
+    }
+}
+
+impl idl::InOrderSequencerImpl for ServerImpl {
+    fn psu_status(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<PsuStatus, RequestError<PsuError>> {
+        // An absent PSU has a status too: it's absent.
+        let psu = self
+            .psus
+            .get(usize::from(index))
+            .ok_or(PsuError::BadIndex)?;
+        Ok(psu.status())
+    }
+
+    fn set_psu_on(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+        on: bool,
+        force: bool,
+    ) -> Result<(), RequestError<PsuError>> {
+        if !on && !force && self.psu(index)?.state() == PsuState::On {
+            let shelf = self.shelf();
+            let capacity = Self::capacity(shelf.on - 1);
+            if shelf.load_watts > capacity {
+                ringbuf_entry!(Trace::OverBudget {
+                    load: shelf.load_watts,
+                    capacity,
+                });
+                return Err(PsuError::OverBudget.into());
+            }
+        }
+        ringbuf_entry!(Trace::SetOn(index, on));
+        self.psu(index)?.set_on(on).map_err(|e| {
+            ringbuf_entry!(Trace::PmbusError(index, e));
+            PsuError::PmbusFailed.into()
+        })
+    }
+
+    fn clear_psu_faults(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<(), RequestError<PsuError>> {
+        ringbuf_entry!(Trace::ClearFaults(index));
+        self.psu(index)?.clear_faults().map_err(|e| {
+            ringbuf_entry!(Trace::PmbusError(index, e));
+            PsuError::PmbusFailed.into()
+        })
+    }
+
+    fn psu_firmware_version(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+        sink: LenLimit<Leased<W, [u8]>, FIRMWARE_VERSION_LEN>,
+    ) -> Result<u32, RequestError<PsuError>> {
+        let version = self
+            .psu(index)?
+            .firmware_version()
+            .ok_or(PsuError::NoFirmwareVersion)?;
+        let len = version.len().min(sink.len());
+        sink.write_range(0..len, &version[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(len as u32)
+    }
+
+    fn shelf_status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<ShelfStatus, RequestError<core::convert::Infallible>> {
+        Ok(self.shelf())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.poll();
+    }
+}
+
 #[export_name = "main"]
 fn main() -> ! {
     let sys = sys_api::Sys::from(SYS.get_task_id());
@@ -42,9 +214,23 @@ fn main() -> ! {
     jefe.set_state(PowerState::A2 as u32);
     sys.gpio_set(STATUS_LED);
 
-    // We have nothing else to do, so sleep forever via waiting for a message
-    // from the kernel that won't arrive.
+    // Page 0 is the 54V output, which is the one that's turned on and off.
+    let devs = i2c_config::devices::mwocp68(I2C.get_task_id());
+    let mut server = ServerImpl {
+        psus: devs.map(|dev| Psu::new(Mwocp68::new(&dev, 0))),
+    };
+    server.poll();
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
     loop {
-        _ = sys_recv_closed(&mut [], 0, TaskId::KERNEL);
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
     }
 }
+
+mod idl {
+    use drv_psc_seq_api::{PsuError, PsuStatus, ShelfStatus};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,259 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Tracking of a single PSU, from what it says over PMBus.
+//!
+//! There's no presence or AC-OK signal we can see from here, so both come
+//! from PMBus: a PSU that answers is present, and one that complains of low
+//! input doesn't have AC. Both are debounced, since a PSU being pulled or
+//! pushed in, or browning out, can answer on one poll and not the next.
+
+use drv_i2c_devices::mwocp68::{Error, Mwocp68};
+use drv_psc_seq_api::{faults, PsuState, PsuStatus};
+
+/// Polls a reading has to hold for before we believe it.
+const DEBOUNCE: u8 = 3;
+
+/// Longest firmware version we keep.
+pub const FIRMWARE_VERSION_LEN: usize = 32;
+
+// STATUS_WORD bits, from the PMBus spec, part II, section 17.2.
+const SW_OTHER: u16 = 1 << 0;
+const SW_CML: u16 = 1 << 1;
+const SW_TEMPERATURE: u16 = 1 << 2;
+const SW_VIN_UV: u16 = 1 << 3;
+const SW_IOUT_OC: u16 = 1 << 4;
+const SW_VOUT_OV: u16 = 1 << 5;
+const SW_OFF: u16 = 1 << 6;
+const SW_OTHER_UNKNOWN: u16 = 1 << 8;
+const SW_OTHER_UPPER: u16 = 1 << 9;
+const SW_FANS: u16 = 1 << 10;
+const SW_POWER_GOOD_N: u16 = 1 << 11;
+const SW_MFR_SPECIFIC: u16 = 1 << 12;
+const SW_INPUT: u16 = 1 << 13;
+const SW_IOUT_POUT: u16 = 1 << 14;
+const SW_VOUT: u16 = 1 << 15;
+
+// STATUS_INPUT bits that mean the AC isn't good.
+const SI_VIN_UV_FAULT: u8 = 1 << 4;
+const SI_UNIT_OFF_LOW_INPUT: u8 = 1 << 3;
+
+/// Turns STATUS_WORD into the bits we latch.
+fn decode(word: u16) -> u16 {
+    const MAP: [(u16, u16); 10] = [
+        (SW_VOUT | SW_VOUT_OV, faults::VOUT),
+        (SW_IOUT_POUT | SW_IOUT_OC, faults::IOUT),
+        (SW_INPUT | SW_VIN_UV, faults::INPUT),
+        (SW_TEMPERATURE, faults::TEMPERATURE),
+        (SW_FANS, faults::FANS),
+        (SW_CML, faults::CML),
+        (SW_MFR_SPECIFIC, faults::MFR_SPECIFIC),
+        (SW_POWER_GOOD_N, faults::POWER_NOT_GOOD),
+        (SW_OFF, faults::OFF),
+        (SW_OTHER | SW_OTHER_UNKNOWN | SW_OTHER_UPPER, faults::OTHER),
+    ];
+    MAP.iter()
+        .filter(|(sw, _)| word & sw != 0)
+        .fold(0, |acc, (_, f)| acc | f)
+}
+
+/// A reading that only changes once it's held for `DEBOUNCE` polls.
+struct Debounced {
+    value: bool,
+    disagreements: u8,
+}
+
+impl Debounced {
+    const fn new(value: bool) -> Self {
+        Self {
+            value,
+            disagreements: 0,
+        }
+    }
+
+    /// Takes a sample, returning true if the value changed.
+    fn update(&mut self, sample: bool) -> bool {
+        if sample == self.value {
+            self.disagreements = 0;
+            return false;
+        }
+        self.disagreements += 1;
+        if self.disagreements < DEBOUNCE {
+            return false;
+        }
+        self.value = sample;
+        self.disagreements = 0;
+        true
+    }
+}
+
+/// What changed on a poll, for the ringbuf.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    Inserted,
+    Removed,
+    AcLost,
+    AcRestored,
+    StateChanged(PsuState),
+}
+
+pub struct Psu {
+    dev: Mwocp68,
+    state: PsuState,
+    present: Debounced,
+    ac_ok: Debounced,
+    commanded_on: bool,
+    faults: u16,
+    status_word: u16,
+    input_watts: u32,
+    output_watts: u32,
+    firmware: Option<([u8; FIRMWARE_VERSION_LEN], usize)>,
+}
+
+impl Psu {
+    pub fn new(dev: Mwocp68) -> Self {
+        Self {
+            dev,
+            state: PsuState::Absent,
+            present: Debounced::new(false),
+            ac_ok: Debounced::new(true),
+            // PSUs come up on, and we've not said otherwise.
+            commanded_on: true,
+            faults: 0,
+            status_word: 0,
+            input_watts: 0,
+            output_watts: 0,
+            firmware: None,
+        }
+    }
+
+    pub fn state(&self) -> PsuState {
+        self.state
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.value
+    }
+
+    pub fn output_watts(&self) -> u32 {
+        self.output_watts
+    }
+
+    pub fn status(&self) -> PsuStatus {
+        PsuStatus::new(
+            self.state,
+            self.commanded_on,
+            self.faults,
+            self.status_word,
+            self.input_watts,
+            self.output_watts,
+        )
+    }
+
+    pub fn firmware_version(&self) -> Option<&[u8]> {
+        self.firmware.as_ref().map(|(buf, len)| &buf[..*len])
+    }
+
+    /// Reads everything there is to know, calling `event` for anything
+    /// that's changed.
+    pub fn poll(&mut self, mut event: impl FnMut(Event)) {
+        let word = self.dev.status_word();
+        if self.present.update(word.is_ok()) {
+            if self.present.value {
+                event(Event::Inserted);
+            } else {
+                event(Event::Removed);
+                self.forget();
+            }
+        }
+
+        if self.present.value {
+            // A PSU that's present but missed this poll keeps its last
+            // readings, until it's missed enough to be called absent.
+            if let Ok(word) = word {
+                self.update(word, &mut event);
+            }
+        }
+
+        let state = if !self.present.value {
+            PsuState::Absent
+        } else if !self.ac_ok.value {
+            PsuState::NoAc
+        } else if !self.commanded_on {
+            PsuState::Off
+        } else if self.status_word & (SW_POWER_GOOD_N | SW_OFF) != 0 {
+            PsuState::Faulted
+        } else {
+            PsuState::On
+        };
+        if state != self.state {
+            self.state = state;
+            event(Event::StateChanged(state));
+        }
+    }
+
+    /// Forgets what we knew of a PSU that's gone, so that whatever goes in
+    /// next starts afresh.
+    fn forget(&mut self) {
+        self.ac_ok = Debounced::new(true);
+        self.commanded_on = true;
+        self.faults = 0;
+        self.status_word = 0;
+        self.input_watts = 0;
+        self.output_watts = 0;
+        self.firmware = None;
+    }
+
+    fn update(&mut self, word: u16, event: &mut impl FnMut(Event)) {
+        self.status_word = word;
+        self.faults |= decode(word);
+
+        let ac_ok = if word & (SW_INPUT | SW_VIN_UV) == 0 {
+            true
+        } else {
+            match self.dev.status_input() {
+                Ok(input) => {
+                    input & (SI_VIN_UV_FAULT | SI_UNIT_OFF_LOW_INPUT) == 0
+                }
+                Err(_) => self.ac_ok.value,
+            }
+        };
+        if self.ac_ok.update(ac_ok) {
+            event(if ac_ok {
+                Event::AcRestored
+            } else {
+                Event::AcLost
+            });
+        }
+
+        if let Ok(w) = self.dev.read_pin() {
+            self.input_watts = w.0 as u32;
+        }
+        if let Ok(w) = self.dev.read_pout() {
+            self.output_watts = w.0 as u32;
+        }
+        if self.firmware.is_none() {
+            self.read_firmware_version();
+        }
+    }
+
+    fn read_firmware_version(&mut self) {
+        let mut buf = [0; FIRMWARE_VERSION_LEN];
+        if let Ok(len) = self.dev.read_firmware_version(&mut buf) {
+            self.firmware = Some((buf, len));
+        }
+    }
+
+    pub fn set_on(&mut self, on: bool) -> Result<(), Error> {
+        self.dev.set_on(on)?;
+        self.commanded_on = on;
+        Ok(())
+    }
+
+    pub fn clear_faults(&mut self) -> Result<(), Error> {
+        self.dev.clear_faults()?;
+        self.faults = 0;
+        Ok(())
+    }
+}
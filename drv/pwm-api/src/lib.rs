@@ -0,0 +1,37 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the PWM server.
+//!
+//! Outputs are named by timer number (1 for TIM1) and channel (1 to 4), as
+//! in the datasheet and the app's config. Each channel has an owner, and so
+//! may each timer; only the owner may change it.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+/// A duty cycle of 100%, in the units of `set_duty`.
+pub const FULL_DUTY: u16 = 10_000;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum PwmError {
+    /// No such timer or channel is configured.
+    NoSuchOutput = 1,
+    /// The channel or timer belongs to another task.
+    NotOwner,
+    /// The timer has no owner, so its frequency is fixed by the config.
+    FrequencyFixed,
+    /// The timer's clock can't be divided down to that frequency, or can
+    /// only do so with too little duty cycle resolution.
+    BadFrequency,
+    /// Duty cycles go up to `FULL_DUTY`.
+    BadDuty,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -22,11 +22,33 @@ pub const PAGE_SIZE_BYTES: usize = 256;
 /// will need to change to something more flexible.
 pub const SECTOR_SIZE_BYTES: usize = 65_536;
 
+/// Flash commands.
+///
+/// Unsuffixed address-bearing commands are the dedicated 4-byte-address forms,
+/// which work without switching the part into 4-byte address mode (a switch
+/// the host CPU, which shares the flash, wouldn't know about). Parts of 16 MiB
+/// or less may not implement them, and instead use the `*3` 3-byte forms.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Command {
+    WriteStatusReg = 0x01,
+    PageProgram3 = 0x02,
+    Read3 = 0x03,
     ReadStatusReg = 0x05,
     WriteEnable = 0x06,
     PageProgram = 0x12,
     Read = 0x13,
+    QuadPageProgram3 = 0x32,
+    QuadPageProgram = 0x34,
+
+    // Winbond parts only: reads status register 2, which holds the QE
+    // (quad enable) and SUS (suspended) bits. On Micron parts, this opcode
+    // instead switches the part into quad I/O protocol mode!
+    ReadStatusReg2 = 0x35,
+
+    QuadOutputRead3 = 0x6B,
+    QuadOutputRead = 0x6C,
+    Suspend = 0x75,
+    Resume = 0x7A,
 
     // Note, There are multiple ReadId commands.
     // Gimlet and Gemini's flash parts both respond to 0x9F.
@@ -35,7 +57,10 @@ pub enum Command {
     ReadId = 0x9F,
 
     BulkErase = 0xC7,
+    SectorErase3 = 0xD8,
     SectorErase = 0xDC,
+    QuadIoRead3 = 0xEB,
+    QuadIoRead = 0xEC,
 }
 
 impl From<Command> for u8 {
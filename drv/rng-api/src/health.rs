@@ -0,0 +1,561 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Health testing and reseeding shared by the RNG drivers.
+//!
+//! The drivers feed each raw 32-bit word read from the hardware through the
+//! two continuous health tests from NIST SP 800-90B section 4.4: the
+//! Repetition Count Test and the Adaptive Proportion Test. Before a source is
+//! used (at startup and after every failure) it must also pass a startup test,
+//! which runs the same tests over `STARTUP_SAMPLES` words and discards them.
+//!
+//! The cutoffs below assume a conservative min-entropy of 8 bits per 32-bit
+//! word and a false positive probability of roughly 2^-20 per test.
+//!
+//! Raw words never reach clients directly: they only ever seed a DRBG through
+//! `ReseedingRng`, which reseeds after a fixed number of bytes. If a reseed
+//! fails, the error is returned and nothing more is generated until a later
+//! reseed succeeds.
+
+use crate::{RngError, RngHealthStats};
+use rand_core::{impls, Error, RngCore, SeedableRng};
+
+/// Number of words discarded by the startup test.
+pub const STARTUP_SAMPLES: usize = 1024;
+
+/// Repetition Count Test cutoff: `1 + ceil(20 / H)` with H = 8.
+const RCT_CUTOFF: u32 = 4;
+
+/// Adaptive Proportion Test window size for non-binary sources.
+const APT_WINDOW: u32 = 512;
+
+/// Adaptive Proportion Test cutoff, i.e. the smallest count for which
+/// `P(X >= count) < 2^-20` with `X ~ Binomial(512, 2^-8)`.
+const APT_CUTOFF: u32 = 13;
+
+/// Which health test rejected a sample.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HealthFailure {
+    RepetitionCount,
+    AdaptiveProportion,
+}
+
+/// State for the SP 800-90B continuous health tests.
+pub struct HealthTests {
+    rct_last: u32,
+    rct_count: u32,
+    apt_first: u32,
+    apt_count: u32,
+    apt_seen: u32,
+}
+
+impl HealthTests {
+    pub const fn new() -> Self {
+        Self {
+            rct_last: 0,
+            rct_count: 0,
+            apt_first: 0,
+            apt_count: 0,
+            apt_seen: 0,
+        }
+    }
+
+    /// Forgets all history, as if no samples had been seen.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Runs one sample through both tests.
+    pub fn check(&mut self, sample: u32) -> Result<(), HealthFailure> {
+        if self.rct_count != 0 && sample == self.rct_last {
+            self.rct_count += 1;
+            if self.rct_count >= RCT_CUTOFF {
+                return Err(HealthFailure::RepetitionCount);
+            }
+        } else {
+            self.rct_last = sample;
+            self.rct_count = 1;
+        }
+
+        if self.apt_seen == 0 {
+            self.apt_first = sample;
+            self.apt_count = 1;
+        } else if sample == self.apt_first {
+            self.apt_count += 1;
+            if self.apt_count >= APT_CUTOFF {
+                return Err(HealthFailure::AdaptiveProportion);
+            }
+        }
+        self.apt_seen += 1;
+        if self.apt_seen == APT_WINDOW {
+            self.apt_seen = 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for HealthTests {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A hardware noise source.
+pub trait EntropySource {
+    /// Returns the next raw word from the hardware.
+    fn next_word(&mut self) -> Result<u32, RngError>;
+
+    /// Puts the hardware back into a known-good state after an error.
+    fn restart(&mut self) -> Result<(), RngError>;
+}
+
+/// Wraps an `EntropySource` with startup and continuous health tests.
+///
+/// Any failure, whether reported by the hardware or by the health tests,
+/// marks the source unhealthy. The next request then restarts the hardware
+/// and reruns the startup test before any words are handed out.
+pub struct HealthChecked<S> {
+    source: S,
+    tests: HealthTests,
+    healthy: bool,
+    stats: RngHealthStats,
+}
+
+impl<S: EntropySource> HealthChecked<S> {
+    /// Wraps `source`, which must already be initialized. The startup test
+    /// runs on first use.
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            tests: HealthTests::new(),
+            healthy: false,
+            stats: RngHealthStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> RngHealthStats {
+        self.stats
+    }
+
+    fn fail(&mut self, err: RngError) -> RngError {
+        self.healthy = false;
+        match err {
+            RngError::HealthTestFailed => (),
+            _ => {
+                self.stats.source_errors =
+                    self.stats.source_errors.wrapping_add(1)
+            }
+        }
+        err
+    }
+
+    fn next_checked(&mut self) -> Result<u32, RngError> {
+        let word = self.source.next_word().map_err(|e| self.fail(e))?;
+        self.stats.samples = self.stats.samples.wrapping_add(1);
+        match self.tests.check(word) {
+            Ok(()) => Ok(word),
+            Err(HealthFailure::RepetitionCount) => {
+                self.stats.rct_failures =
+                    self.stats.rct_failures.wrapping_add(1);
+                Err(self.fail(RngError::HealthTestFailed))
+            }
+            Err(HealthFailure::AdaptiveProportion) => {
+                self.stats.apt_failures =
+                    self.stats.apt_failures.wrapping_add(1);
+                Err(self.fail(RngError::HealthTestFailed))
+            }
+        }
+    }
+
+    fn startup(&mut self) -> Result<(), RngError> {
+        if self.stats.startups != 0 {
+            self.source.restart().map_err(|e| self.fail(e))?;
+        }
+        self.stats.startups = self.stats.startups.wrapping_add(1);
+        self.tests.reset();
+
+        for _ in 0..STARTUP_SAMPLES {
+            if let Err(e) = self.next_checked() {
+                self.stats.startup_failures =
+                    self.stats.startup_failures.wrapping_add(1);
+                return Err(match e {
+                    RngError::HealthTestFailed => RngError::StartupTestFailed,
+                    e => e,
+                });
+            }
+        }
+        self.healthy = true;
+        Ok(())
+    }
+
+    fn next_word(&mut self) -> Result<u32, RngError> {
+        if !self.healthy {
+            self.startup()?;
+        }
+        self.next_checked()
+    }
+}
+
+impl<S: EntropySource> RngCore for HealthChecked<S> {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("Failed to get entropy from RNG.")
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        for chunk in dest.chunks_mut(4) {
+            let word = self.next_word()?;
+            chunk.copy_from_slice(&word.to_ne_bytes()[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+// low-budget rand::rngs::adapter::ReseedingRng w/o fork stuff
+pub struct ReseedingRng<T: SeedableRng, S> {
+    inner: T,
+    reseeder: HealthChecked<S>,
+    threshold: usize,
+    bytes_until_reseed: usize,
+    reseeds: u32,
+}
+
+impl<T, S> ReseedingRng<T, S>
+where
+    T: SeedableRng,
+    S: EntropySource,
+{
+    pub fn new(
+        mut reseeder: HealthChecked<S>,
+        threshold: usize,
+    ) -> Result<Self, Error> {
+        let threshold = if threshold == 0 {
+            usize::MAX
+        } else {
+            threshold
+        };
+
+        let inner = T::from_rng(&mut reseeder)?;
+        Ok(ReseedingRng {
+            inner,
+            reseeder,
+            threshold,
+            bytes_until_reseed: threshold,
+            reseeds: 1,
+        })
+    }
+
+    pub fn stats(&self) -> RngHealthStats {
+        RngHealthStats {
+            reseeds: self.reseeds,
+            ..self.reseeder.stats()
+        }
+    }
+
+    fn reseed(&mut self) -> Result<(), Error> {
+        // If this fails we keep the old DRBG state but leave the budget at
+        // zero, so that nothing more is generated until a reseed succeeds.
+        self.bytes_until_reseed = 0;
+        self.inner = T::from_rng(&mut self.reseeder)?;
+        self.bytes_until_reseed = self.threshold;
+        self.reseeds = self.reseeds.wrapping_add(1);
+        Ok(())
+    }
+}
+
+impl<T, S> RngCore for ReseedingRng<T, S>
+where
+    T: SeedableRng + RngCore,
+    S: EntropySource,
+{
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("Failed to get entropy from RNG.")
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        let num_bytes = dest.len();
+        if num_bytes >= self.bytes_until_reseed || num_bytes >= self.threshold {
+            self.reseed()?;
+        }
+        self.bytes_until_reseed -= num_bytes.min(self.bytes_until_reseed);
+        self.inner.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Mode {
+        Good,
+        Stuck,
+        Biased,
+        Broken,
+    }
+
+    /// A source that behaves as well or as badly as we tell it to.
+    struct TestSource {
+        mode: Mode,
+        state: u32,
+        words: u32,
+        restarts: u32,
+    }
+
+    impl TestSource {
+        fn new(mode: Mode) -> Self {
+            Self {
+                mode,
+                state: 0x1234_5678,
+                words: 0,
+                restarts: 0,
+            }
+        }
+    }
+
+    impl EntropySource for TestSource {
+        fn next_word(&mut self) -> Result<u32, RngError> {
+            // xorshift32, which never repeats within our windows
+            let x = self.state;
+            let x = x ^ (x << 13);
+            let x = x ^ (x >> 17);
+            self.state = x ^ (x << 5);
+            self.words += 1;
+
+            match self.mode {
+                Mode::Good => Ok(self.state),
+                Mode::Stuck => Ok(0xdead_beef),
+                // Every other word is the same, so never twice in a row.
+                Mode::Biased if self.words % 2 == 1 => Ok(0xdead_beef),
+                Mode::Biased => Ok(self.state),
+                Mode::Broken => Err(RngError::SeedError),
+            }
+        }
+
+        fn restart(&mut self) -> Result<(), RngError> {
+            self.restarts += 1;
+            Ok(())
+        }
+    }
+
+    /// A "DRBG" that just remembers its seed.
+    struct TestDrbg(u32);
+
+    impl SeedableRng for TestDrbg {
+        type Seed = [u8; 4];
+
+        fn from_seed(seed: Self::Seed) -> Self {
+            Self(u32::from_ne_bytes(seed))
+        }
+    }
+
+    impl RngCore for TestDrbg {
+        fn next_u32(&mut self) -> u32 {
+            self.0
+        }
+        fn next_u64(&mut self) -> u64 {
+            u64::from(self.0)
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0);
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    fn rng_error(e: Error) -> RngError {
+        RngError::from(e)
+    }
+
+    #[test]
+    fn rct_cutoff() {
+        let mut tests = HealthTests::new();
+        for _ in 1..RCT_CUTOFF {
+            assert_eq!(tests.check(7), Ok(()));
+        }
+        assert_eq!(tests.check(7), Err(HealthFailure::RepetitionCount));
+
+        // A different sample starts the count over.
+        let mut tests = HealthTests::new();
+        for _ in 1..RCT_CUTOFF {
+            assert_eq!(tests.check(7), Ok(()));
+        }
+        assert_eq!(tests.check(8), Ok(()));
+        assert_eq!(tests.check(7), Ok(()));
+    }
+
+    #[test]
+    fn apt_cutoff() {
+        // The first sample of the window recurs, but never twice in a row.
+        let mut tests = HealthTests::new();
+        let mut other = 100;
+        for _ in 1..APT_CUTOFF {
+            assert_eq!(tests.check(7), Ok(()));
+            assert_eq!(tests.check(other), Ok(()));
+            other += 1;
+        }
+        assert_eq!(tests.check(7), Err(HealthFailure::AdaptiveProportion));
+
+        // Just under the cutoff in one window doesn't carry into the next.
+        let mut tests = HealthTests::new();
+        let mut seen = 0;
+        for _ in 1..APT_CUTOFF {
+            assert_eq!(tests.check(7), Ok(()));
+            assert_eq!(tests.check(other), Ok(()));
+            other += 1;
+            seen += 2;
+        }
+        while seen < APT_WINDOW {
+            assert_eq!(tests.check(other), Ok(()));
+            other += 1;
+            seen += 1;
+        }
+        for _ in 1..APT_CUTOFF {
+            assert_eq!(tests.check(7), Ok(()));
+            assert_eq!(tests.check(other), Ok(()));
+            other += 1;
+        }
+    }
+
+    #[test]
+    fn startup_discards_samples() {
+        let mut rng = HealthChecked::new(TestSource::new(Mode::Good));
+        rng.next_u32();
+
+        let stats = rng.stats();
+        assert_eq!(stats.startups, 1);
+        assert_eq!(stats.samples, STARTUP_SAMPLES as u32 + 1);
+        assert_eq!(stats.startup_failures, 0);
+        // The source was already initialized, so isn't restarted.
+        assert_eq!(rng.source.restarts, 0);
+
+        rng.next_u32();
+        assert_eq!(rng.stats().startups, 1);
+    }
+
+    #[test]
+    fn stuck_source_fails_startup() {
+        let mut rng = HealthChecked::new(TestSource::new(Mode::Stuck));
+        let mut buf = [0; 4];
+        let e = rng.try_fill_bytes(&mut buf).unwrap_err();
+        assert_eq!(rng_error(e), RngError::StartupTestFailed);
+
+        let stats = rng.stats();
+        assert_eq!(stats.rct_failures, 1);
+        assert_eq!(stats.startup_failures, 1);
+        assert_eq!(stats.samples, RCT_CUTOFF);
+
+        // The next request restarts the source and tries again.
+        assert!(rng.try_fill_bytes(&mut buf).is_err());
+        assert_eq!(rng.source.restarts, 1);
+        assert_eq!(rng.stats().startups, 2);
+    }
+
+    #[test]
+    fn biased_source_fails_startup() {
+        let mut rng = HealthChecked::new(TestSource::new(Mode::Biased));
+        let mut buf = [0; 4];
+        let e = rng.try_fill_bytes(&mut buf).unwrap_err();
+        assert_eq!(rng_error(e), RngError::StartupTestFailed);
+
+        let stats = rng.stats();
+        assert_eq!(stats.apt_failures, 1);
+        assert_eq!(stats.rct_failures, 0);
+        assert_eq!(stats.samples, 2 * APT_CUTOFF - 1);
+    }
+
+    #[test]
+    fn stuck_source_fails_continuously() {
+        let mut rng = HealthChecked::new(TestSource::new(Mode::Good));
+        rng.next_u32();
+
+        rng.source.mode = Mode::Stuck;
+        let mut buf = [0; 4];
+        for _ in 1..RCT_CUTOFF {
+            rng.try_fill_bytes(&mut buf).unwrap();
+        }
+        let e = rng.try_fill_bytes(&mut buf).unwrap_err();
+        assert_eq!(rng_error(e), RngError::HealthTestFailed);
+        assert_eq!(rng.stats().rct_failures, 1);
+        assert_eq!(rng.stats().startup_failures, 0);
+
+        // Once the source recovers, it's restarted and retested.
+        rng.source.mode = Mode::Good;
+        rng.try_fill_bytes(&mut buf).unwrap();
+        assert_eq!(rng.source.restarts, 1);
+        assert_eq!(rng.stats().startups, 2);
+        assert_eq!(rng.stats().startup_failures, 0);
+    }
+
+    #[test]
+    fn broken_source_reports_its_error() {
+        let mut rng = HealthChecked::new(TestSource::new(Mode::Broken));
+        let mut buf = [0; 4];
+        let e = rng.try_fill_bytes(&mut buf).unwrap_err();
+        assert_eq!(rng_error(e), RngError::SeedError);
+
+        let stats = rng.stats();
+        assert_eq!(stats.source_errors, 1);
+        assert_eq!(stats.startup_failures, 1);
+        assert_eq!(stats.samples, 0);
+    }
+
+    #[test]
+    fn reseeds_on_budget() {
+        let source = HealthChecked::new(TestSource::new(Mode::Good));
+        let mut rng = ReseedingRng::<TestDrbg, _>::new(source, 16).unwrap();
+        assert_eq!(rng.stats().reseeds, 1);
+
+        let mut buf = [0; 8];
+        rng.try_fill_bytes(&mut buf).unwrap();
+        assert_eq!(rng.stats().reseeds, 1);
+        rng.try_fill_bytes(&mut buf).unwrap();
+        assert_eq!(rng.stats().reseeds, 2);
+
+        // A request as large as the budget always gets a fresh seed.
+        let mut big = [0; 16];
+        rng.try_fill_bytes(&mut big).unwrap();
+        assert_eq!(rng.stats().reseeds, 3);
+    }
+
+    #[test]
+    fn failed_reseed_generates_nothing() {
+        let source = HealthChecked::new(TestSource::new(Mode::Good));
+        let mut rng = ReseedingRng::<TestDrbg, _>::new(source, 16).unwrap();
+        let seed = rng.inner.0;
+
+        rng.reseeder.source.mode = Mode::Broken;
+        let mut buf = [0; 4];
+        for _ in 0..3 {
+            rng.try_fill_bytes(&mut buf).unwrap();
+        }
+        let e = rng.try_fill_bytes(&mut buf).unwrap_err();
+        assert_eq!(rng_error(e), RngError::SeedError);
+        assert_eq!(rng.inner.0, seed);
+
+        // Even a small request fails until a reseed succeeds.
+        let mut byte = [0; 1];
+        assert!(rng.try_fill_bytes(&mut byte).is_err());
+        assert_eq!(rng.stats().reseeds, 1);
+
+        rng.reseeder.source.mode = Mode::Good;
+        rng.try_fill_bytes(&mut byte).unwrap();
+        assert_eq!(rng.stats().reseeds, 2);
+        assert_ne!(rng.inner.0, seed);
+    }
+}
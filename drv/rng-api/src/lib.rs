@@ -4,13 +4,16 @@
 
 //! API crate for the random number generator.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use core::num::NonZeroU32;
 use derive_idol_err::IdolError;
 use rand_core::impls;
 pub use rand_core::{Error, RngCore};
 use userlib::{sys_send, FromPrimitive};
+use zerocopy::{AsBytes, FromBytes};
+
+pub mod health;
 
 #[repr(u32)]
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
@@ -20,11 +23,35 @@ pub enum RngError {
     ClockError,
     SeedError,
     UnknownRngError,
+    /// A continuous health test rejected output from the hardware.
+    HealthTestFailed,
+    /// The hardware failed the startup health test after a restart.
+    StartupTestFailed,
 
     #[idol(server_death)]
     ServerRestarted,
 }
 
+/// Counters kept by the RNG driver's health testing.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct RngHealthStats {
+    /// Raw words read from the hardware.
+    pub samples: u32,
+    /// Repetition Count Test failures.
+    pub rct_failures: u32,
+    /// Adaptive Proportion Test failures.
+    pub apt_failures: u32,
+    /// Startup tests run, including the initial one.
+    pub startups: u32,
+    /// Startup tests that failed.
+    pub startup_failures: u32,
+    /// Errors reported by the hardware itself (seed, clock, timeout).
+    pub source_errors: u32,
+    /// Times the DRBG has been seeded, including the initial seed.
+    pub reseeds: u32,
+}
+
 // This function transforms an RngError to an error code appropriate for
 // rng_core by adding Error::CUSTOM_START to its u32 representation:
 // https://docs.rs/rand_core/0.6.3/rand_core/struct.Error.html#associatedconstant.CUSTOM_START
@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the real-time clock.
+//!
+//! The clock keeps wall-clock time across resets, and across power loss if
+//! it has a battery, but it knows nothing until someone tells it: the SP
+//! learns the time from upstream and passes it on with `set_time`. Until
+//! then, `get_time` fails with `NotSet`, and anything wanting a timestamp
+//! should fall back to the kernel's uptime rather than make one up.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum RtcError {
+    /// The clock has never been set, or lost power since it was.
+    NotSet = 1,
+    /// Outside the years the clock can count, 2000 through 2099.
+    BadTime,
+    /// Every alarm slot is taken by another task.
+    TooManyAlarms,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+impl Rtc {
+    /// Wall-clock time in whole seconds since the Unix epoch, or `None` if
+    /// the clock hasn't been set; for stamping records.
+    pub fn unix_secs(&self) -> Option<u64> {
+        self.get_time().ok().map(|us| us / 1_000_000)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
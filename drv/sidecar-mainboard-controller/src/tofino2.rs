@@ -131,14 +131,31 @@ pub struct TofinoSeqAbort {
     pub error: TofinoSeqError,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+/// The Tofino power rails, in the order the sequencer enables them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
 pub enum PowerRails {
-    Vdd18,
-    VddCore,
-    VddPcie,
-    Vddt,
-    Vdda15,
-    Vdda18,
+    Vdd18 = 0,
+    VddCore = 1,
+    VddPcie = 2,
+    Vddt = 3,
+    Vdda15 = 4,
+    Vdda18 = 5,
+}
+
+impl TofinoSeqStep {
+    /// The rail the sequencer is waiting on in this step, if any.
+    pub fn rail(self) -> Option<PowerRails> {
+        match self {
+            Self::AwaitVdd18PowerGood => Some(PowerRails::Vdd18),
+            Self::AwaitVddCorePowerGood => Some(PowerRails::VddCore),
+            Self::AwaitVddPciePowerGood => Some(PowerRails::VddPcie),
+            Self::AwaitVddtPowerGood => Some(PowerRails::Vddt),
+            Self::AwaitVdda15PowerGood => Some(PowerRails::Vdda15),
+            Self::AwaitVdda18PowerGood => Some(PowerRails::Vdda18),
+            _ => None,
+        }
+    }
 }
 
 #[derive(
@@ -240,6 +257,35 @@ impl PowerRail {
             ))?,
         ])
     }
+
+    /// Find the rail to blame for a sequencer abort: the rail the sequencer
+    /// was waiting on when it aborted, or failing that the first rail, in
+    /// sequencing order, which timed out or was aborted. Aborts which aren't
+    /// down to a rail (software, VID or thermal) return `None`.
+    pub fn fault(
+        abort: &TofinoSeqAbort,
+        rails: &[PowerRail; 6],
+    ) -> Option<PowerRails> {
+        match abort.error {
+            TofinoSeqError::PowerGoodTimeout
+            | TofinoSeqError::PowerFault
+            | TofinoSeqError::PowerVrHot
+            | TofinoSeqError::PowerAbort => (),
+            _ => return None,
+        }
+
+        abort.step.rail().or_else(|| {
+            rails
+                .iter()
+                .find(|r| {
+                    matches!(
+                        r.state,
+                        PowerRailState::GoodTimeout | PowerRailState::Aborted
+                    )
+                })
+                .map(|r| r.id)
+        })
+    }
 }
 
 /// VID to voltage mapping. The VID values are specified in TF2-DS2, with the
@@ -357,6 +403,19 @@ impl Sequencer {
         self.fpga.read(Addr::TOFINO_POWER_VDD18_STATE)
     }
 
+    pub fn power_rails(&self) -> Result<[PowerRail; 6], FpgaError> {
+        PowerRail::from_raw(self.raw_power_rails()?)
+    }
+
+    /// The rail which caused the last sequencer abort, or `None` if the
+    /// sequencer hasn't aborted or the abort wasn't down to a rail.
+    pub fn rail_fault(&self) -> Result<Option<PowerRails>, FpgaError> {
+        match self.status()?.abort {
+            Some(abort) => Ok(PowerRail::fault(&abort, &self.power_rails()?)),
+            None => Ok(None),
+        }
+    }
+
     /// The VID is only valid once Tofino is powered up and a delay after PoR
     /// has lapsed. If the VID is read while in this state a `Some(..)` will be
     /// returned. Attempting to read the VID outside this window will result in
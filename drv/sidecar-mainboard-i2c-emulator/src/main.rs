@@ -33,10 +33,13 @@ fn main() -> ! {
 
                 let (addr, _, _, _) = Marshal::unmarshal(payload)?;
 
-                if let Some(_) = ReservedAddress::from_u8(addr) {
+                if addr.is_reserved() {
                     return Err(ResponseCode::ReservedAddress);
                 }
 
+                // We don't emulate any devices with 10-bit addresses.
+                let addr = addr.seven_bit().ok_or(ResponseCode::NoDevice)?;
+
                 ringbuf_entry!(Trace::Addr(addr));
 
                 let wbuf = caller.borrow(0);
@@ -67,6 +70,16 @@ fn main() -> ! {
                 caller.reply(0);
                 Ok(())
             }
+            Op::WriteReadPec
+            | Op::WriteReadBlockPec
+            | Op::SmbAlertPoll
+            | Op::SelectedMuxSegment
+            | Op::Transaction
+            | Op::SubmitTransfer
+            | Op::CollectTransfer
+            | Op::SegmentStatus
+            | Op::Scan
+            | Op::ErrorCounts => Err(ResponseCode::OperationNotSupported),
         });
     }
 }
@@ -8,9 +8,11 @@
 
 use derive_idol_err::IdolError;
 use drv_fpga_api::FpgaError;
+pub use drv_fpga_api::FpgaUserDesignIdent;
 pub use drv_sidecar_mainboard_controller::tofino2::{
-    DebugPortState, DirectBarSegment, PowerRail, SpiEepromInstruction,
-    TofinoPcieReset, TofinoSeqError, TofinoSeqState, TofinoSeqStep,
+    DebugPortState, DirectBarSegment, PowerRail, PowerRailState, PowerRails,
+    SpiEepromInstruction, TofinoPcieReset, TofinoSeqError, TofinoSeqState,
+    TofinoSeqStep,
 };
 use userlib::*;
 use zerocopy::AsBytes;
@@ -25,6 +27,9 @@ pub enum SeqError {
     InvalidTofinoVid,
     SetVddCoreVoutFailed,
     NoFrontIOBoard,
+    FrontIOBoardFailed,
+    NoRailFault,
+    NoFanModule,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -44,4 +49,68 @@ pub enum TofinoSequencerPolicy {
     RestartOnFault = 2,
 }
 
+/// Where the sequencer has got to in bringing up the front IO board.
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, AsBytes)]
+#[repr(u8)]
+pub enum FrontIOStatus {
+    /// There's no board, or there was and it's been removed
+    NotPresent = 0,
+    /// Loading the board's FPGAs, or waiting for its PHY to power up
+    Initializing = 1,
+    /// The FPGAs are running and the PHY is powered up, held in COMA_MODE
+    /// until whoever drives the PHY has configured it
+    Ready = 2,
+    /// We couldn't bring the board up, and have given up trying until it's
+    /// reseated
+    Failed = 3,
+}
+
+/// Number of fan modules in a Sidecar
+pub const NUM_FAN_MODULES: usize = 4;
+
+/// Patterns that a fan module's LED can be asked to display, which the
+/// sequencer plays out on a timer.
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, AsBytes)]
+#[repr(u8)]
+pub enum LedPattern {
+    Off = 0,
+    On = 1,
+    /// Slow (1 Hz) blink, to help someone find a particular module
+    Locate = 2,
+    /// Fast (4 Hz) blink, indicating a fault
+    Fault = 3,
+    /// Double blink once per second, indicating degraded operation
+    Degraded = 4,
+}
+
+impl LedPattern {
+    /// Number of ticks in a pattern
+    pub const TICKS: u32 = 8;
+
+    /// Length of a single tick, in milliseconds
+    pub const TICK_MS: u64 = 125;
+
+    /// Returns the pattern as a bitmask of `TICKS` ticks, least-significant
+    /// bit first, where a set bit indicates that the LED is on.
+    pub fn mask(self) -> u8 {
+        match self {
+            LedPattern::Off => 0b0000_0000,
+            LedPattern::On => 0b1111_1111,
+            LedPattern::Locate => 0b0000_1111,
+            LedPattern::Fault => 0b0101_0101,
+            LedPattern::Degraded => 0b0000_0101,
+        }
+    }
+
+    /// Returns `true` if the LED should be lit at the given tick.
+    pub fn is_on(self, tick: u32) -> bool {
+        self.mask() & (1 << (tick % Self::TICKS)) != 0
+    }
+
+    /// Returns `true` if this pattern requires the LED to change over time.
+    pub fn is_animated(self) -> bool {
+        !matches!(self, LedPattern::Off | LedPattern::On)
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
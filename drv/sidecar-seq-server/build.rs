@@ -13,7 +13,7 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         std::process::exit(1);
     }
 
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/sidecar-seq.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
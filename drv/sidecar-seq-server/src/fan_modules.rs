@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! LED patterns for the fan modules.
+//!
+//! The mainboard controller doesn't (yet) have outputs for the fan module
+//! LEDs, let alone blink them for us, so we keep a pattern for each module
+//! and play it out from our timer.  Until the FPGA grows those outputs, the
+//! LEDs as they would be lit are only visible through `fan_module_leds`;
+//! `FanModuleLeds::poll` is where they'd be written out.
+
+use drv_sidecar_seq_api::{LedPattern, SeqError, NUM_FAN_MODULES};
+
+pub(crate) struct FanModuleLeds {
+    /// Pattern being displayed by each module's LED
+    patterns: [LedPattern; NUM_FAN_MODULES],
+
+    /// Current position within our patterns
+    tick: u32,
+
+    /// Time of our next tick, if any patterns are animated
+    deadline: Option<u64>,
+}
+
+impl FanModuleLeds {
+    pub fn new() -> Self {
+        Self {
+            patterns: [LedPattern::Off; NUM_FAN_MODULES],
+            tick: 0,
+            deadline: None,
+        }
+    }
+
+    pub fn pattern(&self, module: u8) -> Result<LedPattern, SeqError> {
+        self.patterns
+            .get(usize::from(module))
+            .copied()
+            .ok_or(SeqError::NoFanModule)
+    }
+
+    /// Sets the pattern for the given module, starting our ticks if this is
+    /// the first animated pattern.
+    pub fn set_pattern(
+        &mut self,
+        module: u8,
+        pattern: LedPattern,
+        now: u64,
+    ) -> Result<(), SeqError> {
+        let p = self
+            .patterns
+            .get_mut(usize::from(module))
+            .ok_or(SeqError::NoFanModule)?;
+        *p = pattern;
+
+        if !self.any_animated() {
+            self.deadline = None;
+        } else if self.deadline.is_none() {
+            self.deadline = Some(now + LedPattern::TICK_MS);
+        }
+        Ok(())
+    }
+
+    /// Returns the LEDs as they are currently lit, one bit per module.
+    pub fn state(&self) -> u8 {
+        self.patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_on(self.tick))
+            .fold(0, |mask, (module, _)| mask | (1 << module))
+    }
+
+    /// Returns when we next need to be polled, if any patterns are animated.
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    /// Advances our patterns if a tick is due.
+    pub fn poll(&mut self, now: u64) {
+        if let Some(deadline) = self.deadline {
+            if now >= deadline {
+                self.tick = self.tick.wrapping_add(1);
+                self.deadline = Some(deadline + LedPattern::TICK_MS);
+            }
+        }
+    }
+
+    fn any_animated(&self) -> bool {
+        self.patterns.iter().any(|p| p.is_animated())
+    }
+}
@@ -2,15 +2,48 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+//! Bring-up of the front IO board.
+//!
+//! The board can come and go (and can be slow to answer at boot), so rather
+//! than bringing it up once, we poll it from our timer: once it appears we
+//! load its FPGAs, then power up its PHY, holding the PHY in COMA_MODE so
+//! that it stays quiet until monorail has configured it.  If the board goes
+//! away we start over once it comes back; if we can't bring it up after a
+//! few attempts, we give up until it's reseated rather than restarting (and
+//! taking Tofino down with us).
+
 use crate::*;
 use drv_i2c_devices::{at24csw080::At24Csw080, Validate};
 use drv_sidecar_front_io::{controller::FrontIOController, phy_smi::PhySmi};
+use drv_sidecar_seq_api::FrontIOStatus;
+
+/// How many times we try to bring the board up before giving up on it
+const MAX_INIT_ATTEMPTS: u8 = 3;
+
+/// How long the PHY has to power up
+const PHY_READY_TIMEOUT_MS: u64 = 1000;
+
+/// How many polls in a row the board's FRUID has to be missing before we
+/// believe it's gone, so that one failed I2C read doesn't start us over
+const REMOVAL_DEBOUNCE: u8 = 3;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    NotPresent,
+    LoadingFpgas { attempts: u8 },
+    PhyPoweringUp { attempts: u8, deadline: u64 },
+    Ready,
+    Failed,
+}
 
 #[allow(dead_code)]
 pub(crate) struct FrontIOBoard {
     pub fruid: I2cDevice,
     pub controllers: [FrontIOController; 2],
     pub state_reset: bool,
+    state: State,
+    /// Polls in a row in which the FRUID has been missing
+    missing: u8,
     fpga_task: userlib::TaskId,
     auxflash_task: userlib::TaskId,
     i2c_task: userlib::TaskId,
@@ -29,6 +62,8 @@ impl FrontIOBoard {
                 FrontIOController::new(fpga_task, 1),
             ],
             state_reset: false,
+            state: State::NotPresent,
+            missing: 0,
             fpga_task,
             auxflash_task,
             i2c_task,
@@ -43,6 +78,115 @@ impl FrontIOBoard {
         At24Csw080::validate(&self.fruid).unwrap_or(false)
     }
 
+    pub fn status(&self) -> FrontIOStatus {
+        match self.state {
+            State::NotPresent => FrontIOStatus::NotPresent,
+            State::LoadingFpgas { .. } | State::PhyPoweringUp { .. } => {
+                FrontIOStatus::Initializing
+            }
+            State::Ready => FrontIOStatus::Ready,
+            State::Failed => FrontIOStatus::Failed,
+        }
+    }
+
+    /// Takes the next step in bringing the board up, or notices that it's
+    /// come or gone.
+    pub fn poll(&mut self) {
+        let prev = self.status();
+
+        if self.present() {
+            self.missing = 0;
+        } else if self.state == State::NotPresent {
+            return;
+        } else {
+            self.missing += 1;
+            if self.missing >= REMOVAL_DEBOUNCE {
+                ringbuf_entry!(Trace::NoFrontIOBoardPresent);
+                self.state = State::NotPresent;
+                self.missing = 0;
+            }
+            return;
+        }
+
+        self.state = match self.state {
+            State::NotPresent => {
+                ringbuf_entry!(Trace::FrontIOBoardPresent);
+                self.load_fpgas(0)
+            }
+            State::LoadingFpgas { attempts } => self.load_fpgas(attempts),
+            State::PhyPoweringUp { attempts, deadline } => {
+                self.await_phy(attempts, deadline)
+            }
+            State::Ready => {
+                // If the PHY has lost power (or its FPGA has been reset), go
+                // around again.
+                let phy_smi = self.phy_smi();
+                match phy_smi.phy_powered_up_and_ready() {
+                    Ok(true) => State::Ready,
+                    Ok(false) | Err(_) => {
+                        ringbuf_entry!(Trace::FrontIOVsc8562Lost);
+                        State::LoadingFpgas { attempts: 0 }
+                    }
+                }
+            }
+            State::Failed => State::Failed,
+        };
+
+        let status = self.status();
+        if status != prev {
+            ringbuf_entry!(Trace::FrontIOStatus(status));
+        }
+    }
+
+    fn load_fpgas(&mut self, attempts: u8) -> State {
+        if !self.init().unwrap_or(false) {
+            return self.retry(attempts);
+        }
+
+        // Hold the PHY in COMA_MODE as it powers up, so that it stays quiet
+        // until it's been configured.
+        let phy_smi = self.phy_smi();
+        let powered = phy_smi
+            .set_phy_coma_mode(true)
+            .and_then(|()| phy_smi.set_phy_power_enabled(true));
+        match powered {
+            Ok(()) => State::PhyPoweringUp {
+                attempts,
+                deadline: sys_get_timer().now + PHY_READY_TIMEOUT_MS,
+            },
+            Err(_) => self.retry(attempts),
+        }
+    }
+
+    fn await_phy(&mut self, attempts: u8, deadline: u64) -> State {
+        let phy_smi = self.phy_smi();
+        match phy_smi.phy_powered_up_and_ready() {
+            Ok(true) => {
+                ringbuf_entry!(Trace::FrontIOVsc8562Ready);
+                State::Ready
+            }
+            Ok(false) if sys_get_timer().now < deadline => {
+                State::PhyPoweringUp { attempts, deadline }
+            }
+            Ok(false) | Err(_) => {
+                ringbuf_entry!(Trace::FrontIOVsc8562Timeout);
+                // Power it off, so that the next attempt starts it cleanly.
+                let _ = phy_smi.set_phy_power_enabled(false);
+                self.retry(attempts)
+            }
+        }
+    }
+
+    fn retry(&self, attempts: u8) -> State {
+        let attempts = attempts + 1;
+        ringbuf_entry!(Trace::FrontIOInitFailed { attempts });
+        if attempts < MAX_INIT_ATTEMPTS {
+            State::LoadingFpgas { attempts }
+        } else {
+            State::Failed
+        }
+    }
+
     pub fn init(&mut self) -> Result<bool, FpgaError> {
         let mut controllers_ready = true;
 
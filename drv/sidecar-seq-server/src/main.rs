@@ -8,6 +8,7 @@
 #![no_main]
 
 use crate::clock_generator::ClockGenerator;
+use crate::fan_modules::FanModuleLeds;
 use crate::front_io::FrontIOBoard;
 use crate::tofino::Tofino;
 use drv_fpga_api::{DeviceState, FpgaError, WriteOp};
@@ -15,7 +16,10 @@ use drv_i2c_api::{I2cDevice, ResponseCode};
 use drv_packrat_vpd_loader::{read_vpd_and_load_packrat, Packrat};
 use drv_sidecar_mainboard_controller::tofino2::*;
 use drv_sidecar_mainboard_controller::MainboardController;
-use drv_sidecar_seq_api::{SeqError, TofinoSequencerPolicy};
+use drv_sidecar_seq_api::{
+    FpgaUserDesignIdent, FrontIOStatus, LedPattern, SeqError,
+    TofinoSequencerPolicy,
+};
 use idol_runtime::{
     ClientError, Leased, NotificationHandler, RequestError, R, W,
 };
@@ -31,6 +35,7 @@ task_slot!(PACKRAT, packrat);
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 mod clock_generator;
+mod fan_modules;
 mod front_io;
 mod tofino;
 
@@ -69,6 +74,7 @@ enum Trace {
     SetPCIePresent,
     ClearPCIePresent,
     ClearingTofinoSequencerFault(TofinoSeqError),
+    RetryingTofinoSequence(PowerRails),
     FrontIOBoardPresent,
     NoFrontIOBoardPresent,
     LoadingFrontIOControllerBitstream {
@@ -87,6 +93,12 @@ enum Trace {
         expected: [u8; 4],
     },
     FrontIOVsc8562Ready,
+    FrontIOVsc8562Timeout,
+    FrontIOVsc8562Lost,
+    FrontIOInitFailed {
+        attempts: u8,
+    },
+    FrontIOStatus(FrontIOStatus),
 }
 ringbuf!(Trace, 32, Trace::None);
 
@@ -97,6 +109,22 @@ struct ServerImpl {
     clock_generator: ClockGenerator,
     tofino: Tofino,
     front_io_board: FrontIOBoard,
+    fan_module_leds: FanModuleLeds,
+
+    /// Time of our next Tofino and front IO tick
+    deadline: u64,
+}
+
+impl ServerImpl {
+    /// Sets our timer for whichever is due first: our regular tick, or the
+    /// next step of an animated fan module LED pattern.
+    fn set_timer(&self) {
+        let deadline = match self.fan_module_leds.deadline() {
+            Some(leds) => leds.min(self.deadline),
+            None => self.deadline,
+        };
+        sys_set_timer(Some(deadline), notifications::TIMER_MASK);
+    }
 }
 
 impl idl::InOrderSequencerImpl for ServerImpl {
@@ -167,6 +195,61 @@ impl idl::InOrderSequencerImpl for ServerImpl {
             .map_err(RequestError::from)
     }
 
+    fn tofino_rail_status(
+        &mut self,
+        _: &RecvMessage,
+        rail: PowerRails,
+    ) -> Result<PowerRailState, RequestError<SeqError>> {
+        let rails = self
+            .tofino
+            .sequencer
+            .power_rails()
+            .map_err(SeqError::from)?;
+        Ok(rails[rail as usize].state)
+    }
+
+    fn tofino_rail_fault(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<PowerRails, RequestError<SeqError>> {
+        self.tofino
+            .sequencer
+            .rail_fault()
+            .map_err(SeqError::from)?
+            .ok_or(RequestError::Runtime(SeqError::NoRailFault))
+    }
+
+    fn retry_tofino_sequence(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<SeqError>> {
+        let rail = self
+            .tofino
+            .sequencer
+            .rail_fault()
+            .map_err(SeqError::from)?
+            .ok_or(SeqError::NoRailFault)?;
+        if self.tofino.policy == TofinoSequencerPolicy::Disabled {
+            return Err(SeqError::IllegalTransition.into());
+        }
+
+        //
+        // The sequencer enables the rails in a fixed order, so there's no
+        // bringing up the failed rail on its own: clear the abort and run
+        // the whole sequence again.
+        //
+        ringbuf_entry!(Trace::RetryingTofinoSequence(rail));
+        self.tofino
+            .sequencer
+            .clear_error()
+            .map_err(SeqError::from)?;
+        self.tofino
+            .sequencer
+            .set_enable(false)
+            .map_err(SeqError::from)?;
+        Ok(self.tofino.power_up()?)
+    }
+
     fn tofino_pcie_hotplug_ctrl(
         &mut self,
         _: &userlib::RecvMessage,
@@ -250,21 +333,58 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         &mut self,
         _: &RecvMessage,
     ) -> Result<bool, RequestError<SeqError>> {
-        Ok(self.front_io_board.present())
+        Ok(self.front_io_board.status() != FrontIOStatus::NotPresent)
     }
 
     fn front_io_phy_ready(
         &mut self,
         _: &RecvMessage,
     ) -> Result<bool, RequestError<SeqError>> {
-        if !self.front_io_board.present() {
-            Err(SeqError::NoFrontIOBoard.into())
-        } else {
-            let phy_smi = self.front_io_board.phy_smi();
-            Ok(phy_smi.phy_powered_up_and_ready().map_err(SeqError::from)?)
+        match self.front_io_board.status() {
+            FrontIOStatus::NotPresent => Err(SeqError::NoFrontIOBoard.into()),
+            FrontIOStatus::Failed => Err(SeqError::FrontIOBoardFailed.into()),
+            FrontIOStatus::Initializing => Ok(false),
+            FrontIOStatus::Ready => Ok(true),
         }
     }
 
+    fn front_io_status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FrontIOStatus, RequestError<SeqError>> {
+        Ok(self.front_io_board.status())
+    }
+
+    fn fan_module_led_pattern(
+        &mut self,
+        _: &RecvMessage,
+        module: u8,
+    ) -> Result<LedPattern, RequestError<SeqError>> {
+        Ok(self.fan_module_leds.pattern(module)?)
+    }
+
+    fn set_fan_module_led_pattern(
+        &mut self,
+        _: &RecvMessage,
+        module: u8,
+        pattern: LedPattern,
+    ) -> Result<(), RequestError<SeqError>> {
+        self.fan_module_leds.set_pattern(
+            module,
+            pattern,
+            sys_get_timer().now,
+        )?;
+        self.set_timer();
+        Ok(())
+    }
+
+    fn fan_module_leds(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u8, RequestError<SeqError>> {
+        Ok(self.fan_module_leds.state())
+    }
+
     fn tofino_debug_port_state(
         &mut self,
         _: &RecvMessage,
@@ -400,6 +520,16 @@ impl idl::InOrderSequencerImpl for ServerImpl {
             .map_err(SeqError::from)
             .map_err(RequestError::from)
     }
+
+    fn mainboard_controller_ident(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FpgaUserDesignIdent, RequestError<SeqError>> {
+        self.mainboard_controller
+            .read_ident()
+            .map_err(SeqError::from)
+            .map_err(RequestError::from)
+    }
 }
 
 impl NotificationHandler for ServerImpl {
@@ -410,23 +540,32 @@ impl NotificationHandler for ServerImpl {
     fn handle_notification(&mut self, _bits: u32) {
         let start = sys_get_timer().now;
 
-        if let Err(e) = self.tofino.handle_tick() {
-            ringbuf_entry!(Trace::TofinoSequencerError(e));
-        }
+        // The fan module LEDs tick faster than we do, so we may have been
+        // woken just for them.
+        self.fan_module_leds.poll(start);
+
+        if start >= self.deadline {
+            if let Err(e) = self.tofino.handle_tick() {
+                ringbuf_entry!(Trace::TofinoSequencerError(e));
+            }
 
-        let finish = sys_get_timer().now;
+            self.front_io_board.poll();
 
-        // We now know when we were notified and when any work was completed.
-        // Note that the assumption here is that `start` < `finish` and that
-        // this won't hold if the system time rolls over. But, the system timer
-        // is a u64, with each bit representing a ms, so in practice this should
-        // be fine. Anyway, armed with this information, find the next deadline
-        // some multiple of `TIMER_INTERVAL` in the future.
+            let finish = sys_get_timer().now;
 
-        let delta = finish - start;
-        let next_deadline = finish + TIMER_INTERVAL - (delta % TIMER_INTERVAL);
+            // We now know when we were notified and when any work was
+            // completed. Note that the assumption here is that `start` <
+            // `finish` and that this won't hold if the system time rolls over.
+            // But, the system timer is a u64, with each bit representing a ms,
+            // so in practice this should be fine. Anyway, armed with this
+            // information, find the next deadline some multiple of
+            // `TIMER_INTERVAL` in the future.
 
-        sys_set_timer(Some(next_deadline), notifications::TIMER_MASK);
+            let delta = finish - start;
+            self.deadline = finish + TIMER_INTERVAL - (delta % TIMER_INTERVAL);
+        }
+
+        self.set_timer();
     }
 }
 
@@ -449,6 +588,8 @@ fn main() -> ! {
         clock_generator,
         tofino,
         front_io_board,
+        fan_module_leds: FanModuleLeds::new(),
+        deadline: 0,
     };
 
     ringbuf_entry!(Trace::FpgaInit);
@@ -579,23 +720,15 @@ fn main() -> ! {
     }
     ringbuf_entry!(Trace::ClockConfigurationComplete);
 
-    // Initialize a connected Front IO board.
-    if server.front_io_board.present() {
-        ringbuf_entry!(Trace::FrontIOBoardPresent);
-
-        if !server.front_io_board.init().unwrap() {
-            panic!();
-        }
-
-        let phy_smi = server.front_io_board.phy_smi();
-        phy_smi.set_phy_power_enabled(true).unwrap();
-
-        while !phy_smi.phy_powered_up_and_ready().unwrap() {
-            userlib::hl::sleep_for(10);
-        }
-
-        ringbuf_entry!(Trace::FrontIOVsc8562Ready);
-    } else {
+    // Bring up a connected front IO board before anyone asks about it; from
+    // here on, our timer keeps an eye on it (and brings up one that arrives
+    // later).
+    server.front_io_board.poll();
+    while server.front_io_board.status() == FrontIOStatus::Initializing {
+        userlib::hl::sleep_for(10);
+        server.front_io_board.poll();
+    }
+    if server.front_io_board.status() == FrontIOStatus::NotPresent {
         ringbuf_entry!(Trace::NoFrontIOBoardPresent);
     }
 
@@ -642,8 +775,9 @@ fn main() -> ! {
 
 mod idl {
     use super::{
-        DebugPortState, DirectBarSegment, SeqError, TofinoPcieReset,
-        TofinoSeqError, TofinoSeqState, TofinoSeqStep, TofinoSequencerPolicy,
+        DebugPortState, DirectBarSegment, FrontIOStatus, LedPattern,
+        PowerRailState, PowerRails, SeqError, TofinoPcieReset, TofinoSeqError,
+        TofinoSeqState, TofinoSeqStep, TofinoSequencerPolicy,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
@@ -41,6 +41,15 @@ pub enum SpiError {
     ///
     /// This is almost certainly a programming error on the client side.
     BadDevice = 4,
+
+    /// Another task holds (or is next in line for) the controller. The server
+    /// has queued the caller, which should retry shortly; the `SpiServer`
+    /// impl for `Spi` does this automatically.
+    Busy = 5,
+
+    /// The caller's lock was forcibly released because it sat idle for too
+    /// long; any CS state it had set up is gone.
+    LockExpired = 6,
 }
 
 impl From<SpiError> for GwSpiError {
@@ -50,6 +59,8 @@ impl From<SpiError> for GwSpiError {
             SpiError::TaskRestarted => Self::TaskRestarted,
             SpiError::NothingToRelease => Self::NothingToRelease,
             SpiError::BadDevice => Self::BadDevice,
+            SpiError::Busy => Self::Busy,
+            SpiError::LockExpired => Self::LockExpired,
         }
     }
 }
@@ -63,6 +74,30 @@ pub enum CsState {
     Asserted = 1,
 }
 
+/// Counters describing contention for the controller lock, as returned by
+/// `Spi::lock_stats`.
+#[derive(
+    Copy, Clone, Debug, Default, zerocopy::AsBytes, zerocopy::FromBytes,
+)]
+#[repr(C)]
+pub struct SpiLockStats {
+    /// Number of times the controller has been locked
+    pub locks: u32,
+    /// Requests turned away because another task held the controller or was
+    /// ahead in the queue
+    pub contended: u32,
+    /// Locks forcibly released because the holder sat idle too long
+    pub timeouts: u32,
+    /// Locks forcibly released because the holder faulted
+    pub forced_releases: u32,
+    /// Queued tasks dropped for not coming back when it was their turn
+    pub abandoned: u32,
+    /// Requests turned away without being queued, because the queue was full
+    pub queue_overflows: u32,
+    /// Deepest the wait queue has been
+    pub max_queue_depth: u32,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct ControllerLock<'a, S: SpiServer>(&'a S);
@@ -123,6 +158,31 @@ pub trait SpiServer {
     fn release(&self) -> Result<(), SpiError>;
 }
 
+/// How long to wait before retrying a request that the server turned away
+/// with `SpiError::Busy`, in milliseconds.
+const BUSY_RETRY_MS: u64 = 1;
+
+/// Issues `op`, retrying for as long as the server says it's `Busy`. The
+/// server queues us on the first refusal and holds the controller for us
+/// when our turn comes, so this doesn't starve.
+///
+/// This isn't free: where a contended request used to block in the kernel
+/// until the server would take it, a waiting task now wakes every
+/// `BUSY_RETRY_MS` to ask again, costing it a timer wakeup and an IPC per
+/// retry, and it may start up to `BUSY_RETRY_MS` after the controller frees
+/// up. A task that would rather do something else while it waits can call
+/// the inherent `Spi` methods, which return `Busy` to it directly.
+fn retry_busy(
+    mut op: impl FnMut() -> Result<(), SpiError>,
+) -> Result<(), SpiError> {
+    loop {
+        match op() {
+            Err(SpiError::Busy) => hl::sleep_for(BUSY_RETRY_MS),
+            r => return r,
+        }
+    }
+}
+
 impl SpiServer for Spi {
     fn exchange(
         &self,
@@ -130,14 +190,14 @@ impl SpiServer for Spi {
         src: &[u8],
         dest: &mut [u8],
     ) -> Result<(), SpiError> {
-        Spi::exchange(self, device_index, src, dest)
+        retry_busy(|| Spi::exchange(self, device_index, src, dest))
     }
     fn write(&self, device_index: u8, src: &[u8]) -> Result<(), SpiError> {
-        Spi::write(self, device_index, src)
+        retry_busy(|| Spi::write(self, device_index, src))
     }
 
     fn read(&self, device_index: u8, dest: &mut [u8]) -> Result<(), SpiError> {
-        Spi::read(self, device_index, dest)
+        retry_busy(|| Spi::read(self, device_index, dest))
     }
 
     fn lock(
@@ -145,7 +205,7 @@ impl SpiServer for Spi {
         device_index: u8,
         cs_state: CsState,
     ) -> Result<(), SpiError> {
-        Spi::lock(self, device_index, cs_state)
+        retry_busy(|| Spi::lock(self, device_index, cs_state))
     }
 
     fn release(&self) -> Result<(), SpiError> {
@@ -206,9 +266,12 @@ impl<S: SpiServer> SpiDevice<S> {
     /// Locks the SPI controller in communication between your task and the
     /// device.
     ///
-    /// If the server receives this message, it means no other task had locked
-    /// it. It will respond by only listening to messages from your task until
-    /// you send `release` or crash.
+    /// If another task has the controller locked, this waits its turn; other
+    /// tasks waiting for the controller are served in the order they asked.
+    /// Once locked, the server will only serve your task until you send
+    /// `release` or crash -- or until you go longer than the server's lock
+    /// timeout without using the controller, at which point the lock is
+    /// forcibly released and your next operation gets `LockExpired`.
     ///
     /// During this time, the server will refuse any attempts to manipulate a
     /// device other than the `device_index` of this device.
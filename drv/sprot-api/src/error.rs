@@ -9,6 +9,7 @@ use drv_spi_api::SpiError;
 use drv_update_api::UpdateError;
 use dumper_api::DumperError;
 use hubpack::SerializedSize;
+pub use measurements::MeasurementError;
 use serde::{Deserialize, Serialize};
 
 use gateway_messages::{
@@ -184,3 +185,60 @@ impl From<DumpOrSprotError> for Result<(), RequestError<DumpOrSprotError>> {
         Err(RequestError::Runtime(err.into()))
     }
 }
+
+/// An error from the RoT's certificate store
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum CertError {
+    /// There's no certificate at that index.
+    NoCert,
+    /// The index is past the end of the store.
+    BadIndex,
+    /// The offset is past the end of the certificate.
+    BadOffset,
+    /// The certificate is empty, too big to store, or shorter than the chunk.
+    BadLength,
+    /// A chunk didn't follow on from the ones before it.
+    OutOfOrder,
+    /// Storing or reading the certificate failed.
+    FlashError,
+    /// The chain has been locked, so can't be written.
+    Locked,
+}
+
+impl From<SprotError> for RequestError<CertOrSprotError> {
+    fn from(err: SprotError) -> Self {
+        CertOrSprotError::from(err).into()
+    }
+}
+
+impl From<CertError> for RequestError<CertOrSprotError> {
+    fn from(err: CertError) -> Self {
+        CertOrSprotError::from(err).into()
+    }
+}
+
+#[derive(Copy, Clone, Debug, From, Deserialize, Serialize, SerializedSize)]
+pub enum CertOrSprotError {
+    Cert(CertError),
+    Sprot(SprotError),
+}
+
+impl From<SprotError> for RequestError<MeasurementOrSprotError> {
+    fn from(err: SprotError) -> Self {
+        MeasurementOrSprotError::from(err).into()
+    }
+}
+
+impl From<MeasurementError> for RequestError<MeasurementOrSprotError> {
+    fn from(err: MeasurementError) -> Self {
+        MeasurementOrSprotError::from(err).into()
+    }
+}
+
+#[derive(Copy, Clone, Debug, From, Deserialize, Serialize, SerializedSize)]
+pub enum MeasurementOrSprotError {
+    Measurement(MeasurementError),
+    Sprot(SprotError),
+}
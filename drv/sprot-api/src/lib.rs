@@ -11,7 +11,8 @@ extern crate memoffset;
 mod error;
 use dumper_api::DumperError;
 pub use error::{
-    DumpOrSprotError, SprocketsError, SprotError, SprotProtocolError,
+    CertError, CertOrSprotError, DumpOrSprotError, MeasurementError,
+    MeasurementOrSprotError, SprocketsError, SprotError, SprotProtocolError,
 };
 
 use crc::{Crc, CRC_16_XMODEM};
@@ -21,6 +22,10 @@ pub use drv_update_api::{
 };
 use hubpack::SerializedSize;
 use idol_runtime::{Leased, LenLimit, R};
+pub use measurements::{
+    Quote, MAX_MEASUREMENT_DIGEST, MEASUREMENT_SIZE, NUM_MEASUREMENT_REGS,
+    QUOTE_DOMAIN_TAG, SIGNATURE_SIZE,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 pub use sprockets_common::msgs::{
     RotRequestV1 as SprocketsReq, RotResponseV1 as SprocketsRsp,
@@ -42,7 +47,7 @@ pub const MIN_VERSION: Version = Version(2);
 /// Code between the `CURRENT_VERSION` and `MIN_VERSION` must remain
 /// compatible. Use the rules described in the comments for [`Msg`] to evolve
 /// the protocol such that this remains true.
-pub const CURRENT_VERSION: Version = Version(2);
+pub const CURRENT_VERSION: Version = Version(4);
 
 /// We allow room in the buffer for message evolution
 pub const REQUEST_BUF_SIZE: usize = 1024;
@@ -197,10 +202,23 @@ where
     // Note that we unwrap instead of returning an error here because failure
     // to serialize is a programmer error rather than a runtime error.
     pub fn pack(body: &T, buf: &mut [u8; N]) -> usize {
+        Self::pack_with_slice(body, buf, &[])
+    }
+
+    /// Serialize a `Header` followed by a `ReqBody` or `RspBody`, copy a blob
+    /// that we already hold into `buf` after the serialized body, compute a
+    /// CRC, serialize the CRC, and return the total size of the serialized
+    /// message.
+    pub fn pack_with_slice(body: &T, buf: &mut [u8; N], blob: &[u8]) -> usize {
         // Serialize `body`
         let mut size = hubpack::serialize(&mut buf[Header::MAX_SIZE..], body)
             .unwrap_lite();
 
+        // Copy the blob into the buffer after the serialized body
+        let start = Header::MAX_SIZE + size;
+        buf[start..start + blob.len()].copy_from_slice(blob);
+        size += blob.len();
+
         // Create a header, now that we know the size of the body
         let header = Header::new(size.try_into().unwrap_lite());
 
@@ -308,6 +326,10 @@ pub enum ReqBody {
     Update(UpdateReq),
     Sprockets(SprocketsReq),
     Dump(DumpReq),
+    // Added in version 3
+    Certs(CertReq),
+    // Added in version 4
+    Measurements(MeasurementReq),
 }
 
 /// Instruct the RoT to take a dump of the SP via SWD
@@ -318,6 +340,77 @@ pub enum DumpReq {
     V1 { addr: u32 },
 }
 
+/// A request for the RoT's stored certificate chain
+///
+/// The chain is stored leaf first: certificate 0 is the device identity, and
+/// each after it is the one that signed the certificate before it. Any one
+/// certificate may be bigger than a blob, so they're moved in chunks.
+//
+// Added in version 3
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum CertReq {
+    /// Return the number of certificates in the chain
+    Count,
+    /// Return the length of certificate `index`
+    Len { index: u32 },
+    /// Return as much of certificate `index` as fits in a blob, starting at
+    /// `offset`
+    Read { index: u32, offset: u32 },
+    /// Write the blob at `offset` into certificate `index`, which is `len`
+    /// bytes long in all. Chunks must be sent in order; the certificate
+    /// replaces whatever was at `index` once its last chunk arrives.
+    Write { index: u32, offset: u32, len: u32 },
+    /// Refuse any further writes, for good
+    Lock,
+}
+
+/// A response to a `CertReq`
+//
+// Added in version 3
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum CertRsp {
+    Count(u32),
+    Len(u32),
+    /// The part of the certificate that was read is the blob
+    Read,
+    Written,
+    Err(CertError),
+    Locked,
+}
+
+/// A request for the RoT's measurement registers
+///
+/// Each register starts out as zeroes when the RoT sees the SP reset, and
+/// extending it with a digest replaces its value `r` with `SHA3-256(r ||
+/// digest)`, so a register's value commits to everything extended into it,
+/// in order, since then.
+//
+// Added in version 4
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum MeasurementReq {
+    /// Extend register `index` with the digest in the blob
+    Extend { index: u32 },
+    /// Return the value of register `index`
+    Read { index: u32 },
+    /// Return a signed [`Quote`] of every register
+    Quote { nonce: [u8; 32] },
+}
+
+/// A response to a `MeasurementReq`
+//
+// Added in version 4
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub enum MeasurementRsp {
+    Extended,
+    Value([u8; MEASUREMENT_SIZE]),
+    /// The blob is the serialized [`Quote`], followed by the RoT alias key's
+    /// Ed25519 signature of [`QUOTE_DOMAIN_TAG`] and the quote
+    Quote,
+    Err(MeasurementError),
+}
+
+const_assert!(Quote::MAX_SIZE + SIGNATURE_SIZE <= MAX_BLOB_SIZE);
+
 /// A request used for RoT updates
 #[derive(Clone, Serialize, Deserialize, SerializedSize)]
 pub enum UpdateReq {
@@ -362,6 +455,10 @@ pub enum RspBody {
     Update(UpdateRsp),
     Sprockets(SprocketsRsp),
     Dump(DumpRsp),
+    // Added in version 3
+    Certs(CertRsp),
+    // Added in version 4
+    Measurements(MeasurementRsp),
 }
 
 /// A response from the Dumper
@@ -495,4 +592,12 @@ pub struct SprotIoStats {
     pub sp: SpIoStats,
 }
 
+impl SpRot {
+    /// Checks that the sprot server was built from the same revision of this
+    /// interface as we were; if it wasn't, it may misread our requests.
+    pub fn interface_matches(&self) -> Result<bool, SprotError> {
+        Ok(self.interface_hash()? == SP_ROT_INTERFACE_HASH)
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
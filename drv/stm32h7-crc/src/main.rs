@@ -0,0 +1,130 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Driver for the STM32H7 CRC unit.
+//!
+//! Use the crc-api crate to interact with this driver.
+//!
+//! The unit is reprogrammed with the caller's polynomial and running state on
+//! every request, so requests from different clients can interleave freely.
+//! The H7 unit handles 7, 8, 16 and 32-bit odd polynomials; anything else is
+//! refused as `Unsupported`, for the client to compute in software.
+
+#![no_std]
+#![no_main]
+
+use drv_crc_api::{CrcError, CrcParams, MAX_UPDATE_SZ};
+use drv_stm32xx_sys_api::{Peripheral, Sys};
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R};
+
+#[cfg(feature = "h743")]
+use stm32h7::stm32h743 as device;
+
+#[cfg(feature = "h753")]
+use stm32h7::stm32h753 as device;
+
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+/// How much of a lease we copy in at once.
+const CHUNK: usize = 256;
+
+struct ServerImpl {
+    crc: &'static device::crc::RegisterBlock,
+}
+
+impl ServerImpl {
+    fn feed(&self, data: &[u8]) {
+        let dr = &self.crc.dr;
+        let mut words = data.chunks_exact(4);
+        for word in &mut words {
+            // The unit takes the most significant byte of a word first.
+            let word = u32::from_be_bytes(word.try_into().unwrap_lite());
+            dr.write(|w| unsafe { w.bits(word) });
+        }
+        // Stragglers have to be written with byte accesses, or the unit
+        // would take them as a whole word.
+        for &b in words.remainder() {
+            unsafe {
+                core::ptr::write_volatile(dr.as_ptr() as *mut u8, b);
+            }
+        }
+    }
+}
+
+impl idl::InOrderCrcImpl for ServerImpl {
+    fn update(
+        &mut self,
+        _: &RecvMessage,
+        params: CrcParams,
+        state: u32,
+        data: LenLimit<Leased<R, [u8]>, MAX_UPDATE_SZ>,
+    ) -> Result<u32, RequestError<CrcError>> {
+        if !params.is_valid() {
+            return Err(CrcError::BadParams.into());
+        }
+        let polysize = match params.width {
+            32 => 0b00,
+            16 => 0b01,
+            8 => 0b10,
+            7 => 0b11,
+            _ => return Err(CrcError::Unsupported.into()),
+        };
+        if params.poly & 1 == 0 {
+            return Err(CrcError::Unsupported.into());
+        }
+
+        self.crc.pol.write(|w| unsafe { w.bits(params.poly) });
+        self.crc.init.write(|w| unsafe { w.bits(state) });
+        // We always read the register unreflected, since that's the state
+        // clients carry between calls; reflection of the output happens when
+        // they finalize.
+        self.crc.cr.write(|w| unsafe {
+            w.polysize()
+                .bits(polysize)
+                .rev_in()
+                .bits(if params.reflect_in != 0 { 0b01 } else { 0b00 })
+                .rev_out()
+                .clear_bit()
+                .reset()
+                .set_bit()
+        });
+
+        let mut buf = [0; CHUNK];
+        let mut offset = 0;
+        while offset < data.len() {
+            let n = usize::min(CHUNK, data.len() - offset);
+            data.read_range(offset..offset + n, &mut buf[..n])
+                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+            self.feed(&buf[..n]);
+            offset += n;
+        }
+
+        let mask = u32::MAX >> (32 - u32::from(params.width));
+        Ok(self.crc.dr.read().bits() & mask)
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    sys.enable_clock(Peripheral::Crc);
+    sys.leave_reset(Peripheral::Crc);
+
+    let mut server = ServerImpl {
+        crc: unsafe { &*device::CRC::ptr() },
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_crc_api::{CrcError, CrcParams};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
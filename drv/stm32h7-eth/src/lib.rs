@@ -190,6 +190,7 @@ impl Ethernet {
         // later, once we figure out how we assign MAC addresses across the
         // redundant segments.
         mac.macpfr.write(|w| w.pr().set_bit());
+
         // Force 100mbps full-duplex. TODO: it would be polite to negotiate
         // this, but the KSZ-series switches we talk to won't negotiate.
         mac.maccr.write(|w| {
@@ -473,6 +474,111 @@ impl Ethernet {
         Some(result)
     }
 
+    /// Returns counts of bad frames received so far.
+    pub fn rx_errors(&self) -> ring::RxErrors {
+        self.rx_ring.errors()
+    }
+
+    /// Sets the counts returned by `rx_errors` back to zero.
+    pub fn reset_rx_errors(&self) {
+        self.rx_ring.reset_errors()
+    }
+
+    /// Starts the IEEE 1588 clock from zero, and has every frame in and out
+    /// timestamped against it.
+    ///
+    /// We use fine correction, where the clock ticks on by `PTP_TICK_NS` each
+    /// time adding `addend` to a 32-bit accumulator at the AHB clock rate
+    /// carries, so that someone synchronizing it later can trim the rate.
+    /// For ticks at the right rate, `addend` must be 2^32 times the tick rate
+    /// over the AHB clock rate; the `net` build works it out from the config.
+    #[cfg(feature = "ptp")]
+    pub fn start_ptp_clock(&self, addend: u32) {
+        let mac = self.mac;
+        mac.macssir
+            .write(|w| unsafe { w.ssinc().bits(PTP_TICK_NS) });
+        mac.mactsar.write(|w| unsafe { w.tsar().bits(addend) });
+        mac.mactscr.write(|w| {
+            w.tsena()
+                .set_bit()
+                .tscfupdt()
+                .set_bit()
+                .tsctrlssr() // count nanoseconds, not 2^-31 seconds
+                .set_bit()
+                .tsenall()
+                .set_bit()
+        });
+        mac.mactscr.modify(|_, w| w.tsaddreg().set_bit());
+        crappy_spin_until(|| !mac.mactscr.read().tsaddreg().bit());
+        mac.macstsur.write(|w| unsafe { w.tss().bits(0) });
+        mac.macstnur.write(|w| unsafe { w.tsss().bits(0) });
+        mac.mactscr.modify(|_, w| w.tsinit().set_bit());
+        crappy_spin_until(|| !mac.mactscr.read().tsinit().bit());
+    }
+
+    /// Reads the IEEE 1588 clock that frames are timestamped against.
+    #[cfg(feature = "ptp")]
+    pub fn ptp_now(&self) -> ring::Timestamp {
+        // The nanoseconds may roll over between reading the two halves, so
+        // read the seconds on both sides and try again if they don't match.
+        loop {
+            let seconds = self.mac.macstsr.read().tss().bits();
+            let nanos = self.mac.macstnr.read().tsss().bits();
+            if self.mac.macstsr.read().tss().bits() == seconds {
+                return ring::Timestamp { seconds, nanos };
+            }
+        }
+    }
+
+    /// Returns the timestamp of the frame being received, when called from
+    /// within the `readout` closure passed to `recv` or `vlan_recv`.
+    #[cfg(feature = "ptp")]
+    pub fn rx_timestamp(&self) -> Option<ring::Timestamp> {
+        self.rx_ring.timestamp()
+    }
+
+    /// Returns the number the next frame queued by `try_send` or
+    /// `vlan_try_send` will get (and, from within the `fillout` closure, the
+    /// number of the frame being queued), for `tx_timestamp`.
+    #[cfg(feature = "ptp")]
+    pub fn next_tx_frame(&self) -> u32 {
+        self.tx_ring.next_queued()
+    }
+
+    /// Returns when frame `frame`, as numbered by `next_tx_frame`, went out.
+    /// Returns `None` if it hasn't gone out yet, or went out so long ago that
+    /// the timestamp has been overwritten.
+    #[cfg(feature = "ptp")]
+    pub fn tx_timestamp(&self, frame: u32) -> Option<ring::Timestamp> {
+        self.tx_ring.timestamp(frame)
+    }
+
+    /// Loads the multicast hash filter with `groups`, so frames sent to any
+    /// of them (and to the others sharing their hash bins) get through.
+    ///
+    /// This replaces whatever the filter held before. While the MAC is
+    /// promiscuous, as it is for now, every frame gets through regardless;
+    /// the filter is kept current so that turning that off doesn't cost us
+    /// multicast.
+    pub fn set_multicast_filter(
+        &self,
+        groups: impl IntoIterator<Item = [u8; 6]>,
+    ) {
+        let mut table = 0u64;
+        for mac in groups {
+            // The MAC indexes its 64-bin table with the top six bits of the
+            // bit-reversed, inverted CRC-32 of the destination address.
+            table |= 1u64 << ((!crc32(&mac)).reverse_bits() >> 26);
+        }
+        self.mac
+            .macht0r
+            .write(|w| unsafe { w.ht31t0().bits(table as u32) });
+        self.mac
+            .macht1r
+            .write(|w| unsafe { w.ht63t32().bits((table >> 32) as u32) });
+        self.mac.macpfr.modify(|_, w| w.hmc().set_bit());
+    }
+
     pub fn can_recv(&self) -> bool {
         let (can_recv, any_dropped) = self.rx_ring.is_next_free();
         if any_dropped {
@@ -543,3 +649,25 @@ impl Ethernet {
         Some(result)
     }
 }
+
+/// Nanoseconds the IEEE 1588 clock moves on by at each tick. (`build-net`
+/// works out the addend for this tick from the AHB clock rate.)
+#[cfg(feature = "ptp")]
+const PTP_TICK_NS: u8 = 20;
+
+/// The Ethernet CRC-32 of `data`, as the MAC computes it for hash filtering:
+/// reflected, starting from all ones, without the final inversion.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
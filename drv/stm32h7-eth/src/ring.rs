@@ -94,6 +94,39 @@ const TDES3_CIC_BIT: u32 = 16;
 /// CIC value for enabling all checksum offloading.
 const TDES3_CIC_CHECKSUMS_ENABLED: u32 = 0b11;
 
+// TDES bits which are only used for timestamping, gated to avoid compiler
+// warnings
+cfg_if::cfg_if! {
+    if #[cfg(feature = "ptp")] {
+        /// Index of Transmit Timestamp Enable bit, asking the MAC to record
+        /// when the packet goes out.
+        const TDES2_TTSE_BIT: u32 = 30;
+        /// Index of Tx Timestamp Status bit, in a descriptor the DMA has
+        /// written back, indicating that TDES0 and TDES1 hold the timestamp.
+        const TDES3_TTSS_BIT: u32 = 17;
+    }
+}
+
+/// A time from the MAC's IEEE 1588 clock, which counts from when the driver
+/// started it unless someone has set it since.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Timestamp {
+    pub seconds: u32,
+    pub nanos: u32,
+}
+
+impl Timestamp {
+    /// Reads the timestamp the DMA has written back into the first two words
+    /// of a descriptor: nanoseconds, then seconds.
+    #[cfg(feature = "ptp")]
+    fn from_desc(desc: &[AtomicU32; 4]) -> Self {
+        Self {
+            nanos: desc[0].load(Ordering::Relaxed),
+            seconds: desc[1].load(Ordering::Relaxed),
+        }
+    }
+}
+
 // TDES bits which are only used in VLAN code, gated to avoid compiler warnings
 cfg_if::cfg_if! {
     if #[cfg(feature = "vlan")] {
@@ -118,6 +151,10 @@ pub struct TxRing {
     /// next transmitted packet. This must be in the range `0..storage.len()` at
     /// all times.
     next: Cell<usize>,
+    /// Count of packets queued so far, which numbers them for
+    /// `timestamp`.
+    #[cfg(feature = "ptp")]
+    queued: Cell<u32>,
 }
 
 impl TxRing {
@@ -156,6 +193,8 @@ impl TxRing {
             storage,
             buffers,
             next: Cell::new(0),
+            #[cfg(feature = "ptp")]
+            queued: Cell::new(0),
         }
     }
 
@@ -172,8 +211,61 @@ impl TxRing {
     pub fn tail_ptr(&self) -> *const TxDesc {
         self.storage.as_ptr_range().end
     }
+
+    /// Returns the number the next packet queued will get (and, from within
+    /// the `body` closure, the number of the packet being queued), to be
+    /// passed to `timestamp`.
+    #[cfg(feature = "ptp")]
+    pub fn next_queued(&self) -> u32 {
+        self.queued.get()
+    }
+
+    /// Returns the time that packet number `packet` (as numbered by
+    /// `next_queued`) went out, if it has gone out and its descriptor hasn't
+    /// been used for another packet since.
+    #[cfg(feature = "ptp")]
+    pub fn timestamp(&self, packet: u32) -> Option<Timestamp> {
+        // Each packet takes the next slot in the ring, so we can count back
+        // from `next` to find where this one went; that slot is reused for
+        // the packet `storage.len()` after it.
+        let since = self.queued.get().wrapping_sub(packet) as usize;
+        let len = self.storage.len();
+        if since == 0 || since > len {
+            return None;
+        }
+        let d = &self.storage[(self.next.get() + len - since) % len];
+        #[cfg(not(feature = "vlan"))]
+        let desc = &d.tdes;
+        #[cfg(feature = "vlan")]
+        let desc = &d.tdes[1];
+
+        let tdes3 = desc[3].load(Ordering::Acquire);
+        if tdes3 & (1 << TDES3_OWN_BIT) != 0
+            || tdes3 & (1 << TDES3_TTSS_BIT) == 0
+        {
+            return None;
+        }
+        Some(Timestamp::from_desc(desc))
+    }
+
+    /// Moves on to the next slot, once we've filled this one.
+    fn advance(&self) {
+        self.next.set(if self.next.get() + 1 == self.storage.len() {
+            0
+        } else {
+            self.next.get() + 1
+        });
+        #[cfg(feature = "ptp")]
+        self.queued.set(self.queued.get().wrapping_add(1));
+    }
 }
 
+/// TDES2 bits to set in every transmit descriptor, besides the length.
+#[cfg(feature = "ptp")]
+const TDES2_FLAGS: u32 = 1 << TDES2_TTSE_BIT;
+#[cfg(not(feature = "ptp"))]
+const TDES2_FLAGS: u32 = 0;
+
 #[cfg(not(feature = "vlan"))]
 impl TxRing {
     /// Returns the count of entries in the descriptor ring / buffers in the
@@ -241,7 +333,7 @@ impl TxRing {
             // is set in TDES3 using a RELEASE store.
             d.tdes[0].store(buffer.as_ptr() as u32, Ordering::Relaxed);
             d.tdes[1].store(0, Ordering::Relaxed);
-            d.tdes[2].store(len as u32, Ordering::Relaxed);
+            d.tdes[2].store(TDES2_FLAGS | len as u32, Ordering::Relaxed);
             let tdes3 = 1 << TDES3_OWN_BIT
                 | 1 << TDES3_FD_BIT
                 | 1 << TDES3_LD_BIT
@@ -249,11 +341,7 @@ impl TxRing {
                 | len as u32;
             d.tdes[3].store(tdes3, Ordering::Release); // <-- release
 
-            self.next.set(if self.next.get() + 1 == self.storage.len() {
-                0
-            } else {
-                self.next.get() + 1
-            });
+            self.advance();
 
             Some(result)
         }
@@ -328,7 +416,8 @@ impl TxRing {
             // same strategy as above for memory access ordering.
             d.tdes[1][0].store(buffer.as_ptr() as u32, Ordering::Relaxed);
             d.tdes[1][1].store(0, Ordering::Relaxed);
-            let tdes2 = TDES2_VTIR_INSERT << TDES2_VTIR_BIT | len as u32;
+            let tdes2 =
+                TDES2_FLAGS | TDES2_VTIR_INSERT << TDES2_VTIR_BIT | len as u32;
             d.tdes[1][2].store(tdes2, Ordering::Relaxed);
             let tdes3 = 1 << TDES3_OWN_BIT
                 | 1 << TDES3_FD_BIT
@@ -337,11 +426,7 @@ impl TxRing {
                 | len as u32;
             d.tdes[1][3].store(tdes3, Ordering::Release); // <-- release
 
-            self.next.set(if self.next.get() + 1 == self.storage.len() {
-                0
-            } else {
-                self.next.get() + 1
-            });
+            self.advance();
 
             Some(result)
         }
@@ -384,6 +469,78 @@ const RDES3_LD_BIT: u32 = 28;
 const RDES3_BUF1_VALID_BIT: u32 = 24;
 /// Mask for the Packet Length portion of RDES3.
 const RDES3_PL_MASK: u32 = (1 << 15) - 1;
+/// Index of CRC Error bit, in a descriptor the DMA has written back.
+const RDES3_CE_BIT: u32 = 24;
+/// Index of Overflow Error bit, in a descriptor the DMA has written back.
+const RDES3_OE_BIT: u32 = 21;
+/// Index of Receive Status RDES1 Valid bit, indicating that RDES1 has been
+/// written by the DMA.
+const RDES3_RS1V_BIT: u32 = 26;
+/// Index of IP Header Error bit, which is set when the IPv4 header checksum
+/// (or the IP version, or length) doesn't match.
+const RDES1_IPHE_BIT: u32 = 3;
+/// Index of IP Payload Error bit, which is set when the TCP, UDP or ICMP
+/// checksum doesn't match.
+const RDES1_IPCE_BIT: u32 = 7;
+
+// RDES bits which are only used for timestamping, gated to avoid compiler
+// warnings
+cfg_if::cfg_if! {
+    if #[cfg(feature = "ptp")] {
+        /// Index of Timestamp Available bit, indicating that the DMA writes
+        /// the frame's timestamp into a context descriptor following this
+        /// one.
+        const RDES1_TSA_BIT: u32 = 14;
+        /// Index of Receive Context Descriptor bit, set in a descriptor the
+        /// DMA has written back with a timestamp rather than a frame.
+        const RDES3_CTXT_BIT: u32 = 30;
+    }
+}
+
+/// Counts of received frames that were bad in one way or another.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RxErrors {
+    /// Frames dropped because their CRC didn't match.
+    pub crc: u32,
+    /// Frames dropped because the receive FIFO overflowed.
+    pub overflow: u32,
+    /// Frames dropped for any other error the MAC reports (or because they
+    /// didn't fit in a single buffer).
+    pub other: u32,
+    /// Frames dropped because they didn't have a VLAN tag we know about.
+    pub unknown_vlan: u32,
+    /// Frames dropped because their IP, TCP, UDP or ICMP checksum didn't
+    /// match. (The IP stack leaves checking these to us.)
+    pub checksum: u32,
+}
+
+impl RxErrors {
+    /// Counts a frame we're about to drop, described by `rdes3`.
+    fn drop(&mut self, rdes3: u32) {
+        let field = if rdes3 & (1 << RDES3_ES_BIT) == 0 {
+            &mut self.other
+        } else if rdes3 & (1 << RDES3_CE_BIT) != 0 {
+            &mut self.crc
+        } else if rdes3 & (1 << RDES3_OE_BIT) != 0 {
+            &mut self.overflow
+        } else {
+            &mut self.other
+        };
+        *field = field.wrapping_add(1);
+    }
+}
+
+/// What's at the front of the receive ring, once the DMA has released it.
+enum Front {
+    /// A complete frame, with good checksums, and its timestamp if it has
+    /// one
+    Frame,
+    /// A frame whose timestamp the DMA hasn't finished writing back
+    #[cfg_attr(not(feature = "ptp"), allow(dead_code))]
+    Waiting,
+    /// Something to throw away, which has been counted if it's a bad frame
+    Discard,
+}
 
 // RDES bits which are only used in VLAN code, gated to avoid compiler warnings
 cfg_if::cfg_if! {
@@ -406,6 +563,11 @@ pub struct RxRing {
     /// received packet. This must be in the range `0..storage.len()` at all
     /// times.
     next: Cell<usize>,
+    /// Bad frames we've seen since the last `take_errors`.
+    errors: Cell<RxErrors>,
+    /// Timestamp of the frame most recently handed out.
+    #[cfg(feature = "ptp")]
+    timestamp: Cell<Option<Timestamp>>,
 }
 
 impl RxRing {
@@ -439,6 +601,121 @@ impl RxRing {
             storage,
             buffers,
             next: Cell::new(0),
+            errors: Cell::new(RxErrors::default()),
+            #[cfg(feature = "ptp")]
+            timestamp: Cell::new(None),
+        }
+    }
+
+    /// Returns the counts of bad frames seen so far.
+    pub fn errors(&self) -> RxErrors {
+        self.errors.get()
+    }
+
+    /// Sets the counts of bad frames back to zero.
+    pub fn reset_errors(&self) {
+        self.errors.set(RxErrors::default());
+    }
+
+    fn count_errors(&self, f: impl FnOnce(&mut RxErrors)) {
+        let mut errors = self.errors.get();
+        f(&mut errors);
+        self.errors.set(errors);
+    }
+
+    /// Returns the timestamp of the frame being handed out, when called from
+    /// within the `body` closure passed to `with_next` or `vlan_with_next`.
+    #[cfg(feature = "ptp")]
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp.get()
+    }
+
+    /// Works out what to do with descriptor `d`, at the front of the ring,
+    /// which the DMA has written back as `rdes3`.
+    fn check_front(&self, d: &RxDesc, rdes3: u32) -> Front {
+        #[cfg(feature = "ptp")]
+        if rdes3 & (1 << RDES3_CTXT_BIT) != 0 {
+            // The timestamp of a frame we've already dropped.
+            return Front::Discard;
+        }
+
+        let errors = rdes3 & (1 << RDES3_ES_BIT) != 0;
+        let first_and_last = rdes3
+            & ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT))
+            == ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT));
+        if errors || !first_and_last {
+            self.count_errors(|e| e.drop(rdes3));
+            return Front::Discard;
+        }
+
+        if rdes3 & (1 << RDES3_RS1V_BIT) != 0 {
+            let rdes1 = d.rdes[1].load(Ordering::Relaxed);
+            // The IP stack trusts the MAC to have checked these, so we
+            // mustn't let through any that failed.
+            if rdes1 & ((1 << RDES1_IPHE_BIT) | (1 << RDES1_IPCE_BIT)) != 0 {
+                self.count_errors(|e| e.checksum = e.checksum.wrapping_add(1));
+                return Front::Discard;
+            }
+            #[cfg(feature = "ptp")]
+            if rdes1 & (1 << RDES1_TSA_BIT) != 0
+                && self.context_after().is_err()
+            {
+                return Front::Waiting;
+            }
+        }
+        Front::Frame
+    }
+
+    /// Looks at the descriptor after the front of the ring for the front
+    /// frame's timestamp. Returns `Err(())` if the DMA still owns it, and
+    /// `Ok(None)` if it isn't a timestamp.
+    #[cfg(feature = "ptp")]
+    fn context_after(&self) -> Result<Option<Timestamp>, ()> {
+        let d = &self.storage[(self.next.get() + 1) % self.storage.len()];
+        let rdes3 = d.rdes[3].load(Ordering::Acquire);
+        if rdes3 & (1 << RDES3_OWN_BIT) != 0 {
+            Err(())
+        } else if rdes3 & (1 << RDES3_CTXT_BIT) != 0 {
+            Ok(Some(Timestamp::from_desc(&d.rdes)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads the timestamp of the front frame, described by `rdes3`, if it
+    /// has one, and keeps it for `timestamp` to return.
+    #[cfg(feature = "ptp")]
+    fn take_timestamp(&self, d: &RxDesc, rdes3: u32) -> Option<Timestamp> {
+        let has_timestamp = rdes3 & (1 << RDES3_RS1V_BIT) != 0
+            && d.rdes[1].load(Ordering::Relaxed) & (1 << RDES1_TSA_BIT) != 0;
+        let timestamp = if has_timestamp {
+            self.context_after().ok().flatten()
+        } else {
+            None
+        };
+        self.timestamp.set(timestamp);
+        timestamp
+    }
+
+    /// Hands the descriptor at the front of the ring back to the DMA, and
+    /// moves on to the next one.
+    fn recycle_front(&self) {
+        let d = &self.storage[self.next.get()];
+        Self::set_descriptor(d, self.buffers[self.next.get()].0.get());
+        self.next.set(if self.next.get() + 1 == self.storage.len() {
+            0
+        } else {
+            self.next.get() + 1
+        });
+    }
+
+    /// Finishes with the frame at the front of the ring, once `body` has seen
+    /// it, along with the context descriptor holding its timestamp.
+    fn finish_front(&self) {
+        self.recycle_front();
+        #[cfg(feature = "ptp")]
+        if self.timestamp.take().is_some() {
+            self.recycle_front();
         }
     }
 
@@ -494,24 +771,17 @@ impl RxRing {
                 return (false, any_dropped);
             }
 
-            // What sort of descriptor is this?
-            let errors = rdes3 & (1 << RDES3_ES_BIT) != 0;
-            let first_and_last = rdes3
-                & ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT))
-                == ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT));
-
             // If this descriptor is error-free and represents a complete
             // packet, then return true so that the netstack loads it
-            if !errors && first_and_last {
-                return (true, any_dropped);
+            match self.check_front(d, rdes3) {
+                Front::Frame => return (true, any_dropped),
+                Front::Waiting => return (false, any_dropped),
+                Front::Discard => (),
             }
 
-            // Otherwise, drop the packet by bumping our index
-            self.next.set(if self.next.get() + 1 == self.storage.len() {
-                0
-            } else {
-                self.next.get() + 1
-            });
+            // Otherwise, drop the packet, handing its descriptor back to the
+            // DMA
+            self.recycle_front();
             any_dropped = true;
         }
     }
@@ -571,22 +841,18 @@ impl RxRing {
 
         // Work out the valid slice of the packet.
         let packet_len = (rdes3 & RDES3_PL_MASK) as usize;
+        #[cfg(feature = "ptp")]
+        self.take_timestamp(d, rdes3);
 
         // Pass in the initialized prefix of the packet.
         let result = (body)(&mut buffer[..packet_len]);
 
         // We need to consume this descriptor whether or not we handed
-        // it off. Rewrite it as an empty rx descriptor:
-        Self::set_descriptor(d, buffer);
-        // At this point the descriptor is no longer free, the buffer is
-        // potentially in use, and we must not access either.
-
-        // Bump index forward.
-        self.next.set(if self.next.get() + 1 == self.storage.len() {
-            0
-        } else {
-            self.next.get() + 1
-        });
+        // it off. Rewrite it as an empty rx descriptor, and bump the index
+        // forward (past the timestamp too, if there was one). At this point
+        // the descriptor is no longer free, the buffer is potentially in
+        // use, and we must not access either.
+        self.finish_front();
 
         result
     }
@@ -617,16 +883,21 @@ impl RxRing {
             }
 
             // Check to see if this is an error descriptor.  If so (or if it's
-            // not a complete packet, which shouldn't happen), then drop it.
-            let errors = rdes3 & (1 << RDES3_ES_BIT) != 0;
-            let first_and_last = rdes3
-                & ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT))
-                == ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT));
-            let packet_okay = !errors && first_and_last;
+            // not a complete packet, which shouldn't happen, or a bad
+            // checksum), then drop it.
+            let front = self.check_front(d, rdes3);
 
             // If RDES0 is valid, then check for a VLAN match
             let rdes0_valid = rdes3 & (1 << RDES3_RS0V_BIT) != 0;
-            if packet_okay && rdes0_valid {
+            if let Front::Waiting = front {
+                return (false, any_dropped);
+            } else if let Front::Discard = front {
+                // Already counted, if it needs to be
+            } else if !rdes0_valid {
+                self.count_errors(|e| {
+                    e.unknown_vlan = e.unknown_vlan.wrapping_add(1)
+                });
+            } else {
                 let rdes0 = d.rdes[0].load(Ordering::Relaxed);
                 let this_vid = ((rdes0 >> RDES0_OUTER_VID_BIT) & 0xFFF) as u16;
 
@@ -638,23 +909,19 @@ impl RxRing {
                     // and trust that another instance will handle it.
                     return (false, any_dropped);
                 }
+                self.count_errors(|e| {
+                    e.unknown_vlan = e.unknown_vlan.wrapping_add(1)
+                });
             }
 
             // If we've gotten to this point in the code, the packet is
             //  (a) owned by userspace and
-            //  (b) either has no VID or has an invalid VID
+            //  (b) either has an error, or has no VID or an invalid VID
             // so we're going to drop it to avoid clogging the queue.
 
-            // Rewrite to an empty rx descriptor (owned by DMA)
-            let buffer = self.buffers[self.next.get()].0.get();
-            Self::set_descriptor(d, buffer);
-
-            // Bump index forward.
-            self.next.set(if self.next.get() + 1 == self.storage.len() {
-                0
-            } else {
-                self.next.get() + 1
-            });
+            // Rewrite to an empty rx descriptor (owned by DMA), and bump the
+            // index forward
+            self.recycle_front();
             any_dropped = true;
         }
     }
@@ -709,22 +976,18 @@ impl RxRing {
 
         // Work out the valid slice of the packet.
         let packet_len = (rdes3 & RDES3_PL_MASK) as usize;
+        #[cfg(feature = "ptp")]
+        self.take_timestamp(d, rdes3);
 
         // Pass in the initialized prefix of the packet.
         let retval = (body)(&mut buffer[..packet_len]);
 
         // We need to consume this descriptor whether or not we handed
-        // it off. Rewrite it as an empty rx descriptor:
-        Self::set_descriptor(d, buffer);
-        // At this point the descriptor is no longer free, the buffer is
-        // potentially in use, and we must not access either.
-
-        // Bump index forward.
-        self.next.set(if self.next.get() + 1 == self.storage.len() {
-            0
-        } else {
-            self.next.get() + 1
-        });
+        // it off. Rewrite it as an empty rx descriptor, and bump the index
+        // forward (past the timestamp too, if there was one). At this point
+        // the descriptor is no longer free, the buffer is potentially in
+        // use, and we must not access either.
+        self.finish_front();
 
         retval
     }
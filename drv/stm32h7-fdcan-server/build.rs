@@ -0,0 +1,94 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{anyhow, bail, Result};
+use build_fdcan::FdcanGlobalConfig;
+use indexmap::IndexMap;
+use std::io::Write;
+
+fn main() -> Result<()> {
+    build_util::build_notifications()?;
+    build_util::idol::build_server_support(
+        "../../idl/can.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+
+    let full_task_config = build_util::task_full_config_toml()?;
+    let can = check_uses_and_interrupts(
+        &full_task_config.uses,
+        &full_task_config.interrupts,
+    )?;
+
+    // Confirm that we've enabled the matching feature, and no other.
+    let feat = format!("CARGO_FEATURE_{}", can.to_uppercase());
+    if std::env::var(&feat).is_err() {
+        bail!("when using {can} peripheral, '{can}' feature must be enabled");
+    }
+    if let Some(f) = std::env::vars()
+        .map(|(k, _v)| k)
+        .filter(|k| k.starts_with("CARGO_FEATURE_FDCAN"))
+        .find(|f| f != &feat)
+    {
+        bail!(
+            "cannot have feature '{}' defined when using peripheral {can}",
+            f.trim_start_matches("CARGO_FEATURE_").to_lowercase()
+        );
+    }
+
+    let global_config = build_util::config::<FdcanGlobalConfig>()?;
+    let config = global_config
+        .fdcan
+        .get(&can)
+        .ok_or_else(|| anyhow!("reference to undefined fdcan config {can}"))?;
+    if format!("fdcan{}", config.controller) != can {
+        bail!(
+            "config for {can} names controller {}, which is a different \
+             peripheral",
+            config.controller
+        );
+    }
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("fdcan_config.rs");
+    let mut out = std::fs::File::create(&dest_path)?;
+    writeln!(out, "{}", build_fdcan::generate(config)?)?;
+    drop(out);
+    call_rustfmt::rustfmt(&dest_path)?;
+
+    Ok(())
+}
+
+fn check_uses_and_interrupts(
+    uses: &[String],
+    interrupts: &IndexMap<String, String>,
+) -> Result<String> {
+    let mut can = None;
+
+    let re = regex::Regex::new(r"^fdcan\d$").unwrap();
+    for p in uses {
+        if re.is_match(p) {
+            if let Some(q) = can {
+                bail!("multiple FDCAN peripherals in use: {p} and {q}");
+            }
+            can = Some(p);
+        }
+    }
+    let can = match can {
+        Some(c) => c,
+        None => bail!("No FDCAN peripheral in {uses:?}"),
+    };
+
+    // The controllers share one message RAM, of which each gets a slice.
+    let ram = format!("{can}_ram");
+    if !uses.contains(&ram) {
+        bail!("uses should contain '{ram}' along with '{can}'");
+    }
+    let irq = format!("{can}.it0");
+    if !interrupts.contains_key(&irq) {
+        bail!("interrupts should contain '{irq}'");
+    }
+
+    Ok(can.to_owned())
+}
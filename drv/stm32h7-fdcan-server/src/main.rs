@@ -0,0 +1,337 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Server for an STM32H7 FDCAN controller.
+//!
+//! The controller, its pins, and its bit rates come from the app's
+//! `[config.fdcan.fdcanN]` table by way of `build.rs`. Frames are queued
+//! straight into the controller's transmit FIFO and taken straight from its
+//! receive FIFO, so there's no buffering here beyond what the message RAM
+//! provides; a client that `subscribe`s is told when to come back.
+//!
+//! Out of reset every frame is accepted. Clients narrow that by installing
+//! filters and then turning off `set_accept_unmatched`.
+
+#![no_std]
+#![no_main]
+
+use drv_can_api::{
+    BusState, CanCounters, CanError, CanFilter, FrameInfo, MAX_EXTENDED_ID,
+    MAX_STANDARD_ID,
+};
+use drv_stm32h7_fdcan::{irq, BitTiming, Fdcan, Header};
+use drv_stm32xx_sys_api::{self as sys_api, Sys};
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+struct ServerConfig {
+    controller: usize,
+    nominal: BitTiming,
+    data: Option<BitTiming>,
+    pins: &'static [(sys_api::PinSet, sys_api::Alternate)],
+    auto_recover: bool,
+}
+
+include!(concat!(env!("OUT_DIR"), "/fdcan_config.rs"));
+
+/// Interrupts that we handle; all of them wake a subscriber.
+const IRQS: u32 = irq::RX_NEW
+    | irq::RX_LOST
+    | irq::TX_EMPTY
+    | irq::ERROR_PASSIVE
+    | irq::WARNING
+    | irq::BUS_OFF
+    | irq::PROTOCOL_ARB
+    | irq::PROTOCOL_DATA;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    BusOff,
+    Recovering,
+    RxLost,
+    ProtocolError(u8),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct ServerImpl {
+    can: Fdcan,
+    subscriber: Option<(TaskId, u32)>,
+    counters: CanCounters,
+}
+
+impl ServerImpl {
+    fn notify(&self) {
+        if let Some((task, bits)) = self.subscriber {
+            sys_post(sys_refresh_task_id(task), bits);
+        }
+    }
+}
+
+impl idl::InOrderCanImpl for ServerImpl {
+    fn send(
+        &mut self,
+        _: &RecvMessage,
+        id: u32,
+        flags: u8,
+        data: LenLimit<Leased<R, [u8]>, 64>,
+    ) -> Result<(), RequestError<CanError>> {
+        let extended = flags & FrameInfo::EXTENDED != 0;
+        let fd = flags & FrameInfo::FD != 0;
+        let remote = flags & FrameInfo::REMOTE != 0;
+        let max_id = if extended {
+            MAX_EXTENDED_ID
+        } else {
+            MAX_STANDARD_ID
+        };
+        if id > max_id
+            || (fd && (remote || !self.can.fd_enabled()))
+            || (!fd && data.len() > 8)
+        {
+            return Err(CanError::BadFrame.into());
+        }
+        if self.can.status().bus_off {
+            return Err(CanError::BusOff.into());
+        }
+
+        let mut buf = [0; 64];
+        let len = data.len();
+        data.read_range(0..len, &mut buf[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        let header = Header {
+            id,
+            extended,
+            remote,
+            fd,
+            bit_rate_switch: fd && flags & FrameInfo::BIT_RATE_SWITCH != 0,
+            len,
+            timestamp: 0,
+        };
+        self.can
+            .try_send(&header, &buf[..len])
+            .map_err(|_| CanError::TxFull.into())
+    }
+
+    fn recv(
+        &mut self,
+        _: &RecvMessage,
+        data: LenLimit<Leased<W, [u8]>, 64>,
+    ) -> Result<FrameInfo, RequestError<CanError>> {
+        let mut buf = [0; 64];
+        let h = self
+            .can
+            .try_recv(&mut buf[..data.len()])
+            .ok_or(CanError::RxEmpty)?;
+
+        let n = usize::min(data.len(), h.len);
+        data.write_range(0..n, &buf[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        let mut flags = 0;
+        if h.extended {
+            flags |= FrameInfo::EXTENDED;
+        }
+        if h.fd {
+            flags |= FrameInfo::FD;
+        }
+        if h.bit_rate_switch {
+            flags |= FrameInfo::BIT_RATE_SWITCH;
+        }
+        if h.remote {
+            flags |= FrameInfo::REMOTE;
+        }
+        Ok(FrameInfo {
+            id: h.id,
+            len: h.len as u8,
+            flags,
+            timestamp: h.timestamp,
+        })
+    }
+
+    fn subscribe(
+        &mut self,
+        msg: &RecvMessage,
+        notify: u32,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.subscriber = Some((msg.sender, notify));
+        Ok(())
+    }
+
+    fn set_filter(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+        filter: CanFilter,
+    ) -> Result<(), RequestError<CanError>> {
+        let index = usize::from(index);
+        if filter.extended != 0 {
+            if index >= drv_stm32h7_fdcan::EXT_FILTERS
+                || filter.id > MAX_EXTENDED_ID
+                || filter.mask > MAX_EXTENDED_ID
+            {
+                return Err(CanError::BadFilter.into());
+            }
+            self.can
+                .set_ext_filter(index, Some((filter.id, filter.mask)));
+        } else {
+            if index >= drv_stm32h7_fdcan::STD_FILTERS
+                || filter.id > MAX_STANDARD_ID
+                || filter.mask > MAX_STANDARD_ID
+            {
+                return Err(CanError::BadFilter.into());
+            }
+            self.can
+                .set_std_filter(index, Some((filter.id, filter.mask)));
+        }
+        Ok(())
+    }
+
+    fn clear_filter(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+        extended: bool,
+    ) -> Result<(), RequestError<CanError>> {
+        let index = usize::from(index);
+        if extended {
+            if index >= drv_stm32h7_fdcan::EXT_FILTERS {
+                return Err(CanError::BadFilter.into());
+            }
+            self.can.set_ext_filter(index, None);
+        } else {
+            if index >= drv_stm32h7_fdcan::STD_FILTERS {
+                return Err(CanError::BadFilter.into());
+            }
+            self.can.set_std_filter(index, None);
+        }
+        Ok(())
+    }
+
+    fn set_accept_unmatched(
+        &mut self,
+        _: &RecvMessage,
+        accept: bool,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.can.set_accept_unmatched(accept);
+        Ok(())
+    }
+
+    fn counters(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<CanCounters, RequestError<core::convert::Infallible>> {
+        let status = self.can.status();
+        let state = if status.bus_off {
+            BusState::BusOff
+        } else if status.error_passive {
+            BusState::ErrorPassive
+        } else if status.error_warning {
+            BusState::ErrorWarning
+        } else {
+            BusState::ErrorActive
+        };
+        Ok(CanCounters {
+            tec: status.tec,
+            rec: status.rec,
+            state: state as u8,
+            last_error: status.last_error,
+            ..self.counters
+        })
+    }
+
+    fn recover(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        if self.can.status().bus_off {
+            ringbuf_entry!(Trace::Recovering);
+            self.can.start_recovery();
+        }
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::CAN_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        let pending = self.can.take_interrupts();
+
+        if pending & irq::BUS_OFF != 0 && self.can.status().bus_off {
+            ringbuf_entry!(Trace::BusOff);
+            self.counters.bus_off_events =
+                self.counters.bus_off_events.wrapping_add(1);
+            if CONFIG.auto_recover {
+                ringbuf_entry!(Trace::Recovering);
+                self.can.start_recovery();
+            }
+        }
+        if pending & irq::RX_LOST != 0 {
+            ringbuf_entry!(Trace::RxLost);
+            self.counters.rx_overruns =
+                self.counters.rx_overruns.wrapping_add(1);
+        }
+        if pending & (irq::PROTOCOL_ARB | irq::PROTOCOL_DATA) != 0 {
+            ringbuf_entry!(Trace::ProtocolError(self.can.status().last_error));
+            self.counters.protocol_errors =
+                self.counters.protocol_errors.wrapping_add(1);
+        }
+
+        if pending & IRQS != 0 {
+            self.notify();
+        }
+        sys_irq_control(notifications::CAN_IRQ_MASK, true);
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+
+    // Both controllers share one clock enable and reset line.
+    sys.enable_clock(sys_api::Peripheral::Fdcan);
+    sys.leave_reset(sys_api::Peripheral::Fdcan);
+    for &(pins, alternate) in CONFIG.pins {
+        sys.gpio_configure_alternate(
+            pins,
+            sys_api::OutputType::PushPull,
+            sys_api::Speed::Medium,
+            sys_api::Pull::None,
+            alternate,
+        );
+    }
+
+    let can = Fdcan::new(CONFIG.controller);
+    can.start(&CONFIG.nominal, CONFIG.data.as_ref());
+    can.enable_interrupts(IRQS);
+    sys_irq_control(notifications::CAN_IRQ_MASK, true);
+
+    let mut server = ServerImpl {
+        can,
+        subscriber: None,
+        counters: CanCounters::default(),
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_can_api::{CanCounters, CanError, CanFilter, FrameInfo};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
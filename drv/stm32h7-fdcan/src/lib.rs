@@ -0,0 +1,457 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register-level driver for the STM32H7 FDCAN controllers.
+//!
+//! The two controllers share 10 KiB of message RAM, in which software lays
+//! out filter lists, receive FIFOs and transmit buffers for each. We give
+//! each controller its own 1 KiB slice (the most the MPU can map for
+//! controller 1, which starts on a 1 KiB boundary), laid out as:
+//!
+//! | words   | contents                                        |
+//! |---------|-------------------------------------------------|
+//! | 0-15    | 16 standard ID filters                          |
+//! | 16-31   | 8 extended ID filters, 2 words each             |
+//! | 32-175  | receive FIFO 0: 8 elements of 18 words          |
+//! | 176-247 | transmit FIFO: 4 elements of 18 words           |
+//!
+//! Every element has room for a 64-byte FD payload, whether or not FD is
+//! enabled. Accepted frames all go to receive FIFO 0.
+//!
+//! We go through raw offsets from RM0433 rather than the PAC, since the
+//! message RAM has to be handled that way regardless.
+
+#![no_std]
+
+use core::ptr::{read_volatile, write_volatile};
+
+const REG_BASE: [usize; 2] = [0x4000_a000, 0x4000_a400];
+const RAM_BASE: [usize; 2] = [0x4000_ac00, 0x4000_b000];
+
+// Register offsets.
+const DBTP: usize = 0x00c;
+const CCCR: usize = 0x018;
+const NBTP: usize = 0x01c;
+const TSCC: usize = 0x020;
+const ECR: usize = 0x040;
+const PSR: usize = 0x044;
+const TDCR: usize = 0x048;
+const IR: usize = 0x050;
+const IE: usize = 0x054;
+const ILS: usize = 0x058;
+const ILE: usize = 0x05c;
+const GFC: usize = 0x080;
+const SIDFC: usize = 0x084;
+const XIDFC: usize = 0x088;
+const XIDAM: usize = 0x090;
+const RXF0C: usize = 0x0a0;
+const RXF0S: usize = 0x0a4;
+const RXF0A: usize = 0x0a8;
+const RXESC: usize = 0x0bc;
+const TXBC: usize = 0x0c0;
+const TXFQS: usize = 0x0c4;
+const TXESC: usize = 0x0c8;
+const TXBAR: usize = 0x0d0;
+
+// CCCR bits.
+const CCCR_INIT: u32 = 1 << 0;
+const CCCR_CCE: u32 = 1 << 1;
+const CCCR_FDOE: u32 = 1 << 8;
+const CCCR_BRSE: u32 = 1 << 9;
+
+// PSR bits.
+const PSR_EP: u32 = 1 << 5;
+const PSR_EW: u32 = 1 << 6;
+const PSR_BO: u32 = 1 << 7;
+
+/// Interrupt flags, as found in `IR` and accepted by `enable_interrupts`.
+pub mod irq {
+    /// New message in receive FIFO 0.
+    pub const RX_NEW: u32 = 1 << 0;
+    /// A message was dropped because receive FIFO 0 was full.
+    pub const RX_LOST: u32 = 1 << 3;
+    /// The transmit FIFO has become empty.
+    pub const TX_EMPTY: u32 = 1 << 11;
+    /// Error passive status changed.
+    pub const ERROR_PASSIVE: u32 = 1 << 23;
+    /// Error warning status changed.
+    pub const WARNING: u32 = 1 << 24;
+    /// Bus-off status changed.
+    pub const BUS_OFF: u32 = 1 << 25;
+    /// Protocol error in the arbitration phase.
+    pub const PROTOCOL_ARB: u32 = 1 << 27;
+    /// Protocol error in the data phase.
+    pub const PROTOCOL_DATA: u32 = 1 << 28;
+}
+
+// Message RAM layout, in words from the start of our slice.
+pub const STD_FILTERS: usize = 16;
+pub const EXT_FILTERS: usize = 8;
+const RX_FIFO_LEN: usize = 8;
+const TX_FIFO_LEN: usize = 4;
+const ELEMENT_WORDS: usize = 18;
+const STD_FILTER_OFFSET: usize = 0;
+const EXT_FILTER_OFFSET: usize = STD_FILTER_OFFSET + STD_FILTERS;
+const RX_FIFO_OFFSET: usize = EXT_FILTER_OFFSET + 2 * EXT_FILTERS;
+const TX_FIFO_OFFSET: usize = RX_FIFO_OFFSET + RX_FIFO_LEN * ELEMENT_WORDS;
+const RAM_WORDS: usize = TX_FIFO_OFFSET + TX_FIFO_LEN * ELEMENT_WORDS;
+const _: () = assert!(RAM_WORDS <= 256);
+
+/// Element size code for 64-byte payloads, in RXESC and TXESC.
+const ELEMENT_64_BYTES: u32 = 0b111;
+
+/// Bit timing in time quanta, in natural (not minus-one) form.
+#[derive(Copy, Clone, Debug)]
+pub struct BitTiming {
+    pub prescaler: u16,
+    pub seg1: u16,
+    pub seg2: u8,
+    pub sjw: u8,
+}
+
+/// Header of a frame to send or that was received.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Header {
+    pub id: u32,
+    pub extended: bool,
+    pub remote: bool,
+    pub fd: bool,
+    pub bit_rate_switch: bool,
+    pub len: usize,
+    /// Reception time; ignored on transmit.
+    pub timestamp: u16,
+}
+
+/// Error counters and state, from ECR and PSR.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Status {
+    pub tec: u8,
+    pub rec: u8,
+    pub error_warning: bool,
+    pub error_passive: bool,
+    pub bus_off: bool,
+    pub last_error: u8,
+}
+
+/// The transmit FIFO is full.
+#[derive(Copy, Clone, Debug)]
+pub struct TxFull;
+
+/// Returns the data length code for an FD payload of `len` bytes, rounding
+/// up to the next length a frame can carry.
+pub fn len_to_dlc(len: usize) -> u32 {
+    match len {
+        0..=8 => len as u32,
+        9..=12 => 9,
+        13..=16 => 10,
+        17..=20 => 11,
+        21..=24 => 12,
+        25..=32 => 13,
+        33..=48 => 14,
+        _ => 15,
+    }
+}
+
+/// Returns the payload length for a data length code. Classic frames with a
+/// code over 8 still carry only 8 bytes.
+pub fn dlc_to_len(dlc: u32, fd: bool) -> usize {
+    match (dlc, fd) {
+        (0..=8, _) => dlc as usize,
+        (_, false) => 8,
+        (9, true) => 12,
+        (10, true) => 16,
+        (11, true) => 20,
+        (12, true) => 24,
+        (13, true) => 32,
+        (14, true) => 48,
+        _ => 64,
+    }
+}
+
+pub struct Fdcan {
+    regs: usize,
+    ram: usize,
+}
+
+impl Fdcan {
+    /// Returns a handle for controller 1 or 2. The caller is responsible for
+    /// turning on its clock and taking it out of reset, and for having the
+    /// registers and message RAM slice mapped.
+    pub fn new(controller: usize) -> Self {
+        Self {
+            regs: REG_BASE[controller - 1],
+            ram: RAM_BASE[controller - 1],
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        // Safety: `offset` is one of the register offsets above, within the
+        // controller's block.
+        unsafe { read_volatile((self.regs + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        // Safety: as for `read`.
+        unsafe { write_volatile((self.regs + offset) as *mut u32, value) }
+    }
+
+    fn modify(&self, offset: usize, f: impl FnOnce(u32) -> u32) {
+        self.write(offset, f(self.read(offset)));
+    }
+
+    fn ram_read(&self, word: usize) -> u32 {
+        assert!(word < RAM_WORDS);
+        // Safety: in bounds of our slice, as checked above. The message RAM
+        // only supports word accesses, which these are.
+        unsafe { read_volatile((self.ram as *const u32).add(word)) }
+    }
+
+    fn ram_write(&self, word: usize, value: u32) {
+        assert!(word < RAM_WORDS);
+        // Safety: as for `ram_read`.
+        unsafe { write_volatile((self.ram as *mut u32).add(word), value) }
+    }
+
+    /// Address of our slice relative to the start of message RAM, in the form
+    /// the start address fields want (a byte offset with the low two bits
+    /// clear).
+    fn ram_offset(&self, word: usize) -> u32 {
+        (self.ram - RAM_BASE[0] + word * 4) as u32
+    }
+
+    /// Stops the controller and enables writes to its configuration.
+    fn enter_init(&self) {
+        self.modify(CCCR, |v| v | CCCR_INIT);
+        while self.read(CCCR) & CCCR_INIT == 0 {}
+        self.modify(CCCR, |v| v | CCCR_CCE);
+    }
+
+    /// Rejoins the bus once 11 consecutive recessive bits have gone by.
+    fn leave_init(&self) {
+        self.modify(CCCR, |v| v & !(CCCR_INIT | CCCR_CCE));
+        while self.read(CCCR) & CCCR_INIT != 0 {}
+    }
+
+    /// Sets the controller up from scratch and starts it. With no filters
+    /// installed, every frame is accepted; remote frames are rejected.
+    /// `data` enables FD operation with bit rate switching.
+    pub fn start(&self, nominal: &BitTiming, data: Option<&BitTiming>) {
+        self.enter_init();
+
+        self.write(
+            NBTP,
+            (u32::from(nominal.sjw) - 1) << 25
+                | (u32::from(nominal.prescaler) - 1) << 16
+                | (u32::from(nominal.seg1) - 1) << 8
+                | (u32::from(nominal.seg2) - 1),
+        );
+        self.modify(CCCR, |v| v & !(CCCR_FDOE | CCCR_BRSE));
+        if let Some(d) = data {
+            // Transmitter delay compensation lets us sample our own data
+            // phase bits at high rates; place its secondary sample point at
+            // the data phase sample point.
+            let offset = u32::from(d.prescaler) * (1 + u32::from(d.seg1));
+            self.write(TDCR, offset.min(0x7f) << 8);
+            self.write(
+                DBTP,
+                1 << 23
+                    | (u32::from(d.prescaler) - 1) << 16
+                    | (u32::from(d.seg1) - 1) << 8
+                    | (u32::from(d.seg2) - 1) << 4
+                    | (u32::from(d.sjw) - 1),
+            );
+            self.modify(CCCR, |v| v | CCCR_FDOE | CCCR_BRSE);
+        }
+
+        // Timestamps count bit times.
+        self.write(TSCC, 1);
+
+        // Accept unmatched frames into FIFO 0, reject remote frames.
+        self.write(GFC, 0b11);
+        self.write(
+            SIDFC,
+            (STD_FILTERS as u32) << 16 | self.ram_offset(STD_FILTER_OFFSET),
+        );
+        self.write(
+            XIDFC,
+            (EXT_FILTERS as u32) << 16 | self.ram_offset(EXT_FILTER_OFFSET),
+        );
+        self.write(XIDAM, 0x1fff_ffff);
+        for i in 0..STD_FILTERS {
+            self.set_std_filter(i, None);
+        }
+        for i in 0..EXT_FILTERS {
+            self.set_ext_filter(i, None);
+        }
+
+        // Receive FIFO 0, blocking (not overwriting) when full.
+        self.write(
+            RXF0C,
+            (RX_FIFO_LEN as u32) << 16 | self.ram_offset(RX_FIFO_OFFSET),
+        );
+        self.write(RXESC, ELEMENT_64_BYTES);
+
+        // Transmit FIFO (not queue) with no dedicated buffers.
+        self.write(
+            TXBC,
+            (TX_FIFO_LEN as u32) << 24 | self.ram_offset(TX_FIFO_OFFSET),
+        );
+        self.write(TXESC, ELEMENT_64_BYTES);
+
+        // Everything goes to interrupt line 0.
+        self.write(ILS, 0);
+        self.write(ILE, 1);
+
+        self.leave_init();
+    }
+
+    /// Chooses which interrupt flags (from `irq`) raise an interrupt.
+    pub fn enable_interrupts(&self, mask: u32) {
+        self.write(IE, mask);
+    }
+
+    /// Reads and clears the interrupt flags.
+    pub fn take_interrupts(&self) -> u32 {
+        let ir = self.read(IR);
+        self.write(IR, ir);
+        ir
+    }
+
+    /// Installs (or, with `None`, disables) a standard ID filter matching
+    /// `(id, mask)` in slot `index`. The ID and mask must fit in 11 bits.
+    pub fn set_std_filter(&self, index: usize, filter: Option<(u32, u32)>) {
+        let element = match filter {
+            // SFT = classic (filter and mask), SFEC = store in FIFO 0.
+            Some((id, mask)) => 0b10 << 30 | 0b001 << 27 | id << 16 | mask,
+            None => 0,
+        };
+        self.ram_write(STD_FILTER_OFFSET + index, element);
+    }
+
+    /// As `set_std_filter`, for extended IDs of 29 bits.
+    pub fn set_ext_filter(&self, index: usize, filter: Option<(u32, u32)>) {
+        let word = EXT_FILTER_OFFSET + 2 * index;
+        match filter {
+            Some((id, mask)) => {
+                // EFT = classic; write the enabling word last.
+                self.ram_write(word + 1, 0b10 << 30 | mask);
+                self.ram_write(word, 0b001 << 29 | id);
+            }
+            None => {
+                self.ram_write(word, 0);
+                self.ram_write(word + 1, 0);
+            }
+        }
+    }
+
+    /// Chooses whether frames that match no filter are accepted into FIFO 0
+    /// or rejected. This has to stop the controller briefly.
+    pub fn set_accept_unmatched(&self, accept: bool) {
+        self.enter_init();
+        // ANFS and ANFE: 0b00 accepts into FIFO 0, 0b10 rejects.
+        let anf = if accept { 0b00 } else { 0b10 };
+        self.modify(GFC, |v| (v & !0b11_11_00) | anf << 4 | anf << 2);
+        self.leave_init();
+    }
+
+    /// Queues a frame; `data` is padded with zeros up to the length the
+    /// frame will carry.
+    pub fn try_send(&self, h: &Header, data: &[u8]) -> Result<(), TxFull> {
+        let fqs = self.read(TXFQS);
+        if fqs & (1 << 21) != 0 {
+            return Err(TxFull);
+        }
+        let index = ((fqs >> 16) & 0x1f) as usize;
+        let base = TX_FIFO_OFFSET + index * ELEMENT_WORDS;
+
+        let id = if h.extended { h.id } else { h.id << 18 };
+        self.ram_write(
+            base,
+            u32::from(h.extended) << 30 | u32::from(h.remote) << 29 | id,
+        );
+        let dlc = len_to_dlc(h.len);
+        self.ram_write(
+            base + 1,
+            u32::from(h.fd) << 21
+                | u32::from(h.bit_rate_switch) << 20
+                | dlc << 16,
+        );
+        let padded = dlc_to_len(dlc, h.fd);
+        for w in 0..(padded + 3) / 4 {
+            let mut bytes = [0u8; 4];
+            for (i, b) in bytes.iter_mut().enumerate() {
+                *b = data.get(w * 4 + i).copied().unwrap_or(0);
+            }
+            self.ram_write(base + 2 + w, u32::from_le_bytes(bytes));
+        }
+
+        self.write(TXBAR, 1 << index);
+        Ok(())
+    }
+
+    /// Takes the oldest frame from receive FIFO 0, copying as much of its
+    /// payload as fits into `out`.
+    pub fn try_recv(&self, out: &mut [u8]) -> Option<Header> {
+        let fs = self.read(RXF0S);
+        if fs & 0x7f == 0 {
+            return None;
+        }
+        let index = ((fs >> 8) & 0x3f) as usize;
+        let base = RX_FIFO_OFFSET + index * ELEMENT_WORDS;
+
+        let r0 = self.ram_read(base);
+        let r1 = self.ram_read(base + 1);
+        let extended = r0 & (1 << 30) != 0;
+        let fd = r1 & (1 << 21) != 0;
+        let h = Header {
+            id: if extended {
+                r0 & 0x1fff_ffff
+            } else {
+                (r0 >> 18) & 0x7ff
+            },
+            extended,
+            remote: r0 & (1 << 29) != 0,
+            fd,
+            bit_rate_switch: r1 & (1 << 20) != 0,
+            len: dlc_to_len((r1 >> 16) & 0xf, fd),
+            timestamp: r1 as u16,
+        };
+
+        let n = usize::min(out.len(), h.len);
+        for (w, chunk) in out[..n].chunks_mut(4).enumerate() {
+            let bytes = self.ram_read(base + 2 + w).to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+
+        self.write(RXF0A, index as u32);
+        Some(h)
+    }
+
+    pub fn status(&self) -> Status {
+        let ecr = self.read(ECR);
+        let psr = self.read(PSR);
+        Status {
+            tec: ecr as u8,
+            rec: ((ecr >> 8) & 0x7f) as u8,
+            error_warning: psr & PSR_EW != 0,
+            error_passive: psr & PSR_EP != 0,
+            bus_off: psr & PSR_BO != 0,
+            last_error: (psr & 0b111) as u8,
+        }
+    }
+
+    /// Whether FD frames were enabled by `start`.
+    pub fn fd_enabled(&self) -> bool {
+        self.read(CCCR) & CCCR_FDOE != 0
+    }
+
+    /// After bus-off the controller sets INIT and stays off the bus until
+    /// software clears it, after which it waits out 128 sequences of 11
+    /// recessive bits before rejoining. Does nothing if not bus-off.
+    pub fn start_recovery(&self) {
+        if self.read(PSR) & PSR_BO != 0 {
+            self.modify(CCCR, |v| v & !CCCR_INIT);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Server for the STM32H7's flash option bytes and bank swap.
+//!
+//! Only one task may change option bytes. On boards with the update server,
+//! that's the update server, which swaps banks when an update is finished;
+//! this server is for boards that don't have one, or that need the other
+//! option bytes changed, and must not be used alongside it.
+//!
+//! Like the LPC55's CMPA (see the OTP server), option bytes are staged and
+//! verified before they're committed, and read back afterwards. Built with
+//! the `simulate` feature, we start from a copy of the option bytes in force
+//! and commit to that instead, so a manufacturing image can be rehearsed.
+//! Only the tasks named in the app's `allowed-callers` for `commit` may
+//! commit; with none named, nothing can be.
+//!
+//! Swapping to a bank that holds no image would leave the part unbootable
+//! until someone with a debugger turns up, so we check the other bank for an
+//! image header first. That needs the `bank2` extern region.
+
+#![no_std]
+#![no_main]
+
+use drv_flash_options_api::{
+    BankState, FlashOptionsError, OptionField, OptionsStatus, NUM_FIELDS,
+};
+use drv_stm32h7_flash_options::FlashOptions;
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R};
+use ringbuf::*;
+use userlib::*;
+
+extern "C" {
+    // Symbols injected by the linker.
+    //
+    // This requires adding `extern-regions = ["bank2"]` to the task config
+    static __REGION_BANK2_BASE: [u32; 0];
+}
+
+/// Where the image header sits, at the end of the vector table.
+const HEADER_OFFSET: usize = 0x298;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    SwapOnReset(bool),
+    Staged(OptionField, u32),
+    Mismatch,
+    Set(OptionField, u32),
+    Committed,
+    ReadbackMismatch,
+    Failed(FlashOptionsError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Checks for an image header in the bank we're not running from.
+fn other_bank_has_image() -> bool {
+    // Safety: the linker gives us the region's address, and the region is
+    // in our memory map.
+    let header = unsafe {
+        let base = __REGION_BANK2_BASE.as_ptr() as usize;
+        core::ptr::read_volatile((base + HEADER_OFFSET) as *const ImageHeader)
+    };
+    header.magic == HEADER_MAGIC
+}
+
+/// A value for every field, indexed by `OptionField`.
+type Values = [u32; NUM_FIELDS];
+
+struct ServerImpl {
+    options: FlashOptions,
+    /// What we pretend the fields are set to
+    #[cfg(feature = "simulate")]
+    simulated: Values,
+    staged: Option<Values>,
+    verified: bool,
+    commits: u32,
+}
+
+impl ServerImpl {
+    /// Reads the value of every field that's in force.
+    #[cfg(not(feature = "simulate"))]
+    fn current(&self) -> Values {
+        OptionField::ALL.map(|f| self.options.get(f))
+    }
+
+    #[cfg(feature = "simulate")]
+    fn current(&self) -> Values {
+        self.simulated
+    }
+
+    /// Changes a field, which `stage` has already checked will fit.
+    #[cfg(not(feature = "simulate"))]
+    fn program(
+        &mut self,
+        field: OptionField,
+        value: u32,
+    ) -> Result<(), FlashOptionsError> {
+        self.options
+            .set(field, value)
+            .map_err(FlashOptionsError::from)
+    }
+
+    #[cfg(feature = "simulate")]
+    fn program(
+        &mut self,
+        field: OptionField,
+        value: u32,
+    ) -> Result<(), FlashOptionsError> {
+        self.simulated[field as usize] = value;
+        Ok(())
+    }
+}
+
+impl idl::InOrderFlashOptionsImpl for ServerImpl {
+    fn bank_state(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<BankState, RequestError<core::convert::Infallible>> {
+        Ok(BankState {
+            running_swapped: self.options.running_swapped() as u8,
+            swap_on_reset: self.options.swap_on_reset() as u8,
+        })
+    }
+
+    fn set_swap_on_reset(
+        &mut self,
+        _: &RecvMessage,
+        swapped: bool,
+    ) -> Result<(), RequestError<FlashOptionsError>> {
+        if swapped != self.options.running_swapped() && !other_bank_has_image()
+        {
+            ringbuf_entry!(Trace::Failed(FlashOptionsError::NoImage));
+            return Err(FlashOptionsError::NoImage.into());
+        }
+        ringbuf_entry!(Trace::SwapOnReset(swapped));
+        self.options.set_swap_on_reset(swapped).map_err(|e| {
+            let e = FlashOptionsError::from(e);
+            ringbuf_entry!(Trace::Failed(e));
+            e.into()
+        })
+    }
+
+    fn get(
+        &mut self,
+        _: &RecvMessage,
+        field: OptionField,
+    ) -> Result<u32, RequestError<core::convert::Infallible>> {
+        Ok(self.options.get(field))
+    }
+
+    fn stage(
+        &mut self,
+        _: &RecvMessage,
+        field: OptionField,
+        value: u32,
+    ) -> Result<(), RequestError<FlashOptionsError>> {
+        if value > field.max() {
+            return Err(FlashOptionsError::BadValue.into());
+        }
+        self.verified = false;
+        let current = self.current();
+        let staged = self.staged.get_or_insert(current);
+        staged[field as usize] = value;
+        ringbuf_entry!(Trace::Staged(field, value));
+        Ok(())
+    }
+
+    fn read_staged(
+        &mut self,
+        _: &RecvMessage,
+        field: OptionField,
+    ) -> Result<u32, RequestError<FlashOptionsError>> {
+        let staged =
+            self.staged.as_ref().ok_or(FlashOptionsError::NotStaged)?;
+        Ok(staged[field as usize])
+    }
+
+    fn verify(
+        &mut self,
+        _: &RecvMessage,
+        expected: LenLimit<Leased<R, [u8]>, 32>,
+    ) -> Result<(), RequestError<FlashOptionsError>> {
+        let mut raw = [0u8; NUM_FIELDS * 4];
+        if expected.len() != raw.len() {
+            return Err(FlashOptionsError::Mismatch.into());
+        }
+        let staged = self.staged.ok_or(FlashOptionsError::NotStaged)?;
+        expected
+            .read_range(0..raw.len(), &mut raw)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        let expected: Values = core::array::from_fn(|i| {
+            u32::from_le_bytes(raw[i * 4..][..4].try_into().unwrap_lite())
+        });
+        self.verified = expected == staged;
+        if self.verified {
+            Ok(())
+        } else {
+            ringbuf_entry!(Trace::Mismatch);
+            Err(FlashOptionsError::Mismatch.into())
+        }
+    }
+
+    fn commit(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<FlashOptionsError>> {
+        let staged = self.staged.ok_or(FlashOptionsError::NotStaged)?;
+        if !self.verified {
+            return Err(FlashOptionsError::NotVerified.into());
+        }
+
+        for field in OptionField::ALL {
+            let value = staged[field as usize];
+            ringbuf_entry!(Trace::Set(field, value));
+            self.program(field, value).map_err(|e| {
+                ringbuf_entry!(Trace::Failed(e));
+                e
+            })?;
+        }
+
+        if self.current() != staged {
+            ringbuf_entry!(Trace::ReadbackMismatch);
+            return Err(FlashOptionsError::ReadbackMismatch.into());
+        }
+
+        ringbuf_entry!(Trace::Committed);
+        self.staged = None;
+        self.verified = false;
+        self.commits = self.commits.wrapping_add(1);
+        Ok(())
+    }
+
+    fn abort(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.staged = None;
+        self.verified = false;
+        Ok(())
+    }
+
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<OptionsStatus, RequestError<core::convert::Infallible>> {
+        let current = self.current();
+        let changes = self.staged.map_or(0, |staged| {
+            staged.iter().zip(&current).filter(|(s, c)| s != c).count()
+        });
+        Ok(OptionsStatus {
+            staged: self.staged.is_some() as u8,
+            verified: self.verified as u8,
+            changes: changes as u8,
+            simulated: cfg!(feature = "simulate") as u8,
+            commits: self.commits,
+        })
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    // Safety: the flash controller is in our memory map, and the app gives
+    // nobody else the job of changing option bytes.
+    let options = unsafe { FlashOptions::new() };
+
+    let mut server = ServerImpl {
+        // Start from the real thing, so that what we'd commit is checked
+        // against what's really there.
+        #[cfg(feature = "simulate")]
+        simulated: OptionField::ALL.map(|f| options.get(f)),
+        options,
+        staged: None,
+        verified: false,
+        commits: 0,
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_flash_options_api::{
+        BankState, FlashOptionsError, OptionField, OptionsStatus,
+    };
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
@@ -0,0 +1,242 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The STM32H7's flash option bytes, including the bank swap that SP updates
+//! rely on.
+//!
+//! Option bytes are programmed by staging a value in an `_PRG` register and
+//! starting a change, after which the `_CUR` register shows what's in force;
+//! some settings, like the bank swap, only take effect at the next reset.
+//! Each change here runs the whole sequence, from unlocking to locking
+//! again, and checks the result.
+//!
+//! Only settings that can't brick a part can be changed. Readout protection,
+//! secure areas, write protection and the like are left to a debugger and a
+//! person who means it.
+//!
+//! Whoever uses this needs the flash controller in their memory map, and
+//! should be the only task on the board changing option bytes.
+
+#![no_std]
+
+use num_derive::FromPrimitive;
+use zerocopy::AsBytes;
+
+const FLASH_BASE: usize = 0x5200_2000;
+
+mod reg {
+    pub const OPTKEYR: usize = 0x008;
+    pub const OPTCR: usize = 0x018;
+    pub const OPTSR_CUR: usize = 0x01c;
+    pub const OPTSR_PRG: usize = 0x020;
+    pub const OPTCCR: usize = 0x024;
+    pub const BOOT_CUR: usize = 0x040;
+    pub const BOOT_PRG: usize = 0x044;
+}
+
+// Keys constants are defined in RM0433 Rev 7
+// Section 4.9.3
+const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
+const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
+
+const OPTCR_OPTLOCK: u32 = 1 << 0;
+const OPTCR_OPTSTART: u32 = 1 << 1;
+/// Whether the banks are swapped right now, which is read-only.
+const OPTCR_SWAP_BANK: u32 = 1 << 31;
+
+const OPTSR_OPT_BUSY: u32 = 1 << 0;
+const OPTSR_OPTCHANGEERR: u32 = 1 << 30;
+/// Whether the banks will be swapped after a reset.
+const OPTSR_SWAP_BANK_OPT: u32 = 1 << 31;
+/// Bits of OPTSR_CUR that are status rather than option bytes.
+const OPTSR_STATUS: u32 = OPTSR_OPT_BUSY | OPTSR_OPTCHANGEERR;
+
+const OPTCCR_CLR_OPTCHANGEERR: u32 = 1 << 30;
+
+/// Number of option bytes that can be changed, which is the number of
+/// [`OptionField`]s.
+pub const NUM_FIELDS: usize = 8;
+
+/// The option bytes that can be changed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum OptionField {
+    /// Brownout reset threshold, from 0 (off) to 3 (highest).
+    BorLevel = 0,
+    /// 1 to have the independent watchdog under software control, 0 to have
+    /// it running from reset.
+    Iwdg1Software,
+    /// 1 to enter Stop mode without resetting.
+    NrstStop,
+    /// 1 to enter Standby mode without resetting.
+    NrstStandby,
+    /// 1 to keep the independent watchdog running in Stop mode.
+    IwdgRunInStop,
+    /// 1 to keep the independent watchdog running in Standby mode.
+    IwdgRunInStandby,
+    /// Where to boot from with BOOT0 low, as bits 31:16 of the address.
+    BootAddress0,
+    /// Where to boot from with BOOT0 high, as bits 31:16 of the address.
+    BootAddress1,
+}
+
+impl OptionField {
+    /// Every field, in the order of their discriminants.
+    pub const ALL: [Self; NUM_FIELDS] = [
+        Self::BorLevel,
+        Self::Iwdg1Software,
+        Self::NrstStop,
+        Self::NrstStandby,
+        Self::IwdgRunInStop,
+        Self::IwdgRunInStandby,
+        Self::BootAddress0,
+        Self::BootAddress1,
+    ];
+
+    /// The largest value the field can hold.
+    pub fn max(self) -> u32 {
+        let (_, _, width) = self.location();
+        (1 << width) - 1
+    }
+
+    /// The `_CUR` register holding this field, and its shift and width.
+    fn location(self) -> (usize, u32, u32) {
+        match self {
+            Self::BorLevel => (reg::OPTSR_CUR, 2, 2),
+            Self::Iwdg1Software => (reg::OPTSR_CUR, 4, 1),
+            Self::NrstStop => (reg::OPTSR_CUR, 6, 1),
+            Self::NrstStandby => (reg::OPTSR_CUR, 7, 1),
+            Self::IwdgRunInStop => (reg::OPTSR_CUR, 17, 1),
+            Self::IwdgRunInStandby => (reg::OPTSR_CUR, 18, 1),
+            Self::BootAddress0 => (reg::BOOT_CUR, 0, 16),
+            Self::BootAddress1 => (reg::BOOT_CUR, 16, 16),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// An option byte change is already under way.
+    Busy,
+    /// Too large for the field.
+    BadValue,
+    /// The flash controller refused the change.
+    ChangeFailed,
+    /// The change went through, but didn't take.
+    VerifyFailed,
+}
+
+pub struct FlashOptions {
+    _private: (),
+}
+
+impl FlashOptions {
+    /// # Safety
+    ///
+    /// The flash controller must be in the caller's memory map, and nothing
+    /// else in the system may change option bytes.
+    pub unsafe fn new() -> Self {
+        Self { _private: () }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        // Safety: the flash controller is mapped, per `new`.
+        unsafe { ((FLASH_BASE + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        // Safety: as above.
+        unsafe { ((FLASH_BASE + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Whether we booted with the banks swapped, so that what the memory map
+    /// calls bank 1 is physically bank 2.
+    pub fn running_swapped(&self) -> bool {
+        self.read(reg::OPTCR) & OPTCR_SWAP_BANK != 0
+    }
+
+    /// Whether the banks will be swapped after the next reset.
+    pub fn swap_on_reset(&self) -> bool {
+        self.read(reg::OPTSR_CUR) & OPTSR_SWAP_BANK_OPT != 0
+    }
+
+    /// Chooses whether the banks are swapped after the next reset. To boot
+    /// the other bank, that's `!running_swapped()`.
+    pub fn set_swap_on_reset(&mut self, swapped: bool) -> Result<(), Error> {
+        let bits = if swapped { OPTSR_SWAP_BANK_OPT } else { 0 };
+        self.change(reg::OPTSR_CUR, OPTSR_SWAP_BANK_OPT, bits)
+    }
+
+    /// Reads the value of a field that's in force.
+    pub fn get(&self, field: OptionField) -> u32 {
+        let (cur, shift, width) = field.location();
+        self.read(cur) >> shift & ((1 << width) - 1)
+    }
+
+    /// Changes a field.
+    pub fn set(&mut self, field: OptionField, value: u32) -> Result<(), Error> {
+        let (cur, shift, _) = field.location();
+        let max = field.max();
+        if value > max {
+            return Err(Error::BadValue);
+        }
+        self.change(cur, max << shift, value << shift)
+    }
+
+    /// Changes the bits of `mask` in an option register, given by its `_CUR`
+    /// register, to `bits`. See RM0433 Rev 7 section 4.4.3.
+    fn change(
+        &mut self,
+        cur: usize,
+        mask: u32,
+        bits: u32,
+    ) -> Result<(), Error> {
+        if self.read(reg::OPTSR_CUR) & OPTSR_OPT_BUSY != 0 {
+            return Err(Error::Busy);
+        }
+        if self.read(cur) & mask == bits {
+            // Nothing to do, and no reason to wear the flash doing it.
+            return Ok(());
+        }
+
+        if self.read(reg::OPTCR) & OPTCR_OPTLOCK != 0 {
+            self.write(reg::OPTKEYR, FLASH_OPT_KEY1);
+            self.write(reg::OPTKEYR, FLASH_OPT_KEY2);
+        }
+
+        // Every `_PRG` register is four bytes after its `_CUR` register. We
+        // start from what's in force rather than whatever was last staged,
+        // which may be a change somebody abandoned.
+        let prg = cur + 4;
+        debug_assert!(prg == reg::OPTSR_PRG || prg == reg::BOOT_PRG);
+        let status = if cur == reg::OPTSR_CUR {
+            OPTSR_STATUS
+        } else {
+            0
+        };
+        let base = self.read(cur) & !status;
+        self.write(prg, base & !mask | bits);
+
+        let optcr = self.read(reg::OPTCR);
+        self.write(reg::OPTCR, optcr | OPTCR_OPTSTART);
+        while self.read(reg::OPTSR_CUR) & OPTSR_OPT_BUSY != 0 {
+            // spin
+        }
+
+        let failed = self.read(reg::OPTSR_CUR) & OPTSR_OPTCHANGEERR != 0;
+        if failed {
+            self.write(reg::OPTCCR, OPTCCR_CLR_OPTCHANGEERR);
+        }
+        let optcr = self.read(reg::OPTCR);
+        self.write(reg::OPTCR, optcr | OPTCR_OPTLOCK);
+
+        if failed {
+            Err(Error::ChangeFailed)
+        } else if self.read(cur) & mask != bits {
+            Err(Error::VerifyFailed)
+        } else {
+            Ok(())
+        }
+    }
+}
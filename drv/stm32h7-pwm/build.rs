@@ -0,0 +1,143 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{anyhow, bail, Result};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskConfig {
+    timers: IndexMap<String, TimerConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TimerConfig {
+    /// The timer's kernel clock, which is twice its APB clock whenever the
+    /// APB prescaler is dividing.
+    clock_hz: u32,
+    /// Frequency out of reset, and forever if there's no owner.
+    frequency_hz: u32,
+    /// Task allowed to change the frequency.
+    owner: Option<String>,
+    channels: Vec<ChannelConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChannelConfig {
+    channel: u8,
+    pin: PinConfig,
+    /// Task allowed to set the duty cycle.
+    owner: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PinConfig {
+    port: String,
+    pin: u8,
+    af: u8,
+}
+
+/// The advanced-control timers, which are the ones we drive.
+fn timer_info(name: &str) -> Option<(u8, u32, &'static str)> {
+    match name {
+        "tim1" => Some((1, 0x4001_0000, "Tim1")),
+        "tim8" => Some((8, 0x4001_0400, "Tim8")),
+        _ => None,
+    }
+}
+
+fn main() -> Result<()> {
+    build_util::idol::build_server_support(
+        "../../idl/pwm.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+
+    let task = build_util::task_full_config::<TaskConfig>()?;
+    let config = task
+        .config
+        .ok_or_else(|| anyhow!("missing task config for the PWM timers"))?;
+    let task_ids = build_util::task_ids();
+    let task_index = |name: &str| {
+        task_ids
+            .get(name)
+            .ok_or_else(|| anyhow!("PWM owner '{name}' isn't a task"))
+    };
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "const TIMERS: [TimerConfig; {}] = [",
+        config.timers.len()
+    ));
+    for (name, timer) in &config.timers {
+        let Some((number, base, periph)) = timer_info(name) else {
+            bail!("'{name}' isn't an advanced timer (tim1 or tim8)");
+        };
+        if !task.uses.contains(name) {
+            bail!("uses should contain '{name}'");
+        }
+        let owner = match &timer.owner {
+            Some(o) => format!("Some({})", task_index(o)?),
+            None => "None".to_string(),
+        };
+        out.push_str(&format!(
+            "TimerConfig {{
+                number: {number},
+                base: {base:#x},
+                peripheral: sys_api::Peripheral::{periph},
+                clock_hz: {},
+                frequency_hz: {},
+                owner: {owner},
+                channels: &[",
+            timer.clock_hz, timer.frequency_hz,
+        ));
+
+        let mut seen = 0u8;
+        for ch in &timer.channels {
+            if !(1..=4).contains(&ch.channel) {
+                bail!("{name} has no channel {}", ch.channel);
+            }
+            if seen & 1 << ch.channel != 0 {
+                bail!("{name} channel {} is configured twice", ch.channel);
+            }
+            seen |= 1 << ch.channel;
+
+            let port = match ch.pin.port.as_str() {
+                p @ ("A" | "B" | "C" | "D" | "E" | "F" | "G" | "H" | "I"
+                | "J" | "K") => p,
+                p => bail!("bad GPIO port {p:?}"),
+            };
+            if ch.pin.pin > 15 || ch.pin.af > 15 {
+                bail!("bad pin {}{} AF{}", port, ch.pin.pin, ch.pin.af);
+            }
+            out.push_str(&format!(
+                "ChannelConfig {{
+                    channel: {},
+                    pin: sys_api::Port::{port}.pin({}),
+                    af: sys_api::Alternate::AF{},
+                    owner: {},
+                }},",
+                ch.channel,
+                ch.pin.pin,
+                ch.pin.af,
+                task_index(&ch.owner)?,
+            ));
+        }
+        out.push_str("]},");
+    }
+    out.push_str("];");
+
+    let dest_path = build_util::out_dir().join("pwm_config.rs");
+    let mut file = std::fs::File::create(&dest_path)?;
+    writeln!(file, "{out}")?;
+    drop(file);
+    call_rustfmt::rustfmt(&dest_path)?;
+
+    Ok(())
+}
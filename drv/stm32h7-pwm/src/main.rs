@@ -0,0 +1,331 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PWM outputs from the STM32H7's advanced-control timers, shared between
+//! tasks.
+//!
+//! The timers, their channels, and their pins come from the task config,
+//! which also gives each channel an owner -- the only task that may set its
+//! duty cycle -- and optionally gives each timer an owner that may change
+//! its frequency. The channels of a timer share one period, so a fan
+//! controller can own a timer running at 25 kHz while another task dims
+//! LEDs on its spare channels:
+//!
+//! ```toml
+//! [tasks.pwm.config.timers.tim1]
+//! clock-hz = 200_000_000
+//! frequency-hz = 25_000
+//! owner = "thermal"
+//! channels = [
+//!     { channel = 1, pin = { port = "E", pin = 9, af = 1 }, owner = "thermal" },
+//!     { channel = 2, pin = { port = "E", pin = 11, af = 1 }, owner = "user_leds" },
+//! ]
+//! ```
+//!
+//! Every register that sets the waveform is preloaded, so changes land at
+//! the end of a period: there are no runt pulses, and a frequency change
+//! takes effect on every channel in the same period.
+
+#![no_std]
+#![no_main]
+
+use drv_pwm_api::{PwmError, FULL_DUTY};
+use drv_stm32xx_sys_api::{self as sys_api, Sys};
+use idol_runtime::RequestError;
+use ringbuf::*;
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+struct TimerConfig {
+    number: u8,
+    base: usize,
+    peripheral: sys_api::Peripheral,
+    clock_hz: u32,
+    frequency_hz: u32,
+    owner: Option<usize>,
+    channels: &'static [ChannelConfig],
+}
+
+struct ChannelConfig {
+    channel: u8,
+    pin: sys_api::PinSet,
+    af: sys_api::Alternate,
+    owner: usize,
+}
+
+include!(concat!(env!("OUT_DIR"), "/pwm_config.rs"));
+
+/// Register offsets, which are the same for TIM1 and TIM8.
+mod reg {
+    pub const CR1: usize = 0x00;
+    pub const EGR: usize = 0x14;
+    pub const CCMR1: usize = 0x18;
+    pub const CCMR2: usize = 0x1c;
+    pub const CCER: usize = 0x20;
+    pub const PSC: usize = 0x28;
+    pub const ARR: usize = 0x2c;
+    pub const CCR1: usize = 0x34;
+    pub const BDTR: usize = 0x44;
+}
+
+const CR1_CEN: u32 = 1 << 0;
+/// Stops update events, so that a set of preloaded registers can be written
+/// without a period starting with only some of them.
+const CR1_UDIS: u32 = 1 << 1;
+const CR1_ARPE: u32 = 1 << 7;
+const EGR_UG: u32 = 1 << 0;
+const BDTR_MOE: u32 = 1 << 15;
+/// PWM mode 1, with the compare register preloaded, for the low channel of
+/// a CCMR register; the high channel is the same, 8 bits up.
+const CCMR_PWM1_PRELOAD: u32 = 0b110 << 4 | 1 << 3;
+
+/// Fewest steps we'll divide a period into; below this, duty cycles come
+/// out too far from what was asked for to be useful.
+const MIN_STEPS: u32 = 100;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Frequency { timer: u8, hz: u32 },
+    NotOwner { timer: u8, task: u16 },
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Finds the prescaler and auto-reload values giving `hz` from `clock_hz`,
+/// with as many steps per period as possible.
+fn timing(clock_hz: u32, hz: u32) -> Option<(u16, u16)> {
+    if hz == 0 {
+        return None;
+    }
+    let ticks = u64::from(clock_hz) / u64::from(hz);
+    let div = ((ticks + 0xffff) >> 16).max(1);
+    let steps = ticks / div;
+    if div > 1 << 16 || steps < u64::from(MIN_STEPS) {
+        return None;
+    }
+    Some(((div - 1) as u16, (steps - 1) as u16))
+}
+
+struct Timer {
+    config: &'static TimerConfig,
+    psc: u16,
+    arr: u16,
+    duty: [u16; 4],
+}
+
+impl Timer {
+    fn write(&self, offset: usize, value: u32) {
+        // Safety: the timer is in our memory map (`build.rs` checks), and
+        // nobody else uses it.
+        unsafe {
+            ((self.config.base + offset) as *mut u32).write_volatile(value)
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        // Safety: as above.
+        unsafe { ((self.config.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn frequency(&self) -> u32 {
+        let steps = (u32::from(self.psc) + 1) * (u32::from(self.arr) + 1);
+        self.config.clock_hz / steps
+    }
+
+    fn compare(&self, duty: u16) -> u32 {
+        // At full duty this is ARR + 1, which holds the output high.
+        (u32::from(self.arr) + 1) * u32::from(duty) / u32::from(FULL_DUTY)
+    }
+
+    fn write_compare(&self, channel: u8) {
+        let i = usize::from(channel - 1);
+        self.write(reg::CCR1 + 4 * i, self.compare(self.duty[i]));
+    }
+
+    /// Writes the period and every compare register, all of which are
+    /// preloaded, holding off the update event until they're all written.
+    fn write_waveform(&self) {
+        let cr1 = self.read(reg::CR1);
+        self.write(reg::CR1, cr1 | CR1_UDIS);
+        self.write(reg::PSC, self.psc.into());
+        self.write(reg::ARR, self.arr.into());
+        for ch in self.config.channels {
+            self.write_compare(ch.channel);
+        }
+        self.write(reg::CR1, cr1 & !CR1_UDIS);
+    }
+
+    fn start(&self) {
+        let mut ccmr = [0; 2];
+        let mut ccer = 0;
+        for ch in self.config.channels {
+            let i = usize::from(ch.channel - 1);
+            ccmr[i / 2] |= CCMR_PWM1_PRELOAD << (8 * (i % 2));
+            ccer |= 1 << (4 * i);
+        }
+        self.write(reg::CCMR1, ccmr[0]);
+        self.write(reg::CCMR2, ccmr[1]);
+        self.write(reg::CR1, CR1_ARPE);
+        self.write_waveform();
+        // Load the preloaded registers now, rather than after a period of
+        // whatever was there out of reset.
+        self.write(reg::EGR, EGR_UG);
+        self.write(reg::CCER, ccer);
+        // Advanced timers keep their outputs off until this is set.
+        self.write(reg::BDTR, BDTR_MOE);
+        self.write(reg::CR1, CR1_ARPE | CR1_CEN);
+    }
+}
+
+struct ServerImpl {
+    timers: [Timer; TIMERS.len()],
+}
+
+impl ServerImpl {
+    fn timer(&mut self, number: u8) -> Result<&mut Timer, PwmError> {
+        self.timers
+            .iter_mut()
+            .find(|t| t.config.number == number)
+            .ok_or(PwmError::NoSuchOutput)
+    }
+
+    fn channel(
+        &mut self,
+        number: u8,
+        channel: u8,
+    ) -> Result<(&mut Timer, &'static ChannelConfig), PwmError> {
+        let timer = self.timer(number)?;
+        let ch = timer
+            .config
+            .channels
+            .iter()
+            .find(|c| c.channel == channel)
+            .ok_or(PwmError::NoSuchOutput)?;
+        Ok((timer, ch))
+    }
+}
+
+fn check_owner(
+    msg: &RecvMessage,
+    timer: u8,
+    owner: usize,
+) -> Result<(), PwmError> {
+    if msg.sender.index() == owner {
+        Ok(())
+    } else {
+        ringbuf_entry!(Trace::NotOwner {
+            timer,
+            task: msg.sender.index() as u16,
+        });
+        Err(PwmError::NotOwner)
+    }
+}
+
+impl idl::InOrderPwmImpl for ServerImpl {
+    fn set_duty(
+        &mut self,
+        msg: &RecvMessage,
+        timer: u8,
+        channel: u8,
+        duty: u16,
+    ) -> Result<(), RequestError<PwmError>> {
+        let (t, ch) = self.channel(timer, channel)?;
+        check_owner(msg, timer, ch.owner)?;
+        if duty > FULL_DUTY {
+            return Err(PwmError::BadDuty.into());
+        }
+        t.duty[usize::from(channel - 1)] = duty;
+        t.write_compare(channel);
+        Ok(())
+    }
+
+    fn duty(
+        &mut self,
+        _: &RecvMessage,
+        timer: u8,
+        channel: u8,
+    ) -> Result<u16, RequestError<PwmError>> {
+        let (t, _) = self.channel(timer, channel)?;
+        Ok(t.duty[usize::from(channel - 1)])
+    }
+
+    fn set_frequency(
+        &mut self,
+        msg: &RecvMessage,
+        timer: u8,
+        hz: u32,
+    ) -> Result<u32, RequestError<PwmError>> {
+        let t = self.timer(timer)?;
+        let owner = t.config.owner.ok_or(PwmError::FrequencyFixed)?;
+        check_owner(msg, timer, owner)?;
+        let (psc, arr) =
+            timing(t.config.clock_hz, hz).ok_or(PwmError::BadFrequency)?;
+        t.psc = psc;
+        t.arr = arr;
+        t.write_waveform();
+        ringbuf_entry!(Trace::Frequency {
+            timer,
+            hz: t.frequency()
+        });
+        Ok(t.frequency())
+    }
+
+    fn frequency(
+        &mut self,
+        _: &RecvMessage,
+        timer: u8,
+    ) -> Result<u32, RequestError<PwmError>> {
+        Ok(self.timer(timer)?.frequency())
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+
+    let timers = core::array::from_fn(|i| {
+        let config = &TIMERS[i];
+        sys.enable_clock(config.peripheral);
+        sys.leave_reset(config.peripheral);
+
+        let (psc, arr) = timing(config.clock_hz, config.frequency_hz)
+            .unwrap_or_else(|| {
+                panic!("bad frequency for TIM{}", config.number)
+            });
+        let timer = Timer {
+            config,
+            psc,
+            arr,
+            duty: [0; 4],
+        };
+        timer.start();
+
+        // Only hand the pins to the timer once it's driving them low.
+        for ch in config.channels {
+            sys.gpio_configure_alternate(
+                ch.pin,
+                sys_api::OutputType::PushPull,
+                sys_api::Speed::Low,
+                sys_api::Pull::None,
+                ch.af,
+            );
+        }
+        timer
+    });
+
+    let mut server = ServerImpl { timers };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_pwm_api::PwmError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
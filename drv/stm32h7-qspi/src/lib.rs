@@ -21,19 +21,143 @@ use zerocopy::AsBytes;
 const FIFO_SIZE: usize = 32;
 const FIFO_THRESH: usize = 16;
 
+/// Mode byte sent after the address in quad I/O reads. Both Winbond (M5-4 !=
+/// 0b10) and Micron (XIP confirmation bit set) parts take all-ones to mean
+/// "don't enter continuous read / XIP mode", which would otherwise leave the
+/// part expecting address-only reads -- a state that the host CPU, or we after
+/// a restart, would have no idea about.
+const MODE_NO_XIP: u8 = 0xFF;
+
+/// Width of the address sent with memory commands.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AddressWidth {
+    /// 3-byte addresses, for parts of 16 MiB or less.
+    ThreeByte,
+    /// 4-byte addresses, using the dedicated 4-byte-address commands.
+    FourByte,
+}
+
+/// How memory reads are issued.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReadMode {
+    /// Instruction, address, and data on one line.
+    Single,
+    /// Instruction and address on one line, data on four.
+    QuadOutput,
+    /// Instruction on one line, address and data on four.
+    QuadIo,
+}
+
+/// Describes how to talk to a particular flash part.
+#[derive(Copy, Clone, Debug)]
+pub struct Protocol {
+    pub address: AddressWidth,
+    pub read: ReadMode,
+
+    /// Number of clock cycles between the address and data phases of a quad
+    /// read, as given by the part's datasheet. For `QuadIo`, this includes the
+    /// two cycles taken by the mode byte. Ignored for `Single` reads.
+    pub read_dummy_cycles: u8,
+
+    /// Whether page programs send data on four lines.
+    pub quad_program: bool,
+
+    /// Whether the part implements program/erase suspend and resume.
+    pub suspend: bool,
+}
+
+impl Default for Protocol {
+    /// The lowest common denominator: single-line everything, with 4-byte
+    /// addresses, and no suspend.
+    fn default() -> Self {
+        Self {
+            address: AddressWidth::FourByte,
+            read: ReadMode::Single,
+            read_dummy_cycles: 0,
+            quad_program: false,
+            suspend: false,
+        }
+    }
+}
+
+/// Number of lines used for a phase of a command, encoded as the CCR expects.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Lines {
+    None = 0b00,
+    Single = 0b01,
+    Quad = 0b11,
+}
+
+/// Shape of a single command on the wire.
+struct Frame {
+    command: Command,
+    address: Option<u32>,
+    address_lines: Lines,
+    /// Sent on the address lines between the address and any dummy cycles.
+    mode: Option<u8>,
+    dummy_cycles: u8,
+    data_lines: Lines,
+}
+
+impl Frame {
+    /// A command without an address, with any data on one line.
+    fn simple(command: Command) -> Self {
+        Self {
+            command,
+            address: None,
+            address_lines: Lines::None,
+            mode: None,
+            dummy_cycles: 0,
+            data_lines: Lines::Single,
+        }
+    }
+
+    /// A single-line command with a single-line address.
+    fn addressed(command: Command, address: u32) -> Self {
+        Self {
+            address: Some(address),
+            address_lines: Lines::Single,
+            ..Self::simple(command)
+        }
+    }
+}
+
 /// Wrapper for a reference to the register block.
 pub struct Qspi {
     reg: &'static device::quadspi::RegisterBlock,
     interrupt: u32,
+    protocol: Protocol,
 }
 
 impl Qspi {
-    /// Creates a new wrapper for `reg`.
+    /// Creates a new wrapper for `reg`, using the default `Protocol` until
+    /// told otherwise.
     pub fn new(
         reg: &'static device::quadspi::RegisterBlock,
         interrupt: u32,
     ) -> Self {
-        Self { reg, interrupt }
+        Self {
+            reg,
+            interrupt,
+            protocol: Protocol::default(),
+        }
+    }
+
+    /// Changes how we talk to the flash part; see `Protocol`.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        if protocol.read != ReadMode::Single {
+            // DCYC is five bits wide, and quad I/O spends two of the cycles
+            // on the mode byte.
+            assert!(protocol.read_dummy_cycles < 32);
+            if protocol.read == ReadMode::QuadIo {
+                assert!(protocol.read_dummy_cycles >= 2);
+            }
+        }
+        self.protocol = protocol;
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
     }
 
     /// Sets up the QSPI controller with some canned settings.
@@ -74,26 +198,108 @@ impl Qspi {
     /// This can be used to get basic details of the chip, and also to detect
     /// whether a chip is attached at all.
     pub fn read_id(&self, buf: &mut [u8; 20]) {
-        self.read_impl(Command::ReadId, None, buf)
+        self.read_impl(&Frame::simple(Command::ReadId), buf)
     }
 
     /// Reads the Status register.
     pub fn read_status(&self) -> u8 {
         let mut status = 0u8;
-        self.read_impl(Command::ReadStatusReg, None, status.as_bytes_mut());
+        self.read_impl(
+            &Frame::simple(Command::ReadStatusReg),
+            status.as_bytes_mut(),
+        );
+        status
+    }
+
+    /// Writes the Status register, which must be preceded by `write_enable`.
+    /// Like a program, this keeps the part busy -- for up to 15 ms -- until
+    /// the WIP bit of the status register reads as clear.
+    ///
+    /// Only the block protect bits, and the bit that locks them, are
+    /// writable; where they sit varies by part.
+    pub fn write_status(&self, status: u8) {
+        self.write_impl(
+            &Frame::simple(Command::WriteStatusReg),
+            status.as_bytes(),
+        )
+    }
+
+    /// Reads status register 2 of a Winbond part.
+    ///
+    /// Do not call this on Micron parts, which interpret the same opcode as
+    /// "switch to quad I/O protocol".
+    pub fn read_status_reg2(&self) -> u8 {
+        let mut status = 0u8;
+        self.read_impl(
+            &Frame::simple(Command::ReadStatusReg2),
+            status.as_bytes_mut(),
+        );
         status
     }
 
     /// Reads from flash storage starting at `address` and continuing for
     /// `data.len()` bytes, depositing the bytes into `data`.
     pub fn read_memory(&self, address: u32, data: &mut [u8]) {
-        self.read_impl(Command::Read, Some(address), data);
+        let p = &self.protocol;
+        let four = p.address == AddressWidth::FourByte;
+        let frame = match p.read {
+            ReadMode::Single => Frame::addressed(
+                if four { Command::Read } else { Command::Read3 },
+                address,
+            ),
+            ReadMode::QuadOutput => Frame {
+                dummy_cycles: p.read_dummy_cycles,
+                data_lines: Lines::Quad,
+                ..Frame::addressed(
+                    if four {
+                        Command::QuadOutputRead
+                    } else {
+                        Command::QuadOutputRead3
+                    },
+                    address,
+                )
+            },
+            ReadMode::QuadIo => Frame {
+                address_lines: Lines::Quad,
+                // The mode byte takes two cycles on four lines.
+                mode: Some(MODE_NO_XIP),
+                dummy_cycles: p.read_dummy_cycles - 2,
+                data_lines: Lines::Quad,
+                ..Frame::addressed(
+                    if four {
+                        Command::QuadIoRead
+                    } else {
+                        Command::QuadIoRead3
+                    },
+                    address,
+                )
+            },
+        };
+        self.read_impl(&frame, data);
     }
 
     /// Sets the Write Enable Latch on the flash chip, allowing a write/erase
     /// command sent immediately after to succeed.
     pub fn write_enable(&self) {
-        self.write_impl(Command::WriteEnable, None, &[])
+        self.write_impl(&Frame::simple(Command::WriteEnable), &[])
+    }
+
+    /// Suspends an in-progress program or sector erase, so that other parts
+    /// of the flash can be read. The part is not ready to be read until the
+    /// WIP bit of the status register reads as clear.
+    ///
+    /// Only valid if the `Protocol` says the part supports it. Bulk erases
+    /// cannot be suspended.
+    pub fn suspend(&self) {
+        assert!(self.protocol.suspend);
+        self.write_impl(&Frame::simple(Command::Suspend), &[])
+    }
+
+    /// Resumes a program or erase paused by `suspend`. This is ignored by the
+    /// part if nothing is suspended.
+    pub fn resume(&self) {
+        assert!(self.protocol.suspend);
+        self.write_impl(&Frame::simple(Command::Resume), &[])
     }
 
     /// Performs a bulk erase of the chip. Note that this may take a rather long
@@ -103,7 +309,7 @@ impl Qspi {
     ///
     /// Erasing a NAND flash chip resets all bits to 1.
     pub fn bulk_erase(&self) {
-        self.write_impl(Command::BulkErase, None, &[])
+        self.write_impl(&Frame::simple(Command::BulkErase), &[])
     }
 
     /// Erases the 64kiB sector containing `addr`.
@@ -113,7 +319,11 @@ impl Qspi {
     ///
     /// Erasing a sector of a NAND flash chip resets all bits to 1.
     pub fn sector_erase(&self, addr: u32) {
-        self.write_impl(Command::SectorErase, Some(addr), &[])
+        let command = match self.protocol.address {
+            AddressWidth::FourByte => Command::SectorErase,
+            AddressWidth::ThreeByte => Command::SectorErase3,
+        };
+        self.write_impl(&Frame::addressed(command, addr), &[])
     }
 
     /// Writes `data` into flash memory beginning at `addr`.
@@ -127,44 +337,93 @@ impl Qspi {
     /// this routine, to update information without erasing -- but of course it
     /// can only clear bits.
     pub fn page_program(&self, addr: u32, data: &[u8]) {
-        self.write_impl(Command::PageProgram, Some(addr), data)
+        let four = self.protocol.address == AddressWidth::FourByte;
+        let frame = if self.protocol.quad_program {
+            Frame {
+                data_lines: Lines::Quad,
+                ..Frame::addressed(
+                    if four {
+                        Command::QuadPageProgram
+                    } else {
+                        Command::QuadPageProgram3
+                    },
+                    addr,
+                )
+            }
+        } else {
+            Frame::addressed(
+                if four {
+                    Command::PageProgram
+                } else {
+                    Command::PageProgram3
+                },
+                addr,
+            )
+        };
+        self.write_impl(&frame, data)
     }
 
-    /// Internal implementation of writes.
-    fn write_impl(&self, command: Command, addr: Option<u32>, data: &[u8]) {
-        if !data.is_empty() {
-            self.set_transfer_length(data.len());
+    /// Writes the instruction, address, and mode phases of `frame` to the
+    /// CCR, ABR, and AR, in functional mode `fmode`. If there is an address,
+    /// the AR write is what kicks off the transfer; otherwise the CCR write
+    /// does.
+    fn start(&self, frame: &Frame, fmode: u8, has_data: bool) {
+        let adsize = match self.protocol.address {
+            AddressWidth::ThreeByte => 0b10,
+            AddressWidth::FourByte => 0b11,
+        };
+        if let Some(mode) = frame.mode {
+            self.reg
+                .abr
+                .write(|w| unsafe { w.alternate().bits(u32::from(mode)) });
         }
 
-        // Clear flags we'll use later.
-        self.reg.fcr.write(|w| w.ctcf().set_bit());
-
-        // Note: if we aren't using an address, this write will kick things off.
-        // Otherwise it's the AR write below.
         #[rustfmt::skip]
-        #[allow(clippy::bool_to_int_with_if)]
         self.reg.ccr.write(|w| unsafe {
             w
-                // Indirect write
-                .fmode().bits(0b00)
-                // Data on single line, or no data
-                .dmode().bits(if data.is_empty() { 0b00 } else { 0b01 })
-                // Dummy cycles = 0 for this
-                .dcyc().bits(0)
-                // No alternate bytes
-                .abmode().bits(0)
-                // 32-bit address, if present.
-                .adsize().bits(if addr.is_some() { 0b11 } else { 0b00 })
-                // ...on one line for now, if present.
-                .admode().bits(if addr.is_some() { 0b01 } else { 0b00 })
+                .fmode().bits(fmode)
+                // Data lines, or no data
+                .dmode().bits(if has_data {
+                    frame.data_lines as u8
+                } else {
+                    Lines::None as u8
+                })
+                .dcyc().bits(frame.dummy_cycles)
+                // Mode byte, if any, is a single alternate byte sent on the
+                // address lines.
+                .absize().bits(0b00)
+                .abmode().bits(if frame.mode.is_some() {
+                    frame.address_lines as u8
+                } else {
+                    Lines::None as u8
+                })
+                .adsize().bits(if frame.address.is_some() { adsize } else { 0 })
+                .admode().bits(if frame.address.is_some() {
+                    frame.address_lines as u8
+                } else {
+                    Lines::None as u8
+                })
                 // Instruction on single line
                 .imode().bits(0b01)
                 // And, the op
-                .instruction().bits(command as u8)
+                .instruction().bits(frame.command as u8)
         });
-        if let Some(addr) = addr {
+        if let Some(addr) = frame.address {
             self.reg.ar.write(|w| unsafe { w.address().bits(addr) });
         }
+    }
+
+    /// Internal implementation of writes.
+    fn write_impl(&self, frame: &Frame, data: &[u8]) {
+        if !data.is_empty() {
+            self.set_transfer_length(data.len());
+        }
+
+        // Clear flags we'll use later.
+        self.reg.fcr.write(|w| w.ctcf().set_bit());
+
+        // Indirect write
+        self.start(frame, 0b00, !data.is_empty());
 
         // We're going to update this slice in place as we send data by lopping
         // off the front.
@@ -225,7 +484,7 @@ impl Qspi {
     }
 
     /// Internal implementation of reads.
-    fn read_impl(&self, command: Command, addr: Option<u32>, out: &mut [u8]) {
+    fn read_impl(&self, frame: &Frame, out: &mut [u8]) {
         assert!(!out.is_empty());
 
         self.set_transfer_length(out.len());
@@ -234,30 +493,8 @@ impl Qspi {
         // hanging around from some previous transfer -- ensure this:
         self.reg.fcr.write(|w| w.ctcf().set_bit());
 
-        #[rustfmt::skip]
-        #[allow(clippy::bool_to_int_with_if)]
-        self.reg.ccr.write(|w| unsafe {
-            w
-                // Indirect read
-                .fmode().bits(0b01)
-                // Data on single line, or no data
-                .dmode().bits(if out.is_empty() { 0b00 } else { 0b01 })
-                // Dummy cycles = 0 for this
-                .dcyc().bits(0)
-                // No alternate bytes
-                .abmode().bits(0)
-                // 32-bit address if present.
-                .adsize().bits(if addr.is_some() { 0b11 } else { 0b00 })
-                // ...on one line for now, if present.
-                .admode().bits(if addr.is_some() { 0b01 } else { 0b00 })
-                // Instruction on single line
-                .imode().bits(0b01)
-                // And, the op
-                .instruction().bits(command as u8)
-        });
-        if let Some(addr) = addr {
-            self.reg.ar.write(|w| unsafe { w.address().bits(addr) });
-        }
+        // Indirect read
+        self.start(frame, 0b01, true);
 
         // We're going to shorten this slice by lopping off the front as we
         // perform transfers.
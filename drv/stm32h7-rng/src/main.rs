@@ -5,13 +5,20 @@
 //! Driver for the STM32H7 random number generator.
 //!
 //! Use the rng-api crate to interact with this driver.
+//!
+//! Output from the hardware is health tested and used to seed a ChaCha20
+//! DRBG, which is what clients actually read from; see `drv_rng_api::health`.
 
 #![no_std]
 #![no_main]
 
-use drv_rng_api::RngError;
+use core::mem::size_of;
+use drv_rng_api::health::{EntropySource, HealthChecked, ReseedingRng};
+use drv_rng_api::{RngError, RngHealthStats};
 use drv_stm32xx_sys_api::{Peripheral, Sys};
 use idol_runtime::{ClientError, RequestError};
+use rand_chacha::ChaCha20Rng;
+use rand_core::{Error, RngCore};
 
 #[cfg(feature = "h743")]
 use stm32h7::stm32h743 as device;
@@ -67,6 +74,20 @@ impl Stm32h7Rng {
         Ok(self.dr.read().rndata().bits())
     }
 
+    /// Recovers from a seed or clock error by clearing the sticky error
+    /// flags and cycling the peripheral, which restarts its internal
+    /// conditioning and discards anything left in the output pipeline.
+    fn restart(&mut self) -> Result<(), RngError> {
+        self.cr.modify(|_, w| w.rngen().clear_bit());
+        self.sr
+            .modify(|_, w| w.seis().clear_bit().ceis().clear_bit());
+        self.enable_rng();
+        if self.is_clock_error() {
+            return Err(RngError::ClockError);
+        }
+        Ok(())
+    }
+
     fn enable_rng(&self) {
         self.cr.modify(|_, w| w.rngen().set_bit());
     }
@@ -93,13 +114,22 @@ impl Stm32h7Rng {
     }
 }
 
-struct Stm32h7RngServer {
-    rng: Stm32h7Rng,
+impl EntropySource for Stm32h7Rng {
+    fn next_word(&mut self) -> Result<u32, RngError> {
+        self.read()
+    }
+
+    fn restart(&mut self) -> Result<(), RngError> {
+        Stm32h7Rng::restart(self)
+    }
 }
 
+struct Stm32h7RngServer(ReseedingRng<ChaCha20Rng, Stm32h7Rng>);
+
 impl Stm32h7RngServer {
-    fn new(rng: Stm32h7Rng) -> Self {
-        Stm32h7RngServer { rng }
+    fn new(rng: Stm32h7Rng, threshold: usize) -> Result<Self, Error> {
+        let reseeder = HealthChecked::new(rng);
+        Ok(Stm32h7RngServer(ReseedingRng::new(reseeder, threshold)?))
     }
 }
 
@@ -110,26 +140,32 @@ impl idl::InOrderRngImpl for Stm32h7RngServer {
         dest: idol_runtime::Leased<idol_runtime::W, [u8]>,
     ) -> Result<usize, RequestError<RngError>> {
         let mut cnt = 0;
-        for _ in 0..(dest.len() / 4) {
-            let ent = self.rng.read()?;
-            dest.write_range(cnt..cnt + 4, &ent.to_ne_bytes()[0..4])
+        const STEP: usize = size_of::<u32>();
+        let mut buf = [0u8; STEP];
+        for _ in 0..(dest.len() / STEP) {
+            self.0.try_fill_bytes(&mut buf).map_err(RngError::from)?;
+            dest.write_range(cnt..cnt + STEP, &buf)
                 .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
-            cnt += 4;
+            cnt += STEP;
         }
 
         let remain = dest.len() - cnt;
-        if remain > 4 {
-            panic!("RNG state machine bork");
-        }
         if remain > 0 {
-            let ent = self.rng.read()?;
-            dest.write_range(cnt..dest.len(), &ent.to_ne_bytes()[0..remain])
+            self.0.try_fill_bytes(&mut buf).map_err(RngError::from)?;
+            dest.write_range(cnt..dest.len(), &buf[0..remain])
                 .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
             cnt += remain;
         }
 
         Ok(cnt)
     }
+
+    fn health_stats(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<RngHealthStats, RequestError<core::convert::Infallible>> {
+        Ok(self.0.stats())
+    }
 }
 
 #[export_name = "main"]
@@ -137,7 +173,9 @@ fn main() -> ! {
     let mut rng = Stm32h7Rng::new();
     rng.init().expect("init failed");
 
-    let mut srv = Stm32h7RngServer::new(rng);
+    let threshold = 0x100000; // 1 MiB
+    let mut srv = Stm32h7RngServer::new(rng, threshold)
+        .expect("Failed to create Stm32h7RngServer");
     let mut buffer = [0u8; idl::INCOMING_SIZE];
 
     loop {
@@ -146,7 +184,7 @@ fn main() -> ! {
 }
 
 mod idl {
-    use drv_rng_api::RngError;
+    use drv_rng_api::{RngError, RngHealthStats};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
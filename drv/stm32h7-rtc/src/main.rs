@@ -0,0 +1,316 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Driver for the STM32H7's real-time clock.
+//!
+//! The RTC lives in the backup domain, so it keeps counting through resets,
+//! and through power loss if VBAT has a battery on it. The kernel sets up its
+//! clock, since that's a once-per-boot job for the board's startup code (see
+//! `enable_rtc_lse`); we expect the 32.768 kHz LSE, and count in 1/256ths of
+//! a second. That also spares us calling `sys`, so we can sit at a higher
+//! priority than tasks like `net` that want timestamps.
+//!
+//! The RTC counts in calendar dates, which we convert to and from Unix time.
+//! Whether it has ever been set is recorded in a backup register alongside
+//! it, so that after a power loss without a battery we say we don't know the
+//! time rather than claim it's 2000.
+//!
+//! Alarms are kept here rather than in the RTC's two alarm units: there are
+//! more tasks than units, and a kernel timer is good enough for waking
+//! someone within a millisecond or so of a given second. We check the RTC
+//! when the timer goes off, so drift between the two clocks only makes an
+//! alarm late by a tick, never early.
+
+#![no_std]
+#![no_main]
+
+use calendar::DateTime;
+use drv_rtc_api::RtcError;
+use idol_runtime::{NotificationHandler, RequestError};
+use ringbuf::*;
+use userlib::*;
+
+const RTC_BASE: usize = 0x5800_4000;
+
+mod reg {
+    pub const TR: usize = 0x00;
+    pub const DR: usize = 0x04;
+    pub const CR: usize = 0x08;
+    pub const ISR: usize = 0x0c;
+    pub const PRER: usize = 0x10;
+    pub const WPR: usize = 0x24;
+    pub const SSR: usize = 0x28;
+    pub const SHIFTR: usize = 0x2c;
+    pub const BKP0R: usize = 0x50;
+}
+
+const CR_FMT: u32 = 1 << 6;
+const ISR_INIT: u32 = 1 << 7;
+const ISR_INITF: u32 = 1 << 6;
+const ISR_RSF: u32 = 1 << 5;
+const ISR_SHPF: u32 = 1 << 3;
+const SHIFTR_ADD1S: u32 = 1 << 31;
+
+/// Prescalers taking the 32.768 kHz LSE to 1 Hz, with the synchronous one as
+/// large as it can be for the finest sub-second count.
+const PREDIV_A: u32 = 127;
+const PREDIV_S: u32 = 255;
+
+/// Written to BKP0R once the time has been set.
+const TIME_SET: u32 = 0x5254_4331;
+
+const MAX_ALARMS: usize = 4;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Set { unix_us: u64 },
+    BadCalendar { tr: u32, dr: u32 },
+    Alarm { task: u16, unix_secs: u64 },
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+fn read(offset: usize) -> u32 {
+    // Safety: the RTC is in our memory map, and nobody else uses it.
+    unsafe { ((RTC_BASE + offset) as *const u32).read_volatile() }
+}
+
+fn write(offset: usize, value: u32) {
+    // Safety: as above.
+    unsafe { ((RTC_BASE + offset) as *mut u32).write_volatile(value) }
+}
+
+fn wait_for(offset: usize, mask: u32, set: bool) {
+    while (read(offset) & mask != 0) != set {
+        // spin
+    }
+}
+
+fn bcd(v: u8) -> u32 {
+    u32::from(v / 10) << 4 | u32::from(v % 10)
+}
+
+fn from_bcd(v: u32) -> u8 {
+    ((v >> 4 & 0xf) * 10 + (v & 0xf)) as u8
+}
+
+/// Reads the time, if the RTC has been set and is making sense.
+fn now_us() -> Option<u64> {
+    if read(reg::BKP0R) != TIME_SET {
+        return None;
+    }
+    // Reading SSR freezes TR and DR until DR is read, so the three agree.
+    let ss = read(reg::SSR);
+    let tr = read(reg::TR);
+    let dr = read(reg::DR);
+
+    let date = DateTime {
+        year: 2000 + u16::from(from_bcd(dr >> 16)),
+        month: from_bcd(dr >> 8 & 0x1f),
+        day: from_bcd(dr & 0x3f),
+        hour: from_bcd(tr >> 16 & 0x3f),
+        minute: from_bcd(tr >> 8 & 0x7f),
+        second: from_bcd(tr & 0x7f),
+    };
+    if !date.is_valid() {
+        ringbuf_entry!(Trace::BadCalendar { tr, dr });
+        return None;
+    }
+
+    // SS counts down from PREDIV_S. Just after a shift it can be above
+    // PREDIV_S, meaning TR is a second ahead, which this also gets right.
+    let ticks = i64::from(PREDIV_S) - i64::from(ss);
+    let us = date.to_unix() as i64 * 1_000_000
+        + ticks * 1_000_000 / i64::from(PREDIV_S + 1);
+    Some(us as u64)
+}
+
+fn set_time(unix_us: u64) -> Result<(), RtcError> {
+    let date =
+        DateTime::from_unix(unix_us / 1_000_000).ok_or(RtcError::BadTime)?;
+    let frac_us = (unix_us % 1_000_000) as u32;
+
+    // Unlock the RTC's registers, and stop the calendar.
+    write(reg::WPR, 0xca);
+    write(reg::WPR, 0x53);
+    write(reg::ISR, read(reg::ISR) | ISR_INIT);
+    wait_for(reg::ISR, ISR_INITF, true);
+
+    // The prescalers take two separate writes.
+    write(reg::PRER, PREDIV_S);
+    write(reg::PRER, PREDIV_A << 16 | PREDIV_S);
+    write(reg::CR, read(reg::CR) & !CR_FMT);
+    write(
+        reg::TR,
+        bcd(date.hour) << 16 | bcd(date.minute) << 8 | bcd(date.second),
+    );
+    write(
+        reg::DR,
+        bcd((date.year - 2000) as u8) << 16
+            | u32::from(date.weekday()) << 13
+            | bcd(date.month) << 8
+            | bcd(date.day),
+    );
+
+    // Restart the calendar, and wait for the shadow registers to catch up
+    // before anyone reads them.
+    write(reg::ISR, read(reg::ISR) & !(ISR_INIT | ISR_RSF));
+    wait_for(reg::ISR, ISR_RSF, true);
+
+    // The calendar restarts at the top of the second; move it on by the
+    // fraction we were asked for, by adding a second and taking back the
+    // rest of it.
+    let ticks = frac_us * (PREDIV_S + 1) / 1_000_000;
+    if ticks != 0 {
+        wait_for(reg::ISR, ISR_SHPF, false);
+        write(reg::SHIFTR, SHIFTR_ADD1S | (PREDIV_S + 1 - ticks));
+    }
+
+    write(reg::BKP0R, TIME_SET);
+    write(reg::WPR, 0xff);
+
+    ringbuf_entry!(Trace::Set { unix_us });
+    Ok(())
+}
+
+#[derive(Copy, Clone)]
+struct Alarm {
+    task: TaskId,
+    notification: u32,
+    unix_secs: u64,
+}
+
+struct ServerImpl {
+    alarms: [Option<Alarm>; MAX_ALARMS],
+}
+
+impl ServerImpl {
+    /// Sets off any alarms that are due, and sets the timer for the next.
+    fn check_alarms(&mut self) {
+        let Some(now) = now_us() else {
+            // Having lost track of the time, we can't say when anything is
+            // due; keep the alarms until someone sets it again.
+            sys_set_timer(None, notifications::TIMER_MASK);
+            return;
+        };
+
+        let mut next: Option<u64> = None;
+        for slot in &mut self.alarms {
+            let Some(a) = *slot else { continue };
+            let due_us = a.unix_secs.saturating_mul(1_000_000);
+            if due_us <= now {
+                ringbuf_entry!(Trace::Alarm {
+                    task: a.task.index() as u16,
+                    unix_secs: a.unix_secs,
+                });
+                sys_post(a.task, a.notification);
+                *slot = None;
+            } else {
+                next = Some(next.map_or(due_us, |n| n.min(due_us)));
+            }
+        }
+
+        // Round up, so that we're never woken early.
+        let deadline = next
+            .map(|due_us| sys_get_timer().now + (due_us - now + 999) / 1000);
+        sys_set_timer(deadline, notifications::TIMER_MASK);
+    }
+}
+
+impl idl::InOrderRtcImpl for ServerImpl {
+    fn get_time(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u64, RequestError<RtcError>> {
+        now_us().ok_or_else(|| RtcError::NotSet.into())
+    }
+
+    fn set_time(
+        &mut self,
+        _: &RecvMessage,
+        unix_us: u64,
+    ) -> Result<(), RequestError<RtcError>> {
+        set_time(unix_us)?;
+        // The alarms are in wall-clock time, which has just moved.
+        self.check_alarms();
+        Ok(())
+    }
+
+    fn set_alarm(
+        &mut self,
+        msg: &RecvMessage,
+        unix_secs: u64,
+        notification: u32,
+    ) -> Result<(), RequestError<RtcError>> {
+        if now_us().is_none() {
+            return Err(RtcError::NotSet.into());
+        }
+        let alarm = Alarm {
+            task: msg.sender,
+            notification,
+            unix_secs,
+        };
+        // Replace the caller's alarm, if it has one, and otherwise take a
+        // free slot.
+        let slot = match self.alarms.iter().position(|a| {
+            a.map_or(false, |a| a.task.index() == msg.sender.index())
+        }) {
+            Some(i) => &mut self.alarms[i],
+            None => self
+                .alarms
+                .iter_mut()
+                .find(|a| a.is_none())
+                .ok_or(RtcError::TooManyAlarms)?,
+        };
+        *slot = Some(alarm);
+        self.check_alarms();
+        Ok(())
+    }
+
+    fn cancel_alarm(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        for slot in &mut self.alarms {
+            if slot.map_or(false, |a| a.task.index() == msg.sender.index()) {
+                *slot = None;
+            }
+        }
+        self.check_alarms();
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.check_alarms();
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    // After a reset, the shadow registers aren't good until RSF says so.
+    wait_for(reg::ISR, ISR_RSF, true);
+
+    let mut server = ServerImpl {
+        alarms: [None; MAX_ALARMS],
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_rtc_api::RtcError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
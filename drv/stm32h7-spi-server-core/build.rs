@@ -17,6 +17,14 @@ fn main() -> Result<()> {
         &full_task_config.uses,
         &full_task_config.interrupts,
     )?;
+    if std::env::var("CARGO_FEATURE_DMA").is_ok() {
+        check_dma(
+            &full_task_config.uses,
+            &full_task_config.interrupts,
+            &full_task_config.sections,
+            &spi,
+        )?;
+    }
 
     // Confirm that we've enabled the appropriate SPI feature, and *not* enabled
     // any other SPI features.
@@ -103,9 +111,40 @@ fn check_uses_and_interrupts(
     if !interrupts.contains_key(&spi_irq) {
         bail!("interrupts should contain '{spi_irq}'");
     }
+
     Ok(spi.to_owned())
 }
 
+fn check_dma(
+    uses: &[String],
+    interrupts: &IndexMap<String, String>,
+    sections: &IndexMap<String, String>,
+    spi: &str,
+) -> Result<()> {
+    for p in ["dma1", "dmamux1"] {
+        if !uses.iter().any(|u| u == p) {
+            bail!("'dma' feature requires '{p}' in uses");
+        }
+    }
+
+    // We wait on the RX stream's interrupt in place of the SPI interrupt, so
+    // it has to land on the same notification.
+    let spi_irq = format!("{spi}.irq");
+    if interrupts.get("dma1.stream0") != interrupts.get(&spi_irq) {
+        bail!(
+            "'dma' feature requires 'dma1.stream0' to be routed to the same \
+             notification as '{spi_irq}'"
+        );
+    }
+
+    // The bounce buffers must be somewhere the DMA controller can reach.
+    if !sections.contains_key("spi_dma") {
+        bail!("'dma' feature requires a 'spi_dma' entry in sections");
+    }
+
+    Ok(())
+}
+
 fn check_spi_config(
     config: &BTreeMap<String, SpiConfig>,
     global_config: &str,
@@ -0,0 +1,214 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! DMA transfer support for the SPI server, enabled by the `dma` feature.
+//!
+//! We can't point the DMA controller directly at a caller's lease: we don't
+//! know where it lives, and task RAM is in DTCM, which DMA1 can't reach
+//! anyway. Instead, transfers are bounced through a pair of buffers in the
+//! `.spi_dma` section, which the app must place in a DMA-capable region
+//! (e.g. `sections = {spi_dma = "sram1"}`). Leased data is gathered into the
+//! TX buffer and scattered out of the RX buffer a chunk at a time; between
+//! chunks the SPI block simply stalls the clock, as it has nothing to send.
+//!
+//! We use DMA1 streams 0 (RX) and 1 (TX), routed through DMAMUX1 channels 0
+//! and 1. The task must have `dma1` and `dmamux1` in its `uses`, and must
+//! route `dma1.stream0` to the same notification as the SPI interrupt.
+//! Because those streams are claimed outright, only one SPI server in an
+//! image may enable the `dma` feature.
+
+use core::cell::{RefCell, RefMut};
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use drv_stm32h7_spi as spi_core;
+use drv_stm32xx_sys_api as sys_api;
+use mutable_statics::mutable_statics;
+
+use crate::device;
+
+/// Size of each bounce buffer, and thus the largest chunk moved per DMA
+/// transfer.
+pub const CHUNK: usize = 256;
+
+const RX_STREAM: usize = 0;
+const TX_STREAM: usize = 1;
+
+cfg_if::cfg_if! {
+    // DMAMUX1 request lines, from RM0433 table 121.
+    if #[cfg(feature = "spi1")] {
+        const RX_REQUEST: u32 = 37;
+        const TX_REQUEST: u32 = 38;
+    } else if #[cfg(feature = "spi2")] {
+        const RX_REQUEST: u32 = 39;
+        const TX_REQUEST: u32 = 40;
+    } else if #[cfg(feature = "spi3")] {
+        const RX_REQUEST: u32 = 61;
+        const TX_REQUEST: u32 = 62;
+    } else if #[cfg(feature = "spi4")] {
+        const RX_REQUEST: u32 = 83;
+        const TX_REQUEST: u32 = 84;
+    } else if #[cfg(feature = "spi5")] {
+        const RX_REQUEST: u32 = 85;
+        const TX_REQUEST: u32 = 86;
+    } else {
+        // SPI6 lives in D3 and is served by BDMA rather than DMA1.
+        compile_error!("DMA is not supported on this SPI controller");
+    }
+}
+
+// Stream configuration register (DMA_SxCR) bits.
+const CR_EN: u32 = 1 << 0;
+const CR_DMEIE: u32 = 1 << 1;
+const CR_TEIE: u32 = 1 << 2;
+const CR_TCIE: u32 = 1 << 4;
+const CR_DIR_M2P: u32 = 0b01 << 6;
+const CR_MINC: u32 = 1 << 10;
+
+// Per-stream bits in LISR/LIFCR, before shifting into place for a stream.
+const FEIF: u32 = 1 << 0;
+const DMEIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+const HTIF: u32 = 1 << 4;
+const TCIF: u32 = 1 << 5;
+
+/// Shifts per-stream interrupt flags into place for streams 0-3, which share
+/// LISR/LIFCR.
+const fn flags(stream: usize, bits: u32) -> u32 {
+    const SHIFT: [u32; 4] = [0, 6, 16, 22];
+    bits << SHIFT[stream]
+}
+
+const ALL_FLAGS: u32 = FEIF | DMEIF | TEIF | HTIF | TCIF;
+const ERROR_FLAGS: u32 = DMEIF | TEIF;
+
+pub struct Buffers {
+    pub tx: [u8; CHUNK],
+    pub rx: [u8; CHUNK],
+}
+
+impl Buffers {
+    const fn new() -> Self {
+        Self {
+            tx: [0; CHUNK],
+            rx: [0; CHUNK],
+        }
+    }
+}
+
+/// Outcome of polling a chunk in flight.
+pub enum Status {
+    Busy,
+    Done,
+    Error,
+}
+
+#[derive(Clone)]
+pub struct Dma {
+    dma: &'static device::dma1::RegisterBlock,
+    bufs: &'static RefCell<Buffers>,
+}
+
+impl Dma {
+    /// Claims the bounce buffers and points our streams at the SPI data
+    /// registers. Can only be called once.
+    pub fn init(sys: &sys_api::Sys, spi: &spi_core::Spi) -> Self {
+        let bufs = mutable_statics! {
+            #[link_section = ".spi_dma"]
+            static mut BUFS: [RefCell<Buffers>; 1] =
+                [|| RefCell::new(Buffers::new()); _];
+        };
+        let bufs = &bufs[0];
+
+        sys.enable_clock(sys_api::Peripheral::Dma1);
+        sys.enter_reset(sys_api::Peripheral::Dma1);
+        sys.leave_reset(sys_api::Peripheral::Dma1);
+
+        let dma = unsafe { &*device::DMA1::ptr() };
+        let mux = unsafe { &*device::DMAMUX1::ptr() };
+
+        // DMAMUX1 channels 0-7 feed DMA1 streams 0-7.
+        mux.ccr[RX_STREAM].write(|w| unsafe { w.bits(RX_REQUEST) });
+        mux.ccr[TX_STREAM].write(|w| unsafe { w.bits(TX_REQUEST) });
+
+        // The buffers are never moved, so the addresses only need to be set
+        // once.
+        let (tx, rx) = {
+            let b = bufs.borrow();
+            (b.tx.as_ptr() as u32, b.rx.as_ptr() as u32)
+        };
+        let rx_st = &dma.st[RX_STREAM];
+        rx_st.par.write(|w| unsafe { w.bits(spi.rxdr_addr()) });
+        rx_st.m0ar.write(|w| unsafe { w.bits(rx) });
+        let tx_st = &dma.st[TX_STREAM];
+        tx_st.par.write(|w| unsafe { w.bits(spi.txdr_addr()) });
+        tx_st.m0ar.write(|w| unsafe { w.bits(tx) });
+
+        Self { dma, bufs }
+    }
+
+    pub fn buffers(&self) -> RefMut<'_, Buffers> {
+        self.bufs.borrow_mut()
+    }
+
+    /// Starts moving the first `len` bytes of the TX buffer out, and the
+    /// same number of bytes into the RX buffer. Completion is signalled by
+    /// the RX stream's interrupt.
+    pub fn start(&self, len: usize) {
+        assert!(len > 0 && len <= CHUNK);
+
+        self.clear_flags();
+
+        // Make sure the TX buffer contents land before the DMA controller
+        // goes looking for them.
+        compiler_fence(Ordering::Release);
+        cortex_m::asm::dsb();
+
+        let rx_st = &self.dma.st[RX_STREAM];
+        rx_st.ndtr.write(|w| unsafe { w.bits(len as u32) });
+        rx_st.cr.write(|w| unsafe {
+            w.bits(CR_MINC | CR_TCIE | CR_TEIE | CR_DMEIE | CR_EN)
+        });
+
+        let tx_st = &self.dma.st[TX_STREAM];
+        tx_st.ndtr.write(|w| unsafe { w.bits(len as u32) });
+        tx_st.cr.write(|w| unsafe {
+            w.bits(CR_DIR_M2P | CR_MINC | CR_TEIE | CR_DMEIE | CR_EN)
+        });
+    }
+
+    pub fn poll(&self) -> Status {
+        let isr = self.dma.lisr.read().bits();
+
+        if isr & (flags(RX_STREAM, ERROR_FLAGS) | flags(TX_STREAM, ERROR_FLAGS))
+            != 0
+        {
+            Status::Error
+        } else if isr & flags(RX_STREAM, TCIF) != 0 {
+            Status::Done
+        } else {
+            Status::Busy
+        }
+    }
+
+    /// Disables both streams, waiting for them to wind down, and clears
+    /// their flags.
+    pub fn stop(&self) {
+        for stream in [RX_STREAM, TX_STREAM] {
+            let st = &self.dma.st[stream];
+            st.cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_EN) });
+            while st.cr.read().bits() & CR_EN != 0 {}
+        }
+
+        self.clear_flags();
+
+        // Don't let reads of the RX buffer get hoisted above this point.
+        compiler_fence(Ordering::Acquire);
+    }
+
+    fn clear_flags(&self) {
+        self.dma.lifcr.write(|w| unsafe {
+            w.bits(flags(RX_STREAM, ALL_FLAGS) | flags(TX_STREAM, ALL_FLAGS))
+        });
+    }
+}
@@ -6,6 +6,9 @@
 //!
 //! Currently this hardcodes the clock rate.
 //!
+//! With the `dma` feature, larger transfers are moved by DMA rather than by
+//! PIO; see the `dma` module for the requirements this places on the task.
+//!
 //! See the `spi-api` crate for the protocol being implemented here.
 //!
 //! # Why is everything `spi1`
@@ -34,6 +37,13 @@ use sys_api::PinSet;
 
 use core::cell::Cell;
 
+#[cfg(feature = "dma")]
+mod dma;
+
+/// Transfers shorter than this are done by PIO even when DMA is available,
+/// as setting up the streams would cost more than it saves.
+const DMA_THRESHOLD: usize = 32;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// The `SpiServerCore` owns a particular SPI peripheral and allows us to talk
@@ -58,6 +68,8 @@ pub struct SpiServerCore {
     irq_mask: u32,
     lock_holder: &'static Cell<Option<LockState>>, // used by Idol server
     current_mux_index: &'static Cell<usize>,
+    #[cfg(feature = "dma")]
+    dma: dma::Dma,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -68,6 +80,8 @@ enum Trace {
     Tx(u8),
     Rx(u8),
     WaitISR(u32),
+    #[cfg(feature = "dma")]
+    DmaChunk(u16),
     None,
 }
 
@@ -140,12 +154,17 @@ impl SpiServerCore {
             &spi,
         );
 
+        #[cfg(feature = "dma")]
+        let dma = dma::Dma::init(&sys, &spi);
+
         Self {
             spi,
             sys,
             irq_mask,
             lock_holder,
             current_mux_index,
+            #[cfg(feature = "dma")]
+            dma,
         }
     }
 
@@ -153,6 +172,13 @@ impl SpiServerCore {
         self.lock_holder.get().map(|s| s.task)
     }
 
+    /// Returns how long the current lock may sit idle before it's taken
+    /// away, if the controller is locked to a device whose locks expire.
+    pub fn lock_timeout(&self) -> Option<u64> {
+        let lockstate = self.lock_holder.get()?;
+        CONFIG.devices[lockstate.device_index].lock_timeout
+    }
+
     pub fn closed_recv_fail(&self) {
         // Welp, someone had asked us to lock and then died. Release the lock
         self.lock_holder.set(None);
@@ -209,9 +235,9 @@ impl SpiServerCore {
 
         // If we are locked there are more rules:
         if let Some(lockstate) = &self.lock_holder.get() {
-            // The fact that we received this message _at all_ means
-            // that the sender matched our closed receive, but just
-            // in case we have a server logic bug, let's check.
+            // Our caller is responsible for only letting the lock holder
+            // through, but just in case we have a server logic bug, let's
+            // check.
             assert!(lockstate.task == sender);
             // The caller is not allowed to change the device index
             // once locked.
@@ -245,8 +271,8 @@ impl SpiServerCore {
 
     pub fn release(&self, sender: TaskId) -> Result<(), SpiError> {
         if let Some(lockstate) = &self.lock_holder.get() {
-            // The fact that we were able to receive this means we
-            // should be locked by the sender...but double check.
+            // Our caller should only let the lock holder through...but
+            // double check.
             assert!(lockstate.task == sender);
 
             let device = &CONFIG.devices[lockstate.device_index];
@@ -264,12 +290,25 @@ impl SpiServerCore {
         }
     }
 
+    /// Releases the lock no matter who holds it, deasserting CS, and returns
+    /// the task that held it. This is for use by a server that has decided
+    /// the holder is never coming back.
+    pub fn force_release(&self) -> Option<TaskId> {
+        let lockstate = self.lock_holder.take()?;
+
+        for pin in CONFIG.devices[lockstate.device_index].cs {
+            self.sys.gpio_set(*pin);
+        }
+
+        Some(lockstate.task)
+    }
+
     fn ready_writey<'b, BufRead: BufReader<'b>, BufWrite: BufWriter<'b>>(
         &self,
         op: SpiOperation,
         device_index: u8,
-        mut tx: Option<BufRead>,
-        mut rx: Option<BufWrite>,
+        tx: Option<BufRead>,
+        rx: Option<BufWrite>,
     ) -> Result<(), SpiError> {
         let device_index = usize::from(device_index);
 
@@ -345,6 +384,14 @@ impl SpiServerCore {
         // limitation, maybe. Doing so would require managing data
         // in 64kiB chunks (because the peripheral is 16-bit) and
         // using the "reload" facility on the peripheral.
+        //
+        // Transfers that are large enough to be worth it are moved by DMA, if
+        // we have it; DMA requests must be turned on before the block is.
+        let use_dma =
+            cfg!(feature = "dma") && usize::from(overall_len) >= DMA_THRESHOLD;
+        if use_dma {
+            self.spi.enable_dma();
+        }
         self.spi.enable(overall_len, device.clock_divider);
 
         // Load transfer count and start the state machine. At this
@@ -364,8 +411,11 @@ impl SpiServerCore {
         //
         // The BufReader/Writer types manage position tracking for us.
 
-        // Enable interrupt on the conditions we're interested in.
-        self.spi.enable_transfer_interrupts();
+        // Enable interrupt on the conditions we're interested in. (When
+        // using DMA, it's the DMA controller's interrupt we wait on.)
+        if !use_dma {
+            self.spi.enable_transfer_interrupts();
+        }
 
         self.spi.clear_eot();
 
@@ -377,6 +427,42 @@ impl SpiServerCore {
             }
         }
 
+        if use_dma {
+            #[cfg(feature = "dma")]
+            self.dma_exchange(overall_len, tx, rx);
+        } else {
+            self.pio_exchange(overall_len, tx, rx);
+        }
+
+        // Because we've pulled all the bytes from the RX FIFO, we should be
+        // able to observe the EOT condition here.
+        if !self.spi.check_eot() {
+            panic!();
+        }
+        self.spi.clear_eot();
+
+        // Wrap up the transfer and restore things to a reasonable
+        // state.
+        self.spi.end();
+
+        // Deassert (set) CS, if we asserted it in the first place.
+        if !cs_override {
+            for pin in device.cs {
+                self.sys.gpio_set(*pin);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves `overall_len` bytes through the FIFOs by hand, sleeping on the
+    /// SPI interrupt when there's nothing to do.
+    fn pio_exchange<'b, BufRead: BufReader<'b>, BufWrite: BufWriter<'b>>(
+        &self,
+        overall_len: u16,
+        mut tx: Option<BufRead>,
+        mut rx: Option<BufWrite>,
+    ) {
         // We use this to exert backpressure on the TX state machine as the RX
         // FIFO fills. Its initial value is the configured FIFO size, because
         // the FIFO size varies on SPI blocks on the H7; it would be nice if we
@@ -506,26 +592,73 @@ impl SpiServerCore {
                 let _ = sys_recv_closed(&mut [], self.irq_mask, TaskId::KERNEL);
             }
         }
+    }
 
-        // Because we've pulled all the bytes from the RX FIFO, we should be
-        // able to observe the EOT condition here.
-        if !self.spi.check_eot() {
-            panic!();
-        }
-        self.spi.clear_eot();
+    /// Moves `overall_len` bytes by DMA, gathering from `tx` into the bounce
+    /// buffer and scattering from the bounce buffer into `rx` a chunk at a
+    /// time.
+    #[cfg(feature = "dma")]
+    fn dma_exchange<'b, BufRead: BufReader<'b>, BufWrite: BufWriter<'b>>(
+        &self,
+        overall_len: u16,
+        mut tx: Option<BufRead>,
+        mut rx: Option<BufWrite>,
+    ) {
+        let mut remaining = usize::from(overall_len);
 
-        // Wrap up the transfer and restore things to a reasonable
-        // state.
-        self.spi.end();
+        while remaining > 0 {
+            let len = remaining.min(dma::CHUNK);
+            ringbuf_entry!(Trace::DmaChunk(len as u16));
 
-        // Deassert (set) CS, if we asserted it in the first place.
-        if !cs_override {
-            for pin in device.cs {
-                self.sys.gpio_set(*pin);
+            // Gather. As with PIO, we pad with zeroes once we run off the end
+            // of the caller's lease.
+            for b in &mut self.dma.buffers().tx[..len] {
+                *b = if let Some(txbuf) = &mut tx {
+                    if let Some(b) = txbuf.read() {
+                        b
+                    } else {
+                        tx = None;
+                        0
+                    }
+                } else {
+                    0
+                };
             }
-        }
 
-        Ok(())
+            self.dma.start(len);
+
+            loop {
+                match self.dma.poll() {
+                    dma::Status::Done => break,
+                    // The streams only fault on a bus error, which would mean
+                    // we've misconfigured them.
+                    dma::Status::Error => panic!(),
+                    dma::Status::Busy => {
+                        ringbuf_entry!(Trace::WaitISR(self.spi.read_status()));
+                        sys_irq_control(self.irq_mask, true);
+                        let _ = sys_recv_closed(
+                            &mut [],
+                            self.irq_mask,
+                            TaskId::KERNEL,
+                        );
+                    }
+                }
+            }
+
+            self.dma.stop();
+
+            // Scatter, discarding anything beyond the caller's lease.
+            if let Some(rx_writer) = &mut rx {
+                for &b in &self.dma.buffers().rx[..len] {
+                    if rx_writer.write(b).is_err() {
+                        rx = None;
+                        break;
+                    }
+                }
+            }
+
+            remaining -= len;
+        }
     }
 }
 
@@ -636,6 +769,10 @@ struct DeviceDescriptor {
     /// Clock divider to apply while speaking with this device. Yes, this says
     /// spi1 no matter which SPI block we're in charge of.
     clock_divider: device::spi1::cfg1::MBR_A,
+    /// How long a lock on this device may sit idle before a server that
+    /// arbitrates between tasks takes it away, in milliseconds; `None` if it
+    /// never should.
+    lock_timeout: Option<u64>,
 }
 
 /// Any impl of ServerConfig for Server has to pass these tests at startup.
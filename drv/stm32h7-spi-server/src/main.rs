@@ -6,6 +6,22 @@
 //!
 //! This is a thin wrapper around `stm32h7-spi-server-core`, which can be used
 //! both in this task and embedded into other tasks.
+//!
+//! What this task adds is arbitration of the controller lock between its
+//! clients. A task that locks the controller and then stalls would otherwise
+//! block everyone, so:
+//!
+//! - A lock held without being used for its device's `lock_timeout_ms` (from
+//!   the app config; 1 s unless configured otherwise) is forcibly released,
+//!   and the former holder's next request fails with `LockExpired`. Devices
+//!   configured with a timeout of zero, like FPGA configuration ports, keep
+//!   their locks for as long as the holder likes.
+//! - Jefe posts us a notification whenever a task faults; if the holder has
+//!   been restarted, we release its lock on the spot.
+//! - Requests that arrive while the controller is locked are refused with
+//!   `Busy` and the sender is queued. When the controller frees up, it's
+//!   reserved for the head of the queue (for up to `GRANT_TIMEOUT`), so that
+//!   tasks are served in the order they asked rather than by priority.
 
 #![no_std]
 #![no_main]
@@ -18,6 +34,9 @@ use userlib::*;
 
 use drv_stm32h7_spi_server_core::SpiServerCore;
 use drv_stm32xx_sys_api as sys_api;
+use heapless::Deque;
+use idol_runtime::NotificationHandler;
+use ringbuf::*;
 
 task_slot!(SYS, sys);
 
@@ -26,6 +45,25 @@ task_slot!(SYS, sys);
 // the FIFO depth; for simplicity we set:
 const BUFSIZ: usize = 16;
 
+/// How long the head of the wait queue has to come back and claim the
+/// controller once it's their turn, in milliseconds.
+const GRANT_TIMEOUT: u64 = 50;
+
+/// Number of tasks we'll queue waiting for the controller.
+const MAX_WAITERS: usize = 8;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    Locked(TaskId),
+    Queued(TaskId),
+    LockTimeout(TaskId),
+    HolderRestarted(TaskId),
+    Abandoned(TaskId),
+    None,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
 #[export_name = "main"]
 fn main() -> ! {
     let sys = sys_api::Sys::from(SYS.get_task_id());
@@ -33,68 +71,230 @@ fn main() -> ! {
         sys,
         notifications::SPI_IRQ_MASK
     );
-    let mut server = ServerImpl { core };
+    let mut server = ServerImpl {
+        core,
+        waiters: Deque::new(),
+        lock_deadline: None,
+        grant_deadline: None,
+        expired: None,
+        stats: SpiLockStats::default(),
+    };
     let mut incoming = [0u8; INCOMING_SIZE];
     loop {
-        idol_runtime::dispatch(&mut incoming, &mut server);
+        idol_runtime::dispatch_n(&mut incoming, &mut server);
     }
 }
 
 struct ServerImpl {
     core: SpiServerCore,
+
+    /// Tasks waiting for the controller, in the order they first asked
+    waiters: Deque<TaskId, MAX_WAITERS>,
+
+    /// Time at which the current lock expires unless the holder uses it
+    lock_deadline: Option<u64>,
+
+    /// Time by which the head of `waiters` must claim the controller
+    grant_deadline: Option<u64>,
+
+    /// Task whose lock most recently expired, which has yet to be told
+    expired: Option<TaskId>,
+
+    stats: SpiLockStats,
 }
 
-impl InOrderSpiImpl for ServerImpl {
-    fn recv_source(&self) -> Option<userlib::TaskId> {
-        self.core.recv_source()
+impl ServerImpl {
+    /// Performs `op` on behalf of `sender`, if it's allowed to use the
+    /// controller right now.
+    fn serve(
+        &mut self,
+        sender: TaskId,
+        op: impl FnOnce(&SpiServerCore) -> Result<(), SpiError>,
+    ) -> Result<(), RequestError<SpiError>> {
+        let r = self.admit(sender).and_then(|()| op(&self.core));
+        self.hand_off();
+        r.map_err(RequestError::from)
+    }
+
+    /// Decides whether `sender` may use the controller now, queueing it and
+    /// returning `Busy` if not.
+    fn admit(&mut self, sender: TaskId) -> Result<(), SpiError> {
+        let now = sys_get_timer().now;
+        self.expire(now);
+
+        if self.expired == Some(sender) {
+            self.expired = None;
+            return Err(SpiError::LockExpired);
+        }
+
+        if let Some(holder) = self.core.recv_source() {
+            if holder == sender {
+                self.lock_deadline = self.core.lock_timeout().map(|t| now + t);
+                return Ok(());
+            }
+        } else {
+            match self.waiters.front() {
+                None => return Ok(()),
+                Some(&head) if head == sender => {
+                    self.waiters.pop_front();
+                    self.grant_deadline = None;
+                    return Ok(());
+                }
+                Some(_) => (),
+            }
+        }
+
+        self.stats.contended = self.stats.contended.wrapping_add(1);
+
+        if !self.waiters.iter().any(|&t| t == sender) {
+            if self.waiters.push_back(sender).is_ok() {
+                ringbuf_entry!(Trace::Queued(sender));
+                let depth = self.waiters.len() as u32;
+                self.stats.max_queue_depth =
+                    self.stats.max_queue_depth.max(depth);
+            } else {
+                self.stats.queue_overflows =
+                    self.stats.queue_overflows.wrapping_add(1);
+            }
+        }
+
+        Err(SpiError::Busy)
     }
 
-    fn closed_recv_fail(&mut self) {
-        self.core.closed_recv_fail()
+    /// Called after every request: if the controller is unlocked, reserves it
+    /// for the next task in line (if any). Either way, (re)arms our timer for
+    /// whichever deadline comes first.
+    fn hand_off(&mut self) {
+        if self.core.recv_source().is_none() {
+            self.lock_deadline = None;
+
+            if self.grant_deadline.is_none() && !self.waiters.is_empty() {
+                self.grant_deadline = Some(sys_get_timer().now + GRANT_TIMEOUT);
+            }
+        }
+
+        self.arm_timer();
     }
 
+    /// Takes the lock away from an idle holder, and the controller away from
+    /// a waiter that didn't come back for it.
+    fn expire(&mut self, now: u64) {
+        if self.lock_deadline.map_or(false, |d| now >= d) {
+            if let Some(holder) = self.core.force_release() {
+                ringbuf_entry!(Trace::LockTimeout(holder));
+                self.stats.timeouts = self.stats.timeouts.wrapping_add(1);
+                self.expired = Some(holder);
+            }
+            self.lock_deadline = None;
+        }
+
+        if self.grant_deadline.map_or(false, |d| now >= d) {
+            if let Some(task) = self.waiters.pop_front() {
+                ringbuf_entry!(Trace::Abandoned(task));
+                self.stats.abandoned = self.stats.abandoned.wrapping_add(1);
+            }
+            self.grant_deadline = if self.waiters.is_empty() {
+                None
+            } else {
+                Some(now + GRANT_TIMEOUT)
+            };
+        }
+    }
+
+    /// Some task has faulted; if it was the lock holder (and Jefe has
+    /// restarted it), release its lock, and drop any restarted waiters.
+    fn check_restarted(&mut self) {
+        if let Some(holder) = self.core.recv_source() {
+            if sys_refresh_task_id(holder) != holder {
+                ringbuf_entry!(Trace::HolderRestarted(holder));
+                self.core.force_release();
+                self.stats.forced_releases =
+                    self.stats.forced_releases.wrapping_add(1);
+                self.lock_deadline = None;
+            }
+        }
+
+        let n = self.waiters.len();
+        for _ in 0..n {
+            let task = self.waiters.pop_front().unwrap_lite();
+            if sys_refresh_task_id(task) == task {
+                // Order is preserved, as we rotate through exactly once.
+                self.waiters.push_back(task).unwrap_lite();
+            }
+        }
+
+        if self.expired.map_or(false, |t| sys_refresh_task_id(t) != t) {
+            self.expired = None;
+        }
+    }
+
+    fn arm_timer(&self) {
+        let deadline = match (self.lock_deadline, self.grant_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        sys_set_timer(deadline, notifications::TIMER_MASK);
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK | notifications::TASK_FAULT_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::TASK_FAULT_MASK != 0 {
+            self.check_restarted();
+        }
+
+        self.expire(sys_get_timer().now);
+        self.hand_off();
+    }
+}
+
+impl InOrderSpiImpl for ServerImpl {
     fn read(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
         dest: LenLimit<Leased<W, [u8]>, 65535>,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core
-            .read::<LeaseBufWriter<_, BUFSIZ>>(
+        self.serve(rm.sender, |core| {
+            core.read::<LeaseBufWriter<_, BUFSIZ>>(
                 device_index,
                 dest.into_inner().into(),
             )
-            .map_err(RequestError::from)
+        })
     }
 
     fn write(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
         src: LenLimit<Leased<R, [u8]>, 65535>,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core
-            .write::<LeaseBufReader<_, BUFSIZ>>(
+        self.serve(rm.sender, |core| {
+            core.write::<LeaseBufReader<_, BUFSIZ>>(
                 device_index,
                 src.into_inner().into(),
             )
-            .map_err(RequestError::from)
+        })
     }
 
     fn exchange(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
         src: LenLimit<Leased<R, [u8]>, 65535>,
         dest: LenLimit<Leased<W, [u8]>, 65535>,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core
-            .exchange::<LeaseBufReader<_, BUFSIZ>, LeaseBufWriter<_, BUFSIZ>>(
+        self.serve(rm.sender, |core| {
+            core.exchange::<LeaseBufReader<_, BUFSIZ>, LeaseBufWriter<_, BUFSIZ>>(
                 device_index,
                 src.into_inner().into(),
                 dest.into_inner().into(),
             )
-            .map_err(RequestError::from)
+        })
     }
 
     fn lock(
@@ -103,16 +303,44 @@ impl InOrderSpiImpl for ServerImpl {
         devidx: u8,
         cs_state: CsState,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core
-            .lock(rm.sender, devidx, cs_state)
-            .map_err(RequestError::from)
+        let was_locked = self.core.recv_source().is_some();
+        self.serve(rm.sender, |core| core.lock(rm.sender, devidx, cs_state))?;
+
+        if !was_locked {
+            ringbuf_entry!(Trace::Locked(rm.sender));
+            self.stats.locks = self.stats.locks.wrapping_add(1);
+            let now = sys_get_timer().now;
+            self.lock_deadline = self.core.lock_timeout().map(|t| now + t);
+            self.arm_timer();
+        }
+
+        Ok(())
     }
 
     fn release(
         &mut self,
         rm: &RecvMessage,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core.release(rm.sender).map_err(RequestError::from)
+        self.expire(sys_get_timer().now);
+
+        let r = match self.core.recv_source() {
+            Some(holder) if holder == rm.sender => self.core.release(rm.sender),
+            _ if self.expired == Some(rm.sender) => {
+                self.expired = None;
+                Err(SpiError::LockExpired)
+            }
+            _ => Err(SpiError::NothingToRelease),
+        };
+
+        self.hand_off();
+        r.map_err(RequestError::from)
+    }
+
+    fn lock_stats(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<SpiLockStats, RequestError<SpiError>> {
+        Ok(self.stats)
     }
 }
 
@@ -25,6 +25,12 @@
 //! In host role, the SPI needs to have at least `ker_ck` running to do useful
 //! work.
 //!
+//! # DMA
+//!
+//! This driver only knows how to point the peripheral's DMA requests at its
+//! data registers (see `enable_dma`, `txdr_addr`, and `rxdr_addr`); setting
+//! up the DMA controller itself is left to the caller.
+//!
 //! # Automagic CRC generation
 //!
 //! We do not currently support the hardware's automatic CRC features.
@@ -117,6 +123,27 @@ impl Spi {
         self.reg.cr1.modify(|_, w| w.spe().set_bit());
     }
 
+    /// Enables DMA requests in both directions for the next transfer.
+    ///
+    /// This must be called before `enable`, as the DMA enables can only be
+    /// changed while the block is disabled; `end` turns them back off.
+    pub fn enable_dma(&self) {
+        self.reg
+            .cfg1
+            .modify(|_, w| w.rxdmaen().set_bit().txdmaen().set_bit());
+    }
+
+    /// Returns the address of the TX data register, for use as a DMA
+    /// destination.
+    pub fn txdr_addr(&self) -> u32 {
+        &self.reg.txdr as *const _ as u32
+    }
+
+    /// Returns the address of the RX data register, for use as a DMA source.
+    pub fn rxdr_addr(&self) -> u32 {
+        &self.reg.rxdr as *const _ as u32
+    }
+
     pub fn start(&self) {
         self.reg.cr1.modify(|_, w| w.cstart().set_bit());
         // Clear EOT flag
@@ -230,6 +257,10 @@ impl Spi {
         self.reg.ifcr.write(|w| w.txtfc().set_bit());
         // Disable the transfer state machine.
         self.reg.cr1.modify(|_, w| w.spe().clear_bit());
+        // Turn off DMA requests, if they were on; they're per-transfer.
+        self.reg
+            .cfg1
+            .modify(|_, w| w.rxdmaen().clear_bit().txdmaen().clear_bit());
         // Turn off interrupt enables.
         self.reg.ier.reset();
 
@@ -2,14 +2,43 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Map of operation names to tasks allowed to call them.
+    #[serde(default)]
+    allowed_callers: BTreeMap<String, Vec<String>>,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
 
-    idol::server::build_server_support(
+    let mut cfg =
+        build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    // The RoT's certificate chain is the device's identity, so nobody can
+    // write or lock it unless the app says so -- which it should only do
+    // for whatever provisions it.
+    for op in ["write_cert", "lock_certs"] {
+        cfg.allowed_callers.entry(op.to_string()).or_default();
+    }
+    let allowed_callers = build_util::task_ids()
+        .remap_allowed_caller_names_to_ids(&cfg.allowed_callers)?;
+
+    idol::server::build_restricted_server_support(
         "../../idl/sprot.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+        &allowed_callers,
+    )?;
+    build_util::idol::append_interface_hash(
+        "../../idl/sprot.idol",
+        "server_stub.rs",
+        build_util::idol::Role::Server,
     )?;
     Ok(())
 }
@@ -83,6 +83,13 @@ const PART2_DELAY: u64 = 2; // Observed to be at least 2ms on gimletlet
 
 const MAX_UPDATE_ATTEMPTS: u16 = 3;
 
+// Storing a certificate means erasing and programming a couple of RoT flash
+// pages; locking the chain, one.
+const TIMEOUT_WRITE_CERT: u32 = 100;
+
+// Signing a quote takes the RoT a while in software.
+const TIMEOUT_QUOTE: u32 = 100;
+
 // Time to wait for a dump
 //
 // This timeout is probably longer than it needs to be, but there is no real harm
@@ -463,6 +470,13 @@ impl<S: SpiServer> ServerImpl<S> {
 }
 
 impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
+    fn interface_hash(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u64, RequestError<SprotError>> {
+        Ok(idl::SP_ROT_INTERFACE_HASH)
+    }
+
     /// Clear the RoT Tx buffer and have the RoT deassert ROT_IRQ.
     /// The status of ROT_IRQ before and after the assert is returned.
     ///
@@ -702,12 +716,208 @@ impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
             Err(SprotError::Protocol(SprotProtocolError::UnexpectedResponse))?
         }
     }
+
+    /// Return the number of certificates in the RoT's stored chain
+    fn cert_count(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<u32, RequestError<SprotError>> {
+        let body = ReqBody::Certs(CertReq::Count);
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        if let RspBody::Certs(CertRsp::Count(count)) = rsp.body? {
+            Ok(count)
+        } else {
+            Err(SprotProtocolError::UnexpectedResponse)?
+        }
+    }
+
+    /// Return the length of a certificate in the RoT's stored chain
+    fn cert_len(
+        &mut self,
+        _: &userlib::RecvMessage,
+        index: u32,
+    ) -> Result<u32, RequestError<CertOrSprotError>> {
+        let body = ReqBody::Certs(CertReq::Len { index });
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        match rsp.body? {
+            RspBody::Certs(CertRsp::Len(len)) => Ok(len),
+            RspBody::Certs(CertRsp::Err(e)) => Err(e.into()),
+            _ => Err(SprotError::from(SprotProtocolError::UnexpectedResponse))?,
+        }
+    }
+
+    /// Read part of a certificate from the RoT's stored chain
+    fn cert(
+        &mut self,
+        _: &userlib::RecvMessage,
+        index: u32,
+        offset: u32,
+        data: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::W, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+    ) -> Result<u32, RequestError<CertOrSprotError>> {
+        let body = ReqBody::Certs(CertReq::Read { index, offset });
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        match rsp.body? {
+            RspBody::Certs(CertRsp::Read) => {
+                let len = rsp.blob.len().min(data.len());
+                data.write_range(0..len, &rsp.blob[..len]).map_err(|_| {
+                    RequestError::Fail(idol_runtime::ClientError::WentAway)
+                })?;
+                Ok(len as u32)
+            }
+            RspBody::Certs(CertRsp::Err(e)) => Err(e.into()),
+            _ => Err(SprotError::from(SprotProtocolError::UnexpectedResponse))?,
+        }
+    }
+
+    /// Write part of a certificate into the RoT's stored chain
+    fn write_cert(
+        &mut self,
+        _: &userlib::RecvMessage,
+        index: u32,
+        offset: u32,
+        len: u32,
+        data: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+    ) -> Result<(), RequestError<CertOrSprotError>> {
+        let body = ReqBody::Certs(CertReq::Write { index, offset, len });
+        let tx_size = Request::pack_with_blob(&body, &mut self.tx_buf, data)
+            .map_err(SprotError::from)?;
+
+        // The RoT accepts a chunk it has already taken again, so retrying
+        // after a lost response is safe.
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_WRITE_CERT,
+            DEFAULT_ATTEMPTS,
+        )?;
+        match rsp.body? {
+            RspBody::Certs(CertRsp::Written) => Ok(()),
+            RspBody::Certs(CertRsp::Err(e)) => Err(e.into()),
+            _ => Err(SprotError::from(SprotProtocolError::UnexpectedResponse))?,
+        }
+    }
+
+    /// Lock the RoT's stored chain against writes
+    fn lock_certs(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<(), RequestError<CertOrSprotError>> {
+        let body = ReqBody::Certs(CertReq::Lock);
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+
+        // Locking a locked chain does nothing, so this is safe to retry too.
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_WRITE_CERT,
+            DEFAULT_ATTEMPTS,
+        )?;
+        match rsp.body? {
+            RspBody::Certs(CertRsp::Locked) => Ok(()),
+            RspBody::Certs(CertRsp::Err(e)) => Err(e.into()),
+            _ => Err(SprotError::from(SprotProtocolError::UnexpectedResponse))?,
+        }
+    }
+
+    /// Extend an RoT measurement register
+    fn extend_measurement(
+        &mut self,
+        _: &userlib::RecvMessage,
+        index: u32,
+        digest: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::R, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+    ) -> Result<(), RequestError<MeasurementOrSprotError>> {
+        let body = ReqBody::Measurements(MeasurementReq::Extend { index });
+        let tx_size = Request::pack_with_blob(&body, &mut self.tx_buf, digest)
+            .map_err(SprotError::from)?;
+
+        // Extending twice isn't the same as extending once, so we can't retry
+        // if the response is lost.
+        let rsp = self.do_send_recv_retries(tx_size, TIMEOUT_QUICK, 1)?;
+        match rsp.body? {
+            RspBody::Measurements(MeasurementRsp::Extended) => Ok(()),
+            RspBody::Measurements(MeasurementRsp::Err(e)) => Err(e.into()),
+            _ => Err(SprotError::from(SprotProtocolError::UnexpectedResponse))?,
+        }
+    }
+
+    /// Return the value of an RoT measurement register
+    fn measurement(
+        &mut self,
+        _: &userlib::RecvMessage,
+        index: u32,
+    ) -> Result<[u8; 32], RequestError<MeasurementOrSprotError>> {
+        let body = ReqBody::Measurements(MeasurementReq::Read { index });
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUICK,
+            DEFAULT_ATTEMPTS,
+        )?;
+        match rsp.body? {
+            RspBody::Measurements(MeasurementRsp::Value(v)) => Ok(v),
+            RspBody::Measurements(MeasurementRsp::Err(e)) => Err(e.into()),
+            _ => Err(SprotError::from(SprotProtocolError::UnexpectedResponse))?,
+        }
+    }
+
+    /// Read a signed quote of the RoT measurement registers
+    fn quote(
+        &mut self,
+        _: &userlib::RecvMessage,
+        nonce: [u8; 32],
+        quote: idol_runtime::LenLimit<
+            idol_runtime::Leased<idol_runtime::W, [u8]>,
+            MAX_BLOB_SIZE,
+        >,
+    ) -> Result<u32, RequestError<MeasurementOrSprotError>> {
+        let body = ReqBody::Measurements(MeasurementReq::Quote { nonce });
+        let tx_size = Request::pack(&body, &mut self.tx_buf);
+        let rsp = self.do_send_recv_retries(
+            tx_size,
+            TIMEOUT_QUOTE,
+            DEFAULT_ATTEMPTS,
+        )?;
+        match rsp.body? {
+            RspBody::Measurements(MeasurementRsp::Quote) => {
+                let len = rsp.blob.len().min(quote.len());
+                quote.write_range(0..len, &rsp.blob[..len]).map_err(|_| {
+                    RequestError::Fail(idol_runtime::ClientError::WentAway)
+                })?;
+                Ok(len as u32)
+            }
+            RspBody::Measurements(MeasurementRsp::Err(e)) => Err(e.into()),
+            _ => Err(SprotError::from(SprotProtocolError::UnexpectedResponse))?,
+        }
+    }
 }
 
 mod idl {
     use super::{
-        DumpOrSprotError, PulseStatus, RotState, SlotId, SprotError,
-        SprotIoStats, SprotStatus, SwitchDuration, UpdateTarget,
+        CertOrSprotError, DumpOrSprotError, MeasurementOrSprotError,
+        PulseStatus, RotState, SlotId, SprotError, SprotIoStats, SprotStatus,
+        SwitchDuration, UpdateTarget,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
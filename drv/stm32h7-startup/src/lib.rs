@@ -323,3 +323,35 @@ pub fn system_init_custom(
     // do anything.
     p
 }
+
+/// Clocks the RTC from the 32.768 kHz LSE crystal, for boards that have one.
+///
+/// The RTC and its clock selection live in the backup domain, which survives
+/// resets (and, with a battery, power loss), so if the RTC is already running
+/// from the LSE we leave it alone rather than disturb the time it's keeping.
+///
+/// Backup domain writes are left enabled afterwards, and the RTC's register
+/// interface is clocked, since the RTC driver needs both; this lets it run
+/// at a higher priority than `sys`.
+pub fn enable_rtc_lse(p: &device::Peripherals) {
+    p.RCC.apb4enr.modify(|_, w| w.rtcapben().set_bit());
+    p.PWR.cr1.modify(|_, w| w.dbp().set_bit());
+    while !p.PWR.cr1.read().dbp().bit() {
+        // spin
+    }
+
+    let bdcr = p.RCC.bdcr.read();
+    if bdcr.rtcen().bit() && bdcr.rtcsel().is_lse() && bdcr.lserdy().bit() {
+        return;
+    }
+
+    p.RCC.bdcr.modify(|_, w| w.lseon().set_bit());
+    // The crystal can take a couple of seconds to start.
+    while !p.RCC.bdcr.read().lserdy().bit() {
+        // spin
+    }
+    // RTCSEL can only be written once per backup domain reset, but if it had
+    // been written with anything else, the RTC wouldn't be enabled either, so
+    // this is only ever the first time.
+    p.RCC.bdcr.modify(|_, w| w.rtcsel().lse().rtcen().set_bit());
+}
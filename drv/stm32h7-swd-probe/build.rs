@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskConfig {
+    swclk: Pin,
+    swdio: Pin,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pin {
+    port: String,
+    pin: u8,
+}
+
+impl Pin {
+    /// Returns the port's index, counting from A.
+    fn port_index(&self) -> Result<u8> {
+        match self.port.as_bytes() {
+            &[p @ b'A'..=b'K'] => Ok(p - b'A'),
+            _ => bail!("bad GPIO port {:?}", self.port),
+        }
+    }
+
+    /// Returns the name of the `gpiosN` block containing this pin's port,
+    /// which we map to drive it directly.
+    fn block(&self) -> Result<&'static str> {
+        Ok(match self.port_index()? {
+            0..=7 => "gpios1",
+            8..=9 => "gpios2",
+            _ => "gpios3",
+        })
+    }
+
+    fn to_code(&self) -> Result<String> {
+        if self.pin > 15 {
+            bail!("bad GPIO pin number {}", self.pin);
+        }
+        self.port_index()?;
+        Ok(format!(
+            "drv_stm32xx_sys_api::Port::{}.pin({})",
+            self.port, self.pin
+        ))
+    }
+}
+
+fn main() -> Result<()> {
+    build_util::idol::build_server_support(
+        "../../idl/swd-probe.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+
+    let task = build_util::task_full_config::<TaskConfig>()?;
+    let Some(config) = task.config else {
+        bail!("missing task config giving the SWCLK and SWDIO pins");
+    };
+    for pin in [&config.swclk, &config.swdio] {
+        let block = pin.block()?;
+        if !task.uses.iter().any(|u| u == block) {
+            bail!("uses should contain '{block}' for port {}", pin.port);
+        }
+    }
+
+    let dest_path = build_util::out_dir().join("probe_config.rs");
+    let mut out = std::fs::File::create(&dest_path)?;
+    writeln!(
+        out,
+        "const SWCLK: drv_stm32xx_sys_api::PinSet = {};
+const SWDIO: drv_stm32xx_sys_api::PinSet = {};",
+        config.swclk.to_code()?,
+        config.swdio.to_code()?,
+    )?;
+    drop(out);
+    call_rustfmt::rustfmt(&dest_path)?;
+
+    Ok(())
+}
@@ -0,0 +1,339 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A debug probe, driving another chip's SWD port from a pair of GPIOs, so
+//! that a wedged chip can be looked at, stopped, fixed up, and restarted
+//! without anyone plugging in a debugger.
+//!
+//! The pins are set up through `sys`, but a call to `sys` per clock edge
+//! would be painfully slow, so we also map the port registers and toggle the
+//! pins ourselves. We only touch BSRR, whose writes affect only the pins
+//! named in them, and IDR, which is read-only, so this can't disturb `sys`
+//! or anyone else using the port. SWDIO changes direction twice per
+//! transfer, which does go through `sys`; SWD doesn't mind the clock
+//! stopping while it does.
+//!
+//! The pins come from the task config:
+//!
+//! ```toml
+//! [tasks.swd_probe.config]
+//! swclk = { port = "G", pin = 2 }
+//! swdio = { port = "G", pin = 3 }
+//! ```
+//!
+//! with the `gpiosN` block holding their port in the task's `uses`.
+
+#![no_std]
+#![no_main]
+
+use drv_stm32xx_sys_api::{OutputType, PinSet, Pull, Speed, Sys};
+use drv_swd_probe_api::ProbeError;
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R, W};
+use ringbuf::*;
+use swd::{Swd, Wire};
+use userlib::*;
+use zerocopy::AsBytes;
+
+task_slot!(SYS, sys);
+
+include!(concat!(env!("OUT_DIR"), "/probe_config.rs"));
+
+const GPIO_BASE: usize = 0x5802_0000;
+const GPIO_PORT_STRIDE: usize = 0x400;
+const GPIO_IDR: usize = 0x10;
+const GPIO_BSRR: usize = 0x18;
+
+/// CPU cycles in each half of an SWCLK period, which makes for about 1 MHz.
+/// That's slow by debug probe standards, but leaves plenty of margin for
+/// flying leads, and moving a few KiB around is all we expect to do.
+const HALF_PERIOD: u32 = 200;
+
+/// Largest `read` or `write`, in words.
+const MAX_WORDS: usize = 256;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Attached(u32),
+    Detached,
+    Failed(swd::Error),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct GpioWire {
+    sys: Sys,
+}
+
+impl GpioWire {
+    fn port_register(pins: PinSet, offset: usize) -> usize {
+        GPIO_BASE + pins.port as usize * GPIO_PORT_STRIDE + offset
+    }
+
+    fn set(pins: PinSet, high: bool) {
+        let mask = u32::from(pins.pin_mask);
+        let bits = if high { mask } else { mask << 16 };
+        let bsrr = Self::port_register(pins, GPIO_BSRR) as *mut u32;
+        // Safety: the port is in our memory map (`build.rs` checks), and a
+        // BSRR write only changes the pins whose bits are set, which are
+        // ours.
+        unsafe { bsrr.write_volatile(bits) }
+    }
+
+    fn swdio() -> bool {
+        let idr = Self::port_register(SWDIO, GPIO_IDR) as *const u32;
+        // Safety: as above; and reading IDR has no side effects.
+        let v = unsafe { idr.read_volatile() };
+        v & u32::from(SWDIO.pin_mask) != 0
+    }
+
+    /// One SWCLK period. The target samples SWDIO on the rising edge, and
+    /// changes it just after.
+    fn cycle(sample: bool) -> bool {
+        Self::set(SWCLK, false);
+        cortex_m::asm::delay(HALF_PERIOD);
+        let bit = sample && Self::swdio();
+        Self::set(SWCLK, true);
+        cortex_m::asm::delay(HALF_PERIOD);
+        bit
+    }
+
+    /// Drives both pins, with SWCLK idling high.
+    fn enable(&self) {
+        Self::set(SWCLK, true);
+        Self::set(SWDIO, true);
+        for pins in [SWCLK, SWDIO] {
+            self.sys.gpio_configure_output(
+                pins,
+                OutputType::PushPull,
+                Speed::High,
+                Pull::None,
+            );
+        }
+    }
+
+    /// Lets go of both pins.
+    fn disable(&self) {
+        for pins in [SWCLK, SWDIO] {
+            self.sys.gpio_configure_input(pins, Pull::None);
+        }
+    }
+}
+
+impl Wire for GpioWire {
+    fn write_bits(&mut self, bits: u32, n: u8) {
+        for i in 0..n {
+            Self::set(SWDIO, bits >> i & 1 != 0);
+            Self::cycle(false);
+        }
+    }
+
+    fn read_bits(&mut self, n: u8) -> u32 {
+        let mut v = 0;
+        for i in 0..n {
+            if Self::cycle(true) {
+                v |= 1 << i;
+            }
+        }
+        v
+    }
+
+    fn release(&mut self) {
+        self.sys.gpio_configure_input(SWDIO, Pull::Up);
+        Self::cycle(false);
+    }
+
+    fn drive(&mut self) {
+        Self::cycle(false);
+        self.sys.gpio_configure_output(
+            SWDIO,
+            OutputType::PushPull,
+            Speed::High,
+            Pull::None,
+        );
+    }
+}
+
+struct ServerImpl {
+    swd: Swd<GpioWire>,
+    attached: bool,
+}
+
+impl ServerImpl {
+    /// Runs `f` against the target, if we're attached to one.
+    fn run<T>(
+        &mut self,
+        f: impl FnOnce(&mut Swd<GpioWire>) -> Result<T, swd::Error>,
+    ) -> Result<T, RequestError<ProbeError>> {
+        if !self.attached {
+            return Err(ProbeError::NotAttached.into());
+        }
+        f(&mut self.swd).map_err(|e| {
+            ringbuf_entry!(Trace::Failed(e));
+            ProbeError::from(e).into()
+        })
+    }
+}
+
+impl idl::InOrderSwdProbeImpl for ServerImpl {
+    fn attach(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<ProbeError>> {
+        self.swd.wire_mut().enable();
+        self.attached = true;
+        match self.run(|swd| swd.connect()) {
+            Ok(id) => {
+                ringbuf_entry!(Trace::Attached(id));
+                Ok(id)
+            }
+            Err(e) => {
+                self.attached = false;
+                Err(e)
+            }
+        }
+    }
+
+    fn detach(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        ringbuf_entry!(Trace::Detached);
+        self.swd.wire_mut().disable();
+        self.attached = false;
+        Ok(())
+    }
+
+    fn read_word(
+        &mut self,
+        _: &RecvMessage,
+        addr: u32,
+    ) -> Result<u32, RequestError<ProbeError>> {
+        self.run(|swd| swd.read_word(addr))
+    }
+
+    fn write_word(
+        &mut self,
+        _: &RecvMessage,
+        addr: u32,
+        value: u32,
+    ) -> Result<(), RequestError<ProbeError>> {
+        self.run(|swd| swd.write_word(addr, value))
+    }
+
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        addr: u32,
+        sink: LenLimit<Leased<W, [u8]>, 1024>,
+    ) -> Result<(), RequestError<ProbeError>> {
+        let len = sink.len();
+        if len % 4 != 0 {
+            return Err(ProbeError::Unaligned.into());
+        }
+        let mut buf = [0u32; MAX_WORDS];
+        let words = &mut buf[..len / 4];
+        self.run(|swd| swd.read_words(addr, words))?;
+        sink.write_range(0..len, words.as_bytes())
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        _: &RecvMessage,
+        addr: u32,
+        source: LenLimit<Leased<R, [u8]>, 1024>,
+    ) -> Result<(), RequestError<ProbeError>> {
+        let len = source.len();
+        if len % 4 != 0 {
+            return Err(ProbeError::Unaligned.into());
+        }
+        let mut buf = [0u32; MAX_WORDS];
+        let words = &mut buf[..len / 4];
+        source
+            .read_range(0..len, words.as_bytes_mut())
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        self.run(|swd| swd.write_words(addr, words))
+    }
+
+    fn halt(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<ProbeError>> {
+        self.run(|swd| swd.halt())
+    }
+
+    fn resume(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<ProbeError>> {
+        self.run(|swd| swd.resume())
+    }
+
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<ProbeError>> {
+        self.run(|swd| swd.dhcsr())
+    }
+
+    fn reset(
+        &mut self,
+        _: &RecvMessage,
+        halt: bool,
+    ) -> Result<(), RequestError<ProbeError>> {
+        self.run(|swd| swd.reset(halt))
+    }
+
+    fn set_vector_catch(
+        &mut self,
+        _: &RecvMessage,
+        catch: u32,
+    ) -> Result<(), RequestError<ProbeError>> {
+        self.run(|swd| swd.set_vector_catch(catch))
+    }
+
+    fn read_core_register(
+        &mut self,
+        _: &RecvMessage,
+        register: u16,
+    ) -> Result<u32, RequestError<ProbeError>> {
+        self.run(|swd| swd.read_core_register(register))
+    }
+
+    fn write_core_register(
+        &mut self,
+        _: &RecvMessage,
+        register: u16,
+        value: u32,
+    ) -> Result<(), RequestError<ProbeError>> {
+        self.run(|swd| swd.write_core_register(register, value))
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let wire = GpioWire {
+        sys: Sys::from(SYS.get_task_id()),
+    };
+    // Leave the target alone until someone asks for it.
+    wire.disable();
+
+    let mut server = ServerImpl {
+        swd: Swd::new(wire),
+        attached: false,
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_swd_probe_api::ProbeError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
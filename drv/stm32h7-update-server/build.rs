@@ -2,15 +2,92 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
 
+/// Task config for the trial an image goes through after an update, all of
+/// it optional:
+///
+/// ```toml
+/// [tasks.update_server.config]
+/// commit-after-ms = 300000
+/// trial-timeout-ms = 1800000
+/// max-boots = 3
+///
+/// [tasks.update_server.config.allowed-callers]
+/// commit_image = ["control_plane_agent"]
+/// ```
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    /// How long jefe must report the system healthy, without a break, before
+    /// we commit a new image.
+    #[serde(default = "TaskConfig::default_commit_after_ms")]
+    commit_after_ms: u64,
+    /// How long after boot we give a new image to be committed, before
+    /// giving up on it.
+    #[serde(default = "TaskConfig::default_trial_timeout_ms")]
+    trial_timeout_ms: u64,
+    /// How many times a new image can boot without being committed.
+    #[serde(default = "TaskConfig::default_max_boots")]
+    max_boots: usize,
+    /// Map of operation names to tasks allowed to call them.
+    #[serde(default)]
+    allowed_callers: BTreeMap<String, Vec<String>>,
+}
+
+impl TaskConfig {
+    fn default_commit_after_ms() -> u64 {
+        5 * 60 * 1000
+    }
+
+    fn default_trial_timeout_ms() -> u64 {
+        30 * 60 * 1000
+    }
+
+    fn default_max_boots() -> usize {
+        3
+    }
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        Self {
+            commit_after_ms: Self::default_commit_after_ms(),
+            trial_timeout_ms: Self::default_trial_timeout_ms(),
+            max_boots: Self::default_max_boots(),
+            allowed_callers: BTreeMap::new(),
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::build_notifications()?;
-    idol::server::build_server_support(
+
+    let mut config =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+
+    // Committing an image cuts its trial short, so it's up to the app who
+    // can.
+    config
+        .allowed_callers
+        .entry("commit_image".to_string())
+        .or_default();
+    let allowed_callers = build_util::task_ids()
+        .remap_allowed_caller_names_to_ids(&config.allowed_callers)?;
+
+    idol::server::build_restricted_server_support(
         "../../idl/update.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+        &allowed_callers,
+    )?;
+    build_util::idol::append_interface_hash(
+        "../../idl/update.idol",
+        "server_stub.rs",
+        build_util::idol::Role::Server,
     )?;
 
     let out = build_util::out_dir();
@@ -22,5 +99,27 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     writeln!(ver_file, "const HUBRIS_BUILD_VERSION: u32 = {};", version)?;
     writeln!(ver_file, "const HUBRIS_BUILD_EPOCH: u32 = {};", epoch)?;
 
+    // The upper limit on `max-boots` is checked in the task, where it's
+    // defined.
+    if config.max_boots == 0 {
+        return Err("max-boots can't be zero".into());
+    }
+    if config.trial_timeout_ms <= config.commit_after_ms {
+        return Err(
+            "trial-timeout-ms must be longer than commit-after-ms".into()
+        );
+    }
+    writeln!(
+        ver_file,
+        "const COMMIT_AFTER_MS: u64 = {};",
+        config.commit_after_ms
+    )?;
+    writeln!(
+        ver_file,
+        "const TRIAL_TIMEOUT_MS: u64 = {};",
+        config.trial_timeout_ms
+    )?;
+    writeln!(ver_file, "const MAX_BOOTS: usize = {};", config.max_boots)?;
+
     Ok(())
 }
@@ -5,18 +5,21 @@
 // Functions for writing to flash for updates
 //
 // This driver is intended to carry as little state as possible. Most of the
-// heavy work and decision making should be handled in other tasks.
+// heavy work and decision making should be handled in other tasks. The
+// exception is the trial a new image goes through after an update, which
+// has to outlive the task that wrote it (see `trial.rs`).
 #![no_std]
 #![no_main]
 
 use core::convert::Infallible;
 use drv_caboose::{CabooseError, CabooseReader};
+use drv_stm32h7_flash_options::FlashOptions;
 use drv_update_api::stm32h7::{
-    BLOCK_SIZE_BYTES, FLASH_WORDS_PER_BLOCK, FLASH_WORD_BYTES,
+    BLOCK_SIZE_BYTES, FLASH_WORDS_PER_BLOCK, FLASH_WORD_BYTES, TRAILER_BYTES,
 };
 use drv_update_api::{
-    ImageVersion, SlotId, SwitchDuration, UpdateError, UpdateStatus,
-    UpdateTarget,
+    ImageVersion, SlotId, SpImageState, SwitchDuration, UpdateError,
+    UpdateStatus, UpdateTarget,
 };
 use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R};
 use ringbuf::*;
@@ -32,17 +35,33 @@ const FLASH_WORD_WORDS: usize = FLASH_WORD_BYTES / 4;
 const FLASH_KEY1: u32 = 0x4567_0123;
 const FLASH_KEY2: u32 = 0xCDEF_89AB;
 
-// Keys constants are defined in RM0433 Rev 7
-// Section 4.9.3
-const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
-const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
-
 extern "C" {
     // Symbols injected by the linker.
     //
     // This requires adding `extern-regions = ["bank2"]` to the task config
     pub static mut __REGION_BANK2_BASE: [u32; 0];
     pub static mut __REGION_BANK2_END: [u32; 0];
+
+    // Likewise `extern-regions = ["bank1_trailer"]`
+    pub static mut __REGION_BANK1_TRAILER_BASE: [u32; 0];
+}
+
+mod trial;
+
+task_slot!(JEFE, jefe);
+
+/// Where the image header is, from the start of an image: at the end of the
+/// vector table, whose length is fixed in hardware.
+const HEADER_OFFSET: u32 = 0x298;
+
+/// A flash bank, as it's mapped: after a bank swap, these are the other way
+/// around physically.
+#[derive(Copy, Clone)]
+enum Bank {
+    /// The bank we're running from
+    Running,
+    /// The other bank, which updates are written to
+    Alternate,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -54,6 +73,11 @@ enum Trace {
     FinishStart,
     FinishEnd,
     WriteBlock(usize),
+    TrialBoot(u8),
+    TrialHealthy,
+    TrialCommitted,
+    TrialReverted,
+    NoFallback,
     None,
 }
 
@@ -67,36 +91,37 @@ ringbuf!(Trace, 64, Trace::None);
 
 struct ServerImpl<'a> {
     flash: &'a device::flash::RegisterBlock,
+    options: FlashOptions,
     state: UpdateState,
+    trial: trial::Trial,
 }
 
 impl<'a> ServerImpl<'a> {
+    fn regs(&self, bank: Bank) -> &'a device::flash::BANK {
+        match bank {
+            Bank::Running => self.flash.bank1(),
+            Bank::Alternate => self.flash.bank2(),
+        }
+    }
+
     // See RM0433 Rev 7 section 4.3.13
     fn swap_banks(&mut self) -> Result<(), RequestError<UpdateError>> {
         ringbuf_entry!(Trace::FinishStart);
-        if self.flash.optsr_cur().read().swap_bank_opt().bit() {
-            self.flash
-                .optsr_prg()
-                .modify(|_, w| w.swap_bank_opt().clear_bit());
-        } else {
-            self.flash
-                .optsr_prg()
-                .modify(|_, w| w.swap_bank_opt().set_bit());
-        }
-
-        self.flash.optcr().modify(|_, w| w.optstart().set_bit());
-
-        loop {
-            if !self.flash.optsr_cur().read().opt_busy().bit() {
-                break;
-            }
-        }
-
+        // Boot whichever bank we aren't running from, which is the one we've
+        // just written. This is relative to what's running rather than to
+        // what's scheduled, so that finishing twice doesn't swap back.
+        let swapped = !self.options.running_swapped();
+        self.options
+            .set_swap_on_reset(swapped)
+            .map_err(|_| UpdateError::FlashError)?;
         ringbuf_entry!(Trace::FinishEnd);
         Ok(())
     }
 
-    fn poll_flash_done(&mut self) -> Result<(), RequestError<UpdateError>> {
+    fn poll_flash_done(
+        &mut self,
+        bank: Bank,
+    ) -> Result<(), RequestError<UpdateError>> {
         // This method should implement step 5 of the Single Write Sequence from
         // RM0433 Rev 7 section 4.3.9, which states
         //
@@ -108,16 +133,16 @@ impl<'a> ServerImpl<'a> {
         // have observed this race in practice, so we omit the check that QW2
         // has been raised and only wait until QW2 is reset to 0.
         loop {
-            if !self.flash.bank2().sr.read().qw().bit() {
+            if !self.regs(bank).sr.read().qw().bit() {
                 break;
             }
         }
 
-        self.bank2_status()
+        self.bank_status(bank)
     }
 
-    fn bank2_status(&self) -> Result<(), RequestError<UpdateError>> {
-        let err = self.flash.bank2().sr.read();
+    fn bank_status(&self, bank: Bank) -> Result<(), RequestError<UpdateError>> {
+        let err = self.regs(bank).sr.read();
 
         if err.dbeccerr().bit() {
             return Err(UpdateError::EccDoubleErr.into());
@@ -187,9 +212,22 @@ impl<'a> ServerImpl<'a> {
             return Err(UpdateError::BadLength.into());
         }
 
+        let b = self.program_word(Bank::Alternate, start, words);
+        ringbuf_entry!(Trace::WriteEnd);
+        b
+    }
+
+    /// Programs the flash word at `start`, which must be in `bank` and not
+    /// have been written since it was erased.
+    fn program_word(
+        &mut self,
+        bank: Bank,
+        start: usize,
+        words: &[u32; FLASH_WORD_WORDS],
+    ) -> Result<(), RequestError<UpdateError>> {
         let addresses = (start..start + FLASH_WORD_BYTES).step_by(4);
 
-        self.flash.bank2().cr.write(|w| {
+        self.regs(bank).cr.write(|w| {
             // SAFETY
             // The `psize().bits(_)` function is marked unsafe in the stm32
             // crate because it allows arbitrary bit patterns. `0b11`
@@ -203,39 +241,26 @@ impl<'a> ServerImpl<'a> {
             // SAFETY
             // This code is running out of bank #1. The programming for bank #2
             // is completely separate so it will not affect running code.
-            // The address is bounds checked against the start and end of
-            // the bank limits.
+            // The only part of bank #1 we program is its trailer, which
+            // holds no code; reads from the bank stall while it's written.
+            // Callers bounds check the address against the bank limits.
             unsafe {
                 core::ptr::write_volatile(addr as *mut u32, word);
             }
         }
 
-        let b = self.poll_flash_done();
-        ringbuf_entry!(Trace::WriteEnd);
-        b
+        self.poll_flash_done(bank)
     }
 
     // All sequences can be found in RM0433 Rev 7
-    fn unlock(&mut self) {
-        if !self.flash.bank2().cr.read().lock().bit() {
+    fn unlock(&mut self, bank: Bank) {
+        let regs = self.regs(bank);
+        if !regs.cr.read().lock().bit() {
             return;
         }
 
-        self.flash
-            .bank2()
-            .keyr
-            .write(|w| unsafe { w.keyr().bits(FLASH_KEY1) });
-        self.flash
-            .bank2()
-            .keyr
-            .write(|w| unsafe { w.keyr().bits(FLASH_KEY2) });
-
-        self.flash
-            .optkeyr()
-            .write(|w| unsafe { w.optkeyr().bits(FLASH_OPT_KEY1) });
-        self.flash
-            .optkeyr()
-            .write(|w| unsafe { w.optkeyr().bits(FLASH_OPT_KEY2) });
+        regs.keyr.write(|w| unsafe { w.keyr().bits(FLASH_KEY1) });
+        regs.keyr.write(|w| unsafe { w.keyr().bits(FLASH_KEY2) });
     }
 
     fn bank_erase(&mut self) -> Result<(), RequestError<UpdateError>> {
@@ -279,7 +304,7 @@ impl<'a> ServerImpl<'a> {
             }
         }
 
-        let b = self.bank2_status();
+        let b = self.bank_status(Bank::Alternate);
         ringbuf_entry!(Trace::EraseEnd);
         b
     }
@@ -306,7 +331,13 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             _ => return Err(UpdateError::BadImageType.into()),
         }
 
-        self.unlock();
+        // Until the last update has settled, one way or the other, the
+        // alternate bank is what we'd fall back to.
+        if !self.trial.is_committed() {
+            return Err(UpdateError::UpdateInProgress.into());
+        }
+
+        self.unlock(Bank::Alternate);
         self.bank_erase()?;
         self.state = UpdateState::InProgress;
         Ok(())
@@ -346,6 +377,16 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             UpdateState::InProgress => (),
         }
 
+        // Images can't reach into the trailer.
+        let bank_size = unsafe {
+            __REGION_BANK2_END.as_ptr() as usize
+                - __REGION_BANK2_BASE.as_ptr() as usize
+        };
+        let end = (block_num + 1).checked_mul(BLOCK_SIZE_BYTES);
+        if end.map_or(true, |end| end > bank_size - TRAILER_BYTES) {
+            return Err(UpdateError::OutOfBounds.into());
+        }
+
         let len = block.len();
         // While our input arrives as unstructured borrowed bytes, we want to
         // ensure that we've got it aligned to 32-bits for internal reasons, and
@@ -393,6 +434,7 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             UpdateState::InProgress => (),
         }
 
+        self.mark_pending()?;
         self.swap_banks()?;
         self.state = UpdateState::Finished;
         Ok(())
@@ -439,19 +481,8 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         // If all is going according to plan, there will be a valid Hubris image
         // flashed into the other slot, delimited by `__REGION_BANK2_BASE` and
         // `__REGION_BASE2_END` (which are symbols injected by the linker).
-        //
-        // We'll first want to read the image header, which is at a fixed
-        // location at the end of the vector table.  The length of the vector
-        // table is fixed in hardware, so this should never change.
-        const HEADER_OFFSET: u32 = 0x298;
-        let header: ImageHeader = unsafe {
-            core::ptr::read_volatile(
-                (image_start + HEADER_OFFSET) as *const ImageHeader,
-            )
-        };
-        if header.magic != HEADER_MAGIC {
-            return Err(CabooseError::NoImageHeader.into());
-        }
+        let header =
+            alternate_image_header().ok_or(CabooseError::NoImageHeader)?;
 
         // Calculate where the image header implies that the image should end
         //
@@ -536,6 +567,25 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         Err(UpdateError::NotImplemented.into())
     }
 
+    fn sp_image_state(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<SpImageState, RequestError<UpdateError>> {
+        let bank = |swapped| if swapped { SlotId::B } else { SlotId::A };
+        Ok(SpImageState {
+            active: bank(self.options.running_swapped()),
+            next_boot: bank(self.options.swap_on_reset()),
+            trial: self.trial.state(sys_get_timer().now),
+        })
+    }
+
+    fn commit_image(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<UpdateError>> {
+        self.commit()
+    }
+
     fn reset(
         &mut self,
         _: &RecvMessage,
@@ -549,25 +599,59 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
     }
 }
 
+impl idol_runtime::NotificationHandler for ServerImpl<'_> {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::TIMER_MASK != 0 {
+            self.check_trial();
+        }
+    }
+}
+
+/// Returns the header of the image in the alternate bank, if there is one.
+fn alternate_image_header() -> Option<ImageHeader> {
+    let image_start = unsafe { __REGION_BANK2_BASE.as_ptr() } as u32;
+    let header: ImageHeader = unsafe {
+        core::ptr::read_volatile(
+            (image_start + HEADER_OFFSET) as *const ImageHeader,
+        )
+    };
+    if header.magic == HEADER_MAGIC {
+        Some(header)
+    } else {
+        None
+    }
+}
+
 #[export_name = "main"]
 fn main() -> ! {
     let flash = unsafe { &*device::FLASH::ptr() };
 
     let mut server = ServerImpl {
         flash,
+        // Safety: the flash controller is in our memory map, and we're the
+        // only task that changes option bytes.
+        options: unsafe { FlashOptions::new() },
         state: UpdateState::NoUpdate,
+        trial: trial::Trial::default(),
     };
+    server.start_trial();
+
     let mut incoming = [0u8; idl::INCOMING_SIZE];
 
     loop {
-        idol_runtime::dispatch(&mut incoming, &mut server);
+        idol_runtime::dispatch_n(&mut incoming, &mut server);
     }
 }
 
 include!(concat!(env!("OUT_DIR"), "/consts.rs"));
 mod idl {
     use super::{
-        CabooseError, ImageVersion, SlotId, SwitchDuration, UpdateTarget,
+        CabooseError, ImageVersion, SlotId, SpImageState, SwitchDuration,
+        UpdateTarget,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
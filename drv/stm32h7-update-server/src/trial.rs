@@ -0,0 +1,213 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The trial a new image goes through after an update.
+//!
+//! Before swapping banks at the end of an update, we write a pending record
+//! to the trailer of the bank we've just written, so the new image boots
+//! knowing it's on trial (see `drv_update_api::stm32h7::TRAILER_BYTES` for
+//! the format). Each time it boots, we record that in its trailer; once it
+//! has used up its boots without being committed, we switch back to the
+//! other bank and reset. While it runs, we commit it once jefe has reported
+//! the SP ready for `COMMIT_AFTER_MS` without a break. If that hasn't
+//! happened `TRIAL_TIMEOUT_MS` after boot, we switch the boot preference
+//! back to the other bank, and leave the reset to whoever wants one.
+//!
+//! An image that can't get as far as running this task can't revert itself,
+//! of course; that takes a debugger.
+
+use crate::{
+    alternate_image_header, notifications, Bank, ServerImpl, Trace,
+    COMMIT_AFTER_MS, FLASH_WORD_WORDS, JEFE, MAX_BOOTS, TRIAL_TIMEOUT_MS,
+    __REGION_BANK1_TRAILER_BASE, __REGION_BANK2_END,
+};
+use drv_update_api::stm32h7::{
+    FLASH_WORD_BYTES, MAX_TRIAL_BOOTS, TRAILER_BOOT, TRAILER_BYTES,
+    TRAILER_COMMITTED, TRAILER_FIRST_BOOT, TRAILER_PENDING,
+};
+use drv_update_api::{SpTrial, UpdateError};
+use idol_runtime::RequestError;
+use ringbuf::*;
+use task_jefe_api::Jefe;
+use userlib::*;
+
+const _: () = assert!(MAX_BOOTS <= MAX_TRIAL_BOOTS);
+
+/// How often we ask jefe how we're doing, while on trial.
+const CHECK_INTERVAL_MS: u64 = 1000;
+
+/// Trailer words, by index.
+const PENDING: usize = 0;
+const COMMITTED: usize = 1;
+
+#[derive(Default)]
+pub(crate) struct Trial {
+    /// Times the running image has booted while on trial, or `None` if it
+    /// isn't on trial.
+    boots: Option<u8>,
+    /// Whether we've given up on the running image.
+    reverted: bool,
+    /// When jefe started reporting the SP ready, this time around.
+    ready_since: Option<u64>,
+}
+
+impl Trial {
+    pub(crate) fn is_committed(&self) -> bool {
+        self.boots.is_none()
+    }
+
+    pub(crate) fn state(&self, now: u64) -> SpTrial {
+        match self.boots {
+            None => SpTrial::Committed,
+            Some(_) if self.reverted => SpTrial::Reverted,
+            Some(boots) => SpTrial::Pending {
+                boots,
+                healthy_ms: self.ready_since.map_or(0, |t| now - t),
+            },
+        }
+    }
+}
+
+/// Returns the address of trailer word `n` of `bank`.
+fn trailer_word(bank: Bank, n: usize) -> usize {
+    // SAFETY: these are symbols populated by the linker.
+    let trailer = match bank {
+        Bank::Running => unsafe {
+            __REGION_BANK1_TRAILER_BASE.as_ptr() as usize
+        },
+        Bank::Alternate => unsafe {
+            __REGION_BANK2_END.as_ptr() as usize - TRAILER_BYTES
+        },
+    };
+    trailer + n * FLASH_WORD_BYTES
+}
+
+/// Returns the magic number of trailer word `n` of `bank`.
+fn read_record(bank: Bank, n: usize) -> u32 {
+    // SAFETY: the trailers are in our memory map, and the word is in range
+    // by construction.
+    unsafe { core::ptr::read_volatile(trailer_word(bank, n) as *const u32) }
+}
+
+impl ServerImpl<'_> {
+    fn write_record(
+        &mut self,
+        bank: Bank,
+        n: usize,
+        magic: u32,
+    ) -> Result<(), RequestError<UpdateError>> {
+        let mut words = [0; FLASH_WORD_WORDS];
+        words[0] = magic;
+        self.unlock(bank);
+        self.program_word(bank, trailer_word(bank, n), &words)
+    }
+
+    /// Marks the image we've just written to the alternate bank as on trial.
+    pub(crate) fn mark_pending(
+        &mut self,
+    ) -> Result<(), RequestError<UpdateError>> {
+        self.write_record(Bank::Alternate, PENDING, TRAILER_PENDING)
+    }
+
+    /// Works out, at boot, whether the running image is on trial, and counts
+    /// this boot against it if so.
+    pub(crate) fn start_trial(&mut self) {
+        if read_record(Bank::Running, PENDING) != TRAILER_PENDING
+            || read_record(Bank::Running, COMMITTED) == TRAILER_COMMITTED
+        {
+            return;
+        }
+
+        let boots = (TRAILER_FIRST_BOOT..TRAILER_FIRST_BOOT + MAX_TRIAL_BOOTS)
+            .take_while(|&n| read_record(Bank::Running, n) == TRAILER_BOOT)
+            .count();
+        self.trial.boots = Some(boots as u8);
+
+        if boots >= MAX_BOOTS {
+            // It's had its chances.
+            self.revert();
+            if self.trial.reverted {
+                Jefe::from(JEFE.get_task_id()).request_reset();
+                panic!();
+            }
+            // With nothing to fall back to, carry on as best we can.
+        } else {
+            // If we can't record this boot, it just doesn't count against
+            // the image; the timeout still applies.
+            let n = TRAILER_FIRST_BOOT + boots;
+            if self.write_record(Bank::Running, n, TRAILER_BOOT).is_ok() {
+                self.trial.boots = Some(boots as u8 + 1);
+            }
+        }
+        ringbuf_entry!(Trace::TrialBoot(self.trial.boots.unwrap_or(0)));
+
+        sys_set_timer(
+            Some(sys_get_timer().now + CHECK_INTERVAL_MS),
+            notifications::TIMER_MASK,
+        );
+    }
+
+    /// Checks how the trial is going, committing or reverting the running
+    /// image if it's time to.
+    pub(crate) fn check_trial(&mut self) {
+        if self.trial.is_committed() || self.trial.reverted {
+            return;
+        }
+
+        let now = sys_get_timer().now;
+        if Jefe::from(JEFE.get_task_id()).get_readiness() == 0 {
+            let since = *self.trial.ready_since.get_or_insert(now);
+            if now - since >= COMMIT_AFTER_MS {
+                ringbuf_entry!(Trace::TrialHealthy);
+                if self.commit().is_ok() {
+                    return;
+                }
+            }
+        } else {
+            self.trial.ready_since = None;
+        }
+
+        if now >= TRIAL_TIMEOUT_MS {
+            self.revert();
+            if self.trial.reverted {
+                return;
+            }
+        }
+
+        sys_set_timer(Some(now + CHECK_INTERVAL_MS), notifications::TIMER_MASK);
+    }
+
+    /// Commits the running image, which also cancels a revert.
+    pub(crate) fn commit(&mut self) -> Result<(), RequestError<UpdateError>> {
+        if self.trial.is_committed() {
+            return Ok(());
+        }
+        if self.trial.reverted {
+            let running = self.options.running_swapped();
+            self.options
+                .set_swap_on_reset(running)
+                .map_err(|_| UpdateError::FlashError)?;
+            self.trial.reverted = false;
+        }
+
+        self.write_record(Bank::Running, COMMITTED, TRAILER_COMMITTED)?;
+        self.trial.boots = None;
+        self.trial.ready_since = None;
+        ringbuf_entry!(Trace::TrialCommitted);
+        Ok(())
+    }
+
+    /// Has the alternate bank boot next, if there's an image in it.
+    fn revert(&mut self) {
+        if alternate_image_header().is_none() {
+            ringbuf_entry!(Trace::NoFallback);
+            return;
+        }
+        let other = !self.options.running_swapped();
+        if self.options.set_swap_on_reset(other).is_ok() {
+            ringbuf_entry!(Trace::TrialReverted);
+            self.trial.reverted = true;
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Server for an STM32H7 U(S)ART.
+//!
+//! Received bytes go straight into a DMA ring buffer (see
+//! `drv_stm32h7_usart::dma`), so we only wake when the line goes idle, the
+//! ring fills halfway, or something goes wrong on the line, rather than once
+//! per byte. Clients `read` whatever has accumulated and `write` into a
+//! transmit queue that we drain from the TX FIFO interrupt. A client that
+//! `subscribe`s is notified when either side has made progress, so it need
+//! not poll.
+//!
+//! Breaks from the other end show up as a framing error, which we count as a
+//! break event; the 0x00 character that comes with it is left in the data.
+
+#![no_std]
+#![no_main]
+
+use drv_stm32h7_usart::dma::{self, DmaRx};
+use drv_stm32h7_usart::drv_stm32xx_sys_api::Sys;
+use drv_stm32h7_usart::Usart;
+use drv_uart_api::{UartError, UartEvents};
+use heapless::Deque;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+/// DMA1 stream used for reception; streams 0 and 1 belong to the SPI server.
+const RX_STREAM: usize = 2;
+
+const TX_QUEUE: usize = 256;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Break,
+    HwOverrun,
+    Lost(usize),
+    DmaError,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct ServerImpl {
+    usart: Usart,
+    rx: DmaRx,
+    tx: Deque<u8, TX_QUEUE>,
+    subscriber: Option<(TaskId, u32)>,
+    events: UartEvents,
+}
+
+impl ServerImpl {
+    fn notify(&self) {
+        if let Some((task, bits)) = self.subscriber {
+            sys_post(sys_refresh_task_id(task), bits);
+        }
+    }
+
+    /// Moves as much of the transmit queue into the FIFO as fits, returning
+    /// true if anything moved.
+    fn pump_tx(&mut self) -> bool {
+        let mut moved = false;
+        while let Some(&b) = self.tx.front() {
+            if !self.usart.try_tx_push(b) {
+                break;
+            }
+            self.tx.pop_front();
+            moved = true;
+        }
+        if self.tx.is_empty() {
+            self.usart.disable_tx_fifo_empty_interrupt();
+        } else {
+            self.usart.enable_tx_fifo_empty_interrupt();
+        }
+        moved
+    }
+}
+
+impl idl::InOrderUartImpl for ServerImpl {
+    fn write(
+        &mut self,
+        _: &RecvMessage,
+        data: LenLimit<Leased<R, [u8]>, 256>,
+    ) -> Result<u32, RequestError<UartError>> {
+        let n = usize::min(data.len(), self.tx.capacity() - self.tx.len());
+        if n == 0 {
+            return Err(UartError::TxFull.into());
+        }
+        let mut buf = [0; TX_QUEUE];
+        data.read_range(0..n, &mut buf[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        for &b in &buf[..n] {
+            // Can't fail, we checked for space above.
+            let _ = self.tx.push_back(b);
+        }
+        self.pump_tx();
+        Ok(n as u32)
+    }
+
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        data: LenLimit<Leased<W, [u8]>, 256>,
+    ) -> Result<u32, RequestError<UartError>> {
+        // Pick up anything that's arrived since the last interrupt.
+        self.note_rx();
+
+        let mut buf = [0; 256];
+        let n = self.rx.read(&mut buf[..data.len()]);
+        data.write_range(0..n, &buf[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(n as u32)
+    }
+
+    fn subscribe(
+        &mut self,
+        msg: &RecvMessage,
+        notify: u32,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.subscriber = Some((msg.sender, notify));
+        Ok(())
+    }
+
+    fn events(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<UartEvents, RequestError<core::convert::Infallible>> {
+        Ok(self.events)
+    }
+
+    fn send_break(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        self.usart.send_break();
+        Ok(())
+    }
+}
+
+impl ServerImpl {
+    /// Polls the receive ring, accounting for any loss. Returns true if
+    /// anything arrived.
+    fn note_rx(&mut self) -> bool {
+        let poll = self.rx.poll();
+        if poll.lost != 0 {
+            ringbuf_entry!(Trace::Lost(poll.lost));
+            self.events.bytes_lost =
+                self.events.bytes_lost.wrapping_add(poll.lost as u32);
+        }
+        if poll.dma_error {
+            ringbuf_entry!(Trace::DmaError);
+            self.events.dma_errors = self.events.dma_errors.wrapping_add(1);
+        }
+        poll.new != 0
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::USART_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        let mut progress = false;
+
+        let errors = self.usart.check_and_clear_rx_errors();
+        if errors.framing {
+            ringbuf_entry!(Trace::Break);
+            self.events.breaks = self.events.breaks.wrapping_add(1);
+        }
+        if errors.overrun {
+            ringbuf_entry!(Trace::HwOverrun);
+            self.events.hw_overruns = self.events.hw_overruns.wrapping_add(1);
+        }
+        if errors.noise {
+            self.events.noise_errors = self.events.noise_errors.wrapping_add(1);
+        }
+        if errors.parity {
+            self.events.parity_errors =
+                self.events.parity_errors.wrapping_add(1);
+        }
+        progress |= errors.any();
+
+        self.usart.check_and_clear_idle();
+        progress |= self.note_rx();
+        progress |= self.pump_tx();
+
+        if progress {
+            self.notify();
+        }
+        sys_irq_control(notifications::USART_IRQ_MASK, true);
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    let (usart, request) = configure_uart_device(&sys);
+    let rx = DmaRx::start(&sys, &usart, RX_STREAM, request);
+    usart.enable_idle_interrupt();
+
+    sys_irq_control(notifications::USART_IRQ_MASK, true);
+
+    let mut server = ServerImpl {
+        usart,
+        rx,
+        tx: Deque::new(),
+        subscriber: None,
+        events: UartEvents::default(),
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+fn configure_uart_device(sys: &Sys) -> (Usart, u32) {
+    use drv_stm32h7_usart::device;
+    use drv_stm32h7_usart::drv_stm32xx_sys_api::*;
+
+    // TODO: this module should _not_ know our clock rate. That's a hack.
+    const CLOCK_HZ: u32 = 100_000_000;
+
+    #[cfg(feature = "baud_rate_115_200")]
+    const BAUD_RATE: u32 = 115_200;
+    #[cfg(feature = "baud_rate_3M")]
+    const BAUD_RATE: u32 = 3_000_000;
+
+    let hardware_flow_control = cfg!(feature = "hardware_flow_control");
+
+    let usart;
+    let peripheral;
+    let pins;
+    let request;
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "usart1")] {
+            const PINS: &[(PinSet, Alternate)] = {
+                if cfg!(feature = "hardware_flow_control") {
+                    // NOTE: These pins are for gimletlet, not gimlet!
+                    &[
+                        // TX, RX
+                        (Port::B.pin(6).and_pin(7), Alternate::AF7),
+                        // CTS, RTS
+                        (Port::A.pin(11).and_pin(12), Alternate::AF7),
+                    ]
+                } else {
+                    &[(Port::B.pin(6).and_pin(7), Alternate::AF7)]
+                }
+            };
+            usart = unsafe { &*device::USART1::ptr() };
+            peripheral = Peripheral::Usart1;
+            pins = PINS;
+            request = dma::request::USART1_RX;
+        } else if #[cfg(feature = "usart2")] {
+            const PINS: &[(PinSet, Alternate)] = {
+                if cfg!(feature = "hardware_flow_control") {
+                    &[(
+                        Port::D.pin(3).and_pin(4).and_pin(5).and_pin(6),
+                        Alternate::AF7
+                    )]
+                } else {
+                    &[(Port::D.pin(5).and_pin(6), Alternate::AF7)]
+                }
+            };
+            usart = unsafe { &*device::USART2::ptr() };
+            peripheral = Peripheral::Usart2;
+            pins = PINS;
+            request = dma::request::USART2_RX;
+        } else if #[cfg(feature = "usart3")] {
+            const PINS: &[(PinSet, Alternate)] = {
+                if cfg!(feature = "hardware_flow_control") {
+                    &[(
+                        Port::D.pin(8).and_pin(9).and_pin(11).and_pin(12),
+                        Alternate::AF7
+                    )]
+                } else {
+                    // These are the ST-LINK virtual COM port on Nucleo boards.
+                    &[(Port::D.pin(8).and_pin(9), Alternate::AF7)]
+                }
+            };
+            usart = unsafe { &*device::USART3::ptr() };
+            peripheral = Peripheral::Usart3;
+            pins = PINS;
+            request = dma::request::USART3_RX;
+        } else if #[cfg(feature = "uart7")] {
+            const PINS: &[(PinSet, Alternate)] = {
+                if cfg!(feature = "hardware_flow_control") {
+                    &[(
+                        Port::E.pin(7).and_pin(8).and_pin(9).and_pin(10),
+                        Alternate::AF7
+                    )]
+                } else {
+                    &[(Port::E.pin(7).and_pin(8), Alternate::AF7)]
+                }
+            };
+            usart = unsafe { &*device::UART7::ptr() };
+            peripheral = Peripheral::Uart7;
+            pins = PINS;
+            request = dma::request::UART7_RX;
+        } else {
+            compile_error!("no usartX/uartX feature specified");
+        }
+    }
+
+    let usart = Usart::turn_on(
+        sys,
+        usart,
+        peripheral,
+        pins,
+        CLOCK_HZ,
+        BAUD_RATE,
+        hardware_flow_control,
+    );
+    (usart, request)
+}
+
+mod idl {
+    use drv_uart_api::{UartError, UartEvents};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,217 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! DMA ring-buffer reception, enabled by the `dma` feature.
+//!
+//! Rather than taking an interrupt per byte, we point a DMA1 stream in
+//! circular mode at the USART's receive register and let it fill a ring
+//! buffer on its own. The caller only has to wake up to drain the ring: on
+//! the USART's idle-line interrupt (the sender paused) and on the stream's
+//! half and full transfer interrupts (the sender didn't). As long as the
+//! ring is drained at least once per half-lap, nothing is lost.
+//!
+//! The ring lives in the `.usart_dma` section, which the app must place in
+//! a region DMA1 can reach (e.g. `sections = {usart_dma = "sram1"}`); task
+//! RAM is in DTCM, which it can't. The caller picks the stream so that it
+//! can stay clear of other DMA1 users, such as the SPI server's streams 0
+//! and 1, and must route that stream's interrupt to the same notification
+//! as the USART's.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use drv_stm32xx_sys_api::{Peripheral, Sys};
+use mutable_statics::mutable_statics;
+
+use crate::{device, Usart};
+
+/// Size of the receive ring.
+pub const RING_SIZE: usize = 1024;
+
+// Stream configuration register (DMA_SxCR) bits.
+const CR_EN: u32 = 1 << 0;
+const CR_TEIE: u32 = 1 << 2;
+const CR_HTIE: u32 = 1 << 3;
+const CR_TCIE: u32 = 1 << 4;
+const CR_CIRC: u32 = 1 << 8;
+const CR_MINC: u32 = 1 << 10;
+
+// Per-stream bits in xISR/xIFCR, before shifting into place for a stream.
+const FEIF: u32 = 1 << 0;
+const DMEIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+const HTIF: u32 = 1 << 4;
+const TCIF: u32 = 1 << 5;
+const ALL_FLAGS: u32 = FEIF | DMEIF | TEIF | HTIF | TCIF;
+
+/// Shifts per-stream interrupt flags into place; streams 0-3 use LISR/LIFCR
+/// and 4-7 use HISR/HIFCR, at the same offsets.
+const fn flags(stream: usize, bits: u32) -> u32 {
+    const SHIFT: [u32; 4] = [0, 6, 16, 22];
+    bits << SHIFT[stream % 4]
+}
+
+/// DMAMUX1 request lines for each USART's receiver, from RM0433 table 121.
+pub mod request {
+    pub const USART1_RX: u32 = 41;
+    pub const USART2_RX: u32 = 43;
+    pub const USART3_RX: u32 = 45;
+    pub const UART4_RX: u32 = 63;
+    pub const UART5_RX: u32 = 65;
+    pub const USART6_RX: u32 = 71;
+    pub const UART7_RX: u32 = 79;
+    pub const UART8_RX: u32 = 81;
+}
+
+/// What `DmaRx::poll` found.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RxPoll {
+    /// Bytes that arrived since the last poll.
+    pub new: usize,
+    /// Unread bytes that were overwritten because the ring wasn't drained in
+    /// time.
+    pub lost: usize,
+    /// The stream stopped on a bus error and had to be restarted.
+    pub dma_error: bool,
+}
+
+pub struct DmaRx {
+    dma: &'static device::dma1::RegisterBlock,
+    stream: usize,
+    ring: &'static mut [u8; RING_SIZE],
+    /// Index in `ring` of the next byte to hand out.
+    read: usize,
+    /// Index in `ring` the DMA had reached at the last poll.
+    write: usize,
+    /// Bytes between `read` and `write`.
+    pending: usize,
+}
+
+impl DmaRx {
+    /// Claims the ring and starts DMA1 `stream` moving bytes from `usart`
+    /// into it. `request` is the USART's DMAMUX1 receive request, one of the
+    /// constants in `request`. Can only be called once.
+    pub fn start(
+        sys: &Sys,
+        usart: &Usart,
+        stream: usize,
+        request: u32,
+    ) -> Self {
+        let ring = mutable_statics! {
+            #[link_section = ".usart_dma"]
+            static mut RING: [u8; RING_SIZE] = [|| 0; _];
+        };
+
+        // Other tasks may have streams running on DMA1, so we must not reset
+        // it.
+        sys.enable_clock(Peripheral::Dma1);
+
+        let dma = unsafe { &*device::DMA1::ptr() };
+        let mux = unsafe { &*device::DMAMUX1::ptr() };
+
+        // DMAMUX1 channels 0-7 feed DMA1 streams 0-7.
+        mux.ccr[stream].write(|w| unsafe { w.bits(request) });
+
+        let st = &dma.st[stream];
+        st.par.write(|w| unsafe { w.bits(usart.rdr_addr()) });
+        st.m0ar.write(|w| unsafe { w.bits(ring.as_ptr() as u32) });
+
+        let this = Self {
+            dma,
+            stream,
+            ring,
+            read: 0,
+            write: 0,
+            pending: 0,
+        };
+        this.enable();
+        usart.enable_dma_rx();
+        this
+    }
+
+    fn enable(&self) {
+        self.clear_flags();
+        let st = &self.dma.st[self.stream];
+        st.ndtr.write(|w| unsafe { w.bits(RING_SIZE as u32) });
+        st.cr.write(|w| unsafe {
+            w.bits(CR_MINC | CR_CIRC | CR_HTIE | CR_TCIE | CR_TEIE | CR_EN)
+        });
+    }
+
+    fn isr(&self) -> u32 {
+        if self.stream < 4 {
+            self.dma.lisr.read().bits()
+        } else {
+            self.dma.hisr.read().bits()
+        }
+    }
+
+    fn clear_flags(&self) {
+        let bits = flags(self.stream, ALL_FLAGS);
+        if self.stream < 4 {
+            self.dma.lifcr.write(|w| unsafe { w.bits(bits) });
+        } else {
+            self.dma.hifcr.write(|w| unsafe { w.bits(bits) });
+        }
+    }
+
+    /// Works out how much has arrived since the last poll. Call this on
+    /// every USART or stream interrupt.
+    pub fn poll(&mut self) -> RxPoll {
+        let mut result = RxPoll::default();
+
+        let isr = self.isr();
+        self.clear_flags();
+
+        let ndtr = self.dma.st[self.stream].ndtr.read().bits() as usize;
+        let write = (RING_SIZE - ndtr) % RING_SIZE;
+        let mut new = (write + RING_SIZE - self.write) % RING_SIZE;
+        // If the positions match but the stream has wrapped since we last
+        // looked, a whole lap arrived (we can't tell if it was more).
+        if new == 0 && isr & flags(self.stream, TCIF | HTIF) != 0 {
+            new = RING_SIZE;
+        }
+        self.write = write;
+        result.new = new;
+
+        self.pending += new;
+        if self.pending > RING_SIZE {
+            // The oldest data has been overwritten; what's left starts where
+            // the DMA is about to write.
+            result.lost = self.pending - RING_SIZE;
+            self.pending = RING_SIZE;
+            self.read = write;
+        }
+
+        if isr & flags(self.stream, TEIF) != 0 {
+            // The hardware disables the stream on a transfer error. Start
+            // over with an empty ring.
+            result.dma_error = true;
+            result.lost += self.pending;
+            self.read = 0;
+            self.write = 0;
+            self.pending = 0;
+            self.enable();
+        }
+
+        // Don't let reads of the ring get hoisted above this point.
+        compiler_fence(Ordering::Acquire);
+        result
+    }
+
+    /// Number of bytes waiting to be read, as of the last poll.
+    pub fn available(&self) -> usize {
+        self.pending
+    }
+
+    /// Copies out as many waiting bytes as fit in `out`, returning how many.
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let n = usize::min(out.len(), self.pending);
+        let first = usize::min(n, RING_SIZE - self.read);
+        out[..first].copy_from_slice(&self.ring[self.read..self.read + first]);
+        out[first..n].copy_from_slice(&self.ring[..n - first]);
+        self.read = (self.read + n) % RING_SIZE;
+        self.pending -= n;
+        n
+    }
+}
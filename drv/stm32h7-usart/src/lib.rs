@@ -12,6 +12,9 @@
 
 #![no_std]
 
+#[cfg(feature = "dma")]
+pub mod dma;
+
 pub use drv_stm32xx_sys_api;
 
 #[cfg(feature = "h743")]
@@ -149,8 +152,78 @@ impl Usart {
         self.usart.cr3.modify(|_, w| w.txftie().clear_bit());
     }
 
+    /// Address of the receive data register, for pointing DMA at.
+    pub fn rdr_addr(&self) -> u32 {
+        self.usart.rdr.as_ptr() as u32
+    }
+
+    /// Hands received bytes to DMA rather than the RX interrupt. Error
+    /// conditions (framing, noise, overrun) still raise the USART interrupt;
+    /// see `check_and_clear_rx_errors`.
+    pub fn enable_dma_rx(&self) {
+        self.disable_rx_interrupt();
+        self.usart
+            .cr3
+            .modify(|_, w| w.dmar().set_bit().eie().set_bit());
+    }
+
+    /// Interrupt when the line goes idle for a character time after
+    /// receiving, so a DMA reader learns about short messages promptly.
+    pub fn enable_idle_interrupt(&self) {
+        self.usart.cr1.modify(|_, w| w.idleie().set_bit());
+    }
+
+    pub fn check_and_clear_idle(&self) -> bool {
+        if self.usart.isr.read().idle().bit() {
+            self.usart.icr.write(|w| w.idlecf().set_bit());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Checks for and clears every receive error condition at once.
+    pub fn check_and_clear_rx_errors(&self) -> RxErrors {
+        let isr = self.usart.isr.read();
+        let errors = RxErrors {
+            overrun: isr.ore().bit(),
+            framing: isr.fe().bit(),
+            noise: isr.ne().bit(),
+            parity: isr.pe().bit(),
+        };
+        self.usart.icr.write(|w| {
+            w.orecf()
+                .set_bit()
+                .fecf()
+                .set_bit()
+                .ncf()
+                .set_bit()
+                .pecf()
+                .set_bit()
+        });
+        errors
+    }
+
     pub fn send_break(&self) {
         self.usart.rqr.write(|w| w.sbkrq().set_bit());
         // TODO: should we wait for the flag (SBKF) to clear?
     }
 }
+
+/// Receive error conditions reported by `Usart::check_and_clear_rx_errors`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RxErrors {
+    pub overrun: bool,
+    /// A stop bit was missing. At a matching baud rate this almost always
+    /// means the other end is sending a break, which arrives as a single 0x00
+    /// byte with this set.
+    pub framing: bool,
+    pub noise: bool,
+    pub parity: bool,
+}
+
+impl RxErrors {
+    pub fn any(&self) -> bool {
+        self.overrun || self.framing || self.noise || self.parity
+    }
+}
@@ -0,0 +1,98 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// Pin toggled to pet the watchdog.
+    wdi: Pin,
+    /// Pin enabling the watchdog, if it has one.
+    enable: Option<ActivePin>,
+    /// Pin driven with jefe's idea of whether the SP is ready, if any.
+    ready: Option<ActivePin>,
+    /// The watchdog's timeout, from its datasheet (or its strapping).
+    timeout_ms: u32,
+    /// How often to pet it, out of reset.
+    period_ms: u32,
+    /// Longest holdoff we'll grant.
+    max_holdoff_ms: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pin {
+    port: String,
+    pin: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ActivePin {
+    port: String,
+    pin: u8,
+    active_high: bool,
+}
+
+fn pinset(port: &str, pin: u8) -> Result<String> {
+    if port.len() != 1 || !matches!(port.as_bytes()[0], b'A'..=b'K') {
+        bail!("bad GPIO port {port:?}");
+    }
+    if pin > 15 {
+        bail!("bad GPIO pin number {pin}");
+    }
+    Ok(format!("drv_stm32xx_sys_api::Port::{port}.pin({pin})"))
+}
+
+fn main() -> Result<()> {
+    build_util::build_notifications()?;
+    build_util::idol::build_server_support(
+        "../../idl/ext-watchdog.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+
+    let config = build_util::task_config::<TaskConfig>()?;
+    if config.period_ms == 0 || config.period_ms > config.timeout_ms / 2 {
+        bail!("period-ms must be nonzero, and at most half of timeout-ms");
+    }
+
+    let active_pin = |p: &Option<ActivePin>| -> Result<String> {
+        Ok(match p {
+            Some(p) => format!(
+                "Some(ActivePin {{ pins: {}, active_high: {} }})",
+                pinset(&p.port, p.pin)?,
+                p.active_high
+            ),
+            None => "None".to_string(),
+        })
+    };
+    let enable = active_pin(&config.enable)?;
+    let ready = active_pin(&config.ready)?;
+
+    let dest_path = build_util::out_dir().join("watchdog_config.rs");
+    let mut out = std::fs::File::create(&dest_path)?;
+    writeln!(
+        out,
+        "const CONFIG: Config = Config {{
+            wdi: {},
+            enable: {enable},
+            ready: {ready},
+            timeout_ms: {},
+            period_ms: {},
+            max_holdoff_ms: {},
+        }};",
+        pinset(&config.wdi.port, config.wdi.pin)?,
+        config.timeout_ms,
+        config.period_ms,
+        config.max_holdoff_ms,
+    )?;
+    drop(out);
+    call_rustfmt::rustfmt(&dest_path)?;
+
+    Ok(())
+}
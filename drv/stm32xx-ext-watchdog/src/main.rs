@@ -0,0 +1,284 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Driver for an external watchdog or supervisor IC, petted by toggling a
+//! GPIO -- which can just as well be an input to an FPGA playing the part.
+//!
+//! We pet the watchdog every period, but only after asking jefe whether the
+//! system is healthy. If it isn't, we let the watchdog starve and reset
+//! everything, which is the point of having one. Our own death has the same
+//! effect, unless jefe gets us restarted inside the timeout.
+//!
+//! A holdoff keeps the watchdog quiet for a while regardless of health, for
+//! jobs that are expected to upset things. If the IC has an enable pin, it's
+//! turned off for the duration; if not, we keep petting.
+//!
+//! Each healthy pet is reported to jefe, which counts a starved watchdog
+//! against the SP's readiness. If there's a `ready` pin (say, an input to
+//! the sequencer FPGA or the RoT), we drive it from jefe's readiness after
+//! each check, so that they can see whether the SP is ready rather than
+//! guess.
+//!
+//! The pins and timing come from the task config:
+//!
+//! ```toml
+//! [tasks.watchdog.config]
+//! wdi = { port = "G", pin = 4 }
+//! enable = { port = "G", pin = 5, active-high = true }
+//! ready = { port = "G", pin = 6, active-high = true }
+//! timeout-ms = 1600
+//! period-ms = 400
+//! max-holdoff-ms = 600_000
+//! ```
+
+#![no_std]
+#![no_main]
+
+use drv_ext_watchdog_api::{WatchdogError, WatchdogStatus};
+use drv_stm32xx_sys_api::{OutputType, PinSet, Pull, Speed, Sys};
+use idol_runtime::{NotificationHandler, RequestError};
+use ringbuf::*;
+use task_jefe_api::Jefe;
+use userlib::*;
+
+task_slot!(SYS, sys);
+task_slot!(JEFE, jefe);
+
+struct Config {
+    wdi: PinSet,
+    enable: Option<ActivePin>,
+    ready: Option<ActivePin>,
+    timeout_ms: u32,
+    period_ms: u32,
+    max_holdoff_ms: u32,
+}
+
+struct ActivePin {
+    pins: PinSet,
+    active_high: bool,
+}
+
+include!(concat!(env!("OUT_DIR"), "/watchdog_config.rs"));
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Refused { health: u32 },
+    Holdoff { ms: u32 },
+    HoldoffOver,
+    Period { ms: u32 },
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct ServerImpl {
+    sys: Sys,
+    jefe: Jefe,
+    /// Level last driven onto WDI.
+    wdi_high: bool,
+    deadline: u64,
+    holdoff_until: Option<u64>,
+    status: WatchdogStatus,
+}
+
+impl ServerImpl {
+    fn pet(&mut self) {
+        self.wdi_high = !self.wdi_high;
+        self.sys.gpio_set_to(CONFIG.wdi, self.wdi_high);
+        self.status.pets = self.status.pets.wrapping_add(1);
+    }
+
+    fn set_enabled(&self, on: bool) {
+        if let Some(en) = &CONFIG.enable {
+            self.sys.gpio_set_to(en.pins, on == en.active_high);
+        }
+    }
+
+    /// Checks on the system, and pets the watchdog if all is well.
+    fn check_and_pet(&mut self) -> Result<(), WatchdogError> {
+        let now = sys_get_timer().now;
+        let held_off = match self.holdoff_until {
+            Some(t) if now >= t => {
+                ringbuf_entry!(Trace::HoldoffOver);
+                self.holdoff_until = None;
+                // Give the watchdog a full timeout before it judges us.
+                self.pet();
+                self.set_enabled(true);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        self.status.health = self.jefe.get_health();
+        let result = if self.status.health == 0 || held_off {
+            self.pet();
+            if !held_off {
+                self.jefe.watchdog_fed();
+            }
+            Ok(())
+        } else {
+            ringbuf_entry!(Trace::Refused {
+                health: self.status.health
+            });
+            self.status.refused = self.status.refused.wrapping_add(1);
+            Err(WatchdogError::Unhealthy)
+        };
+
+        if let Some(ready) = &CONFIG.ready {
+            let is_ready = self.jefe.get_readiness() == 0;
+            self.sys
+                .gpio_set_to(ready.pins, is_ready == ready.active_high);
+        }
+        result
+    }
+}
+
+impl idl::InOrderExtWatchdogImpl for ServerImpl {
+    fn configure(
+        &mut self,
+        _: &RecvMessage,
+        period_ms: u32,
+    ) -> Result<(), RequestError<WatchdogError>> {
+        if period_ms == 0 || period_ms > CONFIG.timeout_ms / 2 {
+            return Err(WatchdogError::BadPeriod.into());
+        }
+        ringbuf_entry!(Trace::Period { ms: period_ms });
+        self.status.period_ms = period_ms;
+        // Bring the next pet forward, if it's now due sooner.
+        let next = sys_get_timer().now + u64::from(period_ms);
+        if next < self.deadline {
+            self.deadline = next;
+            sys_set_timer(Some(next), notifications::TIMER_MASK);
+        }
+        Ok(())
+    }
+
+    fn feed(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<WatchdogError>> {
+        self.check_and_pet().map_err(Into::into)
+    }
+
+    fn holdoff(
+        &mut self,
+        _: &RecvMessage,
+        ms: u32,
+    ) -> Result<(), RequestError<WatchdogError>> {
+        if ms > CONFIG.max_holdoff_ms {
+            return Err(WatchdogError::HoldoffTooLong.into());
+        }
+        ringbuf_entry!(Trace::Holdoff { ms });
+        let now = sys_get_timer().now;
+        if ms == 0 {
+            // Ends on the next check, which is now.
+            if self.holdoff_until.is_some() {
+                self.holdoff_until = Some(now);
+                let _ = self.check_and_pet();
+            }
+        } else {
+            self.pet();
+            self.set_enabled(false);
+            self.holdoff_until = Some(now + u64::from(ms));
+        }
+        Ok(())
+    }
+
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<WatchdogStatus, RequestError<core::convert::Infallible>> {
+        let now = sys_get_timer().now;
+        let holdoff_ms = self
+            .holdoff_until
+            .map(|t| t.saturating_sub(now).min(u64::from(u32::MAX)) as u32)
+            .unwrap_or(0);
+        Ok(WatchdogStatus {
+            holdoff_ms,
+            ..self.status
+        })
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        let now = sys_get_timer().now;
+        if now < self.deadline {
+            return;
+        }
+        // Refusal is recorded in the status and ringbuf; there's nobody
+        // else to tell.
+        let _ = self.check_and_pet();
+        self.deadline = now + u64::from(self.status.period_ms);
+        sys_set_timer(Some(self.deadline), notifications::TIMER_MASK);
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+
+    sys.gpio_reset(CONFIG.wdi);
+    sys.gpio_configure_output(
+        CONFIG.wdi,
+        OutputType::PushPull,
+        Speed::Low,
+        Pull::None,
+    );
+    if let Some(en) = &CONFIG.enable {
+        sys.gpio_set_to(en.pins, en.active_high);
+        sys.gpio_configure_output(
+            en.pins,
+            OutputType::PushPull,
+            Speed::Low,
+            Pull::None,
+        );
+    }
+
+    if let Some(ready) = &CONFIG.ready {
+        // Not ready until jefe says so.
+        sys.gpio_set_to(ready.pins, !ready.active_high);
+        sys.gpio_configure_output(
+            ready.pins,
+            OutputType::PushPull,
+            Speed::Low,
+            Pull::None,
+        );
+    }
+
+    let deadline = sys_get_timer().now + u64::from(CONFIG.period_ms);
+    sys_set_timer(Some(deadline), notifications::TIMER_MASK);
+
+    let mut server = ServerImpl {
+        sys,
+        jefe: Jefe::from(JEFE.get_task_id()),
+        wdi_high: false,
+        deadline,
+        holdoff_until: None,
+        status: WatchdogStatus {
+            period_ms: CONFIG.period_ms,
+            ..Default::default()
+        },
+    };
+    // Start the clock on a known edge.
+    server.pet();
+
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_ext_watchdog_api::{WatchdogError, WatchdogStatus};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -3,6 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    build_util::expose_m_profile();
     build_util::expose_target_board();
     build_util::build_notifications()?;
 
@@ -0,0 +1,185 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Mux and segment health tracking
+//!
+//! When a mux wedges (or a device on one of its segments holds the bus), we
+//! want to contain the damage:  rather than allowing every device behind the
+//! mux to accumulate errors, we track failures to select a segment.  After
+//! [`RESET_THRESHOLD`] consecutive failures on a mux, we escalate to resetting
+//! the mux itself (via its reset line, if it has one); after
+//! [`QUARANTINE_THRESHOLD`] consecutive failures on a segment, we quarantine
+//! the segment, failing operations to it without touching the bus until
+//! [`QUARANTINE_TICKS`] have elapsed, at which point we will allow a single
+//! attempt to select it again.
+//!
+//! There is room to track every mux in the config, and no more; callers must
+//! check that a mux exists before recording anything about it.
+
+use drv_i2c_api::*;
+use fixedmap::FixedMap;
+
+/// Consecutive select failures on a mux before we reset it
+pub const RESET_THRESHOLD: u32 = 2;
+
+/// Consecutive select failures on a segment before we quarantine it
+pub const QUARANTINE_THRESHOLD: u32 = 4;
+
+/// Duration of a quarantine, in ticks
+pub const QUARANTINE_TICKS: u64 = 10_000;
+
+/// The number of segments that a mux can have
+const NSEGMENTS: usize = 8;
+
+#[derive(Copy, Clone, Default)]
+struct SegmentState {
+    consecutive: u32,
+    failures: u32,
+    quarantined_until: Option<u64>,
+}
+
+#[derive(Copy, Clone, Default)]
+struct MuxState {
+    consecutive: u32,
+    resets: u32,
+    segments: [SegmentState; NSEGMENTS],
+}
+
+type Key = (Controller, PortIndex, Mux);
+
+pub struct MuxHealth<const N: usize> {
+    map: FixedMap<Key, MuxState, N>,
+}
+
+impl<const N: usize> Default for MuxHealth<N> {
+    fn default() -> Self {
+        Self {
+            map: FixedMap::default(),
+        }
+    }
+}
+
+impl<const N: usize> MuxHealth<N> {
+    fn state(&self, key: Key) -> MuxState {
+        self.map.get(key).unwrap_or_default()
+    }
+
+    ///
+    /// Checks that the specified segment is not quarantined, returning
+    /// [`ResponseCode::SegmentQuarantined`] if it is.
+    ///
+    pub fn check(
+        &self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        now: u64,
+    ) -> Result<(), ResponseCode> {
+        if let Some((id, segment)) = mux {
+            let state = self.state((controller, port, id));
+
+            if let Some(until) =
+                state.segments[segment as usize - 1].quarantined_until
+            {
+                if now < until {
+                    return Err(ResponseCode::SegmentQuarantined);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records a successful selection of the specified segment.
+    pub fn success(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+    ) {
+        if let Some((id, segment)) = mux {
+            let key = (controller, port, id);
+            let mut state = self.state(key);
+            let seg = &mut state.segments[segment as usize - 1];
+
+            if state.consecutive == 0
+                && seg.consecutive == 0
+                && seg.quarantined_until.is_none()
+            {
+                // Nothing to update -- and nothing to insert.
+                return;
+            }
+
+            state.consecutive = 0;
+            seg.consecutive = 0;
+            seg.quarantined_until = None;
+            self.map.insert(key, state);
+        }
+    }
+
+    ///
+    /// Records a failure to select the specified segment, returning `true`
+    /// if the failures on this mux warrant resetting it.
+    ///
+    pub fn failure(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        now: u64,
+    ) -> bool {
+        let (id, segment) = match mux {
+            Some(mux) => mux,
+            None => return false,
+        };
+
+        let key = (controller, port, id);
+        let mut state = self.state(key);
+        let seg = &mut state.segments[segment as usize - 1];
+
+        seg.consecutive += 1;
+        seg.failures = seg.failures.saturating_add(1);
+
+        if seg.consecutive >= QUARANTINE_THRESHOLD {
+            seg.quarantined_until = Some(now + QUARANTINE_TICKS);
+        }
+
+        state.consecutive += 1;
+
+        let escalate = state.consecutive >= RESET_THRESHOLD;
+
+        if escalate {
+            state.consecutive = 0;
+            state.resets = state.resets.saturating_add(1);
+        }
+
+        self.map.insert(key, state);
+        escalate
+    }
+
+    /// Returns the status of the specified segment.
+    pub fn status(
+        &self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Mux,
+        segment: Segment,
+        now: u64,
+    ) -> SegmentStatus {
+        let state = self.state((controller, port, mux));
+        let seg = &state.segments[segment as usize - 1];
+
+        let health = match seg.quarantined_until {
+            Some(until) if now < until => SegmentHealth::Quarantined,
+            _ if seg.consecutive > 0 => SegmentHealth::Degraded,
+            _ => SegmentHealth::Healthy,
+        };
+
+        SegmentStatus {
+            health,
+            failures: seg.failures,
+            mux_resets: state.resets,
+        }
+    }
+}
@@ -9,12 +9,23 @@
 
 use drv_i2c_api::*;
 use drv_stm32xx_i2c::*;
-use drv_stm32xx_sys_api::{Mode, OutputType, PinSet, Pull, Speed, Sys};
+use drv_stm32xx_sys_api::{Edge, Mode, OutputType, PinSet, Pull, Speed, Sys};
 
+use core::sync::atomic::{AtomicU32, Ordering};
 use fixedmap::*;
+use hubpack::SerializedSize;
 use ringbuf::*;
 use userlib::*;
 
+#[cfg(armv6m)]
+use armv6m_atomic_hack::AtomicU32Ext;
+
+mod health;
+mod queue;
+
+use health::MuxHealth;
+use queue::{Transfer, TransferQueue};
+
 task_slot!(SYS, sys);
 
 fn lookup_controller<'a, 'b>(
@@ -141,6 +152,88 @@ fn configure_mux(
     })
 }
 
+///
+/// Selects the specified mux segment (or deselects any selected segment if
+/// `mux` is `None`), tracking the health of the mux and its segments.  If a
+/// mux repeatedly fails to select a segment, we escalate to resetting both
+/// the controller and the mux -- regardless of whether the error would
+/// otherwise have indicated a reset -- and retry the selection once.
+///
+fn select_segment(
+    health: &mut Health,
+    map: &mut MuxMap,
+    controller: &I2cController<'_>,
+    port: PortIndex,
+    mux: Option<(Mux, Segment)>,
+    muxes: &[I2cMux<'_>],
+    ctrl: &I2cControl,
+) -> Result<(), ResponseCode> {
+    // The health map only has room for the muxes we know about, so a
+    // nonexistent one mustn't get anywhere near it.
+    if let Some((id, _)) = mux {
+        validate_mux(controller, port, id, muxes)?;
+    }
+
+    let now = sys_get_timer().now;
+    health.check(controller.controller, port, mux, now)?;
+
+    let code = match configure_mux(map, controller, port, mux, muxes, ctrl) {
+        Ok(_) => {
+            health.success(controller.controller, port, mux);
+            return Ok(());
+        }
+        Err(code) => code,
+    };
+
+    ringbuf_entry!(Trace::Error);
+
+    if !health.failure(controller.controller, port, mux, now) {
+        reset_if_needed(code, controller, port, muxes, mux);
+        return Err(code);
+    }
+
+    if let Some((id, segment)) = mux {
+        ringbuf_entry!(Trace::Escalate(id, segment, code));
+    }
+    count_error(code);
+
+    //
+    // Having reset the mux, we can no longer assume that the segment that
+    // we believe to be enabled is in fact enabled.
+    //
+    reset(controller, port, muxes, mux);
+    map.remove((controller.controller, port));
+
+    match configure_mux(map, controller, port, mux, muxes, ctrl) {
+        Ok(_) => {
+            health.success(controller.controller, port, mux);
+            Ok(())
+        }
+        Err(code) => {
+            ringbuf_entry!(Trace::Error);
+            health.failure(controller.controller, port, mux, now);
+            reset_if_needed(code, controller, port, muxes, mux);
+            Err(code)
+        }
+    }
+}
+
+/// Checks that mux `id` is configured on the given controller and port.
+fn validate_mux(
+    controller: &I2cController<'_>,
+    port: PortIndex,
+    id: Mux,
+    muxes: &[I2cMux<'_>],
+) -> Result<(), ResponseCode> {
+    if muxes.iter().any(|m| {
+        m.controller == controller.controller && m.port == port && m.id == id
+    }) {
+        Ok(())
+    } else {
+        Err(ResponseCode::MuxNotFound)
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
     Error, // ringbuf line indicates error location
@@ -149,11 +242,27 @@ enum Trace {
     SegmentFailed(ResponseCode),
     ConfigureFailed(ResponseCode),
     Wiggles(u8),
+    SmbAlert(u8),
+    Escalate(Mux, Segment, ResponseCode),
     None,
 }
 
 ringbuf!(Trace, 8, Trace::None);
 
+//
+// Counts of the errors we've seen, for `Op::ErrorCounts`.
+//
+static ERRORS: AtomicU32 = AtomicU32::new(0);
+static NO_DEVICE: AtomicU32 = AtomicU32::new(0);
+static RESETS: AtomicU32 = AtomicU32::new(0);
+
+fn count_error(code: ResponseCode) {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    if code == ResponseCode::NoDevice {
+        NO_DEVICE.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 fn reset(
     controller: &I2cController<'_>,
     port: PortIndex,
@@ -161,6 +270,7 @@ fn reset(
     mux: Option<(Mux, Segment)>,
 ) {
     ringbuf_entry!(Trace::Reset(controller.controller, port));
+    RESETS.fetch_add(1, Ordering::Relaxed);
 
     let sys = SYS.get_task_id();
     let sys = Sys::from(sys);
@@ -195,11 +305,274 @@ fn reset_if_needed(
     muxes: &[I2cMux<'_>],
     mux: Option<(Mux, Segment)>,
 ) {
+    count_error(code);
     if reset_needed(code) {
         reset(controller, port, muxes, mux)
     }
 }
 
+///
+/// Performs a write and/or read to the specified device with SMBus Packet
+/// Error Checking:  if there is no read, a PEC is computed over the write
+/// and appended to it; if there is a read, the PEC sent by the device at its
+/// conclusion is verified against the entire message.  Returns the number
+/// of bytes read.
+///
+#[allow(clippy::too_many_arguments)]
+fn write_read_pec(
+    controller: &I2cController<'_>,
+    addr: u8,
+    wbuf: &hl::Borrow<'_>,
+    wlen: usize,
+    rbuf: &hl::Borrow<'_>,
+    rlen: usize,
+    block: bool,
+    ctrl: &I2cControl,
+) -> Result<usize, ResponseCode> {
+    let mut pec = 0;
+
+    if wlen > 0 {
+        pec = smbus_pec(pec, &[addr << 1]);
+
+        for pos in 0..wlen {
+            let byte = wbuf.read_at(pos).ok_or(ResponseCode::BadArg)?;
+            pec = smbus_pec(pec, &[byte]);
+        }
+    }
+
+    if rlen == 0 {
+        //
+        // With no read, the PEC is ours to send -- and we need room for it.
+        //
+        if wlen >= 255 {
+            return Err(ResponseCode::BadArg);
+        }
+
+        controller.write_read(
+            addr,
+            wlen + 1,
+            |pos| {
+                if pos == wlen {
+                    Some(pec)
+                } else {
+                    wbuf.read_at(pos)
+                }
+            },
+            ReadLength::Fixed(0),
+            |_, _| Some(()),
+            ctrl,
+        )?;
+
+        return Ok(0);
+    }
+
+    if !block && rlen >= 255 {
+        return Err(ResponseCode::BadArg);
+    }
+
+    pec = smbus_pec(pec, &[(addr << 1) | 1]);
+
+    //
+    // For a block read, the length byte is passed through to us (it's covered
+    // by the PEC), and we only know the length of the data once we've seen
+    // it.  In either case, the byte following the data is the PEC.
+    //
+    let mut len = if block { None } else { Some(rlen) };
+    let mut received = None;
+    let mut nread = 0;
+
+    controller.write_read(
+        addr,
+        wlen,
+        |pos| wbuf.read_at(pos),
+        if block {
+            ReadLength::VariableWithPec
+        } else {
+            ReadLength::Fixed(rlen + 1)
+        },
+        |pos, byte| {
+            let pos = if block {
+                if pos == 0 {
+                    len = Some(byte.into());
+                    pec = smbus_pec(pec, &[byte]);
+                    return Some(());
+                }
+
+                pos - 1
+            } else {
+                pos
+            };
+
+            if Some(pos) == len {
+                received = Some(byte);
+                return Some(());
+            }
+
+            pec = smbus_pec(pec, &[byte]);
+            nread = pos + 1;
+            rbuf.write_at(pos, byte)
+        },
+        ctrl,
+    )?;
+
+    if received != Some(pec) {
+        return Err(ResponseCode::PecMismatch);
+    }
+
+    Ok(nread)
+}
+
+///
+/// Advances the transfers queued via `SubmitTransfer` on the specified
+/// controller:  we advance the one in progress as far as the controller
+/// allows and, as each completes, start the next.  If `block` is set, we
+/// wait on the controller until the queue is empty (as we must before
+/// anything else uses the bus); otherwise, we leave the controller's
+/// interrupt enabled for the transfer left waiting on it, and return.
+///
+#[allow(clippy::too_many_arguments)]
+fn advance_queue(
+    queue: &mut Queue,
+    controller: &I2cController<'_>,
+    pins: &[I2cPins],
+    muxes: &[I2cMux<'_>],
+    portmap: &mut PortMap,
+    muxmap: &mut MuxMap,
+    health: &mut Health,
+    ctrl: &I2cControl,
+    block: bool,
+) {
+    while let Some(transfer) = queue.current(controller.controller) {
+        let (port, mux) = (transfer.port, transfer.mux);
+
+        if transfer.progress.is_none() {
+            //
+            // Selecting the segment is itself a (brief) synchronous transfer
+            // to the mux; it's the transfer proper that we leave to the
+            // controller's interrupts.
+            //
+            configure_port(portmap, controller, port, pins);
+
+            if let Err(code) = select_segment(
+                health, muxmap, controller, port, mux, muxes, ctrl,
+            ) {
+                transfer.complete(Err(code));
+                continue;
+            }
+        }
+
+        let rval = match transfer.progress {
+            Some(_) => Ok(()),
+            None => controller
+                .start_transfer(transfer.addr, transfer.wlen, transfer.rlen)
+                .map(|progress| transfer.progress = Some(progress)),
+        };
+
+        let write = &transfer.write[..transfer.wlen];
+        let read = &mut transfer.read[..transfer.rlen];
+
+        let rval = rval.and_then(|_| {
+            let progress = transfer.progress.as_mut().unwrap_lite();
+
+            controller.advance_transfer(
+                progress,
+                |pos| write.get(pos).copied(),
+                |pos, byte| {
+                    *read.get_mut(pos)? = byte;
+                    Some(())
+                },
+            )
+        });
+
+        match rval {
+            Ok(true) => transfer.complete(Ok(transfer.rlen)),
+            Ok(false) if block => {
+                (ctrl.wfi)(controller.notification);
+                (ctrl.enable)(controller.notification);
+            }
+            Ok(false) => {
+                (ctrl.enable)(controller.notification);
+                return;
+            }
+            Err(code) => {
+                ringbuf_entry!(Trace::Error);
+                reset_if_needed(code, controller, port, muxes, mux);
+                transfer.complete(Err(code));
+            }
+        }
+    }
+}
+
+///
+/// The maximum number of devices that we will service in a single poll of
+/// the SMBus Alert Response Address.  Each responding device should
+/// deassert SMBALERT# once it has been acknowledged; this bound assures that
+/// a device that fails to do so cannot cause us to spin forever.
+///
+const SMBALERT_MAX_RESPONSES: usize = 8;
+
+///
+/// Services SMBALERT# on the given controller, port and segment (which must
+/// already be configured and selected), notifying the owner of each device
+/// that responds to the Alert Response Address.  Returns the number of
+/// devices that responded.
+///
+fn service_smbalert(
+    controller: &I2cController<'_>,
+    port: PortIndex,
+    mux: Option<(Mux, Segment)>,
+    owners: &[I2cSmbAlertOwner],
+    muxes: &[I2cMux<'_>],
+    ctrl: &I2cControl,
+) -> Result<usize, ResponseCode> {
+    let mut serviced = 0;
+
+    //
+    // Each read of the Alert Response Address yields the address of one
+    // alerting device (with the lowest address winning arbitration); we keep
+    // reading until no device responds.
+    //
+    for _ in 0..SMBALERT_MAX_RESPONSES {
+        let mut response = 0;
+
+        match controller.write_read(
+            SMBUS_ALERT_RESPONSE_ADDRESS,
+            0,
+            |_| Some(0),
+            ReadLength::Fixed(1),
+            |_, byte| {
+                response = byte;
+                Some(())
+            },
+            ctrl,
+        ) {
+            Ok(_) => {}
+            Err(ResponseCode::NoDevice) => break,
+            Err(code) => {
+                ringbuf_entry!(Trace::Error);
+                reset_if_needed(code, controller, port, muxes, mux);
+                return Err(code);
+            }
+        }
+
+        let addr = response >> 1;
+        ringbuf_entry!(Trace::SmbAlert(addr));
+
+        for owner in owners.iter().filter(|o| {
+            o.controller == controller.controller
+                && o.port == port
+                && o.segment == mux
+                && o.address == addr
+        }) {
+            sys_post(sys_refresh_task_id(owner.task), owner.notification);
+        }
+
+        serviced += 1;
+    }
+
+    Ok(serviced)
+}
+
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 type PortMap = FixedMap<Controller, PortIndex, { i2c_config::NCONTROLLERS }>;
@@ -210,23 +583,57 @@ type MuxMap = FixedMap<
     { i2c_config::NMUXEDBUSES },
 >;
 
+type Health = MuxHealth<{ i2c_config::NMUXES }>;
+
+type Queue = TransferQueue<{ i2c_config::NCONTROLLERS }>;
+
 #[export_name = "main"]
 fn main() -> ! {
     let controllers = i2c_config::controllers();
     let pins = i2c_config::pins();
     let muxes = i2c_config::muxes();
+    let smbalerts = i2c_config::smbalerts();
+    let smbalert_owners = i2c_config::smbalert_owners();
 
     // This is our actual mutable state
     let mut portmap = PortMap::default();
     let mut muxmap = MuxMap::default();
+    let mut health = Health::default();
 
     // Turn the actual peripheral on so that we can interact with it.
     turn_on_i2c(&controllers);
     configure_pins(&controllers, &pins, &mut portmap);
     configure_controllers(&controllers);
 
+    // Our queue of asynchronous transfers, one per controller.
+    let mut queue =
+        Queue::new(core::array::from_fn(|i| controllers[i].controller));
+
+    // The interrupts that advance those transfers.
+    let irqs = controllers.iter().fold(0, |irqs, c| irqs | c.notification);
+
+    //
+    // Any SMBALERT# lines routed to us as GPIO interrupts; we service each
+    // as it is asserted, rather than waiting for a client to poll.
+    //
+    let alerts = smbalerts.iter().fold(0, |n, a| n | a.notification);
+
+    if alerts != 0 {
+        let sys = Sys::from(SYS.get_task_id());
+        sys.gpio_irq_configure(alerts, Edge::Falling).unwrap_lite();
+        sys.gpio_irq_control(0, alerts).unwrap_lite();
+    }
+
+    let mask = irqs | alerts;
+
     // Field messages.
-    let mut buffer = [0; 4];
+    const BUFSIZE: usize = if TRANSACTION_MESSAGE_SIZE > SUBMIT_MESSAGE_SIZE {
+        TRANSACTION_MESSAGE_SIZE
+    } else {
+        SUBMIT_MESSAGE_SIZE
+    };
+
+    let mut buffer = [0; BUFSIZE];
 
     let ctrl = I2cControl {
         enable: |notification| {
@@ -240,8 +647,19 @@ fn main() -> ! {
     configure_muxes(&muxes, &controllers, &pins, &mut portmap, &ctrl);
 
     loop {
-        hl::recv_without_notification(&mut buffer, |op, msg| match op {
-            Op::WriteRead | Op::WriteReadBlock => {
+        //
+        // Beyond being woken by them, our controllers' interrupts need no
+        // handling here: we advance our queued transfers after every
+        // receive.  Asserted SMBALERT# lines are serviced below.
+        //
+        let mut alerted = 0;
+        let woken = |(), bits: u32| alerted = bits & alerts;
+
+        hl::recv(&mut buffer, mask, (), woken, |_, op, msg| match op {
+            Op::WriteRead
+            | Op::WriteReadBlock
+            | Op::WriteReadPec
+            | Op::WriteReadBlockPec => {
                 let lease_count = msg.lease_count();
 
                 let (payload, caller) = msg
@@ -255,30 +673,47 @@ fn main() -> ! {
                 let (addr, controller, port, mux) =
                     Marshal::unmarshal(payload)?;
 
-                if ReservedAddress::from_u8(addr).is_some() {
+                if addr.is_reserved() {
                     return Err(ResponseCode::ReservedAddress);
                 }
 
+                let pec = op == Op::WriteReadPec || op == Op::WriteReadBlockPec;
+
+                //
+                // SMBus (and therefore PEC) has no notion of 10-bit
+                // addresses.
+                //
+                if pec && addr.seven_bit().is_none() {
+                    return Err(ResponseCode::OperationNotSupported);
+                }
+
                 let controller = lookup_controller(&controllers, controller)?;
                 validate_port(&pins, controller.controller, port)?;
 
+                // Our queued transfers on this controller go first.
+                advance_queue(
+                    &mut queue,
+                    controller,
+                    &pins,
+                    &muxes,
+                    &mut portmap,
+                    &mut muxmap,
+                    &mut health,
+                    &ctrl,
+                    true,
+                );
+
                 configure_port(&mut portmap, controller, port, &pins);
 
-                match configure_mux(
+                select_segment(
+                    &mut health,
                     &mut muxmap,
                     controller,
                     port,
                     mux,
                     &muxes,
                     &ctrl,
-                ) {
-                    Ok(_) => {}
-                    Err(code) => {
-                        ringbuf_entry!(Trace::Error);
-                        reset_if_needed(code, controller, port, &muxes, mux);
-                        return Err(code);
-                    }
-                }
+                )?;
 
                 let mut total = 0;
 
@@ -313,26 +748,46 @@ fn main() -> ! {
 
                     let mut nread = 0;
 
-                    match controller.write_read(
-                        addr,
-                        winfo.len,
-                        |pos| wbuf.read_at(pos),
-                        // Only the final read operation in a WriteReadBlock is
-                        // a block read; everything else is a normal read.
-                        if op == Op::WriteReadBlock && i == lease_count - 2 {
-                            ReadLength::Variable
-                        } else {
-                            ReadLength::Fixed(rinfo.len)
-                        },
-                        |pos, byte| {
-                            if pos + 1 > nread {
-                                nread = pos + 1;
-                            }
-
-                            rbuf.write_at(pos, byte)
-                        },
-                        &ctrl,
-                    ) {
+                    // Only the final read operation in a WriteReadBlock is
+                    // a block read; everything else is a normal read.
+                    let block = (op == Op::WriteReadBlock
+                        || op == Op::WriteReadBlockPec)
+                        && i == lease_count - 2;
+
+                    let rval = if pec {
+                        write_read_pec(
+                            controller,
+                            addr.seven_bit().unwrap_lite(),
+                            &wbuf,
+                            winfo.len,
+                            &rbuf,
+                            rinfo.len,
+                            block,
+                            &ctrl,
+                        )
+                        .map(|n| nread = n)
+                    } else {
+                        controller.write_read(
+                            addr,
+                            winfo.len,
+                            |pos| wbuf.read_at(pos),
+                            if block {
+                                ReadLength::Variable
+                            } else {
+                                ReadLength::Fixed(rinfo.len)
+                            },
+                            |pos, byte| {
+                                if pos + 1 > nread {
+                                    nread = pos + 1;
+                                }
+
+                                rbuf.write_at(pos, byte)
+                            },
+                            &ctrl,
+                        )
+                    };
+
+                    match rval {
                         Err(code) => {
                             ringbuf_entry!(Trace::Error);
                             reset_if_needed(
@@ -349,6 +804,403 @@ fn main() -> ! {
                 caller.reply(total);
                 Ok(())
             }
+            Op::Transaction => {
+                let lease_count = msg.lease_count();
+
+                let (payload, caller) = msg
+                    .fixed::<[u8; TRANSACTION_MESSAGE_SIZE], usize>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let mut device = [0u8; 4];
+                device.copy_from_slice(&payload[..4]);
+
+                let (addr, controller, port, mux) =
+                    Marshal::unmarshal(&device)?;
+
+                if addr.is_reserved() {
+                    return Err(ResponseCode::ReservedAddress);
+                }
+
+                //
+                // Decode our steps up front:  the steps are terminated by
+                // the first zero (or by the end of the payload), and there
+                // must be exactly one lease per step.  We validate all of
+                // this before touching the bus, as we don't want to discover
+                // a malformed step after we have already begun the
+                // transaction.
+                //
+                let steps = &payload[4..];
+                let nsteps = steps.iter().take_while(|&&s| s != 0).count();
+
+                if nsteps == 0 {
+                    return Err(ResponseCode::BadArg);
+                }
+
+                if nsteps != lease_count {
+                    return Err(ResponseCode::IllegalLeaseCount);
+                }
+
+                for (i, step) in steps[..nsteps].iter().enumerate() {
+                    let step = TransactionStep::from_u8(*step)
+                        .ok_or(ResponseCode::BadArg)?;
+
+                    let info =
+                        caller.borrow(i).info().ok_or(ResponseCode::BadArg)?;
+
+                    let attr = match step {
+                        TransactionStep::Write => LeaseAttributes::READ,
+                        TransactionStep::Read | TransactionStep::ReadBlock => {
+                            LeaseAttributes::WRITE
+                        }
+                    };
+
+                    if !info.attributes.contains(attr) {
+                        return Err(ResponseCode::BadArg);
+                    }
+
+                    if info.len == 0 || info.len > 255 {
+                        // As with WriteRead, we don't support transfers of
+                        // more than 255 bytes -- and a zero-length step is
+                        // meaningless.
+                        return Err(ResponseCode::BadArg);
+                    }
+                }
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                // Our queued transfers on this controller go first.
+                advance_queue(
+                    &mut queue,
+                    controller,
+                    &pins,
+                    &muxes,
+                    &mut portmap,
+                    &mut muxmap,
+                    &mut health,
+                    &ctrl,
+                    true,
+                );
+
+                configure_port(&mut portmap, controller, port, &pins);
+
+                select_segment(
+                    &mut health,
+                    &mut muxmap,
+                    controller,
+                    port,
+                    mux,
+                    &muxes,
+                    &ctrl,
+                )?;
+
+                let mut total = 0;
+
+                let rval = controller.wait_until_notbusy().and_then(|_| {
+                    for (i, step) in steps[..nsteps].iter().enumerate() {
+                        let step = TransactionStep::from_u8(*step).unwrap();
+                        let buf = caller.borrow(i);
+                        let len = buf.info().ok_or(ResponseCode::BadArg)?.len;
+                        let mut nread = 0;
+
+                        let (wlen, rlen) = match step {
+                            TransactionStep::Write => {
+                                (len, ReadLength::Fixed(0))
+                            }
+                            TransactionStep::Read => {
+                                (0, ReadLength::Fixed(len))
+                            }
+                            TransactionStep::ReadBlock => {
+                                (0, ReadLength::Variable)
+                            }
+                        };
+
+                        controller.write_read_continued(
+                            addr,
+                            wlen,
+                            |pos| buf.read_at(pos),
+                            rlen,
+                            |pos, byte| {
+                                if pos + 1 > nread {
+                                    nread = pos + 1;
+                                }
+
+                                buf.write_at(pos, byte)
+                            },
+                            &ctrl,
+                        )?;
+
+                        total += nread;
+                    }
+
+                    controller.stop();
+                    Ok(())
+                });
+
+                if let Err(code) = rval {
+                    ringbuf_entry!(Trace::Error);
+                    reset_if_needed(code, controller, port, &muxes, mux);
+                    return Err(code);
+                }
+
+                caller.reply(total);
+                Ok(())
+            }
+            Op::SmbAlertPoll => {
+                let (payload, caller) = msg
+                    .fixed::<[u8; 4], usize>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let (_, controller, port, mux) = Marshal::unmarshal(payload)?;
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                //
+                // If this bus has an SMBALERT# line and it isn't asserted,
+                // there is no one to service -- and no need to touch the bus.
+                //
+                if let Some(alert) = smbalerts.iter().find(|a| {
+                    a.controller == controller.controller && a.port == port
+                }) {
+                    let sys = Sys::from(SYS.get_task_id());
+
+                    if sys.gpio_read(alert.pin.gpio_pins) != 0 {
+                        caller.reply(0);
+                        return Ok(());
+                    }
+                }
+
+                // Our queued transfers on this controller go first.
+                advance_queue(
+                    &mut queue,
+                    controller,
+                    &pins,
+                    &muxes,
+                    &mut portmap,
+                    &mut muxmap,
+                    &mut health,
+                    &ctrl,
+                    true,
+                );
+
+                configure_port(&mut portmap, controller, port, &pins);
+
+                select_segment(
+                    &mut health,
+                    &mut muxmap,
+                    controller,
+                    port,
+                    mux,
+                    &muxes,
+                    &ctrl,
+                )?;
+
+                let serviced = service_smbalert(
+                    controller,
+                    port,
+                    mux,
+                    &smbalert_owners,
+                    &muxes,
+                    &ctrl,
+                )?;
+
+                caller.reply(serviced);
+                Ok(())
+            }
+            Op::SubmitTransfer => {
+                let (payload, caller) = msg
+                    .fixed_with_leases::<[u8; SUBMIT_MESSAGE_SIZE], u32>(1)
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let mut device = [0u8; 4];
+                device.copy_from_slice(&payload[..4]);
+
+                let (addr, controller, port, mux) =
+                    Marshal::unmarshal(&device)?;
+
+                if addr.is_reserved() {
+                    return Err(ResponseCode::ReservedAddress);
+                }
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                let notification =
+                    u32::from_le_bytes(payload[4..8].try_into().unwrap_lite());
+                let rlen = payload[8] as usize;
+
+                let wbuf = caller.borrow(0);
+                let winfo = wbuf.info().ok_or(ResponseCode::BadArg)?;
+
+                if !winfo.attributes.contains(LeaseAttributes::READ) {
+                    return Err(ResponseCode::BadArg);
+                }
+
+                if winfo.len > ASYNC_TRANSFER_MAX
+                    || rlen > ASYNC_TRANSFER_MAX
+                    || (winfo.len == 0 && rlen == 0)
+                {
+                    return Err(ResponseCode::BadArg);
+                }
+
+                let mut transfer =
+                    Transfer::new(caller.task_id(), notification);
+                transfer.addr = addr;
+                transfer.port = port;
+                transfer.mux = mux;
+                transfer.wlen = winfo.len;
+                transfer.rlen = rlen;
+
+                wbuf.read_fully_at(0, &mut transfer.write[..winfo.len])
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let ticket = queue.submit(controller.controller, transfer)?;
+                caller.reply(ticket);
+                Ok(())
+            }
+            Op::CollectTransfer => {
+                let (&ticket, caller) = msg
+                    .fixed_with_leases::<u32, usize>(1)
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let transfer = queue.collect(caller.task_id(), ticket)?;
+
+                //
+                // Our transfer is now collected, successful or not; if it
+                // failed, we return its error to the caller.
+                //
+                let nread = transfer.result.unwrap_lite()?;
+
+                caller
+                    .borrow(0)
+                    .write_fully_at(0, &transfer.read[..nread])
+                    .ok_or(ResponseCode::BadArg)?;
+
+                caller.reply(nread);
+                Ok(())
+            }
+            Op::SegmentStatus => {
+                let (payload, caller) = msg
+                    .fixed::<[u8; 4], [u8; SegmentStatus::MAX_SIZE]>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let (_, controller, port, mux) = Marshal::unmarshal(payload)?;
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                let (id, segment) = mux.ok_or(ResponseCode::BadArg)?;
+                validate_mux(controller, port, id, &muxes)?;
+
+                let status = health.status(
+                    controller.controller,
+                    port,
+                    id,
+                    segment,
+                    sys_get_timer().now,
+                );
+
+                let mut response = [0u8; SegmentStatus::MAX_SIZE];
+                hubpack::serialize(&mut response, &status)
+                    .map_err(|_| ResponseCode::BadResponse)?;
+
+                caller.reply(response);
+                Ok(())
+            }
+            #[cfg(feature = "diagnostics")]
+            Op::Scan => {
+                let (payload, caller) = msg
+                    .fixed::<[u8; 4], Presence>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let (_, controller, port, mux) = Marshal::unmarshal(payload)?;
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                // Our queued transfers on this controller go first.
+                advance_queue(
+                    &mut queue,
+                    controller,
+                    &pins,
+                    &muxes,
+                    &mut portmap,
+                    &mut muxmap,
+                    &mut health,
+                    &ctrl,
+                    true,
+                );
+
+                configure_port(&mut portmap, controller, port, &pins);
+
+                select_segment(
+                    &mut health,
+                    &mut muxmap,
+                    controller,
+                    port,
+                    mux,
+                    &muxes,
+                    &ctrl,
+                )?;
+
+                let mut presence = Presence::default();
+
+                //
+                // We probe each address with a single byte read:  unlike a
+                // zero-byte write (the SMBus "quick command"), this cannot
+                // be misinterpreted as a command by a device -- but it can
+                // consume a byte of a device's read pointer, so this is not
+                // something to do on a production system.
+                //
+                for addr in 0..128 {
+                    if ReservedAddress::from_u8(addr).is_some() {
+                        continue;
+                    }
+
+                    match controller.write_read(
+                        addr,
+                        0,
+                        |_| Some(0),
+                        ReadLength::Fixed(1),
+                        |_, _| Some(()),
+                        &ctrl,
+                    ) {
+                        Ok(_) => presence.set(addr),
+                        Err(ResponseCode::NoDevice) => {}
+                        Err(code) => {
+                            ringbuf_entry!(Trace::Error);
+                            reset_if_needed(
+                                code, controller, port, &muxes, mux,
+                            );
+                            return Err(code);
+                        }
+                    }
+                }
+
+                caller.reply(presence);
+                Ok(())
+            }
+            #[cfg(not(feature = "diagnostics"))]
+            Op::Scan => Err(ResponseCode::OperationNotSupported),
+            Op::ErrorCounts => {
+                let (_, caller) = msg
+                    .fixed::<(), [u8; ErrorCounts::MAX_SIZE]>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let counts = ErrorCounts {
+                    errors: ERRORS.load(Ordering::Relaxed),
+                    no_device: NO_DEVICE.load(Ordering::Relaxed),
+                    resets: RESETS.load(Ordering::Relaxed),
+                };
+
+                let mut response = [0u8; ErrorCounts::MAX_SIZE];
+                hubpack::serialize(&mut response, &counts)
+                    .map_err(|_| ResponseCode::BadResponse)?;
+
+                caller.reply(response);
+                Ok(())
+            }
             Op::SelectedMuxSegment => {
                 let (payload, caller) = msg
                     .fixed::<[u8; 4], [u8; 4]>()
@@ -370,6 +1222,94 @@ fn main() -> ! {
                 Ok(())
             }
         });
+
+        for alert in smbalerts.iter().filter(|a| alerted & a.notification != 0)
+        {
+            let controller =
+                lookup_controller(&controllers, alert.controller).unwrap_lite();
+
+            advance_queue(
+                &mut queue,
+                controller,
+                &pins,
+                &muxes,
+                &mut portmap,
+                &mut muxmap,
+                &mut health,
+                &ctrl,
+                true,
+            );
+
+            configure_port(&mut portmap, controller, alert.port, &pins);
+
+            //
+            // The alerting device may be on any segment of this port, so we
+            // read the Alert Response Address on each segment that has an
+            // owner (and on the port itself, if nothing is muxed).
+            //
+            let owners = smbalert_owners.iter().filter(|o| {
+                o.controller == alert.controller && o.port == alert.port
+            });
+
+            let mut segments = owners.clone().map(|o| o.segment).peekable();
+
+            if segments.peek().is_none() {
+                let _ = service_smbalert(
+                    controller,
+                    alert.port,
+                    None,
+                    &smbalert_owners,
+                    &muxes,
+                    &ctrl,
+                );
+            }
+
+            for (i, mux) in segments.enumerate() {
+                if owners.clone().take(i).any(|o| o.segment == mux) {
+                    continue;
+                }
+
+                if select_segment(
+                    &mut health,
+                    &mut muxmap,
+                    controller,
+                    alert.port,
+                    mux,
+                    &muxes,
+                    &ctrl,
+                )
+                .is_ok()
+                {
+                    let _ = service_smbalert(
+                        controller,
+                        alert.port,
+                        mux,
+                        &smbalert_owners,
+                        &muxes,
+                        &ctrl,
+                    );
+                }
+            }
+        }
+
+        //
+        // Now that we have replied to our caller (or been interrupted),
+        // advance any queued transfers, notifying each submitter as its
+        // transfer completes.
+        //
+        for controller in &controllers {
+            advance_queue(
+                &mut queue,
+                controller,
+                &pins,
+                &muxes,
+                &mut portmap,
+                &mut muxmap,
+                &mut health,
+                &ctrl,
+                false,
+            );
+        }
     }
 }
 
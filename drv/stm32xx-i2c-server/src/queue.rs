@@ -0,0 +1,220 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Per-controller queues of asynchronous transfers
+//!
+//! A caller submits a transfer and receives a ticket in reply; the transfer
+//! is then started after the reply has been sent and advanced as the
+//! controller interrupts (allowing both the caller and us to run while the
+//! bus does its work), and the caller is notified once it completes.  The
+//! caller then collects the result (and any data read) by presenting its
+//! ticket.  Transfers are performed in submission order on a given
+//! controller, one at a time.
+
+use drv_i2c_api::*;
+use drv_stm32xx_i2c::PendingTransfer;
+use userlib::*;
+
+/// The number of transfers that can be queued on a single controller
+pub const QUEUE_DEPTH: usize = 4;
+
+/// The number of those that a single task can hold at once, so that no one
+/// task can starve the others of the queue
+pub const TASK_QUEUE_DEPTH: usize = 2;
+
+/// How long a completed transfer waits to be collected before we reclaim its
+/// slot, in milliseconds
+pub const COLLECT_TIMEOUT_MS: u64 = 1000;
+
+#[derive(Copy, Clone)]
+pub struct Transfer {
+    pub caller: TaskId,
+    pub notification: u32,
+    pub addr: Address,
+    pub port: PortIndex,
+    pub mux: Option<(Mux, Segment)>,
+    pub wlen: usize,
+    pub write: [u8; ASYNC_TRANSFER_MAX],
+    pub rlen: usize,
+    pub read: [u8; ASYNC_TRANSFER_MAX],
+
+    /// Progress of the transfer on the controller, if it has been started
+    pub progress: Option<PendingTransfer>,
+
+    /// Result of the transfer, or `None` if it is still pending
+    pub result: Option<Result<usize, ResponseCode>>,
+
+    /// When the transfer completed, if it has
+    completed: u64,
+
+    /// Sequence number, used to both order transfers and validate tickets
+    sequence: u16,
+}
+
+pub struct TransferQueue<const N: usize> {
+    controllers: [Controller; N],
+    slots: [[Option<Transfer>; QUEUE_DEPTH]; N],
+    sequence: u16,
+}
+
+impl<const N: usize> TransferQueue<N> {
+    pub fn new(controllers: [Controller; N]) -> Self {
+        Self {
+            controllers,
+            slots: [[None; QUEUE_DEPTH]; N],
+            sequence: 0,
+        }
+    }
+
+    fn index(&self, controller: Controller) -> Result<usize, ResponseCode> {
+        self.controllers
+            .iter()
+            .position(|&c| c == controller)
+            .ok_or(ResponseCode::BadController)
+    }
+
+    ///
+    /// Enqueues a transfer on the specified controller, returning the ticket
+    /// with which it can later be collected.  Before looking for a free
+    /// slot, we reclaim any completed transfers whose callers have since
+    /// restarted or have left them uncollected for longer than
+    /// [`COLLECT_TIMEOUT_MS`] (and therefore will likely never collect
+    /// them).  The queue is full to a caller that already holds
+    /// [`TASK_QUEUE_DEPTH`] slots on the controller.
+    ///
+    pub fn submit(
+        &mut self,
+        controller: Controller,
+        mut transfer: Transfer,
+    ) -> Result<u32, ResponseCode> {
+        let index = self.index(controller)?;
+        let queue = &mut self.slots[index];
+        let now = sys_get_timer().now;
+
+        for slot in queue.iter_mut() {
+            if let Some(t) = slot {
+                if t.result.is_some()
+                    && (sys_refresh_task_id(t.caller) != t.caller
+                        || now.saturating_sub(t.completed)
+                            >= COLLECT_TIMEOUT_MS)
+                {
+                    *slot = None;
+                }
+            }
+        }
+
+        let held = queue
+            .iter()
+            .flatten()
+            .filter(|t| t.caller == transfer.caller)
+            .count();
+
+        if held >= TASK_QUEUE_DEPTH {
+            return Err(ResponseCode::QueueFull);
+        }
+
+        let slot = queue
+            .iter()
+            .position(|s| s.is_none())
+            .ok_or(ResponseCode::QueueFull)?;
+
+        self.sequence = self.sequence.wrapping_add(1);
+        transfer.sequence = self.sequence;
+        transfer.progress = None;
+        transfer.result = None;
+        queue[slot] = Some(transfer);
+
+        Ok(
+            ((self.sequence as u32) << 16)
+                | ((index as u32) << 8)
+                | slot as u32,
+        )
+    }
+
+    ///
+    /// Returns the oldest transfer yet to complete on the specified
+    /// controller, if any.  As we start transfers in order and one at a
+    /// time, this is the one in progress, if one is.
+    ///
+    pub fn current(&mut self, controller: Controller) -> Option<&mut Transfer> {
+        let index = self.index(controller).ok()?;
+        let sequence = self.sequence;
+
+        //
+        // Our sequence numbers wrap, so we determine age relative to the
+        // most recently issued sequence number.
+        //
+        self.slots[index]
+            .iter_mut()
+            .flatten()
+            .filter(|t| t.result.is_none())
+            .max_by_key(|t| sequence.wrapping_sub(t.sequence))
+    }
+
+    ///
+    /// Collects the transfer denoted by the ticket on behalf of the
+    /// specified caller, freeing its slot.  If the transfer has not yet been
+    /// performed, [`ResponseCode::TransferPending`] is returned and the
+    /// transfer remains queued.
+    ///
+    pub fn collect(
+        &mut self,
+        caller: TaskId,
+        ticket: u32,
+    ) -> Result<Transfer, ResponseCode> {
+        let sequence = (ticket >> 16) as u16;
+        let index = ((ticket >> 8) & 0xff) as usize;
+        let slot = (ticket & 0xff) as usize;
+
+        let entry = self
+            .slots
+            .get_mut(index)
+            .and_then(|q| q.get_mut(slot))
+            .ok_or(ResponseCode::BadTicket)?;
+
+        let t = match entry {
+            Some(t) if t.sequence == sequence && t.caller == caller => *t,
+            _ => return Err(ResponseCode::BadTicket),
+        };
+
+        if t.result.is_none() {
+            return Err(ResponseCode::TransferPending);
+        }
+
+        *entry = None;
+        Ok(t)
+    }
+}
+
+impl Transfer {
+    pub fn new(caller: TaskId, notification: u32) -> Self {
+        Self {
+            caller,
+            notification,
+            addr: Address::SevenBit(0),
+            port: PortIndex(0),
+            mux: None,
+            wlen: 0,
+            write: [0; ASYNC_TRANSFER_MAX],
+            rlen: 0,
+            read: [0; ASYNC_TRANSFER_MAX],
+            progress: None,
+            result: None,
+            completed: 0,
+            sequence: 0,
+        }
+    }
+
+    ///
+    /// Records the result of the transfer and notifies its caller that it
+    /// can be collected.
+    ///
+    pub fn complete(&mut self, result: Result<usize, ResponseCode>) {
+        self.progress = None;
+        self.result = Some(result);
+        self.completed = sys_get_timer().now;
+
+        sys_post(sys_refresh_task_id(self.caller), self.notification);
+    }
+}
@@ -38,6 +38,7 @@ pub mod pca9548;
 use ringbuf::*;
 use userlib::*;
 
+use drv_i2c_api::Address;
 use drv_stm32xx_sys_api as sys_api;
 
 pub struct I2cPins {
@@ -53,6 +54,34 @@ pub struct I2cGpio {
     pub gpio_pins: sys_api::PinSet,
 }
 
+/// An SMBALERT# line, shared by devices on a given controller and port
+pub struct I2cSmbAlert {
+    pub controller: drv_i2c_api::Controller,
+    pub port: drv_i2c_api::PortIndex,
+
+    /// The (active-low) SMBALERT# line itself
+    pub pin: I2cGpio,
+
+    /// Our notification that the line has been asserted, if it has been
+    /// routed to us as a GPIO interrupt; 0 if it is only serviced when a
+    /// client polls
+    pub notification: u32,
+}
+
+///
+/// A device that can assert SMBALERT#, along with the task (and
+/// notification) that owns it.  When the device responds to the Alert
+/// Response Address, the owning task is notified.
+///
+pub struct I2cSmbAlertOwner {
+    pub controller: drv_i2c_api::Controller,
+    pub port: drv_i2c_api::PortIndex,
+    pub segment: Option<(drv_i2c_api::Mux, drv_i2c_api::Segment)>,
+    pub address: u8,
+    pub task: TaskId,
+    pub notification: u32,
+}
+
 pub struct I2cController<'a> {
     pub controller: drv_i2c_api::Controller,
     pub peripheral: sys_api::Peripheral,
@@ -133,6 +162,40 @@ pub enum ReadLength {
     Fixed(usize),
     /// Read size is variable: first byte contains length
     Variable,
+    /// Read size is variable, as with [`ReadLength::Variable`], but the data
+    /// is followed by an SMBus Packet Error Code (PEC) byte.  Because the PEC
+    /// covers the length byte, the length byte is passed through to the
+    /// caller as the first byte read, and the PEC as the last.
+    VariableWithPec,
+}
+
+///
+/// A transfer that, unlike one made with [`I2cController::write_read`],
+/// doesn't block waiting on the controller:  it is begun with
+/// [`I2cController::start_transfer`] and then advanced with
+/// [`I2cController::advance_transfer`] each time the controller interrupts,
+/// until the latter reports it complete.  Only fixed-length reads are
+/// supported.
+///
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PendingTransfer {
+    add10: bool,
+    sadd: u16,
+    wlen: usize,
+    rlen: usize,
+    phase: Phase,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Phase {
+    /// Writing, with the position of the next byte to write
+    Write(usize),
+    /// Written, awaiting transfer complete
+    WriteWait,
+    /// Reading, with the position of the next byte to read
+    Read(usize),
+    /// Read, awaiting transfer complete
+    ReadWait,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -217,6 +280,18 @@ impl I2cMux<'_> {
     }
 }
 
+///
+/// Returns the ADD10 bit and the SADD field for the specified address.  Note
+/// that a 7-bit address occupies SADD[7:1], while a 10-bit address occupies
+/// all of SADD[9:0].
+///
+fn sadd(addr: Address) -> (bool, u16) {
+    match addr {
+        Address::SevenBit(addr) => (false, u16::from(addr) << 1),
+        Address::TenBit(addr) => (true, addr & 0x3ff),
+    }
+}
+
 impl I2cController<'_> {
     pub fn enable(&self, sys: &sys_api::Sys) {
         sys.enable_clock(self.peripheral);
@@ -426,7 +501,12 @@ impl I2cController<'_> {
         Ok(())
     }
 
-    fn wait_until_notbusy(&self) -> Result<(), drv_i2c_api::ResponseCode> {
+    ///
+    /// Waits for the controller to no longer be busy, returning an error if
+    /// it appears hung.  This must be called before issuing the first segment
+    /// of a transaction via [`I2cController::write_read_continued`].
+    ///
+    pub fn wait_until_notbusy(&self) -> Result<(), drv_i2c_api::ResponseCode> {
         let i2c = self.registers;
 
         //
@@ -499,7 +579,43 @@ impl I2cController<'_> {
     /// be extended in the future to allow them.
     pub fn write_read(
         &self,
-        addr: u8,
+        addr: impl Into<Address>,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: impl FnMut(usize, u8) -> Option<()>,
+        ctrl: &I2cControl,
+    ) -> Result<(), drv_i2c_api::ResponseCode> {
+        self.wait_until_notbusy()?;
+        self.write_read_continued(
+            addr.into(),
+            wlen,
+            getbyte,
+            rlen,
+            putbyte,
+            ctrl,
+        )?;
+
+        //
+        // Whether we did a write alone, a read alone, or a write followed
+        // by a read, we're done now -- manually send a STOP.
+        //
+        self.stop();
+
+        Ok(())
+    }
+
+    /// Perform a write to and then a read from the specified device, but
+    /// without waiting for the bus to be idle beforehand and without sending
+    /// a STOP afterwards.  This allows for several write and read segments to
+    /// be chained together with repeated STARTs into a single transaction:
+    /// the caller is responsible for first calling
+    /// [`I2cController::wait_until_notbusy`] and for calling
+    /// [`I2cController::stop`] after the final segment.  The preconditions
+    /// are the same as for [`I2cController::write_read`].
+    pub fn write_read_continued(
+        &self,
+        addr: impl Into<Address>,
         wlen: usize,
         getbyte: impl Fn(usize) -> Option<u8>,
         mut rlen: ReadLength,
@@ -516,16 +632,15 @@ impl I2cController<'_> {
 
         let i2c = self.registers;
         let notification = self.notification;
-
-        self.wait_until_notbusy()?;
+        let (add10, sadd) = sadd(addr.into());
 
         if wlen > 0 {
             #[rustfmt::skip]
             i2c.cr2.modify(|_, w| { w
                 .nbytes().bits(wlen as u8)
                 .autoend().clear_bit()
-                .add10().clear_bit()
-                .sadd().bits((addr << 1).into())
+                .add10().bit(add10)
+                .sadd().bits(sadd)
                 .rd_wrn().clear_bit()
                 .start().set_bit()
             });
@@ -595,8 +710,8 @@ impl I2cController<'_> {
                 i2c.cr2.modify(|_, w| { w
                     .nbytes().bits(rlen as u8)
                     .autoend().clear_bit()
-                    .add10().clear_bit()
-                    .sadd().bits((addr << 1).into())
+                    .add10().bit(add10)
+                    .sadd().bits(sadd)
                     .rd_wrn().set_bit()
                     .start().set_bit()
                 });
@@ -606,8 +721,8 @@ impl I2cController<'_> {
                     .nbytes().bits(1)
                     .autoend().clear_bit()
                     .reload().set_bit()
-                    .add10().clear_bit()
-                    .sadd().bits((addr << 1).into())
+                    .add10().bit(add10)
+                    .sadd().bits(sadd)
                     .rd_wrn().set_bit()
                     .start().set_bit()
                 });
@@ -655,6 +770,26 @@ impl I2cController<'_> {
                     continue;
                 }
 
+                if rlen == ReadLength::VariableWithPec {
+                    //
+                    // We need to read the PEC byte in addition to the data;
+                    // if the device claims the maximum length, we can't
+                    // express the PEC in our byte count.
+                    //
+                    let nbytes = byte
+                        .checked_add(1)
+                        .ok_or(drv_i2c_api::ResponseCode::BadDeviceState)?;
+
+                    #[rustfmt::skip]
+                    i2c.cr2.modify(|_, w| { w
+                        .nbytes().bits(nbytes)
+                        .reload().clear_bit()
+                    });
+
+                    // Account for the length byte itself and the PEC byte.
+                    rlen = ReadLength::Fixed(usize::from(byte) + 2);
+                }
+
                 putbyte(pos, byte).ok_or(drv_i2c_api::ResponseCode::BadArg)?;
                 pos += 1;
             }
@@ -675,15 +810,169 @@ impl I2cController<'_> {
             }
         }
 
-        //
-        // Whether we did a write alone, a read alone, or a write followed
-        // by a read, we're done now -- manually send a STOP.
-        //
-        i2c.cr2.modify(|_, w| w.stop().set_bit());
-
         Ok(())
     }
 
+    /// Send a STOP, concluding the current transaction.
+    pub fn stop(&self) {
+        self.registers.cr2.modify(|_, w| w.stop().set_bit());
+    }
+
+    ///
+    /// Begins a write of `wlen` bytes followed by a read of `rlen` bytes,
+    /// without waiting for either; the transfer must then be advanced with
+    /// [`I2cController::advance_transfer`] until complete.  The
+    /// preconditions are those of [`I2cController::write_read`], save that
+    /// the read must be of fixed length.
+    ///
+    pub fn start_transfer(
+        &self,
+        addr: impl Into<Address>,
+        wlen: usize,
+        rlen: usize,
+    ) -> Result<PendingTransfer, drv_i2c_api::ResponseCode> {
+        assert!(wlen > 0 || rlen > 0);
+        assert!(wlen <= 255 && rlen <= 255);
+
+        self.wait_until_notbusy()?;
+
+        let (add10, sadd) = sadd(addr.into());
+
+        let mut transfer = PendingTransfer {
+            add10,
+            sadd,
+            wlen,
+            rlen,
+            phase: Phase::Write(0),
+        };
+
+        if wlen > 0 {
+            self.start_segment(&transfer, wlen, false);
+        } else {
+            self.start_segment(&transfer, rlen, true);
+            transfer.phase = Phase::Read(0);
+        }
+
+        Ok(transfer)
+    }
+
+    fn start_segment(&self, transfer: &PendingTransfer, len: usize, rd: bool) {
+        #[rustfmt::skip]
+        self.registers.cr2.modify(|_, w| { w
+            .nbytes().bits(len as u8)
+            .autoend().clear_bit()
+            .add10().bit(transfer.add10)
+            .sadd().bits(transfer.sadd)
+            .rd_wrn().bit(rd)
+            .start().set_bit()
+        });
+    }
+
+    ///
+    /// Advances a transfer begun with [`I2cController::start_transfer`] as
+    /// far as the controller allows, returning `true` if the transfer is
+    /// complete (in which case a STOP has been sent) and `false` if it must
+    /// wait for the controller to interrupt again.  On error, the transfer
+    /// is abandoned, and the controller may need to be reset.
+    ///
+    pub fn advance_transfer(
+        &self,
+        transfer: &mut PendingTransfer,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        mut putbyte: impl FnMut(usize, u8) -> Option<()>,
+    ) -> Result<bool, drv_i2c_api::ResponseCode> {
+        let i2c = self.registers;
+
+        loop {
+            let isr = i2c.isr.read();
+
+            match transfer.phase {
+                Phase::Write(pos) => {
+                    ringbuf_entry!(Trace::WriteISR(isr.bits()));
+                    self.check_errors(&isr)?;
+
+                    if isr.nackf().is_nack() {
+                        i2c.icr.write(|w| w.nackcf().set_bit());
+                        return Err(drv_i2c_api::ResponseCode::NoDevice);
+                    }
+
+                    if !isr.txis().is_empty() {
+                        return Ok(false);
+                    }
+
+                    let byte = getbyte(pos)
+                        .ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+                    i2c.txdr.write(|w| w.txdata().bits(byte));
+
+                    transfer.phase = if pos + 1 < transfer.wlen {
+                        Phase::Write(pos + 1)
+                    } else {
+                        Phase::WriteWait
+                    };
+                }
+                Phase::WriteWait => {
+                    ringbuf_entry!(Trace::WriteWaitISR(isr.bits()));
+                    self.check_errors(&isr)?;
+
+                    if isr.nackf().is_nack() {
+                        i2c.icr.write(|w| w.nackcf().set_bit());
+                        return Err(drv_i2c_api::ResponseCode::NoRegister);
+                    }
+
+                    if !isr.tc().is_complete() {
+                        return Ok(false);
+                    }
+
+                    if transfer.rlen == 0 {
+                        self.stop();
+                        return Ok(true);
+                    }
+
+                    //
+                    // As with a synchronous write and read, we issue a
+                    // RESTART rather than a STOP between the two.
+                    //
+                    self.start_segment(transfer, transfer.rlen, true);
+                    transfer.phase = Phase::Read(0);
+                }
+                Phase::Read(pos) => {
+                    ringbuf_entry!(Trace::ReadISR(isr.bits()));
+                    self.check_errors(&isr)?;
+
+                    if isr.nackf().is_nack() {
+                        i2c.icr.write(|w| w.nackcf().set_bit());
+                        return Err(drv_i2c_api::ResponseCode::NoDevice);
+                    }
+
+                    if isr.rxne().is_empty() {
+                        return Ok(false);
+                    }
+
+                    let byte: u8 = i2c.rxdr.read().rxdata().bits();
+                    putbyte(pos, byte)
+                        .ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+
+                    transfer.phase = if pos + 1 < transfer.rlen {
+                        Phase::Read(pos + 1)
+                    } else {
+                        Phase::ReadWait
+                    };
+                }
+                Phase::ReadWait => {
+                    ringbuf_entry!(Trace::ReadWaitISR(isr.bits()));
+
+                    if isr.tc().is_complete() {
+                        self.stop();
+                        return Ok(true);
+                    }
+
+                    self.check_errors(&isr)?;
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
     ///
     /// Regrettably, some devices insist on special sequences to be sent to
     /// unlock functionality -- effectively a Konami Code for an I2C device.
@@ -76,7 +76,8 @@ pub enum Peripheral {
     Eth1Tx = periph(Group::Ahb1, 16),  // 43/47 only
     Eth1Mac = periph(Group::Ahb1, 15), // 43/47 only
     Art = periph(Group::Ahb1, 14),     // 47 only
-    Crc = periph(Group::Ahb1, 9),      // B3 only
+    #[cfg(feature = "h7b3")]
+    Crc = periph(Group::Ahb1, 9), // B3 differs from 43/47
     Adc1 = periph(Group::Ahb1, 5),
     Dma2 = periph(Group::Ahb1, 1),
     Dma1 = periph(Group::Ahb1, 0),
@@ -110,6 +111,9 @@ pub enum Peripheral {
     #[cfg(any(feature = "h743", feature = "h747", feature = "h757"))]
     Bdma = periph(Group::Ahb4, 21),
 
+    #[cfg(any(feature = "h743", feature = "h747", feature = "h753"))]
+    Crc = periph(Group::Ahb4, 19), // 43/47: differs from B3
+
     GpioK = periph(Group::Ahb4, 10),
     GpioJ = periph(Group::Ahb4, 9),
     GpioI = periph(Group::Ahb4, 8),
@@ -20,6 +20,7 @@ cfg_if::cfg_if! {
 
 use derive_idol_err::IdolError;
 use userlib::*;
+use zerocopy::AsBytes;
 
 pub use drv_stm32xx_gpio_common::{
     Alternate, Mode, OutputType, PinSet, Port, Pull, Speed,
@@ -31,6 +32,23 @@ pub enum RccError {
     NoSuchPeripheral = 1,
 }
 
+#[derive(Copy, Clone, Debug, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum GpioIrqError {
+    /// Some bit in the notification mask doesn't correspond to a GPIO
+    /// interrupt owned by the caller.
+    NotOwner = 1,
+}
+
+/// Which edges of a GPIO interrupt pin should generate notifications.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum Edge {
+    Rising = 1,
+    Falling = 2,
+    Both = 3,
+}
+
 impl Sys {
     /// Requests that the clock to a peripheral be turned on.
     ///
@@ -2,12 +2,116 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::server::build_server_support(
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// This represents our _subset_ of the task config, and _must not_ be marked
+/// with `deny_unknown_fields`!
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct SysConfig {
+    /// GPIO pins to be routed through EXTI to other tasks, by name
+    #[serde(default)]
+    gpio_irqs: BTreeMap<String, GpioIrqConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct GpioIrqConfig {
+    port: char,
+    pin: u8,
+    owner: GpioIrqOwner,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct GpioIrqOwner {
+    name: String,
+    notification: String,
+}
+
+fn main() -> Result<()> {
+    build_util::idol::build_server_support(
         "../../idl/stm32xx-sys.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+    )
+    .unwrap();
+
+    let cfg = build_util::task_maybe_config::<SysConfig>()?.unwrap_or_default();
+
+    if build_util::has_feature("exti") {
+        build_util::build_notifications()?;
+        generate_exti_config(&cfg)?;
+    } else if !cfg.gpio_irqs.is_empty() {
+        bail!("`gpio-irqs` are configured, but the `exti` feature is not");
+    }
+
+    Ok(())
+}
+
+fn generate_exti_config(cfg: &SysConfig) -> Result<()> {
+    let task_ids = build_util::task_ids();
+
+    // Each EXTI line serves the pin of the same number on one port.
+    let mut lines: [Option<(&str, &GpioIrqConfig)>; 16] = Default::default();
+    for (name, irq) in &cfg.gpio_irqs {
+        if irq.pin >= 16 {
+            bail!("gpio-irq {name}: pin {} is out of range", irq.pin);
+        }
+        if !irq.port.is_ascii_uppercase() {
+            bail!("gpio-irq {name}: bad port {:?}", irq.port);
+        }
+        if task_ids.get(&irq.owner.name).is_none() {
+            bail!("gpio-irq {name}: no such task {:?}", irq.owner.name);
+        }
+        let line = &mut lines[usize::from(irq.pin)];
+        if let Some((other, _)) = line {
+            bail!(
+                "gpio-irqs {other} and {name} are both on pin {}; only one \
+                 port's pin can use each EXTI line",
+                irq.pin
+            );
+        }
+        *line = Some((name, irq));
+    }
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("exti_config.rs");
+    let mut out =
+        std::fs::File::create(dest_path).context("creating exti_config.rs")?;
+
+    writeln!(
+        out,
+        "pub(crate) const EXTI_DISPATCH_TABLE: [Option<ExtiDispatch>; 16] = ["
     )?;
+    for line in lines {
+        match line {
+            None => writeln!(out, "    None,")?,
+            Some((name, irq)) => {
+                let owner = &irq.owner.name;
+                writeln!(out, "    // {name}")?;
+                writeln!(out, "    Some(ExtiDispatch {{")?;
+                writeln!(out, "        port: Port::{},", irq.port)?;
+                writeln!(
+                    out,
+                    "        task: hubris_num_tasks::Task::{owner},"
+                )?;
+                writeln!(
+                    out,
+                    "        mask: crate::notifications::{owner}::{}_MASK,",
+                    irq.owner
+                        .notification
+                        .to_ascii_uppercase()
+                        .replace('-', "_")
+                )?;
+                writeln!(out, "    }}),")?;
+            }
+        }
+    }
+    writeln!(out, "];")?;
 
     Ok(())
 }
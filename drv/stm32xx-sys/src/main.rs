@@ -3,6 +3,24 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! A driver for the STM32xx RCC and GPIO blocks, combined for compactness.
+//!
+//! # GPIO interrupts
+//!
+//! With the `exti` feature, this task also owns the EXTI block, and routes
+//! edges on GPIO pins to the tasks that care about them. Pins are assigned to
+//! tasks in the app config:
+//!
+//! ```toml
+//! [tasks.sys.config.gpio-irqs.rot_irq]
+//! port = "E"
+//! pin = 3
+//! owner = {name = "sprot", notification = "rot-irq"}
+//! ```
+//!
+//! The owner then picks which edges it wants with `gpio_irq_configure` and
+//! unmasks the interrupt with `gpio_irq_control`, both of which name the pin
+//! by the owner's own notification bit. When the edge occurs, we post that
+//! notification to the owner. Only the owner may configure or mask a pin.
 
 #![no_std]
 #![no_main]
@@ -36,7 +54,7 @@ cfg_if::cfg_if! {
 }
 
 use drv_stm32xx_gpio_common::{server::get_gpio_regs, Port};
-use drv_stm32xx_sys_api::{Group, RccError};
+use drv_stm32xx_sys_api::{Edge, GpioIrqError, Group, RccError};
 use idol_runtime::RequestError;
 use task_jefe_api::{Jefe, ResetReason};
 use userlib::*;
@@ -136,16 +154,28 @@ fn main() -> ! {
         Jefe::from(JEFE.get_task_id()).set_reset_reason(reason);
     }
 
+    #[cfg(feature = "exti")]
+    let exti = exti::init(rcc);
+
     // Field messages.
     let mut buffer = [0u8; idl::INCOMING_SIZE];
-    let mut server = ServerImpl { rcc };
+    let mut server = ServerImpl {
+        rcc,
+        #[cfg(feature = "exti")]
+        exti,
+    };
     loop {
+        #[cfg(feature = "exti")]
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+        #[cfg(not(feature = "exti"))]
         idol_runtime::dispatch(&mut buffer, &mut server);
     }
 }
 
 struct ServerImpl<'a> {
     rcc: &'a device::rcc::RegisterBlock,
+    #[cfg(feature = "exti")]
+    exti: &'a device::exti::RegisterBlock,
 }
 
 impl ServerImpl<'_> {
@@ -242,6 +272,51 @@ impl idl::InOrderSysImpl for ServerImpl<'_> {
         Ok(unsafe { get_gpio_regs(port) }.read())
     }
 
+    fn gpio_irq_configure(
+        &mut self,
+        rm: &RecvMessage,
+        mask: u32,
+        sensitivity: Edge,
+    ) -> Result<(), RequestError<GpioIrqError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "exti")] {
+                let lines = exti::owned_lines(rm.sender, mask)?;
+                exti::configure(self.exti, lines, sensitivity);
+                Ok(())
+            } else {
+                let _ = (rm, sensitivity);
+                if mask == 0 {
+                    Ok(())
+                } else {
+                    Err(GpioIrqError::NotOwner.into())
+                }
+            }
+        }
+    }
+
+    fn gpio_irq_control(
+        &mut self,
+        rm: &RecvMessage,
+        disable_mask: u32,
+        enable_mask: u32,
+    ) -> Result<(), RequestError<GpioIrqError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "exti")] {
+                let disable = exti::owned_lines(rm.sender, disable_mask)?;
+                let enable = exti::owned_lines(rm.sender, enable_mask)?;
+                exti::control(self.exti, disable, enable);
+                Ok(())
+            } else {
+                let _ = rm;
+                if disable_mask | enable_mask == 0 {
+                    Ok(())
+                } else {
+                    Err(GpioIrqError::NotOwner.into())
+                }
+            }
+        }
+    }
+
     fn read_uid(
         &mut self,
         _: &RecvMessage,
@@ -476,8 +551,167 @@ cfg_if::cfg_if! {
     }
 }
 
+#[cfg(feature = "exti")]
+impl idol_runtime::NotificationHandler for ServerImpl<'_> {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::EXTI_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::EXTI_IRQ_MASK != 0 {
+            exti::dispatch(self.exti);
+        }
+    }
+}
+
+#[cfg(feature = "exti")]
+mod exti {
+    use super::{device, FlagsRegister};
+    use drv_stm32xx_sys_api::{Edge, GpioIrqError, Port};
+    use userlib::*;
+
+    #[cfg(not(feature = "family-stm32h7"))]
+    compile_error!("EXTI routing is only implemented for STM32H7");
+
+    pub(crate) struct ExtiDispatch {
+        pub(crate) port: Port,
+        pub(crate) task: hubris_num_tasks::Task,
+        /// Notification bit(s) to post to `task`
+        pub(crate) mask: u32,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/exti_config.rs"));
+
+    /// Routes each configured EXTI line to its port, leaving them all masked
+    /// and with no edges selected until their owners ask otherwise.
+    pub(crate) fn init(
+        rcc: &device::rcc::RegisterBlock,
+    ) -> &'static device::exti::RegisterBlock {
+        rcc.apb4enr.modify(|_, w| w.syscfgen().set_bit());
+
+        // Safety: as with the RCC, these are essentially statics, and we only
+        // touch them through shared references.
+        let syscfg = unsafe { &*device::SYSCFG::ptr() };
+        let exti = unsafe { &*device::EXTI::ptr() };
+
+        for (line, entry) in EXTI_DISPATCH_TABLE.iter().enumerate() {
+            let Some(entry) = entry else { continue };
+
+            // Each EXTICRn selects the port for four lines, four bits apiece.
+            let shift = (line % 4) * 4;
+            let port = entry.port as u32;
+            macro_rules! select {
+                ($reg:expr) => {
+                    $reg.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(0xF << shift) | port << shift)
+                    })
+                };
+            }
+            match line / 4 {
+                0 => select!(syscfg.exticr1),
+                1 => select!(syscfg.exticr2),
+                2 => select!(syscfg.exticr3),
+                _ => select!(syscfg.exticr4),
+            }
+
+            // Safety: these registers only affect interrupt generation.
+            unsafe {
+                exti.cpuimr1.clear_bit(line as u8);
+                exti.rtsr1.clear_bit(line as u8);
+                exti.ftsr1.clear_bit(line as u8);
+            }
+        }
+
+        sys_irq_control(crate::notifications::EXTI_IRQ_MASK, true);
+        exti
+    }
+
+    /// Returns the EXTI lines corresponding to the notification bits in
+    /// `mask`, or an error unless `caller` owns all of them.
+    pub(crate) fn owned_lines(
+        caller: TaskId,
+        mask: u32,
+    ) -> Result<u16, GpioIrqError> {
+        let mut lines = 0;
+        let mut owned = 0;
+        for (line, entry) in EXTI_DISPATCH_TABLE.iter().enumerate() {
+            if let Some(entry) = entry {
+                if entry.task as usize == caller.index()
+                    && entry.mask & mask != 0
+                {
+                    lines |= 1 << line;
+                    owned |= entry.mask;
+                }
+            }
+        }
+        if mask & !owned != 0 {
+            return Err(GpioIrqError::NotOwner);
+        }
+        Ok(lines)
+    }
+
+    pub(crate) fn configure(
+        exti: &device::exti::RegisterBlock,
+        lines: u16,
+        sensitivity: Edge,
+    ) {
+        let rising = matches!(sensitivity, Edge::Rising | Edge::Both);
+        let falling = matches!(sensitivity, Edge::Falling | Edge::Both);
+        for line in (0..16).filter(|i| lines & (1 << i) != 0) {
+            // Safety: these registers only affect interrupt generation.
+            unsafe {
+                if rising {
+                    exti.rtsr1.set_bit(line);
+                } else {
+                    exti.rtsr1.clear_bit(line);
+                }
+                if falling {
+                    exti.ftsr1.set_bit(line);
+                } else {
+                    exti.ftsr1.clear_bit(line);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn control(
+        exti: &device::exti::RegisterBlock,
+        disable: u16,
+        enable: u16,
+    ) {
+        let disable = u32::from(disable);
+        let enable = u32::from(enable);
+        exti.cpuimr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !disable | enable) });
+    }
+
+    /// Forwards pending edges to their owners, and re-enables our interrupts.
+    pub(crate) fn dispatch(exti: &device::exti::RegisterBlock) {
+        let pending = exti.cpupr1.read().bits() & 0xFFFF;
+        // Write-one-to-clear.
+        exti.cpupr1.write(|w| unsafe { w.bits(pending) });
+
+        for (line, entry) in EXTI_DISPATCH_TABLE.iter().enumerate() {
+            let Some(entry) = entry else { continue };
+            if pending & (1 << line) != 0 {
+                let task = TaskId::for_index_and_gen(
+                    entry.task as usize,
+                    Generation::ZERO,
+                );
+                let task = sys_refresh_task_id(task);
+                sys_post(task, entry.mask);
+            }
+        }
+
+        sys_irq_control(crate::notifications::EXTI_IRQ_MASK, true);
+    }
+}
+
 mod idl {
-    use super::{Port, RccError};
+    use super::{Edge, GpioIrqError, Port, RccError};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
+
+#[cfg(feature = "exti")]
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
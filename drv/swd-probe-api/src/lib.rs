@@ -0,0 +1,54 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for a debug probe, which drives another chip's SWD port.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+pub use swd::{dhcsr, vector_catch};
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[repr(u32)]
+pub enum ProbeError {
+    /// `attach` hasn't been called, or failed.
+    NotAttached = 1,
+    /// The target didn't answer; it may be unpowered or not connected.
+    NoResponse,
+    /// The target kept answering WAIT.
+    Busy,
+    /// The target refused the access, usually because the address is bad.
+    Fault,
+    /// Data from the target was corrupted on the wire.
+    Parity,
+    /// The core didn't halt, or didn't finish a register transfer.
+    Timeout,
+    /// Core registers can only be reached while the core is halted.
+    NotHalted,
+    BadRegister,
+    /// Addresses and lengths must be multiples of 4.
+    Unaligned,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+impl From<swd::Error> for ProbeError {
+    fn from(e: swd::Error) -> Self {
+        match e {
+            swd::Error::NoResponse => Self::NoResponse,
+            swd::Error::Wait => Self::Busy,
+            swd::Error::Fault => Self::Fault,
+            swd::Error::Parity => Self::Parity,
+            swd::Error::Timeout => Self::Timeout,
+            swd::Error::NotHalted => Self::NotHalted,
+            swd::Error::BadRegister => Self::BadRegister,
+            swd::Error::Unaligned => Self::Unaligned,
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
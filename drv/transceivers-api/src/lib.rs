@@ -20,6 +20,10 @@ pub enum TransceiversError {
     InvalidPowerState,
     InvalidModuleResult,
     LedI2cError,
+    /// The module did not complete an I2C transaction successfully
+    ModuleI2cError,
+    /// The module's management interface is unknown or not yet determined
+    UnsupportedInterface,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -59,6 +63,56 @@ pub const PAGE_SIZE_BYTES: usize = 128;
 /// ports.
 pub const NUM_PORTS: u8 = 32;
 
+/// Maximum number of lanes (channels) of a module for which we report monitors
+///
+/// SFF-8636 modules have 4 lanes, CMIS modules up to 8.
+pub const MAX_LANES: usize = 8;
+
+/// Digital optical monitors (DOM) of a module, as read from its memory map
+///
+/// Per-lane values are only valid for the first `lanes` entries; modules with
+/// a flat memory map (e.g. passive copper cables) report no lanes.
+#[derive(Copy, Clone, Debug, Default, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct ModuleMonitors {
+    /// Module temperature, in degrees Celsius
+    pub temperature: f32,
+    /// Module supply voltage, in volts
+    pub supply_voltage: f32,
+    /// Received optical power per lane, in milliwatts
+    pub rx_power: [f32; MAX_LANES],
+    /// Transmitted optical power per lane, in milliwatts
+    pub tx_power: [f32; MAX_LANES],
+    /// Number of lanes for which power monitors are valid
+    pub lanes: u8,
+    _pad: [u8; 3],
+}
+
+impl ModuleMonitors {
+    pub fn new(temperature: f32, supply_voltage: f32) -> Self {
+        Self {
+            temperature,
+            supply_voltage,
+            ..Default::default()
+        }
+    }
+}
+
+/// Operational state of a module, following the CMIS module state machine
+///
+/// SFF-8636 modules have no such state machine; their state is derived from
+/// the power override controls and the data-not-ready flag.
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, AsBytes)]
+#[repr(u8)]
+pub enum ModuleState {
+    Unknown = 0,
+    LowPower = 1,
+    PoweringUp = 2,
+    Ready = 3,
+    PoweringDown = 4,
+    Fault = 5,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub const TRANSCEIVER_TEMPERATURE_SENSORS: [SensorId; NUM_PORTS as usize] = [
@@ -95,6 +149,41 @@ pub const TRANSCEIVER_TEMPERATURE_SENSORS: [SensorId; NUM_PORTS as usize] = [
     other_sensors::QSFP_XCVR30_TEMPERATURE_SENSOR,
     other_sensors::QSFP_XCVR31_TEMPERATURE_SENSOR,
 ];
+
+pub const TRANSCEIVER_VOLTAGE_SENSORS: [SensorId; NUM_PORTS as usize] = [
+    other_sensors::QSFP_XCVR0_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR1_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR2_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR3_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR4_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR5_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR6_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR7_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR8_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR9_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR10_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR11_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR12_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR13_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR14_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR15_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR16_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR17_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR18_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR19_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR20_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR21_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR22_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR23_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR24_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR25_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR26_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR27_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR28_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR29_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR30_VOLTAGE_SENSOR,
+    other_sensors::QSFP_XCVR31_VOLTAGE_SENSOR,
+];
 ////////////////////////////////////////////////////////////////////////////////
 
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -15,11 +15,12 @@ use drv_sidecar_front_io::{
 };
 use drv_sidecar_seq_api::{SeqError, Sequencer};
 use drv_transceivers_api::{
-    ModuleStatus, TransceiversError, NUM_PORTS, PAGE_SIZE_BYTES,
-    TRANSCEIVER_TEMPERATURE_SENSORS,
+    ModuleMonitors, ModuleState, ModuleStatus, TransceiversError, NUM_PORTS,
+    PAGE_SIZE_BYTES, TRANSCEIVER_TEMPERATURE_SENSORS,
+    TRANSCEIVER_VOLTAGE_SENSORS,
 };
 use idol_runtime::{
-    ClientError, Leased, NotificationHandler, RequestError, R, W,
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
 };
 use ringbuf::*;
 use task_sensor_api::{NoData, Sensor, SensorError};
@@ -30,6 +31,7 @@ use transceiver_messages::{
 use userlib::{units::Celsius, *};
 use zerocopy::{AsBytes, FromBytes};
 
+mod module; // Memory map access to individual modules
 mod udp; // UDP API is implemented in a separate file
 
 task_slot!(I2C, i2c_driver);
@@ -62,6 +64,7 @@ enum Trace {
     UnpluggedModule(usize),
     TemperatureReadError(usize, Reg::QSFP::PORT0_STATUS::Encoded),
     TemperatureReadUnexpectedError(usize, FpgaError),
+    SupplyVoltageReadError(usize, FpgaError),
     SensorError(usize, SensorError),
     ThermalError(usize, ThermalError),
     GetInterfaceError(usize, Reg::QSFP::PORT0_STATUS::Encoded),
@@ -210,6 +213,37 @@ impl ServerImpl {
         port: LogicalPort,
         reg: u8,
     ) -> Result<Celsius, FpgaError> {
+        // "Internally measured free side device temperatures are
+        // represented as a 16-bit signed twos complement value in
+        // increments of 1/256 degrees Celsius"
+        //
+        // - SFF-8636 rev 2.10a, Section 6.2.4
+        let t = self.read_u16(port, reg)?;
+        Ok(Celsius(t as i16 as f32 / 256.0))
+    }
+
+    /// Returns the supply voltage (in volts) of a transceiver.
+    ///
+    /// `port` is a logical port index, i.e. 0-31.
+    fn read_supply_voltage(
+        &self,
+        port: LogicalPort,
+        interface: ManagementInterface,
+    ) -> Result<f32, FpgaError> {
+        const CMIS_SUPPLY_VOLTAGE_MSB: u8 = 16; // CMIS, Table 8-9
+        const SFF8636_SUPPLY_VOLTAGE_MSB: u8 = 26; // SFF-8636, Table 6-7
+
+        let reg = match interface {
+            ManagementInterface::Cmis => CMIS_SUPPLY_VOLTAGE_MSB,
+            _ => SFF8636_SUPPLY_VOLTAGE_MSB,
+        };
+        let v = self.read_u16(port, reg)?;
+        Ok(module::supply_voltage(&v.to_be_bytes()))
+    }
+
+    /// Trigger a read from the given port's given register, which is assumed to
+    /// be a big-endian `u16`.
+    fn read_u16(&self, port: LogicalPort, reg: u8) -> Result<u16, FpgaError> {
         let result = self.transceivers.setup_i2c_read(reg, 2, port.as_mask());
         if !result.error().is_empty() {
             return Err(FpgaError::CommsError);
@@ -217,25 +251,20 @@ impl ServerImpl {
 
         #[derive(Copy, Clone, FromBytes, AsBytes)]
         #[repr(C)]
-        struct StatusAndTemperature {
+        struct StatusAndValue {
             status: u8,
-            temperature: zerocopy::I16<zerocopy::BigEndian>,
+            value: zerocopy::U16<zerocopy::BigEndian>,
         }
 
         loop {
-            let mut out = StatusAndTemperature::new_zeroed();
+            let mut out = StatusAndValue::new_zeroed();
             self.transceivers
                 .get_i2c_status_and_read_buffer(port, out.as_bytes_mut())?;
             if out.status & Reg::QSFP::PORT0_STATUS::BUSY == 0 {
                 if out.status & Reg::QSFP::PORT0_STATUS::ERROR != 0 {
                     return Err(FpgaError::ImplError(out.status));
                 } else {
-                    // "Internally measured free side device temperatures are
-                    // represented as a 16-bit signed twos complement value in
-                    // increments of 1/256 degrees Celsius"
-                    //
-                    // - SFF-8636 rev 2.10a, Section 6.2.4
-                    return Ok(Celsius(out.temperature.get() as f32 / 256.0));
+                    return Ok(out.value.get());
                 }
             }
             userlib::hl::sleep_for(1);
@@ -352,11 +381,16 @@ impl ServerImpl {
                 }
 
                 // Tell the `sensor` task that this device is no longer present
-                if let Err(e) = self.sensor_api.nodata_now(
+                for sensor in [
                     TRANSCEIVER_TEMPERATURE_SENSORS[i],
-                    NoData::DeviceNotPresent,
-                ) {
-                    ringbuf_entry!(Trace::SensorError(i, e));
+                    TRANSCEIVER_VOLTAGE_SENSORS[i],
+                ] {
+                    if let Err(e) = self
+                        .sensor_api
+                        .nodata_now(sensor, NoData::DeviceNotPresent)
+                    {
+                        ringbuf_entry!(Trace::SensorError(i, e));
+                    }
                 }
 
                 ringbuf_entry!(Trace::UnpluggedModule(i));
@@ -424,6 +458,18 @@ impl ServerImpl {
                     ringbuf_entry!(Trace::TemperatureReadUnexpectedError(i, e));
                 }
             }
+
+            match self.read_supply_voltage(port, m.interface) {
+                Ok(v) => {
+                    if let Err(e) = self
+                        .sensor_api
+                        .post_now(TRANSCEIVER_VOLTAGE_SENSORS[i], v)
+                    {
+                        ringbuf_entry!(Trace::SensorError(i, e));
+                    }
+                }
+                Err(e) => ringbuf_entry!(Trace::SupplyVoltageReadError(i, e)),
+            }
         }
     }
 }
@@ -628,6 +674,109 @@ impl idl::InOrderTransceiversImpl for ServerImpl {
         }
     }
 
+    fn read_module_memory(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        logical_port: u8,
+        page: u8,
+        offset: u8,
+        dest: LenLimit<Leased<W, [u8]>, PAGE_SIZE_BYTES>,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        if logical_port >= NUM_PORTS {
+            return Err(TransceiversError::InvalidPortNumber.into());
+        }
+
+        let mut buf = [0u8; PAGE_SIZE_BYTES];
+        let buf = &mut buf[..dest.len()];
+
+        self.read_module(LogicalPort(logical_port), page, offset, buf)?;
+
+        dest.write_range(0..dest.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(())
+    }
+
+    fn write_module_memory(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        logical_port: u8,
+        page: u8,
+        offset: u8,
+        data: LenLimit<Leased<R, [u8]>, PAGE_SIZE_BYTES>,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        if logical_port >= NUM_PORTS {
+            return Err(TransceiversError::InvalidPortNumber.into());
+        }
+
+        let mut buf = [0u8; PAGE_SIZE_BYTES];
+        let buf = &mut buf[..data.len()];
+
+        data.read_range(0..data.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        self.write_module(LogicalPort(logical_port), page, offset, buf)
+            .map_err(RequestError::from)
+    }
+
+    fn get_module_monitors(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        logical_port: u8,
+    ) -> Result<ModuleMonitors, idol_runtime::RequestError<TransceiversError>>
+    {
+        if logical_port >= NUM_PORTS {
+            return Err(TransceiversError::InvalidPortNumber.into());
+        }
+
+        self.read_module_monitors(LogicalPort(logical_port))
+            .map_err(RequestError::from)
+    }
+
+    fn get_module_state(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        logical_port: u8,
+    ) -> Result<ModuleState, idol_runtime::RequestError<TransceiversError>>
+    {
+        if logical_port >= NUM_PORTS {
+            return Err(TransceiversError::InvalidPortNumber.into());
+        }
+
+        self.read_module_state(LogicalPort(logical_port))
+            .map_err(RequestError::from)
+    }
+
+    fn set_module_low_power(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        logical_port: u8,
+        low_power: bool,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        if logical_port >= NUM_PORTS {
+            return Err(TransceiversError::InvalidPortNumber.into());
+        }
+
+        ServerImpl::set_module_low_power(
+            self,
+            LogicalPort(logical_port),
+            low_power,
+        )
+        .map_err(RequestError::from)
+    }
+
+    fn reset_module(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        logical_port: u8,
+    ) -> Result<(), idol_runtime::RequestError<TransceiversError>> {
+        if logical_port >= NUM_PORTS {
+            return Err(TransceiversError::InvalidPortNumber.into());
+        }
+
+        ServerImpl::reset_module(self, LogicalPort(logical_port))
+            .map_err(RequestError::from)
+    }
+
     fn set_port_led_on(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -759,10 +908,11 @@ fn main() -> ! {
                     ringbuf_entry!(Trace::FrontIOReady(true));
                     break;
                 }
-                Err(SeqError::NoFrontIOBoard) => {
-                    ringbuf_entry!(Trace::FrontIOSeqErr(
-                        SeqError::NoFrontIOBoard
-                    ));
+                Err(
+                    e @ (SeqError::NoFrontIOBoard
+                    | SeqError::FrontIOBoardFailed),
+                ) => {
+                    ringbuf_entry!(Trace::FrontIOSeqErr(e));
                     break;
                 }
                 _ => {
@@ -836,7 +986,7 @@ pub fn claim_statics() -> (
 ////////////////////////////////////////////////////////////////////////////////
 
 mod idl {
-    use super::{ModuleStatus, TransceiversError};
+    use super::{ModuleMonitors, ModuleState, ModuleStatus, TransceiversError};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
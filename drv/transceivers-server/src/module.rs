@@ -0,0 +1,398 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Management of individual modules through their memory map
+//!
+//! This adds functions to our existing `ServerImpl` for paged access to a
+//! single module's SFF-8636 or CMIS memory map, and builds reading digital
+//! optical monitors and managing the module's power state on top of that.
+//!
+//! Only bank 0 of banked CMIS pages is supported, which covers modules with
+//! up to 8 lanes.
+use crate::ServerImpl;
+use drv_fpga_api::FpgaError;
+use drv_sidecar_front_io::{
+    transceivers::{LogicalPort, LogicalPortMask, ModuleResult},
+    Reg,
+};
+use drv_transceivers_api::{
+    ModuleMonitors, ModuleState, TransceiversError, MAX_LANES, PAGE_SIZE_BYTES,
+};
+use transceiver_messages::mgmt::ManagementInterface;
+use userlib::FromPrimitive;
+
+/// Size of the memory map addressable in a single page
+const MEMORY_MAP_SIZE: usize = 2 * PAGE_SIZE_BYTES;
+
+// Common to both CMIS and SFF-8636
+const BANK_SELECT: u8 = 0x7E;
+const PAGE_SELECT: u8 = 0x7F;
+
+// SFF-8636, Table 6-2 and 6-7
+const SFF8636_STATUS: u8 = 2;
+const SFF8636_DATA_NOT_READY: u8 = 1 << 0;
+const SFF8636_MONITORS: u8 = 22;
+const SFF8636_POWER_CONTROL: u8 = 93;
+const SFF8636_POWER_OVERRIDE: u8 = 1 << 0;
+const SFF8636_POWER_SET: u8 = 1 << 1;
+const SFF8636_LANES: usize = 4;
+
+// CMIS 5.0, Table 8-4, 8-6 and 8-10
+const CMIS_FLAT_MEM: u8 = 2;
+const CMIS_FLAT_MEM_BIT: u8 = 1 << 7;
+const CMIS_MODULE_STATE: u8 = 3;
+const CMIS_MONITORS: u8 = 14;
+const CMIS_MODULE_GLOBAL_CONTROLS: u8 = 26;
+const CMIS_LOW_PWR_REQUEST_SW: u8 = 1 << 4;
+
+// CMIS 5.0, Table 8-79: lane monitors are in page 11h
+const CMIS_LANE_MONITORS_PAGE: u8 = 0x11;
+const CMIS_LANE_MONITORS: u8 = 154;
+
+/// Converts a big-endian optical power reading, in units of 0.1 µW, into mW.
+fn optical_power(b: &[u8]) -> f32 {
+    f32::from(u16::from_be_bytes([b[0], b[1]])) / 10_000.0
+}
+
+/// Converts a big-endian temperature reading, in units of 1/256 °C.
+fn temperature(b: &[u8]) -> f32 {
+    f32::from(i16::from_be_bytes([b[0], b[1]])) / 256.0
+}
+
+/// Converts a big-endian supply voltage reading, in units of 100 µV, into V.
+pub(crate) fn supply_voltage(b: &[u8]) -> f32 {
+    f32::from(u16::from_be_bytes([b[0], b[1]])) / 10_000.0
+}
+
+/// Converts the result of an operation into an error for the modules in
+/// `mask`, ignoring any others the operation may have affected.
+fn check<R: Into<ModuleResult>>(
+    result: R,
+    mask: LogicalPortMask,
+) -> Result<(), TransceiversError> {
+    let result = result.into();
+    if !(result.error() & mask).is_empty() {
+        Err(TransceiversError::FpgaError)
+    } else if !(result.failure() & mask).is_empty() {
+        Err(TransceiversError::ModuleI2cError)
+    } else {
+        Ok(())
+    }
+}
+
+impl ServerImpl {
+    /// Selects the given page and (CMIS only) bank for each module in `mask`.
+    ///
+    /// When `page` is `None`, only the lower memory is accessible and nothing
+    /// needs to be done.
+    pub(crate) fn select_page_and_bank(
+        &mut self,
+        page: Option<u8>,
+        bank: Option<u8>,
+        mask: LogicalPortMask,
+    ) -> ModuleResult {
+        // If the request is to the lower page it is always successful
+        let mut result =
+            ModuleResult::new(mask, LogicalPortMask(0), LogicalPortMask(0))
+                .unwrap();
+
+        // We can always write the lower page; upper pages require modifying
+        // registers in the transceiver to select it.
+        if let Some(page) = page {
+            self.transceivers.set_i2c_write_buffer(&[page]);
+            result = result.chain(self.transceivers.setup_i2c_write(
+                PAGE_SELECT,
+                1,
+                mask,
+            ));
+            result = result
+                .chain(self.transceivers.wait_and_check_i2c(result.success()));
+        }
+
+        if let Some(bank) = bank {
+            self.transceivers.set_i2c_write_buffer(&[bank]);
+            result = result.chain(self.transceivers.setup_i2c_write(
+                BANK_SELECT,
+                1,
+                result.success(),
+            ));
+            result = result
+                .chain(self.transceivers.wait_and_check_i2c(result.success()));
+        }
+        result
+    }
+
+    /// Checks that an access of `len` bytes at `offset` stays within either
+    /// the lower memory or the upper page, returning the page to select.
+    fn page_for_access(
+        page: u8,
+        offset: u8,
+        len: usize,
+    ) -> Result<Option<u8>, TransceiversError> {
+        let start = usize::from(offset);
+        let end = start + len;
+        if len == 0
+            || end > MEMORY_MAP_SIZE
+            || (start < PAGE_SIZE_BYTES && end > PAGE_SIZE_BYTES)
+        {
+            return Err(TransceiversError::InvalidNumberOfBytes);
+        }
+
+        Ok(if start >= PAGE_SIZE_BYTES {
+            Some(page)
+        } else {
+            None
+        })
+    }
+
+    /// Reads `buf.len()` bytes from the memory map of a single module.
+    ///
+    /// Offsets of 128 and above address the upper memory of `page`; `page` is
+    /// ignored for accesses to the lower memory.
+    pub(crate) fn read_module(
+        &mut self,
+        port: LogicalPort,
+        page: u8,
+        offset: u8,
+        buf: &mut [u8],
+    ) -> Result<(), TransceiversError> {
+        let page = Self::page_for_access(page, offset, buf.len())?;
+        let mask = port.as_mask();
+
+        check(self.select_page_and_bank(page, None, mask), mask)?;
+        check(
+            self.transceivers
+                .setup_i2c_read(offset, buf.len() as u8, mask),
+            mask,
+        )?;
+
+        // The status register is contiguous with the read buffer, so we can
+        // read both in one go; this normally terminates with a single read,
+        // since I2C is faster than Hubris IPC.
+        let mut out = [0u8; PAGE_SIZE_BYTES + 1];
+        let out = &mut out[..buf.len() + 1];
+        loop {
+            self.transceivers
+                .get_i2c_status_and_read_buffer(port, out)
+                .map_err(TransceiversError::from)?;
+
+            let status = out[0];
+            if status & Reg::QSFP::PORT0_STATUS::BUSY == 0 {
+                if status & Reg::QSFP::PORT0_STATUS::ERROR != 0 {
+                    return Err(TransceiversError::ModuleI2cError);
+                }
+                buf.copy_from_slice(&out[1..]);
+                return Ok(());
+            }
+            userlib::hl::sleep_for(1);
+        }
+    }
+
+    /// Writes `data` to the memory map of a single module.
+    ///
+    /// Offsets of 128 and above address the upper memory of `page`; `page` is
+    /// ignored for accesses to the lower memory.
+    pub(crate) fn write_module(
+        &mut self,
+        port: LogicalPort,
+        page: u8,
+        offset: u8,
+        data: &[u8],
+    ) -> Result<(), TransceiversError> {
+        let page = Self::page_for_access(page, offset, data.len())?;
+        let mask = port.as_mask();
+
+        check(self.select_page_and_bank(page, None, mask), mask)?;
+
+        check(self.transceivers.set_i2c_write_buffer(data), mask)?;
+        check(
+            self.transceivers
+                .setup_i2c_write(offset, data.len() as u8, mask),
+            mask,
+        )?;
+        check(self.transceivers.wait_and_check_i2c(mask), mask)
+    }
+
+    /// Returns the management interface of a single module, using what we
+    /// learned when it was inserted if possible.
+    fn module_interface(
+        &mut self,
+        port: LogicalPort,
+    ) -> Result<ManagementInterface, TransceiversError> {
+        let interface = match self.thermal_models[usize::from(port.0)] {
+            Some(m) => m.interface,
+            None => {
+                self.get_transceiver_interface(port).map_err(|e| match e {
+                    FpgaError::ImplError(_) => {
+                        TransceiversError::ModuleI2cError
+                    }
+                    e => TransceiversError::from(e),
+                })?
+            }
+        };
+
+        match interface {
+            ManagementInterface::Sff8636 | ManagementInterface::Cmis => {
+                Ok(interface)
+            }
+            ManagementInterface::Unknown(..) => {
+                Err(TransceiversError::UnsupportedInterface)
+            }
+        }
+    }
+
+    /// Reads the digital optical monitors of a single module.
+    ///
+    /// This deliberately avoids reading the (clear-on-read) latched flags
+    /// which sit alongside the monitors in the lower memory.
+    pub(crate) fn read_module_monitors(
+        &mut self,
+        port: LogicalPort,
+    ) -> Result<ModuleMonitors, TransceiversError> {
+        match self.module_interface(port)? {
+            ManagementInterface::Sff8636 => {
+                // Temperature (22), supply voltage (26), then rx power (34),
+                // tx bias (42) and tx power (50) for each of 4 lanes.
+                let mut buf = [0u8; 36];
+                self.read_module(port, 0, SFF8636_MONITORS, &mut buf)?;
+
+                let mut m = ModuleMonitors::new(
+                    temperature(&buf),
+                    supply_voltage(&buf[4..]),
+                );
+                for lane in 0..SFF8636_LANES {
+                    m.rx_power[lane] = optical_power(&buf[12 + 2 * lane..]);
+                    m.tx_power[lane] = optical_power(&buf[28 + 2 * lane..]);
+                }
+                m.lanes = SFF8636_LANES as u8;
+                Ok(m)
+            }
+            ManagementInterface::Cmis => {
+                let mut buf = [0u8; 4];
+                self.read_module(port, 0, CMIS_MONITORS, &mut buf)?;
+                let mut m = ModuleMonitors::new(
+                    temperature(&buf),
+                    supply_voltage(&buf[2..]),
+                );
+
+                // Modules with a flat memory map (e.g. passive copper) have
+                // no page 11h and thus no lane monitors.
+                let mut flat = [0u8];
+                self.read_module(port, 0, CMIS_FLAT_MEM, &mut flat)?;
+                if flat[0] & CMIS_FLAT_MEM_BIT == 0 {
+                    // Tx power (154), tx bias (170), then rx power (186) for
+                    // each of 8 lanes.
+                    let mut buf = [0u8; 48];
+                    self.read_module(
+                        port,
+                        CMIS_LANE_MONITORS_PAGE,
+                        CMIS_LANE_MONITORS,
+                        &mut buf,
+                    )?;
+                    for lane in 0..MAX_LANES {
+                        m.tx_power[lane] = optical_power(&buf[2 * lane..]);
+                        m.rx_power[lane] = optical_power(&buf[32 + 2 * lane..]);
+                    }
+                    m.lanes = MAX_LANES as u8;
+                }
+                Ok(m)
+            }
+            ManagementInterface::Unknown(..) => unreachable!(),
+        }
+    }
+
+    /// Reads the operational state of a single module.
+    pub(crate) fn read_module_state(
+        &mut self,
+        port: LogicalPort,
+    ) -> Result<ModuleState, TransceiversError> {
+        match self.module_interface(port)? {
+            ManagementInterface::Sff8636 => {
+                let mut status = [0u8];
+                self.read_module(port, 0, SFF8636_STATUS, &mut status)?;
+                if status[0] & SFF8636_DATA_NOT_READY != 0 {
+                    return Ok(ModuleState::PoweringUp);
+                }
+
+                // Without a software override, power is controlled by the
+                // LpMode signal.
+                let mut control = [0u8];
+                self.read_module(port, 0, SFF8636_POWER_CONTROL, &mut control)?;
+                let low_power = if control[0] & SFF8636_POWER_OVERRIDE != 0 {
+                    control[0] & SFF8636_POWER_SET != 0
+                } else {
+                    let (status, result) =
+                        self.transceivers.get_module_status();
+                    check(result, port.as_mask())?;
+                    status.lpmode_txdis & (1 << port.0) != 0
+                };
+
+                Ok(if low_power {
+                    ModuleState::LowPower
+                } else {
+                    ModuleState::Ready
+                })
+            }
+            ManagementInterface::Cmis => {
+                let mut state = [0u8];
+                self.read_module(port, 0, CMIS_MODULE_STATE, &mut state)?;
+                Ok(ModuleState::from_u8((state[0] >> 1) & 0b111)
+                    .unwrap_or(ModuleState::Unknown))
+            }
+            ManagementInterface::Unknown(..) => unreachable!(),
+        }
+    }
+
+    /// Requests that a single module enter or leave low power mode through its
+    /// memory map, overriding the LpMode signal where the module requires it.
+    pub(crate) fn set_module_low_power(
+        &mut self,
+        port: LogicalPort,
+        low_power: bool,
+    ) -> Result<(), TransceiversError> {
+        let (reg, set, clear) = match self.module_interface(port)? {
+            ManagementInterface::Sff8636 => {
+                if low_power {
+                    (
+                        SFF8636_POWER_CONTROL,
+                        SFF8636_POWER_OVERRIDE | SFF8636_POWER_SET,
+                        0,
+                    )
+                } else {
+                    (
+                        SFF8636_POWER_CONTROL,
+                        SFF8636_POWER_OVERRIDE,
+                        SFF8636_POWER_SET,
+                    )
+                }
+            }
+            ManagementInterface::Cmis => {
+                if low_power {
+                    (CMIS_MODULE_GLOBAL_CONTROLS, CMIS_LOW_PWR_REQUEST_SW, 0)
+                } else {
+                    (CMIS_MODULE_GLOBAL_CONTROLS, 0, CMIS_LOW_PWR_REQUEST_SW)
+                }
+            }
+            ManagementInterface::Unknown(..) => unreachable!(),
+        };
+
+        let mut value = [0u8];
+        self.read_module(port, 0, reg, &mut value)?;
+        value[0] = (value[0] | set) & !clear;
+        self.write_module(port, 0, reg, &value)
+    }
+
+    /// Pulses ResetL of a single module.
+    pub(crate) fn reset_module(
+        &mut self,
+        port: LogicalPort,
+    ) -> Result<(), TransceiversError> {
+        let mask = port.as_mask();
+
+        // SFF-8636 and CMIS both require ResetL be asserted for at least 10 us
+        check(self.transceivers.assert_reset(mask), mask)?;
+        userlib::hl::sleep_for(1);
+        check(self.transceivers.deassert_reset(mask), mask)
+    }
+}
@@ -747,41 +747,7 @@ impl ServerImpl {
         page: Page,
         mask: LogicalPortMask,
     ) -> ModuleResult {
-        // Common to both CMIS and SFF-8636
-        const BANK_SELECT: u8 = 0x7E;
-        const PAGE_SELECT: u8 = 0x7F;
-
-        let mut result =
-            ModuleResult::new(mask, LogicalPortMask(0), LogicalPortMask(0))
-                .unwrap();
-
-        // We can always write the lower page; upper pages require modifying
-        // registers in the transceiver to select it.
-        if let Some(page) = page.page() {
-            self.transceivers.set_i2c_write_buffer(&[page]);
-            result = result.chain(self.transceivers.setup_i2c_write(
-                PAGE_SELECT,
-                1,
-                mask,
-            ));
-            result = result.chain(self.wait_and_check_i2c(result.success()));
-        } else {
-            // If the request is to the lower page it is always successful
-            result =
-                ModuleResult::new(mask, LogicalPortMask(0), LogicalPortMask(0))
-                    .unwrap();
-        }
-
-        if let Some(bank) = page.bank() {
-            self.transceivers.set_i2c_write_buffer(&[bank]);
-            result = result.chain(self.transceivers.setup_i2c_write(
-                BANK_SELECT,
-                1,
-                result.success(),
-            ));
-            result = result.chain(self.wait_and_check_i2c(result.success()));
-        }
-        result
+        self.select_page_and_bank(page.page(), page.bank(), mask)
     }
 
     // Polls the status register for each module in the mask. The returned
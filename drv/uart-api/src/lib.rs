@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! API crate for UART servers.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum UartError {
+    /// The transmit queue is full; wait for a notification and try again.
+    TxFull = 1,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Line events counted by the server.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct UartEvents {
+    /// Breaks received from the other end.
+    pub breaks: u32,
+    /// Bytes the USART dropped because its receive FIFO was full. With DMA
+    /// reception this should never happen.
+    pub hw_overruns: u32,
+    /// Bytes overwritten in the receive ring before anyone read them.
+    pub bytes_lost: u32,
+    pub noise_errors: u32,
+    pub parity_errors: u32,
+    /// Times the receive DMA stream had to be restarted.
+    pub dma_errors: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -3,6 +3,9 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::client::build_client_stub("../../idl/update.idol", "client_stub.rs")?;
+    build_util::idol::build_client_stub(
+        "../../idl/update.idol",
+        "client_stub.rs",
+    )?;
     Ok(())
 }
@@ -173,6 +173,40 @@ pub enum SlotId {
     B,
 }
 
+/// Where an SP image stands in its trial, after being installed by an update.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum SpTrial {
+    /// The image is trusted: it passed its trial, or wasn't installed by an
+    /// update (a debugger put it there).
+    Committed,
+    /// The image was installed by an update, and hasn't yet been healthy for
+    /// long enough to be committed.
+    Pending {
+        /// How many times the image has booted, including this one.
+        boots: u8,
+        /// How long the system has been healthy without a break, in
+        /// milliseconds.
+        healthy_ms: u64,
+    },
+    /// The image wasn't committed in time, so the other bank will boot
+    /// next.
+    Reverted,
+}
+
+/// The state of the SP's flash banks, as far as updates are concerned.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct SpImageState {
+    /// The physical bank we're running from, with `SlotId::A` being bank 1.
+    pub active: SlotId,
+    /// The physical bank that will boot next.
+    pub next_boot: SlotId,
+    pub trial: SpTrial,
+}
+
 pub mod stm32h7 {
     // RM0433 Rev 7 section 4.3.9
     // Flash word is defined as 256 bits
@@ -191,6 +225,31 @@ pub mod stm32h7 {
         FLASH_WORD_BYTES * FLASH_WORDS_PER_BLOCK;
 
     pub const BLOCK_SIZE_WORDS: usize = BLOCK_SIZE_BYTES / 4;
+
+    /// Bytes at the end of each flash bank that are kept out of images, and
+    /// hold the update server's records about the image in that bank.
+    ///
+    /// Each record is a flash word (which can only be written once between
+    /// erases) starting with a magic number, and the rest zero; a word that's
+    /// still erased holds no record. The first word is `TRAILER_PENDING`,
+    /// written to the bank an update has just been written to; the second is
+    /// `TRAILER_COMMITTED`, written once the image has passed its trial; and
+    /// each word after that is a `TRAILER_BOOT`, written each time a pending
+    /// image boots. A bank with no records holds a committed image.
+    pub const TRAILER_BYTES: usize = 256;
+
+    // "PEND", "COMT", and "BOOT", as big-endian ASCII.
+    pub const TRAILER_PENDING: u32 = 0x5045_4e44;
+    pub const TRAILER_COMMITTED: u32 = 0x434f_4d54;
+    pub const TRAILER_BOOT: u32 = 0x424f_4f54;
+
+    /// Index of the first `TRAILER_BOOT` word.
+    pub const TRAILER_FIRST_BOOT: usize = 2;
+
+    /// How many times a pending image can boot, which is how many
+    /// `TRAILER_BOOT` records fit.
+    pub const MAX_TRIAL_BOOTS: usize =
+        TRAILER_BYTES / FLASH_WORD_BYTES - TRAILER_FIRST_BOOT;
 }
 
 pub mod lpc55 {
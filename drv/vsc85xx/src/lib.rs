@@ -212,6 +212,21 @@ pub enum Counter {
     Value(u16),
 }
 
+/// Link-quality counts from a PHY, as returned by [Phy::link_quality].  The
+/// counts are eight bits wide, as the PHY keeps them.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LinkQuality {
+    /// Receive errors on the media
+    pub rx_errors: u8,
+    /// False carrier events on the media
+    pub false_carriers: u8,
+    /// Times the copper link has gone down
+    pub disconnects: u8,
+    /// Whether autonegotiation settled for a lower speed than advertised,
+    /// after failing to bring up the link at the higher one
+    pub downshifted: bool,
+}
+
 impl Default for Counter {
     fn default() -> Self {
         Self::Inactive
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::{Phy, PhyRw, Trace};
+use crate::{LinkQuality, Phy, PhyRw, Trace};
 
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
 use vsc7448_pac::{phy, types::PhyRegisterAddress};
@@ -24,6 +24,26 @@ impl<'a, P: PhyRw> Phy<'a, P> {
         })
     }
 
+    /// Reads the PHY's link-quality counts, which are common to the whole
+    /// VSC85xx family.  The error and disconnect counts are kept on the
+    /// copper media side; ports running fiber media leave them alone, and
+    /// their errors show up in the media SerDes counters instead.
+    pub fn link_quality(&self) -> Result<LinkQuality, VscError> {
+        // Error counters 1-3 (registers 19-21) and extended PHY control 3
+        // (register 20E1), which aren't in the PAC
+        let read = |page, addr| {
+            self.read(PhyRegisterAddress::<u16>::from_page_and_addr_unchecked(
+                page, addr,
+            ))
+        };
+        Ok(LinkQuality {
+            rx_errors: read(0, 19)? as u8,
+            false_carriers: read(0, 20)? as u8,
+            disconnects: read(0, 21)? as u8,
+            downshifted: read(1, 20)? & (1 << 1) != 0,
+        })
+    }
+
     /// The VSC85xx family supports sending commands to the system by writing to
     /// register 19G.  This helper function sends a command then waits for it
     /// to finish, return [VscError::PhyInitTimeout] if it fails (or another
@@ -0,0 +1,200 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Conversion between Unix time and the calendar dates kept by real-time
+//! clocks.
+//!
+//! RTCs count in years, months, days and so on, usually with a two-digit
+//! year, so we only deal with 2000 through 2099. Within that range every
+//! fourth year is a leap year, which keeps things simple. Leap seconds don't
+//! exist here, as they don't in Unix time.
+
+#![cfg_attr(not(test), no_std)]
+
+/// First year we can represent.
+pub const MIN_YEAR: u16 = 2000;
+/// Last year we can represent.
+pub const MAX_YEAR: u16 = 2099;
+
+const SECS_PER_DAY: u64 = 86_400;
+/// Days from 1970-01-01 to 2000-01-01.
+const EPOCH_2000_DAYS: u64 = 10_957;
+/// Days in each month of a non-leap year.
+const MONTH_DAYS: [u8; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+/// A time of day on a date, in UTC.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn is_leap(year: u16) -> bool {
+    // Good for 1901 to 2099, which is all we need.
+    year & 3 == 0
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    if month == 2 && is_leap(year) {
+        29
+    } else {
+        MONTH_DAYS[usize::from(month - 1)]
+    }
+}
+
+fn days_in_year(year: u16) -> u64 {
+    if is_leap(year) {
+        366
+    } else {
+        365
+    }
+}
+
+impl DateTime {
+    /// Converts Unix time, returning `None` if it's outside the years we
+    /// handle.
+    pub fn from_unix(secs: u64) -> Option<Self> {
+        let mut days = (secs / SECS_PER_DAY).checked_sub(EPOCH_2000_DAYS)?;
+        let rem = secs % SECS_PER_DAY;
+
+        let mut year = MIN_YEAR;
+        while days >= days_in_year(year) {
+            days -= days_in_year(year);
+            year += 1;
+            if year > MAX_YEAR {
+                return None;
+            }
+        }
+        let mut month = 1;
+        while days >= u64::from(days_in_month(year, month)) {
+            days -= u64::from(days_in_month(year, month));
+            month += 1;
+        }
+
+        Some(Self {
+            year,
+            month,
+            day: days as u8 + 1,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        })
+    }
+
+    /// Checks that this is a real time on a date we handle, which is worth
+    /// doing before believing anything read back from hardware.
+    pub fn is_valid(&self) -> bool {
+        (MIN_YEAR..=MAX_YEAR).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Converts to Unix time. The result is meaningless unless `is_valid`.
+    pub fn to_unix(&self) -> u64 {
+        let mut days = EPOCH_2000_DAYS;
+        for y in MIN_YEAR..self.year {
+            days += days_in_year(y);
+        }
+        for m in 1..self.month {
+            days += u64::from(days_in_month(self.year, m));
+        }
+        days += u64::from(self.day) - 1;
+
+        days * SECS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    /// Day of the week, from 1 for Monday to 7 for Sunday, as ISO 8601 and
+    /// most RTCs count them.
+    pub fn weekday(&self) -> u8 {
+        let days = self.to_unix() / SECS_PER_DAY;
+        // 1970-01-01 was a Thursday.
+        ((days + 3) % 7) as u8 + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(year: u16, month: u8, day: u8, h: u8, m: u8, s: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour: h,
+            minute: m,
+            second: s,
+        }
+    }
+
+    #[test]
+    fn known_dates() {
+        let cases = [
+            (946_684_800, dt(2000, 1, 1, 0, 0, 0)),
+            (951_782_400, dt(2000, 2, 29, 0, 0, 0)),
+            (1_234_567_890, dt(2009, 2, 13, 23, 31, 30)),
+            (1_700_000_000, dt(2023, 11, 14, 22, 13, 20)),
+            (4_102_444_799, dt(2099, 12, 31, 23, 59, 59)),
+        ];
+        for (secs, date) in cases {
+            assert_eq!(DateTime::from_unix(secs), Some(date), "{secs}");
+            assert_eq!(date.to_unix(), secs, "{date:?}");
+            assert!(date.is_valid());
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(DateTime::from_unix(0), None);
+        assert_eq!(DateTime::from_unix(946_684_799), None);
+        assert_eq!(DateTime::from_unix(4_102_444_800), None);
+    }
+
+    #[test]
+    fn round_trip_every_day() {
+        let start = dt(2000, 1, 1, 12, 34, 56).to_unix();
+        let end = dt(2099, 12, 31, 12, 34, 56).to_unix();
+        let mut prev: Option<DateTime> = None;
+        for secs in (start..=end).step_by(SECS_PER_DAY as usize) {
+            let date = DateTime::from_unix(secs).unwrap();
+            assert!(date.is_valid(), "{date:?}");
+            assert_eq!(date.to_unix(), secs);
+            if let Some(p) = prev {
+                assert_eq!(date.weekday(), p.weekday() % 7 + 1);
+            }
+            prev = Some(date);
+        }
+    }
+
+    #[test]
+    fn weekdays() {
+        // A Saturday, a Monday, and a Sunday.
+        assert_eq!(dt(2000, 1, 1, 0, 0, 0).weekday(), 6);
+        assert_eq!(dt(2024, 1, 1, 23, 59, 59).weekday(), 1);
+        assert_eq!(dt(2099, 12, 27, 0, 0, 0).weekday(), 7);
+    }
+
+    #[test]
+    fn validity() {
+        assert!(!dt(2001, 2, 29, 0, 0, 0).is_valid());
+        assert!(dt(2004, 2, 29, 0, 0, 0).is_valid());
+        assert!(!dt(2000, 4, 31, 0, 0, 0).is_valid());
+        assert!(!dt(2000, 13, 1, 0, 0, 0).is_valid());
+        assert!(!dt(2000, 1, 0, 0, 0, 0).is_valid());
+        assert!(!dt(2000, 1, 1, 24, 0, 0).is_valid());
+        assert!(!dt(2100, 1, 1, 0, 0, 0).is_valid());
+    }
+}
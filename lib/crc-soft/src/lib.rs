@@ -0,0 +1,180 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Software CRC with run-time parameters.
+//!
+//! This is the portable half of the checksum API in `drv-crc-api`: it computes
+//! the same thing as the STM32 CRC unit, bit for bit, for chips that don't
+//! have one (or for parameters the unit doesn't support). It favors size over
+//! speed, processing a bit at a time with no tables.
+//!
+//! Algorithms are described with the usual "Rocksoft" parameters. A
+//! computation is split into `update`, which can be called any number of
+//! times, and `finalize`, with the running state in between kept in the
+//! unreflected form that the hardware's data register uses. That lets a
+//! caller move a computation between hardware and software partway through.
+
+#![cfg_attr(not(test), no_std)]
+
+use zerocopy::{AsBytes, FromBytes};
+
+/// Parameters of a CRC algorithm, in the form used by the CRC catalogue.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct CrcParams {
+    /// Generator polynomial, without the leading term.
+    pub poly: u32,
+    /// Initial register value.
+    pub init: u32,
+    /// Value XORed into the register after reflection, if any.
+    pub xorout: u32,
+    /// Width of the CRC in bits, from 1 to 32.
+    pub width: u8,
+    /// Nonzero if each input byte is processed least significant bit first.
+    pub reflect_in: u8,
+    /// Nonzero if the register is bit-reversed before the final XOR.
+    pub reflect_out: u8,
+    _reserved: u8,
+}
+
+impl CrcParams {
+    pub const fn new(
+        width: u8,
+        poly: u32,
+        init: u32,
+        reflect_in: bool,
+        reflect_out: bool,
+        xorout: u32,
+    ) -> Self {
+        Self {
+            poly,
+            init,
+            xorout,
+            width,
+            reflect_in: reflect_in as u8,
+            reflect_out: reflect_out as u8,
+            _reserved: 0,
+        }
+    }
+
+    /// Checks that the width is in range and that no parameter has bits set
+    /// above it.
+    pub fn is_valid(&self) -> bool {
+        if self.width == 0 || self.width > 32 {
+            return false;
+        }
+        let mask = self.mask();
+        self.poly & !mask == 0
+            && self.init & !mask == 0
+            && self.xorout & !mask == 0
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX >> (32 - u32::from(self.width))
+    }
+
+    /// Runs `data` through the CRC, starting from `state` (which is `init`
+    /// for the first chunk of a message), and returns the new state.
+    ///
+    /// The parameters must be valid.
+    pub fn update(&self, state: u32, data: &[u8]) -> u32 {
+        // Work with the register aligned to the top of a u32, so that every
+        // width, including those under 8 bits, takes the same path.
+        let shift = 32 - u32::from(self.width);
+        let poly = self.poly << shift;
+        let mut reg = state << shift;
+
+        for &b in data {
+            let b = if self.reflect_in != 0 {
+                b.reverse_bits()
+            } else {
+                b
+            };
+            reg ^= u32::from(b) << 24;
+            for _ in 0..8 {
+                reg = if reg & 0x8000_0000 != 0 {
+                    (reg << 1) ^ poly
+                } else {
+                    reg << 1
+                };
+            }
+        }
+
+        reg >> shift
+    }
+
+    /// Turns the state left by `update` into the CRC of the message.
+    pub fn finalize(&self, state: u32) -> u32 {
+        let state = if self.reflect_out != 0 {
+            state.reverse_bits() >> (32 - u32::from(self.width))
+        } else {
+            state
+        };
+        state ^ self.xorout
+    }
+
+    /// Computes the CRC of `data` in one go.
+    pub fn checksum(&self, data: &[u8]) -> u32 {
+        self.finalize(self.update(self.init, data))
+    }
+}
+
+/// The CRC-32 used by Ethernet, zip, PNG, and many others.
+pub const CRC_32_ISO_HDLC: CrcParams =
+    CrcParams::new(32, 0x04c1_1db7, 0xffff_ffff, true, true, 0xffff_ffff);
+
+/// CRC-32C (Castagnoli).
+pub const CRC_32_ISCSI: CrcParams =
+    CrcParams::new(32, 0x1edc_6f41, 0xffff_ffff, true, true, 0xffff_ffff);
+
+/// What the fixed-function STM32F1/F2/F4 CRC unit computes.
+pub const CRC_32_MPEG_2: CrcParams =
+    CrcParams::new(32, 0x04c1_1db7, 0xffff_ffff, false, false, 0);
+
+pub const CRC_16_XMODEM: CrcParams =
+    CrcParams::new(16, 0x1021, 0, false, false, 0);
+
+pub const CRC_8_SMBUS: CrcParams = CrcParams::new(8, 0x07, 0, false, false, 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK: &[u8] = b"123456789";
+
+    #[test]
+    fn catalogue_check_values() {
+        assert_eq!(CRC_32_ISO_HDLC.checksum(CHECK), 0xcbf4_3926);
+        assert_eq!(CRC_32_ISCSI.checksum(CHECK), 0xe306_9283);
+        assert_eq!(CRC_32_MPEG_2.checksum(CHECK), 0x0376_e6e7);
+        assert_eq!(CRC_16_XMODEM.checksum(CHECK), 0x31c3);
+        assert_eq!(CRC_8_SMBUS.checksum(CHECK), 0xf4);
+
+        let crc7_mmc = CrcParams::new(7, 0x09, 0, false, false, 0);
+        assert_eq!(crc7_mmc.checksum(CHECK), 0x75);
+        let crc16_arc = CrcParams::new(16, 0x8005, 0, true, true, 0);
+        assert_eq!(crc16_arc.checksum(CHECK), 0xbb3d);
+    }
+
+    #[test]
+    fn chunked_matches_whole() {
+        for params in [CRC_32_ISO_HDLC, CRC_16_XMODEM, CRC_8_SMBUS] {
+            for split in 0..=CHECK.len() {
+                let (a, b) = CHECK.split_at(split);
+                let state = params.update(params.init, a);
+                let state = params.update(state, b);
+                assert_eq!(params.finalize(state), params.checksum(CHECK));
+            }
+        }
+    }
+
+    #[test]
+    fn validation() {
+        assert!(CRC_32_ISO_HDLC.is_valid());
+        assert!(!CrcParams::new(0, 0, 0, false, false, 0).is_valid());
+        assert!(!CrcParams::new(33, 0, 0, false, false, 0).is_valid());
+        assert!(!CrcParams::new(8, 0x107, 0, false, false, 0).is_valid());
+        assert!(!CrcParams::new(8, 0x07, 0x100, false, false, 0).is_valid());
+    }
+}
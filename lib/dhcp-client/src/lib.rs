@@ -0,0 +1,948 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A DHCPv4 client (RFC 2131), without a network stack of its own.
+//!
+//! The client is driven by its owner: `receive` hands it a packet that came
+//! in for the client port, `poll` moves it along with the clock, and
+//! `transmit` asks it for a packet to send. Packets go in and out as whole
+//! IPv4 datagrams, since much of what a DHCP client sends goes out before it
+//! has an address, which network stacks won't generally do for a UDP socket.
+//!
+//! Once bound, the client renews its lease with the server that gave it at
+//! T1, and with any server at T2, as the lease says or, if it doesn't, at
+//! one-half and seven-eighths of the way through. A lease that runs out is
+//! dropped, and the client starts over. If the client goes `give_up_after`
+//! without a lease, it says so, once, so that its owner can fall back on
+//! something else; it keeps trying, and reports a lease found later as usual.
+//!
+//! Times are in milliseconds, from whatever clock the owner likes.
+
+#![cfg_attr(not(test), no_std)]
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+/// Largest packet we send or, unless the server ignores the maximum message
+/// size we ask for, receive.
+pub const MAX_PACKET: usize = 576;
+
+const IPV4_HEADER: usize = 20;
+const UDP_HEADER: usize = 8;
+const BOOTP_LEN: usize = 236;
+const OPTIONS_OFFSET: usize = BOOTP_LEN + 4;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const IPPROTO_UDP: u8 = 17;
+
+/// Some relays and servers drop BOOTP messages shorter than this.
+const MIN_MESSAGE: usize = 300;
+
+const BROADCAST: [u8; 4] = [255; 4];
+const UNSPECIFIED: [u8; 4] = [0; 4];
+
+/// DISCOVER and REQUEST are retried after 4 seconds, then 8, and so on up
+/// to 64 (RFC 2131 section 4.1).
+const FIRST_RETRY_MS: u64 = 4_000;
+const MAX_RETRY_MS: u64 = 64_000;
+
+/// REQUESTs we send for an offer before going back to DISCOVER.
+const REQUEST_ATTEMPTS: u8 = 4;
+
+/// Shortest wait between REQUESTs when renewing or rebinding (RFC 2131
+/// section 4.4.5).
+const MIN_RENEW_RETRY_MS: u64 = 60_000;
+
+mod opt {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const REQUESTED_IP: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_ID: u8 = 54;
+    pub const PARAMETER_REQUEST: u8 = 55;
+    pub const MAX_MESSAGE_SIZE: u8 = 57;
+    pub const RENEWAL_TIME: u8 = 58;
+    pub const REBINDING_TIME: u8 = 59;
+    pub const CLIENT_ID: u8 = 61;
+    pub const END: u8 = 255;
+}
+
+mod msg {
+    pub const DISCOVER: u8 = 1;
+    pub const OFFER: u8 = 2;
+    pub const REQUEST: u8 = 3;
+    pub const ACK: u8 = 5;
+    pub const NAK: u8 = 6;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum State {
+    /// Looking for a server, with DISCOVER.
+    Selecting,
+    /// Asking for what a server offered.
+    Requesting,
+    /// Holding a lease.
+    Bound,
+    /// Past T1, asking the server that gave us our lease to extend it.
+    Renewing,
+    /// Past T2, asking any server to extend our lease.
+    Rebinding,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Lease {
+    pub address: [u8; 4],
+    pub prefix_len: u8,
+    pub router: Option<[u8; 4]>,
+    /// The server that gave us the lease.
+    pub server: [u8; 4],
+    /// Length of the lease, in seconds.
+    pub duration: u32,
+    /// T1, in seconds from the start of the lease.
+    pub renew: u32,
+    /// T2, in seconds from the start of the lease.
+    pub rebind: u32,
+}
+
+/// A lease, and when we got it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Binding {
+    pub lease: Lease,
+    pub at: u64,
+}
+
+impl Binding {
+    pub fn renew_at(&self) -> u64 {
+        self.at + u64::from(self.lease.renew) * 1000
+    }
+
+    pub fn rebind_at(&self) -> u64 {
+        self.at + u64::from(self.lease.rebind) * 1000
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.at + u64::from(self.lease.duration) * 1000
+    }
+}
+
+/// Something the client's owner needs to act on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    /// We have a lease, or the one we have now says something different.
+    Configured(Lease),
+    /// The lease has run out, or been taken back.
+    Deconfigured,
+    /// We've gone `give_up_after` without a lease.
+    GaveUp,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Offer {
+    address: [u8; 4],
+    server: [u8; 4],
+}
+
+pub struct Client {
+    mac: [u8; 6],
+    give_up_after: u64,
+    state: State,
+    xid: u32,
+    /// When we next send, in a state that sends.
+    send_at: u64,
+    /// How many times we've sent in this state.
+    attempts: u8,
+    offer: Option<Offer>,
+    binding: Option<Binding>,
+    /// When we last had no lease, for giving up.
+    unbound_since: u64,
+    gave_up: bool,
+}
+
+impl Client {
+    /// Makes a client for the interface with address `mac`, which will start
+    /// looking for a server on the first call to `transmit`.
+    ///
+    /// `seed` picks the transaction IDs, and so should differ between
+    /// clients sharing a network, and between a client and its restarts.
+    pub fn new(mac: [u8; 6], seed: u32, give_up_after: u64, now: u64) -> Self {
+        let mut c = Self {
+            mac,
+            give_up_after,
+            state: State::Selecting,
+            xid: seed,
+            send_at: now,
+            attempts: 0,
+            offer: None,
+            binding: None,
+            unbound_since: now,
+            gave_up: false,
+        };
+        c.next_xid();
+        c
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn binding(&self) -> Option<&Binding> {
+        self.binding.as_ref()
+    }
+
+    /// Returns the next time `poll` or `transmit` has something to do.
+    pub fn poll_at(&self) -> u64 {
+        let deadline = match (self.state, &self.binding) {
+            (State::Bound, Some(b)) => b.renew_at(),
+            (State::Renewing, Some(b)) => self.send_at.min(b.rebind_at()),
+            (State::Rebinding, Some(b)) => self.send_at.min(b.expires_at()),
+            _ => self.send_at,
+        };
+        if self.binding.is_none() && !self.gave_up {
+            deadline.min(self.unbound_since + self.give_up_after)
+        } else {
+            deadline
+        }
+    }
+
+    /// Moves the client along to `now`.
+    pub fn poll(&mut self, now: u64) -> Option<Change> {
+        if let Some(b) = self.binding {
+            if self.state == State::Bound && now >= b.renew_at() {
+                self.enter(State::Renewing, now);
+            }
+            if self.state == State::Renewing && now >= b.rebind_at() {
+                self.enter(State::Rebinding, now);
+            }
+            if self.state == State::Rebinding && now >= b.expires_at() {
+                self.unbind(now);
+                return Some(Change::Deconfigured);
+            }
+        }
+
+        if self.state == State::Requesting
+            && self.attempts >= REQUEST_ATTEMPTS
+            && now >= self.send_at
+        {
+            self.enter(State::Selecting, now);
+        }
+
+        if self.binding.is_none()
+            && !self.gave_up
+            && now >= self.unbound_since + self.give_up_after
+        {
+            self.gave_up = true;
+            return Some(Change::GaveUp);
+        }
+        None
+    }
+
+    /// Takes an IPv4 packet that may be for us.
+    pub fn receive(&mut self, now: u64, packet: &[u8]) -> Option<Change> {
+        let reply = Reply::parse(packet, self.xid, &self.mac)?;
+
+        match (self.state, reply.kind) {
+            (State::Selecting, msg::OFFER) => {
+                let server = reply.server?;
+                if reply.address == UNSPECIFIED {
+                    return None;
+                }
+                self.offer = Some(Offer {
+                    address: reply.address,
+                    server,
+                });
+                self.state = State::Requesting;
+                self.attempts = 0;
+                self.send_at = now;
+                None
+            }
+            (State::Requesting | State::Renewing | State::Rebinding, _)
+                if !self.is_from_our_server(&reply) =>
+            {
+                None
+            }
+            (
+                State::Requesting | State::Renewing | State::Rebinding,
+                msg::ACK,
+            ) => self.bind(now, &reply),
+            (
+                State::Requesting | State::Renewing | State::Rebinding,
+                msg::NAK,
+            ) => {
+                if self.binding.is_some() {
+                    self.unbind(now);
+                    Some(Change::Deconfigured)
+                } else {
+                    self.enter(State::Selecting, now);
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes a packet to send into `buf`, returning its length, if it's
+    /// time to send one.
+    pub fn transmit(
+        &mut self,
+        now: u64,
+        buf: &mut [u8; MAX_PACKET],
+    ) -> Option<usize> {
+        if now < self.send_at {
+            return None;
+        }
+        let backoff =
+            (FIRST_RETRY_MS << self.attempts.min(8)).min(MAX_RETRY_MS);
+
+        let len = match self.state {
+            State::Selecting => {
+                self.send_at = now + backoff;
+                self.encode(buf, msg::DISCOVER, None, None)
+            }
+            State::Requesting => {
+                if self.attempts >= REQUEST_ATTEMPTS {
+                    return None;
+                }
+                let offer = self.offer?;
+                self.send_at = now + backoff;
+                self.encode(buf, msg::REQUEST, None, Some(offer))
+            }
+            State::Renewing | State::Rebinding => {
+                let b = self.binding?;
+                let (deadline, server) = if self.state == State::Renewing {
+                    (b.rebind_at(), b.lease.server)
+                } else {
+                    (b.expires_at(), BROADCAST)
+                };
+                let wait =
+                    (deadline.saturating_sub(now) / 2).max(MIN_RENEW_RETRY_MS);
+                self.send_at = now + wait;
+                self.encode(
+                    buf,
+                    msg::REQUEST,
+                    Some((b.lease.address, server)),
+                    None,
+                )
+            }
+            State::Bound => return None,
+        };
+        self.attempts = self.attempts.saturating_add(1);
+        Some(len)
+    }
+
+    fn is_from_our_server(&self, reply: &Reply) -> bool {
+        let ours = match (self.state, &self.offer, &self.binding) {
+            (State::Requesting, Some(o), _) => o.server,
+            (State::Renewing, _, Some(b)) => b.lease.server,
+            _ => return true,
+        };
+        !matches!(reply.server, Some(s) if s != ours)
+    }
+
+    fn bind(&mut self, now: u64, reply: &Reply) -> Option<Change> {
+        let duration = reply.lease?;
+        if reply.address == UNSPECIFIED {
+            return None;
+        }
+        let server = reply
+            .server
+            .or(self.offer.map(|o| o.server))
+            .or(self.binding.map(|b| b.lease.server))?;
+
+        // T1 and T2 default to one-half and seven-eighths of the lease
+        // (RFC 2131 section 4.4.5), which they also do if the server gives
+        // us nonsense.
+        let mut renew = reply.renew.unwrap_or(duration / 2);
+        let mut rebind =
+            reply.rebind.unwrap_or((u64::from(duration) * 7 / 8) as u32);
+        if !(renew <= rebind && rebind <= duration) {
+            renew = duration / 2;
+            rebind = (u64::from(duration) * 7 / 8) as u32;
+        }
+
+        let lease = Lease {
+            address: reply.address,
+            prefix_len: reply
+                .mask
+                .and_then(prefix_len)
+                .unwrap_or_else(|| default_prefix_len(reply.address)),
+            router: reply.router,
+            server,
+            duration,
+            renew,
+            rebind,
+        };
+        let changed = match self.binding {
+            Some(b) => {
+                (b.lease.address, b.lease.prefix_len, b.lease.router)
+                    != (lease.address, lease.prefix_len, lease.router)
+            }
+            None => true,
+        };
+
+        self.binding = Some(Binding { lease, at: now });
+        self.offer = None;
+        self.gave_up = false;
+        self.enter(State::Bound, now);
+        self.send_at = u64::MAX;
+
+        if changed {
+            Some(Change::Configured(lease))
+        } else {
+            None
+        }
+    }
+
+    fn unbind(&mut self, now: u64) {
+        self.binding = None;
+        self.unbound_since = now;
+        self.gave_up = false;
+        self.enter(State::Selecting, now);
+    }
+
+    fn enter(&mut self, state: State, now: u64) {
+        self.state = state;
+        self.attempts = 0;
+        self.send_at = now;
+        if state == State::Selecting {
+            self.offer = None;
+        }
+        self.next_xid();
+    }
+
+    fn next_xid(&mut self) {
+        self.xid = self.xid.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+    }
+
+    /// Encodes a DISCOVER or REQUEST. `bound` is our address and where to
+    /// send to, when renewing or rebinding; `offer` is what we're asking
+    /// for, when requesting.
+    fn encode(
+        &self,
+        buf: &mut [u8; MAX_PACKET],
+        kind: u8,
+        bound: Option<([u8; 4], [u8; 4])>,
+        offer: Option<Offer>,
+    ) -> usize {
+        let (src, dst) = bound.unwrap_or((UNSPECIFIED, BROADCAST));
+
+        let dhcp = &mut buf[IPV4_HEADER + UDP_HEADER..];
+        dhcp.fill(0);
+        dhcp[0] = 1; // BOOTREQUEST
+        dhcp[1] = 1; // Ethernet
+        dhcp[2] = 6;
+        dhcp[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        if bound.is_none() {
+            // We can't take unicast until we have an address.
+            dhcp[10] = 0x80;
+        }
+        dhcp[12..16].copy_from_slice(&src);
+        dhcp[28..34].copy_from_slice(&self.mac);
+        dhcp[BOOTP_LEN..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut w = OptionWriter {
+            buf: dhcp,
+            pos: OPTIONS_OFFSET,
+        };
+        w.put(opt::MESSAGE_TYPE, &[kind]);
+        let mut client_id = [1; 7];
+        client_id[1..].copy_from_slice(&self.mac);
+        w.put(opt::CLIENT_ID, &client_id);
+        if let Some(offer) = offer {
+            w.put(opt::REQUESTED_IP, &offer.address);
+            w.put(opt::SERVER_ID, &offer.server);
+        }
+        w.put(
+            opt::PARAMETER_REQUEST,
+            &[
+                opt::SUBNET_MASK,
+                opt::ROUTER,
+                opt::LEASE_TIME,
+                opt::RENEWAL_TIME,
+                opt::REBINDING_TIME,
+            ],
+        );
+        w.put(opt::MAX_MESSAGE_SIZE, &(MAX_PACKET as u16).to_be_bytes());
+        w.put_end();
+        let dhcp_len = w.pos.max(MIN_MESSAGE);
+
+        let udp_len = UDP_HEADER + dhcp_len;
+        let udp = &mut buf[IPV4_HEADER..IPV4_HEADER + udp_len];
+        udp[0..2].copy_from_slice(&CLIENT_PORT.to_be_bytes());
+        udp[2..4].copy_from_slice(&SERVER_PORT.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        udp[6..8].fill(0);
+        let mut pseudo = sum(0, &src);
+        pseudo = sum(pseudo, &dst);
+        pseudo = sum(pseudo, &[0, IPPROTO_UDP]);
+        pseudo = sum(pseudo, &(udp_len as u16).to_be_bytes());
+        let cksum = match fold(sum(pseudo, udp)) {
+            0 => 0xffff,
+            c => c,
+        };
+        udp[6..8].copy_from_slice(&cksum.to_be_bytes());
+
+        let len = IPV4_HEADER + udp_len;
+        let ip = &mut buf[..IPV4_HEADER];
+        ip.fill(0);
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        ip[8] = 64;
+        ip[9] = IPPROTO_UDP;
+        ip[12..16].copy_from_slice(&src);
+        ip[16..20].copy_from_slice(&dst);
+        let cksum = fold(sum(0, ip));
+        ip[10..12].copy_from_slice(&cksum.to_be_bytes());
+
+        len
+    }
+}
+
+struct OptionWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl OptionWriter<'_> {
+    fn put(&mut self, code: u8, value: &[u8]) {
+        let end = self.pos + 2 + value.len();
+        self.buf[self.pos] = code;
+        self.buf[self.pos + 1] = value.len() as u8;
+        self.buf[self.pos + 2..end].copy_from_slice(value);
+        self.pos = end;
+    }
+
+    fn put_end(&mut self) {
+        self.buf[self.pos] = opt::END;
+        self.pos += 1;
+    }
+}
+
+/// What we care about in an OFFER, ACK or NAK.
+struct Reply {
+    kind: u8,
+    address: [u8; 4],
+    server: Option<[u8; 4]>,
+    mask: Option<[u8; 4]>,
+    router: Option<[u8; 4]>,
+    lease: Option<u32>,
+    renew: Option<u32>,
+    rebind: Option<u32>,
+}
+
+impl Reply {
+    fn parse(packet: &[u8], xid: u32, mac: &[u8; 6]) -> Option<Self> {
+        let ihl = usize::from(packet.first()? & 0xf) * 4;
+        if packet[0] >> 4 != 4
+            || ihl < IPV4_HEADER
+            || packet.len() < ihl + UDP_HEADER
+            || packet[9] != IPPROTO_UDP
+            // We've no use for fragments.
+            || u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0
+        {
+            return None;
+        }
+        let total = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+        let udp = packet.get(ihl..total)?;
+        let udp_len = usize::from(u16::from_be_bytes([udp[4], udp[5]]));
+        if u16::from_be_bytes([udp[0], udp[1]]) != SERVER_PORT
+            || u16::from_be_bytes([udp[2], udp[3]]) != CLIENT_PORT
+        {
+            return None;
+        }
+
+        let dhcp = udp.get(UDP_HEADER..udp_len)?;
+        if dhcp.len() < OPTIONS_OFFSET
+            || dhcp[0] != 2 // BOOTREPLY
+            || dhcp[4..8] != xid.to_be_bytes()
+            || dhcp[28..34] != mac[..]
+            || dhcp[BOOTP_LEN..OPTIONS_OFFSET] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut reply = Self {
+            kind: 0,
+            address: dhcp[16..20].try_into().unwrap(),
+            server: None,
+            mask: None,
+            router: None,
+            lease: None,
+            renew: None,
+            rebind: None,
+        };
+        let mut opts = &dhcp[OPTIONS_OFFSET..];
+        loop {
+            match opts {
+                [] | [opt::END, ..] => break,
+                [opt::PAD, rest @ ..] => opts = rest,
+                [code, len, rest @ ..] => {
+                    let len = usize::from(*len);
+                    if rest.len() < len {
+                        return None;
+                    }
+                    let (value, rest) = rest.split_at(len);
+                    match *code {
+                        opt::MESSAGE_TYPE => reply.kind = *value.first()?,
+                        opt::SERVER_ID => reply.server = addr(value),
+                        opt::SUBNET_MASK => reply.mask = addr(value),
+                        opt::ROUTER => reply.router = addr(value),
+                        opt::LEASE_TIME => reply.lease = secs(value),
+                        opt::RENEWAL_TIME => reply.renew = secs(value),
+                        opt::REBINDING_TIME => reply.rebind = secs(value),
+                        _ => (),
+                    }
+                    opts = rest;
+                }
+                [_] => return None,
+            }
+        }
+        if reply.kind == 0 {
+            return None;
+        }
+        Some(reply)
+    }
+}
+
+fn addr(value: &[u8]) -> Option<[u8; 4]> {
+    value.get(..4)?.try_into().ok()
+}
+
+fn secs(value: &[u8]) -> Option<u32> {
+    addr(value).map(u32::from_be_bytes)
+}
+
+/// Turns a subnet mask into a prefix length, if it's a sensible one.
+fn prefix_len(mask: [u8; 4]) -> Option<u8> {
+    let mask = u32::from_be_bytes(mask);
+    let len = mask.leading_ones();
+    if mask.checked_shl(len).unwrap_or(0) == 0 {
+        Some(len as u8)
+    } else {
+        None
+    }
+}
+
+/// The prefix length for an address's class, for a server that doesn't give
+/// a subnet mask (RFC 2131 section 2).
+fn default_prefix_len(address: [u8; 4]) -> u8 {
+    match address[0] {
+        0..=127 => 8,
+        128..=191 => 16,
+        _ => 24,
+    }
+}
+
+/// Adds `data` to a running Internet checksum.
+fn sum(mut sum: u32, data: &[u8]) -> u32 {
+    for pair in data.chunks(2) {
+        let hi = pair[0];
+        let lo = pair.get(1).copied().unwrap_or(0);
+        sum += u32::from(u16::from_be_bytes([hi, lo]));
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x0e, 0x1d, 1, 2, 3, 4];
+    const SERVER: [u8; 4] = [10, 0, 0, 1];
+    const ADDRESS: [u8; 4] = [10, 0, 0, 42];
+    const GIVE_UP: u64 = 30_000;
+
+    /// What a server needs from one of our packets.
+    struct Sent {
+        src: [u8; 4],
+        dst: [u8; 4],
+        xid: u32,
+        broadcast: bool,
+        ciaddr: [u8; 4],
+        kind: u8,
+        requested: Option<[u8; 4]>,
+        server: Option<[u8; 4]>,
+    }
+
+    fn sent(packet: &[u8]) -> Sent {
+        assert_eq!(fold(sum(0, &packet[..IPV4_HEADER])), 0);
+        let udp = &packet[IPV4_HEADER..];
+        assert_eq!(
+            udp.len(),
+            usize::from(u16::from_be_bytes([udp[4], udp[5]]))
+        );
+        let mut pseudo = sum(0, &packet[12..20]);
+        pseudo = sum(pseudo, &[0, IPPROTO_UDP]);
+        pseudo = sum(pseudo, &udp[4..6]);
+        assert_eq!(fold(sum(pseudo, udp)), 0);
+        assert_eq!(&udp[0..4], &[0, 68, 0, 67]);
+
+        let dhcp = &udp[UDP_HEADER..];
+        assert!(dhcp.len() >= MIN_MESSAGE);
+        assert_eq!(dhcp[0], 1);
+        assert_eq!(&dhcp[28..34], &MAC);
+        let mut s = Sent {
+            src: packet[12..16].try_into().unwrap(),
+            dst: packet[16..20].try_into().unwrap(),
+            xid: u32::from_be_bytes(dhcp[4..8].try_into().unwrap()),
+            broadcast: dhcp[10] & 0x80 != 0,
+            ciaddr: dhcp[12..16].try_into().unwrap(),
+            kind: 0,
+            requested: None,
+            server: None,
+        };
+        let mut opts = &dhcp[OPTIONS_OFFSET..];
+        while opts[0] != opt::END {
+            let (code, len) = (opts[0], usize::from(opts[1]));
+            let value = &opts[2..2 + len];
+            match code {
+                opt::MESSAGE_TYPE => s.kind = value[0],
+                opt::REQUESTED_IP => s.requested = addr(value),
+                opt::SERVER_ID => s.server = addr(value),
+                _ => (),
+            }
+            opts = &opts[2 + len..];
+        }
+        s
+    }
+
+    fn reply(
+        kind: u8,
+        xid: u32,
+        address: [u8; 4],
+        opts: &[(u8, &[u8])],
+    ) -> Vec<u8> {
+        let mut dhcp = vec![0; OPTIONS_OFFSET];
+        dhcp[0] = 2;
+        dhcp[1] = 1;
+        dhcp[2] = 6;
+        dhcp[4..8].copy_from_slice(&xid.to_be_bytes());
+        dhcp[16..20].copy_from_slice(&address);
+        dhcp[28..34].copy_from_slice(&MAC);
+        dhcp[BOOTP_LEN..].copy_from_slice(&MAGIC_COOKIE);
+        dhcp.extend([opt::MESSAGE_TYPE, 1, kind]);
+        dhcp.extend([opt::SERVER_ID, 4]);
+        dhcp.extend(SERVER);
+        for (code, value) in opts {
+            dhcp.extend([*code, value.len() as u8]);
+            dhcp.extend(*value);
+        }
+        dhcp.push(opt::END);
+
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, IPPROTO_UDP];
+        packet.extend([0, 0]);
+        packet.extend(SERVER);
+        packet.extend(BROADCAST);
+        packet.extend(SERVER_PORT.to_be_bytes());
+        packet.extend(CLIENT_PORT.to_be_bytes());
+        packet.extend(((UDP_HEADER + dhcp.len()) as u16).to_be_bytes());
+        packet.extend([0, 0]);
+        packet.extend(dhcp);
+        let len = (packet.len() as u16).to_be_bytes();
+        packet[2..4].copy_from_slice(&len);
+        packet
+    }
+
+    fn send(c: &mut Client, now: u64) -> Option<Sent> {
+        let mut buf = [0; MAX_PACKET];
+        c.transmit(now, &mut buf).map(|len| sent(&buf[..len]))
+    }
+
+    const LEASE: &[(u8, &[u8])] = &[
+        (opt::LEASE_TIME, &[0, 0, 0x0e, 0x10]), // an hour
+        (opt::SUBNET_MASK, &[255, 255, 255, 0]),
+        (opt::ROUTER, &[10, 0, 0, 1]),
+    ];
+
+    /// Takes a new client through to bound, at time 0.
+    fn bound() -> Client {
+        let mut c = Client::new(MAC, 1, GIVE_UP, 0);
+        let discover = send(&mut c, 0).unwrap();
+        c.receive(0, &reply(msg::OFFER, discover.xid, ADDRESS, &[]));
+        let request = send(&mut c, 0).unwrap();
+        let change =
+            c.receive(0, &reply(msg::ACK, request.xid, ADDRESS, LEASE));
+        assert!(matches!(change, Some(Change::Configured(_))));
+        c
+    }
+
+    #[test]
+    fn discovers_with_backoff() {
+        let mut c = Client::new(MAC, 1, GIVE_UP, 0);
+        let d = send(&mut c, 0).unwrap();
+        assert_eq!(d.kind, msg::DISCOVER);
+        assert_eq!((d.src, d.dst), (UNSPECIFIED, BROADCAST));
+        assert!(d.broadcast);
+
+        assert!(send(&mut c, 3_999).is_none());
+        assert_eq!(c.poll_at(), 4_000);
+        assert_eq!(send(&mut c, 4_000).unwrap().xid, d.xid);
+        assert!(send(&mut c, 11_999).is_none());
+        assert!(send(&mut c, 12_000).is_some());
+    }
+
+    #[test]
+    fn binds() {
+        let mut c = Client::new(MAC, 1, GIVE_UP, 0);
+        let d = send(&mut c, 0).unwrap();
+
+        // Neither a stranger's transaction nor an ACK out of turn count.
+        c.receive(0, &reply(msg::OFFER, d.xid + 1, ADDRESS, &[]));
+        c.receive(0, &reply(msg::ACK, d.xid, ADDRESS, LEASE));
+        assert_eq!(c.state(), State::Selecting);
+
+        c.receive(100, &reply(msg::OFFER, d.xid, ADDRESS, &[]));
+        assert_eq!(c.state(), State::Requesting);
+        let r = send(&mut c, 100).unwrap();
+        assert_eq!(r.kind, msg::REQUEST);
+        assert_eq!((r.src, r.dst), (UNSPECIFIED, BROADCAST));
+        assert_eq!(r.requested, Some(ADDRESS));
+        assert_eq!(r.server, Some(SERVER));
+
+        let change = c.receive(200, &reply(msg::ACK, r.xid, ADDRESS, LEASE));
+        let lease = Lease {
+            address: ADDRESS,
+            prefix_len: 24,
+            router: Some(SERVER),
+            server: SERVER,
+            duration: 3600,
+            renew: 1800,
+            rebind: 3150,
+        };
+        assert_eq!(change, Some(Change::Configured(lease)));
+        assert_eq!(c.state(), State::Bound);
+        assert_eq!(c.binding(), Some(&Binding { lease, at: 200 }));
+        assert_eq!(c.poll_at(), 1_800_200);
+        assert!(send(&mut c, 1_000_000).is_none());
+    }
+
+    #[test]
+    fn renews_and_rebinds() {
+        let mut c = bound();
+        assert_eq!(c.poll(1_799_999), None);
+        assert_eq!(c.poll(1_800_000), None);
+        assert_eq!(c.state(), State::Renewing);
+
+        let r = send(&mut c, 1_800_000).unwrap();
+        assert_eq!((r.src, r.dst, r.ciaddr), (ADDRESS, SERVER, ADDRESS));
+        assert!(!r.broadcast);
+        assert_eq!((r.requested, r.server), (None, None));
+        // Half the time to T2, in this case.
+        assert_eq!(c.poll_at(), 1_800_000 + 675_000);
+
+        c.poll(3_150_000);
+        assert_eq!(c.state(), State::Rebinding);
+        let r = send(&mut c, 3_150_000).unwrap();
+        assert_eq!((r.src, r.dst, r.ciaddr), (ADDRESS, BROADCAST, ADDRESS));
+        // Half the time to expiry.
+        assert_eq!(c.poll_at(), 3_150_000 + 225_000);
+
+        // An ACK that changes nothing extends the lease without a fuss.
+        let change =
+            c.receive(3_200_000, &reply(msg::ACK, r.xid, ADDRESS, LEASE));
+        assert_eq!(change, None);
+        assert_eq!(c.state(), State::Bound);
+        assert_eq!(c.poll_at(), 3_200_000 + 1_800_000);
+    }
+
+    #[test]
+    fn expires() {
+        let mut c = bound();
+        c.poll(3_599_999);
+        assert_eq!(c.state(), State::Rebinding);
+        assert_eq!(c.poll(3_600_000), Some(Change::Deconfigured));
+        assert_eq!(c.state(), State::Selecting);
+        assert_eq!(c.binding(), None);
+        assert_eq!(send(&mut c, 3_600_000).unwrap().kind, msg::DISCOVER);
+    }
+
+    #[test]
+    fn nak_while_renewing() {
+        let mut c = bound();
+        c.poll(1_800_000);
+        let r = send(&mut c, 1_800_000).unwrap();
+        let change =
+            c.receive(1_800_001, &reply(msg::NAK, r.xid, UNSPECIFIED, &[]));
+        assert_eq!(change, Some(Change::Deconfigured));
+        assert_eq!(c.state(), State::Selecting);
+    }
+
+    #[test]
+    fn server_times_and_changes() {
+        let mut c = bound();
+        c.poll(1_800_000);
+        let r = send(&mut c, 1_800_000).unwrap();
+        let change = c.receive(
+            1_800_000,
+            &reply(
+                msg::ACK,
+                r.xid,
+                ADDRESS,
+                &[
+                    (opt::LEASE_TIME, &[0, 0, 0x0e, 0x10]),
+                    (opt::RENEWAL_TIME, &[0, 0, 0, 60]),
+                    (opt::REBINDING_TIME, &[0, 0, 0, 120]),
+                ],
+            ),
+        );
+        // No mask means a class A default, and no router; both are changes.
+        let Some(Change::Configured(lease)) = change else {
+            panic!("{change:?}");
+        };
+        assert_eq!((lease.prefix_len, lease.router), (8, None));
+        assert_eq!((lease.renew, lease.rebind), (60, 120));
+    }
+
+    #[test]
+    fn requests_give_out() {
+        // Not giving up, so that it doesn't get in the way of `poll_at`.
+        let mut c = Client::new(MAC, 1, 1_000_000, 0);
+        let d = send(&mut c, 0).unwrap();
+        c.receive(0, &reply(msg::OFFER, d.xid, ADDRESS, &[]));
+        let mut now = 0;
+        for _ in 0..REQUEST_ATTEMPTS {
+            now = now.max(c.poll_at());
+            c.poll(now);
+            assert_eq!(send(&mut c, now).unwrap().kind, msg::REQUEST);
+        }
+        now = c.poll_at();
+        assert!(send(&mut c, now).is_none());
+        c.poll(now);
+        assert_eq!(c.state(), State::Selecting);
+        let d2 = send(&mut c, now).unwrap();
+        assert_eq!(d2.kind, msg::DISCOVER);
+        assert_ne!(d2.xid, d.xid);
+    }
+
+    #[test]
+    fn gives_up_once() {
+        let mut c = Client::new(MAC, 1, GIVE_UP, 0);
+        assert_eq!(c.poll(GIVE_UP - 1), None);
+        assert_eq!(c.poll(GIVE_UP), Some(Change::GaveUp));
+        assert_eq!(c.poll(GIVE_UP * 2), None);
+
+        // ...but it's still trying.
+        let d = send(&mut c, GIVE_UP * 2).unwrap();
+        c.receive(GIVE_UP * 2, &reply(msg::OFFER, d.xid, ADDRESS, &[]));
+        let r = send(&mut c, GIVE_UP * 2).unwrap();
+        let change =
+            c.receive(GIVE_UP * 2, &reply(msg::ACK, r.xid, ADDRESS, LEASE));
+        assert!(matches!(change, Some(Change::Configured(_))));
+    }
+
+    #[test]
+    fn masks() {
+        assert_eq!(prefix_len([255, 255, 255, 0]), Some(24));
+        assert_eq!(prefix_len([255, 255, 255, 255]), Some(32));
+        assert_eq!(prefix_len([0, 0, 0, 0]), Some(0));
+        assert_eq!(prefix_len([255, 0, 255, 0]), None);
+    }
+}
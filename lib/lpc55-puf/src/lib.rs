@@ -96,6 +96,108 @@ impl<'a> Puf<'a> {
         self.is_success()
     }
 
+    /// Size of the activation code produced by `enroll`, in words.
+    pub const ACTIVATION_CODE_WORDS: usize = 1192 / mem::size_of::<u32>();
+
+    /// Enroll the PUF, producing the activation code that `start` needs
+    /// after every subsequent power cycle.
+    ///
+    /// WARNING: Enrolling invalidates every activation code and key code
+    /// produced by a previous enrollment, including the one the ROM keeps in
+    /// the PFR for DICE. Only do this on parts where the ROM hasn't enrolled
+    /// the PUF.
+    pub fn enroll(
+        &self,
+        activation_code: &mut [u32; Self::ACTIVATION_CODE_WORDS],
+    ) -> bool {
+        if !self.is_enroll_allowed() {
+            return false;
+        }
+
+        self.puf.ctrl.write(|w| w.enroll().set_bit());
+        if !self.wait_for_cmd_accept() {
+            return false;
+        }
+
+        let mut idx = 0;
+        while self.is_busy() {
+            if self.is_keycode_part_avail() {
+                let part = self.puf.codeoutput.read().bits();
+                if idx < activation_code.len() {
+                    activation_code[idx] = part;
+                }
+                idx += 1;
+            }
+        }
+
+        idx == activation_code.len() && self.is_success()
+    }
+
+    /// Start the PUF with an activation code from a previous `enroll`.
+    pub fn start(
+        &self,
+        activation_code: &[u32; Self::ACTIVATION_CODE_WORDS],
+    ) -> bool {
+        if !self.is_start_allowed() {
+            return false;
+        }
+
+        self.puf.ctrl.write(|w| w.start().set_bit());
+        if !self.wait_for_cmd_accept() {
+            return false;
+        }
+
+        let mut idx = 0;
+        while self.is_busy() {
+            if self.is_keycode_part_req() {
+                let part = activation_code.get(idx).copied().unwrap_or(0);
+                self.puf.codeinput.write(|w| unsafe { w.bits(part) });
+                idx += 1;
+            }
+        }
+
+        self.is_success()
+    }
+
+    /// Power up the PUF's SRAM, which the ROM leaves off unless it started
+    /// the PUF itself. The PUF's clock must already be enabled.
+    pub fn power_on(&self) {
+        self.puf.pwrctrl.modify(|_, w| w.ramon().set_bit());
+        while self.puf.pwrctrl.read().ramstat().bit_is_clear() {}
+    }
+
+    /// Returns true if the PUF has been started, either by us or by the ROM,
+    /// and is able to reconstruct keys.
+    pub fn is_started(&self) -> bool {
+        self.is_getkey_allowed()
+    }
+
+    /// Reconstruct a key generated for index 0, which the PUF delivers over
+    /// its internal bus to the AES engine rather than through KEYOUTPUT, so
+    /// the key is never visible to software. The AES engine must already be
+    /// set up to take its key from the PUF.
+    pub fn get_key_to_bus(&self, keycode: &[u32]) -> bool {
+        if !self.is_getkey_allowed() || index_from_keycode(keycode) != Some(0) {
+            return false;
+        }
+
+        self.puf.ctrl.write(|w| w.getkey().set_bit());
+        if !self.wait_for_cmd_accept() {
+            return false;
+        }
+
+        let mut kc_idx = 0;
+        while self.is_busy() && !self.is_error() {
+            if self.is_keycode_part_req() {
+                let part = keycode.get(kc_idx).copied().unwrap_or(0);
+                self.puf.codeinput.write(|w| unsafe { w.bits(part) });
+                kc_idx += 1;
+            }
+        }
+
+        self.is_success()
+    }
+
     /// Get the key associated with the given keycode from the PUF. The
     /// keycode should be a value generated by the 'GENERATEKEY' PUF
     /// function.
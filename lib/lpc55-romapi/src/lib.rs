@@ -729,7 +729,8 @@ pub fn get_cmpa_data(
     offset: u32,
     len: u32,
 ) -> Result<(), FlashStatus> {
-    assert!(len <= (data.len() as u32));
+    // `len` is in bytes.
+    assert!(len as usize <= data.len() * 4);
 
     let mut f: FlashConfig = Default::default();
     f.mode_config.sys_freq_in_mhz = get_system_clock_speed_mhz();
@@ -758,6 +759,40 @@ pub fn get_cmpa_data(
     })
 }
 
+/// Writes the whole CMPA, which the ROM checks before accepting.
+///
+/// With `seal`, the ROM also records a digest of the page, after which the
+/// CMPA can never be written again. There's no undoing that, so the caller
+/// had better be sure.
+pub fn write_cmpa(
+    page: &[u8; FLASH_PAGE_SIZE],
+    seal: bool,
+) -> Result<(), FlashStatus> {
+    let mut f: FlashConfig = Default::default();
+    f.mode_config.sys_freq_in_mhz = get_system_clock_speed_mhz();
+
+    handle_flash_status(unsafe {
+        (bootloader_tree()
+            .flash_driver
+            .version1_flash_driver
+            .flash_init)(&mut f)
+    })?;
+
+    handle_flash_status(unsafe {
+        (bootloader_tree()
+            .flash_driver
+            .version1_flash_driver
+            .ffr_init)(&mut f)
+    })?;
+
+    handle_flash_status(unsafe {
+        (bootloader_tree()
+            .flash_driver
+            .version1_flash_driver
+            .ffr_cust_factory_page_write)(&mut f, page, seal)
+    })
+}
+
 // Keep this as a sample function for now
 pub fn get_bootloader_version() -> u32 {
     let version = &bootloader_tree().version;
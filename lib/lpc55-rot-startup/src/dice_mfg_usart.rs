@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::dice::{MfgResult, KEYCODE_LEN, KEY_INDEX, SEED_LEN};
-use core::ops::Deref;
+use core::ops::{Deref, Range};
 use dice_crate::{
     CertSerialNumber, DiceMfg, IntermediateCert, PersistIdCert, PersistIdSeed,
     PlatformId, SeedBuf, SerialMfg,
@@ -12,10 +12,14 @@ use hubpack::SerializedSize;
 use lib_lpc55_usart::Usart;
 use lpc55_pac::Peripherals;
 use lpc55_puf::Puf;
+use memory_map::flash::DICE_MFG;
 use salty::signature::Keypair;
 use serde::{Deserialize, Serialize};
 use static_assertions as sa;
 
+/// Where the results of manufacturing are kept, from the app's memory map
+const DICE_FLASH: Range<usize> = DICE_MFG.start as usize..DICE_MFG.end as usize;
+
 macro_rules! flash_page_align {
     ($size:expr) => {
         if $size % lpc55_romapi::FLASH_PAGE_SIZE != 0 {
@@ -297,5 +301,3 @@ fn flexcomm0_setup(
     // set flexcomm0 / uart clock to 12Mhz
     syscon.fcclksel0().modify(|_, w| w.sel().enum_0x2());
 }
-
-include!(concat!(env!("OUT_DIR"), "/dice-mfg.rs"));
@@ -0,0 +1,692 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A multicast DNS (RFC 6762) responder for a single DNS-SD (RFC 6763)
+//! service, without a network stack of its own.
+//!
+//! The service is advertised as `<instance>.<service>.local`, on a host
+//! named `<instance>.local`; we answer PTR queries for the service type (and
+//! for `_services._dns-sd._udp.local`, for browsers that want to know what's
+//! out there), SRV and TXT queries for the instance, and A and AAAA queries
+//! for the host. Its owner hands `respond` each packet that comes in on the
+//! mDNS port and sends what comes back, and sends what `announce` gives it
+//! when it starts up or its addresses change.
+//!
+//! This is a good deal less than the RFCs ask for. We don't probe for our
+//! names before claiming them, on the grounds that they're built from a
+//! serial number; we don't suppress answers that the querier says it
+//! already has; and we don't delay or aggregate responses. All of which
+//! costs some extra traffic on the link, and nothing else.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::iter::once;
+
+pub const PORT: u16 = 5353;
+
+/// Multicast groups that queries are sent to, and responses sent back to.
+pub const IPV6_GROUP: [u8; 16] =
+    [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xfb];
+pub const IPV4_GROUP: [u8; 4] = [224, 0, 0, 251];
+
+/// Largest packet we build, which is room enough for every record we have.
+pub const MAX_PACKET: usize = 1024;
+
+const HEADER: usize = 12;
+
+const FLAG_RESPONSE: u16 = 1 << 15;
+const FLAG_AUTHORITATIVE: u16 = 1 << 10;
+const OPCODE_MASK: u16 = 0xf << 11;
+
+const LOCAL: &[u8] = b"local";
+const DNS_SD: [&[u8]; 3] = [b"_services", b"_dns-sd", b"_udp"];
+
+mod rtype {
+    pub const A: u16 = 1;
+    pub const PTR: u16 = 12;
+    pub const TXT: u16 = 16;
+    pub const AAAA: u16 = 28;
+    pub const SRV: u16 = 33;
+    pub const ANY: u16 = 255;
+}
+
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+
+/// In a question, the top bit of the class asks for a unicast response; in
+/// a record, it tells caches to forget what else they have for the name.
+const CLASS_TOP_BIT: u16 = 1 << 15;
+
+/// TTLs for records naming a host, and for everything else (RFC 6762
+/// section 10), and the most we give a legacy resolver (section 6.7).
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+const LEGACY_TTL: u32 = 10;
+
+/// Records we have, as bits in a set.
+mod record {
+    pub const META_PTR: u8 = 1 << 0;
+    pub const PTR: u8 = 1 << 1;
+    pub const SRV: u8 = 1 << 2;
+    pub const TXT: u8 = 1 << 3;
+    pub const A: u8 = 1 << 4;
+    pub const AAAA: u8 = 1 << 5;
+
+    pub const ALL: [u8; 6] = [META_PTR, PTR, SRV, TXT, A, AAAA];
+}
+
+/// The service we advertise. Each name here is a sequence of labels, none
+/// longer than 63 bytes.
+#[derive(Copy, Clone, Debug)]
+pub struct Service<'a> {
+    /// Name of this instance of the service, which is also our host name
+    pub instance: &'a [u8],
+    /// Service type, e.g. `["_http", "_tcp"]`
+    pub service: &'a [&'a [u8]],
+    pub port: u16,
+    /// TXT record strings, usually `key=value`
+    pub txt: &'a [&'a [u8]],
+}
+
+impl<'a> Service<'a> {
+    fn meta_name(&self) -> impl Iterator<Item = &'a [u8]> + Clone {
+        DNS_SD.into_iter().chain(once(LOCAL))
+    }
+
+    fn service_name(&self) -> impl Iterator<Item = &'a [u8]> + Clone {
+        self.service.iter().copied().chain(once(LOCAL))
+    }
+
+    fn instance_name(&self) -> impl Iterator<Item = &'a [u8]> + Clone {
+        once(self.instance).chain(self.service_name())
+    }
+
+    fn host_name(&self) -> impl Iterator<Item = &'a [u8]> + Clone {
+        once(self.instance).chain(once(LOCAL))
+    }
+}
+
+/// Addresses of the host, for A and AAAA records.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Addresses {
+    pub ipv6: Option<[u8; 16]>,
+    pub ipv4: Option<[u8; 4]>,
+}
+
+/// Where a response should go.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Destination {
+    /// To the mDNS group, on the port
+    Multicast,
+    /// Back to whoever asked
+    Unicast,
+}
+
+/// Answers `query`, which came from port `src_port`, writing the response to
+/// `out`. Returns the length of the response and where it should go, or
+/// `None` if there's nothing to say (or no room to say it in).
+pub fn respond(
+    service: &Service<'_>,
+    addrs: &Addresses,
+    query: &[u8],
+    src_port: u16,
+    out: &mut [u8],
+) -> Option<(usize, Destination)> {
+    if query.len() < HEADER {
+        return None;
+    }
+    let flags = be16(query, 2)?;
+    if flags & (FLAG_RESPONSE | OPCODE_MASK) != 0 {
+        return None;
+    }
+    let questions = be16(query, 4)?;
+
+    let mut answers = 0;
+    let mut all_unicast = true;
+    let mut pos = HEADER;
+    for _ in 0..questions {
+        let name = pos;
+        pos = skip_name(query, pos)?;
+        let qtype = be16(query, pos)?;
+        let qclass = be16(query, pos + 2)?;
+        pos += 4;
+
+        all_unicast &= qclass & CLASS_TOP_BIT != 0;
+        if !matches!(qclass & !CLASS_TOP_BIT, CLASS_IN | CLASS_ANY) {
+            continue;
+        }
+        answers |= matching_records(service, addrs, query, name, qtype);
+    }
+    if answers == 0 {
+        return None;
+    }
+
+    // Queries from anything but the mDNS port come from simple resolvers,
+    // which need an answer that looks like one from a unicast DNS server
+    // (RFC 6762 section 6.7): with the ID and questions they sent, and
+    // without any mDNS-specific bits.
+    let legacy = src_port != PORT;
+    let mut r = Response::new(service, addrs, out, legacy);
+    if legacy {
+        r.w.put_u16(be16(query, 0)?)?;
+        r.w.put_u16(FLAG_RESPONSE | FLAG_AUTHORITATIVE)?;
+        r.w.put_u16(questions)?;
+        r.w.put(&[0; 6])?;
+        // Any compression pointers in here point into the question section,
+        // which is at the same offset in the response, so they still work.
+        r.w.put(&query[HEADER..pos])?;
+    } else {
+        r.w.put(&[0; 2])?;
+        r.w.put_u16(FLAG_RESPONSE | FLAG_AUTHORITATIVE)?;
+        r.w.put(&[0; 8])?;
+    }
+
+    // Give them what they'll want next, too (RFC 6763 section 12).
+    let mut additional = 0;
+    if answers & record::PTR != 0 {
+        additional |= record::SRV | record::TXT | record::A | record::AAAA;
+    }
+    if answers & (record::SRV | record::A | record::AAAA) != 0 {
+        additional |= record::A | record::AAAA;
+    }
+    additional &= !answers;
+
+    let ancount = r.records(answers)?;
+    let arcount = r.records(additional)?;
+    let len = r.w.len;
+    out[6..8].copy_from_slice(&ancount.to_be_bytes());
+    out[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+    let dest = if legacy || all_unicast {
+        Destination::Unicast
+    } else {
+        Destination::Multicast
+    };
+    Some((len, dest))
+}
+
+/// Writes an unsolicited response with every record we have to `out`, to be
+/// sent to the mDNS group. Returns its length, or `None` if it won't fit.
+pub fn announce(
+    service: &Service<'_>,
+    addrs: &Addresses,
+    out: &mut [u8],
+) -> Option<usize> {
+    let mut r = Response::new(service, addrs, out, false);
+    r.w.put(&[0; 2])?;
+    r.w.put_u16(FLAG_RESPONSE | FLAG_AUTHORITATIVE)?;
+    r.w.put(&[0; 8])?;
+    let ancount =
+        r.records(record::ALL.into_iter().fold(0, |acc, r| acc | r))?;
+    let len = r.w.len;
+    out[6..8].copy_from_slice(&ancount.to_be_bytes());
+    Some(len)
+}
+
+/// Works out which of our records answer a question about the name at
+/// `name` in `packet`, of type `qtype`.
+fn matching_records(
+    service: &Service<'_>,
+    addrs: &Addresses,
+    packet: &[u8],
+    name: usize,
+    qtype: u16,
+) -> u8 {
+    let wants = |t| qtype == t || qtype == rtype::ANY;
+    let mut records = 0;
+    if name_is(packet, name, service.meta_name()) && wants(rtype::PTR) {
+        records |= record::META_PTR;
+    }
+    if name_is(packet, name, service.service_name()) && wants(rtype::PTR) {
+        records |= record::PTR;
+    }
+    if name_is(packet, name, service.instance_name()) {
+        if wants(rtype::SRV) {
+            records |= record::SRV;
+        }
+        if wants(rtype::TXT) {
+            records |= record::TXT;
+        }
+    }
+    if name_is(packet, name, service.host_name()) {
+        if wants(rtype::A) && addrs.ipv4.is_some() {
+            records |= record::A;
+        }
+        if wants(rtype::AAAA) && addrs.ipv6.is_some() {
+            records |= record::AAAA;
+        }
+    }
+    records
+}
+
+fn be16(packet: &[u8], pos: usize) -> Option<u16> {
+    let b = packet.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+/// Returns the offset just past the name at `pos` in `packet`.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = usize::from(*packet.get(pos)?);
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xc0 == 0xc0 => {
+                packet.get(pos + 1)?;
+                return Some(pos + 2);
+            }
+            l if l < 64 => pos += 1 + l,
+            _ => return None,
+        }
+    }
+}
+
+/// Checks whether the name at `pos` in `packet` is `labels`, ignoring case
+/// (as names are compared in DNS) and following compression pointers.
+fn name_is<'a>(
+    packet: &[u8],
+    mut pos: usize,
+    mut labels: impl Iterator<Item = &'a [u8]>,
+) -> bool {
+    loop {
+        let len = match packet.get(pos) {
+            Some(&len) => usize::from(len),
+            None => return false,
+        };
+        match len {
+            0 => return labels.next().is_none(),
+            l if l & 0xc0 == 0xc0 => {
+                let target = match packet.get(pos + 1) {
+                    Some(&lo) => (l & 0x3f) << 8 | usize::from(lo),
+                    None => return false,
+                };
+                // Only ever pointing backwards keeps us out of loops.
+                if target >= pos {
+                    return false;
+                }
+                pos = target;
+            }
+            l if l < 64 => {
+                let label = match packet.get(pos + 1..pos + 1 + l) {
+                    Some(label) => label,
+                    None => return false,
+                };
+                match labels.next() {
+                    Some(want) if want.eq_ignore_ascii_case(label) => {
+                        pos += 1 + l;
+                    }
+                    _ => return false,
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+
+    fn put_u16(&mut self, v: u16) -> Option<()> {
+        self.put(&v.to_be_bytes())
+    }
+
+    fn put_u32(&mut self, v: u32) -> Option<()> {
+        self.put(&v.to_be_bytes())
+    }
+
+    fn put_name<'b>(
+        &mut self,
+        labels: impl Iterator<Item = &'b [u8]>,
+    ) -> Option<()> {
+        for label in labels {
+            self.put(&[label.len() as u8])?;
+            self.put(label)?;
+        }
+        self.put(&[0])
+    }
+}
+
+struct Response<'a, 's> {
+    service: &'s Service<'s>,
+    addrs: &'s Addresses,
+    w: Writer<'a>,
+    legacy: bool,
+}
+
+impl<'a, 's> Response<'a, 's> {
+    fn new(
+        service: &'s Service<'s>,
+        addrs: &'s Addresses,
+        buf: &'a mut [u8],
+        legacy: bool,
+    ) -> Self {
+        Self {
+            service,
+            addrs,
+            w: Writer { buf, len: 0 },
+            legacy,
+        }
+    }
+
+    /// Writes each of the records in `set`, returning how many there were.
+    fn records(&mut self, set: u8) -> Option<u16> {
+        let mut count = 0;
+        for r in record::ALL {
+            if set & r != 0 && self.record(r)? {
+                count += 1;
+            }
+        }
+        Some(count)
+    }
+
+    /// Writes record `r`, returning false if we don't have it after all.
+    fn record(&mut self, r: u8) -> Option<bool> {
+        let s = self.service;
+        match r {
+            record::META_PTR => {
+                self.header(s.meta_name(), rtype::PTR, false, OTHER_TTL)?;
+                self.rdata(|w| w.put_name(s.service_name()))?;
+            }
+            record::PTR => {
+                self.header(s.service_name(), rtype::PTR, false, OTHER_TTL)?;
+                self.rdata(|w| w.put_name(s.instance_name()))?;
+            }
+            record::SRV => {
+                self.header(s.instance_name(), rtype::SRV, true, HOST_TTL)?;
+                self.rdata(|w| {
+                    // Priority and weight, which don't matter with only one
+                    // of us.
+                    w.put(&[0; 4])?;
+                    w.put_u16(s.port)?;
+                    w.put_name(s.host_name())
+                })?;
+            }
+            record::TXT => {
+                self.header(s.instance_name(), rtype::TXT, true, OTHER_TTL)?;
+                self.rdata(|w| {
+                    // An empty TXT record still has to have one string in
+                    // it (RFC 6763 section 6.1).
+                    if s.txt.is_empty() {
+                        return w.put(&[0]);
+                    }
+                    for t in s.txt {
+                        w.put(&[t.len() as u8])?;
+                        w.put(t)?;
+                    }
+                    Some(())
+                })?;
+            }
+            record::A => {
+                let a = match self.addrs.ipv4 {
+                    Some(a) => a,
+                    None => return Some(false),
+                };
+                self.header(s.host_name(), rtype::A, true, HOST_TTL)?;
+                self.rdata(|w| w.put(&a))?;
+            }
+            record::AAAA => {
+                let a = match self.addrs.ipv6 {
+                    Some(a) => a,
+                    None => return Some(false),
+                };
+                self.header(s.host_name(), rtype::AAAA, true, HOST_TTL)?;
+                self.rdata(|w| w.put(&a))?;
+            }
+            _ => return Some(false),
+        }
+        Some(true)
+    }
+
+    fn header<'b>(
+        &mut self,
+        name: impl Iterator<Item = &'b [u8]>,
+        rtype: u16,
+        unique: bool,
+        ttl: u32,
+    ) -> Option<()> {
+        self.w.put_name(name)?;
+        self.w.put_u16(rtype)?;
+        if self.legacy {
+            self.w.put_u16(CLASS_IN)?;
+            self.w.put_u32(ttl.min(LEGACY_TTL))
+        } else {
+            let flush = if unique { CLASS_TOP_BIT } else { 0 };
+            self.w.put_u16(CLASS_IN | flush)?;
+            self.w.put_u32(ttl)
+        }
+    }
+
+    /// Writes the RDATA length and then, with `body`, the RDATA.
+    fn rdata(
+        &mut self,
+        body: impl FnOnce(&mut Writer<'a>) -> Option<()>,
+    ) -> Option<()> {
+        let at = self.w.len;
+        self.w.put_u16(0)?;
+        body(&mut self.w)?;
+        let len = (self.w.len - at - 2) as u16;
+        self.w.buf[at..at + 2].copy_from_slice(&len.to_be_bytes());
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICE: Service<'static> = Service {
+        instance: b"gimlet-BRM42220001",
+        service: &[b"_oxide-sp", b"_udp"],
+        port: 11111,
+        txt: &[b"board=gimlet", b"serial=BRM42220001"],
+    };
+
+    const ADDRS: Addresses = Addresses {
+        ipv6: Some([
+            0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0xaa, 0x40, 0x25, 0xff, 0xfe, 0, 0, 1,
+        ]),
+        ipv4: None,
+    };
+
+    fn query(id: u16, questions: &[(&[&[u8]], u16, u16)]) -> Vec<u8> {
+        let mut q = vec![];
+        q.extend(id.to_be_bytes());
+        q.extend([0, 0]);
+        q.extend((questions.len() as u16).to_be_bytes());
+        q.extend([0; 6]);
+        for (name, qtype, qclass) in questions {
+            for label in *name {
+                q.push(label.len() as u8);
+                q.extend(*label);
+            }
+            q.push(0);
+            q.extend(qtype.to_be_bytes());
+            q.extend(qclass.to_be_bytes());
+        }
+        q
+    }
+
+    /// Types, classes and TTLs of the answers and additional records in a
+    /// response.
+    type Records = Vec<(u16, u16, u32)>;
+
+    fn parse(packet: &[u8]) -> (Records, Records) {
+        let questions = be16(packet, 4).unwrap();
+        let ancount = be16(packet, 6).unwrap();
+        let arcount = be16(packet, 10).unwrap();
+        let mut pos = HEADER;
+        for _ in 0..questions {
+            pos = skip_name(packet, pos).unwrap() + 4;
+        }
+        let mut records = vec![];
+        for _ in 0..ancount + arcount {
+            pos = skip_name(packet, pos).unwrap();
+            let rtype = be16(packet, pos).unwrap();
+            let class = be16(packet, pos + 2).unwrap();
+            let ttl = u32::from_be_bytes(
+                packet[pos + 4..pos + 8].try_into().unwrap(),
+            );
+            let rdlen = be16(packet, pos + 8).unwrap();
+            pos += 10 + usize::from(rdlen);
+            records.push((rtype, class, ttl));
+        }
+        assert_eq!(pos, packet.len());
+        let additional = records.split_off(usize::from(ancount));
+        (records, additional)
+    }
+
+    fn types(records: &Records) -> Vec<u16> {
+        records.iter().map(|r| r.0).collect()
+    }
+
+    #[test]
+    fn service_ptr() {
+        let q = query(0, &[(&[b"_oxide-sp", b"_udp", b"local"], 12, 1)]);
+        let mut out = [0; MAX_PACKET];
+        let (len, dest) =
+            respond(&SERVICE, &ADDRS, &q, PORT, &mut out).unwrap();
+        assert_eq!(dest, Destination::Multicast);
+        let (answers, additional) = parse(&out[..len]);
+        assert_eq!(answers, vec![(rtype::PTR, CLASS_IN, OTHER_TTL)]);
+        // No A, since we've no IPv4 address.
+        assert_eq!(
+            types(&additional),
+            vec![rtype::SRV, rtype::TXT, rtype::AAAA]
+        );
+        assert_eq!(additional[0].1, CLASS_IN | CLASS_TOP_BIT);
+    }
+
+    #[test]
+    fn meta_ptr() {
+        let q = query(
+            0,
+            &[(&[b"_services", b"_dns-sd", b"_udp", b"local"], 12, 1)],
+        );
+        let mut out = [0; MAX_PACKET];
+        let (len, _) = respond(&SERVICE, &ADDRS, &q, PORT, &mut out).unwrap();
+        let (answers, additional) = parse(&out[..len]);
+        assert_eq!(types(&answers), vec![rtype::PTR]);
+        assert!(additional.is_empty());
+    }
+
+    #[test]
+    fn host_any_ignores_case() {
+        let addrs = Addresses {
+            ipv4: Some([10, 0, 0, 2]),
+            ..ADDRS
+        };
+        let q = query(
+            0,
+            &[(&[b"GIMLET-brm42220001", b"LOCAL"], 255, 1 | CLASS_TOP_BIT)],
+        );
+        let mut out = [0; MAX_PACKET];
+        let (len, dest) =
+            respond(&SERVICE, &addrs, &q, PORT, &mut out).unwrap();
+        assert_eq!(dest, Destination::Unicast);
+        let (answers, additional) = parse(&out[..len]);
+        assert_eq!(types(&answers), vec![rtype::A, rtype::AAAA]);
+        assert!(additional.is_empty());
+    }
+
+    #[test]
+    fn legacy_unicast() {
+        // A question we've nothing for, and then one whose name ends in a
+        // pointer back into the first.
+        let mut q = query(
+            0x1234,
+            &[(&[b"gimlet-BRM42220001", b"local"], rtype::TXT, 1)],
+        );
+        let local = HEADER + 1 + 18;
+        q[5] = 2;
+        q.push(18);
+        q.extend(b"gimlet-BRM42220001");
+        q.extend([0xc0 | (local >> 8) as u8, local as u8]);
+        q.extend(rtype::AAAA.to_be_bytes());
+        q.extend(1u16.to_be_bytes());
+
+        let mut out = [0; MAX_PACKET];
+        let (len, dest) =
+            respond(&SERVICE, &ADDRS, &q, 40000, &mut out).unwrap();
+        assert_eq!(dest, Destination::Unicast);
+        assert_eq!(&out[..2], &[0x12, 0x34]);
+        assert_eq!(be16(&out, 4), Some(2));
+        let (answers, additional) = parse(&out[..len]);
+        assert_eq!(answers, vec![(rtype::AAAA, CLASS_IN, LEGACY_TTL)]);
+        assert!(additional.is_empty());
+    }
+
+    #[test]
+    fn no_answer() {
+        let mut out = [0; MAX_PACKET];
+        let q = query(0, &[(&[b"someone-else", b"local"], 28, 1)]);
+        assert_eq!(respond(&SERVICE, &ADDRS, &q, PORT, &mut out), None);
+        // We've no A record to give.
+        let q = query(0, &[(&[b"gimlet-BRM42220001", b"local"], 1, 1)]);
+        assert_eq!(respond(&SERVICE, &ADDRS, &q, PORT, &mut out), None);
+        // Nor do we answer responses.
+        let mut q = query(0, &[(&[b"gimlet-BRM42220001", b"local"], 28, 1)]);
+        q[2] = 0x84;
+        assert_eq!(respond(&SERVICE, &ADDRS, &q, PORT, &mut out), None);
+    }
+
+    #[test]
+    fn pointer_loop() {
+        let mut q = query(0, &[]);
+        q[5] = 1;
+        // A pointer to itself.
+        q.extend([0xc0, HEADER as u8]);
+        q.extend(28u16.to_be_bytes());
+        q.extend(1u16.to_be_bytes());
+        let mut out = [0; MAX_PACKET];
+        assert_eq!(respond(&SERVICE, &ADDRS, &q, PORT, &mut out), None);
+    }
+
+    #[test]
+    fn truncated() {
+        let q = query(0, &[(&[b"_oxide-sp", b"_udp", b"local"], 12, 1)]);
+        for len in 0..q.len() {
+            let mut out = [0; MAX_PACKET];
+            assert_eq!(
+                respond(&SERVICE, &ADDRS, &q[..len], PORT, &mut out),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn announcement() {
+        let addrs = Addresses {
+            ipv4: Some([10, 0, 0, 2]),
+            ..ADDRS
+        };
+        let mut out = [0; MAX_PACKET];
+        let len = announce(&SERVICE, &addrs, &mut out).unwrap();
+        let (answers, additional) = parse(&out[..len]);
+        assert_eq!(
+            types(&answers),
+            vec![
+                rtype::PTR,
+                rtype::PTR,
+                rtype::SRV,
+                rtype::TXT,
+                rtype::A,
+                rtype::AAAA
+            ]
+        );
+        assert!(additional.is_empty());
+
+        // And it doesn't fit in anything much smaller.
+        let mut out = [0; 64];
+        assert_eq!(announce(&SERVICE, &addrs, &mut out), None);
+    }
+}
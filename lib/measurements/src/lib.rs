@@ -0,0 +1,255 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Measurement registers, which the RoT keeps for the SP's boot.
+//!
+//! Each register starts out as zeroes, and extending it with a digest
+//! replaces its value `r` with `SHA3-256(r || digest)`, so a register's value
+//! commits to everything extended into it, in order, since it was last
+//! reset.
+//!
+//! A quote of the registers is signed over [`QUOTE_DOMAIN_TAG`] followed by
+//! the hubpack serialization of a [`Quote`]. The tag isn't part of what's
+//! handed back, so a verifier has to put it back in front of the quote before
+//! checking the signature; it's there so that a signature over a quote can't
+//! be passed off as one over anything else the same key signs.
+
+#![cfg_attr(not(test), no_std)]
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use unwrap_lite::UnwrapLite;
+
+/// Number of measurement registers
+pub const NUM_MEASUREMENT_REGS: usize = 8;
+
+/// Size of a measurement register, which is a SHA3-256 digest
+pub const MEASUREMENT_SIZE: usize = 32;
+
+/// Largest digest that can be extended into a register
+pub const MAX_MEASUREMENT_DIGEST: usize = 64;
+
+/// Size of an Ed25519 signature
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// What's signed ahead of a serialized [`Quote`]
+pub const QUOTE_DOMAIN_TAG: &[u8] = b"hubris-measurement-quote-v1";
+
+/// An error from the measurement registers
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum MeasurementError {
+    /// There's no register with that index.
+    BadIndex,
+    /// The digest is empty or too long.
+    BadDigest,
+    /// The RoT has no attestation key, so it can't sign a quote.
+    NoKey,
+}
+
+/// The measurement registers, as signed by the RoT
+#[derive(Clone, Serialize, Deserialize, SerializedSize)]
+pub struct Quote {
+    /// The nonce from the request, so that quotes can't be replayed
+    pub nonce: [u8; 32],
+    /// Number of times the registers have been reset
+    pub sp_resets: u32,
+    /// Number of times each register has been extended since it was reset
+    pub extends: [u32; NUM_MEASUREMENT_REGS],
+    pub registers: [[u8; MEASUREMENT_SIZE]; NUM_MEASUREMENT_REGS],
+}
+
+pub struct Registers {
+    registers: [[u8; MEASUREMENT_SIZE]; NUM_MEASUREMENT_REGS],
+    extends: [u32; NUM_MEASUREMENT_REGS],
+    resets: u32,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registers {
+    pub const fn new() -> Self {
+        Self {
+            registers: [[0; MEASUREMENT_SIZE]; NUM_MEASUREMENT_REGS],
+            extends: [0; NUM_MEASUREMENT_REGS],
+            resets: 0,
+        }
+    }
+
+    /// Clears every register, counting the reset.
+    pub fn reset(&mut self) {
+        self.registers = [[0; MEASUREMENT_SIZE]; NUM_MEASUREMENT_REGS];
+        self.extends = [0; NUM_MEASUREMENT_REGS];
+        self.resets = self.resets.wrapping_add(1);
+    }
+
+    fn index(index: u32) -> Result<usize, MeasurementError> {
+        let index = index as usize;
+        if index < NUM_MEASUREMENT_REGS {
+            Ok(index)
+        } else {
+            Err(MeasurementError::BadIndex)
+        }
+    }
+
+    /// Extends register `index` with `digest`, returning the index.
+    pub fn extend(
+        &mut self,
+        index: u32,
+        digest: &[u8],
+    ) -> Result<usize, MeasurementError> {
+        let index = Self::index(index)?;
+        if digest.is_empty() || digest.len() > MAX_MEASUREMENT_DIGEST {
+            return Err(MeasurementError::BadDigest);
+        }
+
+        let register = &mut self.registers[index];
+        let mut hasher = Sha3_256::new();
+        hasher.update(&register[..]);
+        hasher.update(digest);
+        register.copy_from_slice(&hasher.finalize());
+
+        self.extends[index] = self.extends[index].wrapping_add(1);
+        Ok(index)
+    }
+
+    pub fn read(
+        &self,
+        index: u32,
+    ) -> Result<[u8; MEASUREMENT_SIZE], MeasurementError> {
+        Ok(self.registers[Self::index(index)?])
+    }
+
+    /// Writes a quote of every register to `out`, followed by `sign`'s
+    /// signature of [`QUOTE_DOMAIN_TAG`] and the quote, returning the length
+    /// of the two.
+    ///
+    /// `out` must have room for `Quote::MAX_SIZE + SIGNATURE_SIZE` bytes.
+    pub fn quote(
+        &self,
+        nonce: [u8; 32],
+        out: &mut [u8],
+        sign: impl FnOnce(&[u8]) -> [u8; SIGNATURE_SIZE],
+    ) -> usize {
+        let quote = Quote {
+            nonce,
+            sp_resets: self.resets,
+            extends: self.extends,
+            registers: self.registers,
+        };
+
+        const TAG_LEN: usize = QUOTE_DOMAIN_TAG.len();
+        let mut message = [0; TAG_LEN + Quote::MAX_SIZE];
+        message[..TAG_LEN].copy_from_slice(QUOTE_DOMAIN_TAG);
+        let len =
+            hubpack::serialize(&mut message[TAG_LEN..], &quote).unwrap_lite();
+        let message = &message[..TAG_LEN + len];
+
+        out[..len].copy_from_slice(&message[TAG_LEN..]);
+        out[len..][..SIGNATURE_SIZE].copy_from_slice(&sign(message));
+        len + SIGNATURE_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(register: [u8; MEASUREMENT_SIZE], digest: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha3_256::new();
+        hasher.update(register);
+        hasher.update(digest);
+        hasher.finalize().into()
+    }
+
+    #[test]
+    fn extend_chains() {
+        let mut regs = Registers::new();
+        assert_eq!(regs.extend(2, b"first"), Ok(2));
+        assert_eq!(regs.extend(2, b"second"), Ok(2));
+
+        let expected = chain(chain([0; MEASUREMENT_SIZE], b"first"), b"second");
+        assert_eq!(regs.read(2), Ok(expected));
+        assert_eq!(regs.extends[2], 2);
+
+        // The same digests the other way round give something else.
+        let mut swapped = Registers::new();
+        swapped.extend(2, b"second").unwrap();
+        swapped.extend(2, b"first").unwrap();
+        assert_ne!(swapped.read(2), Ok(expected));
+
+        // And no other register has moved.
+        for i in (0..NUM_MEASUREMENT_REGS as u32).filter(|&i| i != 2) {
+            assert_eq!(regs.read(i), Ok([0; MEASUREMENT_SIZE]));
+        }
+    }
+
+    #[test]
+    fn extend_checks_arguments() {
+        let mut regs = Registers::new();
+        let index = NUM_MEASUREMENT_REGS as u32;
+        assert_eq!(regs.extend(index, b"x"), Err(MeasurementError::BadIndex));
+        assert_eq!(regs.read(index), Err(MeasurementError::BadIndex));
+        assert_eq!(regs.extend(0, &[]), Err(MeasurementError::BadDigest));
+        assert_eq!(
+            regs.extend(0, &[0; MAX_MEASUREMENT_DIGEST + 1]),
+            Err(MeasurementError::BadDigest)
+        );
+        assert_eq!(regs.extend(0, &[0; MAX_MEASUREMENT_DIGEST]), Ok(0));
+    }
+
+    #[test]
+    fn reset_clears_and_counts() {
+        let mut regs = Registers::new();
+        for i in 0..NUM_MEASUREMENT_REGS as u32 {
+            regs.extend(i, b"measured").unwrap();
+        }
+        regs.reset();
+        regs.reset();
+
+        for i in 0..NUM_MEASUREMENT_REGS as u32 {
+            assert_eq!(regs.read(i), Ok([0; MEASUREMENT_SIZE]));
+        }
+        assert_eq!(regs.extends, [0; NUM_MEASUREMENT_REGS]);
+        assert_eq!(regs.resets, 2);
+    }
+
+    #[test]
+    fn quote_layout() {
+        let mut regs = Registers::new();
+        regs.reset();
+        regs.extend(1, b"measured").unwrap();
+        let register = regs.read(1).unwrap();
+
+        let mut out = [0; Quote::MAX_SIZE + SIGNATURE_SIZE];
+        let mut signed = Vec::new();
+        let len = regs.quote([0x5a; 32], &mut out, |message| {
+            signed = message.to_vec();
+            [0xee; SIGNATURE_SIZE]
+        });
+        assert_eq!(len, out.len());
+
+        // The nonce, the resets, every register's extend count, then every
+        // register's value, with integers little-endian.
+        let quote = &out[..Quote::MAX_SIZE];
+        assert_eq!(quote.len(), 32 + 4 + 8 * 4 + 8 * 32);
+        assert_eq!(&quote[..32], &[0x5a; 32]);
+        assert_eq!(&quote[32..36], &1u32.to_le_bytes());
+        assert_eq!(&quote[36..40], &0u32.to_le_bytes());
+        assert_eq!(&quote[40..44], &1u32.to_le_bytes());
+        assert_eq!(&quote[68..100], &[0; MEASUREMENT_SIZE]);
+        assert_eq!(&quote[100..132], &register);
+
+        // The signature is of the tag and the quote, and follows the quote.
+        assert_eq!(&signed[..QUOTE_DOMAIN_TAG.len()], QUOTE_DOMAIN_TAG);
+        assert_eq!(&signed[QUOTE_DOMAIN_TAG.len()..], quote);
+        assert_eq!(&out[Quote::MAX_SIZE..], &[0xee; SIGNATURE_SIZE]);
+    }
+}
@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Our subset of `xtask`'s `Output`; this must not be marked with
+/// `deny_unknown_fields`!
+#[derive(Deserialize)]
+struct Region {
+    name: String,
+    address: u32,
+    size: u32,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let map = build_util::env_var("HUBRIS_MEMORY_MAP").map_err(|e| {
+        format!("{e}; does the app.toml set `memory-map = true`?")
+    })?;
+    let map: BTreeMap<String, Vec<Region>> = toml::from_str(&map)?;
+
+    let out_dir = build_util::out_dir();
+    let mut out = std::fs::File::create(out_dir.join("memory_map.rs"))?;
+
+    for (kind, regions) in &map {
+        writeln!(out, "pub mod {} {{", ident(kind).to_lowercase())?;
+        writeln!(out, "    use core::ops::Range;")?;
+        for r in regions {
+            let end = r
+                .address
+                .checked_add(r.size)
+                .ok_or_else(|| format!("{kind} region {} overflows", r.name))?;
+            writeln!(
+                out,
+                "    pub const {}: Range<u32> = {:#010x}..{:#010x};",
+                ident(&r.name).to_uppercase(),
+                r.address,
+                end,
+            )?;
+        }
+        writeln!(out, "}}")?;
+    }
+
+    Ok(())
+}
+
+/// Turns a region name, like `dice-mfg`, into an identifier.
+fn ident(name: &str) -> String {
+    name.replace('-', "_")
+}
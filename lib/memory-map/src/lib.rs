@@ -0,0 +1,20 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The app's memory map, generated from the same config as the linker
+//! scripts, for the kernel, tasks, and host tools to share.
+//!
+//! There is a module for each kind of memory in the chip's `memory.toml` (such
+//! as `flash` or `ram`), holding the address range of each of its regions,
+//! named after the image (or other use) it's for:
+//!
+//! ```ignore
+//! let stage0: Range<u32> = memory_map::flash::STAGE0;
+//! ```
+//!
+//! This is only available to apps that set `memory-map = true`.
+
+#![no_std]
+
+include!(concat!(env!("OUT_DIR"), "/memory_map.rs"));
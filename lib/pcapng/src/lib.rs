@@ -0,0 +1,244 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Just enough of pcap-ng to write captured frames out: the section header,
+//! interface description and enhanced packet blocks, little-endian, each
+//! written into a buffer the caller owns.
+//!
+//! See draft-ietf-opsawg-pcapng for the format.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Link type for Ethernet frames (what libpcap calls `DLT_EN10MB`).
+pub const LINKTYPE_ETHERNET: u16 = 1;
+
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const ENHANCED_PACKET: u32 = 0x0000_0006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_END: u16 = 0;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+/// Length of a section header block, which has no options.
+pub const SECTION_HEADER_LEN: usize = 28;
+
+/// Length of an interface description block, with its one option.
+pub const INTERFACE_DESCRIPTION_LEN: usize = 32;
+
+/// Length of an enhanced packet block, less its (padded) data.
+pub const ENHANCED_PACKET_OVERHEAD: usize = 44;
+
+/// Which way a frame was going.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Writes a section header block, which starts a capture, to the start of
+/// `out`. Returns its length, or `None` if `out` is too short.
+pub fn section_header(out: &mut [u8]) -> Option<usize> {
+    let mut w = Block::start(out, SECTION_HEADER)?;
+    w.u32(BYTE_ORDER_MAGIC)?;
+    // Version 1.0, of unknown length.
+    w.u16(1)?;
+    w.u16(0)?;
+    w.bytes(&u64::MAX.to_le_bytes())?;
+    w.finish()
+}
+
+/// Writes an interface description block to the start of `out`, for an
+/// interface whose frames have link type `linktype`, are cut to `snaplen`
+/// bytes, and have timestamps in units of 10^-`tsresol` seconds. The first
+/// such block in a section is interface 0, the next interface 1, and so on.
+/// Returns its length, or `None` if `out` is too short.
+pub fn interface_description(
+    out: &mut [u8],
+    linktype: u16,
+    snaplen: u32,
+    tsresol: u8,
+) -> Option<usize> {
+    let mut w = Block::start(out, INTERFACE_DESCRIPTION)?;
+    w.u16(linktype)?;
+    w.u16(0)?;
+    w.u32(snaplen)?;
+    w.option(IF_TSRESOL, &[tsresol])?;
+    w.option(OPT_END, &[])?;
+    w.finish()
+}
+
+/// Writes an enhanced packet block to the start of `out`, holding `data`,
+/// which is all or the start of a frame `orig_len` bytes long, seen on
+/// `interface` at `timestamp` (in the interface's units) going in
+/// `direction`. Returns its length, or `None` if `out` is too short.
+pub fn enhanced_packet(
+    out: &mut [u8],
+    interface: u32,
+    timestamp: u64,
+    data: &[u8],
+    orig_len: u32,
+    direction: Direction,
+) -> Option<usize> {
+    let mut w = Block::start(out, ENHANCED_PACKET)?;
+    w.u32(interface)?;
+    w.u32((timestamp >> 32) as u32)?;
+    w.u32(timestamp as u32)?;
+    w.u32(data.len() as u32)?;
+    w.u32(orig_len)?;
+    w.bytes(data)?;
+    w.pad()?;
+    let flags: u32 = match direction {
+        Direction::Inbound => 0b01,
+        Direction::Outbound => 0b10,
+    };
+    w.option(EPB_FLAGS, &flags.to_le_bytes())?;
+    w.option(OPT_END, &[])?;
+    w.finish()
+}
+
+/// Length of the enhanced packet block `enhanced_packet` would write for
+/// `data_len` bytes of data.
+pub fn enhanced_packet_len(data_len: usize) -> usize {
+    ENHANCED_PACKET_OVERHEAD + padded(data_len)
+}
+
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A block being written: its type and length, then its body, then its
+/// length again.
+struct Block<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Block<'a> {
+    fn start(buf: &'a mut [u8], block_type: u32) -> Option<Self> {
+        let mut b = Self { buf, len: 0 };
+        b.u32(block_type)?;
+        // Length, which we fill in at the end.
+        b.u32(0)?;
+        Some(b)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+
+    fn u16(&mut self, v: u16) -> Option<()> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> Option<()> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    fn pad(&mut self) -> Option<()> {
+        let n = padded(self.len) - self.len;
+        self.bytes(&[0; 3][..n])
+    }
+
+    fn option(&mut self, code: u16, value: &[u8]) -> Option<()> {
+        self.u16(code)?;
+        self.u16(value.len() as u16)?;
+        self.bytes(value)?;
+        self.pad()
+    }
+
+    fn finish(mut self) -> Option<usize> {
+        let len = self.len as u32 + 4;
+        self.u32(len)?;
+        self.buf[4..8].copy_from_slice(&len.to_le_bytes());
+        Some(self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn le32(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    /// Checks the framing every block has, returning its body.
+    fn body(block: &[u8], block_type: u32) -> &[u8] {
+        assert_eq!(le32(block, 0), block_type);
+        assert_eq!(le32(block, 4) as usize, block.len());
+        assert_eq!(le32(block, block.len() - 4) as usize, block.len());
+        assert_eq!(block.len() % 4, 0);
+        &block[8..block.len() - 4]
+    }
+
+    #[test]
+    fn section() {
+        let mut out = [0xff; 64];
+        let len = section_header(&mut out).unwrap();
+        assert_eq!(len, SECTION_HEADER_LEN);
+        let b = body(&out[..len], SECTION_HEADER);
+        assert_eq!(&b[..4], &[0x4d, 0x3c, 0x2b, 0x1a]);
+        assert_eq!(&b[4..8], &[1, 0, 0, 0]);
+        assert_eq!(&b[8..], &[0xff; 8]);
+    }
+
+    #[test]
+    fn interface() {
+        let mut out = [0xff; 64];
+        let len =
+            interface_description(&mut out, LINKTYPE_ETHERNET, 128, 3).unwrap();
+        assert_eq!(len, INTERFACE_DESCRIPTION_LEN);
+        let b = body(&out[..len], INTERFACE_DESCRIPTION);
+        assert_eq!(&b[..8], &[1, 0, 0, 0, 128, 0, 0, 0]);
+        // if_tsresol, padded, then the end of the options.
+        assert_eq!(&b[8..], &[9, 0, 1, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn packet() {
+        let data = [0xaa; 5];
+        let mut out = [0xff; 128];
+        let ts = 0x1234_5678_9abc;
+        let len =
+            enhanced_packet(&mut out, 1, ts, &data, 60, Direction::Outbound)
+                .unwrap();
+        assert_eq!(len, enhanced_packet_len(data.len()));
+        let b = body(&out[..len], ENHANCED_PACKET);
+        assert_eq!(le32(b, 0), 1);
+        assert_eq!(le32(b, 4), 0x1234);
+        assert_eq!(le32(b, 8), 0x5678_9abc);
+        assert_eq!(le32(b, 12), 5);
+        assert_eq!(le32(b, 16), 60);
+        assert_eq!(&b[20..28], &[0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0, 0, 0]);
+        // epb_flags, saying outbound, then the end of the options.
+        assert_eq!(&b[28..], &[2, 0, 4, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn too_short() {
+        let data = [0; 8];
+        let need = enhanced_packet_len(data.len());
+        for n in 0..need {
+            let mut out = vec![0; n];
+            assert_eq!(
+                enhanced_packet(&mut out, 0, 0, &data, 8, Direction::Inbound),
+                None
+            );
+        }
+        let mut out = vec![0; need];
+        assert_eq!(
+            enhanced_packet(&mut out, 0, 0, &data, 8, Direction::Inbound),
+            Some(need)
+        );
+
+        let mut out = [0; SECTION_HEADER_LEN - 1];
+        assert_eq!(section_header(&mut out), None);
+    }
+}
@@ -17,6 +17,22 @@ impl PerfectHash for u32 {
     }
 }
 
+/// Scrambles the output of [`PerfectHash::phash`], which on its own is a
+/// plain multiplication: its low bits only depend on the low bits of the key,
+/// so keys that differ in their high bits tend to collide modulo small table
+/// sizes. This works on 32 bits so that the build machine and the target
+/// agree.
+#[inline(always)]
+pub fn mix(h: usize) -> usize {
+    let mut x = h as u32;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x as usize
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct PerfectHashMap<'a, K, V> {
@@ -80,6 +96,45 @@ impl<'a, K: Copy + PerfectHash + PartialEq, V> NestedPerfectHashMap<'a, K, V> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// A two-level ("hash and displace") perfect hash map, for tables that are
+/// too large or awkward for [`PerfectHashMap`].
+///
+/// Keys are hashed with `m` into one of the buckets in `g`. Each bucket has its
+/// own seed, chosen so that its keys land in free slots of a single flat table
+/// of `values`. Unlike [`NestedPerfectHashMap`], this copes with hundreds of
+/// keys, and keeps the table nearly full.
+pub struct DisplacedPerfectHashMap<'a, K, V> {
+    pub m: u32,
+    pub g: &'a [u32],
+    pub values: &'a [(K, V)],
+}
+
+impl<'a, K: Copy + PerfectHash + PartialEq, V>
+    DisplacedPerfectHashMap<'a, K, V>
+{
+    /// Looks up a value in the table by key, returning `None` if the key was
+    /// not stored in the table.
+    #[inline(always)]
+    pub fn get(&self, key: K) -> Option<&V> {
+        if self.g.is_empty() || self.values.is_empty() {
+            return None;
+        }
+        let i = mix(key.phash(self.m)) % self.g.len();
+        let j = mix(key.phash(self.g[i])) % self.values.len();
+        if key == self.values[j].0 {
+            Some(&self.values[j].1)
+        } else {
+            None
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(K, V)> {
+        self.values.iter()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub struct SortedList<'a, K, V> {
     pub values: &'a [(K, V)],
 }
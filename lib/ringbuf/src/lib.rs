@@ -62,6 +62,25 @@
 //! ringbuf_entry!((temp, Some(Register::TempMSB)));
 //! ```
 //!
+//! ## Timestamps and dropped entries
+//!
+//! Entries record the order of events, but not when they happened. If that
+//! matters, declare the ring buffer with [`timestamped_ringbuf!`] instead,
+//! which takes the same arguments; each entry's payload is then wrapped in a
+//! [`Timestamped`], recording the kernel's timer (in ticks) when the entry
+//! was made. Entries are added with [`ringbuf_entry!`] either way.
+//!
+//! ```
+//! timestamped_ringbuf!(Trace, 64, Trace::None);
+//! ```
+//!
+//! A run of identical entries is still recorded as one entry with a `count`,
+//! and is stamped with the time the run began.
+//!
+//! Every ring buffer also counts the entries it has `dropped`: those
+//! overwritten when it wrapped around. Without this, a buffer that has
+//! wrapped once looks just like one that has wrapped a thousand times.
+//!
 //! ## Inspecting a ring buffer via Humility
 //!
 //! Humility has built-in support for dumping a ring buffer, and will (by
@@ -91,6 +110,16 @@
 //! variable is `RINGBUF` prefixed with the stem of the file that declared
 //! it.)
 //!
+//! ## Inspecting a ring buffer remotely
+//!
+//! Each ring buffer declared with [`ringbuf!`] is also described in the
+//! task's `.ringbuf_registry` section, which (like `.task_slot_table`) is
+//! never loaded, but read by the build system. The build gathers these into
+//! a registry in jefe, which lets authorized tasks enumerate ring buffers
+//! and read their entries over IPC, so traces can be pulled from systems
+//! without a debugger attached. This relies on [`Ringbuf`] and
+//! [`RingbufEntry`] having a fixed (`repr(C)`) layout.
+//!
 //! ## Inspecting a ring buffer via GDB
 //!
 //! Assuming symbols are loaded, one can use GDB's `print` command,
@@ -142,6 +171,8 @@
 /// macros is guaranteed to be able to find them.
 pub use static_cell::StaticCell;
 
+use userlib::sys_get_timer;
+
 /// Declares a ringbuffer in the current module or context.
 ///
 /// `ringbuf!(NAME, Type, N, expr)` makes a ringbuffer named `NAME`,
@@ -159,6 +190,18 @@ pub use static_cell::StaticCell;
 #[cfg(not(feature = "disabled"))]
 #[macro_export]
 macro_rules! ringbuf {
+    (@registry $name:ident, $flags:expr) => {
+        #[cfg(target_os = "none")]
+        const _: () = {
+            const NAME: &str = concat!(module_path!(), "::", stringify!($name));
+            const NAME_LEN: usize = NAME.len();
+
+            #[used]
+            #[link_section = ".ringbuf_registry"]
+            static ENTRY: $crate::RegistryEntry<NAME_LEN> =
+                $crate::RegistryEntry::new(NAME, &$name, $flags);
+        };
+    };
     ($name:ident, $t:ty, $n:expr, $init:expr) => {
         #[used]
         static $name: $crate::StaticCell<$crate::Ringbuf<$t, $n>> =
@@ -170,13 +213,51 @@ macro_rules! ringbuf {
                     count: 0,
                     payload: $init,
                 }; $n],
+                dropped: 0,
             });
+
+        $crate::ringbuf!(@registry $name, 0);
     };
     ($t:ty, $n:expr, $init:expr) => {
         $crate::ringbuf!(__RINGBUF, $t, $n, $init);
     };
 }
 
+/// Declares a ringbuffer whose entries are timestamped.
+///
+/// This takes the same arguments as [`ringbuf!`], but each entry's payload is
+/// wrapped in a [`Timestamped`], which records the kernel's timer when the
+/// entry was made. Entries are added with [`ringbuf_entry!`] as usual.
+///
+/// The actual type of `name` will be `StaticCell<Ringbuf<Timestamped<T>, N>>`.
+#[cfg(not(feature = "disabled"))]
+#[macro_export]
+macro_rules! timestamped_ringbuf {
+    ($name:ident, $t:ty, $n:expr, $init:expr) => {
+        #[used]
+        static $name: $crate::StaticCell<
+            $crate::Ringbuf<$crate::Timestamped<$t>, $n>,
+        > = $crate::StaticCell::new($crate::Ringbuf {
+            last: None,
+            buffer: [$crate::RingbufEntry {
+                line: 0,
+                generation: 0,
+                count: 0,
+                payload: $crate::Timestamped {
+                    timestamp: 0,
+                    payload: $init,
+                },
+            }; $n],
+            dropped: 0,
+        });
+
+        $crate::ringbuf!(@registry $name, $crate::REGISTRY_TIMESTAMPED);
+    };
+    ($t:ty, $n:expr, $init:expr) => {
+        $crate::timestamped_ringbuf!(__RINGBUF, $t, $n, $init);
+    };
+}
+
 #[cfg(feature = "disabled")]
 #[macro_export]
 macro_rules! ringbuf {
@@ -190,6 +271,19 @@ macro_rules! ringbuf {
     };
 }
 
+#[cfg(feature = "disabled")]
+#[macro_export]
+macro_rules! timestamped_ringbuf {
+    ($name:ident, $t:ty, $n:expr, $init:expr) => {
+        #[allow(dead_code)]
+        const _: $t = $init;
+    };
+    ($t:ty, $n:expr, $init:expr) => {
+        #[allow(dead_code)]
+        const _: $t = $init;
+    };
+}
+
 /// Inserts data into a named ringbuffer (which should have been declared with
 /// the `ringbuf!` macro).
 ///
@@ -208,8 +302,8 @@ macro_rules! ringbuf_entry {
         let (p, buf) = ($payload, &$buf);
         // Invoke these functions using slightly weird syntax to avoid
         // accidentally calling a _different_ routine called borrow_mut or
-        // entry.
-        $crate::Ringbuf::entry(
+        // record_entry.
+        $crate::RecordEntry::record_entry(
             &mut *$crate::StaticCell::borrow_mut(buf),
             line!() as u16,
             p,
@@ -257,6 +351,7 @@ macro_rules! ringbuf_entry_root {
 /// be incremented rather than generating a new entry.
 ///
 #[derive(Debug, Copy, Clone)]
+#[repr(C)]
 pub struct RingbufEntry<T: Copy + PartialEq> {
     pub line: u16,
     pub generation: u16,
@@ -264,24 +359,51 @@ pub struct RingbufEntry<T: Copy + PartialEq> {
     pub payload: T,
 }
 
+///
+/// The payload of an entry in a ring buffer declared with
+/// [`timestamped_ringbuf!`]: the entry's payload, and the kernel's timer when
+/// it was made.
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct Timestamped<T: Copy + PartialEq> {
+    pub timestamp: u64,
+    pub payload: T,
+}
+
 ///
 /// A ring buffer of parametrized type and size.  In practice, instantiating
 /// this directly is strange -- see the [`ringbuf!`] macro.
 ///
 #[derive(Debug)]
+#[repr(C)]
 pub struct Ringbuf<T: Copy + PartialEq, const N: usize> {
     pub last: Option<usize>,
     pub buffer: [RingbufEntry<T>; N],
+    /// Number of entries overwritten when the buffer wrapped around
+    /// (saturating).
+    pub dropped: u32,
 }
 
 impl<T: Copy + PartialEq, const N: usize> Ringbuf<T, { N }> {
     pub fn entry(&mut self, line: u16, payload: T) {
+        self.entry_with(line, payload, |a, b| a == b);
+    }
+
+    /// Adds an entry, unless `same` says that it repeats the last one (from
+    /// the same line), in which case that entry's count goes up instead.
+    fn entry_with(
+        &mut self,
+        line: u16,
+        payload: T,
+        same: impl FnOnce(&T, &T) -> bool,
+    ) {
         let ndx = match self.last {
             None => 0,
             Some(last) => {
                 let ent = &mut self.buffer[last];
 
-                if ent.line == line && ent.payload == payload {
+                if ent.line == line && same(&ent.payload, &payload) {
                     // Only reuse this entry if we don't overflow the
                     // count.
                     if let Some(new_count) = ent.count.checked_add(1) {
@@ -299,6 +421,9 @@ impl<T: Copy + PartialEq, const N: usize> Ringbuf<T, { N }> {
         };
 
         let ent = &mut self.buffer[ndx];
+        if ent.count != 0 {
+            self.dropped = self.dropped.saturating_add(1);
+        }
         ent.line = line;
         ent.payload = payload;
         ent.count = 1;
@@ -307,3 +432,112 @@ impl<T: Copy + PartialEq, const N: usize> Ringbuf<T, { N }> {
         self.last = Some(ndx);
     }
 }
+
+/// Adds entries to a ring buffer; this is how [`ringbuf_entry!`] works with
+/// both plain and timestamped ring buffers.
+pub trait RecordEntry<T> {
+    fn record_entry(&mut self, line: u16, payload: T);
+}
+
+impl<T: Copy + PartialEq, const N: usize> RecordEntry<T> for Ringbuf<T, N> {
+    fn record_entry(&mut self, line: u16, payload: T) {
+        self.entry(line, payload);
+    }
+}
+
+impl<T: Copy + PartialEq, const N: usize> RecordEntry<T>
+    for Ringbuf<Timestamped<T>, N>
+{
+    fn record_entry(&mut self, line: u16, payload: T) {
+        let timestamp = sys_get_timer().now;
+        // Repeats are compared without their timestamps (which would always
+        // differ), so a run of them keeps the time it began.
+        self.entry_with(line, Timestamped { timestamp, payload }, |a, b| {
+            a.payload == b.payload
+        });
+    }
+}
+
+/// Flag in a [`RegistryEntry`]: the payload is a [`Timestamped`]. This must
+/// match `task_jefe_api::ringbuf_flags::TIMESTAMPED`.
+pub const REGISTRY_TIMESTAMPED: u32 = 1 << 0;
+
+///
+/// Description of a ring buffer in the `.ringbuf_registry` ELF section, which
+/// the build system reads to build jefe's registry of ring buffers. Like
+/// `.task_slot_table`, the section isn't loaded, so these never exist at
+/// runtime; they're only here for their layout, which is part of the task's
+/// ABI with the build system.
+///
+/// Offsets are from `address`, the start of the ring buffer's `StaticCell`.
+///
+#[repr(C, packed)]
+pub struct RegistryEntry<const L: usize> {
+    address: *const u8,
+    size: u32,
+    buffer_offset: u32,
+    entries: u32,
+    entry_size: u32,
+    payload_offset: u32,
+    payload_size: u32,
+    dropped_offset: u32,
+    flags: u32,
+    name_len: u32,
+    name: [u8; L],
+}
+
+impl<const L: usize> RegistryEntry<L> {
+    pub const fn new<T: Copy + PartialEq, const N: usize>(
+        name: &'static str,
+        ringbuf: &'static StaticCell<Ringbuf<T, N>>,
+        flags: u32,
+    ) -> Self {
+        use core::mem::{align_of, size_of};
+
+        const fn round_up(n: usize, align: usize) -> usize {
+            (n + align - 1) / align * align
+        }
+
+        let bytes = name.as_bytes();
+        let mut buf = [0u8; L];
+        let mut i = 0;
+        while i < L {
+            buf[i] = bytes[i];
+            i += 1;
+        }
+
+        // These follow from `Ringbuf` and `RingbufEntry` being `repr(C)`.
+        let buffer_offset = StaticCell::<Ringbuf<T, N>>::CONTENTS_OFFSET
+            + round_up(
+                size_of::<Option<usize>>(),
+                align_of::<RingbufEntry<T>>(),
+            );
+        let dropped_offset = round_up(
+            buffer_offset + N * size_of::<RingbufEntry<T>>(),
+            align_of::<u32>(),
+        );
+        let payload_offset =
+            round_up(2 * size_of::<u16>() + size_of::<u32>(), align_of::<T>());
+
+        Self {
+            address: ringbuf as *const _ as *const u8,
+            size: size_of::<StaticCell<Ringbuf<T, N>>>() as u32,
+            buffer_offset: buffer_offset as u32,
+            entries: N as u32,
+            entry_size: size_of::<RingbufEntry<T>>() as u32,
+            payload_offset: payload_offset as u32,
+            payload_size: size_of::<T>() as u32,
+            dropped_offset: dropped_offset as u32,
+            flags,
+            name_len: L as u32,
+            name: buf,
+        }
+    }
+}
+
+// SAFETY
+//
+// As with `TaskSlotTableEntry`, the pointer keeps this from being Sync, but
+// it can only point at a static ring buffer, and these only ever exist in a
+// section that isn't loaded.
+unsafe impl<const L: usize> Sync for RegistryEntry<L> {}
@@ -0,0 +1,796 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! IPv6 stateless address autoconfiguration (RFC 4862), without a network
+//! stack of its own.
+//!
+//! The client is driven by its owner, like `dhcp-client`: `receive` hands it
+//! an ICMPv6 packet, `poll` moves it along with the clock, and `transmit`
+//! asks it for a packet to send. Packets go in and out as whole IPv6
+//! datagrams, since duplicate address detection has to send from the
+//! unspecified address.
+//!
+//! The client solicits router advertisements when it starts, and forms an
+//! address from each autonomous /64 prefix it's told about (up to
+//! `MAX_PREFIXES` of them), using the same EUI-64 interface identifier as
+//! the link-local address. Each address is checked for duplicates before
+//! it's used, and kept for as long as the prefix's lifetimes say, following
+//! the rules of RFC 4862 section 5.5.3. The router that last advertised
+//! itself as a default router is kept too, for its lifetime.
+//!
+//! The link-local address is assumed to be unique, being made from the MAC
+//! address, and isn't checked.
+//!
+//! Times are in milliseconds, from whatever clock the owner likes.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Most prefixes we make addresses for. Any more that are advertised are
+/// ignored, until one of those we have runs out.
+pub const MAX_PREFIXES: usize = 2;
+
+/// Largest packet we send.
+pub const MAX_PACKET: usize = 64;
+
+const IPV6_HEADER: usize = 40;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// Everything we send or receive must have a hop limit of 255, which shows
+/// that it came from (or is staying on) the link (RFC 4861 section 6.1).
+const HOP_LIMIT: u8 = 255;
+
+mod icmp {
+    pub const ROUTER_SOLICITATION: u8 = 133;
+    pub const ROUTER_ADVERTISEMENT: u8 = 134;
+    pub const NEIGHBOR_SOLICITATION: u8 = 135;
+    pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+}
+
+mod opt {
+    pub const SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+    pub const PREFIX_INFORMATION: u8 = 3;
+
+    /// Prefix information flag: the prefix can be used for SLAAC.
+    pub const AUTONOMOUS: u8 = 0x40;
+}
+
+const UNSPECIFIED: [u8; 16] = [0; 16];
+const ALL_ROUTERS: [u8; 16] =
+    [0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+
+/// Router solicitations we send when starting, and how far apart (RFC 4861
+/// section 10).
+const RTR_SOLICITATIONS: u8 = 3;
+const RTR_SOLICITATION_INTERVAL_MS: u64 = 4_000;
+
+/// Neighbor solicitations we send for an address before using it, and how
+/// long we wait for an answer after each (RFC 4862 section 5.1, and RFC 4861
+/// section 10).
+const DAD_TRANSMITS: u8 = 1;
+const RETRANS_TIMER_MS: u64 = 1_000;
+
+/// A prefix advertised with a short valid lifetime can only cut an address's
+/// remaining lifetime down to this (RFC 4862 section 5.5.3 (e)).
+const TWO_HOURS_MS: u64 = 2 * 60 * 60 * 1000;
+
+/// Lifetime that means forever.
+const INFINITE: u32 = u32::MAX;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AddressState {
+    /// Being checked for duplicates.
+    Tentative,
+    /// In use.
+    Preferred,
+    /// Still in use, but past its preferred lifetime.
+    Deprecated,
+    /// Someone else has it, so we don't.
+    Duplicate,
+}
+
+/// An address we've made from an advertised prefix.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Address {
+    pub address: [u8; 16],
+    pub state: AddressState,
+    /// When it stops being preferred, or `u64::MAX` for never.
+    pub preferred_until: u64,
+    /// When it stops being valid, and goes away, or `u64::MAX` for never.
+    pub valid_until: u64,
+}
+
+impl Address {
+    /// Whether the address should be on the interface.
+    pub fn is_usable(&self) -> bool {
+        matches!(
+            self.state,
+            AddressState::Preferred | AddressState::Deprecated
+        )
+    }
+}
+
+/// A default router.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Router {
+    /// Its link-local address.
+    pub address: [u8; 16],
+    /// When it stops being our default router.
+    pub until: u64,
+}
+
+/// An address, and where we are in checking it.
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    address: Address,
+    /// Neighbor solicitations sent so far, and when the next is due (or, once
+    /// they've all gone, when we stop waiting for an answer).
+    probes: u8,
+    probe_at: u64,
+}
+
+pub struct Client {
+    mac: [u8; 6],
+    link_local: [u8; 16],
+    entries: [Option<Entry>; MAX_PREFIXES],
+    router: Option<Router>,
+    /// Router solicitations sent so far, and when the next is due; we stop
+    /// once we hear an advertisement.
+    solicitations: u8,
+    solicit_at: u64,
+    heard_advertisement: bool,
+}
+
+impl Client {
+    /// Makes a client for the interface with address `mac`, which will start
+    /// soliciting routers shortly after `now`.
+    pub fn new(mac: [u8; 6], now: u64) -> Self {
+        let mut link_local = [0; 16];
+        link_local[0] = 0xfe;
+        link_local[1] = 0x80;
+        link_local[8..].copy_from_slice(&interface_id(mac));
+        Self {
+            mac,
+            link_local,
+            entries: [None; MAX_PREFIXES],
+            router: None,
+            solicitations: 0,
+            // Wait up to a second, so that boards starting together don't
+            // all solicit at once (RFC 4861 section 6.3.7). The MAC will do
+            // for randomness.
+            solicit_at: now + u64::from(mac[5]) * 1000 / 256,
+            heard_advertisement: false,
+        }
+    }
+
+    pub fn link_local(&self) -> [u8; 16] {
+        self.link_local
+    }
+
+    /// Iterates over the addresses we've made, in any state.
+    pub fn addresses(&self) -> impl Iterator<Item = &Address> + '_ {
+        self.entries.iter().flatten().map(|e| &e.address)
+    }
+
+    pub fn router(&self) -> Option<&Router> {
+        self.router.as_ref()
+    }
+
+    /// Returns the next time `poll` or `transmit` has something to do.
+    pub fn poll_at(&self) -> u64 {
+        let mut at = match &self.router {
+            Some(r) => r.until,
+            None => u64::MAX,
+        };
+        if self.is_soliciting() {
+            at = at.min(self.solicit_at);
+        }
+        for e in self.entries.iter().flatten() {
+            at = at.min(e.address.valid_until);
+            match e.address.state {
+                AddressState::Tentative => at = at.min(e.probe_at),
+                AddressState::Preferred => {
+                    at = at.min(e.address.preferred_until)
+                }
+                AddressState::Deprecated | AddressState::Duplicate => (),
+            }
+        }
+        at
+    }
+
+    /// Moves the client along to `now`. Returns true if the addresses that
+    /// should be on the interface, or the default router, have changed.
+    pub fn poll(&mut self, now: u64) -> bool {
+        let mut changed = false;
+
+        if matches!(self.router, Some(r) if now >= r.until) {
+            self.router = None;
+            changed = true;
+        }
+
+        for slot in &mut self.entries {
+            let Some(e) = slot else { continue };
+            if now >= e.address.valid_until {
+                changed |= e.address.is_usable();
+                *slot = None;
+                continue;
+            }
+            match e.address.state {
+                AddressState::Tentative
+                    if e.probes >= DAD_TRANSMITS && now >= e.probe_at =>
+                {
+                    e.address.state = if now >= e.address.preferred_until {
+                        AddressState::Deprecated
+                    } else {
+                        AddressState::Preferred
+                    };
+                    changed = true;
+                }
+                AddressState::Preferred if now >= e.address.preferred_until => {
+                    e.address.state = AddressState::Deprecated;
+                }
+                _ => (),
+            }
+        }
+        changed
+    }
+
+    /// Takes an IPv6 packet that may be for us. Returns true if the
+    /// addresses that should be on the interface, or the default router,
+    /// have changed.
+    pub fn receive(&mut self, now: u64, packet: &[u8]) -> bool {
+        let Some(msg) = Message::parse(packet) else {
+            return false;
+        };
+        match msg.kind {
+            icmp::ROUTER_ADVERTISEMENT => self.advertisement(now, &msg),
+            icmp::NEIGHBOR_ADVERTISEMENT => {
+                self.conflict(&msg);
+                false
+            }
+            // Someone else checking the same address (RFC 4862 section
+            // 5.4.3).
+            icmp::NEIGHBOR_SOLICITATION if msg.src == UNSPECIFIED => {
+                self.conflict(&msg);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes a packet to send into `buf`, returning its length, if it's
+    /// time to send one.
+    pub fn transmit(
+        &mut self,
+        now: u64,
+        buf: &mut [u8; MAX_PACKET],
+    ) -> Option<usize> {
+        for e in self.entries.iter_mut().flatten() {
+            if e.address.state == AddressState::Tentative
+                && e.probes < DAD_TRANSMITS
+                && now >= e.probe_at
+            {
+                e.probes += 1;
+                e.probe_at = now + RETRANS_TIMER_MS;
+                return Some(neighbor_solicitation(buf, e.address.address));
+            }
+        }
+
+        if self.is_soliciting() && now >= self.solicit_at {
+            self.solicitations += 1;
+            self.solicit_at = now + RTR_SOLICITATION_INTERVAL_MS;
+            return Some(router_solicitation(buf, self.link_local, self.mac));
+        }
+        None
+    }
+
+    fn is_soliciting(&self) -> bool {
+        !self.heard_advertisement && self.solicitations < RTR_SOLICITATIONS
+    }
+
+    fn advertisement(&mut self, now: u64, msg: &Message<'_>) -> bool {
+        // Only routers on this link count.
+        if msg.src[..2] != [0xfe, 0x80] || msg.body.len() < 12 {
+            return false;
+        }
+        self.heard_advertisement = true;
+        let mut changed = false;
+
+        let lifetime = u16::from_be_bytes([msg.body[2], msg.body[3]]);
+        if lifetime == 0 {
+            if matches!(self.router, Some(r) if r.address == msg.src) {
+                self.router = None;
+                changed = true;
+            }
+        } else {
+            changed |= self.router.map(|r| r.address) != Some(msg.src);
+            self.router = Some(Router {
+                address: msg.src,
+                until: now + u64::from(lifetime) * 1000,
+            });
+        }
+
+        let mut opts = &msg.body[12..];
+        while opts.len() >= 8 {
+            let len = usize::from(opts[1]) * 8;
+            if len == 0 || len > opts.len() {
+                break;
+            }
+            if opts[0] == opt::PREFIX_INFORMATION && len == 32 {
+                self.prefix(now, &opts[..len]);
+            }
+            opts = &opts[len..];
+        }
+        changed
+    }
+
+    /// Deals with a prefix information option.
+    fn prefix(&mut self, now: u64, opt: &[u8]) {
+        let prefix_len = opt[2];
+        let flags = opt[3];
+        let valid = u32::from_be_bytes([opt[4], opt[5], opt[6], opt[7]]);
+        let preferred = u32::from_be_bytes([opt[8], opt[9], opt[10], opt[11]]);
+        let prefix = &opt[16..24];
+
+        // Our interface identifier is 64 bits, so the prefix has to be too.
+        if flags & opt::AUTONOMOUS == 0
+            || prefix_len != 64
+            || prefix[..2] == [0xfe, 0x80]
+            || preferred > valid
+        {
+            return;
+        }
+
+        let existing = self
+            .entries
+            .iter_mut()
+            .flatten()
+            .find(|e| e.address.address[..8] == *prefix);
+        if let Some(e) = existing {
+            let a = &mut e.address;
+            let remaining = a.valid_until.saturating_sub(now);
+            let valid_ms = lifetime_ms(valid);
+            if valid_ms > TWO_HOURS_MS || valid_ms > remaining {
+                a.valid_until = deadline(now, valid);
+            } else if remaining > TWO_HOURS_MS {
+                a.valid_until = now + TWO_HOURS_MS;
+            }
+            a.preferred_until = deadline(now, preferred);
+            if a.state == AddressState::Deprecated && preferred > 0 {
+                a.state = AddressState::Preferred;
+            }
+            return;
+        }
+
+        if valid == 0 {
+            return;
+        }
+        let Some(slot) = self.entries.iter_mut().find(|e| e.is_none()) else {
+            return;
+        };
+        let mut address = self.link_local;
+        address[..8].copy_from_slice(prefix);
+        *slot = Some(Entry {
+            address: Address {
+                address,
+                state: AddressState::Tentative,
+                preferred_until: deadline(now, preferred),
+                valid_until: deadline(now, valid),
+            },
+            probes: 0,
+            probe_at: now,
+        });
+    }
+
+    /// Marks a tentative address as a duplicate, if a neighbor
+    /// advertisement or solicitation says someone else has it.
+    fn conflict(&mut self, msg: &Message<'_>) {
+        let Some(target) = msg.target() else { return };
+        for e in self.entries.iter_mut().flatten() {
+            if e.address.address == target
+                && e.address.state == AddressState::Tentative
+            {
+                e.address.state = AddressState::Duplicate;
+            }
+        }
+    }
+}
+
+/// Turns a MAC address into a modified EUI-64 interface identifier (RFC 4291
+/// appendix A).
+fn interface_id(mac: [u8; 6]) -> [u8; 8] {
+    [
+        mac[0] ^ 0b10,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]
+}
+
+fn lifetime_ms(secs: u32) -> u64 {
+    if secs == INFINITE {
+        u64::MAX
+    } else {
+        u64::from(secs) * 1000
+    }
+}
+
+fn deadline(now: u64, secs: u32) -> u64 {
+    now.saturating_add(lifetime_ms(secs))
+}
+
+/// An ICMPv6 message that's passed the checks every neighbor discovery
+/// message has to.
+struct Message<'a> {
+    src: [u8; 16],
+    kind: u8,
+    /// Everything after the type, code and checksum.
+    body: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        let header = packet.get(..IPV6_HEADER)?;
+        let payload_len =
+            usize::from(u16::from_be_bytes([header[4], header[5]]));
+        if header[0] >> 4 != 6
+            || header[6] != IPPROTO_ICMPV6
+            || header[7] != HOP_LIMIT
+        {
+            return None;
+        }
+        let icmp = packet.get(IPV6_HEADER..IPV6_HEADER + payload_len)?;
+        if icmp.len() < 4 || icmp[1] != 0 {
+            return None;
+        }
+        let mut pseudo = sum(0, &header[8..40]);
+        pseudo = sum(pseudo, &(payload_len as u32).to_be_bytes());
+        pseudo = sum(pseudo, &[0, IPPROTO_ICMPV6]);
+        if fold(sum(pseudo, icmp)) != 0 {
+            return None;
+        }
+        Some(Self {
+            src: header[8..24].try_into().ok()?,
+            kind: icmp[0],
+            body: &icmp[4..],
+        })
+    }
+
+    /// Gets the target address of a neighbor solicitation or advertisement.
+    fn target(&self) -> Option<[u8; 16]> {
+        self.body.get(4..20)?.try_into().ok()
+    }
+}
+
+fn router_solicitation(
+    buf: &mut [u8; MAX_PACKET],
+    src: [u8; 16],
+    mac: [u8; 6],
+) -> usize {
+    let mut body = [0; 12];
+    body[4] = opt::SOURCE_LINK_LAYER_ADDRESS;
+    body[5] = 1;
+    body[6..].copy_from_slice(&mac);
+    encode(buf, src, ALL_ROUTERS, icmp::ROUTER_SOLICITATION, &body)
+}
+
+/// Writes a neighbor solicitation for `target`, for duplicate address
+/// detection: from the unspecified address, to the target's solicited-node
+/// multicast group.
+fn neighbor_solicitation(
+    buf: &mut [u8; MAX_PACKET],
+    target: [u8; 16],
+) -> usize {
+    let mut dst = [0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0, 0];
+    dst[13..].copy_from_slice(&target[13..]);
+    let mut body = [0; 20];
+    body[4..].copy_from_slice(&target);
+    encode(buf, UNSPECIFIED, dst, icmp::NEIGHBOR_SOLICITATION, &body)
+}
+
+fn encode(
+    buf: &mut [u8; MAX_PACKET],
+    src: [u8; 16],
+    dst: [u8; 16],
+    kind: u8,
+    body: &[u8],
+) -> usize {
+    let payload_len = 4 + body.len();
+    buf[..IPV6_HEADER].fill(0);
+    buf[0] = 0x60;
+    buf[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+    buf[6] = IPPROTO_ICMPV6;
+    buf[7] = HOP_LIMIT;
+    buf[8..24].copy_from_slice(&src);
+    buf[24..40].copy_from_slice(&dst);
+
+    let icmp = &mut buf[IPV6_HEADER..IPV6_HEADER + payload_len];
+    icmp[0] = kind;
+    icmp[1] = 0;
+    icmp[2..4].fill(0);
+    icmp[4..].copy_from_slice(body);
+
+    let mut pseudo = sum(0, &src);
+    pseudo = sum(pseudo, &dst);
+    pseudo = sum(pseudo, &(payload_len as u32).to_be_bytes());
+    pseudo = sum(pseudo, &[0, IPPROTO_ICMPV6]);
+    let checksum = fold(sum(pseudo, icmp));
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    IPV6_HEADER + payload_len
+}
+
+/// Adds `data` to a running Internet checksum.
+fn sum(mut sum: u32, data: &[u8]) -> u32 {
+    for pair in data.chunks(2) {
+        let hi = pair[0];
+        let lo = pair.get(1).copied().unwrap_or(0);
+        sum += u32::from(u16::from_be_bytes([hi, lo]));
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x0e, 0x1d, 1, 2, 3, 0];
+    const ROUTER: [u8; 16] =
+        [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 1];
+    const OTHER_PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 2];
+
+    fn packet(src: [u8; 16], dst: [u8; 16], kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; IPV6_HEADER + 4 + body.len()];
+        let payload_len = 4 + body.len();
+        buf[0] = 0x60;
+        buf[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+        buf[6] = IPPROTO_ICMPV6;
+        buf[7] = HOP_LIMIT;
+        buf[8..24].copy_from_slice(&src);
+        buf[24..40].copy_from_slice(&dst);
+        buf[40] = kind;
+        buf[44..].copy_from_slice(body);
+        let mut pseudo = sum(0, &buf[8..40]);
+        pseudo = sum(pseudo, &(payload_len as u32).to_be_bytes());
+        pseudo = sum(pseudo, &[0, IPPROTO_ICMPV6]);
+        let checksum = fold(sum(pseudo, &buf[40..]));
+        buf[42..44].copy_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    /// An advertisement from `ROUTER` with the given router lifetime and
+    /// prefixes of (prefix, valid, preferred).
+    fn advertisement(
+        lifetime: u16,
+        prefixes: &[([u8; 8], u32, u32)],
+    ) -> Vec<u8> {
+        let mut body = vec![64, 0];
+        body.extend(lifetime.to_be_bytes());
+        body.extend([0; 8]);
+        for (prefix, valid, preferred) in prefixes {
+            body.extend([opt::PREFIX_INFORMATION, 4, 64, 0xc0]);
+            body.extend(valid.to_be_bytes());
+            body.extend(preferred.to_be_bytes());
+            body.extend([0; 4]);
+            body.extend(prefix);
+            body.extend([0; 8]);
+        }
+        let all_nodes = [0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        packet(ROUTER, all_nodes, icmp::ROUTER_ADVERTISEMENT, &body)
+    }
+
+    fn address(prefix: [u8; 8]) -> [u8; 16] {
+        let mut a = [0; 16];
+        a[..8].copy_from_slice(&prefix);
+        a[8..].copy_from_slice(&[0x0c, 0x1d, 1, 0xff, 0xfe, 2, 3, 0]);
+        a
+    }
+
+    fn send(c: &mut Client, now: u64) -> Option<Vec<u8>> {
+        let mut buf = [0; MAX_PACKET];
+        let len = c.transmit(now, &mut buf)?;
+        // Whatever we send, we'd accept.
+        assert!(Message::parse(&buf[..len]).is_some());
+        Some(buf[..len].to_vec())
+    }
+
+    fn state(c: &Client, prefix: [u8; 8]) -> Option<AddressState> {
+        c.addresses()
+            .find(|a| a.address == address(prefix))
+            .map(|a| a.state)
+    }
+
+    /// Takes a new client through to having an address for `PREFIX`, at
+    /// time 0.
+    fn configured(valid: u32, preferred: u32) -> Client {
+        let mut c = Client::new(MAC, 0);
+        assert!(
+            c.receive(0, &advertisement(1800, &[(PREFIX, valid, preferred)]))
+        );
+        send(&mut c, 0).unwrap();
+        assert!(c.poll(RETRANS_TIMER_MS));
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Preferred));
+        c
+    }
+
+    #[test]
+    fn solicits() {
+        let mut c = Client::new(MAC, 0);
+        assert_eq!(c.link_local()[8..], address(PREFIX)[8..]);
+        let rs = send(&mut c, 0).unwrap();
+        assert_eq!(rs[40], icmp::ROUTER_SOLICITATION);
+        assert_eq!(&rs[8..24], &c.link_local());
+        assert_eq!(&rs[24..40], &ALL_ROUTERS);
+        assert_eq!(&rs[44..], &[0, 0, 0, 0, 1, 1, 0x0e, 0x1d, 1, 2, 3, 0]);
+
+        assert!(send(&mut c, 3_999).is_none());
+        assert!(send(&mut c, 4_000).is_some());
+        assert!(send(&mut c, 8_000).is_some());
+        assert!(send(&mut c, 12_000).is_none());
+        assert_eq!(c.poll_at(), u64::MAX);
+    }
+
+    #[test]
+    fn stops_soliciting_when_told() {
+        let mut c = Client::new(MAC, 0);
+        send(&mut c, 0).unwrap();
+        assert!(c.receive(100, &advertisement(1800, &[])));
+        assert!(send(&mut c, 4_000).is_none());
+        assert_eq!(
+            c.router(),
+            Some(&Router {
+                address: ROUTER,
+                until: 100 + 1_800_000
+            })
+        );
+    }
+
+    #[test]
+    fn checks_for_duplicates() {
+        let mut c = Client::new(MAC, 0);
+        c.receive(0, &advertisement(0, &[(PREFIX, 3600, 1800)]));
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Tentative));
+
+        let ns = send(&mut c, 0).unwrap();
+        assert_eq!(ns[40], icmp::NEIGHBOR_SOLICITATION);
+        assert_eq!(&ns[8..24], &UNSPECIFIED);
+        assert_eq!(
+            &ns[24..40],
+            &[0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 2, 3, 0]
+        );
+        assert_eq!(&ns[48..64], &address(PREFIX));
+        assert_eq!(c.poll_at(), RETRANS_TIMER_MS);
+
+        assert!(!c.poll(RETRANS_TIMER_MS - 1));
+        assert!(c.poll(RETRANS_TIMER_MS));
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Preferred));
+    }
+
+    #[test]
+    fn finds_duplicates() {
+        let mut c = Client::new(MAC, 0);
+        c.receive(0, &advertisement(0, &[(PREFIX, 3600, 1800)]));
+        send(&mut c, 0).unwrap();
+
+        let mut body = vec![0x20, 0, 0, 0];
+        body.extend(address(PREFIX));
+        let na =
+            packet(ROUTER, c.link_local(), icmp::NEIGHBOR_ADVERTISEMENT, &body);
+        assert!(!c.receive(500, &na));
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Duplicate));
+        assert!(!c.poll(RETRANS_TIMER_MS));
+        assert!(c.addresses().all(|a| !a.is_usable()));
+
+        // Someone else checking the same address at the same time counts too.
+        c.receive(0, &advertisement(0, &[(OTHER_PREFIX, 3600, 1800)]));
+        let mut body = vec![0, 0, 0, 0];
+        body.extend(address(OTHER_PREFIX));
+        let ns =
+            packet(UNSPECIFIED, ROUTER, icmp::NEIGHBOR_SOLICITATION, &body);
+        c.receive(0, &ns);
+        assert_eq!(state(&c, OTHER_PREFIX), Some(AddressState::Duplicate));
+    }
+
+    #[test]
+    fn lifetimes() {
+        let mut c = configured(3600, 1800);
+        assert_eq!(c.poll_at(), 1_800_000);
+
+        // The router and the address's preference run out together; only
+        // the router going changes what's on the interface.
+        assert!(c.poll(1_800_000));
+        assert!(c.router().is_none());
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Deprecated));
+
+        // Advertising the prefix again brings it back.
+        c.receive(1_800_000, &advertisement(0, &[(PREFIX, 3600, 1800)]));
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Preferred));
+
+        assert!(!c.poll(5_399_999));
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Deprecated));
+        assert!(c.poll(5_400_000));
+        assert_eq!(state(&c, PREFIX), None);
+    }
+
+    #[test]
+    fn two_hour_rule() {
+        let mut c = configured(INFINITE, INFINITE);
+        assert_eq!(c.addresses().next().unwrap().valid_until, u64::MAX);
+
+        // A short lifetime can only cut a long one down to two hours...
+        c.receive(0, &advertisement(0, &[(PREFIX, 60, 60)]));
+        let a = *c.addresses().next().unwrap();
+        assert_eq!(a.valid_until, TWO_HOURS_MS);
+        assert_eq!(a.preferred_until, 60_000);
+
+        // ...and not at all once it's less than that.
+        c.receive(1000, &advertisement(0, &[(PREFIX, 60, 60)]));
+        assert_eq!(c.addresses().next().unwrap().valid_until, TWO_HOURS_MS);
+
+        // A longer one always goes.
+        c.receive(1000, &advertisement(0, &[(PREFIX, 3 * 3600, 60)]));
+        assert_eq!(
+            c.addresses().next().unwrap().valid_until,
+            1000 + 3 * 3600 * 1000
+        );
+    }
+
+    #[test]
+    fn router_goes_away() {
+        let mut c = configured(3600, 1800);
+        assert!(!c.receive(10, &advertisement(1800, &[])));
+        assert!(c.receive(10, &advertisement(0, &[])));
+        assert!(c.router().is_none());
+        assert_eq!(state(&c, PREFIX), Some(AddressState::Preferred));
+    }
+
+    #[test]
+    fn ignores_what_it_should() {
+        let mut c = Client::new(MAC, 0);
+
+        // Too many prefixes.
+        let third = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 3];
+        c.receive(
+            0,
+            &advertisement(
+                0,
+                &[(PREFIX, 60, 60), (OTHER_PREFIX, 60, 60), (third, 60, 60)],
+            ),
+        );
+        assert_eq!(c.addresses().count(), MAX_PREFIXES);
+        assert_eq!(state(&c, third), None);
+
+        let mut c = Client::new(MAC, 0);
+        // Link-local, preferred for longer than it's valid, and not
+        // autonomous.
+        let link_local = [0xfe, 0x80, 0, 0, 0, 0, 0, 0];
+        c.receive(0, &advertisement(0, &[(link_local, 60, 60)]));
+        c.receive(0, &advertisement(0, &[(PREFIX, 60, 120)]));
+        let mut ra = advertisement(0, &[(PREFIX, 60, 60)]);
+        ra[IPV6_HEADER + 4 + 12 + 3] = 0x80;
+        assert!(!c.receive(0, &ra));
+        assert_eq!(c.addresses().count(), 0);
+
+        // Off the link, or mangled.
+        let mut ra = advertisement(1800, &[(PREFIX, 60, 60)]);
+        ra[7] = 64;
+        assert!(!c.receive(0, &ra));
+        let mut ra = advertisement(1800, &[(PREFIX, 60, 60)]);
+        ra[60] ^= 1;
+        assert!(!c.receive(0, &ra));
+        assert!(c.router().is_none());
+        assert_eq!(c.addresses().count(), 0);
+    }
+}
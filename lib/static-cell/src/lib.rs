@@ -16,13 +16,22 @@ use armv6m_atomic_hack::AtomicBoolExt;
 /// This only provides `mut` access because that's what we've needed so far. It
 /// does _not_ provide the many-reader one-writer behavior of `RefCell`, only
 /// the one-writer part.
+///
+/// The layout is fixed (`repr(C)`), so that something reading a task's
+/// memory from outside -- as jefe does for ringbufs -- can find the contents:
+/// they're at [`StaticCell::CONTENTS_OFFSET`].
 #[derive(Default)]
+#[repr(C)]
 pub struct StaticCell<T> {
     borrowed: AtomicBool,
     cell: UnsafeCell<T>,
 }
 
 impl<T> StaticCell<T> {
+    /// Offset of the contents from the start of the `StaticCell`: the
+    /// one-byte flag, rounded up to the contents' alignment.
+    pub const CONTENTS_OFFSET: usize = core::mem::align_of::<T>();
+
     /// Creates a `StaticCell` containing `contents`.
     pub const fn new(contents: T) -> Self {
         Self {
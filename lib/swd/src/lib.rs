@@ -0,0 +1,816 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The host side of Serial Wire Debug: the ADIv5 wire protocol, and enough of
+//! the ARMv7-M/ARMv8-M debug architecture on top of it to stop, inspect, and
+//! restart a Cortex-M.
+//!
+//! How bits get onto the wire is left to a `Wire`, which is usually a pair of
+//! GPIOs. Everything above that -- packet headers, parity, acknowledgements,
+//! retrying on WAIT, clearing sticky errors after a FAULT, the posted reads of
+//! the MEM-AP -- lives here.
+//!
+//! All memory access is through AP 0, as 32-bit words.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Moves bits over SWCLK and SWDIO.
+///
+/// Bits go least significant first. SWD is fully static, so there's no limit
+/// on how long the clock can be held between calls, or within them.
+pub trait Wire {
+    /// Drives the low `n` bits of `bits` onto SWDIO, one per clock.
+    fn write_bits(&mut self, bits: u32, n: u8);
+
+    /// Samples `n` bits (at most 32) from SWDIO, one per clock. Only called
+    /// between `release` and `drive`.
+    fn read_bits(&mut self, n: u8) -> u32;
+
+    /// Stops driving SWDIO and gives the target a turnaround clock in which
+    /// to start.
+    fn release(&mut self);
+
+    /// Gives a turnaround clock and then drives SWDIO again.
+    fn drive(&mut self);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Nobody acknowledged the request; the target isn't there, isn't
+    /// powered, or has lost track of the protocol.
+    NoResponse,
+    /// The target kept answering WAIT, and we gave up on the transfer.
+    Wait,
+    /// The target answered FAULT. The sticky error has been cleared.
+    Fault,
+    /// Data from the target didn't match its parity bit.
+    Parity,
+    /// The core didn't get to the state we asked for.
+    Timeout,
+    /// Core registers can only be reached while the core is halted.
+    NotHalted,
+    /// Not a core register number.
+    BadRegister,
+    /// Memory is accessed in aligned words.
+    Unaligned,
+}
+
+/// Debug Port registers, by address.
+pub mod dp {
+    /// DPIDR on reads, ABORT on writes.
+    pub const DPIDR: u8 = 0x0;
+    pub const ABORT: u8 = 0x0;
+    pub const CTRL_STAT: u8 = 0x4;
+    pub const SELECT: u8 = 0x8;
+    pub const RDBUFF: u8 = 0xc;
+}
+
+/// Registers of a MEM-AP, by address.
+pub mod ap {
+    pub const CSW: u8 = 0x0;
+    pub const TAR: u8 = 0x4;
+    pub const DRW: u8 = 0xc;
+}
+
+/// Bits in DEMCR that stop the core on the way into an exception. A core
+/// stopped this way can be looked at, and have its registers fixed up, before
+/// it runs any of the handler.
+pub mod vector_catch {
+    /// Halt on the way out of reset, before the first instruction.
+    pub const CORE_RESET: u32 = 1 << 0;
+    pub const MEM_MANAGE: u32 = 1 << 4;
+    pub const NO_COPROCESSOR: u32 = 1 << 5;
+    pub const CHECK: u32 = 1 << 6;
+    pub const STATE: u32 = 1 << 7;
+    pub const BUS_FAULT: u32 = 1 << 8;
+    pub const INTERRUPT: u32 = 1 << 9;
+    pub const HARD_FAULT: u32 = 1 << 10;
+    /// SecureFault; ARMv8-M with the Security Extension only.
+    pub const SECURE_FAULT: u32 = 1 << 11;
+
+    pub const ALL: u32 = CORE_RESET
+        | MEM_MANAGE
+        | NO_COPROCESSOR
+        | CHECK
+        | STATE
+        | BUS_FAULT
+        | INTERRUPT
+        | HARD_FAULT
+        | SECURE_FAULT;
+}
+
+/// Bits of DHCSR, as returned by `Swd::dhcsr`.
+pub mod dhcsr {
+    pub const C_DEBUGEN: u32 = 1 << 0;
+    pub const C_HALT: u32 = 1 << 1;
+    pub const S_REGRDY: u32 = 1 << 16;
+    pub const S_HALT: u32 = 1 << 17;
+    pub const S_SLEEP: u32 = 1 << 18;
+    pub const S_LOCKUP: u32 = 1 << 19;
+    pub const S_RESET_ST: u32 = 1 << 25;
+
+    /// Has to be in the top half of every write, or the write is ignored.
+    pub(crate) const DBGKEY: u32 = 0xa05f << 16;
+}
+
+const ACK_OK: u32 = 0b001;
+const ACK_WAIT: u32 = 0b010;
+const ACK_FAULT: u32 = 0b100;
+
+/// Sent between two line resets to move a SWJ-DP from JTAG to SWD.
+const JTAG_TO_SWD: u32 = 0xe79e;
+
+const ABORT_DAPABORT: u32 = 1 << 0;
+/// Clears STICKCMP, STICKERR, WDATAERR, and STICKYORUN.
+const ABORT_CLEAR_STICKY: u32 = 0b1_1110;
+
+const CTRL_CDBGPWRUPREQ: u32 = 1 << 28;
+const CTRL_CDBGPWRUPACK: u32 = 1 << 29;
+const CTRL_CSYSPWRUPREQ: u32 = 1 << 30;
+const CTRL_CSYSPWRUPACK: u32 = 1 << 31;
+
+/// 32-bit accesses, incrementing TAR after each, as a privileged data access.
+const CSW_VALUE: u32 = 0x0b00_0052;
+
+/// TAR is only guaranteed to increment within a 1 KiB block, so it has to be
+/// rewritten at each boundary.
+const AUTOINC_BLOCK: u32 = 1024;
+
+const AIRCR: u32 = 0xe000_ed0c;
+const AIRCR_VECTKEY: u32 = 0x05fa << 16;
+const AIRCR_SYSRESETREQ: u32 = 1 << 2;
+
+const DHCSR: u32 = 0xe000_edf0;
+const DCRSR: u32 = 0xe000_edf4;
+const DCRSR_REGWNR: u32 = 1 << 16;
+const DCRSR_REGSEL_MAX: u16 = 0x7f;
+const DCRDR: u32 = 0xe000_edf8;
+const DEMCR: u32 = 0xe000_edfc;
+
+/// Times to repeat a transfer that's answered with WAIT.
+const WAIT_RETRIES: usize = 100;
+/// Times to look at a status register for the bit we're waiting for.
+const POLL_LIMIT: usize = 100;
+
+fn parity(v: u32) -> u32 {
+    v.count_ones() & 1
+}
+
+/// The 8-bit request that starts every transfer: start bit, APnDP, RnW, two
+/// address bits, parity over those four, stop bit, and park bit.
+fn request(ap: bool, read: bool, addr: u8) -> u32 {
+    let body =
+        u32::from(ap) | u32::from(read) << 1 | u32::from(addr >> 2 & 3) << 2;
+    1 | body << 1 | parity(body) << 5 | 1 << 7
+}
+
+pub struct Swd<W> {
+    wire: W,
+}
+
+impl<W: Wire> Swd<W> {
+    pub fn new(wire: W) -> Self {
+        Self { wire }
+    }
+
+    pub fn wire_mut(&mut self) -> &mut W {
+        &mut self.wire
+    }
+
+    /// Brings up the link: switches the target's debug port to SWD if it was
+    /// doing JTAG, clears any errors left over from whoever used it last,
+    /// powers up the debug domain, and sets up AP 0 for word accesses.
+    ///
+    /// Returns DPIDR, which says what sort of debug port this is.
+    pub fn connect(&mut self) -> Result<u32, Error> {
+        self.line_reset();
+        self.wire.write_bits(JTAG_TO_SWD, 16);
+        self.line_reset();
+        self.wire.write_bits(0, 8);
+
+        // After a line reset, the first thing has to be a DPIDR read.
+        let id = self.dp_read(dp::DPIDR)?;
+        self.dp_write(dp::ABORT, ABORT_CLEAR_STICKY)?;
+        self.dp_write(dp::SELECT, 0)?;
+        self.dp_write(dp::CTRL_STAT, CTRL_CDBGPWRUPREQ | CTRL_CSYSPWRUPREQ)?;
+
+        let acks = CTRL_CDBGPWRUPACK | CTRL_CSYSPWRUPACK;
+        let mut powered = false;
+        for _ in 0..POLL_LIMIT {
+            if self.dp_read(dp::CTRL_STAT)? & acks == acks {
+                powered = true;
+                break;
+            }
+        }
+        if !powered {
+            return Err(Error::Timeout);
+        }
+
+        self.ap_write(ap::CSW, CSW_VALUE)?;
+        Ok(id)
+    }
+
+    /// At least 50 clocks with SWDIO high, which puts the target back at the
+    /// start of the protocol whatever it was doing.
+    fn line_reset(&mut self) {
+        self.wire.write_bits(!0, 32);
+        self.wire.write_bits(!0, 24);
+    }
+
+    pub fn dp_read(&mut self, addr: u8) -> Result<u32, Error> {
+        self.transfer(false, addr, None)
+    }
+
+    pub fn dp_write(&mut self, addr: u8, value: u32) -> Result<(), Error> {
+        self.transfer(false, addr, Some(value)).map(|_| ())
+    }
+
+    /// Reads a register of the selected AP. AP reads are posted, returning
+    /// the result of the one before; this collects our own from RDBUFF.
+    pub fn ap_read(&mut self, addr: u8) -> Result<u32, Error> {
+        self.transfer(true, addr, None)?;
+        self.dp_read(dp::RDBUFF)
+    }
+
+    pub fn ap_write(&mut self, addr: u8, value: u32) -> Result<(), Error> {
+        self.transfer(true, addr, Some(value)).map(|_| ())
+    }
+
+    fn transfer(
+        &mut self,
+        ap: bool,
+        addr: u8,
+        write: Option<u32>,
+    ) -> Result<u32, Error> {
+        for _ in 0..WAIT_RETRIES {
+            match self.transfer_once(ap, addr, write) {
+                Err(Error::Wait) => continue,
+                Err(Error::Fault) => {
+                    // Until this is cleared, every AP access will fault.
+                    let _ = self.transfer_once(
+                        false,
+                        dp::ABORT,
+                        Some(ABORT_CLEAR_STICKY),
+                    );
+                    return Err(Error::Fault);
+                }
+                r => return r,
+            }
+        }
+        // Give up on whatever the AP is stuck on, so that the next transfer
+        // has a chance.
+        let _ = self.transfer_once(false, dp::ABORT, Some(ABORT_DAPABORT));
+        Err(Error::Wait)
+    }
+
+    fn transfer_once(
+        &mut self,
+        ap: bool,
+        addr: u8,
+        write: Option<u32>,
+    ) -> Result<u32, Error> {
+        self.wire.write_bits(request(ap, write.is_none(), addr), 8);
+        self.wire.release();
+        let ack = self.wire.read_bits(3);
+
+        let result = match (ack, write) {
+            (ACK_OK, None) => {
+                let data = self.wire.read_bits(32);
+                let p = self.wire.read_bits(1);
+                self.wire.drive();
+                if p == parity(data) {
+                    Ok(data)
+                } else {
+                    Err(Error::Parity)
+                }
+            }
+            (ACK_OK, Some(data)) => {
+                self.wire.drive();
+                self.wire.write_bits(data, 32);
+                self.wire.write_bits(parity(data), 1);
+                Ok(0)
+            }
+            (ACK_WAIT, _) => {
+                self.wire.drive();
+                Err(Error::Wait)
+            }
+            (ACK_FAULT, _) => {
+                self.wire.drive();
+                Err(Error::Fault)
+            }
+            _ => {
+                self.wire.drive();
+                Err(Error::NoResponse)
+            }
+        };
+
+        // Idle cycles, which some targets need to finish the transfer.
+        self.wire.write_bits(0, 8);
+        result
+    }
+
+    pub fn read_word(&mut self, addr: u32) -> Result<u32, Error> {
+        if addr & 3 != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.ap_write(ap::TAR, addr)?;
+        self.ap_read(ap::DRW)
+    }
+
+    pub fn write_word(&mut self, addr: u32, value: u32) -> Result<(), Error> {
+        if addr & 3 != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.ap_write(ap::TAR, addr)?;
+        self.ap_write(ap::DRW, value)?;
+        // Writes are posted too; this is where a failed one shows up.
+        self.dp_read(dp::RDBUFF).map(|_| ())
+    }
+
+    /// Reads consecutive words, starting at `addr`.
+    pub fn read_words(
+        &mut self,
+        addr: u32,
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        if addr & 3 != 0 {
+            return Err(Error::Unaligned);
+        }
+        let mut addr = addr;
+        let mut rest = out;
+        while !rest.is_empty() {
+            let n = usize::min(Self::block_words(addr), rest.len());
+            let (chunk, tail) = rest.split_at_mut(n);
+
+            self.ap_write(ap::TAR, addr)?;
+            // Each read returns the word before, so the first starts things
+            // off and RDBUFF holds the last.
+            self.transfer(true, ap::DRW, None)?;
+            let (last, others) = chunk.split_last_mut().unwrap();
+            for w in others {
+                *w = self.transfer(true, ap::DRW, None)?;
+            }
+            *last = self.dp_read(dp::RDBUFF)?;
+
+            addr = addr.wrapping_add(4 * n as u32);
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /// Writes consecutive words, starting at `addr`.
+    pub fn write_words(
+        &mut self,
+        addr: u32,
+        data: &[u32],
+    ) -> Result<(), Error> {
+        if addr & 3 != 0 {
+            return Err(Error::Unaligned);
+        }
+        let mut addr = addr;
+        for chunk in Self::blocks(addr, data) {
+            self.ap_write(ap::TAR, addr)?;
+            for &w in chunk {
+                self.ap_write(ap::DRW, w)?;
+            }
+            addr = addr.wrapping_add(4 * chunk.len() as u32);
+        }
+        self.dp_read(dp::RDBUFF).map(|_| ())
+    }
+
+    /// Words left before the auto-increment block containing `addr` ends.
+    fn block_words(addr: u32) -> usize {
+        ((AUTOINC_BLOCK - (addr & (AUTOINC_BLOCK - 1))) / 4) as usize
+    }
+
+    /// Splits `data` into runs that can each be written with one TAR.
+    fn blocks(addr: u32, data: &[u32]) -> impl Iterator<Item = &[u32]> {
+        let mut rest = data;
+        let mut addr = addr;
+        core::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None;
+            }
+            let n = usize::min(Self::block_words(addr), rest.len());
+            let (chunk, tail) = rest.split_at(n);
+            rest = tail;
+            addr = addr.wrapping_add(4 * n as u32);
+            Some(chunk)
+        })
+    }
+
+    pub fn dhcsr(&mut self) -> Result<u32, Error> {
+        self.read_word(DHCSR)
+    }
+
+    /// Stops the core, and waits until it has.
+    pub fn halt(&mut self) -> Result<(), Error> {
+        self.write_word(
+            DHCSR,
+            dhcsr::DBGKEY | dhcsr::C_DEBUGEN | dhcsr::C_HALT,
+        )?;
+        self.wait_for_halt()
+    }
+
+    /// Lets the core go. Halting debug stays enabled, so that vector catch
+    /// keeps working.
+    pub fn resume(&mut self) -> Result<(), Error> {
+        self.write_word(DHCSR, dhcsr::DBGKEY | dhcsr::C_DEBUGEN)
+    }
+
+    fn wait_for_halt(&mut self) -> Result<(), Error> {
+        for _ in 0..POLL_LIMIT {
+            if self.dhcsr()? & dhcsr::S_HALT != 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Turns on halting debug, leaving the core halted or running as it was.
+    fn enable_debug(&mut self) -> Result<(), Error> {
+        let halt = self.dhcsr()? & dhcsr::S_HALT != 0;
+        let c_halt = if halt { dhcsr::C_HALT } else { 0 };
+        self.write_word(DHCSR, dhcsr::DBGKEY | dhcsr::C_DEBUGEN | c_halt)
+    }
+
+    /// Chooses which exceptions stop the core, from the `vector_catch` bits.
+    pub fn set_vector_catch(&mut self, catch: u32) -> Result<(), Error> {
+        let demcr = self.read_word(DEMCR)?;
+        self.write_word(
+            DEMCR,
+            demcr & !vector_catch::ALL | catch & vector_catch::ALL,
+        )?;
+        // Vector catch does nothing without halting debug.
+        self.enable_debug()
+    }
+
+    pub fn vector_catch(&mut self) -> Result<u32, Error> {
+        Ok(self.read_word(DEMCR)? & vector_catch::ALL)
+    }
+
+    /// Resets the target through the core's AIRCR. With `halt`, the core
+    /// stops before running its first instruction, whatever the vector catch
+    /// settings; otherwise it's left to run.
+    pub fn reset(&mut self, halt: bool) -> Result<(), Error> {
+        let demcr = self.read_word(DEMCR)?;
+        if halt {
+            self.write_word(DEMCR, demcr | vector_catch::CORE_RESET)?;
+            self.enable_debug()?;
+        } else {
+            // A halt request would otherwise survive the reset.
+            self.resume()?;
+        }
+        self.write_word(AIRCR, AIRCR_VECTKEY | AIRCR_SYSRESETREQ)?;
+
+        if halt {
+            let r = self.wait_for_halt();
+            self.write_word(DEMCR, demcr)?;
+            r
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn read_core_register(&mut self, reg: u16) -> Result<u32, Error> {
+        self.select_register(reg, false)?;
+        self.read_word(DCRDR)
+    }
+
+    pub fn write_core_register(
+        &mut self,
+        reg: u16,
+        value: u32,
+    ) -> Result<(), Error> {
+        if reg > DCRSR_REGSEL_MAX {
+            return Err(Error::BadRegister);
+        }
+        self.write_word(DCRDR, value)?;
+        self.select_register(reg, true)
+    }
+
+    /// Starts a transfer between DCRDR and core register `reg`, and waits for
+    /// it to finish.
+    fn select_register(&mut self, reg: u16, write: bool) -> Result<(), Error> {
+        if reg > DCRSR_REGSEL_MAX {
+            return Err(Error::BadRegister);
+        }
+        if self.dhcsr()? & dhcsr::S_HALT == 0 {
+            return Err(Error::NotHalted);
+        }
+        let wnr = if write { DCRSR_REGWNR } else { 0 };
+        self.write_word(DCRSR, u32::from(reg) | wnr)?;
+        for _ in 0..POLL_LIMIT {
+            if self.dhcsr()? & dhcsr::S_REGRDY != 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A Cortex-M on the other end of the wire, as far as the debugger can
+    /// see one: a DP, a MEM-AP, memory, and the debug registers.
+    #[derive(Copy, Clone, Debug, Default)]
+    enum Phase {
+        #[default]
+        Idle,
+        Header(u32),
+        ReadData(u32),
+        ReadParity(u32),
+        WriteData(u32),
+        WriteParity(u32),
+    }
+
+    #[derive(Default)]
+    struct Target {
+        phase: Phase,
+        ctrl_stat: u32,
+        csw: u32,
+        tar: u32,
+        rdbuff: u32,
+        memory: HashMap<u32, u32>,
+        halted: bool,
+        debugen: bool,
+        demcr: u32,
+        dcrdr: u32,
+        regs: HashMap<u16, u32>,
+        /// Answer WAIT to this many requests before doing any work.
+        waits: usize,
+        /// Answer FAULT to accesses here.
+        bad_address: Option<u32>,
+        /// Every TAR write, to check the 1 KiB blocks.
+        tars: Vec<u32>,
+        aborts: Vec<u32>,
+        line: Vec<u32>,
+    }
+
+    impl Target {
+        fn mem_read(&mut self, addr: u32) -> u32 {
+            match addr {
+                DHCSR => {
+                    let mut v = dhcsr::S_REGRDY;
+                    if self.debugen {
+                        v |= dhcsr::C_DEBUGEN;
+                    }
+                    if self.halted {
+                        v |= dhcsr::S_HALT | dhcsr::C_HALT;
+                    }
+                    v
+                }
+                DCRDR => self.dcrdr,
+                DEMCR => self.demcr,
+                _ => *self.memory.get(&addr).unwrap_or(&0),
+            }
+        }
+
+        fn mem_write(&mut self, addr: u32, v: u32) {
+            match addr {
+                DHCSR if v >> 16 == 0xa05f => {
+                    self.debugen = v & dhcsr::C_DEBUGEN != 0;
+                    self.halted = v & dhcsr::C_HALT != 0;
+                }
+                DCRSR => {
+                    let r = (v & 0x7f) as u16;
+                    if v & DCRSR_REGWNR != 0 {
+                        self.regs.insert(r, self.dcrdr);
+                    } else {
+                        self.dcrdr = *self.regs.get(&r).unwrap_or(&0);
+                    }
+                }
+                DCRDR => self.dcrdr = v,
+                DEMCR => self.demcr = v,
+                AIRCR if v == AIRCR_VECTKEY | AIRCR_SYSRESETREQ => {
+                    self.regs.insert(15, 0x100);
+                    self.halted = self.debugen
+                        && (self.halted
+                            || self.demcr & vector_catch::CORE_RESET != 0);
+                }
+                _ => {
+                    self.memory.insert(addr, v);
+                }
+            }
+        }
+
+        /// Like real hardware, only the bottom 10 bits of TAR count.
+        fn increment(&mut self) {
+            if self.csw & 0x30 == 0x10 {
+                self.tar = self.tar & !0x3ff | self.tar.wrapping_add(4) & 0x3ff;
+            }
+        }
+
+        fn ack(&mut self, h: u32) -> u32 {
+            if self.waits > 0 {
+                self.waits -= 1;
+                return ACK_WAIT;
+            }
+            let ap = h & 2 != 0;
+            let addr = (h >> 3 & 3) << 2;
+            if ap
+                && addr == u32::from(ap::DRW)
+                && Some(self.tar) == self.bad_address
+            {
+                return ACK_FAULT;
+            }
+            ACK_OK
+        }
+
+        fn read(&mut self, h: u32) -> u32 {
+            let addr = ((h >> 3 & 3) << 2) as u8;
+            if h & 2 == 0 {
+                match addr {
+                    dp::DPIDR => 0x2ba0_1477,
+                    dp::CTRL_STAT => {
+                        self.ctrl_stat | (self.ctrl_stat & 0x5000_0000) << 1
+                    }
+                    dp::RDBUFF => self.rdbuff,
+                    _ => panic!("read of DP {addr:#x}"),
+                }
+            } else {
+                let old = self.rdbuff;
+                self.rdbuff = match addr {
+                    ap::CSW => self.csw,
+                    ap::TAR => self.tar,
+                    ap::DRW => {
+                        let v = self.mem_read(self.tar);
+                        self.increment();
+                        v
+                    }
+                    _ => panic!("read of AP {addr:#x}"),
+                };
+                old
+            }
+        }
+
+        fn write(&mut self, h: u32, v: u32) {
+            let addr = ((h >> 3 & 3) << 2) as u8;
+            if h & 2 == 0 {
+                match addr {
+                    dp::ABORT => self.aborts.push(v),
+                    dp::CTRL_STAT => self.ctrl_stat = v,
+                    dp::SELECT => assert_eq!(v, 0),
+                    _ => panic!("write of DP {addr:#x}"),
+                }
+            } else {
+                match addr {
+                    ap::CSW => self.csw = v,
+                    ap::TAR => {
+                        self.tars.push(v);
+                        self.tar = v;
+                    }
+                    ap::DRW => {
+                        self.mem_write(self.tar, v);
+                        self.increment();
+                    }
+                    _ => panic!("write of AP {addr:#x}"),
+                }
+            }
+        }
+    }
+
+    impl Wire for Target {
+        fn write_bits(&mut self, bits: u32, n: u8) {
+            self.phase = match self.phase {
+                Phase::WriteData(h) => {
+                    assert_eq!(n, 32);
+                    self.write(h, bits);
+                    Phase::WriteParity(bits)
+                }
+                Phase::WriteParity(v) => {
+                    assert_eq!((n, bits), (1, parity(v)), "bad parity");
+                    Phase::Idle
+                }
+                Phase::Idle
+                    if n == 8 && bits & 0x81 == 0x81 && bits != 0xff =>
+                {
+                    let body = bits >> 1 & 0xf;
+                    assert_eq!(bits >> 5 & 1, parity(body), "bad parity");
+                    assert_eq!(bits >> 6 & 1, 0, "bad stop bit");
+                    Phase::Header(bits)
+                }
+                Phase::Idle => {
+                    self.line.push(bits);
+                    Phase::Idle
+                }
+                p => panic!("write of {n} bits in {p:?}"),
+            };
+        }
+
+        fn read_bits(&mut self, n: u8) -> u32 {
+            let (v, next) = match (self.phase, n) {
+                (Phase::Header(h), 3) => match self.ack(h) {
+                    ACK_OK if h & 4 != 0 => (ACK_OK, Phase::ReadData(h)),
+                    ACK_OK => (ACK_OK, Phase::WriteData(h)),
+                    ack => (ack, Phase::Idle),
+                },
+                (Phase::ReadData(h), 32) => {
+                    let v = self.read(h);
+                    (v, Phase::ReadParity(v))
+                }
+                (Phase::ReadParity(v), 1) => (parity(v), Phase::Idle),
+                (p, _) => panic!("read of {n} bits in {p:?}"),
+            };
+            self.phase = next;
+            v
+        }
+
+        fn release(&mut self) {}
+        fn drive(&mut self) {}
+    }
+
+    fn connected() -> Swd<Target> {
+        let mut swd = Swd::new(Target::default());
+        assert_eq!(swd.connect(), Ok(0x2ba0_1477));
+        swd
+    }
+
+    #[test]
+    fn requests() {
+        // DPIDR read, RDBUFF read, and TAR write.
+        assert_eq!(request(false, true, dp::DPIDR), 0xa5);
+        assert_eq!(request(false, true, dp::RDBUFF), 0xbd);
+        assert_eq!(request(true, false, ap::TAR), 0x8b);
+    }
+
+    #[test]
+    fn connect() {
+        let swd = connected();
+        let t = &swd.wire;
+        assert_eq!(t.aborts, [ABORT_CLEAR_STICKY]);
+        assert_eq!(t.ctrl_stat, CTRL_CDBGPWRUPREQ | CTRL_CSYSPWRUPREQ);
+        assert_eq!(t.csw, CSW_VALUE);
+        // Line reset, switch sequence, line reset, idle.
+        assert_eq!(&t.line[..5], &[!0, !0, JTAG_TO_SWD, !0, !0]);
+    }
+
+    #[test]
+    fn memory() {
+        let mut swd = connected();
+        swd.write_word(0x2000_0000, 0xdead_beef).unwrap();
+        assert_eq!(swd.read_word(0x2000_0000), Ok(0xdead_beef));
+        assert_eq!(swd.read_word(0x2000_0002), Err(Error::Unaligned));
+
+        // Straddles a 1 KiB boundary, so TAR has to be written twice each way.
+        let data: Vec<u32> = (0..8).collect();
+        swd.wire.tars.clear();
+        swd.write_words(0x2000_03f0, &data).unwrap();
+        assert_eq!(swd.wire.tars, [0x2000_03f0, 0x2000_0400]);
+
+        let mut back = [0; 8];
+        swd.wire.tars.clear();
+        swd.read_words(0x2000_03f0, &mut back).unwrap();
+        assert_eq!(swd.wire.tars, [0x2000_03f0, 0x2000_0400]);
+        assert_eq!(&back[..], &data[..]);
+    }
+
+    #[test]
+    fn wait_and_fault() {
+        let mut swd = connected();
+        swd.wire.waits = 3;
+        swd.write_word(0x2000_0000, 7).unwrap();
+        assert_eq!(swd.read_word(0x2000_0000), Ok(7));
+
+        swd.wire.waits = WAIT_RETRIES;
+        assert_eq!(swd.read_word(0x2000_0000), Err(Error::Wait));
+        assert_eq!(swd.wire.aborts.last(), Some(&ABORT_DAPABORT));
+
+        swd.wire.bad_address = Some(0x4000_0000);
+        assert_eq!(swd.read_word(0x4000_0000), Err(Error::Fault));
+        assert_eq!(swd.wire.aborts.last(), Some(&ABORT_CLEAR_STICKY));
+        assert_eq!(swd.read_word(0x2000_0000), Ok(7));
+    }
+
+    #[test]
+    fn core_registers() {
+        let mut swd = connected();
+        assert_eq!(swd.read_core_register(0), Err(Error::NotHalted));
+        swd.halt().unwrap();
+        swd.write_core_register(15, 0x1234).unwrap();
+        assert_eq!(swd.read_core_register(15), Ok(0x1234));
+        assert_eq!(swd.read_core_register(0x80), Err(Error::BadRegister));
+        swd.resume().unwrap();
+        assert_eq!(swd.dhcsr().unwrap() & dhcsr::S_HALT, 0);
+    }
+
+    #[test]
+    fn reset_and_catch() {
+        let mut swd = connected();
+        swd.set_vector_catch(vector_catch::HARD_FAULT).unwrap();
+        assert_eq!(swd.vector_catch(), Ok(vector_catch::HARD_FAULT));
+
+        swd.reset(true).unwrap();
+        assert_ne!(swd.dhcsr().unwrap() & dhcsr::S_HALT, 0);
+        assert_eq!(swd.read_core_register(15), Ok(0x100));
+        // The catch used for the reset is put back the way it was.
+        assert_eq!(swd.vector_catch(), Ok(vector_catch::HARD_FAULT));
+
+        swd.reset(false).unwrap();
+        assert_eq!(swd.dhcsr().unwrap() & dhcsr::S_HALT, 0);
+    }
+}
@@ -26,6 +26,10 @@ pub struct Task<T = ordered_toml::Value> {
     pub uses: Vec<String>,
     #[serde(default)]
     pub features: Vec<String>,
+    /// Features to check both with and without, in every combination, when
+    /// running `cargo xtask check-features`
+    #[serde(default)]
+    pub feature_matrix: Vec<String>,
     #[serde(default)]
     pub notifications: Vec<String>,
     #[serde(default)]
@@ -58,6 +62,11 @@ pub struct Task<T = ordered_toml::Value> {
     pub sections: IndexMap<String, String>,
     #[serde(default)]
     pub max_sizes: IndexMap<String, u32>,
+
+    /// Where indirect calls may go, for stack depth analysis: maps part of a
+    /// function's path to parts of the paths of the functions it may call.
+    #[serde(default)]
+    pub stack_calls: IndexMap<String, Vec<String>>,
 }
 
 impl<T> Task<T> {
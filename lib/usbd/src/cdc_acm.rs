@@ -0,0 +1,265 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! CDC ACM, the "virtual serial port" class.
+//!
+//! This is a communications interface, with an interrupt endpoint for
+//! notifications, and a data interface with a pair of bulk endpoints. We
+//! handle the requests terminal programs make: line coding (which we
+//! remember, but which means nothing to a USB pipe), control line state, and
+//! breaks. Moving the data is left to the caller, who reads and writes the
+//! data endpoint with `Bus::read` and `Bus::write`, and arms it for reading
+//! when it has room.
+
+use crate::{
+    descriptor, BufferFull, Bus, Class, DescriptorWriter, EndpointType,
+    SetupPacket, Stall, WouldBlock, EP_IN,
+};
+
+const COMM_CLASS: [u8; 3] = [0x02, 0x02, 0x00]; // CDC, ACM, no protocol
+const DATA_CLASS: [u8; 3] = [0x0a, 0x00, 0x00];
+
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+const SEND_BREAK: u8 = 0x23;
+
+const SERIAL_STATE: u8 = 0x20;
+
+/// The notification endpoint only ever carries SERIAL_STATE, which is 10
+/// bytes long.
+const NOTIFY_MAX_PACKET: u16 = 16;
+
+/// Bits of a SERIAL_STATE notification.
+pub mod serial_state {
+    pub const DCD: u16 = 1 << 0;
+    pub const DSR: u16 = 1 << 1;
+    pub const BREAK: u16 = 1 << 2;
+    pub const RING: u16 = 1 << 3;
+    pub const FRAMING: u16 = 1 << 4;
+    pub const PARITY: u16 = 1 << 5;
+    pub const OVERRUN: u16 = 1 << 6;
+}
+
+/// Line settings chosen by the host.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LineCoding {
+    pub baud: u32,
+    /// 0 for 1 stop bit, 1 for 1.5, 2 for 2.
+    pub stop_bits: u8,
+    /// 0 for none, then odd, even, mark and space.
+    pub parity: u8,
+    pub data_bits: u8,
+}
+
+impl LineCoding {
+    fn to_bytes(self) -> [u8; 7] {
+        let b = self.baud.to_le_bytes();
+        [
+            b[0],
+            b[1],
+            b[2],
+            b[3],
+            self.stop_bits,
+            self.parity,
+            self.data_bits,
+        ]
+    }
+
+    fn from_bytes(b: &[u8]) -> Option<Self> {
+        match *b {
+            [b0, b1, b2, b3, stop_bits, parity, data_bits, ..] => Some(Self {
+                baud: u32::from_le_bytes([b0, b1, b2, b3]),
+                stop_bits,
+                parity,
+                data_bits,
+            }),
+            _ => None,
+        }
+    }
+}
+
+pub struct CdcAcm {
+    comm_ep: u8,
+    data_ep: u8,
+    comm_interface: u8,
+    line_coding: LineCoding,
+    dtr: bool,
+    rts: bool,
+    breaks: u32,
+    endpoints_reset: bool,
+}
+
+impl CdcAcm {
+    /// Creates the class, which will use IN endpoint `comm_ep` for
+    /// notifications and both directions of `data_ep` for data.
+    pub const fn new(comm_ep: u8, data_ep: u8) -> Self {
+        Self {
+            comm_ep,
+            data_ep,
+            comm_interface: 0,
+            line_coding: LineCoding {
+                baud: 115_200,
+                stop_bits: 0,
+                parity: 0,
+                data_bits: 8,
+            },
+            dtr: false,
+            rts: false,
+            breaks: 0,
+            endpoints_reset: false,
+        }
+    }
+
+    /// Number of the bulk endpoint pair carrying data.
+    pub fn data_ep(&self) -> u8 {
+        self.data_ep
+    }
+
+    /// Largest packet on the data endpoints.
+    pub fn max_packet(high_speed: bool) -> usize {
+        if high_speed {
+            512
+        } else {
+            64
+        }
+    }
+
+    pub fn line_coding(&self) -> LineCoding {
+        self.line_coding
+    }
+
+    /// Data Terminal Ready, which the host raises while a program has the
+    /// port open.
+    pub fn dtr(&self) -> bool {
+        self.dtr
+    }
+
+    pub fn rts(&self) -> bool {
+        self.rts
+    }
+
+    /// Number of breaks the host has asked for.
+    pub fn breaks(&self) -> u32 {
+        self.breaks
+    }
+
+    /// Returns true, once, after the endpoints have been reset by a bus
+    /// reset or a change of configuration, so that the caller can forget
+    /// about any packets it had in flight.
+    pub fn take_endpoints_reset(&mut self) -> bool {
+        core::mem::take(&mut self.endpoints_reset)
+    }
+
+    /// Sends a SERIAL_STATE notification; `state` is some combination of
+    /// the `serial_state` bits. Bits for events, like `BREAK`, are
+    /// reported once per notification.
+    pub fn notify_serial_state(
+        &self,
+        bus: &mut dyn Bus,
+        state: u16,
+    ) -> Result<(), WouldBlock> {
+        let s = state.to_le_bytes();
+        let i = self.comm_interface;
+        bus.write(
+            self.comm_ep,
+            &[0xa1, SERIAL_STATE, 0, 0, i, 0, 2, 0, s[0], s[1]],
+        )
+    }
+}
+
+impl Class for CdcAcm {
+    fn interface_count(&self) -> u8 {
+        2
+    }
+
+    fn write_descriptors(
+        &self,
+        first: u8,
+        w: &mut DescriptorWriter<'_>,
+    ) -> Result<(), BufferFull> {
+        let hs = w.is_high_speed();
+        let data = first + 1;
+
+        w.association(first, 2, COMM_CLASS)?;
+        w.interface(first, 1, COMM_CLASS)?;
+        // Header: CDC 1.10.
+        w.write(descriptor::CS_INTERFACE, &[0x00, 0x10, 0x01])?;
+        // Call management: none, but name the data interface anyway.
+        w.write(descriptor::CS_INTERFACE, &[0x01, 0x00, data])?;
+        // ACM: line coding, control line state, and breaks.
+        w.write(descriptor::CS_INTERFACE, &[0x02, 0x06])?;
+        // Union: which interfaces belong together.
+        w.write(descriptor::CS_INTERFACE, &[0x06, first, data])?;
+        // Polled every 32 ms; at high speed the interval is an exponent.
+        w.endpoint(
+            EP_IN | self.comm_ep,
+            EndpointType::Interrupt,
+            NOTIFY_MAX_PACKET,
+            if hs { 9 } else { 32 },
+        )?;
+
+        let mp = Self::max_packet(hs) as u16;
+        w.interface(data, 2, DATA_CLASS)?;
+        w.endpoint(self.data_ep, EndpointType::Bulk, mp, 0)?;
+        w.endpoint(EP_IN | self.data_ep, EndpointType::Bulk, mp, 0)
+    }
+
+    fn configure(&mut self, first_interface: u8, bus: &mut dyn Bus) {
+        self.comm_interface = first_interface;
+        let mp = Self::max_packet(bus.is_high_speed()) as u16;
+        bus.configure_endpoint(
+            EP_IN | self.comm_ep,
+            EndpointType::Interrupt,
+            NOTIFY_MAX_PACKET,
+        );
+        bus.configure_endpoint(self.data_ep, EndpointType::Bulk, mp);
+        bus.configure_endpoint(EP_IN | self.data_ep, EndpointType::Bulk, mp);
+    }
+
+    fn reset(&mut self) {
+        self.dtr = false;
+        self.rts = false;
+        self.endpoints_reset = true;
+    }
+
+    fn control_in(
+        &mut self,
+        setup: &SetupPacket,
+        interface: u8,
+        data: &mut [u8],
+    ) -> Result<usize, Stall> {
+        match (interface, setup.request) {
+            (0, GET_LINE_CODING) => {
+                data[..7].copy_from_slice(&self.line_coding.to_bytes());
+                Ok(7)
+            }
+            _ => Err(Stall),
+        }
+    }
+
+    fn control_out(
+        &mut self,
+        setup: &SetupPacket,
+        interface: u8,
+        data: &[u8],
+    ) -> Result<(), Stall> {
+        match (interface, setup.request) {
+            (0, SET_LINE_CODING) => {
+                self.line_coding = LineCoding::from_bytes(data).ok_or(Stall)?;
+            }
+            (0, SET_CONTROL_LINE_STATE) => {
+                self.dtr = setup.value & 1 != 0;
+                self.rts = setup.value & 2 != 0;
+            }
+            // A duration of 0 ends a break; anything else starts one.
+            (0, SEND_BREAK) if setup.value != 0 => {
+                self.breaks = self.breaks.wrapping_add(1);
+            }
+            (0, SEND_BREAK) => (),
+            _ => return Err(Stall),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1185 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A small USB device stack.
+//!
+//! `Device` handles the standard requests on endpoint 0 and leaves the rest
+//! to a fixed set of *classes*. Each class implements `Class`: it owns a run
+//! of interfaces and whatever endpoints it names, writes its part of the
+//! configuration descriptor, and gets the class and vendor requests
+//! addressed to its interfaces. Interfaces are numbered in the order the
+//! classes are passed in, and each class's interfaces are grouped with an
+//! interface association descriptor, so that a host binds a driver to each
+//! class separately. A controller driver implements `Bus`.
+//!
+//! There is a single configuration and no alternate settings. Adding a
+//! function (DFU, say) means implementing `Class` for it and passing it in
+//! alongside the others; nothing here needs to change.
+//!
+//! Everything is driven by the caller, from whatever tells it the controller
+//! needs attention: `reset` on a bus reset, `setup` when a SETUP packet
+//! arrives, and `ep0_out` or `ep0_in_complete` when endpoint 0 finishes a
+//! transfer in either direction.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod cdc_acm;
+
+/// Largest packet on endpoint 0, which we use at both full and high speed.
+pub const EP0_MAX_PACKET: usize = 64;
+
+/// Largest control transfer data stage we handle, in either direction.
+pub const CONTROL_BUF_SIZE: usize = 256;
+
+/// Direction bit of an endpoint address; set for IN (device to host).
+pub const EP_IN: u8 = 0x80;
+
+/// Descriptor type codes.
+pub mod descriptor {
+    pub const DEVICE: u8 = 1;
+    pub const CONFIGURATION: u8 = 2;
+    pub const STRING: u8 = 3;
+    pub const INTERFACE: u8 = 4;
+    pub const ENDPOINT: u8 = 5;
+    pub const DEVICE_QUALIFIER: u8 = 6;
+    pub const INTERFACE_ASSOCIATION: u8 = 11;
+    /// Class-specific interface descriptor.
+    pub const CS_INTERFACE: u8 = 0x24;
+}
+
+/// Standard request codes.
+mod request {
+    pub const GET_STATUS: u8 = 0;
+    pub const CLEAR_FEATURE: u8 = 1;
+    pub const SET_FEATURE: u8 = 3;
+    pub const SET_ADDRESS: u8 = 5;
+    pub const GET_DESCRIPTOR: u8 = 6;
+    pub const GET_CONFIGURATION: u8 = 8;
+    pub const SET_CONFIGURATION: u8 = 9;
+    pub const GET_INTERFACE: u8 = 10;
+    pub const SET_INTERFACE: u8 = 11;
+}
+
+const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+/// Device class triple announcing that interfaces are grouped by interface
+/// association descriptors.
+const DEVICE_CLASS_IAD: [u8; 3] = [0xef, 0x02, 0x01];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EndpointType {
+    Control = 0,
+    Isochronous = 1,
+    Bulk = 2,
+    Interrupt = 3,
+}
+
+/// The endpoint isn't ready: an IN endpoint still holds the last packet
+/// written to it, or an OUT endpoint hasn't received one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WouldBlock;
+
+/// Returned by a class to reject a request, which stalls endpoint 0.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Stall;
+
+/// A descriptor didn't fit in the buffer it was being written to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BufferFull;
+
+/// Operations on a device controller.
+///
+/// Endpoints are named by number, except where both directions are meant,
+/// in which case they're named by address (the number, with `EP_IN` set for
+/// the IN direction).
+pub trait Bus {
+    /// Whether the host negotiated high speed at the last reset.
+    fn is_high_speed(&self) -> bool;
+
+    /// Sets the address the device responds to.
+    fn set_address(&mut self, address: u8);
+
+    /// Enables an endpoint other than endpoint 0, with its data toggle
+    /// reset.
+    fn configure_endpoint(
+        &mut self,
+        address: u8,
+        kind: EndpointType,
+        max_packet: u16,
+    );
+
+    /// Disables every endpoint but endpoint 0.
+    fn reset_endpoints(&mut self);
+
+    /// Queues one packet, which may be empty, on IN endpoint `ep`.
+    fn write(&mut self, ep: u8, data: &[u8]) -> Result<(), WouldBlock>;
+
+    /// Takes the packet received on OUT endpoint `ep`, returning its length.
+    /// The endpoint won't accept another until it's been `arm`ed.
+    fn read(&mut self, ep: u8, buf: &mut [u8]) -> Result<usize, WouldBlock>;
+
+    /// Makes OUT endpoint `ep` ready to receive a packet.
+    fn arm(&mut self, ep: u8);
+
+    /// Sets or clears the halt condition on an endpoint; clearing it also
+    /// resets the data toggle. A stall of endpoint 0 must be cleared by the
+    /// controller when the next SETUP arrives.
+    fn set_stalled(&mut self, address: u8, stalled: bool);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RequestKind {
+    Standard,
+    Class,
+    Vendor,
+    Reserved,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Recipient {
+    Device,
+    Interface,
+    Endpoint,
+    Other,
+}
+
+/// The 8-byte packet that starts every control transfer.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub fn parse(b: &[u8; 8]) -> Self {
+        Self {
+            request_type: b[0],
+            request: b[1],
+            value: u16::from_le_bytes([b[2], b[3]]),
+            index: u16::from_le_bytes([b[4], b[5]]),
+            length: u16::from_le_bytes([b[6], b[7]]),
+        }
+    }
+
+    /// Whether the data stage, if any, goes to the host.
+    pub fn is_in(&self) -> bool {
+        self.request_type & 0x80 != 0
+    }
+
+    pub fn kind(&self) -> RequestKind {
+        match (self.request_type >> 5) & 0b11 {
+            0 => RequestKind::Standard,
+            1 => RequestKind::Class,
+            2 => RequestKind::Vendor,
+            _ => RequestKind::Reserved,
+        }
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        match self.request_type & 0x1f {
+            0 => Recipient::Device,
+            1 => Recipient::Interface,
+            2 => Recipient::Endpoint,
+            _ => Recipient::Other,
+        }
+    }
+}
+
+/// Appends descriptors to a buffer.
+pub struct DescriptorWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    high_speed: bool,
+}
+
+impl<'a> DescriptorWriter<'a> {
+    pub fn new(buf: &'a mut [u8], high_speed: bool) -> Self {
+        Self {
+            buf,
+            len: 0,
+            high_speed,
+        }
+    }
+
+    /// Whether the descriptors are for high speed operation, which changes
+    /// the packet sizes and polling intervals a class should ask for.
+    pub fn is_high_speed(&self) -> bool {
+        self.high_speed
+    }
+
+    /// Appends a descriptor of type `kind`; `body` is everything after the
+    /// length and type bytes.
+    pub fn write(&mut self, kind: u8, body: &[u8]) -> Result<(), BufferFull> {
+        let n = body.len() + 2;
+        let out = self.buf.get_mut(self.len..self.len + n).ok_or(BufferFull)?;
+        out[0] = n as u8;
+        out[1] = kind;
+        out[2..].copy_from_slice(body);
+        self.len += n;
+        Ok(())
+    }
+
+    /// Appends an interface association descriptor, grouping `count`
+    /// interfaces starting at `first` into one function.
+    pub fn association(
+        &mut self,
+        first: u8,
+        count: u8,
+        class: [u8; 3],
+    ) -> Result<(), BufferFull> {
+        self.write(
+            descriptor::INTERFACE_ASSOCIATION,
+            &[first, count, class[0], class[1], class[2], 0],
+        )
+    }
+
+    /// Appends an interface descriptor for alternate setting 0.
+    pub fn interface(
+        &mut self,
+        number: u8,
+        endpoints: u8,
+        class: [u8; 3],
+    ) -> Result<(), BufferFull> {
+        self.write(
+            descriptor::INTERFACE,
+            &[number, 0, endpoints, class[0], class[1], class[2], 0],
+        )
+    }
+
+    pub fn endpoint(
+        &mut self,
+        address: u8,
+        kind: EndpointType,
+        max_packet: u16,
+        interval: u8,
+    ) -> Result<(), BufferFull> {
+        let mp = max_packet.to_le_bytes();
+        self.write(
+            descriptor::ENDPOINT,
+            &[address, kind as u8, mp[0], mp[1], interval],
+        )
+    }
+}
+
+/// A function provided by the device.
+pub trait Class {
+    /// Number of interfaces the class uses.
+    fn interface_count(&self) -> u8;
+
+    /// Appends the class's descriptors to the configuration descriptor,
+    /// numbering its interfaces from `first_interface`.
+    fn write_descriptors(
+        &self,
+        first_interface: u8,
+        w: &mut DescriptorWriter<'_>,
+    ) -> Result<(), BufferFull>;
+
+    /// The host has selected our configuration; enable the class's
+    /// endpoints.
+    fn configure(&mut self, first_interface: u8, bus: &mut dyn Bus);
+
+    /// The bus was reset or the configuration deselected. The endpoints
+    /// have already been disabled.
+    fn reset(&mut self);
+
+    /// Handles a class or vendor request that sends data to the host,
+    /// addressed to the class's `interface`th interface (counting from 0).
+    /// Returns the number of bytes written to `data`.
+    fn control_in(
+        &mut self,
+        _setup: &SetupPacket,
+        _interface: u8,
+        _data: &mut [u8],
+    ) -> Result<usize, Stall> {
+        Err(Stall)
+    }
+
+    /// Handles a class or vendor request with no data stage, or with data
+    /// from the host, addressed as for `control_in`.
+    fn control_out(
+        &mut self,
+        _setup: &SetupPacket,
+        _interface: u8,
+        _data: &[u8],
+    ) -> Result<(), Stall> {
+        Err(Stall)
+    }
+}
+
+/// What goes in the device descriptor and its strings.
+pub struct DeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Device release number, in binary-coded decimal.
+    pub release: u16,
+    pub manufacturer: &'static str,
+    pub product: &'static str,
+    pub serial: &'static str,
+    pub max_power_ma: u16,
+    pub self_powered: bool,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum State {
+    Default,
+    Addressed,
+    Configured,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Control {
+    Idle,
+    /// Sending `buf[pos..len]`, to be followed by an empty packet if `zlp`.
+    DataIn {
+        len: usize,
+        pos: usize,
+        zlp: bool,
+    },
+    /// Collecting `len` bytes of data for `setup`.
+    DataOut {
+        setup: SetupPacket,
+        len: usize,
+        pos: usize,
+    },
+    /// Acknowledging; take `address` once the host has seen it.
+    StatusIn {
+        address: Option<u8>,
+    },
+}
+
+/// How to finish a request once it's been looked at.
+enum Reply {
+    /// Send `buf[..n]`.
+    Data(usize),
+    /// Acknowledge with an empty packet.
+    Ack,
+    /// Acknowledge, then switch to the given address.
+    Address(u8),
+    /// Collect the data stage before going further.
+    ReceiveData,
+}
+
+pub struct Device {
+    info: &'static DeviceInfo,
+    state: State,
+    configuration: u8,
+    /// Halted endpoints, one bit per address: OUT endpoints in the low half,
+    /// IN in the high.
+    halted: u32,
+    control: Control,
+    buf: [u8; CONTROL_BUF_SIZE],
+}
+
+impl Device {
+    pub const fn new(info: &'static DeviceInfo) -> Self {
+        Self {
+            info,
+            state: State::Default,
+            configuration: 0,
+            halted: 0,
+            control: Control::Idle,
+            buf: [0; CONTROL_BUF_SIZE],
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.state == State::Configured
+    }
+
+    /// Handles a bus reset.
+    pub fn reset(&mut self, bus: &mut dyn Bus, classes: &mut [&mut dyn Class]) {
+        self.state = State::Default;
+        self.configuration = 0;
+        self.halted = 0;
+        self.control = Control::Idle;
+        bus.reset_endpoints();
+        bus.set_address(0);
+        for c in classes.iter_mut() {
+            c.reset();
+        }
+        bus.arm(0);
+    }
+
+    /// Handles a SETUP packet, which abandons any transfer in progress.
+    pub fn setup(
+        &mut self,
+        bus: &mut dyn Bus,
+        classes: &mut [&mut dyn Class],
+        packet: &[u8; 8],
+    ) {
+        let setup = SetupPacket::parse(packet);
+        self.control = Control::Idle;
+        // Whichever way the data goes, something comes back on OUT: data,
+        // or the status stage of an IN transfer.
+        bus.arm(0);
+
+        let reply = self.dispatch(bus, classes, &setup);
+        let length = usize::from(setup.length);
+        match reply {
+            Ok(Reply::Data(n)) => {
+                let len = n.min(length);
+                // A transfer shorter than the host asked for ends with a
+                // short packet, which must be an empty one if the data
+                // filled the last.
+                let zlp = len > 0 && len < length && len % EP0_MAX_PACKET == 0;
+                self.control = Control::DataIn { len, pos: 0, zlp };
+                self.send_in_packet(bus);
+            }
+            Ok(Reply::Ack) => self.ack(bus, None),
+            Ok(Reply::Address(a)) => self.ack(bus, Some(a)),
+            Ok(Reply::ReceiveData) if length <= CONTROL_BUF_SIZE => {
+                self.control = Control::DataOut {
+                    setup,
+                    len: length,
+                    pos: 0,
+                };
+            }
+            Ok(Reply::ReceiveData) | Err(Stall) => self.stall(bus),
+        }
+    }
+
+    /// Handles the completion of an OUT transfer on endpoint 0.
+    pub fn ep0_out(
+        &mut self,
+        bus: &mut dyn Bus,
+        classes: &mut [&mut dyn Class],
+    ) {
+        let mut packet = [0; EP0_MAX_PACKET];
+        let n = match bus.read(0, &mut packet) {
+            Ok(n) => n,
+            Err(WouldBlock) => return,
+        };
+        bus.arm(0);
+
+        match self.control {
+            Control::DataOut { setup, len, pos } => {
+                let n = n.min(len - pos);
+                self.buf[pos..pos + n].copy_from_slice(&packet[..n]);
+                let pos = pos + n;
+                if pos < len && n == EP0_MAX_PACKET {
+                    self.control = Control::DataOut { setup, len, pos };
+                    return;
+                }
+                match self.class_out(classes, &setup, pos) {
+                    Ok(()) => self.ack(bus, None),
+                    Err(Stall) => self.stall(bus),
+                }
+            }
+            // This is the status stage of an IN transfer (possibly cutting
+            // it short, which the host is entitled to do).
+            _ => self.control = Control::Idle,
+        }
+    }
+
+    /// Handles the completion of an IN transfer on endpoint 0.
+    pub fn ep0_in_complete(&mut self, bus: &mut dyn Bus) {
+        match self.control {
+            Control::DataIn { len, pos, zlp } => {
+                if pos < len {
+                    self.send_in_packet(bus);
+                } else if zlp {
+                    let _ = bus.write(0, &[]);
+                    self.control = Control::DataIn {
+                        len,
+                        pos,
+                        zlp: false,
+                    };
+                } else {
+                    // The host will finish with an empty OUT packet.
+                    self.control = Control::Idle;
+                }
+            }
+            Control::StatusIn { address } => {
+                if let Some(a) = address {
+                    bus.set_address(a);
+                    self.state = if a == 0 {
+                        State::Default
+                    } else {
+                        State::Addressed
+                    };
+                }
+                self.control = Control::Idle;
+            }
+            _ => (),
+        }
+    }
+
+    fn send_in_packet(&mut self, bus: &mut dyn Bus) {
+        if let Control::DataIn { len, pos, zlp } = self.control {
+            let n = (len - pos).min(EP0_MAX_PACKET);
+            // The host doesn't ask for a packet until it has taken the last,
+            // so endpoint 0 is always free here.
+            let _ = bus.write(0, &self.buf[pos..pos + n]);
+            self.control = Control::DataIn {
+                len,
+                pos: pos + n,
+                zlp,
+            };
+        }
+    }
+
+    fn ack(&mut self, bus: &mut dyn Bus, address: Option<u8>) {
+        let _ = bus.write(0, &[]);
+        self.control = Control::StatusIn { address };
+    }
+
+    fn stall(&mut self, bus: &mut dyn Bus) {
+        bus.set_stalled(0, true);
+        bus.set_stalled(EP_IN, true);
+        self.control = Control::Idle;
+    }
+
+    fn dispatch(
+        &mut self,
+        bus: &mut dyn Bus,
+        classes: &mut [&mut dyn Class],
+        setup: &SetupPacket,
+    ) -> Result<Reply, Stall> {
+        match (setup.kind(), setup.recipient()) {
+            (RequestKind::Standard, Recipient::Device) => {
+                self.device_request(bus, classes, setup)
+            }
+            (RequestKind::Standard, Recipient::Interface) => {
+                self.interface_request(classes, setup)
+            }
+            (RequestKind::Standard, Recipient::Endpoint) => {
+                self.endpoint_request(bus, setup)
+            }
+            (
+                RequestKind::Class | RequestKind::Vendor,
+                Recipient::Interface,
+            ) => {
+                if !self.is_configured() {
+                    return Err(Stall);
+                }
+                if setup.is_in() {
+                    let (i, interface) = find_class(classes, setup.index)?;
+                    let n = classes[i].control_in(
+                        setup,
+                        interface,
+                        &mut self.buf,
+                    )?;
+                    Ok(Reply::Data(n))
+                } else if setup.length == 0 {
+                    self.class_out(classes, setup, 0)?;
+                    Ok(Reply::Ack)
+                } else {
+                    Ok(Reply::ReceiveData)
+                }
+            }
+            _ => Err(Stall),
+        }
+    }
+
+    fn class_out(
+        &mut self,
+        classes: &mut [&mut dyn Class],
+        setup: &SetupPacket,
+        len: usize,
+    ) -> Result<(), Stall> {
+        let (i, interface) = find_class(classes, setup.index)?;
+        classes[i].control_out(setup, interface, &self.buf[..len])
+    }
+
+    fn device_request(
+        &mut self,
+        bus: &mut dyn Bus,
+        classes: &mut [&mut dyn Class],
+        setup: &SetupPacket,
+    ) -> Result<Reply, Stall> {
+        match (setup.request, setup.is_in()) {
+            (request::GET_STATUS, true) => {
+                self.buf[0] = u8::from(self.info.self_powered);
+                self.buf[1] = 0;
+                Ok(Reply::Data(2))
+            }
+            (request::SET_ADDRESS, false) => {
+                if setup.value > 127 || self.state == State::Configured {
+                    return Err(Stall);
+                }
+                Ok(Reply::Address(setup.value as u8))
+            }
+            (request::GET_DESCRIPTOR, true) => {
+                self.get_descriptor(bus, classes, setup).map(Reply::Data)
+            }
+            (request::GET_CONFIGURATION, true) => {
+                self.buf[0] = self.configuration;
+                Ok(Reply::Data(1))
+            }
+            (request::SET_CONFIGURATION, false) => {
+                if self.state == State::Default || setup.value > 1 {
+                    return Err(Stall);
+                }
+                bus.reset_endpoints();
+                self.halted = 0;
+                for c in classes.iter_mut() {
+                    c.reset();
+                }
+                if setup.value == 1 {
+                    let mut first = 0;
+                    for c in classes.iter_mut() {
+                        c.configure(first, bus);
+                        first += c.interface_count();
+                    }
+                    self.state = State::Configured;
+                } else {
+                    self.state = State::Addressed;
+                }
+                self.configuration = setup.value as u8;
+                Ok(Reply::Ack)
+            }
+            // We don't offer remote wakeup or test modes, which are the
+            // only device features.
+            _ => Err(Stall),
+        }
+    }
+
+    fn interface_request(
+        &mut self,
+        classes: &mut [&mut dyn Class],
+        setup: &SetupPacket,
+    ) -> Result<Reply, Stall> {
+        if !self.is_configured() {
+            return Err(Stall);
+        }
+        find_class(classes, setup.index)?;
+        match (setup.request, setup.is_in()) {
+            (request::GET_STATUS, true) => {
+                self.buf[..2].fill(0);
+                Ok(Reply::Data(2))
+            }
+            (request::GET_INTERFACE, true) => {
+                self.buf[0] = 0;
+                Ok(Reply::Data(1))
+            }
+            (request::SET_INTERFACE, false) if setup.value == 0 => {
+                Ok(Reply::Ack)
+            }
+            _ => Err(Stall),
+        }
+    }
+
+    fn endpoint_request(
+        &mut self,
+        bus: &mut dyn Bus,
+        setup: &SetupPacket,
+    ) -> Result<Reply, Stall> {
+        let address = setup.index as u8;
+        if address & 0x70 != 0 || (address & 0xf != 0 && !self.is_configured())
+        {
+            return Err(Stall);
+        }
+        let bit =
+            1 << ((address & 0xf) + if address & EP_IN != 0 { 16 } else { 0 });
+        match (setup.request, setup.is_in()) {
+            (request::GET_STATUS, true) => {
+                self.buf[0] = u8::from(self.halted & bit != 0);
+                self.buf[1] = 0;
+                Ok(Reply::Data(2))
+            }
+            (request::CLEAR_FEATURE, false)
+                if setup.value == FEATURE_ENDPOINT_HALT =>
+            {
+                bus.set_stalled(address, false);
+                self.halted &= !bit;
+                Ok(Reply::Ack)
+            }
+            (request::SET_FEATURE, false)
+                if setup.value == FEATURE_ENDPOINT_HALT =>
+            {
+                bus.set_stalled(address, true);
+                self.halted |= bit;
+                Ok(Reply::Ack)
+            }
+            _ => Err(Stall),
+        }
+    }
+
+    fn get_descriptor(
+        &mut self,
+        bus: &mut dyn Bus,
+        classes: &mut [&mut dyn Class],
+        setup: &SetupPacket,
+    ) -> Result<usize, Stall> {
+        let info = self.info;
+        let index = setup.value as u8;
+        match (setup.value >> 8) as u8 {
+            descriptor::DEVICE => {
+                let vid = info.vendor_id.to_le_bytes();
+                let pid = info.product_id.to_le_bytes();
+                let rel = info.release.to_le_bytes();
+                let [class, subclass, protocol] = DEVICE_CLASS_IAD;
+                let d = [
+                    18,
+                    descriptor::DEVICE,
+                    0x00,
+                    0x02, // USB 2.0
+                    class,
+                    subclass,
+                    protocol,
+                    EP0_MAX_PACKET as u8,
+                    vid[0],
+                    vid[1],
+                    pid[0],
+                    pid[1],
+                    rel[0],
+                    rel[1],
+                    1, // manufacturer string
+                    2, // product string
+                    3, // serial number string
+                    1, // configurations
+                ];
+                self.buf[..d.len()].copy_from_slice(&d);
+                Ok(d.len())
+            }
+            descriptor::DEVICE_QUALIFIER => {
+                let [class, subclass, protocol] = DEVICE_CLASS_IAD;
+                let d = [
+                    10,
+                    descriptor::DEVICE_QUALIFIER,
+                    0x00,
+                    0x02,
+                    class,
+                    subclass,
+                    protocol,
+                    EP0_MAX_PACKET as u8,
+                    1,
+                    0,
+                ];
+                self.buf[..d.len()].copy_from_slice(&d);
+                Ok(d.len())
+            }
+            descriptor::CONFIGURATION if index == 0 => {
+                self.configuration_descriptor(bus.is_high_speed(), classes)
+            }
+            descriptor::STRING => {
+                let s = match index {
+                    // The list of languages: just US English.
+                    0 => {
+                        self.buf[..4].copy_from_slice(&[
+                            4,
+                            descriptor::STRING,
+                            0x09,
+                            0x04,
+                        ]);
+                        return Ok(4);
+                    }
+                    1 => info.manufacturer,
+                    2 => info.product,
+                    3 => info.serial,
+                    _ => return Err(Stall),
+                };
+                Ok(string_descriptor(s, &mut self.buf))
+            }
+            _ => Err(Stall),
+        }
+    }
+
+    fn configuration_descriptor(
+        &mut self,
+        high_speed: bool,
+        classes: &mut [&mut dyn Class],
+    ) -> Result<usize, Stall> {
+        const HEADER: usize = 9;
+        let mut w = DescriptorWriter::new(&mut self.buf[HEADER..], high_speed);
+        let mut interfaces = 0;
+        for c in classes.iter() {
+            // A class that doesn't fit is a bug in the firmware, and we'd
+            // rather stall than describe the device wrongly.
+            c.write_descriptors(interfaces, &mut w).map_err(|_| Stall)?;
+            interfaces += c.interface_count();
+        }
+        let total = HEADER + w.len;
+
+        let info = self.info;
+        let t = (total as u16).to_le_bytes();
+        self.buf[..HEADER].copy_from_slice(&[
+            HEADER as u8,
+            descriptor::CONFIGURATION,
+            t[0],
+            t[1],
+            interfaces,
+            1, // configuration value
+            0, // no string
+            0x80 | u8::from(info.self_powered) << 6,
+            (info.max_power_ma / 2).min(255) as u8,
+        ]);
+        Ok(total)
+    }
+}
+
+/// Finds the class owning interface `index`, returning its position in
+/// `classes` and the interface's number within the class.
+fn find_class(
+    classes: &[&mut dyn Class],
+    index: u16,
+) -> Result<(usize, u8), Stall> {
+    let mut first = 0u16;
+    for (i, c) in classes.iter().enumerate() {
+        let count = u16::from(c.interface_count());
+        if index >= first && index < first + count {
+            return Ok((i, (index - first) as u8));
+        }
+        first += count;
+    }
+    Err(Stall)
+}
+
+/// Writes `s` as a string descriptor, returning its length. Strings are
+/// expected to be ASCII, and are truncated to fit.
+fn string_descriptor(s: &str, buf: &mut [u8]) -> usize {
+    let mut n = 2;
+    for c in s.chars() {
+        if n + 2 > buf.len().min(255) {
+            break;
+        }
+        let c = if c.is_ascii() {
+            c as u16
+        } else {
+            u16::from(b'?')
+        };
+        buf[n..n + 2].copy_from_slice(&c.to_le_bytes());
+        n += 2;
+    }
+    buf[0] = n as u8;
+    buf[1] = descriptor::STRING;
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[derive(Default)]
+    struct MockBus {
+        high_speed: bool,
+        address: u8,
+        /// Packets written to each IN endpoint, oldest first.
+        written: Vec<(u8, Vec<u8>)>,
+        /// Packets waiting on OUT endpoint 0.
+        ep0_out: VecDeque<Vec<u8>>,
+        configured: Vec<(u8, EndpointType, u16)>,
+        stalled: Vec<u8>,
+    }
+
+    impl Bus for MockBus {
+        fn is_high_speed(&self) -> bool {
+            self.high_speed
+        }
+        fn set_address(&mut self, address: u8) {
+            self.address = address;
+        }
+        fn configure_endpoint(
+            &mut self,
+            address: u8,
+            kind: EndpointType,
+            max_packet: u16,
+        ) {
+            self.configured.push((address, kind, max_packet));
+        }
+        fn reset_endpoints(&mut self) {
+            self.configured.clear();
+        }
+        fn write(&mut self, ep: u8, data: &[u8]) -> Result<(), WouldBlock> {
+            self.written.push((ep, data.to_vec()));
+            Ok(())
+        }
+        fn read(
+            &mut self,
+            ep: u8,
+            buf: &mut [u8],
+        ) -> Result<usize, WouldBlock> {
+            assert_eq!(ep, 0);
+            let p = self.ep0_out.pop_front().ok_or(WouldBlock)?;
+            buf[..p.len()].copy_from_slice(&p);
+            Ok(p.len())
+        }
+        fn arm(&mut self, _ep: u8) {}
+        fn set_stalled(&mut self, address: u8, stalled: bool) {
+            if stalled {
+                self.stalled.push(address);
+            }
+        }
+    }
+
+    static INFO: DeviceInfo = DeviceInfo {
+        vendor_id: 0x1209,
+        product_id: 0x0001,
+        release: 0x0100,
+        manufacturer: "Maker",
+        product: "Thing",
+        serial: "1",
+        max_power_ma: 100,
+        self_powered: false,
+    };
+
+    fn setup(
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> [u8; 8] {
+        let v = value.to_le_bytes();
+        let i = index.to_le_bytes();
+        let l = length.to_le_bytes();
+        [request_type, request, v[0], v[1], i[0], i[1], l[0], l[1]]
+    }
+
+    /// Runs an IN control transfer to completion, returning the data.
+    fn control_in(
+        dev: &mut Device,
+        bus: &mut MockBus,
+        classes: &mut [&mut dyn Class],
+        packet: [u8; 8],
+    ) -> Vec<u8> {
+        bus.written.clear();
+        dev.setup(bus, classes, &packet);
+        let mut seen = 0;
+        while seen < bus.written.len() {
+            seen = bus.written.len();
+            dev.ep0_in_complete(bus);
+        }
+        assert!(bus.stalled.is_empty(), "request stalled");
+        let mut data = vec![];
+        for (i, (ep, p)) in bus.written.iter().enumerate() {
+            assert_eq!(*ep, 0);
+            data.extend_from_slice(p);
+            if p.len() < EP0_MAX_PACKET {
+                assert_eq!(i, bus.written.len() - 1, "short packet not last");
+            }
+        }
+        data
+    }
+
+    fn configured(bus: &mut MockBus, classes: &mut [&mut dyn Class]) -> Device {
+        let mut dev = Device::new(&INFO);
+        dev.reset(bus, classes);
+        dev.setup(bus, classes, &setup(0x00, request::SET_ADDRESS, 5, 0, 0));
+        dev.ep0_in_complete(bus);
+        dev.setup(
+            bus,
+            classes,
+            &setup(0x00, request::SET_CONFIGURATION, 1, 0, 0),
+        );
+        dev.ep0_in_complete(bus);
+        assert!(dev.is_configured());
+        dev
+    }
+
+    #[test]
+    fn device_descriptor() {
+        let mut bus = MockBus::default();
+        let mut dev = Device::new(&INFO);
+        dev.reset(&mut bus, &mut []);
+        let d = control_in(
+            &mut dev,
+            &mut bus,
+            &mut [],
+            setup(0x80, request::GET_DESCRIPTOR, 0x0100, 0, 64),
+        );
+        assert_eq!(d.len(), 18);
+        assert_eq!(&d[8..10], &0x1209u16.to_le_bytes());
+
+        let s = control_in(
+            &mut dev,
+            &mut bus,
+            &mut [],
+            setup(0x80, request::GET_DESCRIPTOR, 0x0302, 0x0409, 255),
+        );
+        assert_eq!(s, [12, 3, b'T', 0, b'h', 0, b'i', 0, b'n', 0, b'g', 0]);
+    }
+
+    #[test]
+    fn address_taken_after_status() {
+        let mut bus = MockBus::default();
+        let mut dev = Device::new(&INFO);
+        dev.reset(&mut bus, &mut []);
+        dev.setup(
+            &mut bus,
+            &mut [],
+            &setup(0x00, request::SET_ADDRESS, 9, 0, 0),
+        );
+        assert_eq!(bus.address, 0);
+        assert_eq!(bus.written, [(0, vec![])]);
+        dev.ep0_in_complete(&mut bus);
+        assert_eq!(bus.address, 9);
+        assert_eq!(dev.state(), State::Addressed);
+    }
+
+    #[test]
+    fn configuration_descriptor() {
+        let mut bus = MockBus {
+            high_speed: true,
+            ..Default::default()
+        };
+        let mut cdc = cdc_acm::CdcAcm::new(1, 2);
+        let classes: &mut [&mut dyn Class] = &mut [&mut cdc];
+        let mut dev = Device::new(&INFO);
+        dev.reset(&mut bus, classes);
+
+        // Hosts ask for the header first, to learn the total length.
+        let header = control_in(
+            &mut dev,
+            &mut bus,
+            classes,
+            setup(0x80, request::GET_DESCRIPTOR, 0x0200, 0, 9),
+        );
+        assert_eq!(header.len(), 9);
+        let total = u16::from_le_bytes([header[2], header[3]]);
+        assert_eq!(header[4], 2);
+
+        let full = control_in(
+            &mut dev,
+            &mut bus,
+            classes,
+            setup(0x80, request::GET_DESCRIPTOR, 0x0200, 0, total),
+        );
+        assert_eq!(full.len(), usize::from(total));
+        assert!(full.len() > EP0_MAX_PACKET);
+
+        // Walk the descriptors, checking their lengths add up, and pick out
+        // the bulk endpoints.
+        let mut pos = 0;
+        let mut bulk = vec![];
+        while pos < full.len() {
+            let len = usize::from(full[pos]);
+            if full[pos + 1] == descriptor::ENDPOINT
+                && full[pos + 3] == EndpointType::Bulk as u8
+            {
+                bulk.push((
+                    full[pos + 2],
+                    u16::from_le_bytes([full[pos + 4], full[pos + 5]]),
+                ));
+            }
+            pos += len;
+        }
+        assert_eq!(pos, full.len());
+        assert_eq!(bulk, [(0x02, 512), (0x82, 512)]);
+    }
+
+    /// A class with one interface, answering any vendor request with as
+    /// many bytes as the request's value.
+    struct Blob;
+
+    impl Class for Blob {
+        fn interface_count(&self) -> u8 {
+            1
+        }
+        fn write_descriptors(
+            &self,
+            first: u8,
+            w: &mut DescriptorWriter<'_>,
+        ) -> Result<(), BufferFull> {
+            w.interface(first, 0, [0xff, 0, 0])
+        }
+        fn configure(&mut self, _first: u8, _bus: &mut dyn Bus) {}
+        fn reset(&mut self) {}
+        fn control_in(
+            &mut self,
+            setup: &SetupPacket,
+            _interface: u8,
+            data: &mut [u8],
+        ) -> Result<usize, Stall> {
+            let n = usize::from(setup.value);
+            data[..n].fill(0xaa);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn short_reply_ends_with_zlp() {
+        let mut bus = MockBus::default();
+        let mut blob = Blob;
+        let classes: &mut [&mut dyn Class] = &mut [&mut blob];
+        let mut dev = configured(&mut bus, classes);
+
+        // Exactly one packet, when the host asked for more: an empty packet
+        // has to follow to end the transfer.
+        let d =
+            control_in(&mut dev, &mut bus, classes, setup(0xc1, 0, 64, 0, 128));
+        assert_eq!(d.len(), 64);
+        assert_eq!(bus.written.len(), 2);
+        assert!(bus.written[1].1.is_empty());
+
+        // But not when the host asked for exactly that much.
+        let d = control_in(
+            &mut dev,
+            &mut bus,
+            classes,
+            setup(0xc1, 0, 128, 0, 128),
+        );
+        assert_eq!(d.len(), 128);
+        assert_eq!(bus.written.len(), 2);
+    }
+
+    #[test]
+    fn unknown_request_stalls() {
+        let mut bus = MockBus::default();
+        let mut dev = Device::new(&INFO);
+        dev.reset(&mut bus, &mut []);
+        dev.setup(&mut bus, &mut [], &setup(0x80, 0x42, 0, 0, 8));
+        assert_eq!(bus.stalled, [0, EP_IN]);
+    }
+
+    #[test]
+    fn cdc_line_coding() {
+        let mut bus = MockBus::default();
+        let mut cdc = cdc_acm::CdcAcm::new(1, 2);
+        {
+            let classes: &mut [&mut dyn Class] = &mut [&mut cdc];
+            let mut dev = configured(&mut bus, classes);
+            assert_eq!(
+                bus.configured,
+                [
+                    (0x81, EndpointType::Interrupt, 16),
+                    (0x02, EndpointType::Bulk, 64),
+                    (0x82, EndpointType::Bulk, 64),
+                ]
+            );
+
+            // SET_LINE_CODING, with its data stage.
+            let coding = [0x00, 0xc2, 0x01, 0x00, 0, 0, 8];
+            bus.written.clear();
+            dev.setup(&mut bus, classes, &setup(0x21, 0x20, 0, 0, 7));
+            assert!(bus.written.is_empty());
+            bus.ep0_out.push_back(coding.to_vec());
+            dev.ep0_out(&mut bus, classes);
+            assert_eq!(bus.written, [(0, vec![])]);
+            dev.ep0_in_complete(&mut bus);
+
+            let d = control_in(
+                &mut dev,
+                &mut bus,
+                classes,
+                setup(0xa1, 0x21, 0, 0, 7),
+            );
+            assert_eq!(d, coding);
+
+            // SET_CONTROL_LINE_STATE, raising DTR, then a break. Both go to
+            // the communications interface, 0.
+            dev.setup(&mut bus, classes, &setup(0x21, 0x22, 1, 0, 0));
+            dev.ep0_in_complete(&mut bus);
+            dev.setup(&mut bus, classes, &setup(0x21, 0x23, 100, 0, 0));
+            dev.ep0_in_complete(&mut bus);
+            assert!(bus.stalled.is_empty());
+
+            // The data interface has no requests.
+            dev.setup(&mut bus, classes, &setup(0xa1, 0x21, 0, 1, 7));
+            assert_eq!(bus.stalled, [0, EP_IN]);
+        }
+        assert_eq!(cdc.line_coding().baud, 115_200);
+        assert!(cdc.dtr());
+        assert!(!cdc.rts());
+        assert_eq!(cdc.breaks(), 1);
+    }
+}
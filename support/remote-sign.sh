@@ -0,0 +1,25 @@
+#!/bin/sh
+# This Source Code Form is subject to the terms of the Mozilla Public
+# License, v. 2.0. If a copy of the MPL was not distributed with this
+# file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+# Sends a Hubris archive to a signing service, and replaces it with the
+# signed archive the service returns. This is a minimal client for use as
+# the signer in an app.toml:
+#
+#   [signing]
+#   signer.command = ["../../support/remote-sign.sh"]
+#
+# The service is expected to take the archive as the body of a POST to
+# $HUBRIS_SIGNING_URL, and to answer with the signed archive.
+
+set -eu
+
+: "${HUBRIS_SIGNING_URL:?set HUBRIS_SIGNING_URL to the signing service}"
+
+archive="$1"
+curl --fail --silent --show-error \
+    --data-binary @"$archive" \
+    --output "$archive.signed" \
+    "$HUBRIS_SIGNING_URL"
+mv "$archive.signed" "$archive"
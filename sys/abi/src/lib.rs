@@ -479,6 +479,26 @@ pub struct TaskDumpRegion {
     pub size: u32,
 }
 
+/// Number of distinct interrupts the kernel keeps spurious counts for. Any
+/// beyond that are counted together.
+pub const SPURIOUS_IRQ_SLOTS: usize = 8;
+
+/// Number of times an interrupt with a slot may fire spuriously before the
+/// kernel takes it for a storm and disables it. Interrupts without a slot are
+/// disabled the first time they fire.
+pub const SPURIOUS_IRQ_LIMIT: u32 = 100;
+
+/// Counts of interrupts that fired with no task to handle them, as returned
+/// by the `read_spurious_irqs` kipc.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SpuriousIrqCounts {
+    /// Interrupt numbers, in the order they were first seen, with how many
+    /// times each fired. Unused slots have a count of 0.
+    pub irqs: [(u32, u32); SPURIOUS_IRQ_SLOTS],
+    /// How many times other interrupts fired, once every slot was taken
+    pub other: u32,
+}
+
 /// Representation of kipc numbers
 pub enum Kipcnum {
     ReadTaskStatus = 1,
@@ -489,6 +509,7 @@ pub enum Kipcnum {
     ReadCaboosePos = 6,
     GetTaskDumpRegion = 7,
     ReadTaskDumpRegion = 8,
+    ReadSpuriousIrqs = 9,
 }
 
 impl core::convert::TryFrom<u16> for Kipcnum {
@@ -504,6 +525,7 @@ impl core::convert::TryFrom<u16> for Kipcnum {
             6 => Ok(Self::ReadCaboosePos),
             7 => Ok(Self::GetTaskDumpRegion),
             8 => Ok(Self::ReadTaskDumpRegion),
+            9 => Ok(Self::ReadSpuriousIrqs),
             _ => Err(()),
         }
     }
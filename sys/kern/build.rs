@@ -9,7 +9,7 @@ use std::io::Write;
 use anyhow::{bail, Context, Result};
 use build_kconfig::{
     InterruptConfig, KernelConfig, OwnedAddress, RegionAttributes,
-    RegionConfig, SpecialRole,
+    RegionConfig, SpecialRole, SpuriousIrqPolicy,
 };
 use indexmap::IndexMap;
 use proc_macro2::TokenStream;
@@ -27,6 +27,7 @@ struct Generated {
     tasks: Vec<TokenStream>,
     regions: Vec<TokenStream>,
     irq_code: TokenStream,
+    spurious_irq_policy: TokenStream,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -217,9 +218,11 @@ fn process_config() -> Result<Generated> {
         let map1 = if let Ok(task_irq_map) =
             phash_gen::OwnedPerfectHashMap::build(task_irq_map.clone())
         {
+            let doc = fmt_density(task_irq_map.density());
             let map_literal =
                 fmt_perfect_hash_map(&task_irq_map, fmt_opt_task_irq);
             quote::quote! {
+                #[doc = #doc]
                 pub const HUBRIS_TASK_IRQ_LOOKUP:
                     phash::PerfectHashMap<
                     '_,
@@ -228,15 +231,19 @@ fn process_config() -> Result<Generated> {
                     > = #map_literal;
             }
         } else {
-            // Single-level perfect hash failed, make it work with a nested map.
+            // Single-level perfect hash failed, which is likely with many
+            // IRQs; fall back to a two-level map, which costs one more
+            // multiply and remainder per lookup.
             let task_irq_map =
-                phash_gen::OwnedNestedPerfectHashMap::build(task_irq_map)
+                phash_gen::OwnedDisplacedPerfectHashMap::build(task_irq_map)
                     .context("building task-to-IRQ perfect hash")?;
+            let doc = fmt_density(task_irq_map.density());
             let task_irq_literal =
-                fmt_nested_perfect_hash_map(&task_irq_map, fmt_opt_task_irq);
+                fmt_displaced_perfect_hash_map(&task_irq_map, fmt_opt_task_irq);
             quote::quote! {
+                #[doc = #doc]
                 pub const HUBRIS_TASK_IRQ_LOOKUP:
-                    phash::NestedPerfectHashMap<
+                    phash::DisplacedPerfectHashMap<
                     abi::InterruptOwner,
                     &'static [abi::InterruptNum],
                     > = #task_irq_literal;
@@ -247,9 +254,11 @@ fn process_config() -> Result<Generated> {
         let map2 = if let Ok(irq_task_map) =
             phash_gen::OwnedPerfectHashMap::build(irq_task_map.clone())
         {
+            let doc = fmt_density(irq_task_map.density());
             let map_literal =
                 fmt_perfect_hash_map(&irq_task_map, fmt_opt_irq_task);
             quote::quote! {
+                #[doc = #doc]
                 pub const HUBRIS_IRQ_TASK_LOOKUP:
                     phash::PerfectHashMap<
                     '_,
@@ -259,13 +268,15 @@ fn process_config() -> Result<Generated> {
             }
         } else {
             let irq_task_map =
-                phash_gen::OwnedNestedPerfectHashMap::build(irq_task_map)
+                phash_gen::OwnedDisplacedPerfectHashMap::build(irq_task_map)
                     .context("building IRQ-to-task perfect hash")?;
+            let doc = fmt_density(irq_task_map.density());
             let map_literal =
-                fmt_nested_perfect_hash_map(&irq_task_map, fmt_opt_irq_task);
+                fmt_displaced_perfect_hash_map(&irq_task_map, fmt_opt_irq_task);
             quote::quote! {
+                #[doc = #doc]
                 pub const HUBRIS_IRQ_TASK_LOOKUP:
-                    phash::NestedPerfectHashMap<
+                    phash::DisplacedPerfectHashMap<
                     abi::InterruptNum,
                     abi::InterruptOwner,
                     > = #map_literal;
@@ -280,10 +291,17 @@ fn process_config() -> Result<Generated> {
         panic!("Don't know the target {target}");
     };
 
+    let spurious_irq_policy = match kconfig.spurious_irqs {
+        SpuriousIrqPolicy::Panic => quote::quote! { Panic },
+        SpuriousIrqPolicy::Count => quote::quote! { Count },
+        SpuriousIrqPolicy::Notify(n) => quote::quote! { Notify(#n) },
+    };
+
     Ok(Generated {
         tasks: task_descs,
         regions: region_descs,
         irq_code,
+        spurious_irq_policy,
     })
 }
 
@@ -409,6 +427,16 @@ fn generate_statics(gen: &Generated) -> Result<()> {
 
     writeln!(file, "{}", gen.irq_code)?;
 
+    let policy = &gen.spurious_irq_policy;
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub const HUBRIS_SPURIOUS_IRQ_POLICY: crate::spurious::Policy =
+                crate::spurious::Policy::#policy;
+        },
+    )?;
+
     drop(file);
     call_rustfmt::rustfmt(kconfig_path)?;
 
@@ -481,20 +509,23 @@ fn fmt_perfect_hash_map<K, V>(
     }
 }
 
-fn fmt_nested_perfect_hash_map<K, V>(
-    map: &phash_gen::OwnedNestedPerfectHashMap<K, V>,
+/// Describes how full a generated table is, for its doc comment.
+fn fmt_density(density: f64) -> String {
+    format!(
+        " {:.0}% of the slots in this table are in use.",
+        density * 100.0
+    )
+}
+
+fn fmt_displaced_perfect_hash_map<K, V>(
+    map: &phash_gen::OwnedDisplacedPerfectHashMap<K, V>,
     element: impl Fn(Option<&(K, V)>) -> TokenStream,
 ) -> TokenStream {
-    let values = map.values.iter().map(|v| {
-        let inner = v.iter().map(|o| element(o.as_ref()));
-        quote::quote! {
-            &[#(#inner,)*]
-        }
-    });
+    let values = map.values.iter().map(|o| element(o.as_ref()));
     let m = map.m;
     let g = &map.g;
     quote::quote! {
-        phash::NestedPerfectHashMap {
+        phash::DisplacedPerfectHashMap {
             m: #m,
             g: &[#(#g,)*],
             values: &[#(#values,)*],
@@ -1118,17 +1118,20 @@ pub unsafe extern "C" fn DefaultHandler() {
             // Hardware interrupt
             let irq_num = exception_num - 16;
             let owner = crate::startup::HUBRIS_IRQ_TASK_LOOKUP
-                .get(abi::InterruptNum(irq_num))
-                .unwrap_or_else(|| panic!("unhandled IRQ {irq_num}"));
+                .get(abi::InterruptNum(irq_num));
 
-            let switch = with_task_table(|tasks| {
-                disable_irq(irq_num);
+            let switch = if let Some(owner) = owner {
+                with_task_table(|tasks| {
+                    disable_irq(irq_num);
 
-                // Now, post the notification and return the
-                // scheduling hint.
-                let n = task::NotificationSet(owner.notification);
-                tasks[owner.task as usize].post(n)
-            });
+                    // Now, post the notification and return the
+                    // scheduling hint.
+                    let n = task::NotificationSet(owner.notification);
+                    tasks[owner.task as usize].post(n)
+                })
+            } else {
+                crate::spurious::handle(irq_num)
+            };
             if switch {
                 pend_context_switch_from_isr()
             }
@@ -34,6 +34,9 @@ pub fn handle_kernel_message(
         Ok(Kipcnum::ReadCaboosePos) => {
             read_caboose_pos(tasks, caller, args.response?)
         }
+        Ok(Kipcnum::ReadSpuriousIrqs) => {
+            read_spurious_irqs(tasks, caller, args.response?)
+        }
         #[cfg(feature = "dump")]
         Ok(Kipcnum::GetTaskDumpRegion) => {
             get_task_dump_region(tasks, caller, args.message?, args.response?)
@@ -274,6 +277,26 @@ fn read_caboose_pos(
     Ok(NextTask::Same)
 }
 
+fn read_spurious_irqs(
+    tasks: &mut [Task],
+    caller: usize,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+
+    let counts = crate::spurious::counts();
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &counts)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
 #[cfg(feature = "dump")]
 fn get_task_dump_region(
     tasks: &mut [Task],
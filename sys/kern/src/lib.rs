@@ -43,6 +43,7 @@ pub mod fail;
 pub mod header;
 pub mod kipc;
 pub mod profiling;
+pub mod spurious;
 pub mod startup;
 pub mod syscalls;
 pub mod task;
@@ -0,0 +1,111 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Handling of spurious interrupts: those that fire with no task to handle
+//! them.
+//!
+//! What we do with them is up to the app (`spurious-irqs` in the `[kernel]`
+//! section of its `app.toml`). By default we panic, naming the interrupt.
+//! Otherwise we count the interrupt, so that the supervisor can find out which
+//! interrupts they were, and how often they fired, with the
+//! `read_spurious_irqs` kipc; the supervisor may also ask to be notified.
+//!
+//! We can't tell edge-triggered interrupts from level-triggered ones, and
+//! nobody will clear the source of a level-triggered one, so it will fire
+//! again as soon as we return. We therefore leave each interrupt enabled
+//! until it has fired `SPURIOUS_IRQ_LIMIT` times, at which point we take it
+//! for a storm and disable it (no task will ever re-enable it). An interrupt
+//! without a slot of its own can't be counted, so we disable it straight
+//! away.
+//!
+//! The counters are only written from interrupt handlers and only read from
+//! the kernel's own exceptions, which all run at the same priority and so
+//! can't preempt one another. Plain loads and stores are therefore enough,
+//! which is as well, since ARMv6-M has nothing better.
+
+use abi::{SpuriousIrqCounts, SPURIOUS_IRQ_LIMIT, SPURIOUS_IRQ_SLOTS};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::startup::{with_task_table, HUBRIS_SPURIOUS_IRQ_POLICY};
+use crate::task::NotificationSet;
+
+/// What to do with a spurious interrupt.
+// Each build constructs only the variant its app asked for.
+#[allow(dead_code)]
+pub enum Policy {
+    Panic,
+    Count,
+    /// Count the interrupt, and post these notification bits to the
+    /// supervisor.
+    Notify(u32),
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU32 = AtomicU32::new(0);
+
+/// Interrupt number in each slot, plus one, so that 0 marks a free slot.
+static IRQS: [AtomicU32; SPURIOUS_IRQ_SLOTS] = [ZERO; SPURIOUS_IRQ_SLOTS];
+/// Times the interrupt in each slot has fired.
+static COUNTS: [AtomicU32; SPURIOUS_IRQ_SLOTS] = [ZERO; SPURIOUS_IRQ_SLOTS];
+/// Times any interrupt without a slot has fired.
+static OTHER: AtomicU32 = ZERO;
+
+/// Adds one to `count`, returning the new value.
+fn increment(count: &AtomicU32) -> u32 {
+    let n = count.load(Ordering::Relaxed).saturating_add(1);
+    count.store(n, Ordering::Relaxed);
+    n
+}
+
+/// Counts `irq`, returning `true` if it should now be disabled.
+fn record(irq: u32) -> bool {
+    for (slot, count) in IRQS.iter().zip(&COUNTS) {
+        let n = match slot.load(Ordering::Relaxed) {
+            0 => {
+                slot.store(irq + 1, Ordering::Relaxed);
+                count.store(1, Ordering::Relaxed);
+                1
+            }
+            n if n == irq + 1 => increment(count),
+            _ => continue,
+        };
+        return n >= SPURIOUS_IRQ_LIMIT;
+    }
+    increment(&OTHER);
+    true
+}
+
+/// Deals with spurious interrupt `irq` according to the app's policy.
+/// Returns `true` if a context switch may be needed, as `Task::post` does.
+pub fn handle(irq: u32) -> bool {
+    let notification = match HUBRIS_SPURIOUS_IRQ_POLICY {
+        Policy::Panic => panic!("unhandled IRQ {irq}"),
+        Policy::Count => None,
+        Policy::Notify(n) => Some(n),
+    };
+
+    if record(irq) {
+        crate::arch::disable_irq(irq);
+    }
+
+    match notification {
+        Some(n) => with_task_table(|tasks| tasks[0].post(NotificationSet(n))),
+        None => false,
+    }
+}
+
+/// Reads out the counters.
+pub fn counts() -> SpuriousIrqCounts {
+    let mut out = SpuriousIrqCounts {
+        other: OTHER.load(Ordering::Relaxed),
+        ..Default::default()
+    };
+    for ((slot, count), o) in IRQS.iter().zip(&COUNTS).zip(&mut out.irqs) {
+        let irq = slot.load(Ordering::Relaxed);
+        if irq != 0 {
+            *o = (irq - 1, count.load(Ordering::Relaxed));
+        }
+    }
+    out
+}
@@ -6,13 +6,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     build_util::expose_m_profile();
 
     // Do an architecture check.
-    if build_util::target_os() != "none" {
+    if build_util::target_os() != "none" && !build_util::is_sim() {
         eprintln!("***********************************************");
         eprintln!("Hi!");
         eprintln!("You appear to be building this natively,");
         eprintln!("i.e. for your workstation. This won't work.");
         eprintln!("Please specify --target=some-triple, e.g.");
         eprintln!("--target=thumbv7em-none-eabihf");
+        eprintln!("(or use `cargo xtask sim` to run tests");
+        eprintln!("on the host.)");
         eprintln!("***********************************************");
         panic!()
     }
@@ -97,6 +97,22 @@ pub fn system_restart() -> ! {
     panic!();
 }
 
+/// Reads the kernel's counts of interrupts that fired with no task to handle
+/// them. These are only kept if the app's `spurious-irqs` policy isn't
+/// `panic`; only the supervisor may ask.
+pub fn read_spurious_irqs() -> abi::SpuriousIrqCounts {
+    let mut response = [0; core::mem::size_of::<abi::SpuriousIrqCounts>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadSpuriousIrqs as u16,
+        &[],
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
 pub fn read_image_id() -> u64 {
     let mut response = [0; core::mem::size_of::<u64>()];
     let (rc, len) = sys_send(
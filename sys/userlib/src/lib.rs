@@ -23,11 +23,20 @@
 //! all registers.
 //!
 //! See: https://github.com/rust-lang/rust/issues/73450#issuecomment-650463347
+//!
+//! # Host simulation
+//!
+//! Built for the host with `--cfg hubris_sim`, the stubs are replaced by calls
+//! into the simulated kernel in the [`sim`] module, and tasks run as threads
+//! of an ordinary host program. `cargo xtask sim` builds tasks this way.
 
 #![no_std]
 #![feature(asm_const)]
 #![feature(naked_functions)]
 
+#[cfg(hubris_sim)]
+extern crate std;
+
 #[macro_use]
 pub mod macros;
 
@@ -36,28 +45,43 @@ pub use num_derive::{FromPrimitive, ToPrimitive};
 pub use num_traits::{FromPrimitive, ToPrimitive};
 pub use unwrap_lite::UnwrapLite;
 
+#[cfg(not(hubris_sim))]
 use core::arch;
 use core::marker::PhantomData;
 
 pub mod hl;
 pub mod kipc;
+#[cfg(hubris_sim)]
+pub mod sim;
 pub mod task_slot;
 pub mod units;
 
+#[cfg(not(hubris_sim))]
+use abi::ULease as LeaseRep;
+#[cfg(hubris_sim)]
+use sim::LeaseRep;
+#[cfg(hubris_sim)]
+use sim::{
+    sys_borrow_info_stub, sys_borrow_read_stub, sys_borrow_write_stub,
+    sys_get_timer_stub, sys_irq_control_stub, sys_panic_stub, sys_post_stub,
+    sys_recv_stub, sys_refresh_task_id_stub, sys_reply_fault_stub,
+    sys_reply_stub, sys_send_stub, sys_set_timer_stub,
+};
+
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct Lease<'a> {
-    _kern_rep: abi::ULease,
+    _kern_rep: LeaseRep,
     _marker: PhantomData<&'a mut ()>,
 }
 
 impl<'a> Lease<'a> {
     pub fn read_only(x: &'a [u8]) -> Self {
         Self {
-            _kern_rep: abi::ULease {
+            _kern_rep: LeaseRep {
                 attributes: abi::LeaseAttributes::READ,
-                base_address: x.as_ptr() as u32,
-                length: x.len() as u32,
+                base_address: x.as_ptr() as _,
+                length: x.len() as _,
             },
             _marker: PhantomData,
         }
@@ -65,10 +89,10 @@ impl<'a> Lease<'a> {
 
     pub fn read_write(x: &'a mut [u8]) -> Self {
         Self {
-            _kern_rep: abi::ULease {
+            _kern_rep: LeaseRep {
                 attributes: LeaseAttributes::READ | LeaseAttributes::WRITE,
-                base_address: x.as_ptr() as u32,
-                length: x.len() as u32,
+                base_address: x.as_ptr() as _,
+                length: x.len() as _,
             },
             _marker: PhantomData,
         }
@@ -76,10 +100,10 @@ impl<'a> Lease<'a> {
 
     pub fn write_only(x: &'a mut [u8]) -> Self {
         Self {
-            _kern_rep: abi::ULease {
+            _kern_rep: LeaseRep {
                 attributes: LeaseAttributes::WRITE,
-                base_address: x.as_ptr() as u32,
-                length: x.len() as u32,
+                base_address: x.as_ptr() as _,
+                length: x.len() as _,
             },
             _marker: PhantomData,
         }
@@ -150,6 +174,7 @@ struct SendArgs<'a> {
 /// Core implementation of the SEND syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_send_stub(_args: &mut SendArgs<'_>) -> RcLen {
     cfg_if::cfg_if! {
@@ -327,6 +352,7 @@ pub struct RecvMessage {
 /// Core implementation of the RECV syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 #[must_use]
 unsafe extern "C" fn sys_recv_stub(
@@ -444,6 +470,7 @@ pub fn sys_reply(peer: TaskId, code: u32, message: &[u8]) {
 /// Core implementation of the REPLY syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_reply_stub(
     _peer: u32,
@@ -543,6 +570,7 @@ pub fn sys_set_timer(deadline: Option<u64>, notifications: u32) {
 /// Core implementation of the SET_TIMER syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_set_timer_stub(
     _set_timer: u32,
@@ -631,6 +659,7 @@ pub fn sys_borrow_read(
 /// Core implementation of the BORROW_READ syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_borrow_read_stub(_args: *mut BorrowReadArgs) -> RcLen {
     cfg_if::cfg_if! {
@@ -726,6 +755,7 @@ pub fn sys_borrow_write(
 /// Core implementation of the BORROW_WRITE syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_borrow_write_stub(
     _args: *mut BorrowWriteArgs,
@@ -843,6 +873,7 @@ pub struct BorrowInfo {
 /// Core implementation of the BORROW_INFO syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_borrow_info_stub(
     _lender: u32,
@@ -919,6 +950,7 @@ pub fn sys_irq_control(mask: u32, enable: bool) {
 /// Core implementation of the IRQ_CONTROL syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_irq_control_stub(_mask: u32, _enable: u32) {
     cfg_if::cfg_if! {
@@ -986,6 +1018,7 @@ pub fn sys_panic(msg: &[u8]) -> ! {
 /// Core implementation of the PANIC syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_panic_stub(_msg: *const u8, _len: usize) -> ! {
     cfg_if::cfg_if! {
@@ -1096,6 +1129,7 @@ struct RawTimerState {
 /// Core implementation of the GET_TIMER syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_get_timer_stub(_out: *mut RawTimerState) {
     cfg_if::cfg_if! {
@@ -1161,6 +1195,7 @@ unsafe extern "C" fn sys_get_timer_stub(_out: *mut RawTimerState) {
 /// This is the entry point for the task, invoked by the kernel. Its job is to
 /// set up our memory before jumping to user-defined `main`.
 #[doc(hidden)]
+#[cfg(not(hubris_sim))]
 #[no_mangle]
 #[link_section = ".text.start"]
 #[naked]
@@ -1293,7 +1328,7 @@ pub unsafe extern "C" fn _start() -> ! {
 /// task, to ensure that memory is available for the panic message, even if the
 /// resources have been trimmed aggressively using `xtask sizes` and `humility
 /// stackmargin`.
-#[cfg(feature = "panic-messages")]
+#[cfg(all(feature = "panic-messages", not(hubris_sim)))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
     // Implementation Note
@@ -1440,7 +1475,7 @@ fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
 /// Panic handler for tasks without the `panic-messages` feature enabled. This
 /// kills the task with a fixed message, `"PANIC"`. While this is less helpful
 /// than a proper panic message, the stack trace can still be informative.
-#[cfg(not(feature = "panic-messages"))]
+#[cfg(not(any(feature = "panic-messages", hubris_sim)))]
 #[panic_handler]
 fn panic(_: &core::panic::PanicInfo<'_>) -> ! {
     sys_panic(b"PANIC")
@@ -1455,6 +1490,7 @@ pub fn sys_refresh_task_id(task_id: TaskId) -> TaskId {
 /// Core implementation of the REFRESH_TASK_ID syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_refresh_task_id_stub(_tid: u32) -> u32 {
     cfg_if::cfg_if! {
@@ -1524,6 +1560,7 @@ pub fn sys_post(task_id: TaskId, bits: u32) -> u32 {
 /// Core implementation of the POST syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_post_stub(_tid: u32, _mask: u32) -> u32 {
     cfg_if::cfg_if! {
@@ -1594,6 +1631,7 @@ pub fn sys_reply_fault(task_id: TaskId, reason: ReplyFaultReason) {
 /// Core implementation of the REPLY_FAULT syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
+#[cfg(not(hubris_sim))]
 #[naked]
 unsafe extern "C" fn sys_reply_fault_stub(_tid: u32, _reason: u32) {
     cfg_if::cfg_if! {
@@ -6,7 +6,17 @@ pub use bstringify;
 pub use paste;
 
 cfg_if::cfg_if! {
-    if #[cfg(feature = "log-itm")] {
+    if #[cfg(hubris_sim)] {
+        #[macro_export]
+        macro_rules! sys_log {
+            ($s:expr) => {
+                $crate::sim::log(format_args!($s))
+            };
+            ($s:expr, $($tt:tt)*) => {
+                $crate::sim::log(format_args!($s, $($tt)*))
+            };
+        }
+    } else if #[cfg(feature = "log-itm")] {
         #[macro_export]
         macro_rules! sys_log {
             ($s:expr) => {
@@ -67,6 +77,16 @@ cfg_if::cfg_if! {
     }
 }
 
+#[cfg(hubris_sim)]
+#[macro_export]
+macro_rules! task_slot {
+    ($var:ident, $task_name:ident) => {
+        static $var: $crate::task_slot::TaskSlot =
+            $crate::task_slot::TaskSlot::named(stringify!($task_name));
+    };
+}
+
+#[cfg(not(hubris_sim))]
 #[macro_export]
 macro_rules! task_slot {
     ($var:ident, $task_name:ident) => {
@@ -0,0 +1,1021 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A simulated kernel, for running tasks on the host.
+//!
+//! With `--cfg hubris_sim`, each task runs on a thread of its own, and the
+//! syscall stubs call into a [`Sim`] instead of trapping into the kernel.
+//! SEND, RECV, REPLY, leases, notifications and timers behave as they do on
+//! hardware, so a test can drive a server's real message handling, with mock
+//! tasks (such as the `drv-mock-i2c` and `drv-mock-gpio` servers) standing in
+//! for the drivers it calls:
+//!
+//! ```ignore
+//! Sim::new()
+//!     .task("i2c_driver", 1, move || drv_mock_i2c::serve(devices.clone()))
+//!     .run("test", 2, || {
+//!         // ...calls through the i2c API land in the mock...
+//!     });
+//! ```
+//!
+//! Timer ticks are milliseconds since the `Sim` started. There is no
+//! supervisor: a task that panics is restarted at once with its generation
+//! bumped, and anyone waiting on it is told it died. Sends to the kernel
+//! (`kipc`) aren't simulated, and fault the sender.
+//!
+//! Hardware interrupts can be simulated with [`irq`], which posts only the
+//! notifications the task has enabled with `sys_irq_control` -- disabling them
+//! again, just as the kernel masks an interrupt once it fires.
+
+use core::fmt;
+use core::mem;
+use core::panic::AssertUnwindSafe;
+use core::ptr;
+use core::time::Duration;
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::string::String;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+use std::vec::Vec;
+
+use abi::{
+    FaultInfo, Generation, LeaseAttributes, ReplyFaultReason, TaskId,
+    UsageError,
+};
+
+use crate::{
+    BorrowReadArgs, BorrowWriteArgs, Lease, RawBorrowInfo, RawRecvMessage,
+    RawTimerState, RcLen, SendArgs,
+};
+
+/// How a [`Lease`] is represented on the host, where addresses don't fit in
+/// an `abi::ULease`.
+#[derive(Debug)]
+pub(crate) struct LeaseRep {
+    pub attributes: LeaseAttributes,
+    pub base_address: usize,
+    pub length: usize,
+}
+
+/// A set of tasks to be simulated.
+pub struct Sim {
+    tasks: Vec<Spec>,
+}
+
+struct Spec {
+    name: &'static str,
+    priority: u8,
+    /// The task's `main`, or `None` for the task run by [`Sim::run`]
+    entry: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sim {
+    pub fn new() -> Self {
+        Self { tasks: Vec::new() }
+    }
+
+    /// Adds a task, which will run `entry` on its own thread once the
+    /// simulation starts. Tasks are numbered in the order they're added, and
+    /// (as on hardware) a lower `priority` is more important.
+    pub fn task(
+        mut self,
+        name: &'static str,
+        priority: u8,
+        entry: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        self.tasks.push(Spec {
+            name,
+            priority,
+            entry: Some(Arc::new(entry)),
+        });
+        self
+    }
+
+    /// Starts the tasks, and runs `test` on this thread as one more task,
+    /// named `name`. The other tasks are stopped when `test` returns (or
+    /// panics).
+    pub fn run<R>(
+        mut self,
+        name: &'static str,
+        priority: u8,
+        test: impl FnOnce() -> R,
+    ) -> R {
+        let me = self.tasks.len();
+        self.tasks.push(Spec {
+            name,
+            priority,
+            entry: None,
+        });
+
+        let shared = Arc::new(Shared {
+            kernel: Mutex::new(Kernel {
+                start: Instant::now(),
+                tasks: self.tasks.iter().map(Task::new).collect(),
+                shutdown: false,
+            }),
+            wake: Condvar::new(),
+        });
+
+        for (index, spec) in self.tasks.iter().enumerate() {
+            let Some(entry) = spec.entry.clone() else {
+                continue;
+            };
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(spec.name.into())
+                .spawn(move || run_task(shared, index, entry))
+                .expect("could not spawn task thread");
+        }
+
+        let _stop = Stop(shared.clone());
+        CURRENT.with(|c| *c.borrow_mut() = Some((shared, me)));
+        test()
+    }
+}
+
+/// Stops a simulation when dropped.
+struct Stop(Arc<Shared>);
+
+impl Drop for Stop {
+    fn drop(&mut self) {
+        self.0.lock().shutdown = true;
+        self.0.wake.notify_all();
+        CURRENT.with(|c| *c.borrow_mut() = None);
+    }
+}
+
+/// Payload of the unwind that stops a task's thread at shutdown.
+struct Shutdown;
+
+fn run_task(
+    shared: Arc<Shared>,
+    me: usize,
+    entry: Arc<dyn Fn() + Send + Sync>,
+) {
+    CURRENT.with(|c| *c.borrow_mut() = Some((shared.clone(), me)));
+    loop {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| entry()));
+        let mut k = shared.lock();
+        if k.shutdown || matches!(&result, Err(e) if e.is::<Shutdown>()) {
+            return;
+        }
+        // Tasks don't return, so this is a fault either way.
+        k.restart(me);
+        drop(k);
+        shared.wake.notify_all();
+    }
+}
+
+std::thread_local! {
+    /// The simulation this thread is a task of, and its index
+    static CURRENT: RefCell<Option<(Arc<Shared>, usize)>> =
+        RefCell::new(None);
+}
+
+fn current() -> (Arc<Shared>, usize) {
+    CURRENT
+        .with(|c| c.borrow().clone())
+        .expect("syscall from a thread that isn't a simulated task")
+}
+
+/// Returns the ID of the task named `name`, with its current generation.
+pub fn task_id(name: &str) -> TaskId {
+    let (shared, _) = current();
+    let k = shared.lock();
+    match k.tasks.iter().position(|t| t.name == name) {
+        Some(index) => k.id(index),
+        None => {
+            drop(k);
+            panic!("no task named {name} in the simulation");
+        }
+    }
+}
+
+/// Returns the number of times the task named `name` has been restarted.
+pub fn restarts(name: &str) -> u32 {
+    let (shared, _) = current();
+    let k = shared.lock();
+    k.tasks
+        .iter()
+        .find(|t| t.name == name)
+        .map_or(0, |t| t.restarts)
+}
+
+/// Simulates interrupts for the task named `name`: posts whichever of `bits`
+/// it has enabled, and disables them.
+pub fn irq(name: &str, bits: u32) {
+    let (shared, _) = current();
+    let mut k = shared.lock();
+    if let Some(task) = k.tasks.iter_mut().find(|t| t.name == name) {
+        let fired = task.irqs_enabled & bits;
+        task.irqs_enabled &= !fired;
+        task.notifications |= fired;
+    }
+    drop(k);
+    shared.wake.notify_all();
+}
+
+/// Prints a `sys_log!` message, prefixed with the task's name.
+pub fn log(args: fmt::Arguments<'_>) {
+    let name = CURRENT.with(|c| {
+        c.borrow()
+            .as_ref()
+            .map(|(shared, me)| shared.lock().tasks[*me].name)
+    });
+    std::eprintln!("[{}] {}", name.unwrap_or("?"), args);
+}
+
+/// Stands in for a task slot: rather than being patched after the build, it
+/// looks the task up by name in the running simulation.
+pub struct TaskSlot(&'static str);
+
+impl TaskSlot {
+    pub const fn named(name: &'static str) -> Self {
+        Self(name)
+    }
+
+    pub fn get_task_id(&self) -> TaskId {
+        task_id(self.0)
+    }
+
+    pub fn get_task_index(&self) -> u16 {
+        self.get_task_id().index() as u16
+    }
+}
+
+struct Shared {
+    kernel: Mutex<Kernel>,
+    /// Signalled whenever any task's state changes
+    wake: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Kernel> {
+        // A task panicking can't leave the kernel half-updated, since we never
+        // panic with it locked.
+        self.kernel.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Blocks task `me` until `ready` returns something, firing its timer
+    /// when that comes due.
+    fn block<'a, T>(
+        &self,
+        mut k: MutexGuard<'a, Kernel>,
+        me: usize,
+        mut ready: impl FnMut(&mut Kernel) -> Option<T>,
+    ) -> (MutexGuard<'a, Kernel>, T) {
+        loop {
+            if k.shutdown {
+                k.tasks[me].state = State::Runnable;
+                drop(k);
+                std::panic::resume_unwind(Box::new(Shutdown));
+            }
+            if let State::Faulted(fault) = k.tasks[me].state {
+                k.tasks[me].state = State::Runnable;
+                drop(k);
+                panic!("faulted: {fault:?}");
+            }
+
+            let now = k.now();
+            k.fire_timer(me, now);
+            if let Some(t) = ready(&mut k) {
+                return (k, t);
+            }
+
+            k = match k.tasks[me].deadline {
+                Some(dl) => {
+                    let wait = Duration::from_millis(dl.saturating_sub(now));
+                    self.wake
+                        .wait_timeout(k, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self.wake.wait(k).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+struct Kernel {
+    start: Instant,
+    tasks: Vec<Task>,
+    shutdown: bool,
+}
+
+struct Task {
+    name: &'static str,
+    priority: u8,
+    generation: Generation,
+    state: State,
+    notifications: u32,
+    deadline: Option<u64>,
+    /// Notifications to post when `deadline` passes
+    timer_bits: u32,
+    irqs_enabled: u32,
+    restarts: u32,
+}
+
+impl Task {
+    fn new(spec: &Spec) -> Self {
+        Self {
+            name: spec.name,
+            priority: spec.priority,
+            generation: Generation::default(),
+            state: State::Runnable,
+            notifications: 0,
+            deadline: None,
+            timer_bits: 0,
+            irqs_enabled: 0,
+            restarts: 0,
+        }
+    }
+}
+
+enum State {
+    Runnable,
+    /// Blocked in SEND, waiting for the message to be received
+    Sending(Message),
+    /// Blocked in SEND, waiting for the reply
+    Replying(Message),
+    /// Done with SEND, and about to return this code and length
+    Replied(u32, usize),
+    /// Faulted by another task (or the kernel on its behalf), and about to
+    /// panic
+    Faulted(FaultInfo),
+}
+
+/// A message being sent, pointing into the sender's stack frame -- which
+/// stays put for as long as it's blocked in SEND.
+struct Message {
+    to: TaskId,
+    operation: u16,
+    message: *const u8,
+    message_len: usize,
+    response: *mut u8,
+    response_len: usize,
+    leases: *const Lease<'static>,
+    lease_count: usize,
+}
+
+// Safety: the pointers are only followed with the kernel locked, and while the
+// sender is blocked.
+unsafe impl Send for Message {}
+
+/// Why a syscall naming another task didn't go ahead
+enum Refusal {
+    /// The syscall returns this code
+    Code(u32),
+    /// The caller is faulted
+    Fault(UsageError),
+}
+
+impl Kernel {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    fn id(&self, index: usize) -> TaskId {
+        TaskId::for_index_and_gen(index, self.tasks[index].generation)
+    }
+
+    /// Checks `id` against the task table, just as the kernel does.
+    fn check(&self, id: TaskId) -> Result<usize, Refusal> {
+        let index = id.index();
+        match self.tasks.get(index) {
+            None => Err(Refusal::Fault(UsageError::TaskOutOfRange)),
+            Some(t) if t.generation != id.generation() => {
+                Err(Refusal::Code(abi::dead_response_code(t.generation)))
+            }
+            Some(_) => Ok(index),
+        }
+    }
+
+    fn fire_timer(&mut self, me: usize, now: u64) {
+        let task = &mut self.tasks[me];
+        if task.deadline.map_or(false, |dl| dl <= now) {
+            task.deadline = None;
+            task.notifications |= task.timer_bits;
+        }
+    }
+
+    /// Restarts task `me` after it panicked, telling everyone waiting on it
+    /// that it's dead.
+    fn restart(&mut self, me: usize) {
+        let task = &mut self.tasks[me];
+        task.generation = task.generation.next();
+        task.state = State::Runnable;
+        task.notifications = 0;
+        task.deadline = None;
+        task.timer_bits = 0;
+        task.irqs_enabled = 0;
+        task.restarts += 1;
+
+        let dead = abi::dead_response_code(task.generation);
+        for t in &mut self.tasks {
+            if let State::Sending(m) | State::Replying(m) = &t.state {
+                if m.to.index() == me {
+                    t.state = State::Replied(dead, 0);
+                }
+            }
+        }
+    }
+
+    /// Tries to receive a notification or message for task `me`.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for `buffer_len` bytes of writes.
+    unsafe fn recv(
+        &mut self,
+        me: usize,
+        buffer: *mut u8,
+        buffer_len: usize,
+        mask: u32,
+        from: Option<TaskId>,
+    ) -> Option<Result<RawRecvMessage, Refusal>> {
+        // As in the kernel, notifications come first.
+        if from.map_or(true, |id| id == TaskId::KERNEL) {
+            let bits = self.tasks[me].notifications & mask;
+            if bits != 0 {
+                self.tasks[me].notifications &= !bits;
+                return Some(Ok(RawRecvMessage {
+                    sender: u32::from(TaskId::KERNEL.0),
+                    operation: bits,
+                    message_len: 0,
+                    response_capacity: 0,
+                    lease_count: 0,
+                }));
+            }
+            if from.is_some() {
+                return None;
+            }
+        }
+
+        let me_id = self.id(me);
+        let waiting =
+            |t: &Task| matches!(&t.state, State::Sending(m) if m.to == me_id);
+        let sender = match from {
+            Some(id) => match self.check(id) {
+                Ok(i) => Some(i).filter(|&i| waiting(&self.tasks[i]))?,
+                Err(r) => return Some(Err(r)),
+            },
+            None => (0..self.tasks.len())
+                .filter(|&i| waiting(&self.tasks[i]))
+                .min_by_key(|&i| (self.tasks[i].priority, i))?,
+        };
+
+        let State::Sending(m) =
+            mem::replace(&mut self.tasks[sender].state, State::Runnable)
+        else {
+            unreachable!();
+        };
+        // Like the kernel, deliver as much of the message as fits.
+        let len = m.message_len.min(buffer_len);
+        ptr::copy_nonoverlapping(m.message, buffer, len);
+        let out = RawRecvMessage {
+            sender: u32::from(self.id(sender).0),
+            operation: u32::from(m.operation),
+            message_len: len,
+            response_capacity: m.response_len,
+            lease_count: m.lease_count,
+        };
+        self.tasks[sender].state = State::Replying(m);
+        Some(Ok(out))
+    }
+
+    /// Looks up lease `index` of `lender`, which must be waiting on a reply
+    /// from task `me`, returning its attributes, and address and length after
+    /// `offset`.
+    fn lease(
+        &self,
+        me: usize,
+        lender: u32,
+        index: usize,
+        offset: usize,
+    ) -> Result<(LeaseAttributes, usize, usize), Refusal> {
+        let lender = self.check(TaskId(lender as u16))?;
+        let me_id = self.id(me);
+        let m = match &self.tasks[lender].state {
+            State::Replying(m) if m.to == me_id => m,
+            _ => return Err(Refusal::Code(abi::DEFECT)),
+        };
+        if index >= m.lease_count {
+            return Err(Refusal::Fault(UsageError::LeaseOutOfRange));
+        }
+        // Safety: the lender is blocked, so its lease table is still there.
+        let lease = unsafe { &(*m.leases.add(index))._kern_rep };
+        if offset > lease.length {
+            return Err(Refusal::Fault(UsageError::OffsetOutOfRange));
+        }
+        Ok((
+            lease.attributes,
+            lease.base_address + offset,
+            lease.length - offset,
+        ))
+    }
+}
+
+/// Faults the calling task.
+fn fault(k: MutexGuard<'_, Kernel>, e: UsageError) -> ! {
+    drop(k);
+    panic!("faulted: {:?}", FaultInfo::SyscallUsage(e));
+}
+
+fn rc_len(rc: u32, len: usize) -> RcLen {
+    RcLen(u64::from(rc) | (len as u64) << 32)
+}
+
+pub(crate) unsafe fn sys_send_stub(args: &mut SendArgs<'_>) -> RcLen {
+    let (shared, me) = current();
+    let mut k = shared.lock();
+
+    let to = TaskId((args.packed_target_operation >> 16) as u16);
+    if to == TaskId::KERNEL {
+        drop(k);
+        panic!("messages to the kernel aren't simulated");
+    }
+    match k.check(to) {
+        Ok(i) if i == me => fault(k, UsageError::IllegalTask),
+        Ok(_) => (),
+        Err(Refusal::Code(rc)) => return rc_len(rc, 0),
+        Err(Refusal::Fault(e)) => fault(k, e),
+    }
+
+    k.tasks[me].state = State::Sending(Message {
+        to,
+        operation: args.packed_target_operation as u16,
+        message: args.outgoing_ptr,
+        message_len: args.outgoing_len,
+        response: args.incoming_ptr,
+        response_len: args.incoming_len,
+        leases: args.lease_ptr.cast(),
+        lease_count: args.lease_len,
+    });
+    shared.wake.notify_all();
+
+    let (mut k, (rc, len)) = shared.block(k, me, |k| match k.tasks[me].state {
+        State::Replied(rc, len) => Some((rc, len)),
+        _ => None,
+    });
+    k.tasks[me].state = State::Runnable;
+    rc_len(rc, len)
+}
+
+pub(crate) unsafe fn sys_recv_stub(
+    buffer_ptr: *mut u8,
+    buffer_len: usize,
+    notification_mask: u32,
+    specific_sender: u32,
+    out: *mut RawRecvMessage,
+) -> u32 {
+    let (shared, me) = current();
+    let k = shared.lock();
+
+    let from = (specific_sender & (1 << 31) != 0)
+        .then_some(TaskId(specific_sender as u16));
+    let (k, result) = shared.block(k, me, |k| {
+        k.recv(me, buffer_ptr, buffer_len, notification_mask, from)
+    });
+    match result {
+        Ok(msg) => {
+            out.write(msg);
+            0
+        }
+        Err(Refusal::Code(rc)) => rc,
+        Err(Refusal::Fault(e)) => fault(k, e),
+    }
+}
+
+pub(crate) unsafe fn sys_reply_stub(
+    peer: u32,
+    code: u32,
+    message_ptr: *const u8,
+    message_len: usize,
+) {
+    let (shared, me) = current();
+    let mut k = shared.lock();
+
+    // Like the kernel, we tolerate replies to tasks that have since died.
+    let peer = match k.check(TaskId(peer as u16)) {
+        Ok(i) => i,
+        Err(Refusal::Code(_)) => return,
+        Err(Refusal::Fault(e)) => fault(k, e),
+    };
+    let me_id = k.id(me);
+    let State::Replying(m) = &k.tasks[peer].state else {
+        return;
+    };
+    if m.to != me_id {
+        return;
+    }
+
+    let len = message_len.min(m.response_len);
+    ptr::copy_nonoverlapping(message_ptr, m.response, len);
+    k.tasks[peer].state = State::Replied(code, len);
+    drop(k);
+    shared.wake.notify_all();
+}
+
+pub(crate) unsafe fn sys_set_timer_stub(
+    set_timer: u32,
+    deadline_lo: u32,
+    deadline_hi: u32,
+    notification: u32,
+) {
+    let (shared, me) = current();
+    let mut k = shared.lock();
+
+    let now = k.now();
+    let task = &mut k.tasks[me];
+    let deadline = u64::from(deadline_lo) | u64::from(deadline_hi) << 32;
+    task.timer_bits = notification;
+    task.deadline = (set_timer != 0).then_some(deadline);
+    k.fire_timer(me, now);
+}
+
+pub(crate) unsafe fn sys_get_timer_stub(out: *mut RawTimerState) {
+    let (shared, me) = current();
+    let mut k = shared.lock();
+
+    let now = k.now();
+    k.fire_timer(me, now);
+    let task = &k.tasks[me];
+    let dl = task.deadline.unwrap_or(0);
+    out.write(RawTimerState {
+        now_lo: now as u32,
+        now_hi: (now >> 32) as u32,
+        set: task.deadline.is_some() as u32,
+        dl_lo: dl as u32,
+        dl_hi: (dl >> 32) as u32,
+        on_dl: task.timer_bits,
+    });
+}
+
+pub(crate) unsafe fn sys_borrow_read_stub(args: *mut BorrowReadArgs) -> RcLen {
+    let (shared, me) = current();
+    let k = shared.lock();
+
+    let args = &*args;
+    match k.lease(me, args.lender, args.index, args.offset) {
+        Ok((atts, base, len)) if atts.contains(LeaseAttributes::READ) => {
+            let n = len.min(args.dest_len);
+            ptr::copy_nonoverlapping(base as *const u8, args.dest, n);
+            rc_len(0, n)
+        }
+        Ok(_) => rc_len(abi::DEFECT, 0),
+        Err(Refusal::Code(rc)) => rc_len(rc, 0),
+        Err(Refusal::Fault(e)) => fault(k, e),
+    }
+}
+
+pub(crate) unsafe fn sys_borrow_write_stub(
+    args: *mut BorrowWriteArgs,
+) -> RcLen {
+    let (shared, me) = current();
+    let k = shared.lock();
+
+    let args = &*args;
+    match k.lease(me, args.lender, args.index, args.offset) {
+        Ok((atts, base, len)) if atts.contains(LeaseAttributes::WRITE) => {
+            let n = len.min(args.src_len);
+            ptr::copy_nonoverlapping(args.src, base as *mut u8, n);
+            rc_len(0, n)
+        }
+        Ok(_) => rc_len(abi::DEFECT, 0),
+        Err(Refusal::Code(rc)) => rc_len(rc, 0),
+        Err(Refusal::Fault(e)) => fault(k, e),
+    }
+}
+
+pub(crate) unsafe fn sys_borrow_info_stub(
+    lender: u32,
+    index: usize,
+    out: *mut RawBorrowInfo,
+) {
+    let (shared, me) = current();
+    let k = shared.lock();
+
+    let (rc, atts, length) = match k.lease(me, lender, index, 0) {
+        Ok((atts, _, len)) => (0, atts.bits(), len),
+        Err(Refusal::Code(rc)) => (rc, 0, 0),
+        Err(Refusal::Fault(e)) => fault(k, e),
+    };
+    out.write(RawBorrowInfo { rc, atts, length });
+}
+
+pub(crate) unsafe fn sys_irq_control_stub(mask: u32, enable: u32) {
+    let (shared, me) = current();
+    let mut k = shared.lock();
+
+    match enable {
+        0 => k.tasks[me].irqs_enabled &= !mask,
+        1 => k.tasks[me].irqs_enabled |= mask,
+        _ => fault(k, UsageError::NoIrq),
+    }
+}
+
+pub(crate) unsafe fn sys_panic_stub(msg: *const u8, len: usize) -> ! {
+    let msg = core::slice::from_raw_parts(msg, len);
+    panic!("{}", String::from_utf8_lossy(msg));
+}
+
+pub(crate) unsafe fn sys_refresh_task_id_stub(tid: u32) -> u32 {
+    let (shared, _) = current();
+    let k = shared.lock();
+
+    let index = TaskId(tid as u16).index();
+    if index >= k.tasks.len() {
+        fault(k, UsageError::TaskOutOfRange);
+    }
+    u32::from(k.id(index).0)
+}
+
+pub(crate) unsafe fn sys_post_stub(tid: u32, mask: u32) -> u32 {
+    let (shared, _) = current();
+    let mut k = shared.lock();
+
+    match k.check(TaskId(tid as u16)) {
+        Ok(i) => k.tasks[i].notifications |= mask,
+        Err(Refusal::Code(rc)) => return rc,
+        Err(Refusal::Fault(e)) => fault(k, e),
+    }
+    drop(k);
+    shared.wake.notify_all();
+    0
+}
+
+pub(crate) unsafe fn sys_reply_fault_stub(tid: u32, reason: u32) {
+    let (shared, me) = current();
+    let mut k = shared.lock();
+
+    let Ok(reason) = ReplyFaultReason::try_from(reason) else {
+        fault(k, UsageError::BadReplyFaultReason);
+    };
+    let peer = match k.check(TaskId(tid as u16)) {
+        Ok(i) => i,
+        Err(Refusal::Code(_)) => return,
+        Err(Refusal::Fault(e)) => fault(k, e),
+    };
+    let me_id = k.id(me);
+    if matches!(&k.tasks[peer].state, State::Replying(m) if m.to == me_id) {
+        k.tasks[peer].state =
+            State::Faulted(FaultInfo::FromServer(me_id, reason));
+        drop(k);
+        shared.wake.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sys_borrow_info, sys_borrow_read, sys_borrow_write, sys_get_timer,
+        sys_irq_control, sys_post, sys_recv_closed, sys_recv_open, sys_reply,
+        sys_reply_fault, sys_send, sys_set_timer,
+    };
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Replies to each message with its operation and its bytes reversed, or
+    /// panics if the operation is `DIE`.
+    fn echo() {
+        let mut buffer = [0; 8];
+        loop {
+            let msg = sys_recv_open(&mut buffer, 0);
+            if msg.operation == u32::from(DIE) {
+                panic!("asked to die");
+            }
+            let reply = &mut buffer[..msg.message_len];
+            reply.reverse();
+            sys_reply(msg.sender, msg.operation, reply);
+        }
+    }
+
+    const DIE: u16 = 0xdead;
+
+    /// Blocks forever, as a task with nothing more to do.
+    fn idle() {
+        loop {
+            let _ = sys_recv_closed(&mut [], 0, TaskId::KERNEL);
+        }
+    }
+
+    /// Waits for the task named `name` to block in SEND.
+    fn wait_for_send(name: &str) {
+        let (shared, _) = current();
+        loop {
+            let k = shared.lock();
+            let task = k.tasks.iter().find(|t| t.name == name).unwrap();
+            if matches!(task.state, State::Sending(_)) {
+                return;
+            }
+            drop(k);
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn send_and_reply() {
+        Sim::new().task("echo", 1, echo).run("test", 2, || {
+            let echo = task_id("echo");
+            let mut response = [0; 4];
+            assert_eq!(sys_send(echo, 7, b"abc", &mut response, &[]), (7, 3));
+            assert_eq!(&response[..3], b"cba");
+
+            // Replies too long for the sender are cut short, as are messages
+            // too long for the receiver.
+            let mut short = [0; 2];
+            assert_eq!(sys_send(echo, 1, b"abcd", &mut short, &[]), (1, 2));
+            assert_eq!(short, *b"dc");
+            let long = *b"0123456789";
+            assert_eq!(sys_send(echo, 1, &long, &mut response, &[]), (1, 4));
+            assert_eq!(response, *b"7654");
+        });
+    }
+
+    #[test]
+    fn send_to_dead_task() {
+        Sim::new().task("echo", 1, echo).run("test", 2, || {
+            let old = task_id("echo");
+            let (rc, len) = sys_send(old, DIE, &[], &mut [], &[]);
+            let new = task_id("echo");
+            assert_ne!(new, old);
+            assert_eq!(abi::extract_new_generation(rc), Some(new.generation()));
+            assert_eq!(len, 0);
+            assert_eq!(restarts("echo"), 1);
+
+            // The old ID stays dead, but the new one is answered.
+            assert_eq!(sys_send(old, 1, b"a", &mut [0], &[]).0, rc);
+            assert_eq!(sys_send(new, 1, b"a", &mut [0], &[]), (1, 1));
+        });
+    }
+
+    #[test]
+    fn recv_takes_most_important_sender() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let server = {
+            let order = order.clone();
+            move || {
+                // Wait to be told that both senders are waiting.
+                sys_recv_closed(&mut [], 1, TaskId::KERNEL).unwrap();
+                let mut buffer = [0; 4];
+                loop {
+                    let msg = sys_recv_open(&mut buffer, 0);
+                    order
+                        .lock()
+                        .unwrap()
+                        .push(buffer[..msg.message_len].to_vec());
+                    sys_reply(msg.sender, 0, &[]);
+                }
+            }
+        };
+        let sender = |message: &'static [u8]| {
+            move || {
+                sys_send(task_id("server"), 0, message, &mut [], &[]);
+                idle();
+            }
+        };
+
+        Sim::new()
+            .task("server", 1, server)
+            .task("low", 3, sender(b"low"))
+            .task("high", 2, sender(b"high"))
+            .run("test", 4, || {
+                wait_for_send("low");
+                wait_for_send("high");
+                sys_post(task_id("server"), 1);
+                while order.lock().unwrap().len() < 2 {
+                    thread::yield_now();
+                }
+            });
+        assert_eq!(*order.lock().unwrap(), [&b"high"[..], &b"low"[..]]);
+    }
+
+    #[test]
+    fn notifications() {
+        let sender = || {
+            sys_send(task_id("test"), 5, b"hi", &mut [], &[]);
+            idle();
+        };
+        Sim::new().task("sender", 1, sender).run("test", 2, || {
+            let me = task_id("test");
+            wait_for_send("sender");
+            assert_eq!(sys_post(me, 0b101), 0);
+
+            // Notifications come before messages, but only those asked for;
+            // the rest stay pending.
+            let mut buffer = [0; 4];
+            let msg = sys_recv_open(&mut buffer, 0b001);
+            assert_eq!((msg.sender, msg.operation), (TaskId::KERNEL, 0b001));
+            let msg = sys_recv_open(&mut buffer, 0b010);
+            assert_eq!((msg.operation, msg.message_len), (5, 2));
+            assert_eq!(&buffer[..2], b"hi");
+            sys_reply(msg.sender, 0, &[]);
+            let msg = sys_recv_closed(&mut [], !0, TaskId::KERNEL).unwrap();
+            assert_eq!(msg.operation, 0b100);
+        });
+    }
+
+    #[test]
+    fn timer() {
+        Sim::new().run("test", 1, || {
+            let start = sys_get_timer().now;
+            sys_set_timer(Some(start + 20), 0b10);
+            assert_eq!(sys_get_timer().deadline, Some(start + 20));
+
+            let msg = sys_recv_closed(&mut [], 0b10, TaskId::KERNEL).unwrap();
+            assert_eq!(msg.operation, 0b10);
+            let t = sys_get_timer();
+            assert!(t.now >= start + 20);
+            assert_eq!(t.deadline, None);
+
+            // A deadline that has already passed fires at once.
+            sys_set_timer(Some(0), 0b1);
+            let msg = sys_recv_closed(&mut [], 0b1, TaskId::KERNEL).unwrap();
+            assert_eq!(msg.operation, 0b1);
+        });
+    }
+
+    #[test]
+    fn leases() {
+        let server = || loop {
+            let msg = sys_recv_open(&mut [], 0);
+            let info = sys_borrow_info(msg.sender, 0).unwrap();
+            assert_eq!(info.attributes, LeaseAttributes::READ);
+            assert_eq!(info.len, 5);
+
+            let mut buffer = [0; 8];
+            let (rc, n) = sys_borrow_read(msg.sender, 0, 1, &mut buffer);
+            assert_eq!((rc, n), (0, 4));
+            buffer.make_ascii_uppercase();
+            assert_eq!(sys_borrow_write(msg.sender, 1, 0, &buffer[..n]).0, 0);
+
+            // A lease can only be used as lent.
+            let (rc, _) = sys_borrow_write(msg.sender, 0, 0, b"x");
+            assert_eq!(rc, abi::DEFECT);
+            sys_reply(msg.sender, 0, &[]);
+        };
+        Sim::new().task("server", 1, server).run("test", 2, || {
+            let src = *b"hello";
+            let mut dst = [0; 8];
+            let leases = [Lease::read_only(&src), Lease::write_only(&mut dst)];
+            let server = task_id("server");
+            assert_eq!(sys_send(server, 0, &[], &mut [], &leases), (0, 0));
+            assert_eq!(&dst, b"ELLO\0\0\0\0");
+        });
+    }
+
+    #[test]
+    fn reply_fault() {
+        let server = || loop {
+            let msg = sys_recv_open(&mut [], 0);
+            sys_reply_fault(msg.sender, ReplyFaultReason::UndefinedOperation);
+        };
+        let sent = Arc::new(AtomicBool::new(false));
+        let client = {
+            let sent = sent.clone();
+            move || {
+                if !sent.swap(true, Ordering::Relaxed) {
+                    sys_send(task_id("server"), 0, &[], &mut [], &[]);
+                }
+                idle();
+            }
+        };
+        Sim::new()
+            .task("server", 1, server)
+            .task("client", 2, client)
+            .run("test", 3, || {
+                while restarts("client") == 0 {
+                    thread::yield_now();
+                }
+                assert_eq!(restarts("server"), 0);
+            });
+    }
+
+    #[test]
+    fn interrupts() {
+        Sim::new().run("test", 1, || {
+            let me = task_id("test");
+            sys_irq_control(0b11, true);
+
+            // Only enabled interrupts are posted...
+            irq("test", 0b110);
+            let msg = sys_recv_closed(&mut [], !0, TaskId::KERNEL).unwrap();
+            assert_eq!(msg.operation, 0b10);
+
+            // ...and firing disables them.
+            irq("test", 0b10);
+            sys_post(me, 0b1000);
+            let msg = sys_recv_closed(&mut [], !0, TaskId::KERNEL).unwrap();
+            assert_eq!(msg.operation, 0b1000);
+        });
+    }
+}
@@ -2,9 +2,15 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+#[cfg(hubris_sim)]
+pub use crate::sim::TaskSlot;
+
+#[cfg(not(hubris_sim))]
 use self::volatile_const::VolatileConst;
+#[cfg(not(hubris_sim))]
 use abi::{Generation, TaskId};
 
+#[cfg(not(hubris_sim))]
 mod volatile_const {
     /// Wraps a T which is expected to be constant at runtime but may change
     /// after compilation.
@@ -58,9 +64,11 @@ mod volatile_const {
 /// are used to create compile-time placeholders that are filled in with a
 /// task's identifying information by a post-compile process.  These
 /// placeholders can then be converted into TaskId at runtime.
+#[cfg(not(hubris_sim))]
 #[repr(C)]
 pub struct TaskSlot(VolatileConst<u16>);
 
+#[cfg(not(hubris_sim))]
 impl TaskSlot {
     /// A TaskSlot that has not been resolved by a later processing step.
     ///
@@ -98,6 +106,7 @@ impl TaskSlot {
 /// slot's placeholder in the task's binary. While not part of the kernel/task
 /// ABI, these entries are part of the task's ABI that is used by the build
 /// system.
+#[cfg(not(hubris_sim))]
 #[repr(C)]
 #[repr(packed)]
 pub struct TaskSlotTableEntry<const N: usize> {
@@ -106,6 +115,7 @@ pub struct TaskSlotTableEntry<const N: usize> {
     slot_name: [u8; N],
 }
 
+#[cfg(not(hubris_sim))]
 impl<const N: usize> TaskSlotTableEntry<N> {
     pub const fn for_task_slot(
         slot_name: &'static [u8; N],
@@ -139,4 +149,5 @@ impl<const N: usize> TaskSlotTableEntry<N> {
 // addresses are allocated to the contents and the section is not loaded into
 // the process space.  As such, instances of TaskSlotTableEntry will never exist
 // at runtime.
+#[cfg(not(hubris_sim))]
 unsafe impl<const N: usize> Sync for TaskSlotTableEntry<N> {}
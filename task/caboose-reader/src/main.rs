@@ -2,19 +2,32 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+//! Reads keys from the caboose of the running image, which the kernel tells
+//! us where to find, or of the inactive one, which we ask the update server
+//! for (since it's the one with the other bank mapped).
+
 #![no_std]
 #![no_main]
 
-use drv_caboose::{CabooseError, CabooseReader};
+use drv_caboose::{CabooseError, CabooseReader, CabooseSlot};
+use drv_update_api::Update;
 use idol_runtime::{ClientError, Leased, RequestError, W};
 use userlib::*;
 
+task_slot!(UPDATE_SERVER, update_server);
+
+/// The longest value we can read from the inactive image's caboose. The
+/// update server faults us for asking with a buffer too small, so this is
+/// the largest caboose any of our apps has.
+const MAX_INACTIVE_VALUE_LEN: usize = 256;
+
 #[export_name = "main"]
 fn main() -> ! {
     let mut buffer = [0; idl::INCOMING_SIZE];
 
     let mut server = ServerImpl {
         caboose: kipc::get_caboose(),
+        update: Update::from(UPDATE_SERVER.get_task_id()),
     };
 
     loop {
@@ -26,6 +39,46 @@ fn main() -> ! {
 
 struct ServerImpl {
     caboose: Option<&'static [u8]>,
+    update: Update,
+}
+
+impl ServerImpl {
+    fn read_active(
+        &self,
+        name: [u8; 4],
+        data: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<CabooseError>> {
+        let reader = self
+            .caboose
+            .map(CabooseReader::new)
+            .ok_or(CabooseError::MissingCaboose)?;
+
+        let chunk = reader.get(name)?;
+        if chunk.len() > data.len() {
+            return Err(RequestError::Fail(ClientError::BadLease))?;
+        }
+
+        data.write_range(0..chunk.len(), chunk)
+            .map_err(|_| RequestError::Fail(ClientError::BadLease))?;
+        Ok(chunk.len() as u32)
+    }
+
+    fn read_inactive(
+        &self,
+        name: [u8; 4],
+        data: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<CabooseError>> {
+        // Leases can't be passed along, so this goes by way of our own buffer.
+        let mut buf = [0u8; MAX_INACTIVE_VALUE_LEN];
+        let len = self.update.read_image_caboose(name, &mut buf)? as usize;
+        if len > data.len() {
+            return Err(RequestError::Fail(ClientError::BadLease))?;
+        }
+
+        data.write_range(0..len, &buf[..len])
+            .map_err(|_| RequestError::Fail(ClientError::BadLease))?;
+        Ok(len as u32)
+    }
 }
 
 impl idl::InOrderCabooseImpl for ServerImpl {
@@ -46,25 +99,43 @@ impl idl::InOrderCabooseImpl for ServerImpl {
         name: [u8; 4],
         data: Leased<W, [u8]>,
     ) -> Result<u32, RequestError<CabooseError>> {
+        self.read_active(name, data)
+    }
+
+    fn get_slot_key_by_tag(
+        &mut self,
+        _: &userlib::RecvMessage,
+        slot: CabooseSlot,
+        name: [u8; 4],
+        data: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<CabooseError>> {
+        match slot {
+            CabooseSlot::Active => self.read_active(name, data),
+            CabooseSlot::Inactive => self.read_inactive(name, data),
+        }
+    }
+
+    fn get_tag_by_index(
+        &mut self,
+        _: &userlib::RecvMessage,
+        index: u32,
+    ) -> Result<[u8; 4], RequestError<CabooseError>> {
         let reader = self
             .caboose
             .map(CabooseReader::new)
             .ok_or(CabooseError::MissingCaboose)?;
 
-        let chunk = reader.get(name)?;
-        if chunk.len() > data.len() {
-            return Err(RequestError::Fail(ClientError::BadLease))?;
-        }
-
-        data.write_range(0..chunk.len(), chunk)
-            .map_err(|_| RequestError::Fail(ClientError::BadLease))?;
-        Ok(chunk.len() as u32)
+        let (tag, _value) = reader
+            .iter()
+            .nth(index as usize)
+            .ok_or(CabooseError::NoSuchTag)??;
+        Ok(tag)
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
 mod idl {
-    use super::CabooseError;
+    use super::{CabooseError, CabooseSlot};
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -0,0 +1,103 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the host serial console multiplexer.
+//!
+//! The console mux owns the UART to the host and splits it into channels,
+//! each of which can be attached by at most one task at a time. Everything
+//! the host sends is copied into the buffer of every channel that is
+//! listening; anything an owner writes is interleaved onto the one line back
+//! to the host.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum ConsoleError {
+    /// The caller doesn't own the channel.
+    NotAttached = 1,
+    /// Another task owns the channel.
+    AlreadyAttached,
+    /// The channel can't be written to.
+    ReadOnly,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum Channel {
+    /// The host OS console, forwarded to the management network. Buffers
+    /// only while attached; if the owner falls behind, the oldest data is
+    /// dropped.
+    HostConsole = 0,
+    /// A debugger's view of the console. Buffers only while attached, and
+    /// holds the host off with flow control rather than drop anything, so
+    /// a slow reader slows the host down.
+    Debug = 1,
+    /// A record of the most recent console output, kept whether or not
+    /// anyone is attached; the oldest data is dropped to make room. Can't be
+    /// written to.
+    LogCapture = 2,
+}
+
+impl Channel {
+    pub const ALL: [Self; 3] =
+        [Self::HostConsole, Self::Debug, Self::LogCapture];
+
+    /// Whether the channel applies backpressure to the host rather than
+    /// dropping data.
+    pub fn is_lossless(self) -> bool {
+        self == Self::Debug
+    }
+
+    /// Whether the channel buffers data with no one attached.
+    pub fn is_always_on(self) -> bool {
+        self == Self::LogCapture
+    }
+
+    pub fn is_writable(self) -> bool {
+        self != Self::LogCapture
+    }
+}
+
+/// Result of a successful `read`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct ReadInfo {
+    /// Bytes copied into the lease.
+    pub len: u32,
+    /// Bytes the channel dropped since the last read, all of which came
+    /// before the ones returned. Always zero for lossless channels.
+    pub dropped: u32,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct ChannelStatus {
+    /// Nonzero if a task owns the channel.
+    pub attached: u8,
+    _pad: [u8; 3],
+    /// Bytes waiting to be read.
+    pub buffered: u32,
+    /// Bytes dropped since the server started.
+    pub dropped: u32,
+}
+
+impl ChannelStatus {
+    pub fn new(attached: bool, buffered: u32, dropped: u32) -> Self {
+        Self {
+            attached: attached as u8,
+            _pad: [0; 3],
+            buffered,
+            dropped,
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,471 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Host serial console multiplexer.
+//!
+//! We own the UART to the host and hand it out by channel (see
+//! `task_console_mux_api::Channel`), so that the control plane agent, a
+//! debugger, and anyone interested in recent console history can share it
+//! without stepping on each other.
+//!
+//! Every byte the host sends is copied into the ring of each channel that's
+//! listening. What happens when a ring fills depends on the channel: lossy
+//! channels drop their oldest data and count it, while a lossless channel
+//! makes us stop draining the USART's receive FIFO, at which point hardware
+//! flow control holds the host off until the owner reads. In the other
+//! direction, writes from any owner go into one transmit queue, in the order
+//! they arrive.
+//!
+//! Owners are told about new data, and about transmit space after a short
+//! write, by posting the notification bits they gave us in `attach`.
+
+#![no_std]
+#![no_main]
+
+use drv_stm32h7_usart::Usart;
+use heapless::Deque;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use mutable_statics::mutable_statics;
+use ringbuf::*;
+use task_console_mux_api::{Channel, ChannelStatus, ConsoleError, ReadInfo};
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+/// Receive buffering per channel.
+const RING_SIZE: usize = 2048;
+
+/// Transmit queue shared by all channels. This should be at least as large
+/// as the payload of one serial console packet from MGS; otherwise MGS will
+/// have to resend data in subsequent packets.
+const TX_QUEUE: usize = 1024;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Attach(Channel, u16),
+    Detach(Channel),
+    Dropping(Channel),
+    RxOverrun,
+    RxStalled,
+    TxFull { remaining: usize },
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+struct Slot {
+    channel: Channel,
+    ring: &'static mut Deque<u8, RING_SIZE>,
+    owner: Option<(TaskId, u32)>,
+    /// Bytes dropped since the owner last read.
+    dropped: u32,
+    /// Bytes dropped since we started.
+    dropped_total: u32,
+    /// Something happened that the owner should hear about.
+    wake: bool,
+}
+
+impl Slot {
+    fn is_listening(&self) -> bool {
+        self.channel.is_always_on() || self.owner.is_some()
+    }
+
+    /// Whether this channel is keeping us from draining the USART.
+    fn is_blocking_rx(&self) -> bool {
+        self.channel.is_lossless()
+            && self.owner.is_some()
+            && self.ring.is_full()
+    }
+
+    fn push(&mut self, b: u8) {
+        if let Err(b) = self.ring.push_back(b) {
+            // Lossless channels never get here: we stop receiving before
+            // they fill. For the others, make room by dropping the oldest
+            // byte; the pop can't fail because the ring is full.
+            if self.dropped == 0 {
+                ringbuf_entry!(Trace::Dropping(self.channel));
+            }
+            self.ring.pop_front();
+            let _ = self.ring.push_back(b);
+            self.dropped = self.dropped.wrapping_add(1);
+            self.dropped_total = self.dropped_total.wrapping_add(1);
+        }
+        self.wake = true;
+    }
+
+    fn clear(&mut self) {
+        self.ring.clear();
+        self.dropped = 0;
+    }
+}
+
+struct ServerImpl {
+    usart: Usart,
+    tx: &'static mut Deque<u8, TX_QUEUE>,
+    slots: [Slot; Channel::ALL.len()],
+    /// We've turned off the receive interrupt because a lossless channel is
+    /// full.
+    rx_stalled: bool,
+    /// A write was cut short; owners should hear when space frees up.
+    tx_waiting: bool,
+}
+
+impl ServerImpl {
+    fn slot(&mut self, channel: Channel) -> &mut Slot {
+        &mut self.slots[channel as usize]
+    }
+
+    /// Returns the slot for `channel` if the sender of `msg` owns it.
+    fn owned_slot(
+        &mut self,
+        msg: &RecvMessage,
+        channel: Channel,
+    ) -> Result<&mut Slot, ConsoleError> {
+        let slot = self.slot(channel);
+        match slot.owner {
+            Some((task, _)) if task == msg.sender => Ok(slot),
+            _ => Err(ConsoleError::NotAttached),
+        }
+    }
+
+    /// Moves as much of the transmit queue into the FIFO as fits.
+    fn pump_tx(&mut self) {
+        let mut moved = false;
+        while let Some(&b) = self.tx.front() {
+            if !self.usart.try_tx_push(b) {
+                break;
+            }
+            self.tx.pop_front();
+            moved = true;
+        }
+
+        if self.tx.is_empty() {
+            self.usart.disable_tx_fifo_empty_interrupt();
+        } else {
+            ringbuf_entry!(Trace::TxFull {
+                remaining: self.tx.len()
+            });
+            self.usart.enable_tx_fifo_empty_interrupt();
+        }
+
+        if moved && self.tx_waiting {
+            self.tx_waiting = false;
+            for slot in &mut self.slots {
+                if slot.channel.is_writable() {
+                    slot.wake = true;
+                }
+            }
+        }
+    }
+
+    /// Drains the receive FIFO into every listening channel, unless a
+    /// lossless channel fills up first.
+    fn pump_rx(&mut self) {
+        if self.usart.check_and_clear_rx_overrun() {
+            ringbuf_entry!(Trace::RxOverrun);
+        }
+
+        loop {
+            if self.slots.iter().any(Slot::is_blocking_rx) {
+                // Leave the rest in the FIFO, and let hardware flow control
+                // hold the host off once it fills. `unstall_rx` turns the
+                // interrupt back on when there's room again.
+                if !self.rx_stalled {
+                    ringbuf_entry!(Trace::RxStalled);
+                    self.rx_stalled = true;
+                    self.usart.disable_rx_interrupt();
+                }
+                break;
+            }
+            let Some(b) = self.usart.try_rx_pop() else {
+                break;
+            };
+            for slot in &mut self.slots {
+                if slot.is_listening() {
+                    slot.push(b);
+                }
+            }
+        }
+    }
+
+    fn unstall_rx(&mut self) {
+        if self.rx_stalled && !self.slots.iter().any(Slot::is_blocking_rx) {
+            self.rx_stalled = false;
+            self.usart.enable_rx_interrupt();
+        }
+    }
+
+    fn notify_owners(&mut self) {
+        for slot in &mut self.slots {
+            if core::mem::take(&mut slot.wake) {
+                if let Some((task, bits)) = slot.owner {
+                    sys_post(sys_refresh_task_id(task), bits);
+                }
+            }
+        }
+    }
+}
+
+impl idl::InOrderConsoleMuxImpl for ServerImpl {
+    fn attach(
+        &mut self,
+        msg: &RecvMessage,
+        channel: Channel,
+        notify: u32,
+    ) -> Result<(), RequestError<ConsoleError>> {
+        let slot = self.slot(channel);
+        match slot.owner {
+            Some((task, _)) if task == msg.sender => (),
+            Some((task, _)) if sys_refresh_task_id(task) == task => {
+                // Still alive, and not the caller.
+                return Err(ConsoleError::AlreadyAttached.into());
+            }
+            _ => {
+                // No owner, or the owner has since restarted: take over.
+                // Whatever was buffered was meant for someone else, except
+                // on a channel that keeps history regardless.
+                ringbuf_entry!(Trace::Attach(
+                    channel,
+                    msg.sender.index() as u16
+                ));
+                if !channel.is_always_on() {
+                    slot.clear();
+                }
+            }
+        }
+        slot.owner = Some((msg.sender, notify));
+        if !slot.ring.is_empty() {
+            slot.wake = true;
+        }
+
+        self.unstall_rx();
+        self.notify_owners();
+        Ok(())
+    }
+
+    fn detach(
+        &mut self,
+        msg: &RecvMessage,
+        channel: Channel,
+    ) -> Result<(), RequestError<ConsoleError>> {
+        let slot = self.owned_slot(msg, channel)?;
+        ringbuf_entry!(Trace::Detach(channel));
+        slot.owner = None;
+        slot.wake = false;
+        if !channel.is_always_on() {
+            slot.clear();
+        }
+
+        self.unstall_rx();
+        Ok(())
+    }
+
+    fn read(
+        &mut self,
+        msg: &RecvMessage,
+        channel: Channel,
+        data: LenLimit<Leased<W, [u8]>, 512>,
+    ) -> Result<ReadInfo, RequestError<ConsoleError>> {
+        let slot = self.owned_slot(msg, channel)?;
+
+        let n = usize::min(data.len(), slot.ring.len());
+        let (a, b) = slot.ring.as_slices();
+        let first = usize::min(n, a.len());
+        data.write_range(0..first, &a[..first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        data.write_range(first..n, &b[..n - first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        for _ in 0..n {
+            slot.ring.pop_front();
+        }
+
+        let info = ReadInfo {
+            len: n as u32,
+            dropped: core::mem::take(&mut slot.dropped),
+        };
+
+        self.unstall_rx();
+        Ok(info)
+    }
+
+    fn write(
+        &mut self,
+        msg: &RecvMessage,
+        channel: Channel,
+        data: LenLimit<Leased<R, [u8]>, 512>,
+    ) -> Result<u32, RequestError<ConsoleError>> {
+        self.owned_slot(msg, channel)?;
+        if !channel.is_writable() {
+            return Err(ConsoleError::ReadOnly.into());
+        }
+
+        let n = usize::min(data.len(), self.tx.capacity() - self.tx.len());
+        if n < data.len() {
+            self.tx_waiting = true;
+        }
+        let mut buf = [0; 512];
+        data.read_range(0..n, &mut buf[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        for &b in &buf[..n] {
+            // Can't fail, we checked for space above.
+            let _ = self.tx.push_back(b);
+        }
+
+        self.pump_tx();
+        Ok(n as u32)
+    }
+
+    fn send_break(
+        &mut self,
+        msg: &RecvMessage,
+        channel: Channel,
+    ) -> Result<(), RequestError<ConsoleError>> {
+        self.owned_slot(msg, channel)?;
+        if !channel.is_writable() {
+            return Err(ConsoleError::ReadOnly.into());
+        }
+        self.usart.send_break();
+        Ok(())
+    }
+
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+        channel: Channel,
+    ) -> Result<ChannelStatus, RequestError<core::convert::Infallible>> {
+        let slot = self.slot(channel);
+        Ok(ChannelStatus::new(
+            slot.owner.is_some(),
+            slot.ring.len() as u32,
+            slot.dropped_total,
+        ))
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::USART_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.pump_tx();
+        self.pump_rx();
+        self.notify_owners();
+        sys_irq_control(notifications::USART_IRQ_MASK, true);
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let usart = configure_usart();
+
+    let (rings, tx) = mutable_statics! {
+        static mut RINGS: [Deque<u8, RING_SIZE>; Channel::ALL.len()] =
+            [Deque::new; _];
+        static mut TX: [Deque<u8, TX_QUEUE>; 1] = [Deque::new; _];
+    };
+    let [host_console, debug, log_capture] = rings;
+    let [tx] = tx;
+    let slot = |channel, ring| Slot {
+        channel,
+        ring,
+        owner: None,
+        dropped: 0,
+        dropped_total: 0,
+        wake: false,
+    };
+
+    sys_irq_control(notifications::USART_IRQ_MASK, true);
+
+    let mut server = ServerImpl {
+        usart,
+        tx,
+        slots: [
+            slot(Channel::HostConsole, host_console),
+            slot(Channel::Debug, debug),
+            slot(Channel::LogCapture, log_capture),
+        ],
+        rx_stalled: false,
+        tx_waiting: false,
+    };
+    let mut buffer = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+fn configure_usart() -> Usart {
+    use drv_stm32h7_usart::device;
+    use drv_stm32h7_usart::drv_stm32xx_sys_api::*;
+
+    // TODO: this module should _not_ know our clock rate. That's a hack.
+    const CLOCK_HZ: u32 = 100_000_000;
+
+    // For gimlet, we only expect baud rate 3 Mbit, usart1, with hardware flow
+    // control enabled. We could expand our cargo features to cover other cases
+    // as needed. Currently, failing to enable any of those three features will
+    // cause a compilation error.
+    #[cfg(feature = "baud_rate_3M")]
+    const BAUD_RATE: u32 = 3_000_000;
+
+    #[cfg(all(feature = "usart1", feature = "usart1-gimletlet"))]
+    compile_error!(concat!(
+        "at most one usart feature (`usart1`, `usart1-gimletlet`)",
+        " should be enabled",
+    ));
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "usart1")] {
+            const PINS: &[(PinSet, Alternate)] = &[(
+                Port::A.pin(9).and_pin(10).and_pin(11).and_pin(12),
+                Alternate::AF7
+            )];
+
+            // From thin air, pluck a pointer to the USART register block.
+            //
+            // Safety: this is needlessly unsafe in the API. The USART is
+            // essentially a static, and we access it through a & reference so
+            // aliasing is not a concern. Were it literally a static, we could
+            // just reference it.
+            let usart = unsafe { &*device::USART1::ptr() };
+            let peripheral = Peripheral::Usart1;
+            let pins = PINS;
+        } else if #[cfg(feature = "usart1-gimletlet")] {
+            const PINS: &[(PinSet, Alternate)] = &[
+                (Port::A.pin(11).and_pin(12), Alternate::AF7),
+                (Port::B.pin(6).and_pin(7), Alternate::AF7),
+            ];
+
+            // See above.
+            let usart = unsafe { &*device::USART1::ptr() };
+            let peripheral = Peripheral::Usart1;
+            let pins = PINS;
+        } else {
+            compile_error!("no usartX feature specified");
+        }
+    }
+
+    Usart::turn_on(
+        &Sys::from(SYS.get_task_id()),
+        usart,
+        peripheral,
+        pins,
+        CLOCK_HZ,
+        BAUD_RATE,
+        true, // hardware_flow_control
+    )
+}
+
+mod idl {
+    use task_console_mux_api::{
+        Channel, ChannelStatus, ConsoleError, ReadInfo,
+    };
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -23,6 +23,9 @@ pub enum ControlPlaneAgentError {
     InvalidStartupOptions,
     OperationUnsupported,
     MgsAttachedToUart,
+    DebugConsoleUnavailable,
+    NoSuchDevice,
+    NoSuchSensor,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -34,4 +37,100 @@ pub enum UartClient {
     Humility,
 }
 
+/// Layout version of [`BoardInventory`] and [`InventoryDevice`].
+///
+/// This is bumped whenever a field is added, removed or changes meaning, so
+/// that whoever is reading the inventory off the management network can tell
+/// whether it knows how.
+pub const INVENTORY_VERSION: u32 = 1;
+
+/// Length of [`InventoryDevice::device`]; longer device names are truncated.
+pub const INVENTORY_DEVICE_NAME_LEN: usize = 16;
+
+/// Everything we know about what this board is and what it's running, in one
+/// place.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoardInventory {
+    /// Always [`INVENTORY_VERSION`]
+    pub version: u32,
+    /// VPD part number, as in [`VpdIdentity`]
+    pub part_number: [u8; VpdIdentity::PART_NUMBER_LEN],
+    /// VPD revision, as in [`VpdIdentity`]
+    pub revision: u32,
+    /// VPD serial number, as in [`VpdIdentity`]
+    pub serial: [u8; VpdIdentity::SERIAL_LEN],
+    /// The board's FPGA, if it has one and it answered
+    pub fpga: Option<InventoryFpga>,
+    /// The RoT's images, if it answered
+    pub rot: Option<InventoryRot>,
+    /// Number of devices in the I2C config, for [`InventoryDevice`] queries
+    pub devices: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryFpga {
+    pub ident: u32,
+    pub checksum: u32,
+    /// Design version and commit, for designs that report them
+    pub version: Option<u32>,
+    pub sha: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryRot {
+    /// Active slot: 0 for A, 1 for B
+    pub active: u8,
+    pub slot_a: Option<InventoryRotImage>,
+    pub slot_b: Option<InventoryRotImage>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryRotImage {
+    pub epoch: u32,
+    pub version: u32,
+    pub digest: [u8; 32],
+}
+
+/// One device from the I2C config.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryDevice {
+    /// Always [`INVENTORY_VERSION`]
+    pub version: u32,
+    /// Device name (e.g. `tmp117`), NUL-padded
+    pub device: [u8; INVENTORY_DEVICE_NAME_LEN],
+    pub presence: InventoryPresence,
+    /// Number of sensors, for `inventory_sensor` queries
+    pub sensors: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InventoryPresence {
+    Present,
+    NotPresent,
+    Failed,
+    Unavailable,
+    Timeout,
+    Error,
+}
+
+/// How the host's phase 2 image fetches have gone since we started.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct HostPhase2Stats {
+    /// Blocks asked for by the host
+    pub requests: u32,
+    /// Requests answered from our cache, without going to MGS
+    pub cache_hits: u32,
+    /// Blocks received from MGS
+    pub fetched: u32,
+    pub bytes_fetched: u64,
+    /// Requests sent to MGS again, for lack of an answer
+    pub resends: u32,
+    /// Requests given up on after running out of retries
+    pub failures: u32,
+    /// Offset of the most recent request
+    pub last_offset: u64,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
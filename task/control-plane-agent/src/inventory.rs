@@ -10,6 +10,10 @@ use gateway_messages::sp_impl::{BoundsChecked, DeviceDescription};
 use gateway_messages::{
     ComponentDetails, DeviceCapabilities, DevicePresence, SpComponent, SpError,
 };
+use task_control_plane_agent_api::{
+    ControlPlaneAgentError, InventoryDevice, InventoryPresence,
+    INVENTORY_DEVICE_NAME_LEN, INVENTORY_VERSION,
+};
 use task_sensor_api::Sensor as SensorTask;
 use task_sensor_api::SensorError;
 use task_validate_api::{Sensor, DEVICES as VALIDATE_DEVICES};
@@ -38,6 +42,12 @@ impl Inventory {
         OUR_DEVICES.len() + VALIDATE_DEVICES.len()
     }
 
+    /// Number of devices in the I2C config, as described by
+    /// `inventory_device`.
+    pub(crate) fn num_validate_devices(&self) -> usize {
+        VALIDATE_DEVICES.len()
+    }
+
     pub(crate) fn num_component_details(
         &self,
         component: &SpComponent,
@@ -93,22 +103,13 @@ impl Inventory {
         };
 
         let device = &VALIDATE_DEVICES[index];
-
-        let presence = match self.validate_task.validate_i2c(index as u32) {
-            Ok(ValidateOk::Present | ValidateOk::Validated) => {
-                DevicePresence::Present
-            }
-            Ok(ValidateOk::Removed) | Err(ValidateError::NotPresent) => {
-                DevicePresence::NotPresent
-            }
-            Err(ValidateError::BadValidation) => DevicePresence::Failed,
-            Err(ValidateError::Unavailable | ValidateError::DeviceOff) => {
-                DevicePresence::Unavailable
-            }
-            Err(ValidateError::DeviceTimeout) => DevicePresence::Timeout,
-            Err(ValidateError::InvalidDevice | ValidateError::DeviceError) => {
-                DevicePresence::Error
-            }
+        let presence = match self.presence(index) {
+            InventoryPresence::Present => DevicePresence::Present,
+            InventoryPresence::NotPresent => DevicePresence::NotPresent,
+            InventoryPresence::Failed => DevicePresence::Failed,
+            InventoryPresence::Unavailable => DevicePresence::Unavailable,
+            InventoryPresence::Timeout => DevicePresence::Timeout,
+            InventoryPresence::Error => DevicePresence::Error,
         };
 
         // This format string is statically guaranteed to fit in `component`
@@ -135,6 +136,64 @@ impl Inventory {
             presence,
         }
     }
+
+    /// Describes device `index` of the I2C config, for the versioned
+    /// inventory.
+    pub(crate) fn inventory_device(
+        &self,
+        index: usize,
+    ) -> Result<InventoryDevice, ControlPlaneAgentError> {
+        let device = VALIDATE_DEVICES
+            .get(index)
+            .ok_or(ControlPlaneAgentError::NoSuchDevice)?;
+
+        let mut name = [0; INVENTORY_DEVICE_NAME_LEN];
+        let n = usize::min(name.len(), device.device.len());
+        name[..n].copy_from_slice(&device.device.as_bytes()[..n]);
+
+        Ok(InventoryDevice {
+            version: INVENTORY_VERSION,
+            device: name,
+            presence: self.presence(index),
+            sensors: device.sensors.len() as u32,
+        })
+    }
+
+    /// Returns the sensor ID of sensor `sensor` of device `index` of the I2C
+    /// config.
+    pub(crate) fn inventory_sensor(
+        &self,
+        index: usize,
+        sensor: usize,
+    ) -> Result<u32, ControlPlaneAgentError> {
+        let device = VALIDATE_DEVICES
+            .get(index)
+            .ok_or(ControlPlaneAgentError::NoSuchDevice)?;
+        let sensor = device
+            .sensors
+            .get(sensor)
+            .ok_or(ControlPlaneAgentError::NoSuchSensor)?;
+        Ok(sensor.id.into())
+    }
+
+    fn presence(&self, index: usize) -> InventoryPresence {
+        match self.validate_task.validate_i2c(index as u32) {
+            Ok(ValidateOk::Present | ValidateOk::Validated) => {
+                InventoryPresence::Present
+            }
+            Ok(ValidateOk::Removed) | Err(ValidateError::NotPresent) => {
+                InventoryPresence::NotPresent
+            }
+            Err(ValidateError::BadValidation) => InventoryPresence::Failed,
+            Err(ValidateError::Unavailable | ValidateError::DeviceOff) => {
+                InventoryPresence::Unavailable
+            }
+            Err(ValidateError::DeviceTimeout) => InventoryPresence::Timeout,
+            Err(ValidateError::InvalidDevice | ValidateError::DeviceError) => {
+                InventoryPresence::Error
+            }
+        }
+    }
 }
 
 // Our parent deals primarily in overall device indices (`0..num_devices()`),
@@ -438,6 +497,10 @@ impl From<SensorErrorConvert> for MeasurementError {
             SensorError::DeviceTimeout => Self::DeviceTimeout,
             SensorError::DeviceOff => Self::DeviceOff,
 
+            // We never ask for aggregates or set thresholds, so never get
+            // these.
+            SensorError::InvalidWindow => panic!(),
+            SensorError::InvalidThresholds => panic!(),
             SensorError::ServerDied => panic!(),
         }
     }
@@ -16,9 +16,11 @@ use idol_runtime::{
 };
 use mutable_statics::mutable_statics;
 use ringbuf::{ringbuf, ringbuf_entry};
+use task_console_mux_api::ConsoleError;
 use task_control_plane_agent_api::MAX_INSTALLINATOR_IMAGE_ID_LEN;
 use task_control_plane_agent_api::{
-    BarcodeParseError, ControlPlaneAgentError, UartClient, VpdIdentity,
+    BarcodeParseError, BoardInventory, ControlPlaneAgentError, HostPhase2Stats,
+    InventoryDevice, UartClient, VpdIdentity,
 };
 use task_net_api::{
     Address, LargePayloadBehavior, Net, RecvError, SendError, SocketName,
@@ -43,7 +45,6 @@ use self::mgs_handler::MgsHandler;
 
 task_slot!(JEFE, jefe);
 task_slot!(NET, net);
-task_slot!(SYS, sys);
 
 #[allow(dead_code)] // Not all cases are used by all variants
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -53,9 +54,8 @@ enum Log {
     Rx(UdpMetadata),
     SendError(SendError),
     MgsMessage(MgsMessage),
-    UsartTxFull { remaining: usize },
-    UsartRxOverrun,
     UsartRxBufferDataDropped { num_bytes: u64 },
+    ConsoleMuxError(ConsoleError),
     SerialConsoleSend { buffered: usize },
     UpdatePartial { bytes_written: u32 },
     UpdateComplete,
@@ -186,13 +186,13 @@ impl ServerImpl {
 impl NotificationHandler for ServerImpl {
     fn current_notification_mask(&self) -> u32 {
         notifications::SOCKET_MASK
-            | notifications::USART_IRQ_MASK
+            | notifications::CONSOLE_MASK
             | notifications::TIMER_MASK
     }
 
     fn handle_notification(&mut self, bits: u32) {
-        if (bits & notifications::USART_IRQ_MASK) != 0 {
-            self.mgs_handler.drive_usart();
+        if (bits & notifications::CONSOLE_MASK) != 0 {
+            self.mgs_handler.drive_console();
         }
 
         if (bits & notifications::TIMER_MASK) != 0 {
@@ -235,6 +235,13 @@ impl idl::InOrderControlPlaneAgentImpl for ServerImpl {
             .get_host_phase2_data(image_hash, offset, data)
     }
 
+    fn host_phase2_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<HostPhase2Stats, RequestError<ControlPlaneAgentError>> {
+        self.mgs_handler.host_phase2_stats()
+    }
+
     fn get_startup_options(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -260,6 +267,30 @@ impl idl::InOrderControlPlaneAgentImpl for ServerImpl {
         Ok(self.mgs_handler.identity())
     }
 
+    fn inventory(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<BoardInventory, RequestError<core::convert::Infallible>> {
+        Ok(self.mgs_handler.board_inventory())
+    }
+
+    fn inventory_device(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        index: u32,
+    ) -> Result<InventoryDevice, RequestError<ControlPlaneAgentError>> {
+        Ok(self.mgs_handler.inventory_device(index)?)
+    }
+
+    fn inventory_sensor(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        index: u32,
+        sensor: u32,
+    ) -> Result<u32, RequestError<ControlPlaneAgentError>> {
+        Ok(self.mgs_handler.inventory_sensor(index, sensor)?)
+    }
+
     #[cfg(feature = "gimlet")]
     fn get_installinator_image_id(
         &mut self,
@@ -550,7 +581,8 @@ const fn usize_max(a: usize, b: usize) -> usize {
 
 mod idl {
     use task_control_plane_agent_api::{
-        ControlPlaneAgentError, HostStartupOptions, UartClient, VpdIdentity,
+        BoardInventory, ControlPlaneAgentError, HostPhase2Stats,
+        HostStartupOptions, InventoryDevice, UartClient, VpdIdentity,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -15,7 +15,10 @@ use gateway_messages::{
 };
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
 use static_assertions::const_assert;
-use task_control_plane_agent_api::VpdIdentity;
+use task_control_plane_agent_api::{
+    BoardInventory, InventoryFpga, InventoryRot, InventoryRotImage,
+    VpdIdentity, INVENTORY_VERSION,
+};
 use task_net_api::MacAddress;
 use task_packrat_api::Packrat;
 use userlib::{kipc, task_slot};
@@ -100,6 +103,26 @@ impl MgsCommon {
         Ok(state)
     }
 
+    /// Gathers the board inventory: our VPD identity, the FPGA `fpga` (which
+    /// our caller knows how to ask about, if there is one), and the RoT's
+    /// images.
+    pub(crate) fn board_inventory(
+        &self,
+        update: &SpUpdate,
+        fpga: Option<InventoryFpga>,
+    ) -> BoardInventory {
+        let id = self.identity();
+        BoardInventory {
+            version: INVENTORY_VERSION,
+            part_number: id.part_number,
+            revision: id.revision,
+            serial: id.serial,
+            fpga,
+            rot: rot_inventory(update.sprot_task()),
+            devices: self.inventory.num_validate_devices() as u32,
+        }
+    }
+
     #[inline(always)]
     pub(crate) fn inventory(&self) -> &Inventory {
         &self.inventory
@@ -237,6 +260,7 @@ impl MgsCommon {
                 };
                 Ok(slot)
             }
+            SpComponent::SP_ITSELF => update.active_slot(),
             _ => return Err(SpError::RequestUnsupportedForComponent),
         }
     }
@@ -264,12 +288,11 @@ impl MgsCommon {
                 Ok(())
             }
 
-            // SpComponent::SP_ITSELF:
-            // update_server for SP needs to decouple finish_update()
-            // from swap_banks() for SwitchDuration::Forever to make sense.
-            // There isn't currently a mechanism implemented for SP that
-            // enables SwitchDuration::Once.
-            //
+            // Persistently "switching" to the SP's running bank commits the
+            // image there, ending its trial after an update; the banks
+            // themselves only swap by way of an update.
+            SpComponent::SP_ITSELF => update.set_active_slot(slot, persist),
+
             // Other components might also be served someday.
             _ => return Err(SpError::RequestUnsupportedForComponent),
         }
@@ -298,6 +321,24 @@ fn rot_state(sprot: &SpRot) -> Result<RotState, RotError> {
     })
 }
 
+fn rot_inventory(sprot: &SpRot) -> Option<InventoryRot> {
+    let SprotRotState::V1 { state, .. } = sprot.rot_state().ok()?;
+    let active = match state.active {
+        drv_sprot_api::RotSlot::A => 0,
+        drv_sprot_api::RotSlot::B => 1,
+    };
+    let image = |d: drv_update_api::RotImageDetails| InventoryRotImage {
+        epoch: d.version.epoch,
+        version: d.version.version,
+        digest: d.digest,
+    };
+    Some(InventoryRot {
+        active,
+        slot_a: state.a.map(image),
+        slot_b: state.b.map(image),
+    })
+}
+
 pub(crate) struct RotImageDetailsConvert(pub drv_update_api::RotImageDetails);
 
 impl From<RotImageDetailsConvert> for RotImageDetails {
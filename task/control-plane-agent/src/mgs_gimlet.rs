@@ -5,12 +5,11 @@
 use crate::{
     mgs_common::MgsCommon, notifications, update::host_flash::HostFlashUpdate,
     update::rot::RotUpdate, update::sp::SpUpdate, update::ComponentUpdater,
-    usize_max, vlan_id_from_sp_port, Log, MgsMessage, SYS,
+    usize_max, vlan_id_from_sp_port, Log, MgsMessage,
 };
 use core::sync::atomic::{AtomicBool, Ordering};
 use core::time::Duration;
-use drv_gimlet_seq_api::Sequencer;
-use drv_stm32h7_usart::Usart;
+use drv_gimlet_seq_api::{PowerReason, Sequencer};
 use drv_user_leds_api::UserLeds;
 use gateway_messages::sp_impl::{
     BoundsChecked, DeviceDescription, SocketAddrV6, SpHandler,
@@ -26,12 +25,13 @@ use heapless::{Deque, Vec};
 use host_sp_messages::HostStartupOptions;
 use idol_runtime::{Leased, RequestError};
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
+use task_console_mux_api::{Channel, ConsoleError, ConsoleMux};
 use task_control_plane_agent_api::{
-    ControlPlaneAgentError, UartClient, VpdIdentity,
-    MAX_INSTALLINATOR_IMAGE_ID_LEN,
+    BoardInventory, ControlPlaneAgentError, HostPhase2Stats, InventoryDevice,
+    InventoryFpga, UartClient, VpdIdentity, MAX_INSTALLINATOR_IMAGE_ID_LEN,
 };
 use task_net_api::{Address, MacAddress, UdpMetadata};
-use userlib::{sys_get_timer, sys_irq_control, FromPrimitive, UnwrapLite};
+use userlib::{sys_get_timer, FromPrimitive, UnwrapLite};
 
 // We're included under a special `path` cfg from main.rs, which confuses rustc
 // about where our submodules live. Pass explicit paths to correct it.
@@ -60,17 +60,12 @@ pub(crate) type BorrowedUpdateBuffer = update_buffer::BorrowedUpdateBuffer<
 // Our single, shared update buffer.
 static UPDATE_MEMORY: UpdateBuffer = UpdateBuffer::new();
 
-/// Buffer sizes for serial console UDP / USART proxying.
+/// Buffer size for serial console data on its way to MGS. (Data on its way
+/// from MGS is queued by the console mux.)
 ///
-/// MGS -> SP should be at least as large as the amount of data we can receive
-/// in a single packet; otherwise MGS will have to resend data in subsequent
-/// packets.
-///
-/// SP -> MGS can be whatever size we want, but the larger it is the less likely
-/// we are to lose data while waiting to flush from our buffer out to UDP. We'll
+/// This can be whatever size we want, but the larger it is the less likely we
+/// are to lose data while waiting to flush from our buffer out to UDP. We'll
 /// start flushing once we cross SP_TO_MGS_SERIAL_CONSOLE_FLUSH_WATERMARK.
-const MGS_TO_SP_SERIAL_CONSOLE_BUFFER_SIZE: usize =
-    gateway_messages::MAX_SERIALIZED_SIZE;
 const SP_TO_MGS_SERIAL_CONSOLE_BUFFER_SIZE: usize = 4096;
 const SP_TO_MGS_SERIAL_CONSOLE_FLUSH_WATERMARK: usize =
     gateway_messages::MAX_SERIALIZED_SIZE;
@@ -87,6 +82,7 @@ const SERIAL_CONSOLE_FLUSH_TIMEOUT_MILLIS: u64 = 500;
 userlib::task_slot!(HOST_FLASH, hf);
 userlib::task_slot!(GIMLET_SEQ, gimlet_seq);
 userlib::task_slot!(USER_LEDS, user_leds);
+userlib::task_slot!(CONSOLE_MUX, console_mux);
 
 type InstallinatorImageIdBuf = Vec<u8, MAX_INSTALLINATOR_IMAGE_ID_LEN>;
 
@@ -124,7 +120,7 @@ pub(crate) struct MgsHandler {
     rot_update: RotUpdate,
     host_flash_update: HostFlashUpdate,
     host_phase2: HostPhase2Requester,
-    usart: UsartHandler,
+    console: ConsoleHandler,
     user_leds: UserLeds,
     attached_serial_console_mgs: Option<AttachedSerialConsoleMgs>,
     serial_console_write_offset: u64,
@@ -136,7 +132,7 @@ impl MgsHandler {
     /// Instantiate an `MgsHandler` that claims static buffers and device
     /// resources. Can only be called once; will panic if called multiple times!
     pub(crate) fn claim_static_resources(base_mac_address: MacAddress) -> Self {
-        let usart = UsartHandler::claim_static_resources();
+        let console = ConsoleHandler::claim_static_resources();
 
         Self {
             common: MgsCommon::claim_static_resources(base_mac_address),
@@ -146,7 +142,7 @@ impl MgsHandler {
             rot_update: RotUpdate::new(),
             sequencer: Sequencer::from(GIMLET_SEQ.get_task_id()),
             user_leds: UserLeds::from(USER_LEDS.get_task_id()),
-            usart,
+            console,
             attached_serial_console_mgs: None,
             serial_console_write_offset: 0,
             next_message_id: 0,
@@ -158,6 +154,33 @@ impl MgsHandler {
         self.common.identity()
     }
 
+    pub(crate) fn board_inventory(&self) -> BoardInventory {
+        let fpga = self.sequencer.fpga_ident().ok().map(|id| InventoryFpga {
+            ident: id.ident,
+            checksum: id.checksum,
+            version: None,
+            sha: None,
+        });
+        self.common.board_inventory(&self.sp_update, fpga)
+    }
+
+    pub(crate) fn inventory_device(
+        &self,
+        index: u32,
+    ) -> Result<InventoryDevice, ControlPlaneAgentError> {
+        self.common.inventory().inventory_device(index as usize)
+    }
+
+    pub(crate) fn inventory_sensor(
+        &self,
+        index: u32,
+        sensor: u32,
+    ) -> Result<u32, ControlPlaneAgentError> {
+        self.common
+            .inventory()
+            .inventory_sensor(index as usize, sensor as usize)
+    }
+
     pub(crate) fn installinator_image_id(&self) -> &[u8] {
         self.installinator_image_id
     }
@@ -177,7 +200,7 @@ impl MgsHandler {
             Some(sys_get_timer().now + 1)
         } else {
             match (
-                self.usart.from_rx_flush_deadline,
+                self.console.from_rx_flush_deadline,
                 self.host_phase2.timer_deadline(),
             ) {
                 (Some(a), Some(b)) => Some(a.min(b)),
@@ -194,46 +217,47 @@ impl MgsHandler {
         // no-op.
         self.host_flash_update.step_preparation();
         self.sp_update.step_preparation();
-        // Even though `timer_deadline()` can return a timer related to usart
-        // flushing or host phase2 data handling, we don't need to do anything
-        // here; `NetHandler` in main.rs will call
+        // Even though `timer_deadline()` can return a timer related to
+        // console flushing or host phase2 data handling, we don't need to do
+        // anything here; `NetHandler` in main.rs will call
         // `wants_to_send_packet_to_mgs()` below when it's ready to grab any
         // data we want to send.
     }
 
     pub(crate) fn uart_client(&self) -> UartClient {
-        self.usart.client
+        if self.console.humility_attached {
+            UartClient::Humility
+        } else {
+            UartClient::Mgs
+        }
     }
 
+    /// Humility gets the console mux's debug channel, which it shares with
+    /// (rather than takes from) any attached MGS.
     pub(crate) fn set_uart_client(
         &mut self,
         client: UartClient,
     ) -> Result<(), ControlPlaneAgentError> {
-        // Refuse to switch to humility if MGS is currently attached.
-        if client == UartClient::Humility
-            && self.attached_serial_console_mgs.is_some()
-        {
-            return Err(ControlPlaneAgentError::MgsAttachedToUart);
+        match client {
+            UartClient::Humility => self.console.attach_humility(),
+            UartClient::Mgs => {
+                self.console.detach_humility();
+                Ok(())
+            }
         }
-
-        self.usart.set_client(client);
-        Ok(())
     }
 
-    pub(crate) fn drive_usart(&mut self) {
-        self.usart.run_until_blocked();
+    pub(crate) fn drive_console(&mut self) {
+        self.console.pull_host_console();
     }
 
     pub(crate) fn wants_to_send_packet_to_mgs(&mut self) -> bool {
-        // If we should be forwarding uart data to MGS but we don't have one
-        // attached, discard any buffered data.
-        if self.usart.client == UartClient::Mgs
-            && self.attached_serial_console_mgs.is_none()
-        {
-            self.usart.clear_rx_data();
+        // If we don't have an MGS attached, discard any buffered data.
+        if self.attached_serial_console_mgs.is_none() {
+            self.console.clear_rx_data();
         }
 
-        self.usart.should_flush_to_mgs()
+        self.console.should_flush_to_mgs()
             || self.host_phase2.wants_to_send_packet()
     }
 
@@ -257,8 +281,8 @@ impl MgsHandler {
             }
         }
 
-        // Should we flush any buffered usart data out to MGS?
-        if !self.usart.should_flush_to_mgs() {
+        // Should we flush any buffered console data out to MGS?
+        if !self.console.should_flush_to_mgs() {
             return None;
         }
 
@@ -272,22 +296,23 @@ impl MgsHandler {
                 if Duration::from_millis(client_age_ms)
                     > SERIAL_CONSOLE_IDLE_TIMEOUT
                 {
-                    self.usart.clear_rx_data();
                     self.attached_serial_console_mgs = None;
+                    self.console.detach_mgs();
                     return None;
                 }
                 (attached.address, attached.port)
             }
             None => {
-                // Discard any buffered data and reset any usart-related timers.
-                self.usart.clear_rx_data();
+                // Discard any buffered data and reset any console-related
+                // timers.
+                self.console.clear_rx_data();
                 return None;
             }
         };
 
         // We have data we want to flush and an attached MGS; build our packet.
         ringbuf_entry!(Log::SerialConsoleSend {
-            buffered: self.usart.from_rx.len(),
+            buffered: self.console.from_rx.len(),
         });
 
         let message = Message {
@@ -297,11 +322,11 @@ impl MgsHandler {
             },
             kind: MessageKind::SpRequest(SpRequest::SerialConsole {
                 component: SpComponent::SP3_HOST_CPU,
-                offset: self.usart.from_rx_offset,
+                offset: self.console.from_rx_offset,
             }),
         };
 
-        let (from_rx0, from_rx1) = self.usart.from_rx.as_slices();
+        let (from_rx0, from_rx1) = self.console.from_rx.as_slices();
         let (n, written) = gateway_messages::serialize_with_trailing_data(
             tx_buf,
             &message,
@@ -312,7 +337,7 @@ impl MgsHandler {
         // hope it receives it, but if not, it's lost. We don't have the buffer
         // space to keep a bunch of data around waiting for acks, and in
         // practice we don't expect lost packets to be a problem.
-        self.usart.drain_flushed_data(written);
+        self.console.drain_flushed_data(written);
 
         Some(UdpMetadata {
             addr: Address::Ipv6(mgs_addr.ip.into()),
@@ -326,27 +351,21 @@ impl MgsHandler {
         &mut self,
         data: Leased<idol_runtime::W, [u8]>,
     ) -> Result<usize, RequestError<ControlPlaneAgentError>> {
-        // This function is only called by humility; switch control to it.
+        // This function is only called by humility; make sure it's attached.
         self.set_uart_client(UartClient::Humility)?;
 
+        let mut chunk = [0; CONSOLE_CHUNK_SIZE];
         let mut i = 0;
+
         while i < data.len() {
-            let Some(b) = self.usart.from_rx.pop_front() else {
+            let n = usize::min(CONSOLE_CHUNK_SIZE, data.len() - i);
+            let n = self.console.read_debug(&mut chunk[..n]);
+            if n == 0 {
                 break;
-            };
-
-            data.write_at(i, b)
+            }
+            data.write_range(i..i + n, &chunk[..n])
                 .map_err(|()| RequestError::went_away())?;
-
-            i += 1;
-        }
-
-        if !self.usart.from_rx.is_full() {
-            // If `from_rx` was full and our client was set to Humility the last
-            // time we handled a uart interrupt, we disabled the rx interrupt.
-            // Re-enable it now that humility has pulled some data from us and
-            // made space for more.
-            self.usart.usart.enable_rx_interrupt();
+            i += n;
         }
 
         Ok(i)
@@ -356,24 +375,21 @@ impl MgsHandler {
         &mut self,
         data: Leased<idol_runtime::R, [u8]>,
     ) -> Result<usize, RequestError<ControlPlaneAgentError>> {
-        const CHUNK_SIZE: usize = 32;
-
-        // This function is only called by humility; switch control to it.
+        // This function is only called by humility; make sure it's attached.
         self.set_uart_client(UartClient::Humility)?;
 
-        let mut chunk = [0; CHUNK_SIZE];
+        let mut chunk = [0; CONSOLE_CHUNK_SIZE];
         let mut i = 0;
 
-        while self.usart.tx_buffer_remaining_capacity() > 0 && i < data.len() {
-            // Min of all three: remaining buffer, remaining data, sizeof(chunk)
-            let n = usize::min(
-                self.usart.tx_buffer_remaining_capacity(),
-                usize::min(CHUNK_SIZE, data.len() - i),
-            );
+        while i < data.len() {
+            let n = usize::min(CONSOLE_CHUNK_SIZE, data.len() - i);
             data.read_range(i..i + n, &mut chunk[..n])
                 .map_err(|()| RequestError::went_away())?;
-            self.usart.tx_buffer_append(&chunk[..n]);
-            i += n;
+            let written = self.console.write(Channel::Debug, &chunk[..n]);
+            i += written;
+            if written < n {
+                break;
+            }
         }
 
         Ok(i)
@@ -404,6 +420,12 @@ impl MgsHandler {
         self.host_phase2.get_data(image_hash, offset, data)
     }
 
+    pub(crate) fn host_phase2_stats(
+        &self,
+    ) -> Result<HostPhase2Stats, RequestError<ControlPlaneAgentError>> {
+        Ok(self.host_phase2.stats())
+    }
+
     pub(crate) fn startup_options_impl(
         &self,
     ) -> Result<HostStartupOptions, RequestError<ControlPlaneAgentError>> {
@@ -710,7 +732,7 @@ impl SpHandler for MgsHandler {
         };
 
         self.sequencer
-            .set_state(power_state)
+            .set_state_with_reason(power_state, PowerReason::ControlPlane)
             .map_err(|e| SpError::PowerStateError(e as u32))
     }
 
@@ -732,6 +754,7 @@ impl SpHandler for MgsHandler {
         if self.attached_serial_console_mgs.is_some() {
             return Err(SpError::SerialConsoleAlreadyAttached);
         }
+        self.console.attach_mgs()?;
 
         // TODO: Add some kind of auth check before allowing a serial console
         // attach. https://github.com/oxidecomputer/hubris/issues/723
@@ -741,13 +764,6 @@ impl SpHandler for MgsHandler {
             last_keepalive_received: sys_get_timer().now,
         });
         self.serial_console_write_offset = 0;
-        self.usart.from_rx_offset = 0;
-
-        // Forcibly setting the client to MGS here will disconnect any active
-        // humility connections the next time they poll us or send us data to
-        // write, which seems fine: If MGS is available, we probably don't have
-        // a dongle attached to even use humility.
-        self.usart.set_client(UartClient::Mgs);
 
         Ok(())
     }
@@ -789,9 +805,14 @@ impl SpHandler for MgsHandler {
 
         // Buffer as much of `data` as we can, then notify MGS how much we
         // ingested.
-        let can_recv =
-            usize::min(self.usart.tx_buffer_remaining_capacity(), data.len());
-        self.usart.tx_buffer_append(&data[..can_recv]);
+        let mut can_recv = 0;
+        for chunk in data.chunks(CONSOLE_CHUNK_SIZE) {
+            let n = self.console.write(Channel::HostConsole, chunk);
+            can_recv += n;
+            if n < chunk.len() {
+                break;
+            }
+        }
         self.serial_console_write_offset = offset + can_recv as u64;
         Ok(self.serial_console_write_offset)
     }
@@ -816,6 +837,7 @@ impl SpHandler for MgsHandler {
     ) -> Result<(), SpError> {
         ringbuf_entry!(Log::MgsMessage(MgsMessage::SerialConsoleDetach));
         self.attached_serial_console_mgs = None;
+        self.console.detach_mgs();
         Ok(())
     }
 
@@ -830,8 +852,7 @@ impl SpHandler for MgsHandler {
             .as_mut()
             .ok_or(SpError::SerialConsoleNotAttached)?
             .check_sender_and_update_keepalive(sender, port)?;
-        self.usart.send_break();
-        Ok(())
+        self.console.send_break()
     }
 
     fn num_devices(&mut self, _sender: SocketAddrV6, _port: SpPort) -> u32 {
@@ -1067,70 +1088,103 @@ impl SpHandler for MgsHandler {
     }
 }
 
-struct UsartHandler {
-    usart: Usart,
-    to_tx: &'static mut Deque<u8, MGS_TO_SP_SERIAL_CONSOLE_BUFFER_SIZE>,
+/// Largest chunk we move to or from the console mux in one go; this is the
+/// mux's lease limit.
+const CONSOLE_CHUNK_SIZE: usize = 512;
+
+/// Our side of the console mux: MGS gets the host console channel, and
+/// humility gets the debug channel.
+struct ConsoleHandler {
+    mux: ConsoleMux,
     from_rx: &'static mut Deque<u8, SP_TO_MGS_SERIAL_CONSOLE_BUFFER_SIZE>,
     from_rx_flush_deadline: Option<u64>,
     from_rx_offset: u64,
-    client: UartClient,
+    humility_attached: bool,
 }
 
-impl UsartHandler {
+impl ConsoleHandler {
     fn claim_static_resources() -> Self {
-        let usart = configure_usart();
-        let to_tx = claim_mgs_to_sp_usart_buf_static();
-        let from_rx = claim_sp_to_mgs_usart_buf_static();
-
-        // Enable USART interrupts.
-        sys_irq_control(notifications::USART_IRQ_MASK, true);
-
         Self {
-            usart,
-            to_tx,
-            from_rx,
+            mux: ConsoleMux::from(CONSOLE_MUX.get_task_id()),
+            from_rx: claim_sp_to_mgs_usart_buf_static(),
             from_rx_flush_deadline: None,
             from_rx_offset: 0,
-            client: UartClient::Mgs,
+            humility_attached: false,
         }
     }
 
-    fn tx_buffer_remaining_capacity(&self) -> usize {
-        self.to_tx.capacity() - self.to_tx.len()
+    fn attach_mgs(&mut self) -> Result<(), SpError> {
+        self.clear_rx_data();
+        self.from_rx_offset = 0;
+        self.mux
+            .attach(Channel::HostConsole, notifications::CONSOLE_MASK)
+            .map_err(|_| SpError::SerialConsoleAlreadyAttached)
+    }
+
+    fn detach_mgs(&mut self) {
+        self.clear_rx_data();
+        // Failure means we weren't attached, which is what we wanted.
+        let _ = self.mux.detach(Channel::HostConsole);
     }
 
-    /// Panics if `self.tx_buffer_remaining_capacity() < data.len()`.
-    fn tx_buffer_append(&mut self, data: &[u8]) {
-        if self.to_tx.is_empty() {
-            self.usart.enable_tx_fifo_empty_interrupt();
+    fn attach_humility(&mut self) -> Result<(), ControlPlaneAgentError> {
+        if self.humility_attached {
+            return Ok(());
         }
-        for &b in data {
-            self.to_tx.push_back(b).unwrap_lite();
+        // Humility polls us, so it doesn't need notifications.
+        match self.mux.attach(Channel::Debug, 0) {
+            Ok(()) => {
+                self.humility_attached = true;
+                Ok(())
+            }
+            Err(_) => Err(ControlPlaneAgentError::DebugConsoleUnavailable),
         }
     }
 
-    fn set_client(&mut self, client: UartClient) {
-        self.client = client;
+    fn detach_humility(&mut self) {
+        if core::mem::take(&mut self.humility_attached) {
+            let _ = self.mux.detach(Channel::Debug);
+        }
+    }
 
-        match client {
-            UartClient::Humility => {
-                // We never flush to humility; it polls us.
-                self.from_rx_flush_deadline = None;
+    fn read_debug(&mut self, buf: &mut [u8]) -> usize {
+        match self.mux.read(Channel::Debug, buf) {
+            Ok(info) => info.len as usize,
+            Err(e) => {
+                self.note_mux_error(Channel::Debug, e);
+                0
             }
-            UartClient::Mgs => {
-                // Humility might've disabled the rx interrupt if our rx buffer
-                // filled; re-enable it.
-                self.usart.enable_rx_interrupt();
+        }
+    }
+
+    /// Queues as much of `data` as the mux will take, returning how much
+    /// that was.
+    fn write(&mut self, channel: Channel, data: &[u8]) -> usize {
+        match self.mux.write(channel, data) {
+            Ok(n) => n as usize,
+            Err(e) => {
+                self.note_mux_error(channel, e);
+                0
             }
         }
     }
 
-    fn should_flush_to_mgs(&self) -> bool {
-        // If we're configured to speak to humility, we never flush to MGS.
-        if self.client == UartClient::Humility {
-            return false;
+    fn send_break(&mut self) -> Result<(), SpError> {
+        self.mux
+            .send_break(Channel::HostConsole)
+            .map_err(|_| SpError::SerialConsoleNotAttached)
+    }
+
+    /// If the mux has forgotten us (because it restarted), so should we; the
+    /// next request from MGS or humility will attach again.
+    fn note_mux_error(&mut self, channel: Channel, e: ConsoleError) {
+        ringbuf_entry!(Log::ConsoleMuxError(e));
+        if channel == Channel::Debug {
+            self.humility_attached = false;
         }
+    }
 
+    fn should_flush_to_mgs(&self) -> bool {
         // Bail out early if our buffer is empty or past the "we should flush"
         // watermark.
         let len = self.from_rx.len();
@@ -1164,11 +1218,6 @@ impl UsartHandler {
     /// `self.from_rx.is_empty()`; callers are responsible for checking or
     /// ensuring both.
     fn set_from_rx_flush_deadline(&mut self) {
-        // If we're configured to speak to humility, we never flush to MGS.
-        if self.client == UartClient::Humility {
-            return;
-        }
-
         assert!(self.from_rx_flush_deadline.is_none());
         assert!(!self.from_rx.is_empty());
         let deadline =
@@ -1176,93 +1225,44 @@ impl UsartHandler {
         self.from_rx_flush_deadline = Some(deadline);
     }
 
-    fn run_until_blocked(&mut self) {
-        // Transmit as much as we have and can.
-        let mut n_transmitted = 0;
-        for &b in &*self.to_tx {
-            if self.usart.try_tx_push(b) {
-                n_transmitted += 1;
-            } else {
-                break;
-            }
-        }
-
-        // Drain the data we successfully transmitted.
-        if n_transmitted > 0 {
-            self.to_tx.drain_front(n_transmitted);
-        }
-
-        // Either disable the tx fifo interrupt (if we have no data left to
-        // send) or ringbuf-log that we filled the fifo.
-        if self.to_tx.is_empty() {
-            self.usart.disable_tx_fifo_empty_interrupt();
-        } else {
-            ringbuf_entry!(Log::UsartTxFull {
-                remaining: self.to_tx.len()
-            });
-        }
-
-        // Clear any errors.
-        if self.usart.check_and_clear_rx_overrun() {
-            ringbuf_entry!(Log::UsartRxOverrun);
-            // TODO-correctness Should we notify MGS of dropped data here? We
-            // could increment `self.from_rx_offset`, but (a) we don't know how
-            // much data we lost, and (b) it would indicate lost data in the
-            // wrong place (i.e., data lost at the current
-            // `self.from_rx_offset`, instead of `self.from_rx_offset +
-            // self.from_rx.len()`, which is where we actually are now).
-        }
-
+    /// Moves whatever the mux has buffered on the host console channel into
+    /// `from_rx`, called when the mux tells us there's data.
+    fn pull_host_console(&mut self) {
+        let mut chunk = [0; CONSOLE_CHUNK_SIZE];
         let mut n_received = 0;
         let mut discarded_data = 0;
 
-        // If our client is Humility, we only want to read from the usart if we
-        // have room in our buffer (i.e., we want to make use of flow control
-        // because Humility is a _very slow_ reader). By refusing to read from
-        // the RX FIFO in such a case, we'll get flow control (because we enable
-        // hardware flow control, which is automatic based on the FIFO
-        // fullness).
-        //
-        // If our client is MGS (the default), we always recv as much as we can
-        // from the USART FIFO, even if that means discarding old data. If an
-        // MGS instance is actually attached, we should be flushing packets out
-        // to it fast enough that we don't see this in practice.
-        match self.client {
-            UartClient::Humility => {
-                while !self.from_rx.is_full() {
-                    let Some(b) = self.usart.try_rx_pop() else {
+        loop {
+            let info = match self.mux.read(Channel::HostConsole, &mut chunk) {
+                Ok(info) => info,
+                Err(e) => {
+                    self.note_mux_error(Channel::HostConsole, e);
                     break;
-                };
-                    self.from_rx.push_back(b).unwrap_lite();
-                    n_received += 1;
                 }
+            };
 
-                if self.from_rx.is_full() {
-                    // If our buffer is full, we need to disable the RX
-                    // interrupt until Humility makes space (or detaches). We'll
-                    // re-enable the rx interrupt in either:
-                    //
-                    // 1. `uart_read()` when Humility polls us for data
-                    // 2. `set_client()` if our client is set back to Mgs
-                    self.usart.disable_rx_interrupt();
-                }
+            // If the mux had to drop data, the gap comes after everything we
+            // already hold. We can only tell MGS about a gap at the start of
+            // what we send, so give up on what we hold too.
+            if info.dropped > 0 {
+                discarded_data += self.from_rx.len() as u64;
+                discarded_data += u64::from(info.dropped);
+                self.from_rx.clear();
             }
-            UartClient::Mgs => {
-                while let Some(b) = self.usart.try_rx_pop() {
-                    n_received += 1;
-                    match self.from_rx.push_back(b) {
-                        Ok(()) => (),
-                        Err(b) => {
-                            // If `push_back` failed, we know there is at least
-                            // one item, allowing us to unwrap `pop_front`. At
-                            // that point we know there's space for at least
-                            // one, allowing us to unwrap a subsequent
-                            // `push_back`.
-                            self.from_rx.pop_front().unwrap_lite();
-                            self.from_rx.push_back(b).unwrap_lite();
-                            discarded_data += 1;
-                        }
-                    }
+
+            if info.len == 0 {
+                break;
+            }
+            for &b in &chunk[..info.len as usize] {
+                n_received += 1;
+                if let Err(b) = self.from_rx.push_back(b) {
+                    // If `push_back` failed, we know there is at least one
+                    // item, allowing us to unwrap `pop_front`. At that point we
+                    // know there's space for at least one, allowing us to
+                    // unwrap a subsequent `push_back`.
+                    self.from_rx.pop_front().unwrap_lite();
+                    self.from_rx.push_back(b).unwrap_lite();
+                    discarded_data += 1;
                 }
             }
         }
@@ -1276,86 +1276,14 @@ impl UsartHandler {
             });
         }
 
-        if n_received > 0 && self.from_rx_flush_deadline.is_none() {
+        if self.from_rx.is_empty() {
+            self.from_rx_flush_deadline = None;
+        } else if n_received > 0 && self.from_rx_flush_deadline.is_none() {
             self.set_from_rx_flush_deadline();
         }
-
-        // Re-enable USART interrupts.
-        sys_irq_control(notifications::USART_IRQ_MASK, true);
-    }
-
-    fn send_break(&self) {
-        self.usart.send_break();
     }
 }
 
-fn configure_usart() -> Usart {
-    use drv_stm32h7_usart::device;
-    use drv_stm32h7_usart::drv_stm32xx_sys_api::*;
-
-    // TODO: this module should _not_ know our clock rate. That's a hack.
-    const CLOCK_HZ: u32 = 100_000_000;
-
-    // For gimlet, we only expect baud rate 3 Mbit, usart1, with hardware flow
-    // control enabled. We could expand our cargo features to cover other cases
-    // as needed. Currently, failing to enable any of those three features will
-    // cause a compilation error.
-    #[cfg(feature = "baud_rate_3M")]
-    const BAUD_RATE: u32 = 3_000_000;
-
-    #[cfg(all(feature = "usart1", feature = "usart1-gimletlet"))]
-    compile_error!(concat!(
-        "at most one usart feature (`usart1`, `usart1-gimletlet`)",
-        " should be enabled",
-    ));
-
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "usart1")] {
-            const PINS: &[(PinSet, Alternate)] = &[(
-                Port::A.pin(9).and_pin(10).and_pin(11).and_pin(12),
-                Alternate::AF7
-            )];
-
-            // From thin air, pluck a pointer to the USART register block.
-            //
-            // Safety: this is needlessly unsafe in the API. The USART is
-            // essentially a static, and we access it through a & reference so
-            // aliasing is not a concern. Were it literally a static, we could
-            // just reference it.
-            let usart = unsafe { &*device::USART1::ptr() };
-            let peripheral = Peripheral::Usart1;
-            let pins = PINS;
-        } else if #[cfg(feature = "usart1-gimletlet")] {
-            const PINS: &[(PinSet, Alternate)] = &[
-                (Port::A.pin(11).and_pin(12), Alternate::AF7),
-                (Port::B.pin(6).and_pin(7), Alternate::AF7),
-            ];
-
-            // From thin air, pluck a pointer to the USART register block.
-            //
-            // Safety: this is needlessly unsafe in the API. The USART is
-            // essentially a static, and we access it through a & reference so
-            // aliasing is not a concern. Were it literally a static, we could
-            // just reference it.
-            let usart = unsafe { &*device::USART1::ptr() };
-            let peripheral = Peripheral::Usart1;
-            let pins = PINS;
-        } else {
-            compile_error!("no usartX feature specified");
-        }
-    }
-
-    Usart::turn_on(
-        &Sys::from(SYS.get_task_id()),
-        usart,
-        peripheral,
-        pins,
-        CLOCK_HZ,
-        BAUD_RATE,
-        true, // hardware_flow_control
-    )
-}
-
 trait DequeExt {
     fn drain_front(&mut self, n: usize);
 }
@@ -1368,23 +1296,6 @@ impl<T, const N: usize> DequeExt for Deque<T, N> {
     }
 }
 
-fn claim_mgs_to_sp_usart_buf_static(
-) -> &'static mut Deque<u8, MGS_TO_SP_SERIAL_CONSOLE_BUFFER_SIZE> {
-    static mut UART_TX_BUF: Deque<u8, MGS_TO_SP_SERIAL_CONSOLE_BUFFER_SIZE> =
-        Deque::new();
-
-    static TAKEN: AtomicBool = AtomicBool::new(false);
-    if TAKEN.swap(true, Ordering::Relaxed) {
-        panic!()
-    }
-
-    // Safety: unsafe because of references to mutable statics; safe because of
-    // the AtomicBool swap above, combined with the lexical scoping of
-    // `UART_TX_BUF`, means that this reference can't be aliased by any
-    // other reference in the program.
-    unsafe { &mut UART_TX_BUF }
-}
-
 fn claim_sp_to_mgs_usart_buf_static(
 ) -> &'static mut Deque<u8, SP_TO_MGS_SERIAL_CONSOLE_BUFFER_SIZE> {
     static mut UART_RX_BUF: Deque<u8, SP_TO_MGS_SERIAL_CONSOLE_BUFFER_SIZE> =
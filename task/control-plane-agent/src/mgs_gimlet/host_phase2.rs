@@ -7,7 +7,7 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use gateway_messages::{Header, Message, MessageKind, SpPort, SpRequest};
 use heapless::Vec;
 use idol_runtime::{Leased, RequestError};
-use task_control_plane_agent_api::ControlPlaneAgentError;
+use task_control_plane_agent_api::{ControlPlaneAgentError, HostPhase2Stats};
 use task_net_api::{Address, Ipv6Address, UdpMetadata};
 use userlib::{sys_get_timer, sys_post, TaskId, UnwrapLite};
 
@@ -51,14 +51,26 @@ const MGS_UDP_PORT: u16 = 22222;
 //    results in a call to `get_data()` below.
 // 6. `host-sp-comms` relays the data (or failure) back to the host over the
 //    uart.
+//
+// Whatever MGS sends us lands in a small cache, evicting the least recently
+// used block. If the host asks again for a block we have (most often because
+// our reply to it got lost on the uart), we notify `host-sp-comms` right away
+// instead of going back to MGS.
 const DELAY_TRY_OTHER_MGS: u64 = 500;
 const DELAY_RETRY: u64 = 1_000;
 const MAX_ATTEMPTS: u8 = 6;
+const CACHE_ENTRIES: usize = 4;
+
+type Block = Vec<u8, { gateway_messages::MAX_SERIALIZED_SIZE }>;
 
 pub(crate) struct HostPhase2Requester {
     current: Option<CurrentRequest>,
     last_responsive_mgs: SpPort,
-    buffer: &'static mut Vec<u8, { gateway_messages::MAX_SERIALIZED_SIZE }>,
+    cache: &'static mut [CacheEntry; CACHE_ENTRIES],
+    // Bumped on every cache access, to order entries by when they were last
+    // used.
+    cache_clock: u32,
+    stats: HostPhase2Stats,
 }
 
 impl HostPhase2Requester {
@@ -68,10 +80,16 @@ impl HostPhase2Requester {
         Self {
             current: None,
             last_responsive_mgs: SpPort::One,
-            buffer: claim_phase2_buffer(),
+            cache: claim_phase2_cache(),
+            cache_clock: 0,
+            stats: HostPhase2Stats::default(),
         }
     }
 
+    pub(crate) fn stats(&self) -> HostPhase2Stats {
+        self.stats
+    }
+
     pub(crate) fn start_fetch(
         &mut self,
         requesting_task: TaskId,
@@ -79,15 +97,29 @@ impl HostPhase2Requester {
         hash: [u8; 32],
         offset: u64,
     ) {
-        self.current = Some(CurrentRequest {
+        self.stats.requests = self.stats.requests.wrapping_add(1);
+        self.stats.last_offset = offset;
+
+        let cached = self.cache_lookup(hash, offset).is_some();
+        let state = if cached {
+            self.stats.cache_hits = self.stats.cache_hits.wrapping_add(1);
+            State::Fetched
+        } else {
+            State::NeedToSendFirstMgs(self.last_responsive_mgs)
+        };
+
+        let current = CurrentRequest {
             requesting_task,
             requesting_task_notification_bit,
             hash,
             offset,
-            state: State::NeedToSendFirstMgs(self.last_responsive_mgs),
+            state,
             retry_count: 0,
-        });
-        self.buffer.clear();
+        };
+        if cached {
+            current.notify_calling_task();
+        }
+        self.current = Some(current);
     }
 
     pub(crate) fn timer_deadline(&self) -> Option<u64> {
@@ -130,6 +162,7 @@ impl HostPhase2Requester {
                 }
                 // Timed out waiting for a response from the first MGS we tried;
                 // flip to the other one.
+                self.stats.resends = self.stats.resends.wrapping_add(1);
                 let port = match port {
                     SpPort::One => SpPort::Two,
                     SpPort::Two => SpPort::One,
@@ -148,10 +181,12 @@ impl HostPhase2Requester {
                 // tried; flip back to the first and retry.
                 current.retry_count += 1;
                 if current.retry_count >= MAX_ATTEMPTS {
+                    self.stats.failures = self.stats.failures.wrapping_add(1);
                     current.notify_calling_task();
                     self.current = None;
                     return None;
                 }
+                self.stats.resends = self.stats.resends.wrapping_add(1);
                 let port = match port {
                     SpPort::One => SpPort::Two,
                     SpPort::Two => SpPort::One,
@@ -226,18 +261,30 @@ impl HostPhase2Requester {
             return;
         }
 
-        // If we're expecting to ingest data, our buffer should be empty.
-        assert!(self.buffer.is_empty());
+        // Store the data over the least recently used cache entry (which may
+        // be an empty one).
+        self.cache_clock = self.cache_clock.wrapping_add(1);
+        let entry = self
+            .cache
+            .iter_mut()
+            .min_by_key(|e| e.last_used)
+            .unwrap_lite();
+        entry.key = Some((hash, offset));
+        entry.last_used = self.cache_clock;
+        entry.data.clear();
 
-        // Given `self.buffer` is sized to
+        // Given `entry.data` is sized to
         // `gateway_messages::MAX_SERIALIZED_SIZE` and `data` is coming from MGS
         // (so therefore <= `gateway_messages::MAX_SERIALIZED_SIZE`), we always
         // expect this `min` to return `data.len()`. If somehow we end up in a
         // position where that isn't the case, this will still work but will
         // discard some data, wasting some bandwidth but otherwise not having an
         // adverse effect.
-        let n = usize::min(self.buffer.capacity(), data.len());
-        self.buffer.extend_from_slice(&data[..n]).unwrap_lite();
+        let n = usize::min(entry.data.capacity(), data.len());
+        entry.data.extend_from_slice(&data[..n]).unwrap_lite();
+
+        self.stats.fetched = self.stats.fetched.wrapping_add(1);
+        self.stats.bytes_fetched += n as u64;
 
         current.state = State::Fetched;
         current.notify_calling_task();
@@ -245,25 +292,45 @@ impl HostPhase2Requester {
     }
 
     pub(crate) fn get_data(
-        &self,
+        &mut self,
         hash: [u8; 32],
         offset: u64,
         data: Leased<idol_runtime::W, [u8]>,
     ) -> Result<usize, RequestError<ControlPlaneAgentError>> {
-        match self.current.as_ref() {
-            Some(current)
-                if hash == current.hash && offset == current.offset =>
-            {
-                let n = usize::min(data.len(), self.buffer.len());
-                data.write_range(0..n, &self.buffer[..n])
-                    .map_err(|()| RequestError::went_away())?;
-                Ok(n)
-            }
-            _ => Err(ControlPlaneAgentError::DataUnavailable.into()),
-        }
+        let block = self
+            .cache_lookup(hash, offset)
+            .ok_or(ControlPlaneAgentError::DataUnavailable)?;
+        let n = usize::min(data.len(), block.len());
+        data.write_range(0..n, &block[..n])
+            .map_err(|()| RequestError::went_away())?;
+        Ok(n)
+    }
+
+    /// Finds the block at `offset` in the image with `hash`, if we have it,
+    /// and marks it used.
+    fn cache_lookup(&mut self, hash: [u8; 32], offset: u64) -> Option<&Block> {
+        let entry = self
+            .cache
+            .iter_mut()
+            .find(|e| e.key == Some((hash, offset)))?;
+        self.cache_clock = self.cache_clock.wrapping_add(1);
+        entry.last_used = self.cache_clock;
+        Some(&entry.data)
     }
 }
 
+struct CacheEntry {
+    key: Option<([u8; 32], u64)>,
+    last_used: u32,
+    data: Block,
+}
+
+const EMPTY_CACHE_ENTRY: CacheEntry = CacheEntry {
+    key: None,
+    last_used: 0,
+    data: Vec::new(),
+};
+
 struct CurrentRequest {
     requesting_task: TaskId,
     requesting_task_notification_bit: u8,
@@ -317,10 +384,9 @@ impl State {
     }
 }
 
-fn claim_phase2_buffer(
-) -> &'static mut Vec<u8, { gateway_messages::MAX_SERIALIZED_SIZE }> {
-    static mut PHASE2_BUF: Vec<u8, { gateway_messages::MAX_SERIALIZED_SIZE }> =
-        Vec::new();
+fn claim_phase2_cache() -> &'static mut [CacheEntry; CACHE_ENTRIES] {
+    static mut PHASE2_CACHE: [CacheEntry; CACHE_ENTRIES] =
+        [EMPTY_CACHE_ENTRY; CACHE_ENTRIES];
 
     static TAKEN: AtomicBool = AtomicBool::new(false);
     if TAKEN.swap(true, Ordering::Relaxed) {
@@ -329,7 +395,7 @@ fn claim_phase2_buffer(
 
     // Safety: unsafe because of references to mutable statics; safe because of
     // the AtomicBool swap above, combined with the lexical scoping of
-    // `PHASE2_BUF`, means that this reference can't be aliased by any
+    // `PHASE2_CACHE`, means that this reference can't be aliased by any
     // other reference in the program.
-    unsafe { &mut PHASE2_BUF }
+    unsafe { &mut PHASE2_CACHE }
 }
@@ -19,7 +19,10 @@ use gateway_messages::{
 use host_sp_messages::HostStartupOptions;
 use idol_runtime::{Leased, RequestError};
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
-use task_control_plane_agent_api::{ControlPlaneAgentError, VpdIdentity};
+use task_control_plane_agent_api::{
+    BoardInventory, ControlPlaneAgentError, HostPhase2Stats, InventoryDevice,
+    VpdIdentity,
+};
 use task_net_api::{MacAddress, UdpMetadata};
 use userlib::sys_get_timer;
 
@@ -65,6 +68,28 @@ impl MgsHandler {
         self.common.identity()
     }
 
+    pub(crate) fn board_inventory(&self) -> BoardInventory {
+        // No FPGA on the PSC.
+        self.common.board_inventory(&self.sp_update, None)
+    }
+
+    pub(crate) fn inventory_device(
+        &self,
+        index: u32,
+    ) -> Result<InventoryDevice, ControlPlaneAgentError> {
+        self.common.inventory().inventory_device(index as usize)
+    }
+
+    pub(crate) fn inventory_sensor(
+        &self,
+        index: u32,
+        sensor: u32,
+    ) -> Result<u32, ControlPlaneAgentError> {
+        self.common
+            .inventory()
+            .inventory_sensor(index as usize, sensor as usize)
+    }
+
     /// If we want to be woken by the system timer, we return a deadline here.
     /// `main()` is responsible for calling this method and actually setting the
     /// timer.
@@ -104,6 +129,12 @@ impl MgsHandler {
         Err(ControlPlaneAgentError::DataUnavailable.into())
     }
 
+    pub(crate) fn host_phase2_stats(
+        &self,
+    ) -> Result<HostPhase2Stats, RequestError<ControlPlaneAgentError>> {
+        Err(ControlPlaneAgentError::OperationUnsupported.into())
+    }
+
     pub(crate) fn get_host_phase2_data(
         &mut self,
         _image_hash: [u8; 32],
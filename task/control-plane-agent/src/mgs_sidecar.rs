@@ -22,7 +22,10 @@ use gateway_messages::{
 use host_sp_messages::HostStartupOptions;
 use idol_runtime::{Leased, RequestError};
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
-use task_control_plane_agent_api::{ControlPlaneAgentError, VpdIdentity};
+use task_control_plane_agent_api::{
+    BoardInventory, ControlPlaneAgentError, HostPhase2Stats, InventoryDevice,
+    InventoryFpga, VpdIdentity,
+};
 use task_net_api::{MacAddress, UdpMetadata};
 use userlib::sys_get_timer;
 
@@ -85,6 +88,35 @@ impl MgsHandler {
         self.common.identity()
     }
 
+    pub(crate) fn board_inventory(&self) -> BoardInventory {
+        let fpga = self.sequencer.mainboard_controller_ident().ok().map(|id| {
+            InventoryFpga {
+                ident: id.id.get(),
+                checksum: id.checksum.get(),
+                version: Some(id.version.get()),
+                sha: Some(id.sha.get()),
+            }
+        });
+        self.common.board_inventory(&self.sp_update, fpga)
+    }
+
+    pub(crate) fn inventory_device(
+        &self,
+        index: u32,
+    ) -> Result<InventoryDevice, ControlPlaneAgentError> {
+        self.common.inventory().inventory_device(index as usize)
+    }
+
+    pub(crate) fn inventory_sensor(
+        &self,
+        index: u32,
+        sensor: u32,
+    ) -> Result<u32, ControlPlaneAgentError> {
+        self.common
+            .inventory()
+            .inventory_sensor(index as usize, sensor as usize)
+    }
+
     /// If we want to be woken by the system timer, we return a deadline here.
     /// `main()` is responsible for calling this method and actually setting the
     /// timer.
@@ -124,6 +156,12 @@ impl MgsHandler {
         Err(ControlPlaneAgentError::DataUnavailable.into())
     }
 
+    pub(crate) fn host_phase2_stats(
+        &self,
+    ) -> Result<HostPhase2Stats, RequestError<ControlPlaneAgentError>> {
+        Err(ControlPlaneAgentError::OperationUnsupported.into())
+    }
+
     pub(crate) fn get_host_phase2_data(
         &mut self,
         _image_hash: [u8; 32],
@@ -5,6 +5,22 @@
 use gateway_messages::UpdateId;
 use userlib::UnwrapLite;
 
+/// Trims off the front of a chunk at `offset` whatever we already have, given
+/// that we've received everything before `expected_offset`.
+///
+/// MGS sends a chunk again if it doesn't hear back about it (say, because the
+/// connection dropped), so a chunk that overlaps data we've already received
+/// is one whose acknowledgement got lost, not an error. Returns `None` if the
+/// chunk starts past `expected_offset`, leaving a gap.
+pub(super) fn unreceived_data(
+    expected_offset: u32,
+    offset: u32,
+    data: &[u8],
+) -> Option<&[u8]> {
+    let received = expected_offset.checked_sub(offset)? as usize;
+    Some(data.get(received..).unwrap_or(&[]))
+}
+
 /// Helper type for all the update implementation state machines.
 ///
 /// Tracks the current update ID, the total update size, and the state of the
@@ -6,8 +6,8 @@ use super::{common::CurrentUpdate, ComponentUpdater};
 use crate::mgs_handler::{BorrowedUpdateBuffer, UpdateBuffer};
 use core::ops::Range;
 use drv_gimlet_hf_api::{
-    HfDevSelect, HfError, HfProtectMode, HostFlash, PAGE_SIZE_BYTES,
-    SECTOR_SIZE_BYTES,
+    HfDevSelect, HfError, HfProtectMode, HostFlash,
+    MAX_WRITE_PROTECT_WINDOW_MS, PAGE_SIZE_BYTES, SECTOR_SIZE_BYTES,
 };
 use gateway_messages::{
     ComponentUpdatePrepare, SpComponent, SpError, UpdateId,
@@ -137,6 +137,13 @@ impl ComponentUpdater for HostFlashUpdate {
         }
         let num_sectors = (capacity / SECTOR_SIZE_BYTES) as u32;
 
+        // The host flash server refuses our erases and programs in whatever
+        // range the control plane has write protected, so lift it for as long
+        // as we can; it comes back by itself if we're never done.
+        self.task
+            .lift_write_protect(MAX_WRITE_PROTECT_WINDOW_MS)
+            .map_err(|err| SpError::UpdateFailed(err as u32))?;
+
         // Note that we preserve sector 0, which is used for Hubris-level
         // persistent data.
         self.current = Some(CurrentUpdate::new(
@@ -328,6 +335,9 @@ impl ComponentUpdater for HostFlashUpdate {
         // to write this update?
         if *next_write_offset == total_size {
             *current.state_mut() = State::Complete;
+            // If this fails, protection still comes back when the window
+            // we opened ends.
+            let _ = self.task.engage_write_protect();
         }
 
         Ok(())
@@ -354,6 +364,7 @@ impl ComponentUpdater for HostFlashUpdate {
                 // TODO should we erase the slot? TODO should we set_dev() back
                 // to what it was (if we changed it)?
                 *current.state_mut() = State::Aborted;
+                let _ = self.task.engage_write_protect();
                 Ok(())
             }
 
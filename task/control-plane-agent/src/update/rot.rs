@@ -83,6 +83,12 @@ impl ComponentUpdater for RotUpdate {
             _ => return Err(SpError::InvalidSlotForComponent),
         };
 
+        // An sprot server built from another revision of its interface
+        // could misread what we send it, so we check before we start.
+        if !self.task.interface_matches()? {
+            return Err(SpError::RequestUnsupportedForComponent);
+        }
+
         self.task.prep_image_update(target)?;
 
         self.current = Some(CurrentUpdate::new(
@@ -54,13 +54,14 @@
                                  └───────────────┘
 */
 
+use super::common::unreceived_data;
 use crate::mgs_handler::{BorrowedUpdateBuffer, UpdateBuffer};
 use cfg_if::cfg_if;
 use core::ops::{Deref, DerefMut};
 use drv_caboose::CabooseReader;
 use drv_sprot_api::SpRot;
 use drv_update_api::stm32h7::BLOCK_SIZE_BYTES;
-use drv_update_api::{Update, UpdateError, UpdateTarget};
+use drv_update_api::{SlotId, Update, UpdateError, UpdateTarget};
 use gateway_messages::{
     ImageVersion, SpComponent, SpError, SpUpdatePrepare, UpdateId,
     UpdateInProgressStatus, UpdateStatus,
@@ -125,11 +126,58 @@ impl SpUpdate {
         &self.sprot_task
     }
 
+    /// Returns the bank we're running from, with bank 1 being slot 0.
+    pub(crate) fn active_slot(&self) -> Result<u16, SpError> {
+        let state = self
+            .sp_task
+            .sp_image_state()
+            .map_err(|err| SpError::ComponentOperationFailed(err as u32))?;
+        Ok(match state.active {
+            SlotId::A => 0,
+            SlotId::B => 1,
+        })
+    }
+
+    /// Commits the image we're running, ending its trial after an update.
+    /// We can't switch banks any other way than by updating, so `slot` must
+    /// be the active one, and the switch persistent.
+    pub(crate) fn set_active_slot(
+        &self,
+        slot: u16,
+        persist: bool,
+    ) -> Result<(), SpError> {
+        if !persist || slot != self.active_slot()? {
+            return Err(SpError::RequestUnsupportedForComponent);
+        }
+        self.sp_task
+            .commit_image()
+            .map_err(|err| SpError::ComponentOperationFailed(err as u32))
+    }
+
     pub(crate) fn prepare(
         &mut self,
         buffer: &'static UpdateBuffer,
         update: SpUpdatePrepare,
     ) -> Result<(), SpError> {
+        // Is this the update we're already in the middle of? Then MGS lost
+        // track of it (e.g. its connection to us dropped) and is picking it
+        // back up: leave everything as it is, and let it find out from
+        // `status()` how much we've received so it can carry on from there.
+        if let Some(current) = self.current.as_ref() {
+            if current.id() == update.id
+                && current.aux_flash_size == update.aux_flash_size
+                && current.sp_image_size == update.sp_image_size
+                && matches!(
+                    current.state(),
+                    State::AuxFlash(_)
+                        | State::FoundMatchingAuxFlashChck { .. }
+                        | State::AcceptingData(_)
+                )
+            {
+                return Ok(());
+            }
+        }
+
         // Do we have an update already in progress?
         match self.current.as_ref().map(|c| c.state()) {
             // These states are obviously "update in progress":
@@ -493,17 +541,26 @@ impl AcceptingData {
         sp_task: &Update,
         sp_image_size: u32,
         offset: u32,
-        mut data: &[u8],
+        data: &[u8],
     ) -> (State, Result<(), SpError>) {
-        // Check that this chunk starts where our data ends.
+        // Check that this chunk doesn't leave a gap after our data or run
+        // past the end of the image, and skip any part of it we already have.
         let expected_offset = self.next_write_offset + self.buffer.len() as u32;
-        if offset != expected_offset
-            || offset + data.len() as u32 > sp_image_size
-        {
+        if offset + data.len() as u32 > sp_image_size {
+            return (
+                State::AcceptingData(self),
+                Err(SpError::InvalidUpdateChunk),
+            );
+        }
+        let Some(mut data) = unreceived_data(expected_offset, offset, data)
+        else {
             return (
                 State::AcceptingData(self),
                 Err(SpError::InvalidUpdateChunk),
             );
+        };
+        if data.is_empty() {
+            return (State::AcceptingData(self), Ok(()));
         }
 
         while !data.is_empty() {
@@ -3,6 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::mgs_handler::{BorrowedUpdateBuffer, UpdateBuffer};
+use crate::update::common::unreceived_data;
 use core::ops::Range;
 use drv_auxflash_api::{
     AuxFlash, AuxFlashChecksum, AuxFlashError, PAGE_SIZE_BYTES,
@@ -276,19 +277,31 @@ impl AcceptingData {
         mut self,
         task: &AuxFlash,
         offset: u32,
-        mut data: &[u8],
+        data: &[u8],
         aux_flash_size: u32,
     ) -> (IngestDataResult, Result<(), SpError>) {
-        // Check that this chunk starts where our data ends.
+        // Check that this chunk doesn't leave a gap after our data or run
+        // past the end of the image, and skip any part of it we already have.
         let expected_offset = self.next_write_offset + self.buffer.len() as u32;
-        if offset != expected_offset
-            || offset + data.len() as u32 > aux_flash_size
-        {
+        if offset + data.len() as u32 > aux_flash_size {
             return (
                 IngestDataResult::NewState(State::AcceptingData(self)),
                 Err(SpError::InvalidUpdateChunk),
             );
         }
+        let Some(mut data) = unreceived_data(expected_offset, offset, data)
+        else {
+            return (
+                IngestDataResult::NewState(State::AcceptingData(self)),
+                Err(SpError::InvalidUpdateChunk),
+            );
+        };
+        if data.is_empty() {
+            return (
+                IngestDataResult::NewState(State::AcceptingData(self)),
+                Ok(()),
+            );
+        }
 
         while !data.is_empty() {
             data = self.buffer.extend_from_slice(data);
@@ -104,4 +104,52 @@ pub const DUMP_AGENT_VERSION: u8 = 0x10_u8;
 pub const DUMP_AGENT_TASKS: u8 = 0x12_u8;
 pub const DUMP_AGENT_SYSTEM: u8 = 0x13_u8;
 
+/// Messages the dump agent pushes to a collector, unprompted, when a task
+/// dump is taken.
+///
+/// Each packet is a hubpack-encoded [`push::Message`]. A dump is sent as a
+/// `Start`, then its contents in order as `Chunk`s, then an `End`. Each
+/// chunk's payload follows its message in the packet, compressed with
+/// `gnarle`; its CRC (CRC-32/ISO-HDLC, as used by zip) is of the
+/// uncompressed data, as is the CRC of the whole dump in `End`.
+///
+/// Delivery is best-effort: a collector that misses a chunk or finds a bad
+/// CRC can read the dump area itself with the usual `ReadDump` requests, so
+/// long as the dump hasn't been overwritten.
+pub mod push {
+    use hubpack::SerializedSize;
+    use serde::{Deserialize, Serialize};
+
+    pub const VERSION: u8 = 1;
+
+    #[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+    pub struct Message {
+        pub version: u8,
+        /// Distinguishes this dump from others pushed since the SP booted.
+        pub dump_id: u32,
+        /// Dump area the dump is in.
+        pub area: u8,
+        pub body: Body,
+    }
+
+    #[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+    pub enum Body {
+        Start {
+            /// Length of the whole (uncompressed) dump, in bytes
+            length: u32,
+        },
+        Chunk {
+            /// Where this chunk goes in the uncompressed dump
+            offset: u32,
+            /// Uncompressed length of this chunk
+            length: u32,
+            crc: u32,
+        },
+        End {
+            length: u32,
+            crc: u32,
+        },
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -4,11 +4,60 @@
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::build_notifications()?;
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/dump-agent.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
     )?;
 
+    #[cfg(feature = "push")]
+    write_push_config()?;
+
+    Ok(())
+}
+
+/// Task config, for pushing dumps:
+///
+/// ```toml
+/// [tasks.dump_agent.config]
+/// collector = { address = "fe80::aa40:25ff:fe04:1", port = 11114 }
+/// ```
+#[cfg(feature = "push")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskConfig {
+    collector: Collector,
+}
+
+#[cfg(feature = "push")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Collector {
+    address: String,
+    port: u16,
+}
+
+#[cfg(feature = "push")]
+fn write_push_config() -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let config = build_util::task_config::<TaskConfig>()?;
+    let address: std::net::Ipv6Addr = config
+        .collector
+        .address
+        .parse()
+        .map_err(|e| anyhow::anyhow!("bad collector address: {e}"))?;
+    let dest_path = build_util::out_dir().join("push_config.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+    writeln!(
+        out,
+        "const COLLECTOR_ADDR: [u8; 16] = {:?};",
+        address.octets()
+    )?;
+    writeln!(
+        out,
+        "const COLLECTOR_PORT: u16 = {};",
+        config.collector.port
+    )?;
     Ok(())
 }
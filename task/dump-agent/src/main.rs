@@ -16,6 +16,9 @@ use userlib::*;
 #[cfg(feature = "net")]
 mod udp;
 
+#[cfg(feature = "push")]
+mod push;
+
 //
 // Our DUMP_READ_SIZE must be an even power of 2 -- and practically speaking
 // cannot be more than 1K
@@ -27,6 +30,8 @@ struct ServerImpl {
     jefe: Jefe,
     #[cfg(feature = "net")]
     net: task_net_api::Net,
+    #[cfg(feature = "push")]
+    pusher: push::Pusher,
 }
 
 #[cfg(not(feature = "no-rot"))]
@@ -35,7 +40,9 @@ task_slot!(SPROT, sprot);
 task_slot!(JEFE, jefe);
 
 impl ServerImpl {
-    fn initialize(&self) -> Result<(), DumpAgentError> {
+    fn initialize(&mut self) -> Result<(), DumpAgentError> {
+        #[cfg(feature = "push")]
+        self.pusher.forget();
         self.jefe.reinitialize_dump_areas()
     }
 
@@ -135,10 +142,24 @@ impl ServerImpl {
         &mut self,
         index: u8,
     ) -> Result<(), DumpAgentError> {
+        #[cfg(feature = "push")]
+        self.pusher.forget();
         self.jefe.reinitialize_dump_from(index)?;
         Ok(())
     }
 
+    #[cfg(feature = "push")]
+    fn push_task(&mut self, task_index: u32) -> Result<u8, DumpAgentError> {
+        let area = self.dump_task(task_index)?;
+        self.pusher.queue(area);
+        Ok(area)
+    }
+
+    #[cfg(not(feature = "push"))]
+    fn push_task(&mut self, _task_index: u32) -> Result<u8, DumpAgentError> {
+        Err(DumpAgentError::NotSupported)
+    }
+
     #[cfg(not(feature = "no-rot"))]
     fn take_dump(&mut self) -> Result<(), DumpAgentError> {
         use drv_sprot_api::DumpOrSprotError;
@@ -163,15 +184,27 @@ impl ServerImpl {
     }
 }
 
+#[cfg(feature = "push")]
+const PUSH_MASK: u32 =
+    notifications::TIMER_MASK | notifications::JEFE_FAULT_MASK;
+#[cfg(all(feature = "net", not(feature = "push")))]
+const PUSH_MASK: u32 = 0;
+
 #[cfg(feature = "net")]
 impl idol_runtime::NotificationHandler for ServerImpl {
     fn current_notification_mask(&self) -> u32 {
-        notifications::SOCKET_MASK
+        notifications::SOCKET_MASK | PUSH_MASK
     }
     fn handle_notification(&mut self, bits: u32) {
         if (bits & notifications::SOCKET_MASK) != 0 {
             // Nothing to do here; we'll handle it in the main loop
         }
+        #[cfg(feature = "push")]
+        if (bits & notifications::JEFE_FAULT_MASK) != 0 {
+            // Jefe will have dumped whoever faulted, if it could; we'll
+            // push the dump from the main loop.
+            self.scan_for_dumps();
+        }
     }
 }
 
@@ -247,6 +280,14 @@ impl idl::InOrderDumpAgentImpl for ServerImpl {
     ) -> Result<(), RequestError<DumpAgentError>> {
         self.reinitialize_dump_from(index).map_err(|e| e.into())
     }
+
+    fn push_task(
+        &mut self,
+        _msg: &RecvMessage,
+        task_index: u32,
+    ) -> Result<u8, RequestError<DumpAgentError>> {
+        self.push_task(task_index).map_err(|e| e.into())
+    }
 }
 
 #[export_name = "main"]
@@ -260,13 +301,32 @@ fn main() -> ! {
         let mut server = ServerImpl {
             jefe: Jefe::from(JEFE.get_task_id()),
             net: task_net_api::Net::from(NET.get_task_id()),
+            #[cfg(feature = "push")]
+            pusher: Default::default(),
         };
 
+        // Push anything that was dumped before we started.
+        #[cfg(feature = "push")]
+        server.scan_for_dumps();
+
         loop {
             server.check_net(
                 rx_data_buf.as_mut_slice(),
                 tx_data_buf.as_mut_slice(),
             );
+            #[cfg(feature = "push")]
+            {
+                server.push_dumps(tx_data_buf.as_mut_slice());
+                if server.pusher.busy() {
+                    // The outgoing queue is full; come back once it's had
+                    // a chance to drain.
+                    let now = sys_get_timer().now;
+                    sys_set_timer(
+                        Some(now + push::RETRY_MS),
+                        notifications::TIMER_MASK,
+                    );
+                }
+            }
             idol_runtime::dispatch_n(&mut buffer, &mut server);
         }
     }
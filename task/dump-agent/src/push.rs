@@ -0,0 +1,249 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Pushing task dumps to a collector as they're taken, rather than waiting
+//! for someone to come and read them.
+//!
+//! Jefe tells us whenever a task faults, by which point it has dumped the
+//! task (if it's been built to). We then look for task dumps we haven't
+//! pushed yet, and send each one to the collector configured for this task,
+//! in the format described in `dump_agent_api::push`.
+
+use crate::ServerImpl;
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+use dump_agent_api::push::{Body, Message, VERSION};
+use dump_agent_api::{DumpAreaHeader, DumpContents, DUMP_READ_SIZE};
+use hubpack::SerializedSize;
+use ringbuf::*;
+use task_net_api::{Address, Ipv6Address, SendError, SocketName, UdpMetadata};
+
+include!(concat!(env!("OUT_DIR"), "/push_config.rs"));
+
+static CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const SOCKET: SocketName = SocketName::dump_agent;
+
+/// Compressing a chunk can make it bigger -- up to three times bigger, if
+/// it's nothing but lone escape bytes.
+const CHUNK_MAX_SIZE: usize = Message::MAX_SIZE + 3 * DUMP_READ_SIZE;
+
+static_assertions::const_assert!(
+    CHUNK_MAX_SIZE <= task_net_api::SOCKET_TX_SIZE[SOCKET as usize]
+);
+
+/// How long to wait before trying again, when the outgoing queue is full.
+pub(crate) const RETRY_MS: u64 = 10;
+
+/// We only track this many dump areas; any beyond them aren't pushed.
+const MAX_AREAS: u8 = 32;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Queued(u8),
+    Start { dump_id: u32, area: u8, length: u32 },
+    End { dump_id: u32, crc: u32 },
+    SendError(SendError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Where we are in pushing a dump.
+struct Push {
+    dump_id: u32,
+    area: u8,
+    length: u32,
+    /// Whether we've sent the `Start`
+    started: bool,
+    /// Offset of the next chunk to send
+    offset: u32,
+    crc: Digest<'static, u32>,
+}
+
+#[derive(Default)]
+pub(crate) struct Pusher {
+    /// Areas holding dumps we have pushed, or are going to, as a bitmask.
+    seen: u32,
+    /// Areas waiting to be pushed, as a bitmask.
+    queued: u32,
+    current: Option<Push>,
+    next_id: u32,
+}
+
+impl Pusher {
+    /// Returns true if we have dumps to push.
+    pub(crate) fn busy(&self) -> bool {
+        self.current.is_some() || self.queued != 0
+    }
+
+    pub(crate) fn queue(&mut self, area: u8) {
+        if area < MAX_AREAS {
+            ringbuf_entry!(Trace::Queued(area));
+            self.seen |= 1 << area;
+            self.queued |= 1 << area;
+        }
+    }
+
+    /// Forgets the dumps we've seen, because the dump areas have been
+    /// reinitialized.
+    pub(crate) fn forget(&mut self) {
+        self.seen = 0;
+        self.queued = 0;
+        self.current = None;
+    }
+}
+
+impl ServerImpl {
+    /// Queues any task dumps that we haven't seen before.
+    pub(crate) fn scan_for_dumps(&mut self) {
+        for index in 0..MAX_AREAS {
+            let Ok(area) = self.dump_area(index) else {
+                break;
+            };
+            let bit = 1 << index;
+            match area.contents {
+                DumpContents::SingleTask | DumpContents::TaskRegion => {
+                    if self.pusher.seen & bit == 0 {
+                        self.pusher.queue(index);
+                    }
+                }
+                _ => {
+                    // Either free, or part of a whole-system dump, which is
+                    // too big to push and is read out by whoever took it.
+                    self.pusher.seen &= !bit;
+                }
+            }
+        }
+    }
+
+    /// Sends as much of the queued dumps as the outgoing queue will take.
+    pub(crate) fn push_dumps(&mut self, tx_data_buf: &mut [u8]) {
+        loop {
+            let push = match &mut self.pusher.current {
+                Some(push) => push,
+                None => {
+                    if self.pusher.queued == 0 {
+                        return;
+                    }
+                    let area = self.pusher.queued.trailing_zeros() as u8;
+                    self.pusher.queued &= !(1 << area);
+                    let Ok(dump) = self.dump_area(area) else {
+                        continue;
+                    };
+                    let length = unsafe {
+                        let header =
+                            dump.region.address as *const DumpAreaHeader;
+                        core::ptr::read_volatile(header).written
+                    };
+                    let dump_id = self.pusher.next_id;
+                    self.pusher.next_id = dump_id.wrapping_add(1);
+                    self.pusher.current.insert(Push {
+                        dump_id,
+                        area,
+                        length,
+                        started: false,
+                        offset: 0,
+                        crc: CRC.digest(),
+                    })
+                }
+            };
+
+            let mut message = Message {
+                version: VERSION,
+                dump_id: push.dump_id,
+                area: push.area,
+                body: Body::Start {
+                    length: push.length,
+                },
+            };
+            let mut data = [0u8; DUMP_READ_SIZE];
+            let mut chunk_len = 0;
+            if push.started {
+                if push.offset < push.length {
+                    chunk_len = usize::min(
+                        DUMP_READ_SIZE,
+                        (push.length - push.offset) as usize,
+                    );
+                    let (area, offset) = (push.area, push.offset);
+                    match self.read_dump(area, offset) {
+                        Ok(d) => data = d,
+                        Err(_) => {
+                            // The dump has gone out from under us; there's
+                            // nothing left to push.
+                            self.pusher.current = None;
+                            continue;
+                        }
+                    }
+                    message.body = Body::Chunk {
+                        offset,
+                        length: chunk_len as u32,
+                        crc: CRC.checksum(&data[..chunk_len]),
+                    };
+                } else {
+                    message.body = Body::End {
+                        length: push.length,
+                        crc: push.crc.clone().finalize(),
+                    };
+                }
+            }
+
+            let mut n = hubpack::serialize(tx_data_buf, &message).unwrap();
+            if chunk_len != 0 {
+                gnarle::compress(&data[..chunk_len], |c| {
+                    tx_data_buf[n..n + c.len()].copy_from_slice(c);
+                    n += c.len();
+                    Ok::<_, core::convert::Infallible>(())
+                })
+                .ok();
+            }
+
+            let meta = UdpMetadata {
+                addr: Address::Ipv6(Ipv6Address(COLLECTOR_ADDR)),
+                port: COLLECTOR_PORT,
+                size: n as u32,
+                #[cfg(feature = "vlan")]
+                vid: task_net_api::VLAN_RANGE.start,
+            };
+            if let Err(e) =
+                self.net.send_packet(SOCKET, meta, &tx_data_buf[..n])
+            {
+                ringbuf_entry!(Trace::SendError(e));
+                match e {
+                    // We'll try again once the queue has drained, or `net`
+                    // has come back.
+                    SendError::QueueFull | SendError::ServerRestarted => return,
+                    SendError::Other
+                    | SendError::NotYours
+                    | SendError::InvalidVLan => panic!(),
+                }
+            }
+
+            // It's away; move on.
+            let Some(push) = &mut self.pusher.current else {
+                return;
+            };
+            match message.body {
+                Body::Start { length } => {
+                    ringbuf_entry!(Trace::Start {
+                        dump_id: push.dump_id,
+                        area: push.area,
+                        length,
+                    });
+                    push.started = true;
+                }
+                Body::Chunk { .. } => {
+                    push.crc.update(&data[..chunk_len]);
+                    push.offset += chunk_len as u32;
+                }
+                Body::End { crc, .. } => {
+                    ringbuf_entry!(Trace::End {
+                        dump_id: push.dump_id,
+                        crc,
+                    });
+                    self.pusher.current = None;
+                }
+            }
+        }
+    }
+}
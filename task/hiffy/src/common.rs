@@ -6,7 +6,7 @@ use hif::{Failure, Fault};
 use hubris_num_tasks::NUM_TASKS;
 #[allow(unused_imports)]
 use userlib::task_slot;
-#[cfg(any(feature = "update"))]
+#[cfg(any(feature = "update", feature = "fpga"))]
 use userlib::FromPrimitive;
 use userlib::{sys_refresh_task_id, sys_send, Generation, TaskId};
 
@@ -420,6 +420,265 @@ pub(crate) fn send_lease_write(
     Ok(nreply + nlease)
 }
 
+/// Returns the bulk buffer.
+#[cfg(feature = "bulk")]
+fn bulk() -> &'static mut [u8] {
+    // SAFETY: the bulk buffer is only touched by Hiffy functions (and the
+    // debugger, while we're not executing), and they run one at a time, each
+    // dropping its reference before returning.
+    unsafe { &mut crate::HIFFY_BULK[..] }
+}
+
+/// Checks that `len` bytes at `offset` fit in a buffer of `size` bytes,
+/// returning the range if so.
+#[cfg(feature = "bulk")]
+fn bulk_range(
+    offset: u32,
+    len: u32,
+    size: usize,
+) -> Result<core::ops::Range<usize>, Failure> {
+    let (offset, len) = (offset as usize, len as usize);
+    match offset.checked_add(len) {
+        Some(end) if end <= size => Ok(offset..end),
+        _ => Err(Failure::Fault(Fault::AccessOutOfBounds)),
+    }
+}
+
+///
+/// Pulls apart the frame shared by the bulk send functions, returning the
+/// task, the operation, the number of argument bytes (which are copied into
+/// `payload`), the number of reply bytes, and the range of the bulk buffer
+/// to lease.
+///
+#[cfg(feature = "bulk")]
+fn bulk_send_args(
+    stack: &[Option<u32>],
+    payload: &mut [u8],
+) -> Result<(TaskId, u16, usize, usize, core::ops::Range<usize>), Failure> {
+    if stack.len() < 6 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+
+    let sp = stack.len();
+
+    let len = stack[sp - 1].ok_or(Failure::Fault(Fault::EmptyParameter(6)))?;
+    let offset =
+        stack[sp - 2].ok_or(Failure::Fault(Fault::EmptyParameter(5)))?;
+    let range = bulk_range(offset, len, bulk().len())?;
+
+    let nreply =
+        stack[sp - 3].ok_or(Failure::Fault(Fault::EmptyParameter(4)))?;
+    let nbytes =
+        stack[sp - 4].ok_or(Failure::Fault(Fault::EmptyParameter(3)))?;
+    let (nreply, nbytes) = (nreply as usize, nbytes as usize);
+
+    if stack.len() < nbytes + 6 {
+        return Err(Failure::Fault(Fault::StackUnderflow));
+    }
+
+    let fp = sp - (nbytes + 6);
+
+    let task = match stack[fp + 0] {
+        Some(task) if task < NUM_TASKS as u32 => {
+            let prototype =
+                TaskId::for_index_and_gen(task as usize, Generation::default());
+
+            sys_refresh_task_id(prototype)
+        }
+        Some(_) => return Err(Failure::Fault(Fault::BadParameter(0))),
+        None => return Err(Failure::Fault(Fault::EmptyParameter(0))),
+    };
+
+    let op = match stack[fp + 1] {
+        Some(op) if op <= core::u16::MAX.into() => op as u16,
+        Some(_) => return Err(Failure::Fault(Fault::BadParameter(1))),
+        None => return Err(Failure::Fault(Fault::EmptyParameter(1))),
+    };
+
+    if nbytes > payload.len() {
+        return Err(Failure::Fault(Fault::StackUnderflow));
+    }
+
+    let base = fp + 2;
+
+    for i in base..base + nbytes {
+        payload[i - base] = match stack[i] {
+            Some(byte) if byte <= core::u8::MAX.into() => byte as u8,
+            Some(_) => return Err(Failure::Fault(Fault::BadParameter(2))),
+            None => return Err(Failure::Fault(Fault::EmptyParameter(2))),
+        };
+    }
+
+    Ok((task, op, nbytes, nreply, range))
+}
+
+///
+/// Function to send an arbitrary message to an arbitrary task with a single
+/// read lease on part of the bulk buffer
+///
+/// arg2+n+3: Length of lease
+/// arg2+n+2: Offset of lease in bulk buffer
+/// arg2+n+1: Number of reply bytes
+/// arg2+n: Number of bytes
+/// arg2: Argument bytes
+/// arg1: Operation
+/// arg0: Task
+///
+#[cfg(feature = "bulk")]
+pub(crate) fn bulk_send_lease_read(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    let mut payload = [0u8; 32];
+    let (task, op, nbytes, nreply, range) =
+        bulk_send_args(stack, &mut payload)?;
+
+    if nreply > rval.len() {
+        return Err(Failure::Fault(Fault::ReturnStackOverflow));
+    }
+
+    let (code, _) = sys_send(
+        task,
+        op,
+        &payload[0..nbytes],
+        &mut rval[0..nreply],
+        &[userlib::Lease::read_only(&bulk()[range])],
+    );
+
+    if code != 0 {
+        return Err(Failure::FunctionError(code));
+    }
+
+    Ok(nreply)
+}
+
+///
+/// Function to send an arbitrary message to an arbitrary task with a single
+/// write lease on part of the bulk buffer
+///
+/// arg2+n+3: Length of lease
+/// arg2+n+2: Offset of lease in bulk buffer
+/// arg2+n+1: Number of reply bytes
+/// arg2+n: Number of bytes
+/// arg2: Argument bytes
+/// arg1: Operation
+/// arg0: Task
+///
+#[cfg(feature = "bulk")]
+pub(crate) fn bulk_send_lease_write(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    let mut payload = [0u8; 32];
+    let (task, op, nbytes, nreply, range) =
+        bulk_send_args(stack, &mut payload)?;
+
+    if nreply > rval.len() {
+        return Err(Failure::Fault(Fault::ReturnStackOverflow));
+    }
+
+    let (code, _) = sys_send(
+        task,
+        op,
+        &payload[0..nbytes],
+        &mut rval[0..nreply],
+        &[userlib::Lease::write_only(&mut bulk()[range])],
+    );
+
+    if code != 0 {
+        return Err(Failure::FunctionError(code));
+    }
+
+    Ok(nreply)
+}
+
+///
+/// Function to copy from the caller's data into the bulk buffer
+///
+/// arg2: Length
+/// arg1: Offset in bulk buffer
+/// arg0: Offset in data
+///
+#[cfg(feature = "bulk")]
+pub(crate) fn bulk_copy_in(
+    stack: &[Option<u32>],
+    data: &[u8],
+    _rval: &mut [u8],
+) -> Result<usize, Failure> {
+    if stack.len() < 3 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+    let frame = &stack[stack.len() - 3..];
+    let src = frame[0].ok_or(Failure::Fault(Fault::MissingParameters))?;
+    let dst = frame[1].ok_or(Failure::Fault(Fault::MissingParameters))?;
+    let len = frame[2].ok_or(Failure::Fault(Fault::MissingParameters))?;
+
+    let bulk = bulk();
+    let src = bulk_range(src, len, data.len())?;
+    let dst = bulk_range(dst, len, bulk.len())?;
+    bulk[dst].copy_from_slice(&data[src]);
+    Ok(0)
+}
+
+///
+/// Function to copy from the bulk buffer onto the return stack
+///
+/// arg1: Length
+/// arg0: Offset in bulk buffer
+///
+#[cfg(feature = "bulk")]
+pub(crate) fn bulk_copy_out(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    if stack.len() < 2 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+    let frame = &stack[stack.len() - 2..];
+    let src = frame[0].ok_or(Failure::Fault(Fault::MissingParameters))?;
+    let len = frame[1].ok_or(Failure::Fault(Fault::MissingParameters))?;
+
+    let bulk = bulk();
+    let src = bulk_range(src, len, bulk.len())?;
+    if src.len() > rval.len() {
+        return Err(Failure::Fault(Fault::ReturnStackOverflow));
+    }
+    rval[..src.len()].copy_from_slice(&bulk[src.clone()]);
+    Ok(src.len())
+}
+
+///
+/// Function to copy a block within the bulk buffer; the source and
+/// destination may overlap.
+///
+/// arg2: Length
+/// arg1: Destination offset
+/// arg0: Source offset
+///
+#[cfg(feature = "bulk")]
+pub(crate) fn bulk_copy(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    _rval: &mut [u8],
+) -> Result<usize, Failure> {
+    if stack.len() < 3 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+    let frame = &stack[stack.len() - 3..];
+    let src = frame[0].ok_or(Failure::Fault(Fault::MissingParameters))?;
+    let dst = frame[1].ok_or(Failure::Fault(Fault::MissingParameters))?;
+    let len = frame[2].ok_or(Failure::Fault(Fault::MissingParameters))?;
+
+    let bulk = bulk();
+    let src = bulk_range(src, len, bulk.len())?;
+    bulk_range(dst, len, bulk.len())?;
+    bulk.copy_within(src, dst as usize);
+    Ok(0)
+}
+
 #[cfg(feature = "spi")]
 fn spi_args(stack: &[Option<u32>]) -> Result<(TaskId, u8, usize), Failure> {
     if stack.len() < 3 {
@@ -1044,3 +1303,198 @@ pub(crate) fn reset(
     func_err(drv_update_api::Update::from(UPDATE.get_task_id()).reset())?;
     Ok(0)
 }
+
+#[cfg(feature = "sprot")]
+task_slot!(SPROT, sprot);
+
+#[cfg(feature = "sprot")]
+const _: () = assert!(
+    <drv_sprot_api::SprotError as hubpack::SerializedSize>::MAX_SIZE <= 4
+);
+
+///
+/// Puts the reply to a sprot operation onto the return stack. Sprot errors
+/// aren't C-like, so they're returned as their hubpack encoding, which fits
+/// in the (little-endian) function error.
+///
+#[cfg(feature = "sprot")]
+fn sprot_reply<T: serde::Serialize>(
+    r: Result<T, drv_sprot_api::SprotError>,
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    match r {
+        Ok(v) => hubpack::serialize(rval, &v)
+            .map_err(|_| Failure::Fault(Fault::ReturnStackOverflow)),
+        Err(e) => {
+            let mut buf = [0u8; 4];
+            // This can't fail, as checked above.
+            let _ = hubpack::serialize(&mut buf, &e);
+            Err(Failure::FunctionError(u32::from_le_bytes(buf)))
+        }
+    }
+}
+
+#[cfg(feature = "sprot")]
+pub(crate) fn sprot_status(
+    _stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    let sprot = drv_sprot_api::SpRot::from(SPROT.get_task_id());
+    sprot_reply(sprot.status(), rval)
+}
+
+#[cfg(feature = "sprot")]
+pub(crate) fn sprot_io_stats(
+    _stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    let sprot = drv_sprot_api::SpRot::from(SPROT.get_task_id());
+    sprot_reply(sprot.io_stats(), rval)
+}
+
+#[cfg(feature = "sprot")]
+pub(crate) fn sprot_rot_state(
+    _stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    let sprot = drv_sprot_api::SpRot::from(SPROT.get_task_id());
+    sprot_reply(sprot.rot_state(), rval)
+}
+
+///
+/// Function to assert the RoT's chip select, wait, and deassert it
+///
+/// arg0: Delay, in milliseconds
+///
+#[cfg(feature = "sprot")]
+pub(crate) fn sprot_pulse_cs(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    if stack.is_empty() {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+
+    let fp = stack.len() - 1;
+    let delay = match stack[fp + 0] {
+        Some(delay) if delay <= core::u16::MAX.into() => delay as u16,
+        Some(_) => return Err(Failure::Fault(Fault::BadParameter(0))),
+        None => return Err(Failure::Fault(Fault::EmptyParameter(0))),
+    };
+
+    let sprot = drv_sprot_api::SpRot::from(SPROT.get_task_id());
+    sprot_reply(sprot.pulse_cs(delay), rval)
+}
+
+#[cfg(feature = "fpga")]
+task_slot!(FPGA, fpga);
+
+/// Checks the device index and address given to an FPGA function; `n` is the
+/// parameter number of the address.
+#[cfg(feature = "fpga")]
+fn fpga_args(
+    device: Option<u32>,
+    addr: Option<u32>,
+    n: u8,
+) -> Result<(u8, u16), Failure> {
+    let device = match device {
+        Some(device) if device <= core::u8::MAX.into() => device as u8,
+        Some(_) => return Err(Failure::Fault(Fault::BadParameter(0))),
+        None => return Err(Failure::Fault(Fault::EmptyParameter(0))),
+    };
+
+    let addr = match addr {
+        Some(addr) if addr <= core::u16::MAX.into() => addr as u16,
+        Some(_) => return Err(Failure::Fault(Fault::BadParameter(n))),
+        None => return Err(Failure::Fault(Fault::EmptyParameter(n))),
+    };
+
+    Ok((device, addr))
+}
+
+///
+/// Function to read from an FPGA's user design
+///
+/// arg2: Number of bytes
+/// arg1: Address
+/// arg0: Device index
+///
+#[cfg(feature = "fpga")]
+pub(crate) fn fpga_user_design_read(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    if stack.len() < 3 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+
+    let fp = stack.len() - 3;
+    let (device, addr) = fpga_args(stack[fp + 0], stack[fp + 1], 1)?;
+
+    let len = match stack[fp + 2] {
+        Some(len) => len as usize,
+        None => return Err(Failure::Fault(Fault::EmptyParameter(2))),
+    };
+
+    if len > rval.len() {
+        return Err(Failure::Fault(Fault::ReturnStackOverflow));
+    }
+
+    let fpga = drv_fpga_api::FpgaUserDesign::new(FPGA.get_task_id(), device);
+    func_err(fpga.read_bytes(addr, &mut rval[..len]))?;
+    Ok(len)
+}
+
+///
+/// Function to write to an FPGA's user design, from the caller's data
+///
+/// arg4: Number of bytes
+/// arg3: Offset in data
+/// arg2: Address
+/// arg1: Operation
+/// arg0: Device index
+///
+#[cfg(feature = "fpga")]
+pub(crate) fn fpga_user_design_write(
+    stack: &[Option<u32>],
+    data: &[u8],
+    _rval: &mut [u8],
+) -> Result<usize, Failure> {
+    if stack.len() < 5 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+
+    let fp = stack.len() - 5;
+    let op = match stack[fp + 1] {
+        Some(op) => match drv_fpga_api::WriteOp::from_u32(op) {
+            Some(op) => op,
+            None => return Err(Failure::Fault(Fault::BadParameter(1))),
+        },
+        None => return Err(Failure::Fault(Fault::EmptyParameter(1))),
+    };
+
+    let (device, addr) = fpga_args(stack[fp + 0], stack[fp + 2], 2)?;
+
+    let offset = match stack[fp + 3] {
+        Some(offset) => offset as usize,
+        None => return Err(Failure::Fault(Fault::EmptyParameter(3))),
+    };
+
+    let len = match stack[fp + 4] {
+        Some(len) => len as usize,
+        None => return Err(Failure::Fault(Fault::EmptyParameter(4))),
+    };
+
+    if offset.checked_add(len).map_or(true, |end| end > data.len()) {
+        return Err(Failure::Fault(Fault::AccessOutOfBounds));
+    }
+
+    let fpga = drv_fpga_api::FpgaUserDesign::new(FPGA.get_task_id(), device);
+    func_err(fpga.write_bytes(op, addr, &data[offset..offset + len]))?;
+    Ok(0)
+}
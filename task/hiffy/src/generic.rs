@@ -12,6 +12,16 @@ pub enum Functions {
     Send((Task, u16, Buffer, usize), u32),
     SendLeaseRead((Task, u16, Buffer, usize, usize), u32),
     SendLeaseWrite((Task, u16, Buffer, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseRead((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseWrite((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyIn((usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyOut((usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopy((usize, usize, usize), u32),
 }
 
 #[no_mangle]
@@ -22,6 +32,16 @@ pub(crate) static HIFFY_FUNCS: &[Function] = &[
     crate::common::send,
     crate::common::send_lease_read,
     crate::common::send_lease_write,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_read,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_write,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_in,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_out,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy,
 ];
 
 pub(crate) fn trace_execute(_offset: usize, _op: hif::Op) {}
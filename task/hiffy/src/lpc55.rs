@@ -90,6 +90,16 @@ pub enum Functions {
     ),
     #[cfg(feature = "update")]
     Reset((), drv_update_api::UpdateError),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseRead((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseWrite((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyIn((usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyOut((usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopy((usize, usize, usize), u32),
 }
 
 #[cfg(feature = "spctrl")]
@@ -400,6 +410,16 @@ pub(crate) static HIFFY_FUNCS: &[Function] = &[
     crate::common::switch_default_image,
     #[cfg(feature = "update")]
     crate::common::reset,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_read,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_write,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_in,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_out,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy,
 ];
 
 //
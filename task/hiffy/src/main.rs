@@ -83,6 +83,13 @@ cfg_if::cfg_if! {
 /// the cost of RAM.
 const HIFFY_SCRATCH_SIZE: usize = 512;
 
+/// Number of bytes in the bulk buffer, which lets Humility move more data per
+/// HIF program than fits in [`HIFFY_DATA`] or [`HIFFY_RSTACK`] (e.g., for
+/// flash and EEPROM operations). It's only there with the `bulk` feature,
+/// as it costs RAM that most images would rather spend elsewhere.
+#[cfg(feature = "bulk")]
+const HIFFY_BULK_SIZE: usize = 16_384;
+
 ///
 /// These HIFFY_* global variables constitute the interface with Humility;
 /// they should not be altered without modifying Humility as well.
@@ -91,6 +98,9 @@ const HIFFY_SCRATCH_SIZE: usize = 512;
 /// - [`HIFFY_DATA`]       => Binary data from the caller
 /// - [`HIFFY_RSTACK`]     => HIF return stack
 /// - [`HIFFY_SCRATCH`]    => Scratch space for hiffy functions
+/// - [`HIFFY_BULK`]       => Bulk buffer, addressed by offset and length
+///                           from HIF functions, that Humility reads and
+///                           writes directly (only with the `bulk` feature)
 /// - [`HIFFY_REQUESTS`]   => Count of succesful requests
 /// - [`HIFFY_ERRORS`]     => Count of HIF execution failures
 /// - [`HIFFY_FAILURE`]    => Most recent HIF failure, if any
@@ -103,6 +113,10 @@ static mut HIFFY_TEXT: [u8; HIFFY_TEXT_SIZE] = [0; HIFFY_TEXT_SIZE];
 static mut HIFFY_DATA: [u8; HIFFY_DATA_SIZE] = [0; HIFFY_DATA_SIZE];
 static mut HIFFY_RSTACK: [u8; HIFFY_RSTACK_SIZE] = [0; HIFFY_RSTACK_SIZE];
 
+#[cfg(feature = "bulk")]
+#[used]
+static mut HIFFY_BULK: [u8; HIFFY_BULK_SIZE] = [0; HIFFY_BULK_SIZE];
+
 static HIFFY_SCRATCH: StaticCell<[u8; HIFFY_SCRATCH_SIZE]> =
     StaticCell::new([0; HIFFY_SCRATCH_SIZE]);
 
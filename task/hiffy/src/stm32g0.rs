@@ -66,6 +66,16 @@ pub enum Functions {
         ),
         u32,
     ),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseRead((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseWrite((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyIn((usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyOut((usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopy((usize, usize, usize), u32),
 }
 
 #[cfg(feature = "i2c")]
@@ -474,6 +484,16 @@ pub(crate) static HIFFY_FUNCS: &[Function] = &[
     gpio_reset,
     #[cfg(feature = "gpio")]
     gpio_configure,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_read,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_write,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_in,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_out,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy,
 ];
 
 //
@@ -74,6 +74,8 @@ pub enum Functions {
     ),
     #[cfg(feature = "i2c")]
     I2cSelectedMuxSegment((Controller, PortIndex), ResponseCode),
+    #[cfg(feature = "i2c")]
+    I2cScan((Controller, PortIndex, Mux, Segment), ResponseCode),
     #[cfg(feature = "gpio")]
     GpioInput(drv_stm32xx_sys_api::Port, u32),
     #[cfg(feature = "gpio")]
@@ -139,6 +141,33 @@ pub enum Functions {
     BlockSize((), drv_update_api::UpdateError),
     #[cfg(feature = "update")]
     Reset((), drv_update_api::UpdateError),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseRead((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkSendLeaseWrite((Task, u16, Buffer, usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyIn((usize, usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopyOut((usize, usize), u32),
+    #[cfg(feature = "bulk")]
+    BulkCopy((usize, usize, usize), u32),
+    // Sprot errors are returned as their hubpack encoding; see
+    // `common::sprot_reply`.
+    #[cfg(feature = "sprot")]
+    SprotStatus((), u32),
+    #[cfg(feature = "sprot")]
+    SprotIoStats((), u32),
+    #[cfg(feature = "sprot")]
+    SprotRotState((), u32),
+    #[cfg(feature = "sprot")]
+    SprotPulseCs(u16, u32),
+    #[cfg(feature = "fpga")]
+    FpgaUserDesignRead((u8, u16, usize), drv_fpga_api::FpgaError),
+    #[cfg(feature = "fpga")]
+    FpgaUserDesignWrite(
+        (u8, drv_fpga_api::WriteOp, u16, usize, usize),
+        drv_fpga_api::FpgaError,
+    ),
 }
 
 #[cfg(feature = "i2c")]
@@ -403,6 +432,48 @@ fn i2c_selected_mux_segment(
     }
 }
 
+#[cfg(feature = "i2c")]
+fn i2c_scan(
+    stack: &[Option<u32>],
+    _data: &[u8],
+    rval: &mut [u8],
+) -> Result<usize, Failure> {
+    //
+    // We need exactly 4 parameters: the controller, port, mux and segment;
+    // the mux and segment may be None to scan an unmuxed bus.
+    //
+    if stack.len() < 4 {
+        return Err(Failure::Fault(Fault::MissingParameters));
+    }
+
+    let fp = stack.len() - 4;
+    let args = [stack[fp], stack[fp + 1], stack[fp + 2], stack[fp + 3]];
+
+    //
+    // The scan is of the bus rather than a device, so we supply a dummy
+    // address (and no register) to reuse our argument parsing.
+    //
+    let (controller, port, mux, _, _) =
+        i2c_args(&[args[0], args[1], args[2], args[3], Some(0), None])?;
+
+    let presence = drv_i2c_api::Presence::default();
+
+    if rval.len() < presence.0.len() {
+        return Err(Failure::Fault(Fault::ReturnValueOverflow));
+    }
+
+    let task = I2C.get_task_id();
+    let device = I2cDevice::new(task, controller, port, mux, 0);
+
+    match device.scan() {
+        Ok(presence) => {
+            rval[..presence.0.len()].copy_from_slice(&presence.0);
+            Ok(presence.0.len())
+        }
+        Err(err) => Err(Failure::FunctionError(err.into())),
+    }
+}
+
 #[cfg(feature = "gpio")]
 fn gpio_args(
     stack: &[Option<u32>],
@@ -597,6 +668,8 @@ pub(crate) static HIFFY_FUNCS: &[Function] = &[
     i2c_bulk_write,
     #[cfg(feature = "i2c")]
     i2c_selected_mux_segment,
+    #[cfg(feature = "i2c")]
+    i2c_scan,
     #[cfg(feature = "gpio")]
     gpio_input,
     #[cfg(feature = "gpio")]
@@ -653,6 +726,28 @@ pub(crate) static HIFFY_FUNCS: &[Function] = &[
     crate::common::switch_default_image,
     #[cfg(feature = "update")]
     crate::common::reset,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_read,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_send_lease_write,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_in,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy_out,
+    #[cfg(feature = "bulk")]
+    crate::common::bulk_copy,
+    #[cfg(feature = "sprot")]
+    crate::common::sprot_status,
+    #[cfg(feature = "sprot")]
+    crate::common::sprot_io_stats,
+    #[cfg(feature = "sprot")]
+    crate::common::sprot_rot_state,
+    #[cfg(feature = "sprot")]
+    crate::common::sprot_pulse_cs,
+    #[cfg(feature = "fpga")]
+    crate::common::fpga_user_design_read,
+    #[cfg(feature = "fpga")]
+    crate::common::fpga_user_design_write,
 ];
 
 //
@@ -9,7 +9,7 @@
 use drv_stm32h7_usart as drv_usart;
 
 use drv_gimlet_hf_api::{HfDevSelect, HostFlash};
-use drv_gimlet_seq_api::{PowerState, SeqError, Sequencer};
+use drv_gimlet_seq_api::{PowerReason, PowerState, SeqError, Sequencer};
 use drv_stm32xx_sys_api as sys_api;
 use drv_usart::Usart;
 use enum_map::Enum;
@@ -251,7 +251,10 @@ impl ServerImpl {
             // Attempt to move to A2; given we only call this function in
             // response to a host request, we expect we're currently in A0 and
             // this should work.
-            let err = match self.sequencer.set_state(PowerState::A2) {
+            let err = match self
+                .sequencer
+                .set_state_with_reason(PowerState::A2, PowerReason::HostRequest)
+            {
                 Ok(()) => {
                     ringbuf_entry!(Trace::SetState {
                         now: sys_get_timer().now,
@@ -961,7 +964,8 @@ fn handle_reboot_waiting_in_a2_timer(
             now: sys_get_timer().now,
             state: PowerState::A0,
         });
-        _ = sequencer.set_state(PowerState::A0);
+        _ = sequencer
+            .set_state_with_reason(PowerState::A0, PowerReason::HostRequest);
         *reboot_state = None;
     }
 }
@@ -10,6 +10,7 @@ use derive_idol_err::IdolError;
 pub use dump_agent_api::DumpAgentError;
 use serde::{Deserialize, Serialize};
 use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
 
 /// Platform-agnostic (but heavily influenced) reset status bits.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -27,6 +28,113 @@ pub enum ResetReason {
     Unknown, // TODO remove and use `Option<ResetReason>` once we switch to hubpack
 }
 
+/// Reasons the system might be unhealthy, as bits of `Jefe::get_health`.
+pub mod health {
+    /// A task has faulted, and is being held rather than restarted.
+    pub const TASK_HELD: u32 = 1 << 0;
+    /// Tasks have been faulting, and being restarted, more often than a
+    /// working system would.
+    pub const FAULT_STORM: u32 = 1 << 1;
+}
+
+/// Reasons the SP might not be ready, as bits of `Jefe::get_readiness`. The
+/// SP is ready when none of these are set; that's a stronger claim than
+/// being healthy, meant for the sequencer and the RoT to gate things on.
+pub mod readiness {
+    /// The system isn't healthy (see `health`).
+    pub const UNHEALTHY: u32 = 1 << 0;
+    /// A task the app calls critical isn't running, or is waiting to be
+    /// restarted.
+    pub const CRITICAL_TASK_DOWN: u32 = 1 << 1;
+    /// A task has faulted recently.
+    pub const RECENT_FAULT: u32 = 1 << 2;
+    /// The watchdog hasn't been fed lately.
+    pub const WATCHDOG_STARVED: u32 = 1 << 3;
+}
+
+/// Errors from the task control operations (`hold_task` and friends).
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[repr(u32)]
+pub enum TaskControlError {
+    /// The supervisor can't be controlled, only the tasks it supervises.
+    IllegalTask = 1,
+    /// There's no task with that index.
+    BadTask,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Ringbuf names in jefe's registry are (the last) this many bytes of the
+/// ringbuf's full path, e.g. `task_thermal::__RINGBUF`.
+pub const RINGBUF_NAME_LEN: usize = 48;
+
+/// Most bytes of an entry's payload that `read_ringbuf_entry` returns.
+pub const RINGBUF_PAYLOAD_MAX: usize = 64;
+
+/// Flags in `RingbufInfo`.
+pub mod ringbuf_flags {
+    /// Each entry's payload starts with the (little-endian `u64`) kernel
+    /// timer when the entry was made, followed by the payload proper.
+    pub const TIMESTAMPED: u32 = 1 << 0;
+}
+
+/// Errors from reading ringbufs through jefe's registry.
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[repr(u32)]
+pub enum RingbufError {
+    /// There's no ringbuf with that index in the registry.
+    NoSuchRingbuf = 1,
+    /// The ringbuf has no entry with that index.
+    NoSuchEntry,
+    /// Jefe was built without a ringbuf registry.
+    Unsupported,
+    /// The ringbuf isn't in its task's memory; the registry is wrong.
+    BadRingbuf,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// A ringbuf in jefe's registry, as of when it was asked.
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct RingbufInfo {
+    /// Index of the task the ringbuf is in.
+    pub task: u32,
+    pub entries: u32,
+    /// Size of each entry's payload, which may be more than
+    /// `RINGBUF_PAYLOAD_MAX`.
+    pub payload_size: u32,
+    /// Index of the most recent entry, or `u32::MAX` if there isn't one yet.
+    pub last: u32,
+    /// Entries lost to the ringbuf wrapping around.
+    pub dropped: u32,
+    /// Bits from `ringbuf_flags`.
+    pub flags: u32,
+    pub name_len: u32,
+    pub name: [u8; RINGBUF_NAME_LEN],
+}
+
+impl RingbufInfo {
+    pub fn name(&self) -> &[u8] {
+        &self.name[..usize::min(self.name_len as usize, RINGBUF_NAME_LEN)]
+    }
+}
+
+/// An entry read from a ringbuf. The payload is the raw bytes of the
+/// ringbuf's payload type, truncated to `RINGBUF_PAYLOAD_MAX`; making sense
+/// of it takes the task's debug info (and a look at the ringbuf's flags).
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct RingbufEntryData {
+    pub line: u16,
+    pub generation: u16,
+    pub count: u32,
+    pub payload_len: u32,
+    pub payload: [u8; RINGBUF_PAYLOAD_MAX],
+}
+
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
 #[repr(C)]
 pub enum DumpAreaError {
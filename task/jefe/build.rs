@@ -8,7 +8,23 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
 
 fn main() -> Result<()> {
-    let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+    let mut cfg =
+        build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    // Letting just anyone hold or fault tasks would be a bad idea, so unlike
+    // our other operations, nobody can call these unless the app says so.
+    // Likewise reading ringbufs, which can hold anything.
+    for op in [
+        "hold_task",
+        "release_task",
+        "restart_task",
+        "fault_task",
+        "ringbuf_count",
+        "ringbuf_info",
+        "read_ringbuf_entry",
+    ] {
+        cfg.allowed_callers.entry(op.to_string()).or_default();
+    }
 
     let allowed_callers = build_util::task_ids()
         .remap_allowed_caller_names_to_ids(&cfg.allowed_callers)?;
@@ -20,6 +36,11 @@ fn main() -> Result<()> {
         &allowed_callers,
     )
     .unwrap();
+    build_util::idol::append_interface_hash(
+        "../../idl/jefe.idol",
+        "server_stub.rs",
+        build_util::idol::Role::Server,
+    )?;
 
     build_util::expose_m_profile();
     build_util::expose_target_board();
@@ -48,6 +69,66 @@ fn main() -> Result<()> {
         writeln!(out, "];")?;
     }
 
+    {
+        let count = cfg.on_task_fault.len();
+
+        writeln!(
+            out,
+            "pub(crate) const FAULT_MAILING_LIST: [({task}, u32); {count}] = [",
+        )?;
+        for (name, rec) in cfg.on_task_fault {
+            writeln!(
+                out,
+                "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+                rec.to_ascii_uppercase().replace("-", "_"),
+            )?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    {
+        let count = cfg.on_readiness_change.len();
+
+        writeln!(
+            out,
+            "pub(crate) const READINESS_MAILING_LIST: \
+             [({task}, u32); {count}] = [",
+        )?;
+        for (name, rec) in cfg.on_readiness_change {
+            writeln!(
+                out,
+                "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+                rec.to_ascii_uppercase().replace("-", "_"),
+            )?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    {
+        let count = cfg.critical_tasks.len();
+        writeln!(
+            out,
+            "pub(crate) const CRITICAL_TASKS: [{task}; {count}] = [",
+        )?;
+        for name in cfg.critical_tasks {
+            writeln!(out, "    {task}::{name},")?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    writeln!(
+        out,
+        "#[allow(dead_code)]\n\
+         pub(crate) const MAX_RINGBUFS: usize = {};",
+        cfg.max_ringbufs.unwrap_or(64),
+    )?;
+
+    writeln!(
+        out,
+        "pub(crate) const WATCHDOG_STARVED_MS: Option<u64> = {:?};",
+        cfg.watchdog_starved_ms.map(u64::from),
+    )?;
+
     {
         let count = cfg.tasks_to_hold.len();
         writeln!(out, "pub(crate) const HELD_TASKS: [{task}; {count}] = [",)?;
@@ -57,6 +138,56 @@ fn main() -> Result<()> {
         writeln!(out, "];")?;
     }
 
+    {
+        let count = cfg.restart_policy.len();
+        writeln!(
+            out,
+            "pub(crate) const RESTART_POLICIES: \
+             [({task}, crate::policy::RestartPolicy); {count}] = [",
+        )?;
+        for (name, p) in cfg.restart_policy {
+            let escalation = match p.escalate {
+                Escalation::Hold => "Hold",
+                Escalation::Reset => "Reset",
+            };
+            writeln!(
+                out,
+                "    ({task}::{name}, crate::policy::RestartPolicy {{
+        backoff_ms: {},
+        max_backoff_ms: {},
+        max_restarts: {},
+        window_ms: {},
+        escalation: crate::policy::Escalation::{escalation},
+    }}),",
+                p.backoff_ms,
+                p.max_backoff_ms,
+                p.max_restarts.unwrap_or(u32::MAX),
+                p.window_ms,
+            )?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    {
+        let count = cfg.dependency_groups.len();
+        writeln!(
+            out,
+            "pub(crate) const DEPENDENCY_GROUPS: \
+             [({task}, &[{task}]); {count}] = [",
+        )?;
+        for (name, dependents) in cfg.dependency_groups {
+            if dependents.contains(&name) {
+                anyhow::bail!("task {name} can't depend on itself");
+            }
+            write!(out, "    ({task}::{name}, &[")?;
+            for d in dependents {
+                write!(out, "{task}::{d}, ")?;
+            }
+            writeln!(out, "]),")?;
+        }
+        writeln!(out, "];")?;
+    }
+
     #[cfg(feature = "dump")]
     output_dump_areas(&mut out)?;
     Ok(())
@@ -70,6 +201,25 @@ struct Config {
     /// notification name (in the target task)
     #[serde(default)]
     on_state_change: BTreeMap<String, String>,
+    /// Tasks to be notified whenever any task faults (after it has been
+    /// restarted, if it's going to be), as a map from task name to
+    /// notification name (in the target task)
+    #[serde(default)]
+    on_task_fault: BTreeMap<String, String>,
+    /// Tasks to be notified whenever the SP becomes ready or stops being
+    /// ready, as a map from task name to notification name (in the target
+    /// task)
+    #[serde(default)]
+    on_readiness_change: BTreeMap<String, String>,
+    /// Tasks that must be up for the SP to be ready.
+    #[serde(default)]
+    critical_tasks: BTreeSet<String>,
+    /// If set, the SP isn't ready unless the watchdog has been fed (as
+    /// reported through `watchdog_fed`) in this many milliseconds.
+    watchdog_starved_ms: Option<u32>,
+    /// Room in the ringbuf registry, with the `ringbuf-registry` feature
+    /// (default 64).
+    max_ringbufs: Option<usize>,
     /// Map of operation names to tasks allowed to call them.
     #[serde(default)]
     allowed_callers: BTreeMap<String, Vec<String>>,
@@ -77,6 +227,55 @@ struct Config {
     /// failure, unless overridden at runtime through Humility.
     #[serde(default)]
     tasks_to_hold: BTreeSet<String>,
+    /// Restart policies, as a map from task name to policy. Tasks without one
+    /// are restarted as soon as they fault, every time.
+    #[serde(default)]
+    restart_policy: BTreeMap<String, RestartPolicy>,
+    /// Tasks that depend on other tasks, as a map from the name of the task
+    /// depended on to the names of those depending on it. Whenever a task is
+    /// restarted, the tasks depending on it are restarted right after it.
+    #[serde(default)]
+    dependency_groups: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RestartPolicy {
+    /// Delay before the first restart in a window, in milliseconds; doubled
+    /// for each further restart in the window
+    #[serde(default)]
+    backoff_ms: u32,
+    /// Cap on the delay, in milliseconds
+    #[serde(default = "RestartPolicy::default_max_backoff_ms")]
+    max_backoff_ms: u32,
+    /// Restarts allowed in one window before escalating; unlimited if absent
+    max_restarts: Option<u32>,
+    /// Length of the window, in milliseconds
+    #[serde(default = "RestartPolicy::default_window_ms")]
+    window_ms: u32,
+    /// What to do once a task has used up its restarts
+    #[serde(default)]
+    escalate: Escalation,
+}
+
+impl RestartPolicy {
+    fn default_max_backoff_ms() -> u32 {
+        10_000
+    }
+
+    fn default_window_ms() -> u32 {
+        60_000
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+enum Escalation {
+    /// Hold the task at its fault, for inspection
+    #[default]
+    Hold,
+    /// Reset the system
+    Reset,
 }
 
 #[cfg(feature = "dump")]
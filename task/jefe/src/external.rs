@@ -42,6 +42,10 @@
 //! additional warning, surely fated to become half sunk in the lone and level
 //! sands...
 //!
+//! (Jefe does also offer the same controls over IPC, for automated tests and
+//! for reaching a system without a debugger attached; see `apply` below. That
+//! path has all the problems above, and so doesn't replace this one.)
+//!
 
 use crate::{Disposition, TaskStatus};
 use core::sync::atomic::{AtomicU32, Ordering};
@@ -54,7 +58,7 @@ use userlib::*;
 
 /// The actual requests that we honor from an external source entity
 #[derive(FromPrimitive, Copy, Clone, Debug, Eq, PartialEq)]
-enum Request {
+pub(crate) enum Request {
     None = 0,
     Start = 1,
     Hold = 2,
@@ -136,6 +140,13 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
     // Ensure the task index is in range.
     let state = states.get_mut(ndx).ok_or(Error::BadTask)?;
 
+    apply(state, ndx, request);
+    Ok(true)
+}
+
+/// Carries out `request` on task `ndx` (which is not the supervisor, and whose
+/// status is `state`), whether it came from the debugger or over IPC.
+pub(crate) fn apply(state: &mut TaskStatus, ndx: usize, request: Request) {
     let task = TaskIndex(ndx as u16);
     ringbuf_entry!(Trace::Request(request, task));
 
@@ -160,7 +171,11 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
             // Note that this command does _not_ clear task holds! For that, you
             // must issue Release, below. This means it's useful for starting
             // the task but still catching it on the _next_ fault.
-            kipc::restart_task(ndx, true);
+            //
+            // It does, however, preempt any backoff the task's restart policy
+            // has it waiting on.
+            state.restart_at = None;
+            state.restart(ndx);
         }
 
         Request::Release => {
@@ -170,7 +185,7 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
             state.disposition = Disposition::Restart;
             if state.holding_fault {
                 state.holding_fault = false;
-                kipc::restart_task(ndx, true);
+                state.restart(ndx);
             }
         }
 
@@ -185,7 +200,6 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
     }
 
     ringbuf_entry!(Trace::Disposition(task, state.disposition));
-    Ok(true)
 }
 
 ///
@@ -8,12 +8,16 @@
 //!
 //! - Maintaining the system console output (currently via semihosting).
 //! - Monitoring tasks for failures and restarting them.
+//! - Judging whether the system as a whole is healthy, for the benefit of
+//!   whoever pets a watchdog.
+//! - Judging whether the SP is ready -- healthy, with its critical tasks up
+//!   and nothing recently restarted -- for the benefit of whoever mirrors
+//!   that to the sequencer and the RoT.
 //!
 //! It will probably become responsible for:
 //!
 //! - Evacuating kernel log information.
 //! - Coordinating certain shared resources, such as the RCC and GPIO muxing.
-//! - Managing a watchdog timer.
 //!
 //! It's unwise for the supervisor to use `SEND`, ever, except to talk to the
 //! kernel. This is because a `SEND` to a misbehaving task could block forever,
@@ -29,16 +33,47 @@
 #[cfg(feature = "dump")]
 mod dump;
 
+#[cfg(feature = "ringbuf-registry")]
+mod registry;
+
 mod external;
+mod policy;
 
 use core::convert::Infallible;
 
 use hubris_num_tasks::NUM_TASKS;
 use humpty::DumpArea;
 use idol_runtime::RequestError;
-use task_jefe_api::{DumpAgentError, ResetReason};
+use policy::{Escalation, RestartHistory, RestartPolicy, Verdict};
+use task_jefe_api::{
+    health, readiness, DumpAgentError, ResetReason, RingbufEntryData,
+    RingbufError, RingbufInfo, TaskControlError,
+};
 use userlib::*;
 
+/// Logs the kernel's counts of interrupts that nobody has hooked, which it
+/// tells us about when the app's `spurious-irqs` policy is `notify`. Only
+/// what has changed since `logged`, the counts as last logged, is logged;
+/// `logged` is then brought up to date.
+#[cfg(feature = "spurious-irq")]
+fn log_spurious_irqs(logged: &mut abi::SpuriousIrqCounts) {
+    let counts = kipc::read_spurious_irqs();
+    for ((irq, count), (_, was)) in counts.irqs.iter().zip(&logged.irqs) {
+        if count == was {
+            continue;
+        }
+        if *count >= abi::SPURIOUS_IRQ_LIMIT {
+            sys_log!("Spurious IRQ {} fired {} times; disabled", irq, count);
+        } else {
+            sys_log!("Spurious IRQ {} fired {} times", irq, count);
+        }
+    }
+    if counts.other != logged.other {
+        sys_log!("Other spurious IRQs fired {} times", counts.other);
+    }
+    *logged = counts;
+}
+
 fn log_fault(t: usize, fault: &abi::FaultInfo) {
     match fault {
         abi::FaultInfo::MemoryAccess { address, .. } => match address {
@@ -117,6 +152,13 @@ pub enum Disposition {
 // notification, but can otherwise be arbitrary.
 const TIMER_INTERVAL: u64 = 100;
 
+// Faults are counted in windows of this many milliseconds. The system is
+// considered to be in a fault storm while the current and previous windows
+// together hold more than `FAULT_STORM_LIMIT` faults, which a working system
+// doesn't produce even when one of its tasks is having a bad day.
+const FAULT_WINDOW: u64 = 10_000;
+const FAULT_STORM_LIMIT: u32 = 8;
+
 #[export_name = "main"]
 fn main() -> ! {
     sys_log!("viva el jefe");
@@ -126,7 +168,8 @@ fn main() -> ! {
         task_states[held_task as usize].disposition = Disposition::Hold;
     }
 
-    let deadline = sys_get_timer().now + TIMER_INTERVAL;
+    let now = sys_get_timer().now;
+    let deadline = now + TIMER_INTERVAL;
 
     sys_set_timer(Some(deadline), notifications::TIMER_MASK);
 
@@ -137,8 +180,18 @@ fn main() -> ! {
         deadline,
         task_states: &mut task_states,
         reset_reason: ResetReason::Unknown,
+        faults: FaultWindows {
+            end: deadline + FAULT_WINDOW,
+            current: 0,
+            previous: 0,
+        },
+        // Give the watchdog its full allowance from boot.
+        watchdog_fed_at: now,
+        ready: false,
         #[cfg(feature = "dump")]
         dump_areas: dump::initialize_dump_areas(),
+        #[cfg(feature = "spurious-irq")]
+        spurious_irqs: Default::default(),
     };
     let mut buf = [0u8; idl::INCOMING_SIZE];
 
@@ -152,8 +205,47 @@ struct ServerImpl<'s> {
     task_states: &'s mut [TaskStatus; NUM_TASKS],
     deadline: u64,
     reset_reason: ResetReason,
+    faults: FaultWindows,
+    watchdog_fed_at: u64,
+    /// Whether we were ready, last we checked; when this changes, we tell
+    /// the readiness mailing list.
+    ready: bool,
     #[cfg(feature = "dump")]
     dump_areas: u32,
+    /// Spurious interrupt counts, as last logged.
+    #[cfg(feature = "spurious-irq")]
+    spurious_irqs: abi::SpuriousIrqCounts,
+}
+
+/// Fault counts for the current and previous `FAULT_WINDOW`s.
+struct FaultWindows {
+    end: u64,
+    current: u32,
+    previous: u32,
+}
+
+impl FaultWindows {
+    fn tick(&mut self, now: u64) {
+        if now >= self.end {
+            // If a whole window has gone by since the last tick, the previous
+            // one was empty.
+            self.previous = if now >= self.end + FAULT_WINDOW {
+                0
+            } else {
+                self.current
+            };
+            self.current = 0;
+            self.end = now + FAULT_WINDOW;
+        }
+    }
+
+    fn storming(&self) -> bool {
+        self.current + self.previous > FAULT_STORM_LIMIT
+    }
+
+    fn any(&self) -> bool {
+        self.current + self.previous != 0
+    }
 }
 
 impl idl::InOrderJefeImpl for ServerImpl<'_> {
@@ -166,6 +258,31 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
         kipc::system_restart();
     }
 
+    fn get_health(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<u32, RequestError<Infallible>> {
+        self.faults.tick(sys_get_timer().now);
+        Ok(self.health())
+    }
+
+    fn get_readiness(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<u32, RequestError<Infallible>> {
+        Ok(self.update_readiness(sys_get_timer().now))
+    }
+
+    fn watchdog_fed(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), RequestError<Infallible>> {
+        let now = sys_get_timer().now;
+        self.watchdog_fed_at = now;
+        self.update_readiness(now);
+        Ok(())
+    }
+
     fn get_reset_reason(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -207,6 +324,102 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
         Ok(())
     }
 
+    fn hold_task(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<(), RequestError<TaskControlError>> {
+        self.control_task(task_index, external::Request::Hold)
+    }
+
+    fn release_task(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<(), RequestError<TaskControlError>> {
+        self.control_task(task_index, external::Request::Release)
+    }
+
+    fn restart_task(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<(), RequestError<TaskControlError>> {
+        self.control_task(task_index, external::Request::Start)
+    }
+
+    fn fault_task(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<(), RequestError<TaskControlError>> {
+        self.control_task(task_index, external::Request::Fault)
+    }
+
+    fn restart_count(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<u32, RequestError<TaskControlError>> {
+        let state = self
+            .task_states
+            .get(task_index as usize)
+            .ok_or(TaskControlError::BadTask)?;
+        Ok(state.restart_count)
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "ringbuf-registry")] {
+            fn ringbuf_count(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+            ) -> Result<u32, RequestError<Infallible>> {
+                Ok(registry::count())
+            }
+
+            fn ringbuf_info(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                index: u32,
+            ) -> Result<RingbufInfo, RequestError<RingbufError>> {
+                registry::info(index).map_err(|e| e.into())
+            }
+
+            fn read_ringbuf_entry(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                index: u32,
+                entry: u32,
+            ) -> Result<RingbufEntryData, RequestError<RingbufError>> {
+                registry::read_entry(index, entry).map_err(|e| e.into())
+            }
+        } else {
+            fn ringbuf_count(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+            ) -> Result<u32, RequestError<Infallible>> {
+                Ok(0)
+            }
+
+            fn ringbuf_info(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                _index: u32,
+            ) -> Result<RingbufInfo, RequestError<RingbufError>> {
+                Err(RingbufError::Unsupported.into())
+            }
+
+            fn read_ringbuf_entry(
+                &mut self,
+                _msg: &userlib::RecvMessage,
+                _index: u32,
+                _entry: u32,
+            ) -> Result<RingbufEntryData, RequestError<RingbufError>> {
+                Err(RingbufError::Unsupported.into())
+            }
+        }
+    }
+
     cfg_if::cfg_if! {
         if #[cfg(feature = "dump")] {
             fn get_dump_area(
@@ -334,11 +547,133 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
 struct TaskStatus {
     disposition: Disposition,
     holding_fault: bool,
+    /// When a faulted task is due to be restarted, if its restart policy has
+    /// it waiting
+    restart_at: Option<u64>,
+    restarts: RestartHistory,
+    /// Times we've restarted the task since boot (wrapping)
+    restart_count: u32,
+}
+
+impl TaskStatus {
+    /// Restarts task `ndx`, whose status this is, counting the restart.
+    pub(crate) fn restart(&mut self, ndx: usize) {
+        self.restart_count = self.restart_count.wrapping_add(1);
+        kipc::restart_task(ndx, true);
+    }
+}
+
+impl ServerImpl<'_> {
+    /// Restarts task `i`, and then any tasks that depend on it (unless
+    /// they're being held at a fault).
+    fn restart_task(&mut self, i: usize) {
+        self.task_states[i].restart_at = None;
+        self.task_states[i].restart(i);
+
+        for &d in policy::dependents(i) {
+            let status = &mut self.task_states[d as usize];
+            if !status.holding_fault {
+                status.restart_at = None;
+                status.restart(d as usize);
+            }
+        }
+    }
+
+    fn control_task(
+        &mut self,
+        task_index: u32,
+        request: external::Request,
+    ) -> Result<(), RequestError<TaskControlError>> {
+        let ndx = task_index as usize;
+        if ndx == 0 {
+            // We don't get to fault ourselves.
+            return Err(TaskControlError::IllegalTask.into());
+        }
+        let state = self
+            .task_states
+            .get_mut(ndx)
+            .ok_or(TaskControlError::BadTask)?;
+        external::apply(state, ndx, request);
+        Ok(())
+    }
+
+    fn health(&self) -> u32 {
+        let mut bits = 0;
+        if self.task_states.iter().any(|s| s.holding_fault) {
+            bits |= health::TASK_HELD;
+        }
+        if self.faults.storming() {
+            bits |= health::FAULT_STORM;
+        }
+        bits
+    }
+
+    /// Works out the `readiness` bits, and tells the readiness mailing list
+    /// if whether we're ready has changed since last time.
+    fn update_readiness(&mut self, now: u64) -> u32 {
+        self.faults.tick(now);
+
+        let mut bits = 0;
+        if self.health() != 0 {
+            bits |= readiness::UNHEALTHY;
+        }
+        if self.faults.any() {
+            bits |= readiness::RECENT_FAULT;
+        }
+        let down = generated::CRITICAL_TASKS.iter().any(|&t| {
+            let status = &self.task_states[t as usize];
+            status.holding_fault
+                || status.restart_at.is_some()
+                || !matches!(
+                    kipc::read_task_status(t as usize),
+                    abi::TaskState::Healthy(s) if s != abi::SchedState::Stopped
+                )
+        });
+        if down {
+            bits |= readiness::CRITICAL_TASK_DOWN;
+        }
+        if let Some(ms) = generated::WATCHDOG_STARVED_MS {
+            if now >= self.watchdog_fed_at + ms {
+                bits |= readiness::WATCHDOG_STARVED;
+            }
+        }
+
+        let ready = bits == 0;
+        if ready != self.ready {
+            self.ready = ready;
+            if ready {
+                sys_log!("SP ready");
+            } else {
+                sys_log!("SP not ready: {:#x}", bits);
+            }
+            for (task, mask) in generated::READINESS_MAILING_LIST {
+                let taskid =
+                    TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+                let taskid = sys_refresh_task_id(taskid);
+                sys_post(taskid, mask);
+            }
+        }
+        bits
+    }
+
+    /// Sets our timer for whichever comes first: our next periodic check, or
+    /// the next restart a policy has us waiting for.
+    fn set_timer(&self) {
+        let next = self
+            .task_states
+            .iter()
+            .filter_map(|s| s.restart_at)
+            .fold(self.deadline, u64::min);
+        sys_set_timer(Some(next), notifications::TIMER_MASK);
+    }
 }
 
 impl idol_runtime::NotificationHandler for ServerImpl<'_> {
     fn current_notification_mask(&self) -> u32 {
-        notifications::FAULT_MASK | notifications::TIMER_MASK
+        let mask = notifications::FAULT_MASK | notifications::TIMER_MASK;
+        #[cfg(feature = "spurious-irq")]
+        let mask = mask | notifications::SPURIOUS_IRQ_MASK;
+        mask
     }
 
     fn handle_notification(&mut self, bits: u32) {
@@ -346,10 +681,24 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
         external::check(self.task_states);
 
         if bits & notifications::TIMER_MASK != 0 {
-            // If our timer went off, we need to reestablish it
-            if sys_get_timer().now >= self.deadline {
+            let now = sys_get_timer().now;
+            if now >= self.deadline {
                 self.deadline += TIMER_INTERVAL;
-                sys_set_timer(Some(self.deadline), notifications::TIMER_MASK);
+            }
+            self.faults.tick(now);
+
+            // Restart anything whose backoff is up.
+            for i in 0..self.task_states.len() {
+                if self.task_states[i].restart_at.map_or(false, |t| now >= t) {
+                    self.restart_task(i);
+                }
+            }
+        }
+
+        #[cfg(feature = "spurious-irq")]
+        {
+            if bits & notifications::SPURIOUS_IRQ_MASK != 0 {
+                log_spurious_irqs(&mut self.spurious_irqs);
             }
         }
 
@@ -361,10 +710,14 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
             //
             // TODO: it would be fantastic to have a way of finding this out in
             // one syscall.
-            for (i, status) in self.task_states.iter_mut().enumerate() {
+            let mut any_faulted = false;
+            let now = sys_get_timer().now;
+            for i in 0..self.task_states.len() {
+                let status = &mut self.task_states[i];
+
                 // If we're aware that this task is in a fault state, don't
                 // bother making a syscall to enquire.
-                if status.holding_fault {
+                if status.holding_fault || status.restart_at.is_some() {
                     continue;
                 }
 
@@ -372,6 +725,9 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
                     abi::TaskState::Faulted { fault, .. } => {
                         // Well! A fault we didn't know about.
                         log_fault(i, &fault);
+                        any_faulted = true;
+                        self.faults.current =
+                            self.faults.current.saturating_add(1);
 
                         #[cfg(feature = "dump")]
                         {
@@ -383,13 +739,40 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
                             _ = dump::dump_task(self.dump_areas, i);
                         }
 
-                        if status.disposition == Disposition::Restart {
-                            // Stand it back up
-                            kipc::restart_task(i, true);
-                        } else {
+                        if status.disposition == Disposition::Hold {
                             // Mark this one off so we don't revisit it until
                             // requested.
                             status.holding_fault = true;
+                            continue;
+                        }
+
+                        let Some(policy) = RestartPolicy::for_task(i) else {
+                            // Stand it back up
+                            self.restart_task(i);
+                            continue;
+                        };
+                        match policy.on_fault(&mut status.restarts, now) {
+                            Verdict::RestartAt(t) if t <= now => {
+                                self.restart_task(i);
+                            }
+                            Verdict::RestartAt(t) => {
+                                // Leave it faulted until its backoff is up.
+                                status.restart_at = Some(t);
+                            }
+                            Verdict::Escalate(Escalation::Hold) => {
+                                sys_log!(
+                                    "Task #{} faulting too often; holding",
+                                    i
+                                );
+                                status.holding_fault = true;
+                            }
+                            Verdict::Escalate(Escalation::Reset) => {
+                                sys_log!(
+                                    "Task #{} faulting too often; resetting",
+                                    i
+                                );
+                                kipc::system_restart();
+                            }
                         }
                     }
 
@@ -397,7 +780,26 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
                     _ => (),
                 }
             }
+
+            // Let anyone who cares about faults (say, to clean up after a
+            // client that died holding a resource) know.
+            if any_faulted {
+                for (task, mask) in generated::FAULT_MAILING_LIST {
+                    let taskid = TaskId::for_index_and_gen(
+                        task as usize,
+                        Generation::ZERO,
+                    );
+                    let taskid = sys_refresh_task_id(taskid);
+                    sys_post(taskid, mask);
+                }
+            }
         }
+
+        // Faults, restarts, and the passage of time can all change whether
+        // we're ready.
+        self.update_readiness(sys_get_timer().now);
+
+        self.set_timer();
     }
 }
 
@@ -410,6 +812,9 @@ include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
 
 // And the Idol bits
 mod idl {
-    use task_jefe_api::{DumpAgentError, ResetReason};
+    use task_jefe_api::{
+        DumpAgentError, ResetReason, RingbufEntryData, RingbufError,
+        RingbufInfo, TaskControlError,
+    };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
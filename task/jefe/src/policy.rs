@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Restart policies, for tasks that shouldn't just be restarted the moment
+//! they fault, every time.
+//!
+//! A task with a policy is restarted after a delay that doubles with each
+//! restart in the policy's window, so that a task that keeps falling over
+//! (say, because something it depends on is sick) backs off rather than
+//! spinning. If it faults more than the policy allows in one window, we stop
+//! restarting it and escalate instead. Tasks without a policy are restarted
+//! right away, with no limit.
+
+use crate::generated::{DEPENDENCY_GROUPS, RESTART_POLICIES};
+
+// Only constructed by the generated config, if the app has any policies.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Escalation {
+    /// Hold the task at its fault, as if its disposition were `Hold`.
+    Hold,
+    /// Reset the whole system.
+    Reset,
+}
+
+#[allow(dead_code)]
+pub(crate) struct RestartPolicy {
+    /// Delay before the first restart in a window
+    pub backoff_ms: u32,
+    /// Cap on the delay, however many restarts there have been
+    pub max_backoff_ms: u32,
+    /// Restarts allowed in a window before we escalate
+    pub max_restarts: u32,
+    pub window_ms: u32,
+    pub escalation: Escalation,
+}
+
+/// What happens to a task that has just faulted.
+pub(crate) enum Verdict {
+    /// Restart it at this time
+    RestartAt(u64),
+    Escalate(Escalation),
+}
+
+/// A task's restarts in its policy's current window.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct RestartHistory {
+    window_start: u64,
+    restarts: u32,
+}
+
+impl RestartPolicy {
+    pub(crate) fn for_task(task: usize) -> Option<&'static Self> {
+        RESTART_POLICIES
+            .iter()
+            .find(|(t, _)| *t as usize == task)
+            .map(|(_, p)| p)
+    }
+
+    pub(crate) fn on_fault(
+        &self,
+        history: &mut RestartHistory,
+        now: u64,
+    ) -> Verdict {
+        if history.restarts == 0
+            || now >= history.window_start + u64::from(self.window_ms)
+        {
+            history.window_start = now;
+            history.restarts = 0;
+        }
+        if history.restarts >= self.max_restarts {
+            return Verdict::Escalate(self.escalation);
+        }
+
+        let delay = (u64::from(self.backoff_ms) << history.restarts.min(31))
+            .min(u64::from(self.max_backoff_ms));
+        history.restarts += 1;
+        Verdict::RestartAt(now + delay)
+    }
+}
+
+/// Returns the tasks to restart after `task` is restarted, because they
+/// depend on it.
+pub(crate) fn dependents(task: usize) -> &'static [hubris_num_tasks::Task] {
+    DEPENDENCY_GROUPS
+        .iter()
+        .find(|(t, _)| *t as usize == task)
+        .map(|(_, d)| *d)
+        .unwrap_or(&[])
+}
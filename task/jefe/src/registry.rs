@@ -0,0 +1,186 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The ringbuf registry, which lets ringbufs be read over IPC rather than
+//! only with a debugger.
+//!
+//! We leave the registry zeroed, and the build fills it in once every task is
+//! linked, from the descriptions that `ringbuf!` leaves in each task's
+//! `.ringbuf_registry` section. Ringbufs are read with the same kernel
+//! interface as task dumps (so the kernel needs its `dump` feature), after
+//! checking that they're in memory the task could dump anyway.
+
+use crate::generated::MAX_RINGBUFS;
+use task_jefe_api::{
+    RingbufEntryData, RingbufError, RingbufInfo, RINGBUF_NAME_LEN,
+    RINGBUF_PAYLOAD_MAX,
+};
+use userlib::*;
+
+/// A ringbuf, as the build describes it; the layout is shared with
+/// `build/xtask/src/ringbuf.rs`. Offsets are from `address`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct Entry {
+    task: u32,
+    address: u32,
+    size: u32,
+    buffer_offset: u32,
+    entries: u32,
+    entry_size: u32,
+    payload_offset: u32,
+    payload_size: u32,
+    dropped_offset: u32,
+    flags: u32,
+    name_len: u32,
+    name: [u8; RINGBUF_NAME_LEN],
+}
+
+impl Entry {
+    const EMPTY: Self = Self {
+        task: 0,
+        address: 0,
+        size: 0,
+        buffer_offset: 0,
+        entries: 0,
+        entry_size: 0,
+        payload_offset: 0,
+        payload_size: 0,
+        dropped_offset: 0,
+        flags: 0,
+        name_len: 0,
+        name: [0; RINGBUF_NAME_LEN],
+    };
+}
+
+/// Filled in by the build; found by its (unmangled) name. Entries are packed
+/// at the front, and the rest left empty.
+#[no_mangle]
+#[used]
+static RINGBUF_REGISTRY: [Entry; MAX_RINGBUFS] = [Entry::EMPTY; MAX_RINGBUFS];
+
+fn entry(index: u32) -> Result<Entry, RingbufError> {
+    let e = RINGBUF_REGISTRY
+        .get(index as usize)
+        .ok_or(RingbufError::NoSuchRingbuf)?;
+    // The compiler thinks it knows what's in here (zeroes), so we need to
+    // keep it from folding the read away.
+    let e = unsafe { core::ptr::read_volatile(e) };
+    if e.size == 0 {
+        return Err(RingbufError::NoSuchRingbuf);
+    }
+    Ok(e)
+}
+
+pub fn count() -> u32 {
+    (0..MAX_RINGBUFS as u32)
+        .take_while(|&i| entry(i).is_ok())
+        .count() as u32
+}
+
+/// Reads `buf.len()` bytes at `offset` into ringbuf `e`.
+fn read(e: &Entry, offset: u32, buf: &mut [u8]) -> Result<(), RingbufError> {
+    let len = buf.len() as u32;
+    if offset.checked_add(len).map_or(true, |end| end > e.size) {
+        return Err(RingbufError::BadRingbuf);
+    }
+    let base = e.address + offset;
+
+    // The kernel will fault us for reading anything the task couldn't dump,
+    // so make sure the whole ringbuf is in one of its regions. (Region 0 is
+    // the task's TCB.)
+    let task = e.task as usize;
+    let in_task = (1..)
+        .map_while(|r| kipc::get_task_dump_region(task, r))
+        .any(|r| {
+            e.address >= r.base
+                && u64::from(e.address) + u64::from(e.size)
+                    <= u64::from(r.base) + u64::from(r.size)
+        });
+    if !in_task {
+        return Err(RingbufError::BadRingbuf);
+    }
+
+    let region = abi::TaskDumpRegion { base, size: len };
+    if kipc::read_task_dump_region(task, region, buf) != buf.len() {
+        return Err(RingbufError::BadRingbuf);
+    }
+    Ok(())
+}
+
+/// The line, generation, and count that start each entry.
+fn entry_header(e: &Entry, n: u32) -> Result<(u16, u16, u32), RingbufError> {
+    let mut header = [0u8; 8];
+    read(e, e.buffer_offset + n * e.entry_size, &mut header)?;
+    Ok((
+        u16::from_le_bytes([header[0], header[1]]),
+        u16::from_le_bytes([header[2], header[3]]),
+        u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+    ))
+}
+
+pub fn info(index: u32) -> Result<RingbufInfo, RingbufError> {
+    let e = entry(index)?;
+
+    // Rather than decode the ringbuf's `last` (an `Option`, whose layout
+    // isn't ours to rely on), we work it out from the entries: they're
+    // written in order, and each one written bumps its generation, so the
+    // most recent is the last one with the same generation as the first.
+    let written =
+        |n| entry_header(&e, n).map(|(_, gen, count)| (gen, count != 0));
+    let (first_gen, any) = written(0)?;
+    let last = if !any {
+        u32::MAX
+    } else {
+        // Entry `lo` is in the run; entry `hi` isn't (or doesn't exist).
+        let (mut lo, mut hi) = (0, e.entries);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            match written(mid)? {
+                (gen, true) if gen == first_gen => lo = mid,
+                _ => hi = mid,
+            }
+        }
+        lo
+    };
+
+    let mut dropped = [0u8; 4];
+    read(&e, e.dropped_offset, &mut dropped)?;
+
+    Ok(RingbufInfo {
+        task: e.task,
+        entries: e.entries,
+        payload_size: e.payload_size,
+        last,
+        dropped: u32::from_le_bytes(dropped),
+        flags: e.flags,
+        name_len: e.name_len,
+        name: e.name,
+    })
+}
+
+pub fn read_entry(
+    index: u32,
+    n: u32,
+) -> Result<RingbufEntryData, RingbufError> {
+    let e = entry(index)?;
+    if n >= e.entries {
+        return Err(RingbufError::NoSuchEntry);
+    }
+
+    let (line, generation, count) = entry_header(&e, n)?;
+    let mut data = RingbufEntryData {
+        line,
+        generation,
+        count,
+        payload_len: e.payload_size.min(RINGBUF_PAYLOAD_MAX as u32),
+        payload: [0; RINGBUF_PAYLOAD_MAX],
+    };
+    read(
+        &e,
+        e.buffer_offset + n * e.entry_size + e.payload_offset,
+        &mut data.payload[..data.payload_len as usize],
+    )?;
+    Ok(data)
+}
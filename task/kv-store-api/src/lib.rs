@@ -0,0 +1,80 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the RoT key-value store.
+//!
+//! The store holds a small, fixed set of values that have to survive resets
+//! and power loss. Each [`Key`] has a [`Kind`], which decides how its value
+//! may change: counters only count up, epochs only move forward, and flags
+//! are set and cleared. A change is durable once the call that made it
+//! returns `Ok`; if power is lost before then, the store comes back with
+//! the value from before.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::AsBytes;
+
+/// Number of keys the store has room for.
+pub const MAX_KEYS: usize = 32;
+
+/// A value in the store.
+///
+/// Keys are stored by number, so new ones go at the end, no key is ever
+/// renumbered, and a key's kind never changes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum Key {
+    /// Number of attestations signed
+    AttestationCounter = 0,
+    /// Lowest image epoch we'll accept
+    RollbackEpoch = 1,
+    /// Which debug features are enabled
+    DebugPolicy = 2,
+    /// Nonzero once the identity certificate chain has been locked
+    CertLock = 3,
+}
+
+const _: () = assert!((Key::CertLock as usize) < MAX_KEYS);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// Counts up from zero, with `increment`
+    Counter,
+    /// Moves forward, but never back, with `advance`
+    Epoch,
+    /// Bits set and cleared with `update_flags`, all clear to start with
+    Flags,
+}
+
+impl Key {
+    pub fn kind(self) -> Kind {
+        match self {
+            Key::AttestationCounter => Kind::Counter,
+            Key::RollbackEpoch => Kind::Epoch,
+            Key::DebugPolicy => Kind::Flags,
+            Key::CertLock => Kind::Epoch,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+pub enum KvError {
+    /// The key has never been given a value.
+    NotSet = 1,
+    /// The operation doesn't apply to the key's kind.
+    WrongKind,
+    /// The counter can't count any higher.
+    Overflow,
+    /// The epoch is behind the one already stored.
+    Regression,
+    /// The change couldn't be written to flash, so hasn't been made.
+    FlashError,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,557 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! RoT key-value store.
+//!
+//! We keep every value in RAM, and a snapshot of them all -- with a
+//! generation number and a CRC -- in one of two pages of persistent
+//! storage. To commit a change, we write a new snapshot, with the next
+//! generation, to the other page; at boot, we take the valid snapshot with
+//! the higher generation. The page holding the latest snapshot is never
+//! touched until a newer one has been written and checked, so losing power
+//! partway through a commit leaves us with either the old values or the
+//! new ones, never neither.
+//!
+//! Every commit costs an erase, so this is for values that change now and
+//! then, not constantly.
+
+#![no_std]
+#![cfg_attr(not(test), no_main)]
+
+use drv_lpc55_flash_api::{Flash, FlashError, FlashRegion, PAGE_SIZE};
+use idol_runtime::RequestError;
+use ringbuf::*;
+use task_kv_store_api::{Key, Kind, KvError, MAX_KEYS};
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+task_slot!(FLASH, flash);
+
+/// The pages of our storage partition we alternate between.
+const PAGES: [u32; 2] = [0, 1];
+
+const MAGIC: u32 = 0x5453_564b; // "KVST"
+
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+struct Snapshot {
+    magic: u32,
+    /// Goes up by one with each commit
+    generation: u32,
+    /// Bit `n` is set if key `n` has a value
+    present: u32,
+    _pad: u32,
+    values: [u64; MAX_KEYS],
+    /// CRC of everything before it
+    checksum: u32,
+    _pad2: u32,
+}
+
+const _: () = assert!(core::mem::size_of::<Snapshot>() <= PAGE_SIZE);
+
+impl Snapshot {
+    fn empty() -> Self {
+        Self {
+            magic: MAGIC,
+            generation: 0,
+            present: 0,
+            _pad: 0,
+            values: [0; MAX_KEYS],
+            checksum: 0,
+            _pad2: 0,
+        }
+    }
+
+    fn expected_checksum(&self) -> u32 {
+        let c = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+        let bytes = self.as_bytes();
+        c.checksum(&bytes[..bytes.len() - 2 * core::mem::size_of::<u32>()])
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.expected_checksum()
+    }
+
+    fn get(&self, key: Key) -> Option<u64> {
+        if self.present & (1 << key as u32) != 0 {
+            Some(self.values[key as usize])
+        } else {
+            None
+        }
+    }
+
+    fn set(&mut self, key: Key, value: u64) {
+        self.present |= 1 << key as u32;
+        self.values[key as usize] = value;
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Loaded { page: u32, generation: u32 },
+    Unreadable { page: u32, err: FlashError },
+    Invalid { page: u32 },
+    Empty,
+    Committed { page: u32, generation: u32 },
+    CommitFailed(FlashError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Our storage partition, as the flash server provides it
+trait Pages {
+    fn read(
+        &self,
+        page: u32,
+        buf: &mut [u8; PAGE_SIZE],
+    ) -> Result<(), FlashError>;
+    fn write(&self, page: u32, buf: &[u8; PAGE_SIZE])
+        -> Result<(), FlashError>;
+}
+
+impl Pages for Flash {
+    fn read(
+        &self,
+        page: u32,
+        buf: &mut [u8; PAGE_SIZE],
+    ) -> Result<(), FlashError> {
+        Flash::read(self, FlashRegion::Storage, page, buf)
+    }
+
+    fn write(
+        &self,
+        page: u32,
+        buf: &[u8; PAGE_SIZE],
+    ) -> Result<(), FlashError> {
+        self.erase(FlashRegion::Storage, page)?;
+        self.program(FlashRegion::Storage, page, buf)
+    }
+}
+
+/// Returns true if `a` is a later generation than `b`.
+///
+/// Generations wrap, so this holds if `a` is less than half the space of
+/// generations ahead of `b`. The two pages are never more than one commit
+/// apart, so that's always enough.
+fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Reads the snapshot in `page`, if it holds a valid one.
+fn load(pages: &impl Pages, page: u32) -> Option<Snapshot> {
+    let mut buf = [0u8; PAGE_SIZE];
+    loop {
+        match pages.read(page, &mut buf) {
+            Ok(()) => break,
+            // Nothing's been written here, or a commit was cut short.
+            Err(e @ (FlashError::Erased | FlashError::Corrupt)) => {
+                ringbuf_entry!(Trace::Unreadable { page, err: e });
+                return None;
+            }
+            // If we carried on without this page, we could go back to older
+            // values, and then overwrite the newer ones; better to keep
+            // trying.
+            Err(FlashError::ServerRestarted) => continue,
+            Err(_) => panic!(),
+        }
+    }
+
+    let snapshot = Snapshot::read_from_prefix(&buf[..]).unwrap_lite();
+    if snapshot.is_valid() {
+        ringbuf_entry!(Trace::Loaded {
+            page,
+            generation: snapshot.generation
+        });
+        Some(snapshot)
+    } else {
+        ringbuf_entry!(Trace::Invalid { page });
+        None
+    }
+}
+
+struct ServerImpl<P: Pages> {
+    pages: P,
+    /// The values as last committed
+    current: Snapshot,
+    /// Index into `PAGES` of the page holding `current`, if any does
+    page: Option<usize>,
+}
+
+impl<P: Pages> ServerImpl<P> {
+    fn new(pages: P) -> Self {
+        let mut best: Option<(usize, Snapshot)> = None;
+        for (i, &page) in PAGES.iter().enumerate() {
+            if let Some(s) = load(&pages, page) {
+                match best {
+                    Some((_, b)) if !is_newer(s.generation, b.generation) => (),
+                    _ => best = Some((i, s)),
+                }
+            }
+        }
+
+        let (page, current) = match best {
+            Some((i, s)) => (Some(i), s),
+            None => {
+                ringbuf_entry!(Trace::Empty);
+                (None, Snapshot::empty())
+            }
+        };
+        Self {
+            pages,
+            current,
+            page,
+        }
+    }
+
+    /// Writes `next` to the page not holding the current snapshot, and makes
+    /// it current.
+    fn commit(&mut self, mut next: Snapshot) -> Result<(), KvError> {
+        let target = match self.page {
+            Some(i) => 1 - i,
+            None => 0,
+        };
+        let page = PAGES[target];
+
+        next.generation = self.current.generation.wrapping_add(1);
+        next.checksum = next.expected_checksum();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        next.write_to_prefix(&mut buf[..]).unwrap_lite();
+
+        if let Err(e) = self.pages.write(page, &buf) {
+            ringbuf_entry!(Trace::CommitFailed(e));
+            return Err(KvError::FlashError);
+        }
+
+        ringbuf_entry!(Trace::Committed {
+            page,
+            generation: next.generation
+        });
+        self.current = next;
+        self.page = Some(target);
+        Ok(())
+    }
+
+    fn check_kind(key: Key, kind: Kind) -> Result<(), KvError> {
+        if key.kind() == kind {
+            Ok(())
+        } else {
+            Err(KvError::WrongKind)
+        }
+    }
+
+    fn get(&self, key: Key) -> Result<u64, KvError> {
+        self.current.get(key).ok_or(KvError::NotSet)
+    }
+
+    fn increment(&mut self, key: Key) -> Result<u64, KvError> {
+        Self::check_kind(key, Kind::Counter)?;
+        let value = self
+            .current
+            .get(key)
+            .unwrap_or(0)
+            .checked_add(1)
+            .ok_or(KvError::Overflow)?;
+
+        let mut next = self.current;
+        next.set(key, value);
+        self.commit(next)?;
+        Ok(value)
+    }
+
+    fn advance(&mut self, key: Key, value: u64) -> Result<(), KvError> {
+        Self::check_kind(key, Kind::Epoch)?;
+        match self.current.get(key) {
+            Some(v) if value < v => return Err(KvError::Regression),
+            Some(v) if value == v => return Ok(()),
+            _ => (),
+        }
+
+        let mut next = self.current;
+        next.set(key, value);
+        self.commit(next)?;
+        Ok(())
+    }
+
+    fn update_flags(
+        &mut self,
+        key: Key,
+        set: u64,
+        clear: u64,
+    ) -> Result<u64, KvError> {
+        Self::check_kind(key, Kind::Flags)?;
+        let old = self.current.get(key);
+        let value = (old.unwrap_or(0) & !clear) | set;
+        if old == Some(value) {
+            return Ok(value);
+        }
+
+        let mut next = self.current;
+        next.set(key, value);
+        self.commit(next)?;
+        Ok(value)
+    }
+}
+
+impl<P: Pages> idl::InOrderKvStoreImpl for ServerImpl<P> {
+    fn get(
+        &mut self,
+        _: &RecvMessage,
+        key: Key,
+    ) -> Result<u64, RequestError<KvError>> {
+        Ok(ServerImpl::get(self, key)?)
+    }
+
+    fn increment(
+        &mut self,
+        _: &RecvMessage,
+        key: Key,
+    ) -> Result<u64, RequestError<KvError>> {
+        Ok(ServerImpl::increment(self, key)?)
+    }
+
+    fn advance(
+        &mut self,
+        _: &RecvMessage,
+        key: Key,
+        value: u64,
+    ) -> Result<(), RequestError<KvError>> {
+        Ok(ServerImpl::advance(self, key, value)?)
+    }
+
+    fn update_flags(
+        &mut self,
+        _: &RecvMessage,
+        key: Key,
+        set: u64,
+        clear: u64,
+    ) -> Result<u64, RequestError<KvError>> {
+        Ok(ServerImpl::update_flags(self, key, set, clear)?)
+    }
+}
+
+#[cfg_attr(not(test), export_name = "main")]
+fn main() -> ! {
+    let mut server = ServerImpl::new(Flash::from(FLASH.get_task_id()));
+    let mut incoming = [0u8; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch(&mut incoming, &mut server);
+    }
+}
+
+mod idl {
+    use task_kv_store_api::{Key, KvError};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+
+    /// Storage in RAM, which can be told to fail writes
+    #[derive(Default)]
+    struct FakePages {
+        pages: RefCell<[Option<[u8; PAGE_SIZE]>; 2]>,
+        fail_writes: Cell<bool>,
+    }
+
+    impl Pages for &FakePages {
+        fn read(
+            &self,
+            page: u32,
+            buf: &mut [u8; PAGE_SIZE],
+        ) -> Result<(), FlashError> {
+            let p = self.pages.borrow()[page as usize];
+            *buf = p.ok_or(FlashError::Erased)?;
+            Ok(())
+        }
+
+        fn write(
+            &self,
+            page: u32,
+            buf: &[u8; PAGE_SIZE],
+        ) -> Result<(), FlashError> {
+            // A failed write leaves the page erased, at best.
+            let mut pages = self.pages.borrow_mut();
+            pages[page as usize] = None;
+            if self.fail_writes.get() {
+                return Err(FlashError::FlashError);
+            }
+            pages[page as usize] = Some(*buf);
+            Ok(())
+        }
+    }
+
+    fn generation(pages: &FakePages, page: usize) -> Option<u32> {
+        let p = pages.pages.borrow()[page]?;
+        Some(Snapshot::read_from_prefix(&p[..]).unwrap().generation)
+    }
+
+    #[test]
+    fn starts_empty() {
+        let pages = FakePages::default();
+        let server = ServerImpl::new(&pages);
+        assert_eq!(server.get(Key::RollbackEpoch), Err(KvError::NotSet));
+        assert_eq!(server.page, None);
+    }
+
+    #[test]
+    fn commits_alternate_pages() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        assert_eq!(server.increment(Key::AttestationCounter), Ok(1));
+        assert_eq!(generation(&pages, 0), Some(1));
+        assert_eq!(generation(&pages, 1), None);
+
+        assert_eq!(server.increment(Key::AttestationCounter), Ok(2));
+        assert_eq!(generation(&pages, 0), Some(1));
+        assert_eq!(generation(&pages, 1), Some(2));
+
+        assert_eq!(server.increment(Key::AttestationCounter), Ok(3));
+        assert_eq!(generation(&pages, 0), Some(3));
+    }
+
+    #[test]
+    fn reloads_newest() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        server.advance(Key::RollbackEpoch, 5).unwrap();
+        server.advance(Key::RollbackEpoch, 7).unwrap();
+        server.update_flags(Key::DebugPolicy, 0b101, 0).unwrap();
+
+        let server = ServerImpl::new(&pages);
+        assert_eq!(server.get(Key::RollbackEpoch), Ok(7));
+        assert_eq!(server.get(Key::DebugPolicy), Ok(0b101));
+        assert_eq!(server.get(Key::AttestationCounter), Err(KvError::NotSet));
+    }
+
+    #[test]
+    fn failed_commit_keeps_old_values() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        server.advance(Key::RollbackEpoch, 5).unwrap();
+
+        pages.fail_writes.set(true);
+        assert_eq!(
+            server.advance(Key::RollbackEpoch, 6),
+            Err(KvError::FlashError)
+        );
+        assert_eq!(server.get(Key::RollbackEpoch), Ok(5));
+
+        // Nor does a reboot bring back anything but the old value.
+        let server = ServerImpl::new(&pages);
+        assert_eq!(server.get(Key::RollbackEpoch), Ok(5));
+
+        // And the next commit still goes to the page we failed to write.
+        pages.fail_writes.set(false);
+        let mut server = server;
+        server.advance(Key::RollbackEpoch, 6).unwrap();
+        assert_eq!(generation(&pages, 0), Some(1));
+        assert_eq!(generation(&pages, 1), Some(2));
+    }
+
+    #[test]
+    fn ignores_corrupt_snapshot() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        server.advance(Key::RollbackEpoch, 5).unwrap();
+        server.advance(Key::RollbackEpoch, 6).unwrap();
+
+        // Flip a bit in the newer snapshot's value.
+        let offset = core::mem::size_of::<u32>() * 4
+            + Key::RollbackEpoch as usize * core::mem::size_of::<u64>();
+        pages.pages.borrow_mut()[1].as_mut().unwrap()[offset] ^= 1;
+
+        let server = ServerImpl::new(&pages);
+        assert_eq!(server.get(Key::RollbackEpoch), Ok(5));
+        assert_eq!(server.page, Some(0));
+    }
+
+    #[test]
+    fn generations_wrap() {
+        assert!(is_newer(1, 0));
+        assert!(!is_newer(0, 1));
+        assert!(!is_newer(3, 3));
+        assert!(is_newer(0, u32::MAX));
+        assert!(!is_newer(u32::MAX, 0));
+
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        server.current.generation = u32::MAX - 1;
+        server.advance(Key::RollbackEpoch, 1).unwrap();
+        server.advance(Key::RollbackEpoch, 2).unwrap();
+        assert_eq!(generation(&pages, 0), Some(u32::MAX));
+        assert_eq!(generation(&pages, 1), Some(0));
+
+        let server = ServerImpl::new(&pages);
+        assert_eq!(server.get(Key::RollbackEpoch), Ok(2));
+        assert_eq!(server.page, Some(1));
+    }
+
+    #[test]
+    fn kinds_are_enforced() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        assert_eq!(
+            server.increment(Key::RollbackEpoch),
+            Err(KvError::WrongKind)
+        );
+        assert_eq!(
+            server.advance(Key::AttestationCounter, 1),
+            Err(KvError::WrongKind)
+        );
+        assert_eq!(
+            server.update_flags(Key::RollbackEpoch, 1, 0),
+            Err(KvError::WrongKind)
+        );
+    }
+
+    #[test]
+    fn epochs_only_advance() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        server.advance(Key::RollbackEpoch, 5).unwrap();
+        assert_eq!(
+            server.advance(Key::RollbackEpoch, 4),
+            Err(KvError::Regression)
+        );
+
+        // Advancing to the same epoch doesn't cost a commit.
+        let before = server.current.generation;
+        server.advance(Key::RollbackEpoch, 5).unwrap();
+        assert_eq!(server.current.generation, before);
+    }
+
+    #[test]
+    fn counters_do_not_overflow() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        server.current.set(Key::AttestationCounter, u64::MAX);
+        assert_eq!(
+            server.increment(Key::AttestationCounter),
+            Err(KvError::Overflow)
+        );
+        assert_eq!(server.get(Key::AttestationCounter), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn flags_set_and_clear() {
+        let pages = FakePages::default();
+        let mut server = ServerImpl::new(&pages);
+        assert_eq!(server.update_flags(Key::DebugPolicy, 0b110, 0), Ok(0b110));
+        assert_eq!(
+            server.update_flags(Key::DebugPolicy, 0b001, 0b010),
+            Ok(0b101)
+        );
+
+        // Setting what's already set doesn't cost a commit.
+        let before = server.current.generation;
+        assert_eq!(server.update_flags(Key::DebugPolicy, 0b100, 0), Ok(0b101));
+        assert_eq!(server.current.generation, before);
+    }
+}
@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the log task.
+//!
+//! Tasks log an entry -- a level, a code of the task's own choosing, and a
+//! couple of scalars to go with it -- with [`Log::log`]; the log task
+//! records which task it came from, and when. Logging is cheap (the log task
+//! only copies the entry into its buffer before replying), but it is an IPC,
+//! so the log task must be higher priority than anyone logging to it.
+
+#![no_std]
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+use userlib::*;
+use zerocopy::AsBytes;
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    FromPrimitive,
+    AsBytes,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
+#[repr(u8)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, Deserialize, SerializedSize,
+)]
+pub struct LogStats {
+    /// Entries logged
+    pub logged: u32,
+    /// Entries sent to the collector
+    pub forwarded: u32,
+    /// Entries dropped, because we had no room for them before they could be
+    /// sent
+    pub dropped: u32,
+}
+
+impl Log {
+    pub fn error(&self, code: u16, arg0: u32, arg1: u32) {
+        self.log(Level::Error, code, arg0, arg1)
+    }
+
+    pub fn warn(&self, code: u16, arg0: u32, arg1: u32) {
+        self.log(Level::Warn, code, arg0, arg1)
+    }
+
+    pub fn info(&self, code: u16, arg0: u32, arg1: u32) {
+        self.log(Level::Info, code, arg0, arg1)
+    }
+
+    pub fn debug(&self, code: u16, arg0: u32, arg1: u32) {
+        self.log(Level::Debug, code, arg0, arg1)
+    }
+}
+
+/// Packets the log task sends to the collector.
+///
+/// Each packet is a hubpack-encoded [`forward::Header`], followed by `count`
+/// hubpack-encoded [`forward::Entry`]s, oldest first. Delivery is
+/// best-effort; a collector can tell it has missed a packet by a gap in
+/// `sequence`, and how many entries we've had to drop by `dropped`.
+pub mod forward {
+    use super::Level;
+    use hubpack::SerializedSize;
+    use serde::{Deserialize, Serialize};
+
+    pub const VERSION: u8 = 1;
+
+    #[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+    pub struct Header {
+        pub version: u8,
+        /// Counts packets sent since the SP booted
+        pub sequence: u32,
+        /// Entries dropped since the SP booted
+        pub dropped: u32,
+        /// Number of entries following
+        pub count: u8,
+    }
+
+    #[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+    pub struct Entry {
+        /// When the entry was logged, in milliseconds since boot
+        pub timestamp: u64,
+        /// Index of the task that logged it
+        pub task: u16,
+        pub level: Level,
+        pub code: u16,
+        pub args: [u32; 2],
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,83 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::Write;
+
+/// Task config:
+///
+/// ```toml
+/// [tasks.log.config]
+/// collector = { address = "fe80::aa40:25ff:fe04:1", port = 11116 }
+/// capacity = 64
+/// flush-ms = 1000
+/// ```
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    collector: Collector,
+    /// Entries we can hold while waiting to send them
+    #[serde(default = "TaskConfig::default_capacity")]
+    capacity: usize,
+    /// Longest we hold on to an entry before sending it, in milliseconds
+    #[serde(default = "TaskConfig::default_flush_ms")]
+    flush_ms: u64,
+}
+
+impl TaskConfig {
+    fn default_capacity() -> usize {
+        64
+    }
+
+    fn default_flush_ms() -> u64 {
+        1000
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Collector {
+    address: String,
+    port: u16,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    build_util::build_notifications()?;
+    build_util::idol::build_server_support(
+        "../../idl/log.idol",
+        "server_stub.rs",
+        idol::server::ServerStyle::InOrder,
+    )?;
+
+    write_log_config()?;
+
+    Ok(())
+}
+
+fn write_log_config() -> anyhow::Result<()> {
+    let config = build_util::task_config::<TaskConfig>()?;
+    let address: std::net::Ipv6Addr = config
+        .collector
+        .address
+        .parse()
+        .map_err(|e| anyhow::anyhow!("bad collector address: {e}"))?;
+    if config.capacity == 0 {
+        anyhow::bail!("capacity must be at least 1");
+    }
+
+    let dest_path = build_util::out_dir().join("log_config.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+    writeln!(
+        out,
+        "const COLLECTOR_ADDR: [u8; 16] = {:?};",
+        address.octets()
+    )?;
+    writeln!(
+        out,
+        "const COLLECTOR_PORT: u16 = {};",
+        config.collector.port
+    )?;
+    writeln!(out, "const CAPACITY: usize = {};", config.capacity)?;
+    writeln!(out, "const FLUSH_MS: u64 = {};", config.flush_ms)?;
+    Ok(())
+}
@@ -0,0 +1,253 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Log task
+//!
+//! Collects structured log entries from other tasks (see `task_log_api`),
+//! and forwards them in batches to a collector on the management network,
+//! in the format described in `task_log_api::forward`. We send a batch once
+//! we have a packet's worth of entries, or once the oldest has waited
+//! `flush-ms`. If entries come in faster than we can send them, we drop the
+//! oldest, and count them.
+
+#![no_std]
+#![no_main]
+
+use core::convert::Infallible;
+use hubpack::SerializedSize;
+use idol_runtime::{NotificationHandler, RequestError};
+use mutable_statics::mutable_statics;
+use ringbuf::*;
+use task_log_api::forward::{Entry, Header, VERSION};
+use task_log_api::{Level, LogStats};
+use task_net_api::{
+    Address, Ipv6Address, LargePayloadBehavior, Net, RecvError, SendError,
+    SocketName, UdpMetadata,
+};
+use userlib::*;
+
+include!(concat!(env!("OUT_DIR"), "/log_config.rs"));
+
+task_slot!(NET, net);
+
+const SOCKET: SocketName = SocketName::log;
+const SOCKET_TX_SIZE: usize = task_net_api::SOCKET_TX_SIZE[SOCKET as usize];
+const SOCKET_RX_SIZE: usize = task_net_api::SOCKET_RX_SIZE[SOCKET as usize];
+
+const _: () = assert!(SOCKET_TX_SIZE >= Header::MAX_SIZE + Entry::MAX_SIZE);
+
+/// Entries we send in a packet.
+const BATCH: usize = {
+    let n = (SOCKET_TX_SIZE - Header::MAX_SIZE) / Entry::MAX_SIZE;
+    if n > u8::MAX as usize {
+        u8::MAX as usize
+    } else {
+        n
+    }
+};
+
+/// How long to wait before trying again, when the outgoing queue is full.
+const RETRY_MS: u64 = 10;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Sent { sequence: u32, count: u8 },
+    SendError(SendError),
+    Dropped(u32),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+const EMPTY: Entry = Entry {
+    timestamp: 0,
+    task: 0,
+    level: Level::Debug,
+    code: 0,
+    args: [0; 2],
+};
+
+struct ServerImpl {
+    net: Net,
+    entries: &'static mut [Entry; CAPACITY],
+    /// Index of the oldest entry
+    head: usize,
+    len: usize,
+    sequence: u32,
+    stats: LogStats,
+    /// When the oldest entry has to be sent by
+    deadline: Option<u64>,
+}
+
+impl ServerImpl {
+    fn push(&mut self, entry: Entry) {
+        if self.len == CAPACITY {
+            // Make room by dropping the oldest.
+            self.head = (self.head + 1) % CAPACITY;
+            self.len -= 1;
+            self.stats.dropped = self.stats.dropped.wrapping_add(1);
+            ringbuf_entry!(Trace::Dropped(self.stats.dropped));
+        }
+        self.entries[(self.head + self.len) % CAPACITY] = entry;
+        self.len += 1;
+        self.stats.logged = self.stats.logged.wrapping_add(1);
+        self.deadline.get_or_insert(entry.timestamp + FLUSH_MS);
+    }
+
+    /// Sends batches for as long as we have a full one, or any at all once
+    /// the deadline has passed. Returns when we next need to send.
+    fn flush(&mut self, tx_data_buf: &mut [u8]) -> Option<u64> {
+        while self.len != 0 {
+            let now = sys_get_timer().now;
+            let deadline = self.deadline.unwrap_or(now);
+            if self.len < BATCH && now < deadline {
+                return Some(deadline);
+            }
+
+            let count = usize::min(self.len, BATCH);
+            let header = Header {
+                version: VERSION,
+                sequence: self.sequence,
+                dropped: self.stats.dropped,
+                count: count as u8,
+            };
+            let mut n = hubpack::serialize(tx_data_buf, &header).unwrap();
+            for i in 0..count {
+                let entry = &self.entries[(self.head + i) % CAPACITY];
+                n += hubpack::serialize(&mut tx_data_buf[n..], entry).unwrap();
+            }
+
+            let meta = UdpMetadata {
+                addr: Address::Ipv6(Ipv6Address(COLLECTOR_ADDR)),
+                port: COLLECTOR_PORT,
+                size: n as u32,
+                #[cfg(feature = "vlan")]
+                vid: task_net_api::VLAN_RANGE.start,
+            };
+            if let Err(e) =
+                self.net.send_packet(SOCKET, meta, &tx_data_buf[..n])
+            {
+                ringbuf_entry!(Trace::SendError(e));
+                match e {
+                    // Try again once the queue has drained, or `net` has
+                    // come back.
+                    SendError::QueueFull | SendError::ServerRestarted => {
+                        return Some(now + RETRY_MS);
+                    }
+                    SendError::Other
+                    | SendError::NotYours
+                    | SendError::InvalidVLan => panic!(),
+                }
+            }
+
+            ringbuf_entry!(Trace::Sent {
+                sequence: self.sequence,
+                count: count as u8,
+            });
+            self.sequence = self.sequence.wrapping_add(1);
+            self.head = (self.head + count) % CAPACITY;
+            self.len -= count;
+            self.stats.forwarded =
+                self.stats.forwarded.wrapping_add(count as u32);
+            self.deadline = if self.len == 0 {
+                None
+            } else {
+                let oldest = &self.entries[self.head];
+                Some(oldest.timestamp + FLUSH_MS)
+            };
+        }
+        None
+    }
+
+    /// Discards anything sent to our socket; we've nothing to say to anyone
+    /// but the collector.
+    fn drain_socket(&mut self, rx_data_buf: &mut [u8]) {
+        loop {
+            match self.net.recv_packet(
+                SOCKET,
+                LargePayloadBehavior::Discard,
+                rx_data_buf,
+            ) {
+                Ok(_) => (),
+                Err(RecvError::QueueEmpty | RecvError::ServerRestarted) => {
+                    return
+                }
+                Err(RecvError::NotYours | RecvError::Other) => panic!(),
+            }
+        }
+    }
+}
+
+impl idl::InOrderLogImpl for ServerImpl {
+    fn log(
+        &mut self,
+        msg: &RecvMessage,
+        level: Level,
+        code: u16,
+        arg0: u32,
+        arg1: u32,
+    ) -> Result<(), RequestError<Infallible>> {
+        self.push(Entry {
+            timestamp: sys_get_timer().now,
+            task: msg.sender.index() as u16,
+            level,
+            code,
+            args: [arg0, arg1],
+        });
+        Ok(())
+    }
+
+    fn stats(
+        &mut self,
+        _msg: &RecvMessage,
+    ) -> Result<LogStats, RequestError<Infallible>> {
+        Ok(self.stats)
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::SOCKET_MASK | notifications::TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        // Both are handled from the main loop.
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let (entries, rx_data_buf, tx_data_buf) = mutable_statics! {
+        static mut ENTRIES: [Entry; CAPACITY] = [|| EMPTY; _];
+        static mut RX_BUF: [u8; SOCKET_RX_SIZE] = [|| 0u8; _];
+        static mut TX_BUF: [u8; SOCKET_TX_SIZE] = [|| 0u8; _];
+    };
+
+    let mut server = ServerImpl {
+        net: Net::from(NET.get_task_id()),
+        entries,
+        head: 0,
+        len: 0,
+        sequence: 0,
+        stats: LogStats::default(),
+        deadline: None,
+    };
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        server.drain_socket(rx_data_buf);
+        if let Some(when) = server.flush(tx_data_buf) {
+            sys_set_timer(Some(when), notifications::TIMER_MASK);
+        }
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use task_log_api::{Level, LogStats};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,98 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Wire format for the metrics task.
+//!
+//! A client asks for metrics by sending a hubpack-encoded [`Request`] to the
+//! metrics task's socket. The reply is a hubpack-encoded [`Header`],
+//! followed by `count` hubpack-encoded [`Metric`]s, numbered from `start`.
+//! If there are more than fit in a packet (`start + count < total`), the
+//! client asks again from where the reply left off.
+//!
+//! Each metric carries its own [`MetricId`], so a client needs no prior
+//! knowledge of which metrics a given SP has; a metric whose source couldn't
+//! be read when the request came in is left out, rather than reported as
+//! zero.
+
+#![no_std]
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+
+pub const VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+pub struct Request {
+    pub version: u8,
+    /// Number of the first metric to send
+    pub start: u16,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+pub struct Header {
+    /// Our version; if it isn't the one asked for, no metrics follow.
+    pub version: u8,
+    /// Number of metrics we have
+    pub total: u16,
+    /// Number of the first metric following
+    pub start: u16,
+    /// Number of metrics following
+    pub count: u8,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, SerializedSize)]
+pub struct Metric {
+    pub id: MetricId,
+    /// Which instance of the thing `id` counts this is: a VLAN ID for the
+    /// `Net*` metrics, a task index for `TaskRestarts`, and otherwise zero
+    pub index: u16,
+    /// The count, which wraps
+    pub value: u32,
+}
+
+/// What a [`Metric`] counts.
+///
+/// These are encoded by their position in this list, so new ones go at the
+/// end, and none are ever removed.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum MetricId {
+    // Net task, per VLAN (see `task_net_api::InterfaceStats`)
+    NetRxFrames,
+    NetTxFrames,
+    NetTxRingFull,
+    NetRxCrcErrors,
+    NetRxOverflowErrors,
+    NetRxOtherErrors,
+    NetRxChecksumErrors,
+    NetRxUnknownVlan,
+
+    // I2C server (see `drv_i2c_api::ErrorCounts`)
+    I2cErrors,
+    I2cNoDevice,
+    I2cResets,
+
+    // SP side of sprot (see `drv_sprot_api::SpIoStats`)
+    SprotTxSent,
+    SprotTxErrors,
+    SprotRxReceived,
+    SprotRxErrors,
+    SprotRxInvalid,
+    SprotRetries,
+    SprotCsnPulses,
+    SprotCsnPulseFailures,
+    SprotTimeouts,
+
+    // RoT side of sprot (see `drv_sprot_api::RotIoStats`)
+    RotRxReceived,
+    RotRxOverrun,
+    RotCsnPulses,
+    RotTxUnderrun,
+    RotRxInvalid,
+    RotTxIncomplete,
+
+    // Jefe, per task
+    TaskRestarts,
+}
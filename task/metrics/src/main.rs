@@ -0,0 +1,207 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Metrics task
+//!
+//! Gathers counters from the servers that keep them -- the net task's
+//! per-VLAN counts, the I2C server's error counts, sprot's IO stats, and how
+//! often jefe has restarted each task -- and serves them over UDP, in the
+//! format described in `task_metrics_api`. We gather afresh for every
+//! request, so there's nothing to go stale, and nothing for us to keep.
+
+#![no_std]
+#![no_main]
+
+use core::ops::Range;
+use hubpack::SerializedSize;
+use task_jefe_api::Jefe;
+use task_metrics_api::{Header, Metric, MetricId, Request, VERSION};
+use task_net_api::{
+    LargePayloadBehavior, Net, RecvError, SendError, SocketName,
+};
+use userlib::*;
+
+task_slot!(NET, net);
+task_slot!(JEFE, jefe);
+
+#[cfg(feature = "i2c")]
+task_slot!(I2C, i2c_driver);
+
+#[cfg(feature = "sprot")]
+task_slot!(SPROT, sprot);
+
+const SOCKET: SocketName = SocketName::metrics;
+const SOCKET_TX_SIZE: usize = task_net_api::SOCKET_TX_SIZE[SOCKET as usize];
+
+/// Metrics we send in a packet.
+const BATCH: usize = {
+    let n = (SOCKET_TX_SIZE - Header::MAX_SIZE) / Metric::MAX_SIZE;
+    if n > u8::MAX as usize {
+        u8::MAX as usize
+    } else {
+        n
+    }
+};
+
+const _: () = assert!(BATCH > 0);
+
+/// VLANs whose counts we report.
+#[cfg(feature = "vlan")]
+const VIDS: Range<u16> = task_net_api::VLAN_RANGE;
+#[cfg(not(feature = "vlan"))]
+const VIDS: Range<u16> = 0..1;
+
+/// Calls `f` with each metric we can get hold of, always in the same order.
+fn gather(net: &Net, jefe: &Jefe, mut f: impl FnMut(Metric)) {
+    let mut emit = |id, index, value| f(Metric { id, index, value });
+
+    for vid in VIDS {
+        if let Ok(s) = net.interface_stats(vid) {
+            emit(MetricId::NetRxFrames, vid, s.rx_frames);
+            emit(MetricId::NetTxFrames, vid, s.tx_frames);
+            emit(MetricId::NetTxRingFull, vid, s.tx_ring_full);
+            emit(MetricId::NetRxCrcErrors, vid, s.rx_crc_errors);
+            emit(MetricId::NetRxOverflowErrors, vid, s.rx_overflow_errors);
+            emit(MetricId::NetRxOtherErrors, vid, s.rx_other_errors);
+            emit(MetricId::NetRxChecksumErrors, vid, s.rx_checksum_errors);
+            emit(MetricId::NetRxUnknownVlan, vid, s.rx_unknown_vlan);
+        }
+    }
+
+    #[cfg(feature = "i2c")]
+    if let Ok(c) = drv_i2c_api::error_counts(I2C.get_task_id()) {
+        emit(MetricId::I2cErrors, 0, c.errors);
+        emit(MetricId::I2cNoDevice, 0, c.no_device);
+        emit(MetricId::I2cResets, 0, c.resets);
+    }
+
+    #[cfg(feature = "sprot")]
+    {
+        let sprot = drv_sprot_api::SpRot::from(SPROT.get_task_id());
+        if let Ok(drv_sprot_api::SprotIoStats { rot, sp }) = sprot.io_stats() {
+            emit(MetricId::SprotTxSent, 0, sp.tx_sent);
+            emit(MetricId::SprotTxErrors, 0, sp.tx_errors);
+            emit(MetricId::SprotRxReceived, 0, sp.rx_received);
+            emit(MetricId::SprotRxErrors, 0, sp.rx_errors);
+            emit(MetricId::SprotRxInvalid, 0, sp.rx_invalid);
+            emit(MetricId::SprotRetries, 0, sp.retries);
+            emit(MetricId::SprotCsnPulses, 0, sp.csn_pulses);
+            emit(MetricId::SprotCsnPulseFailures, 0, sp.csn_pulse_failures);
+            emit(MetricId::SprotTimeouts, 0, sp.timeouts);
+            emit(MetricId::RotRxReceived, 0, rot.rx_received);
+            emit(MetricId::RotRxOverrun, 0, rot.rx_overrun);
+            emit(MetricId::RotCsnPulses, 0, rot.csn_pulses);
+            emit(MetricId::RotTxUnderrun, 0, rot.tx_underrun);
+            emit(MetricId::RotRxInvalid, 0, rot.rx_invalid);
+            emit(MetricId::RotTxIncomplete, 0, rot.tx_incomplete);
+        }
+    }
+
+    // Jefe doesn't supervise itself, so skip task 0.
+    for task in 1..hubris_num_tasks::NUM_TASKS as u16 {
+        if let Ok(n) = jefe.restart_count(u32::from(task)) {
+            emit(MetricId::TaskRestarts, task, n);
+        }
+    }
+}
+
+/// Fills in `tx_data_buf` with a reply to `request`, returning its length.
+fn reply(
+    net: &Net,
+    jefe: &Jefe,
+    request: &Request,
+    tx_data_buf: &mut [u8],
+) -> usize {
+    let mut header = Header {
+        version: VERSION,
+        total: 0,
+        start: request.start,
+        count: 0,
+    };
+
+    let mut n = Header::MAX_SIZE;
+    if request.version == VERSION {
+        gather(net, jefe, |metric| {
+            let number = header.total;
+            header.total += 1;
+            if number >= request.start && usize::from(header.count) < BATCH {
+                n += hubpack::serialize(&mut tx_data_buf[n..], &metric)
+                    .unwrap_lite();
+                header.count += 1;
+            }
+        });
+    }
+
+    // Header is fixed-size, so this fills exactly the space we left for it.
+    hubpack::serialize(&mut tx_data_buf[..Header::MAX_SIZE], &header)
+        .unwrap_lite();
+    n
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let net = Net::from(NET.get_task_id());
+    let jefe = Jefe::from(JEFE.get_task_id());
+
+    let mut rx_data_buf = [0u8; Request::MAX_SIZE];
+    let mut tx_data_buf = [0u8; SOCKET_TX_SIZE];
+
+    loop {
+        match net.recv_packet(
+            SOCKET,
+            LargePayloadBehavior::Discard,
+            &mut rx_data_buf,
+        ) {
+            Ok(mut meta) => {
+                let request = match hubpack::deserialize::<Request>(
+                    &rx_data_buf[..meta.size as usize],
+                ) {
+                    Ok((request, _)) => request,
+                    // Not for us to answer.
+                    Err(_) => continue,
+                };
+
+                let n = reply(&net, &jefe, &request, &mut tx_data_buf);
+                meta.size = n as u32;
+
+                loop {
+                    match net.send_packet(SOCKET, meta, &tx_data_buf[..n]) {
+                        Ok(()) => break,
+                        Err(SendError::QueueFull) => {
+                            // Our outgoing queue is full; wait for space.
+                            sys_recv_closed(
+                                &mut [],
+                                notifications::SOCKET_MASK,
+                                TaskId::KERNEL,
+                            )
+                            .unwrap();
+                        }
+                        // The client can ask again.
+                        Err(SendError::ServerRestarted) => break,
+                        Err(
+                            SendError::NotYours
+                            | SendError::InvalidVLan
+                            | SendError::Other,
+                        ) => panic!(),
+                    }
+                }
+            }
+            Err(RecvError::QueueEmpty) => {
+                // Wait for someone to ask.
+                sys_recv_closed(
+                    &mut [],
+                    notifications::SOCKET_MASK,
+                    TaskId::KERNEL,
+                )
+                .unwrap();
+            }
+            Err(RecvError::ServerRestarted) => {
+                // `net` restarted; just retry.
+            }
+            Err(RecvError::NotYours | RecvError::Other) => panic!(),
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
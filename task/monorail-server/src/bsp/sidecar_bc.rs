@@ -138,11 +138,14 @@ pub fn preinit() {
         sleep_for(10);
     }
     // Wait for the front IO board to be configured (or for the board to
-    // be reported as missing).
+    // be reported as missing, or as one the sequencer has given up on).
     loop {
         let ready = seq.front_io_phy_ready();
         match ready {
-            Ok(true) | Err(SeqError::NoFrontIOBoard) => break,
+            Ok(true)
+            | Err(SeqError::NoFrontIOBoard | SeqError::FrontIOBoardFailed) => {
+                break
+            }
             _ => sleep_for(10),
         }
     }
@@ -154,7 +157,9 @@ impl<'a, R: Vsc7448Rw> Bsp<'a, R> {
         let seq = Sequencer::from(SEQ.get_task_id());
         let has_front_io = match seq.front_io_phy_ready() {
             Ok(true) => true,
-            Err(SeqError::NoFrontIOBoard) => false,
+            Err(SeqError::NoFrontIOBoard | SeqError::FrontIOBoardFailed) => {
+                false
+            }
             _ => panic!("front IO board went away after preinit()"),
         };
         let mut out = Bsp {
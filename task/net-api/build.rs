@@ -3,7 +3,10 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::client::build_client_stub("../../idl/net.idol", "client_stub.rs")?;
+    build_util::idol::build_client_stub(
+        "../../idl/net.idol",
+        "client_stub.rs",
+    )?;
 
     let out_dir = build_util::out_dir();
     let dest_path = out_dir.join("net_config.rs");
@@ -74,6 +74,19 @@ pub enum KszError {
 
     WrongChipId,
 
+    /// The port doesn't exist, or doesn't support the operation
+    BadPort,
+    /// The VLAN tag is out of range (0-4095)
+    BadVid,
+    /// The VLAN table index is too large
+    BadVLanIndex,
+    /// There's no room in the VLAN table for another VLAN
+    VLanTableFull,
+    /// The port is in 100BASE-FX mode, so there's no cable to test
+    NotCopper,
+    /// The cable test didn't finish
+    CableTestTimeout,
+
     #[idol(server_death)]
     ServerRestarted,
 }
@@ -99,6 +112,44 @@ impl From<ksz8463::KszRawMacTableEntry> for KszMacTableEntry {
     }
 }
 
+/// A valid entry in the KSZ8463 VLAN table
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct KszVLanEntry {
+    pub vid: u16,
+    /// Member ports, with port 1 in bit 0
+    pub port_mask: u8,
+    /// Filter ID
+    pub fid: u8,
+}
+
+#[cfg(feature = "ksz8463")]
+impl From<ksz8463::VLanEntry> for KszVLanEntry {
+    fn from(e: ksz8463::VLanEntry) -> Self {
+        Self {
+            vid: e.vid,
+            port_mask: e.port_mask,
+            fid: e.fid,
+        }
+    }
+}
+
+/// State of one port on the KSZ8463
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct KszPortStatus {
+    /// Whether the port has link; port 3, which faces the SP over RMII,
+    /// always does
+    pub link_up: bool,
+    /// VLAN tag given to untagged frames arriving on the port
+    pub default_vid: u16,
+    /// VLANs the port is a member of, with bit `i` set for VLAN table index
+    /// `i` (see `read_ksz8463_vlan`)
+    pub vlans: u16,
+}
+
 #[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
 #[repr(C)]
 pub struct MacAddress(pub [u8; 6]);
@@ -149,6 +200,69 @@ pub struct ManagementCounters {
     pub vsc85x2_mac_valid: bool,
 }
 
+/// Link-quality counts for one port of the VSC85x2 PHY
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct ManagementLinkQualityVsc85x2 {
+    /// Receive errors on the copper media (8 bits)
+    pub rx_errors: u8,
+    /// False carrier events on the copper media (8 bits)
+    pub false_carriers: u8,
+    /// Times the copper link has gone down (8 bits)
+    pub disconnects: u8,
+    /// Whether the link came up at a lower speed than advertised
+    pub downshifted: bool,
+}
+
+/// Link-quality counts for the management network.  The VSC85x2 ports run
+/// 100BASE-FX on most boards, where these stay at zero, and the media SerDes
+/// counters in `ManagementCounters` are the ones to look at.
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct ManagementLinkQuality {
+    pub vsc85x2: [ManagementLinkQualityVsc85x2; 2],
+}
+
+/// What a cable test found
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub enum CableStatus {
+    Normal,
+    Open,
+    Short,
+    /// The test couldn't be run properly, e.g. because the link partner was
+    /// transmitting
+    Failed,
+}
+
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct CableDiagnostics {
+    pub status: CableStatus,
+    /// Estimated distance to an open or short, in decimetres (at a
+    /// resolution of 4)
+    pub distance_dm: u16,
+}
+
+#[cfg(feature = "ksz8463")]
+impl From<ksz8463::CableDiagnostics> for CableDiagnostics {
+    fn from(d: ksz8463::CableDiagnostics) -> Self {
+        Self {
+            status: match d.status {
+                ksz8463::CableStatus::Normal => CableStatus::Normal,
+                ksz8463::CableStatus::Open => CableStatus::Open,
+                ksz8463::CableStatus::Short => CableStatus::Short,
+                ksz8463::CableStatus::Failed => CableStatus::Failed,
+            },
+            distance_dm: d.distance_dm,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
 #[repr(u32)]
 pub enum MgmtError {
@@ -160,6 +274,455 @@ pub enum MgmtError {
     ServerRestarted,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum DhcpError {
+    /// The net task was built without DHCP
+    NotAvailable = 1,
+
+    /// The specified VID is not in the configured range
+    InvalidVLan,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Where the DHCP client is in getting or keeping a lease (RFC 2131 4.4)
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub enum DhcpState {
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+}
+
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct Ipv4Cidr {
+    pub address: Ipv4Address,
+    pub prefix_len: u8,
+}
+
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct DhcpLease {
+    pub address: Ipv4Cidr,
+    pub router: Option<Ipv4Address>,
+    pub server: Ipv4Address,
+    /// Length of the lease, in seconds
+    pub duration: u32,
+    /// Seconds until we ask our server to renew the lease (T1), or 0 if
+    /// we're already asking
+    pub renew_in: u32,
+    /// Seconds until we ask any server to renew the lease (T2), or 0 if
+    /// we're already asking
+    pub rebind_in: u32,
+    /// Seconds until the lease runs out
+    pub expires_in: u32,
+}
+
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct DhcpStatus {
+    pub state: DhcpState,
+    pub lease: Option<DhcpLease>,
+    /// The address from the build configuration that we're using for want
+    /// of a lease, if any
+    pub fallback: Option<Ipv4Cidr>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum TcpError {
+    /// The net task was built without TCP
+    NotAvailable = 1,
+
+    /// The selected socket is not owned by this task
+    NotYours,
+
+    /// The specified VID is not in the configured range
+    InvalidVLan,
+
+    /// The socket isn't in a state to do that: listening or connecting
+    /// requires it to be closed, and sending or receiving requires it to be
+    /// open
+    InvalidState,
+
+    /// The outgoing buffer is full, or the connection isn't up yet; the
+    /// owner will be notified when that changes
+    QueueFull,
+
+    /// Nothing has come in, or no connection has been accepted, yet; the
+    /// owner will be notified when that changes
+    QueueEmpty,
+
+    /// The connection is closing, and there's nothing more to send or
+    /// receive in this direction
+    Closed,
+
+    /// The remote endpoint can't be reached from here
+    Unaddressable,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Connection states, as in RFC 793
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct TcpEndpoint {
+    pub addr: Address,
+    pub port: u16,
+}
+
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct TcpStatus {
+    pub state: TcpState,
+    pub remote: Option<TcpEndpoint>,
+    /// Bytes waiting to be received
+    pub recv_queue: u32,
+    /// Bytes that can be sent without the outgoing buffer filling
+    pub send_space: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum CaptureError {
+    /// The net task was built without packet capture
+    NotAvailable = 1,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Which frames the capture tap sends on to the collector. Zero in any field
+/// means "any" or, for `snaplen` and `rate`, the configured default.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Serialize,
+    SerializedSize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
+pub struct CaptureFilter {
+    /// Only frames of this ethertype
+    pub ethertype: u16,
+    /// Only TCP and UDP frames to or from this port
+    pub port: u16,
+    /// Bytes of each frame to keep
+    pub snaplen: u16,
+    /// Most frames to capture in a second
+    pub rate: u16,
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Serialize,
+    SerializedSize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
+pub struct CaptureStats {
+    /// The filter in use, if capture is running, with defaults filled in
+    pub filter: Option<CaptureFilter>,
+    /// Frames sent on to the collector
+    pub captured: u32,
+    /// Frames the filter turned away
+    pub filtered: u32,
+    /// Frames that matched, but went over the rate limit
+    pub rate_limited: u32,
+    /// Frames that matched, but couldn't be queued or sent
+    pub dropped: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum FirewallError {
+    /// The net task was built without the firewall
+    NotAvailable = 1,
+    /// There's no rule with that index in the policy
+    NoSuchRule,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Counts for the firewall as a whole, since the net task started or the
+/// counts were last reset.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Serialize,
+    SerializedSize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
+pub struct FirewallStats {
+    /// Number of rules in the policy
+    pub rules: u32,
+    /// Packets to a guarded port that no rule let in
+    pub denied: u32,
+}
+
+/// Counts for one firewall rule, since the net task started or the counts
+/// were last reset.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Serialize,
+    SerializedSize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
+pub struct FirewallRuleStats {
+    /// Packets the rule let in
+    pub hits: u32,
+    /// Packets the rule matched, but turned away for going over its rate
+    pub rate_limited: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum StatsError {
+    /// The specified VID is not in the configured range
+    InvalidVLan = 1,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Counts for one socket on one VLAN, since the net task started or the
+/// counts were last reset. These are only kept for UDP sockets.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Serialize,
+    SerializedSize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
+pub struct SocketStats {
+    /// Packets handed to the socket's owner
+    pub rx_packets: u32,
+    /// Packets queued to send
+    pub tx_packets: u32,
+    /// Packets thrown away because they were too big for the owner's buffer
+    pub rx_too_large: u32,
+    /// Sends turned away because the queue was full
+    pub tx_queue_full: u32,
+    /// Sends turned away for any other reason
+    pub tx_errors: u32,
+    /// Times the queues were emptied after being full for too long, losing
+    /// whatever was in them
+    pub queue_resets: u32,
+}
+
+/// Counts for one VLAN (or the only interface, without VLANs), since the net
+/// task started or the counts were last reset.
+///
+/// The `rx_*_errors` counts, and `rx_unknown_vlan`, are for the MAC as a
+/// whole, and so are the same for every VLAN. smoltcp keeps its neighbor
+/// cache to itself, so there's nothing here about that.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Serialize,
+    SerializedSize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
+pub struct InterfaceStats {
+    /// Frames passed up to the IP stack
+    pub rx_frames: u32,
+    /// Frames sent
+    pub tx_frames: u32,
+    /// Frames the IP stack couldn't send because the TX ring was full
+    pub tx_ring_full: u32,
+    /// Frames dropped because their CRC didn't match
+    pub rx_crc_errors: u32,
+    /// Frames dropped because the MAC's receive FIFO overflowed
+    pub rx_overflow_errors: u32,
+    /// Frames dropped for any other error the MAC reports
+    pub rx_other_errors: u32,
+    /// Frames with a bad IP, TCP or UDP checksum, which the IP stack drops
+    pub rx_checksum_errors: u32,
+    /// Frames dropped because they didn't have a VLAN tag we know about
+    pub rx_unknown_vlan: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum Ipv6Error {
+    /// The specified VID is not in the configured range
+    InvalidVLan = 1,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Most addresses an interface takes from router advertisements, on top of
+/// its link-local address.
+pub const SLAAC_ADDRESSES: usize = 2;
+
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub enum Ipv6AddressState {
+    /// Being checked to see if anyone else has it
+    Tentative,
+    /// In use
+    Preferred,
+    /// Still in use, but past its preferred lifetime
+    Deprecated,
+    /// Someone else has it, so we don't use it
+    Duplicate,
+}
+
+/// An address made from an advertised prefix.
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct Ipv6AddressStatus {
+    pub address: Ipv6Address,
+    pub prefix_len: u8,
+    pub state: Ipv6AddressState,
+    /// Seconds until the address stops being preferred, or `u32::MAX` for
+    /// never
+    pub preferred_for: u32,
+    /// Seconds until the address goes away, or `u32::MAX` for never
+    pub valid_for: u32,
+}
+
+/// The IPv6 addresses on an interface.
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct Ipv6Status {
+    pub link_local: Ipv6Address,
+    /// Addresses from router advertisements, if the net task was built with
+    /// SLAAC
+    pub addresses: [Option<Ipv6AddressStatus>; SLAAC_ADDRESSES],
+    /// The default router, and seconds until it stops being one
+    pub router: Option<Ipv6Address>,
+    pub router_for: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum MulticastError {
+    /// The specified VID is not in the configured range
+    InvalidVLan = 1,
+    /// The specified socket is not owned by this task
+    NotYours,
+    /// The address given isn't a multicast group
+    NotMulticast,
+    /// The VLAN is already in as many groups as it can be
+    TooManyGroups,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Most multicast groups that sockets on one VLAN can join between them.
+pub const MULTICAST_GROUPS: usize = 4;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum TimestampError {
+    /// The net task was built without timestamping (the `ptp` feature)
+    NotAvailable = 1,
+    /// The specified socket is not owned by this task
+    NotYours,
+    /// The specified VID is not in the configured range
+    InvalidVLan,
+    /// Nothing has gone out on the socket since the net task started, or the
+    /// last packet hasn't gone out yet, or went out so long ago that its
+    /// timestamp is gone
+    NoTimestamp,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// A time from the ethernet MAC's IEEE 1588 clock. Until something sets that
+/// clock, it counts from when the net task started.
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct PtpTimestamp {
+    pub seconds: u32,
+    pub nanos: u32,
+}
+
+/// What `recv_packet_timestamped` says about a packet: what `recv_packet`
+/// would, and when it arrived, if the net task was built with timestamping
+/// and could tell.
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct TimestampedUdpMetadata {
+    pub meta: UdpMetadata,
+    pub timestamp: Option<PtpTimestamp>,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(
@@ -209,6 +772,10 @@ impl From<UdpMetadata> for smoltcp::wire::IpEndpoint {
 #[repr(C)]
 pub enum Address {
     Ipv6(Ipv6Address),
+    /// Only seen on sockets configured to take IPv4, which requires the `net`
+    /// task to be built with DHCP
+    #[cfg(feature = "ipv4")]
+    Ipv4(Ipv4Address),
 }
 
 #[cfg(feature = "use-smoltcp")]
@@ -216,6 +783,8 @@ impl From<Address> for smoltcp::wire::IpAddress {
     fn from(a: Address) -> Self {
         match a {
             Address::Ipv6(a) => Self::Ipv6(a.into()),
+            #[cfg(feature = "ipv4")]
+            Address::Ipv4(a) => Self::Ipv4(a.into()),
         }
     }
 }
@@ -229,6 +798,8 @@ impl TryFrom<smoltcp::wire::IpAddress> for Address {
 
         match a {
             IpAddress::Ipv6(a) => Ok(Self::Ipv6(a.into())),
+            #[cfg(feature = "ipv4")]
+            IpAddress::Ipv4(a) => Ok(Self::Ipv4(a.into())),
         }
     }
 }
@@ -256,5 +827,25 @@ impl From<Ipv6Address> for smoltcp::wire::Ipv6Address {
     }
 }
 
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+#[serde(transparent)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+#[cfg(all(feature = "use-smoltcp", feature = "ipv4"))]
+impl From<smoltcp::wire::Ipv4Address> for Ipv4Address {
+    fn from(a: smoltcp::wire::Ipv4Address) -> Self {
+        Self(a.0)
+    }
+}
+
+#[cfg(all(feature = "use-smoltcp", feature = "ipv4"))]
+impl From<Ipv4Address> for smoltcp::wire::Ipv4Address {
+    fn from(a: Ipv4Address) -> Self {
+        Self(a.0)
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
 include!(concat!(env!("OUT_DIR"), "/net_config.rs"));
@@ -8,7 +8,7 @@ use proc_macro2::TokenStream;
 use std::io::Write;
 
 fn main() -> Result<()> {
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/net.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
@@ -16,6 +16,16 @@ fn main() -> Result<()> {
     .map_err(|e| anyhow!(e))?;
 
     let net_config = build_net::load_net_config()?;
+    check_feature("dhcpv4", "dhcp", net_config.dhcp.is_some())?;
+    check_feature("mdns", "mdns", net_config.mdns.is_some())?;
+    check_feature("capture", "capture", net_config.capture.is_some())?;
+    check_feature("firewall", "firewall", net_config.firewall.is_some())?;
+    check_feature("ptp", "ptp", net_config.ptp.is_some())?;
+    if net_config.sockets.values().any(|s| s.kind == "tcp")
+        && !build_util::has_feature("tcp")
+    {
+        bail!("there are TCP sockets in the config, but no tcp feature");
+    }
 
     generate_net_config(&net_config)?;
     build_util::expose_target_board();
@@ -24,6 +34,19 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Checks that `feature` is on iff config `section` is `present`.
+fn check_feature(feature: &str, section: &str, present: bool) -> Result<()> {
+    match (build_util::has_feature(feature), present) {
+        (true, false) => bail!(
+            "{feature} feature is enabled, but {section} is missing from config"
+        ),
+        (false, true) => bail!(
+            "{feature} feature is disabled, but {section} is present in config"
+        ),
+        _ => Ok(()),
+    }
+}
+
 fn generate_net_config(config: &NetConfig) -> Result<()> {
     let out_dir = build_util::out_dir();
     let dest_path = out_dir.join("net_config.rs");
@@ -31,12 +54,19 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
     let mut out = std::fs::File::create(dest_path)?;
 
     let socket_count = config.sockets.len();
+    let tcp = if config.sockets.values().any(|s| s.kind == "tcp") {
+        quote::quote! { use smoltcp::socket::tcp; }
+    } else {
+        TokenStream::new()
+    };
     writeln!(
         out,
         "{}",
         quote::quote! {
             use core::sync::atomic::{AtomicBool, Ordering};
+            use crate::server::{ConfiguredSocket, SocketKind};
             use smoltcp::socket::udp;
+            #tcp
 
             pub const SOCKET_COUNT: usize = #socket_count;
         }
@@ -45,7 +75,23 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
     if build_util::has_feature("vlan") {
         build_net::generate_vlan_consts(config, &mut out)?;
     }
+    if config.dhcp.is_some() {
+        build_net::generate_dhcp_consts(config, &mut out)?;
+    }
+    if config.mdns.is_some() {
+        build_net::generate_mdns_consts(config, &mut out)?;
+    }
+    if config.capture.is_some() {
+        build_net::generate_capture_consts(config, &mut out)?;
+    }
+    if config.firewall.is_some() {
+        build_net::generate_firewall_consts(config, &mut out)?;
+    }
+    if config.ptp.is_some() {
+        build_net::generate_ptp_consts(config, &mut out)?;
+    }
 
+    // Each socket only gets buffers for the VLANs it's bound on
     for (name, socket) in &config.sockets {
         writeln!(
             out,
@@ -53,7 +99,7 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
             generate_socket_state(
                 name,
                 socket,
-                config.vlan.map(|v| v.count).unwrap_or(1)
+                build_net::socket_vlans(config, socket).len()
             )?
         )?;
     }
@@ -79,10 +125,31 @@ fn generate_port_table(config: &NetConfig) -> Result<TokenStream> {
 
     let n = config.sockets.len();
 
+    let kinds = config.sockets.values().map(|socket| match &*socket.kind {
+        "tcp" => quote::quote! { SocketKind::Tcp },
+        _ => quote::quote! { SocketKind::Udp },
+    });
+
+    // Sockets take IPv4 only if there's DHCP to give us an address.
+    let ipv4 = if config.dhcp.is_some() {
+        let ipv4 = config.sockets.values().map(|socket| socket.ipv4);
+        quote::quote! {
+            pub(crate) const SOCKET_IPV4: [bool; #n] = [
+                #( #ipv4 ),*
+            ];
+        }
+    } else {
+        TokenStream::new()
+    };
+
     Ok(quote::quote! {
         pub(crate) const SOCKET_PORTS: [u16; #n] = [
             #( #consts ),*
         ];
+        pub(crate) const SOCKET_KINDS: [SocketKind; #n] = [
+            #( #kinds ),*
+        ];
+        #ipv4
     })
 }
 
@@ -123,18 +190,44 @@ fn generate_socket_state(
     config: &SocketConfig,
     vlan_count: usize,
 ) -> Result<TokenStream> {
-    if config.kind != "udp" {
-        bail!("unsupported socket kind");
-    }
-
-    let tx = generate_buffers(name, "TX", &config.tx, vlan_count);
-    let rx = generate_buffers(name, "RX", &config.rx, vlan_count);
+    let (tx, rx) = match &*config.kind {
+        "udp" => {
+            if config.tx.packets == 0 || config.rx.packets == 0 {
+                bail!("UDP socket {name} needs room for some packets");
+            }
+            (
+                generate_buffers(name, "TX", &config.tx, vlan_count),
+                generate_buffers(name, "RX", &config.rx, vlan_count),
+            )
+        }
+        "tcp" => (
+            generate_tcp_buffer(name, "TX", &config.tx, vlan_count),
+            generate_tcp_buffer(name, "RX", &config.rx, vlan_count),
+        ),
+        kind => bail!("unsupported socket kind {kind:?}"),
+    };
     Ok(quote::quote! {
         #tx
         #rx
     })
 }
 
+/// TCP sockets are byte streams, so their buffers don't need headers.
+fn generate_tcp_buffer(
+    name: &str,
+    dir: &str,
+    config: &BufSize,
+    vlan_count: usize,
+) -> TokenStream {
+    let bytecnt = config.bytes;
+    let upname = name.to_ascii_uppercase();
+    let bufname: syn::Ident =
+        syn::parse_str(&format!("SOCK_{}_DAT_{}", dir, upname)).unwrap();
+    quote::quote! {
+        static mut #bufname: [[u8; #bytecnt]; #vlan_count] = [[0u8; #bytecnt]; #vlan_count];
+    }
+}
+
 fn generate_buffers(
     name: &str,
     dir: &str,
@@ -159,12 +252,43 @@ fn generate_buffers(
 fn generate_state_struct(config: &NetConfig) -> TokenStream {
     let n = config.sockets.len();
     quote::quote! {
-        pub(crate) struct Sockets<'a, const N: usize>(pub [[udp::Socket<'a>; #n]; N]);
+        pub(crate) struct Sockets<'a, const N: usize>(pub [[ConfiguredSocket<'a>; #n]; N]);
     }
 }
 
 fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
-    let name_to_sockets = |name: &String, i: usize| {
+    let name_to_sockets = |name: &String, vlan: usize| {
+        let socket = &config.sockets[name];
+
+        // Sockets that aren't bound on this VLAN get empty buffers, and are
+        // never bound to a port.
+        let Some(i) = build_net::socket_vlans(config, socket)
+            .iter()
+            .position(|&v| v == vlan)
+        else {
+            return if socket.kind == "tcp" {
+                quote::quote! {
+                    ConfiguredSocket::Tcp(tcp::Socket::new(
+                        tcp::SocketBuffer::new(&mut [] as &mut [u8]),
+                        tcp::SocketBuffer::new(&mut [] as &mut [u8]),
+                    ))
+                }
+            } else {
+                quote::quote! {
+                    ConfiguredSocket::Udp(udp::Socket::new(
+                        udp::PacketBuffer::new(
+                            &mut [] as &mut [udp::PacketMetadata],
+                            &mut [] as &mut [u8],
+                        ),
+                        udp::PacketBuffer::new(
+                            &mut [] as &mut [udp::PacketMetadata],
+                            &mut [] as &mut [u8],
+                        ),
+                    ))
+                }
+            };
+        };
+
         let upname = name.to_ascii_uppercase();
         let rxhdrs: syn::Ident =
             syn::parse_str(&format!("SOCK_RX_HDR_{}", upname)).unwrap();
@@ -175,8 +299,16 @@ fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
         let txbytes: syn::Ident =
             syn::parse_str(&format!("SOCK_TX_DAT_{}", upname)).unwrap();
 
+        if socket.kind == "tcp" {
+            return quote::quote! {
+                ConfiguredSocket::Tcp(tcp::Socket::new(
+                    tcp::SocketBuffer::new(unsafe { &mut #rxbytes[#i][..] }),
+                    tcp::SocketBuffer::new(unsafe { &mut #txbytes[#i][..] }),
+                ))
+            };
+        }
         quote::quote! {
-            udp::Socket::new(
+            ConfiguredSocket::Udp(udp::Socket::new(
                 udp::PacketBuffer::new(
                     unsafe { &mut #rxhdrs[#i][..] },
                     unsafe { &mut #rxbytes[#i][..] },
@@ -185,10 +317,10 @@ fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
                     unsafe { &mut #txhdrs[#i][..] },
                     unsafe { &mut #txbytes[#i][..] },
                 ),
-            )
+            ))
         }
     };
-    let vlan_count = config.vlan.map(|v| v.count).unwrap_or(1);
+    let vlan_count = config.vlan.as_ref().map(|v| v.count).unwrap_or(1);
     let sockets = (0..vlan_count)
         .map(|i| {
             let s = config
@@ -20,7 +20,8 @@ use ksz8463::{
 };
 use ringbuf::*;
 use task_net_api::{
-    ManagementCounters, ManagementLinkStatus, MgmtError, PhyError,
+    ManagementCounters, ManagementLinkQuality, ManagementLinkStatus, MgmtError,
+    PhyError,
 };
 use userlib::task_slot;
 use vsc7448_pac::{phy, types::PhyRegisterAddress};
@@ -302,4 +303,11 @@ impl bsp_support::Bsp for BspImpl {
     ) -> Result<ManagementCounters, MgmtError> {
         self.mgmt.management_counters(eth)
     }
+
+    fn management_link_quality(
+        &self,
+        eth: &crate::eth::Ethernet,
+    ) -> Result<ManagementLinkQuality, MgmtError> {
+        self.mgmt.management_link_quality(eth)
+    }
 }
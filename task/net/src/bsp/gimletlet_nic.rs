@@ -73,7 +73,11 @@ impl bsp_support::Bsp for BspImpl {
             #[cfg(not(feature = "vlan"))]
             let vlan_mode = ksz8463::VLanMode::Optional;
 
-            match ksz8463.configure(ksz8463::Mode::Copper, vlan_mode) {
+            match ksz8463.configure(
+                ksz8463::Mode::Copper,
+                vlan_mode,
+                &bsp_support::KSZ8463_VLANS,
+            ) {
                 Err(err) => {
                     ringbuf_entry!(Trace::KszErr { err });
                     sleep_for(100);
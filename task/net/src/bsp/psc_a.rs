@@ -15,7 +15,8 @@ use drv_stm32h7_eth as eth;
 use drv_stm32xx_sys_api::{Alternate, Port, Sys};
 use task_jefe_api::Jefe;
 use task_net_api::{
-    ManagementCounters, ManagementLinkStatus, MgmtError, PhyError,
+    ManagementCounters, ManagementLinkQuality, ManagementLinkStatus, MgmtError,
+    PhyError,
 };
 use userlib::{sys_recv_closed, task_slot, FromPrimitive, TaskId};
 use vsc7448_pac::types::PhyRegisterAddress;
@@ -152,4 +153,11 @@ impl bsp_support::Bsp for BspImpl {
     ) -> Result<ManagementCounters, MgmtError> {
         self.0.management_counters(eth)
     }
+
+    fn management_link_quality(
+        &self,
+        eth: &crate::eth::Ethernet,
+    ) -> Result<ManagementLinkQuality, MgmtError> {
+        self.0.management_link_quality(eth)
+    }
 }
@@ -21,7 +21,8 @@ use drv_spi_api::SpiServer;
 use drv_stm32h7_eth as eth;
 use drv_stm32xx_sys_api::{Alternate, Port, Sys};
 use task_net_api::{
-    ManagementCounters, ManagementLinkStatus, MgmtError, PhyError,
+    ManagementCounters, ManagementLinkQuality, ManagementLinkStatus, MgmtError,
+    PhyError,
 };
 use userlib::{hl::sleep_for, task_slot};
 use vsc7448_pac::types::PhyRegisterAddress;
@@ -143,4 +144,11 @@ impl bsp_support::Bsp for BspImpl {
     ) -> Result<ManagementCounters, MgmtError> {
         self.0.management_counters(eth)
     }
+
+    fn management_link_quality(
+        &self,
+        eth: &crate::eth::Ethernet,
+    ) -> Result<ManagementLinkQuality, MgmtError> {
+        self.0.management_link_quality(eth)
+    }
 }
@@ -49,6 +49,27 @@ cfg_if::cfg_if! {
     }
 }
 
+/// VLANs to set up on the KSZ8463, as laid out in the app's net config
+#[cfg(all(feature = "ksz8463", feature = "vlan"))]
+pub const KSZ8463_VLANS: [ksz8463::VLan; crate::generated::VLAN_COUNT] = {
+    use crate::generated::{VLAN_COUNT, VLAN_RANGE, VLAN_SWITCH_PORTS};
+    let mut out = [ksz8463::VLan { vid: 0, ports: 0 }; VLAN_COUNT];
+    let mut i = 0;
+    while i < VLAN_COUNT {
+        out[i] = ksz8463::VLan {
+            vid: VLAN_RANGE.start + i as u16,
+            ports: VLAN_SWITCH_PORTS[i],
+        };
+        i += 1;
+    }
+    out
+};
+
+/// Without VLANs in the netstack, the KSZ8463 still gets its usual VLANs
+/// (in `VLanMode::Optional`), so that tagged frames can reach us untagged.
+#[cfg(all(feature = "ksz8463", not(feature = "vlan")))]
+pub const KSZ8463_VLANS: [ksz8463::VLan; 2] = ksz8463::DEFAULT_VLANS;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(feature = "mgmt")]
@@ -114,4 +135,10 @@ pub trait Bsp: Sized {
         &self,
         eth: &crate::eth::Ethernet,
     ) -> Result<task_net_api::ManagementCounters, MgmtError>;
+
+    #[cfg(feature = "mgmt")]
+    fn management_link_quality(
+        &self,
+        eth: &crate::eth::Ethernet,
+    ) -> Result<task_net_api::ManagementLinkQuality, MgmtError>;
 }
@@ -0,0 +1,364 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Packet capture, for when it's not clear what's going on on the wire.
+//!
+//! Every frame in or out goes past a `Tap`, from the smoltcp device tokens.
+//! While capture is running, those that pass the filter (and the rate limit)
+//! are cut down and written out as pcap-ng enhanced packet blocks, which are
+//! gathered up and sent to the collector once per poll. Each datagram starts
+//! with a section header and interface descriptions (one interface per
+//! VLAN), so that it stands alone, and a collector can start listening at
+//! any time: `socat -u udp6-recv:PORT - | wireshark -k -i -` does the job.
+//!
+//! We don't capture what we send to the collector, or there'd be no end of
+//! it.
+//!
+//! Without the `capture` feature, the tap is there but does nothing.
+
+/// Which way a frame was going.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Direction {
+    In,
+    Out,
+}
+
+#[cfg(feature = "capture")]
+pub(crate) use enabled::*;
+
+#[cfg(not(feature = "capture"))]
+pub(crate) use disabled::*;
+
+#[cfg(not(feature = "capture"))]
+mod disabled {
+    use super::Direction;
+
+    pub struct Tap;
+
+    impl Tap {
+        pub(crate) fn frame(&self, _vlan: usize, _dir: Direction, _f: &[u8]) {}
+        pub(crate) fn set_now(&self, _now: u64) {}
+    }
+
+    pub(crate) struct Statics {
+        pub tap: &'static Tap,
+    }
+
+    pub(crate) fn claim_statics() -> Statics {
+        Statics { tap: &Tap }
+    }
+}
+
+#[cfg(feature = "capture")]
+mod enabled {
+    use super::Direction;
+    use crate::generated::{
+        CAPTURE_COLLECTOR, CAPTURE_PORT, CAPTURE_RATE, CAPTURE_SNAPLEN,
+    };
+    use core::cell::RefCell;
+    use mutable_statics::mutable_statics;
+    use smoltcp::iface::{SocketHandle, SocketSet};
+    use smoltcp::socket::udp;
+    use smoltcp::wire::Ipv6Address;
+    use task_net_api::{CaptureFilter, CaptureStats};
+    use userlib::UnwrapLite;
+
+    #[cfg(feature = "vlan")]
+    const INTERFACES: usize = crate::generated::VLAN_COUNT;
+    #[cfg(not(feature = "vlan"))]
+    const INTERFACES: usize = 1;
+
+    /// Largest datagram we send, which keeps clear of fragmentation.
+    const DATAGRAM: usize = 1400;
+
+    /// Timestamps are in milliseconds.
+    const TSRESOL: u8 = 3;
+
+    const ETHERTYPE_IPV4: u16 = 0x0800;
+    const ETHERTYPE_IPV6: u16 = 0x86dd;
+    const IPPROTO_TCP: u8 = 6;
+    const IPPROTO_UDP: u8 = 17;
+
+    /// Rate limit tokens are in thousandths of a frame, so that they can be
+    /// topped up every millisecond.
+    const TOKENS_PER_FRAME: u64 = 1000;
+
+    pub struct Tap {
+        state: RefCell<State>,
+    }
+
+    struct State {
+        stats: CaptureStats,
+        now: u64,
+        tokens: u64,
+        refilled_at: u64,
+        /// The datagram being put together, and the frames in it
+        buf: [u8; DATAGRAM],
+        len: usize,
+        frames: u32,
+    }
+
+    impl Default for Tap {
+        fn default() -> Self {
+            Self {
+                state: RefCell::new(State {
+                    stats: CaptureStats::default(),
+                    now: 0,
+                    tokens: 0,
+                    refilled_at: 0,
+                    buf: [0; DATAGRAM],
+                    len: 0,
+                    frames: 0,
+                }),
+            }
+        }
+    }
+
+    impl Tap {
+        /// Starts capturing, or changes what's captured, filling in any
+        /// defaults the filter leaves out.
+        pub(crate) fn start(&self, mut filter: CaptureFilter) {
+            if filter.snaplen == 0 {
+                filter.snaplen = CAPTURE_SNAPLEN;
+            }
+            if filter.rate == 0 {
+                filter.rate = CAPTURE_RATE;
+            }
+            let mut s = self.state.borrow_mut();
+            s.stats = CaptureStats {
+                filter: Some(filter),
+                ..CaptureStats::default()
+            };
+            s.tokens = u64::from(filter.rate) * TOKENS_PER_FRAME;
+            s.refilled_at = s.now;
+            // Frames cut to the old length are still worth sending.
+        }
+
+        pub(crate) fn stop(&self) {
+            self.state.borrow_mut().stats.filter = None;
+        }
+
+        pub(crate) fn stats(&self) -> CaptureStats {
+            self.state.borrow().stats
+        }
+
+        pub(crate) fn set_now(&self, now: u64) {
+            self.state.borrow_mut().now = now;
+        }
+
+        /// Looks at a frame going `dir` on interface `vlan`, and keeps it if
+        /// it's wanted.
+        pub(crate) fn frame(&self, vlan: usize, dir: Direction, frame: &[u8]) {
+            let mut s = self.state.borrow_mut();
+            let s = &mut *s;
+            let filter = match s.stats.filter {
+                Some(f) => f,
+                None => return,
+            };
+            let headers = Headers::parse(frame);
+            if headers.to_collector {
+                return;
+            }
+            if !headers.matches(&filter) {
+                s.stats.filtered = s.stats.filtered.wrapping_add(1);
+                return;
+            }
+
+            let elapsed = s.now.saturating_sub(s.refilled_at);
+            let max = u64::from(filter.rate) * TOKENS_PER_FRAME;
+            s.tokens = (s.tokens + elapsed * u64::from(filter.rate)).min(max);
+            s.refilled_at = s.now;
+            if s.tokens < TOKENS_PER_FRAME {
+                s.stats.rate_limited = s.stats.rate_limited.wrapping_add(1);
+                return;
+            }
+            s.tokens -= TOKENS_PER_FRAME;
+
+            if s.len == 0 {
+                s.len = start_datagram(&mut s.buf, filter.snaplen);
+            }
+            let snap = frame.len().min(usize::from(filter.snaplen));
+            let dir = match dir {
+                Direction::In => pcapng::Direction::Inbound,
+                Direction::Out => pcapng::Direction::Outbound,
+            };
+            match pcapng::enhanced_packet(
+                &mut s.buf[s.len..],
+                vlan as u32,
+                s.now,
+                &frame[..snap],
+                frame.len() as u32,
+                dir,
+            ) {
+                Some(n) => {
+                    s.len += n;
+                    s.frames += 1;
+                }
+                None => s.stats.dropped = s.stats.dropped.wrapping_add(1),
+            }
+        }
+    }
+
+    /// Writes the blocks every datagram starts with, returning their length.
+    fn start_datagram(buf: &mut [u8], snaplen: u16) -> usize {
+        let mut len = pcapng::section_header(buf).unwrap_lite();
+        for _ in 0..INTERFACES {
+            len += pcapng::interface_description(
+                &mut buf[len..],
+                pcapng::LINKTYPE_ETHERNET,
+                u32::from(snaplen),
+                TSRESOL,
+            )
+            .unwrap_lite();
+        }
+        len
+    }
+
+    /// What the filter needs to know about a frame.
+    struct Headers {
+        ethertype: u16,
+        /// TCP or UDP source and destination ports
+        ports: Option<(u16, u16)>,
+        /// Whether this is one of ours, going to the collector
+        to_collector: bool,
+    }
+
+    impl Headers {
+        /// Picks apart an (untagged) Ethernet frame. IPv6 extension headers
+        /// and IPv4 fragments are beyond us, and just don't have ports.
+        fn parse(frame: &[u8]) -> Self {
+            let be16 = |at: usize| {
+                frame
+                    .get(at..at + 2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            };
+            let ethertype = be16(12).unwrap_or(0);
+            let (proto, l4) = match ethertype {
+                ETHERTYPE_IPV6 => (frame.get(14 + 6).copied(), 14 + 40),
+                ETHERTYPE_IPV4 => {
+                    let ihl = frame.get(14).map_or(0, |b| usize::from(b & 0xf));
+                    (frame.get(14 + 9).copied(), 14 + ihl * 4)
+                }
+                _ => (None, 0),
+            };
+            let ports = match proto {
+                Some(IPPROTO_TCP | IPPROTO_UDP) => be16(l4).zip(be16(l4 + 2)),
+                _ => None,
+            };
+            let to_collector = ethertype == ETHERTYPE_IPV6
+                && proto == Some(IPPROTO_UDP)
+                && ports == Some((CAPTURE_PORT, CAPTURE_PORT))
+                && frame.get(14 + 24..14 + 40) == Some(&CAPTURE_COLLECTOR[..]);
+            Self {
+                ethertype,
+                ports,
+                to_collector,
+            }
+        }
+
+        fn matches(&self, filter: &CaptureFilter) -> bool {
+            if filter.ethertype != 0 && filter.ethertype != self.ethertype {
+                return false;
+            }
+            if filter.port != 0 {
+                return match self.ports {
+                    Some((src, dst)) => {
+                        src == filter.port || dst == filter.port
+                    }
+                    None => false,
+                };
+            }
+            true
+        }
+    }
+
+    const PACKETS: usize = 2;
+
+    pub struct CaptureStorage {
+        rx_meta: [udp::PacketMetadata; 1],
+        rx: [u8; 0],
+        tx_meta: [udp::PacketMetadata; PACKETS],
+        tx: [u8; PACKETS * DATAGRAM],
+    }
+
+    impl Default for CaptureStorage {
+        fn default() -> Self {
+            Self {
+                rx_meta: [udp::PacketMetadata::EMPTY; 1],
+                rx: [],
+                tx_meta: [udp::PacketMetadata::EMPTY; PACKETS],
+                tx: [0; PACKETS * DATAGRAM],
+            }
+        }
+    }
+
+    pub(crate) struct Statics {
+        pub tap: &'static Tap,
+        pub storage: &'static mut CaptureStorage,
+    }
+
+    /// Grabs the tap and collector socket storage.  Can only be called once!
+    pub(crate) fn claim_statics() -> Statics {
+        let (tap, storage) = mutable_statics! {
+            static mut TAP: [Tap; 1] = [Default::default; _];
+            static mut STORAGE: [CaptureStorage; 1] = [Default::default; _];
+        };
+        Statics {
+            tap: &tap[0],
+            storage: &mut storage[0],
+        }
+    }
+
+    /// The socket captured frames go out through.
+    pub(crate) struct Collector {
+        socket: SocketHandle,
+    }
+
+    impl Collector {
+        pub(crate) fn new(
+            storage: &'static mut CaptureStorage,
+            socket_set: &mut SocketSet<'static>,
+        ) -> Self {
+            let mut socket = udp::Socket::new(
+                udp::PacketBuffer::new(
+                    &mut storage.rx_meta[..],
+                    &mut storage.rx[..],
+                ),
+                udp::PacketBuffer::new(
+                    &mut storage.tx_meta[..],
+                    &mut storage.tx[..],
+                ),
+            );
+            // Sending from the collector's port is what tells us not to
+            // capture these.
+            socket.bind(CAPTURE_PORT).unwrap_lite();
+            Self {
+                socket: socket_set.add(socket),
+            }
+        }
+
+        /// Sends whatever the tap has gathered. Returns true if there was
+        /// anything.
+        pub(crate) fn flush(
+            &self,
+            tap: &Tap,
+            socket_set: &mut SocketSet<'static>,
+        ) -> bool {
+            let mut s = tap.state.borrow_mut();
+            if s.len == 0 {
+                return false;
+            }
+            let socket = socket_set.get_mut::<udp::Socket<'_>>(self.socket);
+            let to = (Ipv6Address(CAPTURE_COLLECTOR), CAPTURE_PORT);
+            if socket.send_slice(&s.buf[..s.len], to).is_ok() {
+                s.stats.captured = s.stats.captured.wrapping_add(s.frames);
+            } else {
+                s.stats.dropped = s.stats.dropped.wrapping_add(s.frames);
+            }
+            s.len = 0;
+            s.frames = 0;
+            true
+        }
+    }
+}
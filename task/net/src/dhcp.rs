@@ -0,0 +1,269 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! DHCPv4, for an IPv4 address on each interface.
+//!
+//! The client itself lives in `dhcp-client`; this gives it a raw socket to
+//! talk through, since it has to send before it has an address, and applies
+//! what it finds to the interface. Without a lease, we fall back to the
+//! address in the build configuration or, if there isn't one, to a link-local
+//! address (RFC 3927). We don't probe to see whether anyone else has that
+//! link-local address first, so two boards whose MACs hash alike will fight
+//! over it.
+
+use crate::generated::{DHCP_FALLBACK, DHCP_FALLBACK_GATEWAY, DHCP_TIMEOUT_MS};
+use dhcp_client::{Change, Client, State, MAX_PACKET};
+use ringbuf::*;
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::socket::raw;
+use smoltcp::wire::{
+    EthernetAddress, IpCidr, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr,
+};
+use task_net_api::{DhcpLease, DhcpState, DhcpStatus};
+use userlib::UnwrapLite;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Trace {
+    None,
+    Configured {
+        vlan: usize,
+        address: [u8; 4],
+        prefix_len: u8,
+    },
+    Deconfigured(usize),
+    FellBack {
+        vlan: usize,
+        address: [u8; 4],
+        prefix_len: u8,
+    },
+    SendFailed(usize),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Packets we can hold in each direction. The raw socket sees every IPv4 UDP
+/// packet, not just DHCP, so this needs to be enough to ride out a burst.
+const PACKETS: usize = 4;
+
+pub struct DhcpStorage {
+    rx_meta: [raw::PacketMetadata; PACKETS],
+    rx: [u8; PACKETS * MAX_PACKET],
+    tx_meta: [raw::PacketMetadata; PACKETS],
+    tx: [u8; PACKETS * MAX_PACKET],
+}
+
+impl Default for DhcpStorage {
+    fn default() -> Self {
+        Self {
+            rx_meta: [raw::PacketMetadata::EMPTY; PACKETS],
+            rx: [0; PACKETS * MAX_PACKET],
+            tx_meta: [raw::PacketMetadata::EMPTY; PACKETS],
+            tx: [0; PACKETS * MAX_PACKET],
+        }
+    }
+}
+
+pub(crate) struct Dhcp {
+    vlan: usize,
+    client: Client,
+    socket: SocketHandle,
+    fallback: Ipv4Cidr,
+    gateway: Option<Ipv4Address>,
+    /// Whether we're using `fallback`
+    fallen_back: bool,
+}
+
+impl Dhcp {
+    pub(crate) fn new(
+        vlan: usize,
+        mac: EthernetAddress,
+        storage: &'static mut DhcpStorage,
+        socket_set: &mut SocketSet<'static>,
+        now: u64,
+    ) -> Self {
+        let socket = socket_set.add(raw::Socket::new(
+            IpVersion::Ipv4,
+            IpProtocol::Udp,
+            raw::PacketBuffer::new(
+                &mut storage.rx_meta[..],
+                &mut storage.rx[..],
+            ),
+            raw::PacketBuffer::new(
+                &mut storage.tx_meta[..],
+                &mut storage.tx[..],
+            ),
+        ));
+
+        let (fallback, gateway) = match (vlan, DHCP_FALLBACK) {
+            (0, Some((address, prefix_len))) => (
+                Ipv4Cidr::new(Ipv4Address(address), prefix_len),
+                DHCP_FALLBACK_GATEWAY.map(Ipv4Address),
+            ),
+            _ => (link_local_ipv4_addr(mac), None),
+        };
+
+        // The MAC keeps our transaction IDs apart from other boards', and the
+        // time keeps them apart from those of our last life.
+        let seed = u32::from_be_bytes(mac.0[2..].try_into().unwrap_lite())
+            ^ now as u32;
+
+        Self {
+            vlan,
+            client: Client::new(mac.0, seed, DHCP_TIMEOUT_MS, now),
+            socket,
+            fallback,
+            gateway,
+            fallen_back: false,
+        }
+    }
+
+    /// Feeds the client whatever's come in, moves it along to `now`, and
+    /// queues whatever it has to send. Returns true if anything changed or
+    /// was queued, meaning the interface needs polling again.
+    pub(crate) fn poll(
+        &mut self,
+        now: u64,
+        iface: &mut Interface,
+        socket_set: &mut SocketSet<'static>,
+    ) -> bool {
+        let mut activity = false;
+        let socket = socket_set.get_mut::<raw::Socket<'_>>(self.socket);
+
+        while let Ok(packet) = socket.recv() {
+            if let Some(change) = self.client.receive(now, packet) {
+                self.apply(change, iface);
+                activity = true;
+            }
+        }
+
+        if let Some(change) = self.client.poll(now) {
+            self.apply(change, iface);
+            activity = true;
+        }
+
+        let mut buf = [0; MAX_PACKET];
+        if let Some(len) = self.client.transmit(now, &mut buf) {
+            // If this fails, the client will try again later.
+            if socket.send_slice(&buf[..len]).is_err() {
+                ringbuf_entry!(Trace::SendFailed(self.vlan));
+            }
+            activity = true;
+        }
+
+        activity
+    }
+
+    /// Returns the next time we need to `poll`, absent any packets.
+    pub(crate) fn poll_at(&self) -> u64 {
+        self.client.poll_at()
+    }
+
+    pub(crate) fn status(&self, now: u64) -> DhcpStatus {
+        let secs_until =
+            |t: u64| (t.saturating_sub(now) / 1000).min(u32::MAX.into()) as u32;
+        let lease = self.client.binding().map(|b| DhcpLease {
+            address: task_net_api::Ipv4Cidr {
+                address: task_net_api::Ipv4Address(b.lease.address),
+                prefix_len: b.lease.prefix_len,
+            },
+            router: b.lease.router.map(task_net_api::Ipv4Address),
+            server: task_net_api::Ipv4Address(b.lease.server),
+            duration: b.lease.duration,
+            renew_in: secs_until(b.renew_at()),
+            rebind_in: secs_until(b.rebind_at()),
+            expires_in: secs_until(b.expires_at()),
+        });
+        let fallback = if self.fallen_back {
+            Some(task_net_api::Ipv4Cidr {
+                address: self.fallback.address().into(),
+                prefix_len: self.fallback.prefix_len(),
+            })
+        } else {
+            None
+        };
+        DhcpStatus {
+            state: match self.client.state() {
+                State::Selecting => DhcpState::Selecting,
+                State::Requesting => DhcpState::Requesting,
+                State::Bound => DhcpState::Bound,
+                State::Renewing => DhcpState::Renewing,
+                State::Rebinding => DhcpState::Rebinding,
+            },
+            lease,
+            fallback,
+        }
+    }
+
+    fn apply(&mut self, change: Change, iface: &mut Interface) {
+        match change {
+            Change::Configured(lease) => {
+                ringbuf_entry!(Trace::Configured {
+                    vlan: self.vlan,
+                    address: lease.address,
+                    prefix_len: lease.prefix_len,
+                });
+                self.fallen_back = false;
+                set_ipv4_addr(
+                    iface,
+                    Some(Ipv4Cidr::new(
+                        Ipv4Address(lease.address),
+                        lease.prefix_len,
+                    )),
+                    lease.router.map(Ipv4Address),
+                );
+            }
+            Change::Deconfigured => {
+                ringbuf_entry!(Trace::Deconfigured(self.vlan));
+                set_ipv4_addr(iface, None, None);
+            }
+            Change::GaveUp => {
+                ringbuf_entry!(Trace::FellBack {
+                    vlan: self.vlan,
+                    address: self.fallback.address().0,
+                    prefix_len: self.fallback.prefix_len(),
+                });
+                self.fallen_back = true;
+                set_ipv4_addr(iface, Some(self.fallback), self.gateway);
+            }
+        }
+    }
+}
+
+/// Replaces the interface's IPv4 address and default route, leaving its IPv6
+/// address alone.
+fn set_ipv4_addr(
+    iface: &mut Interface,
+    cidr: Option<Ipv4Cidr>,
+    router: Option<Ipv4Address>,
+) {
+    iface.update_ip_addrs(|addrs| {
+        addrs.retain(|a| !matches!(a, IpCidr::Ipv4(_)));
+        if let Some(cidr) = cidr {
+            addrs.push(IpCidr::Ipv4(cidr)).unwrap_lite();
+        }
+    });
+    match router {
+        Some(router) => {
+            iface
+                .routes_mut()
+                .add_default_ipv4_route(router)
+                .unwrap_lite();
+        }
+        None => {
+            iface.routes_mut().remove_default_ipv4_route();
+        }
+    }
+}
+
+/// Picks an IPv4 link-local address, in 169.254.1.0 to 169.254.254.255, from
+/// the MAC address, as RFC 3927 section 2.1 suggests.
+fn link_local_ipv4_addr(mac: EthernetAddress) -> Ipv4Cidr {
+    let hash = mac
+        .0
+        .iter()
+        .fold(0u32, |h, &b| h.wrapping_mul(31).wrapping_add(u32::from(b)));
+    let n = hash % (254 * 256);
+    let address = Ipv4Address([169, 254, 1 + (n / 256) as u8, n as u8]);
+    Ipv4Cidr::new(address, 16)
+}
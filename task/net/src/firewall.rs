@@ -0,0 +1,436 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Ingress filtering, by a policy from the app config.
+//!
+//! Each rule names some destination ports, the source prefixes allowed to
+//! reach them, and optionally how many packets a second it lets in. A port
+//! named by any rule is guarded: TCP or UDP traffic to it only gets in if
+//! the first rule matching its port and source says so (and isn't over its
+//! rate). Traffic to other ports, and everything that isn't TCP or UDP --
+//! including the neighbor discovery, DHCP and mDNS the stack itself relies
+//! on -- goes by untouched.
+//!
+//! Frames are checked in the device tokens, before smoltcp sees them, across
+//! all VLANs alike. A refused frame is handed on to smoltcp cut down to
+//! nothing, which it throws away as a runt; that's the only way for a token
+//! to turn down what it's been given.
+//!
+//! Without the `firewall` feature, everything gets in.
+
+#[cfg(feature = "firewall")]
+pub(crate) use enabled::*;
+
+#[cfg(not(feature = "firewall"))]
+pub(crate) use disabled::*;
+
+#[cfg(not(feature = "firewall"))]
+mod disabled {
+    pub struct Firewall;
+
+    impl Firewall {
+        pub(crate) fn admit(&self, _frame: &[u8]) -> bool {
+            true
+        }
+        pub(crate) fn set_now(&self, _now: u64) {}
+        pub(crate) fn reset(&self) {}
+    }
+
+    pub(crate) fn claim_statics() -> &'static Firewall {
+        &Firewall
+    }
+}
+
+#[cfg(feature = "firewall")]
+mod enabled {
+    use crate::generated::FIREWALL_RULES;
+    use core::cell::RefCell;
+    use mutable_statics::mutable_statics;
+    use task_net_api::{FirewallRuleStats, FirewallStats};
+
+    const ETHERTYPE_IPV6: u16 = 0x86dd;
+    #[cfg(feature = "dhcpv4")]
+    const ETHERTYPE_IPV4: u16 = 0x0800;
+    #[cfg(feature = "dhcpv4")]
+    const IPV4_FRAGMENT_OFFSET: u16 = 0x1fff;
+    const IPPROTO_TCP: u8 = 6;
+    const IPPROTO_UDP: u8 = 17;
+
+    /// IPv6 extension headers smoltcp will look past to find TCP or UDP, so
+    /// we must too: hop-by-hop, routing and destination options. (Fragments
+    /// it just drops.)
+    const IPV6_EXTENSIONS: [u8; 3] = [0, 43, 60];
+
+    /// Rate limit tokens are in thousandths of a packet, so that they can be
+    /// topped up every millisecond.
+    const TOKENS_PER_PACKET: u64 = 1000;
+
+    /// An address prefix. IPv4 prefixes are IPv4-mapped, so that they can be
+    /// checked against IPv4 sources mapped the same way.
+    pub struct Prefix {
+        pub addr: [u8; 16],
+        pub len: u8,
+    }
+
+    impl Prefix {
+        fn contains(&self, addr: &[u8; 16]) -> bool {
+            let whole = usize::from(self.len / 8);
+            let rest = self.len % 8;
+            if self.addr[..whole] != addr[..whole] {
+                return false;
+            }
+            if rest == 0 {
+                return true;
+            }
+            let mask = !(0xffu8 >> rest);
+            self.addr[whole] & mask == addr[whole] & mask
+        }
+    }
+
+    /// A rule, as generated from the app config.
+    pub struct Rule {
+        pub ports: &'static [u16],
+        /// Sources allowed in; empty for any
+        pub sources: &'static [Prefix],
+        /// Packets let in per second, if limited
+        pub rate: Option<u16>,
+    }
+
+    impl Rule {
+        fn matches(&self, port: u16, src: &[u8; 16]) -> bool {
+            self.ports.contains(&port)
+                && (self.sources.is_empty()
+                    || self.sources.iter().any(|p| p.contains(src)))
+        }
+    }
+
+    pub struct Firewall {
+        state: RefCell<State>,
+    }
+
+    struct State {
+        now: u64,
+        rules: [RuleState; FIREWALL_RULES.len()],
+        denied: u32,
+    }
+
+    #[derive(Default)]
+    struct RuleState {
+        stats: FirewallRuleStats,
+        tokens: u64,
+        refilled_at: u64,
+    }
+
+    impl RuleState {
+        /// Tops up our tokens for the time since we last did, at `rate`
+        /// packets a second, and takes a packet's worth if there's enough.
+        fn take(&mut self, rate: u16, now: u64) -> bool {
+            let rate = u64::from(rate);
+            let elapsed = now.saturating_sub(self.refilled_at);
+            self.tokens =
+                (self.tokens + elapsed * rate).min(rate * TOKENS_PER_PACKET);
+            self.refilled_at = now;
+            if self.tokens < TOKENS_PER_PACKET {
+                return false;
+            }
+            self.tokens -= TOKENS_PER_PACKET;
+            true
+        }
+    }
+
+    impl Default for Firewall {
+        fn default() -> Self {
+            let firewall = Self {
+                state: RefCell::new(State {
+                    now: 0,
+                    rules: core::array::from_fn(|_| RuleState::default()),
+                    denied: 0,
+                }),
+            };
+            firewall.reset();
+            firewall
+        }
+    }
+
+    impl Firewall {
+        /// Decides whether `frame` gets in, and counts it.
+        pub(crate) fn admit(&self, frame: &[u8]) -> bool {
+            let Some((src, port)) = parse(frame) else {
+                return true;
+            };
+            if !FIREWALL_RULES.iter().any(|r| r.ports.contains(&port)) {
+                return true;
+            }
+
+            let mut s = self.state.borrow_mut();
+            let s = &mut *s;
+            let Some((rule, r)) = FIREWALL_RULES
+                .iter()
+                .zip(&mut s.rules)
+                .find(|(rule, _)| rule.matches(port, &src))
+            else {
+                s.denied = s.denied.wrapping_add(1);
+                return false;
+            };
+
+            if let Some(rate) = rule.rate {
+                if !r.take(rate, s.now) {
+                    r.stats.rate_limited = r.stats.rate_limited.wrapping_add(1);
+                    return false;
+                }
+            }
+            r.stats.hits = r.stats.hits.wrapping_add(1);
+            true
+        }
+
+        pub(crate) fn set_now(&self, now: u64) {
+            self.state.borrow_mut().now = now;
+        }
+
+        /// Clears the counters, and lets every rule have its full burst.
+        pub(crate) fn reset(&self) {
+            let mut s = self.state.borrow_mut();
+            let now = s.now;
+            s.denied = 0;
+            for (rule, r) in FIREWALL_RULES.iter().zip(&mut s.rules) {
+                *r = RuleState {
+                    stats: FirewallRuleStats::default(),
+                    tokens: u64::from(rule.rate.unwrap_or(0))
+                        * TOKENS_PER_PACKET,
+                    refilled_at: now,
+                };
+            }
+        }
+
+        pub(crate) fn stats(&self) -> FirewallStats {
+            FirewallStats {
+                rules: FIREWALL_RULES.len() as u32,
+                denied: self.state.borrow().denied,
+            }
+        }
+
+        pub(crate) fn rule_stats(
+            &self,
+            rule: usize,
+        ) -> Option<FirewallRuleStats> {
+            self.state.borrow().rules.get(rule).map(|r| r.stats)
+        }
+    }
+
+    /// Grabs the firewall state.  Can only be called once!
+    pub(crate) fn claim_statics() -> &'static Firewall {
+        let firewall = mutable_statics! {
+            static mut FIREWALL: [Firewall; 1] = [Default::default; _];
+        };
+        &firewall[0]
+    }
+
+    /// Picks the source address (IPv4-mapped, for IPv4) and destination port
+    /// out of an (untagged) Ethernet frame, if it's TCP or UDP.
+    fn parse(frame: &[u8]) -> Option<([u8; 16], u16)> {
+        let be16 = |at: usize| {
+            frame
+                .get(at..at + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        let mut src = [0; 16];
+        let (proto, l4) = match be16(12)? {
+            ETHERTYPE_IPV6 => {
+                src.copy_from_slice(frame.get(22..38)?);
+                let (mut proto, mut l4) = (*frame.get(14 + 6)?, 14 + 40);
+                while IPV6_EXTENSIONS.contains(&proto) {
+                    proto = *frame.get(l4)?;
+                    l4 += (usize::from(*frame.get(l4 + 1)?) + 1) * 8;
+                }
+                (proto, l4)
+            }
+            #[cfg(feature = "dhcpv4")]
+            ETHERTYPE_IPV4 => {
+                // A fragment other than the first has no TCP or UDP header,
+                // just more payload, so there's no port to check. smoltcp
+                // can't reassemble fragments and drops them, so they go by
+                // unchecked.
+                if be16(14 + 6)? & IPV4_FRAGMENT_OFFSET != 0 {
+                    return None;
+                }
+                src[10..12].fill(0xff);
+                src[12..].copy_from_slice(frame.get(26..30)?);
+                let ihl = usize::from(*frame.get(14)? & 0xf);
+                (*frame.get(14 + 9)?, 14 + ihl * 4)
+            }
+            _ => return None,
+        };
+        match proto {
+            IPPROTO_TCP | IPPROTO_UDP => Some((src, be16(l4 + 2)?)),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        extern crate std;
+        use std::vec::Vec;
+
+        const SRC: [u8; 16] = [
+            0xfd, 0x00, 0x11, 0x22, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01,
+        ];
+
+        /// An IPv6 frame carrying `next` after `ext`, which holds any
+        /// extension headers, then `l4`.
+        fn ipv6(next: u8, ext: &[u8], l4: &[u8]) -> Vec<u8> {
+            let mut frame = std::vec![0; 12];
+            frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+            frame.extend_from_slice(&[0x60, 0, 0, 0, 0, 0, next, 64]);
+            frame.extend_from_slice(&SRC);
+            frame.extend_from_slice(&[0; 16]);
+            frame.extend_from_slice(ext);
+            frame.extend_from_slice(l4);
+            frame
+        }
+
+        /// A UDP or TCP header's source and destination ports.
+        fn ports(dst: u16) -> [u8; 4] {
+            let [a, b] = 50000u16.to_be_bytes();
+            let [c, d] = dst.to_be_bytes();
+            [a, b, c, d]
+        }
+
+        #[test]
+        fn ipv6_ports() {
+            assert_eq!(
+                parse(&ipv6(IPPROTO_UDP, &[], &ports(7))),
+                Some((SRC, 7))
+            );
+            assert_eq!(
+                parse(&ipv6(IPPROTO_TCP, &[], &ports(22))),
+                Some((SRC, 22))
+            );
+            // ICMPv6 isn't ours to judge.
+            assert_eq!(parse(&ipv6(58, &[], &ports(7))), None);
+        }
+
+        #[test]
+        fn ipv6_extension_headers() {
+            // Hop-by-hop options, eight bytes, then destination options,
+            // sixteen, then UDP.
+            let mut ext = std::vec![60, 0, 0, 0, 0, 0, 0, 0];
+            ext.extend_from_slice(&[IPPROTO_UDP, 1]);
+            ext.extend_from_slice(&[0; 14]);
+            assert_eq!(parse(&ipv6(0, &ext, &ports(7))), Some((SRC, 7)));
+
+            // A routing header, then TCP.
+            let ext = [IPPROTO_TCP, 0, 0, 0, 0, 0, 0, 0];
+            assert_eq!(parse(&ipv6(43, &ext, &ports(22))), Some((SRC, 22)));
+
+            // A fragment header isn't looked past.
+            let ext = [IPPROTO_UDP, 0, 0, 0, 0, 0, 0, 0];
+            assert_eq!(parse(&ipv6(44, &ext, &ports(7))), None);
+        }
+
+        #[test]
+        fn truncated_frames() {
+            let ext = [IPPROTO_UDP, 0, 0, 0, 0, 0, 0, 0];
+            let frame = ipv6(0, &ext, &ports(7));
+            assert!(parse(&frame).is_some());
+            for len in 0..frame.len() {
+                assert_eq!(parse(&frame[..len]), None, "{len} bytes");
+            }
+
+            // An extension header claiming to run past the end.
+            let ext = [IPPROTO_UDP, 0xff, 0, 0, 0, 0, 0, 0];
+            assert_eq!(parse(&ipv6(0, &ext, &ports(7))), None);
+        }
+
+        #[cfg(feature = "dhcpv4")]
+        fn ipv4(ihl: u8, frag: u16, l4: &[u8]) -> Vec<u8> {
+            let mut frame = std::vec![0; 12];
+            frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            frame.extend_from_slice(&[0x40 | ihl, 0, 0, 0, 0, 0]);
+            frame.extend_from_slice(&frag.to_be_bytes());
+            frame.extend_from_slice(&[64, IPPROTO_UDP, 0, 0]);
+            frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+            frame.resize(14 + usize::from(ihl) * 4, 0);
+            frame.extend_from_slice(l4);
+            frame
+        }
+
+        #[cfg(feature = "dhcpv4")]
+        #[test]
+        fn ipv4_ports_and_fragments() {
+            let mut mapped = [0; 16];
+            mapped[10..12].fill(0xff);
+            mapped[12..].copy_from_slice(&[10, 0, 0, 1]);
+
+            assert_eq!(parse(&ipv4(5, 0, &ports(7))), Some((mapped, 7)));
+            // Options push the header along.
+            assert_eq!(parse(&ipv4(6, 0, &ports(7))), Some((mapped, 7)));
+            // The first fragment (more fragments, offset zero) has ports...
+            assert_eq!(parse(&ipv4(5, 0x2000, &ports(7))), Some((mapped, 7)));
+            // ...but the rest don't, whatever their payload looks like.
+            assert_eq!(parse(&ipv4(5, 0x2001, &ports(7))), None);
+            assert_eq!(parse(&ipv4(5, 0x0100, &ports(7))), None);
+
+            let frame = ipv4(5, 0, &ports(7));
+            for len in 0..frame.len() {
+                assert_eq!(parse(&frame[..len]), None, "{len} bytes");
+            }
+        }
+
+        #[test]
+        fn prefix_masks() {
+            let prefix = |len| Prefix { addr: SRC, len };
+            let mut other = SRC;
+
+            assert!(prefix(0).contains(&[0; 16]));
+            assert!(prefix(128).contains(&SRC));
+
+            // fd00:1122::/28 covers fd00:112x::, but not fd00:113x::.
+            other[3] = 0x2f;
+            assert!(prefix(28).contains(&other));
+            other[3] = 0x32;
+            assert!(!prefix(28).contains(&other));
+
+            // fd00::/9 takes just the top bit of the second byte.
+            other = SRC;
+            other[1] = 0x7f;
+            assert!(prefix(9).contains(&other));
+            other[1] = 0x80;
+            assert!(!prefix(9).contains(&other));
+
+            // The last bit of a /127 doesn't matter; the one before does.
+            other = SRC;
+            other[15] = 0x00;
+            assert!(prefix(127).contains(&other));
+            assert!(!prefix(128).contains(&other));
+            other[15] = 0x03;
+            assert!(!prefix(127).contains(&other));
+        }
+
+        #[test]
+        fn token_refill() {
+            // Two packets a second, starting with a full burst.
+            let mut r = RuleState {
+                tokens: 2 * TOKENS_PER_PACKET,
+                ..Default::default()
+            };
+            assert!(r.take(2, 0));
+            assert!(r.take(2, 0));
+            assert!(!r.take(2, 0));
+
+            // Half a second earns one more packet, and no more.
+            assert!(!r.take(2, 499));
+            assert!(r.take(2, 500));
+            assert!(!r.take(2, 500));
+
+            // A long wait earns only a full burst.
+            assert!(r.take(2, 60_000));
+            assert!(r.take(2, 60_000));
+            assert!(!r.take(2, 60_000));
+
+            // Time going backwards earns nothing.
+            assert!(!r.take(2, 0));
+        }
+    }
+}
@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 
 pub mod pins;
 
@@ -46,11 +46,27 @@ mod server_impl;
 #[cfg(feature = "mgmt")]
 pub(crate) mod mgmt;
 
+mod capture;
+#[cfg(feature = "dhcpv4")]
+mod dhcp;
+mod firewall;
+#[cfg(feature = "mdns")]
+mod mdns;
+#[cfg(feature = "slaac")]
+mod slaac;
+mod timestamps;
+
 mod idl {
     use task_net_api::{
-        KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
-        MacAddressBlock, ManagementCounters, ManagementLinkStatus, MgmtError,
-        PhyError, RecvError, SendError, SocketName, UdpMetadata,
+        Address, CableDiagnostics, CaptureError, CaptureFilter, CaptureStats,
+        DhcpError, DhcpStatus, FirewallError, FirewallRuleStats, FirewallStats,
+        InterfaceStats, Ipv6Error, Ipv6Status, KszError, KszMacTableEntry,
+        KszPortStatus, KszVLanEntry, LargePayloadBehavior, MacAddress,
+        MacAddressBlock, ManagementCounters, ManagementLinkQuality,
+        ManagementLinkStatus, MgmtError, MulticastError, PhyError,
+        PtpTimestamp, RecvError, SendError, SocketName, SocketStats,
+        StatsError, TcpEndpoint, TcpError, TcpStatus, TimestampError,
+        TimestampedUdpMetadata, UdpMetadata,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -149,7 +165,7 @@ const RX_WATCHDOG_INTERVAL: u64 = 60_000;
 /// debugger.
 static ITER_COUNT: AtomicU32 = AtomicU32::new(0);
 
-#[export_name = "main"]
+#[cfg_attr(not(test), export_name = "main")]
 fn main() -> ! {
     let sys = SYS.get_task_id();
     let sys = Sys::from(sys);
@@ -193,6 +209,8 @@ fn main() -> ! {
         unsafe { &*device::TIM16::ptr() },
         notifications::MDIO_TIMER_IRQ_MASK,
     );
+    #[cfg(feature = "ptp")]
+    eth.start_ptp_clock(generated::PTP_ADDEND);
 
     // Set up the network stack.
     #[cfg(feature = "vpd-mac")]
@@ -210,11 +228,18 @@ fn main() -> ! {
     // Turn on our IRQ.
     userlib::sys_irq_control(notifications::ETH_IRQ_MASK, true);
 
-    // We use two timers:
+    // We use two timers, or three with DHCP, mDNS or TCP:
     #[derive(Copy, Clone, Enum)]
     enum Timers {
         Wake,
         Watchdog,
+        #[cfg(any(
+            feature = "dhcpv4",
+            feature = "mdns",
+            feature = "slaac",
+            feature = "tcp"
+        ))]
+        Poll,
     }
     let mut multitimer =
         Multitimer::<Timers>::new(notifications::WAKE_TIMER_BIT);
@@ -242,6 +267,17 @@ fn main() -> ! {
         let now = sys_get_timer().now;
         let activity = server.poll(now);
 
+        // DHCP, mDNS, SLAAC and TCP have timers of their own (retransmits and
+        // the like), which only need to wake us up: the poll above is what
+        // serves them.
+        #[cfg(any(
+            feature = "dhcpv4",
+            feature = "mdns",
+            feature = "slaac",
+            feature = "tcp"
+        ))]
+        multitimer.set_timer(Timers::Poll, server.poll_at(now), None);
+
         if activity.mac_rx {
             // Whenever we observe activity we bump the timer forward. Because
             // we're going to poll the timer below (after doing this) and we
@@ -266,6 +302,13 @@ fn main() -> ! {
                         // timer is set to auto-repeat
                     }
                     Timers::Watchdog => panic!("MAC RX watchdog"),
+                    #[cfg(any(
+                        feature = "dhcpv4",
+                        feature = "mdns",
+                        feature = "slaac",
+                        feature = "tcp"
+                    ))]
+                    Timers::Poll => (),
                 }
             }
             let mut msgbuf = [0u8; idl::INCOMING_SIZE];
@@ -323,8 +366,21 @@ fn ethernet_capabilities(
     caps.max_transmission_unit = 1514;
     caps.max_burst_size = Some(1514 * eth.max_tx_burst_len());
 
-    // We do not rely on _any_ of the IP checksum features, so we can leave
-    // caps.checksum at default.
+    // The MAC fills in IPv4, TCP, UDP and ICMP checksums on the way out (see
+    // the CIC field in the TX descriptors), so smoltcp needn't. We still
+    // check everything on the way in: the MAC only checks what it can
+    // parse, and lets through IPv6 packets with extension headers, say,
+    // unchecked.
+    use smoltcp::phy::Checksum;
+    caps.checksum.udp = Checksum::Rx;
+    caps.checksum.tcp = Checksum::Rx;
+    caps.checksum.icmpv6 = Checksum::Rx;
+    // (smoltcp only has IPv4 with DHCP.)
+    #[cfg(feature = "dhcpv4")]
+    {
+        caps.checksum.ipv4 = Checksum::Rx;
+        caps.checksum.icmpv4 = Checksum::Rx;
+    }
 
     caps
 }
@@ -0,0 +1,296 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! mDNS, so that the SP can be found on a link without knowing its address.
+//!
+//! The responder itself lives in `mdns-responder`; this gives it a socket on
+//! each interface, and tells it who we are and where. We call ourselves
+//! `<board>-<serial>`, taking the serial from VPD where we have it and
+//! making one up from the MAC address where we don't, and advertise the
+//! service and socket named in the build configuration under that name.
+
+use crate::generated::{MDNS_BOARD, MDNS_PORT, MDNS_SERVICE};
+use core::fmt::Write;
+use heapless::{String, Vec};
+use mdns_responder::{
+    Addresses, Destination, Service, IPV6_GROUP, MAX_PACKET, PORT,
+};
+use ringbuf::*;
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::socket::udp;
+use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint, Ipv6Address};
+use userlib::UnwrapLite;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Trace {
+    None,
+    Announce(usize),
+    Respond { vlan: usize, unicast: bool },
+    SendFailed(usize),
+    TooBig(usize),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Packets we can hold in each direction.
+const PACKETS: usize = 4;
+
+/// Times we announce ourselves when our addresses change, a second apart
+/// (RFC 6762 section 8.3).
+const ANNOUNCEMENTS: u8 = 2;
+const ANNOUNCE_INTERVAL_MS: u64 = 1000;
+
+/// mDNS packets are sent with an IP TTL of 255 (RFC 6762 section 11).
+const HOP_LIMIT: u8 = 255;
+
+pub struct MdnsStorage {
+    rx_meta: [udp::PacketMetadata; PACKETS],
+    rx: [u8; 2 * MAX_PACKET],
+    tx_meta: [udp::PacketMetadata; PACKETS],
+    tx: [u8; 2 * MAX_PACKET],
+    /// Where responses are put together, so as not to need room on the stack
+    scratch: [u8; MAX_PACKET],
+}
+
+impl Default for MdnsStorage {
+    fn default() -> Self {
+        Self {
+            rx_meta: [udp::PacketMetadata::EMPTY; PACKETS],
+            rx: [0; 2 * MAX_PACKET],
+            tx_meta: [udp::PacketMetadata::EMPTY; PACKETS],
+            tx: [0; 2 * MAX_PACKET],
+            scratch: [0; MAX_PACKET],
+        }
+    }
+}
+
+/// Who we say we are, which is the same on every interface.
+#[derive(Clone)]
+pub(crate) struct Identity {
+    instance: String<63>,
+    txt: Vec<String<32>, 4>,
+}
+
+impl Identity {
+    pub(crate) fn new(mac: [u8; 6]) -> Self {
+        let mut instance = String::new();
+        let mut txt = Vec::new();
+        let mut board = String::new();
+        write!(board, "board={MDNS_BOARD}").unwrap_lite();
+        txt.push(board).unwrap_lite();
+
+        #[cfg(feature = "vpd-mac")]
+        if let Some(id) = vpd_identity() {
+            let serial = trim(&id.serial);
+            let part = trim(&id.part_number);
+            if !serial.is_empty()
+                && serial.bytes().all(|b| b.is_ascii_alphanumeric())
+            {
+                write!(instance, "{MDNS_BOARD}-{serial}").unwrap_lite();
+                let mut s = String::new();
+                write!(s, "serial={serial}").unwrap_lite();
+                txt.push(s).unwrap_lite();
+                let mut s = String::new();
+                write!(s, "part={part}").unwrap_lite();
+                txt.push(s).unwrap_lite();
+                let mut s = String::new();
+                write!(s, "rev={}", id.revision).unwrap_lite();
+                txt.push(s).unwrap_lite();
+                return Self { instance, txt };
+            }
+        }
+
+        write!(
+            instance,
+            "{MDNS_BOARD}-{:02x}{:02x}{:02x}",
+            mac[3], mac[4], mac[5]
+        )
+        .unwrap_lite();
+        Self { instance, txt }
+    }
+}
+
+#[cfg(feature = "vpd-mac")]
+fn vpd_identity() -> Option<task_packrat_api::VpdIdentity> {
+    // As with the MAC address, packrat has this by the time we get here.
+    use task_packrat_api::Packrat;
+    let packrat = Packrat::from(crate::PACKRAT.get_task_id());
+    packrat.get_identity().ok()
+}
+
+/// Turns a NUL-padded VPD field into a string, or an empty one if it isn't
+/// valid.
+#[cfg(feature = "vpd-mac")]
+fn trim(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
+
+pub(crate) struct Mdns {
+    vlan: usize,
+    identity: Identity,
+    socket: SocketHandle,
+    scratch: &'static mut [u8; MAX_PACKET],
+    /// Addresses we last announced
+    announced: Addresses,
+    /// Announcements we've still to make, and when the next is due
+    announcements: u8,
+    next_announcement: u64,
+}
+
+impl Mdns {
+    pub(crate) fn new(
+        vlan: usize,
+        identity: Identity,
+        storage: &'static mut MdnsStorage,
+        socket_set: &mut SocketSet<'static>,
+    ) -> Self {
+        let mut socket = udp::Socket::new(
+            udp::PacketBuffer::new(
+                &mut storage.rx_meta[..],
+                &mut storage.rx[..],
+            ),
+            udp::PacketBuffer::new(
+                &mut storage.tx_meta[..],
+                &mut storage.tx[..],
+            ),
+        );
+        // Listen on any address, so as to hear queries to the group.
+        socket.bind(PORT).unwrap_lite();
+        socket.set_hop_limit(Some(HOP_LIMIT));
+
+        Self {
+            vlan,
+            identity,
+            socket: socket_set.add(socket),
+            scratch: &mut storage.scratch,
+            announced: Addresses::default(),
+            announcements: 0,
+            next_announcement: 0,
+        }
+    }
+
+    /// Answers whatever's come in, and announces us if our addresses have
+    /// changed. Returns true if anything was queued to send.
+    pub(crate) fn poll(
+        &mut self,
+        now: u64,
+        iface: &Interface,
+        socket_set: &mut SocketSet<'static>,
+    ) -> bool {
+        let addrs = addresses(iface);
+        if addrs != self.announced {
+            self.announced = addrs;
+            self.announcements = ANNOUNCEMENTS;
+            self.next_announcement = now;
+        }
+
+        let txt: Vec<&[u8], 4> =
+            self.identity.txt.iter().map(|t| t.as_bytes()).collect();
+        let service = Service {
+            instance: self.identity.instance.as_bytes(),
+            service: MDNS_SERVICE,
+            port: MDNS_PORT,
+            txt: &txt,
+        };
+
+        let socket = socket_set.get_mut::<udp::Socket<'_>>(self.socket);
+        let mut activity = false;
+        while let Ok((query, from)) = socket.recv() {
+            let (len, dest) = match mdns_responder::respond(
+                &service,
+                &addrs,
+                query,
+                from.port,
+                self.scratch,
+            ) {
+                Some(r) => r,
+                None => continue,
+            };
+            let to = match dest {
+                Destination::Multicast => group(from.addr),
+                Destination::Unicast => from,
+            };
+            ringbuf_entry!(Trace::Respond {
+                vlan: self.vlan,
+                unicast: dest == Destination::Unicast,
+            });
+            self.send(socket, len, to);
+            activity = true;
+        }
+
+        if self.announcements > 0 && now >= self.next_announcement {
+            ringbuf_entry!(Trace::Announce(self.vlan));
+            match mdns_responder::announce(&service, &addrs, self.scratch) {
+                Some(len) => {
+                    if addrs.ipv6.is_some() {
+                        self.send(socket, len, ipv6_group());
+                    }
+                    #[cfg(feature = "dhcpv4")]
+                    if addrs.ipv4.is_some() {
+                        self.send(socket, len, ipv4_group());
+                    }
+                }
+                None => ringbuf_entry!(Trace::TooBig(self.vlan)),
+            }
+            self.announcements -= 1;
+            self.next_announcement = now + ANNOUNCE_INTERVAL_MS;
+            activity = true;
+        }
+
+        activity
+    }
+
+    /// Returns the next time we need to `poll`, absent any packets.
+    pub(crate) fn poll_at(&self) -> u64 {
+        if self.announcements > 0 {
+            self.next_announcement
+        } else {
+            u64::MAX
+        }
+    }
+
+    fn send(&self, socket: &mut udp::Socket<'_>, len: usize, to: IpEndpoint) {
+        // Whoever wanted this will ask again.
+        if socket.send_slice(&self.scratch[..len], to).is_err() {
+            ringbuf_entry!(Trace::SendFailed(self.vlan));
+        }
+    }
+}
+
+/// Gets the addresses an interface has, for A and AAAA records.
+fn addresses(iface: &Interface) -> Addresses {
+    let mut addrs = Addresses::default();
+    for cidr in iface.ip_addrs() {
+        match cidr {
+            IpCidr::Ipv6(c) => {
+                addrs.ipv6.get_or_insert(c.address().0);
+            }
+            #[cfg(feature = "dhcpv4")]
+            IpCidr::Ipv4(c) => {
+                addrs.ipv4.get_or_insert(c.address().0);
+            }
+        }
+    }
+    addrs
+}
+
+/// Returns the mDNS group endpoint for the address family of `addr`.
+fn group(addr: IpAddress) -> IpEndpoint {
+    match addr {
+        IpAddress::Ipv6(_) => ipv6_group(),
+        #[cfg(feature = "dhcpv4")]
+        IpAddress::Ipv4(_) => ipv4_group(),
+    }
+}
+
+fn ipv6_group() -> IpEndpoint {
+    IpEndpoint::new(Ipv6Address(IPV6_GROUP).into(), PORT)
+}
+
+#[cfg(feature = "dhcpv4")]
+fn ipv4_group() -> IpEndpoint {
+    let addr = smoltcp::wire::Ipv4Address(mdns_responder::IPV4_GROUP);
+    IpEndpoint::new(addr.into(), PORT)
+}
@@ -8,7 +8,8 @@ use drv_stm32xx_sys_api::{self as sys_api, OutputType, Pull, Speed, Sys};
 use ksz8463::{Error as KszError, MIBCounterValue, Register as KszRegister};
 use ringbuf::*;
 use task_net_api::{
-    ManagementCounters, ManagementLinkStatus, MgmtError, PhyError,
+    ManagementCounters, ManagementLinkQuality, ManagementLinkQualityVsc85x2,
+    ManagementLinkStatus, MgmtError, PhyError,
 };
 use userlib::hl::sleep_for;
 use vsc7448_pac::{phy, types::PhyRegisterAddress};
@@ -93,7 +94,11 @@ impl Config {
         // The KSZ8463 connects to the SP over RMII, then sends data to the
         // VSC8552 over 100-BASE FX
         self.ksz8463
-            .configure(ksz8463::Mode::Fiber, self.ksz8463_vlan_mode)
+            .configure(
+                ksz8463::Mode::Fiber,
+                self.ksz8463_vlan_mode,
+                &crate::bsp_support::KSZ8463_VLANS,
+            )
             .unwrap();
         self.ksz8463
     }
@@ -341,4 +346,31 @@ impl Bsp {
 
         Ok(out)
     }
+
+    pub fn management_link_quality(
+        &self,
+        eth: &Ethernet,
+    ) -> Result<ManagementLinkQuality, MgmtError> {
+        let mut out = ManagementLinkQuality::default();
+        let rw = &mut MiimBridge::new(eth);
+        for (i, q) in out.vsc85x2.iter_mut().enumerate() {
+            let port = i as u8;
+            let phy = self.vsc85x2.phy(port, rw);
+            match phy.phy.link_quality() {
+                Ok(lq) => {
+                    *q = ManagementLinkQualityVsc85x2 {
+                        rx_errors: lq.rx_errors,
+                        false_carriers: lq.false_carriers,
+                        disconnects: lq.disconnects,
+                        downshifted: lq.downshifted,
+                    }
+                }
+                Err(err) => {
+                    ringbuf_entry!(Trace::Vsc85x2Err { port, err });
+                    return Err(MgmtError::VscError);
+                }
+            }
+        }
+        Ok(out)
+    }
 }
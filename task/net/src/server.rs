@@ -13,16 +13,36 @@ use crate::generated::VLAN_RANGE;
 use drv_stm32h7_eth as eth;
 use idol_runtime::{ClientError, RequestError};
 use task_net_api::{
-    KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
-    ManagementCounters, ManagementLinkStatus, MgmtError, PhyError, RecvError,
-    SendError, SocketName, UdpMetadata,
+    CableDiagnostics, CaptureError, CaptureFilter, CaptureStats, DhcpError,
+    DhcpStatus, FirewallError, FirewallRuleStats, FirewallStats,
+    InterfaceStats, Ipv6Error, Ipv6Status, KszError, KszMacTableEntry,
+    KszPortStatus, KszVLanEntry, LargePayloadBehavior, MacAddress,
+    ManagementCounters, ManagementLinkQuality, ManagementLinkStatus, MgmtError,
+    MulticastError, PhyError, PtpTimestamp, RecvError, SendError, SocketName,
+    SocketStats, StatsError, TcpEndpoint, TcpError, TcpStatus, TimestampError,
+    TimestampedUdpMetadata, UdpMetadata, MULTICAST_GROUPS,
 };
 
+use crate::capture::{self, Tap};
+#[cfg(feature = "dhcpv4")]
+use crate::dhcp::{Dhcp, DhcpStorage};
+use crate::firewall::Firewall;
+#[cfg(feature = "mdns")]
+use crate::mdns::{Identity, Mdns, MdnsStorage};
+#[cfg(feature = "slaac")]
+use crate::slaac::{Slaac, SlaacStorage};
+use crate::timestamps::Timestamps;
+
+use core::cell::Cell;
 use core::iter::zip;
 use heapless::Vec;
 use smoltcp::iface::{Interface, SocketHandle, SocketStorage};
+#[cfg(feature = "tcp")]
+use smoltcp::socket::tcp;
 use smoltcp::socket::udp;
-use smoltcp::wire::{EthernetAddress, Ipv6Cidr};
+use smoltcp::wire::{
+    EthernetAddress, IpAddress, IpListenEndpoint, Ipv6Address, Ipv6Cidr,
+};
 use userlib::{sys_post, sys_refresh_task_id, UnwrapLite};
 use zerocopy::byteorder::U16;
 
@@ -39,7 +59,21 @@ where
         large_payload_behavior: LargePayloadBehavior,
         payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
     ) -> Result<UdpMetadata, RequestError<RecvError>> {
-        self.net_recv_packet(msg, socket, large_payload_behavior, payload)
+        let (meta, _) =
+            self.net_recv_packet(msg, socket, large_payload_behavior, payload)?;
+        Ok(meta)
+    }
+
+    fn recv_packet_timestamped(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        large_payload_behavior: LargePayloadBehavior,
+        payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<TimestampedUdpMetadata, RequestError<RecvError>> {
+        let (meta, timestamp) =
+            self.net_recv_packet(msg, socket, large_payload_behavior, payload)?;
+        Ok(TimestampedUdpMetadata { meta, timestamp })
     }
 
     fn send_packet(
@@ -147,6 +181,44 @@ where
         Err(KszError::NotAvailable.into())
     }
 
+    #[cfg(not(feature = "ksz8463"))]
+    fn read_ksz8463_vlan(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _i: u8,
+    ) -> Result<Option<KszVLanEntry>, RequestError<KszError>> {
+        Err(KszError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "ksz8463"))]
+    fn set_ksz8463_vlan_membership(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _port: u8,
+        _vid: u16,
+        _member: bool,
+    ) -> Result<(), RequestError<KszError>> {
+        Err(KszError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "ksz8463"))]
+    fn ksz8463_cable_diagnostics(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _port: u8,
+    ) -> Result<CableDiagnostics, RequestError<KszError>> {
+        Err(KszError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "ksz8463"))]
+    fn ksz8463_port_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _port: u8,
+    ) -> Result<KszPortStatus, RequestError<KszError>> {
+        Err(KszError::NotAvailable.into())
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     // Main KSZ8463 functions
     #[cfg(feature = "ksz8463")]
@@ -202,6 +274,95 @@ where
         Ok(out)
     }
 
+    #[cfg(feature = "ksz8463")]
+    fn read_ksz8463_vlan(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        i: u8,
+    ) -> Result<Option<KszVLanEntry>, RequestError<KszError>> {
+        if i >= 16 {
+            return Err(KszError::BadVLanIndex.into());
+        }
+        let (_eth, bsp) = self.eth_bsp();
+        let out = bsp.ksz8463().read_vlan_table(i).unwrap_lite();
+        Ok(out.map(KszVLanEntry::from))
+    }
+
+    #[cfg(feature = "ksz8463")]
+    fn set_ksz8463_vlan_membership(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+        vid: u16,
+        member: bool,
+    ) -> Result<(), RequestError<KszError>> {
+        if !(1..=3).contains(&port) {
+            return Err(KszError::BadPort.into());
+        }
+        if vid > 0xFFF {
+            return Err(KszError::BadVid.into());
+        }
+        let (_eth, bsp) = self.eth_bsp();
+        let r = bsp.ksz8463().set_vlan_membership(port, vid, member);
+        if let Err(ksz8463::Error::VLanTableFull) = r {
+            return Err(KszError::VLanTableFull.into());
+        }
+        r.unwrap_lite();
+        Ok(())
+    }
+
+    #[cfg(feature = "ksz8463")]
+    fn ksz8463_cable_diagnostics(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<CableDiagnostics, RequestError<KszError>> {
+        if !(1..=2).contains(&port) {
+            return Err(KszError::BadPort.into());
+        }
+        let (_eth, bsp) = self.eth_bsp();
+        let r = bsp.ksz8463().cable_diagnostics(port);
+        match r {
+            Err(ksz8463::Error::NotCopper) => {
+                return Err(KszError::NotCopper.into())
+            }
+            Err(ksz8463::Error::LinkMdTimeout) => {
+                return Err(KszError::CableTestTimeout.into())
+            }
+            _ => (),
+        }
+        Ok(r.unwrap_lite().into())
+    }
+
+    #[cfg(feature = "ksz8463")]
+    fn ksz8463_port_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<KszPortStatus, RequestError<KszError>> {
+        if !(1..=3).contains(&port) {
+            return Err(KszError::BadPort.into());
+        }
+        let (_eth, bsp) = self.eth_bsp();
+        let ksz8463 = bsp.ksz8463();
+        // Port 3 faces the SP over RMII, and has no PHY to ask
+        let link_up = port == 3 || ksz8463.port_link_up(port).unwrap_lite();
+        let default_vid = ksz8463.port_default_vid(port).unwrap_lite();
+        let mut vlans = 0;
+        for i in 0..16 {
+            if let Some(e) = ksz8463.read_vlan_table(i).unwrap_lite() {
+                if e.port_mask & (1 << (port - 1)) != 0 {
+                    vlans |= 1 << i;
+                }
+            }
+        }
+        Ok(KszPortStatus {
+            link_up,
+            default_vid,
+            vlans,
+        })
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     // Management network functions, if it's not present
     #[cfg(not(feature = "mgmt"))]
@@ -220,6 +381,14 @@ where
         Err(MgmtError::NotAvailable.into())
     }
 
+    #[cfg(not(feature = "mgmt"))]
+    fn management_link_quality(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<ManagementLinkQuality, RequestError<MgmtError>> {
+        Err(MgmtError::NotAvailable.into())
+    }
+
     #[cfg(feature = "mgmt")]
     fn management_link_status(
         &mut self,
@@ -239,11 +408,731 @@ where
         let out = bsp.management_counters(eth).map_err(MgmtError::from)?;
         Ok(out)
     }
+
+    #[cfg(feature = "mgmt")]
+    fn management_link_quality(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<ManagementLinkQuality, RequestError<MgmtError>> {
+        let (eth, bsp) = self.eth_bsp();
+        let out = bsp.management_link_quality(eth).map_err(MgmtError::from)?;
+        Ok(out)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // IPv6 address functions
+    fn ipv6_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        vid: u16,
+    ) -> Result<Ipv6Status, RequestError<Ipv6Error>> {
+        #[cfg(feature = "vlan")]
+        let vlan_index = {
+            if !VLAN_RANGE.contains(&vid) {
+                return Err(Ipv6Error::InvalidVLan.into());
+            }
+            usize::from(vid - VLAN_RANGE.start)
+        };
+        #[cfg(not(feature = "vlan"))]
+        let vlan_index = {
+            let _ = vid;
+            0
+        };
+        let vlan = &self.vlan_state[vlan_index];
+
+        #[cfg(feature = "slaac")]
+        let status = vlan.slaac.status(userlib::sys_get_timer().now);
+        #[cfg(not(feature = "slaac"))]
+        let status = Ipv6Status {
+            link_local: vlan.ipv6_addr.into(),
+            addresses: [None; task_net_api::SLAAC_ADDRESSES],
+            router: None,
+            router_for: 0,
+        };
+        Ok(status)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Multicast functions
+    fn join_multicast_group(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+        group: task_net_api::Address,
+    ) -> Result<(), RequestError<MulticastError>> {
+        let group = IpAddress::from(group);
+        let vlan_index = self.multicast_vlan_index(msg, socket, vid, group)?;
+        let bit = 1 << socket as usize;
+        let vlan = &mut self.vlan_state[vlan_index];
+
+        if let Some(m) = vlan
+            .multicast
+            .iter_mut()
+            .flatten()
+            .find(|m| m.group == group)
+        {
+            m.sockets |= bit;
+            return Ok(());
+        }
+        let slot = vlan
+            .multicast
+            .iter_mut()
+            .find(|m| m.is_none())
+            .ok_or(MulticastError::TooManyGroups)?;
+
+        // smoltcp drops IPv4 datagrams for groups the interface isn't in, so
+        // it needs telling; it also sends the IGMP report. It has no MLD, so
+        // IPv6 groups are only tracked here.
+        #[cfg(feature = "dhcpv4")]
+        if let IpAddress::Ipv4(addr) = group {
+            let now = smoltcp::time::Instant::from_millis(
+                userlib::sys_get_timer().now as i64,
+            );
+            match vlan.iface.join_multicast_group(&mut vlan.device, addr, now) {
+                Err(smoltcp::iface::MulticastError::GroupTableFull) => {
+                    return Err(MulticastError::TooManyGroups.into());
+                }
+                // Anything else means the report didn't go out, but we're in
+                // the group, and will say so when next queried.
+                _ => (),
+            }
+        }
+        *slot = Some(Membership {
+            group,
+            sockets: bit,
+        });
+        self.update_multicast_filter();
+        Ok(())
+    }
+
+    fn leave_multicast_group(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+        group: task_net_api::Address,
+    ) -> Result<(), RequestError<MulticastError>> {
+        let group = IpAddress::from(group);
+        let vlan_index = self.multicast_vlan_index(msg, socket, vid, group)?;
+        let bit = 1 << socket as usize;
+        let vlan = &mut self.vlan_state[vlan_index];
+
+        let Some(slot) = vlan
+            .multicast
+            .iter_mut()
+            .find(|m| m.map_or(false, |m| m.group == group))
+        else {
+            return Ok(());
+        };
+        let m = slot.as_mut().unwrap_lite();
+        m.sockets &= !bit;
+        if m.sockets != 0 {
+            return Ok(());
+        }
+        *slot = None;
+
+        #[cfg(feature = "dhcpv4")]
+        if let IpAddress::Ipv4(addr) = group {
+            let now = smoltcp::time::Instant::from_millis(
+                userlib::sys_get_timer().now as i64,
+            );
+            // smoltcp forgets the group before sending the leave report, so
+            // failing to send it leaves nothing to undo.
+            let _ =
+                vlan.iface
+                    .leave_multicast_group(&mut vlan.device, addr, now);
+        }
+        self.update_multicast_filter();
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Timestamp functions
+    #[cfg(feature = "ptp")]
+    fn tx_timestamp(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<PtpTimestamp, RequestError<TimestampError>> {
+        let socket_index = socket as usize;
+        if generated::SOCKET_OWNERS[socket_index].0.index()
+            != msg.sender.index()
+        {
+            return Err(TimestampError::NotYours.into());
+        }
+
+        #[cfg(feature = "vlan")]
+        let vlan_index = {
+            // Convert from absolute VID to an index in our VLAN array
+            if !VLAN_RANGE.contains(&vid) {
+                return Err(TimestampError::InvalidVLan.into());
+            }
+            usize::from(vid - VLAN_RANGE.start)
+        };
+        #[cfg(not(feature = "vlan"))]
+        let vlan_index = {
+            let _ = vid;
+            0
+        };
+        let timestamp = self.vlan_state[vlan_index]
+            .device
+            .timestamps()
+            .tx_timestamp(self.eth, socket_index)
+            .ok_or(TimestampError::NoTimestamp)?;
+        Ok(timestamp)
+    }
+
+    #[cfg(feature = "ptp")]
+    fn ptp_time(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<PtpTimestamp, RequestError<TimestampError>> {
+        Ok(crate::timestamps::ptp_timestamp(self.eth.ptp_now()))
+    }
+
+    #[cfg(not(feature = "ptp"))]
+    fn tx_timestamp(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+    ) -> Result<PtpTimestamp, RequestError<TimestampError>> {
+        Err(TimestampError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "ptp"))]
+    fn ptp_time(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<PtpTimestamp, RequestError<TimestampError>> {
+        Err(TimestampError::NotAvailable.into())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Statistics functions
+    fn socket_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<SocketStats, RequestError<StatsError>> {
+        let vlan_index = stats_vlan_index(vid)?;
+        Ok(self.vlan_state[vlan_index].socket_stats[socket as usize])
+    }
+
+    fn interface_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        vid: u16,
+    ) -> Result<InterfaceStats, RequestError<StatsError>> {
+        let vlan_index = stats_vlan_index(vid)?;
+        let counters = self.vlan_state[vlan_index].device.counters();
+        let errors = self.eth.rx_errors();
+        Ok(InterfaceStats {
+            rx_frames: counters.rx_frames.get(),
+            tx_frames: counters.tx_frames.get(),
+            tx_ring_full: counters.tx_ring_full.get(),
+            rx_crc_errors: errors.crc,
+            rx_overflow_errors: errors.overflow,
+            rx_other_errors: errors.other,
+            rx_checksum_errors: errors.checksum,
+            rx_unknown_vlan: errors.unknown_vlan,
+        })
+    }
+
+    fn reset_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        for vlan in &mut self.vlan_state {
+            vlan.socket_stats = [SocketStats::default(); SOCKET_COUNT];
+            vlan.device.counters().reset();
+        }
+        self.eth.reset_rx_errors();
+        self.firewall.reset();
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Packet capture functions
+    #[cfg(not(feature = "capture"))]
+    fn capture_start(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _filter: CaptureFilter,
+    ) -> Result<(), RequestError<CaptureError>> {
+        Err(CaptureError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "capture"))]
+    fn capture_stop(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), RequestError<CaptureError>> {
+        Err(CaptureError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "capture"))]
+    fn capture_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<CaptureStats, RequestError<CaptureError>> {
+        Err(CaptureError::NotAvailable.into())
+    }
+
+    #[cfg(feature = "capture")]
+    fn capture_start(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        filter: CaptureFilter,
+    ) -> Result<(), RequestError<CaptureError>> {
+        self.tap.start(filter);
+        Ok(())
+    }
+
+    #[cfg(feature = "capture")]
+    fn capture_stop(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), RequestError<CaptureError>> {
+        self.tap.stop();
+        Ok(())
+    }
+
+    #[cfg(feature = "capture")]
+    fn capture_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<CaptureStats, RequestError<CaptureError>> {
+        Ok(self.tap.stats())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Firewall functions
+    #[cfg(not(feature = "firewall"))]
+    fn firewall_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<FirewallStats, RequestError<FirewallError>> {
+        Err(FirewallError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "firewall"))]
+    fn firewall_rule_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _rule: u32,
+    ) -> Result<FirewallRuleStats, RequestError<FirewallError>> {
+        Err(FirewallError::NotAvailable.into())
+    }
+
+    #[cfg(feature = "firewall")]
+    fn firewall_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<FirewallStats, RequestError<FirewallError>> {
+        Ok(self.firewall.stats())
+    }
+
+    #[cfg(feature = "firewall")]
+    fn firewall_rule_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        rule: u32,
+    ) -> Result<FirewallRuleStats, RequestError<FirewallError>> {
+        self.firewall
+            .rule_stats(rule as usize)
+            .ok_or_else(|| FirewallError::NoSuchRule.into())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // DHCP functions
+    #[cfg(not(feature = "dhcpv4"))]
+    fn dhcp_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _vid: u16,
+    ) -> Result<DhcpStatus, RequestError<DhcpError>> {
+        Err(DhcpError::NotAvailable.into())
+    }
+
+    #[cfg(feature = "dhcpv4")]
+    fn dhcp_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        vid: u16,
+    ) -> Result<DhcpStatus, RequestError<DhcpError>> {
+        #[cfg(feature = "vlan")]
+        let vlan_index = {
+            if !VLAN_RANGE.contains(&vid) {
+                return Err(DhcpError::InvalidVLan.into());
+            }
+            usize::from(vid - VLAN_RANGE.start)
+        };
+        #[cfg(not(feature = "vlan"))]
+        let vlan_index = {
+            let _ = vid;
+            0
+        };
+
+        let now = userlib::sys_get_timer().now;
+        Ok(self.vlan_state[vlan_index].dhcp.status(now))
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Stubs for TCP functions when it's not available
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_listen(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+    ) -> Result<(), RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_accept(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+    ) -> Result<TcpEndpoint, RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_connect(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+        _remote: TcpEndpoint,
+    ) -> Result<(), RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_send(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+        _payload: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_recv(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+        _payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_close(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+    ) -> Result<(), RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_abort(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+    ) -> Result<(), RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _vid: u16,
+    ) -> Result<TcpStatus, RequestError<TcpError>> {
+        Err(TcpError::NotAvailable.into())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Main TCP functions
+    #[cfg(feature = "tcp")]
+    fn tcp_listen(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<(), RequestError<TcpError>> {
+        let i = socket as usize;
+        let vlan = self.tcp_vlan_index(msg, socket, vid)?;
+        let vlan = &mut self.vlan_state[vlan];
+        let endpoint =
+            listen_endpoint(i, vlan.ipv6_addr, generated::SOCKET_PORTS[i]);
+        let s = vlan.get_tcp_socket_mut(i).unwrap_lite();
+        s.listen(endpoint).map_err(|e| match e {
+            tcp::ListenError::InvalidState => TcpError::InvalidState,
+            tcp::ListenError::Unaddressable => TcpError::Unaddressable,
+        })?;
+        Ok(())
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_accept(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<TcpEndpoint, RequestError<TcpError>> {
+        let s = self.tcp_socket_mut(msg, socket, vid)?;
+        match s.state() {
+            tcp::State::Closed => Err(TcpError::InvalidState.into()),
+            tcp::State::Listen | tcp::State::SynReceived => {
+                Err(TcpError::QueueEmpty.into())
+            }
+            _ => s
+                .remote_endpoint()
+                .and_then(tcp_endpoint)
+                .ok_or_else(|| TcpError::InvalidState.into()),
+        }
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_connect(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+        remote: TcpEndpoint,
+    ) -> Result<(), RequestError<TcpError>> {
+        let i = socket as usize;
+        let port = match generated::SOCKET_PORTS[i] {
+            0 => self.ephemeral_port(),
+            port => port,
+        };
+        let vlan = self.tcp_vlan_index(msg, socket, vid)?;
+        let vlan = &mut self.vlan_state[vlan];
+        // Not `get_tcp_socket_mut`, since we need the interface too.
+        let handle = vlan.get_handle(i).unwrap_lite();
+        let s = vlan.socket_set.get_mut::<tcp::Socket<'_>>(handle);
+        let remote = (smoltcp::wire::IpAddress::from(remote.addr), remote.port);
+        s.connect(vlan.iface.context(), remote, port)
+            .map_err(|e| match e {
+                tcp::ConnectError::InvalidState => TcpError::InvalidState,
+                tcp::ConnectError::Unaddressable => TcpError::Unaddressable,
+            })?;
+        Ok(())
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_send(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+        payload: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let i = socket as usize;
+        let vlan = self.tcp_vlan_index(msg, socket, vid)?;
+        let s = self.vlan_state[vlan].get_tcp_socket_mut(i).unwrap_lite();
+        if !s.may_send() {
+            return Err(match s.state() {
+                tcp::State::Closed | tcp::State::Listen => {
+                    TcpError::InvalidState
+                }
+                tcp::State::SynSent | tcp::State::SynReceived => {
+                    // We'll wake the client once it's connected.
+                    self.client_waiting_to_send[i] = true;
+                    TcpError::QueueFull
+                }
+                _ => TcpError::Closed,
+            }
+            .into());
+        }
+        let n = s
+            .send(|buf| {
+                let n = buf.len().min(payload.len());
+                (n, payload.read_range(0..n, &mut buf[..n]).map(|()| n))
+            })
+            .map_err(|_| TcpError::InvalidState)?
+            .map_err(|_| RequestError::went_away())?;
+        if n == 0 && !payload.is_empty() {
+            self.client_waiting_to_send[i] = true;
+            return Err(TcpError::QueueFull.into());
+        }
+        self.client_waiting_to_send[i] = false;
+        Ok(n as u32)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_recv(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+        payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let s = self.tcp_socket_mut(msg, socket, vid)?;
+        if let tcp::State::Listen
+        | tcp::State::SynSent
+        | tcp::State::SynReceived = s.state()
+        {
+            return Err(TcpError::QueueEmpty.into());
+        }
+        let n = s
+            .recv(|buf| {
+                let n = buf.len().min(payload.len());
+                (n, payload.write_range(0..n, &buf[..n]).map(|()| n))
+            })
+            .map_err(|e| match e {
+                tcp::RecvError::InvalidState => TcpError::InvalidState,
+                tcp::RecvError::Finished => TcpError::Closed,
+            })?
+            .map_err(|_| RequestError::went_away())?;
+        if n == 0 && !payload.is_empty() {
+            return Err(TcpError::QueueEmpty.into());
+        }
+        Ok(n as u32)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_close(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<(), RequestError<TcpError>> {
+        let s = self.tcp_socket_mut(msg, socket, vid)?;
+        s.close();
+        Ok(())
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_abort(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<(), RequestError<TcpError>> {
+        let s = self.tcp_socket_mut(msg, socket, vid)?;
+        s.abort();
+        Ok(())
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_status(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<TcpStatus, RequestError<TcpError>> {
+        use task_net_api::TcpState;
+
+        let s = self.tcp_socket_mut(msg, socket, vid)?;
+        Ok(TcpStatus {
+            state: match s.state() {
+                tcp::State::Closed => TcpState::Closed,
+                tcp::State::Listen => TcpState::Listen,
+                tcp::State::SynSent => TcpState::SynSent,
+                tcp::State::SynReceived => TcpState::SynReceived,
+                tcp::State::Established => TcpState::Established,
+                tcp::State::FinWait1 => TcpState::FinWait1,
+                tcp::State::FinWait2 => TcpState::FinWait2,
+                tcp::State::CloseWait => TcpState::CloseWait,
+                tcp::State::Closing => TcpState::Closing,
+                tcp::State::LastAck => TcpState::LastAck,
+                tcp::State::TimeWait => TcpState::TimeWait,
+            },
+            remote: s.remote_endpoint().and_then(tcp_endpoint),
+            recv_queue: s.recv_queue() as u32,
+            send_space: (s.send_capacity() - s.send_queue()) as u32,
+        })
+    }
+}
+
+#[cfg(feature = "tcp")]
+fn tcp_endpoint(e: smoltcp::wire::IpEndpoint) -> Option<TcpEndpoint> {
+    Some(TcpEndpoint {
+        addr: e.addr.try_into().ok()?,
+        port: e.port,
+    })
+}
+
+/// Checks whether socket `index` is bound on the VLAN at `vlan_index`;
+/// without VLANs, every socket is bound on the one interface.
+fn socket_on_vlan(index: usize, vlan_index: usize) -> bool {
+    #[cfg(feature = "vlan")]
+    {
+        generated::SOCKET_VLANS[index] & (1 << vlan_index) != 0
+    }
+    #[cfg(not(feature = "vlan"))]
+    {
+        let _ = (index, vlan_index);
+        true
+    }
+}
+
+/// Converts a VID into an index in `vlan_state`, for the stats functions.
+fn stats_vlan_index(vid: u16) -> Result<usize, StatsError> {
+    #[cfg(feature = "vlan")]
+    {
+        if !VLAN_RANGE.contains(&vid) {
+            return Err(StatsError::InvalidVLan);
+        }
+        Ok(usize::from(vid - VLAN_RANGE.start))
+    }
+    #[cfg(not(feature = "vlan"))]
+    {
+        let _ = vid;
+        Ok(0)
+    }
+}
+
+/// Multicast MAC addresses the stack wants frames for by itself, whichever
+/// groups sockets are in. (Each interface's solicited-node group is added to
+/// these.)
+const STACK_MULTICAST_MACS: &[[u8; 6]] = &[
+    // All IPv6 nodes, ff02::1
+    [0x33, 0x33, 0, 0, 0, 1],
+    // mDNS, ff02::fb
+    #[cfg(feature = "mdns")]
+    [0x33, 0x33, 0, 0, 0, 0xfb],
+    // All IPv4 hosts, 224.0.0.1
+    #[cfg(feature = "dhcpv4")]
+    [0x01, 0x00, 0x5e, 0, 0, 1],
+];
+
+/// Returns the MAC address that datagrams to multicast group `group` are
+/// sent to (RFC 1112 section 6.4, RFC 2464 section 7).
+fn multicast_mac(group: IpAddress) -> [u8; 6] {
+    match group {
+        IpAddress::Ipv6(a) => [0x33, 0x33, a.0[12], a.0[13], a.0[14], a.0[15]],
+        #[cfg(feature = "dhcpv4")]
+        IpAddress::Ipv4(a) => [0x01, 0x00, 0x5e, a.0[1] & 0x7f, a.0[2], a.0[3]],
+    }
 }
 
 pub trait DeviceExt: smoltcp::phy::Device {
     fn read_and_clear_activity_flag(&self) -> bool;
 
+    /// Returns the counts kept as this device's tokens are used.
+    fn counters(&self) -> &DeviceCounters;
+
+    /// Returns the notes kept as this device's tokens are used, for matching
+    /// timestamps to socket traffic.
+    fn timestamps(&self) -> &Timestamps;
+
     fn make_meta(
         &self,
         port: u16,
@@ -252,6 +1141,26 @@ pub trait DeviceExt: smoltcp::phy::Device {
     ) -> UdpMetadata;
 }
 
+/// Frame counts kept by a device, for `interface_stats`.
+#[derive(Default)]
+pub struct DeviceCounters {
+    pub rx_frames: Cell<u32>,
+    pub tx_frames: Cell<u32>,
+    pub tx_ring_full: Cell<u32>,
+}
+
+impl DeviceCounters {
+    pub fn bump(counter: &Cell<u32>) {
+        counter.set(counter.get().wrapping_add(1));
+    }
+
+    fn reset(&self) {
+        self.rx_frames.set(0);
+        self.tx_frames.set(0);
+        self.tx_ring_full.set(0);
+    }
+}
+
 /// State for the running network server
 pub struct GenServerImpl<'a, B, E, const N: usize>
 where
@@ -265,6 +1174,33 @@ where
 
     mac: EthernetAddress,
     spare_macs: MacAddressBlock,
+
+    /// Sees every frame, and keeps those we're capturing
+    tap: &'static Tap,
+    /// Where captured frames go, from VLAN 0
+    #[cfg(feature = "capture")]
+    collector: capture::Collector,
+    /// Decides which frames get in, shared with the devices
+    firewall: &'static Firewall,
+
+    /// Next local port for a TCP socket connecting from port 0
+    #[cfg(feature = "tcp")]
+    next_ephemeral_port: u16,
+}
+
+/// What a socket in the config is.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SocketKind {
+    Udp,
+    #[cfg(feature = "tcp")]
+    Tcp,
+}
+
+/// A socket in the config, as constructed by generated code.
+pub(crate) enum ConfiguredSocket<'a> {
+    Udp(udp::Socket<'a>),
+    #[cfg(feature = "tcp")]
+    Tcp(tcp::Socket<'a>),
 }
 
 struct VLanState<E>
@@ -278,8 +1214,38 @@ where
 
     /// Used to detect stuck queues (due to smoltcp#594)
     queue_watchdog: [QueueWatchdog; SOCKET_COUNT],
+
+    /// Counts for `socket_stats`
+    socket_stats: [SocketStats; SOCKET_COUNT],
+
+    /// TCP socket states as of the last `wake_sockets`, so that owners can
+    /// be told of changes
+    #[cfg(feature = "tcp")]
+    tcp_state: [tcp::State; SOCKET_COUNT],
+    /// Link-local address
+    ipv6_addr: Ipv6Address,
+    /// Multicast groups joined through `join_multicast_group`
+    multicast: [Option<Membership>; MULTICAST_GROUPS],
+
+    #[cfg(feature = "dhcpv4")]
+    dhcp: Dhcp,
+    #[cfg(feature = "slaac")]
+    slaac: Slaac,
+    #[cfg(feature = "mdns")]
+    mdns: Mdns,
 }
 
+/// A multicast group that sockets on a VLAN have joined.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Membership {
+    group: IpAddress,
+    /// Which sockets are in the group, one bit per socket index
+    sockets: u32,
+}
+
+// Every socket has a bit in `Membership::sockets`.
+const _: () = assert!(SOCKET_COUNT <= 32);
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum QueueWatchdog {
     /// Data is flowing through the queue
@@ -300,20 +1266,37 @@ impl<E: DeviceExt> VLanState<E> {
         self.socket_handles.get(index).cloned()
     }
 
-    /// Gets the socket `index`. If `index` is out of range, returns
-    /// `None`.
-    ///
-    /// Sockets are currently assumed to be UDP.
+    /// Gets the UDP socket `index`. If `index` is out of range, or not a UDP
+    /// socket, returns `None`.
     pub(crate) fn get_socket_mut(
         &mut self,
         index: usize,
     ) -> Option<&mut udp::Socket<'static>> {
+        if *generated::SOCKET_KINDS.get(index)? != SocketKind::Udp {
+            return None;
+        }
         Some(
             self.socket_set
                 .get_mut::<udp::Socket<'_>>(self.get_handle(index)?),
         )
     }
 
+    /// Gets the TCP socket `index`. If `index` is out of range, or not a TCP
+    /// socket, returns `None`.
+    #[cfg(feature = "tcp")]
+    pub(crate) fn get_tcp_socket_mut(
+        &mut self,
+        index: usize,
+    ) -> Option<&mut tcp::Socket<'static>> {
+        if *generated::SOCKET_KINDS.get(index)? != SocketKind::Tcp {
+            return None;
+        }
+        Some(
+            self.socket_set
+                .get_mut::<tcp::Socket<'_>>(self.get_handle(index)?),
+        )
+    }
+
     pub(crate) fn check_socket_watchdog(&mut self) -> bool {
         let mut changed = false;
         for socket_index in 0..SOCKET_COUNT {
@@ -329,6 +1312,8 @@ impl<E: DeviceExt> VLanState<E> {
                 s.close();
                 s.bind(e).unwrap_lite();
                 changed = true;
+                let stats = &mut self.socket_stats[socket_index];
+                stats.queue_resets = stats.queue_resets.wrapping_add(1);
 
                 // Reset the watchdog, so it doesn't fire right away
                 self.queue_watchdog[socket_index] = QueueWatchdog::Nominal;
@@ -350,6 +1335,8 @@ where
         bsp: B,
         storage: &'static mut [Storage; N],
         sockets: generated::Sockets<'static, N>,
+        capture: capture::Statics,
+        firewall: &'static Firewall,
         mut mkdevice: impl FnMut(usize) -> E,
     ) -> Self {
         // Local storage; this will end up owned by the returned ServerImpl.
@@ -359,13 +1346,16 @@ where
         assert!(mac_address_block.count.get() as usize >= N);
         let mut mac: [u8; 6] = mac_address_block.base_mac;
 
+        #[cfg(feature = "mdns")]
+        let identity = Identity::new(mac_address_block.base_mac);
+
         // Each of these is replicated once per VID. Loop over them in lockstep.
         for (i, (sockets, storage)) in zip(sockets.0, storage).enumerate() {
             let mac_addr = EthernetAddress::from_bytes(&mac);
             let ipv6_addr = link_local_iface_addr(mac_addr);
 
             // Make some types explicit to try and make this clearer.
-            let sockets: [udp::Socket<'_>; SOCKET_COUNT] = sockets;
+            let sockets: [ConfiguredSocket<'_>; SOCKET_COUNT] = sockets;
 
             let mut config = smoltcp::iface::Config::new();
             config.hardware_addr = Some(mac_addr.into());
@@ -379,15 +1369,52 @@ where
             // Associate sockets with this interface.
             let mut socket_set =
                 smoltcp::iface::SocketSet::new(storage.sockets.as_mut_slice());
-            let socket_handles = sockets.map(|s| socket_set.add(s));
-            // Bind sockets to their ports.
-            for (&h, port) in zip(&socket_handles, generated::SOCKET_PORTS) {
+            let socket_handles = sockets.map(|s| match s {
+                ConfiguredSocket::Udp(s) => socket_set.add(s),
+                #[cfg(feature = "tcp")]
+                ConfiguredSocket::Tcp(s) => socket_set.add(s),
+            });
+            // Bind UDP sockets to their ports, on the VLANs they're meant
+            // for. TCP sockets wait for their owners to listen or connect.
+            let vlan_index = i;
+            for (i, (&h, port)) in
+                zip(&socket_handles, generated::SOCKET_PORTS).enumerate()
+            {
+                if generated::SOCKET_KINDS[i] != SocketKind::Udp
+                    || !socket_on_vlan(i, vlan_index)
+                {
+                    continue;
+                }
                 socket_set
                     .get_mut::<udp::Socket<'_>>(h)
-                    .bind((ipv6_addr, port))
+                    .bind(listen_endpoint(i, ipv6_addr, port))
                     .unwrap_lite();
             }
 
+            #[cfg(feature = "dhcpv4")]
+            let dhcp = Dhcp::new(
+                i,
+                mac_addr,
+                &mut storage.dhcp,
+                &mut socket_set,
+                userlib::sys_get_timer().now,
+            );
+            #[cfg(feature = "slaac")]
+            let slaac = Slaac::new(
+                i,
+                mac_addr,
+                &mut storage.slaac,
+                &mut socket_set,
+                userlib::sys_get_timer().now,
+            );
+            #[cfg(feature = "mdns")]
+            let mdns = Mdns::new(
+                i,
+                identity.clone(),
+                &mut storage.mdns,
+                &mut socket_set,
+            );
+
             vlan_state
                 .push(VLanState {
                     socket_handles,
@@ -395,6 +1422,17 @@ where
                     device,
                     socket_set,
                     queue_watchdog: [QueueWatchdog::Nominal; SOCKET_COUNT],
+                    socket_stats: [SocketStats::default(); SOCKET_COUNT],
+                    #[cfg(feature = "tcp")]
+                    tcp_state: [tcp::State::Closed; SOCKET_COUNT],
+                    ipv6_addr,
+                    multicast: [None; MULTICAST_GROUPS],
+                    #[cfg(feature = "dhcpv4")]
+                    dhcp,
+                    #[cfg(feature = "slaac")]
+                    slaac,
+                    #[cfg(feature = "mdns")]
+                    mdns,
                 })
                 .unwrap_lite();
 
@@ -425,7 +1463,13 @@ where
             mac[3..].copy_from_slice(&next_mac.to_be_bytes()[1..]);
         }
 
-        Self {
+        #[cfg(feature = "capture")]
+        let collector = capture::Collector::new(
+            capture.storage,
+            &mut vlan_state[0].socket_set,
+        );
+
+        let server = Self {
             eth,
             client_waiting_to_send: [false; SOCKET_COUNT],
             vlan_state: vlan_state.into_array().unwrap_lite(),
@@ -436,7 +1480,21 @@ where
                 count: U16::new(mac_address_block.count.get() - N as u16),
                 stride: mac_address_block.stride,
             },
-        }
+            tap: capture.tap,
+            #[cfg(feature = "capture")]
+            collector,
+            firewall,
+            // Start somewhere different each time, so as not to trip over
+            // connections from before a restart.
+            #[cfg(feature = "tcp")]
+            next_ephemeral_port: EPHEMERAL_PORT_BASE
+                + (userlib::sys_get_timer().now
+                    % (u64::from(u16::MAX - EPHEMERAL_PORT_BASE) + 1))
+                    as u16,
+        };
+        // Start the filter off with the stack's own groups.
+        server.update_multicast_filter();
+        server
     }
 
     pub(crate) fn poll(&mut self, t: u64) -> crate::Activity {
@@ -445,20 +1503,77 @@ where
         // we really do want to poll all of them.
         let mut ip = false;
         let mut mac_rx = false;
+        self.tap.set_now(t);
+        self.firewall.set_now(t);
         for vlan in &mut self.vlan_state {
             ip |= vlan.iface.poll(
                 instant,
                 &mut vlan.device,
                 &mut vlan.socket_set,
             );
+            #[cfg(feature = "dhcpv4")]
+            {
+                ip |= vlan.dhcp.poll(t, vlan.iface, &mut vlan.socket_set);
+            }
+            #[cfg(feature = "slaac")]
+            {
+                ip |= vlan.slaac.poll(t, vlan.iface, &mut vlan.socket_set);
+            }
+            #[cfg(feature = "mdns")]
+            {
+                ip |= vlan.mdns.poll(t, vlan.iface, &mut vlan.socket_set);
+            }
             // Test and clear our receive activity flag.
             mac_rx |= vlan.device.read_and_clear_activity_flag();
             ip |= vlan.check_socket_watchdog();
         }
+        // Anything captured just now goes out on the next poll, which this
+        // makes sure we have.
+        #[cfg(feature = "capture")]
+        {
+            ip |= self
+                .collector
+                .flush(self.tap, &mut self.vlan_state[0].socket_set);
+        }
 
         crate::Activity { ip, mac_rx }
     }
 
+    /// Returns the next time we need to `poll`, absent any packets, for DHCP,
+    /// SLAAC, mDNS or TCP timers.
+    #[cfg(any(
+        feature = "dhcpv4",
+        feature = "mdns",
+        feature = "slaac",
+        feature = "tcp"
+    ))]
+    pub(crate) fn poll_at(&mut self, t: u64) -> u64 {
+        let mut at = u64::MAX;
+        for vlan in &mut self.vlan_state {
+            #[cfg(feature = "dhcpv4")]
+            {
+                at = at.min(vlan.dhcp.poll_at());
+            }
+            #[cfg(feature = "slaac")]
+            {
+                at = at.min(vlan.slaac.poll_at());
+            }
+            #[cfg(feature = "mdns")]
+            {
+                at = at.min(vlan.mdns.poll_at());
+            }
+            #[cfg(feature = "tcp")]
+            {
+                let instant = smoltcp::time::Instant::from_millis(t as i64);
+                if let Some(i) = vlan.iface.poll_at(instant, &vlan.socket_set) {
+                    at = at.min(i.total_millis() as u64);
+                }
+            }
+        }
+        let _ = t;
+        at
+    }
+
     /// Iterate over sockets, waking any that can do work.
     ///
     /// A task can do work if...
@@ -469,21 +1584,19 @@ where
     ///   across all VLANs can accept an outgoing packet. (The "all" is
     ///   important here since we don't keep track of which one it's trying to
     ///   send through.)
+    ///
+    /// TCP sockets are the same, except that any copy being able to send is
+    /// enough (since the owner picks the VLAN), and that a change of
+    /// connection state on any of them is also work.
     pub fn wake_sockets(&mut self) {
         for i in 0..SOCKET_COUNT {
-            // recv wake depends only on the state of the sockets.
-            let recv_wake = self
-                .vlan_state
-                .iter_mut()
-                .any(|v| v.get_socket_mut(i).unwrap().can_recv());
-            // send wake only happens if the wait flag is set.
-            let send_wake = self.client_waiting_to_send[i]
-                && self
-                    .vlan_state
-                    .iter_mut()
-                    .all(|v| v.get_socket_mut(i).unwrap().can_send());
-
-            if recv_wake || send_wake {
+            let wake = match generated::SOCKET_KINDS[i] {
+                SocketKind::Udp => self.udp_wake(i),
+                #[cfg(feature = "tcp")]
+                SocketKind::Tcp => self.tcp_wake(i),
+            };
+
+            if wake {
                 let (task_id, notification) = generated::SOCKET_OWNERS[i];
                 let task_id = sys_refresh_task_id(task_id);
                 sys_post(task_id, notification);
@@ -491,6 +1604,162 @@ where
         }
     }
 
+    fn udp_wake(&mut self, i: usize) -> bool {
+        // recv wake depends only on the state of the sockets.
+        let recv_wake = self
+            .vlan_state
+            .iter_mut()
+            .any(|v| v.get_socket_mut(i).unwrap().can_recv());
+        // send wake only happens if the wait flag is set. Copies on VLANs
+        // the socket isn't bound on have no buffers, and never can send.
+        let send_wake = self.client_waiting_to_send[i]
+            && self.vlan_state.iter_mut().enumerate().all(|(j, v)| {
+                !socket_on_vlan(i, j) || v.get_socket_mut(i).unwrap().can_send()
+            });
+        recv_wake || send_wake
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_wake(&mut self, i: usize) -> bool {
+        // As above, don't short circuit: we need to see every state.
+        let mut wake = false;
+        for v in &mut self.vlan_state {
+            let s = v.get_tcp_socket_mut(i).unwrap_lite();
+            let state = s.state();
+            wake |= s.can_recv()
+                || (self.client_waiting_to_send[i] && s.can_send())
+                || state != v.tcp_state[i];
+            v.tcp_state[i] = state;
+        }
+        wake
+    }
+
+    /// Checks that `socket` is a TCP socket belonging to the sender of
+    /// `msg`, and returns the index of VLAN `vid`.
+    #[cfg(feature = "tcp")]
+    fn tcp_vlan_index(
+        &self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<usize, RequestError<TcpError>> {
+        let socket_index = socket as usize;
+        if generated::SOCKET_OWNERS[socket_index].0.index()
+            != msg.sender.index()
+        {
+            return Err(TcpError::NotYours.into());
+        }
+        if generated::SOCKET_KINDS[socket_index] != SocketKind::Tcp {
+            return Err(RequestError::Fail(ClientError::BadMessageContents));
+        }
+
+        #[cfg(feature = "vlan")]
+        let vlan_index = {
+            // Convert from absolute VID to an index in our VLAN array
+            if !VLAN_RANGE.contains(&vid) {
+                return Err(TcpError::InvalidVLan.into());
+            }
+            let i = usize::from(vid - VLAN_RANGE.start);
+            if !socket_on_vlan(socket_index, i) {
+                return Err(TcpError::InvalidVLan.into());
+            }
+            i
+        };
+        #[cfg(not(feature = "vlan"))]
+        let vlan_index = {
+            let _ = vid;
+            0
+        };
+        Ok(vlan_index)
+    }
+
+    /// Checks that `socket` is a UDP socket belonging to the sender of `msg`
+    /// and that `group` is a multicast group, and returns the index of VLAN
+    /// `vid`.
+    fn multicast_vlan_index(
+        &self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+        group: IpAddress,
+    ) -> Result<usize, RequestError<MulticastError>> {
+        let socket_index = socket as usize;
+        if generated::SOCKET_OWNERS[socket_index].0.index()
+            != msg.sender.index()
+        {
+            return Err(MulticastError::NotYours.into());
+        }
+        if generated::SOCKET_KINDS[socket_index] != SocketKind::Udp {
+            return Err(RequestError::Fail(ClientError::BadMessageContents));
+        }
+        if !group.is_multicast() {
+            return Err(MulticastError::NotMulticast.into());
+        }
+
+        #[cfg(feature = "vlan")]
+        let vlan_index = {
+            // Convert from absolute VID to an index in our VLAN array
+            if !VLAN_RANGE.contains(&vid) {
+                return Err(MulticastError::InvalidVLan.into());
+            }
+            let i = usize::from(vid - VLAN_RANGE.start);
+            if !socket_on_vlan(socket_index, i) {
+                return Err(MulticastError::InvalidVLan.into());
+            }
+            i
+        };
+        #[cfg(not(feature = "vlan"))]
+        let vlan_index = {
+            let _ = vid;
+            0
+        };
+        Ok(vlan_index)
+    }
+
+    /// Loads the MAC's multicast filter with every group we want frames for:
+    /// those of the stack itself, each interface's solicited-node group, and
+    /// those sockets have joined.
+    fn update_multicast_filter(&self) {
+        let solicited = self.vlan_state.iter().map(|v| {
+            let a = v.ipv6_addr.0;
+            [0x33, 0x33, 0xff, a[13], a[14], a[15]]
+        });
+        let joined = self.vlan_state.iter().flat_map(|v| {
+            v.multicast.iter().flatten().map(|m| multicast_mac(m.group))
+        });
+        self.eth.set_multicast_filter(
+            STACK_MULTICAST_MACS
+                .iter()
+                .copied()
+                .chain(solicited)
+                .chain(joined),
+        );
+    }
+
+    /// Looks up TCP socket `socket` on VLAN `vid` for the sender of `msg`.
+    #[cfg(feature = "tcp")]
+    fn tcp_socket_mut(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        vid: u16,
+    ) -> Result<&mut tcp::Socket<'static>, RequestError<TcpError>> {
+        let vlan = self.tcp_vlan_index(msg, socket, vid)?;
+        Ok(self.vlan_state[vlan]
+            .get_tcp_socket_mut(socket as usize)
+            .unwrap_lite())
+    }
+
+    /// Hands out a local port for a TCP connection, for sockets that aren't
+    /// configured with one.
+    #[cfg(feature = "tcp")]
+    fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_ephemeral_port;
+        self.next_ephemeral_port =
+            port.checked_add(1).unwrap_or(EPHEMERAL_PORT_BASE);
+        port
+    }
+
     pub fn wake(&self) {
         self.bsp.wake(self.eth)
     }
@@ -507,15 +1776,16 @@ where
     /// into loaned memory at `payload`.
     ///
     /// If a packet is available and fits, copies it into `payload` and returns
-    /// its `UdpMetadata`. Otherwise, leaves `payload` untouched and returns an
-    /// error.
+    /// its `UdpMetadata`, and its timestamp if we have one. Otherwise, leaves
+    /// `payload` untouched and returns an error.
     fn net_recv_packet(
         &mut self,
         msg: &userlib::RecvMessage,
         socket: SocketName,
         large_payload_behavior: LargePayloadBehavior,
         payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
-    ) -> Result<UdpMetadata, RequestError<RecvError>> {
+    ) -> Result<(UdpMetadata, Option<PtpTimestamp>), RequestError<RecvError>>
+    {
         let socket_index = socket as usize;
 
         if generated::SOCKET_OWNERS[socket_index].0.index()
@@ -530,10 +1800,14 @@ where
             let socket = vlan
                 .get_socket_mut(socket_index)
                 .ok_or(RequestError::Fail(ClientError::BadMessageContents))?;
+            // Counted here, and added to the stats once we're done with the
+            // socket.
+            let mut too_large = 0;
             loop {
                 match socket.recv() {
                     Ok((body, endp)) => {
                         if payload.len() < body.len() {
+                            too_large += 1;
                             match large_payload_behavior {
                                 LargePayloadBehavior::Discard => continue,
                                 // If we add a `::Fail` case, we will need to
@@ -548,11 +1822,22 @@ where
                         // Release borrow on self/socket
                         let body_len = body.len();
 
-                        return Ok(vlan.device.make_meta(
+                        let stats = &mut vlan.socket_stats[socket_index];
+                        stats.rx_packets = stats.rx_packets.wrapping_add(1);
+                        stats.rx_too_large =
+                            stats.rx_too_large.wrapping_add(too_large);
+
+                        let meta = vlan.device.make_meta(
                             endp.port,
                             body_len,
                             endp.addr.try_into().map_err(|_| ()).unwrap(),
-                        ));
+                        );
+                        let timestamp = vlan.device.timestamps().rx_timestamp(
+                            socket_index,
+                            endp,
+                            body_len,
+                        );
+                        return Ok((meta, timestamp));
                     }
                     Err(udp::RecvError::Exhausted) => {
                         // Move on to next vid
@@ -560,6 +1845,8 @@ where
                     }
                 }
             }
+            let stats = &mut vlan.socket_stats[socket_index];
+            stats.rx_too_large = stats.rx_too_large.wrapping_add(too_large);
         }
         Err(RecvError::QueueEmpty.into())
     }
@@ -586,7 +1873,11 @@ where
             if !VLAN_RANGE.contains(&metadata.vid) {
                 return Err(SendError::InvalidVLan.into());
             }
-            usize::from(metadata.vid - VLAN_RANGE.start)
+            let i = usize::from(metadata.vid - VLAN_RANGE.start);
+            if !socket_on_vlan(socket_index, i) {
+                return Err(SendError::InvalidVLan.into());
+            }
+            i
         };
         #[cfg(not(feature = "vlan"))]
         let vlan_index = 0;
@@ -602,6 +1893,8 @@ where
                     .map_err(|_| RequestError::went_away())?;
                 self.client_waiting_to_send[socket_index] = false;
                 vlan.queue_watchdog[socket_index] = QueueWatchdog::Nominal;
+                let stats = &mut vlan.socket_stats[socket_index];
+                stats.tx_packets = stats.tx_packets.wrapping_add(1);
                 Ok(())
             }
             Err(udp::SendError::BufferFull) => {
@@ -625,10 +1918,14 @@ where
                     QueueWatchdog::QueueFullTimeout => (),
                 }
                 self.client_waiting_to_send[socket_index] = true;
+                let stats = &mut vlan.socket_stats[socket_index];
+                stats.tx_queue_full = stats.tx_queue_full.wrapping_add(1);
                 Err(SendError::QueueFull.into())
             }
             Err(_e) => {
                 // uhhhh TODO
+                let stats = &mut vlan.socket_stats[socket_index];
+                stats.tx_errors = stats.tx_errors.wrapping_add(1);
                 Err(SendError::Other.into())
             }
         }
@@ -654,9 +1951,28 @@ where
     }
 }
 
+/// Start of the dynamic port range (RFC 6335), from which TCP connections
+/// get local ports if their sockets don't have one.
+#[cfg(feature = "tcp")]
+const EPHEMERAL_PORT_BASE: u16 = 49152;
+
+/// Sockets of our own, on top of those in the config: one each for DHCP,
+/// SLAAC, mDNS and the capture collector, if they're on. (The collector only
+/// needs one on VLAN 0, but the storage is the same for every VLAN.)
+const EXTRA_SOCKETS: usize = cfg!(feature = "dhcpv4") as usize
+    + cfg!(feature = "slaac") as usize
+    + cfg!(feature = "mdns") as usize
+    + cfg!(feature = "capture") as usize;
+
 pub struct Storage {
-    sockets: [SocketStorage<'static>; SOCKET_COUNT],
+    sockets: [SocketStorage<'static>; SOCKET_COUNT + EXTRA_SOCKETS],
     iface: core::mem::MaybeUninit<Interface>,
+    #[cfg(feature = "dhcpv4")]
+    dhcp: DhcpStorage,
+    #[cfg(feature = "slaac")]
+    slaac: SlaacStorage,
+    #[cfg(feature = "mdns")]
+    mdns: MdnsStorage,
 }
 
 impl Default for Storage {
@@ -664,6 +1980,32 @@ impl Default for Storage {
         Self {
             sockets: Default::default(),
             iface: core::mem::MaybeUninit::uninit(),
+            #[cfg(feature = "dhcpv4")]
+            dhcp: Default::default(),
+            #[cfg(feature = "slaac")]
+            slaac: Default::default(),
+            #[cfg(feature = "mdns")]
+            mdns: Default::default(),
         }
     }
 }
+
+/// Where socket `i` listens: on our IPv6 address or, if it takes IPv4 too, on
+/// any address.
+#[cfg(feature = "dhcpv4")]
+fn listen_endpoint(i: usize, addr: Ipv6Address, port: u16) -> IpListenEndpoint {
+    if generated::SOCKET_IPV4[i] {
+        port.into()
+    } else {
+        (addr, port).into()
+    }
+}
+
+#[cfg(not(feature = "dhcpv4"))]
+fn listen_endpoint(
+    _i: usize,
+    addr: Ipv6Address,
+    port: u16,
+) -> IpListenEndpoint {
+    (addr, port).into()
+}
@@ -9,9 +9,12 @@
 use drv_stm32h7_eth as eth;
 
 use crate::bsp_support;
+use crate::capture::{self, Direction, Tap};
+use crate::firewall::{self, Firewall};
 use crate::generated;
 use crate::{
-    server::{DeviceExt, GenServerImpl, Storage},
+    server::{DeviceCounters, DeviceExt, GenServerImpl, Storage},
+    timestamps::Timestamps,
     MacAddressBlock,
 };
 use core::cell::Cell;
@@ -37,13 +40,18 @@ pub fn new<B>(
 where
     B: bsp_support::Bsp,
 {
+    let capture = capture::claim_statics();
+    let tap = capture.tap;
+    let firewall = firewall::claim_statics();
     ServerImpl::new(
         eth,
         mac,
         bsp,
         claim_server_storage_statics(),
         generated::construct_sockets(),
-        |_| Smol::from(eth),
+        capture,
+        firewall,
+        |_| Smol::new(eth, tap, firewall),
     )
 }
 
@@ -55,36 +63,64 @@ where
 
 pub struct Smol<'d> {
     eth: &'d eth::Ethernet,
+    tap: &'d Tap,
+    firewall: &'d Firewall,
     mac_rx: Cell<bool>,
+    counters: DeviceCounters,
+    timestamps: Timestamps,
 }
 
-impl<'d> From<&'d eth::Ethernet> for Smol<'d> {
-    fn from(eth: &'d eth::Ethernet) -> Self {
+impl<'d> Smol<'d> {
+    fn new(
+        eth: &'d eth::Ethernet,
+        tap: &'d Tap,
+        firewall: &'d Firewall,
+    ) -> Self {
         Self {
             eth,
+            tap,
+            firewall,
             mac_rx: Cell::new(false),
+            counters: DeviceCounters::default(),
+            timestamps: Timestamps::default(),
         }
     }
 }
 
-pub struct OurRxToken<'d>(&'d eth::Ethernet);
+pub struct OurRxToken<'d>(&'d Smol<'d>);
 impl<'d> smoltcp::phy::RxToken for OurRxToken<'d> {
     fn consume<R, F>(self, f: F) -> R
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0.recv(f)
+        let dev = self.0;
+        DeviceCounters::bump(&dev.counters.rx_frames);
+        dev.eth.recv(|frame| {
+            dev.tap.frame(0, Direction::In, frame);
+            if !dev.firewall.admit(frame) {
+                return f(&mut frame[..0]);
+            }
+            dev.timestamps.received(dev.eth, frame);
+            f(frame)
+        })
     }
 }
 
-pub struct OurTxToken<'d>(&'d eth::Ethernet);
+pub struct OurTxToken<'d>(&'d Smol<'d>);
 impl<'d> smoltcp::phy::TxToken for OurTxToken<'d> {
     fn consume<R, F>(self, len: usize, f: F) -> R
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0
-            .try_send(len, f)
+        let dev = self.0;
+        DeviceCounters::bump(&dev.counters.tx_frames);
+        dev.eth
+            .try_send(len, |frame| {
+                let r = f(frame);
+                dev.tap.frame(0, Direction::Out, frame);
+                dev.timestamps.sending(dev.eth, frame);
+                r
+            })
             .expect("TX token existed without descriptor available")
     }
 }
@@ -96,7 +132,7 @@ impl<'a> smoltcp::phy::Device for Smol<'a> {
     fn receive(
         &mut self,
         _timestamp: smoltcp::time::Instant,
-    ) -> Option<(Self::RxToken<'a>, Self::TxToken<'a>)> {
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
         // Note: smoltcp wants a transmit token every time it receives a
         // packet. This is because it automatically handles stuff like
         // NDP by itself, but means that if the tx queue fills up, we stop
@@ -111,7 +147,7 @@ impl<'a> smoltcp::phy::Device for Smol<'a> {
             // for some reason (that'd be a software bug instead).
             self.mac_rx.set(true);
 
-            Some((OurRxToken(self.eth), OurTxToken(self.eth)))
+            Some((OurRxToken(self), OurTxToken(self)))
         } else {
             None
         }
@@ -120,10 +156,11 @@ impl<'a> smoltcp::phy::Device for Smol<'a> {
     fn transmit(
         &mut self,
         _i: smoltcp::time::Instant,
-    ) -> Option<Self::TxToken<'a>> {
+    ) -> Option<Self::TxToken<'_>> {
         if self.eth.can_send() {
-            Some(OurTxToken(self.eth))
+            Some(OurTxToken(self))
         } else {
+            DeviceCounters::bump(&self.counters.tx_ring_full);
             None
         }
     }
@@ -138,6 +175,14 @@ impl DeviceExt for Smol<'_> {
         self.mac_rx.take()
     }
 
+    fn counters(&self) -> &DeviceCounters {
+        &self.counters
+    }
+
+    fn timestamps(&self) -> &Timestamps {
+        &self.timestamps
+    }
+
     fn make_meta(
         &self,
         port: u16,
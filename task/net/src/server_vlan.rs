@@ -14,9 +14,12 @@ use mutable_statics::mutable_statics;
 use task_net_api::UdpMetadata;
 
 use crate::bsp_support;
+use crate::capture::{self, Direction, Tap};
+use crate::firewall::{self, Firewall};
 use crate::generated::{self, VLAN_COUNT, VLAN_RANGE};
 use crate::{
-    server::{DeviceExt, GenServerImpl, Storage},
+    server::{DeviceCounters, DeviceExt, GenServerImpl, Storage},
+    timestamps::Timestamps,
     MacAddressBlock,
 };
 
@@ -32,23 +35,32 @@ fn claim_server_storage_statics() -> &'static mut [Storage; VLAN_COUNT] {
 pub struct VLanEthernet<'a> {
     pub eth: &'a eth::Ethernet,
     pub vid: u16,
+    tap: &'a Tap,
+    firewall: &'a Firewall,
     mac_rx: Cell<bool>,
+    counters: DeviceCounters,
+    timestamps: Timestamps,
+}
+
+impl VLanEthernet<'_> {
+    /// Index of this VLAN in the server's arrays (and the capture's
+    /// interfaces)
+    fn index(&self) -> usize {
+        usize::from(self.vid - VLAN_RANGE.start)
+    }
 }
 
 impl<'a> smoltcp::phy::Device for VLanEthernet<'a> {
-    type RxToken<'b> = VLanRxToken<'a> where Self: 'b;
-    type TxToken<'b> = VLanTxToken<'a> where Self: 'b;
+    type RxToken<'b> = VLanRxToken<'b> where Self: 'b;
+    type TxToken<'b> = VLanTxToken<'b> where Self: 'b;
 
     fn receive(
         &mut self,
         _timestamp: smoltcp::time::Instant,
-    ) -> Option<(Self::RxToken<'a>, Self::TxToken<'a>)> {
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
         if self.eth.vlan_can_recv(self.vid, VLAN_RANGE) && self.eth.can_send() {
             self.mac_rx.set(true);
-            Some((
-                VLanRxToken(self.eth, self.vid),
-                VLanTxToken(self.eth, self.vid),
-            ))
+            Some((VLanRxToken(self), VLanTxToken(self)))
         } else {
             None
         }
@@ -56,10 +68,11 @@ impl<'a> smoltcp::phy::Device for VLanEthernet<'a> {
     fn transmit(
         &mut self,
         _timestamp: smoltcp::time::Instant,
-    ) -> Option<Self::TxToken<'a>> {
+    ) -> Option<Self::TxToken<'_>> {
         if self.eth.can_send() {
-            Some(VLanTxToken(self.eth, self.vid))
+            Some(VLanTxToken(self))
         } else {
+            DeviceCounters::bump(&self.counters.tx_ring_full);
             None
         }
     }
@@ -73,6 +86,14 @@ impl DeviceExt for VLanEthernet<'_> {
         self.mac_rx.take()
     }
 
+    fn counters(&self) -> &DeviceCounters {
+        &self.counters
+    }
+
+    fn timestamps(&self) -> &Timestamps {
+        &self.timestamps
+    }
+
     fn make_meta(
         &self,
         port: u16,
@@ -90,24 +111,40 @@ impl DeviceExt for VLanEthernet<'_> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct VLanRxToken<'a>(&'a eth::Ethernet, u16);
+pub struct VLanRxToken<'a>(&'a VLanEthernet<'a>);
 impl<'a> smoltcp::phy::RxToken for VLanRxToken<'a> {
     fn consume<R, F>(self, f: F) -> R
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0.vlan_recv(self.1, f)
+        let dev = self.0;
+        DeviceCounters::bump(&dev.counters.rx_frames);
+        dev.eth.vlan_recv(dev.vid, |frame| {
+            dev.tap.frame(dev.index(), Direction::In, frame);
+            if !dev.firewall.admit(frame) {
+                return f(&mut frame[..0]);
+            }
+            dev.timestamps.received(dev.eth, frame);
+            f(frame)
+        })
     }
 }
 
-pub struct VLanTxToken<'a>(&'a eth::Ethernet, u16);
+pub struct VLanTxToken<'a>(&'a VLanEthernet<'a>);
 impl<'a> smoltcp::phy::TxToken for VLanTxToken<'a> {
     fn consume<R, F>(self, len: usize, f: F) -> R
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0
-            .vlan_try_send(len, self.1, f)
+        let dev = self.0;
+        DeviceCounters::bump(&dev.counters.tx_frames);
+        dev.eth
+            .vlan_try_send(len, dev.vid, |frame| {
+                let r = f(frame);
+                dev.tap.frame(dev.index(), Direction::Out, frame);
+                dev.timestamps.sending(dev.eth, frame);
+                r
+            })
             .expect("TX token existed without descriptor available")
     }
 }
@@ -124,16 +161,25 @@ pub fn new<B>(
 where
     B: bsp_support::Bsp,
 {
+    let capture = capture::claim_statics();
+    let tap = capture.tap;
+    let firewall = firewall::claim_statics();
     ServerImpl::new(
         eth,
         mac,
         bsp,
         claim_server_storage_statics(),
         generated::construct_sockets(),
+        capture,
+        firewall,
         |i| VLanEthernet {
             eth,
             vid: generated::VLAN_RANGE.start + i as u16,
+            tap,
+            firewall,
             mac_rx: Cell::new(false),
+            counters: DeviceCounters::default(),
+            timestamps: Timestamps::default(),
         },
     )
 }
@@ -0,0 +1,217 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! SLAAC, for IPv6 addresses beyond link-local on each interface.
+//!
+//! The client itself lives in `slaac`; this gives it a raw ICMPv6 socket to
+//! talk through, since duplicate address detection sends from the
+//! unspecified address, and applies what it finds to the interface: an
+//! address for each prefix that's passed detection, and a default route via
+//! the router. smoltcp still does the rest of neighbor discovery itself.
+
+use ringbuf::*;
+use slaac::{AddressState, Client, MAX_PACKET, MAX_PREFIXES};
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::socket::raw;
+use smoltcp::wire::{
+    EthernetAddress, IpCidr, IpProtocol, IpVersion, Ipv6Address, Ipv6Cidr,
+};
+use task_net_api::{
+    Ipv6AddressState, Ipv6AddressStatus, Ipv6Status, SLAAC_ADDRESSES,
+};
+use userlib::UnwrapLite;
+
+// The API has room for every address we can make.
+const _: () = assert!(MAX_PREFIXES == SLAAC_ADDRESSES);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Trace {
+    None,
+    Address {
+        vlan: usize,
+        address: [u8; 16],
+        state: AddressState,
+    },
+    Router {
+        vlan: usize,
+        router: Option<[u8; 16]>,
+    },
+    SendFailed(usize),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Packets we can hold in each direction. The raw socket sees every ICMPv6
+/// packet, including all the neighbor discovery smoltcp deals with, so this
+/// needs to be enough to ride out a burst.
+const PACKETS: usize = 4;
+
+/// Largest packet we take in. Advertisements with more options than will fit
+/// in this are dropped.
+const MAX_RX_PACKET: usize = 512;
+
+pub struct SlaacStorage {
+    rx_meta: [raw::PacketMetadata; PACKETS],
+    rx: [u8; PACKETS * MAX_RX_PACKET],
+    tx_meta: [raw::PacketMetadata; PACKETS],
+    tx: [u8; PACKETS * MAX_PACKET],
+}
+
+impl Default for SlaacStorage {
+    fn default() -> Self {
+        Self {
+            rx_meta: [raw::PacketMetadata::EMPTY; PACKETS],
+            rx: [0; PACKETS * MAX_RX_PACKET],
+            tx_meta: [raw::PacketMetadata::EMPTY; PACKETS],
+            tx: [0; PACKETS * MAX_PACKET],
+        }
+    }
+}
+
+pub(crate) struct Slaac {
+    vlan: usize,
+    client: Client,
+    socket: SocketHandle,
+}
+
+impl Slaac {
+    pub(crate) fn new(
+        vlan: usize,
+        mac: EthernetAddress,
+        storage: &'static mut SlaacStorage,
+        socket_set: &mut SocketSet<'static>,
+        now: u64,
+    ) -> Self {
+        let socket = socket_set.add(raw::Socket::new(
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            raw::PacketBuffer::new(
+                &mut storage.rx_meta[..],
+                &mut storage.rx[..],
+            ),
+            raw::PacketBuffer::new(
+                &mut storage.tx_meta[..],
+                &mut storage.tx[..],
+            ),
+        ));
+        Self {
+            vlan,
+            client: Client::new(mac.0, now),
+            socket,
+        }
+    }
+
+    /// Feeds the client whatever's come in, moves it along to `now`, and
+    /// queues whatever it has to send. Returns true if anything changed or
+    /// was queued, meaning the interface needs polling again.
+    pub(crate) fn poll(
+        &mut self,
+        now: u64,
+        iface: &mut Interface,
+        socket_set: &mut SocketSet<'static>,
+    ) -> bool {
+        let socket = socket_set.get_mut::<raw::Socket<'_>>(self.socket);
+
+        let mut changed = false;
+        while let Ok(packet) = socket.recv() {
+            changed |= self.client.receive(now, packet);
+        }
+        changed |= self.client.poll(now);
+
+        let mut activity = changed;
+        let mut buf = [0; MAX_PACKET];
+        while let Some(len) = self.client.transmit(now, &mut buf) {
+            // If this fails, we go without: a lost probe means we don't hear
+            // about a duplicate, and a lost solicitation means waiting for
+            // the router to advertise by itself.
+            if socket.send_slice(&buf[..len]).is_err() {
+                ringbuf_entry!(Trace::SendFailed(self.vlan));
+            }
+            activity = true;
+        }
+
+        if changed {
+            self.apply(iface);
+        }
+        activity
+    }
+
+    /// Returns the next time we need to `poll`, absent any packets.
+    pub(crate) fn poll_at(&self) -> u64 {
+        self.client.poll_at()
+    }
+
+    /// Reports our addresses, and the router and how long it's good for.
+    pub(crate) fn status(&self, now: u64) -> Ipv6Status {
+        let secs_until = |t: u64| {
+            if t == u64::MAX {
+                u32::MAX
+            } else {
+                (t.saturating_sub(now) / 1000).min(u32::MAX.into()) as u32
+            }
+        };
+        let mut addresses = [None; SLAAC_ADDRESSES];
+        for (out, a) in addresses.iter_mut().zip(self.client.addresses()) {
+            *out = Some(Ipv6AddressStatus {
+                address: task_net_api::Ipv6Address(a.address),
+                prefix_len: 64,
+                state: match a.state {
+                    AddressState::Tentative => Ipv6AddressState::Tentative,
+                    AddressState::Preferred => Ipv6AddressState::Preferred,
+                    AddressState::Deprecated => Ipv6AddressState::Deprecated,
+                    AddressState::Duplicate => Ipv6AddressState::Duplicate,
+                },
+                preferred_for: secs_until(a.preferred_until),
+                valid_for: secs_until(a.valid_until),
+            });
+        }
+        let router = self.client.router();
+        Ipv6Status {
+            link_local: task_net_api::Ipv6Address(self.client.link_local()),
+            addresses,
+            router: router.map(|r| task_net_api::Ipv6Address(r.address)),
+            router_for: router.map_or(0, |r| secs_until(r.until)),
+        }
+    }
+
+    /// Replaces the interface's IPv6 addresses, other than its link-local
+    /// one, and its IPv6 default route, with what the client has.
+    fn apply(&self, iface: &mut Interface) {
+        for a in self.client.addresses() {
+            ringbuf_entry!(Trace::Address {
+                vlan: self.vlan,
+                address: a.address,
+                state: a.state,
+            });
+        }
+        iface.update_ip_addrs(|addrs| {
+            addrs.retain(|a| match a {
+                IpCidr::Ipv6(c) => c.address().is_link_local(),
+                #[cfg(feature = "dhcpv4")]
+                IpCidr::Ipv4(_) => true,
+            });
+            for a in self.client.addresses().filter(|a| a.is_usable()) {
+                let cidr = Ipv6Cidr::new(Ipv6Address(a.address), 64);
+                addrs.push(IpCidr::Ipv6(cidr)).unwrap_lite();
+            }
+        });
+
+        let router = self.client.router().map(|r| r.address);
+        ringbuf_entry!(Trace::Router {
+            vlan: self.vlan,
+            router,
+        });
+        match router {
+            Some(router) => {
+                iface
+                    .routes_mut()
+                    .add_default_ipv6_route(Ipv6Address(router))
+                    .unwrap_lite();
+            }
+            None => {
+                iface.routes_mut().remove_default_ipv6_route();
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Timestamps for UDP socket traffic, from the MAC's IEEE 1588 clock.
+//!
+//! smoltcp doesn't carry anything from the device along with a datagram to
+//! its socket, so we match them up ourselves. Coming in, the device tokens
+//! note the source and length of each datagram for one of our sockets, with
+//! the driver's timestamp, and `recv_packet_timestamped` looks for the note
+//! matching the datagram the socket hands back. Datagrams smoltcp drops
+//! leave notes behind that never match; those are cleared out once a later
+//! one does. Going out, the tokens note which frame each socket last sent,
+//! so that the driver can find its timestamp once it's gone.
+//!
+//! Without the `ptp` feature, there's nothing to note, and no timestamps.
+
+#[cfg(feature = "ptp")]
+pub(crate) use enabled::*;
+
+#[cfg(not(feature = "ptp"))]
+pub(crate) use disabled::*;
+
+#[cfg(not(feature = "ptp"))]
+mod disabled {
+    use drv_stm32h7_eth as eth;
+    use smoltcp::wire::IpEndpoint;
+    use task_net_api::PtpTimestamp;
+
+    #[derive(Default)]
+    pub struct Timestamps;
+
+    impl Timestamps {
+        pub(crate) fn received(&self, _eth: &eth::Ethernet, _f: &[u8]) {}
+        pub(crate) fn sending(&self, _eth: &eth::Ethernet, _f: &[u8]) {}
+
+        pub(crate) fn rx_timestamp(
+            &self,
+            _socket: usize,
+            _from: IpEndpoint,
+            _len: usize,
+        ) -> Option<PtpTimestamp> {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "ptp")]
+mod enabled {
+    use crate::generated::{self, SOCKET_COUNT};
+    use crate::server::SocketKind;
+    use core::cell::{Cell, RefCell};
+    use drv_stm32h7_eth as eth;
+    use heapless::Deque;
+    use smoltcp::wire::{IpAddress, IpEndpoint, Ipv6Address};
+    use task_net_api::PtpTimestamp;
+
+    /// Datagrams we remember per socket, waiting for their owner to receive
+    /// them. This needn't cover the socket's whole queue: datagrams past
+    /// this many just come without timestamps.
+    const RX_NOTES: usize = 4;
+
+    const ETHERTYPE_IPV6: u16 = 0x86dd;
+    #[cfg(feature = "dhcpv4")]
+    const ETHERTYPE_IPV4: u16 = 0x0800;
+    const IPPROTO_UDP: u8 = 17;
+
+    /// A datagram that has arrived for a socket.
+    struct RxNote {
+        from: IpEndpoint,
+        len: usize,
+        at: PtpTimestamp,
+    }
+
+    pub struct Timestamps {
+        rx: RefCell<[Deque<RxNote, RX_NOTES>; SOCKET_COUNT]>,
+        /// Number of the frame each socket last sent, as the driver counts
+        tx: [Cell<Option<u32>>; SOCKET_COUNT],
+    }
+
+    impl Default for Timestamps {
+        fn default() -> Self {
+            Self {
+                rx: RefCell::new(core::array::from_fn(|_| Deque::new())),
+                tx: Default::default(),
+            }
+        }
+    }
+
+    impl Timestamps {
+        /// Notes the arrival of `frame`, if it's for one of our sockets.
+        /// This must be called from within the driver's `recv` closure, so
+        /// that the driver's timestamp is for this frame.
+        pub(crate) fn received(&self, eth: &eth::Ethernet, frame: &[u8]) {
+            let Some(at) = eth.rx_timestamp() else {
+                return;
+            };
+            let Some(udp) = Udp::parse(frame) else {
+                return;
+            };
+            let Some(socket) = socket_on(udp.dst_port) else {
+                return;
+            };
+            let mut rx = self.rx.borrow_mut();
+            let notes = &mut rx[socket];
+            if notes.is_full() {
+                notes.pop_front();
+            }
+            // We just made room, so this can't fail.
+            let _ = notes.push_back(RxNote {
+                from: IpEndpoint::new(udp.src_addr, udp.src_port),
+                len: udp.len,
+                at: ptp_timestamp(at),
+            });
+        }
+
+        /// Notes that `frame` is being sent, if it's from one of our
+        /// sockets. This must be called from within the driver's `try_send`
+        /// closure, so that the driver's frame number is for this frame.
+        pub(crate) fn sending(&self, eth: &eth::Ethernet, frame: &[u8]) {
+            let Some(udp) = Udp::parse(frame) else {
+                return;
+            };
+            if let Some(socket) = socket_on(udp.src_port) {
+                self.tx[socket].set(Some(eth.next_tx_frame()));
+            }
+        }
+
+        /// Finds when the datagram of `len` bytes from `from`, just received
+        /// on socket `socket`, arrived.
+        pub(crate) fn rx_timestamp(
+            &self,
+            socket: usize,
+            from: IpEndpoint,
+            len: usize,
+        ) -> Option<PtpTimestamp> {
+            let mut rx = self.rx.borrow_mut();
+            let notes = &mut rx[socket];
+            // Anything before the match was dropped on its way to the socket.
+            let i =
+                notes.iter().position(|n| n.from == from && n.len == len)?;
+            for _ in 0..i {
+                notes.pop_front();
+            }
+            notes.pop_front().map(|n| n.at)
+        }
+
+        /// Finds when the last frame sent on socket `socket` went out.
+        pub(crate) fn tx_timestamp(
+            &self,
+            eth: &eth::Ethernet,
+            socket: usize,
+        ) -> Option<PtpTimestamp> {
+            let at = eth.tx_timestamp(self.tx[socket].get()?)?;
+            Some(ptp_timestamp(at))
+        }
+    }
+
+    pub(crate) fn ptp_timestamp(t: eth::ring::Timestamp) -> PtpTimestamp {
+        PtpTimestamp {
+            seconds: t.seconds,
+            nanos: t.nanos,
+        }
+    }
+
+    /// Returns the index of the UDP socket on `port`, if there is one.
+    fn socket_on(port: u16) -> Option<usize> {
+        (0..SOCKET_COUNT).find(|&i| {
+            generated::SOCKET_KINDS[i] == SocketKind::Udp
+                && generated::SOCKET_PORTS[i] == port
+        })
+    }
+
+    /// What we need to know about a UDP datagram.
+    struct Udp {
+        src_addr: IpAddress,
+        src_port: u16,
+        dst_port: u16,
+        /// Payload length
+        len: usize,
+    }
+
+    impl Udp {
+        /// Picks apart an (untagged) Ethernet frame. As with capture, IPv6
+        /// extension headers and IPv4 fragments are beyond us.
+        fn parse(frame: &[u8]) -> Option<Self> {
+            let be16 = |at: usize| {
+                frame
+                    .get(at..at + 2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            };
+            let (src_addr, l4) = match be16(12)? {
+                ETHERTYPE_IPV6 if *frame.get(14 + 6)? == IPPROTO_UDP => {
+                    let addr = Ipv6Address::from_bytes(frame.get(22..38)?);
+                    (IpAddress::Ipv6(addr), 14 + 40)
+                }
+                #[cfg(feature = "dhcpv4")]
+                ETHERTYPE_IPV4 if *frame.get(14 + 9)? == IPPROTO_UDP => {
+                    let addr = smoltcp::wire::Ipv4Address::from_bytes(
+                        frame.get(26..30)?,
+                    );
+                    let ihl = usize::from(frame[14] & 0xf);
+                    (IpAddress::Ipv4(addr), 14 + ihl * 4)
+                }
+                _ => return None,
+            };
+            Some(Self {
+                src_addr,
+                src_port: be16(l4)?,
+                dst_port: be16(l4 + 2)?,
+                len: usize::from(be16(l4 + 4)?).checked_sub(8)?,
+            })
+        }
+    }
+}
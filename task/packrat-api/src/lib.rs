@@ -35,4 +35,10 @@ pub enum CacheSetError {
     ValueAlreadySet = 1,
 }
 
+/// The values that can only be set once, as bits of `Packrat::get_cached`.
+pub mod cached {
+    pub const MAC_ADDRESS_BLOCK: u32 = 1 << 0;
+    pub const IDENTITY: u32 = 1 << 1;
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -2,8 +2,28 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Task config, all of it optional:
+///
+/// ```toml
+/// [tasks.packrat.config]
+/// on-update = { udpbroadcast = "packrat-update" }
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    /// Tasks to notify when a value that's set once (the MAC address block
+    /// or identity) is set, and the notification to post to each.
+    #[serde(default)]
+    on_update: BTreeMap<String, String>,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    idol::server::build_server_support(
+    build_util::build_notifications()?;
+    build_util::idol::build_server_support(
         "../../idl/packrat.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
@@ -33,5 +53,25 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         _ => (),
     }
 
+    let config =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+    let dest_path = build_util::out_dir().join("packrat_config.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = config.on_update.len();
+    writeln!(
+        out,
+        "const UPDATE_MAILING_LIST: [({task}, u32); {count}] = ["
+    )?;
+    for (name, rec) in &config.on_update {
+        writeln!(
+            out,
+            "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+            rec.to_ascii_uppercase().replace('-', "_"),
+        )?;
+    }
+    writeln!(out, "];")?;
+
     Ok(())
 }
@@ -25,6 +25,12 @@
 //!    functions.
 //! 3. packrat never calls into any other task, as calling into a task gives the
 //!    callee opportunity to fault the caller.
+//!
+//! Because the values that are set once come from hardware some other task
+//! has to get to first, tasks that want them needn't wait for that before
+//! starting: they can instead be on packrat's `on-update` list, and be
+//! notified whenever one is set. (Posting a notification isn't calling into
+//! a task; it can't fail, and can't block.)
 
 #![no_std]
 #![no_main]
@@ -34,10 +40,12 @@ use idol_runtime::{Leased, LenLimit, RequestError};
 use mutable_statics::mutable_statics;
 use ringbuf::{ringbuf, ringbuf_entry};
 use task_packrat_api::{
-    CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
+    cached, CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
     VpdIdentity,
 };
-use userlib::RecvMessage;
+use userlib::{sys_post, sys_refresh_task_id, Generation, RecvMessage, TaskId};
+
+include!(concat!(env!("OUT_DIR"), "/packrat_config.rs"));
 
 #[cfg(feature = "gimlet")]
 mod gimlet;
@@ -141,12 +149,22 @@ impl ServerImpl {
             None => {
                 ringbuf_entry!(TraceSet::Set(value).into());
                 *storage = Some(value);
+                notify();
                 Ok(())
             }
         }
     }
 }
 
+/// Tells the tasks on our mailing list that a value has been set.
+fn notify() {
+    for (task, mask) in UPDATE_MAILING_LIST {
+        let taskid = TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+        let taskid = sys_refresh_task_id(taskid);
+        sys_post(taskid, mask);
+    }
+}
+
 impl idl::InOrderPackratImpl for ServerImpl {
     fn get_mac_address_block(
         &mut self,
@@ -180,6 +198,20 @@ impl idl::InOrderPackratImpl for ServerImpl {
         Self::set_once(&mut self.identity, identity).map_err(Into::into)
     }
 
+    fn get_cached(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<Infallible>> {
+        let mut bits = 0;
+        if self.mac_address_block.is_some() {
+            bits |= cached::MAC_ADDRESS_BLOCK;
+        }
+        if self.identity.is_some() {
+            bits |= cached::IDENTITY;
+        }
+        Ok(bits)
+    }
+
     #[cfg(feature = "gimlet")]
     fn get_next_boot_host_startup_options(
         &mut self,
@@ -298,3 +330,5 @@ mod idl {
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
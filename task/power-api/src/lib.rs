@@ -185,4 +185,20 @@ impl RenesasBlackbox {
     }
 }
 
+/// Energy delivered by a rail, as accumulated by the power task.
+///
+/// The counter wraps rather than saturating, so consumers should take the
+/// difference (with `wrapping_sub`) between two readings to meter energy over
+/// the time between them.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, SerializedSize)]
+pub struct RailEnergy {
+    /// Output energy, in microjoules
+    pub microjoules: u64,
+    /// Total time over which we've integrated power, in milliseconds; this
+    /// falls behind the time since boot when readings are missed.
+    pub integrated_ms: u64,
+    /// When the most recent power reading was taken
+    pub timestamp: u64,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
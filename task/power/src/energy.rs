@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Energy accounting, so that energy can be metered without polling power
+//! readings at a high rate from across the network.
+//!
+//! On each poll we integrate each rail's output power (the product of its
+//! output voltage and current) over the time since its previous reading,
+//! using the average of the two readings. A rail that's powered off delivers
+//! no energy, so its power is taken to be zero; intervals that end (or start)
+//! with a failed reading, or that are too long for us to guess at what
+//! happened during them, aren't counted.
+
+use task_power_api::RailEnergy;
+
+/// We don't integrate across gaps longer than this between readings, in
+/// milliseconds.
+const MAX_GAP_MS: u64 = 5 * crate::TIMER_INTERVAL;
+
+#[derive(Copy, Clone, Default)]
+pub(crate) struct Energy {
+    /// Accumulated energy, in microjoules; wraps.
+    microjoules: u64,
+    /// Time integrated over, in milliseconds
+    integrated_ms: u64,
+    /// Most recent reading of power, in watts, or `None` if it failed
+    last: Option<f32>,
+    /// When the most recent good reading was taken
+    timestamp: u64,
+}
+
+impl Energy {
+    /// Records a reading of the rail's power, in watts, or `None` if we
+    /// failed to read it.
+    pub(crate) fn record(&mut self, power: Option<f32>, now: u64) {
+        // Small negative readings are noise around zero.
+        let Some(power) = power.filter(|p| p.is_finite()).map(|p| p.max(0.0))
+        else {
+            self.last = None;
+            return;
+        };
+
+        if let Some(last) = self.last {
+            let dt = now.saturating_sub(self.timestamp);
+            if dt <= MAX_GAP_MS {
+                // Watts times milliseconds is millijoules.
+                let uj = (last + power) / 2.0 * dt as f32 * 1000.0;
+                self.microjoules = self.microjoules.wrapping_add(uj as u64);
+                self.integrated_ms += dt;
+            }
+        }
+        self.last = Some(power);
+        self.timestamp = now;
+    }
+
+    pub(crate) fn get(&self) -> RailEnergy {
+        RailEnergy {
+            microjoules: self.microjoules,
+            integrated_ms: self.integrated_ms,
+            timestamp: self.timestamp,
+        }
+    }
+}
@@ -17,18 +17,21 @@ use drv_i2c_devices::ltc4282::*;
 use drv_i2c_devices::max5970::*;
 use drv_i2c_devices::mwocp68::*;
 use drv_i2c_devices::raa229618::*;
+use drv_i2c_devices::raa229620::*;
 use drv_i2c_devices::tps546b24a::*;
+use energy::Energy;
 use pmbus::Phase;
 use ringbuf::*;
 use task_power_api::{
-    Bmr491Event, PmbusValue, RawPmbusBlock, RenesasBlackbox, MAX_BLOCK_LEN,
+    Bmr491Event, PmbusValue, RailEnergy, RawPmbusBlock, RenesasBlackbox,
+    MAX_BLOCK_LEN,
 };
 use task_sensor_api as sensor_api;
 use userlib::units::*;
 use userlib::*;
 use zerocopy::AsBytes;
 
-use drv_i2c_api::{I2cDevice, ResponseCode};
+use drv_i2c_api::{Address, I2cDevice, ResponseCode};
 use drv_i2c_devices::{
     CurrentSensor, InputCurrentSensor, InputVoltageSensor, TempSensor,
     VoltageSensor,
@@ -63,6 +66,7 @@ include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 enum DeviceType {
     IBC,
     Core,
+    CoreRaa229620,
     SerDes,
     Mem,
     MemVpp,
@@ -89,6 +93,7 @@ struct PowerControllerConfig {
 enum Device {
     Bmr491(Bmr491),
     Raa229618(Raa229618),
+    Raa229620(Raa229620),
     Isl68224(Isl68224),
     Tps546B24A(Tps546B24A),
     Adm1272(Adm1272),
@@ -102,6 +107,7 @@ impl Device {
         let r = match &self {
             Device::Bmr491(dev) => dev.read_temperature()?,
             Device::Raa229618(dev) => dev.read_temperature()?,
+            Device::Raa229620(dev) => dev.read_temperature()?,
             Device::Isl68224(dev) => dev.read_temperature()?,
             Device::Tps546B24A(dev) => dev.read_temperature()?,
             Device::Adm1272(dev) => dev.read_temperature()?,
@@ -122,6 +128,7 @@ impl Device {
         let r = match &self {
             Device::Bmr491(dev) => dev.read_iout()?,
             Device::Raa229618(dev) => dev.read_iout()?,
+            Device::Raa229620(dev) => dev.read_iout()?,
             Device::Isl68224(dev) => dev.read_iout()?,
             Device::Tps546B24A(dev) => dev.read_iout()?,
             Device::Adm1272(dev) => dev.read_iout()?,
@@ -136,6 +143,7 @@ impl Device {
         let r = match &self {
             Device::Bmr491(dev) => dev.read_vout()?,
             Device::Raa229618(dev) => dev.read_vout()?,
+            Device::Raa229620(dev) => dev.read_vout()?,
             Device::Isl68224(dev) => dev.read_vout()?,
             Device::Tps546B24A(dev) => dev.read_vout()?,
             Device::Adm1272(dev) => dev.read_vout()?,
@@ -146,6 +154,20 @@ impl Device {
         Ok(r)
     }
 
+    ///
+    /// Reads the device's full telemetry set in a single batch, if the
+    /// device supports doing so; returns `None` if it does not, in which
+    /// case values should be read individually.
+    ///
+    fn read_telemetry(&self) -> Option<Result<Telemetry, ResponseCode>> {
+        match &self {
+            Device::Adm1272(dev) => {
+                Some(dev.read_telemetry().map_err(Into::into))
+            }
+            _ => None,
+        }
+    }
+
     fn read_phase_current(
         &self,
         phase: Phase,
@@ -160,6 +182,7 @@ impl Device {
     fn read_vin(&self) -> Result<Volts, ResponseCode> {
         let r = match &self {
             Device::Mwocp68(dev) => dev.read_vin()?,
+            Device::Raa229620(dev) => dev.read_vin()?,
             // Do any other devices have VIN? For now we only added support to
             // MWOCP68
             _ => return Err(ResponseCode::NoDevice),
@@ -185,6 +208,7 @@ impl Device {
             Device::Mwocp68(dev) => dev.pmbus_read(op)?,
             Device::Bmr491(_)
             | Device::Raa229618(_)
+            | Device::Raa229620(_)
             | Device::Isl68224(_)
             | Device::Tps546B24A(_)
             | Device::Adm1272(_)
@@ -202,6 +226,7 @@ impl Device {
             Device::Bmr491(dev) => dev.read_mode()?,
             Device::Raa229618(dev) => dev.read_mode()?,
             Device::Isl68224(dev) => dev.read_mode()?,
+            Device::Raa229620(dev) => dev.read_mode()?,
             Device::Tps546B24A(dev) => dev.read_mode()?,
             Device::Adm1272(..) | Device::Ltc4282(..) | Device::Max5970(..) => {
                 return Err(ResponseCode::OperationNotSupported)
@@ -215,6 +240,7 @@ impl Device {
             Device::Mwocp68(dev) => dev.i2c_device(),
             Device::Bmr491(dev) => dev.i2c_device(),
             Device::Raa229618(dev) => dev.i2c_device(),
+            Device::Raa229620(dev) => dev.i2c_device(),
             Device::Isl68224(dev) => dev.i2c_device(),
             Device::Tps546B24A(dev) => dev.i2c_device(),
             Device::Adm1272(dev) => dev.i2c_device(),
@@ -232,6 +258,9 @@ impl PowerControllerConfig {
             DeviceType::Core | DeviceType::Mem => {
                 Device::Raa229618(Raa229618::new(&dev, rail))
             }
+            DeviceType::CoreRaa229620 => {
+                Device::Raa229620(Raa229620::new(&dev, rail))
+            }
             DeviceType::MemVpp | DeviceType::SerDes => {
                 Device::Isl68224(Isl68224::new(&dev, rail))
             }
@@ -399,6 +428,8 @@ macro_rules! mwocp68_controller {
 #[cfg_attr(target_board = "gimletlet-2", path = "bsp/gimletlet_2.rs")]
 mod bsp;
 
+mod energy;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[export_name = "main"]
@@ -411,6 +442,10 @@ fn main() -> ! {
         i2c_task,
         sensor: sensor_api::Sensor::from(SENSOR.get_task_id()),
         devices: claim_devices(i2c_task),
+        energy: mutable_statics::mutable_statics!(
+            static mut ENERGY: [Energy; bsp::CONTROLLER_CONFIG_LEN] =
+                [Default::default; _];
+        ),
     };
     let mut buffer = [0; idl::INCOMING_SIZE];
 
@@ -427,6 +462,7 @@ struct ServerImpl {
     i2c_task: TaskId,
     sensor: sensor_api::Sensor,
     devices: &'static mut [Device; bsp::CONTROLLER_CONFIG_LEN],
+    energy: &'static mut [Energy; bsp::CONTROLLER_CONFIG_LEN],
 }
 
 impl ServerImpl {
@@ -434,8 +470,10 @@ impl ServerImpl {
         let state = bsp::get_state();
         let sensor = &self.sensor;
 
-        for (c, dev) in
-            bsp::CONTROLLER_CONFIG.iter().zip(self.devices.iter_mut())
+        for ((c, dev), energy) in bsp::CONTROLLER_CONFIG
+            .iter()
+            .zip(self.devices.iter_mut())
+            .zip(self.energy.iter_mut())
         {
             if c.state == PowerState::A0 && state != PowerState::A0 {
                 let now = sys_get_timer().now;
@@ -446,11 +484,20 @@ impl ServerImpl {
                     sensor.nodata(id, NoData::DeviceOff, now).unwrap();
                 }
 
+                energy.record(Some(0.0), now);
+
                 continue;
             }
 
+            let batch = dev.read_telemetry();
+
             if let Some(id) = c.temperature {
-                match dev.read_temperature() {
+                let reading = match batch {
+                    Some(telemetry) => telemetry.map(|t| t.temperature),
+                    None => dev.read_temperature(),
+                };
+
+                match reading {
                     Ok(reading) => {
                         sensor.post_now(id, reading.0).unwrap();
                     }
@@ -460,7 +507,13 @@ impl ServerImpl {
                 }
             }
 
-            match dev.read_iout() {
+            let reading = match batch {
+                Some(telemetry) => telemetry.map(|t| t.iout),
+                None => dev.read_iout(),
+            };
+
+            let iout = reading.as_ref().ok().map(|r| r.0);
+            match reading {
                 Ok(reading) => {
                     sensor.post_now(c.current, reading.0).unwrap();
                 }
@@ -469,7 +522,13 @@ impl ServerImpl {
                 }
             }
 
-            match dev.read_vout() {
+            let reading = match batch {
+                Some(telemetry) => telemetry.map(|t| t.vout),
+                None => dev.read_vout(),
+            };
+
+            let vout = reading.as_ref().ok().map(|r| r.0);
+            match reading {
                 Ok(reading) => {
                     sensor.post_now(c.voltage, reading.0).unwrap();
                 }
@@ -478,6 +537,9 @@ impl ServerImpl {
                 }
             }
 
+            let power = iout.zip(vout).map(|(i, v)| i * v);
+            energy.record(power, sys_get_timer().now);
+
             if let Some(id) = c.input_voltage {
                 match dev.read_vin() {
                     Ok(reading) => {
@@ -811,6 +873,19 @@ impl idl::InOrderPowerImpl for ServerImpl {
         Err(ResponseCode::BadArg.into())
     }
 
+    fn rail_energy(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        rail: task_sensor_api::SensorId,
+    ) -> Result<RailEnergy, idol_runtime::RequestError<ResponseCode>> {
+        bsp::CONTROLLER_CONFIG
+            .iter()
+            .zip(self.energy.iter())
+            .find(|(c, _)| c.voltage == rail)
+            .map(|(_, energy)| energy.get())
+            .ok_or_else(|| ResponseCode::BadArg.into())
+    }
+
     fn bmr491_event_log_read(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -887,7 +962,7 @@ impl idl::InOrderPowerImpl for ServerImpl {
         let dev = self
             .devices
             .iter()
-            .find(|d| d.i2c_device().address == addr)
+            .find(|d| d.i2c_device().target() == Address::SevenBit(addr))
             .ok_or(ResponseCode::NoDevice)?;
 
         // The isl68224 and raa229618 have identical DMAADDR / DMAFIX / DMASEQ
@@ -994,7 +1069,7 @@ impl idl::InOrderPowerImpl for ServerImpl {
             .devices
             .iter()
             .find(|d| {
-                d.i2c_device().address == addr
+                d.i2c_device().target() == Address::SevenBit(addr)
                     && matches!(d, Device::Raa229618(..) | Device::Isl68224(..))
             })
             .ok_or(ResponseCode::NoDevice)?
@@ -1019,7 +1094,7 @@ impl idl::InOrderPowerImpl for ServerImpl {
             .devices
             .iter()
             .find(|d| {
-                d.i2c_device().address == addr
+                d.i2c_device().target() == Address::SevenBit(addr)
                     && matches!(d, Device::Raa229618(..) | Device::Isl68224(..))
             })
             .ok_or(ResponseCode::NoDevice)?
@@ -31,8 +31,11 @@ struct Sensor {
 }
 
 fn main() -> Result<()> {
-    idol::client::build_client_stub("../../idl/sensor.idol", "client_stub.rs")
-        .map_err(|e| anyhow!("idol error: {e}"))?;
+    build_util::idol::build_client_stub(
+        "../../idl/sensor.idol",
+        "client_stub.rs",
+    )
+    .map_err(|e| anyhow!("idol error: {e}"))?;
 
     build_i2c::codegen(build_i2c::Disposition::Sensors)?;
 
@@ -50,6 +50,84 @@ impl Reading {
     }
 }
 
+/// Readings of a sensor, aggregated over one of the sensor task's windows.
+/// Rather than exactly the window, this covers between one and two window
+/// lengths, from `start` to `end`; `end` may be a little in the future.
+#[derive(Copy, Clone, Debug, SerializedSize, Serialize, Deserialize)]
+pub struct Aggregate {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    /// How many readings were aggregated.
+    pub count: u32,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Thresholds that put a sensor into alarm when its readings reach them. A
+/// sensor leaves an alarm state once its readings fall `hysteresis` below
+/// the threshold, so that one hovering around it doesn't flap.
+///
+/// The hysteresis must be a non-negative number, and the warning threshold
+/// (if both are set) no higher than the critical one.
+#[derive(
+    Copy, Clone, Debug, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub struct Thresholds {
+    pub warning: Option<f32>,
+    pub critical: Option<f32>,
+    pub hysteresis: f32,
+}
+
+impl Thresholds {
+    pub const NONE: Self = Self {
+        warning: None,
+        critical: None,
+        hysteresis: 0.0,
+    };
+
+    pub fn is_valid(&self) -> bool {
+        let finite = |v: Option<f32>| v.map_or(true, f32::is_finite);
+
+        // Written so that a NaN hysteresis fails, too.
+        (self.hysteresis >= 0.0 && self.hysteresis.is_finite())
+            && finite(self.warning)
+            && finite(self.critical)
+            && match (self.warning, self.critical) {
+                (Some(w), Some(c)) => w <= c,
+                _ => true,
+            }
+    }
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Eq,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    SerializedSize,
+    Serialize,
+    Deserialize,
+)]
+#[repr(u8)]
+pub enum AlarmState {
+    Normal,
+    Warning,
+    Critical,
+}
+
+/// A sensor's alarm state, and the reading that put it there.
+#[derive(Copy, Clone, Debug, SerializedSize, Serialize, Deserialize)]
+pub struct SensorAlarm {
+    pub id: SensorId,
+    pub state: AlarmState,
+    pub value: f32,
+    pub timestamp: u64,
+}
+
 //
 // Note that [`counter_encoding`] relies on [`NoData`] being numbered from 0 and
 // being numbered sequentially.
@@ -115,6 +193,8 @@ pub enum SensorError {
     DeviceUnavailable = 5,
     DeviceTimeout = 6,
     DeviceOff = 7,
+    InvalidWindow = 8,
+    InvalidThresholds = 9,
 
     #[idol(server_death)]
     ServerDied,
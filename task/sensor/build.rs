@@ -2,13 +2,135 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Task config, all of it optional:
+///
+/// ```toml
+/// [tasks.sensor.config]
+/// aggregate-windows-ms = [10000, 60000]
+/// history-len = 8
+/// on-alarm = { thermal = "sensor-alarm" }
+///
+/// [[tasks.sensor.config.thresholds]]
+/// sensors = ["TMP117_NORTHEAST_TEMPERATURE_SENSOR"]
+/// warning = 60.0
+/// critical = 70.0
+/// hysteresis = 2.0
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    /// Windows over which to keep each sensor's min, max, and mean.
+    #[serde(default)]
+    aggregate_windows_ms: Vec<u64>,
+    /// How many of each sensor's most recent readings to keep.
+    #[serde(default)]
+    history_len: usize,
+    #[serde(default)]
+    thresholds: Vec<ThresholdConfig>,
+    /// Tasks to notify when a sensor's alarm state changes, and the
+    /// notification to post to each.
+    #[serde(default)]
+    on_alarm: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ThresholdConfig {
+    /// Sensors these apply to, by the names of their `SensorId` constants in
+    /// `task_sensor_api::config` (e.g. `i2c_sensors::TMP117_..._SENSOR`); the
+    /// module can be left off, and arrays indexed.
+    sensors: Vec<String>,
+    warning: Option<f32>,
+    critical: Option<f32>,
+    #[serde(default)]
+    hysteresis: f32,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/sensor.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
     )?;
+
+    let config =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+    if config.aggregate_windows_ms.contains(&0) {
+        return Err("aggregate windows can't be empty".into());
+    }
+    if config.history_len > u8::MAX as usize {
+        return Err(format!("history-len can be at most {}", u8::MAX).into());
+    }
+
+    let dest_path = build_util::out_dir().join("sensor_task_config.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+    writeln!(
+        out,
+        "const NUM_WINDOWS: usize = {};",
+        config.aggregate_windows_ms.len()
+    )?;
+    writeln!(
+        out,
+        "const AGGREGATE_WINDOWS_MS: [u64; NUM_WINDOWS] = {:?};",
+        config.aggregate_windows_ms
+    )?;
+    writeln!(out, "const HISTORY_LEN: usize = {};", config.history_len)?;
+
+    let count: usize = config.thresholds.iter().map(|t| t.sensors.len()).sum();
+    writeln!(
+        out,
+        "const THRESHOLDS: [(SensorId, Thresholds); {count}] = {{
+    #[allow(unused_imports)]
+    use task_sensor_api::config::{{i2c_sensors::*, other_sensors::*, *}};
+    ["
+    )?;
+    for t in &config.thresholds {
+        if !(t.hysteresis >= 0.0 && t.hysteresis.is_finite()) {
+            return Err(format!(
+                "hysteresis for {:?} must be a non-negative number",
+                t.sensors
+            )
+            .into());
+        }
+        if let (Some(w), Some(c)) = (t.warning, t.critical) {
+            if w > c {
+                return Err(format!(
+                    "warning threshold for {:?} is above critical",
+                    t.sensors
+                )
+                .into());
+            }
+        }
+        for sensor in &t.sensors {
+            writeln!(
+                out,
+                "        ({sensor}, Thresholds {{ warning: {:?}, \
+                 critical: {:?}, hysteresis: {:?} }}),",
+                t.warning, t.critical, t.hysteresis,
+            )?;
+        }
+    }
+    writeln!(out, "    ]\n}};")?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = config.on_alarm.len();
+    writeln!(
+        out,
+        "const ALARM_MAILING_LIST: [({task}, u32); {count}] = ["
+    )?;
+    for (name, rec) in &config.on_alarm {
+        writeln!(
+            out,
+            "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+            rec.to_ascii_uppercase().replace('-', "_"),
+        )?;
+    }
+    writeln!(out, "];")?;
     Ok(())
 }
@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Per-sensor aggregates and history, so that a reading that comes and goes
+//! between polls isn't lost.
+//!
+//! Each aggregation window is split into fixed periods of its length (aligned
+//! to multiples of it since boot), and we keep the aggregate of the current
+//! period and of the one before. What we report is the two together, which
+//! covers between one and two window lengths back from now; that's the price
+//! of not keeping every sample.
+
+use crate::{AGGREGATE_WINDOWS_MS, HISTORY_LEN, NUM_WINDOWS};
+use task_sensor_api::config::NUM_SENSORS;
+use task_sensor_api::{Aggregate, Reading};
+
+/// Aggregate of the readings in one period.
+#[derive(Copy, Clone)]
+pub(crate) struct Period {
+    min: f32,
+    max: f32,
+    sum: f32,
+    count: u32,
+}
+
+impl Period {
+    pub(crate) const EMPTY: Self = Self {
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+        sum: 0.0,
+        count: 0,
+    };
+
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn merge(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            sum: self.sum + other.sum,
+            count: self.count + other.count,
+        }
+    }
+}
+
+pub(crate) struct Aggregates {
+    // Structure-of-arrays again, for the same reason as `ServerImpl`.
+    pub(crate) current: &'static mut [[Period; NUM_SENSORS]; NUM_WINDOWS],
+    pub(crate) previous: &'static mut [[Period; NUM_SENSORS]; NUM_WINDOWS],
+    /// Which period `current` is, as a multiple of the window length.
+    pub(crate) epoch: &'static mut [[u32; NUM_SENSORS]; NUM_WINDOWS],
+}
+
+impl Aggregates {
+    pub(crate) fn record(&mut self, index: usize, value: f32, timestamp: u64) {
+        for w in 0..NUM_WINDOWS {
+            let epoch = (timestamp / AGGREGATE_WINDOWS_MS[w]) as u32;
+            let current = &mut self.current[w][index];
+            let last = self.epoch[w][index];
+
+            // A reading from an earlier period than the current one (which
+            // shouldn't happen, but timestamps come from whoever posts) is
+            // counted in the current one.
+            if epoch > last {
+                self.previous[w][index] = if epoch == last + 1 {
+                    *current
+                } else {
+                    Period::EMPTY
+                };
+                *current = Period::EMPTY;
+                self.epoch[w][index] = epoch;
+            }
+            current.add(value);
+        }
+    }
+
+    /// Returns the aggregate for window `w` as of `now`, or `None` if there
+    /// have been no readings in it.
+    pub(crate) fn get(
+        &self,
+        index: usize,
+        w: usize,
+        now: u64,
+    ) -> Option<Aggregate> {
+        let len = AGGREGATE_WINDOWS_MS[w];
+        let now_epoch = (now / len) as u32;
+        let last = self.epoch[w][index];
+        let current = &self.current[w][index];
+        let previous = &self.previous[w][index];
+
+        // If nothing has been posted in a while, the periods we have may be
+        // too old to count.
+        let (earlier, later) = if now_epoch <= last {
+            (previous, current)
+        } else if now_epoch == last + 1 {
+            (current, &Period::EMPTY)
+        } else {
+            (&Period::EMPTY, &Period::EMPTY)
+        };
+        let total = earlier.merge(later);
+        if total.count == 0 {
+            return None;
+        }
+
+        let end = (u64::from(now_epoch) + 1) * len;
+        let start = if earlier.count != 0 {
+            end.saturating_sub(2 * len)
+        } else {
+            end - len
+        };
+        Some(Aggregate {
+            min: total.min,
+            max: total.max,
+            mean: total.sum / total.count as f32,
+            count: total.count,
+            start,
+            end,
+        })
+    }
+}
+
+pub(crate) struct History {
+    pub(crate) value: &'static mut [[f32; HISTORY_LEN]; NUM_SENSORS],
+    pub(crate) time: &'static mut [[u64; HISTORY_LEN]; NUM_SENSORS],
+    /// Slot for each sensor's next reading.
+    pub(crate) next: &'static mut [u8; NUM_SENSORS],
+    /// How many slots hold readings.
+    pub(crate) len: &'static mut [u8; NUM_SENSORS],
+}
+
+impl History {
+    pub(crate) fn record(&mut self, index: usize, value: f32, timestamp: u64) {
+        if HISTORY_LEN == 0 {
+            return;
+        }
+        let slot = self.next[index] as usize;
+        self.value[index][slot] = value;
+        self.time[index][slot] = timestamp;
+        self.next[index] = ((slot + 1) % HISTORY_LEN) as u8;
+        if (self.len[index] as usize) < HISTORY_LEN {
+            self.len[index] += 1;
+        }
+    }
+
+    /// Returns the `n`th most recent reading, starting from 0.
+    pub(crate) fn get(&self, index: usize, n: usize) -> Option<Reading> {
+        if n >= self.len[index] as usize {
+            return None;
+        }
+        let slot =
+            (self.next[index] as usize + HISTORY_LEN - 1 - n) % HISTORY_LEN;
+        Some(Reading::new(
+            self.value[index][slot],
+            self.time[index][slot],
+        ))
+    }
+}
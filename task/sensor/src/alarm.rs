@@ -0,0 +1,101 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Threshold alarms, so that consumers of sensor data can be told when a
+//! sensor crosses a threshold rather than each polling for it.
+//!
+//! Thresholds come from the task config, and can be replaced at runtime. We
+//! re-evaluate a sensor's alarm state with each reading posted to it, and
+//! tell the tasks on `ALARM_MAILING_LIST` whenever any sensor's state
+//! changes; they can then find the sensors in alarm with `next_alarm`.
+
+use crate::{ALARM_MAILING_LIST, THRESHOLDS};
+use task_sensor_api::config::NUM_SENSORS;
+use task_sensor_api::{AlarmState, Thresholds};
+use userlib::*;
+
+pub(crate) struct Alarms {
+    // As elsewhere, structure-of-arrays; a missing threshold is a NaN, which
+    // no reading is ever at or above.
+    pub(crate) state: &'static mut [AlarmState; NUM_SENSORS],
+    pub(crate) warning: &'static mut [f32; NUM_SENSORS],
+    pub(crate) critical: &'static mut [f32; NUM_SENSORS],
+    pub(crate) hysteresis: &'static mut [f32; NUM_SENSORS],
+}
+
+impl Alarms {
+    /// Loads the thresholds from our config.
+    pub(crate) fn init(&mut self) {
+        for (id, t) in THRESHOLDS {
+            self.set_thresholds(id.0 as usize, t);
+        }
+    }
+
+    pub(crate) fn thresholds(&self, index: usize) -> Thresholds {
+        let some = |v: f32| if v.is_nan() { None } else { Some(v) };
+        Thresholds {
+            warning: some(self.warning[index]),
+            critical: some(self.critical[index]),
+            hysteresis: self.hysteresis[index],
+        }
+    }
+
+    pub(crate) fn set_thresholds(&mut self, index: usize, t: Thresholds) {
+        self.warning[index] = t.warning.unwrap_or(f32::NAN);
+        self.critical[index] = t.critical.unwrap_or(f32::NAN);
+        self.hysteresis[index] = t.hysteresis;
+    }
+
+    /// Moves a sensor's alarm state along given a new reading, returning true
+    /// if it changed.
+    pub(crate) fn update(&mut self, index: usize, value: f32) -> bool {
+        let state = self.state[index];
+        let hysteresis = self.hysteresis[index];
+
+        // Once we're at or past a threshold, we stay there until we're
+        // `hysteresis` below it.
+        let reached = |threshold: f32, active: bool| {
+            if active {
+                value > threshold - hysteresis
+            } else {
+                value >= threshold
+            }
+        };
+
+        let critical = state == AlarmState::Critical;
+        let warning = state != AlarmState::Normal;
+        let next = if reached(self.critical[index], critical) {
+            AlarmState::Critical
+        } else if reached(self.warning[index], warning) {
+            AlarmState::Warning
+        } else {
+            AlarmState::Normal
+        };
+
+        self.state[index] = next;
+        next != state
+    }
+
+    /// Returns a sensor to `Normal` (when it's stopped giving us readings),
+    /// returning true if it was in alarm.
+    pub(crate) fn clear(&mut self, index: usize) -> bool {
+        let state = self.state[index];
+        self.state[index] = AlarmState::Normal;
+        state != AlarmState::Normal
+    }
+
+    /// Returns the first sensor from `start` on that's in alarm.
+    pub(crate) fn next(&self, start: usize) -> Option<usize> {
+        (start..NUM_SENSORS).find(|&i| self.state[i] != AlarmState::Normal)
+    }
+}
+
+/// Tells everyone on our mailing list that an alarm has changed.
+pub(crate) fn notify() {
+    for (task, mask) in ALARM_MAILING_LIST {
+        let taskid = TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+        let taskid = sys_refresh_task_id(taskid);
+        sys_post(taskid, mask);
+    }
+}
@@ -8,11 +8,22 @@
 #![no_main]
 
 use idol_runtime::{NotificationHandler, RequestError};
-use task_sensor_api::{NoData, Reading, SensorError, SensorId};
+use task_sensor_api::{
+    Aggregate, AlarmState, NoData, Reading, SensorAlarm, SensorError, SensorId,
+    Thresholds,
+};
 use userlib::*;
 
 use task_sensor_api::config::NUM_SENSORS;
 
+mod aggregate;
+mod alarm;
+
+use aggregate::{Aggregates, History, Period};
+use alarm::Alarms;
+
+include!(concat!(env!("OUT_DIR"), "/sensor_task_config.rs"));
+
 #[derive(Copy, Clone)]
 enum LastReading {
     Data,
@@ -36,6 +47,10 @@ struct ServerImpl {
 
     nerrors: &'static mut [u32; NUM_SENSORS],
     deadline: u64,
+
+    aggregates: Aggregates,
+    history: History,
+    alarms: Alarms,
 }
 
 const TIMER_INTERVAL: u64 = 1000;
@@ -86,6 +101,11 @@ impl idl::InOrderSensorImpl for ServerImpl {
             self.last_reading[index] = Some(LastReading::Data);
             self.data_value[index] = value;
             self.data_time[index] = timestamp;
+            self.aggregates.record(index, value, timestamp);
+            self.history.record(index, value, timestamp);
+            if self.alarms.update(index, value) {
+                alarm::notify();
+            }
             Ok(())
         } else {
             Err(SensorError::InvalidSensor.into())
@@ -122,6 +142,15 @@ impl idl::InOrderSensorImpl for ServerImpl {
                 self.nerrors[index] += incr;
             }
 
+            //
+            // Without a reading, we can't say whether the sensor is still
+            // past its thresholds; rather than leave a stale alarm standing,
+            // clear it until we hear otherwise.
+            //
+            if self.alarms.clear(index) {
+                alarm::notify();
+            }
+
             Ok(())
         } else {
             Err(SensorError::InvalidSensor.into())
@@ -141,6 +170,116 @@ impl idl::InOrderSensorImpl for ServerImpl {
             Err(SensorError::InvalidSensor.into())
         }
     }
+
+    fn get_aggregate(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        window: u32,
+    ) -> Result<Aggregate, RequestError<SensorError>> {
+        let index = id.0 as usize;
+        let window = window as usize;
+
+        if index >= NUM_SENSORS {
+            Err(SensorError::InvalidSensor.into())
+        } else if window >= NUM_WINDOWS {
+            Err(SensorError::InvalidWindow.into())
+        } else {
+            self.aggregates
+                .get(index, window, sys_get_timer().now)
+                .ok_or_else(|| SensorError::NoReading.into())
+        }
+    }
+
+    fn get_history(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        n: u32,
+    ) -> Result<Reading, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            self.history
+                .get(index, n as usize)
+                .ok_or_else(|| SensorError::NoReading.into())
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn get_thresholds(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<Thresholds, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            Ok(self.alarms.thresholds(index))
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn set_thresholds(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        thresholds: Thresholds,
+    ) -> Result<(), RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if !thresholds.is_valid() {
+            Err(SensorError::InvalidThresholds.into())
+        } else if index < NUM_SENSORS {
+            self.alarms.set_thresholds(index, thresholds);
+            if let Some(LastReading::Data) = self.last_reading[index] {
+                if self.alarms.update(index, self.data_value[index]) {
+                    alarm::notify();
+                }
+            }
+            Ok(())
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn get_alarm(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<SensorAlarm, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            Ok(self.alarm(index))
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn next_alarm(
+        &mut self,
+        _: &RecvMessage,
+        start: SensorId,
+    ) -> Result<SensorAlarm, RequestError<SensorError>> {
+        self.alarms
+            .next(start.0 as usize)
+            .map(|index| self.alarm(index))
+            .ok_or_else(|| SensorError::InvalidSensor.into())
+    }
+}
+
+impl ServerImpl {
+    fn alarm(&self, index: usize) -> SensorAlarm {
+        SensorAlarm {
+            id: SensorId(index as u32),
+            state: self.alarms.state[index],
+            value: self.data_value[index],
+            timestamp: self.data_time[index],
+        }
+    }
 }
 
 impl NotificationHandler for ServerImpl {
@@ -172,6 +311,31 @@ fn main() -> ! {
         static mut NERRORS: [u32; NUM_SENSORS] = [|| 0; _];
     };
 
+    let (current, previous, epoch) = mutable_statics::mutable_statics! {
+        static mut AGG_CURRENT: [[Period; NUM_SENSORS]; NUM_WINDOWS] =
+            [|| [Period::EMPTY; NUM_SENSORS]; _];
+        static mut AGG_PREVIOUS: [[Period; NUM_SENSORS]; NUM_WINDOWS] =
+            [|| [Period::EMPTY; NUM_SENSORS]; _];
+        static mut AGG_EPOCH: [[u32; NUM_SENSORS]; NUM_WINDOWS] =
+            [|| [0; NUM_SENSORS]; _];
+    };
+
+    let (hist_value, hist_time, hist_next, hist_len) = mutable_statics::mutable_statics! {
+        static mut HIST_VALUE: [[f32; HISTORY_LEN]; NUM_SENSORS] =
+            [|| [f32::NAN; HISTORY_LEN]; _];
+        static mut HIST_TIME: [[u64; HISTORY_LEN]; NUM_SENSORS] =
+            [|| [0; HISTORY_LEN]; _];
+        static mut HIST_NEXT: [u8; NUM_SENSORS] = [|| 0; _];
+        static mut HIST_LEN: [u8; NUM_SENSORS] = [|| 0; _];
+    };
+
+    let (alarm_state, warning, critical, hysteresis) = mutable_statics::mutable_statics! {
+        static mut ALARM_STATE: [AlarmState; NUM_SENSORS] = [|| AlarmState::Normal; _];
+        static mut WARNING: [f32; NUM_SENSORS] = [|| f32::NAN; _];
+        static mut CRITICAL: [f32; NUM_SENSORS] = [|| f32::NAN; _];
+        static mut HYSTERESIS: [f32; NUM_SENSORS] = [|| 0.0; _];
+    };
+
     let mut server = ServerImpl {
         last_reading,
         data_value,
@@ -180,7 +344,25 @@ fn main() -> ! {
         err_time,
         nerrors,
         deadline,
+        aggregates: Aggregates {
+            current,
+            previous,
+            epoch,
+        },
+        history: History {
+            value: hist_value,
+            time: hist_time,
+            next: hist_next,
+            len: hist_len,
+        },
+        alarms: Alarms {
+            state: alarm_state,
+            warning,
+            critical,
+            hysteresis,
+        },
     };
+    server.alarms.init();
 
     let mut buffer = [0; idl::INCOMING_SIZE];
 
@@ -190,7 +372,10 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::{NoData, Reading, SensorError, SensorId};
+    use super::{
+        Aggregate, NoData, Reading, SensorAlarm, SensorError, SensorId,
+        Thresholds,
+    };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use serde::Deserialize;
-use sha3::{Digest, Sha3_256};
+use sha2::{Digest, Sha256};
 use std::io::Write;
 use std::path::PathBuf;
 
@@ -15,6 +15,8 @@ struct TaskConfig {
 const TEST_SIZE: usize = 0x0010_0000;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    build_util::build_notifications()?;
+
     let out_dir = build_util::out_dir();
     let dest_path = out_dir.join("expected.rs");
     let mut file = std::fs::File::create(&dest_path)?;
@@ -38,7 +40,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     writeln!(&mut file, "const FLASH_END: u32 = FLASH_START + TEST_SIZE;")
         .unwrap();
 
-    let mut sha = Sha3_256::new();
+    let mut sha = Sha256::new();
     sha.update(&bin);
 
     let extra: Vec<u8> = vec![0xff; TEST_SIZE - bin.len()];
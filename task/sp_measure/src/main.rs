@@ -5,9 +5,9 @@
 #![no_std]
 #![no_main]
 
+use drv_lpc55_crypto_api::{Crypto, Sha256};
 use drv_sp_ctrl_api::*;
 use ringbuf::*;
-use sha3::{Digest, Sha3_256};
 use userlib::*;
 
 const READ_SIZE: usize = 256;
@@ -15,6 +15,7 @@ const READ_SIZE: usize = 256;
 const TRANSACTION_SIZE: u32 = 1024;
 
 task_slot!(SP_CTRL, swd);
+task_slot!(CRYPTO, crypto);
 
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
@@ -30,7 +31,9 @@ ringbuf!(Trace, 16, Trace::None);
 #[export_name = "main"]
 fn main() -> ! {
     loop {
-        let mut sha = Sha3_256::new();
+        let crypto = Crypto::from(CRYPTO.get_task_id());
+        let mut sha = Sha256::begin(&crypto, notifications::CRYPTO_DONE_MASK)
+            .unwrap_lite();
         let sp_ctrl = SpCtrl::from(SP_CTRL.get_task_id());
 
         if sp_ctrl.setup().is_err() {
@@ -55,14 +58,14 @@ fn main() -> ! {
                 panic!();
             }
 
-            sha.update(&data);
+            sha.update(&data).unwrap_lite();
         }
 
-        let sha_out = sha.finalize();
+        let sha_out = sha.finish().unwrap_lite();
 
         let end = sys_get_timer().now;
         ringbuf_entry!(Trace::End(end));
-        if sha_out.as_slice() == EXPECTED.as_slice() {
+        if sha_out == EXPECTED {
             ringbuf_entry!(Trace::ShaGood);
         } else {
             ringbuf_entry!(Trace::ShaBad);
@@ -70,10 +73,11 @@ fn main() -> ! {
 
         // Wait for a notification that will never come, politer than
         // busy looping forever
-        if sys_recv_closed(&mut [], 1, TaskId::KERNEL).is_err() {
+        if sys_recv_closed(&mut [], 1 << 31, TaskId::KERNEL).is_err() {
             panic!();
         }
     }
 }
 
 include!(concat!(env!("OUT_DIR"), "/expected.rs"));
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
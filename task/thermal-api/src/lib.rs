@@ -56,6 +56,50 @@ pub enum ThermalAutoState {
     Uncontrollable,
 }
 
+/// Health of an individual fan, as judged from its tachometer
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub enum FanStatus {
+    /// The fan is not being driven (or has not yet been read), so we can't
+    /// judge its health
+    Unknown,
+    /// The fan is spinning at a plausible speed for its duty cycle
+    Ok,
+    /// The fan is spinning, but well below the speed expected for its duty
+    /// cycle
+    Undershoot,
+    /// The fan is being driven but is not spinning
+    Stalled,
+}
+
+impl FanStatus {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, FanStatus::Undershoot | FanStatus::Stalled)
+    }
+}
+
+/// Thresholds used to judge fan health
+#[derive(Clone, Copy, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct FanThresholds {
+    /// Expected speed at 100% duty cycle; if zero, undershoot detection is
+    /// disabled
+    pub max_rpm: u16,
+
+    /// A driven fan spinning slower than this is considered stalled
+    pub stall_rpm: u16,
+
+    /// A fan spinning below this percentage of its expected speed (scaled
+    /// linearly from `max_rpm` by duty cycle) is considered to be
+    /// undershooting
+    pub undershoot_percent: u8,
+
+    /// Number of consecutive bad readings before a fan is declared failed,
+    /// which also gives fans time to spin up after a duty cycle change
+    pub debounce: u8,
+}
+
 /// Properties for a particular part in the system
 #[derive(Clone, Copy, AsBytes, FromBytes)]
 #[repr(C)]
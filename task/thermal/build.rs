@@ -2,16 +2,176 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::io::Write;
+
+/// Task config, all of it optional:
+///
+/// ```toml
+/// [tasks.thermal.config]
+/// sensor-timeout-ms = 10000
+/// prochot-hysteresis = 3.0
+///
+/// [[tasks.thermal.config.zones]]
+/// inputs = [0, 1, 2, 3]
+/// fans = [0, 1]
+/// gain-p = 1.75
+/// gain-i = 0.0135
+/// gain-d = 0.4
+/// zero = 35.0
+/// margin = 2.0
+/// ramp-limit = 10.0
+/// ```
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    /// How old a temperature reading can get before we stop trusting the
+    /// controller for its zone, and run the zone's fans flat out.
+    #[serde(default = "TaskConfig::default_sensor_timeout_ms")]
+    sensor_timeout_ms: u64,
+    /// How far below critical everything has to cool, in degrees, before we
+    /// stop throttling the CPU; this should be wider than the band it takes
+    /// to leave the overheated state, lest we go straight back into it.
+    #[serde(default = "TaskConfig::default_prochot_hysteresis")]
+    prochot_hysteresis: f32,
+    /// Control zones; without any, there's one zone, with every input and
+    /// every fan in it.
+    #[serde(default)]
+    zones: Vec<ZoneConfig>,
+}
+
+impl TaskConfig {
+    fn default_sensor_timeout_ms() -> u64 {
+        10_000
+    }
+
+    fn default_prochot_hysteresis() -> f32 {
+        3.0
+    }
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        Self {
+            sensor_timeout_ms: Self::default_sensor_timeout_ms(),
+            prochot_hysteresis: Self::default_prochot_hysteresis(),
+            zones: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ZoneConfig {
+    /// Temperature inputs in the zone, as indices into the BSP's inputs
+    /// followed by its dynamic inputs; all of them if omitted.
+    inputs: Option<Vec<usize>>,
+    /// Fans that cool the zone, as indices into the BSP's fans; all of them
+    /// if omitted.
+    fans: Option<Vec<usize>>,
+    /// Controller gains; if these are omitted, the zone uses the BSP's (which
+    /// can be changed at runtime).
+    gain_p: Option<f32>,
+    gain_i: Option<f32>,
+    gain_d: Option<f32>,
+    zero: Option<f32>,
+    /// Target margin for the zone; the runtime-settable one if omitted.
+    margin: Option<f32>,
+    /// Most that the zone's output can change in one control cycle, in
+    /// percent.
+    ramp_limit: Option<f32>,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
     build_i2c::codegen(build_i2c::Disposition::Sensors)?;
 
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/thermal.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
     )?;
 
+    let config =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+    write_zones(config)?;
+
+    Ok(())
+}
+
+fn write_zones(
+    mut config: TaskConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if config.zones.is_empty() {
+        config.zones.push(ZoneConfig::default());
+    }
+
+    let dest_path = build_util::out_dir().join("thermal_config.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+
+    writeln!(
+        out,
+        "pub(crate) const SENSOR_TIMEOUT_MS: u64 = {};",
+        config.sensor_timeout_ms
+    )?;
+    if config.prochot_hysteresis < 0.0 {
+        return Err("bad prochot-hysteresis".into());
+    }
+    writeln!(
+        out,
+        "pub(crate) const PROCHOT_HYSTERESIS: Celsius = Celsius({:?});",
+        config.prochot_hysteresis
+    )?;
+    writeln!(
+        out,
+        "pub(crate) const NUM_ZONES: usize = {};",
+        config.zones.len()
+    )?;
+    writeln!(out, "pub(crate) const ZONES: [Zone; NUM_ZONES] = [")?;
+    for (i, z) in config.zones.iter().enumerate() {
+        let gains = [z.gain_p, z.gain_i, z.gain_d, z.zero];
+        let pid = match gains {
+            [Some(gain_p), Some(gain_i), Some(gain_d), Some(zero)] => {
+                if gain_p <= 0.0 || gain_i < 0.0 || gain_d < 0.0 {
+                    return Err(format!("zone {i} has bad gains").into());
+                }
+                format!(
+                    "Some(PidConfig {{ zero: {zero:?}, gain_p: {gain_p:?}, \
+                     gain_i: {gain_i:?}, gain_d: {gain_d:?} }})"
+                )
+            }
+            [None, None, None, None] => "None".to_string(),
+            _ => {
+                return Err(format!(
+                    "zone {i} must give all of its gains and its zero, \
+                     or none of them"
+                )
+                .into())
+            }
+        };
+        if z.ramp_limit.map_or(false, |r| r <= 0.0) {
+            return Err(format!("zone {i} has a bad ramp limit").into());
+        }
+        let slice = |v: &Option<Vec<usize>>| match v {
+            Some(v) => format!("Some(&{v:?})"),
+            None => "None".to_string(),
+        };
+        writeln!(
+            out,
+            "    Zone {{
+        inputs: {},
+        fans: {},
+        pid: {pid},
+        margin: {:?},
+        ramp_limit: {:?},
+    }},",
+            slice(&z.inputs),
+            slice(&z.fans),
+            z.margin,
+            z.ramp_limit,
+        )?;
+    }
+    writeln!(out, "];")?;
     Ok(())
 }
@@ -10,10 +10,10 @@ use crate::{
 };
 use core::convert::TryInto;
 pub use drv_gimlet_seq_api::SeqError;
-use drv_gimlet_seq_api::{PowerState, Sequencer};
+use drv_gimlet_seq_api::{FanEvent, PowerReason, PowerState, Sequencer};
 use drv_i2c_devices::max31790::Max31790;
 use task_sensor_api::SensorId;
-use task_thermal_api::ThermalProperties;
+use task_thermal_api::{FanThresholds, ThermalProperties};
 use userlib::{task_slot, units::Celsius, TaskId};
 
 task_slot!(SEQ, gimlet_seq);
@@ -33,7 +33,7 @@ pub const NUM_TEMPERATURE_INPUTS: usize = sensors::NUM_SBTSI_TEMPERATURE_SENSORS
 pub const NUM_DYNAMIC_TEMPERATURE_INPUTS: usize = 0;
 
 // We've got 6 fans, driven from a single MAX31790 IC
-const NUM_FANS: usize = drv_i2c_devices::max31790::MAX_FANS as usize;
+pub const NUM_FANS: usize = drv_i2c_devices::max31790::MAX_FANS as usize;
 
 /// This controller is tuned and ready to go
 pub const USE_CONTROLLER: bool = true;
@@ -60,6 +60,9 @@ pub(crate) struct Bsp {
 
     /// Tuning for the PID controller
     pub pid_config: PidConfig,
+
+    /// Thresholds for detecting failed fans
+    pub fan_thresholds: FanThresholds,
 }
 
 bitflags::bitflags! {
@@ -89,7 +92,26 @@ impl Bsp {
     }
 
     pub fn power_down(&self) -> Result<(), SeqError> {
-        self.seq.set_state(PowerState::A2)
+        self.seq
+            .set_state_with_reason(PowerState::A2, PowerReason::Overheat)
+    }
+
+    pub fn set_prochot(&self, asserted: bool) -> Result<(), SeqError> {
+        match self.seq.set_prochot(asserted) {
+            // If the host has just gone down, there's nothing to throttle.
+            Err(SeqError::NotInA0) => Ok(()),
+            r => r,
+        }
+    }
+
+    /// Drains the sequencer's fan hot-swap events, returning true if fan
+    /// power has been (re-)enabled since we last asked.
+    pub fn fans_repowered(&self) -> bool {
+        let mut repowered = false;
+        while let Ok(event) = self.seq.next_fan_event() {
+            repowered |= event == FanEvent::Enabled;
+        }
+        repowered
     }
 
     pub fn power_mode(&self) -> PowerBitmask {
@@ -172,6 +194,15 @@ impl Bsp {
                 gain_d: 0.4,
             },
 
+            // Undershoot detection is disabled until we've characterized
+            // the fans' speed curves; a stall is unambiguous, though.
+            fan_thresholds: FanThresholds {
+                max_rpm: 0,
+                stall_rpm: 500,
+                undershoot_percent: 50,
+                debounce: 5,
+            },
+
             inputs: &INPUTS,
             dynamic_inputs: &[],
 
@@ -450,7 +481,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n0,
-            sensors::NVME_BMC_U2_N0_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[0],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -460,7 +491,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n1,
-            sensors::NVME_BMC_U2_N1_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[1],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -470,7 +501,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n2,
-            sensors::NVME_BMC_U2_N2_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[2],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -480,7 +511,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n3,
-            sensors::NVME_BMC_U2_N3_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[3],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -490,7 +521,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n4,
-            sensors::NVME_BMC_U2_N4_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[4],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -500,7 +531,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n5,
-            sensors::NVME_BMC_U2_N5_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[5],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -510,7 +541,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n6,
-            sensors::NVME_BMC_U2_N6_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[6],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -520,7 +551,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n7,
-            sensors::NVME_BMC_U2_N7_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[7],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -530,7 +561,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n8,
-            sensors::NVME_BMC_U2_N8_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[8],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -540,7 +571,7 @@ const INPUTS: [InputChannel; NUM_TEMPERATURE_INPUTS] = [
         TemperatureSensor::new(
             Device::U2,
             devices::nvme_bmc_u2_n9,
-            sensors::NVME_BMC_U2_N9_TEMPERATURE_SENSOR,
+            sensors::NVME_BMC_SLOT_TEMPERATURE_SENSORS[9],
         ),
         U2_THERMALS,
         PowerBitmask::A0,
@@ -13,7 +13,7 @@ use drv_i2c_devices::tmp451::*;
 pub use drv_sidecar_seq_api::SeqError;
 use drv_sidecar_seq_api::{Sequencer, TofinoSeqState, TofinoSequencerPolicy};
 use task_sensor_api::SensorId;
-use task_thermal_api::ThermalProperties;
+use task_thermal_api::{FanThresholds, ThermalProperties};
 use userlib::{task_slot, units::Celsius, TaskId};
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
@@ -37,7 +37,7 @@ pub const NUM_TEMPERATURE_INPUTS: usize =
 pub const NUM_DYNAMIC_TEMPERATURE_INPUTS: usize =
     drv_transceivers_api::NUM_PORTS as usize;
 
-const NUM_FANS: usize = sensors::NUM_MAX31790_SPEED_SENSORS;
+pub const NUM_FANS: usize = sensors::NUM_MAX31790_SPEED_SENSORS;
 
 // Run the PID loop on startup
 pub const USE_CONTROLLER: bool = true;
@@ -139,6 +139,18 @@ impl Bsp {
             .set_tofino_seq_policy(TofinoSequencerPolicy::Disabled)
     }
 
+    /// Tofino has nothing like PROCHOT, so the only way we have of protecting
+    /// it is to power it down.
+    pub fn set_prochot(&self, _asserted: bool) -> Result<(), SeqError> {
+        Ok(())
+    }
+
+    /// Sidecar's fans aren't hot-swapped by the sequencer, so there are no
+    /// fan power events to report.
+    pub fn fans_repowered(&self) -> bool {
+        false
+    }
+
     pub fn new(i2c_task: TaskId) -> Self {
         // Awkwardly build the fan array, because there's not a great way
         // to build a fixed-size array from a function
@@ -170,6 +182,15 @@ impl Bsp {
                 gain_d: 0.4,
             },
 
+            // Like the PID tuning, there's no Sidecar fan characterization
+            // yet, so we only detect outright stalls.
+            fan_thresholds: FanThresholds {
+                max_rpm: 0,
+                stall_rpm: 500,
+                undershoot_percent: 50,
+                debounce: 5,
+            },
+
             inputs: &INPUTS,
             dynamic_inputs:
                 &drv_transceivers_api::TRANSCEIVER_TEMPERATURE_SENSORS,
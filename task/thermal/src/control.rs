@@ -18,8 +18,12 @@ use drv_i2c_devices::{
 };
 
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
-use task_sensor_api::{Reading, Sensor as SensorApi, SensorError, SensorId};
-use task_thermal_api::{ThermalAutoState, ThermalProperties};
+use task_sensor_api::{
+    AlarmState, Reading, Sensor as SensorApi, SensorError, SensorId,
+};
+use task_thermal_api::{
+    FanStatus, FanThresholds, ThermalAutoState, ThermalProperties,
+};
 use userlib::{
     sys_get_timer,
     units::{Celsius, PWMDuty, Rpm},
@@ -99,6 +103,21 @@ pub enum SensorReadError {
 
     /// The reply is structurally incorrect (wrong length, bad checksum, etc)
     CorruptReply,
+
+    /// The device is present but not yet able to report a temperature
+    NotReady,
+}
+
+impl SensorReadError {
+    /// Returns `true` if this error indicates that a removable device is
+    /// absent (or not yet usable), rather than that something went wrong
+    fn is_absent(&self) -> bool {
+        matches!(
+            self,
+            SensorReadError::I2cError(ResponseCode::NoDevice)
+                | SensorReadError::NotReady
+        )
+    }
 }
 
 impl From<drv_i2c_devices::tmp117::Error> for SensorReadError {
@@ -147,6 +166,7 @@ impl From<drv_i2c_devices::nvme_bmc::Error> for SensorReadError {
             SensorFailure => Self::SensorFailure,
             Reserved => Self::ReservedValue,
             InvalidLength | BadChecksum => Self::CorruptReply,
+            DriveNotReady => Self::NotReady,
         }
     }
 }
@@ -155,6 +175,7 @@ impl From<SensorReadError> for task_sensor_api::NoData {
     fn from(code: SensorReadError) -> task_sensor_api::NoData {
         match code {
             SensorReadError::I2cError(v) => v.into(),
+            SensorReadError::NotReady => Self::DeviceUnavailable,
             _ => Self::DeviceError,
         }
     }
@@ -242,6 +263,84 @@ pub(crate) struct DynamicInputChannel {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Tracks the health of a single fan, based on its tachometer readings.
+#[derive(Copy, Clone)]
+struct FanHealth {
+    /// Most recently commanded duty cycle, if known
+    pwm: Option<PWMDuty>,
+
+    /// Current judgement of the fan's health
+    status: FanStatus,
+
+    /// Number of consecutive readings judged to be failures
+    bad_count: u8,
+}
+
+impl FanHealth {
+    const fn new() -> Self {
+        Self {
+            pwm: None,
+            status: FanStatus::Unknown,
+            bad_count: 0,
+        }
+    }
+
+    /// Judges a single tachometer reading against our thresholds, returning
+    /// `None` if the fan isn't being driven (and therefore can't be judged).
+    fn judge(&self, rpm: Rpm, t: &FanThresholds) -> Option<FanStatus> {
+        let pwm = match self.pwm {
+            None | Some(PWMDuty(0)) => return None,
+            Some(pwm) => pwm.0 as u32,
+        };
+
+        let expected = t.max_rpm as u32 * pwm / 100;
+
+        Some(if rpm.0 < t.stall_rpm {
+            FanStatus::Stalled
+        } else if (rpm.0 as u32) * 100 < expected * t.undershoot_percent as u32
+        {
+            FanStatus::Undershoot
+        } else {
+            FanStatus::Ok
+        })
+    }
+
+    /// Updates our status from a new reading, returning the new status if
+    /// it has changed.  Failures must persist for `debounce` consecutive
+    /// readings before they're reported; recovery is immediate.
+    fn update(&mut self, rpm: Rpm, t: &FanThresholds) -> Option<FanStatus> {
+        let next = match self.judge(rpm, t) {
+            Some(next) => next,
+            None => {
+                self.bad_count = 0;
+                return self.set_status(FanStatus::Unknown);
+            }
+        };
+
+        if next.is_failed() {
+            self.bad_count = self.bad_count.saturating_add(1);
+            if self.bad_count >= t.debounce {
+                return self.set_status(next);
+            }
+            None
+        } else {
+            self.bad_count = 0;
+            self.set_status(next)
+        }
+    }
+
+    fn set_status(&mut self, status: FanStatus) -> Option<FanStatus> {
+        if status != self.status {
+            self.status = status;
+            Some(status)
+        } else {
+            None
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 /// The thermal control loop.
 ///
 /// This object uses slices of sensors and fans, which must be owned
@@ -271,6 +370,9 @@ pub(crate) struct ThermalControl<'a> {
     /// by before we return to `Normal`
     overheat_hysteresis: Celsius,
 
+    /// Whether we've asked for PROCHOT to be asserted, throttling the CPU
+    prochot: bool,
+
     /// Most recent power mode mask
     power_mode: PowerBitmask,
 
@@ -282,6 +384,19 @@ pub(crate) struct ThermalControl<'a> {
     /// `None` values in this list are ignored.
     dynamic_inputs:
         [Option<DynamicInputChannel>; bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS],
+
+    /// Per-fan health, derived from tachometer readings
+    fan_health: [FanHealth; bsp::NUM_FANS],
+
+    /// Thresholds for judging fan health, from the BSP by default but
+    /// user-modifiable
+    fan_thresholds: FanThresholds,
+
+    /// Whether the `sensors` task has any sensor in critical alarm.  This
+    /// covers sensors outside our thermal model (e.g. VR temperatures posted
+    /// by `power`), which we learn about when the alarm is raised rather
+    /// than by polling them.
+    critical_alarm: bool,
 }
 
 /// Represents the state of a temperature sensor, which either has a valid
@@ -324,6 +439,71 @@ impl TimestampedTemperatureReading {
     }
 }
 
+include!(concat!(env!("OUT_DIR"), "/thermal_config.rs"));
+
+/// A control zone: some of our temperature inputs, and the fans that cool
+/// them, with a controller of its own. Each fan is driven at the highest
+/// duty cycle asked of it by any zone that it's in; fans that aren't in any
+/// zone are driven at the highest duty cycle asked of any fan.
+pub(crate) struct Zone {
+    /// Indices into our temperature arrays, or `None` for all of them
+    inputs: Option<&'static [usize]>,
+
+    /// Indices into the BSP's fans, or `None` for all of them
+    fans: Option<&'static [usize]>,
+
+    /// Controller gains, or `None` to use our (runtime-modifiable) ones
+    pid: Option<PidConfig>,
+
+    /// Target margin, or `None` to use our (runtime-modifiable) one
+    margin: Option<f32>,
+
+    /// Largest change in output per control cycle, in percent
+    ramp_limit: Option<f32>,
+}
+
+impl Zone {
+    fn has_input(&self, index: usize) -> bool {
+        self.inputs.map_or(true, |i| i.contains(&index))
+    }
+
+    fn has_fan(&self, index: usize) -> bool {
+        self.fans.map_or(true, |f| f.contains(&index))
+    }
+}
+
+// Zones come from the task config, which can't see the BSP, so check them
+// against it here.
+const _: () = {
+    let mut z = 0;
+    while z < NUM_ZONES {
+        if let Some(inputs) = ZONES[z].inputs {
+            let mut i = 0;
+            while i < inputs.len() {
+                assert!(inputs[i] < TEMPERATURE_ARRAY_SIZE);
+                i += 1;
+            }
+        }
+        if let Some(fans) = ZONES[z].fans {
+            let mut i = 0;
+            while i < fans.len() {
+                assert!(fans[i] < bsp::NUM_FANS);
+                i += 1;
+            }
+        }
+        z += 1;
+    }
+};
+
+/// Per-zone controller state
+#[derive(Default)]
+struct ZoneState {
+    pid: OneSidedPidState,
+
+    /// Previous output, for ramp limiting
+    last: Option<f32>,
+}
+
 /// Configuration for a PID controller
 #[derive(Copy, Clone)]
 pub struct PidConfig {
@@ -413,7 +593,7 @@ enum ThermalControlState {
     /// Normal happy control loop
     Running {
         values: [TemperatureReading; TEMPERATURE_ARRAY_SIZE],
-        pid: OneSidedPidState,
+        zones: [ZoneState; NUM_ZONES],
     },
 
     /// In the overheated state, one or more components has entered their
@@ -432,6 +612,8 @@ enum ThermalControlState {
 
 enum ControlResult {
     Pwm(PWMDuty),
+    /// A duty cycle for each fan, from the zone controllers
+    Zoned([PWMDuty; bsp::NUM_FANS]),
     PowerDown,
 }
 
@@ -471,6 +653,11 @@ impl<'a> ThermalControl<'a> {
     /// Constructs a new `ThermalControl` based on a `struct Bsp`. This
     /// requires that every BSP has the same internal structure,
     pub fn new(bsp: &'a Bsp, i2c_task: TaskId, sensor_api: SensorApi) -> Self {
+        // If we're restarting, we may have left the CPU throttled.
+        if let Err(e) = bsp.set_prochot(false) {
+            ringbuf_entry!(Trace::ProchotFailed(e));
+        }
+
         Self {
             bsp,
             i2c_task,
@@ -484,9 +671,35 @@ impl<'a> ThermalControl<'a> {
             overheat_hysteresis: Celsius(1.0),
             overheat_timeout_ms: 60_000,
 
+            prochot: false,
+
             power_mode: PowerBitmask::empty(), // no sensors active
 
             dynamic_inputs: [None; bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS],
+
+            fan_health: [FanHealth::new(); bsp::NUM_FANS],
+            fan_thresholds: bsp.fan_thresholds,
+
+            critical_alarm: false,
+        }
+    }
+
+    /// Re-reads the `sensors` task's alarms, having been told that one of
+    /// them has changed.
+    pub fn update_alarms(&mut self) {
+        let mut critical = false;
+        let mut start = 0;
+        while let Ok(alarm) = self.sensor_api.next_alarm(SensorId(start)) {
+            if alarm.state == AlarmState::Critical {
+                ringbuf_entry!(Trace::CriticalAlarm(alarm.id));
+                critical = true;
+            }
+            start = alarm.id.0 + 1;
+        }
+
+        if critical != self.critical_alarm {
+            self.critical_alarm = critical;
+            ringbuf_entry!(Trace::CriticalAlarmChanged(critical));
         }
     }
 
@@ -509,10 +722,14 @@ impl<'a> ThermalControl<'a> {
 
         // If the incoming integral gain is zero, then it will never be able
         // to wind down the integral accumulator (which is pre-multiplied),
-        // so clear it here.
-        if let ThermalControlState::Running { pid, .. } = &mut self.state {
+        // so clear it here, in the zones that use these gains.
+        if let ThermalControlState::Running { zones, .. } = &mut self.state {
             if i == 0.0 {
-                pid.integral = 0.0;
+                for (z, state) in ZONES.iter().zip(zones.iter_mut()) {
+                    if z.pid.is_none() {
+                        state.pid.integral = 0.0;
+                    }
+                }
             }
         }
 
@@ -555,17 +772,55 @@ impl<'a> ThermalControl<'a> {
         ringbuf_entry!(Trace::AutoState(self.get_state()));
     }
 
+    pub fn set_fan_thresholds(
+        &mut self,
+        thresholds: FanThresholds,
+    ) -> Result<(), ThermalError> {
+        if thresholds.debounce == 0 || thresholds.undershoot_percent > 100 {
+            return Err(ThermalError::InvalidParameter);
+        }
+        self.fan_thresholds = thresholds;
+        Ok(())
+    }
+
+    pub fn fan_status(&self, fan: Fan) -> FanStatus {
+        self.fan_health[fan.0 as usize].status
+    }
+
+    /// Returns `true` if any fan has been judged to have failed
+    fn any_fan_failed(&self) -> bool {
+        self.fan_health.iter().any(|h| h.status.is_failed())
+    }
+
     /// Reads all temperature and fan RPM sensors, posting their results
-    /// to the sensors task API.
+    /// to the sensors task API.  Fan RPM readings are also used to update
+    /// our judgement of fan health.
     ///
     /// Records failed sensor reads and failed posts to the sensors task in
     /// the local ringbuf.
-    pub fn read_sensors(&self) {
+    pub fn read_sensors(&mut self) {
+        // If the fans have just had their power restored, whatever we'd
+        // concluded about them from their time without it no longer holds.
+        if self.bsp.fans_repowered() {
+            for h in self.fan_health.iter_mut() {
+                h.status = FanStatus::Unknown;
+                h.bad_count = 0;
+            }
+            ringbuf_entry!(Trace::FansRepowered);
+        }
+
         // Read fan data and log it to the sensors task
         for (index, sensor_id) in self.bsp.fans.iter().enumerate() {
             let post_result =
                 match self.bsp.fan_control(Fan::from(index)).fan_rpm() {
                     Ok(reading) => {
+                        let t = &self.fan_thresholds;
+                        let health = &mut self.fan_health[index];
+                        if let Some(status) = health.update(reading, t) {
+                            ringbuf_entry!(Trace::FanStatus(
+                                *sensor_id, status
+                            ));
+                        }
                         self.sensor_api.post_now(*sensor_id, reading.0.into())
                     }
                     Err(e) => {
@@ -602,12 +857,12 @@ impl<'a> ThermalControl<'a> {
                     Ok(v) => self.sensor_api.post_now(s.sensor.sensor_id, v.0),
                     Err(e) => {
                         // Record an error errors if the sensor is not removable
-                        // or we get a unexpected error from a removable sensor
-                        if !(s.removable
-                            && e == SensorReadError::I2cError(
-                                ResponseCode::NoDevice,
-                            ))
-                        {
+                        // or we get a unexpected error from a removable sensor.
+                        // A removable device that is absent is reported as not
+                        // present; one that is present but not yet ready (e.g.
+                        // a drive that was just inserted) is reported as
+                        // unavailable.
+                        if !(s.removable && e.is_absent()) {
                             ringbuf_entry!(Trace::SensorReadFailed(
                                 s.sensor.sensor_id,
                                 e
@@ -661,6 +916,80 @@ impl<'a> ThermalControl<'a> {
             .filter_map(|(v, model)| model.map(|t| (v, t)))
     }
 
+    /// Runs each zone's controller, returning the duty cycle for each fan.
+    ///
+    /// A zone whose inputs have gone quiet for too long, or whose controller
+    /// has come up with nonsense, runs its fans flat out.
+    fn run_zones(
+        zones: &mut [ZoneState; NUM_ZONES],
+        values: &[TemperatureReading; TEMPERATURE_ARRAY_SIZE],
+        (static_inputs, dynamic_inputs): (
+            &[InputChannel],
+            &[Option<DynamicInputChannel>],
+        ),
+        now_ms: u64,
+        pid_config: &PidConfig,
+        target_margin: Celsius,
+    ) -> [PWMDuty; bsp::NUM_FANS] {
+        let mut pwm = [None; bsp::NUM_FANS];
+        let mut max_pwm = 0.0f32;
+
+        for (index, (zone, state)) in ZONES.iter().zip(zones).enumerate() {
+            let models = static_inputs
+                .iter()
+                .map(|i| Some(i.model))
+                .chain(dynamic_inputs.iter().map(|i| i.map(|i| i.model)));
+
+            // Remember, positive margin means that all parts are happily
+            // below their max temperature; negative means someone is
+            // overheating.  We want to pick the _smallest_ margin, since
+            // that's the part which is most overheated.
+            let mut worst_margin = f32::MAX;
+            let mut stale = false;
+            for (i, (v, model)) in values.iter().zip(models).enumerate() {
+                let (TemperatureReading::Valid(v), Some(model)) = (v, model)
+                else {
+                    continue;
+                };
+                if !zone.has_input(i) {
+                    continue;
+                }
+                stale |= now_ms.saturating_sub(v.time_ms) > SENSOR_TIMEOUT_MS;
+                let temperature = v.worst_case(now_ms, &model);
+                worst_margin = worst_margin.min(model.margin(temperature).0);
+            }
+
+            let cfg = zone.pid.as_ref().unwrap_or(pid_config);
+            let margin = zone.margin.unwrap_or(target_margin.0);
+            let out = state.pid.run(cfg, margin - worst_margin, 100.0);
+            let out = if stale || !out.is_finite() {
+                ringbuf_entry!(Trace::ZoneFailsafe(index as u8));
+                if !out.is_finite() {
+                    // Don't let a NaN live on in the integral term.
+                    state.pid = OneSidedPidState::default();
+                }
+                100.0
+            } else {
+                match (zone.ramp_limit, state.last) {
+                    (Some(limit), Some(last)) => {
+                        out.clamp(last - limit, last + limit)
+                    }
+                    _ => out,
+                }
+            };
+            state.last = Some(out);
+
+            for (f, p) in pwm.iter_mut().enumerate() {
+                if zone.has_fan(f) {
+                    *p = Some(p.map_or(out, |p: f32| p.max(out)));
+                }
+            }
+            max_pwm = max_pwm.max(out);
+        }
+
+        pwm.map(|p| PWMDuty(p.unwrap_or(max_pwm) as u8))
+    }
+
     /// An extremely simple thermal control loop.
     ///
     /// Returns an error if the control loop failed to read critical sensors;
@@ -693,9 +1022,13 @@ impl<'a> ThermalControl<'a> {
                     Ok(r) => {
                         self.state.write_temperature(i, r);
                     }
-                    Err(SensorError::NotPresent) if s.removable => {
+                    Err(
+                        SensorError::NotPresent
+                        | SensorError::DeviceUnavailable,
+                    ) if s.removable => {
                         // Ignore errors if the sensor is removable and the
-                        // error indicates that it's not present.
+                        // error indicates that it's not present (or not yet
+                        // ready), so that it doesn't hold up the control loop.
                         self.state.write_temperature_inactive(i);
                     }
                     Err(_) => (),
@@ -730,15 +1063,12 @@ impl<'a> ThermalControl<'a> {
             ThermalControlState::Boot { values } => {
                 let mut all_some = true;
                 let mut any_power_down = false;
-                let mut worst_margin = f32::MAX;
                 for (v, model) in Self::zip_temperatures(values, inputs) {
                     match v {
                         Some(TemperatureReading::Valid(v)) => {
                             let temperature = v.worst_case(now_ms, &model);
                             any_power_down |=
                                 model.should_power_down(temperature);
-                            worst_margin =
-                                worst_margin.min(model.margin(temperature).0);
                         }
                         Some(TemperatureReading::Inactive) => {
                             // Inactive sensors are ignored, but do not gate us
@@ -756,41 +1086,35 @@ impl<'a> ThermalControl<'a> {
                     ControlResult::PowerDown
                 } else if all_some {
                     // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
-                    let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
+                    // iteration of the zone controllers.
+                    let values = values.map(Option::unwrap);
+                    let mut zones =
+                        core::array::from_fn(|_| ZoneState::default());
+                    let pwm = Self::run_zones(
+                        &mut zones,
+                        &values,
+                        inputs,
+                        now_ms,
                         &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
+                        self.target_margin,
                     );
-                    self.state = ThermalControlState::Running {
-                        values: values.map(Option::unwrap),
-                        pid,
-                    };
+                    self.state = ThermalControlState::Running { values, zones };
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
+                    ControlResult::Zoned(pwm)
                 } else {
                     ControlResult::Pwm(PWMDuty(100))
                 }
             }
-            ThermalControlState::Running { values, pid } => {
+            ThermalControlState::Running { values, zones } => {
                 let mut any_power_down = false;
                 let mut any_critical = false;
-                let mut worst_margin = f32::MAX;
 
-                // Remember, positive margin means that all parts are happily
-                // below their max temperature; negative means someone is
-                // overheating.  We want to pick the _smallest_ margin, since
-                // that's the part which is most overheated.
                 for (v, model) in Self::zip_temperatures(values, inputs) {
                     if let TemperatureReading::Valid(v) = v {
                         let temperature = v.worst_case(now_ms, &model);
                         any_power_down |= model.should_power_down(temperature);
                         any_critical |= model.is_critical(temperature);
-
-                        worst_margin =
-                            worst_margin.min(model.margin(temperature).0);
                     }
                 }
 
@@ -806,29 +1130,34 @@ impl<'a> ThermalControl<'a> {
                     };
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
+                    ControlResult::Pwm(PWMDuty(100))
+                } else if self.critical_alarm {
+                    // Something outside our model is running hot; we can't
+                    // regulate it, so cool everything as hard as we can.
                     ControlResult::Pwm(PWMDuty(100))
                 } else {
-                    // We adjust the worst component margin by our target
-                    // margin, which must be > 0.  This effectively tells the
-                    // control loop to overcool the system.
+                    // Each zone adjusts its worst component margin by its
+                    // target margin, which must be > 0.  This effectively
+                    // tells the control loop to overcool the system.
                     //
                     // `PidControl::run` expects the sign of the input and
-                    // output to match, so we negate things here: if the worst
+                    // output to match, so the zones negate things: if the worst
                     // margin is negative (i.e. the system is overheating), then
                     // the input to `run` is positive, because we want a
                     // positive fan speed.
-                    let pwm = pid.run(
+                    ControlResult::Zoned(Self::run_zones(
+                        zones,
+                        values,
+                        inputs,
+                        now_ms,
                         &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
-                    );
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
+                        self.target_margin,
+                    ))
                 }
             }
             ThermalControlState::Overheated { values, start_time } => {
                 let mut all_subcritical = true;
                 let mut any_power_down = false;
-                let mut worst_margin = f32::MAX;
 
                 for (v, model) in Self::zip_temperatures(values, inputs) {
                     if let TemperatureReading::Valid(v) = v {
@@ -838,8 +1167,6 @@ impl<'a> ThermalControl<'a> {
                             self.overheat_hysteresis,
                         );
                         any_power_down |= model.should_power_down(temperature);
-                        worst_margin =
-                            worst_margin.min(model.margin(temperature).0);
                     }
                 }
 
@@ -850,20 +1177,22 @@ impl<'a> ThermalControl<'a> {
                     ControlResult::PowerDown
                 } else if all_subcritical {
                     // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
-                    let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
+                    // iteration of the zone controllers.
+                    let values = *values;
+                    let mut zones =
+                        core::array::from_fn(|_| ZoneState::default());
+                    let pwm = Self::run_zones(
+                        &mut zones,
+                        &values,
+                        inputs,
+                        now_ms,
                         &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
+                        self.target_margin,
                     );
-                    self.state = ThermalControlState::Running {
-                        values: *values,
-                        pid,
-                    };
+                    self.state = ThermalControlState::Running { values, zones };
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
+                    ControlResult::Zoned(pwm)
                 } else if now_ms > *start_time + self.overheat_timeout_ms {
                     // If blasting the fans hasn't cooled us down in this amount
                     // of time, then something is terribly wrong - abort!
@@ -878,12 +1207,37 @@ impl<'a> ThermalControl<'a> {
             ThermalControlState::Uncontrollable => ControlResult::PowerDown,
         };
 
+        self.update_prochot(now_ms);
+
+        // If a fan has failed, the remaining fans have to make up for it; run
+        // them flat out rather than trusting the PID loop, which is tuned for
+        // a full complement of fans.
+        let control_result = match control_result {
+            ControlResult::Pwm(..) | ControlResult::Zoned(..)
+                if self.any_fan_failed() =>
+            {
+                ControlResult::Pwm(PWMDuty(100))
+            }
+            r => r,
+        };
+
         match control_result {
             ControlResult::Pwm(target_pwm) => {
                 // Send the new RPM to all of our fans
                 ringbuf_entry!(Trace::ControlPwm(target_pwm.0));
                 self.set_pwm(target_pwm)?;
             }
+            ControlResult::Zoned(pwm) => {
+                let max = pwm.iter().map(|p| p.0).max().unwrap_or(0);
+                ringbuf_entry!(Trace::ControlPwm(max));
+                let mut last_err = Ok(());
+                for (index, p) in pwm.into_iter().enumerate() {
+                    if let Err(e) = self.set_fan_pwm(Fan::from(index), p) {
+                        last_err = Err(e);
+                    }
+                }
+                last_err.map_err(|_| ThermalError::DeviceError)?;
+            }
             ControlResult::PowerDown => {
                 if let Err(e) = self.bsp.power_down() {
                     ringbuf_entry!(Trace::PowerDownFailed(e));
@@ -895,18 +1249,67 @@ impl<'a> ThermalControl<'a> {
         Ok(())
     }
 
+    /// Decides whether the CPU should be throttled.
+    ///
+    /// We assert PROCHOT as soon as anything goes critical, to give the fans
+    /// a chance to catch up before we have to power down, and deassert it
+    /// only once everything has cooled by `PROCHOT_HYSTERESIS`; this is
+    /// further than it takes to leave `Overheated`, so that the sudden
+    /// drop in power when throttling doesn't have us flapping in and out.
+    fn update_prochot(&mut self, now_ms: u64) {
+        let inputs = (self.bsp.inputs, self.dynamic_inputs.as_slice());
+        let asserted = match &self.state {
+            ThermalControlState::Overheated { .. } => true,
+            ThermalControlState::Running { values, .. } if self.prochot => {
+                let mut all_cool = true;
+                for (v, model) in Self::zip_temperatures(values, inputs) {
+                    if let TemperatureReading::Valid(v) = v {
+                        let temperature = v.worst_case(now_ms, &model);
+                        all_cool &= model
+                            .is_sub_critical(temperature, PROCHOT_HYSTERESIS);
+                    }
+                }
+                !all_cool
+            }
+            ThermalControlState::Running { .. } => false,
+
+            // Keep whatever we had until every sensor has reported in.
+            ThermalControlState::Boot { .. } => self.prochot,
+
+            // We're powering down, so there's nothing left to throttle.
+            ThermalControlState::Uncontrollable => false,
+        };
+        self.set_prochot(asserted);
+    }
+
+    fn set_prochot(&mut self, asserted: bool) {
+        if asserted == self.prochot {
+            return;
+        }
+        ringbuf_entry!(Trace::Prochot(asserted));
+        match self.bsp.set_prochot(asserted) {
+            Ok(()) => self.prochot = asserted,
+            // We'll try again next time around.
+            Err(e) => ringbuf_entry!(Trace::ProchotFailed(e)),
+        }
+    }
+
+    /// Stops throttling the CPU, e.g. when leaving automatic control.
+    pub fn release_prochot(&mut self) {
+        self.set_prochot(false);
+    }
+
     /// Attempts to set the PWM duty cycle of every fan in this group.
     ///
     /// Returns the last error if one occurred, but does not short circuit
     /// (i.e. attempts to set *all* fan duty cycles, even if one fails)
-    pub fn set_pwm(&self, pwm: PWMDuty) -> Result<(), ThermalError> {
+    pub fn set_pwm(&mut self, pwm: PWMDuty) -> Result<(), ThermalError> {
         if pwm.0 > 100 {
             return Err(ThermalError::InvalidPWM);
         }
         let mut last_err = Ok(());
-        for (index, _sensor_id) in self.bsp.fans.iter().enumerate() {
-            if let Err(e) = self.bsp.fan_control(Fan::from(index)).set_pwm(pwm)
-            {
+        for index in 0..self.bsp.fans.len() {
+            if let Err(e) = self.set_fan_pwm(Fan::from(index), pwm) {
                 last_err = Err(e);
             }
         }
@@ -915,11 +1318,22 @@ impl<'a> ThermalControl<'a> {
 
     /// Sets the PWM for a single fan
     pub fn set_fan_pwm(
-        &self,
+        &mut self,
         fan: Fan,
         pwm: PWMDuty,
     ) -> Result<(), ResponseCode> {
-        self.bsp.fan_control(fan).set_pwm(pwm)
+        let health = &mut self.fan_health[fan.0 as usize];
+        match self.bsp.fan_control(fan).set_pwm(pwm) {
+            Ok(()) => {
+                health.pwm = Some(pwm);
+                Ok(())
+            }
+            Err(e) => {
+                // We no longer know what the fan is being driven at
+                health.pwm = None;
+                Err(e)
+            }
+        }
     }
 
     pub fn fan(&self, index: u8) -> Option<Fan> {
@@ -996,3 +1410,150 @@ impl<'a> ThermalControl<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use drv_i2c_api::{Controller, I2cDevice, PortIndex};
+    use drv_i2c_devices::max31790::{self, MAX_FANS};
+    use drv_mock_i2c::{Bus, Registers};
+    use userlib::sim::{task_id, Sim};
+
+    const SENSOR_ADDRESS: u8 = 0x48;
+    const FANS_ADDRESS: u8 = 0x20;
+
+    fn sensor(task: TaskId) -> I2cDevice {
+        I2cDevice::new(
+            task,
+            Controller::I2C2,
+            PortIndex(0),
+            None,
+            SENSOR_ADDRESS,
+        )
+    }
+
+    fn fans(task: TaskId) -> I2cDevice {
+        I2cDevice::new(task, Controller::I2C2, PortIndex(0), None, FANS_ADDRESS)
+    }
+
+    const MODEL: ThermalProperties = ThermalProperties {
+        target_temperature: Celsius(80.0),
+        critical_temperature: Celsius(90.0),
+        power_down_temperature: Celsius(100.0),
+        temperature_slew_deg_per_sec: 0.5,
+    };
+
+    /// Every input is the one TMP117, so that every zone sees the same
+    /// temperature, however the app lays them out.
+    const INPUT: InputChannel = InputChannel::new(
+        TemperatureSensor::new(Device::Tmp117, sensor, SensorId(0)),
+        MODEL,
+        PowerBitmask::empty(),
+        false,
+    );
+
+    /// The duty cycle, out of 511, that fan `fan` of a modelled MAX31790 has
+    /// been set to.
+    fn duty(fans: &Registers, fan: usize) -> u16 {
+        let reg = max31790::Register::PWMOut1TargetDutyCycleMSB as usize;
+        let msb = fans.regs[reg + fan * 2];
+        let lsb = fans.regs[reg + fan * 2 + 1];
+        u16::from_be_bytes([msb, lsb]) >> 7
+    }
+
+    /// Reads a TMP117 and drives the fans of a MAX31790, both modelled on the
+    /// mock I2C server, through the zone controllers: the fans should follow
+    /// the temperature, and run flat out if the readings go stale.
+    #[test]
+    fn fans_follow_temperature() {
+        let bus = Bus::default();
+        let server = bus.clone();
+        Sim::new()
+            .task("i2c_driver", 1, move || drv_mock_i2c::serve(server.clone()))
+            .run("test", 2, || {
+                let i2c = task_id("i2c_driver");
+                let tmp117 = sensor(i2c);
+                let tmp117 = bus.attach(
+                    tmp117.controller,
+                    tmp117.port,
+                    tmp117.segment,
+                    SENSOR_ADDRESS,
+                    Registers::default(),
+                );
+                let max31790 = fans(i2c);
+                let regs = bus.attach(
+                    max31790.controller,
+                    max31790.port,
+                    max31790.segment,
+                    FANS_ADDRESS,
+                    Registers::default(),
+                );
+                let fctrl = Max31790::new(&max31790);
+                fctrl.initialize().unwrap();
+
+                let inputs = [INPUT; bsp::NUM_TEMPERATURE_INPUTS];
+                let dynamic = [Some(DynamicInputChannel { model: MODEL });
+                    bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS];
+                let pid = PidConfig {
+                    zero: 35.0,
+                    gain_p: 1.75,
+                    gain_i: 0.0135,
+                    gain_d: 0.4,
+                };
+                let mut zones = core::array::from_fn(|_| ZoneState::default());
+                let num_fans = bsp::NUM_FANS.min(usize::from(MAX_FANS));
+
+                // Runs a control cycle at `now_ms`, with the sensor reading
+                // `celsius` as of `read_ms`, returning the duty cycle the
+                // fans were set to.
+                let mut cycle = |celsius: f32, read_ms: u64, now_ms: u64| {
+                    let raw = ((celsius * 128.0) as i16).to_be_bytes();
+                    tmp117.lock().unwrap().regs[..2].copy_from_slice(&raw);
+                    let value = INPUT.sensor.read_temp(i2c).unwrap();
+                    assert_eq!(value.0, celsius);
+
+                    let reading = TemperatureReading::Valid(
+                        TimestampedTemperatureReading {
+                            time_ms: read_ms,
+                            value,
+                        },
+                    );
+                    let pwm = ThermalControl::run_zones(
+                        &mut zones,
+                        &[reading; TEMPERATURE_ARRAY_SIZE],
+                        (&inputs, &dynamic),
+                        now_ms,
+                        &pid,
+                        Celsius(0.0),
+                    );
+                    for (fan, p) in pwm.iter().enumerate().take(num_fans) {
+                        let fan = max31790::Fan::try_from(fan as u8).unwrap();
+                        FanControl::Max31790(&fctrl, fan).set_pwm(*p).unwrap();
+                    }
+
+                    let regs = regs.lock().unwrap();
+                    let set = duty(&regs, 0);
+                    for fan in 1..num_fans {
+                        assert_eq!(duty(&regs, fan), set, "fan {fan}");
+                    }
+                    set
+                };
+
+                // Well under target, the fans are off; at target and above,
+                // they run harder the hotter it gets.
+                let cool = cycle(60.0, 0, 0);
+                let warm = cycle(80.0, 1000, 1000);
+                let hot = cycle(88.0, 2000, 2000);
+                assert_eq!(cool, 0);
+                assert!(warm > cool, "{warm} at target");
+                assert!(hot > warm, "{hot} over target");
+                assert!(hot < 511, "{hot} over target");
+
+                // Without a fresh reading, the zone fails safe.
+                let stale = SENSOR_TIMEOUT_MS + 1;
+                assert_eq!(cycle(60.0, 0, 3000 + stale), 511);
+            });
+    }
+}
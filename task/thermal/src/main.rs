@@ -10,7 +10,7 @@
 //!
 
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 
 #[cfg_attr(
     any(
@@ -38,7 +38,8 @@ use idol_runtime::{NotificationHandler, RequestError};
 use ringbuf::*;
 use task_sensor_api::{Sensor as SensorApi, SensorError, SensorId};
 use task_thermal_api::{
-    ThermalAutoState, ThermalError, ThermalMode, ThermalProperties,
+    FanStatus, FanThresholds, ThermalAutoState, ThermalError, ThermalMode,
+    ThermalProperties,
 };
 use userlib::units::PWMDuty;
 use userlib::*;
@@ -64,13 +65,20 @@ enum Trace {
     ThermalMode(ThermalMode),
     AutoState(ThermalAutoState),
     FanReadFailed(SensorId, ResponseCode),
+    FanStatus(SensorId, FanStatus),
     MiscReadFailed(SensorId, SensorReadError),
     SensorReadFailed(SensorId, SensorReadError),
     PostFailed(SensorId, SensorError),
     ControlPwm(u8),
+    ZoneFailsafe(u8),
     PowerModeChanged(PowerBitmask),
     PowerDownFailed(SeqError),
+    Prochot(bool),
+    ProchotFailed(SeqError),
     ControlError(ThermalError),
+    FansRepowered,
+    CriticalAlarm(SensorId),
+    CriticalAlarmChanged(bool),
 }
 ringbuf!(Trace, 32, Trace::None);
 
@@ -96,6 +104,7 @@ impl<'a> ServerImpl<'a> {
         initial_pwm: PWMDuty,
     ) -> Result<(), ThermalError> {
         self.set_mode(ThermalMode::Manual);
+        self.control.release_prochot();
         self.control.set_pwm(initial_pwm)
     }
 
@@ -269,6 +278,27 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
             .map_err(RequestError::from)
     }
 
+    fn get_fan_status(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<FanStatus, RequestError<ThermalError>> {
+        match self.control.fan(index) {
+            Some(fan) => Ok(self.control.fan_status(fan)),
+            None => Err(ThermalError::InvalidFan.into()),
+        }
+    }
+
+    fn set_fan_thresholds(
+        &mut self,
+        _: &RecvMessage,
+        thresholds: FanThresholds,
+    ) -> Result<(), RequestError<ThermalError>> {
+        self.control
+            .set_fan_thresholds(thresholds)
+            .map_err(RequestError::from)
+    }
+
     fn get_runtime(
         &mut self,
         _: &RecvMessage,
@@ -279,11 +309,22 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
 
 impl<'a> NotificationHandler for ServerImpl<'a> {
     fn current_notification_mask(&self) -> u32 {
-        notifications::TIMER_MASK
+        notifications::TIMER_MASK | notifications::SENSOR_ALARM_MASK
     }
 
-    fn handle_notification(&mut self, _bits: u32) {
+    fn handle_notification(&mut self, bits: u32) {
         let now = sys_get_timer().now;
+        if bits & notifications::SENSOR_ALARM_MASK != 0 {
+            self.control.update_alarms();
+
+            // Don't wait for the next tick to act on it: an alarm going
+            // critical pins the fans at full speed.
+            if self.mode == ThermalMode::Auto {
+                if let Err(e) = self.control.run_control() {
+                    ringbuf_entry!(Trace::ControlError(e));
+                }
+            }
+        }
         if now >= self.deadline {
             // We *always* read sensor data, which does not touch the control
             // loop; this simply posts results to the `sensors` task.
@@ -317,7 +358,7 @@ impl<'a> NotificationHandler for ServerImpl<'a> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[export_name = "main"]
+#[cfg_attr(not(test), export_name = "main")]
 fn main() -> ! {
     let i2c_task = I2C.get_task_id();
     let sensor_api = SensorApi::from(SENSOR.get_task_id());
@@ -363,7 +404,8 @@ fn main() -> ! {
 
 mod idl {
     use super::{
-        ThermalAutoState, ThermalError, ThermalMode, ThermalProperties,
+        FanStatus, FanThresholds, ThermalAutoState, ThermalError, ThermalMode,
+        ThermalProperties,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -7,7 +7,7 @@ use std::io::Write;
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     write_pub_device_descriptions()?;
 
-    idol::client::build_client_stub(
+    build_util::idol::build_client_stub(
         "../../idl/validate.idol",
         "client_stub.rs",
     )?;
@@ -34,6 +34,11 @@ fn write_pub_device_descriptions(
         writeln!(file, "    DeviceDescription {{")?;
         writeln!(file, "        device: {:?},", dev.device)?;
         writeln!(file, "        description: {:?},", dev.description)?;
+        writeln!(file, "        alias: {:?},", dev.alias)?;
+        match dev.revs {
+            Some(revs) => writeln!(file, "        revs: Some(&{revs:?}),")?,
+            None => writeln!(file, "        revs: None,")?,
+        }
         writeln!(file, "        sensors: &[")?;
         for s in dev.sensors {
             writeln!(file, "            SensorDescription {{")?;
@@ -17,7 +17,18 @@ pub use drv_i2c_api::Mux;
 pub use drv_i2c_api::Segment;
 pub use task_sensor_api::SensorId;
 
-#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    IdolError,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+)]
 pub enum ValidateError {
     InvalidDevice = 1,
     BadValidation,
@@ -46,6 +57,8 @@ impl From<ResponseCode> for ValidateError {
 pub enum ValidateOk {
     Present = 1,
     Validated = 2,
+    /// The device is absent, as expected (e.g., it isn't fitted on this
+    /// board revision).
     Removed = 3,
 }
 
@@ -55,6 +68,77 @@ pub struct MuxSegment {
     pub segment: Segment,
 }
 
+/// An item on the board's validation checklist, which is generated from the
+/// `validate` task's config.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum Check {
+    /// The I2C device with this index (as for `validate_i2c`) responds, and
+    /// has the identity we expect of it, if its driver knows how to check.
+    I2cDevice(u32),
+    /// The ident of an FPGA's user design, read from `addr`, has this ID.
+    FpgaIdent { device: u8, addr: u16, id: u32 },
+    /// The local VPD is intact, going by its checksums.
+    VpdChecksum,
+    /// The most recent voltage reading of a rail is in this range.
+    RailVoltage {
+        sensor: SensorId,
+        min: f32,
+        max: f32,
+    },
+}
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum CheckOutcome {
+    /// The check passed.
+    Pass,
+    /// The device responded, but we don't know how to check its identity.
+    Present,
+    /// The check couldn't be made.
+    Error(ValidateError),
+    /// The device isn't what we expected; we include what it claims to be,
+    /// if we know.
+    BadIdentity(Option<u32>),
+    /// The data failed its checksum.
+    BadChecksum,
+    /// The reading was out of range; we include it.
+    OutOfRange(f32),
+    /// The device isn't there, as expected.
+    Absent,
+}
+
+impl CheckOutcome {
+    pub fn is_failure(&self) -> bool {
+        !matches!(
+            self,
+            CheckOutcome::Pass | CheckOutcome::Present | CheckOutcome::Absent
+        )
+    }
+}
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct CheckResult {
+    pub check: Check,
+    pub outcome: CheckOutcome,
+}
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct ChecklistSummary {
+    pub passed: u32,
+    /// Devices that responded, but whose identity we couldn't check.
+    pub present: u32,
+    pub failed: u32,
+    /// Index of the first item that failed, if any did.
+    pub first_failure: Option<u32>,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -79,6 +163,9 @@ pub struct SensorDescription {
 pub struct DeviceDescription {
     pub device: &'static str,
     pub description: &'static str,
+    pub alias: Option<&'static str>,
+    /// Board revisions on which the device is fitted, if not all of them
+    pub revs: Option<&'static [u32]>,
     pub sensors: &'static [SensorDescription],
 }
 
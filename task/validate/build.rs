@@ -2,15 +2,153 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::io::Write;
+
+/// The checklist to validate the board against. By default, that's just
+/// every I2C device.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    /// Check every I2C device
+    #[serde(default = "TaskConfig::default_i2c")]
+    i2c: bool,
+    /// FPGA user designs whose idents to check (needs the `fpga` feature)
+    #[serde(default)]
+    fpga: Vec<FpgaIdent>,
+    /// Check the local VPD (needs the `vpd` feature)
+    #[serde(default)]
+    vpd: bool,
+    /// Rails whose voltages to check (needs the `sensor` feature)
+    #[serde(default)]
+    rails: Vec<RailVoltage>,
+}
+
+impl TaskConfig {
+    fn default_i2c() -> bool {
+        true
+    }
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        Self {
+            i2c: Self::default_i2c(),
+            fpga: vec![],
+            vpd: false,
+            rails: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct FpgaIdent {
+    device: u8,
+    addr: u16,
+    id: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RailVoltage {
+    /// Name of the rail, as given in the I2C device's `power.rails`
+    rail: String,
+    min: f32,
+    max: f32,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_i2c::codegen(build_i2c::Disposition::Validation)?;
 
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../../idl/validate.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
     )?;
 
+    write_checklist()?;
+
+    Ok(())
+}
+
+fn write_checklist() -> anyhow::Result<()> {
+    let config =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+    let feature = |f: &str| {
+        std::env::var(format!("CARGO_FEATURE_{}", f.to_uppercase())).is_ok()
+    };
+
+    if !config.fpga.is_empty() && !feature("fpga") {
+        bail!("FPGA idents can only be checked with the `fpga` feature");
+    }
+    if config.vpd && !feature("vpd") {
+        bail!("VPD can only be checked with the `vpd` feature");
+    }
+    if !config.rails.is_empty() && !feature("sensor") {
+        bail!("rail voltages can only be checked with the `sensor` feature");
+    }
+    if build_i2c::has_board_rev() != feature("board-rev") {
+        bail!(
+            "the `board-rev` feature is needed if (and only if) the I2C \
+             config has a board-rev strap"
+        );
+    }
+
+    let devices = build_i2c::device_descriptions().collect::<Vec<_>>();
+    let mut checks = vec![];
+
+    if config.i2c {
+        checks.extend((0..devices.len()).map(|i| format!("I2cDevice({i})")));
+    }
+
+    for f in &config.fpga {
+        checks.push(format!(
+            "FpgaIdent {{ device: {}, addr: {:#x}, id: {:#x} }}",
+            f.device, f.addr, f.id
+        ));
+    }
+
+    if config.vpd {
+        checks.push("VpdChecksum".to_string());
+    }
+
+    for r in &config.rails {
+        if r.min > r.max {
+            bail!("rail {}: min must not be more than max", r.rail);
+        }
+        let mut sensors =
+            devices.iter().flat_map(|d| d.sensors.iter()).filter(|s| {
+                s.kind == build_i2c::Sensor::Voltage
+                    && s.name.as_deref() == Some(r.rail.as_str())
+            });
+        let sensor = sensors.next().with_context(|| {
+            format!("no voltage sensor for rail {}", r.rail)
+        })?;
+        if sensors.next().is_some() {
+            bail!("more than one voltage sensor for rail {}", r.rail);
+        }
+        checks.push(format!(
+            "RailVoltage {{ sensor: SensorId({}), min: {:?}, max: {:?} }}",
+            sensor.id, r.min, r.max
+        ));
+    }
+
+    let out_dir = std::env::var("OUT_DIR")?;
+    let dest_path = std::path::Path::new(&out_dir).join("checklist.rs");
+    let mut file = std::fs::File::create(dest_path)?;
+
+    writeln!(
+        file,
+        "pub(crate) const CHECKLIST: [Check; {}] = [",
+        checks.len()
+    )?;
+    for c in checks {
+        writeln!(file, "    Check::{c},")?;
+    }
+    writeln!(file, "];")?;
+
     Ok(())
 }
@@ -0,0 +1,106 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The board's validation checklist, which the build generates from our
+//! config (by default, every I2C device), and the checks themselves.
+
+use crate::Trace;
+use ringbuf::*;
+use task_sensor_api::SensorId;
+use task_validate_api::{Check, CheckOutcome, ValidateError, ValidateOk};
+#[allow(unused_imports)]
+use userlib::*;
+
+include!(concat!(env!("OUT_DIR"), "/checklist.rs"));
+
+#[cfg(feature = "fpga")]
+task_slot!(FPGA, fpga);
+
+#[cfg(feature = "sensor")]
+task_slot!(SENSOR, sensor);
+
+/// Checks item `index` of the checklist, which is `check`.
+pub(crate) fn run(index: u32, check: &Check) -> CheckOutcome {
+    let outcome = match *check {
+        Check::I2cDevice(device) => {
+            match crate::validate_i2c(device as usize) {
+                Ok(ValidateOk::Validated) => CheckOutcome::Pass,
+                Ok(ValidateOk::Removed) => CheckOutcome::Absent,
+                Ok(_) => CheckOutcome::Present,
+                Err(ValidateError::BadValidation) => {
+                    CheckOutcome::BadIdentity(None)
+                }
+                Err(e) => CheckOutcome::Error(e),
+            }
+        }
+        Check::FpgaIdent { device, addr, id } => fpga_ident(device, addr, id),
+        Check::VpdChecksum => vpd_checksum(),
+        Check::RailVoltage { sensor, min, max } => {
+            rail_voltage(sensor, min, max)
+        }
+    };
+
+    if outcome.is_failure() {
+        ringbuf_entry_root!(Trace::CheckFailed(index));
+    }
+    outcome
+}
+
+#[cfg(feature = "fpga")]
+fn fpga_ident(device: u8, addr: u16, id: u32) -> CheckOutcome {
+    use drv_fpga_api::{FpgaUserDesign, FpgaUserDesignIdent};
+
+    let design = FpgaUserDesign::new(FPGA.get_task_id(), device);
+    match design.read::<FpgaUserDesignIdent>(addr) {
+        Ok(ident) if ident.id.get() == id => CheckOutcome::Pass,
+        Ok(ident) => CheckOutcome::BadIdentity(Some(ident.id.get())),
+        Err(_) => CheckOutcome::Error(ValidateError::DeviceError),
+    }
+}
+
+#[cfg(not(feature = "fpga"))]
+fn fpga_ident(_device: u8, _addr: u16, _id: u32) -> CheckOutcome {
+    // The build doesn't put these on the checklist without the feature.
+    CheckOutcome::Error(ValidateError::Unavailable)
+}
+
+#[cfg(feature = "vpd")]
+fn vpd_checksum() -> CheckOutcome {
+    use drv_local_vpd::LocalVpdError;
+
+    match drv_local_vpd::check(crate::I2C.get_task_id()) {
+        Ok(()) => CheckOutcome::Pass,
+        Err(LocalVpdError::DeviceError) => {
+            CheckOutcome::Error(ValidateError::DeviceError)
+        }
+        Err(LocalVpdError::NoRootChunk | LocalVpdError::NoSuchChunk) => {
+            CheckOutcome::Error(ValidateError::NotPresent)
+        }
+        Err(
+            LocalVpdError::InvalidChecksum
+            | LocalVpdError::InvalidChunkSize
+            | LocalVpdError::BadRootChunk,
+        ) => CheckOutcome::BadChecksum,
+    }
+}
+
+#[cfg(not(feature = "vpd"))]
+fn vpd_checksum() -> CheckOutcome {
+    CheckOutcome::Error(ValidateError::Unavailable)
+}
+
+#[cfg(feature = "sensor")]
+fn rail_voltage(sensor: SensorId, min: f32, max: f32) -> CheckOutcome {
+    let sensor_api = task_sensor_api::Sensor::from(SENSOR.get_task_id());
+    match sensor_api.get(sensor) {
+        Ok(v) if (min..=max).contains(&v) => CheckOutcome::Pass,
+        Ok(v) => CheckOutcome::OutOfRange(v),
+        Err(_) => CheckOutcome::Error(ValidateError::Unavailable),
+    }
+}
+
+#[cfg(not(feature = "sensor"))]
+fn rail_voltage(_sensor: SensorId, _min: f32, _max: f32) -> CheckOutcome {
+    CheckOutcome::Error(ValidateError::Unavailable)
+}
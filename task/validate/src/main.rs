@@ -9,9 +9,14 @@
 
 use idol_runtime::RequestError;
 use ringbuf::*;
-use task_validate_api::{MuxSegment, ValidateError, ValidateOk};
+use task_validate_api::{
+    Check, CheckOutcome, CheckResult, ChecklistSummary, MuxSegment,
+    ValidateError, ValidateOk,
+};
 use userlib::*;
 
+mod checklist;
+
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 struct ServerImpl;
@@ -20,6 +25,7 @@ struct ServerImpl;
 enum Trace {
     Validate(usize),
     ValidateFailure(drv_i2c_api::ResponseCode),
+    CheckFailed(u32),
     None,
 }
 
@@ -27,29 +33,21 @@ ringbuf!(Trace, 64, Trace::None);
 
 task_slot!(I2C, i2c_driver);
 
+#[cfg(feature = "board-rev")]
+task_slot!(SYS, sys);
+
+/// The board revision, read from its strap when we start
+#[cfg(feature = "board-rev")]
+static BOARD_REV: core::sync::atomic::AtomicU32 =
+    core::sync::atomic::AtomicU32::new(0);
+
 impl idl::InOrderValidateImpl for ServerImpl {
     fn validate_i2c(
         &mut self,
         _: &RecvMessage,
         index: u32,
     ) -> Result<ValidateOk, RequestError<ValidateError>> {
-        use i2c_config::validation::I2cValidation;
-
-        let index = index as usize;
-        ringbuf_entry!(Trace::Validate(index));
-
-        match i2c_config::validation::validate(I2C.get_task_id(), index) {
-            Err(err) => {
-                ringbuf_entry!(Trace::ValidateFailure(err));
-                let err: ValidateError = err.into();
-                Err(err.into())
-            }
-            Ok(ok) => match ok {
-                I2cValidation::RawReadOk => Ok(ValidateOk::Present),
-                I2cValidation::Good => Ok(ValidateOk::Validated),
-                I2cValidation::Bad => Err(ValidateError::BadValidation.into()),
-            },
-        }
+        validate_i2c(index as usize).map_err(RequestError::from)
     }
 
     //
@@ -79,10 +77,102 @@ impl idl::InOrderValidateImpl for ServerImpl {
             }
         }
     }
+
+    fn checklist_len(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<ValidateError>> {
+        Ok(checklist::CHECKLIST.len() as u32)
+    }
+
+    fn checklist_item(
+        &mut self,
+        _: &RecvMessage,
+        index: u32,
+    ) -> Result<Check, RequestError<ValidateError>> {
+        checklist::CHECKLIST
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| ValidateError::InvalidDevice.into())
+    }
+
+    fn run_check(
+        &mut self,
+        _: &RecvMessage,
+        index: u32,
+    ) -> Result<CheckResult, RequestError<ValidateError>> {
+        let check = *checklist::CHECKLIST
+            .get(index as usize)
+            .ok_or(ValidateError::InvalidDevice)?;
+        Ok(CheckResult {
+            check,
+            outcome: checklist::run(index, &check),
+        })
+    }
+
+    fn run_checklist(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<ChecklistSummary, RequestError<ValidateError>> {
+        let mut summary = ChecklistSummary {
+            passed: 0,
+            present: 0,
+            failed: 0,
+            first_failure: None,
+        };
+        for (index, check) in checklist::CHECKLIST.iter().enumerate() {
+            match checklist::run(index as u32, check) {
+                CheckOutcome::Pass => summary.passed += 1,
+                CheckOutcome::Present => summary.present += 1,
+                _ => {
+                    summary.failed += 1;
+                    summary.first_failure.get_or_insert(index as u32);
+                }
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Validates the I2C device with the given index.
+fn validate_i2c(index: usize) -> Result<ValidateOk, ValidateError> {
+    use i2c_config::validation::I2cValidation;
+
+    ringbuf_entry!(Trace::Validate(index));
+
+    // Without a strap, every device is fitted.
+    #[cfg(feature = "board-rev")]
+    {
+        use core::sync::atomic::Ordering;
+
+        let rev = BOARD_REV.load(Ordering::Relaxed);
+        if !i2c_config::validation::fitted(index, rev) {
+            return Ok(ValidateOk::Removed);
+        }
+    }
+
+    match i2c_config::validation::validate(I2C.get_task_id(), index) {
+        Err(err) => {
+            ringbuf_entry!(Trace::ValidateFailure(err));
+            Err(err.into())
+        }
+        Ok(ok) => match ok {
+            I2cValidation::RawReadOk => Ok(ValidateOk::Present),
+            I2cValidation::Good => Ok(ValidateOk::Validated),
+            I2cValidation::Bad => Err(ValidateError::BadValidation),
+        },
+    }
 }
 
 #[export_name = "main"]
 fn main() -> ! {
+    #[cfg(feature = "board-rev")]
+    {
+        let sys = drv_stm32xx_sys_api::Sys::from(SYS.get_task_id());
+        let rev = i2c_config::validation::board_rev(&sys);
+        BOARD_REV.store(rev, core::sync::atomic::Ordering::Relaxed);
+    }
+
     let mut server = ServerImpl;
     let mut buffer = [0; idl::INCOMING_SIZE];
 
@@ -92,7 +182,10 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::{MuxSegment, ValidateError, ValidateOk};
+    use super::{
+        Check, CheckResult, ChecklistSummary, MuxSegment, ValidateError,
+        ValidateOk,
+    };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
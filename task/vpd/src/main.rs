@@ -5,10 +5,10 @@
 //! VPD manipulation
 
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 
 use drv_i2c_devices::at24csw080::{At24Csw080, EEPROM_SIZE};
-use idol_runtime::RequestError;
+use idol_runtime::{ClientError, Leased, LenLimit, RequestError, R};
 use task_vpd_api::VpdError;
 use userlib::*;
 
@@ -120,9 +120,47 @@ impl idl::InOrderVpdImpl for ServerImpl {
             Ok(rval) => Ok(rval),
         }
     }
+
+    fn write_bulk(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+        offset: u16,
+        contents: LenLimit<Leased<R, [u8]>, 256>,
+    ) -> Result<(), RequestError<VpdError>> {
+        let devs = i2c_config::devices::at24csw080(I2C.get_task_id());
+        let index = index as usize;
+
+        if index >= devs.len() {
+            return Err(VpdError::InvalidDevice.into());
+        }
+
+        let dev = At24Csw080::new(devs[index]);
+
+        if offset as usize + contents.len() > EEPROM_SIZE as usize {
+            return Err(VpdError::BadAddress.into());
+        }
+
+        let mut buf = [0u8; 256];
+        let buf = &mut buf[..contents.len()];
+        contents
+            .read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        match dev.write_buffer(offset, buf) {
+            Err(drv_i2c_devices::at24csw080::Error::I2cError(code)) => {
+                let err: VpdError = code.into();
+                Err(err.into())
+            }
+
+            Err(_) => Err(VpdError::BadWrite.into()),
+
+            Ok(rval) => Ok(rval),
+        }
+    }
 }
 
-#[export_name = "main"]
+#[cfg_attr(not(test), export_name = "main")]
 fn main() -> ! {
     let mut server = ServerImpl;
     let mut buffer = [0; idl::INCOMING_SIZE];
@@ -137,3 +175,98 @@ mod idl {
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use drv_i2c_api::{Address, I2cDevice};
+    use drv_mock_i2c::{Bus, Registers};
+    use std::sync::{Arc, Mutex};
+    use task_vpd_api::Vpd;
+    use userlib::sim::{task_id, Sim};
+
+    /// The blocks of an EEPROM on the mock bus
+    type Blocks = [Arc<Mutex<Registers>>; 4];
+
+    /// Attaches a model of the EEPROM `dev` to `bus`. It answers at four
+    /// addresses, whose low bits are the top bits of the EEPROM address, so
+    /// it's modelled as four blocks of 256 registers.
+    fn attach(bus: &Bus, dev: &I2cDevice) -> Blocks {
+        let Address::SevenBit(addr) = dev.address else {
+            panic!("EEPROM has a 10-bit address");
+        };
+        core::array::from_fn(|block| {
+            let addr = addr | block as u8;
+            let regs = Registers::default();
+            bus.attach(dev.controller, dev.port, dev.segment, addr, regs)
+        })
+    }
+
+    /// Runs `test` against the VPD server, over a mock I2C bus with nothing
+    /// on it yet, passing it the app's EEPROMs.
+    fn with_vpd(test: impl FnOnce(&Vpd, &Bus, &[I2cDevice])) {
+        let bus = Bus::default();
+        let server = bus.clone();
+        Sim::new()
+            .task("i2c_driver", 1, move || drv_mock_i2c::serve(server.clone()))
+            .task("vpd", 2, || main())
+            .run("test", 3, || {
+                let devs = i2c_config::devices::at24csw080(I2C.get_task_id());
+                test(&Vpd::from(task_id("vpd")), &bus, &devs)
+            });
+    }
+
+    #[test]
+    fn read_and_write() {
+        with_vpd(|vpd, bus, devs| {
+            let rom = attach(bus, &devs[0]);
+            rom[1].lock().unwrap().regs[0x10..0x20].fill(0xa5);
+            assert_eq!(vpd.read(0, 0x110), Ok([0xa5; 16]));
+
+            vpd.write(0, EEPROM_SIZE - 1, 0x42).unwrap();
+            assert_eq!(rom[3].lock().unwrap().regs[0xff], 0x42);
+            assert_eq!(vpd.read(0, EEPROM_SIZE - 16).unwrap()[15], 0x42);
+        });
+    }
+
+    #[test]
+    fn write_bulk_across_blocks() {
+        with_vpd(|vpd, bus, devs| {
+            let rom = attach(bus, &devs[0]);
+            let data: [u8; 40] = core::array::from_fn(|i| i as u8 + 1);
+            vpd.write_bulk(0, 0xf0, &data).unwrap();
+
+            assert_eq!(rom[0].lock().unwrap().regs[0xf0..], data[..16]);
+            assert_eq!(rom[1].lock().unwrap().regs[..24], data[16..]);
+            assert_eq!(vpd.read(0, 0x100).unwrap(), data[16..32]);
+        });
+    }
+
+    #[test]
+    fn bad_requests() {
+        with_vpd(|vpd, bus, devs| {
+            attach(bus, &devs[0]);
+            let end = EEPROM_SIZE;
+            assert_eq!(vpd.read(0, end - 15), Err(VpdError::BadAddress));
+            assert_eq!(vpd.write(0, end, 0), Err(VpdError::BadAddress));
+            assert_eq!(
+                vpd.write_bulk(0, end - 16, &[0; 17]),
+                Err(VpdError::BadAddress)
+            );
+
+            let absent = devs.len() as u8;
+            assert_eq!(vpd.read(absent, 0), Err(VpdError::InvalidDevice));
+            assert_eq!(vpd.write(absent, 0, 0), Err(VpdError::InvalidDevice));
+        });
+    }
+
+    #[test]
+    fn missing_eeprom() {
+        with_vpd(|vpd, _, _| {
+            assert_eq!(vpd.read(0, 0), Err(VpdError::NotPresent));
+            assert_eq!(vpd.write(0, 0, 0), Err(VpdError::NotPresent));
+        });
+    }
+}
@@ -0,0 +1,74 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Peer for the benchmarks in `bench-suite`.
+//!
+//! This answers messages and notifications as quickly as it can, so that what
+//! the suite measures is the kernel's side of each exchange. It must be
+//! included in the image with the name `peer`, and the suite with the name
+//! `suite`.
+
+#![no_std]
+#![no_main]
+
+use test_api::*;
+use userlib::*;
+
+/// Size of the buffer that leases are copied through
+const CHUNK: usize = 256;
+
+#[export_name = "main"]
+fn main() -> ! {
+    // The suite is at a lower priority, so we find it by index rather than
+    // through a task slot, which would look like a priority inversion.
+    let suite = TaskId::for_index_and_gen(
+        hubris_num_tasks::Task::suite as usize,
+        Generation::ZERO,
+    );
+
+    let mut buf = [0u8; CHUNK];
+    loop {
+        let msg = sys_recv_open(&mut [], BENCH_PING_MASK);
+        if msg.sender == TaskId::KERNEL {
+            // Ping: pong straight back.
+            sys_post(sys_refresh_task_id(suite), BENCH_PING_MASK);
+            continue;
+        }
+
+        match BenchPeerOp::from_u32(msg.operation) {
+            Some(BenchPeerOp::Echo) => (),
+            Some(BenchPeerOp::ReadLease) => {
+                let len = lease_len(msg.sender);
+                let mut offset = 0;
+                while offset < len {
+                    let n = CHUNK.min(len - offset);
+                    sys_borrow_read(msg.sender, 0, offset, &mut buf[..n]);
+                    offset += n;
+                }
+            }
+            Some(BenchPeerOp::WriteLease) => {
+                let len = lease_len(msg.sender);
+                let mut offset = 0;
+                while offset < len {
+                    let n = CHUNK.min(len - offset);
+                    sys_borrow_write(msg.sender, 0, offset, &buf[..n]);
+                    offset += n;
+                }
+            }
+            None => {
+                sys_reply_fault(
+                    msg.sender,
+                    ReplyFaultReason::UndefinedOperation,
+                );
+                continue;
+            }
+        }
+        sys_reply(msg.sender, 0, &[]);
+    }
+}
+
+/// Returns the length of lease 0 from `lender`, or 0 if there isn't one.
+fn lease_len(lender: TaskId) -> usize {
+    sys_borrow_info(lender, 0).map(|info| info.len).unwrap_or(0)
+}
@@ -0,0 +1,256 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Microbenchmarks, run on the target.
+//!
+//! Each benchmark runs for about `RUN_MS`, and we count how many iterations
+//! fit in that time. Tasks can't read the cycle counter, so time comes from
+//! the kernel timer, checked after every `BATCH` iterations to keep the cost
+//! of asking out of the measurement.
+//!
+//! The IPC benchmarks need the `bench-peer` task, included in the image with
+//! the name `peer`, at a higher priority than this task (so that every
+//! message or notification switches to it and back). The SPI and I2C
+//! benchmarks are behind the `spi` and `i2c` features, and need a device
+//! named `bench` in the app's SPI config, or with the alias `bench` in its
+//! I2C config.
+//!
+//! # Output
+//!
+//! Output is produced on ITM stimulus port 8, like the test runner's:
+//!
+//! - `bench start N` - marks the beginning of the Nth run (from 0)
+//! - `result NAME ITERATIONS ELAPSED_MS BYTES` - for each benchmark that
+//!   completes, where BYTES is the number of bytes it moves per iteration (0
+//!   for those that only measure latency)
+//! - `error NAME` - for each benchmark that fails (say, if a device is
+//!   missing)
+//! - `done` - marks the end of the run
+//!
+//! Runs repeat every `REST_MS`, so that the host can start listening at any
+//! time and catch the next one.
+
+#![no_std]
+#![no_main]
+
+use test_api::*;
+use userlib::*;
+
+#[cfg(feature = "i2c")]
+include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
+
+cfg_if::cfg_if! {
+    if #[cfg(armv6m)] {
+        /// Helper macro for producing output by semihosting :-(
+        macro_rules! bench_output {
+            ($s:expr) => {
+                cortex_m_semihosting::hprintln!($s);
+            };
+            ($s:expr, $($tt:tt)*) => {
+                cortex_m_semihosting::hprintln!($s, $($tt)*);
+            };
+        }
+    } else {
+        /// Helper macro for producing output on stimulus port 8.
+        macro_rules! bench_output {
+            ($s:expr) => {
+                unsafe {
+                    let stim = &mut (*cortex_m::peripheral::ITM::PTR).stim[8];
+                    cortex_m::iprintln!(stim, $s);
+                }
+            };
+            ($s:expr, $($tt:tt)*) => {
+                unsafe {
+                    let stim = &mut (*cortex_m::peripheral::ITM::PTR).stim[8];
+                    cortex_m::iprintln!(stim, $s, $($tt)*);
+                }
+            };
+        }
+    }
+}
+
+task_slot!(PEER, peer);
+
+#[cfg(feature = "spi")]
+task_slot!(SPI, spi_driver);
+
+#[cfg(feature = "i2c")]
+task_slot!(I2C, i2c_driver);
+
+/// How long to run each benchmark for
+const RUN_MS: u64 = 500;
+
+/// Iterations between looks at the timer
+const BATCH: u32 = 32;
+
+/// Pause between runs
+const REST_MS: u64 = 1000;
+
+/// Size of the leases in the borrow benchmarks
+const LEASE_SIZE: usize = 1024;
+
+/// Size of each SPI exchange
+#[cfg(feature = "spi")]
+const SPI_SIZE: usize = 64;
+
+/// Size of each I2C read
+#[cfg(feature = "i2c")]
+const I2C_SIZE: usize = 16;
+
+struct Bench {
+    name: &'static str,
+    /// Bytes moved by each iteration
+    bytes: usize,
+    /// Runs one iteration, returning `false` if it failed
+    run: fn() -> bool,
+}
+
+static BENCHES: &[Bench] = &[
+    Bench {
+        name: "ipc-round-trip",
+        bytes: 0,
+        run: ipc_round_trip,
+    },
+    Bench {
+        name: "context-switch",
+        bytes: 0,
+        run: context_switch,
+    },
+    Bench {
+        name: "borrow-read",
+        bytes: LEASE_SIZE,
+        run: borrow_read,
+    },
+    Bench {
+        name: "borrow-write",
+        bytes: LEASE_SIZE,
+        run: borrow_write,
+    },
+    #[cfg(feature = "spi")]
+    Bench {
+        name: "spi-exchange",
+        bytes: SPI_SIZE,
+        run: spi_exchange,
+    },
+    #[cfg(feature = "i2c")]
+    Bench {
+        name: "i2c-read",
+        bytes: I2C_SIZE,
+        run: i2c_read,
+    },
+];
+
+/// An empty message to the peer, and its empty reply.
+fn ipc_round_trip() -> bool {
+    let (rc, _) = sys_send(
+        PEER.get_task_id(),
+        BenchPeerOp::Echo as u16,
+        &[],
+        &mut [],
+        &[],
+    );
+    rc == 0
+}
+
+/// A notification to the peer, and one back: two context switches, without
+/// any message copying.
+fn context_switch() -> bool {
+    sys_post(PEER.get_task_id(), BENCH_PING_MASK);
+    sys_recv_closed(&mut [], BENCH_PING_MASK, TaskId::KERNEL).is_ok()
+}
+
+/// The peer reads a lease of `LEASE_SIZE` bytes.
+fn borrow_read() -> bool {
+    let buf = [0x55u8; LEASE_SIZE];
+    let (rc, _) = sys_send(
+        PEER.get_task_id(),
+        BenchPeerOp::ReadLease as u16,
+        &[],
+        &mut [],
+        &[Lease::read_only(&buf)],
+    );
+    rc == 0
+}
+
+/// The peer fills a lease of `LEASE_SIZE` bytes.
+fn borrow_write() -> bool {
+    let mut buf = [0u8; LEASE_SIZE];
+    let (rc, _) = sys_send(
+        PEER.get_task_id(),
+        BenchPeerOp::WriteLease as u16,
+        &[],
+        &mut [],
+        &[Lease::write_only(&mut buf)],
+    );
+    rc == 0
+}
+
+#[cfg(feature = "spi")]
+fn spi_exchange() -> bool {
+    use drv_spi_api::SpiServer;
+
+    let spi = drv_spi_api::Spi::from(SPI.get_task_id())
+        .device(drv_spi_api::devices::BENCH);
+    let tx = [0xa5u8; SPI_SIZE];
+    let mut rx = [0u8; SPI_SIZE];
+    spi.exchange(&tx, &mut rx).is_ok()
+}
+
+#[cfg(feature = "i2c")]
+fn i2c_read() -> bool {
+    let dev = i2c_config::devices::bench(I2C.get_task_id());
+    let mut buf = [0u8; I2C_SIZE];
+    dev.read_into(&mut buf).is_ok()
+}
+
+/// Runs `f` for about `RUN_MS`, returning the number of iterations and the
+/// time they took, or `None` if any of them failed.
+fn measure(f: fn() -> bool) -> Option<(u32, u64)> {
+    // Start on a tick boundary, so that we don't lose part of a tick.
+    let t = sys_get_timer().now;
+    while sys_get_timer().now == t {}
+
+    let start = sys_get_timer().now;
+    let mut iterations = 0u32;
+    loop {
+        for _ in 0..BATCH {
+            if !f() {
+                return None;
+            }
+        }
+        iterations += BATCH;
+        let elapsed = sys_get_timer().now - start;
+        if elapsed >= RUN_MS {
+            return Some((iterations, elapsed));
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let mut run = 0u32;
+    loop {
+        bench_output!("bench start {}", run);
+        for b in BENCHES {
+            match measure(b.run) {
+                Some((iterations, ms)) => {
+                    bench_output!(
+                        "result {} {} {} {}",
+                        b.name,
+                        iterations,
+                        ms,
+                        b.bytes
+                    );
+                }
+                None => {
+                    bench_output!("error {}", b.name);
+                }
+            }
+        }
+        bench_output!("done");
+
+        run = run.wrapping_add(1);
+        hl::sleep_for(REST_MS);
+    }
+}
@@ -5,6 +5,7 @@
 #![no_std]
 
 use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
 
 /// Operations that are performed by the test-assist
 #[derive(FromPrimitive, Debug, Eq, PartialEq)]
@@ -47,6 +48,28 @@ pub enum SuiteOp {
     GetCaseName = 2,
     /// Run a case, replying before it starts (`usize -> ()`).
     RunCase = 3,
+    /// Get the limits on running a case (`usize -> CaseParams`).
+    GetCaseParams = 4,
+}
+
+/// Limits on running one test case, which the runner enforces.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct CaseParams {
+    /// How long the case may run before the runner gives up on it and
+    /// restarts the suite, in milliseconds.
+    pub timeout_ms: u32,
+    /// How many times the case is run before it counts as failed. This is
+    /// more than 1 only for cases that are known to be flaky.
+    pub attempts: u32,
+}
+
+impl CaseParams {
+    /// Limits for cases that don't ask for anything else.
+    pub const DEFAULT: Self = Self {
+        timeout_ms: 10_000,
+        attempts: 1,
+    };
 }
 
 /// Operations that are performed by the test-runner
@@ -59,3 +82,18 @@ pub enum RunnerOp {
     /// to passive mode (`() -> ()`).
     TestComplete = 0xFFFF,
 }
+
+/// Operations that are performed by the bench-peer
+#[derive(FromPrimitive, Debug, Eq, PartialEq)]
+pub enum BenchPeerOp {
+    /// Replies straight away (`() -> ()`).
+    Echo = 0,
+    /// Reads all of lease 0, then replies (`() -> ()`).
+    ReadLease = 1,
+    /// Fills all of lease 0, then replies (`() -> ()`).
+    WriteLease = 2,
+}
+
+/// Notification bit that the bench-suite pings the bench-peer with, and that
+/// the peer answers with.
+pub const BENCH_PING_MASK: u32 = 1 << 0;
@@ -5,7 +5,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
 
-    idol::server::build_server_support(
+    build_util::idol::build_server_support(
         "../test-idol-api/api.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
@@ -49,6 +49,9 @@
 //!   and so on
 //! ```
 //!
+//! Each case (and each attempt at a case) gets a freshly restarted testsuite,
+//! so that one case can't leave state behind for the next.
+//!
 //! The key detail in the diagram above: the runner and the testsuite *switch
 //! roles* in terms of who calls who.
 //!
@@ -80,6 +83,19 @@
 //!     STATUS (which is `ok` or `FAIL`).
 //! - `done STATUS` - signals the end of the test suite. STATUS is `ok` if all
 //!   tests passed, `FAIL` if any failed.
+//!
+//! With the `tap` feature, output is instead in TAP (version 13): a plan line,
+//! then `ok N - NAME` or `not ok N - NAME # REASON` for each case, with any
+//! other detail in `#` comment lines. `cargo xtask test` collects this itself,
+//! since `humility test` only understands the format above.
+//!
+//! # Timeouts and retries
+//!
+//! Each case has a deadline, after which the runner restarts the testsuite
+//! and counts the case as failed, rather than letting one hung case stall the
+//! rest of the run. A case that is known to be flaky may be given more than
+//! one attempt, and passes if any attempt does. The testsuite supplies both
+//! limits for each case; see `CaseParams`.
 
 #![no_std]
 #![no_main]
@@ -133,6 +149,20 @@ static TEST_RUNS: AtomicU32 = AtomicU32::new(0);
 /// We are sensitive to all notifications, to catch unexpected ones in test.
 const ALL_NOTIFICATIONS: u32 = !0;
 
+/// Notification bit for the timer that bounds each case. It's kept out of
+/// the notifications that tests can read back, since they didn't ask for it.
+const TIMER_MASK: u32 = 1 << 31;
+
+/// How a single attempt at a test case came out.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Outcome {
+    Pass,
+    /// The testsuite faulted (including by failing an assertion).
+    Fail,
+    /// The testsuite didn't report back before the case's deadline.
+    Timeout,
+}
+
 fn test_run() {
     // Get things rolling by restarting the test task. This ensures that it's
     // running, so that we don't depend on the `start` key in `app.toml` for
@@ -141,103 +171,295 @@ fn test_run() {
 
     // Begin by interrogating the task to understand the shape of the test
     // suite, and produce the `meta` section.
-    test_output!("meta");
     let case_count = get_case_count();
-    test_output!("expect {}", case_count);
-
-    // Read and print the name of each test case.
-    for i in 0..case_count {
-        output_name("case", i);
-    }
+    report::meta(case_count);
 
     // Transition to running tests.
-    test_output!("run");
     let mut failures = 0;
 
     for i in 0..case_count {
-        // Restart every time to ensure state is clear.
+        // Restart to ensure state is clear, and so that we can ask for the
+        // case's limits from a suite that isn't stuck from the last one.
         restart_tester();
+        let params = get_case_params(i);
 
-        // Read the name, again. Yes, this means the test suite could change
-        // test names on us. Oh well. It's easier than storing the names.
-        output_name("start", i);
-
-        // Ask the test to start running. It's *supposed* to immediately reply
-        // and then call us back when it finishes.
-        start_test(i);
+        report::start(i);
 
-        // We now start playing the receiver, monitoring messages from both the
-        // kernel and the testsuite.
+        let attempts = params.attempts.max(1);
+        let mut attempt = 1;
+        let outcome = loop {
+            let outcome = run_case(i, params.timeout_ms);
+            if outcome == Outcome::Pass || attempt == attempts {
+                break outcome;
+            }
+            report::retry(i, outcome, attempt, attempts);
+            attempt += 1;
 
-        // TODO this is where we need to set a timer, but to do that, we need to
-        // be able to read the current time.
+            // Each attempt gets a fresh suite, like each case.
+            restart_tester();
+        };
 
-        struct MonitorState {
-            received_notes: u32,
-            test_status: Option<bool>,
+        if outcome != Outcome::Pass {
+            failures += 1;
         }
+        report::finish(i, outcome, attempt, &params);
+    }
 
-        let mut state = MonitorState {
-            received_notes: 0,
-            test_status: None,
-        };
+    // Indicate final state of the suite.
+    report::done(case_count, failures);
+}
+
+/// Runs one attempt at case `index`, with a fresh testsuite, and waits up to
+/// `timeout_ms` for it to finish.
+fn run_case(index: usize, timeout_ms: u32) -> Outcome {
+    // Ask the test to start running. It's *supposed* to immediately reply
+    // and then call us back when it finishes.
+    start_test(index);
+
+    // We now start playing the receiver, monitoring messages from both the
+    // kernel and the testsuite, until our timer says the case has had long
+    // enough.
+    let deadline = sys_get_timer().now + u64::from(timeout_ms);
+    sys_set_timer(Some(deadline), TIMER_MASK);
+
+    struct MonitorState {
+        received_notes: u32,
+        deadline: u64,
+        outcome: Option<Outcome>,
+    }
+
+    let mut state = MonitorState {
+        received_notes: 0,
+        deadline,
+        outcome: None,
+    };
+
+    // Continue monitoring messages until (1) the test has been reported as
+    // complete, (2) we get notice from the kernel that the testsuite has
+    // crashed, or (3) the deadline passes.
+    while state.outcome.is_none() {
+        hl::recv(
+            &mut [],
+            ALL_NOTIFICATIONS,
+            &mut state,
+            |state, bits| {
+                // Record all received notification bits, other than our own.
+                state.received_notes |= bits & !TIMER_MASK;
+
+                if bits & 1 != 0 {
+                    // Uh-oh, somebody faulted.
+                    if find_and_report_fault() {
+                        // It was the test.
+                        state.outcome = Some(Outcome::Fail);
+                        return;
+                    }
+                }
 
-        // Continue monitoring messages until (1) the test has been reported as
-        // complete, or (2) we get notice from the kernel that the testsuite has
-        // crashed.
-        while state.test_status.is_none() {
-            hl::recv(
-                &mut [],
-                ALL_NOTIFICATIONS,
-                &mut state,
-                |state, bits| {
-                    // Record all received notification bits.
-                    state.received_notes |= bits;
-
-                    if bits & 1 != 0 {
-                        // Uh-oh, somebody faulted.
-                        if find_and_report_fault() {
-                            // It was the test.
-                            state.test_status = Some(false);
-                        }
+                // A timer notification can be left over from the previous
+                // case, if it fired just as that case finished, so check the
+                // time rather than trusting the bit.
+                if bits & TIMER_MASK != 0
+                    && sys_get_timer().now >= state.deadline
+                {
+                    // Stop the testsuite where it is, so that it can't go on
+                    // to call us about a case we've given up on.
+                    restart_tester();
+                    state.outcome = Some(Outcome::Timeout);
+                }
+            },
+            |state, op: RunnerOp, msg| -> Result<(), u32> {
+                match op {
+                    RunnerOp::ReadAndClearNotes => {
+                        let (_, caller) = msg.fixed::<(), u32>().ok_or(2u32)?;
+                        caller.reply(state.received_notes);
+                        state.received_notes = 0;
                     }
-                },
-                |state, op: RunnerOp, msg| -> Result<(), u32> {
-                    match op {
-                        RunnerOp::ReadAndClearNotes => {
-                            let (_, caller) =
-                                msg.fixed::<(), u32>().ok_or(2u32)?;
-                            caller.reply(state.received_notes);
-                            state.received_notes = 0;
-                        }
-                        RunnerOp::TestComplete => {
-                            let (_, caller) =
-                                msg.fixed::<(), ()>().ok_or(2u32)?;
-                            caller.reply(());
-                            state.test_status = Some(true);
-                        }
+                    RunnerOp::TestComplete => {
+                        let (_, caller) = msg.fixed::<(), ()>().ok_or(2u32)?;
+                        caller.reply(());
+                        state.outcome = Some(Outcome::Pass);
                     }
-                    Ok(())
-                },
-            );
+                }
+                Ok(())
+            },
+        );
+    }
+
+    sys_set_timer(None, TIMER_MASK);
+    state.outcome.unwrap()
+}
+
+#[cfg(not(feature = "tap"))]
+mod report {
+    //! Output in the format `humility test` expects.
+
+    use super::*;
+
+    pub fn meta(case_count: usize) {
+        test_output!("meta");
+        test_output!("expect {}", case_count);
+
+        // Read and print the name of each test case.
+        for i in 0..case_count {
+            output_name("case", i);
         }
 
+        test_output!("run");
+    }
+
+    pub fn start(index: usize) {
+        // Read the name, again. Yes, this means the test suite could change
+        // test names on us. Oh well. It's easier than storing the names.
+        output_name("start", index);
+    }
+
+    pub fn retry(index: usize, outcome: Outcome, attempt: u32, of: u32) {
+        // There's no room for this in the report, so it goes in the log.
+        let mut name = [0; 64];
+        sys_log!(
+            "{}: attempt {} of {}: {:?}",
+            case_name(index, &mut name),
+            attempt,
+            of,
+            outcome
+        );
+    }
+
+    pub fn finish(
+        index: usize,
+        outcome: Outcome,
+        _attempt: u32,
+        _params: &CaseParams,
+    ) {
         // Indicate final state of this case.
-        let status_str = if state.test_status.unwrap() {
+        let status_str = if outcome == Outcome::Pass {
             "finish ok"
         } else {
-            failures += 1;
             "finish FAIL"
         };
 
-        output_name(status_str, i);
+        output_name(status_str, index);
     }
 
-    // Indicate final state of the suite.
-    if failures == 0 {
-        test_output!("done pass");
+    pub fn done(_case_count: usize, failures: usize) {
+        if failures == 0 {
+            test_output!("done pass");
+        } else {
+            test_output!("done FAIL");
+        }
+    }
+
+    /// Contacts the test suite to retrieve the name of test case `index`, and
+    /// then prints it after `context`.
+    fn output_name(context: &str, index: usize) {
+        let mut name = [0; 64];
+        test_output!("{} {}", context, case_name(index, &mut name));
+    }
+}
+
+#[cfg(feature = "tap")]
+mod report {
+    //! Output in the Test Anything Protocol (version 13), for consumers other
+    //! than Humility. Cases are numbered from 1, as TAP expects.
+
+    use super::*;
+
+    pub fn meta(case_count: usize) {
+        test_output!("TAP version 13");
+        test_output!("1..{}", case_count);
+    }
+
+    pub fn start(index: usize) {
+        // So that any hang can be blamed on the right case.
+        let mut name = [0; 64];
+        test_output!("# start {}", case_name(index, &mut name));
+    }
+
+    pub fn retry(index: usize, outcome: Outcome, attempt: u32, of: u32) {
+        let mut name = [0; 64];
+        test_output!(
+            "# {}: attempt {} of {}: {:?}",
+            case_name(index, &mut name),
+            attempt,
+            of,
+            outcome
+        );
+    }
+
+    pub fn finish(
+        index: usize,
+        outcome: Outcome,
+        attempt: u32,
+        params: &CaseParams,
+    ) {
+        let mut buf = [0; 64];
+        let name = case_name(index, &mut buf);
+        let n = index + 1;
+        // Arms are blocks, since the semihosting flavor of `test_output!`
+        // isn't an expression.
+        match outcome {
+            Outcome::Pass if attempt > 1 => {
+                test_output!(
+                    "ok {} - {} # passed on attempt {} of {}",
+                    n,
+                    name,
+                    attempt,
+                    params.attempts
+                );
+            }
+            Outcome::Pass => {
+                test_output!("ok {} - {}", n, name);
+            }
+            Outcome::Fail => {
+                test_output!("not ok {} - {} # fault", n, name);
+            }
+            Outcome::Timeout => {
+                test_output!(
+                    "not ok {} - {} # timed out after {} ms",
+                    n,
+                    name,
+                    params.timeout_ms
+                );
+            }
+        }
+    }
+
+    pub fn done(case_count: usize, failures: usize) {
+        test_output!(
+            "# done {}: {} of {} failed",
+            if failures == 0 { "pass" } else { "FAIL" },
+            failures,
+            case_count
+        );
+    }
+}
+
+/// The name of a test case as the suite reports it, or its index if the name
+/// is invalid UTF-8.
+enum CaseName<'a> {
+    Named(&'a str),
+    Numbered(usize),
+}
+
+impl core::fmt::Display for CaseName<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CaseName::Named(name) => f.write_str(name),
+            CaseName::Numbered(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+/// Contacts the test suite to retrieve the name of test case `index`, using
+/// `buf` to hold it.
+fn case_name(index: usize, buf: &mut [u8; 64]) -> CaseName<'_> {
+    let name_slice = get_case_name(index, buf);
+    if let Ok(name_str) = core::str::from_utf8(name_slice) {
+        CaseName::Named(name_str.trim())
     } else {
-        test_output!("done FAIL");
+        // If any tests are not valid UTF-8, replace their name with their
+        // index.
+        CaseName::Numbered(index)
     }
 }
 
@@ -255,23 +477,6 @@ fn main() -> ! {
     }
 }
 
-/// Contacts the test suite to retrieve the name of test case `index`, and then
-/// prints it after `context`.
-///
-/// If the name is invalid UTF-8, substitutes a decimal representation of
-/// `index`.
-fn output_name(context: &str, index: usize) {
-    let mut name = [0; 64];
-    let name_slice = get_case_name(index, &mut name);
-    if let Ok(name_str) = core::str::from_utf8(name_slice) {
-        test_output!("{} {}", context, name_str.trim());
-    } else {
-        // If any tests are not valid UTF-8, replace their name with their
-        // index.
-        test_output!("{} {}", context, index);
-    }
-}
-
 /// Asks the kernel to restart the testsuite task and updates our expected
 /// generation.
 fn restart_tester() {
@@ -308,6 +513,18 @@ fn get_case_name(id: usize, buf: &mut [u8]) -> &[u8] {
     &buf[..len.min(buf.len())]
 }
 
+/// Contacts the test suite to get the limits on running case `id`.
+fn get_case_params(id: usize) -> CaseParams {
+    let tid = tester_task_id();
+    let mut response = CaseParams::DEFAULT;
+    let op = SuiteOp::GetCaseParams as u16;
+    let (rc, len) =
+        sys_send(tid, op, &id.as_bytes(), response.as_bytes_mut(), &[]);
+    assert_eq!(rc, 0);
+    assert_eq!(len, core::mem::size_of::<CaseParams>());
+    response
+}
+
 /// Contacts the testsuite to ask to start case `id`.
 fn start_test(id: usize) {
     let tid = tester_task_id();
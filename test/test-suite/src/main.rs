@@ -32,18 +32,33 @@ use zerocopy::AsBytes;
 /// secure fault, and a different constant will be required.)
 const BAD_ADDRESS: u32 = 0x0;
 
-/// Helper macro for building a list of functions with their names.
+/// Helper macro for building a list of functions with their names, and the
+/// limits the runner should enforce on them. Cases use `CaseParams::DEFAULT`
+/// unless they name their own, as in:
+///
+/// ```ignore
+/// test_slow_thing => CaseParams { timeout_ms: 30_000, ..CaseParams::DEFAULT },
+/// ```
 macro_rules! test_cases {
-    ($($(#[$attr:meta])* $name:path,)*) => {
-        static TESTS: &[(&str, &(dyn Fn() + Send + Sync))] = &[
+    ($($(#[$attr:meta])* $name:path $(=> $params:expr)?,)*) => {
+        static TESTS: &[(&str, &(dyn Fn() + Send + Sync), CaseParams)] = &[
             $(
                 $(#[$attr])*
-                (stringify!($name), &$name)
+                (stringify!($name), &$name, case_params!($($params)?))
             ),*
         ];
     };
 }
 
+macro_rules! case_params {
+    () => {
+        CaseParams::DEFAULT
+    };
+    ($params:expr) => {
+        $params
+    };
+}
+
 // Test the `task_config!` macro, in cooperation with `test_task_config` below
 // and the `[tests.suite.config]` block in the `app.toml` file.
 task_config::task_config! {
@@ -683,8 +698,8 @@ mod at24csw080 {
         // There are a few errors that we can't easily test
         // - InvalidObjectSize requires a single object that's > 64K, which is
         //   unlikely in our embedded system.
-        // - MisalignedPage and InvalidPageSize are both only generated by
-        //   a private function (write_page)
+        // - InvalidPageSize is only generated by a private function
+        //   (write_page), and MisalignedPage is no longer generated at all
         assert_eq!(
             dev.write(1028, 0x1234_u32),
             Err(Error::InvalidAddress(1028))
@@ -741,6 +756,17 @@ mod at24csw080 {
             assert!(dev.read::<BufType>(addr)? == buf);
         }
 
+        // Do the same with a bulk write that spans several pages
+        let addr = 300;
+        dev.write_buffer(addr, &buf)?;
+        dev.write_buffer(addr + BUF_SIZE, &buf)?;
+        assert!(dev.read::<BufType>(addr)? == buf);
+        assert!(dev.read::<BufType>(addr + BUF_SIZE)? == buf);
+        assert_eq!(
+            dev.write_buffer(EEPROM_SIZE - 1, &buf),
+            Err(Error::InvalidEndAddress(EEPROM_SIZE - 1 + BUF_SIZE))
+        );
+
         // Write the upper 16 bytes of the security register based on the lower
         // 16 bytes and the seed, then read back values to test.  We'll read
         // back these values in `validate_eeprom`, since this should be
@@ -1477,6 +1503,11 @@ fn main() -> ! {
                             .copy_from_slice(&name.as_bytes()[..name_len]);
                         caller.reply(name_buf);
                     }
+                    SuiteOp::GetCaseParams => {
+                        let (&idx, caller) =
+                            msg.fixed::<usize, CaseParams>().ok_or(2u32)?;
+                        caller.reply(TESTS[idx].2);
+                    }
                     SuiteOp::RunCase => {
                         let (&idx, caller) =
                             msg.fixed::<usize, ()>().ok_or(2u32)?;